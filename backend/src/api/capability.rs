@@ -0,0 +1,182 @@
+//! The FHIR CapabilityStatement served at `/metadata`.
+//!
+//! FHIR clients fetch it before anything else and will not talk to a server without one. It is
+//! generated from [`FHIR_ROUTES`]: a route is only described while the policy table has an entry
+//! for its path, and the `fhir_metadata` integration test checks every path in the table is one
+//! the router serves, so the statement cannot promise an interaction that is not there.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::api::policy::policy_for;
+
+pub const FHIR_MEDIA_TYPE: &str = "application/fhir+json";
+pub const FHIR_VERSION: &str = "4.0.1";
+
+/// Resource types the statement lists, in this order
+pub const RESOURCE_TYPES: &[&str] =
+    &["Patient", "Encounter", "Observation", "Condition", "MedicationRequest", "Immunization", "AllergyIntolerance"];
+
+/// What a route does with its resource type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interaction {
+    Read,
+    /// Searching within the type, with the route's [`SearchParam`]s
+    SearchType(&'static [SearchParam]),
+    Create,
+    /// A named operation, such as `$everything`, and its OperationDefinition
+    Operation(&'static str, &'static str),
+}
+
+/// A query parameter a search route takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchParam {
+    pub name: &'static str,
+    /// FHIR search parameter type: `string`, `token`, `date`...
+    pub kind: &'static str,
+    pub documentation: &'static str,
+}
+
+/// A route that serves FHIR resources of one type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FhirRoute {
+    pub resource: &'static str,
+    pub interaction: Interaction,
+    pub method: &'static str,
+    pub path: &'static str,
+}
+
+const fn route(resource: &'static str, interaction: Interaction, method: &'static str, path: &'static str) -> FhirRoute {
+    FhirRoute { resource, interaction, method, path }
+}
+
+const fn param(name: &'static str, kind: &'static str, documentation: &'static str) -> SearchParam {
+    SearchParam { name, kind, documentation }
+}
+
+pub const FHIR_ROUTES: &[FhirRoute] = &[
+    route("Patient", Interaction::Read, "GET", "/api/patients/:id"),
+    route(
+        "Patient",
+        Interaction::SearchType(&[param("q", "string", "Name, phone number or identifier; practitioners only")]),
+        "GET",
+        "/api/patients/search",
+    ),
+    route(
+        "Patient",
+        Interaction::Operation("everything", "http://hl7.org/fhir/OperationDefinition/Patient-everything"),
+        "GET",
+        "/api/patients/:id/$everything",
+    ),
+    route("Encounter", Interaction::Create, "POST", "/api/encounters"),
+    route(
+        "Encounter",
+        Interaction::SearchType(&[
+            param("role", "token", "Whose encounters: `patient` (default) or `practitioner`"),
+            param("organization_id", "token", "An organization's encounters instead of the caller's; organization admins only"),
+            param("from", "date", "Encounters starting at or after this instant"),
+            param("to", "date", "Encounters starting before this instant"),
+        ]),
+        "GET",
+        "/api/encounters",
+    ),
+    route("Observation", Interaction::Create, "POST", "/api/encounters/:id/vitals"),
+    route(
+        "Condition",
+        Interaction::SearchType(&[param("include_resolved", "token", "`true` to also list problems no longer active")]),
+        "GET",
+        "/api/patients/:id/problems",
+    ),
+    route("MedicationRequest", Interaction::Create, "POST", "/api/prescriptions"),
+    route(
+        "MedicationRequest",
+        Interaction::SearchType(&[param("status", "token", "Only prescriptions with this status")]),
+        "GET",
+        "/api/patients/:id/prescriptions",
+    ),
+];
+
+lazy_static! {
+    /// The statement as served, dated when it was first asked for, and its ETag
+    pub static ref METADATA: (String, String) = {
+        let body = capability_statement(Utc::now()).to_string();
+        let etag = format!("\"{}\"", hex::encode(Sha256::digest(body.as_bytes())));
+        (body, etag)
+    };
+}
+
+/// The CapabilityStatement for the routes in [`FHIR_ROUTES`] that have a policy
+pub fn capability_statement(date: DateTime<Utc>) -> Value {
+    let resources: Vec<Value> = RESOURCE_TYPES.iter().map(|resource_type| resource(resource_type)).collect();
+    json!({
+        "resourceType": "CapabilityStatement",
+        "status": "active",
+        "date": date.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "publisher": "WeCare",
+        "kind": "instance",
+        "software": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        "implementation": { "description": "WeCare decentralized health records API" },
+        "fhirVersion": FHIR_VERSION,
+        "format": ["json"],
+        "rest": [{
+            "mode": "server",
+            "security": {
+                "cors": true,
+                "service": [{
+                    "coding": [{
+                        "system": "http://terminology.hl7.org/CodeSystem/restful-security-service",
+                        "code": "OAuth",
+                        "display": "OAuth"
+                    }]
+                }],
+                "description": "Send the access token from signing in as `Authorization: Bearer <token>`. \
+                    Some routes also need a token stepped up with a second factor; without one they answer 401."
+            },
+            "resource": resources
+        }]
+    })
+}
+
+fn resource(resource_type: &str) -> Value {
+    let routes: Vec<&FhirRoute> =
+        FHIR_ROUTES.iter().filter(|route| route.resource == resource_type && policy_for(route.path).is_some()).collect();
+    let documentation = |route: &FhirRoute| format!("{} {}", route.method, route.path);
+
+    let mut interactions = Vec::new();
+    let mut search_params = Vec::new();
+    let mut operations = Vec::new();
+    for route in routes.iter().copied() {
+        match route.interaction {
+            Interaction::Read => interactions.push(json!({ "code": "read", "documentation": documentation(route) })),
+            Interaction::Create => interactions.push(json!({ "code": "create", "documentation": documentation(route) })),
+            Interaction::SearchType(params) => {
+                interactions.push(json!({ "code": "search-type", "documentation": documentation(route) }));
+                search_params.extend(params.iter().map(|param| {
+                    json!({ "name": param.name, "type": param.kind, "documentation": param.documentation })
+                }));
+            }
+            Interaction::Operation(name, definition) => {
+                operations.push(json!({ "name": name, "definition": definition, "documentation": documentation(route) }))
+            }
+        }
+    }
+
+    // FHIR does not allow empty arrays, so a list nothing is in is left out
+    let mut resource = Map::new();
+    resource.insert("type".to_string(), json!(resource_type));
+    resource.insert("profile".to_string(), json!(format!("http://hl7.org/fhir/StructureDefinition/{}", resource_type)));
+    if routes.is_empty() {
+        resource.insert(
+            "documentation".to_string(),
+            json!("No route serves this type yet"),
+        );
+    }
+    for (key, list) in [("interaction", interactions), ("searchParam", search_params), ("operation", operations)] {
+        if !list.is_empty() {
+            resource.insert(key.to_string(), Value::Array(list));
+        }
+    }
+    Value::Object(resource)
+}
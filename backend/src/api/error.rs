@@ -0,0 +1,140 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::api::middleware::request_log::LoggedError;
+use crate::i18n;
+use crate::models::ApiResponse;
+use crate::services::ServiceError;
+
+/// Error type for handlers that need to return something other than 200.
+///
+/// Classified failures (a `ServiceError` anywhere in the chain) map to their HTTP
+/// status. Anything else keeps the historical behaviour of a 200 response with
+/// `success: false`, which the mobile app already handles.
+pub struct ApiError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self.0.downcast_ref::<ServiceError>() {
+            Some(ServiceError::Conflict(_)) => StatusCode::CONFLICT,
+            Some(ServiceError::NotFound(_)) => StatusCode::NOT_FOUND,
+            Some(ServiceError::Forbidden(_)) => StatusCode::FORBIDDEN,
+            Some(ServiceError::StepUpRequired) => StatusCode::UNAUTHORIZED,
+            Some(ServiceError::NotConfigured(_)) => StatusCode::NOT_IMPLEMENTED,
+            Some(ServiceError::RequestTimeout) => StatusCode::REQUEST_TIMEOUT,
+            Some(ServiceError::Timeout) => StatusCode::GATEWAY_TIMEOUT,
+            Some(ServiceError::PayloadTooLarge) => StatusCode::PAYLOAD_TOO_LARGE,
+            Some(ServiceError::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+            Some(ServiceError::SmsLimited { .. }) => StatusCode::TOO_MANY_REQUESTS,
+            Some(ServiceError::CaptchaFailed(_)) => StatusCode::BAD_REQUEST,
+            Some(ServiceError::IdempotencyKeyReused) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::InvalidReference { .. }) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::InvalidConfig(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::UnsupportedMediaType) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Some(ServiceError::MalwareDetected) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::InvalidPhoto(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::InvalidLicense(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(ServiceError::ScanUnavailable) => StatusCode::SERVICE_UNAVAILABLE,
+            Some(ServiceError::HederaBalanceExhausted) => StatusCode::SERVICE_UNAVAILABLE,
+            Some(ServiceError::SmsBudgetExhausted) => StatusCode::SERVICE_UNAVAILABLE,
+            None => StatusCode::OK,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let service_error = self.0.downcast_ref::<ServiceError>();
+        let message = match service_error.and_then(ServiceError::message_key) {
+            Some(key) => i18n::text(i18n::current(), key, &[]),
+            None => self.0.to_string(),
+        };
+        let body = match service_error.and_then(ServiceError::code) {
+            Some(code) => ApiResponse::<()>::error_with_code(message, code),
+            None => ApiResponse::<()>::error(message),
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let Some(ServiceError::SmsLimited { retry_at, .. }) = service_error {
+            let seconds = (*retry_at - chrono::Utc::now()).num_seconds().max(1);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        // The request log writes the chain alongside the request id
+        response.extensions_mut().insert(LoggedError(format!("{:#}", self.0)));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Locale;
+
+    #[test]
+    fn service_errors_map_to_their_status() {
+        let conflict = ApiError::from(anyhow::Error::from(ServiceError::Conflict("taken".to_string())));
+        let not_found = ApiError::from(ServiceError::NotFound("no photo".to_string()));
+        let not_configured = ApiError::from(ServiceError::NotConfigured("chat is off".to_string()));
+        let forbidden = ApiError::from(ServiceError::Forbidden("not yours".to_string()));
+        let step_up = ApiError::from(ServiceError::StepUpRequired);
+        let rate_limited = ApiError::from(ServiceError::RateLimited("slow down".to_string()));
+        let captcha = ApiError::from(ServiceError::CaptchaFailed("try again".to_string()));
+        let reused_key = ApiError::from(ServiceError::IdempotencyKeyReused);
+        let unsupported = ApiError::from(ServiceError::UnsupportedMediaType);
+        let scan_unavailable = ApiError::from(ServiceError::ScanUnavailable);
+        let unfunded = ApiError::from(ServiceError::HederaBalanceExhausted);
+        let invalid_config = ApiError::from(ServiceError::InvalidConfig("LOG_LEVEL is not valid".to_string()));
+        let other = ApiError::from(anyhow::anyhow!("boom"));
+
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+        assert_eq!(not_configured.status(), StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+        assert_eq!(step_up.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(rate_limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(captcha.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(reused_key.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(unsupported.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(scan_unavailable.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(unfunded.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(invalid_config.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn fixed_messages_are_sent_in_the_requests_language() {
+        let response = i18n::scope(Locale::Sw, async { ApiError::from(ServiceError::IdempotencyKeyReused).into_response() }).await;
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "Idempotency-Key hii tayari imetumika kwa ombi tofauti");
+        assert_eq!(body["code"], "idempotency_key_reused");
+    }
+
+    #[test]
+    fn sms_limits_say_when_to_try_again() {
+        let retry_at = chrono::Utc::now() + chrono::Duration::minutes(10);
+        let response = ApiError::from(ServiceError::SmsLimited { message: "wait".to_string(), retry_at }).into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let seconds: i64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((590..=600).contains(&seconds), "{}", seconds);
+    }
+
+    #[test]
+    fn context_does_not_hide_the_classification() {
+        let error = anyhow::Error::from(ServiceError::NotConfigured("phone auth is off".to_string()))
+            .context("Failed to initiate phone auth");
+
+        assert_eq!(ApiError::from(error).status(), StatusCode::NOT_IMPLEMENTED);
+    }
+}
@@ -1,18 +1,105 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    Form,
 };
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, StreamExt};
 use serde::Deserialize;
 
+use crate::auditing::health::{self as anchoring_health, RUN_HISTORY};
+use crate::i18n;
 use crate::models::*;
 use crate::services::*;
+use crate::services::audit_analytics::AuditStatsQuery;
+use crate::services::compliance_report::ReportFormat;
 use crate::services::auth::EmailVerificationResponse;
+use crate::services::fhir::FhirManager;
+use crate::services::files::FilePart;
+use crate::services::practitioner::PractitionerRegistration;
+use crate::services::sessions::ClientInfo;
+use crate::services::vc_document::VC_MEDIA_TYPE;
 use crate::state::AppState;
+use crate::api::capability::{FHIR_MEDIA_TYPE, METADATA};
+use crate::api::error::ApiError;
+use crate::api::negotiation::{problem_condition, Format};
+use crate::api::middleware::jwt_auth::{AuthContext, CurrentUser};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use crate::services::ask_gemini;
 
 
+pub async fn health_check() -> Result<Json<serde_json::Value>, StatusCode> {
+    Ok(Json(serde_json::json!({
+        "status": "healthy",
+        "timestamp": chrono::Utc::now()
+    })))
+}
+
+/// The FHIR CapabilityStatement, which FHIR clients fetch before anything else. It only changes
+/// with a deploy, so clients may cache it and revalidate with its ETag.
+pub async fn fhir_metadata(headers: HeaderMap) -> Response {
+    let (body, etag) = &*METADATA;
+    let unchanged = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == "*" || tag.trim() == etag));
+    let headers = [(header::CONTENT_TYPE, FHIR_MEDIA_TYPE), (header::CACHE_CONTROL, "public, max-age=3600"), (header::ETAG, etag.as_str())];
+    if unchanged {
+        (StatusCode::NOT_MODIFIED, headers).into_response()
+    } else {
+        (headers, body.clone()).into_response()
+    }
+}
+
+/// Health including the dependencies that can take the service down, answered from their last
+/// checks: 503 while Hedera calls are held back because the operator account cannot pay.
+/// Requests are served while indexes are built, so `indexes_ready` reports the build without
+/// failing the check. Audit anchoring is reported the same way, without its runs: their errors
+/// are internal details for `GET /api/admin/audit/health` only.
+pub async fn deep_health_check(State(state): State<Arc<AppState<AuthServiceImpl>>>) -> (StatusCode, Json<serde_json::Value>) {
+    let hedera = state.hedera_balance_monitor.snapshot();
+    let (status, health) = if hedera.exhausted { (StatusCode::SERVICE_UNAVAILABLE, "degraded") } else { (StatusCode::OK, "healthy") };
+    let indexes = state.database.index_build_status();
+    let audit_anchoring = match state.anchoring_monitor.current().await {
+        Ok(anchoring) => serde_json::json!({
+            "status": anchoring.status,
+            "unanchored_logs": anchoring.unanchored_logs,
+            "last_success_at": anchoring.last_success_at,
+            "consecutive_failures": anchoring.consecutive_failures,
+        }),
+        Err(e) => {
+            tracing::error!("Failed to read the audit anchoring health: {}", e);
+            serde_json::Value::Null
+        }
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "status": health,
+            "timestamp": chrono::Utc::now(),
+            "hedera": hedera,
+            "indexes_ready": matches!(indexes.state, IndexBuildState::Completed | IndexBuildState::Skipped),
+            "indexes": indexes,
+            "audit_anchoring": audit_anchoring,
+        })),
+    )
+}
+
+/// Gauges for Prometheus to scrape
+pub async fn prometheus_metrics(State(state): State<Arc<AppState<AuthServiceImpl>>>) -> impl IntoResponse {
+    let mut gauges = state.hedera_balance_monitor.prometheus();
+    match state.anchoring_monitor.current().await {
+        Ok(anchoring) => gauges.push_str(&anchoring_health::prometheus(&anchoring)),
+        Err(e) => tracing::error!("Failed to read the audit anchoring health: {}", e),
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], gauges)
+}
+
 // --- Auth Handlers ---
 #[derive(Debug, Clone, Deserialize)]
 pub struct InitiateAuthRequest {
@@ -24,6 +111,10 @@ pub struct RegisterRequest {
     pub name: String,
     pub email: String,
     pub public_key_hex: String,
+    /// Required once a CAPTCHA provider is configured
+    pub captcha_token: Option<String>,
+    /// Language for emails and SMS; the Accept-Language header's when left out
+    pub locale: Option<Locale>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -34,6 +125,8 @@ pub struct GoogleAuthRequest {
 #[derive(Debug, Clone, Deserialize)]
 pub struct PhoneAuthInitiateRequest {
     pub phone_number: String,
+    /// Required once a CAPTCHA provider is configured
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -42,9 +135,235 @@ pub struct PhoneAuthVerifyRequest {
     pub otp: String,
 }
 
+/// Token from the link in a new-device sign-in alert, in its query and in the confirm form
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevokeSessionToken {
+    pub token: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChatRequest {
     pub prompt: String,
+    /// Continue an earlier conversation; a new session is started when absent
+    pub session_id: Option<String>,
+    /// Include a summary of the caller's own record (needs their stored consent)
+    #[serde(default)]
+    pub use_my_data: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatConsentRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimezoneRequest {
+    /// IANA name such as `Africa/Nairobi`
+    pub timezone: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfirmAppointmentRequest {
+    /// Also create the `planned` encounter for the visit
+    #[serde(default)]
+    pub create_encounter: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppointmentQuery {
+    #[serde(default)]
+    pub role: AppointmentParty,
+    /// List the organization's appointments instead of the caller's (organization admins only)
+    pub organization_id: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttachmentUploadQuery {
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhotoQuery {
+    #[serde(default)]
+    pub size: PhotoSize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationFeedQuery {
+    /// The id of the last event the client saw
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncounterQuery {
+    #[serde(default)]
+    pub role: AppointmentParty,
+    /// List the organization's encounters instead of the caller's (organization admins only)
+    pub organization_id: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReferralQuery {
+    #[serde(default)]
+    pub folder: ReferralFolder,
+    pub status: Option<ReferralStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrescriptionQuery {
+    pub status: Option<PrescriptionStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObservationSummaryQuery {
+    /// LOINC code, e.g. `8867-4` for heart rate
+    pub code: String,
+    #[serde(default)]
+    pub period: ObservationPeriod,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProblemListQuery {
+    /// Also list problems whose latest condition is no longer active
+    #[serde(default)]
+    pub include_resolved: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlotQuery {
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BreakGlassQuery {
+    /// Only reviewed (`true`) or unreviewed (`false`) events; all of them when left out
+    pub reviewed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminPatientQuery {
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(default = "default_page_size")]
+    pub page_size: u64,
+}
+
+/// Query of a practitioner's patient name search
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatientSearchQuery {
+    pub q: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminMergeCandidateQuery {
+    pub status: Option<MergeCandidateStatus>,
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(default = "default_page_size")]
+    pub page_size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminJobQuery {
+    pub status: Option<JobStatus>,
+    pub job_type: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(default = "default_page_size")]
+    pub page_size: u64,
+}
+
+fn default_page() -> u64 {
+    1
+}
+
+fn default_page_size() -> u64 {
+    20
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditHealthParams {
+    /// How many of the latest runs to list; 10 by default
+    pub runs: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditStatsParams {
+    /// Defaults to 30 days before `to`
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Defaults to now
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub group_by: AuditGroupBy,
+    /// How many of the busiest DIDs to list
+    pub top: Option<i64>,
+}
+
+/// The period a compliance report covers, `[from, to)`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComplianceReportRequest {
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HederaCostParams {
+    /// Defaults to the start of the month `to` falls in
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Defaults to now
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub group_by: HederaCostGroupBy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminPractitionerQuery {
+    /// Only verified (`true`) or unverified (`false`) licenses; everyone when left out
+    pub verified: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpiringLicenseQuery {
+    /// Licenses expiring within this many days; 30 when left out
+    pub days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatUsageQuery {
+    /// How many days back to report, today included
+    #[serde(default = "default_chat_usage_days")]
+    pub days: u32,
+}
+
+fn default_chat_usage_days() -> u32 {
+    7
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TerminologySearchQuery {
+    /// `icd10`, `loinc`, `snomed` or `rxnorm`
+    pub system: CodeSystem,
+    pub q: String,
+    #[serde(default = "default_terminology_limit")]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MedicationSearchQuery {
+    pub q: String,
+    #[serde(default = "default_terminology_limit")]
+    pub limit: usize,
+}
+
+fn default_terminology_limit() -> usize {
+    10
 }
 
 #[axum::debug_handler]
@@ -61,34 +380,105 @@ pub async fn auth_initiate(
     }
 }
 
+/// Where a sign-in comes from, for recognising the device. The app identifies its installation
+/// in `X-Installation-Id`.
+fn client_info(headers: &HeaderMap, client: SocketAddr) -> ClientInfo {
+    let value = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    ClientInfo { user_agent: value(header::USER_AGENT.as_str()), ip: Some(client.ip()), installation_id: value("x-installation-id") }
+}
+
 #[axum::debug_handler]
 pub async fn register(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(request): Json<RegisterRequest>,
-) -> Result<Json<ApiResponse<RegistrationResponse>>, StatusCode> {
-    match state.auth_service.register_new_user(request).await {
-        Ok(response) => Ok(Json(ApiResponse::success(response))),
-        Err(e) => {
-            tracing::error!("Failed to register user: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+) -> Result<Json<ApiResponse<RegistrationResponse>>, ApiError> {
+    state.captcha_verifier.verify(request.captcha_token.as_deref(), Some(client.ip())).await?;
+    let response = state.auth_service.register_new_user(request, client_info(&headers, client)).await?;
+    Ok(Json(ApiResponse::success(response)))
+}
+
+
+/// Exchange an SMS or authenticator code for a high-assurance token
+#[axum::debug_handler]
+pub async fn step_up_auth(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    current: CurrentUser,
+    Json(request): Json<StepUpRequest>,
+) -> Result<Json<ApiResponse<StepUpResponse>>, ApiError> {
+    let response = state.step_up_service.step_up(&auth, current.patient.as_ref(), request).await?;
+    Ok(Json(ApiResponse::success(response)))
+}
+
+#[axum::debug_handler]
+pub async fn step_up_sms(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    current: CurrentUser,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.step_up_service.send_sms_code(&auth.user_did, current.patient.as_ref()).await?;
+    Ok(Json(ApiResponse::success("OTP sent successfully".to_string())))
+}
+
+#[axum::debug_handler]
+pub async fn totp_enroll(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<TotpEnrollmentStarted>>, ApiError> {
+    let enrollment = state.totp_service.enroll(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(enrollment)))
+}
+
+#[axum::debug_handler]
+pub async fn totp_confirm(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<TotpCodeRequest>,
+) -> Result<Json<ApiResponse<BackupCodes>>, ApiError> {
+    state.totp_service.confirm(&auth.user_did, &request.code).await?;
+    // Shown this once; only their hashes are kept
+    let backup_codes = state.backup_code_service.generate(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(backup_codes)))
 }
 
+#[axum::debug_handler]
+pub async fn totp_disable(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<TotpCodeRequest>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.totp_service.disable(&auth.user_did, &request.code).await?;
+    Ok(Json(ApiResponse::success("Authenticator app disabled".to_string())))
+}
+
+#[axum::debug_handler]
+pub async fn backup_codes_status(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<BackupCodeStatus>>, ApiError> {
+    let status = state.backup_code_service.status(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(status)))
+}
 
 #[axum::debug_handler]
-pub async fn step_up_auth() -> Result<Json<ApiResponse<String>>, StatusCode> {
-    // In a real implementation, this would involve re-authenticating the user
-    // and creating a high-assurance session.
-    Ok(Json(ApiResponse::success("Step-up authentication successful".to_string())))
+pub async fn regenerate_backup_codes(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<BackupCodes>>, ApiError> {
+    let backup_codes = state.backup_code_service.generate(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(backup_codes)))
 }
 
 #[axum::debug_handler]
 pub async fn auth_google(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(request): Json<GoogleAuthRequest>,
 ) -> Result<Json<ApiResponse<RegistrationResponse>>, StatusCode> {
-    match state.auth_service.authenticate_with_google(request).await {
+    match state.auth_service.authenticate_with_google(request, client_info(&headers, client)).await {
         Ok(response) => Ok(Json(ApiResponse::success(response))),
         Err(e) => {
             tracing::error!("Failed to authenticate with Google: {}", e);
@@ -97,33 +487,56 @@ pub async fn auth_google(
     }
 }
 
-// #[axum::debug_handler]
-// pub async fn auth_phone_initiate(
-//     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-//     Json(request): Json<PhoneAuthInitiateRequest>,
-// ) -> Result<Json<ApiResponse<String>>, StatusCode> {
-//     match state.auth_service.initiate_phone_auth(request).await {
-//         Ok(_) => Ok(Json(ApiResponse::success("OTP sent successfully".to_string()))),
-//         Err(e) => {
-//             tracing::error!("Failed to initiate phone auth: {}", e);
-//             Ok(Json(ApiResponse::error(e.to_string())))
-//         }
-//     }
-// }
-
-// #[axum::debug_handler]
-// pub async fn auth_phone_verify(
-//     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-//     Json(request): Json<PhoneAuthVerifyRequest>,
-// ) -> Result<Json<ApiResponse<RegistrationResponse>>, StatusCode> {
-//     match state.auth_service.verify_phone_auth(request).await {
-//         Ok(response) => Ok(Json(ApiResponse::success(response))),
-//         Err(e) => {
-//             tracing::error!("Failed to verify phone auth: {}", e);
-//             Ok(Json(ApiResponse::error(e.to_string())))
-//         }
-//     }
-// }
+#[axum::debug_handler]
+pub async fn auth_phone_initiate(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<PhoneAuthInitiateRequest>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.captcha_verifier.verify(request.captcha_token.as_deref(), Some(client.ip())).await?;
+    state.auth_service.initiate_phone_auth(request).await?;
+    Ok(Json(ApiResponse::success("OTP sent successfully".to_string())))
+}
+
+#[axum::debug_handler]
+pub async fn auth_phone_verify(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<PhoneAuthVerifyRequest>,
+) -> Result<Json<ApiResponse<RegistrationResponse>>, ApiError> {
+    let response = state.auth_service.verify_phone_auth(request, client_info(&headers, client)).await?;
+    Ok(Json(ApiResponse::success(response)))
+}
+
+/// Opened from a new-device sign-in alert: a page asking to confirm, which posts the token back.
+/// Mail scanners and link previews fetch the link too, so fetching it signs nothing out.
+pub async fn confirm_session_revoke(Query(query): Query<RevokeSessionToken>) -> Response {
+    // The token is hex; anything else cannot name a session and is not echoed into the page
+    let form = if !query.token.is_empty() && query.token.bytes().all(|b| b.is_ascii_hexdigit()) {
+        format!(
+            r#"<p>Sign out the new sign-in you were alerted about? If it was you, close this page.</p>
+<form method="post" action="/api/auth/sessions/revoke"><input type="hidden" name="token" value="{}"><button type="submit">Sign it out</button></form>"#,
+            query.token
+        )
+    } else {
+        "<p>This link is invalid.</p>".to_string()
+    };
+    let page = format!(r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta name="robots" content="noindex"><title>Sign out a session</title></head><body>{}</body></html>"#, form);
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8"), (header::CACHE_CONTROL, "no-store"), (header::REFERRER_POLICY, "no-referrer")], page).into_response()
+}
+
+/// Submitted from the confirm page: signs the alerted session out
+#[axum::debug_handler]
+pub async fn revoke_session(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Form(form): Form<RevokeSessionToken>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    if !state.session_service.revoke_with_link(&form.token).await? {
+        return Ok(Json(ApiResponse::error("This link is invalid or the session was already signed out".to_string())));
+    }
+    Ok(Json(ApiResponse::success("The session was signed out".to_string())))
+}
 
 #[axum::debug_handler]
 pub async fn verify_email(
@@ -148,113 +561,1471 @@ pub async fn verify_email(
 #[axum::debug_handler]
 pub async fn chat(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
     Json(request): Json<ChatRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    match ask_gemini(&request.prompt, &state.config).await {
-        Ok(response) => Ok(Json(ApiResponse::success(response))),
-        Err(e) => {
-            tracing::error!("Failed to ask Gemini: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+) -> Result<Json<ApiResponse<ChatReply>>, ApiError> {
+    let reply = state
+        .chat_service
+        .send(&auth.user_did, request.session_id.as_deref(), &request.prompt, request.use_my_data)
+        .await?;
+    Ok(Json(ApiResponse::success(reply)))
 }
 
+#[axum::debug_handler]
+pub async fn list_chat_sessions(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<Vec<ChatSession>>>, ApiError> {
+    let sessions = state.chat_service.list_sessions(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(sessions)))
+}
 
-// --- Patient Handlers ---
 #[axum::debug_handler]
-pub async fn get_patient(
+pub async fn list_chat_messages(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-    Path(patient_did): Path<String>,
-) -> Result<Json<ApiResponse<Option<Patient>>>, StatusCode> {
-    match state.patient_service.get_patient(&patient_did).await {
-        Ok(patient) => Ok(Json(ApiResponse::success(patient))),
-        Err(e) => {
-            tracing::error!("Failed to get patient: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+    auth: AuthContext,
+    Path(session_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<ChatMessage>>>, ApiError> {
+    let messages = state.chat_service.list_messages(&auth.user_did, &session_id).await?;
+    Ok(Json(ApiResponse::success(messages)))
 }
 
 
-// --- Encounter Handlers ---
-#[derive(Debug, Clone, Deserialize)]
-pub struct CreateEncounterRequest {
-    pub patient_did: String,
-    pub practitioner_did: String,
-    pub class: FhirCoding,
-    pub reason_code: Vec<FhirCodeableConcept>,
-    pub period: FhirPeriod,
+// --- Appointment Handlers ---
+#[axum::debug_handler]
+pub async fn request_appointment(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<CreateAppointmentRequest>,
+) -> Result<Json<ApiResponse<Appointment>>, ApiError> {
+    let appointment = state.appointment_service.request_appointment(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(appointment)))
 }
 
 #[axum::debug_handler]
-pub async fn create_encounter(
+pub async fn confirm_appointment(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-    Json(request): Json<CreateEncounterRequest>,
-) -> Result<Json<ApiResponse<Encounter>>, StatusCode> {
-    match state.encounter_service.create_encounter(request).await {
-        Ok(encounter) => Ok(Json(ApiResponse::success(encounter))),
-        Err(e) => {
-            tracing::error!("Failed to create encounter: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+    auth: AuthContext,
+    Path(appointment_id): Path<String>,
+    request: Option<Json<ConfirmAppointmentRequest>>,
+) -> Result<Json<ApiResponse<Appointment>>, ApiError> {
+    let Json(request) = request.unwrap_or_default();
+    let appointment = state
+        .appointment_service
+        .confirm_appointment(&auth.user_did, &appointment_id, request.create_encounter)
+        .await?;
+    Ok(Json(ApiResponse::success(appointment)))
 }
 
 #[axum::debug_handler]
-pub async fn finalize_encounter(
+pub async fn cancel_appointment(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-    Path(encounter_id): Path<String>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    match state.encounter_service.finalize_encounter(&encounter_id).await {
-        Ok(ipfs_hash) => Ok(Json(ApiResponse::success(ipfs_hash))),
-        Err(e) => {
-            tracing::error!("Failed to finalize encounter: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+    auth: AuthContext,
+    Path(appointment_id): Path<String>,
+) -> Result<Json<ApiResponse<Appointment>>, ApiError> {
+    let appointment = state.appointment_service.cancel_appointment(&auth.user_did, &appointment_id).await?;
+    Ok(Json(ApiResponse::success(appointment)))
+}
+
+#[axum::debug_handler]
+pub async fn list_appointments(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    axum::extract::Query(query): axum::extract::Query<AppointmentQuery>,
+) -> Result<Json<ApiResponse<Vec<Appointment>>>, ApiError> {
+    let appointments = state
+        .appointment_service
+        .list_appointments(&auth.user_did, auth.role, query.role, query.organization_id.as_deref(), query.from, query.to)
+        .await?;
+    Ok(Json(ApiResponse::success(appointments)))
 }
 
+// --- Practitioner and Organization Handlers ---
+#[axum::debug_handler]
+pub async fn register_practitioner(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<CreatePractitionerRequest>,
+) -> Result<Json<ApiResponse<PractitionerRegistration>>, ApiError> {
+    let registration = state.practitioner_service.register(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(registration)))
+}
 
-// --- Verifiable Credential Handlers ---
+#[axum::debug_handler]
+pub async fn request_affiliation(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<RequestAffiliationRequest>,
+) -> Result<Json<ApiResponse<Affiliation>>, ApiError> {
+    let affiliation = state.organization_service.request_affiliation(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(affiliation)))
+}
 
-#[derive(Debug, Deserialize)]
-pub struct GoogleToken {
-    token: String,
+#[axum::debug_handler]
+pub async fn list_organization_affiliations(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(organization_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<Affiliation>>>, ApiError> {
+    let affiliations = state.organization_service.list_affiliations(&auth.user_did, auth.role, &organization_id).await?;
+    Ok(Json(ApiResponse::success(affiliations)))
 }
 
 #[axum::debug_handler]
-pub async fn verify_google_token(
+pub async fn approve_affiliation(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-    Json(token): Json<GoogleToken>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    match state.auth_service.verify_google_token(&token.token).await {
-        Ok(email) => Ok(Json(ApiResponse::success(email))),
-        Err(e) => {
-            tracing::error!("Failed to verify Google token: {}", e);
-            Ok(Json(ApiResponse::error("Invalid Google token".to_string())))
-        }
-    }
+    auth: AuthContext,
+    Path((organization_id, affiliation_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Affiliation>>, ApiError> {
+    let affiliation = state
+        .organization_service
+        .decide_affiliation(&auth.user_did, auth.role, &organization_id, &affiliation_id, true)
+        .await?;
+    Ok(Json(ApiResponse::success(affiliation)))
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct IssueCredentialRequest {
-    pub subject_did: String,
-    pub credential_type: String,
-    pub issuer: String,
-    pub expires_at: Option<u64>,
-    pub metadata: String,
+#[axum::debug_handler]
+pub async fn reject_affiliation(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path((organization_id, affiliation_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Affiliation>>, ApiError> {
+    let affiliation = state
+        .organization_service
+        .decide_affiliation(&auth.user_did, auth.role, &organization_id, &affiliation_id, false)
+        .await?;
+    Ok(Json(ApiResponse::success(affiliation)))
 }
 
+// --- Availability Handlers ---
 #[axum::debug_handler]
-pub async fn issue_credential(
+pub async fn get_availability(
     State(state): State<Arc<AppState<AuthServiceImpl>>>,
-    Json(request): Json<IssueCredentialRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    match state.vc_service.issue_credential(request).await {
-        Ok(transaction_id) => Ok(Json(ApiResponse::success(transaction_id))),
-        Err(e) => {
-            tracing::error!("Failed to issue credential: {}", e);
-            Ok(Json(ApiResponse::error(e.to_string())))
-        }
-    }
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<Option<Availability>>>, ApiError> {
+    let availability = state.availability_service.get_availability(&did).await?;
+    Ok(Json(ApiResponse::success(availability)))
+}
+
+#[axum::debug_handler]
+pub async fn set_availability(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(request): Json<SetAvailabilityRequest>,
+) -> Result<Json<ApiResponse<Availability>>, ApiError> {
+    let availability = state.availability_service.set_availability(&auth.user_did, &did, request).await?;
+    Ok(Json(ApiResponse::success(availability)))
+}
+
+#[axum::debug_handler]
+pub async fn list_slots(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(did): Path<String>,
+    axum::extract::Query(query): axum::extract::Query<SlotQuery>,
+) -> Result<Json<ApiResponse<Vec<Slot>>>, ApiError> {
+    let slots = state.availability_service.slots(&did, query.from, query.to).await?;
+    Ok(Json(ApiResponse::success(slots)))
+}
+
+
+// --- Patient Handlers ---
+#[axum::debug_handler]
+pub async fn search_patients(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Query(query): Query<PatientSearchQuery>,
+) -> Result<Response, ApiError> {
+    let results = state.patient_search_service.search(&auth.user_did, auth.role, &query.q).await?;
+    Ok(format.respond(results, |results| {
+        Some(FhirManager::create_searchset(
+            results
+                .into_iter()
+                .map(|result| {
+                    serde_json::json!({
+                        "resourceType": "Patient",
+                        "id": result.did,
+                        "name": [{ "text": result.name }],
+                        "gender": result.gender,
+                        "birthDate": result.birth_date
+                    })
+                })
+                .collect(),
+        ))
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn get_patient(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    current: CurrentUser,
+    format: Format,
+    Path(patient_did): Path<String>,
+) -> Result<Response, ApiError> {
+    let patient = match current.own_patient(&patient_did) {
+        Some(patient) => Some(state.patient_service.own_record(patient.clone()).await),
+        None => state.patient_service.get_patient(&auth.user_did, &patient_did).await?,
+    };
+    Ok(format.respond(patient, |patient| patient.map(|patient| serde_json::json!(patient.fhir_patient))))
+}
+
+#[axum::debug_handler]
+pub async fn patient_everything(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Path(patient_did): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Always the bare Bundle; only the content type follows the Accept header
+    let bundle = state.patient_service.everything(&auth.user_did, &patient_did).await?;
+    Ok(([(header::CONTENT_TYPE, format.media_type())], Json(bundle)))
+}
+
+#[axum::debug_handler]
+pub async fn request_patient_export(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<PatientExportView>>, ApiError> {
+    let export = state.patient_export_service.request(&auth.user_did, &patient_did).await?;
+    Ok(Json(ApiResponse::success(export)))
+}
+
+#[axum::debug_handler]
+pub async fn get_patient_export(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path((patient_did, export_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<PatientExportView>>, ApiError> {
+    let export = state.patient_export_service.get(&auth.user_did, &patient_did, &export_id).await?;
+    Ok(Json(ApiResponse::success(export)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportDownloadQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+/// The archive behind a signed link from [`get_patient_export`]; the link is the credential
+#[axum::debug_handler]
+pub async fn download_patient_export(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(export_id): Path<String>,
+    Query(query): Query<ExportDownloadQuery>,
+) -> Result<Response, ApiError> {
+    let download = state.patient_export_service.download(&export_id, query.expires, &query.signature).await?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_LENGTH, download.archive.len())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", download.filename))
+        .header(header::CACHE_CONTROL, "private, no-store")
+        .body(Body::from(download.archive))?)
+}
+
+#[axum::debug_handler]
+pub async fn grant_access(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<GrantAccessRequest>,
+) -> Result<Json<ApiResponse<AccessControl>>, ApiError> {
+    let access_control = state.patient_service.grant_access(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(access_control)))
+}
+
+#[axum::debug_handler]
+pub async fn create_relationship(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<CreateRelationshipRequest>,
+) -> Result<Json<ApiResponse<Relationship>>, ApiError> {
+    let relationship = state.relationship_service.establish(&auth.user_did, auth.role, request).await?;
+    Ok(Json(ApiResponse::success(relationship)))
+}
+
+#[axum::debug_handler]
+pub async fn list_relationships(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<Vec<Relationship>>>, ApiError> {
+    let relationships = state.relationship_service.list_relationships(&auth.user_did, &did).await?;
+    Ok(Json(ApiResponse::success(relationships)))
+}
+
+#[axum::debug_handler]
+pub async fn break_glass(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<BreakGlassRequest>,
+) -> Result<Json<ApiResponse<BreakGlassEvent>>, ApiError> {
+    let event = state.break_glass_service.break_glass(&auth.user_did, auth.role, request).await?;
+    Ok(Json(ApiResponse::success(event)))
+}
+
+// --- Referral Handlers ---
+#[axum::debug_handler]
+pub async fn create_referral(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<CreateReferralRequest>,
+) -> Result<Json<ApiResponse<Referral>>, ApiError> {
+    let referral = state.referral_service.create(&auth.user_did, auth.role, request).await?;
+    Ok(Json(ApiResponse::success(referral)))
+}
+
+#[axum::debug_handler]
+pub async fn list_referrals(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<ReferralQuery>,
+) -> Result<Json<ApiResponse<Vec<Referral>>>, ApiError> {
+    let referrals = state.referral_service.list(&auth.user_did, auth.role, query.folder, query.status).await?;
+    Ok(Json(ApiResponse::success(referrals)))
+}
+
+#[axum::debug_handler]
+pub async fn accept_referral(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(referral_id): Path<String>,
+) -> Result<Json<ApiResponse<Referral>>, ApiError> {
+    let referral = state.referral_service.accept(&auth.user_did, &referral_id).await?;
+    Ok(Json(ApiResponse::success(referral)))
+}
+
+#[axum::debug_handler]
+pub async fn reject_referral(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(referral_id): Path<String>,
+    Json(request): Json<UpdateReferralRequest>,
+) -> Result<Json<ApiResponse<Referral>>, ApiError> {
+    let referral = state.referral_service.reject(&auth.user_did, &referral_id, request).await?;
+    Ok(Json(ApiResponse::success(referral)))
+}
+
+#[axum::debug_handler]
+pub async fn complete_referral(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(referral_id): Path<String>,
+    Json(request): Json<UpdateReferralRequest>,
+) -> Result<Json<ApiResponse<Referral>>, ApiError> {
+    let referral = state.referral_service.complete(&auth.user_did, &referral_id, request).await?;
+    Ok(Json(ApiResponse::success(referral)))
+}
+
+// --- Prescription Handlers ---
+#[axum::debug_handler]
+pub async fn create_prescription(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Json(request): Json<CreatePrescriptionRequest>,
+) -> Result<Response, ApiError> {
+    let created = state.prescription_service.create(&auth.user_did, auth.role, request).await?;
+    Ok(format.respond_created(created, |created| Some(serde_json::json!(created.prescription.fhir_medication_request))))
+}
+
+#[axum::debug_handler]
+pub async fn list_prescriptions(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Path(patient_did): Path<String>,
+    Query(query): Query<PrescriptionQuery>,
+) -> Result<Response, ApiError> {
+    let prescriptions = state.prescription_service.list(&auth.user_did, &patient_did, query.status).await?;
+    Ok(format.respond(prescriptions, |prescriptions| {
+        Some(FhirManager::create_searchset(
+            prescriptions.into_iter().map(|prescription| serde_json::json!(prescription.fhir_medication_request)).collect(),
+        ))
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn observation_summary(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+    Query(query): Query<ObservationSummaryQuery>,
+) -> Result<Json<ApiResponse<ObservationSummary>>, ApiError> {
+    let summary = state
+        .patient_service
+        .observation_summary(&auth.user_did, &patient_did, &query.code, query.period, query.from, query.to)
+        .await?;
+    Ok(Json(ApiResponse::success(summary)))
+}
+
+#[axum::debug_handler]
+pub async fn list_problems(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Path(patient_did): Path<String>,
+    Query(query): Query<ProblemListQuery>,
+) -> Result<Response, ApiError> {
+    let problems = state.patient_service.problems(&auth.user_did, &patient_did, query.include_resolved).await?;
+    Ok(format.respond(problems, |problems| {
+        Some(FhirManager::create_searchset(problems.iter().map(|problem| problem_condition(&patient_did, problem)).collect()))
+    }))
+}
+
+/// Codes matching what has been typed so far, for autocomplete
+#[axum::debug_handler]
+pub async fn search_terminology(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(query): Query<TerminologySearchQuery>,
+) -> Result<Json<ApiResponse<Vec<TerminologyConcept>>>, ApiError> {
+    let concepts = state.terminology_service.search(query.system, &query.q, query.limit).await?;
+    Ok(Json(ApiResponse::success(concepts)))
+}
+
+/// Medications matching a name as it is typed, with dose form and strength, for prescribing
+#[axum::debug_handler]
+pub async fn search_medications(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(query): Query<MedicationSearchQuery>,
+) -> Result<Json<ApiResponse<Vec<MedicationConcept>>>, ApiError> {
+    let medications = state.terminology_service.search_medications(&query.q, query.limit)?;
+    Ok(Json(ApiResponse::success(medications)))
+}
+
+#[axum::debug_handler]
+pub async fn update_prescription_status(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(prescription_id): Path<String>,
+    Json(request): Json<UpdatePrescriptionStatusRequest>,
+) -> Result<Json<ApiResponse<Prescription>>, ApiError> {
+    let prescription = state.prescription_service.update_status(&auth.user_did, &prescription_id, request).await?;
+    Ok(Json(ApiResponse::success(prescription)))
+}
+
+#[axum::debug_handler]
+pub async fn dispense_prescription(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(prescription_id): Path<String>,
+    Json(request): Json<DispenseRequest>,
+) -> Result<Json<ApiResponse<Prescription>>, ApiError> {
+    let prescription = state.prescription_service.dispense(&auth.user_did, auth.role, &prescription_id, request).await?;
+    Ok(Json(ApiResponse::success(prescription)))
+}
+
+#[axum::debug_handler]
+pub async fn verify_prescription(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(reference): Json<PrescriptionReference>,
+) -> Result<Json<ApiResponse<PrescriptionVerdict>>, ApiError> {
+    let verdict = state.prescription_service.verify(&client.ip().to_string(), reference).await?;
+    Ok(Json(ApiResponse::success(verdict)))
+}
+
+#[axum::debug_handler]
+pub async fn dispense_verified_prescription(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<VerifiedDispenseRequest>,
+) -> Result<Json<ApiResponse<Prescription>>, ApiError> {
+    let prescription = state.prescription_service.dispense_verified(&auth.user_did, auth.role, request).await?;
+    Ok(Json(ApiResponse::success(prescription)))
+}
+
+#[axum::debug_handler]
+pub async fn create_consent(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(request): Json<CreateConsentRequest>,
+) -> Result<Json<ApiResponse<Consent>>, ApiError> {
+    let consent = state.consent_service.create_consent(&auth.user_did, &did, request).await?;
+    Ok(Json(ApiResponse::success(consent)))
+}
+
+#[axum::debug_handler]
+pub async fn list_consents(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<Vec<Consent>>>, ApiError> {
+    let consents = state.consent_service.list_consents(&auth.user_did, &did).await?;
+    Ok(Json(ApiResponse::success(consents)))
+}
+
+#[axum::debug_handler]
+pub async fn revoke_consent(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path((did, consent_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Consent>>, ApiError> {
+    let consent = state.consent_service.revoke_consent(&auth.user_did, &did, &consent_id).await?;
+    Ok(Json(ApiResponse::success(consent)))
+}
+
+#[axum::debug_handler]
+pub async fn get_notification_preferences(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<NotificationPreferences>>, ApiError> {
+    let preferences = state.patient_service.get_notification_preferences(&auth.user_did, &did).await?;
+    Ok(Json(ApiResponse::success(preferences)))
+}
+
+#[axum::debug_handler]
+pub async fn update_notification_preferences(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(preferences): Json<NotificationPreferences>,
+) -> Result<Json<ApiResponse<NotificationPreferences>>, ApiError> {
+    let preferences = state.patient_service.update_notification_preferences(&auth.user_did, &did, preferences).await?;
+    Ok(Json(ApiResponse::success(preferences)))
+}
+
+/// Register the caller's device for push notifications
+#[axum::debug_handler]
+pub async fn register_device(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<RegisterDeviceRequest>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.notification_service.register_device(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success("The device was registered".to_string())))
+}
+
+// --- Notification Feed Handlers ---
+/// Pull fallback for clients that cannot hold a stream open
+#[axum::debug_handler]
+pub async fn list_notifications(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<NotificationFeedQuery>,
+) -> Result<Json<ApiResponse<Vec<FeedEventView>>>, ApiError> {
+    let events = state.notification_feed_service.list(&auth.user_did, query.since.as_deref()).await?;
+    Ok(Json(ApiResponse::success(events)))
+}
+
+/// Server-sent events for the caller. A client that reconnects with `Last-Event-ID` gets what it
+/// missed first; idle streams carry a heartbeat comment so proxies keep them open.
+#[axum::debug_handler]
+pub async fn notification_stream(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    headers: HeaderMap,
+) -> Result<Sse<BoxStream<'static, Result<Event, axum::Error>>>, ApiError> {
+    let last_event_id = headers.get("last-event-id").and_then(|value| value.to_str().ok());
+    let events = state.notification_feed_service.subscribe(&auth.user_did, last_event_id).await?;
+    let events = events
+        .map(|event| {
+            let view = notification_feed::view(&event);
+            Event::default().id(&view.id).event(&view.event_type).json_data(&view)
+        })
+        .boxed();
+    let heartbeat = std::time::Duration::from_secs(state.config.notification_stream.heartbeat_seconds);
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(heartbeat).text("heartbeat")))
+}
+
+#[axum::debug_handler]
+pub async fn get_chat_record_consent(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<bool>>, ApiError> {
+    let enabled = state.patient_service.get_chat_record_consent(&auth.user_did, &did).await?;
+    Ok(Json(ApiResponse::success(enabled)))
+}
+
+#[axum::debug_handler]
+pub async fn set_chat_record_consent(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(request): Json<ChatConsentRequest>,
+) -> Result<Json<ApiResponse<bool>>, ApiError> {
+    let enabled = state.patient_service.set_chat_record_consent(&auth.user_did, &did, request.enabled).await?;
+    Ok(Json(ApiResponse::success(enabled)))
+}
+
+#[axum::debug_handler]
+pub async fn get_timezone(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<Option<String>>>, ApiError> {
+    let timezone = state.patient_service.get_timezone(&auth.user_did, &did).await?;
+    Ok(Json(ApiResponse::success(timezone)))
+}
+
+#[axum::debug_handler]
+pub async fn set_timezone(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(request): Json<TimezoneRequest>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let timezone = state.patient_service.set_timezone(&auth.user_did, &did, &request.timezone).await?;
+    Ok(Json(ApiResponse::success(timezone)))
+}
+
+// --- Encounter Handlers ---
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateEncounterRequest {
+    pub patient_did: String,
+    pub practitioner_did: String,
+    pub class: FhirCoding,
+    pub reason_code: Vec<FhirCodeableConcept>,
+    pub period: FhirPeriod,
+}
+
+#[axum::debug_handler]
+pub async fn create_encounter(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Json(request): Json<CreateEncounterRequest>,
+) -> Result<Response, ApiError> {
+    let encounter = state.encounter_service.create_encounter(&auth.user_did, auth.role, request).await?;
+    Ok(format.respond_created(encounter, |created| Some(serde_json::json!(created.encounter.fhir_encounter))))
+}
+
+#[axum::debug_handler]
+pub async fn list_encounters(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    axum::extract::Query(query): axum::extract::Query<EncounterQuery>,
+) -> Result<Response, ApiError> {
+    let encounters = state
+        .encounter_service
+        .list_encounters(&auth.user_did, auth.role, query.role, query.organization_id.as_deref(), query.from, query.to)
+        .await?;
+    Ok(format.respond(encounters, |encounters| {
+        Some(FhirManager::create_searchset(encounters.into_iter().map(|encounter| serde_json::json!(encounter.fhir_encounter)).collect()))
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn record_vitals(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    format: Format,
+    Path(encounter_id): Path<String>,
+    Json(request): Json<RecordVitalsRequest>,
+) -> Result<Response, ApiError> {
+    let observations = state.encounter_service.record_vitals(&auth.user_did, auth.role, &encounter_id, request).await?;
+    Ok(format.respond_created(observations, |observations| {
+        Some(FhirManager::create_searchset(observations.into_iter().map(|observation| serde_json::json!(observation)).collect()))
+    }))
+}
+
+#[axum::debug_handler]
+pub async fn update_encounter_status(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(encounter_id): Path<String>,
+    Json(request): Json<UpdateEncounterStatusRequest>,
+) -> Result<Json<ApiResponse<Encounter>>, ApiError> {
+    // Finishing an encounter finalizes it, which takes the same step-up as the finalize route
+    if request.status == EncounterStatus::Finished && auth.second_factor.is_none() {
+        return Err(ServiceError::StepUpRequired.into());
+    }
+    let encounter = state.encounter_service.update_status(&auth.user_did, auth.role, &encounter_id, request).await?;
+    Ok(Json(ApiResponse::success(encounter)))
+}
+
+#[axum::debug_handler]
+pub async fn finalize_encounter(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(encounter_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let ipfs_hash = state.encounter_service.finalize_encounter(&auth.user_did, &encounter_id).await?;
+    Ok(Json(ApiResponse::success(ipfs_hash)))
+}
+
+/// The request body is the file itself. Its type is taken from its content; the declared
+/// `Content-Type` and the file name are not trusted.
+#[axum::debug_handler]
+pub async fn upload_attachment(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(encounter_id): Path<String>,
+    Query(query): Query<AttachmentUploadQuery>,
+    body: Bytes,
+) -> Result<Json<ApiResponse<Attachment>>, ApiError> {
+    let attachment = state.attachment_service.upload(&auth.user_did, auth.role, &encounter_id, query.filename, &body).await?;
+    Ok(Json(ApiResponse::success(attachment)))
+}
+
+/// The request body is a JPEG or PNG of the patient's face; a photo already set is replaced
+#[axum::debug_handler]
+pub async fn upload_patient_photo(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+    body: Bytes,
+) -> Result<Json<ApiResponse<PatientPhoto>>, ApiError> {
+    let photo = state.patient_photo_service.upload(&auth.user_did, auth.role, &patient_did, &body).await?;
+    state.current_users.forget(&patient_did);
+    Ok(Json(ApiResponse::success(photo)))
+}
+
+/// The patient's photo, or its thumbnail with `?size=thumb`. Never cached, as it identifies them.
+#[axum::debug_handler]
+pub async fn get_patient_photo(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+    Query(query): Query<PhotoQuery>,
+) -> Result<Response, ApiError> {
+    let photo = state.patient_photo_service.download(&auth.user_did, &patient_did, query.size).await?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, photo.content_type)
+        .header(header::CONTENT_LENGTH, photo.content.len())
+        .header(header::CACHE_CONTROL, "private, no-store")
+        .body(Body::from(photo.content))?)
+}
+
+// --- File Handlers ---
+/// A file from IPFS, decrypted as it streams. A single `Range` gets `206`, so an interrupted
+/// download can resume with `Range` and `If-Range` set to the `ETag` it started with.
+#[axum::debug_handler]
+pub async fn download_file(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(cid): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let header_value = |name: header::HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+    let download = state
+        .file_service
+        .download(&auth.user_did, &cid, header_value(header::RANGE), header_value(header::IF_RANGE))
+        .await?;
+
+    let (status, content_length, content_range) = match &download.part {
+        FilePart::Full => (StatusCode::OK, download.len, None),
+        FilePart::Range(range) => {
+            (StatusCode::PARTIAL_CONTENT, range.end - range.start, Some(format!("bytes {}-{}/{}", range.start, range.end - 1, download.len)))
+        }
+        FilePart::Unsatisfiable => (StatusCode::RANGE_NOT_SATISFIABLE, 0, Some(format!("bytes */{}", download.len))),
+    };
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, &download.content_type)
+        .header(header::CONTENT_LENGTH, content_length)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &download.etag)
+        .header(header::CACHE_CONTROL, "private, no-store");
+    if let Some(content_range) = content_range {
+        response = response.header(header::CONTENT_RANGE, content_range);
+    }
+    Ok(response.body(Body::from_stream(download.body))?)
+}
+
+
+// --- Verifiable Credential Handlers ---
+
+#[derive(Debug, Deserialize)]
+pub struct GoogleToken {
+    token: String,
+}
+
+#[axum::debug_handler]
+pub async fn verify_google_token(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Json(token): Json<GoogleToken>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    match state.auth_service.verify_google_token(&token.token).await {
+        Ok(email) => Ok(Json(ApiResponse::success(email))),
+        Err(e) => {
+            tracing::error!("Failed to verify Google token: {}", e);
+            Ok(Json(ApiResponse::error("Invalid Google token".to_string())))
+        }
+    }
+}
+
+/// The issuer is always the caller; an `issuer` sent along is ignored
+#[derive(Debug, Clone, Deserialize)]
+pub struct IssueCredentialRequest {
+    pub subject_did: String,
+    pub credential_type: String,
+    /// Unix seconds
+    pub expires_at: Option<u64>,
+    /// A JSON object whose members become claims of the credential's subject
+    pub metadata: String,
+}
+
+/// Issue a credential as the caller, who has to be a registered issuer of its type and, as a
+/// practitioner, hold a verified license
+#[axum::debug_handler]
+pub async fn issue_credential(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<IssueCredentialRequest>,
+) -> Result<Json<ApiResponse<VerifiableCredential>>, ApiError> {
+    let credential = state.vc_service.issue_credential(&auth.user_did, auth.role, request).await?;
+    Ok(Json(ApiResponse::success(credential)))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IssueCredentialBatchRequest {
+    pub credentials: Vec<IssueCredentialRequest>,
+}
+
+/// Issue many credentials as the caller, anchored on the ledger as one Merkle root
+#[axum::debug_handler]
+pub async fn issue_credential_batch(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<IssueCredentialBatchRequest>,
+) -> Result<Json<ApiResponse<IssuedCredentialBatch>>, ApiError> {
+    let batch = state.vc_service.issue_credential_batch(&auth.user_did, auth.role, request.credentials).await?;
+    Ok(Json(ApiResponse::success(batch)))
+}
+
+/// The caller's credential as a W3C Verifiable Credential, for importing into a wallet
+#[axum::debug_handler]
+pub async fn get_credential_document(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(credential_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let document = state.vc_service.credential_document(&auth.user_did, &credential_id).await?;
+    Ok(([(header::CONTENT_TYPE, VC_MEDIA_TYPE)], Json(document)))
+}
+
+/// A published revocation status list, for verifiers checking credentials without an account
+#[axum::debug_handler]
+pub async fn get_status_list(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(list_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Served as published, so the bytes match the copy on IPFS
+    let document = state.status_list_service.document(&list_id).await?;
+    Ok(([(header::CONTENT_TYPE, VC_MEDIA_TYPE)], document))
+}
+
+/// A signed, short-lived QR code presenting the caller's credential to one verifier
+#[axum::debug_handler]
+pub async fn present_credential(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(credential_id): Path<String>,
+    Query(query): Query<PresentationQuery>,
+) -> Result<Json<ApiResponse<CredentialPresentation>>, ApiError> {
+    let presentation = state.vc_service.present_credential(&auth.user_did, &credential_id, &query.audience).await?;
+    Ok(Json(ApiResponse::success(presentation)))
+}
+
+#[axum::debug_handler]
+pub async fn verify_credential_presentation(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<VerifyPresentationRequest>,
+) -> Result<Json<ApiResponse<PresentationVerdict>>, ApiError> {
+    let verdict = state.vc_service.verify_presentation(&auth.user_did, &request.token).await?;
+    Ok(Json(ApiResponse::success(verdict)))
+}
+
+// --- Admin Handlers ---
+#[axum::debug_handler]
+pub async fn admin_delete_patient(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    match state.patient_service.soft_delete_patient(&auth.user_did, &patient_did).await {
+        Ok(()) => {
+            // Their tokens are refused from the next request on
+            state.current_users.forget(&patient_did);
+            Ok(Json(ApiResponse::success(patient_did)))
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete patient: {}", e);
+            Ok(Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+/// Remove a patient's authenticator app without a code, e.g. after they lost the device
+#[axum::debug_handler]
+pub async fn admin_disable_totp(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.totp_service.admin_disable(&auth.user_did, &patient_did).await?;
+    Ok(Json(ApiResponse::success(patient_did)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_restore_patient(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    match state.patient_service.restore_patient(&auth.user_did, &patient_did).await {
+        Ok(()) => {
+            state.current_users.forget(&patient_did);
+            Ok(Json(ApiResponse::success(patient_did)))
+        }
+        Err(e) => {
+            tracing::error!("Failed to restore patient: {}", e);
+            Ok(Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn admin_delete_encounter(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(encounter_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    match state.encounter_service.soft_delete_encounter(&auth.user_did, &encounter_id).await {
+        Ok(()) => Ok(Json(ApiResponse::success(encounter_id))),
+        Err(e) => {
+            tracing::error!("Failed to delete encounter: {}", e);
+            Ok(Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn admin_restore_encounter(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(encounter_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    match state.encounter_service.restore_encounter(&auth.user_did, &encounter_id).await {
+        Ok(()) => Ok(Json(ApiResponse::success(encounter_id))),
+        Err(e) => {
+            tracing::error!("Failed to restore encounter: {}", e);
+            Ok(Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub async fn admin_retry_email(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(email_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.email_service.retry_outbox_email(&email_id).await?;
+    state.audit_log_service.log(&auth.user_did, "retry_email", Some(serde_json::json!({ "email_id": email_id }))).await;
+    Ok(Json(ApiResponse::success(email_id)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_jobs(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(query): Query<AdminJobQuery>,
+) -> Result<Json<ApiResponse<Page<Job>>>, ApiError> {
+    let page = state.job_queue.list(query.status, query.job_type.as_deref(), query.page, query.page_size).await?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_retry_job(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(job_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.job_queue.retry(&job_id).await?;
+    state.audit_log_service.log(&auth.user_did, "retry_job", Some(serde_json::json!({ "job_id": job_id }))).await;
+    Ok(Json(ApiResponse::success(job_id)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_chat_usage(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    axum::extract::Query(query): axum::extract::Query<ChatUsageQuery>,
+) -> Result<Json<ApiResponse<Vec<ChatUsage>>>, ApiError> {
+    let usage = state.chat_service.usage(query.days).await?;
+    Ok(Json(ApiResponse::success(usage)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_break_glass(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    axum::extract::Query(query): axum::extract::Query<BreakGlassQuery>,
+) -> Result<Json<ApiResponse<Vec<BreakGlassEvent>>>, ApiError> {
+    let events = state.break_glass_service.list_events(query.reviewed).await?;
+    Ok(Json(ApiResponse::success(events)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_review_break_glass(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(event_id): Path<String>,
+    Json(request): Json<ReviewBreakGlassRequest>,
+) -> Result<Json<ApiResponse<BreakGlassEvent>>, ApiError> {
+    let event = state.break_glass_service.review(&auth.user_did, &event_id, request).await?;
+    Ok(Json(ApiResponse::success(event)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_register_issuer(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<RegisterIssuerRequest>,
+) -> Result<Json<ApiResponse<TrustedIssuer>>, ApiError> {
+    let issuer = state.issuer_registry.register(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(issuer)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_issuers(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<TrustedIssuer>>>, ApiError> {
+    let issuers = state.issuer_registry.list().await?;
+    Ok(Json(ApiResponse::success(issuers)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_get_issuer(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(did): Path<String>,
+) -> Result<Json<ApiResponse<TrustedIssuer>>, ApiError> {
+    let issuer = state.issuer_registry.get(&did).await?;
+    Ok(Json(ApiResponse::success(issuer)))
+}
+
+/// Rename an issuer, change the credential types it may issue, or suspend or reinstate it
+#[axum::debug_handler]
+pub async fn admin_update_issuer(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(did): Path<String>,
+    Json(request): Json<UpdateIssuerRequest>,
+) -> Result<Json<ApiResponse<TrustedIssuer>>, ApiError> {
+    let issuer = state.issuer_registry.update(&auth.user_did, &did, request).await?;
+    Ok(Json(ApiResponse::success(issuer)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_start_reencryption_job(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<ReencryptionJob>>, ApiError> {
+    let job = state.reencryption_service.start(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(job)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_reencryption_jobs(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<ReencryptionJob>>>, ApiError> {
+    let jobs = state.reencryption_service.list().await?;
+    Ok(Json(ApiResponse::success(jobs)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_get_reencryption_job(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<ApiResponse<ReencryptionJob>>, ApiError> {
+    let job = state.reencryption_service.get(&job_id).await?;
+    Ok(Json(ApiResponse::success(job)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_patients(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<AdminPatientQuery>,
+) -> Result<Json<ApiResponse<Page<PatientSummary>>>, ApiError> {
+    let page = state.admin_service.list_patients(&auth.user_did, query.page, query.page_size).await?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_practitioners(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<AdminPractitionerQuery>,
+) -> Result<Json<ApiResponse<Vec<Practitioner>>>, ApiError> {
+    let practitioners = state.admin_service.list_practitioners(&auth.user_did, query.verified).await?;
+    Ok(Json(ApiResponse::success(practitioners)))
+}
+
+/// Verified licenses expiring soon, and any past their date that the daily job has not revoked yet
+#[axum::debug_handler]
+pub async fn admin_list_expiring_licenses(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<ExpiringLicenseQuery>,
+) -> Result<Json<ApiResponse<Vec<ExpiringLicense>>>, ApiError> {
+    let days = query.days.unwrap_or(license_expiry::WARNING_DAYS);
+    let licenses = state.license_expiry_service.expiring(&auth.user_did, days, chrono::Utc::now().date_naive()).await?;
+    Ok(Json(ApiResponse::success(licenses)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_disable_patient(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.admin_service.disable_patient(&auth.user_did, &patient_did).await?;
+    // Their tokens are refused from the next request on
+    state.current_users.forget(&patient_did);
+    Ok(Json(ApiResponse::success(patient_did)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_enable_patient(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(patient_did): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.admin_service.enable_patient(&auth.user_did, &patient_did).await?;
+    state.current_users.forget(&patient_did);
+    Ok(Json(ApiResponse::success(patient_did)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_scan_duplicate_patients(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let job_id = state.patient_merge_service.request_scan(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(job_id)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_merge_candidates(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Query(query): Query<AdminMergeCandidateQuery>,
+) -> Result<Json<ApiResponse<Page<MergeCandidate>>>, ApiError> {
+    let page = state
+        .patient_merge_service
+        .list_candidates(&auth.user_did, query.status, query.page, query.page_size)
+        .await?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_merge_patients(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(payload): Json<MergePatientsRequest>,
+) -> Result<Json<ApiResponse<PatientMerge>>, ApiError> {
+    let merge = state
+        .patient_merge_service
+        .merge(&auth.user_did, &payload.survivor_did, &payload.duplicate_did)
+        .await?;
+    state.current_users.forget(&payload.survivor_did);
+    state.current_users.forget(&payload.duplicate_did);
+    Ok(Json(ApiResponse::success(merge)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_stats(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<SystemStats>>, ApiError> {
+    let stats = state.admin_service.stats(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(stats)))
+}
+
+/// Read the configuration file and environment again and apply the settings that can change
+/// while the server runs
+#[axum::debug_handler]
+pub async fn reload_config(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<ConfigReload>>, ApiError> {
+    let reload = state.config_reload_service.reload(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(reload)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_audit_stats(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(params): Query<AuditStatsParams>,
+) -> Result<Json<ApiResponse<AuditStats>>, ApiError> {
+    let query = AuditStatsQuery { from: params.from, to: params.to, group_by: params.group_by, top: params.top };
+    let stats = state.audit_analytics_service.stats(query).await?;
+    Ok(Json(ApiResponse::success(stats)))
+}
+
+/// The state of audit anchoring and its latest runs, newest first, with why failed ones failed
+#[axum::debug_handler]
+pub async fn admin_audit_health(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(params): Query<AuditHealthParams>,
+) -> Result<Json<ApiResponse<AnchoringHealth>>, ApiError> {
+    let mut health = state.anchoring_monitor.current().await?;
+    health.runs.truncate(params.runs.unwrap_or(10).min(RUN_HISTORY));
+    Ok(Json(ApiResponse::success(health)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_request_compliance_report(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<ComplianceReportRequest>,
+) -> Result<Json<ApiResponse<ComplianceReport>>, ApiError> {
+    let report = state.compliance_report_service.request(&auth.user_did, request.from, request.to).await?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_compliance_reports(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<ComplianceReport>>>, ApiError> {
+    let reports = state.compliance_report_service.list().await?;
+    Ok(Json(ApiResponse::success(reports)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_get_compliance_report(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(report_id): Path<String>,
+) -> Result<Json<ApiResponse<ComplianceReport>>, ApiError> {
+    let report = state.compliance_report_service.get(&report_id).await?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+/// The signed JSON of a completed report, as a download
+#[axum::debug_handler]
+pub async fn admin_download_compliance_report_json(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(report_id): Path<String>,
+) -> Result<Response, ApiError> {
+    compliance_report_artifact(&state, &auth, &report_id, ReportFormat::Json).await
+}
+
+/// The HTML rendering of a completed report
+#[axum::debug_handler]
+pub async fn admin_download_compliance_report_html(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(report_id): Path<String>,
+) -> Result<Response, ApiError> {
+    compliance_report_artifact(&state, &auth, &report_id, ReportFormat::Html).await
+}
+
+async fn compliance_report_artifact(
+    state: &AppState<AuthServiceImpl>,
+    auth: &AuthContext,
+    report_id: &str,
+    format: ReportFormat,
+) -> Result<Response, ApiError> {
+    let artifact = state.compliance_report_service.artifact(&auth.user_did, report_id, format).await?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, artifact.content_type)
+        .header(header::CONTENT_LENGTH, artifact.content.len())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", artifact.filename))
+        .header(header::CACHE_CONTROL, "private, no-store")
+        .body(Body::from(artifact.content))?)
+}
+
+/// Re-check a report's JSON, as downloaded, against its signature and the ledger
+#[axum::debug_handler]
+pub async fn admin_verify_compliance_report(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Json(document): Json<serde_json::Value>,
+) -> Result<Json<ApiResponse<ComplianceReportVerification>>, ApiError> {
+    let verification = state.compliance_report_service.verify(&document).await?;
+    Ok(Json(ApiResponse::success(verification)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_hedera_costs(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Query(params): Query<HederaCostParams>,
+) -> Result<Json<ApiResponse<HederaCostReport>>, ApiError> {
+    let report = state.hedera_cost_service.report(params.from, params.to, params.group_by).await?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_ip_blocks(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<IpBlock>>>, ApiError> {
+    Ok(Json(ApiResponse::success(state.ip_block_service.blocks())))
+}
+
+#[axum::debug_handler]
+pub async fn admin_unblock_ip(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(ip): Path<String>,
+) -> Result<Json<ApiResponse<IpBlock>>, ApiError> {
+    let block = state.ip_block_service.unblock(&auth.user_did, &ip).await?;
+    Ok(Json(ApiResponse::success(block)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_create_organization(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<OrganizationRequest>,
+) -> Result<Json<ApiResponse<Organization>>, ApiError> {
+    let organization = state.organization_service.create_organization(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(organization)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_organizations(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<Organization>>>, ApiError> {
+    let organizations = state.organization_service.list_organizations().await?;
+    Ok(Json(ApiResponse::success(organizations)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_get_organization(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(organization_id): Path<String>,
+) -> Result<Json<ApiResponse<Organization>>, ApiError> {
+    let organization = state.organization_service.get_organization(&organization_id).await?;
+    Ok(Json(ApiResponse::success(organization)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_update_organization(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(organization_id): Path<String>,
+    Json(request): Json<OrganizationRequest>,
+) -> Result<Json<ApiResponse<Organization>>, ApiError> {
+    let organization = state.organization_service.update_organization(&auth.user_did, &organization_id, request).await?;
+    Ok(Json(ApiResponse::success(organization)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_deactivate_organization(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(organization_id): Path<String>,
+) -> Result<Json<ApiResponse<Organization>>, ApiError> {
+    let organization = state.organization_service.deactivate_organization(&auth.user_did, &organization_id).await?;
+    Ok(Json(ApiResponse::success(organization)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_add_affiliation(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(organization_id): Path<String>,
+    Json(request): Json<CreateAffiliationRequest>,
+) -> Result<Json<ApiResponse<Affiliation>>, ApiError> {
+    let affiliation = state.organization_service.add_affiliation(&auth.user_did, &organization_id, request).await?;
+    Ok(Json(ApiResponse::success(affiliation)))
+}
+
+#[axum::debug_handler]
+pub async fn list_webhooks(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<Vec<WebhookView>>>, ApiError> {
+    let webhooks = state.webhook_service.list_webhooks().await?;
+    Ok(Json(ApiResponse::success(webhooks)))
+}
+
+#[axum::debug_handler]
+pub async fn create_webhook(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Json(request): Json<WebhookRequest>,
+) -> Result<Json<ApiResponse<WebhookView>>, ApiError> {
+    let webhook = state.webhook_service.create_webhook(&auth.user_did, request).await?;
+    Ok(Json(ApiResponse::success(webhook)))
+}
+
+#[axum::debug_handler]
+pub async fn get_webhook(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<ApiResponse<WebhookView>>, ApiError> {
+    let webhook = state.webhook_service.get_webhook(&webhook_id).await?;
+    Ok(Json(ApiResponse::success(webhook)))
+}
+
+#[axum::debug_handler]
+pub async fn update_webhook(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(webhook_id): Path<String>,
+    Json(request): Json<WebhookRequest>,
+) -> Result<Json<ApiResponse<WebhookView>>, ApiError> {
+    let webhook = state.webhook_service.update_webhook(&auth.user_did, &webhook_id, request).await?;
+    Ok(Json(ApiResponse::success(webhook)))
+}
+
+#[axum::debug_handler]
+pub async fn delete_webhook(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    state.webhook_service.delete_webhook(&auth.user_did, &webhook_id).await?;
+    Ok(Json(ApiResponse::success(webhook_id)))
+}
+
+#[axum::debug_handler]
+pub async fn list_webhook_deliveries(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<WebhookDelivery>>>, ApiError> {
+    let deliveries = state.webhook_service.deliveries(&webhook_id).await?;
+    Ok(Json(ApiResponse::success(deliveries)))
+}
+
+#[axum::debug_handler]
+pub async fn test_webhook(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<ApiResponse<WebhookEvent>>, ApiError> {
+    let event = state.webhook_service.send_test(&auth.user_did, &webhook_id).await?;
+    Ok(Json(ApiResponse::success(event)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_event_bus_metrics(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<EventBusMetricsSnapshot>>, ApiError> {
+    Ok(Json(ApiResponse::success(state.event_bus.metrics())))
+}
+
+#[axum::debug_handler]
+pub async fn admin_reminder_metrics(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<ReminderMetricsSnapshot>>, ApiError> {
+    Ok(Json(ApiResponse::success(state.reminder_metrics.snapshot())))
+}
+
+#[axum::debug_handler]
+pub async fn admin_list_fhir_date_issues(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    auth: AuthContext,
+) -> Result<Json<ApiResponse<Vec<FhirDateIssue>>>, ApiError> {
+    let issues = state.admin_service.fhir_date_issues(&auth.user_did).await?;
+    Ok(Json(ApiResponse::success(issues)))
+}
+
+#[axum::debug_handler]
+pub async fn admin_missing_translations() -> Result<Json<ApiResponse<Vec<MissingTranslation>>>, ApiError> {
+    Ok(Json(ApiResponse::success(i18n::missing_translations())))
+}
+
+#[axum::debug_handler]
+pub async fn admin_email_outbox_stats(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+) -> Result<Json<ApiResponse<OutboxStats>>, ApiError> {
+    let stats = state.email_service.outbox_stats().await?;
+    Ok(Json(ApiResponse::success(stats)))
 }
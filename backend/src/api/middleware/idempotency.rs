@@ -0,0 +1,91 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use std::sync::Arc;
+
+use crate::api::error::ApiError;
+use crate::api::middleware::jwt_auth::AuthContext;
+use crate::services::idempotency::IdempotencyClaim;
+use crate::services::{AuthServiceImpl, IdempotencyService};
+use crate::state::AppState;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on responses that were replayed rather than produced by running the request again
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+// Define the idempotency middleware.
+// A request sent with an `Idempotency-Key` claims the key for the caller before it runs, and
+// its response is stored for replays. Sits inside the auth middleware, which keys are scoped by;
+// requests without the header run as usual.
+pub async fn idempotency_middleware(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(key) = req.headers().get(&IDEMPOTENCY_KEY) else {
+        return Ok(next.run(req).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| IdempotencyService::is_valid_key(key))
+        .ok_or_else(|| anyhow::anyhow!("Idempotency-Key must be 1 to 255 visible ASCII characters"))?
+        .to_string();
+    let Some(auth) = req.extensions().get::<AuthContext>().cloned() else {
+        return Ok(next.run(req).await);
+    };
+
+    // The body was already buffered, and capped, by the timeout middleware
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read request body: {}", e))?;
+    let request_hash = IdempotencyService::fingerprint(parts.method.as_str(), parts.uri.path(), &body);
+    let service = &state.idempotency_service;
+    let id = match service.claim(&auth.user_did, &key, &request_hash, Utc::now()).await? {
+        IdempotencyClaim::Replay(record) => return Ok(replay(record.response_status, record.response_content_type, record.response_body)),
+        IdempotencyClaim::Claimed(id) => id,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let status = response.status();
+    // Failures the client is expected to retry free the key instead of being replayed
+    if status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS {
+        if let Err(e) = service.release(id).await {
+            tracing::error!("Failed to release Idempotency-Key {}: {:#}", key, e);
+        }
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            if let Err(e) = service.release(id).await {
+                tracing::error!("Failed to release Idempotency-Key {}: {:#}", key, e);
+            }
+            return Err(anyhow::anyhow!("Failed to read response body: {}", e).into());
+        }
+    };
+    let content_type = parts.headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    // The request already ran; a failure here only means a retry is answered 409 until the claim lapses
+    if let Err(e) = service.complete(id, status.as_u16(), content_type, &String::from_utf8_lossy(&body)).await {
+        tracing::error!("Failed to store the response for Idempotency-Key {}: {:#}", key, e);
+    }
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+fn replay(status: Option<u16>, content_type: Option<String>, body: Option<String>) -> Response {
+    let mut response = Response::new(Body::from(body.unwrap_or_default()));
+    *response.status_mut() = status.and_then(|status| StatusCode::from_u16(status).ok()).unwrap_or(StatusCode::OK);
+    if let Some(content_type) = content_type.and_then(|value| HeaderValue::from_str(&value).ok()) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response.headers_mut().insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
@@ -1,28 +1,167 @@
 use axum::{
-    extract::{State, Request},
-    http::{header, StatusCode},
+    async_trait,
+    extract::{FromRequestParts, State, Request},
+    http::{header, request::Parts, StatusCode},
     middleware::Next,
     response::Response,
 };
 use jsonwebtoken::{decode, Validation, DecodingKey};
-use std::sync::Arc;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
 
+use crate::api::middleware::request_log::LoggedCaller;
+use crate::i18n;
+use crate::models::{AccountStatus, Locale, Patient, Practitioner, Role, SecondFactor};
 use crate::state::AppState;
 use crate::services::AuthService;
-use crate::services::AuthServiceImpl;
+use crate::tenancy::{self, DEFAULT_TENANT};
+
+/// How long an account loaded by `auth_middleware` is reused before it is loaded again
+const CURRENT_USER_TTL: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthClaims {
     pub sub: String, // Subject (user's DID)
     pub exp: usize,  // Expiration time
+    #[serde(default)]
+    pub role: Role,  // Tokens issued before roles existed are treated as patients
+    /// Organization the subject administers, for a future organization-scoped admin role
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    /// The factor a high-assurance token was stepped up with; ordinary sign-in tokens have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second_factor: Option<SecondFactor>,
+    /// The sign-in session the token belongs to; revoking the session rejects the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Tenant (clinic) of a practitioner or tenant admin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// Language the patient saved with their notification preferences, for error messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
 }
 
 #[derive(Clone)]
 pub struct AuthContext {
     pub user_did: String,
+    pub role: Role,
+    pub org_id: Option<String>,
+    pub second_factor: Option<SecondFactor>,
+    pub session_id: Option<String>,
+    pub tenant_id: Option<String>,
+    pub locale: Option<Locale>,
+}
+
+impl AuthContext {
+    /// The tenant the caller's requests are confined to. Platform admins see every tenant and
+    /// patients are global; practitioners and tenant admins whose tokens predate tenancy
+    /// belong to the default tenant.
+    pub fn tenant_scope(&self) -> Option<String> {
+        match self.role {
+            Role::PlatformAdmin => None,
+            Role::Practitioner | Role::Admin => Some(self.tenant_id.clone().unwrap_or_else(|| DEFAULT_TENANT.to_string())),
+            // Registered practitioners sign in with a patient token that names their tenant
+            Role::Patient => self.tenant_id.clone(),
+        }
+    }
+}
+
+/// Handlers take the caller as an `AuthContext` argument and act as `user_did`, never as a DID
+/// from the request body. A route mounted outside `auth_middleware` has no caller and gets 401.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthContext {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<AuthContext>().cloned().ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// The caller's own records, loaded by `auth_middleware` alongside the [`AuthContext`] so
+/// handlers do not fetch and decrypt them again
+#[derive(Clone)]
+pub struct CurrentUser {
+    pub did: String,
+    pub role: Role,
+    /// The record of a patient token's subject; None for other roles and for subjects without one
+    pub patient: Option<Patient>,
+    /// The practitioner record of a practitioner token's subject, in their tenant
+    pub practitioner: Option<Practitioner>,
+}
+
+impl CurrentUser {
+    /// The caller's record, when `did` is the caller themselves
+    pub fn own_patient(&self, did: &str) -> Option<&Patient> {
+        self.patient.as_ref().filter(|_| did == self.did)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CurrentUser>().cloned().ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// What `auth_middleware` loaded for one caller
+#[derive(Clone, Default)]
+struct Account {
+    patient: Option<(Patient, AccountStatus)>,
+    practitioner: Option<Practitioner>,
+}
+
+/// The DID, role and tenant an account was loaded for
+type AccountKey = (String, Role, Option<String>);
+
+/// Accounts `auth_middleware` loaded in the last few seconds, so a burst of requests from one
+/// caller costs one lookup and decryption. Handlers that suspend, delete or change a patient
+/// [`forget`](Self::forget) them, so the next request to this instance sees the change. The cache
+/// is per instance: others keep serving their copy until it expires after [`CURRENT_USER_TTL`].
+#[derive(Default)]
+pub struct CurrentUserCache {
+    accounts: Mutex<HashMap<AccountKey, (Instant, Account)>>,
 }
 
+impl CurrentUserCache {
+    fn get(&self, key: &AccountKey) -> Option<Account> {
+        let accounts = self.accounts.lock().ok()?;
+        accounts.get(key).filter(|(loaded_at, _)| loaded_at.elapsed() < CURRENT_USER_TTL).map(|(_, account)| account.clone())
+    }
+
+    fn put(&self, key: AccountKey, account: Account) {
+        if let Ok(mut accounts) = self.accounts.lock() {
+            accounts.retain(|_, (loaded_at, _)| loaded_at.elapsed() < CURRENT_USER_TTL);
+            accounts.insert(key, (Instant::now(), account));
+        }
+    }
+
+    /// Drop whatever is cached for `did`, under any role or tenant
+    pub fn forget(&self, did: &str) {
+        if let Ok(mut accounts) = self.accounts.lock() {
+            accounts.retain(|(cached_did, _, _), _| cached_did != did);
+        }
+    }
+}
+
+/// The patient record of a patient token's subject, or the practitioner record of a
+/// practitioner token's subject; admins have neither loaded
+async fn load_account<T: AuthService>(state: &AppState<T>, context: &AuthContext, tenant: Option<String>) -> anyhow::Result<Account> {
+    match context.role {
+        Role::Patient => Ok(Account { patient: state.auth_service.get_patient_by_did(&context.user_did).await?, practitioner: None }),
+        // Looked up in the caller's tenant, like everything else the request reads
+        Role::Practitioner => {
+            let practitioner = tenancy::scope(tenant, state.database.get_practitioner_by_did(&context.user_did)).await?;
+            Ok(Account { patient: None, practitioner })
+        }
+        Role::Admin | Role::PlatformAdmin => Ok(Account::default()),
+    }
+}
 
 // Define the authentication middleware
 pub async fn auth_middleware<T: AuthService>(
@@ -49,15 +188,74 @@ pub async fn auth_middleware<T: AuthService>(
     };
 
     let validation = Validation::default();
-    let decoding_key = DecodingKey::from_secret(state.config.jwt_secret.as_ref());
+    let decoding_key = DecodingKey::from_secret(state.config.jwt_secret.expose_secret().as_bytes());
 
     match decode::<AuthClaims>(&token, &decoding_key, &validation) {
         Ok(token_data) => {
+            if let Some(session_id) = &token_data.claims.sid {
+                match state.session_service.is_active(session_id).await {
+                    Ok(true) => {}
+                    Ok(false) => return Err(StatusCode::UNAUTHORIZED),
+                    Err(e) => {
+                        tracing::error!("Failed to check session {}: {}", session_id, e);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                }
+            }
             let auth_context = AuthContext {
                 user_did: token_data.claims.sub,
+                role: token_data.claims.role,
+                org_id: token_data.claims.org_id,
+                second_factor: token_data.claims.second_factor,
+                session_id: token_data.claims.sid,
+                tenant_id: token_data.claims.tenant_id,
+                locale: token_data.claims.locale,
+            };
+            // Everything the request reads or writes in tenant-scoped collections stays in this tenant
+            let tenant = auth_context.tenant_scope();
+            let key = (auth_context.user_did.clone(), auth_context.role, tenant.clone());
+            let account = match state.current_users.get(&key) {
+                Some(account) => account,
+                None => match load_account(&state, &auth_context, tenant.clone()).await {
+                    Ok(account) => {
+                        state.current_users.put(key, account.clone());
+                        account
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to load the account of {}: {}", auth_context.user_did, e);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                },
             };
+            // A token can outlive the suspension or deletion of its account
+            if let Some((_, status @ (AccountStatus::Disabled | AccountStatus::Deleted))) = &account.patient {
+                let details = json!({ "reason": status.as_str(), "session_id": auth_context.session_id });
+                state.audit_log_service.log(&auth_context.user_did, "token_rejected", Some(details)).await;
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+            let current_user = CurrentUser {
+                did: auth_context.user_did.clone(),
+                role: auth_context.role,
+                patient: account.patient.map(|(patient, _)| patient),
+                practitioner: account.practitioner,
+            };
+            let locale = auth_context.locale;
+            let caller = LoggedCaller(auth_context.user_did.clone());
             req.extensions_mut().insert(auth_context);
-            Ok(next.run(req).await)
+            req.extensions_mut().insert(current_user);
+            let response = tenancy::scope(tenant, next.run(req));
+            let mut response = match locale {
+                // A saved language wins over Accept-Language; the locale middleware reads it off the response
+                Some(locale) => {
+                    let mut response = i18n::scope(locale, response).await;
+                    response.extensions_mut().insert(locale);
+                    response
+                }
+                None => response.await,
+            };
+            // The request log only sees the response, and hashes the DID before writing it
+            response.extensions_mut().insert(caller);
+            Ok(response)
         }
         Err(_) => {
             // Token is invalid
@@ -65,21 +263,3 @@ pub async fn auth_middleware<T: AuthService>(
         }
     }
 }
-
-// Define the high-assurance authentication middleware
-pub async fn high_assurance_auth_middleware(State(_state): State<Arc<AppState<AuthServiceImpl>>>, req: Request, next: Next) -> Result<Response, StatusCode> {
-    let auth_context = req.extensions().get::<AuthContext>().cloned();
-
-    if let Some(auth_context) = auth_context {
-        // In a real implementation, we would check for a high-assurance session.
-        // For now, we'll just check if the user is authenticated.
-        // This can be extended to check for a recent login, a second factor, etc.
-        if !auth_context.user_did.is_empty() {
-            Ok(next.run(req).await)
-        } else {
-            Err(StatusCode::UNAUTHORIZED)
-        }
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
-    }
-}
\ No newline at end of file
@@ -0,0 +1,128 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use http_body_util::LengthLimitError;
+use std::error::Error as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{timeout, timeout_at, Duration, Instant};
+
+use crate::api::error::ApiError;
+use crate::api::middleware::jwt_auth::AuthContext;
+use crate::config::HttpConfig;
+use crate::deadline;
+use crate::services::{AuthServiceImpl, ServiceError};
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTimeouts {
+    pub body: Duration,
+    pub handler: Duration,
+    /// Handler budget of the routes in `upstream_routes`
+    pub upstream: Duration,
+    /// Route templates that wait on IPFS, Hedera or Gemini
+    pub upstream_routes: &'static [&'static str],
+}
+
+impl From<&HttpConfig> for RequestTimeouts {
+    fn from(config: &HttpConfig) -> Self {
+        Self {
+            body: Duration::from_secs(config.body_timeout_seconds),
+            handler: Duration::from_secs(config.request_timeout_seconds),
+            upstream: Duration::from_secs(config.upstream_timeout_seconds),
+            upstream_routes: &[],
+        }
+    }
+}
+
+impl RequestTimeouts {
+    /// Give the routes with these templates the upstream budget
+    pub fn with_upstream_routes(self, upstream_routes: &'static [&'static str]) -> Self {
+        Self { upstream_routes, ..self }
+    }
+
+    fn handler_budget(&self, route: Option<&str>) -> Duration {
+        match route {
+            Some(route) if self.upstream_routes.contains(&route) => self.upstream,
+            _ => self.handler,
+        }
+    }
+}
+
+// Define the timeout middleware.
+// The body is read up front so a slow client (408) is told apart from a slow
+// handler waiting on IPFS or Hedera (504). Size is capped by the
+// RequestBodyLimitLayer outside this middleware.
+// The handler gets the budget of its route. When it runs out the handler is
+// dropped mid-way, so nothing more is done for a caller that was sent 504; the
+// calls it makes get the deadline too, through `deadline`.
+pub async fn timeout_middleware(
+    State(timeouts): State<RequestTimeouts>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let (parts, body) = req.into_parts();
+    let bytes = match timeout(timeouts.body, axum::body::to_bytes(body, usize::MAX)).await {
+        Err(_) => return Err(ServiceError::RequestTimeout.into()),
+        Ok(Err(e)) if is_length_limit(&e) => return Err(ServiceError::PayloadTooLarge.into()),
+        Ok(Err(e)) => return Err(anyhow::anyhow!("Failed to read request body: {}", e).into()),
+        Ok(Ok(bytes)) => bytes,
+    };
+    let req = Request::from_parts(parts, Body::from(bytes));
+
+    let route = req.extensions().get::<MatchedPath>().map(MatchedPath::as_str);
+    let deadline = Instant::now() + timeouts.handler_budget(route);
+    timeout_at(deadline, deadline::scope(deadline, next.run(req)))
+        .await
+        .map_err(|_| ServiceError::Timeout.into())
+}
+
+// Define the sign-in block middleware.
+// Addresses blocked for failing to sign in too often get 429 from the sign-in endpoints.
+// Requests without a known client address are let through.
+pub async fn ip_block_middleware(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(ConnectInfo(client)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        state.ip_block_service.check(client.ip())?;
+    }
+    Ok(next.run(req).await)
+}
+
+// Define the notification stream limit middleware (runs after auth_middleware).
+// Each user holds at most NOTIFICATION_STREAMS_PER_USER streams open, or gets 429. The body
+// keeps the stream's place until the client disconnects and the body is dropped.
+pub async fn notification_stream_limit_middleware(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(auth) = req.extensions().get::<AuthContext>() else {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    };
+    let permit = state.notification_feed_service.open_stream(&auth.user_did)?;
+    let (parts, body) = next.run(req).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _place = &permit;
+        chunk
+    });
+    Ok(Response::from_parts(parts, Body::from_stream(body)))
+}
+
+fn is_length_limit(error: &axum::Error) -> bool {
+    let mut source = error.source();
+    while let Some(inner) = source {
+        if inner.is::<LengthLimitError>() {
+            return true;
+        }
+        source = inner.source();
+    }
+    false
+}
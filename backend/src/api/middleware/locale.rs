@@ -0,0 +1,46 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::i18n;
+use crate::models::{ApiResponse, Locale};
+
+/// How axum starts the text of its 422 for a JSON body that does not fit the handler's type
+const JSON_REJECTION_PREFIX: &str = "Failed to deserialize the JSON body into the target type: ";
+
+// Define the locale middleware.
+// The request runs in the language its Accept-Language header prefers, English by default;
+// the auth middleware switches to the caller's saved language when their token carries one.
+// Axum answers a body that does not fit the handler's type with a plain-text 422, which is
+// replaced here by the usual envelope in the caller's language.
+pub async fn locale_middleware(req: Request, next: Next) -> Response {
+    let requested = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(i18n::from_accept_language)
+        .unwrap_or_default();
+    let response = i18n::scope(requested, next.run(req)).await;
+    if response.status() != StatusCode::UNPROCESSABLE_ENTITY || !is_plain_text(&response) {
+        return response;
+    }
+
+    let locale = response.extensions().get::<Locale>().copied().unwrap_or(requested);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap_or_default();
+    let rejection = String::from_utf8_lossy(&body);
+    let detail = rejection.strip_prefix(JSON_REJECTION_PREFIX).unwrap_or(&rejection);
+    let message = i18n::text(locale, "error.invalid_body", &[("detail", detail)]);
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(ApiResponse::<()>::error_with_code(message, "invalid_body"))).into_response()
+}
+
+fn is_plain_text(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain"))
+}
@@ -1 +1,5 @@
-pub mod jwt_auth;
\ No newline at end of file
+pub mod idempotency;
+pub mod jwt_auth;
+pub mod limits;
+pub mod locale;
+pub mod request_log;
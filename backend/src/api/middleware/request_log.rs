@@ -0,0 +1,170 @@
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use sentry::{Hub, SentryFutureExt};
+use http_body_util::BodyExt;
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::Instrument;
+
+use crate::config::Config;
+use crate::{error_reporting, telemetry};
+
+/// Correlates a response with its log lines; taken from the request when it is sensible, else generated
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Logged instead of the route template of routes listed in `LOG_REDACTED_ROUTES`
+const REDACTED_ROUTE: &str = "[redacted]";
+
+/// The DID of the authenticated caller, left on the response by the auth middleware
+#[derive(Debug, Clone)]
+pub struct LoggedCaller(pub String);
+
+/// The internal error chain behind an error response, left on the response by `ApiError`
+#[derive(Debug, Clone)]
+pub struct LoggedError(pub String);
+
+// Define the request log middleware.
+// One line per request with the method, route template, status, latency, request id, a keyed
+// hash of the caller's DID and the body sizes. Paths (which carry DIDs and ids), query strings,
+// headers and bodies are never logged. The line is written once the response body has been sent,
+// so latency and the response size cover streamed downloads too. Error responses also log their
+// internal error chain under the same request id: at WARN, or at ERROR for server errors, which
+// are then reported along with anything else the request logged at ERROR.
+pub async fn request_log_middleware(State(config): State<Arc<Config>>, req: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_usable_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) if config.logging.redacted_routes.iter().any(|route| route == path.as_str()) => REDACTED_ROUTE.to_string(),
+        Some(path) => path.as_str().to_string(),
+        None => "[unmatched]".to_string(),
+    };
+    let method = req.method().to_string();
+
+    // Counts what the handler actually read, which a missing or wrong Content-Length cannot skew
+    let request_bytes = Arc::new(AtomicU64::new(0));
+    let counter = request_bytes.clone();
+    let req = req.map(|body| {
+        Body::new(body.map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+                counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+            frame
+        }))
+    });
+
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = %route,
+        http.response.status_code = tracing::field::Empty,
+        request_id = %request_id,
+    );
+    telemetry::continue_trace(&span, req.headers());
+    let hub = error_reporting::request_hub(&route, &request_id);
+    let mut response = next.run(req).instrument(span.clone()).bind_hub(hub.clone()).await;
+    let status = response.status().as_u16();
+    span.record("http.response.status_code", status);
+    if let Some(LoggedError(chain)) = response.extensions().get::<LoggedError>() {
+        if response.status().is_server_error() {
+            Hub::run(hub, || tracing::error!(request_id = %request_id, route = %route, status, "Request failed: {}", chain));
+        } else {
+            tracing::warn!(request_id = %request_id, route = %route, status, "Request failed: {}", chain);
+        }
+    }
+    let caller = response.extensions().get::<LoggedCaller>().map(|LoggedCaller(did)| caller_hash(did, config.jwt_secret.expose_secret()));
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    let mut entry = LogEntry { method, route, status, request_id, caller, started, request_bytes, response_bytes: 0 };
+    response.map(|body| {
+        // Wrapping the body keeps its size hint, so Content-Length is still set for ordinary responses
+        Body::new(body.map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+                entry.response_bytes += data.len() as u64;
+            }
+            frame
+        }))
+    })
+}
+
+/// Up to 64 letters, digits, `-`, `_` and `.`; anything else could smuggle data into the logs
+fn is_usable_request_id(id: &str) -> bool {
+    (1..=64).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Keyed so that log readers cannot confirm a guessed DID, yet stable so one caller's requests line up
+fn caller_hash(did: &str, key: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"request-log:");
+    mac.update(did.as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..8])
+}
+
+/// Written when dropped, which is when the server is done with the response body
+struct LogEntry {
+    method: String,
+    route: String,
+    status: u16,
+    request_id: String,
+    caller: Option<String>,
+    started: Instant,
+    request_bytes: Arc<AtomicU64>,
+    response_bytes: u64,
+}
+
+impl Drop for LogEntry {
+    fn drop(&mut self) {
+        tracing::info!(
+            method = %self.method,
+            route = %self.route,
+            status = self.status,
+            latency_ms = self.started.elapsed().as_millis() as u64,
+            request_id = %self.request_id,
+            caller = self.caller.as_deref(),
+            request_bytes = self.request_bytes.load(Ordering::Relaxed),
+            response_bytes = self.response_bytes,
+            "Request completed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_request_ids_are_taken_from_the_client() {
+        assert!(is_usable_request_id("3f2b9c4e-8d1a-4c55-9a0e-1b2c3d4e5f60"));
+        assert!(is_usable_request_id("lb.7781_a"));
+        assert!(!is_usable_request_id(""));
+        assert!(!is_usable_request_id(&"a".repeat(65)));
+        assert!(!is_usable_request_id("jane@example.com"));
+        assert!(!is_usable_request_id("abc\nstatus=500"));
+    }
+
+    #[test]
+    fn callers_get_a_stable_hash_that_depends_on_the_key() {
+        let did = "did:hedera:testnet:0.0.1";
+        assert_eq!(caller_hash(did, "secret"), caller_hash(did, "secret"));
+        assert_ne!(caller_hash(did, "secret"), caller_hash("did:hedera:testnet:0.0.2", "secret"));
+        assert_ne!(caller_hash(did, "secret"), caller_hash(did, "another secret"));
+        assert_eq!(caller_hash(did, "secret").len(), 16);
+    }
+}
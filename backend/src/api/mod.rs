@@ -1,4 +1,7 @@
+pub mod capability;
+pub mod error;
 pub mod handlers;
 pub mod middleware;
-
-
+pub mod negotiation;
+pub mod policy;
+pub mod routes;
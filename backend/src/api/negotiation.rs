@@ -0,0 +1,227 @@
+//! FHIR responses for integration partners on the clinical routes.
+//!
+//! A caller whose `Accept` header prefers `application/fhir+json` gets the bare resource or
+//! searchset Bundle with that content type, and errors as an `OperationOutcome`. Everyone else
+//! keeps the `ApiResponse` envelope. The clinical routes are the ones listed in
+//! [`FHIR_ROUTES`], so the capability statement describes exactly the routes that negotiate.
+//!
+//! Handlers answer through [`Format::respond`]; [`fhir_format_middleware`] turns the errors
+//! of those routes into an `OperationOutcome`, whichever layer they came from.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, Request},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+
+use crate::api::capability::{FHIR_MEDIA_TYPE, FHIR_ROUTES};
+use crate::models::{ApiResponse, Problem};
+
+/// Media types FHIR servers have been sent for JSON; the second is from DSTU2
+const FHIR_MEDIA_TYPES: &[&str] = &[FHIR_MEDIA_TYPE, "application/json+fhir"];
+const JSON_MEDIA_TYPES: &[&str] = &["application/json", "application/*", "*/*"];
+
+/// Which shape a clinical response takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Our `ApiResponse` envelope
+    #[default]
+    Envelope,
+    /// The bare FHIR resource or Bundle, and `OperationOutcome` for errors
+    Fhir,
+}
+
+impl Format {
+    /// FHIR when the `Accept` header ranks the FHIR media type at least as high as JSON
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()) else {
+            return Self::Envelope;
+        };
+        let quality = |media_types: &[&str]| {
+            accept
+                .split(',')
+                .filter_map(|range| {
+                    let mut parts = range.split(';').map(str::trim);
+                    let media_type = parts.next()?.to_ascii_lowercase();
+                    let q = parts.find_map(|param| param.strip_prefix("q=")).map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                    media_types.contains(&media_type.as_str()).then_some(q)
+                })
+                .fold(0.0_f32, f32::max)
+        };
+        let fhir = quality(FHIR_MEDIA_TYPES);
+        if fhir > 0.0 && fhir >= quality(JSON_MEDIA_TYPES) {
+            Self::Fhir
+        } else {
+            Self::Envelope
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Envelope => "application/json",
+            Self::Fhir => FHIR_MEDIA_TYPE,
+        }
+    }
+
+    /// `data` in the envelope, or what `fhir` makes of it. `fhir` returning `None` means there is
+    /// no resource to send, which FHIR answers with 404.
+    pub fn respond<T: Serialize>(self, data: T, fhir: impl FnOnce(T) -> Option<Value>) -> Response {
+        self.respond_with_status(StatusCode::OK, data, fhir)
+    }
+
+    /// [`Format::respond`] for a route that creates a resource, which FHIR answers with 201
+    pub fn respond_created<T: Serialize>(self, data: T, fhir: impl FnOnce(T) -> Option<Value>) -> Response {
+        self.respond_with_status(StatusCode::CREATED, data, fhir)
+    }
+
+    fn respond_with_status<T: Serialize>(self, status: StatusCode, data: T, fhir: impl FnOnce(T) -> Option<Value>) -> Response {
+        match self {
+            Self::Envelope => Json(ApiResponse::success(data)).into_response(),
+            Self::Fhir => match fhir(data) {
+                Some(resource) => (status, [(header::CONTENT_TYPE, FHIR_MEDIA_TYPE)], Json(resource)).into_response(),
+                None => outcome_response(StatusCode::NOT_FOUND, "Resource not found"),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// An `OperationOutcome` with one error issue
+pub fn operation_outcome(code: &str, diagnostics: &str) -> Value {
+    json!({
+        "resourceType": "OperationOutcome",
+        "issue": [{ "severity": "error", "code": code, "diagnostics": diagnostics }]
+    })
+}
+
+/// The FHIR issue type for an error status
+pub fn issue_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::UNAUTHORIZED => "login",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not-found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "too-long",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE | StatusCode::NOT_IMPLEMENTED => "not-supported",
+        StatusCode::TOO_MANY_REQUESTS => "throttled",
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => "timeout",
+        StatusCode::SERVICE_UNAVAILABLE => "transient",
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => "invalid",
+        status if status.is_server_error() => "exception",
+        _ => "processing",
+    }
+}
+
+fn outcome_response(status: StatusCode, diagnostics: &str) -> Response {
+    (status, [(header::CONTENT_TYPE, FHIR_MEDIA_TYPE)], Json(operation_outcome(issue_code(status), diagnostics))).into_response()
+}
+
+/// A problem-list entry as a Condition, under the id of the last Condition merged into it
+pub fn problem_condition(patient_did: &str, problem: &Problem) -> Value {
+    let mut condition = json!({
+        "resourceType": "Condition",
+        "clinicalStatus": {
+            "coding": [{ "system": "http://terminology.hl7.org/CodeSystem/condition-clinical", "code": problem.clinical_status }]
+        },
+        "code": {
+            "coding": [{ "system": problem.system, "code": problem.code, "display": problem.display }],
+            "text": problem.display
+        },
+        "subject": { "reference": format!("Patient/{}", patient_did) },
+        "onsetDateTime": problem.onset_date_time,
+        "recordedDate": problem.recorded_date
+    });
+    if let Some(id) = problem.condition_ids.last() {
+        condition["id"] = json!(id);
+    }
+    if let Some(encounter_id) = problem.encounter_ids.last() {
+        condition["encounter"] = json!({ "reference": format!("Encounter/{}", encounter_id) });
+    }
+    condition
+}
+
+/// Answers a clinical route's errors with an `OperationOutcome` when the caller asked for FHIR,
+/// keeping the status and headers such as `Retry-After`. Errors the envelope reports with 200
+/// get 400, since FHIR clients only look for errors in 4xx and 5xx responses.
+pub async fn fhir_format_middleware(req: Request, next: Next) -> Response {
+    let clinical = req
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|path| FHIR_ROUTES.iter().any(|route| route.path == path.as_str()));
+    let format = if clinical { Format::from_headers(req.headers()) } else { Format::Envelope };
+    let response = next.run(req).await;
+    let is_fhir = response.headers().get(header::CONTENT_TYPE).is_some_and(|value| value.as_bytes() == FHIR_MEDIA_TYPE.as_bytes());
+    if format != Format::Fhir || is_fhir || (response.status().is_success() && !is_json(&response)) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let envelope: Option<ApiResponse<Value>> = serde_json::from_slice(&body).ok();
+    if envelope.as_ref().is_some_and(|envelope| envelope.success) {
+        return Response::from_parts(parts, body.into());
+    }
+    let status = if parts.status.is_success() { StatusCode::BAD_REQUEST } else { parts.status };
+    let diagnostics = envelope
+        .and_then(|envelope| envelope.error)
+        .unwrap_or_else(|| status.canonical_reason().unwrap_or("Request failed").to_string());
+    parts.status = status;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(FHIR_MEDIA_TYPE));
+    let outcome = serde_json::to_vec(&operation_outcome(issue_code(status), &diagnostics)).unwrap_or_default();
+    Response::from_parts(parts, outcome.into())
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(accept: &str) -> Format {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        Format::from_headers(&headers)
+    }
+
+    #[test]
+    fn the_fhir_media_type_must_be_preferred() {
+        assert_eq!(Format::from_headers(&HeaderMap::new()), Format::Envelope);
+        assert_eq!(accepting("application/json"), Format::Envelope);
+        assert_eq!(accepting("*/*"), Format::Envelope);
+        assert_eq!(accepting("application/fhir+json"), Format::Fhir);
+        assert_eq!(accepting("application/fhir+json, application/json"), Format::Fhir);
+        assert_eq!(accepting("application/json+fhir"), Format::Fhir);
+        assert_eq!(accepting("application/fhir+json;q=0.5, application/json"), Format::Envelope);
+        assert_eq!(accepting("application/json;q=0.2, application/fhir+json; q=0.9"), Format::Fhir);
+        assert_eq!(accepting("application/fhir+json;q=0"), Format::Envelope);
+    }
+
+    #[test]
+    fn errors_map_to_issue_types() {
+        assert_eq!(issue_code(StatusCode::FORBIDDEN), "forbidden");
+        assert_eq!(issue_code(StatusCode::UNAUTHORIZED), "login");
+        assert_eq!(issue_code(StatusCode::TOO_MANY_REQUESTS), "throttled");
+        assert_eq!(issue_code(StatusCode::INTERNAL_SERVER_ERROR), "exception");
+        assert_eq!(operation_outcome("not-found", "gone")["issue"][0], json!({ "severity": "error", "code": "not-found", "diagnostics": "gone" }));
+    }
+}
@@ -0,0 +1,290 @@
+//! Who may call each route behind `auth_middleware`.
+//!
+//! Every authenticated route has an entry in [`POLICIES`] declaring what the caller needs, and
+//! [`policy_middleware`] checks it once `auth_middleware` has resolved the caller. A caller
+//! without what a route needs gets 401 when stepping up would help and 403 otherwise, and the
+//! refusal is audit-logged as `access_denied`. A route missing from the table is refused, and
+//! the `policy` integration test fails for it, so forgetting one cannot leave a route open.
+//!
+//! Policies check what the route itself names: the caller's role, their step-up, and their
+//! relation to a patient in the path. Services still check what only the stored record can tell,
+//! such as who an encounter or prescription belongs to.
+
+use axum::{
+    extract::{MatchedPath, RawPathParams, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use futures_util::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::api::middleware::jwt_auth::AuthContext;
+use crate::models::{Permission, Role as UserRole};
+use crate::services::AuthServiceImpl;
+use crate::state::AppState;
+
+/// What a caller needs to be let through to a route
+#[derive(Debug)]
+pub enum Requirement {
+    /// Any signed-in caller; the service decides what they may see
+    SignedIn,
+    /// The caller is the patient whose DID is in this path parameter
+    Owner(&'static str),
+    /// The caller has this role. Platform admins also meet `Role(Role::Admin)`.
+    Role(UserRole),
+    /// The caller may see what this permission covers of the record of the patient whose DID is
+    /// in the path parameter: as the patient, their guardian, a grantee or through break-glass
+    Grant(&'static str, Permission),
+    /// The caller stepped up with a second factor
+    HighAssurance,
+    /// Every one of these, checked in order
+    All(&'static [Requirement]),
+    /// At least one of these
+    Any(&'static [Requirement]),
+}
+
+use Requirement::*;
+
+const ADMIN: Requirement = Role(UserRole::Admin);
+const PLATFORM_ADMIN: Requirement = Role(UserRole::PlatformAdmin);
+const PRACTITIONER: Requirement = Role(UserRole::Practitioner);
+
+/// The requirement of every authenticated route, by its path as registered in `build_router`
+pub const POLICIES: &[(&str, Requirement)] = &[
+    // Patients: reads go through the patient's grants, settings are the patient's own
+    ("/api/patients/search", PRACTITIONER),
+    ("/api/patients/:id", Grant("id", Permission::Read)),
+    ("/api/patients/:id/$everything", Grant("id", Permission::Read)),
+    // The front desk checks the photo; the service also requires `Write` to replace it
+    ("/api/patients/:id/photo", Any(&[Grant("id", Permission::Read), Grant("id", Permission::Write)])),
+    ("/api/patients/:id/consents", Owner("id")),
+    ("/api/patients/:id/consents/:consent_id/revoke", Owner("id")),
+    ("/api/patients/:id/preferences", Owner("id")),
+    ("/api/patients/:id/chat-consent", Owner("id")),
+    ("/api/patients/:id/timezone", Owner("id")),
+    ("/api/patients/:id/relationships", Owner("id")),
+    ("/api/patients/:id/prescriptions", Grant("id", Permission::ViewPrescriptions)),
+    ("/api/patients/:id/observations/summary", Grant("id", Permission::ViewObservations)),
+    ("/api/patients/:id/problems", Grant("id", Permission::ViewEncounters)),
+    // Exports hold the whole record, so asking for one and fetching its link need a second factor
+    ("/api/patients/:id/export", All(&[HighAssurance, Owner("id")])),
+    ("/api/patients/:id/export/:export_id", All(&[HighAssurance, Owner("id")])),
+    // Encounters: the service checks the caller against the encounter, and audits patients who try
+    ("/api/encounters", SignedIn),
+    ("/api/encounters/:id/vitals", PRACTITIONER),
+    ("/api/encounters/:id/status", PRACTITIONER),
+    ("/api/encounters/:id/attachments", PRACTITIONER),
+    ("/api/encounters/:id/finalize", HighAssurance),
+    // Prescriptions: written and dispensed by practitioners; the service checks grants and licenses
+    ("/api/prescriptions", PRACTITIONER),
+    ("/api/prescriptions/:id/status", PRACTITIONER),
+    ("/api/prescriptions/:id/dispense", PRACTITIONER),
+    ("/api/prescriptions/dispense", PRACTITIONER),
+    // Credentials: only the subject reads or presents one, which the service checks on the credential
+    ("/api/credentials/:id", SignedIn),
+    ("/api/credentials/:id/qr", SignedIn),
+    ("/api/credentials/presentations/verify", SignedIn),
+    ("/api/credentials/issue", HighAssurance),
+    ("/api/credentials/issue-batch", HighAssurance),
+    // Everything else a signed-in caller reaches, scoped to them by the service
+    ("/api/access/grants", SignedIn),
+    ("/api/access/break-glass", HighAssurance),
+    ("/api/devices", SignedIn),
+    ("/api/notifications", SignedIn),
+    ("/api/notifications/stream", SignedIn),
+    ("/api/relationships", SignedIn),
+    ("/api/referrals", SignedIn),
+    ("/api/referrals/:id/accept", SignedIn),
+    ("/api/referrals/:id/reject", SignedIn),
+    ("/api/referrals/:id/complete", SignedIn),
+    ("/api/files/:cid", SignedIn),
+    ("/api/terminology/search", SignedIn),
+    ("/api/terminology/medications", SignedIn),
+    ("/api/appointments", SignedIn),
+    ("/api/appointments/:id/confirm", SignedIn),
+    ("/api/appointments/:id/cancel", SignedIn),
+    ("/api/practitioners", SignedIn),
+    ("/api/affiliations", SignedIn),
+    ("/api/organizations/:id/affiliations", SignedIn),
+    ("/api/organizations/:id/affiliations/:affiliation_id/approve", SignedIn),
+    ("/api/organizations/:id/affiliations/:affiliation_id/reject", SignedIn),
+    ("/api/practitioners/:id/availability", SignedIn),
+    ("/api/practitioners/:id/slots", SignedIn),
+    ("/api/chat", SignedIn),
+    ("/api/chat/sessions", SignedIn),
+    ("/api/chat/sessions/:id/messages", SignedIn),
+    // Stepping up and managing second factors start from an ordinary sign-in token
+    ("/api/auth/step-up", SignedIn),
+    ("/api/auth/step-up/sms", SignedIn),
+    ("/api/auth/totp/enroll", SignedIn),
+    ("/api/auth/totp/confirm", SignedIn),
+    ("/api/auth/totp/disable", SignedIn),
+    ("/api/auth/backup-codes", SignedIn),
+    ("/api/auth/backup-codes/regenerate", HighAssurance),
+    // Tenant admins and platform admins; what a tenant admin sees is confined to its tenant
+    ("/api/admin/encounters/:id", ADMIN),
+    ("/api/admin/encounters/:id/restore", ADMIN),
+    ("/api/admin/organizations", ADMIN),
+    ("/api/admin/organizations/:id", ADMIN),
+    ("/api/admin/organizations/:id/affiliations", ADMIN),
+    ("/api/admin/practitioners/expiring", ADMIN),
+    ("/api/admin/audit/stats", ADMIN),
+    ("/api/admin/audit/health", ADMIN),
+    ("/api/webhooks", ADMIN),
+    ("/api/webhooks/:id", ADMIN),
+    ("/api/webhooks/:id/deliveries", ADMIN),
+    ("/api/webhooks/:id/test", ADMIN),
+    // Patient accounts are shared by every tenant, and the rest is platform infrastructure
+    ("/api/admin/patients/:did", PLATFORM_ADMIN),
+    ("/api/admin/patients/:did/restore", PLATFORM_ADMIN),
+    ("/api/admin/patients/:did/totp", PLATFORM_ADMIN),
+    ("/api/admin/patients/duplicates", PLATFORM_ADMIN),
+    ("/api/admin/patients/duplicates/scan", PLATFORM_ADMIN),
+    ("/api/admin/email/outbox", PLATFORM_ADMIN),
+    ("/api/admin/email/:id/retry", PLATFORM_ADMIN),
+    ("/api/admin/jobs", PLATFORM_ADMIN),
+    ("/api/admin/jobs/:id/retry", PLATFORM_ADMIN),
+    ("/api/admin/chat/usage", PLATFORM_ADMIN),
+    ("/api/admin/reminders/metrics", PLATFORM_ADMIN),
+    ("/api/admin/events/metrics", PLATFORM_ADMIN),
+    ("/api/admin/i18n/missing", PLATFORM_ADMIN),
+    ("/api/admin/fhir-date-issues", PLATFORM_ADMIN),
+    ("/api/admin/break-glass", PLATFORM_ADMIN),
+    ("/api/admin/break-glass/:id/review", PLATFORM_ADMIN),
+    ("/api/admin/issuers", PLATFORM_ADMIN),
+    ("/api/admin/issuers/:did", PLATFORM_ADMIN),
+    ("/api/admin/reencryption-jobs", PLATFORM_ADMIN),
+    ("/api/admin/reencryption-jobs/:id", PLATFORM_ADMIN),
+    ("/api/admin/security/blocks", PLATFORM_ADMIN),
+    ("/api/admin/security/blocks/:ip", PLATFORM_ADMIN),
+    ("/api/admin/hedera/costs", PLATFORM_ADMIN),
+    ("/api/admin/reports/compliance", PLATFORM_ADMIN),
+    ("/api/admin/reports/compliance/verify", PLATFORM_ADMIN),
+    ("/api/admin/reports/compliance/:id", PLATFORM_ADMIN),
+    ("/api/admin/reports/compliance/:id/json", PLATFORM_ADMIN),
+    ("/api/admin/reports/compliance/:id/html", PLATFORM_ADMIN),
+    // Account management also needs the admin to have stepped up with a second factor
+    ("/api/admin/practitioners", All(&[HighAssurance, ADMIN])),
+    ("/api/admin/stats", All(&[HighAssurance, ADMIN])),
+    ("/api/admin/config/reload", All(&[HighAssurance, ADMIN])),
+    ("/api/admin/patients", All(&[HighAssurance, PLATFORM_ADMIN])),
+    ("/api/admin/patients/:did/disable", All(&[HighAssurance, PLATFORM_ADMIN])),
+    ("/api/admin/patients/:did/enable", All(&[HighAssurance, PLATFORM_ADMIN])),
+    ("/api/admin/patients/merge", All(&[HighAssurance, PLATFORM_ADMIN])),
+];
+
+/// The requirement registered for `path`
+pub fn policy_for(path: &str) -> Option<&'static Requirement> {
+    POLICIES.iter().find(|(registered, _)| *registered == path).map(|(_, requirement)| requirement)
+}
+
+/// A requirement the caller did not meet
+struct Denial {
+    status: StatusCode,
+    reason: String,
+    /// The patient whose record the route is about, whom the refusal is audit-logged under
+    patient_did: Option<String>,
+}
+
+impl Denial {
+    fn forbidden(reason: impl Into<String>, patient_did: Option<&str>) -> Self {
+        Self { status: StatusCode::FORBIDDEN, reason: reason.into(), patient_did: patient_did.map(str::to_string) }
+    }
+}
+
+/// Check the route's policy against the caller `auth_middleware` resolved (runs after it)
+pub async fn policy_middleware(
+    State(state): State<Arc<AppState<AuthServiceImpl>>>,
+    route: MatchedPath,
+    params: RawPathParams,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let Some(caller) = req.extensions().get::<AuthContext>().cloned() else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let Some(requirement) = policy_for(route.as_str()) else {
+        tracing::error!("No authorization policy is registered for {}; refusing it", route.as_str());
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let params: Vec<(String, String)> = params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+    match evaluate(&state, requirement, &caller, &params).await {
+        Ok(Ok(())) => Ok(next.run(req).await),
+        Ok(Err(denial)) => {
+            let details = json!({ "actor": &caller.user_did, "route": format!("{} {}", req.method(), route.as_str()), "reason": &denial.reason });
+            let subject = denial.patient_did.as_deref().unwrap_or(&caller.user_did);
+            state.audit_log_service.log(subject, "access_denied", Some(details)).await;
+            Err(denial.status)
+        }
+        Err(e) => {
+            tracing::error!("Failed to check the policy for {}: {:#}", route.as_str(), e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn evaluate<'a>(
+    state: &'a AppState<AuthServiceImpl>,
+    requirement: &'static Requirement,
+    caller: &'a AuthContext,
+    params: &'a [(String, String)],
+) -> BoxFuture<'a, anyhow::Result<Result<(), Denial>>> {
+    async move {
+        let param = |name: &str| params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str());
+        let outcome = match requirement {
+            SignedIn => Ok(()),
+            HighAssurance if caller.second_factor.is_some() => Ok(()),
+            HighAssurance => Err(Denial { status: StatusCode::UNAUTHORIZED, reason: "needs a second factor".to_string(), patient_did: None }),
+            Role(role) if caller.role == *role || (*role == UserRole::Admin && caller.role.is_admin()) => Ok(()),
+            Role(role) => Err(Denial::forbidden(format!("needs the {:?} role", role), None)),
+            Owner(name) => match param(name) {
+                Some(did) if did == caller.user_did => Ok(()),
+                did => Err(Denial::forbidden("only the patient themselves", did)),
+            },
+            Grant(name, permission) => {
+                let Some(did) = param(name) else {
+                    return Ok(Err(Denial::forbidden(format!("no '{}' in the path", name), None)));
+                };
+                let mut granted = false;
+                for data_class in permission.data_classes() {
+                    if state.patient_service.check_access(&caller.user_did, did, data_class).await?.is_some() {
+                        granted = true;
+                        break;
+                    }
+                }
+                if granted {
+                    Ok(())
+                } else {
+                    Err(Denial::forbidden(format!("no {:?} access to the patient's record", permission), Some(did)))
+                }
+            }
+            All(requirements) => {
+                for requirement in *requirements {
+                    if let Err(denial) = evaluate(state, requirement, caller, params).await? {
+                        return Ok(Err(denial));
+                    }
+                }
+                Ok(())
+            }
+            // Refused with 401 only when stepping up would have met one of them
+            Any(requirements) => {
+                let mut denials = Vec::new();
+                for requirement in *requirements {
+                    match evaluate(state, requirement, caller, params).await? {
+                        Ok(()) => return Ok(Ok(())),
+                        Err(denial) => denials.push(denial),
+                    }
+                }
+                match denials.iter().position(|denial| denial.status == StatusCode::UNAUTHORIZED) {
+                    Some(unauthorized) => Err(denials.swap_remove(unauthorized)),
+                    None => Err(denials.pop().unwrap_or_else(|| Denial::forbidden("no requirement can be met", None))),
+                }
+            }
+        };
+        Ok(outcome)
+    }
+    .boxed()
+}
@@ -0,0 +1,286 @@
+use axum::{
+    extract::DefaultBodyLimit,
+    handler::Handler,
+    http::{request, HeaderValue, Method},
+    http::header::{HeaderMap, AUTHORIZATION, ACCEPT, ACCEPT_LANGUAGE, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, RANGE},
+    http::{Extensions, StatusCode, Version},
+    middleware,
+    routing::{delete, get, post, MethodRouter},
+    Router,
+};
+use std::sync::Arc;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+
+use crate::api::handlers::*;
+use crate::api::middleware::idempotency::{idempotency_middleware, IDEMPOTENCY_KEY};
+use crate::api::middleware::jwt_auth::auth_middleware;
+use crate::api::middleware::locale::locale_middleware;
+use crate::api::negotiation::fhir_format_middleware;
+use crate::api::middleware::request_log::{request_log_middleware, REQUEST_ID};
+use crate::api::middleware::limits::{ip_block_middleware, notification_stream_limit_middleware, timeout_middleware, RequestTimeouts};
+use crate::api::policy::policy_middleware;
+use crate::services::AuthServiceImpl;
+use crate::state::AppState;
+
+/// Routes that wait on IPFS, Hedera or Gemini, which get HTTP_UPSTREAM_TIMEOUT_SECONDS
+/// rather than the HTTP_REQUEST_TIMEOUT_SECONDS every other route has to answer in
+const UPSTREAM_ROUTES: &[&str] = &[
+    "/api/encounters/:id/finalize",
+    "/api/encounters/:id/attachments",
+    "/api/patients/:id/photo",
+    "/api/credentials/issue",
+    "/api/credentials/issue-batch",
+    "/api/patients/:id/$everything",
+    "/api/exports/:id/download",
+    "/api/admin/reports/compliance/verify",
+    "/api/chat",
+];
+
+/// Every route the backend serves. Both `main` and the integration tests
+/// build their router here so the two cannot drift apart.
+pub fn build_router(app_state: Arc<AppState<AuthServiceImpl>>) -> Router {
+    build_router_with_paths(app_state).0
+}
+
+/// [`build_router`], with the path of every route that needs a signed-in caller
+pub fn build_router_with_paths(app_state: Arc<AppState<AuthServiceImpl>>) -> (Router, Vec<&'static str>) {
+    // Creating requests that clients retry on timeouts can carry an Idempotency-Key
+    let idempotent = middleware::from_fn_with_state(app_state.clone(), idempotency_middleware);
+    let stream_limit = middleware::from_fn_with_state(app_state.clone(), notification_stream_limit_middleware);
+
+    // --- Authenticated Routes ---
+    // What each one needs of the caller is in the policy table; see `api::policy`
+    let authenticated_routes = Authenticated::new()
+        .route("/api/patients/search", get(search_patients))
+        .route("/api/patients/:id", get(get_patient))
+        .route("/api/patients/:id/$everything", get(patient_everything))
+        .route("/api/patients/:id/consents", get(list_consents).post(create_consent))
+        .route("/api/patients/:id/consents/:consent_id/revoke", post(revoke_consent))
+        .route("/api/patients/:id/preferences", get(get_notification_preferences).put(update_notification_preferences))
+        .route("/api/patients/:id/chat-consent", get(get_chat_record_consent).put(set_chat_record_consent))
+        .route("/api/patients/:id/timezone", get(get_timezone).put(set_timezone))
+        .route("/api/patients/:id/relationships", get(list_relationships))
+        .route("/api/patients/:id/prescriptions", get(list_prescriptions))
+        .route("/api/patients/:id/observations/summary", get(observation_summary))
+        .route("/api/patients/:id/problems", get(list_problems))
+        .route("/api/patients/:id/export", post(request_patient_export))
+        .route("/api/patients/:id/export/:export_id", get(get_patient_export))
+        .route("/api/access/grants", post(grant_access.layer(idempotent.clone())))
+        .route("/api/access/break-glass", post(break_glass))
+        .route("/api/devices", post(register_device))
+        .route("/api/notifications", get(list_notifications))
+        .route("/api/notifications/stream", get(notification_stream.layer(stream_limit)))
+        .route("/api/relationships", post(create_relationship))
+        .route("/api/referrals", get(list_referrals).post(create_referral))
+        .route("/api/referrals/:id/accept", post(accept_referral))
+        .route("/api/referrals/:id/reject", post(reject_referral))
+        .route("/api/referrals/:id/complete", post(complete_referral))
+        .route("/api/prescriptions", post(create_prescription.layer(idempotent.clone())))
+        .route("/api/prescriptions/:id/status", post(update_prescription_status))
+        .route("/api/prescriptions/:id/dispense", post(dispense_prescription))
+        .route("/api/prescriptions/dispense", post(dispense_verified_prescription))
+        .route("/api/encounters", get(list_encounters).post(create_encounter.layer(idempotent.clone())))
+        .route("/api/encounters/:id/vitals", post(record_vitals))
+        .route("/api/encounters/:id/status", post(update_encounter_status))
+        .route("/api/encounters/:id/finalize", post(finalize_encounter))
+        .route("/api/files/:cid", get(download_file))
+        .route("/api/credentials/issue", post(issue_credential.layer(idempotent.clone())))
+        .route("/api/credentials/issue-batch", post(issue_credential_batch.layer(idempotent)))
+        .route("/api/credentials/:id", get(get_credential_document))
+        .route("/api/credentials/:id/qr", get(present_credential))
+        .route("/api/credentials/presentations/verify", post(verify_credential_presentation))
+        .route("/api/terminology/search", get(search_terminology))
+        .route("/api/terminology/medications", get(search_medications))
+        .route("/api/appointments", get(list_appointments).post(request_appointment))
+        .route("/api/appointments/:id/confirm", post(confirm_appointment))
+        .route("/api/appointments/:id/cancel", post(cancel_appointment))
+        .route("/api/practitioners", post(register_practitioner))
+        .route("/api/affiliations", post(request_affiliation))
+        .route("/api/organizations/:id/affiliations", get(list_organization_affiliations))
+        .route("/api/organizations/:id/affiliations/:affiliation_id/approve", post(approve_affiliation))
+        .route("/api/organizations/:id/affiliations/:affiliation_id/reject", post(reject_affiliation))
+        .route("/api/practitioners/:id/availability", get(get_availability).put(set_availability))
+        .route("/api/practitioners/:id/slots", get(list_slots))
+        // Chat history is per user, so chat needs to know who is asking
+        .route("/api/chat", post(chat))
+        .route("/api/chat/sessions", get(list_chat_sessions))
+        .route("/api/chat/sessions/:id/messages", get(list_chat_messages))
+        .route("/api/auth/step-up", post(step_up_auth))
+        .route("/api/auth/step-up/sms", post(step_up_sms))
+        .route("/api/auth/totp/enroll", post(totp_enroll))
+        .route("/api/auth/totp/confirm", post(totp_confirm))
+        .route("/api/auth/totp/disable", post(totp_disable))
+        .route("/api/auth/backup-codes", get(backup_codes_status))
+        .route("/api/auth/backup-codes/regenerate", post(regenerate_backup_codes))
+        .route("/api/admin/encounters/:id", delete(admin_delete_encounter))
+        .route("/api/admin/encounters/:id/restore", post(admin_restore_encounter))
+        .route("/api/admin/organizations", get(admin_list_organizations).post(admin_create_organization))
+        .route(
+            "/api/admin/organizations/:id",
+            get(admin_get_organization).put(admin_update_organization).delete(admin_deactivate_organization),
+        )
+        .route("/api/admin/organizations/:id/affiliations", post(admin_add_affiliation))
+        .route("/api/admin/audit/stats", get(admin_audit_stats))
+        .route("/api/admin/audit/health", get(admin_audit_health))
+        .route("/api/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/api/webhooks/:id", get(get_webhook).put(update_webhook).delete(delete_webhook))
+        .route("/api/webhooks/:id/deliveries", get(list_webhook_deliveries))
+        .route("/api/webhooks/:id/test", post(test_webhook))
+        .route("/api/admin/patients", get(admin_list_patients))
+        .route("/api/admin/patients/:did", delete(admin_delete_patient))
+        .route("/api/admin/patients/:did/restore", post(admin_restore_patient))
+        .route("/api/admin/patients/:did/totp", delete(admin_disable_totp))
+        .route("/api/admin/patients/:did/disable", post(admin_disable_patient))
+        .route("/api/admin/patients/:did/enable", post(admin_enable_patient))
+        .route("/api/admin/patients/merge", post(admin_merge_patients))
+        .route("/api/admin/patients/duplicates", get(admin_list_merge_candidates))
+        .route("/api/admin/patients/duplicates/scan", post(admin_scan_duplicate_patients))
+        .route("/api/admin/practitioners", get(admin_list_practitioners))
+        .route("/api/admin/practitioners/expiring", get(admin_list_expiring_licenses))
+        .route("/api/admin/stats", get(admin_stats))
+        .route("/api/admin/config/reload", post(reload_config))
+        .route("/api/admin/email/outbox", get(admin_email_outbox_stats))
+        .route("/api/admin/email/:id/retry", post(admin_retry_email))
+        .route("/api/admin/jobs", get(admin_list_jobs))
+        .route("/api/admin/jobs/:id/retry", post(admin_retry_job))
+        .route("/api/admin/chat/usage", get(admin_chat_usage))
+        .route("/api/admin/reminders/metrics", get(admin_reminder_metrics))
+        .route("/api/admin/events/metrics", get(admin_event_bus_metrics))
+        .route("/api/admin/i18n/missing", get(admin_missing_translations))
+        .route("/api/admin/fhir-date-issues", get(admin_list_fhir_date_issues))
+        .route("/api/admin/break-glass", get(admin_list_break_glass))
+        .route("/api/admin/break-glass/:id/review", post(admin_review_break_glass))
+        .route("/api/admin/issuers", get(admin_list_issuers).post(admin_register_issuer))
+        .route("/api/admin/issuers/:did", get(admin_get_issuer).put(admin_update_issuer))
+        .route("/api/admin/reencryption-jobs", get(admin_list_reencryption_jobs).post(admin_start_reencryption_job))
+        .route("/api/admin/reencryption-jobs/:id", get(admin_get_reencryption_job))
+        .route("/api/admin/security/blocks", get(admin_list_ip_blocks))
+        .route("/api/admin/security/blocks/:ip", delete(admin_unblock_ip))
+        .route("/api/admin/hedera/costs", get(admin_hedera_costs))
+        .route("/api/admin/reports/compliance", get(admin_list_compliance_reports).post(admin_request_compliance_report))
+        .route("/api/admin/reports/compliance/verify", post(admin_verify_compliance_report))
+        .route("/api/admin/reports/compliance/:id", get(admin_get_compliance_report))
+        .route("/api/admin/reports/compliance/:id/json", get(admin_download_compliance_report_json))
+        .route("/api/admin/reports/compliance/:id/html", get(admin_download_compliance_report_html));
+
+    // --- Sign-in Routes ---
+    // Addresses blocked for repeated failed sign-ins are turned away here
+    let sign_in_routes = Router::new()
+        .route("/api/auth/initiate", post(auth_initiate))
+        .route("/api/auth/register", post(register))
+        .route("/api/auth/verify", get(verify_email))
+        .route("/api/auth/google", post(auth_google))
+        .route("/api/auth/google/verify", post(verify_google_token))
+        .route("/api/auth/phone/initiate", post(auth_phone_initiate))
+        .route("/api/auth/phone/verify", post(auth_phone_verify))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), ip_block_middleware));
+
+    // --- Public Routes ---
+    let public_routes = Router::new()
+        .route("/health", get(health_check))
+        .route("/health/deep", get(deep_health_check))
+        // FHIR clients start here; it describes the routes listed in `api::capability`
+        .route("/metadata", get(fhir_metadata))
+        .route("/metrics", get(prometheus_metrics))
+        // The alert's link opens a confirm page; only its form's POST signs the session out
+        .route("/api/auth/sessions/revoke", get(confirm_session_revoke).post(revoke_session))
+        // Pharmacies check prescription credentials without an account; limited per client address
+        .route("/api/prescriptions/verify", post(verify_prescription))
+        .route("/api/credentials/status-list/:list_id", get(get_status_list))
+        // Export downloads carry a short-lived signature instead of a token
+        .route("/api/exports/:id/download", get(download_patient_export));
+
+    // --- Upload Routes ---
+    // Attachment and photo uploads get the larger body limit; everything else shares the default
+    let upload_routes = Authenticated::new()
+        .route("/api/encounters/:id/attachments", post(upload_attachment))
+        .route("/api/patients/:id/photo", get(get_patient_photo).put(upload_patient_photo));
+    let authenticated_paths = [authenticated_routes.paths.as_slice(), upload_routes.paths.as_slice()].concat();
+    let authenticated_routes = authenticated_routes.finish(&app_state);
+    let upload_routes = upload_routes.finish(&app_state);
+
+    let http = &app_state.config.http;
+    let timeouts = RequestTimeouts::from(http).with_upstream_routes(UPSTREAM_ROUTES);
+    let api_routes = Router::new()
+        .merge(public_routes)
+        .merge(sign_in_routes)
+        .merge(authenticated_routes);
+
+    // Configure CORS to allow FlutterFlow app
+    // Without CORS_ALLOWED_ORIGINS only the FlutterFlow frontend URL is allowed, since that's where your app runs.
+    // Origins are checked against the live settings on each request, so a config reload applies at once.
+    let dynamic = app_state.config.dynamic.clone();
+    let frontend_url = app_state.config.frontend_base_url.trim_end_matches('/').to_string();
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, _: &request::Parts| {
+            let allowed = &dynamic.load().cors_allowed_origins;
+            if allowed.is_empty() {
+                origin.as_bytes() == frontend_url.as_bytes()
+            } else {
+                allowed.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes())
+            }
+        }))
+        .allow_headers([AUTHORIZATION, ACCEPT, ACCEPT_LANGUAGE, CONTENT_TYPE, IDEMPOTENCY_KEY, RANGE, IF_RANGE, REQUEST_ID])
+        .expose_headers([ACCEPT_RANGES, CONTENT_RANGE, ETAG, REQUEST_ID])
+        .allow_credentials(true)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS]);
+
+    // Ranges are offsets into the file as stored, which compressing the body would break
+    let compression = CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(SizeAbove::new(http.compression_min_bytes))
+            .and(|_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| !headers.contains_key(ACCEPT_RANGES)),
+    );
+
+    let router = Router::new()
+        .merge(with_request_limits(api_routes, http.max_body_bytes, timeouts))
+        .merge(with_request_limits(upload_routes, http.max_upload_bytes, timeouts))
+        // Outside the limits, so timeouts and oversized bodies are reported in the caller's language
+        .layer(middleware::from_fn(locale_middleware))
+        // Outside the locale middleware, so FHIR callers get its invalid-body errors as an OperationOutcome too
+        .layer(middleware::from_fn(fhir_format_middleware))
+        // Applied to each route after matching, so it logs the route template rather than the path
+        .layer(middleware::from_fn_with_state(app_state.config.clone(), request_log_middleware))
+        .layer(compression)
+        .layer(cors)
+        .with_state(app_state);
+    (router, authenticated_paths)
+}
+
+/// Routes behind `auth_middleware` and `policy_middleware`. Keeps their paths so the policy
+/// test can check each one has an entry in the policy table.
+struct Authenticated {
+    router: Router<Arc<AppState<AuthServiceImpl>>>,
+    paths: Vec<&'static str>,
+}
+
+impl Authenticated {
+    fn new() -> Self {
+        Self { router: Router::new(), paths: Vec::new() }
+    }
+
+    fn route(mut self, path: &'static str, method_router: MethodRouter<Arc<AppState<AuthServiceImpl>>>) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.paths.push(path);
+        self
+    }
+
+    // Layers run bottom-up: auth_middleware resolves the caller before the policy is checked
+    fn finish(self, app_state: &Arc<AppState<AuthServiceImpl>>) -> Router<Arc<AppState<AuthServiceImpl>>> {
+        self.router
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), policy_middleware))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), auth_middleware))
+    }
+}
+
+/// Cap the body size (413) and bound how long the body and the handler may take (408/504).
+/// The limit layer sits outside the timeout middleware so the body it buffers is already capped.
+fn with_request_limits<S: Clone + Send + Sync + 'static>(router: Router<S>, max_body_bytes: usize, timeouts: RequestTimeouts) -> Router<S> {
+    router
+        .layer(middleware::from_fn_with_state(timeouts, timeout_middleware))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+}
@@ -1,9 +1,13 @@
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 
-use crate::database::Database;
+use crate::events::{DomainEvent, EventSubscriber};
+use crate::store::{AuditStore, PractitionerStore};
 use crate::models::AuditLog;
+use crate::tenancy::DEFAULT_TENANT;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditLogEvent {
@@ -13,11 +17,11 @@ pub struct AuditLogEvent {
 }
 
 pub struct AuditLogService {
-    db: Arc<Database>,
+    db: Arc<dyn AuditStore>,
 }
 
 impl AuditLogService {
-    pub fn new(db: Arc<Database>) -> Self {
+    pub fn new(db: Arc<dyn AuditStore>) -> Self {
         Self { db }
     }
 
@@ -30,6 +34,7 @@ impl AuditLogService {
             details,
             is_anchored: false,
             anchor_batch_id: None,
+            tenant_id: None,
         };
 
         if let Err(e) = self.db.create_audit_log(&log_entry).await {
@@ -39,3 +44,46 @@ impl AuditLogService {
         }
     }
 }
+
+/// Copies access grants into the audit trail of the grantee's clinic. The grant itself is
+/// audit-logged inline under the patient, with no tenant; this adds an entry stamped with the
+/// grantee practitioner's tenant, so its admins see who gained access to which records.
+/// Being on the bus, the copy is best effort.
+pub struct AuditSubscriber {
+    db: Arc<dyn AuditStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+}
+
+impl AuditSubscriber {
+    pub fn new(db: Arc<dyn AuditStore>, practitioners: Arc<dyn PractitionerStore>) -> Self {
+        Self { db, practitioners }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for AuditSubscriber {
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let DomainEvent::AccessGranted { grant_id, patient_did, grantee_did } = event else {
+            return Ok(());
+        };
+        let Some(practitioner) = self.practitioners.get_practitioner_by_did(grantee_did).await? else {
+            return Ok(());
+        };
+        self.db
+            .create_audit_log(&AuditLog {
+                id: None,
+                did: grantee_did.clone(),
+                action: "access_granted".to_string(),
+                timestamp: Utc::now(),
+                details: Some(json!({ "grant_id": grant_id, "patient_did": patient_did })),
+                is_anchored: false,
+                anchor_batch_id: None,
+                tenant_id: Some(practitioner.tenant_id.unwrap_or_else(|| DEFAULT_TENANT.to_string())),
+            })
+            .await
+    }
+}
@@ -0,0 +1,148 @@
+//! Whether audit anchoring is keeping up. Each run's outcome and the logs it leaves unanchored
+//! are kept in the `anchoring_health` document, and admins are emailed when the backlog grows
+//! past a threshold or no run has succeeded for too long: until then nothing on the ledger
+//! would show a changed log.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::models::{AnchoringHealth, AnchoringRun, AnchoringStatus};
+use crate::services::email::EmailService;
+use crate::services::login_anomaly::Clock;
+use crate::store::{AuditStore, PatientStore};
+
+/// Runs kept in the `anchoring_health` document, newest first
+pub const RUN_HISTORY: usize = 50;
+
+// --- AnchoringMonitor ---
+pub struct AnchoringMonitor {
+    store: Arc<dyn AuditStore>,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    config: Arc<Config>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AnchoringMonitor {
+    pub fn new(
+        store: Arc<dyn AuditStore>,
+        patients: Arc<dyn PatientStore>,
+        email_service: Arc<EmailService>,
+        config: Arc<Config>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self { store, patients, email_service, config, clock }
+    }
+
+    /// The state after the latest run; healthy and without runs before the first
+    pub async fn current(&self) -> Result<AnchoringHealth> {
+        Ok(self.store.get_anchoring_health().await?.unwrap_or_default())
+    }
+
+    /// Record how a run ended, with the logs its new batch held or why it failed, and email
+    /// admins when this run lapses anchoring. Returns the state after the run.
+    pub async fn record(&self, outcome: &Result<u64>) -> Result<AnchoringHealth> {
+        let now = self.clock.now();
+        let unanchored_logs = self.store.count_unanchored_audit_logs().await?;
+        let mut health = self.current().await?;
+        let run = AnchoringRun {
+            finished_at: now,
+            anchored_logs: *outcome.as_ref().unwrap_or(&0),
+            unanchored_logs,
+            error: outcome.as_ref().err().map(|e| format!("{:#}", e)),
+        };
+
+        health.last_run_at = Some(now);
+        health.unanchored_logs = unanchored_logs;
+        if run.error.is_none() {
+            health.last_success_at = Some(now);
+            health.failing_since = None;
+            health.consecutive_failures = 0;
+        } else {
+            health.failing_since.get_or_insert(now);
+            health.consecutive_failures += 1;
+        }
+        let lapse = self.lapse(&health, now);
+        let previous = health.status;
+        health.status = match (&lapse, &run.error) {
+            (Some(_), _) => AnchoringStatus::Lapsed,
+            (None, None) => AnchoringStatus::Healthy,
+            // A failed run does not end a lapse, even once the backlog is back under the threshold
+            (None, Some(_)) if previous == AnchoringStatus::Lapsed => AnchoringStatus::Lapsed,
+            (None, Some(_)) => AnchoringStatus::Failing,
+        };
+        if let Some(error) = &run.error {
+            tracing::warn!("Audit anchoring failed {} times in a row; {} logs are unanchored: {}", health.consecutive_failures, unanchored_logs, error);
+        }
+        health.runs.insert(0, run);
+        health.runs.truncate(RUN_HISTORY);
+        self.store.save_anchoring_health(&health).await?;
+
+        if let (Some(reason), true) = (lapse, previous != AnchoringStatus::Lapsed) {
+            tracing::error!("Audit anchoring has lapsed: {}", reason);
+            self.alert(&health, &reason).await?;
+        }
+        Ok(health)
+    }
+
+    /// Why anchoring counts as lapsed in `health`, if it does
+    fn lapse(&self, health: &AnchoringHealth, now: DateTime<Utc>) -> Option<String> {
+        let thresholds = &self.config.audit_anchoring;
+        if health.unanchored_logs >= thresholds.alert_unanchored_logs {
+            return Some(format!(
+                "{} audit logs are waiting to be anchored, at or above the alert threshold of {}.",
+                health.unanchored_logs, thresholds.alert_unanchored_logs
+            ));
+        }
+        // Before any run has succeeded, the age is counted from the first failure
+        let since = health.last_success_at.or(health.failing_since)?;
+        (now - since >= Duration::hours(thresholds.alert_after_hours))
+            .then(|| format!("No audit anchoring run has succeeded for {} hours or more.", thresholds.alert_after_hours))
+    }
+
+    async fn alert(&self, health: &AnchoringHealth, reason: &str) -> Result<()> {
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let Some(admin) = self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await? else {
+                continue;
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "reason": reason,
+                "unanchored_logs": health.unanchored_logs,
+                "last_success": health.last_success_at.map_or("never".to_string(), |at| at.format("%Y-%m-%d %H:%M UTC").to_string()),
+                "last_error": health.runs.first().and_then(|run| run.error.as_deref()).unwrap_or("none"),
+            });
+            self.email_service
+                .enqueue(&email.value, "Audit logs are no longer being anchored", "Audit-anchoring-alert.html", &context)
+                .await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("Audit anchoring has lapsed but no admin has an email address");
+        }
+        Ok(())
+    }
+}
+
+/// `health` as Prometheus gauges, in the text exposition format
+pub fn prometheus(health: &AnchoringHealth) -> String {
+    let mut gauges = String::new();
+    let mut gauge = |name: &str, help: &str, value: f64| {
+        let _ = write!(gauges, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+    };
+    gauge("audit_anchoring_unanchored_logs", "Audit logs waiting to be anchored after the latest run", health.unanchored_logs as f64);
+    gauge("audit_anchoring_consecutive_failures", "Anchoring runs that failed since the last one that succeeded", f64::from(health.consecutive_failures));
+    gauge("audit_anchoring_lapsed", "1 while anchoring is past an alert threshold", f64::from(u8::from(health.status == AnchoringStatus::Lapsed)));
+    if let Some(last_success_at) = health.last_success_at {
+        gauge("audit_anchoring_last_success_timestamp_seconds", "When an anchoring run last succeeded", last_success_at.timestamp() as f64);
+    }
+    gauges
+}
@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::deadline;
+
+const MIRROR_NODE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Contract results read per page, and the most pages read for one lookup
+const PAGE_SIZE: u32 = 100;
+const MAX_PAGES: usize = 10;
+/// Allowance for the clock of this server running ahead of consensus time
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// Finds out whether a Merkle root reached the ledger, for runs that were interrupted
+/// before the transaction id was saved
+#[async_trait]
+pub trait AnchorLookup: Send + Sync {
+    /// Id of a successful transaction that anchored `root` at or after `since`
+    async fn find_anchor(&self, root: [u8; 32], since: DateTime<Utc>) -> Result<Option<String>>;
+}
+
+/// Finds an earlier call to a contract that carried `argument`, for outbox actions whose
+/// dispatcher stopped before it saved the transaction id
+#[async_trait]
+pub trait ContractCallLookup: Send + Sync {
+    /// Id of a successful transaction, at or after `since`, whose call data holds `argument`
+    async fn find_call(&self, argument: &[u8], since: DateTime<Utc>) -> Result<Option<String>>;
+}
+
+#[derive(Deserialize)]
+struct ContractResultsPage {
+    results: Vec<ContractResult>,
+    links: Links,
+}
+
+#[derive(Deserialize)]
+struct ContractResult {
+    #[serde(default)]
+    function_parameters: String,
+    result: String,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct Links {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TransactionsPage {
+    transactions: Vec<MirrorTransaction>,
+}
+
+#[derive(Deserialize)]
+struct MirrorTransaction {
+    transaction_id: String,
+}
+
+/// Searches a contract's call history on a mirror node: the AuditTrail contract's for anchored
+/// roots, or the contract an outbox action calls
+pub struct MirrorNodeAnchorLookup {
+    http: Client,
+    base_url: String,
+    contract_id: String,
+}
+
+impl MirrorNodeAnchorLookup {
+    pub fn new(http: Client, mirror_node_url: &str, contract_id: &str) -> Self {
+        Self { http, base_url: mirror_node_url.trim_end_matches('/').to_string(), contract_id: contract_id.to_string() }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path_and_query: &str) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path_and_query);
+        let response = self.http.get(&url).timeout(deadline::remaining_or(MIRROR_NODE_TIMEOUT)).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Mirror node returned {} for {}", response.status(), path_and_query));
+        }
+        Ok(response.json().await?)
+    }
+}
+
+#[async_trait]
+impl AnchorLookup for MirrorNodeAnchorLookup {
+    async fn find_anchor(&self, root: [u8; 32], since: DateTime<Utc>) -> Result<Option<String>> {
+        self.find_call(&root, since).await
+    }
+}
+
+#[async_trait]
+impl ContractCallLookup for MirrorNodeAnchorLookup {
+    async fn find_call(&self, argument: &[u8], since: DateTime<Utc>) -> Result<Option<String>> {
+        // ABI encoding keeps bytes and strings as they are, padded, so the argument shows up verbatim in the call data
+        let argument = hex::encode(argument);
+        let since = since - chrono::Duration::seconds(CLOCK_SKEW_SECONDS);
+        let mut next = Some(format!(
+            "/api/v1/contracts/{}/results?timestamp=gte:{}.{:09}&order=asc&limit={}",
+            self.contract_id,
+            since.timestamp(),
+            since.timestamp_subsec_nanos(),
+            PAGE_SIZE
+        ));
+        for _ in 0..MAX_PAGES {
+            let Some(path) = next.take() else {
+                return Ok(None);
+            };
+            let page: ContractResultsPage = self.get(&path).await?;
+            let anchored = page
+                .results
+                .iter()
+                .find(|call| call.result == "SUCCESS" && call.function_parameters.to_ascii_lowercase().contains(&argument));
+            if let Some(call) = anchored {
+                let transactions: TransactionsPage = self.get(&format!("/api/v1/transactions?timestamp={}", call.timestamp)).await?;
+                let transaction = transactions
+                    .transactions
+                    .first()
+                    .ok_or_else(|| anyhow!("Mirror node has no transaction at {}", call.timestamp))?;
+                return Ok(Some(sdk_transaction_id(&transaction.transaction_id)));
+            }
+            next = page.links.next;
+        }
+        Err(anyhow!("Mirror node has more than {} contract calls since {}; giving up the search", PAGE_SIZE as usize * MAX_PAGES, since))
+    }
+}
+
+/// `0.0.2-1700000000-000000001` as the SDK prints it: `0.0.2@1700000000.000000001`
+fn sdk_transaction_id(mirror_id: &str) -> String {
+    let mut parts = mirror_id.rsplitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(nanos), Some(seconds), Some(account)) => format!("{}@{}.{}", account, seconds, nanos),
+        _ => mirror_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const ROOT: [u8; 32] = [0xab; 32];
+
+    fn call(parameters: &str, result: &str, timestamp: &str) -> serde_json::Value {
+        json!({ "function_parameters": parameters, "result": result, "timestamp": timestamp })
+    }
+
+    async fn lookup(server: &MockServer) -> MirrorNodeAnchorLookup {
+        MirrorNodeAnchorLookup::new(Client::new(), &format!("{}/", server.uri()), "0.0.1234")
+    }
+
+    #[test]
+    fn converts_mirror_node_transaction_ids() {
+        assert_eq!(sdk_transaction_id("0.0.2-1700000000-000000001"), "0.0.2@1700000000.000000001");
+    }
+
+    #[tokio::test]
+    async fn finds_the_successful_call_carrying_the_root() {
+        let server = MockServer::start().await;
+        let parameters = format!("0x5c1d0e7a{}{}", "00".repeat(96), hex::encode(ROOT));
+        Mock::given(method("GET"))
+            .and(path("/api/v1/contracts/0.0.1234/results"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [
+                    call("0x5c1d0e7a00", "SUCCESS", "1700000000.000000001"),
+                    call(&parameters, "CONTRACT_REVERT_EXECUTED", "1700000001.000000001"),
+                    call(&parameters, "SUCCESS", "1700000002.000000001"),
+                ],
+                "links": { "next": null },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/transactions"))
+            .and(query_param("timestamp", "1700000002.000000001"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "transactions": [{ "transaction_id": "0.0.2-1700000000-000000042" }],
+            })))
+            .mount(&server)
+            .await;
+
+        let found = lookup(&server).await.find_anchor(ROOT, Utc::now()).await.unwrap();
+
+        assert_eq!(found.as_deref(), Some("0.0.2@1700000000.000000042"));
+    }
+
+    #[tokio::test]
+    async fn finds_a_call_by_a_string_argument() {
+        let server = MockServer::start().await;
+        // storeCredential(subject, type, ipfs hash, ...): the hash sits in its own padded words
+        let parameters = format!("0x1a2b3c4d{}{}{}", "00".repeat(160), hex::encode("QmCredentialDocument"), "00".repeat(12));
+        Mock::given(method("GET"))
+            .and(path("/api/v1/contracts/0.0.1234/results"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [call(&parameters, "SUCCESS", "1700000003.000000001")],
+                "links": { "next": null },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/transactions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "transactions": [{ "transaction_id": "0.0.2-1700000003-000000007" }],
+            })))
+            .mount(&server)
+            .await;
+
+        let lookup = lookup(&server).await;
+        assert_eq!(lookup.find_call(b"QmCredentialDocument", Utc::now()).await.unwrap().as_deref(), Some("0.0.2@1700000003.000000007"));
+        assert_eq!(lookup.find_call(b"QmSomethingElse", Utc::now()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn follows_pages_until_they_run_out() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/contracts/0.0.1234/results"))
+            .and(query_param("order", "asc"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "results": [call("0x00", "SUCCESS", "1700000000.000000001")],
+                "links": { "next": "/api/v1/contracts/0.0.1234/results?page=2" },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/contracts/0.0.1234/results"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": [], "links": { "next": null } })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(lookup(&server).await.find_anchor(ROOT, Utc::now()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn mirror_node_errors_are_not_read_as_missing() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+
+        assert!(lookup(&server).await.find_anchor(ROOT, Utc::now()).await.is_err());
+    }
+}
@@ -1,82 +1,544 @@
 pub mod audit_log;
+pub mod health;
+pub mod mirror_node;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use anyhow::Result;
+use chrono::Utc;
 use rs_merkle::{MerkleTree, algorithms::Sha256 as MerkleSha256};
 use sha2::{Digest, Sha256};
 use bson::oid::ObjectId;
 
-use crate::database::Database;
-use crate::services::hedera::HealthcareHederaService;
+use crate::models::{AnchorBatch, AnchorBatchStatus, AuditLog, BatchVerification};
+use crate::store::AuditStore;
+use crate::services::hedera::LedgerAnchor;
 
 pub use audit_log::AuditLogService;
+pub use health::AnchoringMonitor;
+pub use mirror_node::{AnchorLookup, ContractCallLookup, MirrorNodeAnchorLookup};
 
+/// Anchors audit logs on Hedera in Merkle batches.
+///
+/// Each batch is saved as an [`AnchorBatch`] before its root is sent, and moves from pending to
+/// submitted to completed as the run goes on. A run that stops part way leaves the batch
+/// unfinished, and the next run finishes it before batching anything new: a submitted batch
+/// only needs its logs marked, and a pending one is looked up on the mirror node so its root is
+/// sent again only when it never reached the ledger.
 pub struct AuditingService {
-    db: Arc<Database>,
-    hedera_service: Arc<HealthcareHederaService>,
+    db: Arc<dyn AuditStore>,
+    hedera_service: Arc<dyn LedgerAnchor>,
+    anchor_lookup: Option<Arc<dyn AnchorLookup>>,
+    monitor: Option<Arc<AnchoringMonitor>>,
 }
 
 impl AuditingService {
-    pub fn new(db: Arc<Database>, hedera_service: Arc<HealthcareHederaService>) -> Self {
-        Self { db, hedera_service }
+    pub fn new(db: Arc<dyn AuditStore>, hedera_service: Arc<dyn LedgerAnchor>) -> Self {
+        Self { db, hedera_service, anchor_lookup: None, monitor: None }
+    }
+
+    /// Check whether pending batches reached the ledger before sending them again. Without
+    /// a lookup, a batch whose run stopped mid-call is resubmitted and may be anchored twice.
+    pub fn with_anchor_lookup(mut self, anchor_lookup: Arc<dyn AnchorLookup>) -> Self {
+        self.anchor_lookup = Some(anchor_lookup);
+        self
+    }
+
+    /// The mirror node lookup batches are checked against, if there is one
+    pub fn anchor_lookup(&self) -> Option<Arc<dyn AnchorLookup>> {
+        self.anchor_lookup.clone()
+    }
+
+    /// Record how each run ends, for the health checks and the alerts when anchoring lapses
+    pub fn with_monitor(mut self, monitor: Arc<AnchoringMonitor>) -> Self {
+        self.monitor = Some(monitor);
+        self
     }
 
     pub async fn anchor_audit_logs(&self) -> Result<()> {
+        let outcome = self.anchor_new_logs().await;
+        if let Some(monitor) = &self.monitor {
+            if let Err(e) = monitor.record(&outcome).await {
+                tracing::error!("Failed to record the audit anchoring run: {}", e);
+            }
+        }
+        outcome.map(|_| ())
+    }
+
+    /// Returns how many logs the run anchored, in unfinished batches and the new one
+    async fn anchor_new_logs(&self) -> Result<u64> {
+        // Logs of an unfinished batch are still unanchored; finishing it first keeps them out of a second batch
+        let finished = self.reconcile_anchor_batches().await?;
+
         let logs = self.db.get_unanchored_audit_logs().await?;
         if logs.is_empty() {
-            println!("No new audit logs to anchor.");
-            return Ok(());
+            tracing::info!("No new audit logs to anchor");
+            return Ok(finished);
         }
 
-        println!("Found {} new audit logs to anchor.", logs.len());
+        tracing::info!("Found {} new audit logs to anchor", logs.len());
 
         let log_ids: Vec<ObjectId> = logs.iter().map(|log| log.id.unwrap()).collect();
 
-        let leaf_hashes: Vec<[u8; 32]> = logs
-            .iter()
-            .map(|log| {
-                let serialized_log = serde_json::to_string(log).unwrap();
-                let mut hasher = Sha256::new();
-                hasher.update(serialized_log.as_bytes());
-                let result = hasher.finalize();
-                let mut hash = [0u8; 32];
-                hash.copy_from_slice(&result);
-                hash
-            })
-            .collect();
+        let merkle_root = merkle_root(&logs).ok_or_else(|| anyhow::anyhow!("Failed to get Merkle root"))?;
+
+        let batch = AnchorBatch::new(merkle_root, log_ids, Utc::now());
+        if !self.db.create_anchor_batch(&batch).await? {
+            tracing::info!("Merkle root {} is already being anchored by another run", batch.merkle_root);
+            return Ok(finished);
+        }
+        tracing::info!("Calculated Merkle root {} for a batch of {}", batch.merkle_root, batch.log_ids.len());
 
-        let merkle_tree = MerkleTree::<MerkleSha256>::from_leaves(&leaf_hashes);
-        let merkle_root = merkle_tree
-            .root()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get Merkle root"))?;
+        self.submit(&batch).await?;
+        Ok(finished + batch.log_ids.len() as u64)
+    }
 
-        println!(
-            "Calculated Merkle root: {:?}, batch size: {}",
-            merkle_root,
-            logs.len()
-        );
+    /// Finish the batches earlier runs left pending or submitted; returns how many logs they held
+    pub async fn reconcile_anchor_batches(&self) -> Result<u64> {
+        let mut finished = 0;
+        for batch in self.db.unfinished_anchor_batches().await? {
+            let id = batch.id.expect("batches are read from the database");
+            match batch.status {
+                AnchorBatchStatus::Submitted => {
+                    tracing::info!("Marking the logs of submitted anchor batch {}", id);
+                    self.complete(id, &batch.log_ids).await?;
+                }
+                AnchorBatchStatus::Pending => match self.find_anchor(&batch).await? {
+                    Some(transaction_id) => {
+                        tracing::info!("Anchor batch {} reached the ledger in {}; completing it", id, transaction_id);
+                        self.db.update_anchor_batch(id, AnchorBatchStatus::Submitted, Some(&transaction_id)).await?;
+                        self.complete(id, &batch.log_ids).await?;
+                    }
+                    None => {
+                        tracing::info!("Anchor batch {} never reached the ledger; submitting it again", id);
+                        self.submit(&batch).await?;
+                    }
+                },
+                AnchorBatchStatus::Completed => continue,
+            }
+            finished += batch.log_ids.len() as u64;
+        }
+        Ok(finished)
+    }
+
+    /// Check a batch against its logs as they are stored now and, with a mirror node, against
+    /// the ledger. A log changed or deleted since it was anchored gives a different root.
+    /// None when there is no such batch.
+    pub async fn verify_batch(&self, id: ObjectId) -> Result<Option<BatchVerification>> {
+        let Some(batch) = self.db.get_anchor_batch(id).await? else {
+            return Ok(None);
+        };
+        // Leaves were hashed in the batch's order, before the logs were marked anchored
+        let position: HashMap<ObjectId, usize> = batch.log_ids.iter().enumerate().map(|(index, id)| (*id, index)).collect();
+        let mut logs = self.db.get_audit_logs_by_ids(&batch.log_ids).await?;
+        logs.sort_by_key(|log| log.id.and_then(|id| position.get(&id).copied()));
+        for log in &mut logs {
+            log.is_anchored = false;
+            log.anchor_batch_id = None;
+        }
+        let recomputed_root = merkle_root(&logs).map(hex::encode);
+        let ledger_transaction_id = match &self.anchor_lookup {
+            Some(lookup) => lookup.find_anchor(batch.root()?, batch.created_at).await?,
+            None => None,
+        };
+        let missing_logs = batch.log_ids.len() - logs.len();
+        let valid = missing_logs == 0
+            && recomputed_root.as_deref() == Some(batch.merkle_root.as_str())
+            && batch.status == AnchorBatchStatus::Completed
+            && (self.anchor_lookup.is_none() || ledger_transaction_id.is_some());
+        Ok(Some(BatchVerification {
+            batch_id: id.to_hex(),
+            status: batch.status,
+            merkle_root: batch.merkle_root,
+            recomputed_root,
+            log_count: batch.log_ids.len(),
+            missing_logs,
+            transaction_id: batch.transaction_id,
+            ledger_transaction_id,
+            valid,
+        }))
+    }
 
-        // Call hedera_service to anchor the root
-        let transaction_record = self
+    async fn find_anchor(&self, batch: &AnchorBatch) -> Result<Option<String>> {
+        match &self.anchor_lookup {
+            Some(lookup) => lookup.find_anchor(batch.root()?, batch.created_at).await,
+            None => {
+                tracing::warn!("No mirror node to check pending anchor batch {} against; it may be anchored twice", batch.merkle_root);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn submit(&self, batch: &AnchorBatch) -> Result<()> {
+        let id = batch.id.expect("batches are saved before they are submitted");
+        let transaction_id = self
             .hedera_service
-            .anchor_log_batch(merkle_root, logs.len() as u64)
+            .anchor_log_batch(batch.root()?, batch.log_ids.len() as u64)
             .await?;
-        println!(
-            "Successfully anchored log batch. Transaction ID: {:?}",
-            transaction_record.transaction_id
-        );
-
-        // Mark logs as anchored in the database
-        let anchor_batch_id = ObjectId::new();
-        self.db
-            .mark_logs_as_anchored(&log_ids, anchor_batch_id)
-            .await?;
-        println!(
-            "Successfully marked {} logs as anchored with batch ID: {}",
-            log_ids.len(),
-            anchor_batch_id
-        );
+        tracing::info!("Anchored batch {}. Transaction ID: {}", id, transaction_id);
+        self.db.update_anchor_batch(id, AnchorBatchStatus::Submitted, Some(&transaction_id)).await?;
+        self.complete(id, &batch.log_ids).await
+    }
 
+    async fn complete(&self, id: ObjectId, log_ids: &[ObjectId]) -> Result<()> {
+        self.db.mark_logs_as_anchored(log_ids, id).await?;
+        self.db.update_anchor_batch(id, AnchorBatchStatus::Completed, None).await?;
+        tracing::info!("Marked {} logs as anchored with batch ID: {}", log_ids.len(), id);
         Ok(())
     }
 }
+
+/// Root of the Merkle tree over the logs' SHA-256 hashes, in order; None without logs
+fn merkle_root(logs: &[AuditLog]) -> Option<[u8; 32]> {
+    let leaf_hashes: Vec<[u8; 32]> = logs
+        .iter()
+        .map(|log| {
+            let serialized_log = serde_json::to_string(log).unwrap();
+            Sha256::digest(serialized_log.as_bytes()).into()
+        })
+        .collect();
+    MerkleTree::<MerkleSha256>::from_leaves(&leaf_hashes).root()
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use chrono::{DateTime, Duration};
+    use std::sync::Mutex;
+    use crate::config::{AuditAnchoringConfig, Config, SmtpConfig};
+    use crate::fixtures;
+    use crate::models::{AnchoringHealth, AnchoringStatus, AuditLog, FhirContactPoint, FhirPatient};
+    use crate::services::email::EmailService;
+    use crate::services::fakes::RecordingLedgerAnchor;
+    use crate::services::login_anomaly::Clock;
+    use crate::store::{MockAuditStore, MockEmailOutboxStore, MockPatientStore};
+
+    /// What the mocked database holds between runs
+    #[derive(Default)]
+    struct Db {
+        logs: Vec<AuditLog>,
+        batches: Vec<AnchorBatch>,
+        health: Option<AnchoringHealth>,
+    }
+
+    /// The database write that fails, standing in for a crash at that point of the run
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Step {
+        CreateBatch,
+        RecordTransaction,
+        MarkLogs,
+        CompleteBatch,
+    }
+
+    struct UnreachableMirrorNode;
+
+    #[async_trait]
+    impl AnchorLookup for UnreachableMirrorNode {
+        async fn find_anchor(&self, _root: [u8; 32], _since: DateTime<Utc>) -> Result<Option<String>> {
+            Err(anyhow!("Mirror node returned 503 Service Unavailable"))
+        }
+    }
+
+    fn audit_log(action: &str) -> AuditLog {
+        AuditLog {
+            id: Some(ObjectId::new()),
+            did: "did:hedera:testnet:0.0.1".to_string(),
+            action: action.to_string(),
+            timestamp: Utc::now(),
+            details: None,
+            is_anchored: false,
+            anchor_batch_id: None,
+            tenant_id: None,
+        }
+    }
+
+    fn seeded() -> Arc<Mutex<Db>> {
+        let logs = vec![audit_log("get_patient"), audit_log("create_encounter"), audit_log("finalize_encounter")];
+        Arc::new(Mutex::new(Db { logs, ..Default::default() }))
+    }
+
+    fn failed(step: Step, failing: Option<Step>) -> Result<()> {
+        match failing == Some(step) {
+            true => Err(anyhow!("connection reset while running {:?}", step)),
+            false => Ok(()),
+        }
+    }
+
+    /// A store over `db` whose `failing` write errors
+    fn store(db: &Arc<Mutex<Db>>, failing: Option<Step>) -> MockAuditStore {
+        let mut store = MockAuditStore::new();
+        let state = db.clone();
+        store
+            .expect_get_unanchored_audit_logs()
+            .returning(move || Ok(state.lock().unwrap().logs.iter().filter(|log| !log.is_anchored).cloned().collect()));
+        let state = db.clone();
+        store.expect_create_anchor_batch().returning(move |batch| {
+            failed(Step::CreateBatch, failing)?;
+            let mut db = state.lock().unwrap();
+            if db.batches.iter().any(|existing| existing.merkle_root == batch.merkle_root) {
+                return Ok(false);
+            }
+            db.batches.push(batch.clone());
+            Ok(true)
+        });
+        let state = db.clone();
+        store.expect_unfinished_anchor_batches().returning(move || {
+            Ok(state.lock().unwrap().batches.iter().filter(|batch| batch.status != AnchorBatchStatus::Completed).cloned().collect())
+        });
+        let state = db.clone();
+        store.expect_update_anchor_batch().returning(move |id, status, transaction_id| {
+            failed(if status == AnchorBatchStatus::Completed { Step::CompleteBatch } else { Step::RecordTransaction }, failing)?;
+            let mut db = state.lock().unwrap();
+            let batch = db.batches.iter_mut().find(|batch| batch.id == Some(id)).unwrap();
+            batch.status = status;
+            if let Some(transaction_id) = transaction_id {
+                batch.transaction_id = Some(transaction_id.to_string());
+            }
+            Ok(())
+        });
+        let state = db.clone();
+        store.expect_mark_logs_as_anchored().returning(move |ids, batch_id| {
+            failed(Step::MarkLogs, failing)?;
+            for log in state.lock().unwrap().logs.iter_mut().filter(|log| ids.contains(&log.id.unwrap())) {
+                log.is_anchored = true;
+                log.anchor_batch_id = Some(batch_id);
+            }
+            Ok(())
+        });
+        let state = db.clone();
+        store.expect_get_anchor_batch().returning(move |id| Ok(state.lock().unwrap().batches.iter().find(|batch| batch.id == Some(id)).cloned()));
+        let state = db.clone();
+        store
+            .expect_get_audit_logs_by_ids()
+            .returning(move |ids| Ok(state.lock().unwrap().logs.iter().filter(|log| ids.contains(&log.id.unwrap())).cloned().collect()));
+        let state = db.clone();
+        store
+            .expect_count_unanchored_audit_logs()
+            .returning(move || Ok(state.lock().unwrap().logs.iter().filter(|log| !log.is_anchored).count() as u64));
+        let state = db.clone();
+        store.expect_get_anchoring_health().returning(move || Ok(state.lock().unwrap().health.clone()));
+        let state = db.clone();
+        store.expect_save_anchoring_health().returning(move |health| {
+            state.lock().unwrap().health = Some(health.clone());
+            Ok(())
+        });
+        store
+    }
+
+    struct MovableClock(Mutex<DateTime<Utc>>);
+
+    impl Clock for MovableClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// A monitor over `db` alerting the one admin through `outbox` once 6 hours pass without a
+    /// successful run or 100 logs wait
+    fn monitor(db: &Arc<Mutex<Db>>, outbox: MockEmailOutboxStore, clock: Arc<MovableClock>) -> Arc<AnchoringMonitor> {
+        const ADMIN: &str = "did:hedera:testnet:0.0.3";
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|did, _| Ok(Some(fixtures::admin(did))));
+        let config = Arc::new(Config {
+            admin_dids: vec![ADMIN.to_string()],
+            smtp: Some(SmtpConfig::default()),
+            audit_anchoring: AuditAnchoringConfig { alert_unanchored_logs: 100, alert_after_hours: 6 },
+            ..Default::default()
+        });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(outbox)).unwrap());
+        Arc::new(AnchoringMonitor::new(Arc::new(store(db, None)), Arc::new(patients), email_service, config, clock))
+    }
+
+    fn service(db: &Arc<Mutex<Db>>, failing: Option<Step>, ledger: &Arc<RecordingLedgerAnchor>) -> AuditingService {
+        AuditingService::new(Arc::new(store(db, failing)), ledger.clone()).with_anchor_lookup(ledger.clone())
+    }
+
+    /// The single batch, completed with `transaction_id` and holding every log
+    fn assert_completed(db: &Arc<Mutex<Db>>, transaction_id: &str) {
+        let db = db.lock().unwrap();
+        assert_eq!(db.batches.len(), 1);
+        let batch = &db.batches[0];
+        assert_eq!((batch.status, batch.transaction_id.as_deref()), (AnchorBatchStatus::Completed, Some(transaction_id)));
+        assert!(db.logs.iter().all(|log| log.is_anchored && log.anchor_batch_id == batch.id), "{:?}", db.logs);
+    }
+
+    #[tokio::test]
+    async fn anchors_merkle_root_of_unanchored_logs() {
+        let db = seeded();
+        let leaves: Vec<[u8; 32]> = db
+            .lock()
+            .unwrap()
+            .logs
+            .iter()
+            .map(|log| Sha256::digest(serde_json::to_string(log).unwrap().as_bytes()).into())
+            .collect();
+        let expected_root = MerkleTree::<MerkleSha256>::from_leaves(&leaves).root().unwrap();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+
+        service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+        assert_eq!(ledger.anchored_batches(), vec![(expected_root, 3)]);
+        assert_eq!(db.lock().unwrap().batches[0].merkle_root, hex::encode(expected_root));
+        assert_completed(&db, "0.0.2@1700000000.000000000");
+    }
+
+    #[tokio::test]
+    async fn a_batch_verifies_until_one_of_its_logs_is_changed() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let service = service(&db, None, &ledger);
+        service.anchor_audit_logs().await.unwrap();
+        let id = db.lock().unwrap().batches[0].id.unwrap();
+
+        let verification = service.verify_batch(id).await.unwrap().unwrap();
+        assert!(verification.valid, "{:?}", verification);
+        assert_eq!(verification.ledger_transaction_id.as_deref(), Some("0.0.2@1700000000.000000000"));
+
+        db.lock().unwrap().logs[1].action = "delete_patient".to_string();
+        let verification = service.verify_batch(id).await.unwrap().unwrap();
+        assert!(!verification.valid);
+        assert_ne!(verification.recomputed_root, Some(verification.merkle_root));
+        assert!(service.verify_batch(ObjectId::new()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn skips_anchoring_when_nothing_is_pending() {
+        let db = Arc::new(Mutex::new(Db::default()));
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+
+        service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+        assert!(ledger.anchored_batches().is_empty());
+        assert!(db.lock().unwrap().batches.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_run_interrupted_after_anchoring_is_finished_without_anchoring_again() {
+        for step in [Step::RecordTransaction, Step::MarkLogs, Step::CompleteBatch] {
+            let db = seeded();
+            let ledger = Arc::new(RecordingLedgerAnchor::new());
+
+            assert!(service(&db, Some(step), &ledger).anchor_audit_logs().await.is_err(), "{:?}", step);
+            service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+            assert_eq!(ledger.anchored_batches().len(), 1, "anchored twice after failing at {:?}", step);
+            assert_completed(&db, "0.0.2@1700000000.000000000");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_lost_ledger_response_is_found_on_the_mirror_node() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        ledger.lose_next_anchor_response();
+
+        assert!(service(&db, None, &ledger).anchor_audit_logs().await.is_err());
+        assert_eq!(db.lock().unwrap().batches[0].status, AnchorBatchStatus::Pending);
+        service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+        assert_eq!(ledger.anchored_batches().len(), 1);
+        assert_completed(&db, "0.0.2@1700000000.000000000");
+    }
+
+    #[tokio::test]
+    async fn a_batch_that_never_reached_the_ledger_is_submitted_again() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        ledger.reject_next_anchor();
+
+        assert!(service(&db, None, &ledger).anchor_audit_logs().await.is_err());
+        assert!(ledger.anchored_batches().is_empty());
+        service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+        assert_eq!(ledger.anchored_batches().len(), 1);
+        assert_eq!(hex::encode(ledger.anchored_batches()[0].0), db.lock().unwrap().batches[0].merkle_root);
+        assert_completed(&db, "0.0.2@1700000000.000000000");
+    }
+
+    #[tokio::test]
+    async fn a_failed_intent_write_anchors_nothing() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+
+        assert!(service(&db, Some(Step::CreateBatch), &ledger).anchor_audit_logs().await.is_err());
+        assert!(ledger.anchored_batches().is_empty());
+        service(&db, None, &ledger).anchor_audit_logs().await.unwrap();
+
+        assert_completed(&db, "0.0.2@1700000000.000000000");
+    }
+
+    #[tokio::test]
+    async fn failing_runs_lapse_anchoring_once_until_a_run_succeeds() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox
+            .expect_enqueue_email()
+            .withf(|email| email.recipient == "admin@example.com" && email.template == "Audit-anchoring-alert.html")
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let clock = Arc::new(MovableClock(Mutex::new("2026-03-17T09:00:00Z".parse().unwrap())));
+        let service = service(&db, None, &ledger).with_monitor(monitor(&db, outbox, clock.clone()));
+        let status = || db.lock().unwrap().health.as_ref().map(|health| health.status);
+
+        // Hourly runs would fail the same way; every two hours keeps the test short
+        let mut statuses = Vec::new();
+        for _ in 0..5 {
+            ledger.reject_next_anchor();
+            assert!(service.anchor_audit_logs().await.is_err());
+            statuses.push(status().unwrap());
+            *clock.0.lock().unwrap() += Duration::hours(2);
+        }
+        use AnchoringStatus::*;
+        assert_eq!(statuses, [Failing, Failing, Failing, Lapsed, Lapsed]);
+        {
+            let db = db.lock().unwrap();
+            let health = db.health.as_ref().unwrap();
+            assert_eq!((health.consecutive_failures, health.unanchored_logs, health.last_success_at), (5, 3, None));
+            assert_eq!(health.runs.len(), 5);
+            assert!(health.runs.iter().all(|run| run.error.as_deref() == Some("INSUFFICIENT_PAYER_BALANCE")), "{:?}", health.runs);
+        }
+
+        service.anchor_audit_logs().await.unwrap();
+        let health = db.lock().unwrap().health.clone().unwrap();
+        assert_eq!((health.status, health.consecutive_failures, health.unanchored_logs), (Healthy, 0, 0));
+        assert_eq!((health.runs[0].anchored_logs, health.runs[0].error.as_deref()), (3, None));
+        assert_eq!(health.last_success_at, Some("2026-03-17T19:00:00Z".parse().unwrap()));
+        assert!(health::prometheus(&health).contains("audit_anchoring_lapsed 0\n"));
+    }
+
+    #[tokio::test]
+    async fn a_backlog_past_the_threshold_lapses_anchoring_at_once() {
+        let db = seeded();
+        db.lock().unwrap().logs.extend((0..100).map(|_| audit_log("get_patient")));
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox.expect_enqueue_email().times(1).returning(|_| Ok(ObjectId::new()));
+        let clock = Arc::new(MovableClock(Mutex::new(Utc::now())));
+        let service = service(&db, None, &ledger).with_monitor(monitor(&db, outbox, clock));
+
+        ledger.reject_next_anchor();
+        assert!(service.anchor_audit_logs().await.is_err());
+
+        let health = db.lock().unwrap().health.clone().unwrap();
+        assert_eq!((health.status, health.unanchored_logs), (AnchoringStatus::Lapsed, 103));
+        assert!(health::prometheus(&health).contains("audit_anchoring_unanchored_logs 103\n"));
+    }
+
+    #[tokio::test]
+    async fn new_logs_wait_while_a_pending_batch_cannot_be_checked() {
+        let db = seeded();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        ledger.reject_next_anchor();
+        assert!(service(&db, None, &ledger).anchor_audit_logs().await.is_err());
+        db.lock().unwrap().logs.push(audit_log("get_patient"));
+
+        let unreachable = AuditingService::new(Arc::new(store(&db, None)), ledger.clone()).with_anchor_lookup(Arc::new(UnreachableMirrorNode));
+        assert!(unreachable.anchor_audit_logs().await.is_err());
+
+        assert!(ledger.anchored_batches().is_empty());
+        assert_eq!(db.lock().unwrap().batches.len(), 1);
+    }
+}
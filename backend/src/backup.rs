@@ -0,0 +1,462 @@
+//! Disaster recovery: a backup is a directory holding every collection and the IPFS content the
+//! records point at, so the database and the blobs come back together.
+//!
+//! Collections are dumped through the driver as concatenated BSON documents, one file each, and
+//! the bundles, credentials and attachments that encounters, credentials and attachments refer
+//! to are fetched into `content/`, or only checked to be pinned on the node with
+//! [`ContentMode::VerifyPins`]. Every file is encrypted with the current IPFS key; the
+//! `manifest.json` records the key version, each file's SHA-256 and which documents refer to
+//! each CID. It is rewritten as the backup goes, so running it again on the same directory
+//! picks up where it stopped. A restore checks every digest, upserts documents by `_id` and adds
+//! and pins the blobs again, keeping its own progress next to the manifest.
+
+use anyhow::{anyhow, bail, Result};
+use bson::{Bson, Document};
+use chrono::{DateTime, Utc};
+use futures_util::stream::TryStreamExt;
+use mongodb::options::ReplaceOptions;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::services::ipfs::ObjectStorage;
+use crate::utils;
+
+const MANIFEST: &str = "manifest.json";
+const RESTORE_PROGRESS: &str = "restore-progress.json";
+const FORMAT_VERSION: u32 = 1;
+/// Content entries handled between saves of the manifest or restore progress
+const SAVE_EVERY: usize = 25;
+
+/// The collections whose documents point at IPFS content: the field holding the CID and the
+/// one holding the key version it is encrypted with, if any
+const CONTENT_REFERENCES: [(&str, &str, Option<&str>); 3] = [
+    ("encounters", "final_bundle_ipfs_hash", Some("bundle_key_version")),
+    ("verifiable_credentials", "ipfs_hash", None),
+    ("attachments", "ipfs_hash", Some("key_version")),
+];
+
+/// What a backup does with the content the records refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentMode {
+    /// Fetch each blob into the backup
+    Include,
+    /// Only check that the node holds a pin on each, for nodes pinned elsewhere as well
+    VerifyPins,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentStatus {
+    Pending,
+    Included,
+    /// Pinned on the node when the backup was made, and not copied
+    Pinned,
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentReference {
+    pub collection: String,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentEntry {
+    pub cid: String,
+    pub references: Vec<ContentReference>,
+    /// Key version the blob itself is encrypted with; none for credentials, which use the current key
+    pub key_version: Option<u32>,
+    pub status: ContentStatus,
+    /// SHA-256 of the blob as the node returned it
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionDump {
+    pub name: String,
+    pub documents: u64,
+    /// SHA-256 of the dump before encryption
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: u32,
+    pub database: String,
+    pub content_mode: ContentMode,
+    /// Version of the IPFS key every file in the backup is encrypted with
+    pub key_version: u32,
+    pub started_at: DateTime<Utc>,
+    /// Unset while the backup is unfinished
+    pub completed_at: Option<DateTime<Utc>>,
+    pub collections: Vec<CollectionDump>,
+    pub content: Vec<ContentEntry>,
+}
+
+/// A CID a backup or restore could not account for, with the documents pointing at it
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingContent {
+    pub cid: String,
+    pub references: Vec<ContentReference>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupSummary {
+    pub directory: PathBuf,
+    pub collections: usize,
+    pub documents: u64,
+    pub content_referenced: usize,
+    pub content_included: usize,
+    pub content_pinned: usize,
+    pub missing: Vec<MissingContent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreSummary {
+    pub database: String,
+    pub collections: usize,
+    pub documents: u64,
+    pub content_restored: usize,
+    /// Content the backup only verified, found pinned on the target node
+    pub content_already_pinned: usize,
+    pub missing: Vec<MissingContent>,
+}
+
+/// How far a restore into `database` got
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RestoreProgress {
+    database: String,
+    collections: BTreeMap<String, u64>,
+    /// CIDs dealt with, and the reason for those that could not be restored
+    content: BTreeMap<String, Option<String>>,
+}
+
+/// Back up `database` and the content it refers to on `storage` into `dir`, or finish a backup
+/// an earlier run left there
+pub async fn backup(database: &Database, storage: &dyn ObjectStorage, config: &Config, dir: &Path, mode: ContentMode) -> Result<BackupSummary> {
+    let mut manifest = match read_json::<BackupManifest>(&dir.join(MANIFEST))? {
+        Some(manifest) if manifest.completed_at.is_some() => {
+            bail!("{} holds a finished backup; back up into a new directory", dir.display())
+        }
+        Some(manifest) if manifest.key_version != config.ipfs_encryption_key_version || manifest.content_mode != mode => {
+            bail!("The unfinished backup in {} was started with another key version or content mode; start a new one", dir.display())
+        }
+        Some(manifest) => {
+            tracing::info!("Resuming the backup in {}", dir.display());
+            manifest
+        }
+        None => BackupManifest {
+            format: FORMAT_VERSION,
+            database: database.db.name().to_string(),
+            content_mode: mode,
+            key_version: config.ipfs_encryption_key_version,
+            started_at: Utc::now(),
+            completed_at: None,
+            collections: vec![],
+            content: vec![],
+        },
+    };
+    std::fs::create_dir_all(dir.join("collections"))?;
+    std::fs::create_dir_all(dir.join("content"))?;
+    let key = &config.ipfs_encryption_key;
+
+    let mut positions: HashMap<String, usize> = manifest.content.iter().enumerate().map(|(index, entry)| (entry.cid.clone(), index)).collect();
+    let mut names = database.db.list_collection_names(None).await?;
+    names.retain(|name| !name.starts_with("system."));
+    names.sort();
+    for name in names {
+        if manifest.collections.iter().any(|dump| dump.name == name) {
+            continue;
+        }
+        let mut cursor = database.db.collection::<Document>(&name).find(None, None).await?;
+        let mut dump = Vec::new();
+        let mut documents = 0;
+        while let Some(document) = cursor.try_next().await? {
+            document.to_writer(&mut dump)?;
+            documents += 1;
+            record_references(&mut manifest.content, &mut positions, &name, &document);
+        }
+        write_atomically(&collection_path(dir, &name), &utils::chunked::encrypt(&dump, key)?)?;
+        tracing::info!("Backed up {} documents of {}", documents, name);
+        manifest.collections.push(CollectionDump { name, documents, sha256: hex::encode(Sha256::digest(&dump)) });
+        save_json(&dir.join(MANIFEST), &manifest)?;
+    }
+
+    let pending: Vec<usize> = (0..manifest.content.len()).filter(|&index| manifest.content[index].status == ContentStatus::Pending).collect();
+    for (done, index) in pending.into_iter().enumerate() {
+        let entry = &mut manifest.content[index];
+        match mode {
+            ContentMode::Include => match content_path(dir, &entry.cid) {
+                Some(path) => match storage.get_file(&entry.cid).await {
+                    Ok(blob) => {
+                        write_atomically(&path, &utils::chunked::encrypt(&blob, key)?)?;
+                        entry.sha256 = Some(hex::encode(Sha256::digest(&blob)));
+                        entry.size = Some(blob.len() as u64);
+                        entry.status = ContentStatus::Included;
+                    }
+                    Err(e) => missing(entry, e.to_string()),
+                },
+                None => missing(entry, "not a valid CID".to_string()),
+            },
+            ContentMode::VerifyPins => match storage.is_pinned(&entry.cid).await {
+                Ok(true) => entry.status = ContentStatus::Pinned,
+                Ok(false) => missing(entry, "not pinned on the node".to_string()),
+                Err(e) => missing(entry, e.to_string()),
+            },
+        }
+        if (done + 1) % SAVE_EVERY == 0 {
+            save_json(&dir.join(MANIFEST), &manifest)?;
+        }
+    }
+
+    manifest.completed_at = Some(Utc::now());
+    save_json(&dir.join(MANIFEST), &manifest)?;
+    let count = |status| manifest.content.iter().filter(|entry| entry.status == status).count();
+    Ok(BackupSummary {
+        directory: dir.to_path_buf(),
+        collections: manifest.collections.len(),
+        documents: manifest.collections.iter().map(|dump| dump.documents).sum(),
+        content_referenced: manifest.content.len(),
+        content_included: count(ContentStatus::Included),
+        content_pinned: count(ContentStatus::Pinned),
+        missing: missing_content(manifest.content.iter().filter(|entry| entry.status == ContentStatus::Missing).map(|entry| (entry, entry.error.clone()))),
+    })
+}
+
+/// Load the backup in `dir` into `database` and its content onto `storage`, or finish a restore
+/// an earlier run started. Documents are upserted by `_id`; unless `overwrite` is set, a new
+/// restore is refused when the target already has documents in any of the backed-up collections.
+pub async fn restore(database: &Database, storage: &dyn ObjectStorage, config: &Config, dir: &Path, overwrite: bool) -> Result<RestoreSummary> {
+    let manifest: BackupManifest = read_json(&dir.join(MANIFEST))?.ok_or_else(|| anyhow!("{} holds no backup", dir.display()))?;
+    if manifest.format != FORMAT_VERSION {
+        bail!("Backup format {} is not supported", manifest.format);
+    }
+    if manifest.completed_at.is_none() {
+        bail!("The backup in {} is unfinished; run the backup again to complete it", dir.display());
+    }
+    let key = config
+        .encryption_key(manifest.key_version)
+        .ok_or_else(|| anyhow!("The backup is encrypted with key version {}, which is not configured", manifest.key_version))?;
+
+    let target = database.db.name().to_string();
+    let progress_path = dir.join(RESTORE_PROGRESS);
+    let mut progress = match read_json::<RestoreProgress>(&progress_path)? {
+        Some(progress) if progress.database == target => {
+            tracing::info!("Resuming the restore into {}", target);
+            progress
+        }
+        _ => {
+            if !overwrite {
+                let mut occupied = Vec::new();
+                for dump in manifest.collections.iter().filter(|dump| dump.documents > 0) {
+                    if database.db.collection::<Document>(&dump.name).estimated_document_count(None).await? > 0 {
+                        occupied.push(dump.name.as_str());
+                    }
+                }
+                if !occupied.is_empty() {
+                    bail!("{} already has documents in {}; restore into an empty database or pass --overwrite", target, occupied.join(", "));
+                }
+            }
+            RestoreProgress { database: target.clone(), ..Default::default() }
+        }
+    };
+
+    for dump in &manifest.collections {
+        if progress.collections.contains_key(&dump.name) {
+            continue;
+        }
+        let stored = std::fs::read(collection_path(dir, &dump.name))?;
+        let content = utils::chunked::decrypt(&stored, key)?;
+        if hex::encode(Sha256::digest(&content)) != dump.sha256 {
+            bail!("The dump of {} does not match its digest", dump.name);
+        }
+        let collection = database.db.collection::<Document>(&dump.name);
+        let mut reader = Cursor::new(content.as_slice());
+        let mut documents = 0;
+        while (reader.position() as usize) < content.len() {
+            let document = Document::from_reader(&mut reader)?;
+            match document.get("_id").cloned() {
+                Some(id) => {
+                    let options = ReplaceOptions::builder().upsert(true).build();
+                    collection.replace_one(bson::doc! { "_id": id }, &document, options).await?;
+                }
+                None => {
+                    collection.insert_one(&document, None).await?;
+                }
+            }
+            documents += 1;
+        }
+        if documents != dump.documents {
+            bail!("The dump of {} holds {} documents, not the {} recorded", dump.name, documents, dump.documents);
+        }
+        tracing::info!("Restored {} documents of {}", documents, dump.name);
+        progress.collections.insert(dump.name.clone(), documents);
+        save_json(&progress_path, &progress)?;
+    }
+
+    // Content that failed on an earlier run is tried again
+    let pending: Vec<&ContentEntry> =
+        manifest.content.iter().filter(|entry| !matches!(progress.content.get(&entry.cid), Some(None))).collect();
+    for (done, entry) in pending.into_iter().enumerate() {
+        let outcome = match entry.status {
+            ContentStatus::Included => restore_blob(storage, dir, entry, key).await.err(),
+            ContentStatus::Pinned => match storage.is_pinned(&entry.cid).await {
+                Ok(true) => None,
+                Ok(false) => Some(anyhow!("only verified as pinned when backed up, and not pinned on this node")),
+                Err(e) => Some(e),
+            },
+            ContentStatus::Missing | ContentStatus::Pending => Some(anyhow!("missing from the backup: {}", entry.error.as_deref().unwrap_or("not fetched"))),
+        };
+        progress.content.insert(entry.cid.clone(), outcome.map(|e| e.to_string()));
+        if (done + 1) % SAVE_EVERY == 0 {
+            save_json(&progress_path, &progress)?;
+        }
+    }
+    save_json(&progress_path, &progress)?;
+
+    let count = |status| {
+        manifest.content.iter().filter(|entry| entry.status == status && matches!(progress.content.get(&entry.cid), Some(None))).count()
+    };
+    Ok(RestoreSummary {
+        database: target,
+        collections: progress.collections.len(),
+        documents: progress.collections.values().sum(),
+        content_restored: count(ContentStatus::Included),
+        content_already_pinned: count(ContentStatus::Pinned),
+        missing: missing_content(
+            manifest.content.iter().filter_map(|entry| progress.content.get(&entry.cid).cloned().flatten().map(|error| (entry, Some(error)))),
+        ),
+    })
+}
+
+/// Add one backed-up blob to `storage` and pin it, checking it comes back under its old CID
+async fn restore_blob(storage: &dyn ObjectStorage, dir: &Path, entry: &ContentEntry, key: &utils::EncryptionKey) -> Result<()> {
+    let path = content_path(dir, &entry.cid).ok_or_else(|| anyhow!("not a valid CID"))?;
+    let blob = utils::chunked::decrypt(&std::fs::read(path)?, key)?;
+    if entry.sha256.as_deref() != Some(hex::encode(Sha256::digest(&blob)).as_str()) {
+        bail!("does not match its digest");
+    }
+    let cid = storage.add_file(&blob, None).await?;
+    if cid != entry.cid {
+        bail!("was stored as {}; the node builds CIDs differently", cid);
+    }
+    storage.pin_add(&cid).await?;
+    Ok(())
+}
+
+/// Note the CIDs `document` of `collection` points at; `positions` indexes `content` by CID
+fn record_references(content: &mut Vec<ContentEntry>, positions: &mut HashMap<String, usize>, collection: &str, document: &Document) {
+    let Some((_, cid_field, key_field)) = CONTENT_REFERENCES.iter().find(|(name, _, _)| *name == collection) else {
+        return;
+    };
+    let Ok(cid) = document.get_str(cid_field) else {
+        return;
+    };
+    let reference = ContentReference {
+        collection: collection.to_string(),
+        id: document.get_object_id("_id").map(|id| id.to_hex()).unwrap_or_default(),
+    };
+    match positions.get(cid) {
+        Some(&index) => content[index].references.push(reference),
+        None => {
+            positions.insert(cid.to_string(), content.len());
+            content.push(ContentEntry {
+            cid: cid.to_string(),
+            references: vec![reference],
+            key_version: key_field.and_then(|field| match document.get(field) {
+                Some(Bson::Int32(version)) => u32::try_from(*version).ok(),
+                Some(Bson::Int64(version)) => u32::try_from(*version).ok(),
+                _ => None,
+            }),
+            status: ContentStatus::Pending,
+            sha256: None,
+            size: None,
+            error: None,
+        });
+        }
+    }
+}
+
+fn missing(entry: &mut ContentEntry, error: String) {
+    tracing::warn!("Content {} could not be backed up: {}", entry.cid, error);
+    entry.status = ContentStatus::Missing;
+    entry.error = Some(error);
+}
+
+fn missing_content<'a>(entries: impl Iterator<Item = (&'a ContentEntry, Option<String>)>) -> Vec<MissingContent> {
+    entries.map(|(entry, error)| MissingContent { cid: entry.cid.clone(), references: entry.references.clone(), error }).collect()
+}
+
+fn collection_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join("collections").join(format!("{}.bson.enc", name))
+}
+
+/// Where a blob is kept; None for a CID that would not make a plain file name
+fn content_path(dir: &Path, cid: &str) -> Option<PathBuf> {
+    let plain = !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    plain.then(|| dir.join("content").join(format!("{}.enc", cid)))
+}
+
+/// Written under another name first, so a file that exists is always complete
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, content)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn save_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    write_atomically(path, &serde_json::to_vec_pretty(value)?)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content).map_err(|e| anyhow!("{} is not readable: {}", path.display(), e))?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+    use bson::oid::ObjectId;
+
+    #[test]
+    fn references_are_collected_once_per_cid_with_their_key_version() {
+        let mut content = Vec::new();
+        let mut positions = HashMap::new();
+        let encounter = ObjectId::new();
+        record_references(&mut content, &mut positions, "encounters", &doc! { "_id": encounter, "final_bundle_ipfs_hash": "Qm1", "bundle_key_version": 2 });
+        record_references(&mut content, &mut positions, "attachments", &doc! { "_id": ObjectId::new(), "ipfs_hash": "Qm1", "key_version": 2 });
+        record_references(&mut content, &mut positions, "verifiable_credentials", &doc! { "_id": ObjectId::new(), "ipfs_hash": "Qm2" });
+        // Unfinished encounters have no bundle, and other collections refer to nothing
+        record_references(&mut content, &mut positions, "encounters", &doc! { "_id": ObjectId::new(), "final_bundle_ipfs_hash": Bson::Null });
+        record_references(&mut content, &mut positions, "patients", &doc! { "_id": ObjectId::new(), "ipfs_hash": "Qm3" });
+
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[0].cid, "Qm1");
+        assert_eq!(content[0].key_version, Some(2));
+        assert_eq!(content[0].references.len(), 2);
+        assert_eq!(content[0].references[0], ContentReference { collection: "encounters".to_string(), id: encounter.to_hex() });
+        assert_eq!((content[1].cid.as_str(), content[1].key_version), ("Qm2", None));
+    }
+
+    #[test]
+    fn only_plain_cids_become_file_names() {
+        let dir = Path::new("/backup");
+        assert_eq!(content_path(dir, "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG"), Some(dir.join("content/QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG.enc")));
+        assert_eq!(content_path(dir, "../../etc/passwd"), None);
+        assert_eq!(content_path(dir, ""), None);
+    }
+}
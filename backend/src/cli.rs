@@ -0,0 +1,373 @@
+//! The command line: `serve` runs the server, the other subcommands are one-off operational
+//! tasks that connect to what they need, do it and exit.
+//!
+//! Each one-off task prints one JSON object on stdout, `{"command", "ok", "result"}` or
+//! `{"command", "ok": false, "error"}`, and logs to stderr. The exit status is 0 on success,
+//! [`EXIT_FAILED`] when the task failed, [`EXIT_CHECK_FAILED`] when `verify-batch` found the
+//! batch or `verify-report` the report does not check out, or `backup`/`restore` finished with
+//! content missing, [`EXIT_NOT_FOUND`] for an unknown batch and [`EXIT_CONFIG`] for an
+//! invalid configuration; clap exits with 2 on bad usage.
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use crate::auditing::{AnchorLookup, AnchoringMonitor, AuditingService, MirrorNodeAnchorLookup};
+use crate::backup::{self, ContentMode};
+use crate::config::Config;
+use crate::database::Database;
+use crate::fixtures;
+use crate::migrations;
+use crate::models::Role;
+use crate::seed::{self, SeedOptions};
+use crate::services::fakes::{InMemoryDidRegistry, InMemoryObjectStorage, RecordingLedgerAnchor};
+use crate::services::compliance_report::verify_report;
+use crate::services::email::EmailService;
+use crate::services::hedera::HederaClient;
+use crate::services::ipfs::{IpfsClient, ObjectStorage};
+use crate::services::login_anomaly::SystemClock;
+use crate::services::vc_document::CredentialSigner;
+use crate::services::AuthServiceImpl;
+use crate::state::{auditing_service, healthcare_hedera_service, AppState, AppStateBuilder};
+
+pub const EXIT_FAILED: u8 = 1;
+pub const EXIT_CHECK_FAILED: u8 = 3;
+pub const EXIT_NOT_FOUND: u8 = 4;
+/// `EX_CONFIG` from sysexits.h
+pub const EXIT_CONFIG: u8 = 78;
+
+/// Bytecode files `deploy-contracts` reads, as `solc --bin` names them, and the setting each
+/// deployed contract id goes in
+const CONTRACTS: [(&str, &str); 3] = [
+    ("HealthcareAccessControl.bin", "HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID"),
+    ("VerifiableCredentials.bin", "VERIFIABLE_CREDENTIALS_CONTRACT_ID"),
+    ("AuditTrail.bin", "AUDIT_TRAIL_CONTRACT_ID"),
+];
+
+#[derive(Debug, Parser)]
+#[command(name = "healthcare-backend", about = "WeCare health records backend")]
+pub struct Cli {
+    /// TOML config file; environment variables take precedence over it. Also read from CONFIG_FILE.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the API server and background workers (the default)
+    Serve,
+    /// Apply pending database migrations
+    Migrate {
+        /// Build the indexes instead, e.g. out of band with SKIP_INDEX_CREATION set on the servers
+        #[arg(long)]
+        indexes_only: bool,
+    },
+    /// Anchor unanchored audit logs on Hedera now, finishing interrupted batches first
+    AnchorNow,
+    /// Deploy the access control, credentials and audit trail contracts
+    DeployContracts {
+        /// Directory holding HealthcareAccessControl.bin, VerifiableCredentials.bin and AuditTrail.bin
+        #[arg(long)]
+        bytecode_dir: PathBuf,
+    },
+    /// Make a DID an admin, e.g. the first one
+    CreateAdmin {
+        #[arg(long)]
+        did: String,
+        /// Administer the whole platform rather than one tenant
+        #[arg(long)]
+        platform: bool,
+    },
+    /// Check an anchor batch against its audit logs and the ledger
+    VerifyBatch {
+        #[arg(long)]
+        batch_id: String,
+    },
+    /// Check a compliance report's JSON against its signature and, with a mirror node, its
+    /// anchor batches against the ledger; needs no database
+    VerifyReport {
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// Dump every collection, encrypted, with the IPFS content the records point at into a
+    /// directory; rerun with the same directory to resume
+    Backup {
+        #[arg(long)]
+        output: PathBuf,
+        /// Check the content is pinned on the IPFS node instead of copying it
+        #[arg(long)]
+        verify_pins: bool,
+    },
+    /// Load a backup into the configured database and add and pin its content on the IPFS node;
+    /// rerun to resume
+    Restore {
+        #[arg(long)]
+        input: PathBuf,
+        /// Restore into a database that already has documents, replacing those with the same id
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Fill a development database with practitioners, patients and their records; refused when
+    /// APP_ENV is production
+    Seed {
+        #[arg(long, default_value_t = 20)]
+        patients: usize,
+        #[arg(long, default_value_t = 5)]
+        practitioners: usize,
+        /// The same seed generates the same people and records
+        #[arg(long, default_value_t = fixtures::DEFAULT_SEED)]
+        rng_seed: u64,
+    },
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Serve => "serve",
+            Self::Migrate { .. } => "migrate",
+            Self::AnchorNow => "anchor-now",
+            Self::DeployContracts { .. } => "deploy-contracts",
+            Self::CreateAdmin { .. } => "create-admin",
+            Self::VerifyBatch { .. } => "verify-batch",
+            Self::VerifyReport { .. } => "verify-report",
+            Self::Backup { .. } => "backup",
+            Self::Restore { .. } => "restore",
+            Self::Seed { .. } => "seed",
+        }
+    }
+}
+
+/// What a one-off task reports: its exit status and the `result` printed
+#[derive(Debug)]
+pub struct Outcome {
+    pub code: u8,
+    pub result: Value,
+}
+
+impl Outcome {
+    fn ok(result: Value) -> Self {
+        Self { code: 0, result }
+    }
+}
+
+/// Run a one-off task and print its outcome
+pub async fn run(command: Command, config: &Config) -> ExitCode {
+    let name = command.name();
+    let (code, output) = match execute(command, config).await {
+        Ok(Outcome { code, result }) => (code, json!({ "command": name, "ok": code == 0, "result": result })),
+        Err(e) => {
+            tracing::error!("{} failed: {:#}", name, e);
+            (EXIT_FAILED, json!({ "command": name, "ok": false, "error": format!("{:#}", e) }))
+        }
+    };
+    println!("{}", output);
+    ExitCode::from(code)
+}
+
+/// An invalid configuration; one-off tasks also report it on stdout like any failure
+pub fn config_error(command: &Command, problems: &[String]) -> ExitCode {
+    if !matches!(command, Command::Serve) {
+        println!("{}", json!({ "command": command.name(), "ok": false, "error": "invalid configuration", "problems": problems }));
+    }
+    ExitCode::from(EXIT_CONFIG)
+}
+
+async fn execute(command: Command, config: &Config) -> Result<Outcome> {
+    match command {
+        Command::Serve => bail!("serve is not a one-off task"),
+        Command::Migrate { indexes_only: false } => migrate(&connect(config).await?, config).await,
+        Command::Migrate { indexes_only: true } => create_indexes(&connect(config).await?).await,
+        Command::AnchorNow => {
+            let database = Arc::new(connect(config).await?);
+            anchor_now(&database, &auditing(database.clone(), config)?).await
+        }
+        Command::DeployContracts { bytecode_dir } => deploy_contracts(&hedera_client(config)?, &bytecode_dir).await,
+        Command::CreateAdmin { did, platform } => create_admin(&connect(config).await?, &did, platform).await,
+        Command::VerifyBatch { batch_id } => {
+            let database = Arc::new(connect(config).await?);
+            verify_batch(&auditing(database, config)?, &batch_id).await
+        }
+        Command::VerifyReport { file } => {
+            let lookup = config
+                .hedera_costs
+                .mirror_node_url
+                .as_deref()
+                .map(|url| MirrorNodeAnchorLookup::new(reqwest::Client::new(), url, &config.audit_trail_contract_id));
+            let current_key = match &config.credential_signing {
+                Some(signing) => Some(CredentialSigner::from_config(signing)?.public_key_multibase()),
+                None => None,
+            };
+            verify_report_file(&file, lookup.as_ref().map(|lookup| lookup as &dyn AnchorLookup), current_key.as_deref()).await
+        }
+        Command::Backup { output, verify_pins } => {
+            let mode = if verify_pins { ContentMode::VerifyPins } else { ContentMode::Include };
+            backup(&connect(config).await?, &IpfsClient::new(&config.ipfs_url), config, &output, mode).await
+        }
+        Command::Restore { input, overwrite } => {
+            restore(&connect(config).await?, &IpfsClient::new(&config.ipfs_url), config, &input, overwrite).await
+        }
+        Command::Seed { patients, practitioners, rng_seed } => {
+            // Checked before building anything, which would run migrations on the database
+            seed::ensure_not_production(config)?;
+            seed(&seed_state(config).await?, SeedOptions { seed: rng_seed, patients, practitioners }).await
+        }
+    }
+}
+
+/// Unlike the server, which waits for the database to come up, a task fails straight away
+async fn connect(config: &Config) -> Result<Database> {
+    let database = Database::new(config.database_url.expose_secret()).await?;
+    Ok(database.with_patient_search_key(config.patient_search.key.clone()))
+}
+
+fn hedera_client(config: &Config) -> Result<HederaClient> {
+    HederaClient::new(&config.hedera_account_id, config.hedera_private_key.expose_secret(), &config.hedera_network)
+}
+
+/// Runs are recorded like the server's, so a manual run ends a lapse the same way
+fn auditing(database: Arc<Database>, config: &Config) -> Result<AuditingService> {
+    let ledger = Arc::new(healthcare_hedera_service(&hedera_client(config)?, config)?.with_transaction_log(database.clone()));
+    let config = Arc::new(config.clone());
+    let email_service = Arc::new(EmailService::new(config.clone(), database.clone())?);
+    let monitor = AnchoringMonitor::new(database.clone(), database.clone(), email_service, config.clone(), Arc::new(SystemClock));
+    Ok(auditing_service(database, ledger, &config, &reqwest::Client::new()).with_monitor(Arc::new(monitor)))
+}
+
+/// The services the API runs on, with an in-memory ledger and DID registry when Hedera is not
+/// configured and in-memory storage when IPFS is not
+async fn seed_state(config: &Config) -> Result<AppState<AuthServiceImpl>> {
+    let mut builder = AppStateBuilder::new(Arc::new(config.clone())).with_database(Arc::new(connect(config).await?));
+    if config.hedera_account_id.trim().is_empty() {
+        tracing::warn!("HEDERA_ACCOUNT_ID is not set; seed data is anchored on an in-memory ledger and kept nowhere else");
+        builder = builder.with_ledger(Arc::new(RecordingLedgerAnchor::new())).with_did_registry(Arc::new(InMemoryDidRegistry::new()));
+    }
+    if config.ipfs_url.trim().is_empty() {
+        tracing::warn!("IPFS_URL is not set; seeded bundles and credentials are stored in memory and gone once seeding ends");
+        builder = builder.with_ipfs(Arc::new(InMemoryObjectStorage::new()));
+    }
+    builder.build().await
+}
+
+pub async fn migrate(database: &Database, config: &Config) -> Result<Outcome> {
+    let applied = migrations::run(database, config).await?;
+    Ok(Outcome::ok(json!({ "applied": applied })))
+}
+
+/// Indexes that could not be built fail the task, listed in the result
+pub async fn create_indexes(database: &Database) -> Result<Outcome> {
+    let status = database.create_indexes().await;
+    Ok(Outcome {
+        code: if status.failed.is_empty() { 0 } else { EXIT_FAILED },
+        result: serde_json::to_value(status)?,
+    })
+}
+
+pub async fn anchor_now(database: &Database, auditing_service: &AuditingService) -> Result<Outcome> {
+    let unanchored = database.get_unanchored_audit_logs().await?.len();
+    auditing_service.anchor_audit_logs().await?;
+    let remaining = database.get_unanchored_audit_logs().await?.len();
+    // Logs written while the run was going are left for the next one
+    Ok(Outcome::ok(json!({ "anchored": unanchored.saturating_sub(remaining), "unanchored": remaining })))
+}
+
+/// Deploys contract bytecode; implemented by [`HederaClient`]
+#[async_trait]
+pub trait ContractDeployer: Send + Sync {
+    /// The id of the deployed contract, like 0.0.1234
+    async fn deploy_contract(&self, bytecode: &[u8]) -> Result<String>;
+}
+
+#[async_trait]
+impl ContractDeployer for HederaClient {
+    async fn deploy_contract(&self, bytecode: &[u8]) -> Result<String> {
+        Ok(self.create_contract(bytecode).await?.to_string())
+    }
+}
+
+/// Every bytecode file is read before anything is deployed, so a missing one costs no hbar.
+/// The result maps each contract's setting to its new id.
+pub async fn deploy_contracts(deployer: &dyn ContractDeployer, bytecode_dir: &Path) -> Result<Outcome> {
+    let mut bytecodes = Vec::new();
+    for (file, setting) in CONTRACTS {
+        let path = bytecode_dir.join(file);
+        let bytecode = std::fs::read(&path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        // solc writes the bytecode as hex text, which is what Hedera expects in the file
+        let bytecode = String::from_utf8_lossy(&bytecode).trim().trim_start_matches("0x").to_string();
+        if bytecode.is_empty() {
+            bail!("{} is empty", path.display());
+        }
+        bytecodes.push((setting, bytecode));
+    }
+    let mut deployed = serde_json::Map::new();
+    for (setting, bytecode) in bytecodes {
+        let contract_id = deployer.deploy_contract(bytecode.as_bytes()).await?;
+        tracing::info!("Deployed {}={}", setting, contract_id);
+        deployed.insert(setting.to_string(), json!(contract_id));
+    }
+    Ok(Outcome::ok(Value::Object(deployed)))
+}
+
+pub async fn create_admin(database: &Database, did: &str, platform: bool) -> Result<Outcome> {
+    if !did.starts_with("did:") {
+        bail!("'{}' is not a DID", did);
+    }
+    let role = if platform { Role::PlatformAdmin } else { Role::Admin };
+    let created = database.add_admin(did, role).await?;
+    Ok(Outcome::ok(json!({ "did": did, "role": role, "created": created })))
+}
+
+pub async fn verify_batch(auditing_service: &AuditingService, batch_id: &str) -> Result<Outcome> {
+    let id = ObjectId::parse_str(batch_id).map_err(|_| anyhow!("'{}' is not a batch id", batch_id))?;
+    match auditing_service.verify_batch(id).await? {
+        Some(verification) => Ok(Outcome {
+            code: if verification.valid { 0 } else { EXIT_CHECK_FAILED },
+            result: serde_json::to_value(verification)?,
+        }),
+        None => Ok(Outcome { code: EXIT_NOT_FOUND, result: json!({ "batch_id": batch_id, "found": false }) }),
+    }
+}
+
+/// `lookup` checks the batches on the ledger; `current_key` is the key this server signs with
+pub async fn verify_report_file(file: &Path, lookup: Option<&dyn AnchorLookup>, current_key: Option<&str>) -> Result<Outcome> {
+    let content = std::fs::read(file).map_err(|e| anyhow!("Failed to read {}: {}", file.display(), e))?;
+    let document: Value = serde_json::from_slice(&content).map_err(|e| anyhow!("{} is not JSON: {}", file.display(), e))?;
+    let verification = verify_report(&document, lookup, current_key).await?;
+    Ok(Outcome {
+        code: if verification.valid { 0 } else { EXIT_CHECK_FAILED },
+        result: serde_json::to_value(verification)?,
+    })
+}
+
+/// Content that could not be copied or is not pinned fails the run with [`EXIT_CHECK_FAILED`];
+/// everything else is in the backup
+pub async fn backup(database: &Database, storage: &dyn ObjectStorage, config: &Config, dir: &Path, mode: ContentMode) -> Result<Outcome> {
+    let summary = backup::backup(database, storage, config, dir, mode).await?;
+    Ok(Outcome {
+        code: if summary.missing.is_empty() { 0 } else { EXIT_CHECK_FAILED },
+        result: serde_json::to_value(summary)?,
+    })
+}
+
+/// As [`backup`], content missing from the backup fails the run
+pub async fn restore(database: &Database, storage: &dyn ObjectStorage, config: &Config, dir: &Path, overwrite: bool) -> Result<Outcome> {
+    let summary = backup::restore(database, storage, config, dir, overwrite).await?;
+    Ok(Outcome {
+        code: if summary.missing.is_empty() { 0 } else { EXIT_CHECK_FAILED },
+        result: serde_json::to_value(summary)?,
+    })
+}
+
+/// Seed the database behind `state`. The summary table goes to stderr for whoever ran it, the
+/// same records as JSON to stdout.
+pub async fn seed(state: &AppState<AuthServiceImpl>, options: SeedOptions) -> Result<Outcome> {
+    let summary = seed::seed(state, options).await?;
+    eprint!("{}", summary.table());
+    Ok(Outcome::ok(serde_json::to_value(summary)?))
+}
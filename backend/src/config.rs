@@ -1,102 +1,2183 @@
-use anyhow::Result;
+use arc_swap::ArcSwap;
+use hedera::ContractId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::services::login_anomaly::IpNetwork;
+use crate::utils::{EncryptionKey, SecretString};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SmtpConfig {
     pub server: String,
     pub port: u16,
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
     pub from_email: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TwilioConfig {
+    pub account_sid: String,
+    pub auth_token: SecretString,
+    /// Sender for plain SMS; not needed when codes go through Twilio Verify
+    pub phone_number: String,
+    /// Twilio Verify service (`VA...`) that sends and checks phone sign-in codes
+    pub verify_service_sid: Option<String>,
+}
+
+/// Firebase Cloud Messaging, used for push notifications to registered devices
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FcmConfig {
+    /// Service account key file downloaded from the Firebase console
+    pub credentials_path: String,
+}
+
+/// Safety thresholds the Gemini API accepts, from most to least permissive
+pub const GEMINI_SAFETY_THRESHOLDS: [&str; 4] = ["BLOCK_NONE", "BLOCK_ONLY_HIGH", "BLOCK_MEDIUM_AND_ABOVE", "BLOCK_LOW_AND_ABOVE"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiConfig {
+    pub api_key: SecretString,
+    pub model: String,
+    /// Tried in order when the model before it is missing (404) or out of quota (429)
+    pub fallback_models: Vec<String>,
+    pub temperature: f32,
+    pub max_output_tokens: u32,
+    /// Applied to every harm category
+    pub safety_threshold: String,
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        Self {
+            api_key: SecretString::default(),
+            model: "gemini-2.5-flash".to_string(),
+            fallback_models: vec!["gemini-2.0-flash".to_string()],
+            temperature: 0.7,
+            max_output_tokens: 1024,
+            safety_threshold: "BLOCK_MEDIUM_AND_ABOVE".to_string(),
+        }
+    }
+}
+
+/// The platform identity that issues and signs verifiable credentials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialSigningConfig {
+    /// Named as the `issuer` of every credential
+    pub issuer_did: String,
+    /// Ed25519 private key seed, 32 bytes as hex
+    pub key: SecretString,
+    /// Fragment of the key's verification method in the issuer's DID document
+    pub key_id: String,
+}
+
+/// Commercial drug-interaction API used instead of the bundled dataset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractionApiConfig {
+    pub url: String,
+    /// Sent as a bearer token; some APIs are only reachable from an allow-listed network
+    pub api_key: Option<SecretString>,
+    /// Prescribing goes ahead without the check once this runs out
+    pub timeout_seconds: u64,
+}
+
+/// Service that checks the CAPTCHA tokens sent with registration and phone sign-in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptchaProvider {
+    #[default]
+    Turnstile,
+    Recaptcha,
+}
+
+/// Written as `turnstile` or `recaptcha`
+impl FromStr for CaptchaProvider {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "turnstile" => Ok(Self::Turnstile),
+            "recaptcha" => Ok(Self::Recaptcha),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptchaConfig {
+    pub provider: CaptchaProvider,
+    pub secret_key: SecretString,
+    pub timeout_seconds: u64,
+    /// Let requests through when the provider cannot be reached, rather than refusing them
+    pub fail_open: bool,
+}
+
+/// Code lookups for ICD-10, LOINC and SNOMED CT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminologyConfig {
+    /// FHIR terminology server consulted for codes outside the embedded subsets
+    pub server_url: Option<String>,
+    pub timeout_seconds: u64,
+    /// Lookups kept in memory, least recently used evicted first
+    pub cache_size: usize,
+    /// Refuse submitted codes that are not found, instead of only warning about them
+    pub reject_unknown: bool,
+}
+
+impl Default for TerminologyConfig {
+    fn default() -> Self {
+        Self { server_url: None, timeout_seconds: 5, cache_size: 1000, reject_unknown: false }
+    }
+}
+
+/// Re-encrypting archived bundles under the current key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReencryptionConfig {
+    /// Bundles re-encrypted at once, so a job does not hammer IPFS
+    pub concurrency: usize,
+    /// Encounters fetched per page; progress is saved after each one
+    pub batch_size: usize,
+}
+
+impl Default for ReencryptionConfig {
+    fn default() -> Self {
+        Self { concurrency: 2, batch_size: 50 }
+    }
+}
+
+/// Patients' exports of their own data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatientExportConfig {
+    /// How long a built archive is kept before it is deleted
+    pub retention_hours: i64,
+    /// How long each signed download URL stays valid
+    pub download_url_minutes: i64,
+}
+
+impl Default for PatientExportConfig {
+    fn default() -> Self {
+        Self { retention_hours: 72, download_url_minutes: 15 }
+    }
+}
+
+/// Blocking addresses that fail to sign in too often, as credential-stuffing runs do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginProtectionConfig {
+    /// Failed sign-ins from one address within the window that get it blocked
+    pub max_failures: usize,
+    /// Distinct accounts failing to sign in from one address within the window that get it blocked
+    pub max_accounts: usize,
+    pub window_minutes: i64,
+    /// How long a blocked address is answered with 429 by the sign-in endpoints
+    pub block_minutes: i64,
+    /// Addresses or CIDR ranges that are never blocked, such as clinics behind NAT
+    pub allowlist: Vec<String>,
+}
+
+impl Default for LoginProtectionConfig {
+    fn default() -> Self {
+        Self { max_failures: 20, max_accounts: 5, window_minutes: 15, block_minutes: 30, allowlist: Vec::new() }
+    }
+}
+
+/// Reporting what Hedera transactions cost, and warning admins before the budget runs out
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HederaCostConfig {
+    /// Fixed exchange rate for the USD figures; takes precedence over the mirror node
+    pub usd_per_hbar: Option<f64>,
+    /// Mirror node REST API, e.g. `https://mainnet-public.mirrornode.hedera.com`: the current exchange rate is
+    /// fetched from it, and interrupted audit anchoring runs are checked against it
+    pub mirror_node_url: Option<String>,
+    /// Hbar the platform expects to spend per calendar month (UTC); no budget alerts without it
+    pub monthly_budget_hbar: Option<f64>,
+    /// Share of the budget spent at which admins are emailed
+    pub budget_alert_percent: u32,
+    /// Operator account balance below which admins are emailed
+    pub balance_alert_hbar: f64,
+    /// How often the operator account balance is checked
+    pub balance_check_minutes: u64,
+}
+
+impl Default for HederaCostConfig {
+    fn default() -> Self {
+        Self {
+            usd_per_hbar: None,
+            mirror_node_url: None,
+            monthly_budget_hbar: None,
+            budget_alert_percent: 80,
+            balance_alert_hbar: 100.0,
+            balance_check_minutes: 15,
+        }
+    }
+}
+
+/// When admins are told that audit logs are no longer being anchored on Hedera
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditAnchoringConfig {
+    /// Unanchored audit logs at which admins are emailed
+    pub alert_unanchored_logs: u64,
+    /// Hours without a successful anchoring run after which admins are emailed
+    pub alert_after_hours: i64,
+}
+
+impl Default for AuditAnchoringConfig {
+    fn default() -> Self {
+        Self { alert_unanchored_logs: 5000, alert_after_hours: 6 }
+    }
+}
+
+/// Caps on the one-time codes texted for phone sign-in, which cost money per message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmsThrottleConfig {
+    /// Codes one number is sent per 15-minute window
+    pub per_number_per_15_minutes: u32,
+    /// Codes one number is sent per UTC day
+    pub per_number_per_day: u32,
+    /// Codes sent to all numbers per UTC day; phone sign-in is turned off for the rest of the day
+    /// once they are used up. Unlimited without it.
+    pub daily_budget: Option<u32>,
+    /// What one message costs, for the estimates in the admin stats
+    pub usd_per_message: Option<f64>,
+}
+
+impl Default for SmsThrottleConfig {
+    fn default() -> Self {
+        Self { per_number_per_15_minutes: 3, per_number_per_day: 10, daily_budget: None, usd_per_message: None }
+    }
+}
+
+/// The background job workers: how many run at once and how they retry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// Jobs one instance runs at the same time
+    pub concurrency: usize,
+    /// How often an idle worker looks for due jobs
+    pub poll_interval_seconds: u64,
+    /// How long a claimed job stays locked to its worker; a crashed worker's jobs are picked up after this
+    pub lease_seconds: i64,
+    /// Attempts before a failing job is moved to the dead-letter state
+    pub max_attempts: u32,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self { concurrency: 4, poll_interval_seconds: 5, lease_seconds: 300, max_attempts: 8 }
+    }
+}
+
+/// Practitioner search over patient names. The names stay encrypted; they are found through
+/// keyed blind-index tokens, which let anyone holding the database tell which patients share
+/// a name. Without a key no tokens are written and search is off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatientSearchConfig {
+    pub key: Option<SecretString>,
+    pub max_results: usize,
+}
+
+impl Default for PatientSearchConfig {
+    fn default() -> Self {
+        Self { key: None, max_results: 20 }
+    }
+}
+
+/// Outbound webhook deliveries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Accept `http://` targets as well as `https://`; for local development only
+    pub allow_http: bool,
+    /// How long a subscriber gets to answer one delivery
+    pub timeout_seconds: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self { allow_http: false, timeout_seconds: 10 }
+    }
+}
+
+/// The in-process event bus that services publish side effects to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventBusConfig {
+    /// How many events a subscriber can fall behind before it starts dropping them
+    pub capacity: usize,
+}
+
+impl Default for EventBusConfig {
+    fn default() -> Self {
+        Self { capacity: 1024 }
+    }
+}
+
+/// `GET /api/notifications/stream` and the events it replays
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationStreamConfig {
+    /// A comment is sent on idle streams this often, so proxies do not close them
+    pub heartbeat_seconds: u64,
+    /// How long events are kept for clients that reconnect or poll
+    pub retention_hours: i64,
+    /// Streams one user can hold open at the same time
+    pub max_streams_per_user: usize,
+}
+
+impl Default for NotificationStreamConfig {
+    fn default() -> Self {
+        Self { heartbeat_seconds: 15, retention_hours: 24, max_streams_per_user: 5 }
+    }
+}
+
+/// Checks on uploaded files: encounter attachments and patient photos
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadConfig {
+    pub max_pdf_bytes: usize,
+    /// For JPEG and PNG images
+    pub max_image_bytes: usize,
+    pub max_dicom_bytes: usize,
+    /// For patient photos, which are JPEG or PNG
+    pub max_photo_bytes: usize,
+    /// Smallest width and height a patient photo may have, in pixels
+    pub photo_min_dimension: u32,
+    /// Largest width and height a patient photo may have, in pixels
+    pub photo_max_dimension: u32,
+    /// Longest side of the thumbnail made of each patient photo, in pixels
+    pub photo_thumbnail_size: u32,
+    /// clamd to scan uploads with, as `host:port`; uploads go unscanned without it
+    pub clamd_address: Option<String>,
+    pub scan_timeout_seconds: u64,
+    /// Accept uploads when the scanner cannot be reached or takes too long, rather than refusing them
+    pub scan_fail_open: bool,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_pdf_bytes: 20 * 1024 * 1024,
+            max_image_bytes: 10 * 1024 * 1024,
+            max_dicom_bytes: 25 * 1024 * 1024,
+            max_photo_bytes: 5 * 1024 * 1024,
+            photo_min_dimension: 200,
+            photo_max_dimension: 6000,
+            photo_thumbnail_size: 160,
+            clamd_address: None,
+            scan_timeout_seconds: 30,
+            scan_fail_open: false,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// Multi-line and coloured, for reading in a terminal
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log shippers
+    Json,
+}
+
+/// Written as `pretty` or `json`
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Application logs and the per-request log line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Route templates logged as `[redacted]`, for routes whose template alone says too much
+    pub redacted_routes: Vec<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { format: LogFormat::Pretty, redacted_routes: Vec::new() }
+    }
+}
+
+/// Trace export over OTLP, from the standard OpenTelemetry variables. Needs the `otel` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// Collector to send traces to, e.g. `http://localhost:4317`; nothing is exported without it
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Share of new traces that are sampled, from 0 to 1. Traces continued from a caller's
+    /// `traceparent` follow the caller's decision.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self { otlp_endpoint: None, service_name: "healthcare-backend".to_string(), sample_ratio: 1.0 }
+    }
+}
+
+/// Error reporting to Sentry or a compatible service, from the standard Sentry variables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReportingConfig {
+    /// Where events are sent; nothing is reported without it
+    pub dsn: Option<String>,
+    /// e.g. `production` or `staging`
+    pub environment: String,
+    /// Defaults to `healthcare-backend@<crate version>`
+    pub release: Option<String>,
+}
+
+impl Default for ErrorReportingConfig {
+    fn default() -> Self {
+        Self { dsn: None, environment: "production".to_string(), release: None }
+    }
+}
+
+/// How long `Idempotency-Key` responses are kept for replays
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyConfig {
+    pub ttl_hours: i64,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self { ttl_hours: 24 }
+    }
+}
+
+/// Limits applied to every HTTP request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    pub max_body_bytes: usize,
+    /// Larger cap for the upload routes
+    pub max_upload_bytes: usize,
+    /// Responses smaller than this are sent uncompressed
+    pub compression_min_bytes: u16,
+    /// Time allowed for the client to finish sending the request body (408 when exceeded)
+    pub body_timeout_seconds: u64,
+    /// Time allowed for a handler to produce a response (504 when exceeded)
+    pub request_timeout_seconds: u64,
+    /// The same for the routes that wait on IPFS, Hedera or Gemini, like finalizing an encounter
+    pub upstream_timeout_seconds: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 1024 * 1024,
+            max_upload_bytes: 25 * 1024 * 1024,
+            compression_min_bytes: 1024,
+            body_timeout_seconds: 30,
+            request_timeout_seconds: 10,
+            upstream_timeout_seconds: 45,
+        }
+    }
+}
+
+/// Normal range of a vital sign, inclusive; readings outside it are interpreted as low or high
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+pub struct ReferenceRange {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl ReferenceRange {
+    pub const fn new(low: f64, high: f64) -> Self {
+        Self { low, high }
+    }
+}
+
+/// Written as `low-high`, e.g. `60-100`
+impl FromStr for ReferenceRange {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (low, high) = value.split_once('-').ok_or(())?;
+        Ok(Self { low: low.trim().parse().map_err(|_| ())?, high: high.trim().parse().map_err(|_| ())? })
+    }
+}
+
+/// Reference ranges used to interpret vitals entered through quick entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VitalsConfig {
+    pub systolic: ReferenceRange,
+    pub diastolic: ReferenceRange,
+    pub heart_rate: ReferenceRange,
+    pub temperature_c: ReferenceRange,
+    pub spo2: ReferenceRange,
+    pub respiratory_rate: ReferenceRange,
+}
+
+impl Default for VitalsConfig {
+    fn default() -> Self {
+        Self {
+            systolic: ReferenceRange::new(90.0, 140.0),
+            diastolic: ReferenceRange::new(60.0, 90.0),
+            heart_rate: ReferenceRange::new(60.0, 100.0),
+            temperature_c: ReferenceRange::new(36.1, 37.8),
+            spo2: ReferenceRange::new(95.0, 100.0),
+            respiratory_rate: ReferenceRange::new(12.0, 20.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
-    pub database_url: String,
+    /// May carry the database password
+    pub database_url: SecretString,
     pub hedera_network: String,
     pub hedera_account_id: String,
-    pub hedera_private_key: String,
+    pub hedera_private_key: SecretString,
     pub ipfs_url: String,
-    pub jwt_secret: String,
+    pub jwt_secret: SecretString,
     pub jwt_expiration_seconds: i64,
-    pub ipfs_encryption_key: String,
+    pub ipfs_encryption_key: EncryptionKey,
+    /// Version of `ipfs_encryption_key`, recorded on every bundle it encrypts
+    pub ipfs_encryption_key_version: u32,
+    /// Earlier keys by version, kept so bundles encrypted with them can be re-encrypted
+    pub ipfs_retired_encryption_keys: HashMap<u32, EncryptionKey>,
     pub server_port: u16,
     pub healthcare_access_control_contract_id: String,
     pub verifiable_credentials_contract_id: String,
     pub audit_trail_contract_id: String,
     pub google_client_id: String,
-    pub twilio_account_sid: String,
-    pub twilio_auth_token: String,
-    pub twilio_phone_number: String,
-    pub gemini_api_key: String,
+    // Optional integrations: a missing section disables the feature instead of blocking startup
+    pub twilio: Option<TwilioConfig>,
+    pub fcm: Option<FcmConfig>,
+    pub gemini: Option<GeminiConfig>,
+    /// Topics answered with a canned reply instead of being sent to the model
+    pub chat_blocked_topics: Vec<String>,
+    pub smtp: Option<SmtpConfig>,
+    /// Checked for drug interactions when prescribing; the bundled dataset is used without it
+    pub interaction_api: Option<InteractionApiConfig>,
+    /// Registration and phone sign-in go unchecked without it
+    pub captcha: Option<CaptchaConfig>,
+    /// Directory of `.html` templates that replace the built-in emails of the same name
+    pub email_template_dir: Option<String>,
     pub use_tls: bool,
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
     pub frontend_base_url: String,
     pub backend_base_url: String,
-    pub smtp: SmtpConfig, // Added SmtpConfig here
+    /// Tenant admins; each administers the tenant of the organization it is an admin of, or the default tenant
+    pub admin_dids: Vec<String>,
+    /// Admins of the whole platform, across tenants
+    pub platform_admin_dids: Vec<String>,
+    pub soft_delete_grace_days: i64,
+    /// Length of the bookable slots practitioners' availability is divided into
+    pub appointment_slot_minutes: u32,
+    /// How long a break-glass emergency grant stays valid
+    pub break_glass_access_hours: i64,
+    /// Admins are alerted about break-glass events still unreviewed after this long
+    pub break_glass_review_hours: i64,
+    /// How long the grant made when a referral is accepted stays valid
+    pub referral_access_days: i64,
+    /// Issuing credentials is refused without it
+    pub credential_signing: Option<CredentialSigningConfig>,
+    /// Signs credential presentation QR codes; presentations are unavailable without it
+    pub credential_presentation_secret: Option<SecretString>,
+    /// How long a credential presentation stays valid
+    pub credential_presentation_minutes: i64,
+    /// Most credentials one batch issuance may hold
+    pub credential_batch_max_size: usize,
+    /// How long a high-assurance token from the step-up flow stays valid
+    pub step_up_minutes: i64,
+    /// IP geolocation lookup with an `{ip}` placeholder, e.g. `https://ipapi.co/{ip}/json/`;
+    /// new-device sign-in alerts leave the location out without it
+    pub geoip_url: Option<String>,
+    pub vitals: VitalsConfig,
+    pub terminology: TerminologyConfig,
+    pub reencryption: ReencryptionConfig,
+    pub patient_exports: PatientExportConfig,
+    pub login_protection: LoginProtectionConfig,
+    pub hedera_costs: HederaCostConfig,
+    pub audit_anchoring: AuditAnchoringConfig,
+    pub sms_throttle: SmsThrottleConfig,
+    pub run_migrations: bool,
+    /// Leave index builds to `migrate --indexes-only` instead of running them after startup
+    pub skip_index_creation: bool,
+    /// Where this deployment runs (`APP_ENV`), e.g. `production`, `staging` or `development`;
+    /// the `seed` command refuses to run in production
+    pub environment: String,
+    pub http: HttpConfig,
+    pub jobs: JobConfig,
+    pub idempotency: IdempotencyConfig,
+    pub patient_search: PatientSearchConfig,
+    pub webhooks: WebhookConfig,
+    pub events: EventBusConfig,
+    pub notification_stream: NotificationStreamConfig,
+    pub uploads: UploadConfig,
+    pub logging: LoggingConfig,
+    pub telemetry: TelemetryConfig,
+    pub error_reporting: ErrorReportingConfig,
+    /// Settings that can change while the server runs; everything above is fixed at boot
+    pub dynamic: LiveConfig,
+}
+
+/// Settings that can change while the server runs. A reload (SIGHUP or
+/// `POST /api/admin/config/reload`) reads them again and swaps them in whole.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicConfig {
+    /// Browser origins allowed to call the API; just `frontend_base_url` when empty
+    pub cors_allowed_origins: Vec<String>,
+    /// `tracing` filter directives, e.g. `healthcare_backend=info` or `warn,healthcare_backend=debug`
+    pub log_level: String,
+    /// Grantees also need an active consent covering the data they read, not just an access grant
+    pub require_consent: bool,
+    /// Encounters can only be started by practitioners whose license was verified
+    pub require_verified_practitioners: bool,
+    /// Let patients who opted in ask the chat assistant about their own record
+    pub chat_record_context: bool,
+    /// Model calls each user may make per UTC day; 0 means no limit
+    pub chat_daily_request_limit: u32,
+    /// Prompt and reply tokens each user may use per UTC day; 0 means no limit
+    pub chat_daily_token_limit: u64,
+    /// Prescription verifications one client address may make per minute
+    pub prescription_verify_per_minute: u32,
+    /// Distinct patients one caller may read other than themselves per hour; more are refused with 429
+    pub data_access_patients_per_hour: u32,
+    /// Distinct patients read within an hour at which admins are alerted, below the limit
+    pub data_access_patients_alert_per_hour: u32,
+    /// Distinct encounter bundles and attachments one caller may download per hour
+    pub data_access_downloads_per_hour: u32,
+    /// Distinct downloads within an hour at which admins are alerted, below the limit
+    pub data_access_downloads_alert_per_hour: u32,
+}
+
+impl DynamicConfig {
+    /// The settings that differ in `next`, each as `{ "from": .., "to": .. }`
+    pub fn changes(&self, next: &DynamicConfig) -> serde_json::Map<String, serde_json::Value> {
+        let (serde_json::Value::Object(before), serde_json::Value::Object(after)) = (json_or_null(self), json_or_null(next)) else {
+            return serde_json::Map::new();
+        };
+        after
+            .into_iter()
+            .filter(|(name, value)| before.get(name) != Some(value))
+            .map(|(name, value)| {
+                let from = before.get(&name).cloned().unwrap_or_default();
+                (name, serde_json::json!({ "from": from, "to": value }))
+            })
+            .collect()
+    }
+}
+
+fn json_or_null<T: Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+impl Default for DynamicConfig {
+    fn default() -> Self {
+        Self {
+            cors_allowed_origins: Vec::new(),
+            log_level: "healthcare_backend=info".to_string(),
+            require_consent: false,
+            require_verified_practitioners: false,
+            chat_record_context: false,
+            chat_daily_request_limit: 100,
+            chat_daily_token_limit: 100_000,
+            prescription_verify_per_minute: 20,
+            data_access_patients_per_hour: 60,
+            data_access_patients_alert_per_hour: 30,
+            data_access_downloads_per_hour: 120,
+            data_access_downloads_alert_per_hour: 60,
+        }
+    }
+}
+
+/// The current [`DynamicConfig`]. Read it on every use rather than keeping a copy, so a reload
+/// takes effect everywhere at once. Clones share the same settings.
+#[derive(Clone, Default)]
+pub struct LiveConfig(Arc<ArcSwap<DynamicConfig>>);
+
+impl LiveConfig {
+    pub fn load(&self) -> Arc<DynamicConfig> {
+        self.0.load_full()
+    }
+
+    pub fn store(&self, dynamic: DynamicConfig) {
+        self.0.store(Arc::new(dynamic));
+    }
+
+    /// Change some settings in place, keeping the rest
+    pub fn update(&self, change: impl FnOnce(&mut DynamicConfig)) {
+        let mut dynamic = DynamicConfig::clone(&self.load());
+        change(&mut dynamic);
+        self.store(dynamic);
+    }
+}
+
+impl From<DynamicConfig> for LiveConfig {
+    fn from(dynamic: DynamicConfig) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(dynamic)))
+    }
+}
+
+impl std::fmt::Debug for LiveConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.load().fmt(f)
+    }
+}
+
+impl Serialize for LiveConfig {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.load().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LiveConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DynamicConfig::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Summary of optional integrations, logged at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub sms: bool,
+    pub email: bool,
+    pub chat: bool,
+    pub push: bool,
+    pub malware_scan: bool,
+}
+
+impl std::fmt::Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = |enabled: bool| if enabled { "enabled" } else { "disabled" };
+        write!(
+            f,
+            "sms (Twilio): {}, email (SMTP): {}, chat (Gemini): {}, push (FCM): {}, malware scanning (ClamAV): {}",
+            state(self.sms),
+            state(self.email),
+            state(self.chat),
+            state(self.push),
+            state(self.malware_scan)
+        )
+    }
+}
+
+/// Every problem found while loading configuration, reported together so a
+/// first-time setup can fix them all in one pass.
+#[derive(Debug, Error)]
+#[error("invalid configuration:\n{}", .problems.iter().map(|p| format!("  - {}", p)).collect::<Vec<_>>().join("\n"))]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+/// Reads settings by variable name, recording problems instead of failing on the first one.
+///
+/// The environment always wins; values from a config file (flattened to the same
+/// UPPER_SNAKE names, so `[smtp] port` is `SMTP_PORT`) fill in whatever it leaves unset.
+#[derive(Default)]
+struct EnvReader {
+    file: HashMap<String, String>,
+    problems: Vec<String>,
+}
+
+impl EnvReader {
+    fn with_file(file: HashMap<String, String>) -> Self {
+        Self { file, problems: Vec::new() }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        env::var(key).ok().or_else(|| self.file.get(key).cloned())
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.get(key).unwrap_or_else(|| {
+            self.problems.push(format!("{} must be set", key));
+            String::new()
+        })
+    }
+
+    fn optional(&mut self, key: &str) -> Option<String> {
+        self.get(key)
+    }
+
+    /// Whether any variable of an optional section has a non-empty value
+    fn section_present(&self, keys: &[&str]) -> bool {
+        keys.iter().any(|key| self.get(key).is_some_and(|value| !value.is_empty()))
+    }
+
+    fn required_for(&mut self, key: &str, feature: &str) -> String {
+        match self.get(key) {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                self.problems.push(format!("{} must be set to enable {}", key, feature));
+                String::new()
+            }
+        }
+    }
+
+    fn parse_required<T: FromStr + Default>(&mut self, key: &str, expected: &str) -> T {
+        match self.get(key) {
+            Some(value) => self.parse(key, &value, expected),
+            None => {
+                self.problems.push(format!("{} must be set", key));
+                T::default()
+            }
+        }
+    }
+
+    fn parse_or<T: FromStr + Default>(&mut self, key: &str, default: T, expected: &str) -> T {
+        match self.get(key) {
+            Some(value) => self.parse(key, &value, expected),
+            None => default,
+        }
+    }
+
+    fn parse<T: FromStr + Default>(&mut self, key: &str, value: &str, expected: &str) -> T {
+        value.trim().parse().unwrap_or_else(|_| {
+            self.problems.push(format!("{} must be {}, got '{}'", key, expected, value));
+            T::default()
+        })
+    }
+}
+
+/// Flatten a TOML document into the variable names `Config::from_env` reads.
+/// Arrays become comma-separated lists, matching `ADMIN_DIDS`.
+fn flatten_toml(table: &toml::Table, prefix: &str, out: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let name = if prefix.is_empty() {
+            key.to_uppercase()
+        } else {
+            format!("{}_{}", prefix, key.to_uppercase())
+        };
+        match value {
+            toml::Value::Table(nested) => flatten_toml(nested, &name, out),
+            toml::Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(toml_scalar).collect();
+                out.insert(name, items.join(","));
+            }
+            scalar => {
+                out.insert(name, toml_scalar(scalar));
+            }
+        }
+    }
+}
+
+fn toml_scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Keys written as `version:key` pairs, e.g. `1:abcd…,2:ef01…`; `None` if any pair is malformed
+fn parse_versioned_keys(value: &str) -> Option<HashMap<u32, EncryptionKey>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (version, key) = pair.split_once(':')?;
+            Some((version.trim().parse().ok()?, EncryptionKey::from_hex(key.trim())))
+        })
+        .collect()
+}
+
+/// The config file named by `--config <path>` / `--config=<path>`, else `CONFIG_FILE`
+fn config_file_location() -> Option<PathBuf> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    env::var("CONFIG_FILE").ok().filter(|path| !path.is_empty()).map(PathBuf::from)
 }
 
 impl Config {
-    pub fn load() -> Result<Self> {
+    /// Load from the config file selected by `--config`/`CONFIG_FILE` when there is one,
+    /// otherwise from the environment alone
+    pub fn load() -> Result<Self, ConfigError> {
         dotenv::dotenv().ok();
-        
-        Ok(Config {
-            database_url: env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
-            hedera_network: env::var("HEDERA_NETWORK").expect("HEDERA_NETWORK must be set"),
-            hedera_account_id: env::var("HEDERA_ACCOUNT_ID")
-                .expect("HEDERA_ACCOUNT_ID must be set"),
-            hedera_private_key: env::var("HEDERA_PRIVATE_KEY")
-                .expect("HEDERA_PRIVATE_KEY must be set"),
-            ipfs_url: env::var("IPFS_URL").expect("IPFS_URL must be set"),
-            jwt_secret: env::var("JWT_SECRET")
-                .expect("JWT_SECRET must be set"),
-            jwt_expiration_seconds: env::var("JWT_EXPIRATION_SECONDS")
-                .expect("JWT_EXPIRATION_SECONDS must be set")
-                .parse()
-                .expect("Invalid JWT_EXPIRATION_SECONDS"),
-            ipfs_encryption_key: env::var("IPFS_ENCRYPTION_KEY")
-                .expect("IPFS_ENCRYPTION_KEY must be set"),
-            server_port: env::var("SERVER_PORT")
-                .expect("SERVER_PORT must be set")
-                .parse()
-                .expect("Invalid SERVER_PORT"),
-            healthcare_access_control_contract_id: env::var("HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID")
-                .expect("HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID must be set"),
-            verifiable_credentials_contract_id: env::var("VERIFIABLE_CREDENTIALS_CONTRACT_ID")
-                .expect("VERIFIABLE_CREDENTIALS_CONTRACT_ID must be set"),
-            audit_trail_contract_id: env::var("AUDIT_TRAIL_CONTRACT_ID")
-                .expect("AUDIT_TRAIL_CONTRACT_ID must be set"),
-            google_client_id: env::var("GOOGLE_CLIENT_ID").expect("GOOGLE_CLIENT_ID must be set"),
-            twilio_account_sid: env::var("TWILIO_ACCOUNT_SID").expect("TWILIO_ACCOUNT_SID must be set"),
-            twilio_auth_token: env::var("TWILIO_AUTH_TOKEN").expect("TWILIO_AUTH_TOKEN must be set"),
-            twilio_phone_number: env::var("TWILIO_PHONE_NUMBER").expect("TWILIO_PHONE_NUMBER must be set"),
-            gemini_api_key: env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY must be set"),
-            use_tls: env::var("USE_TLS")
-                .expect("USE_TLS must be set")
-                .parse()
-                .expect("Invalid USE_TLS value"),
-            frontend_base_url: env::var("FRONTEND_BASE_URL").expect("FRONTEND_BASE_URL must be set"),
-            backend_base_url: env::var("BACKEND_BASE_URL")
-                .unwrap_or_else(|_| {
-                    // Fallback: construct from server_port and use_tls if not provided
-                    let protocol = if env::var("USE_TLS").unwrap_or_else(|_| "false".to_string()) == "true" {
-                        "https"
-                    } else {
-                        "http"
-                    };
-                    let port = env::var("SERVER_PORT").unwrap_or_else(|_| "8000".to_string());
-                    format!("{}://localhost:{}", protocol, port)
-                }),
-            smtp: SmtpConfig { // Populating SmtpConfig
-                server: env::var("SMTP_SERVER").expect("SMTP_SERVER must be set"),
-                port: env::var("SMTP_PORT")
-                    .expect("SMTP_PORT must be set")
-                    .parse()
-                    .expect("Invalid SMTP_PORT"),
-                username: env::var("SMTP_USERNAME").expect("SMTP_USERNAME must be set"),
-                password: env::var("SMTP_PASSWORD").expect("SMTP_PASSWORD must be set"),
-                from_email: env::var("SMTP_FROM_EMAIL").expect("SMTP_FROM_EMAIL must be set"),
+        match config_file_location() {
+            Some(path) => Self::load_from(path),
+            None => Self::from_env(),
+        }
+    }
+
+    /// Load a TOML config file, with environment variables taking precedence over its values
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let problem = |e: &dyn std::fmt::Display| ConfigError {
+            problems: vec![format!("could not load config file {}: {}", path.display(), e)],
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| problem(&e))?;
+        let table: toml::Table = contents.parse().map_err(|e| problem(&e))?;
+
+        let mut file = HashMap::new();
+        flatten_toml(&table, "", &mut file);
+        Self::from_layers(EnvReader::with_file(file))
+    }
+
+    /// Build the configuration from the process environment alone
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_layers(EnvReader::default())
+    }
+
+    fn from_layers(mut env: EnvReader) -> Result<Self, ConfigError> {
+
+        let use_tls: bool = env.parse_required("USE_TLS", "true or false");
+        let server_port: u16 = env.parse_required("SERVER_PORT", "a port number");
+        // Unset means production, so nothing meant for development runs by accident
+        let environment = env.optional("APP_ENV").map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).unwrap_or_else(|| "production".to_string());
+        let ipfs_retired_encryption_keys = match env.optional("IPFS_RETIRED_ENCRYPTION_KEYS") {
+            Some(keys) => parse_versioned_keys(&keys).unwrap_or_else(|| {
+                env.problems.push("IPFS_RETIRED_ENCRYPTION_KEYS must be a comma-separated list of version:key pairs".to_string());
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+
+        let config = Config {
+            database_url: env.required("DATABASE_URL").into(),
+            hedera_network: env.required("HEDERA_NETWORK"),
+            hedera_account_id: env.required("HEDERA_ACCOUNT_ID"),
+            hedera_private_key: env.required("HEDERA_PRIVATE_KEY").into(),
+            ipfs_url: env.required("IPFS_URL"),
+            jwt_secret: env.required("JWT_SECRET").into(),
+            jwt_expiration_seconds: env.parse_required("JWT_EXPIRATION_SECONDS", "a number"),
+            ipfs_encryption_key: env.required("IPFS_ENCRYPTION_KEY").into(),
+            ipfs_encryption_key_version: env.parse_or("IPFS_ENCRYPTION_KEY_VERSION", 1, "a version number"),
+            ipfs_retired_encryption_keys,
+            server_port,
+            healthcare_access_control_contract_id: env.required("HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID"),
+            verifiable_credentials_contract_id: env.required("VERIFIABLE_CREDENTIALS_CONTRACT_ID"),
+            audit_trail_contract_id: env.required("AUDIT_TRAIL_CONTRACT_ID"),
+            google_client_id: env.required("GOOGLE_CLIENT_ID"),
+            twilio: env.section_present(&["TWILIO_ACCOUNT_SID", "TWILIO_AUTH_TOKEN", "TWILIO_PHONE_NUMBER", "TWILIO_VERIFY_SERVICE_SID"]).then(|| {
+                let verify_service_sid = env.optional("TWILIO_VERIFY_SERVICE_SID").filter(|sid| !sid.is_empty());
+                TwilioConfig {
+                    account_sid: env.required_for("TWILIO_ACCOUNT_SID", "SMS"),
+                    auth_token: env.required_for("TWILIO_AUTH_TOKEN", "SMS").into(),
+                    phone_number: match verify_service_sid {
+                        Some(_) => env.optional("TWILIO_PHONE_NUMBER").unwrap_or_default(),
+                        None => env.required_for("TWILIO_PHONE_NUMBER", "SMS"),
+                    },
+                    verify_service_sid,
+                }
+            }),
+            fcm: env.section_present(&["FCM_CREDENTIALS_PATH"]).then(|| FcmConfig {
+                credentials_path: env.required_for("FCM_CREDENTIALS_PATH", "push notifications"),
+            }),
+            gemini: env.section_present(&["GEMINI_API_KEY"]).then(|| {
+                let defaults = GeminiConfig::default();
+                GeminiConfig {
+                    api_key: env.required_for("GEMINI_API_KEY", "chat").into(),
+                    model: env.optional("GEMINI_MODEL").filter(|model| !model.is_empty()).unwrap_or(defaults.model),
+                    fallback_models: env
+                        .optional("GEMINI_FALLBACK_MODELS")
+                        .map(|v| v.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect())
+                        .unwrap_or(defaults.fallback_models),
+                    temperature: env.parse_or("GEMINI_TEMPERATURE", defaults.temperature, "a number"),
+                    max_output_tokens: env.parse_or("GEMINI_MAX_OUTPUT_TOKENS", defaults.max_output_tokens, "a number of tokens"),
+                    safety_threshold: env.optional("GEMINI_SAFETY_THRESHOLD").unwrap_or(defaults.safety_threshold),
+                }
+            }),
+            chat_blocked_topics: env
+                .optional("CHAT_BLOCKED_TOPICS")
+                .map(|v| v.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+                .unwrap_or_default(),
+            smtp: env.section_present(&["SMTP_SERVER", "SMTP_PORT", "SMTP_USERNAME", "SMTP_PASSWORD", "SMTP_FROM_EMAIL"]).then(|| SmtpConfig {
+                server: env.required_for("SMTP_SERVER", "email"),
+                port: match env.required_for("SMTP_PORT", "email") {
+                    port if port.is_empty() => 0,
+                    port => env.parse("SMTP_PORT", &port, "a port number"),
+                },
+                username: env.required_for("SMTP_USERNAME", "email"),
+                password: env.required_for("SMTP_PASSWORD", "email").into(),
+                from_email: env.required_for("SMTP_FROM_EMAIL", "email"),
+            }),
+            interaction_api: env.section_present(&["INTERACTION_API_URL", "INTERACTION_API_KEY"]).then(|| InteractionApiConfig {
+                url: env.required_for("INTERACTION_API_URL", "the interaction API"),
+                api_key: env.optional("INTERACTION_API_KEY").filter(|key| !key.is_empty()).map(SecretString::from),
+                timeout_seconds: env.parse_or("INTERACTION_API_TIMEOUT_SECONDS", 5, "a number of seconds"),
+            }),
+            captcha: env.section_present(&["CAPTCHA_PROVIDER", "CAPTCHA_SECRET_KEY"]).then(|| CaptchaConfig {
+                provider: match env.required_for("CAPTCHA_PROVIDER", "CAPTCHA checks") {
+                    provider if provider.is_empty() => CaptchaProvider::default(),
+                    provider => env.parse("CAPTCHA_PROVIDER", &provider, "turnstile or recaptcha"),
+                },
+                secret_key: env.required_for("CAPTCHA_SECRET_KEY", "CAPTCHA checks").into(),
+                timeout_seconds: env.parse_or("CAPTCHA_TIMEOUT_SECONDS", 3, "a number of seconds"),
+                fail_open: env.parse_or("CAPTCHA_FAIL_OPEN", false, "true or false"),
+            }),
+            email_template_dir: env.optional("EMAIL_TEMPLATE_DIR").filter(|dir| !dir.is_empty()),
+            use_tls,
+            tls_cert_path: env.optional("TLS_CERT_PATH").unwrap_or_else(|| "cert.pem".to_string()),
+            tls_key_path: env.optional("TLS_KEY_PATH").unwrap_or_else(|| "key.pem".to_string()),
+            frontend_base_url: env.required("FRONTEND_BASE_URL"),
+            backend_base_url: env.optional("BACKEND_BASE_URL").unwrap_or_else(|| {
+                // Fallback: construct from server_port and use_tls if not provided
+                let protocol = if use_tls { "https" } else { "http" };
+                format!("{}://localhost:{}", protocol, server_port)
+            }),
+            admin_dids: env
+                .optional("ADMIN_DIDS")
+                .map(|v| v.split(',').map(|d| d.trim().to_string()).filter(|d| !d.is_empty()).collect())
+                .unwrap_or_default(),
+            platform_admin_dids: env
+                .optional("PLATFORM_ADMIN_DIDS")
+                .map(|v| v.split(',').map(|d| d.trim().to_string()).filter(|d| !d.is_empty()).collect())
+                .unwrap_or_default(),
+            soft_delete_grace_days: env.parse_or("SOFT_DELETE_GRACE_DAYS", 30, "a number of days"),
+            appointment_slot_minutes: env.parse_or("APPOINTMENT_SLOT_MINUTES", 30, "a number of minutes"),
+            break_glass_access_hours: env.parse_or("BREAK_GLASS_ACCESS_HOURS", 4, "a number of hours"),
+            break_glass_review_hours: env.parse_or("BREAK_GLASS_REVIEW_HOURS", 24, "a number of hours"),
+            referral_access_days: env.parse_or("REFERRAL_ACCESS_DAYS", 30, "a number of days"),
+            credential_signing: env.section_present(&["CREDENTIAL_ISSUER_DID", "CREDENTIAL_SIGNING_KEY"]).then(|| CredentialSigningConfig {
+                issuer_did: env.required_for("CREDENTIAL_ISSUER_DID", "credential signing"),
+                key: env.required_for("CREDENTIAL_SIGNING_KEY", "credential signing").into(),
+                key_id: env.optional("CREDENTIAL_SIGNING_KEY_ID").filter(|id| !id.is_empty()).unwrap_or_else(|| "key-1".to_string()),
+            }),
+            credential_presentation_secret: env.optional("CREDENTIAL_PRESENTATION_SECRET").filter(|secret| !secret.is_empty()).map(SecretString::from),
+            credential_presentation_minutes: env.parse_or("CREDENTIAL_PRESENTATION_MINUTES", 5, "a number of minutes"),
+            credential_batch_max_size: env.parse_or("CREDENTIAL_BATCH_MAX_SIZE", 500, "a number of credentials"),
+            step_up_minutes: env.parse_or("STEP_UP_MINUTES", 15, "a number of minutes"),
+            geoip_url: env.optional("GEOIP_URL").filter(|url| !url.is_empty()),
+            vitals: {
+                let defaults = VitalsConfig::default();
+                let expected = "a range like 60-100";
+                VitalsConfig {
+                    systolic: env.parse_or("VITALS_SYSTOLIC_RANGE", defaults.systolic, expected),
+                    diastolic: env.parse_or("VITALS_DIASTOLIC_RANGE", defaults.diastolic, expected),
+                    heart_rate: env.parse_or("VITALS_HEART_RATE_RANGE", defaults.heart_rate, expected),
+                    temperature_c: env.parse_or("VITALS_TEMPERATURE_C_RANGE", defaults.temperature_c, expected),
+                    spo2: env.parse_or("VITALS_SPO2_RANGE", defaults.spo2, expected),
+                    respiratory_rate: env.parse_or("VITALS_RESPIRATORY_RATE_RANGE", defaults.respiratory_rate, expected),
+                }
             },
-        })
+            terminology: {
+                let defaults = TerminologyConfig::default();
+                TerminologyConfig {
+                    server_url: env.optional("TERMINOLOGY_SERVER_URL").filter(|url| !url.is_empty()),
+                    timeout_seconds: env.parse_or("TERMINOLOGY_TIMEOUT_SECONDS", defaults.timeout_seconds, "a number of seconds"),
+                    cache_size: env.parse_or("TERMINOLOGY_CACHE_SIZE", defaults.cache_size, "a number of entries"),
+                    reject_unknown: env.parse_or("TERMINOLOGY_REJECT_UNKNOWN", defaults.reject_unknown, "true or false"),
+                }
+            },
+            reencryption: {
+                let defaults = ReencryptionConfig::default();
+                ReencryptionConfig {
+                    concurrency: env.parse_or("REENCRYPTION_CONCURRENCY", defaults.concurrency, "a number of uploads"),
+                    batch_size: env.parse_or("REENCRYPTION_BATCH_SIZE", defaults.batch_size, "a number of encounters"),
+                }
+            },
+            patient_exports: {
+                let defaults = PatientExportConfig::default();
+                PatientExportConfig {
+                    retention_hours: env.parse_or("PATIENT_EXPORT_RETENTION_HOURS", defaults.retention_hours, "a number of hours"),
+                    download_url_minutes: env.parse_or("PATIENT_EXPORT_DOWNLOAD_URL_MINUTES", defaults.download_url_minutes, "a number of minutes"),
+                }
+            },
+            login_protection: {
+                let defaults = LoginProtectionConfig::default();
+                LoginProtectionConfig {
+                    max_failures: env.parse_or("LOGIN_BLOCK_MAX_FAILURES", defaults.max_failures, "a number of sign-ins"),
+                    max_accounts: env.parse_or("LOGIN_BLOCK_MAX_ACCOUNTS", defaults.max_accounts, "a number of accounts"),
+                    window_minutes: env.parse_or("LOGIN_BLOCK_WINDOW_MINUTES", defaults.window_minutes, "a number of minutes"),
+                    block_minutes: env.parse_or("LOGIN_BLOCK_MINUTES", defaults.block_minutes, "a number of minutes"),
+                    allowlist: env
+                        .optional("LOGIN_BLOCK_ALLOWLIST")
+                        .map(|v| v.split(',').map(|entry| entry.trim().to_string()).filter(|entry| !entry.is_empty()).collect())
+                        .unwrap_or_default(),
+                }
+            },
+            hedera_costs: {
+                let defaults = HederaCostConfig::default();
+                let mut number = |key: &str| {
+                    let value = env.optional(key).filter(|value| !value.is_empty())?;
+                    value.trim().parse::<f64>().ok().or_else(|| {
+                        env.problems.push(format!("{} must be a number, got '{}'", key, value));
+                        None
+                    })
+                };
+                HederaCostConfig {
+                    usd_per_hbar: number("HEDERA_USD_PER_HBAR"),
+                    monthly_budget_hbar: number("HEDERA_MONTHLY_BUDGET_HBAR"),
+                    balance_alert_hbar: number("HEDERA_BALANCE_ALERT_HBAR").unwrap_or(defaults.balance_alert_hbar),
+                    mirror_node_url: env.optional("HEDERA_MIRROR_NODE_URL").filter(|url| !url.is_empty()),
+                    budget_alert_percent: env.parse_or("HEDERA_BUDGET_ALERT_PERCENT", defaults.budget_alert_percent, "a percentage"),
+                    balance_check_minutes: env.parse_or("HEDERA_BALANCE_CHECK_MINUTES", defaults.balance_check_minutes, "a number of minutes"),
+                }
+            },
+            audit_anchoring: {
+                let defaults = AuditAnchoringConfig::default();
+                AuditAnchoringConfig {
+                    alert_unanchored_logs: env.parse_or("AUDIT_ANCHORING_ALERT_UNANCHORED_LOGS", defaults.alert_unanchored_logs, "a number of logs"),
+                    alert_after_hours: env.parse_or("AUDIT_ANCHORING_ALERT_AFTER_HOURS", defaults.alert_after_hours, "a number of hours"),
+                }
+            },
+            sms_throttle: {
+                let defaults = SmsThrottleConfig::default();
+                SmsThrottleConfig {
+                    per_number_per_15_minutes: env.parse_or("SMS_PER_NUMBER_PER_15_MINUTES", defaults.per_number_per_15_minutes, "a number of messages"),
+                    per_number_per_day: env.parse_or("SMS_PER_NUMBER_PER_DAY", defaults.per_number_per_day, "a number of messages"),
+                    daily_budget: match env.optional("SMS_DAILY_BUDGET").filter(|value| !value.is_empty()) {
+                        Some(value) => Some(env.parse("SMS_DAILY_BUDGET", &value, "a number of messages")),
+                        None => None,
+                    },
+                    usd_per_message: match env.optional("SMS_USD_PER_MESSAGE").filter(|value| !value.is_empty()) {
+                        Some(value) => Some(env.parse("SMS_USD_PER_MESSAGE", &value, "a number")),
+                        None => None,
+                    },
+                }
+            },
+            run_migrations: env.parse_or("RUN_MIGRATIONS", false, "true or false"),
+            skip_index_creation: env.parse_or("SKIP_INDEX_CREATION", false, "true or false"),
+            environment: environment.clone(),
+            http: {
+                let defaults = HttpConfig::default();
+                HttpConfig {
+                    max_body_bytes: env.parse_or("HTTP_MAX_BODY_BYTES", defaults.max_body_bytes, "a number of bytes"),
+                    max_upload_bytes: env.parse_or("HTTP_MAX_UPLOAD_BYTES", defaults.max_upload_bytes, "a number of bytes"),
+                    compression_min_bytes: env.parse_or("HTTP_COMPRESSION_MIN_BYTES", defaults.compression_min_bytes, "a number of bytes up to 65535"),
+                    body_timeout_seconds: env.parse_or("HTTP_BODY_TIMEOUT_SECONDS", defaults.body_timeout_seconds, "a number of seconds"),
+                    request_timeout_seconds: env.parse_or("HTTP_REQUEST_TIMEOUT_SECONDS", defaults.request_timeout_seconds, "a number of seconds"),
+                    upstream_timeout_seconds: env.parse_or("HTTP_UPSTREAM_TIMEOUT_SECONDS", defaults.upstream_timeout_seconds, "a number of seconds"),
+                }
+            },
+            jobs: {
+                let defaults = JobConfig::default();
+                JobConfig {
+                    concurrency: env.parse_or("JOB_CONCURRENCY", defaults.concurrency, "a number of workers"),
+                    poll_interval_seconds: env.parse_or("JOB_POLL_INTERVAL_SECONDS", defaults.poll_interval_seconds, "a number of seconds"),
+                    lease_seconds: env.parse_or("JOB_LEASE_SECONDS", defaults.lease_seconds, "a number of seconds"),
+                    max_attempts: env.parse_or("JOB_MAX_ATTEMPTS", defaults.max_attempts, "a number of attempts"),
+                }
+            },
+            idempotency: IdempotencyConfig {
+                ttl_hours: env.parse_or("IDEMPOTENCY_KEY_TTL_HOURS", IdempotencyConfig::default().ttl_hours, "a number of hours"),
+            },
+            patient_search: PatientSearchConfig {
+                key: env.optional("PATIENT_SEARCH_KEY").filter(|key| !key.is_empty()).map(SecretString::from),
+                max_results: env.parse_or("PATIENT_SEARCH_MAX_RESULTS", PatientSearchConfig::default().max_results, "a number of results"),
+            },
+            webhooks: WebhookConfig {
+                allow_http: env.parse_or("WEBHOOK_ALLOW_HTTP", false, "true or false"),
+                timeout_seconds: env.parse_or("WEBHOOK_TIMEOUT_SECONDS", WebhookConfig::default().timeout_seconds, "a number of seconds"),
+            },
+            events: EventBusConfig {
+                capacity: env.parse_or("EVENT_BUS_CAPACITY", EventBusConfig::default().capacity, "a number of events"),
+            },
+            notification_stream: NotificationStreamConfig {
+                heartbeat_seconds: env.parse_or("NOTIFICATION_STREAM_HEARTBEAT_SECONDS", NotificationStreamConfig::default().heartbeat_seconds, "a number of seconds"),
+                retention_hours: env.parse_or("NOTIFICATION_RETENTION_HOURS", NotificationStreamConfig::default().retention_hours, "a number of hours"),
+                max_streams_per_user: env.parse_or("NOTIFICATION_STREAMS_PER_USER", NotificationStreamConfig::default().max_streams_per_user, "a number of streams"),
+            },
+            uploads: {
+                let defaults = UploadConfig::default();
+                UploadConfig {
+                    max_pdf_bytes: env.parse_or("UPLOAD_MAX_PDF_BYTES", defaults.max_pdf_bytes, "a number of bytes"),
+                    max_image_bytes: env.parse_or("UPLOAD_MAX_IMAGE_BYTES", defaults.max_image_bytes, "a number of bytes"),
+                    max_dicom_bytes: env.parse_or("UPLOAD_MAX_DICOM_BYTES", defaults.max_dicom_bytes, "a number of bytes"),
+                    max_photo_bytes: env.parse_or("UPLOAD_MAX_PHOTO_BYTES", defaults.max_photo_bytes, "a number of bytes"),
+                    photo_min_dimension: env.parse_or("PHOTO_MIN_DIMENSION", defaults.photo_min_dimension, "a number of pixels"),
+                    photo_max_dimension: env.parse_or("PHOTO_MAX_DIMENSION", defaults.photo_max_dimension, "a number of pixels"),
+                    photo_thumbnail_size: env.parse_or("PHOTO_THUMBNAIL_SIZE", defaults.photo_thumbnail_size, "a number of pixels"),
+                    clamd_address: env.optional("CLAMD_ADDRESS").filter(|address| !address.is_empty()),
+                    scan_timeout_seconds: env.parse_or("UPLOAD_SCAN_TIMEOUT_SECONDS", defaults.scan_timeout_seconds, "a number of seconds"),
+                    scan_fail_open: env.parse_or("UPLOAD_SCAN_FAIL_OPEN", defaults.scan_fail_open, "true or false"),
+                }
+            },
+            logging: {
+                let defaults = LoggingConfig::default();
+                LoggingConfig {
+                    format: env.parse_or("LOG_FORMAT", defaults.format, "pretty or json"),
+                    redacted_routes: env
+                        .optional("LOG_REDACTED_ROUTES")
+                        .map(|v| v.split(',').map(|route| route.trim().to_string()).filter(|route| !route.is_empty()).collect())
+                        .unwrap_or_default(),
+                }
+            },
+            telemetry: {
+                let defaults = TelemetryConfig::default();
+                TelemetryConfig {
+                    otlp_endpoint: env.optional("OTEL_EXPORTER_OTLP_ENDPOINT").filter(|endpoint| !endpoint.is_empty()),
+                    service_name: env.optional("OTEL_SERVICE_NAME").filter(|name| !name.trim().is_empty()).unwrap_or(defaults.service_name),
+                    sample_ratio: env.parse_or("OTEL_TRACES_SAMPLER_ARG", defaults.sample_ratio, "a ratio between 0 and 1"),
+                }
+            },
+            error_reporting: ErrorReportingConfig {
+                dsn: env.optional("SENTRY_DSN").filter(|dsn| !dsn.is_empty()),
+                // Reports are tagged with the deployment's environment unless Sentry is told otherwise
+                environment: env.optional("SENTRY_ENVIRONMENT").filter(|name| !name.trim().is_empty()).unwrap_or(environment),
+                release: env.optional("SENTRY_RELEASE").filter(|release| !release.trim().is_empty()),
+            },
+            dynamic: {
+                let defaults = DynamicConfig::default();
+                LiveConfig::from(DynamicConfig {
+                    cors_allowed_origins: env
+                        .optional("CORS_ALLOWED_ORIGINS")
+                        .map(|v| v.split(',').map(|origin| origin.trim().trim_end_matches('/').to_string()).filter(|origin| !origin.is_empty()).collect())
+                        .unwrap_or_default(),
+                    log_level: env.optional("LOG_LEVEL").filter(|level| !level.trim().is_empty()).unwrap_or(defaults.log_level),
+                    require_consent: env.parse_or("REQUIRE_CONSENT", defaults.require_consent, "true or false"),
+                    require_verified_practitioners: env.parse_or("REQUIRE_VERIFIED_PRACTITIONERS", defaults.require_verified_practitioners, "true or false"),
+                    chat_record_context: env.parse_or("CHAT_RECORD_CONTEXT", defaults.chat_record_context, "true or false"),
+                    chat_daily_request_limit: env.parse_or("CHAT_DAILY_REQUEST_LIMIT", defaults.chat_daily_request_limit, "a number of requests"),
+                    chat_daily_token_limit: env.parse_or("CHAT_DAILY_TOKEN_LIMIT", defaults.chat_daily_token_limit, "a number of tokens"),
+                    prescription_verify_per_minute: env.parse_or("PRESCRIPTION_VERIFY_PER_MINUTE", defaults.prescription_verify_per_minute, "a number of requests"),
+                    data_access_patients_per_hour: env.parse_or("DATA_ACCESS_PATIENTS_PER_HOUR", defaults.data_access_patients_per_hour, "a number of patients"),
+                    data_access_patients_alert_per_hour: env.parse_or("DATA_ACCESS_PATIENTS_ALERT_PER_HOUR", defaults.data_access_patients_alert_per_hour, "a number of patients"),
+                    data_access_downloads_per_hour: env.parse_or("DATA_ACCESS_DOWNLOADS_PER_HOUR", defaults.data_access_downloads_per_hour, "a number of downloads"),
+                    data_access_downloads_alert_per_hour: env.parse_or("DATA_ACCESS_DOWNLOADS_ALERT_PER_HOUR", defaults.data_access_downloads_alert_per_hour, "a number of downloads"),
+                })
+            },
+        };
+
+        let mut problems = env.problems;
+        problems.extend(config.validate());
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    /// The bundle encryption key with this version, current or retired
+    pub fn encryption_key(&self, version: u32) -> Option<&EncryptionKey> {
+        if version == self.ipfs_encryption_key_version {
+            Some(&self.ipfs_encryption_key)
+        } else {
+            self.ipfs_retired_encryption_keys.get(&version)
+        }
+    }
+
+    /// Settings outside [`DynamicConfig`] that `reloaded` changes, by name. A reload cannot
+    /// apply them; they need a restart. Secrets are compared without being printed.
+    pub fn boot_changes(&self, reloaded: &Config) -> Vec<String> {
+        // Secrets serialize as [REDACTED], so the comparison below cannot see them change
+        let secrets = [
+            ("database_url", self.database_url != reloaded.database_url),
+            ("hedera_private_key", self.hedera_private_key != reloaded.hedera_private_key),
+            ("jwt_secret", self.jwt_secret != reloaded.jwt_secret),
+            ("ipfs_encryption_key", self.ipfs_encryption_key != reloaded.ipfs_encryption_key),
+            ("ipfs_retired_encryption_keys", self.ipfs_retired_encryption_keys != reloaded.ipfs_retired_encryption_keys),
+            ("credential_presentation_secret", self.credential_presentation_secret != reloaded.credential_presentation_secret),
+            ("smtp.password", self.smtp.as_ref().map(|smtp| &smtp.password) != reloaded.smtp.as_ref().map(|smtp| &smtp.password)),
+            ("twilio.auth_token", self.twilio.as_ref().map(|twilio| &twilio.auth_token) != reloaded.twilio.as_ref().map(|twilio| &twilio.auth_token)),
+            ("gemini.api_key", self.gemini.as_ref().map(|gemini| &gemini.api_key) != reloaded.gemini.as_ref().map(|gemini| &gemini.api_key)),
+            ("credential_signing.key", self.credential_signing.as_ref().map(|signing| &signing.key) != reloaded.credential_signing.as_ref().map(|signing| &signing.key)),
+            (
+                "interaction_api.api_key",
+                self.interaction_api.as_ref().map(|api| &api.api_key) != reloaded.interaction_api.as_ref().map(|api| &api.api_key),
+            ),
+            ("captcha.secret_key", self.captcha.as_ref().map(|captcha| &captcha.secret_key) != reloaded.captcha.as_ref().map(|captcha| &captcha.secret_key)),
+            ("patient_search.key", self.patient_search.key != reloaded.patient_search.key),
+        ];
+        let mut changed: Vec<String> = secrets.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name.to_string()).collect();
+        let (serde_json::Value::Object(before), serde_json::Value::Object(after)) = (json_or_null(self), json_or_null(reloaded)) else {
+            return changed;
+        };
+        for (name, value) in &before {
+            if name != "dynamic" && after.get(name) != Some(value) && !changed.iter().any(|secret| secret.split('.').next() == Some(name.as_str())) {
+                changed.push(name.clone());
+            }
+        }
+        changed.sort();
+        changed
+    }
+
+    /// Which optional integrations this configuration enables
+    pub fn validate_features(&self) -> Features {
+        Features {
+            sms: self.twilio.is_some(),
+            email: self.smtp.is_some(),
+            chat: self.gemini.is_some(),
+            push: self.fcm.is_some(),
+            malware_scan: self.uploads.clamd_address.is_some(),
+        }
+    }
+
+    /// Cross-field checks that only make sense once every variable has been read.
+    /// Empty values are skipped; those are already reported as missing.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let contract_ids = [
+            ("HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID", &self.healthcare_access_control_contract_id),
+            ("VERIFIABLE_CREDENTIALS_CONTRACT_ID", &self.verifiable_credentials_contract_id),
+            ("AUDIT_TRAIL_CONTRACT_ID", &self.audit_trail_contract_id),
+        ];
+        for (key, value) in contract_ids {
+            if !value.is_empty() && ContractId::from_str(value).is_err() {
+                problems.push(format!("{} must be a contract id like 0.0.1234, got '{}'", key, value));
+            }
+        }
+
+        if !self.ipfs_encryption_key.is_empty() && !self.ipfs_encryption_key.is_valid() {
+            problems.push("IPFS_ENCRYPTION_KEY must be 32 bytes encoded as 64 hex characters".to_string());
+        }
+        if self.ipfs_encryption_key_version == 0 {
+            problems.push("IPFS_ENCRYPTION_KEY_VERSION must be at least 1".to_string());
+        }
+        let mut retired: Vec<_> = self.ipfs_retired_encryption_keys.iter().collect();
+        retired.sort_by_key(|(version, _)| **version);
+        for (version, key) in retired {
+            if *version == self.ipfs_encryption_key_version {
+                problems.push(format!("IPFS_RETIRED_ENCRYPTION_KEYS must not include the current version {}", version));
+            }
+            if !key.is_valid() {
+                problems.push(format!("IPFS_RETIRED_ENCRYPTION_KEYS key {} must be 32 bytes encoded as 64 hex characters", version));
+            }
+        }
+
+        if self.appointment_slot_minutes == 0 || self.appointment_slot_minutes > 24 * 60 {
+            problems.push(format!("APPOINTMENT_SLOT_MINUTES must be between 1 and 1440, got '{}'", self.appointment_slot_minutes));
+        }
+        for (key, hours) in [("BREAK_GLASS_ACCESS_HOURS", self.break_glass_access_hours), ("BREAK_GLASS_REVIEW_HOURS", self.break_glass_review_hours)] {
+            if hours <= 0 {
+                problems.push(format!("{} must be a positive number of hours, got '{}'", key, hours));
+            }
+        }
+        if self.referral_access_days <= 0 {
+            problems.push(format!("REFERRAL_ACCESS_DAYS must be a positive number of days, got '{}'", self.referral_access_days));
+        }
+        let dynamic = self.dynamic.load();
+        if dynamic.prescription_verify_per_minute == 0 {
+            problems.push("PRESCRIPTION_VERIFY_PER_MINUTE must be at least 1".to_string());
+        }
+        for (key, alert_key, limit, alert) in [
+            ("DATA_ACCESS_PATIENTS_PER_HOUR", "DATA_ACCESS_PATIENTS_ALERT_PER_HOUR", dynamic.data_access_patients_per_hour, dynamic.data_access_patients_alert_per_hour),
+            ("DATA_ACCESS_DOWNLOADS_PER_HOUR", "DATA_ACCESS_DOWNLOADS_ALERT_PER_HOUR", dynamic.data_access_downloads_per_hour, dynamic.data_access_downloads_alert_per_hour),
+        ] {
+            if alert == 0 || alert >= limit {
+                problems.push(format!("{} must be at least 1 and below {} ({}), got '{}'", alert_key, key, limit, alert));
+            }
+        }
+        if let Some(signing) = &self.credential_signing {
+            if !signing.issuer_did.is_empty() && !signing.issuer_did.starts_with("did:") {
+                problems.push(format!("CREDENTIAL_ISSUER_DID must be a DID, got '{}'", signing.issuer_did));
+            }
+            if !signing.key.is_empty() && hex::decode(signing.key.expose_secret()).map(Zeroizing::new).map_or(true, |key| key.len() != 32) {
+                problems.push("CREDENTIAL_SIGNING_KEY must be 32 bytes encoded as 64 hex characters".to_string());
+            }
+        }
+        if self.credential_presentation_secret.as_ref().is_some_and(|secret| secret.expose_secret().len() < 32) {
+            problems.push("CREDENTIAL_PRESENTATION_SECRET must be at least 32 characters".to_string());
+        }
+        if !(1..=15).contains(&self.credential_presentation_minutes) {
+            problems.push(format!("CREDENTIAL_PRESENTATION_MINUTES must be between 1 and 15, got '{}'", self.credential_presentation_minutes));
+        }
+        if !(1..=1000).contains(&self.credential_batch_max_size) {
+            problems.push(format!("CREDENTIAL_BATCH_MAX_SIZE must be between 1 and 1000, got '{}'", self.credential_batch_max_size));
+        }
+        if !(1..=60).contains(&self.step_up_minutes) {
+            problems.push(format!("STEP_UP_MINUTES must be between 1 and 60, got '{}'", self.step_up_minutes));
+        }
+        if self.geoip_url.as_deref().is_some_and(|url| !url.contains("{ip}")) {
+            problems.push("GEOIP_URL must contain an {ip} placeholder".to_string());
+        }
+
+        let ranges = [
+            ("VITALS_SYSTOLIC_RANGE", self.vitals.systolic),
+            ("VITALS_DIASTOLIC_RANGE", self.vitals.diastolic),
+            ("VITALS_HEART_RATE_RANGE", self.vitals.heart_rate),
+            ("VITALS_TEMPERATURE_C_RANGE", self.vitals.temperature_c),
+            ("VITALS_SPO2_RANGE", self.vitals.spo2),
+            ("VITALS_RESPIRATORY_RATE_RANGE", self.vitals.respiratory_rate),
+        ];
+        for (key, range) in ranges {
+            // An unparsable range is already reported and left at 0-0
+            if range != ReferenceRange::default() && range.low >= range.high {
+                problems.push(format!("{} must have its low end below its high end, got '{}-{}'", key, range.low, range.high));
+            }
+        }
+
+        if self.terminology.cache_size == 0 {
+            problems.push("TERMINOLOGY_CACHE_SIZE must be at least 1".to_string());
+        }
+
+        if self.reencryption.concurrency == 0 || self.reencryption.batch_size == 0 {
+            problems.push("REENCRYPTION_CONCURRENCY and REENCRYPTION_BATCH_SIZE must be at least 1".to_string());
+        }
+
+        let login = &self.login_protection;
+        if login.max_failures == 0 || login.max_accounts == 0 || login.window_minutes < 1 || login.block_minutes < 1 {
+            problems.push(
+                "LOGIN_BLOCK_MAX_FAILURES, LOGIN_BLOCK_MAX_ACCOUNTS, LOGIN_BLOCK_WINDOW_MINUTES and LOGIN_BLOCK_MINUTES must be at least 1".to_string(),
+            );
+        }
+        for entry in login.allowlist.iter().filter(|entry| IpNetwork::parse(entry).is_none()) {
+            problems.push(format!("LOGIN_BLOCK_ALLOWLIST entries must be addresses or CIDR ranges, got '{}'", entry));
+        }
+
+        let sms = &self.sms_throttle;
+        if sms.per_number_per_15_minutes == 0 || sms.per_number_per_day == 0 || sms.daily_budget == Some(0) {
+            problems.push("SMS_PER_NUMBER_PER_15_MINUTES, SMS_PER_NUMBER_PER_DAY and SMS_DAILY_BUDGET must be at least 1".to_string());
+        }
+        if sms.usd_per_message.is_some_and(|price| price <= 0.0) {
+            problems.push("SMS_USD_PER_MESSAGE must be greater than 0".to_string());
+        }
+
+        let costs = &self.hedera_costs;
+        for (key, value) in [
+            ("HEDERA_USD_PER_HBAR", costs.usd_per_hbar),
+            ("HEDERA_MONTHLY_BUDGET_HBAR", costs.monthly_budget_hbar),
+            ("HEDERA_BALANCE_ALERT_HBAR", Some(costs.balance_alert_hbar)),
+        ] {
+            if value.is_some_and(|value| value <= 0.0) {
+                problems.push(format!("{} must be greater than 0", key));
+            }
+        }
+        if !(1..=100).contains(&costs.budget_alert_percent) {
+            problems.push(format!("HEDERA_BUDGET_ALERT_PERCENT must be between 1 and 100, got '{}'", costs.budget_alert_percent));
+        }
+        if costs.balance_check_minutes == 0 {
+            problems.push("HEDERA_BALANCE_CHECK_MINUTES must be at least 1".to_string());
+        }
+        if self.audit_anchoring.alert_unanchored_logs == 0 || self.audit_anchoring.alert_after_hours < 1 {
+            problems.push("AUDIT_ANCHORING_ALERT_UNANCHORED_LOGS and AUDIT_ANCHORING_ALERT_AFTER_HOURS must be at least 1".to_string());
+        }
+
+        for (key, value) in [
+            ("JOB_CONCURRENCY", self.jobs.concurrency as i64),
+            ("JOB_POLL_INTERVAL_SECONDS", self.jobs.poll_interval_seconds as i64),
+            ("JOB_LEASE_SECONDS", self.jobs.lease_seconds),
+            ("JOB_MAX_ATTEMPTS", i64::from(self.jobs.max_attempts)),
+            ("IDEMPOTENCY_KEY_TTL_HOURS", self.idempotency.ttl_hours),
+            ("PATIENT_EXPORT_RETENTION_HOURS", self.patient_exports.retention_hours),
+            ("PATIENT_EXPORT_DOWNLOAD_URL_MINUTES", self.patient_exports.download_url_minutes),
+            ("PATIENT_SEARCH_MAX_RESULTS", self.patient_search.max_results as i64),
+            ("WEBHOOK_TIMEOUT_SECONDS", self.webhooks.timeout_seconds as i64),
+            ("EVENT_BUS_CAPACITY", self.events.capacity as i64),
+            ("NOTIFICATION_STREAM_HEARTBEAT_SECONDS", self.notification_stream.heartbeat_seconds as i64),
+            ("NOTIFICATION_RETENTION_HOURS", self.notification_stream.retention_hours),
+            ("NOTIFICATION_STREAMS_PER_USER", self.notification_stream.max_streams_per_user as i64),
+            ("UPLOAD_SCAN_TIMEOUT_SECONDS", self.uploads.scan_timeout_seconds as i64),
+            ("PHOTO_MIN_DIMENSION", i64::from(self.uploads.photo_min_dimension)),
+            ("PHOTO_THUMBNAIL_SIZE", i64::from(self.uploads.photo_thumbnail_size)),
+        ] {
+            if value < 1 {
+                problems.push(format!("{} must be at least 1", key));
+            }
+        }
+        if self.uploads.photo_max_dimension < self.uploads.photo_min_dimension {
+            problems.push("PHOTO_MAX_DIMENSION must not be smaller than PHOTO_MIN_DIMENSION".to_string());
+        }
+
+        if self.http.max_upload_bytes < self.http.max_body_bytes {
+            problems.push("HTTP_MAX_UPLOAD_BYTES must not be smaller than HTTP_MAX_BODY_BYTES".to_string());
+        }
+        if self.http.upstream_timeout_seconds < self.http.request_timeout_seconds {
+            problems.push("HTTP_UPSTREAM_TIMEOUT_SECONDS must not be smaller than HTTP_REQUEST_TIMEOUT_SECONDS".to_string());
+        }
+        for (key, cap) in [
+            ("UPLOAD_MAX_PDF_BYTES", self.uploads.max_pdf_bytes),
+            ("UPLOAD_MAX_IMAGE_BYTES", self.uploads.max_image_bytes),
+            ("UPLOAD_MAX_DICOM_BYTES", self.uploads.max_dicom_bytes),
+            ("UPLOAD_MAX_PHOTO_BYTES", self.uploads.max_photo_bytes),
+        ] {
+            // Larger caps could never be reached, as the body limit applies first
+            if cap == 0 || cap > self.http.max_upload_bytes {
+                problems.push(format!("{} must be between 1 and HTTP_MAX_UPLOAD_BYTES ({}), got '{}'", key, self.http.max_upload_bytes, cap));
+            }
+        }
+        if tracing_subscriber::EnvFilter::try_new(&dynamic.log_level).is_err() {
+            problems.push(format!("LOG_LEVEL must be tracing filter directives such as 'healthcare_backend=info', got '{}'", dynamic.log_level));
+        }
+        for origin in dynamic.cors_allowed_origins.iter().filter(|origin| origin.parse::<axum::http::HeaderValue>().is_err() || !origin.contains("://")) {
+            problems.push(format!("CORS_ALLOWED_ORIGINS entries must be origins such as 'https://app.example.com', got '{}'", origin));
+        }
+        if !(0.0..=1.0).contains(&self.telemetry.sample_ratio) {
+            problems.push(format!("OTEL_TRACES_SAMPLER_ARG must be a ratio between 0 and 1, got '{}'", self.telemetry.sample_ratio));
+        }
+        if self.error_reporting.dsn.as_ref().is_some_and(|dsn| dsn.parse::<sentry::types::Dsn>().is_err()) {
+            problems.push("SENTRY_DSN must be a DSN such as 'https://<key>@sentry.example.com/<project>'".to_string());
+        }
+        for route in self.logging.redacted_routes.iter().filter(|route| !route.starts_with('/')) {
+            problems.push(format!("LOG_REDACTED_ROUTES entries must be route templates such as '/api/patients/:did', got '{}'", route));
+        }
+        if self.patient_search.key.as_ref().is_some_and(|key| key.expose_secret().len() < 32) {
+            problems.push("PATIENT_SEARCH_KEY must be at least 32 characters".to_string());
+        }
+
+        if let Some(gemini) = &self.gemini {
+            if !(0.0..=2.0).contains(&gemini.temperature) {
+                problems.push(format!("GEMINI_TEMPERATURE must be between 0 and 2, got '{}'", gemini.temperature));
+            }
+            if !GEMINI_SAFETY_THRESHOLDS.contains(&gemini.safety_threshold.as_str()) {
+                problems.push(format!(
+                    "GEMINI_SAFETY_THRESHOLD must be one of {}, got '{}'",
+                    GEMINI_SAFETY_THRESHOLDS.join(", "),
+                    gemini.safety_threshold
+                ));
+            }
+        }
+
+        if self.jwt_expiration_seconds < 0 {
+            problems.push(format!("JWT_EXPIRATION_SECONDS must not be negative, got '{}'", self.jwt_expiration_seconds));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    // The environment is process-wide, so tests touching it take turns
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const KEYS: &[&str] = &[
+        "DATABASE_URL", "HEDERA_NETWORK", "HEDERA_ACCOUNT_ID", "HEDERA_PRIVATE_KEY", "IPFS_URL",
+        "JWT_SECRET", "JWT_EXPIRATION_SECONDS", "IPFS_ENCRYPTION_KEY", "SERVER_PORT",
+        "HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID", "VERIFIABLE_CREDENTIALS_CONTRACT_ID", "AUDIT_TRAIL_CONTRACT_ID",
+        "GOOGLE_CLIENT_ID", "TWILIO_ACCOUNT_SID", "TWILIO_AUTH_TOKEN", "TWILIO_PHONE_NUMBER",
+        "TWILIO_VERIFY_SERVICE_SID", "GEMINI_API_KEY", "GEMINI_MODEL", "GEMINI_FALLBACK_MODELS", "GEMINI_TEMPERATURE",
+        "GEMINI_MAX_OUTPUT_TOKENS", "GEMINI_SAFETY_THRESHOLD", "CHAT_RECORD_CONTEXT", "CHAT_BLOCKED_TOPICS", "CHAT_DAILY_REQUEST_LIMIT", "CHAT_DAILY_TOKEN_LIMIT", "USE_TLS", "TLS_CERT_PATH", "TLS_KEY_PATH", "FRONTEND_BASE_URL", "BACKEND_BASE_URL", "SMTP_SERVER", "SMTP_PORT",
+        "SMTP_USERNAME", "SMTP_PASSWORD", "SMTP_FROM_EMAIL", "EMAIL_TEMPLATE_DIR", "ADMIN_DIDS", "PLATFORM_ADMIN_DIDS", "SOFT_DELETE_GRACE_DAYS", "REQUIRE_CONSENT", "REQUIRE_VERIFIED_PRACTITIONERS", "APPOINTMENT_SLOT_MINUTES", "BREAK_GLASS_ACCESS_HOURS", "BREAK_GLASS_REVIEW_HOURS",
+        "REFERRAL_ACCESS_DAYS", "PRESCRIPTION_VERIFY_PER_MINUTE",
+        "DATA_ACCESS_PATIENTS_PER_HOUR", "DATA_ACCESS_PATIENTS_ALERT_PER_HOUR", "DATA_ACCESS_DOWNLOADS_PER_HOUR", "DATA_ACCESS_DOWNLOADS_ALERT_PER_HOUR", "CREDENTIAL_PRESENTATION_SECRET", "CREDENTIAL_PRESENTATION_MINUTES", "CREDENTIAL_BATCH_MAX_SIZE", "STEP_UP_MINUTES", "GEOIP_URL",
+        "CREDENTIAL_ISSUER_DID", "CREDENTIAL_SIGNING_KEY", "CREDENTIAL_SIGNING_KEY_ID",
+        "INTERACTION_API_URL", "INTERACTION_API_KEY", "INTERACTION_API_TIMEOUT_SECONDS",
+        "CAPTCHA_PROVIDER", "CAPTCHA_SECRET_KEY", "CAPTCHA_TIMEOUT_SECONDS", "CAPTCHA_FAIL_OPEN",
+        "VITALS_SYSTOLIC_RANGE", "VITALS_DIASTOLIC_RANGE", "VITALS_HEART_RATE_RANGE", "VITALS_TEMPERATURE_C_RANGE", "VITALS_SPO2_RANGE",
+        "VITALS_RESPIRATORY_RATE_RANGE", "TERMINOLOGY_SERVER_URL", "TERMINOLOGY_TIMEOUT_SECONDS", "TERMINOLOGY_CACHE_SIZE",
+        "TERMINOLOGY_REJECT_UNKNOWN", "IPFS_ENCRYPTION_KEY_VERSION", "IPFS_RETIRED_ENCRYPTION_KEYS",
+        "REENCRYPTION_CONCURRENCY", "REENCRYPTION_BATCH_SIZE", "PATIENT_EXPORT_RETENTION_HOURS", "PATIENT_EXPORT_DOWNLOAD_URL_MINUTES",
+        "LOGIN_BLOCK_MAX_FAILURES", "LOGIN_BLOCK_MAX_ACCOUNTS", "LOGIN_BLOCK_WINDOW_MINUTES", "LOGIN_BLOCK_MINUTES", "LOGIN_BLOCK_ALLOWLIST",
+        "HEDERA_USD_PER_HBAR", "HEDERA_MIRROR_NODE_URL", "HEDERA_MONTHLY_BUDGET_HBAR", "HEDERA_BUDGET_ALERT_PERCENT",
+        "HEDERA_BALANCE_ALERT_HBAR", "HEDERA_BALANCE_CHECK_MINUTES", "AUDIT_ANCHORING_ALERT_UNANCHORED_LOGS", "AUDIT_ANCHORING_ALERT_AFTER_HOURS",
+        "SMS_PER_NUMBER_PER_15_MINUTES", "SMS_PER_NUMBER_PER_DAY", "SMS_DAILY_BUDGET", "SMS_USD_PER_MESSAGE",
+        "RUN_MIGRATIONS", "SKIP_INDEX_CREATION", "HTTP_MAX_BODY_BYTES", "HTTP_MAX_UPLOAD_BYTES", "HTTP_COMPRESSION_MIN_BYTES",
+        "HTTP_BODY_TIMEOUT_SECONDS", "HTTP_REQUEST_TIMEOUT_SECONDS", "HTTP_UPSTREAM_TIMEOUT_SECONDS",
+        "JOB_CONCURRENCY", "JOB_POLL_INTERVAL_SECONDS", "JOB_LEASE_SECONDS", "JOB_MAX_ATTEMPTS",
+        "IDEMPOTENCY_KEY_TTL_HOURS", "PATIENT_SEARCH_KEY", "PATIENT_SEARCH_MAX_RESULTS",
+        "WEBHOOK_ALLOW_HTTP", "WEBHOOK_TIMEOUT_SECONDS", "EVENT_BUS_CAPACITY",
+        "UPLOAD_MAX_PDF_BYTES", "UPLOAD_MAX_IMAGE_BYTES", "UPLOAD_MAX_DICOM_BYTES", "UPLOAD_MAX_PHOTO_BYTES",
+        "PHOTO_MIN_DIMENSION", "PHOTO_MAX_DIMENSION", "PHOTO_THUMBNAIL_SIZE", "CLAMD_ADDRESS",
+        "UPLOAD_SCAN_TIMEOUT_SECONDS", "UPLOAD_SCAN_FAIL_OPEN", "LOG_FORMAT", "LOG_LEVEL", "LOG_REDACTED_ROUTES", "CORS_ALLOWED_ORIGINS",
+        "OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_SERVICE_NAME", "OTEL_TRACES_SAMPLER_ARG",
+        "SENTRY_DSN", "SENTRY_ENVIRONMENT", "SENTRY_RELEASE", "APP_ENV",
+    ];
+
+    /// Replaces the config variables for the lifetime of the guard, restoring them on drop
+    struct ScopedEnv {
+        saved: Vec<(&'static str, Option<String>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl ScopedEnv {
+        fn new(vars: &[(&str, &str)]) -> Self {
+            let lock = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let saved = KEYS.iter().map(|key| (*key, env::var(key).ok())).collect();
+            for key in KEYS {
+                env::remove_var(key);
+            }
+            for (key, value) in vars {
+                env::set_var(key, value);
+            }
+            Self { saved, _lock: lock }
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for (key, value) in &self.saved {
+                match value {
+                    Some(value) => env::set_var(key, value),
+                    None => env::remove_var(key),
+                }
+            }
+        }
+    }
+
+    fn complete_env() -> Vec<(&'static str, String)> {
+        vec![
+            ("DATABASE_URL", "mongodb://localhost:27017".to_string()),
+            ("HEDERA_NETWORK", "testnet".to_string()),
+            ("HEDERA_ACCOUNT_ID", "0.0.2".to_string()),
+            ("HEDERA_PRIVATE_KEY", "key".to_string()),
+            ("IPFS_URL", "http://localhost:5001".to_string()),
+            ("JWT_SECRET", "secret".to_string()),
+            ("JWT_EXPIRATION_SECONDS", "3600".to_string()),
+            ("IPFS_ENCRYPTION_KEY", "00".repeat(32)),
+            ("SERVER_PORT", "8000".to_string()),
+            ("HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID", "0.0.1001".to_string()),
+            ("VERIFIABLE_CREDENTIALS_CONTRACT_ID", "0.0.1002".to_string()),
+            ("AUDIT_TRAIL_CONTRACT_ID", "0.0.1003".to_string()),
+            ("GOOGLE_CLIENT_ID", "client".to_string()),
+            ("TWILIO_ACCOUNT_SID", String::new()),
+            ("TWILIO_AUTH_TOKEN", String::new()),
+            ("TWILIO_PHONE_NUMBER", String::new()),
+            ("GEMINI_API_KEY", "gemini".to_string()),
+            ("USE_TLS", "false".to_string()),
+            ("FRONTEND_BASE_URL", "http://localhost:3000".to_string()),
+            ("SMTP_SERVER", "smtp.example.com".to_string()),
+            ("SMTP_PORT", "587".to_string()),
+            ("SMTP_USERNAME", "user".to_string()),
+            ("SMTP_PASSWORD", "password".to_string()),
+            ("SMTP_FROM_EMAIL", "noreply@example.com".to_string()),
+        ]
+    }
+
+    fn env_with(overrides: &[(&'static str, &str)], removed: &[&str]) -> ScopedEnv {
+        let mut vars = complete_env();
+        vars.retain(|(key, _)| !removed.contains(key) && !overrides.iter().any(|(o, _)| o == key));
+        vars.extend(overrides.iter().map(|(key, value)| (*key, value.to_string())));
+        let vars: Vec<(&str, &str)> = vars.iter().map(|(key, value)| (*key, value.as_str())).collect();
+        ScopedEnv::new(&vars)
+    }
+
+    #[test]
+    fn loads_complete_environment() {
+        let _env = env_with(&[], &[]);
+
+        let config = Config::from_env().unwrap();
+
+        assert_eq!(config.server_port, 8000);
+        assert_eq!(config.backend_base_url, "http://localhost:8000");
+        assert_eq!(config.soft_delete_grace_days, 30);
+        assert_eq!(config.appointment_slot_minutes, 30);
+        assert_eq!((config.break_glass_access_hours, config.break_glass_review_hours), (4, 24));
+        assert_eq!(config.referral_access_days, 30);
+        assert_eq!((config.credential_presentation_secret.as_ref().map(SecretString::expose_secret), config.credential_presentation_minutes), (None, 5));
+        assert_eq!(config.credential_batch_max_size, 500);
+        assert_eq!(config.step_up_minutes, 15);
+        assert!(config.geoip_url.is_none());
+        assert!(config.credential_signing.is_none());
+        assert_eq!(config.vitals.heart_rate, ReferenceRange::new(60.0, 100.0));
+        assert_eq!((config.terminology.cache_size, config.terminology.reject_unknown), (1000, false));
+        assert!(config.terminology.server_url.is_none());
+        assert_eq!(config.ipfs_encryption_key_version, 1);
+        assert!(config.ipfs_retired_encryption_keys.is_empty());
+        assert_eq!((config.reencryption.concurrency, config.reencryption.batch_size), (2, 50));
+        assert_eq!((config.patient_exports.retention_hours, config.patient_exports.download_url_minutes), (72, 15));
+        assert_eq!((config.login_protection.max_failures, config.login_protection.max_accounts), (20, 5));
+        assert!(config.login_protection.allowlist.is_empty());
+        assert!(config.hedera_costs.monthly_budget_hbar.is_none() && config.hedera_costs.usd_per_hbar.is_none());
+        assert_eq!(config.hedera_costs.budget_alert_percent, 80);
+        assert_eq!((config.hedera_costs.balance_alert_hbar, config.hedera_costs.balance_check_minutes), (100.0, 15));
+        assert_eq!((config.audit_anchoring.alert_unanchored_logs, config.audit_anchoring.alert_after_hours), (5000, 6));
+        assert_eq!((config.sms_throttle.per_number_per_15_minutes, config.sms_throttle.per_number_per_day), (3, 10));
+        assert!(config.sms_throttle.daily_budget.is_none() && config.sms_throttle.usd_per_message.is_none());
+        assert_eq!((config.jobs.concurrency, config.jobs.max_attempts), (4, 8));
+        assert_eq!(config.idempotency.ttl_hours, 24);
+        assert_eq!((config.patient_search.key.as_ref().map(SecretString::expose_secret), config.patient_search.max_results), (None, 20));
+        assert_eq!((config.webhooks.allow_http, config.webhooks.timeout_seconds), (false, 10));
+        assert_eq!(config.events.capacity, 1024);
+        assert_eq!(config.notification_stream.heartbeat_seconds, 15);
+        assert_eq!((config.notification_stream.retention_hours, config.notification_stream.max_streams_per_user), (24, 5));
+        assert!(!config.run_migrations);
+        assert!(!config.skip_index_creation);
+        assert_eq!(config.environment, "production");
+        assert_eq!((config.uploads.max_image_bytes, config.uploads.clamd_address.as_deref(), config.uploads.scan_fail_open), (10 * 1024 * 1024, None, false));
+        assert_eq!((config.uploads.max_photo_bytes, config.uploads.photo_min_dimension, config.uploads.photo_thumbnail_size), (5 * 1024 * 1024, 200, 160));
+        assert_eq!(config.logging.format, LogFormat::Pretty);
+        assert_eq!(*config.dynamic.load(), DynamicConfig::default());
+        assert_eq!((config.telemetry.otlp_endpoint.as_deref(), config.telemetry.sample_ratio), (None, 1.0));
+        assert_eq!((config.error_reporting.dsn.as_deref(), config.error_reporting.environment.as_str()), (None, "production"));
+        assert_eq!(config.validate_features(), Features { sms: false, email: true, chat: true, push: false, malware_scan: false });
+    }
+
+    #[test]
+    fn optional_integrations_can_be_left_out() {
+        let _env = env_with(
+            &[],
+            &["GEMINI_API_KEY", "SMTP_SERVER", "SMTP_PORT", "SMTP_USERNAME", "SMTP_PASSWORD", "SMTP_FROM_EMAIL"],
+        );
+
+        let config = Config::from_env().unwrap();
+
+        assert!(config.twilio.is_none() && config.smtp.is_none() && config.gemini.is_none());
+        assert!(config.interaction_api.is_none() && config.captcha.is_none());
+        assert_eq!(
+            config.validate_features().to_string(),
+            "sms (Twilio): disabled, email (SMTP): disabled, chat (Gemini): disabled, push (FCM): disabled, malware scanning (ClamAV): disabled"
+        );
+    }
+
+    #[test]
+    fn partially_configured_integration_is_an_error() {
+        let _env = env_with(&[("TWILIO_ACCOUNT_SID", "AC123")], &[]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec!["TWILIO_AUTH_TOKEN must be set to enable SMS", "TWILIO_PHONE_NUMBER must be set to enable SMS"]
+        );
+    }
+
+    #[test]
+    fn interaction_api_needs_a_url() {
+        let _env = env_with(&[("INTERACTION_API_KEY", "key")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["INTERACTION_API_URL must be set to enable the interaction API"]);
+        drop(_env);
+
+        let _env = env_with(&[("INTERACTION_API_URL", "https://interactions.example.com/check")], &[]);
+        let api = Config::from_env().unwrap().interaction_api.unwrap();
+
+        assert_eq!(api.api_key, None);
+        assert_eq!(api.timeout_seconds, 5);
+    }
+
+    #[test]
+    fn captcha_needs_a_provider_and_a_secret() {
+        let _env = env_with(&[("CAPTCHA_SECRET_KEY", "secret")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["CAPTCHA_PROVIDER must be set to enable CAPTCHA checks"]);
+        drop(_env);
+
+        let _env = env_with(&[("CAPTCHA_PROVIDER", "hcaptcha"), ("CAPTCHA_SECRET_KEY", "secret")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["CAPTCHA_PROVIDER must be turnstile or recaptcha, got 'hcaptcha'"]);
+        drop(_env);
+
+        let _env = env_with(&[("CAPTCHA_PROVIDER", "reCAPTCHA"), ("CAPTCHA_SECRET_KEY", "secret")], &[]);
+        let captcha = Config::from_env().unwrap().captcha.unwrap();
+
+        assert_eq!(captcha.provider, CaptchaProvider::Recaptcha);
+        assert_eq!((captcha.timeout_seconds, captcha.fail_open), (3, false));
+    }
+
+    #[test]
+    fn verify_service_replaces_sender_number() {
+        let _env = env_with(
+            &[("TWILIO_ACCOUNT_SID", "AC123"), ("TWILIO_AUTH_TOKEN", "token"), ("TWILIO_VERIFY_SERVICE_SID", "VA123")],
+            &[],
+        );
+
+        let twilio = Config::from_env().unwrap().twilio.unwrap();
+
+        assert_eq!(twilio.verify_service_sid.as_deref(), Some("VA123"));
+        assert!(twilio.phone_number.is_empty());
+    }
+
+    #[test]
+    fn gemini_settings_default_and_override() {
+        let _env = env_with(&[], &[]);
+        let gemini = Config::from_env().unwrap().gemini.unwrap();
+        assert_eq!(gemini.model, GeminiConfig::default().model);
+        drop(_env);
+
+        let _env = env_with(
+            &[("GEMINI_MODEL", "gemini-pro-latest"), ("GEMINI_FALLBACK_MODELS", "a, b"), ("GEMINI_MAX_OUTPUT_TOKENS", "512")],
+            &[],
+        );
+        let gemini = Config::from_env().unwrap().gemini.unwrap();
+
+        assert_eq!(gemini.model, "gemini-pro-latest");
+        assert_eq!(gemini.fallback_models, vec!["a", "b"]);
+        assert_eq!(gemini.max_output_tokens, 512);
+    }
+
+    #[test]
+    fn rejects_unknown_gemini_safety_threshold() {
+        let _env = env_with(&[("GEMINI_SAFETY_THRESHOLD", "BLOCK_SOME"), ("GEMINI_TEMPERATURE", "3")], &[]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                "GEMINI_TEMPERATURE must be between 0 and 2, got '3'",
+                "GEMINI_SAFETY_THRESHOLD must be one of BLOCK_NONE, BLOCK_ONLY_HIGH, BLOCK_MEDIUM_AND_ABOVE, BLOCK_LOW_AND_ABOVE, got 'BLOCK_SOME'",
+            ]
+        );
+    }
+
+    #[test]
+    fn vital_ranges_are_low_dash_high() {
+        let _env = env_with(&[("VITALS_SPO2_RANGE", "92 - 100"), ("VITALS_HEART_RATE_RANGE", "100-60"), ("VITALS_SYSTOLIC_RANGE", "high")], &[]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                "VITALS_SYSTOLIC_RANGE must be a range like 60-100, got 'high'",
+                "VITALS_HEART_RATE_RANGE must have its low end below its high end, got '100-60'",
+            ]
+        );
+        drop(_env);
+
+        let _env = env_with(&[("VITALS_SPO2_RANGE", "92 - 100")], &[]);
+        assert_eq!(Config::from_env().unwrap().vitals.spo2, ReferenceRange::new(92.0, 100.0));
+    }
+
+    #[test]
+    fn retired_encryption_keys_are_read_by_version() {
+        let retired = format!("1:{}, 2:{}", "11".repeat(32), "22".repeat(32));
+        let _env = env_with(&[("IPFS_ENCRYPTION_KEY_VERSION", "3"), ("IPFS_RETIRED_ENCRYPTION_KEYS", retired.as_str())], &[]);
+        let config = Config::from_env().unwrap();
+
+        assert_eq!(config.encryption_key(3), Some(&EncryptionKey::from_hex(&"00".repeat(32))));
+        assert_eq!(config.encryption_key(1), Some(&EncryptionKey::from_hex(&"11".repeat(32))));
+        assert_eq!(config.encryption_key(4), None);
+        drop(_env);
+
+        let _env = env_with(&[("IPFS_RETIRED_ENCRYPTION_KEYS", "1:abcd,2")], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec!["IPFS_RETIRED_ENCRYPTION_KEYS must be a comma-separated list of version:key pairs"]
+        );
+        drop(_env);
+
+        let retired = format!("1:{},2:abcd", "11".repeat(32));
+        let _env = env_with(&[("IPFS_RETIRED_ENCRYPTION_KEYS", retired.as_str())], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "IPFS_RETIRED_ENCRYPTION_KEYS must not include the current version 1",
+                "IPFS_RETIRED_ENCRYPTION_KEYS key 2 must be 32 bytes encoded as 64 hex characters",
+            ]
+        );
+    }
+
+    #[test]
+    fn terminology_cache_cannot_be_empty() {
+        let _env = env_with(&[("TERMINOLOGY_CACHE_SIZE", "0")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["TERMINOLOGY_CACHE_SIZE must be at least 1"]);
+        drop(_env);
+
+        let _env = env_with(&[("TERMINOLOGY_SERVER_URL", "https://tx.example.com/r4"), ("TERMINOLOGY_REJECT_UNKNOWN", "true")], &[]);
+        let terminology = Config::from_env().unwrap().terminology;
+
+        assert_eq!(terminology.server_url.as_deref(), Some("https://tx.example.com/r4"));
+        assert!(terminology.reject_unknown);
+    }
+
+    #[test]
+    fn credential_signing_needs_an_issuer_and_a_key() {
+        let _env = env_with(&[("CREDENTIAL_ISSUER_DID", "did:hedera:testnet:0.0.5")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["CREDENTIAL_SIGNING_KEY must be set to enable credential signing"]);
+        drop(_env);
+
+        let key = "ab".repeat(32);
+        let _env = env_with(&[("CREDENTIAL_ISSUER_DID", "did:hedera:testnet:0.0.5"), ("CREDENTIAL_SIGNING_KEY", &key)], &[]);
+        let signing = Config::from_env().unwrap().credential_signing.unwrap();
+        assert_eq!((signing.issuer_did.as_str(), signing.key_id.as_str()), ("did:hedera:testnet:0.0.5", "key-1"));
+    }
+
+    #[test]
+    fn patient_search_needs_a_long_key_and_a_result_limit() {
+        let _env = env_with(&[("PATIENT_SEARCH_KEY", "short"), ("PATIENT_SEARCH_MAX_RESULTS", "0")], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec!["PATIENT_SEARCH_MAX_RESULTS must be at least 1", "PATIENT_SEARCH_KEY must be at least 32 characters"]
+        );
+        drop(_env);
+
+        let key = "k".repeat(32);
+        let _env = env_with(&[("PATIENT_SEARCH_KEY", &key)], &[]);
+        assert_eq!(Config::from_env().unwrap().patient_search.key, Some(key.clone().into()));
+    }
+
+    #[test]
+    fn credential_presentations_need_a_long_secret_and_a_short_lifetime() {
+        let _env = env_with(&[("CREDENTIAL_PRESENTATION_SECRET", "short"), ("CREDENTIAL_PRESENTATION_MINUTES", "60")], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "CREDENTIAL_PRESENTATION_SECRET must be at least 32 characters",
+                "CREDENTIAL_PRESENTATION_MINUTES must be between 1 and 15, got '60'",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_missing_variable_at_once() {
+        let _env = env_with(&[], &["DATABASE_URL", "JWT_SECRET", "SMTP_PORT"]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec!["DATABASE_URL must be set", "JWT_SECRET must be set", "SMTP_PORT must be set to enable email"]
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors_with_the_offending_value() {
+        let _env = env_with(&[("SERVER_PORT", "abc"), ("USE_TLS", "yes")], &[]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert!(err.problems.contains(&"SERVER_PORT must be a port number, got 'abc'".to_string()));
+        assert!(err.problems.contains(&"USE_TLS must be true or false, got 'yes'".to_string()));
+        assert!(err.to_string().starts_with("invalid configuration:\n  - "));
+    }
+
+    #[test]
+    fn debug_output_carries_no_secrets() {
+        let secrets = [
+            ("DATABASE_URL", "mongodb://app:db-password-0451@db:27017".to_string()),
+            ("HEDERA_PRIVATE_KEY", "302e0451-operator-key".to_string()),
+            ("JWT_SECRET", "jwt-secret-0451".to_string()),
+            ("IPFS_ENCRYPTION_KEY", "ab".repeat(32)),
+            ("IPFS_RETIRED_ENCRYPTION_KEYS", format!("1:{}", "cd".repeat(32))),
+            ("TWILIO_ACCOUNT_SID", "AC1234".to_string()),
+            ("TWILIO_AUTH_TOKEN", "twilio-token-0451".to_string()),
+            ("GEMINI_API_KEY", "gemini-key-0451".to_string()),
+            ("SMTP_PASSWORD", "smtp-password-0451".to_string()),
+            ("CREDENTIAL_ISSUER_DID", "did:hedera:testnet:0.0.1".to_string()),
+            ("CREDENTIAL_SIGNING_KEY", "ef".repeat(32)),
+            ("CREDENTIAL_PRESENTATION_SECRET", "presentation-secret-0451-0123456789".to_string()),
+            ("INTERACTION_API_URL", "https://interactions.example.com/check".to_string()),
+            ("INTERACTION_API_KEY", "interaction-key-0451".to_string()),
+            ("CAPTCHA_SECRET_KEY", "captcha-secret-0451".to_string()),
+            ("PATIENT_SEARCH_KEY", "patient-search-key-0451-0123456789".to_string()),
+        ];
+        let overrides: Vec<(&'static str, &str)> = secrets.iter().map(|(key, value)| (*key, value.as_str())).collect();
+        let _env = env_with(&overrides, &[]);
+
+        let config = Config::from_env().unwrap();
+        let printed = format!("{:?} {:#?}", config, config);
+
+        // Every secret above is marked with 0451; the hex keys are checked by their repeated bytes
+        for marker in ["0451", "abababab", "cdcdcdcd", "efefefef"] {
+            assert!(!printed.contains(marker), "{} was printed", marker);
+        }
+        assert!(printed.contains("[REDACTED]"));
+    }
+
+    #[test]
+    fn rejects_malformed_contract_ids_and_encryption_key() {
+        let _env = env_with(&[("AUDIT_TRAIL_CONTRACT_ID", "audit"), ("IPFS_ENCRYPTION_KEY", "abcd")], &[]);
+
+        let err = Config::from_env().unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                "AUDIT_TRAIL_CONTRACT_ID must be a contract id like 0.0.1234, got 'audit'",
+                "IPFS_ENCRYPTION_KEY must be 32 bytes encoded as 64 hex characters",
+            ]
+        );
+    }
+
+    fn write_config_file(contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("healthcare-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn environment_beats_file_beats_defaults() {
+        let _env = env_with(&[], &[]);
+        let path = write_config_file(
+            r#"
+            server_port = 9000
+            run_migrations = true
+            "#,
+        );
+
+        let config = Config::load_from(&path).unwrap();
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(config.server_port, 8000);
+        assert!(config.run_migrations);
+        assert_eq!(config.soft_delete_grace_days, 30);
+    }
+
+    #[test]
+    fn file_fills_in_nested_sections_and_lists() {
+        let _env = env_with(&[], &["DATABASE_URL", "SMTP_SERVER", "SMTP_PORT", "ADMIN_DIDS", "PLATFORM_ADMIN_DIDS"]);
+        let path = write_config_file(
+            r#"
+            database_url = "mongodb://db:27017"
+            admin_dids = ["did:hedera:testnet:0.0.1", "did:hedera:testnet:0.0.2"]
+            platform_admin_dids = ["did:hedera:testnet:0.0.3"]
+
+            [smtp]
+            server = "mail.internal"
+            port = 2525
+            "#,
+        );
+
+        let config = Config::load_from(&path).unwrap();
+        std::fs::remove_file(path).ok();
+
+        let smtp = config.smtp.unwrap();
+        assert_eq!(config.database_url.expose_secret(), "mongodb://db:27017");
+        assert_eq!(smtp.server, "mail.internal");
+        assert_eq!(smtp.port, 2525);
+        assert_eq!(config.admin_dids, vec!["did:hedera:testnet:0.0.1", "did:hedera:testnet:0.0.2"]);
+        assert_eq!(config.platform_admin_dids, vec!["did:hedera:testnet:0.0.3"]);
+    }
+
+    #[test]
+    fn logging_takes_a_format_filter_directives_and_route_templates() {
+        let _env = env_with(&[("LOG_FORMAT", "JSON"), ("LOG_LEVEL", "warn,healthcare_backend=debug"), ("LOG_REDACTED_ROUTES", "/api/patients/:did, /api/files/:cid")], &[]);
+        let config = Config::from_env().unwrap();
+        assert_eq!((config.logging.format, config.dynamic.load().log_level.as_str()), (LogFormat::Json, "warn,healthcare_backend=debug"));
+        assert_eq!(config.logging.redacted_routes, vec!["/api/patients/:did", "/api/files/:cid"]);
+        drop(_env);
+
+        let _env = env_with(&[("LOG_FORMAT", "xml"), ("LOG_LEVEL", "healthcare_backend=loud"), ("LOG_REDACTED_ROUTES", "api/patients")], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "LOG_FORMAT must be pretty or json, got 'xml'",
+                "LOG_LEVEL must be tracing filter directives such as 'healthcare_backend=info', got 'healthcare_backend=loud'",
+                "LOG_REDACTED_ROUTES entries must be route templates such as '/api/patients/:did', got 'api/patients'",
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_settings_are_read_with_the_rest_and_validated() {
+        let _env = env_with(
+            &[("CORS_ALLOWED_ORIGINS", "https://app.example.com/, https://admin.example.com"), ("REQUIRE_CONSENT", "true"), ("CHAT_DAILY_REQUEST_LIMIT", "5")],
+            &[],
+        );
+        let dynamic = Config::from_env().unwrap().dynamic.load();
+        assert_eq!(dynamic.cors_allowed_origins, vec!["https://app.example.com", "https://admin.example.com"]);
+        assert_eq!((dynamic.require_consent, dynamic.chat_daily_request_limit), (true, 5));
+        drop(_env);
+
+        let _env = env_with(
+            &[("CORS_ALLOWED_ORIGINS", "app.example.com"), ("PRESCRIPTION_VERIFY_PER_MINUTE", "0"), ("DATA_ACCESS_PATIENTS_ALERT_PER_HOUR", "60")],
+            &[],
+        );
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "PRESCRIPTION_VERIFY_PER_MINUTE must be at least 1",
+                "DATA_ACCESS_PATIENTS_ALERT_PER_HOUR must be at least 1 and below DATA_ACCESS_PATIENTS_PER_HOUR (60), got '60'",
+                "CORS_ALLOWED_ORIGINS entries must be origins such as 'https://app.example.com', got 'app.example.com'",
+            ]
+        );
+    }
+
+    #[test]
+    fn reloads_name_the_boot_settings_they_cannot_change() {
+        let running = Config { jwt_secret: "first".into(), server_port: 8000, ..Default::default() };
+        let reloaded = Config {
+            jwt_secret: "second".into(),
+            server_port: 9000,
+            dynamic: DynamicConfig { require_consent: true, ..Default::default() }.into(),
+            ..Default::default()
+        };
+
+        assert_eq!(running.boot_changes(&reloaded), vec!["jwt_secret", "server_port"]);
+        let changes = running.dynamic.load().changes(&reloaded.dynamic.load());
+        assert_eq!(serde_json::Value::Object(changes), serde_json::json!({ "require_consent": { "from": false, "to": true } }));
+    }
+
+    #[test]
+    fn trace_export_reads_the_standard_opentelemetry_variables() {
+        let _env = env_with(&[("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4317"), ("OTEL_SERVICE_NAME", "wecare-api"), ("OTEL_TRACES_SAMPLER_ARG", "0.25")], &[]);
+        let telemetry = Config::from_env().unwrap().telemetry;
+        assert_eq!(telemetry.otlp_endpoint.as_deref(), Some("http://collector:4317"));
+        assert_eq!((telemetry.service_name.as_str(), telemetry.sample_ratio), ("wecare-api", 0.25));
+        drop(_env);
+
+        let _env = env_with(&[("OTEL_TRACES_SAMPLER_ARG", "1.5")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["OTEL_TRACES_SAMPLER_ARG must be a ratio between 0 and 1, got '1.5'"]);
+    }
+
+    #[test]
+    fn upstream_routes_get_a_longer_timeout_than_the_rest() {
+        let _env = env_with(&[], &[]);
+        let http = Config::from_env().unwrap().http;
+        assert_eq!((http.request_timeout_seconds, http.upstream_timeout_seconds), (10, 45));
+        drop(_env);
+
+        let _env = env_with(&[("HTTP_REQUEST_TIMEOUT_SECONDS", "5"), ("HTTP_UPSTREAM_TIMEOUT_SECONDS", "20")], &[]);
+        let http = Config::from_env().unwrap().http;
+        assert_eq!((http.request_timeout_seconds, http.upstream_timeout_seconds), (5, 20));
+        drop(_env);
+
+        let _env = env_with(&[("HTTP_REQUEST_TIMEOUT_SECONDS", "30"), ("HTTP_UPSTREAM_TIMEOUT_SECONDS", "20")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["HTTP_UPSTREAM_TIMEOUT_SECONDS must not be smaller than HTTP_REQUEST_TIMEOUT_SECONDS"]);
+    }
+
+    #[test]
+    fn error_reporting_reads_the_standard_sentry_variables() {
+        let _env = env_with(&[("SENTRY_DSN", "https://public@errors.example.com/42"), ("SENTRY_ENVIRONMENT", "staging"), ("SENTRY_RELEASE", "backend@2026.10.1")], &[]);
+        let reporting = Config::from_env().unwrap().error_reporting;
+        assert_eq!(reporting.dsn.as_deref(), Some("https://public@errors.example.com/42"));
+        assert_eq!((reporting.environment.as_str(), reporting.release.as_deref()), ("staging", Some("backend@2026.10.1")));
+        drop(_env);
+
+        // Without SENTRY_ENVIRONMENT reports carry the deployment's
+        let _env = env_with(&[("APP_ENV", "development")], &[]);
+        let config = Config::from_env().unwrap();
+        assert_eq!((config.environment.as_str(), config.error_reporting.environment.as_str()), ("development", "development"));
+        drop(_env);
+
+        // A DSN names the project key and id as well as the host
+        let _env = env_with(&[("SENTRY_DSN", "errors.example.com")], &[]);
+        assert_eq!(Config::from_env().unwrap_err().problems, vec!["SENTRY_DSN must be a DSN such as 'https://<key>@sentry.example.com/<project>'"]);
+    }
+
+    #[test]
+    fn login_block_allowlist_takes_addresses_and_ranges() {
+        let _env = env_with(&[("LOGIN_BLOCK_ALLOWLIST", "203.0.113.7, 198.51.100.0/24")], &[]);
+        assert_eq!(Config::from_env().unwrap().login_protection.allowlist, vec!["203.0.113.7", "198.51.100.0/24"]);
+        drop(_env);
+
+        let _env = env_with(&[("LOGIN_BLOCK_ALLOWLIST", "clinic-nat,10.0.0.0/33")], &[]);
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "LOGIN_BLOCK_ALLOWLIST entries must be addresses or CIDR ranges, got 'clinic-nat'",
+                "LOGIN_BLOCK_ALLOWLIST entries must be addresses or CIDR ranges, got '10.0.0.0/33'",
+            ]
+        );
+    }
+
+    #[test]
+    fn hedera_budget_needs_positive_numbers_and_a_percentage() {
+        let _env = env_with(&[("HEDERA_MONTHLY_BUDGET_HBAR", "500"), ("HEDERA_USD_PER_HBAR", "0.07"), ("HEDERA_BUDGET_ALERT_PERCENT", "90")], &[]);
+        let costs = Config::from_env().unwrap().hedera_costs;
+        assert_eq!((costs.monthly_budget_hbar, costs.usd_per_hbar, costs.budget_alert_percent), (Some(500.0), Some(0.07), 90));
+        drop(_env);
+
+        let _env = env_with(
+            &[
+                ("HEDERA_MONTHLY_BUDGET_HBAR", "0"),
+                ("HEDERA_USD_PER_HBAR", "cheap"),
+                ("HEDERA_BUDGET_ALERT_PERCENT", "120"),
+                ("HEDERA_BALANCE_ALERT_HBAR", "-5"),
+                ("HEDERA_BALANCE_CHECK_MINUTES", "0"),
+            ],
+            &[],
+        );
+        assert_eq!(
+            Config::from_env().unwrap_err().problems,
+            vec![
+                "HEDERA_USD_PER_HBAR must be a number, got 'cheap'",
+                "HEDERA_MONTHLY_BUDGET_HBAR must be greater than 0",
+                "HEDERA_BALANCE_ALERT_HBAR must be greater than 0",
+                "HEDERA_BUDGET_ALERT_PERCENT must be between 1 and 100, got '120'",
+                "HEDERA_BALANCE_CHECK_MINUTES must be at least 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_file_reports_only_what_is_still_missing() {
+        let _env = env_with(&[], &["DATABASE_URL", "JWT_SECRET"]);
+        let path = write_config_file(r#"database_url = "mongodb://db:27017""#);
+
+        let err = Config::load_from(&path).unwrap_err();
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(err.problems, vec!["JWT_SECRET must be set"]);
+    }
+
+    #[test]
+    fn unreadable_file_is_reported() {
+        let _env = env_with(&[], &[]);
+
+        let err = Config::load_from("/nonexistent/healthcare.toml").unwrap_err();
+
+        assert!(err.problems[0].starts_with("could not load config file /nonexistent/healthcare.toml"));
     }
 }
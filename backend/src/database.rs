@@ -1,241 +1,598 @@
 use anyhow::Result;
-use mongodb::{Client, Database as MongoDatabase, Collection};
+use mongodb::{Client, ClientSession, Database as MongoDatabase, Collection, IndexModel};
+use mongodb::error::{ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
+use mongodb::event::command::CommandEventHandler;
+use mongodb::options::{AggregateOptions, ClientOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, Hint, IndexOptions, ReplaceOptions, ReturnDocument, UpdateOptions};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use futures_util::stream::TryStreamExt;
-use bson::{oid::ObjectId, doc, DateTime};
-use sha2::{Digest, Sha256};
+use bson::{oid::ObjectId, doc, Bson, DateTime, Document};
+use chrono::{Duration, Utc};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
 
 use crate::models::*;
-use crate::utils::{encrypt, decrypt};
+use crate::telemetry::MongoCommandSpans;
+use crate::tenancy::{tenant_filter, ScopedCollection, TENANT_SCOPED_COLLECTIONS};
+use crate::utils::{encrypt, decrypt, hash_email, hash_phone, name_search_key_id, name_search_tokens, EncryptionKey, SecretString};
+
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(String),
+}
+
+/// Whether a driver error is a unique-index violation (E11000)
+pub(crate) fn is_duplicate_key_error(error: &mongodb::error::Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => write_error.code == DUPLICATE_KEY_CODE,
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
+
+const DUPLICATE_KEY_CODE: i32 = 11000;
+/// `IndexOptionsConflict` and `IndexKeySpecsConflict`: an index on the same keys, or with the
+/// same name, already exists with different options
+const INDEX_CONFLICT_CODES: [i32; 2] = [85, 86];
+/// Failures a re-encryption job keeps for review
+const MAX_JOB_FAILURES: i32 = 100;
+/// Tries at a transaction, or at committing it, that the server reports as worth retrying
+const MAX_TRANSACTION_ATTEMPTS: u32 = 3;
+/// The `_id` of the one `anchoring_health` document
+const ANCHORING_HEALTH_ID: &str = "audit_anchoring";
+
+/// What the observation summary pipeline returns in its one `$facet` document
+#[derive(Deserialize, Default)]
+struct ObservationFacets {
+    buckets: Vec<ObservationBucketRow>,
+    latest: Vec<LatestObservationRow>,
+    skipped: Vec<CountRow>,
+}
+
+#[derive(Deserialize)]
+struct ObservationBucketRow {
+    #[serde(rename = "_id", with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    start: chrono::DateTime<Utc>,
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+}
+
+#[derive(Deserialize)]
+struct LatestObservationRow {
+    value_quantity: Option<FhirQuantity>,
+    effective_date_time: FhirDateTime,
+    #[serde(default)]
+    interpretation: Vec<FhirCodeableConcept>,
+}
+
+#[derive(Deserialize)]
+struct CountRow {
+    count: u64,
+}
 
 pub struct Database {
     pub client: Client,
     pub db: MongoDatabase,
+    /// HMAC key for patient name search tokens; no tokens are written without one
+    patient_search_key: Option<SecretString>,
+    index_status: Arc<Mutex<IndexBuildStatus>>,
+}
+
+/// Tallies one run of [`Database::create_indexes`]
+#[derive(Default)]
+struct IndexBuild {
+    ensured: u32,
+    failed: Vec<IndexFailure>,
+}
+
+impl IndexBuild {
+    /// Create a single index, recording instead of failing if it cannot be built.
+    ///
+    /// `create_index` is a no-op when an identical index already exists, so this is
+    /// safe to run on every startup. An index that exists with different options
+    /// (e.g. a changed TTL) is reported and left alone.
+    async fn ensure<T: Send + Sync>(&mut self, collection: &Collection<T>, keys: Document, options: Option<IndexOptions>) {
+        let index = IndexModel::builder()
+            .keys(keys.clone())
+            .options(options)
+            .build();
+
+        match collection.create_index(index, None).await {
+            Ok(_) => self.ensured += 1,
+            Err(e) => {
+                let conflict = matches!(e.kind.as_ref(), ErrorKind::Command(command_error) if INDEX_CONFLICT_CODES.contains(&command_error.code));
+                if conflict {
+                    tracing::error!(
+                        "Index {} on '{}' already exists with different options, so the one this version expects was not built: {}. \
+                         Drop the existing index (db.{}.dropIndex(...)) and run `migrate --indexes-only` to build it.",
+                        keys,
+                        collection.name(),
+                        e,
+                        collection.name()
+                    );
+                } else {
+                    tracing::warn!(
+                        "Failed to create index {} on collection '{}': {}. Continuing without it.",
+                        keys,
+                        collection.name(),
+                        e
+                    );
+                }
+                self.failed.push(IndexFailure {
+                    collection: collection.name().to_string(),
+                    keys: keys.to_string(),
+                    conflict,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
 }
 
 impl Database {
     pub async fn new(uri: &str) -> Result<Self> {
-        let client = Client::with_uri_str(uri).await?;
-        let db = client.database("healthcare");
-        
-        Self::create_indexes(&db).await?;
+        Self::new_with_name(uri, "healthcare").await
+    }
+
+    /// Connect to a specific database name (used by tests to isolate their data)
+    pub async fn new_with_name(uri: &str, db_name: &str) -> Result<Self> {
+        Self::new_observed(uri, db_name, Arc::new(MongoCommandSpans::default())).await
+    }
+
+    /// Like [`new_with_name`](Self::new_with_name), with every command the driver sends reported
+    /// to `commands` rather than only traced; the test harness counts queries this way
+    pub async fn new_observed(uri: &str, db_name: &str, commands: Arc<dyn CommandEventHandler>) -> Result<Self> {
+        let mut options = ClientOptions::parse(uri).await?;
+        options.command_event_handler = Some(commands);
+        let client = Client::with_options(options)?;
+        let db = client.database(db_name);
         
-        Ok(Database { client, db })
+        Ok(Database { client, db, patient_search_key: None, index_status: Arc::default() })
+    }
+
+    /// Index patient names for search with `key` as records are written
+    pub fn with_patient_search_key(mut self, key: Option<SecretString>) -> Self {
+        self.patient_search_key = key;
+        self
+    }
+
+    /// Where the last index build got to
+    pub fn index_build_status(&self) -> IndexBuildStatus {
+        self.index_status.lock().unwrap().clone()
+    }
+
+    /// Record that indexes are left to `migrate --indexes-only`
+    pub fn skip_index_creation(&self) {
+        self.index_status.lock().unwrap().state = IndexBuildState::Skipped;
+    }
+
+    /// Build every index the queries rely on. Connecting does not, as a build on a large
+    /// collection can take minutes: the server runs this in the background once it is listening,
+    /// and `migrate --indexes-only` out of band. An index that cannot be built is logged and
+    /// listed in the returned status rather than failing the rest.
+    pub async fn create_indexes(&self) -> IndexBuildStatus {
+        {
+            let mut status = self.index_status.lock().unwrap();
+            *status = IndexBuildStatus { state: IndexBuildState::Building, started_at: Some(Utc::now()), ..Default::default() };
+        }
+        let build = Self::build_indexes(&self.db).await;
+        let mut status = self.index_status.lock().unwrap();
+        status.state = if build.failed.is_empty() { IndexBuildState::Completed } else { IndexBuildState::CompletedWithErrors };
+        status.finished_at = Some(Utc::now());
+        status.ensured = build.ensured;
+        status.failed = build.failed;
+        status.clone()
     }
 
-    async fn create_indexes(db: &MongoDatabase) -> Result<()> {
+    async fn build_indexes(db: &MongoDatabase) -> IndexBuild {
+        let mut build = IndexBuild::default();
         // Patient indexes
         let patients: Collection<EncryptedPatient> = db.collection("patients");
-        patients.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "did": 1 })
-                .options(mongodb::options::IndexOptions::builder().unique(true).build())
-                .build(),
-            None,
-        ).await?;
-        patients.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "email_hash": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&patients, doc! { "did": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        Self::drop_mismatched_index(&patients, doc! { "email_hash": 1 }, true).await;
+        // Sparse so phone-only patients (no email, no hash) don't collide with each other
+        build.ensure(&patients, doc! { "email_hash": 1 }, Some(IndexOptions::builder().unique(true).sparse(true).build())).await;
+        build.ensure(&patients, doc! { "phone_hash": 1 }, Some(IndexOptions::builder().sparse(true).build())).await;
+        build.ensure(&patients, doc! { "created_at": -1 }, None).await;
+        // Merged-away DIDs still resolve to the patient they were folded into
+        build.ensure(&patients, doc! { "merged_dids": 1 }, None).await;
+        // Multikey, for name search
+        build.ensure(&patients, doc! { "search_tokens": 1 }, None).await;
+
+        // Duplicate patient indexes: candidates by pair and for review, merges by either DID
+        let merge_candidates: Collection<MergeCandidate> = db.collection("merge_candidates");
+        build.ensure(&merge_candidates, doc! { "patient_dids": 1 }, None).await;
+        build.ensure(&merge_candidates, doc! { "status": 1, "score": -1 }, None).await;
+        let patient_merges: Collection<PatientMerge> = db.collection("patient_merges");
+        build.ensure(&patient_merges, doc! { "survivor_did": 1 }, None).await;
+        build.ensure(&patient_merges, doc! { "duplicate_did": 1 }, None).await;
 
         // Practitioner indexes
         let practitioners: Collection<Practitioner> = db.collection("practitioners");
-        practitioners.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "did": 1 })
-                .options(mongodb::options::IndexOptions::builder().unique(true).build())
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&practitioners, doc! { "did": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        build.ensure(&practitioners, doc! { "tenant_id": 1, "created_at": 1 }, None).await;
+
+        // Organization indexes: an identifier value names at most one organization
+        let organizations: Collection<Organization> = db.collection("organizations");
+        let unique_identifier = IndexOptions::builder()
+            .unique(true)
+            .partial_filter_expression(doc! { "fhir_organization.identifier.value": { "$exists": true } })
+            .build();
+        build.ensure(&organizations, doc! { "fhir_organization.identifier.value": 1 }, Some(unique_identifier)).await;
+        build.ensure(&organizations, doc! { "tenant_id": 1 }, None).await;
+
+        // Affiliation indexes
+        let affiliations: Collection<Affiliation> = db.collection("affiliations");
+        build.ensure(&affiliations, doc! { "organization_id": 1, "created_at": -1 }, None).await;
+        build.ensure(&affiliations, doc! { "practitioner_did": 1, "role": 1, "status": 1 }, None).await;
 
         // Encounter indexes
         let encounters: Collection<Encounter> = db.collection("encounters");
-        encounters.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "patient_did": 1, "status": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&encounters, doc! { "patient_did": 1, "status": 1 }, None).await;
+        build.ensure(&encounters, doc! { "practitioner_did": 1, "status": 1 }, None).await;
+        build.ensure(&encounters, doc! { "final_bundle_ipfs_hash": 1 }, Some(IndexOptions::builder().sparse(true).build())).await;
+        let attachments: Collection<Attachment> = db.collection("attachments");
+        build.ensure(&attachments, doc! { "ipfs_hash": 1 }, None).await;
+        build.ensure(&attachments, doc! { "encounter_id": 1 }, None).await;
+
+        // Observation indexes
+        let observations: Collection<FhirObservation> = db.collection("observations");
+        build.ensure(&observations, Self::observation_search_index(), None).await;
+
+        // Condition indexes: the problem list gathers a patient's conditions across encounters
+        let conditions: Collection<FhirCondition> = db.collection("conditions");
+        build.ensure(&conditions, doc! { "subject.reference": 1 }, None).await;
 
         // Prescription indexes
         let prescriptions: Collection<Prescription> = db.collection("prescriptions");
-        prescriptions.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "patient_did": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&prescriptions, doc! { "patient_did": 1, "status": 1, "created_at": -1 }, None).await;
+        build.ensure(&prescriptions, doc! { "credential.hash": 1 }, Some(IndexOptions::builder().unique(true).sparse(true).build())).await;
 
         // Access control indexes
         let access_controls: Collection<AccessControl> = db.collection("access_controls");
-        access_controls.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "patient_did": 1, "grantee_did": 1 })
-                .options(mongodb::options::IndexOptions::builder().unique(true).build())
-                .build(),
-            None,
-        ).await?;
+        // Not unique: a grantee can hold a patient grant, referral grants and a break-glass grant at once
+        Self::drop_mismatched_index(&access_controls, doc! { "patient_did": 1, "grantee_did": 1 }, false).await;
+        build.ensure(&access_controls, doc! { "patient_did": 1, "grantee_did": 1 }, None).await;
+
+        // FHIR Bundle indexes
+        let bundles: Collection<FhirBundle> = db.collection("fhir_bundles");
+        build.ensure(&bundles, doc! { "patient_did": 1, "version": -1 }, Some(IndexOptions::builder().unique(true).build())).await;
 
         // Verifiable Credential indexes
         let credentials: Collection<VerifiableCredential> = db.collection("verifiable_credentials");
-        credentials.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "subject_did": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&credentials, doc! { "subject_did": 1 }, None).await;
+        build.ensure(&credentials, doc! { "subject_did": 1, "credential_type": 1 }, None).await;
+        build.ensure(&credentials, doc! { "ipfs_hash": 1 }, None).await;
+        build.ensure(&credentials, doc! { "status_list.list_id": 1 }, Some(IndexOptions::builder().sparse(true).build())).await;
+        build.ensure(&credentials, doc! { "batch.batch_id": 1 }, Some(IndexOptions::builder().sparse(true).build())).await;
+
+        // Trusted issuer indexes: a DID is registered once
+        let issuers: Collection<TrustedIssuer> = db.collection("issuers");
+        build.ensure(&issuers, doc! { "did": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+
+        // Credential status list indexes
+        let status_lists: Collection<CredentialStatusList> = db.collection("credential_status_lists");
+        build.ensure(&status_lists, doc! { "list_id": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
 
         // Audit Log indexes
         let audit_logs: Collection<AuditLog> = db.collection("audit_logs");
-        audit_logs.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "is_anchored": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&audit_logs, doc! { "is_anchored": 1 }, None).await;
+        build.ensure(&audit_logs, doc! { "did": 1, "timestamp": -1 }, None).await;
+        build.ensure(&audit_logs, doc! { "action": 1, "timestamp": -1 }, None).await;
+        build.ensure(&audit_logs, doc! { "timestamp": -1 }, None).await;
+        // Tenant admins' audit views
+        build.ensure(&audit_logs, doc! { "tenant_id": 1, "timestamp": -1 }, None).await;
+
+        // Anchor batch indexes: a Merkle root is anchored once; unfinished batches are looked up on every run
+        let anchor_batches: Collection<AnchorBatch> = db.collection("anchor_batches");
+        build.ensure(&anchor_batches, doc! { "merkle_root": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        build.ensure(&anchor_batches, doc! { "status": 1, "created_at": 1 }, None).await;
+
+        // Hedera cost indexes: reports read fees by time; one budget alert per month
+        let hedera_transactions: Collection<HederaTransaction> = db.collection("hedera_transactions");
+        build.ensure(&hedera_transactions, doc! { "recorded_at": -1 }, None).await;
+        let hedera_budget_alerts: Collection<HederaBudgetAlert> = db.collection("hedera_budget_alerts");
+        build.ensure(&hedera_budget_alerts, doc! { "month": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+
+        // Session indexes: known devices are read newest first, revoke links are looked up by token
+        let sessions: Collection<Session> = db.collection("sessions");
+        build.ensure(&sessions, doc! { "did": 1, "created_at": -1 }, None).await;
+        build.ensure(&sessions, doc! { "revoke_token_hash": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+
+        // Notification indexes
+        let notifications: Collection<Notification> = db.collection("notifications");
+        build.ensure(&notifications, doc! { "patient_did": 1, "created_at": -1 }, None).await;
+        let devices: Collection<Device> = db.collection("devices");
+        build.ensure(&devices, doc! { "fcm_token": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        build.ensure(&devices, doc! { "did": 1 }, None).await;
+
+        // Re-encryption job indexes
+        let reencryption_jobs: Collection<ReencryptionJob> = db.collection("reencryption_jobs");
+        // One job runs at a time; a second start collides here
+        let one_running = IndexOptions::builder().unique(true).partial_filter_expression(doc! { "status": "running" }).build();
+        build.ensure(&reencryption_jobs, doc! { "status": 1 }, Some(one_running)).await;
+        build.ensure(&reencryption_jobs, doc! { "created_at": -1 }, None).await;
+
+        // Patient export indexes: one pending export per patient; the sweep looks for exports past their retention
+        let patient_exports: Collection<PatientExport> = db.collection("patient_exports");
+        let one_pending = IndexOptions::builder().unique(true).partial_filter_expression(doc! { "status": "pending" }).build();
+        build.ensure(&patient_exports, doc! { "patient_did": 1 }, Some(one_pending)).await;
+        build.ensure(&patient_exports, doc! { "status": 1, "expires_at": 1 }, None).await;
+
+        // Compliance report indexes: newest first
+        let compliance_reports: Collection<ComplianceReport> = db.collection("compliance_reports");
+        build.ensure(&compliance_reports, doc! { "created_at": -1 }, None).await;
+
+        // Background job indexes: workers look for due pending jobs and lapsed locks; recurring jobs exist once
+        let jobs: Collection<Job> = db.collection("jobs");
+        build.ensure(&jobs, doc! { "status": 1, "run_at": 1 }, None).await;
+        build.ensure(&jobs, doc! { "status": 1, "locked_until": 1 }, None).await;
+        build.ensure(&jobs, doc! { "job_type": 1, "status": 1 }, None).await;
+        build.ensure(&jobs, doc! { "unique_key": 1 }, Some(IndexOptions::builder().unique(true).sparse(true).build())).await;
+
+        // Webhook indexes: subscribers are looked up per tenant and event; deliveries are read per webhook, newest first
+        let webhooks: Collection<Webhook> = db.collection("webhooks");
+        build.ensure(&webhooks, doc! { "tenant_id": 1, "events": 1 }, None).await;
+        let webhook_deliveries: Collection<WebhookDelivery> = db.collection("webhook_deliveries");
+        build.ensure(&webhook_deliveries, doc! { "webhook_id": 1, "attempted_at": -1 }, None).await;
+
+        // Notification feed indexes: replays read one user's events in order, which expire after a short while
+        let notification_events: Collection<FeedEvent> = db.collection("notification_events");
+        build.ensure(&notification_events, doc! { "recipient_did": 1, "_id": 1 }, None).await;
+        build.ensure(&notification_events, doc! { "expires_at": 1 }, Some(IndexOptions::builder().expire_after(StdDuration::from_secs(0)).build())).await;
+
+        // Idempotency key indexes: one claim per caller and key, dropped once it expires
+        let idempotency_keys: Collection<IdempotencyRecord> = db.collection("idempotency_keys");
+        build.ensure(&idempotency_keys, doc! { "user_did": 1, "key": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        build.ensure(&idempotency_keys, doc! { "expires_at": 1 }, Some(IndexOptions::builder().expire_after(StdDuration::from_secs(0)).build())).await;
+
+        // Appointment indexes
+        let appointments: Collection<Appointment> = db.collection("appointments");
+        build.ensure(&appointments, doc! { "patient_did": 1, "start": 1 }, None).await;
+        build.ensure(&appointments, doc! { "practitioner_did": 1, "start": 1 }, None).await;
+        build.ensure(&appointments, doc! { "status": 1, "start": 1 }, None).await;
+        // A practitioner can only have one confirmed appointment starting at a given time
+        let one_confirmed_per_slot = IndexOptions::builder().unique(true).partial_filter_expression(doc! { "status": "confirmed" }).build();
+        build.ensure(&appointments, doc! { "practitioner_did": 1, "start": 1, "status": 1 }, Some(one_confirmed_per_slot)).await;
+        // Overlapping confirmed appointments would share a slot; the multikey index rejects the second
+        let one_confirmed_per_availability_slot = IndexOptions::builder()
+            .unique(true)
+            .partial_filter_expression(doc! { "status": "confirmed", "slots": { "$exists": true } })
+            .build();
+        build.ensure(&appointments, doc! { "practitioner_did": 1, "slots": 1 }, Some(one_confirmed_per_availability_slot)).await;
+
+        // Consent indexes
+        let consents: Collection<Consent> = db.collection("consents");
+        build.ensure(&consents, doc! { "patient_did": 1, "grantee_did": 1, "status": 1 }, None).await;
+        build.ensure(&consents, doc! { "patient_did": 1, "created_at": -1 }, None).await;
+
+        // Relationship indexes: one active link per guardian and dependent
+        let relationships: Collection<Relationship> = db.collection("relationships");
+        let one_active_link = IndexOptions::builder().unique(true).partial_filter_expression(doc! { "active": true }).build();
+        build.ensure(&relationships, doc! { "guardian_did": 1, "dependent_did": 1 }, Some(one_active_link)).await;
+        build.ensure(&relationships, doc! { "dependent_did": 1 }, None).await;
+
+        // Break-glass indexes: the review queue and the alert sweep both look for unreviewed events by age
+        let break_glass_events: Collection<BreakGlassEvent> = db.collection("break_glass_events");
+        build.ensure(&break_glass_events, doc! { "reviewed_at": 1, "created_at": -1 }, None).await;
+
+        // Referral indexes: each practitioner's inbox and outbox, newest first
+        let referrals: Collection<Referral> = db.collection("referrals");
+        build.ensure(&referrals, doc! { "receiving_did": 1, "status": 1, "created_at": -1 }, None).await;
+        build.ensure(&referrals, doc! { "referring_did": 1, "status": 1, "created_at": -1 }, None).await;
+
+        // Availability indexes
+        let availability: Collection<Availability> = db.collection("availability");
+        build.ensure(&availability, doc! { "practitioner_did": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+
+        // Chat indexes
+        let chat_sessions: Collection<ChatSession> = db.collection("chat_sessions");
+        build.ensure(&chat_sessions, doc! { "owner_did": 1, "updated_at": -1 }, None).await;
+        let chat_messages: Collection<ChatMessage> = db.collection("chat_messages");
+        build.ensure(&chat_messages, doc! { "session_id": 1, "created_at": -1 }, None).await;
+        let chat_usage: Collection<ChatUsage> = db.collection("chat_usage");
+        build.ensure(&chat_usage, doc! { "user_did": 1, "day": 1 }, Some(IndexOptions::builder().unique(true).build())).await;
+        build.ensure(&chat_usage, doc! { "day": -1 }, None).await;
+
+        // SMS send counters: the stats read the daily totals; every counter expires after its window
+        let sms_sends: Collection<SmsSendCounter> = db.collection("sms_sends");
+        build.ensure(&sms_sends, doc! { "day": -1 }, Some(IndexOptions::builder().sparse(true).build())).await;
+        build.ensure(&sms_sends, doc! { "expires_at": 1 }, Some(IndexOptions::builder().expire_after(StdDuration::from_secs(0)).build())).await;
 
         // OTP indexes
         let otps: Collection<Otp> = db.collection("otps");
-        otps.create_index(
-            mongodb::IndexModel::builder()
-                .keys(doc! { "phone_number": 1, "otp": 1 })
-                .build(),
-            None,
-        ).await?;
+        build.ensure(&otps, doc! { "phone_number": 1, "otp": 1 }, None).await;
+        // TTL index: MongoDB removes each OTP once its `expires_at` has passed
+        build.ensure(&otps, doc! { "expires_at": 1 }, Some(IndexOptions::builder().expire_after(StdDuration::from_secs(0)).build())).await;
 
-        Ok(())
+        build
+    }
+
+    /// Drop an index on `keys` if it exists with the other uniqueness, so it can be
+    /// rebuilt with `unique`. MongoDB refuses to create an index whose keys match an
+    /// existing one with different options.
+    async fn drop_mismatched_index<T: Send + Sync>(collection: &Collection<T>, keys: Document, unique: bool) {
+        let indexes: Vec<IndexModel> = match collection.list_indexes(None).await {
+            Ok(cursor) => cursor.try_collect().await.unwrap_or_default(),
+            Err(_) => return, // Collection does not exist yet
+        };
+
+        let stale = indexes.into_iter().find(|index| {
+            index.keys == keys && index.options.as_ref().and_then(|o| o.unique).unwrap_or(false) != unique
+        });
+        if let Some(name) = stale.and_then(|index| index.options.and_then(|o| o.name)) {
+            let rebuilt_as = if unique { "unique" } else { "non-unique" };
+            tracing::info!("Dropping index '{}' on '{}' to rebuild it as {}", name, collection.name(), rebuilt_as);
+            if let Err(e) = collection.drop_index(name.clone(), None).await {
+                tracing::warn!("Failed to drop index '{}' on '{}': {}", name, collection.name(), e);
+            }
+        }
+    }
+
+    /// A tenant-scoped collection, confined to the tenant of the running request.
+    ///
+    /// Every access to the collections in [`TENANT_SCOPED_COLLECTIONS`] goes through this,
+    /// so a practitioner or tenant admin never matches another tenant's documents.
+    fn scoped<T: Send + Sync>(&self, name: &str) -> ScopedCollection<T> {
+        debug_assert!(TENANT_SCOPED_COLLECTIONS.contains(&name), "{} is not tenant-scoped", name);
+        ScopedCollection::new(self.db.collection(name))
+    }
+
+    /// Restrict a filter to documents that have not been soft-deleted.
+    ///
+    /// Every read path goes through this so tombstoned patients and encounters
+    /// disappear from the API; only admin paths pass `include_deleted = true`.
+    fn scope_deleted(mut filter: Document, include_deleted: bool) -> Document {
+        if !include_deleted {
+            // `null` also matches documents written before the field existed
+            filter.insert("deleted_at", Bson::Null);
+        }
+        filter
+    }
+
+    /// Name search tokens for a record and the id of the key that made them; none while search is off
+    fn search_index(&self, patient: &FhirPatient) -> (Vec<String>, Option<String>) {
+        let Some(key) = &self.patient_search_key else {
+            return (Vec::new(), None);
+        };
+        let names = patient.name.iter().flat_map(|name| name.given.iter().chain(&name.family)).map(String::as_str);
+        (name_search_tokens(names, key.expose_secret()), Some(name_search_key_id(key.expose_secret())))
+    }
+
+    /// The key a patient's record is encrypted with: its own data key, or the master key for
+    /// records from before data keys. Fails once the patient is erased, as that key is gone.
+    pub(crate) fn patient_data_key(encrypted_patient: &EncryptedPatient, encryption_key: &EncryptionKey) -> Result<EncryptionKey> {
+        if encrypted_patient.erased_at.is_some() {
+            return Err(anyhow::anyhow!("Patient {} has been erased", encrypted_patient.did));
+        }
+        match &encrypted_patient.wrapped_data_key {
+            Some(wrapped) => EncryptionKey::from_wrapped(wrapped, encryption_key),
+            None => Ok(encryption_key.clone()),
+        }
+    }
+
+    pub(crate) fn decrypt_fhir_patient(encrypted_patient: &EncryptedPatient, encryption_key: &EncryptionKey) -> Result<FhirPatient> {
+        let data_key = Self::patient_data_key(encrypted_patient, encryption_key)?;
+        Ok(serde_json::from_slice(&decrypt(&encrypted_patient.encrypted_fhir_patient, &data_key)?)?)
+    }
+
+    pub(crate) fn decrypt_patient(encrypted_patient: EncryptedPatient, encryption_key: &EncryptionKey) -> Result<Patient> {
+        let mut fhir_patient = Self::decrypt_fhir_patient(&encrypted_patient, encryption_key)?;
+        fhir_patient.photo = encrypted_patient.photo.iter().map(|photo| photo.fhir_attachment(&encrypted_patient.did)).collect();
+
+        Ok(Patient {
+            id: encrypted_patient.id,
+            did: encrypted_patient.did,
+            fhir_patient,
+            created_at: encrypted_patient.created_at,
+            updated_at: encrypted_patient.updated_at,
+            email_verified: encrypted_patient.email_verified,
+            verification_token: encrypted_patient.verification_token,
+            verification_token_expires: encrypted_patient.verification_token_expires,
+        })
     }
 
     // Patient operations
-    pub async fn create_patient(&self, patient: &Patient, encryption_key: &str) -> Result<()> {
+    pub async fn create_patient(&self, patient: &Patient, encryption_key: &EncryptionKey) -> Result<()> {
         let collection: Collection<EncryptedPatient> = self.db.collection("patients");
         let fhir_patient_json = serde_json::to_string(&patient.fhir_patient)?;
-        let encrypted_fhir_patient = encrypt(fhir_patient_json.as_bytes(), encryption_key)?;
+        // The record gets its own key, so erasing the patient only has to destroy that
+        let data_key = EncryptionKey::generate();
+        let encrypted_fhir_patient = encrypt(fhir_patient_json.as_bytes(), &data_key)?;
 
-        let email = patient.fhir_patient.telecom.iter().find(|c| c.system == "email").map(|c| c.value.as_str()).unwrap_or("");
-        let mut hasher = Sha256::new();
-        hasher.update(email.as_bytes());
-        let email_hash = format!("{:x}", hasher.finalize());
+        let email_hash = patient.fhir_patient.telecom.iter()
+            .find(|c| c.system == "email")
+            .map(|c| hash_email(&c.value));
+        let phone_hash = patient.fhir_patient.telecom.iter()
+            .find(|c| c.system == "phone")
+            .map(|c| hash_phone(&c.value));
+        let (search_tokens, search_key_id) = self.search_index(&patient.fhir_patient);
 
         let encrypted_patient = EncryptedPatient {
             id: None,
             did: patient.did.clone(),
             encrypted_fhir_patient,
             email_hash,
+            phone_hash,
             created_at: patient.created_at,
             updated_at: patient.updated_at,
             email_verified: patient.email_verified,
             verification_token: patient.verification_token.clone(),
             verification_token_expires: patient.verification_token_expires,
+            deleted_at: None,
+            wrapped_data_key: Some(data_key.wrap(encryption_key)?),
+            erased_at: None,
+            notification_preferences: None,
+            chat_record_consent: false,
+            timezone: None,
+            totp: None,
+            backup_codes: Vec::new(),
+            disabled_at: None,
+            merged_dids: Vec::new(),
+            search_tokens,
+            search_key_id,
+            photo: None,
         };
 
-        collection.insert_one(encrypted_patient, None).await?;
-        Ok(())
-    }
-
-    pub async fn get_patient_by_did(&self, did: &str, encryption_key: &str) -> Result<Option<Patient>> {
-        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
-        let filter = doc! { "did": did };
-        if let Some(encrypted_patient) = collection.find_one(filter, None).await? {
-            let decrypted_fhir_patient_json = decrypt(&encrypted_patient.encrypted_fhir_patient, encryption_key)?;
-            let fhir_patient: FhirPatient = serde_json::from_slice(&decrypted_fhir_patient_json)?;
-
-            let patient = Patient {
-                id: encrypted_patient.id,
-                did: encrypted_patient.did,
-                fhir_patient,
-                created_at: encrypted_patient.created_at,
-                updated_at: encrypted_patient.updated_at,
-                email_verified: encrypted_patient.email_verified,
-                verification_token: encrypted_patient.verification_token,
-                verification_token_expires: encrypted_patient.verification_token_expires,
-            };
-            Ok(Some(patient))
-        } else {
-            Ok(None)
+        match collection.insert_one(encrypted_patient, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub async fn get_patient_by_email(&self, email: &str, encryption_key: &str) -> Result<Option<Patient>> {
-        let mut hasher = Sha256::new();
-        hasher.update(email.as_bytes());
-        let email_hash = format!("{:x}", hasher.finalize());
+    pub async fn get_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>> {
+        self.find_patient_by_did(did, encryption_key, false).await
+    }
 
+    /// Look up a patient by DID, optionally including soft-deleted records (admin paths).
+    /// The DID of a record merged into another finds the live patient it was merged into.
+    pub async fn find_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey, include_deleted: bool) -> Result<Option<Patient>> {
         let collection: Collection<EncryptedPatient> = self.db.collection("patients");
-        let filter = doc! { "email_hash": email_hash };
-        if let Some(encrypted_patient) = collection.find_one(filter, None).await? {
-            let decrypted_fhir_patient_json = decrypt(&encrypted_patient.encrypted_fhir_patient, encryption_key)?;
-            let fhir_patient: FhirPatient = serde_json::from_slice(&decrypted_fhir_patient_json)?;
+        let filter = Self::scope_deleted(doc! { "did": did }, include_deleted);
+        let found = match collection.find_one(filter, None).await? {
+            Some(encrypted_patient) => Some(encrypted_patient),
+            None => collection.find_one(Self::scope_deleted(doc! { "merged_dids": did }, false), None).await?,
+        };
+        found.map(|encrypted_patient| Self::decrypt_patient(encrypted_patient, encryption_key)).transpose()
+    }
 
-            let patient = Patient {
-                id: encrypted_patient.id,
-                did: encrypted_patient.did,
-                fhir_patient,
-                created_at: encrypted_patient.created_at,
-                updated_at: encrypted_patient.updated_at,
-                email_verified: encrypted_patient.email_verified,
-                verification_token: encrypted_patient.verification_token,
-                verification_token_expires: encrypted_patient.verification_token_expires,
-            };
-            Ok(Some(patient))
-        } else {
-            Ok(None)
+    pub async fn get_patient_by_email(&self, email: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "email_hash": hash_email(email) }, false);
+        match collection.find_one(filter, None).await? {
+            Some(encrypted_patient) => Ok(Some(Self::decrypt_patient(encrypted_patient, encryption_key)?)),
+            None => Ok(None),
         }
     }
 
-    pub async fn get_patient_by_phone(&self, phone_number: &str, encryption_key: &str) -> Result<Option<Patient>> {
+    pub async fn get_patient_by_phone(&self, phone_number: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>> {
+        // Relies on `phone_hash`, which older records only have once the phone-hash
+        // backfill migration has run.
         let collection: Collection<EncryptedPatient> = self.db.collection("patients");
-        // This is inefficient, as it requires decrypting all patients. 
-        // A better approach would be to store a hash of the phone number, similar to the email.
-        let mut cursor = collection.find(None, None).await?;
-        while let Some(encrypted_patient) = cursor.try_next().await? {
-            let decrypted_fhir_patient_json = decrypt(&encrypted_patient.encrypted_fhir_patient, encryption_key)?;
-            let fhir_patient: FhirPatient = serde_json::from_slice(&decrypted_fhir_patient_json)?;
-
-            if fhir_patient.telecom.iter().any(|c| c.system == "phone" && c.value == phone_number) {
-                let patient = Patient {
-                    id: encrypted_patient.id,
-                    did: encrypted_patient.did,
-                    fhir_patient,
-                    created_at: encrypted_patient.created_at,
-                    updated_at: encrypted_patient.updated_at,
-                    email_verified: encrypted_patient.email_verified,
-                    verification_token: encrypted_patient.verification_token,
-                    verification_token_expires: encrypted_patient.verification_token_expires,
-                };
-                return Ok(Some(patient));
-            }
+        let filter = Self::scope_deleted(doc! { "phone_hash": hash_phone(phone_number) }, false);
+        match collection.find_one(filter, None).await? {
+            Some(encrypted_patient) => Ok(Some(Self::decrypt_patient(encrypted_patient, encryption_key)?)),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
-    pub async fn find_patient_by_verification_token(&self, token: &str, encryption_key: &str) -> Result<Option<Patient>> {
+    pub async fn find_patient_by_verification_token(&self, token: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>> {
         let collection: Collection<EncryptedPatient> = self.db.collection("patients");
-        let filter = doc! { "verification_token": token };
-        if let Some(encrypted_patient) = collection.find_one(filter, None).await? {
-            let decrypted_fhir_patient_json = decrypt(&encrypted_patient.encrypted_fhir_patient, encryption_key)?;
-            let fhir_patient: FhirPatient = serde_json::from_slice(&decrypted_fhir_patient_json)?;
-
-            let patient = Patient {
-                id: encrypted_patient.id,
-                did: encrypted_patient.did,
-                fhir_patient,
-                created_at: encrypted_patient.created_at,
-                updated_at: encrypted_patient.updated_at,
-                email_verified: encrypted_patient.email_verified,
-                verification_token: encrypted_patient.verification_token,
-                verification_token_expires: encrypted_patient.verification_token_expires,
-            };
-            Ok(Some(patient))
-        } else {
-            Ok(None)
+        let filter = Self::scope_deleted(doc! { "verification_token": token }, false);
+        match collection.find_one(filter, None).await? {
+            Some(encrypted_patient) => Ok(Some(Self::decrypt_patient(encrypted_patient, encryption_key)?)),
+            None => Ok(None),
         }
     }
 
     pub async fn set_patient_email_verified(&self, did: &str, verified: bool) -> Result<()> {
         let collection: Collection<EncryptedPatient> = self.db.collection("patients");
-        let filter = doc! { "did": did };
+        let filter = Self::scope_deleted(doc! { "did": did }, false);
         let update = doc! {
             "$set": {
                 "email_verified": verified,
@@ -250,140 +607,2807 @@ impl Database {
         Ok(())
     }
 
+    /// Tombstone a patient record. The document is kept so audit entries and
+    /// anchored batches still resolve; once the restore window has passed,
+    /// [`erase_deleted_patients`](Self::erase_deleted_patients) makes it unreadable.
+    /// Returns `false` if no live patient matched.
+    pub async fn soft_delete_patient(&self, did: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did }, false);
+        let update = doc! { "$set": { "deleted_at": bson::to_bson(&Utc::now())? } };
+        let result = collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Clear the tombstone on a patient deleted within `grace_period`.
+    /// Returns `false` if the patient isn't deleted or the grace window has passed.
+    pub async fn restore_patient(&self, did: &str, grace_period: Duration) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let deleted_at = match collection.find_one(doc! { "did": did }, None).await? {
+            // An erased record has nothing left to restore
+            Some(EncryptedPatient { deleted_at: Some(deleted_at), erased_at: None, .. }) => deleted_at,
+            _ => return Ok(false),
+        };
+        if deleted_at + grace_period < Utc::now() {
+            return Ok(false);
+        }
+        let update = doc! { "$unset": { "deleted_at": "" } };
+        let result = collection.update_one(doc! { "did": did }, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Erase the patients deleted before `deleted_before`: destroy each one's data key, so the
+    /// record can no longer be decrypted, and drop the hashes and name search tokens that could
+    /// still find it. Records from before data keys have no key to destroy, so their ciphertext
+    /// goes instead. Duplicates merged into another record are left alone, as the survivor
+    /// still answers for them. Returns the DIDs erased.
+    pub async fn erase_deleted_patients(&self, deleted_before: chrono::DateTime<Utc>) -> Result<Vec<String>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let merged: HashSet<Bson> = collection.distinct("merged_dids", Document::new(), None).await?.into_iter().collect();
+        let deleted: Vec<EncryptedPatient> = collection.find(doc! { "deleted_at": { "$ne": null }, "erased_at": null }, None).await?.try_collect().await?;
+        let mut erased = Vec::new();
+        for patient in deleted {
+            if patient.deleted_at.map_or(true, |deleted_at| deleted_at >= deleted_before) || merged.contains(&Bson::String(patient.did.clone())) {
+                continue;
+            }
+            let mut set = doc! { "erased_at": bson::to_bson(&Utc::now())?, "search_tokens": [] };
+            if patient.wrapped_data_key.is_none() {
+                set.insert("encrypted_fhir_patient", "");
+            }
+            let unset = doc! { "wrapped_data_key": "", "email_hash": "", "phone_hash": "", "search_key_id": "", "verification_token": "", "verification_token_expires": "" };
+            let result = collection.update_one(doc! { "did": &patient.did, "erased_at": null }, doc! { "$set": set, "$unset": unset }, None).await?;
+            if result.modified_count > 0 {
+                erased.push(patient.did);
+            }
+        }
+        Ok(erased)
+    }
+
+    /// Suspend or reinstate a live patient. Returns `false` if there is no such patient
+    /// or the account is already in that state.
+    pub async fn set_patient_disabled(&self, did: &str, disabled: bool) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let (filter, update) = if disabled {
+            (doc! { "did": did, "disabled_at": null }, doc! { "$set": { "disabled_at": bson::to_bson(&Utc::now())? } })
+        } else {
+            (doc! { "did": did, "disabled_at": { "$ne": null } }, doc! { "$unset": { "disabled_at": "" } })
+        };
+        let result = collection.update_one(Self::scope_deleted(filter, false), update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// The patient signed in as `did` and the state of their account, usually in one lookup.
+    /// Unlike [`get_patient_by_did`](Self::get_patient_by_did) it finds soft-deleted records too,
+    /// so a token that outlived its account can be told apart from one whose subject never had a
+    /// record. A record merged into another still resolves to the live one.
+    pub async fn get_patient_account(&self, did: &str, encryption_key: &EncryptionKey) -> Result<Option<(Patient, AccountStatus)>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let found = match collection.find_one(doc! { "did": did }, None).await? {
+            Some(encrypted_patient) if encrypted_patient.deleted_at.is_none() => Some(encrypted_patient),
+            tombstone => collection.find_one(Self::scope_deleted(doc! { "merged_dids": did }, false), None).await?.or(tombstone),
+        };
+        found
+            .map(|encrypted_patient| {
+                let status = match (&encrypted_patient.deleted_at, &encrypted_patient.disabled_at) {
+                    (Some(_), _) => AccountStatus::Deleted,
+                    (None, Some(_)) => AccountStatus::Disabled,
+                    (None, None) => AccountStatus::Active,
+                };
+                Ok((Self::decrypt_patient(encrypted_patient, encryption_key)?, status))
+            })
+            .transpose()
+    }
+
+    pub async fn is_patient_disabled(&self, did: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        Ok(collection.count_documents(doc! { "did": did, "disabled_at": { "$ne": null } }, None).await? > 0)
+    }
+
+    /// Patients newest first, tombstoned ones included so admins can still find them
+    pub async fn list_patients(&self, skip: u64, limit: i64) -> Result<(Vec<EncryptedPatient>, u64)> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).skip(skip).limit(limit).build();
+        let patients = collection.find(None, options).await?.try_collect().await?;
+        Ok((patients, collection.count_documents(None, None).await?))
+    }
+
+    // Patient search operations
+    /// Live patients whose name tokens include every one of `tokens`
+    pub async fn search_patients(&self, tokens: &[String], limit: i64) -> Result<Vec<EncryptedPatient>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "search_tokens": { "$all": tokens } }, false);
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// Those of `patient_dids` the practitioner has an active grant from, an appointment with
+    /// (requested or confirmed) or an encounter with
+    pub async fn related_patient_dids(&self, practitioner_did: &str, patient_dids: &[String]) -> Result<HashSet<String>> {
+        let now = bson::to_bson(&Utc::now())?;
+        let lookups = [
+            (
+                "access_controls",
+                doc! {
+                    "patient_did": { "$in": patient_dids },
+                    "grantee_did": practitioner_did,
+                    "active": true,
+                    "$or": [{ "expires_at": null }, { "expires_at": { "$gt": now } }],
+                },
+            ),
+            (
+                "appointments",
+                doc! {
+                    "patient_did": { "$in": patient_dids },
+                    "practitioner_did": practitioner_did,
+                    "status": { "$ne": bson::to_bson(&AppointmentStatus::Cancelled)? },
+                },
+            ),
+            (
+                "encounters",
+                doc! { "patient_did": { "$in": patient_dids }, "practitioner_did": practitioner_did, "deleted_at": null },
+            ),
+        ];
+        let mut related = HashSet::new();
+        for (collection, filter) in lookups {
+            let collection: ScopedCollection<Document> = self.scoped(collection);
+            for did in collection.distinct("patient_did", filter, None).await? {
+                if let Bson::String(did) = did {
+                    related.insert(did);
+                }
+            }
+        }
+        Ok(related)
+    }
+
+    /// Index up to `limit` live patients whose tokens were not made with the current search key,
+    /// i.e. records written before search was turned on or before the key changed. Returns how many.
+    pub async fn reindex_patient_search(&self, encryption_key: &EncryptionKey, limit: i64) -> Result<usize> {
+        let Some(key) = &self.patient_search_key else {
+            return Ok(0);
+        };
+        let key_id = name_search_key_id(key.expose_secret());
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "search_key_id": { "$ne": &key_id } }, false);
+        let stale: Vec<EncryptedPatient> = collection.find(filter, FindOptions::builder().limit(limit).build()).await?.try_collect().await?;
+        for encrypted_patient in &stale {
+            let search_tokens = match Self::decrypt_patient(encrypted_patient.clone(), encryption_key) {
+                Ok(patient) => self.search_index(&patient.fhir_patient).0,
+                Err(e) => {
+                    // Marked as indexed anyway, with no tokens, so the sweep does not keep coming back to it
+                    tracing::warn!(did = %encrypted_patient.did, "Failed to decrypt patient for the search index: {}", e);
+                    Vec::new()
+                }
+            };
+            collection
+                .update_one(
+                    doc! { "did": &encrypted_patient.did },
+                    doc! { "$set": { "search_tokens": search_tokens, "search_key_id": &key_id } },
+                    None,
+                )
+                .await?;
+        }
+        Ok(stale.len())
+    }
+
+    // Duplicate patient operations
+    /// Every patient that has not been deleted, for the duplicate scan
+    pub async fn live_patients(&self) -> Result<Vec<EncryptedPatient>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        Ok(collection.find(Self::scope_deleted(Document::new(), false), None).await?.try_collect().await?)
+    }
+
+    /// Record a suspected duplicate pair, refreshing its score if the pair was found before
+    pub async fn upsert_merge_candidate(&self, candidate: &MergeCandidate) -> Result<()> {
+        let collection: Collection<MergeCandidate> = self.db.collection("merge_candidates");
+        let filter = doc! { "patient_dids": candidate.patient_dids.as_slice() };
+        let update = doc! {
+            "$set": {
+                "score": candidate.score,
+                "reasons": candidate.reasons.as_slice(),
+                "detected_at": bson::to_bson(&candidate.detected_at)?,
+            },
+            "$setOnInsert": { "status": bson::to_bson(&candidate.status)? },
+        };
+        collection.update_one(filter, update, UpdateOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    /// Candidates, most likely duplicates first
+    pub async fn list_merge_candidates(&self, status: Option<MergeCandidateStatus>, skip: u64, limit: i64) -> Result<(Vec<MergeCandidate>, u64)> {
+        let collection: Collection<MergeCandidate> = self.db.collection("merge_candidates");
+        let filter = match status {
+            Some(status) => doc! { "status": bson::to_bson(&status)? },
+            None => Document::new(),
+        };
+        let options = FindOptions::builder().sort(doc! { "score": -1, "detected_at": -1 }).skip(skip).limit(limit).build();
+        let candidates = collection.find(filter.clone(), options).await?.try_collect().await?;
+        Ok((candidates, collection.count_documents(filter, None).await?))
+    }
+
+    /// Fold `duplicate_did` into the survivor, whose record already holds the merged contact
+    /// points: the duplicate's encounters, prescriptions, credentials and access grants move to
+    /// the survivor, its DID becomes an alias of the survivor, and it is tombstoned. The returned
+    /// merge document lists everything that moved.
+    pub async fn merge_patients(
+        &self,
+        survivor: &Patient,
+        duplicate_did: &str,
+        merged_by: &str,
+        telecom_added: usize,
+        encryption_key: &EncryptionKey,
+    ) -> Result<PatientMerge> {
+        let patients: Collection<EncryptedPatient> = self.db.collection("patients");
+        let survivor_before = patients
+            .find_one(Self::scope_deleted(doc! { "did": &survivor.did }, false), None)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Surviving patient not found"))?;
+
+        // Tombstoning the duplicate first claims it: a second merge of the same record stops here.
+        // Its identifier hashes are cleared so the survivor can take them over.
+        let now = Utc::now();
+        let duplicate = patients
+            .find_one_and_update(
+                Self::scope_deleted(doc! { "did": duplicate_did }, false),
+                doc! { "$set": { "deleted_at": bson::to_bson(&now)? }, "$unset": { "email_hash": "", "phone_hash": "" } },
+                None,
+            )
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Duplicate patient not found"))?;
+
+        let survivor_reference = format!("Patient/{}", survivor.did);
+        let encounter_ids = self
+            .repoint("encounters", "patient_did", duplicate_did, doc! { "patient_did": &survivor.did, "fhir_encounter.subject.reference": &survivor_reference })
+            .await?;
+        let prescription_ids = self
+            .repoint("prescriptions", "patient_did", duplicate_did, doc! { "patient_did": &survivor.did, "fhir_medication_request.subject.reference": &survivor_reference })
+            .await?;
+        let credential_ids = self.repoint("verifiable_credentials", "subject_did", duplicate_did, doc! { "subject_did": &survivor.did }).await?;
+        let access_grant_ids = self.repoint("access_controls", "patient_did", duplicate_did, doc! { "patient_did": &survivor.did }).await?;
+
+        // The survivor keeps its own identifiers and takes the duplicate's where it has none
+        let data_key = Self::patient_data_key(&survivor_before, encryption_key)?;
+        let encrypted_fhir_patient = encrypt(serde_json::to_string(&survivor.fhir_patient)?.as_bytes(), &data_key)?;
+        let email_hash = survivor_before.email_hash.clone().or_else(|| duplicate.email_hash.clone());
+        let phone_hash = survivor_before.phone_hash.clone().or_else(|| duplicate.phone_hash.clone());
+        let mut aliases = vec![duplicate_did.to_string()];
+        aliases.extend(duplicate.merged_dids.iter().cloned());
+        let (search_tokens, search_key_id) = self.search_index(&survivor.fhir_patient);
+        let mut set = doc! {
+            "encrypted_fhir_patient": encrypted_fhir_patient,
+            "search_tokens": search_tokens,
+            "search_key_id": search_key_id,
+            "updated_at": bson::to_bson(&now)?,
+        };
+        if let Some(email_hash) = &email_hash {
+            set.insert("email_hash", email_hash);
+        }
+        if let Some(phone_hash) = &phone_hash {
+            set.insert("phone_hash", phone_hash);
+        }
+        patients
+            .update_one(doc! { "did": &survivor.did }, doc! { "$set": set, "$addToSet": { "merged_dids": { "$each": aliases } } }, None)
+            .await?;
+
+        let mut merge = PatientMerge {
+            id: None,
+            survivor_did: survivor.did.clone(),
+            duplicate_did: duplicate_did.to_string(),
+            merged_by: merged_by.to_string(),
+            merged_at: now,
+            encounter_ids,
+            prescription_ids,
+            credential_ids,
+            access_grant_ids,
+            telecom_added,
+            survivor_record_before: survivor_before.encrypted_fhir_patient,
+            survivor_email_hash_before: survivor_before.email_hash,
+            survivor_phone_hash_before: survivor_before.phone_hash,
+            duplicate_email_hash: duplicate.email_hash,
+            duplicate_phone_hash: duplicate.phone_hash,
+        };
+        let merges: Collection<PatientMerge> = self.db.collection("patient_merges");
+        merge.id = merges.insert_one(&merge, None).await?.inserted_id.as_object_id();
+
+        let candidates: Collection<MergeCandidate> = self.db.collection("merge_candidates");
+        candidates
+            .update_many(doc! { "patient_dids": duplicate_did }, doc! { "$set": { "status": bson::to_bson(&MergeCandidateStatus::Merged)? } }, None)
+            .await?;
+        Ok(merge)
+    }
+
+    /// Set `update` on every document of `collection` whose `field` is `from`, returning their ids.
+    /// Patients are global, so this reaches their records in every tenant.
+    async fn repoint(&self, collection: &str, field: &str, from: &str, update: Document) -> Result<Vec<ObjectId>> {
+        let collection: Collection<Document> = self.db.collection(collection);
+        let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+        let ids: Vec<ObjectId> = collection
+            .find(doc! { field: from }, options)
+            .await?
+            .try_collect::<Vec<Document>>()
+            .await?
+            .iter()
+            .filter_map(|document| document.get_object_id("_id").ok())
+            .collect();
+        if !ids.is_empty() {
+            collection.update_many(doc! { "_id": { "$in": ids.as_slice() } }, doc! { "$set": update }, None).await?;
+        }
+        Ok(ids)
+    }
+
     // Practitioner operations
+    /// A DID that is already registered fails with `DatabaseError::DuplicateKey`
     pub async fn create_practitioner(&self, practitioner: &Practitioner) -> Result<()> {
-        let collection: Collection<Practitioner> = self.db.collection("practitioners");
-        collection.insert_one(practitioner, None).await?;
-        Ok(())
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        match collection.insert_one(practitioner, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     pub async fn get_practitioner_by_did(&self, did: &str) -> Result<Option<Practitioner>> {
-        let collection: Collection<Practitioner> = self.db.collection("practitioners");
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
         let filter = doc! { "did": did };
         Ok(collection.find_one(filter, None).await?)
     }
 
+    /// Record that a practitioner's license was checked; false for DIDs that are not registered
+    pub async fn verify_practitioner_license(&self, did: &str) -> Result<bool> {
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        let update = doc! { "$set": { "license_verification.verified": true, "updated_at": bson::to_bson(&Utc::now())? } };
+        Ok(collection.update_one(doc! { "did": did }, update, None).await?.matched_count > 0)
+    }
+
+    /// See [`PractitionerStore::claim_license_expiry_warning`]
+    pub async fn claim_license_expiry_warning(&self, did: &str, expiry_date: &str, now: chrono::DateTime<Utc>) -> Result<bool> {
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        let filter = doc! {
+            "did": did,
+            "license_verification.verified": true,
+            "license_verification.expiry_date": expiry_date,
+            "license_verification.expiry_warning_sent_at": Bson::Null,
+        };
+        let update = doc! { "$set": { "license_verification.expiry_warning_sent_at": bson::to_bson(&now)? } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// See [`PractitionerStore::revoke_license_verification`]
+    pub async fn revoke_license_verification(&self, did: &str) -> Result<bool> {
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        let update = doc! { "$set": { "license_verification.verified": false, "updated_at": bson::to_bson(&Utc::now())? } };
+        Ok(collection.update_one(doc! { "did": did, "license_verification.verified": true }, update, None).await?.modified_count > 0)
+    }
+
+    /// Practitioners in order of registration, optionally only those whose license is (or is not) verified
+    pub async fn list_practitioners(&self, verified: Option<bool>) -> Result<Vec<Practitioner>> {
+        let collection: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        let filter = verified.map(|verified| doc! { "license_verification.verified": verified });
+        let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// The tenant a practitioner belongs to, whichever tenant is asking; `None` for unknown DIDs
+    /// and practitioners registered before tenancy
+    pub async fn practitioner_tenant(&self, did: &str) -> Result<Option<String>> {
+        let collection: Collection<Practitioner> = self.db.collection("practitioners");
+        Ok(collection.find_one(doc! { "did": did }, None).await?.and_then(|practitioner| practitioner.tenant_id))
+    }
+
     // Encounter Operations
+    /// Written outside a tenant, the encounter joins its practitioner's tenant
     pub async fn create_encounter(&self, encounter: &Encounter) -> Result<ObjectId> {
-        let collection: Collection<Encounter> = self.db.collection("encounters");
-        let result = collection.insert_one(encounter, None).await?;
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let mut encounter = encounter.clone();
+        if encounter.tenant_id.is_none() {
+            encounter.tenant_id = self.practitioner_tenant(&encounter.practitioner_did).await?;
+        }
+        let result = collection.insert_one(&encounter, None).await?;
         Ok(result.inserted_id.as_object_id().unwrap())
     }
 
     pub async fn get_encounter(&self, encounter_id: ObjectId) -> Result<Option<Encounter>> {
-        let collection: Collection<Encounter> = self.db.collection("encounters");
-        Ok(collection.find_one(doc! { "_id": encounter_id }, None).await?)
+        self.find_encounter(encounter_id, false).await
     }
 
-    pub async fn get_observations_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirObservation>> {
-        let collection: Collection<FhirObservation> = self.db.collection("observations");
-        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
-        let cursor = collection.find(filter, None).await?;
-        Ok(cursor.try_collect().await?)
+    /// The encounter whose current bundle is stored under `ipfs_hash`
+    pub async fn get_encounter_by_bundle(&self, ipfs_hash: &str) -> Result<Option<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(doc! { "final_bundle_ipfs_hash": ipfs_hash }, false);
+        Ok(collection.find_one(filter, None).await?)
     }
 
-    pub async fn get_conditions_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirCondition>> {
-        let collection: Collection<FhirCondition> = self.db.collection("conditions");
-        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
-        let cursor = collection.find(filter, None).await?;
-        Ok(cursor.try_collect().await?)
+    pub async fn create_attachment(&self, attachment: &Attachment) -> Result<ObjectId> {
+        let collection: Collection<Attachment> = self.db.collection("attachments");
+        let result = collection.insert_one(attachment, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
     }
 
-    pub async fn get_medication_requests_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirMedicationRequest>> {
-        let collection: Collection<FhirMedicationRequest> = self.db.collection("medication_requests");
-        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
-        let cursor = collection.find(filter, None).await?;
-        Ok(cursor.try_collect().await?)
+    /// The attachment stored under `ipfs_hash`
+    pub async fn get_attachment_by_hash(&self, ipfs_hash: &str) -> Result<Option<Attachment>> {
+        let collection: Collection<Attachment> = self.db.collection("attachments");
+        Ok(collection.find_one(doc! { "ipfs_hash": ipfs_hash }, None).await?)
     }
 
-    pub async fn finalize_encounter(&self, encounter_id: ObjectId, ipfs_hash: &str) -> Result<()> {
-        let collection: Collection<Encounter> = self.db.collection("encounters");
-        let filter = doc! { "_id": encounter_id };
-        let update = doc! { "$set": { 
-            "status": "Finalized", 
-            "final_bundle_ipfs_hash": ipfs_hash,
-            "updated_at": DateTime::now()
-        } };
-        collection.update_one(filter, update, None).await?;
-        Ok(())
+    /// Look up an encounter, optionally including soft-deleted ones (admin paths).
+    pub async fn find_encounter(&self, encounter_id: ObjectId, include_deleted: bool) -> Result<Option<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(doc! { "_id": encounter_id }, include_deleted);
+        Ok(collection.find_one(filter, None).await?)
     }
 
-    // Prescription operations
-    pub async fn create_prescription(&self, prescription: &Prescription) -> Result<()> {
-        let collection: Collection<Prescription> = self.db.collection("prescriptions");
-        collection.insert_one(prescription, None).await?;
-        Ok(())
+    /// Tombstone an encounter. Returns `false` if no live encounter matched.
+    pub async fn soft_delete_encounter(&self, encounter_id: ObjectId) -> Result<bool> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(doc! { "_id": encounter_id }, false);
+        let update = doc! { "$set": { "deleted_at": bson::to_bson(&Utc::now())? } };
+        let result = collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count > 0)
     }
 
-    pub async fn get_prescriptions_by_patient(&self, patient_did: &str) -> Result<Vec<Prescription>> {
-        let collection: Collection<Prescription> = self.db.collection("prescriptions");
-        let filter = doc! { "patient_did": patient_did };
-        let cursor = collection.find(filter, None).await?;
+    /// Clear the tombstone on an encounter deleted within `grace_period`.
+    pub async fn restore_encounter(&self, encounter_id: ObjectId, grace_period: Duration) -> Result<bool> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let deleted_at = match collection.find_one(doc! { "_id": encounter_id }, None).await? {
+            Some(Encounter { deleted_at: Some(deleted_at), .. }) => deleted_at,
+            _ => return Ok(false),
+        };
+        if deleted_at + grace_period < Utc::now() {
+            return Ok(false);
+        }
+        let update = doc! { "$unset": { "deleted_at": "" } };
+        let result = collection.update_one(doc! { "_id": encounter_id }, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// The patient's live encounters, most recent first
+    pub async fn list_recent_encounters(&self, patient_did: &str, limit: i64) -> Result<Vec<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(doc! { "patient_did": patient_did }, false);
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit).build();
+        let cursor = collection.find(filter, options).await?;
         Ok(cursor.try_collect().await?)
     }
 
-    // Access control operations
-    pub async fn grant_access(&self, access_control: &AccessControl) -> Result<()> {
-        let collection: Collection<AccessControl> = self.db.collection("access_controls");
-        collection.insert_one(access_control, None).await?;
-        Ok(())
+    /// Live encounters of `did` on the given side created within `[from, to)`, newest first
+    pub async fn list_encounters(
+        &self,
+        party: AppointmentParty,
+        did: &str,
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<Vec<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let did_field = Self::party_field(party);
+        let mut filter = Self::scope_deleted(doc! { did_field: did }, false);
+        Self::insert_time_range(&mut filter, "created_at", from, to)?;
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
     }
 
-    pub async fn check_access(&self, patient_did: &str, grantee_did: &str) -> Result<bool> {
-        let collection: Collection<AccessControl> = self.db.collection("access_controls");
-        let filter = doc! { 
-            "patient_did": patient_did, 
-            "grantee_did": grantee_did,
-            "active": true
+    /// Live encounters created by the affiliated practitioners while each affiliation ran, newest first
+    pub async fn list_affiliated_encounters(
+        &self,
+        affiliations: &[Affiliation],
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<Vec<Encounter>> {
+        let Some(filter) = Self::affiliation_filter(affiliations, "created_at")? else {
+            return Ok(Vec::new());
         };
-        Ok(collection.find_one(filter, None).await?.is_some())
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let mut filter = Self::scope_deleted(filter, false);
+        Self::insert_time_range(&mut filter, "created_at", from, to)?;
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
     }
 
-    // FHIR Bundle operations
-    pub async fn create_fhir_bundle(&self, bundle: &FhirBundle) -> Result<()> {
-        let collection: Collection<FhirBundle> = self.db.collection("fhir_bundles");
-        collection.insert_one(bundle, None).await?;
+    /// Match documents of each affiliated practitioner whose `time_field` falls inside that affiliation's period.
+    /// `None` when there is nothing to match.
+    fn affiliation_filter(affiliations: &[Affiliation], time_field: &str) -> Result<Option<Document>> {
+        if affiliations.is_empty() {
+            return Ok(None);
+        }
+        let mut clauses = Vec::with_capacity(affiliations.len());
+        for affiliation in affiliations {
+            let mut clause = doc! { "practitioner_did": &affiliation.practitioner_did };
+            Self::insert_time_range(&mut clause, time_field, affiliation.period_start, affiliation.period_end)?;
+            clauses.push(clause);
+        }
+        Ok(Some(doc! { "$or": clauses }))
+    }
+
+    /// Restrict `time_field` to `[from, to)`; either end may be open
+    fn insert_time_range(filter: &mut Document, time_field: &str, from: Option<chrono::DateTime<Utc>>, to: Option<chrono::DateTime<Utc>>) -> Result<()> {
+        let mut range = Document::new();
+        if let Some(from) = from {
+            range.insert("$gte", bson::to_bson(&from)?);
+        }
+        if let Some(to) = to {
+            range.insert("$lt", bson::to_bson(&to)?);
+        }
+        if !range.is_empty() {
+            filter.insert(time_field, range);
+        }
         Ok(())
     }
 
-    pub async fn get_fhir_bundle(&self, patient_did: &str) -> Result<Option<FhirBundle>> {
-        let collection: Collection<FhirBundle> = self.db.collection("fhir_bundles");
-        let filter = doc! { "patient_did": patient_did };
-        Ok(collection.find_one(filter, None).await?)
+    fn party_field(party: AppointmentParty) -> &'static str {
+        match party {
+            AppointmentParty::Patient => "patient_did",
+            AppointmentParty::Practitioner => "practitioner_did",
+        }
     }
 
-    // Verifiable Credential operations
-    pub async fn create_verifiable_credential(&self, credential: &VerifiableCredential) -> Result<()> {
-        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
-        collection.insert_one(credential, None).await?;
+    pub async fn create_observation(&self, observation: &FhirObservation) -> Result<()> {
+        let collection: Collection<FhirObservation> = self.db.collection("observations");
+        collection.insert_one(observation, None).await?;
         Ok(())
     }
 
-    // Audit Log operations
-    pub async fn create_audit_log(&self, log: &AuditLog) -> Result<()> {
-        let collection: Collection<AuditLog> = self.db.collection("audit_logs");
+    pub async fn create_observations(&self, observations: &[FhirObservation]) -> Result<()> {
+        let collection: Collection<FhirObservation> = self.db.collection("observations");
+        collection.insert_many(observations, None).await?;
+        Ok(())
+    }
+
+    pub async fn get_observations_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirObservation>> {
+        let collection: Collection<FhirObservation> = self.db.collection("observations");
+        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Observations are searched by patient and code, then by date
+    fn observation_search_index() -> Document {
+        doc! { "subject.reference": 1, "code.coding.code": 1, "effective_date_time": -1 }
+    }
+
+    /// Per-period min/max/mean of the patient's numeric readings of `code` effective within
+    /// `[from, to)`, their latest numeric reading and how many readings had to be left out
+    pub async fn summarize_observations(
+        &self,
+        patient_did: &str,
+        code: &str,
+        period: ObservationPeriod,
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<ObservationSummary> {
+        let collection: Collection<Document> = self.db.collection("observations");
+        // effective_date_time is an RFC 3339 string with any offset, so the range is applied to the parsed instant
+        let mut pipeline = vec![
+            doc! { "$match": { "subject.reference": format!("Patient/{}", patient_did), "code.coding.code": code } },
+            doc! { "$addFields": {
+                "_at": { "$dateFromString": { "dateString": "$effective_date_time", "onError": null, "onNull": null } },
+                "_value": "$value_quantity.value",
+            } },
+        ];
+        let mut range = Document::new();
+        if let Some(from) = from {
+            range.insert("$gte", DateTime::from_chrono(from));
+        }
+        if let Some(to) = to {
+            range.insert("$lt", DateTime::from_chrono(to));
+        }
+        if !range.is_empty() {
+            // Unreadable dates stay in, to be counted as skipped
+            pipeline.push(doc! { "$match": { "$or": [{ "_at": null }, { "_at": range }] } });
+        }
+        let readable = doc! { "_at": { "$ne": null }, "_value": { "$type": "number" } };
+        pipeline.push(doc! { "$facet": {
+            "buckets": [
+                { "$match": readable.clone() },
+                { "$group": {
+                    "_id": { "$dateTrunc": { "date": "$_at", "unit": period.unit(), "startOfWeek": "monday" } },
+                    "count": { "$sum": 1 },
+                    "min": { "$min": "$_value" },
+                    "max": { "$max": "$_value" },
+                    "mean": { "$avg": "$_value" },
+                } },
+                { "$sort": { "_id": 1 } },
+            ],
+            "latest": [
+                { "$match": readable.clone() },
+                { "$sort": { "_at": -1 } },
+                { "$limit": 1 },
+                { "$project": { "_id": 0, "value_quantity": 1, "effective_date_time": 1, "interpretation": 1 } },
+            ],
+            "skipped": [
+                { "$match": { "$nor": [readable] } },
+                { "$count": "count" },
+            ],
+        } });
+
+        let options = AggregateOptions::builder().hint(Hint::Keys(Self::observation_search_index())).build();
+        let mut cursor = collection.aggregate(pipeline, options).await?;
+        let facets: ObservationFacets = match cursor.try_next().await? {
+            Some(document) => bson::from_document(document)?,
+            None => ObservationFacets::default(),
+        };
+        Ok(ObservationSummary {
+            code: code.to_string(),
+            period,
+            buckets: facets
+                .buckets
+                .into_iter()
+                .map(|bucket| ObservationBucket { start: bucket.start, count: bucket.count, min: bucket.min, max: bucket.max, mean: bucket.mean })
+                .collect(),
+            latest: facets.latest.into_iter().next().map(|latest| LatestObservation {
+                value: latest.value_quantity,
+                effective_date_time: latest.effective_date_time,
+                interpretation: latest.interpretation,
+            }),
+            skipped: facets.skipped.first().map_or(0, |skipped| skipped.count),
+        })
+    }
+
+    pub async fn create_condition(&self, condition: &FhirCondition) -> Result<()> {
+        let collection: Collection<FhirCondition> = self.db.collection("conditions");
+        collection.insert_one(condition, None).await?;
+        Ok(())
+    }
+
+    /// Every condition recorded for the patient, whether in an encounter or imported
+    pub async fn list_patient_conditions(&self, patient_did: &str) -> Result<Vec<FhirCondition>> {
+        let collection: Collection<FhirCondition> = self.db.collection("conditions");
+        let cursor = collection.find(doc! { "subject.reference": format!("Patient/{}", patient_did) }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn get_conditions_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirCondition>> {
+        let collection: Collection<FhirCondition> = self.db.collection("conditions");
+        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn get_medication_requests_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirMedicationRequest>> {
+        let collection: Collection<FhirMedicationRequest> = self.db.collection("medication_requests");
+        let filter = doc! { "encounter.reference": format!("Encounter/{}", encounter_id) };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Claim an in-progress encounter for finalizing until `lease_until`, so concurrent calls
+    /// do not each upload a bundle; `None` if it is not in progress or another claim is live.
+    /// A claim left behind by a crashed finalization lapses at its `finalizing_until`.
+    pub async fn claim_encounter_finalization(&self, encounter_id: ObjectId, lease_until: chrono::DateTime<Utc>) -> Result<Option<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(
+            doc! {
+                "_id": encounter_id,
+                "status": bson::to_bson(&EncounterStatus::InProgress)?,
+                "$or": [{ "finalizing_until": Bson::Null }, { "finalizing_until": { "$lte": DateTime::now() } }],
+            },
+            false,
+        );
+        let update = doc! { "$set": { "finalizing_until": DateTime::from_chrono(lease_until) } };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    /// Give up the claim taken with `lease_until`, so finalizing can be retried straight away
+    pub async fn release_encounter_finalization(&self, encounter_id: ObjectId, lease_until: chrono::DateTime<Utc>) -> Result<()> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = doc! { "_id": encounter_id, "finalizing_until": DateTime::from_chrono(lease_until) };
+        collection.update_one(filter, doc! { "$unset": { "finalizing_until": "" } }, None).await?;
+        Ok(())
+    }
+
+    /// Mark an encounter finished with its bundle, encrypted with key `key_version`, provided the
+    /// claim taken with `lease_until` still holds; `false` if it lapsed and the encounter was claimed or changed since
+    pub async fn finalize_encounter(&self, encounter_id: ObjectId, ipfs_hash: &str, key_version: u32, lease_until: chrono::DateTime<Utc>) -> Result<bool> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(
+            doc! {
+                "_id": encounter_id,
+                "status": bson::to_bson(&EncounterStatus::InProgress)?,
+                "finalizing_until": DateTime::from_chrono(lease_until),
+            },
+            false,
+        );
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&EncounterStatus::Finished)?,
+                "fhir_encounter.status": EncounterStatus::Finished.fhir_code(),
+                "final_bundle_ipfs_hash": ipfs_hash,
+                "bundle_key_version": key_version,
+                "updated_at": DateTime::now()
+            },
+            "$unset": { "finalizing_until": "" },
+        };
+        let result = collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Up to `limit` encounters, after `after` in `_id` order, whose bundle is not encrypted with
+    /// key `key_version`. Deleted encounters are included; their bundles are still archived.
+    pub async fn list_bundles_to_reencrypt(&self, key_version: u32, after: Option<ObjectId>, limit: i64) -> Result<Vec<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let mut versions = vec![Bson::from(key_version)];
+        if key_version == LEGACY_BUNDLE_KEY_VERSION {
+            versions.push(Bson::Null);
+        }
+        let mut filter = doc! { "final_bundle_ipfs_hash": { "$ne": Bson::Null }, "bundle_key_version": { "$nin": versions } };
+        if let Some(after) = after {
+            filter.insert("_id", doc! { "$gt": after });
+        }
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).limit(limit).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Point an encounter at its re-encrypted bundle, keeping the one it `replaced` in its history;
+    /// `false` if the encounter no longer points at that bundle
+    pub async fn replace_encounter_bundle(&self, encounter_id: ObjectId, replaced: &BundleVersion, ipfs_hash: &str, key_version: u32) -> Result<bool> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = doc! { "_id": encounter_id, "final_bundle_ipfs_hash": &replaced.ipfs_hash };
+        let update = doc! {
+            "$set": { "final_bundle_ipfs_hash": ipfs_hash, "bundle_key_version": key_version, "updated_at": DateTime::now() },
+            "$push": { "bundle_history": bson::to_bson(replaced)? },
+        };
+        let result = collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Move an encounter from `from` to `to`, keeping the FHIR resource in step;
+    /// `None` if it does not exist or is no longer in `from`
+    pub async fn update_encounter_status(
+        &self,
+        encounter_id: ObjectId,
+        from: EncounterStatus,
+        to: EncounterStatus,
+        reason: Option<String>,
+    ) -> Result<Option<Encounter>> {
+        let collection: ScopedCollection<Encounter> = self.scoped("encounters");
+        let filter = Self::scope_deleted(doc! { "_id": encounter_id, "status": bson::to_bson(&from)? }, false);
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&to)?,
+                "fhir_encounter.status": to.fhir_code(),
+                "status_reason": reason,
+                "updated_at": DateTime::now(),
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    // Prescription operations
+    pub async fn create_prescription(&self, prescription: &Prescription) -> Result<ObjectId> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        let result = collection.insert_one(prescription, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_prescription(&self, id: ObjectId) -> Result<Option<Prescription>> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Newest first, optionally only those in `status`
+    pub async fn list_prescriptions(&self, patient_did: &str, status: Option<PrescriptionStatus>) -> Result<Vec<Prescription>> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        let mut filter = doc! { "patient_did": patient_did };
+        if let Some(status) = status {
+            filter.insert("status", Self::prescription_status_filter(status)?);
+        }
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Move a prescription from `from` to `to`, keeping the FHIR resource in step;
+    /// `None` if it does not exist or is no longer in `from`
+    pub async fn update_prescription_status(
+        &self,
+        id: ObjectId,
+        from: PrescriptionStatus,
+        to: PrescriptionStatus,
+        reason: Option<FhirCodeableConcept>,
+    ) -> Result<Option<Prescription>> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        let filter = doc! { "_id": id, "status": Self::prescription_status_filter(from)? };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&to)?,
+                "fhir_medication_request.status": to.fhir_code(),
+                "fhir_medication_request.status_reason": bson::to_bson(&reason)?,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    pub async fn get_prescription_by_credential(&self, credential_hash: &str) -> Result<Option<Prescription>> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        Ok(collection.find_one(doc! { "credential.hash": credential_hash }, None).await?)
+    }
+
+    /// Record a dispense against a prescription that is still active, not yet fully dispensed and
+    /// still has `previous_dispenses` dispenses, so two pharmacies cannot fill it at once;
+    /// `None` otherwise. `fills` marks it fully dispensed.
+    pub async fn add_dispense(&self, id: ObjectId, dispense: &Dispense, previous_dispenses: usize, fills: bool) -> Result<Option<Prescription>> {
+        let collection: Collection<Prescription> = self.db.collection("prescriptions");
+        let mut filter = doc! {
+            "_id": id,
+            "status": Self::prescription_status_filter(PrescriptionStatus::Active)?,
+            "fully_dispensed": { "$ne": true },
+        };
+        match previous_dispenses {
+            // Also matches prescriptions stored before dispenses were tracked
+            0 => filter.insert("dispenses.0", doc! { "$exists": false }),
+            count => filter.insert("dispenses", doc! { "$size": count as i64 }),
+        };
+        let update = doc! {
+            "$push": { "dispenses": bson::to_bson(dispense)? },
+            "$set": { "fully_dispensed": fills, "updated_at": bson::to_bson(&Utc::now())? },
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    /// Prescriptions stored before statuses were tracked have none and count as active
+    fn prescription_status_filter(status: PrescriptionStatus) -> Result<Bson> {
+        Ok(match status {
+            PrescriptionStatus::Active => Bson::Document(doc! { "$in": [bson::to_bson(&status)?, Bson::Null] }),
+            _ => bson::to_bson(&status)?,
+        })
+    }
+
+    // Access control operations
+    /// Store the grant, queueing `outbox` in the same transaction
+    pub async fn grant_access(&self, access_control: &AccessControl, outbox: &[OutboxAction]) -> Result<ObjectId> {
+        self.insert_with_outbox("access_controls", access_control, outbox).await
+    }
+
+    pub async fn get_grant(&self, id: ObjectId) -> Result<Option<AccessControl>> {
+        let collection: Collection<AccessControl> = self.db.collection("access_controls");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Save the ledger transaction that recorded the grant; false if it already has one or is gone
+    pub async fn set_grant_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        let collection: Collection<AccessControl> = self.db.collection("access_controls");
+        let filter = doc! { "_id": id, "hedera_transaction_id": Bson::Null };
+        let result = collection.update_one(filter, doc! { "$set": { "hedera_transaction_id": transaction_id } }, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// The active, unexpired grants `grantee_did` holds on the patient's record,
+    /// grants from the patient before break-glass ones
+    pub async fn active_grants(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<AccessControl>> {
+        let collection: Collection<AccessControl> = self.db.collection("access_controls");
+        let filter = doc! { 
+            "patient_did": patient_did, 
+            "grantee_did": grantee_did,
+            "active": true,
+            "$or": [{ "expires_at": null }, { "expires_at": { "$gt": bson::to_bson(&Utc::now())? } }],
+        };
+        let options = FindOptions::builder().sort(doc! { "emergency": 1, "created_at": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// False when there was no active grant from the patient to deactivate; break-glass grants are left to expire
+    pub async fn deactivate_access(&self, patient_did: &str, grantee_did: &str) -> Result<bool> {
+        let collection: Collection<AccessControl> = self.db.collection("access_controls");
+        let filter = doc! { "patient_did": patient_did, "grantee_did": grantee_did, "active": true, "emergency": { "$ne": true } };
+        let result = collection.update_many(filter, doc! { "$set": { "active": false } }, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// False when the grant does not exist or was already inactive
+    pub async fn deactivate_grant(&self, id: ObjectId) -> Result<bool> {
+        let collection: Collection<AccessControl> = self.db.collection("access_controls");
+        let result = collection.update_one(doc! { "_id": id, "active": true }, doc! { "$set": { "active": false } }, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// False for patients who never opted in, and for unknown or deleted patients
+    pub async fn get_chat_record_consent(&self, patient_did: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        Ok(collection.find_one(filter, None).await?.is_some_and(|patient| patient.chat_record_consent))
+    }
+
+    /// Returns false when there is no live patient with this DID
+    pub async fn set_chat_record_consent(&self, patient_did: &str, enabled: bool) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        let update = doc! { "$set": { "chat_record_consent": enabled, "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// The patient's time zone name, if they set one
+    pub async fn get_patient_timezone(&self, patient_did: &str) -> Result<Option<String>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        Ok(collection.find_one(filter, None).await?.and_then(|patient| patient.timezone))
+    }
+
+    /// Returns false when there is no live patient with this DID
+    pub async fn set_patient_timezone(&self, patient_did: &str, timezone: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        let update = doc! { "$set": { "timezone": timezone, "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// The patient's photo, if one was uploaded
+    pub async fn get_patient_photo(&self, patient_did: &str) -> Result<Option<PatientPhoto>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        Ok(collection.find_one(filter, None).await?.and_then(|patient| patient.photo))
+    }
+
+    /// Replace the patient's photo. Returns false when there is no live patient with this DID
+    pub async fn set_patient_photo(&self, patient_did: &str, photo: &PatientPhoto) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        let update = doc! { "$set": { "photo": bson::to_bson(photo)?, "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    // Authenticator app (TOTP) operations
+    /// The live patient's authenticator enrollment, pending or confirmed
+    pub async fn get_totp(&self, did: &str) -> Result<Option<TotpEnrollment>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did }, false);
+        Ok(collection.find_one(filter, None).await?.and_then(|patient| patient.totp))
+    }
+
+    /// Replace a pending enrollment or start one. Returns false when there is no live patient
+    /// with this DID or their app is already confirmed.
+    pub async fn start_totp_enrollment(&self, did: &str, enrollment: &TotpEnrollment) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did, "totp.confirmed_at": null }, false);
+        let update = doc! { "$set": { "totp": bson::to_bson(enrollment)?, "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// Activate a pending enrollment with the time step of its first code
+    pub async fn confirm_totp(&self, did: &str, counter: i64) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did, "totp": { "$exists": true }, "totp.confirmed_at": null }, false);
+        let update = doc! { "$set": {
+            "totp.confirmed_at": bson::to_bson(&Utc::now())?,
+            "totp.last_counter": counter,
+            "updated_at": DateTime::now(),
+        } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// Record `counter` as used if it is later than every step accepted before. The check and
+    /// the write are one update, so a code raced in twice is only accepted once.
+    pub async fn accept_totp_counter(&self, did: &str, counter: i64) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(
+            doc! {
+                "did": did,
+                "totp.confirmed_at": { "$ne": null },
+                "$or": [{ "totp.last_counter": null }, { "totp.last_counter": { "$lt": counter } }],
+            },
+            false,
+        );
+        let update = doc! { "$set": { "totp.last_counter": counter } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Returns false when the patient had no enrollment to remove
+    pub async fn remove_totp(&self, did: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did, "totp": { "$exists": true } }, false);
+        let update = doc! { "$unset": { "totp": "" }, "$set": { "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    // Backup code operations
+    pub async fn get_backup_codes(&self, did: &str) -> Result<Vec<BackupCode>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did }, false);
+        Ok(collection.find_one(filter, None).await?.map(|patient| patient.backup_codes).unwrap_or_default())
+    }
+
+    /// Replace the whole set, used or not. Returns false when there is no live patient with this DID.
+    pub async fn replace_backup_codes(&self, did: &str, codes: &[BackupCode]) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": did }, false);
+        let update = doc! { "$set": { "backup_codes": bson::to_bson(codes)?, "updated_at": DateTime::now() } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// Mark the unused code with this hash consumed. Matching and marking are one update,
+    /// so of two requests racing with the same code only one gets true.
+    pub async fn consume_backup_code(&self, did: &str, hash: &str) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(
+            doc! { "did": did, "backup_codes": { "$elemMatch": { "hash": hash, "consumed_at": null } } },
+            false,
+        );
+        let update = doc! { "$set": { "backup_codes.$.consumed_at": bson::to_bson(&Utc::now())? } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    // Session operations
+    pub async fn create_session(&self, session: &Session) -> Result<ObjectId> {
+        let collection: Collection<Session> = self.db.collection("sessions");
+        let result = collection.insert_one(session, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_session(&self, id: ObjectId) -> Result<Option<Session>> {
+        let collection: Collection<Session> = self.db.collection("sessions");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// The DID's most recent unrevoked sessions, newest first
+    pub async fn known_sessions(&self, did: &str, limit: i64) -> Result<Vec<Session>> {
+        let collection: Collection<Session> = self.db.collection("sessions");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit).build();
+        Ok(collection.find(doc! { "did": did, "revoked_at": null }, options).await?.try_collect().await?)
+    }
+
+    /// Revoke the session whose revoke link carries this token; `None` if there is no such
+    /// session or it was already revoked
+    pub async fn revoke_session_by_token(&self, token_hash: &str) -> Result<Option<Session>> {
+        let collection: Collection<Session> = self.db.collection("sessions");
+        let update = doc! { "$set": { "revoked_at": bson::to_bson(&Utc::now())? } };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(doc! { "revoke_token_hash": token_hash, "revoked_at": null }, update, options).await?)
+    }
+
+    /// Sign out every session of `did`; returns how many were still active
+    pub async fn revoke_sessions(&self, did: &str) -> Result<u64> {
+        let collection: Collection<Session> = self.db.collection("sessions");
+        let update = doc! { "$set": { "revoked_at": bson::to_bson(&Utc::now())? } };
+        Ok(collection.update_many(doc! { "did": did, "revoked_at": null }, update, None).await?.modified_count)
+    }
+
+    // Failed sign-in tracking operations
+    pub async fn load_login_activity(&self) -> Result<Vec<LoginActivity>> {
+        let collection: Collection<LoginActivity> = self.db.collection("login_activity");
+        Ok(collection.find(None, None).await?.try_collect().await?)
+    }
+
+    /// Overwrite what was saved with the detector's current state
+    pub async fn replace_login_activity(&self, activity: &[LoginActivity]) -> Result<()> {
+        let collection: Collection<LoginActivity> = self.db.collection("login_activity");
+        collection.delete_many(doc! {}, None).await?;
+        if !activity.is_empty() {
+            collection.insert_many(activity, None).await?;
+        }
+        Ok(())
+    }
+
+    // Notification operations
+    /// Saved preferences of a live patient; `None` when the patient has not saved any or does not exist
+    pub async fn get_notification_preferences(&self, patient_did: &str) -> Result<Option<NotificationPreferences>> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        Ok(collection.find_one(filter, None).await?.and_then(|patient| patient.notification_preferences))
+    }
+
+    /// Returns false when there is no live patient with this DID
+    pub async fn set_notification_preferences(&self, patient_did: &str, preferences: &NotificationPreferences) -> Result<bool> {
+        let collection: Collection<EncryptedPatient> = self.db.collection("patients");
+        let filter = Self::scope_deleted(doc! { "did": patient_did }, false);
+        let update = doc! { "$set": {
+            "notification_preferences": bson::to_bson(preferences)?,
+            "updated_at": DateTime::now(),
+        } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    pub async fn create_notification(&self, notification: &Notification) -> Result<ObjectId> {
+        let collection: Collection<Notification> = self.db.collection("notifications");
+        let result = collection.insert_one(notification, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn update_notification_status(&self, id: ObjectId, status: NotificationStatus, error: Option<String>) -> Result<()> {
+        let collection: Collection<Notification> = self.db.collection("notifications");
+        let update = doc! { "$set": {
+            "status": bson::to_bson(&status)?,
+            "error": error,
+            "updated_at": DateTime::now(),
+        } };
+        collection.update_one(doc! { "_id": id }, update, None).await?;
+        Ok(())
+    }
+
+    // Push device operations
+
+    /// Register `device.fcm_token` for `device.did`, taking it over if another account had it:
+    /// a phone passed on to someone else keeps its token
+    pub async fn register_device(&self, device: &Device) -> Result<()> {
+        let collection: Collection<Device> = self.db.collection("devices");
+        let update = doc! {
+            "$set": {
+                "did": &device.did,
+                "platform": bson::to_bson(&device.platform)?,
+                "updated_at": DateTime::from_chrono(device.updated_at),
+            },
+            "$setOnInsert": { "created_at": DateTime::from_chrono(device.created_at) },
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        collection.update_one(doc! { "fcm_token": &device.fcm_token }, update, options).await?;
+        Ok(())
+    }
+
+    pub async fn list_devices(&self, did: &str) -> Result<Vec<Device>> {
+        let collection: Collection<Device> = self.db.collection("devices");
+        Ok(collection.find(doc! { "did": did }, None).await?.try_collect().await?)
+    }
+
+    /// Returns false when the token was not registered
+    pub async fn remove_device(&self, fcm_token: &str) -> Result<bool> {
+        let collection: Collection<Device> = self.db.collection("devices");
+        Ok(collection.delete_one(doc! { "fcm_token": fcm_token }, None).await?.deleted_count > 0)
+    }
+
+    // FHIR Bundle operations
+
+    /// Store a new bundle version for the patient and return the version number used.
+    ///
+    /// The caller's `version` is ignored: the next number is derived from the latest
+    /// stored version and the unique `(patient_did, version)` index arbitrates between
+    /// concurrent writers, the loser retrying with the following number.
+    pub async fn create_fhir_bundle(&self, bundle: &FhirBundle) -> Result<u32> {
+        const MAX_ATTEMPTS: usize = 5;
+        let collection: Collection<FhirBundle> = self.db.collection("fhir_bundles");
+
+        for _ in 0..MAX_ATTEMPTS {
+            let next_version = self.list_fhir_bundle_versions(&bundle.patient_did).await?
+                .first()
+                .map(|latest| latest.version + 1)
+                .unwrap_or(1);
+
+            let mut versioned = bundle.clone();
+            versioned.id = None;
+            versioned.version = next_version;
+            match collection.insert_one(&versioned, None).await {
+                Ok(_) => return Ok(next_version),
+                Err(e) if is_duplicate_key_error(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Could not allocate a bundle version for {} after {} attempts",
+            bundle.patient_did,
+            MAX_ATTEMPTS
+        ))
+    }
+
+    /// Latest bundle version for the patient
+    pub async fn get_fhir_bundle(&self, patient_did: &str) -> Result<Option<FhirBundle>> {
+        let collection: Collection<FhirBundle> = self.db.collection("fhir_bundles");
+        let filter = doc! { "patient_did": patient_did };
+        let options = FindOneOptions::builder().sort(doc! { "version": -1 }).build();
+        Ok(collection.find_one(filter, options).await?)
+    }
+
+    pub async fn get_fhir_bundle_version(&self, patient_did: &str, version: u32) -> Result<Option<FhirBundle>> {
+        let collection: Collection<FhirBundle> = self.db.collection("fhir_bundles");
+        let filter = doc! { "patient_did": patient_did, "version": version };
+        Ok(collection.find_one(filter, None).await?)
+    }
+
+    /// Version metadata for the patient's bundles, newest first, without the payloads
+    pub async fn list_fhir_bundle_versions(&self, patient_did: &str) -> Result<Vec<FhirBundleVersion>> {
+        let collection: Collection<FhirBundleVersion> = self.db.collection("fhir_bundles");
+        let options = FindOptions::builder()
+            .sort(doc! { "version": -1 })
+            .projection(doc! { "bundle": 0 })
+            .build();
+        let cursor = collection.find(doc! { "patient_did": patient_did }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    // Verifiable Credential operations
+    /// Store the credential, queueing `outbox` in the same transaction
+    pub async fn create_verifiable_credential(&self, credential: &VerifiableCredential, outbox: &[OutboxAction]) -> Result<()> {
+        self.insert_with_outbox("verifiable_credentials", credential, outbox).await?;
+        Ok(())
+    }
+
+    /// Save a batch and its credentials, with what they owe the outbox, in one transaction
+    pub async fn create_credential_batch(&self, batch: &CredentialBatch, credentials: &[VerifiableCredential], outbox: &[OutboxAction]) -> Result<()> {
+        let batches: Collection<CredentialBatch> = self.db.collection("credential_batches");
+        let credentials_collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let now = Utc::now();
+        let jobs = outbox
+            .iter()
+            .map(|action| Ok(Job::new(OUTBOX_JOB, serde_json::to_value(action)?, now)))
+            .collect::<Result<Vec<Job>>>()?;
+        let jobs_collection: Collection<Job> = self.db.collection("jobs");
+
+        let mut session = self.client.start_session(None).await?;
+        let mut attempt = 1;
+        loop {
+            session.start_transaction(None).await?;
+            let inserted = async {
+                batches.insert_one_with_session(batch, None, &mut session).await?;
+                credentials_collection.insert_many_with_session(credentials, None, &mut session).await?;
+                if !jobs.is_empty() {
+                    jobs_collection.insert_many_with_session(&jobs, None, &mut session).await?;
+                }
+                Ok::<_, mongodb::error::Error>(())
+            }
+            .await;
+            let result = match inserted {
+                Ok(()) => Self::commit(&mut session).await,
+                Err(e) => {
+                    // The server may have aborted it already; either way nothing was written
+                    let _ = session.abort_transaction().await;
+                    Err(e)
+                }
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < MAX_TRANSACTION_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub async fn get_credential_batch(&self, id: ObjectId) -> Result<Option<CredentialBatch>> {
+        let collection: Collection<CredentialBatch> = self.db.collection("credential_batches");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Save the ledger transaction that anchored a batch, on the batch and each of its
+    /// credentials; false if the batch already has one or is gone
+    pub async fn set_credential_batch_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        let batches: Collection<CredentialBatch> = self.db.collection("credential_batches");
+        let filter = doc! { "_id": id, "hedera_transaction_id": Bson::Null };
+        let result = batches.update_one(filter, doc! { "$set": { "hedera_transaction_id": transaction_id } }, None).await?;
+        // Credentials are written too when the batch already was, in case a run stopped in between
+        let credentials: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let filter = doc! { "batch.batch_id": id, "hedera_transaction_id": Bson::Null };
+        credentials.update_many(filter, doc! { "$set": { "hedera_transaction_id": transaction_id } }, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Save the ledger transaction that stored the credential; false if it already has one or is gone
+    pub async fn set_credential_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let filter = doc! { "_id": id, "hedera_transaction_id": Bson::Null };
+        let result = collection.update_one(filter, doc! { "$set": { "hedera_transaction_id": transaction_id } }, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    pub async fn get_credential(&self, id: ObjectId) -> Result<Option<VerifiableCredential>> {
+        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    pub async fn get_credential_by_hash(&self, ipfs_hash: &str) -> Result<Option<VerifiableCredential>> {
+        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        Ok(collection.find_one(doc! { "ipfs_hash": ipfs_hash }, None).await?)
+    }
+
+    /// Every credential issued to `subject_did`, revoked ones included, oldest first
+    pub async fn list_credentials_by_subject(&self, subject_did: &str) -> Result<Vec<VerifiableCredential>> {
+        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let options = FindOptions::builder().sort(doc! { "issued_at": 1 }).build();
+        let cursor = collection.find(doc! { "subject_did": subject_did }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Revoke a credential that is not revoked yet; false if there is none
+    pub async fn revoke_credential(&self, ipfs_hash: &str) -> Result<bool> {
+        let collection: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let filter = doc! { "ipfs_hash": ipfs_hash, "revoked_at": Bson::Null };
+        let update = doc! { "$set": { "revoked_at": bson::to_bson(&Utc::now())? } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Indexes of the revoked credentials in a status list
+    pub async fn revoked_status_indexes(&self, list_id: &str) -> Result<Vec<u32>> {
+        #[derive(Deserialize)]
+        struct Revoked {
+            status_list: StatusListEntry,
+        }
+        let collection: Collection<Revoked> = self.db.collection("verifiable_credentials");
+        let filter = doc! { "status_list.list_id": list_id, "revoked_at": { "$ne": Bson::Null } };
+        let options = FindOptions::builder().projection(doc! { "status_list": 1 }).build();
+        let revoked: Vec<Revoked> = collection.find(filter, options).await?.try_collect().await?;
+        Ok(revoked.into_iter().map(|credential| credential.status_list.index).collect())
+    }
+
+    // Trusted issuer operations
+    /// A DID that is already registered fails with `DatabaseError::DuplicateKey`
+    pub async fn create_issuer(&self, issuer: &TrustedIssuer) -> Result<ObjectId> {
+        let collection: Collection<TrustedIssuer> = self.db.collection("issuers");
+        match collection.insert_one(issuer, None).await {
+            Ok(result) => Ok(result.inserted_id.as_object_id().unwrap()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn get_issuer(&self, did: &str) -> Result<Option<TrustedIssuer>> {
+        let collection: Collection<TrustedIssuer> = self.db.collection("issuers");
+        Ok(collection.find_one(doc! { "did": did }, None).await?)
+    }
+
+    /// Every issuer, suspended ones included, by name
+    pub async fn list_issuers(&self) -> Result<Vec<TrustedIssuer>> {
+        let collection: Collection<TrustedIssuer> = self.db.collection("issuers");
+        let options = FindOptions::builder().sort(doc! { "display_name": 1 }).build();
+        Ok(collection.find(None, options).await?.try_collect().await?)
+    }
+
+    /// Save the issuer's name, types and status; `None` if the DID is not registered
+    pub async fn update_issuer(&self, issuer: &TrustedIssuer) -> Result<Option<TrustedIssuer>> {
+        let collection: Collection<TrustedIssuer> = self.db.collection("issuers");
+        let update = doc! {
+            "$set": {
+                "display_name": &issuer.display_name,
+                "credential_types": &issuer.credential_types,
+                "status": bson::to_bson(&issuer.status)?,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            },
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(doc! { "did": &issuer.did }, update, options).await?)
+    }
+
+    // Credential status list operations
+    /// Hand out the next free index of a status list with `list_size` bits, opening a new list
+    /// once every list is full. The unique `list_id` index arbitrates between concurrent openers.
+    pub async fn allocate_status_index(&self, list_size: u32) -> Result<StatusListEntry> {
+        const MAX_ATTEMPTS: usize = 5;
+        let collection: Collection<CredentialStatusList> = self.db.collection("credential_status_lists");
+
+        for _ in 0..MAX_ATTEMPTS {
+            let filter = doc! { "next_index": { "$lt": list_size } };
+            let update = doc! { "$inc": { "next_index": 1 } };
+            let options = FindOneAndUpdateOptions::builder().sort(doc! { "_id": 1 }).return_document(ReturnDocument::Before).build();
+            if let Some(list) = collection.find_one_and_update(filter, update, options).await? {
+                return Ok(StatusListEntry { list_id: list.list_id, index: list.next_index });
+            }
+
+            let list_id = (collection.count_documents(None, None).await? + 1).to_string();
+            // Stale from the start, so the publisher puts the empty list out before anyone asks
+            let list = CredentialStatusList {
+                id: None,
+                list_id: list_id.clone(),
+                next_index: 1,
+                revision: 1,
+                published_revision: 0,
+                document: None,
+                ipfs_hash: None,
+                published_at: None,
+                created_at: Utc::now(),
+            };
+            match collection.insert_one(&list, None).await {
+                Ok(_) => return Ok(StatusListEntry { list_id, index: 0 }),
+                Err(e) if is_duplicate_key_error(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(anyhow::anyhow!("Could not allocate a credential status index after {} attempts", MAX_ATTEMPTS))
+    }
+
+    pub async fn get_status_list(&self, list_id: &str) -> Result<Option<CredentialStatusList>> {
+        let collection: Collection<CredentialStatusList> = self.db.collection("credential_status_lists");
+        Ok(collection.find_one(doc! { "list_id": list_id }, None).await?)
+    }
+
+    /// Note that the list changed and needs publishing again
+    pub async fn mark_status_list_stale(&self, list_id: &str) -> Result<()> {
+        let collection: Collection<CredentialStatusList> = self.db.collection("credential_status_lists");
+        collection.update_one(doc! { "list_id": list_id }, doc! { "$inc": { "revision": 1 } }, None).await?;
+        Ok(())
+    }
+
+    /// Lists changed since their last published document
+    pub async fn stale_status_lists(&self) -> Result<Vec<CredentialStatusList>> {
+        let collection: Collection<CredentialStatusList> = self.db.collection("credential_status_lists");
+        let filter = doc! { "$expr": { "$gt": ["$revision", "$published_revision"] } };
+        Ok(collection.find(filter, None).await?.try_collect().await?)
+    }
+
+    /// Record the document published for `revision`, unless one at least as recent already was.
+    /// Returns the list as it was before, or `None` if this document lost to a newer one.
+    pub async fn publish_status_list(&self, list_id: &str, revision: u64, document: &str, ipfs_hash: &str) -> Result<Option<CredentialStatusList>> {
+        let collection: Collection<CredentialStatusList> = self.db.collection("credential_status_lists");
+        let filter = doc! { "list_id": list_id, "published_revision": { "$lt": revision as i64 } };
+        let update = doc! {
+            "$set": {
+                "published_revision": revision as i64,
+                "document": document,
+                "ipfs_hash": ipfs_hash,
+                "published_at": bson::to_bson(&Utc::now())?,
+            },
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::Before).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    // Audit Log operations
+    pub async fn create_audit_log(&self, log: &AuditLog) -> Result<()> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
         collection.insert_one(log, None).await?;
         Ok(())
     }
 
-    pub async fn get_unanchored_audit_logs(&self) -> Result<Vec<AuditLog>> {
-        let collection: Collection<AuditLog> = self.db.collection("audit_logs");
-        let filter = doc! { "is_anchored": false };
+    pub async fn get_unanchored_audit_logs(&self) -> Result<Vec<AuditLog>> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let filter = doc! { "is_anchored": false };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn count_unanchored_audit_logs(&self) -> Result<u64> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        Ok(collection.count_documents(doc! { "is_anchored": false }, None).await?)
+    }
+
+    pub async fn mark_logs_as_anchored(&self, log_ids: &[ObjectId], anchor_batch_id: ObjectId) -> Result<()> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let filter = doc! { "_id": { "$in": log_ids } };
+        let update = doc! { "$set": { 
+            "is_anchored": true, 
+            "anchor_batch_id": anchor_batch_id 
+        } };
+        collection.update_many(filter, update, None).await?;
+        Ok(())
+    }
+
+    pub async fn get_audit_logs_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<AuditLog>> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let cursor = collection.find(doc! { "_id": { "$in": ids } }, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// The audit trail of `did`, oldest first
+    pub async fn list_audit_logs_by_did(&self, did: &str) -> Result<Vec<AuditLog>> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let options = FindOptions::builder().sort(doc! { "timestamp": 1 }).build();
+        let cursor = collection.find(doc! { "did": did }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Logs in `[from, to)` whose action is one of `actions` or starts with one of
+    /// `action_prefixes`, oldest first
+    pub async fn audit_logs_in_period(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+        actions: &[&str],
+        action_prefixes: &[&str],
+    ) -> Result<Vec<AuditLog>> {
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let mut matches = vec![doc! { "action": { "$in": actions } }];
+        matches.extend(action_prefixes.iter().map(|prefix| doc! { "action": { "$regex": format!("^{}", regex::escape(prefix)) } }));
+        let mut filter = doc! { "$or": matches };
+        Self::insert_time_range(&mut filter, "timestamp", Some(from), Some(to))?;
+        let options = FindOptions::builder().sort(doc! { "timestamp": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// Batches created in `[from, to)`, oldest first
+    pub async fn anchor_batches_in_period(&self, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>) -> Result<Vec<AnchorBatch>> {
+        let collection: Collection<AnchorBatch> = self.db.collection("anchor_batches");
+        let mut filter = Document::new();
+        Self::insert_time_range(&mut filter, "created_at", Some(from), Some(to))?;
+        let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    pub async fn get_anchor_batch(&self, id: ObjectId) -> Result<Option<AnchorBatch>> {
+        let collection: Collection<AnchorBatch> = self.db.collection("anchor_batches");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// The state of audit anchoring after the latest run; None before the first
+    pub async fn get_anchoring_health(&self) -> Result<Option<AnchoringHealth>> {
+        let collection: Collection<AnchoringHealth> = self.db.collection("anchoring_health");
+        Ok(collection.find_one(doc! { "_id": ANCHORING_HEALTH_ID }, None).await?)
+    }
+
+    /// Replace the state of audit anchoring, creating it after the first run
+    pub async fn save_anchoring_health(&self, health: &AnchoringHealth) -> Result<()> {
+        let collection: Collection<AnchoringHealth> = self.db.collection("anchoring_health");
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection.replace_one(doc! { "_id": ANCHORING_HEALTH_ID }, health, options).await?;
+        Ok(())
+    }
+
+    /// Record the intent to anchor a batch; `false` when a batch with the same Merkle root exists
+    pub async fn create_anchor_batch(&self, batch: &AnchorBatch) -> Result<bool> {
+        let collection: Collection<AnchorBatch> = self.db.collection("anchor_batches");
+        match collection.insert_one(batch, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Batches an earlier run did not finish, oldest first
+    pub async fn unfinished_anchor_batches(&self) -> Result<Vec<AnchorBatch>> {
+        let collection: Collection<AnchorBatch> = self.db.collection("anchor_batches");
+        let filter = doc! { "status": { "$ne": bson::to_bson(&AnchorBatchStatus::Completed)? } };
+        let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// Move a batch to `status`, keeping the transaction id once it is known
+    pub async fn update_anchor_batch(&self, id: ObjectId, status: AnchorBatchStatus, transaction_id: Option<&str>) -> Result<()> {
+        let collection: Collection<AnchorBatch> = self.db.collection("anchor_batches");
+        let mut set = doc! { "status": bson::to_bson(&status)?, "updated_at": bson::to_bson(&Utc::now())? };
+        if let Some(transaction_id) = transaction_id {
+            set.insert("transaction_id", transaction_id);
+        }
+        collection.update_one(doc! { "_id": id }, doc! { "$set": set }, None).await?;
+        Ok(())
+    }
+
+    /// Audit activity in `[from, to)`, counted by `group_by`, with the `top` busiest DIDs,
+    /// the anchored/unanchored split and a per-day series of `security_actions`
+    pub async fn audit_stats(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+        group_by: AuditGroupBy,
+        top: i64,
+        security_actions: &[&str],
+    ) -> Result<AuditStats> {
+        /// Grouping by DID can produce a row per user; the dashboard only shows the busiest
+        const MAX_GROUPS: i64 = 1000;
+
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(rename = "_id")]
+            key: String,
+            count: u64,
+        }
+
+        #[derive(Deserialize)]
+        struct SeriesRow {
+            #[serde(rename = "_id")]
+            key: SeriesKey,
+            count: u64,
+        }
+
+        #[derive(Deserialize)]
+        struct SeriesKey {
+            day: String,
+            action: String,
+        }
+
+        #[derive(Deserialize, Default)]
+        struct AuditFacets {
+            groups: Vec<Row>,
+            top_actors: Vec<Row>,
+            anchoring: Vec<AnchoringRow>,
+            security: Vec<SeriesRow>,
+        }
+
+        #[derive(Deserialize)]
+        struct AnchoringRow {
+            #[serde(rename = "_id")]
+            anchored: bool,
+            count: u64,
+        }
+
+        let collection: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        let mut filter = Document::new();
+        Self::insert_time_range(&mut filter, "timestamp", Some(from), Some(to))?;
+        // Timestamps are stored as RFC 3339 strings in UTC, so the date is the first ten characters
+        let day = doc! { "$substrBytes": ["$timestamp", 0, 10] };
+        let field = if group_by == AuditGroupBy::Action { "$action" } else { "$did" };
+        let groups = match group_by {
+            AuditGroupBy::Day => vec![
+                doc! { "$group": { "_id": day.clone(), "count": { "$sum": 1 } } },
+                doc! { "$sort": { "_id": 1 } },
+            ],
+            AuditGroupBy::Action | AuditGroupBy::Did => vec![
+                doc! { "$group": { "_id": field, "count": { "$sum": 1 } } },
+                doc! { "$sort": { "count": -1, "_id": 1 } },
+                doc! { "$limit": MAX_GROUPS },
+            ],
+        };
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$facet": {
+                "groups": groups,
+                "top_actors": [
+                    { "$group": { "_id": "$did", "count": { "$sum": 1 } } },
+                    { "$sort": { "count": -1, "_id": 1 } },
+                    { "$limit": top },
+                ],
+                "anchoring": [
+                    { "$group": { "_id": { "$eq": ["$is_anchored", true] }, "count": { "$sum": 1 } } },
+                ],
+                "security": [
+                    { "$match": { "action": { "$in": security_actions.to_vec() } } },
+                    { "$group": { "_id": { "day": day, "action": "$action" }, "count": { "$sum": 1 } } },
+                    { "$sort": { "_id.day": 1, "_id.action": 1 } },
+                ],
+            } },
+        ];
+
+        let options = AggregateOptions::builder().hint(Hint::Keys(doc! { "timestamp": -1 })).build();
+        let mut cursor = collection.aggregate(pipeline, options).await?;
+        let facets: AuditFacets = match cursor.try_next().await? {
+            Some(document) => bson::from_document(document)?,
+            None => AuditFacets::default(),
+        };
+        let count = |anchored: bool| facets.anchoring.iter().filter(|row| row.anchored == anchored).map(|row| row.count).sum();
+        Ok(AuditStats {
+            from,
+            to,
+            group_by,
+            anchored: count(true),
+            unanchored: count(false),
+            groups: facets.groups.into_iter().map(|row| AuditCount { key: row.key, count: row.count }).collect(),
+            top_actors: facets.top_actors.into_iter().map(|row| AuditCount { key: row.key, count: row.count }).collect(),
+            security: facets
+                .security
+                .into_iter()
+                .map(|row| AuditSeriesPoint { day: row.key.day, action: row.key.action, count: row.count })
+                .collect(),
+        })
+    }
+
+    // Hedera cost operations
+    pub async fn record_hedera_transaction(&self, transaction: &HederaTransaction) -> Result<()> {
+        let collection: Collection<HederaTransaction> = self.db.collection("hedera_transactions");
+        collection.insert_one(transaction, None).await?;
+        Ok(())
+    }
+
+    /// Transactions recorded in `[from, to)`, oldest first
+    pub async fn hedera_transactions(&self, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>) -> Result<Vec<HederaTransaction>> {
+        let collection: Collection<HederaTransaction> = self.db.collection("hedera_transactions");
+        let mut filter = doc! {};
+        Self::insert_time_range(&mut filter, "recorded_at", Some(from), Some(to))?;
+        let options = FindOptions::builder().sort(doc! { "recorded_at": 1 }).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// Save the alert for its month; `false` when admins were already warned this month
+    pub async fn claim_hedera_budget_alert(&self, alert: &HederaBudgetAlert) -> Result<bool> {
+        let collection: Collection<HederaBudgetAlert> = self.db.collection("hedera_budget_alerts");
+        match collection.insert_one(alert, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // SMS send counter operations
+    /// Count one more code in `counter`'s window unless `limit` were already sent in it. The
+    /// check and the increment are one findAndModify, so concurrent sends cannot both take the
+    /// last one; once the counter is full the upsert collides with it on `_id` instead.
+    pub async fn count_sms_send(&self, counter: &SmsSendCounter, limit: u32) -> Result<Option<u32>> {
+        let collection: Collection<SmsSendCounter> = self.db.collection("sms_sends");
+        let filter = doc! { "_id": &counter.key, "count": { "$lt": i64::from(limit) } };
+        let mut on_insert = doc! { "expires_at": DateTime::from_chrono(counter.expires_at) };
+        if let Some(day) = &counter.day {
+            on_insert.insert("day", day);
+        }
+        let update = doc! { "$inc": { "count": 1 }, "$setOnInsert": on_insert };
+        let upsert = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+        match collection.find_one_and_update(filter.clone(), update.clone(), upsert).await {
+            Ok(counted) => Ok(counted.map(|counter| counter.count)),
+            // A full counter, or another send creating the counter at the same moment
+            Err(e) if is_duplicate_key_error(&e) => {
+                let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+                Ok(collection.find_one_and_update(filter, update, options).await?.map(|counter| counter.count))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Give back a send counted before a later limit refused it
+    pub async fn uncount_sms_send(&self, key: &str) -> Result<()> {
+        let collection: Collection<SmsSendCounter> = self.db.collection("sms_sends");
+        collection.update_one(doc! { "_id": key, "count": { "$gt": 0 } }, doc! { "$inc": { "count": -1 } }, None).await?;
+        Ok(())
+    }
+
+    pub async fn daily_sms_sends(&self, since_day: &str) -> Result<Vec<SmsSendCounter>> {
+        let collection: Collection<SmsSendCounter> = self.db.collection("sms_sends");
+        let options = FindOptions::builder().sort(doc! { "day": -1 }).build();
+        Ok(collection.find(doc! { "day": { "$gte": since_day } }, options).await?.try_collect().await?)
+    }
+
+    /// Whether this call is the first to report `day`'s SMS budget used up
+    pub async fn claim_sms_budget_alert(&self, day: &str, expires_at: chrono::DateTime<Utc>) -> Result<bool> {
+        let collection: Collection<SmsSendCounter> = self.db.collection("sms_sends");
+        let alert = SmsSendCounter { key: format!("budget-alert:{}", day), count: 0, day: None, expires_at };
+        match collection.insert_one(alert, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Re-encryption job operations
+    /// Fails with `DatabaseError::DuplicateKey` while another job is running
+    pub async fn create_reencryption_job(&self, job: &ReencryptionJob) -> Result<ObjectId> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        match collection.insert_one(job, None).await {
+            Ok(result) => Ok(result.inserted_id.as_object_id().unwrap()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn get_reencryption_job(&self, id: ObjectId) -> Result<Option<ReencryptionJob>> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Jobs, newest first
+    pub async fn list_reencryption_jobs(&self) -> Result<Vec<ReencryptionJob>> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(None, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Take over the running job if its runner's lease has lapsed, holding it until `lease_until`;
+    /// `None` if there is no running job or its runner is still alive
+    pub async fn claim_reencryption_job(&self, lease_until: chrono::DateTime<Utc>) -> Result<Option<ReencryptionJob>> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        let filter = doc! {
+            "status": bson::to_bson(&ReencryptionJobStatus::Running)?,
+            "lease_until": { "$lte": DateTime::now() },
+        };
+        let update = doc! { "$set": { "lease_until": DateTime::from_chrono(lease_until) } };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    /// Whether a job is running at all, lapsed or not
+    pub async fn running_reencryption_job(&self) -> Result<Option<ReencryptionJob>> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        Ok(collection.find_one(doc! { "status": bson::to_bson(&ReencryptionJobStatus::Running)? }, None).await?)
+    }
+
+    /// Save a finished page and extend the lease held until `lease_until` to `renewed_until`.
+    /// Only the most recent `MAX_JOB_FAILURES` failures are kept. `false` if the lease was lost.
+    pub async fn record_reencryption_progress(
+        &self,
+        id: ObjectId,
+        lease_until: chrono::DateTime<Utc>,
+        renewed_until: chrono::DateTime<Utc>,
+        resume_after: ObjectId,
+        reencrypted: u64,
+        failures: &[ReencryptionFailure],
+    ) -> Result<bool> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        let filter = doc! { "_id": id, "lease_until": DateTime::from_chrono(lease_until) };
+        let update = doc! {
+            "$set": { "resume_after": resume_after, "lease_until": DateTime::from_chrono(renewed_until), "updated_at": bson::to_bson(&Utc::now())? },
+            "$inc": { "reencrypted": reencrypted as i64, "failed": failures.len() as i64 },
+            "$push": { "failures": { "$each": bson::to_bson(failures)?, "$slice": -MAX_JOB_FAILURES } },
+        };
+        let result = collection.update_one(filter, update, None).await?;
+        Ok(result.modified_count > 0)
+    }
+
+    /// Mark the job completed, if the lease held until `lease_until` is still ours
+    pub async fn complete_reencryption_job(&self, id: ObjectId, lease_until: chrono::DateTime<Utc>) -> Result<Option<ReencryptionJob>> {
+        let collection: Collection<ReencryptionJob> = self.db.collection("reencryption_jobs");
+        let filter = doc! { "_id": id, "lease_until": DateTime::from_chrono(lease_until) };
+        let now = bson::to_bson(&Utc::now())?;
+        let update = doc! {
+            "$set": { "status": bson::to_bson(&ReencryptionJobStatus::Completed)?, "completed_at": now.clone(), "updated_at": now },
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    // Patient export operations
+    /// Fails with `DatabaseError::DuplicateKey` while the patient has another export pending
+    pub async fn create_patient_export(&self, export: &PatientExport) -> Result<ObjectId> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        match collection.insert_one(export, None).await {
+            Ok(result) => Ok(result.inserted_id.as_object_id().unwrap()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn get_patient_export(&self, id: ObjectId) -> Result<Option<PatientExport>> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    pub async fn pending_patient_export(&self, patient_did: &str) -> Result<Option<PatientExport>> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        let filter = doc! { "patient_did": patient_did, "status": bson::to_bson(&PatientExportStatus::Pending)? };
+        Ok(collection.find_one(filter, None).await?)
+    }
+
+    /// Mark a pending export ready with its archive, kept until `expires_at`; false if it is no longer pending
+    pub async fn complete_patient_export(&self, id: ObjectId, archive: &ExportArchive, expires_at: chrono::DateTime<Utc>) -> Result<bool> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        let filter = doc! { "_id": id, "status": bson::to_bson(&PatientExportStatus::Pending)? };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&PatientExportStatus::Ready)?,
+                "archive": bson::to_bson(archive)?,
+                "completed_at": bson::to_bson(&Utc::now())?,
+                "expires_at": DateTime::from_chrono(expires_at),
+            },
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Mark a pending export failed; false if it is no longer pending
+    pub async fn fail_patient_export(&self, id: ObjectId, error: &str) -> Result<bool> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        let filter = doc! { "_id": id, "status": bson::to_bson(&PatientExportStatus::Pending)? };
+        let update = doc! {
+            "$set": { "status": bson::to_bson(&PatientExportStatus::Failed)?, "error": error, "completed_at": bson::to_bson(&Utc::now())? },
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Pending and ready exports whose retention ended by `now`, oldest first
+    pub async fn expired_patient_exports(&self, now: chrono::DateTime<Utc>, limit: i64) -> Result<Vec<PatientExport>> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        let statuses = [PatientExportStatus::Pending, PatientExportStatus::Ready].iter().map(bson::to_bson).collect::<Result<Vec<_>, _>>()?;
+        let filter = doc! { "status": { "$in": statuses }, "expires_at": { "$lte": DateTime::from_chrono(now) } };
+        let options = FindOptions::builder().sort(doc! { "expires_at": 1 }).limit(limit).build();
+        Ok(collection.find(filter, options).await?.try_collect().await?)
+    }
+
+    /// Mark an export whose archive was deleted as expired; false if it already was
+    pub async fn expire_patient_export(&self, id: ObjectId) -> Result<bool> {
+        let collection: Collection<PatientExport> = self.db.collection("patient_exports");
+        let filter = doc! { "_id": id, "status": { "$ne": bson::to_bson(&PatientExportStatus::Expired)? } };
+        let update = doc! { "$set": { "status": bson::to_bson(&PatientExportStatus::Expired)? } };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    // Compliance report operations
+    pub async fn create_compliance_report(&self, report: &ComplianceReport) -> Result<ObjectId> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        let result = collection.insert_one(report, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_compliance_report(&self, id: ObjectId) -> Result<Option<ComplianceReport>> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// The most recent reports, newest first
+    pub async fn list_compliance_reports(&self, limit: i64) -> Result<Vec<ComplianceReport>> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(limit).build();
+        Ok(collection.find(None, options).await?.try_collect().await?)
+    }
+
+    /// Record how far generation got; a report that already finished is left alone
+    pub async fn record_compliance_report_progress(&self, id: ObjectId, progress: u8, stage: &str) -> Result<bool> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        let unfinished = [ComplianceReportStatus::Pending, ComplianceReportStatus::Running].iter().map(bson::to_bson).collect::<Result<Vec<_>, _>>()?;
+        let filter = doc! { "_id": id, "status": { "$in": unfinished } };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&ComplianceReportStatus::Running)?,
+                "progress": i32::from(progress),
+                "stage": stage,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            },
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Mark a report completed with its stored files; false if it had already finished
+    pub async fn complete_compliance_report(&self, id: ObjectId, artifacts: &ComplianceReportArtifacts, anchors_valid: bool) -> Result<bool> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        let unfinished = [ComplianceReportStatus::Pending, ComplianceReportStatus::Running].iter().map(bson::to_bson).collect::<Result<Vec<_>, _>>()?;
+        let filter = doc! { "_id": id, "status": { "$in": unfinished } };
+        let now = bson::to_bson(&Utc::now())?;
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&ComplianceReportStatus::Completed)?,
+                "progress": 100,
+                "artifacts": bson::to_bson(artifacts)?,
+                "anchors_valid": anchors_valid,
+                "updated_at": now.clone(),
+                "completed_at": now,
+            },
+            "$unset": { "stage": "" },
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    /// Mark a report failed; false if it had already finished
+    pub async fn fail_compliance_report(&self, id: ObjectId, error: &str) -> Result<bool> {
+        let collection: Collection<ComplianceReport> = self.db.collection("compliance_reports");
+        let unfinished = [ComplianceReportStatus::Pending, ComplianceReportStatus::Running].iter().map(bson::to_bson).collect::<Result<Vec<_>, _>>()?;
+        let filter = doc! { "_id": id, "status": { "$in": unfinished } };
+        let now = bson::to_bson(&Utc::now())?;
+        let update = doc! {
+            "$set": { "status": bson::to_bson(&ComplianceReportStatus::Failed)?, "error": error, "updated_at": now.clone(), "completed_at": now },
+            "$unset": { "stage": "" },
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    // Outbox operations: each action is an `outbox` job
+    /// Insert `document` and queue `outbox` in one transaction, so a write never commits
+    /// without the external calls it owes, nor those calls without it. Transactions need a
+    /// replica set; with nothing to queue this is a plain insert.
+    async fn insert_with_outbox<T: Serialize + Send + Sync>(&self, collection: &str, document: &T, outbox: &[OutboxAction]) -> Result<ObjectId> {
+        let collection: Collection<T> = self.db.collection(collection);
+        if outbox.is_empty() {
+            let result = collection.insert_one(document, None).await?;
+            return Ok(result.inserted_id.as_object_id().unwrap());
+        }
+        let now = Utc::now();
+        let jobs = outbox
+            .iter()
+            .map(|action| Ok(Job::new(OUTBOX_JOB, serde_json::to_value(action)?, now)))
+            .collect::<Result<Vec<Job>>>()?;
+        let jobs_collection: Collection<Job> = self.db.collection("jobs");
+
+        let mut session = self.client.start_session(None).await?;
+        let mut attempt = 1;
+        loop {
+            session.start_transaction(None).await?;
+            let inserted = match collection.insert_one_with_session(document, None, &mut session).await {
+                Ok(result) => jobs_collection.insert_many_with_session(&jobs, None, &mut session).await.map(|_| result.inserted_id),
+                Err(e) => Err(e),
+            };
+            let result = match inserted {
+                Ok(id) => Self::commit(&mut session).await.map(|()| id),
+                Err(e) => {
+                    // The server may have aborted it already; either way nothing was written
+                    let _ = session.abort_transaction().await;
+                    Err(e)
+                }
+            };
+            match result {
+                Ok(id) => return Ok(id.as_object_id().unwrap()),
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < MAX_TRANSACTION_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Commit, retrying while the server cannot tell whether the commit went through
+    async fn commit(session: &mut ClientSession) -> mongodb::error::Result<()> {
+        let mut attempt = 1;
+        loop {
+            match session.commit_transaction().await {
+                Err(e) if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) && attempt < MAX_TRANSACTION_ATTEMPTS => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    // Background job operations
+    pub async fn enqueue_job(&self, job: &Job) -> Result<ObjectId> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        let result = collection.insert_one(job, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    /// Insert a recurring job unless one with its `unique_key` already exists; returns whether it was inserted
+    pub async fn ensure_recurring_job(&self, job: &Job) -> Result<bool> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        match collection.insert_one(job, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Take the next due job of one of `job_types` for `worker`: a pending job whose `run_at`
+    /// has come, or a running one whose worker let the lock lapse. The attempt is counted here,
+    /// so a job that keeps crashing its worker still runs out of attempts.
+    pub async fn claim_job(&self, job_types: &[String], worker: &str, now: chrono::DateTime<Utc>, lease: Duration) -> Result<Option<Job>> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        let now_bson = DateTime::from_chrono(now);
+        let filter = doc! {
+            "job_type": { "$in": job_types.to_vec() },
+            "$or": [
+                { "status": bson::to_bson(&JobStatus::Pending)?, "run_at": { "$lte": now_bson } },
+                { "status": bson::to_bson(&JobStatus::Running)?, "locked_until": { "$lte": now_bson } },
+            ],
+        };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&JobStatus::Running)?,
+                "locked_by": worker,
+                "locked_until": DateTime::from_chrono(now + lease),
+                "updated_at": bson::to_bson(&now)?,
+            },
+            "$inc": { "attempts": 1 },
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "run_at": 1 })
+            .return_document(ReturnDocument::After)
+            .build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    /// The updates below only apply while `worker` still holds the job: once its lock lapsed
+    /// and another worker claimed it, they return false and change nothing
+    pub async fn complete_job(&self, id: ObjectId, worker: &str) -> Result<bool> {
+        self.release_job(id, worker, doc! { "status": bson::to_bson(&JobStatus::Succeeded)?, "last_error": Bson::Null }).await
+    }
+
+    /// Back to pending until `run_at`. With an `error` the attempt failed and is kept;
+    /// without one a recurring job finished and starts its next run with a clean count.
+    pub async fn reschedule_job(&self, id: ObjectId, worker: &str, run_at: chrono::DateTime<Utc>, error: Option<&str>) -> Result<bool> {
+        let mut fields = doc! { "status": bson::to_bson(&JobStatus::Pending)?, "run_at": DateTime::from_chrono(run_at), "last_error": error };
+        if error.is_none() {
+            fields.insert("attempts", 0);
+        }
+        self.release_job(id, worker, fields).await
+    }
+
+    pub async fn bury_job(&self, id: ObjectId, worker: &str, error: &str) -> Result<bool> {
+        self.release_job(id, worker, doc! { "status": bson::to_bson(&JobStatus::Dead)?, "last_error": error }).await
+    }
+
+    async fn release_job(&self, id: ObjectId, worker: &str, mut fields: Document) -> Result<bool> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        fields.insert("locked_by", Bson::Null);
+        fields.insert("updated_at", bson::to_bson(&Utc::now())?);
+        let filter = doc! { "_id": id, "locked_by": worker, "status": bson::to_bson(&JobStatus::Running)? };
+        Ok(collection.update_one(filter, doc! { "$set": fields }, None).await?.modified_count > 0)
+    }
+
+    /// Put a job that is not running back in the queue for immediate delivery with its attempts reset;
+    /// false if there is no such job (of `job_type`, when given)
+    pub async fn requeue_job(&self, id: ObjectId, job_type: Option<&str>) -> Result<bool> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        let mut filter = doc! { "_id": id, "status": { "$ne": bson::to_bson(&JobStatus::Running)? } };
+        if let Some(job_type) = job_type {
+            filter.insert("job_type", job_type);
+        }
+        let update = doc! { "$set": {
+            "status": bson::to_bson(&JobStatus::Pending)?,
+            "attempts": 0,
+            "run_at": DateTime::now(),
+            "updated_at": bson::to_bson(&Utc::now())?,
+        } };
+        Ok(collection.update_one(filter, update, None).await?.matched_count > 0)
+    }
+
+    /// One page of jobs, next due first
+    pub async fn list_jobs(&self, status: Option<JobStatus>, job_type: Option<&str>, skip: u64, limit: i64) -> Result<(Vec<Job>, u64)> {
+        let collection: Collection<Job> = self.db.collection("jobs");
+        let mut filter = doc! {};
+        if let Some(status) = status {
+            filter.insert("status", bson::to_bson(&status)?);
+        }
+        if let Some(job_type) = job_type {
+            filter.insert("job_type", job_type);
+        }
+        let total = collection.count_documents(filter.clone(), None).await?;
+        let options = FindOptions::builder().sort(doc! { "run_at": -1 }).skip(skip).limit(limit).build();
+        Ok((collection.find(filter, options).await?.try_collect().await?, total))
+    }
+
+    pub async fn count_jobs(&self, job_type: Option<&str>) -> Result<JobCounts> {
+        #[derive(Deserialize)]
+        struct StatusCount {
+            #[serde(rename = "_id")]
+            status: JobStatus,
+            count: u64,
+        }
+
+        let collection: Collection<Job> = self.db.collection("jobs");
+        let filter = job_type.map_or_else(Document::new, |job_type| doc! { "job_type": job_type });
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$group": { "_id": "$status", "count": { "$sum": 1 } } },
+        ];
+        let mut by_status = collection.aggregate(pipeline, None).await?;
+        let mut counts = JobCounts::default();
+        while let Some(document) = by_status.try_next().await? {
+            let StatusCount { status, count } = bson::from_document(document)?;
+            match status {
+                JobStatus::Pending => counts.pending = count,
+                JobStatus::Running => counts.running = count,
+                JobStatus::Succeeded => counts.succeeded = count,
+                JobStatus::Dead => counts.dead = count,
+            }
+        }
+        Ok(counts)
+    }
+
+    // Email outbox operations: each email is a `send_email` job
+    pub async fn enqueue_email(&self, email: &OutboxEmail) -> Result<ObjectId> {
+        self.enqueue_job(&Job::new(SEND_EMAIL_JOB, serde_json::to_value(email)?, Utc::now())).await
+    }
+
+    /// Put an email back in the queue for immediate delivery; false if no such email exists or it is being sent
+    pub async fn retry_email(&self, id: ObjectId) -> Result<bool> {
+        self.requeue_job(id, Some(SEND_EMAIL_JOB)).await
+    }
+
+    pub async fn count_emails_by_status(&self) -> Result<OutboxStats> {
+        let counts = self.count_jobs(Some(SEND_EMAIL_JOB)).await?;
+        Ok(OutboxStats { pending: counts.pending + counts.running, sent: counts.succeeded, failed_permanent: counts.dead })
+    }
+
+    // Webhook operations
+    pub async fn create_webhook(&self, webhook: &Webhook) -> Result<ObjectId> {
+        let collection: ScopedCollection<Webhook> = self.scoped("webhooks");
+        let result = collection.insert_one(webhook, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_webhook(&self, id: ObjectId) -> Result<Option<Webhook>> {
+        let collection: ScopedCollection<Webhook> = self.scoped("webhooks");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Every webhook, inactive ones included, oldest first
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>> {
+        let collection: ScopedCollection<Webhook> = self.scoped("webhooks");
+        let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+        let cursor = collection.find(doc! {}, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Replace where the webhook is sent and what; `false` if no webhook has that id
+    pub async fn update_webhook(&self, id: ObjectId, url: &str, events: &[WebhookEventType], active: bool) -> Result<bool> {
+        let collection: ScopedCollection<Webhook> = self.scoped("webhooks");
+        let update = doc! {
+            "$set": {
+                "url": url,
+                "events": bson::to_bson(events)?,
+                "active": active,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            }
+        };
+        Ok(collection.update_one(doc! { "_id": id }, update, None).await?.matched_count > 0)
+    }
+
+    pub async fn delete_webhook(&self, id: ObjectId) -> Result<bool> {
+        let collection: ScopedCollection<Webhook> = self.scoped("webhooks");
+        Ok(collection.delete_one(doc! { "_id": id }, None).await?.deleted_count > 0)
+    }
+
+    /// The active webhooks of `tenant` subscribed to `event_type`. Events are routed by the
+    /// tenant they belong to, not the caller's, so this looks past the request's scope.
+    pub async fn subscribed_webhooks(&self, tenant: &str, event_type: WebhookEventType) -> Result<Vec<Webhook>> {
+        let collection: Collection<Webhook> = self.db.collection("webhooks");
+        let filter = doc! { "$and": [tenant_filter(tenant), { "events": bson::to_bson(&event_type)?, "active": true }] };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Queue the delivery of an event to one webhook as a `deliver_webhook` job
+    pub async fn enqueue_webhook_delivery(&self, delivery: &WebhookDeliveryJob) -> Result<ObjectId> {
+        self.enqueue_job(&Job::new(DELIVER_WEBHOOK_JOB, serde_json::to_value(delivery)?, Utc::now())).await
+    }
+
+    pub async fn record_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<()> {
+        let collection: Collection<WebhookDelivery> = self.db.collection("webhook_deliveries");
+        collection.insert_one(delivery, None).await?;
+        Ok(())
+    }
+
+    /// The latest `limit` delivery attempts to the webhook, newest first
+    pub async fn list_webhook_deliveries(&self, webhook_id: ObjectId, limit: i64) -> Result<Vec<WebhookDelivery>> {
+        let collection: Collection<WebhookDelivery> = self.db.collection("webhook_deliveries");
+        let options = FindOptions::builder().sort(doc! { "attempted_at": -1 }).limit(limit).build();
+        let cursor = collection.find(doc! { "webhook_id": webhook_id }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    // Notification feed operations
+    pub async fn record_feed_event(&self, event: &FeedEvent) -> Result<ObjectId> {
+        let collection: Collection<FeedEvent> = self.db.collection("notification_events");
+        let result = collection.insert_one(event, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    /// Up to `limit` unexpired events for `recipient_did` that came after `after`, oldest first
+    pub async fn feed_events_after(&self, recipient_did: &str, after: Option<ObjectId>, limit: i64) -> Result<Vec<FeedEvent>> {
+        let collection: Collection<FeedEvent> = self.db.collection("notification_events");
+        let mut filter = doc! { "recipient_did": recipient_did, "expires_at": { "$gt": DateTime::now() } };
+        if let Some(after) = after {
+            filter.insert("_id", doc! { "$gt": after });
+        }
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).limit(limit).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    // Idempotency key operations
+    /// Claim `record.key` for `record.user_did`: `None` when the claim was made, or the record
+    /// of the request that already holds the key. A record that expired, or an in-progress claim
+    /// whose request never finished, is replaced.
+    pub async fn claim_idempotency_key(&self, record: &IdempotencyRecord, now: chrono::DateTime<Utc>) -> Result<Option<IdempotencyRecord>> {
+        let collection: Collection<IdempotencyRecord> = self.db.collection("idempotency_keys");
+        let now_bson = DateTime::from_chrono(now);
+        let this_key = doc! { "user_did": &record.user_did, "key": &record.key };
+        // A few rounds cover the record being removed or taken over between the insert and the read
+        for _ in 0..3 {
+            match collection.insert_one(record, None).await {
+                Ok(_) => return Ok(None),
+                Err(e) if is_duplicate_key_error(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            let mut stale = this_key.clone();
+            stale.insert("$or", vec![
+                doc! { "expires_at": { "$lte": now_bson } },
+                doc! { "status": bson::to_bson(&IdempotencyStatus::InProgress)?, "locked_until": { "$lte": now_bson } },
+            ]);
+            if collection.delete_one(stale, None).await?.deleted_count == 1 {
+                continue;
+            }
+            if let Some(existing) = collection.find_one(this_key.clone(), None).await? {
+                return Ok(Some(existing));
+            }
+        }
+        Err(anyhow::anyhow!("Idempotency-Key {} kept changing hands; try again", record.key))
+    }
+
+    /// Store the response of a claimed request
+    pub async fn complete_idempotency_key(&self, id: ObjectId, status: u16, content_type: Option<&str>, body: &str) -> Result<()> {
+        let collection: Collection<IdempotencyRecord> = self.db.collection("idempotency_keys");
+        let filter = doc! { "_id": id, "status": bson::to_bson(&IdempotencyStatus::InProgress)? };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&IdempotencyStatus::Completed)?,
+                "response_status": i32::from(status),
+                "response_content_type": content_type,
+                "response_body": body,
+            },
+        };
+        collection.update_one(filter, update, None).await?;
+        Ok(())
+    }
+
+    /// Give up a claim so the key can be used again, after a response not worth replaying
+    pub async fn release_idempotency_key(&self, id: ObjectId) -> Result<()> {
+        let collection: Collection<IdempotencyRecord> = self.db.collection("idempotency_keys");
+        collection.delete_one(doc! { "_id": id, "status": bson::to_bson(&IdempotencyStatus::InProgress)? }, None).await?;
+        Ok(())
+    }
+
+    /// Counts across the platform for the admin dashboard
+    pub async fn system_stats(&self) -> Result<SystemStats> {
+        #[derive(Deserialize)]
+        struct StatusCount {
+            #[serde(rename = "_id")]
+            status: String,
+            count: u64,
+        }
+
+        let live = Self::scope_deleted(Document::new(), false);
+        let encounters: ScopedCollection<Encounter> = self.scoped("encounters");
+        let pipeline = vec![
+            doc! { "$match": live.clone() },
+            doc! { "$group": { "_id": "$status", "count": { "$sum": 1 } } },
+        ];
+        let mut by_status = encounters.aggregate(pipeline, None).await?;
+        let mut encounter_counts = std::collections::BTreeMap::new();
+        while let Some(document) = by_status.try_next().await? {
+            let StatusCount { status, count } = bson::from_document(document)?;
+            encounter_counts.insert(status, count);
+        }
+
+        let patients: Collection<EncryptedPatient> = self.db.collection("patients");
+        let practitioners: ScopedCollection<Practitioner> = self.scoped("practitioners");
+        let credentials: Collection<VerifiableCredential> = self.db.collection("verifiable_credentials");
+        let audit_logs: ScopedCollection<AuditLog> = self.scoped("audit_logs");
+        Ok(SystemStats {
+            patients: patients.count_documents(live, None).await?,
+            practitioners: practitioners.count_documents(None, None).await?,
+            encounters: encounter_counts,
+            credentials_issued: credentials.count_documents(None, None).await?,
+            unanchored_audit_logs: audit_logs.count_documents(doc! { "is_anchored": false }, None).await?,
+            pending_emails: self.count_emails_by_status().await?.pending,
+            indexes: self.index_build_status(),
+            // Filled in by the admin service, which has the SMS config
+            sms: SmsStats::default(),
+        })
+    }
+
+    /// Replace the report of malformed or out-of-order stored FHIR dates with a fresh scan's findings
+    pub async fn replace_fhir_date_issues(&self, issues: &[FhirDateIssue]) -> Result<()> {
+        let collection: Collection<FhirDateIssue> = self.db.collection("fhir_date_issues");
+        collection.delete_many(doc! {}, None).await?;
+        if !issues.is_empty() {
+            collection.insert_many(issues, None).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn list_fhir_date_issues(&self) -> Result<Vec<FhirDateIssue>> {
+        let collection: Collection<FhirDateIssue> = self.db.collection("fhir_date_issues");
+        let options = FindOptions::builder().sort(doc! { "collection": 1, "record_id": 1 }).build();
+        Ok(collection.find(None, options).await?.try_collect().await?)
+    }
+
+    // Appointment operations
+    /// Patients book outside any tenant; the appointment joins its practitioner's tenant
+    pub async fn create_appointment(&self, appointment: &Appointment) -> Result<ObjectId> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        let mut appointment = appointment.clone();
+        if appointment.tenant_id.is_none() {
+            appointment.tenant_id = self.practitioner_tenant(&appointment.practitioner_did).await?;
+        }
+        let result = collection.insert_one(&appointment, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_appointment(&self, id: ObjectId) -> Result<Option<Appointment>> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Move an appointment from `from` to `to`; false when it was not in `from`.
+    /// Confirming into a slot the practitioner already has confirmed fails with `DatabaseError::DuplicateKey`.
+    pub async fn update_appointment_status(&self, id: ObjectId, from: AppointmentStatus, to: AppointmentStatus) -> Result<bool> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        let filter = doc! { "_id": id, "status": bson::to_bson(&from)? };
+        let update = doc! { "$set": { "status": bson::to_bson(&to)?, "updated_at": bson::to_bson(&Utc::now())? } };
+        match collection.update_one(filter, update, None).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Mark one confirmed appointment due for `stage` as reminded and return it.
+    ///
+    /// The marker is set in the same findAndModify that selects the appointment, so
+    /// each reminder is claimed by exactly one worker, across restarts and replicas.
+    pub async fn claim_appointment_reminder(&self, stage: ReminderStage, now: chrono::DateTime<Utc>) -> Result<Option<Appointment>> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        let (lower, upper) = stage.window();
+        let marker = format!("reminder_sent_at.{}", stage.marker());
+        let filter = doc! {
+            "status": bson::to_bson(&AppointmentStatus::Confirmed)?,
+            "start": { "$gt": bson::to_bson(&(now + lower))?, "$lte": bson::to_bson(&(now + upper))? },
+            &marker: null,
+        };
+        let update = doc! { "$set": { &marker: bson::to_bson(&now)? } };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "start": 1 })
+            .return_document(ReturnDocument::After)
+            .build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    pub async fn link_appointment_encounter(&self, id: ObjectId, encounter_id: ObjectId) -> Result<()> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        let update = doc! { "$set": { "encounter_id": encounter_id, "updated_at": bson::to_bson(&Utc::now())? } };
+        collection.update_one(doc! { "_id": id }, update, None).await?;
+        Ok(())
+    }
+
+    /// Appointments of `did` on the given side starting within `[from, to)`, earliest first
+    pub async fn list_appointments(
+        &self,
+        party: AppointmentParty,
+        did: &str,
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<Vec<Appointment>> {
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        let did_field = Self::party_field(party);
+        let mut filter = doc! { did_field: did };
+        Self::insert_time_range(&mut filter, "start", from, to)?;
+        let options = FindOptions::builder().sort(doc! { "start": 1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Appointments with the affiliated practitioners starting while each affiliation ran, earliest first
+    pub async fn list_affiliated_appointments(
+        &self,
+        affiliations: &[Affiliation],
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<Vec<Appointment>> {
+        let Some(mut filter) = Self::affiliation_filter(affiliations, "start")? else {
+            return Ok(Vec::new());
+        };
+        let collection: ScopedCollection<Appointment> = self.scoped("appointments");
+        Self::insert_time_range(&mut filter, "start", from, to)?;
+        let options = FindOptions::builder().sort(doc! { "start": 1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    // Consent operations
+    pub async fn create_consent(&self, consent: &Consent) -> Result<ObjectId> {
+        let collection: Collection<Consent> = self.db.collection("consents");
+        let result = collection.insert_one(consent, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_consent(&self, id: ObjectId) -> Result<Option<Consent>> {
+        let collection: Collection<Consent> = self.db.collection("consents");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// All of the patient's consents, newest first
+    pub async fn list_consents(&self, patient_did: &str) -> Result<Vec<Consent>> {
+        let collection: Collection<Consent> = self.db.collection("consents");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(doc! { "patient_did": patient_did }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn active_consents(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<Consent>> {
+        let collection: Collection<Consent> = self.db.collection("consents");
+        let filter = doc! {
+            "patient_did": patient_did,
+            "grantee_did": grantee_did,
+            "status": bson::to_bson(&ConsentStatus::Active)?,
+        };
         let cursor = collection.find(filter, None).await?;
         Ok(cursor.try_collect().await?)
     }
 
-    pub async fn mark_logs_as_anchored(&self, log_ids: &[ObjectId], anchor_batch_id: ObjectId) -> Result<()> {
-        let collection: Collection<AuditLog> = self.db.collection("audit_logs");
-        let filter = doc! { "_id": { "$in": log_ids } };
-        let update = doc! { "$set": { 
-            "is_anchored": true, 
-            "anchor_batch_id": anchor_batch_id 
-        } };
-        collection.update_many(filter, update, None).await?;
+    /// Mark an active consent inactive along with its revised FHIR document; false if it was not active
+    pub async fn deactivate_consent(&self, id: ObjectId, fhir_consent: &FhirConsent, ipfs_hash: &str) -> Result<bool> {
+        let collection: Collection<Consent> = self.db.collection("consents");
+        let filter = doc! { "_id": id, "status": bson::to_bson(&ConsentStatus::Active)? };
+        let update = doc! {
+            "$set": {
+                "status": bson::to_bson(&ConsentStatus::Inactive)?,
+                "fhir_consent": bson::to_bson(fhir_consent)?,
+                "ipfs_hash": ipfs_hash,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            }
+        };
+        Ok(collection.update_one(filter, update, None).await?.modified_count > 0)
+    }
+
+    // Relationship operations
+    /// A second active link between the same two DIDs fails with `DatabaseError::DuplicateKey`
+    pub async fn create_relationship(&self, relationship: &Relationship) -> Result<ObjectId> {
+        let collection: Collection<Relationship> = self.db.collection("relationships");
+        match collection.insert_one(relationship, None).await {
+            Ok(result) => Ok(result.inserted_id.as_object_id().unwrap()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The active link from `guardian_did` to `dependent_did`, expired or not
+    pub async fn get_relationship(&self, guardian_did: &str, dependent_did: &str) -> Result<Option<Relationship>> {
+        let collection: Collection<Relationship> = self.db.collection("relationships");
+        let filter = doc! { "guardian_did": guardian_did, "dependent_did": dependent_did, "active": true };
+        Ok(collection.find_one(filter, None).await?)
+    }
+
+    /// Links where `did` is either the guardian or the dependent, newest first
+    pub async fn list_relationships(&self, did: &str) -> Result<Vec<Relationship>> {
+        let collection: Collection<Relationship> = self.db.collection("relationships");
+        let filter = doc! { "$or": [{ "guardian_did": did }, { "dependent_did": did }] };
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    // Organization operations
+    /// An identifier value already used by another organization fails with `DatabaseError::DuplicateKey`
+    pub async fn create_organization(&self, organization: &Organization) -> Result<ObjectId> {
+        let collection: ScopedCollection<Organization> = self.scoped("organizations");
+        match collection.insert_one(organization, None).await {
+            Ok(result) => Ok(result.inserted_id.as_object_id().unwrap()),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn get_organization(&self, id: ObjectId) -> Result<Option<Organization>> {
+        let collection: ScopedCollection<Organization> = self.scoped("organizations");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// The organization with an identifier whose value is `identifier`
+    pub async fn find_organization_by_identifier(&self, identifier: &str) -> Result<Option<Organization>> {
+        let collection: ScopedCollection<Organization> = self.scoped("organizations");
+        Ok(collection.find_one(doc! { "fhir_organization.identifier.value": identifier }, None).await?)
+    }
+
+    /// All organizations, inactive ones included, by name
+    pub async fn list_organizations(&self) -> Result<Vec<Organization>> {
+        let collection: ScopedCollection<Organization> = self.scoped("organizations");
+        let options = FindOptions::builder().sort(doc! { "fhir_organization.name": 1 }).build();
+        let cursor = collection.find(doc! {}, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Replace the FHIR resource; `false` if no organization has that id
+    pub async fn update_organization(&self, id: ObjectId, fhir_organization: &FhirOrganization) -> Result<bool> {
+        let collection: ScopedCollection<Organization> = self.scoped("organizations");
+        let update = doc! {
+            "$set": {
+                "fhir_organization": bson::to_bson(fhir_organization)?,
+                "updated_at": bson::to_bson(&Utc::now())?,
+            }
+        };
+        match collection.update_one(doc! { "_id": id }, update, None).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) if is_duplicate_key_error(&e) => Err(DatabaseError::DuplicateKey(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Affiliation operations
+    pub async fn create_affiliation(&self, affiliation: &Affiliation) -> Result<ObjectId> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        let result = collection.insert_one(affiliation, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_affiliation(&self, id: ObjectId) -> Result<Option<Affiliation>> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Every affiliation with the organization, newest first
+    pub async fn list_affiliations(&self, organization_id: ObjectId) -> Result<Vec<Affiliation>> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(doc! { "organization_id": organization_id }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Approve or reject a pending affiliation; approving starts its period now unless it already has a start.
+    /// `None` if it does not exist or was already decided.
+    pub async fn decide_affiliation(
+        &self,
+        id: ObjectId,
+        status: AffiliationStatus,
+        decided_by: &str,
+        now: chrono::DateTime<Utc>,
+    ) -> Result<Option<Affiliation>> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        let now = bson::to_bson(&now)?;
+        let mut set = doc! { "status": bson::to_bson(&status)?, "decided_by": decided_by, "updated_at": now.clone() };
+        if status == AffiliationStatus::Active {
+            set.insert("period_start", doc! { "$ifNull": ["$period_start", now] });
+        }
+        let filter = doc! { "_id": id, "status": bson::to_bson(&AffiliationStatus::Pending)? };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(filter, vec![doc! { "$set": set }], options).await?)
+    }
+
+    /// Active affiliations of the practitioner in any role, whether or not their period has started or ended
+    pub async fn active_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        let filter = doc! { "practitioner_did": practitioner_did, "status": bson::to_bson(&AffiliationStatus::Active)? };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Active admin affiliations of the practitioner, whether or not their period has started or ended
+    pub async fn admin_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>> {
+        let collection: Collection<Affiliation> = self.db.collection("affiliations");
+        let filter = doc! {
+            "practitioner_did": practitioner_did,
+            "role": bson::to_bson(&AffiliationRole::Admin)?,
+            "status": bson::to_bson(&AffiliationStatus::Active)?,
+        };
+        let cursor = collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Make `did` an admin with `role`; `false` when it already was one, whose role is replaced
+    pub async fn add_admin(&self, did: &str, role: Role) -> Result<bool> {
+        let collection: Collection<AdminAccount> = self.db.collection("admins");
+        let update = doc! {
+            "$set": { "role": bson::to_bson(&role)? },
+            "$setOnInsert": { "created_at": bson::to_bson(&Utc::now())? },
+        };
+        let result = collection.update_one(doc! { "_id": did }, update, UpdateOptions::builder().upsert(true).build()).await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    /// The role of a DID made an admin with `create-admin`
+    pub async fn admin_role(&self, did: &str) -> Result<Option<Role>> {
+        let collection: Collection<AdminAccount> = self.db.collection("admins");
+        Ok(collection.find_one(doc! { "_id": did }, None).await?.map(|admin| admin.role))
+    }
+
+    // Break-glass operations
+    pub async fn create_break_glass_event(&self, event: &BreakGlassEvent) -> Result<ObjectId> {
+        let collection: Collection<BreakGlassEvent> = self.db.collection("break_glass_events");
+        let result = collection.insert_one(event, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    /// Newest first; `reviewed` narrows the list to reviewed or unreviewed events
+    pub async fn list_break_glass_events(&self, reviewed: Option<bool>) -> Result<Vec<BreakGlassEvent>> {
+        let collection: Collection<BreakGlassEvent> = self.db.collection("break_glass_events");
+        let filter = match reviewed {
+            Some(true) => doc! { "reviewed_at": { "$ne": null } },
+            Some(false) => doc! { "reviewed_at": null },
+            None => doc! {},
+        };
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Mark an unreviewed event reviewed; `None` if it does not exist or was already reviewed
+    pub async fn review_break_glass_event(&self, id: ObjectId, reviewer_did: &str, notes: Option<String>) -> Result<Option<BreakGlassEvent>> {
+        let collection: Collection<BreakGlassEvent> = self.db.collection("break_glass_events");
+        let update = doc! {
+            "$set": {
+                "reviewed_at": bson::to_bson(&Utc::now())?,
+                "reviewed_by": reviewer_did,
+                "review_notes": notes,
+            }
+        };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(doc! { "_id": id, "reviewed_at": null }, update, options).await?)
+    }
+
+    /// Claim the oldest unreviewed event created before `created_before` that admins were not yet alerted about
+    pub async fn claim_break_glass_alert(&self, created_before: chrono::DateTime<Utc>, now: chrono::DateTime<Utc>) -> Result<Option<BreakGlassEvent>> {
+        let collection: Collection<BreakGlassEvent> = self.db.collection("break_glass_events");
+        let filter = doc! {
+            "reviewed_at": null,
+            "alerted_at": null,
+            "created_at": { "$lte": bson::to_bson(&created_before)? },
+        };
+        let update = doc! { "$set": { "alerted_at": bson::to_bson(&now)? } };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "created_at": 1 })
+            .return_document(ReturnDocument::After)
+            .build();
+        Ok(collection.find_one_and_update(filter, update, options).await?)
+    }
+
+    // Referral operations
+    pub async fn create_referral(&self, referral: &Referral) -> Result<ObjectId> {
+        let collection: Collection<Referral> = self.db.collection("referrals");
+        let result = collection.insert_one(referral, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    pub async fn get_referral(&self, id: ObjectId) -> Result<Option<Referral>> {
+        let collection: Collection<Referral> = self.db.collection("referrals");
+        Ok(collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    /// Newest first; the inbox holds referrals sent to `did`, the outbox those it made
+    pub async fn list_referrals(&self, folder: ReferralFolder, did: &str, status: Option<ReferralStatus>) -> Result<Vec<Referral>> {
+        let collection: Collection<Referral> = self.db.collection("referrals");
+        let did_field = match folder {
+            ReferralFolder::Inbox => "receiving_did",
+            ReferralFolder::Outbox => "referring_did",
+        };
+        let mut filter = doc! { did_field: did };
+        if let Some(status) = status {
+            filter.insert("status", bson::to_bson(&status)?);
+        }
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let cursor = collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Move a referral from `from` to `to`; `None` if it does not exist or is no longer in `from`
+    pub async fn transition_referral(&self, id: ObjectId, from: ReferralStatus, to: ReferralStatus, note: Option<String>) -> Result<Option<Referral>> {
+        let collection: Collection<Referral> = self.db.collection("referrals");
+        let mut set = doc! { "status": bson::to_bson(&to)?, "updated_at": bson::to_bson(&Utc::now())? };
+        if note.is_some() {
+            set.insert("note", note);
+        }
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        Ok(collection.find_one_and_update(doc! { "_id": id, "status": bson::to_bson(&from)? }, doc! { "$set": set }, options).await?)
+    }
+
+    pub async fn link_referral_grant(&self, id: ObjectId, access_control_id: ObjectId) -> Result<()> {
+        let collection: Collection<Referral> = self.db.collection("referrals");
+        collection
+            .update_one(doc! { "_id": id }, doc! { "$set": { "access_control_id": access_control_id } }, None)
+            .await?;
+        Ok(())
+    }
+
+    // Availability operations
+    pub async fn get_availability(&self, practitioner_did: &str) -> Result<Option<Availability>> {
+        let collection: Collection<Availability> = self.db.collection("availability");
+        Ok(collection.find_one(doc! { "practitioner_did": practitioner_did }, None).await?)
+    }
+
+    /// Replace the practitioner's schedule, creating it on first save
+    pub async fn save_availability(&self, availability: &Availability) -> Result<()> {
+        let collection: Collection<Availability> = self.db.collection("availability");
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection
+            .replace_one(doc! { "practitioner_did": &availability.practitioner_did }, availability, options)
+            .await?;
+        Ok(())
+    }
+
+    // Chat operations
+    pub async fn create_chat_session(&self, session: &ChatSession) -> Result<ObjectId> {
+        let collection: Collection<ChatSession> = self.db.collection("chat_sessions");
+        let result = collection.insert_one(session, None).await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    /// A session only if it belongs to `owner_did`, so one user can never read another's history
+    pub async fn get_chat_session(&self, id: ObjectId, owner_did: &str) -> Result<Option<ChatSession>> {
+        let collection: Collection<ChatSession> = self.db.collection("chat_sessions");
+        Ok(collection.find_one(doc! { "_id": id, "owner_did": owner_did }, None).await?)
+    }
+
+    /// Most recently active first
+    pub async fn list_chat_sessions(&self, owner_did: &str) -> Result<Vec<ChatSession>> {
+        let collection: Collection<ChatSession> = self.db.collection("chat_sessions");
+        let options = FindOptions::builder().sort(doc! { "updated_at": -1 }).build();
+        let cursor = collection.find(doc! { "owner_did": owner_did }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn add_chat_message(&self, message: &ChatMessage) -> Result<ObjectId> {
+        let messages: Collection<ChatMessage> = self.db.collection("chat_messages");
+        let result = messages.insert_one(message, None).await?;
+        let sessions: Collection<ChatSession> = self.db.collection("chat_sessions");
+        sessions
+            .update_one(doc! { "_id": message.session_id }, doc! { "$set": { "updated_at": bson::to_bson(&message.created_at)? } }, None)
+            .await?;
+        Ok(result.inserted_id.as_object_id().unwrap())
+    }
+
+    /// The last `limit` messages of a session, oldest first
+    pub async fn recent_chat_messages(&self, session_id: ObjectId, limit: i64) -> Result<Vec<ChatMessage>> {
+        let collection: Collection<ChatMessage> = self.db.collection("chat_messages");
+        let options = FindOptions::builder().sort(doc! { "created_at": -1, "_id": -1 }).limit(limit).build();
+        let cursor = collection.find(doc! { "session_id": session_id }, options).await?;
+        let mut messages: Vec<ChatMessage> = cursor.try_collect().await?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    pub async fn list_chat_messages(&self, session_id: ObjectId) -> Result<Vec<ChatMessage>> {
+        let collection: Collection<ChatMessage> = self.db.collection("chat_messages");
+        let options = FindOptions::builder().sort(doc! { "created_at": 1, "_id": 1 }).build();
+        let cursor = collection.find(doc! { "session_id": session_id }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn get_chat_usage(&self, user_did: &str, day: &str) -> Result<Option<ChatUsage>> {
+        let collection: Collection<ChatUsage> = self.db.collection("chat_usage");
+        Ok(collection.find_one(doc! { "user_did": user_did, "day": day }, None).await?)
+    }
+
+    /// Count one model call against the user's day, creating the day's entry on first use
+    pub async fn record_chat_usage(&self, user_did: &str, day: &str, prompt_tokens: u32, reply_tokens: u32) -> Result<()> {
+        let collection: Collection<ChatUsage> = self.db.collection("chat_usage");
+        let update = doc! {
+            "$inc": { "requests": 1_i64, "prompt_tokens": i64::from(prompt_tokens), "reply_tokens": i64::from(reply_tokens) }
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        collection.update_one(doc! { "user_did": user_did, "day": day }, update, options).await?;
         Ok(())
     }
 
+    /// Every user's usage from `since_day` on, newest day first
+    pub async fn list_chat_usage(&self, since_day: &str) -> Result<Vec<ChatUsage>> {
+        let collection: Collection<ChatUsage> = self.db.collection("chat_usage");
+        let options = FindOptions::builder().sort(doc! { "day": -1, "user_did": 1 }).build();
+        let cursor = collection.find(doc! { "day": { "$gte": since_day } }, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
     // OTP operations
     pub async fn create_otp(&self, otp: &Otp) -> Result<()> {
         let collection: Collection<Otp> = self.db.collection("otps");
@@ -396,4 +3420,29 @@ impl Database {
         let filter = doc! { "phone_number": phone_number, "otp": otp };
         Ok(collection.find_one(filter, None).await?)
     }
+
+    /// Remove every outstanding code for a phone number once one has been used
+    pub async fn delete_otps(&self, phone_number: &str) -> Result<()> {
+        let collection: Collection<Otp> = self.db.collection("otps");
+        collection.delete_many(doc! { "phone_number": phone_number }, None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_deleted_excludes_tombstones_by_default() {
+        let filter = Database::scope_deleted(doc! { "did": "did:hedera:testnet:0.0.1" }, false);
+        assert_eq!(filter.get("deleted_at"), Some(&Bson::Null));
+        assert_eq!(filter.get_str("did").unwrap(), "did:hedera:testnet:0.0.1");
+    }
+
+    #[test]
+    fn scope_deleted_leaves_filter_untouched_for_admin_paths() {
+        let filter = Database::scope_deleted(doc! { "did": "did:hedera:testnet:0.0.1" }, true);
+        assert!(filter.get("deleted_at").is_none());
+    }
 }
@@ -0,0 +1,88 @@
+//! The time the running request has left, so that the calls it makes to IPFS, Hedera and other
+//! services give up when the caller stops waiting, instead of on timers of their own.
+//!
+//! The timeout middleware sets the deadline. Background work runs outside a request and has none,
+//! so the calls it makes are bounded by their own timeouts alone.
+
+use std::future::Future;
+use tokio::time::{Duration, Instant};
+
+use crate::services::ServiceError;
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Run `future` with `deadline` as the time its request has to be answered by
+pub async fn scope<F: Future>(deadline: Instant, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+/// Time left until the running request's deadline; `None` outside a request
+pub fn remaining() -> Option<Duration> {
+    DEADLINE.try_with(|deadline| deadline.saturating_duration_since(Instant::now())).ok()
+}
+
+/// `timeout`, or less if the running request has less time left
+pub fn remaining_or(timeout: Duration) -> Duration {
+    remaining().map_or(timeout, |left| left.min(timeout))
+}
+
+/// `request`, timing out when the running request's deadline passes
+pub fn limit(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match remaining() {
+        Some(left) => request.timeout(left),
+        None => request,
+    }
+}
+
+/// Await `future` until the running request's deadline, dropping it and failing with
+/// [`ServiceError::Timeout`] (504) once the deadline passes. Outside a request it just awaits `future`.
+pub async fn bound<T, F: Future<Output = anyhow::Result<T>>>(future: F) -> anyhow::Result<T> {
+    match DEADLINE.try_with(|deadline| *deadline) {
+        Ok(deadline) => tokio::time::timeout_at(deadline, future).await.map_err(|_| ServiceError::Timeout)?,
+        Err(_) => future.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::services::ipfs::ObjectStorage;
+
+    #[tokio::test]
+    async fn calls_get_what_is_left_of_the_request_budget() {
+        assert_eq!((remaining(), remaining_or(Duration::from_secs(5))), (None, Duration::from_secs(5)));
+
+        scope(Instant::now() + Duration::from_secs(2), async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let left = remaining().unwrap();
+            assert!(left <= Duration::from_millis(1500) && left > Duration::from_secs(1), "{:?}", left);
+            assert!(remaining_or(Duration::from_secs(5)) <= left);
+            assert_eq!(remaining_or(Duration::from_millis(100)), Duration::from_millis(100));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn calls_past_the_deadline_are_cancelled_with_a_timeout() {
+        let storage = InMemoryObjectStorage::new().with_delay(Duration::from_millis(300));
+
+        let upload = scope(Instant::now() + Duration::from_millis(100), bound(storage.add_file(b"bundle", None))).await;
+
+        let error = upload.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServiceError>(), Some(ServiceError::Timeout)), "{:#}", error);
+        // The upload was dropped mid-way, so it never stores anything
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!((storage.upload_count(), storage.stored_count()), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn calls_outside_a_request_run_to_completion() {
+        let storage = InMemoryObjectStorage::new().with_delay(Duration::from_millis(300));
+
+        assert!(bound(storage.add_file(b"bundle", None)).await.is_ok());
+        assert_eq!(storage.stored_count(), 1);
+    }
+}
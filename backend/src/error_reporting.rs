@@ -0,0 +1,239 @@
+//! Error reporting to Sentry or a compatible service, when `SENTRY_DSN` is set.
+//!
+//! ERROR log events become reports, with the INFO and WARN lines before them as breadcrumbs,
+//! and panics are reported by a hook that runs before the default one. Requests tag their
+//! reports with the route template and request id, background tasks with their name. Every
+//! report passes through [`scrub`] on its way out: its text goes past the same identifier
+//! detectors as chat messages, and the user and request details the SDK could attach are dropped.
+
+use sentry::integrations::tracing::EventFilter;
+use sentry::protocol::{Context, Event, Map, Value};
+use sentry::{ClientInitGuard, ClientOptions, Hub, SentryFutureExt};
+use std::borrow::Cow;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::Level;
+use tracing_subscriber::Layer;
+
+use crate::config::ErrorReportingConfig;
+use crate::services::redaction::Redactor;
+use crate::telemetry::BoxedLayer;
+
+/// Tags the backend sets itself; they hold route templates, request ids and task names, so are sent as they are
+const OWN_TAGS: [&str; 3] = ["route", "request_id", "task"];
+
+/// Start reporting if a DSN is configured. Reports are sent in the background until the guard
+/// is dropped, which waits for the last ones.
+pub fn init(config: &ErrorReportingConfig) -> Option<ClientInitGuard> {
+    config.dsn.as_ref()?;
+    Some(sentry::init(options(config)))
+}
+
+fn options(config: &ErrorReportingConfig) -> ClientOptions {
+    ClientOptions {
+        // Config validation already rejected DSNs that do not parse
+        dsn: config.dsn.as_deref().and_then(|dsn| dsn.parse().ok()),
+        release: config.release.clone().map(Cow::Owned).or_else(|| sentry::release_name!()),
+        environment: Some(Cow::Owned(config.environment.clone())),
+        send_default_pii: false,
+        before_send: Some(Arc::new(|event: Event<'static>| Some(scrub(event)))),
+        ..Default::default()
+    }
+}
+
+/// Reports ERROR events and keeps INFO and WARN events as breadcrumbs for the next report.
+/// Spans are left to the trace exporter.
+pub fn layer() -> BoxedLayer {
+    sentry::integrations::tracing::layer()
+        .event_filter(|metadata| match *metadata.level() {
+            Level::ERROR => EventFilter::Event,
+            Level::WARN | Level::INFO => EventFilter::Breadcrumb,
+            Level::DEBUG | Level::TRACE => EventFilter::Ignore,
+        })
+        .span_filter(|_| false)
+        .boxed()
+}
+
+/// A scope for one request, so its reports carry its route template and request id, and only its own breadcrumbs
+pub fn request_hub(route: &str, request_id: &str) -> Arc<Hub> {
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    hub.configure_scope(|scope| {
+        scope.set_tag("route", route);
+        scope.set_tag("request_id", request_id);
+    });
+    hub
+}
+
+/// Run `task` in a scope tagged with its name. Background work never reaches a handler, so
+/// without this its reports, panics included, would not say where they came from.
+pub fn background<F: Future>(name: &'static str, task: F) -> impl Future<Output = F::Output> {
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    hub.configure_scope(|scope| scope.set_tag("task", name));
+    task.bind_hub(hub)
+}
+
+/// `event` with the emails, phone numbers, DIDs and ID numbers in its messages, exceptions,
+/// breadcrumbs, fields and stack variables replaced by placeholders. The same value gets the
+/// same placeholder throughout one event, so a report can still show that two fields matched.
+pub fn scrub(mut event: Event<'static>) -> Event<'static> {
+    let mut redactor = Redactor::new();
+    event.user = None;
+    event.request = None;
+
+    for text in [&mut event.message, &mut event.culprit, &mut event.transaction].into_iter().flatten() {
+        *text = redactor.redact(text);
+    }
+    if let Some(entry) = &mut event.logentry {
+        entry.message = redactor.redact(&entry.message);
+        entry.params.iter_mut().for_each(|param| scrub_value(&mut redactor, param));
+    }
+    for exception in &mut event.exception.values {
+        if let Some(value) = &mut exception.value {
+            *value = redactor.redact(value);
+        }
+        for stacktrace in [&mut exception.stacktrace, &mut exception.raw_stacktrace].into_iter().flatten() {
+            stacktrace.frames.iter_mut().for_each(|frame| scrub_map(&mut redactor, &mut frame.vars));
+        }
+    }
+    let thread_stacktraces = event.threads.values.iter_mut().flat_map(|thread| [&mut thread.stacktrace, &mut thread.raw_stacktrace]);
+    for stacktrace in std::iter::once(&mut event.stacktrace).chain(thread_stacktraces).flatten() {
+        stacktrace.frames.iter_mut().for_each(|frame| scrub_map(&mut redactor, &mut frame.vars));
+    }
+    for breadcrumb in &mut event.breadcrumbs.values {
+        if let Some(message) = &mut breadcrumb.message {
+            *message = redactor.redact(message);
+        }
+        scrub_map(&mut redactor, &mut breadcrumb.data);
+    }
+    for (_, value) in event.tags.iter_mut().filter(|(key, _)| !OWN_TAGS.contains(&key.as_str())) {
+        *value = redactor.redact(value);
+    }
+    scrub_map(&mut redactor, &mut event.extra);
+    // Log event fields arrive as contexts of their own; the SDK's device and runtime contexts hold no identifiers
+    for context in event.contexts.values_mut() {
+        if let Context::Other(fields) = context {
+            scrub_map(&mut redactor, fields);
+        }
+    }
+    event
+}
+
+fn scrub_map(redactor: &mut Redactor, fields: &mut Map<String, Value>) {
+    fields.values_mut().for_each(|value| scrub_value(redactor, value));
+}
+
+fn scrub_value(redactor: &mut Redactor, value: &mut Value) {
+    match value {
+        Value::String(text) => *text = redactor.redact(text),
+        // A phone or ID number logged as an integer field
+        Value::Number(number) => {
+            let text = number.to_string();
+            let redacted = redactor.redact(&text);
+            if redacted != text {
+                *value = Value::String(redacted);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| scrub_value(redactor, item)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| scrub_value(redactor, field)),
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentry::protocol::{Breadcrumb, Exception, Request, User, Values};
+    use serde_json::json;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const EMAIL: &str = "amina.otieno@example.co.ke";
+    const PHONE: &str = "+254 712 345 678";
+    const DID: &str = "did:hedera:testnet:0.0.48213";
+
+    fn config() -> ErrorReportingConfig {
+        ErrorReportingConfig { dsn: None, environment: "staging".to_string(), release: Some("backend@2026.10.1".to_string()) }
+    }
+
+    #[test]
+    fn identifiers_are_replaced_wherever_they_appear() {
+        let mut event = Event {
+            message: Some(format!("Failed to notify {}", DID)),
+            exception: Values::from(vec![Exception { ty: "anyhow::Error".to_string(), value: Some(format!("SMTP rejected {}", EMAIL)), ..Default::default() }]),
+            breadcrumbs: Values::from(vec![Breadcrumb {
+                message: Some(format!("Texting {}", PHONE)),
+                data: Map::from([("to".to_string(), json!(254712345678u64))]),
+                ..Default::default()
+            }]),
+            extra: Map::from([("recipients".to_string(), json!([EMAIL, { "did": DID }])), ("attempts".to_string(), json!(3))]),
+            ..Default::default()
+        };
+        event.tags.insert("patient".to_string(), DID.to_string());
+        event.contexts.insert("Rust Tracing Fields".to_string(), Context::Other(Map::from([("email".to_string(), json!(EMAIL))])));
+
+        let event = scrub(event);
+
+        assert_eq!(event.message.as_deref(), Some("Failed to notify [DID_1]"));
+        assert_eq!(event.exception.values[0].value.as_deref(), Some("SMTP rejected [EMAIL_1]"));
+        assert_eq!(event.breadcrumbs.values[0].message.as_deref(), Some("Texting [PHONE_1]"));
+        assert_eq!(event.breadcrumbs.values[0].data["to"], "[PHONE_2]");
+        assert_eq!(event.extra["recipients"], json!(["[EMAIL_1]", { "did": "[DID_1]" }]));
+        assert_eq!(event.extra["attempts"], 3);
+        assert_eq!(event.tags["patient"], "[DID_1]");
+        assert_eq!(event.contexts["Rust Tracing Fields"], Context::Other(Map::from([("email".to_string(), json!("[EMAIL_1]"))])));
+    }
+
+    #[test]
+    fn user_and_request_details_are_dropped_and_own_tags_kept() {
+        let mut event = Event {
+            user: Some(User { email: Some(EMAIL.to_string()), ..Default::default() }),
+            request: Some(Request { query_string: Some(format!("email={}", EMAIL)), ..Default::default() }),
+            ..Default::default()
+        };
+        event.tags.insert("route".to_string(), "/api/patients/:id".to_string());
+        event.tags.insert("request_id".to_string(), "12345678".to_string());
+
+        let event = scrub(event);
+
+        assert!(event.user.is_none() && event.request.is_none());
+        assert_eq!((event.tags["route"].as_str(), event.tags["request_id"].as_str()), ("/api/patients/:id", "12345678"));
+    }
+
+    #[test]
+    fn errors_logged_by_background_tasks_are_reported_with_their_task_and_no_identifiers() {
+        let events = sentry::test::with_captured_events_options(
+            || {
+                let subscriber = tracing_subscriber::registry().with(layer());
+                tracing::subscriber::with_default(subscriber, || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    runtime.block_on(background("email_outbox", async {
+                        tracing::info!("Sending the verification email to {}", EMAIL);
+                        tracing::error!("Failed to send to {}: mailbox full", EMAIL);
+                    }));
+                });
+            },
+            options(&config()),
+        );
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.message.as_deref(), Some("Failed to send to [EMAIL_1]: mailbox full"));
+        assert_eq!(event.breadcrumbs.values[0].message.as_deref(), Some("Sending the verification email to [EMAIL_1]"));
+        assert_eq!(event.tags["task"], "email_outbox");
+        assert_eq!((event.release.as_deref(), event.environment.as_deref()), (Some("backend@2026.10.1"), Some("staging")));
+    }
+
+    #[test]
+    fn reports_during_a_request_carry_its_route_and_request_id() {
+        let events = sentry::test::with_captured_events_options(
+            || {
+                Hub::run(request_hub("/api/encounters/:id/finalize", "lb.7781_a"), || {
+                    sentry::capture_message(&format!("Finalizing failed for {}", DID), sentry::Level::Error);
+                });
+            },
+            options(&config()),
+        );
+
+        assert_eq!(events[0].message.as_deref(), Some("Finalizing failed for [DID_1]"));
+        assert_eq!((events[0].tags["route"].as_str(), events[0].tags["request_id"].as_str()), ("/api/encounters/:id/finalize", "lb.7781_a"));
+    }
+}
@@ -0,0 +1,224 @@
+//! An in-process event bus for the side effects of what services do.
+//!
+//! A service makes its primary write inline, then [`publish`](EventBus::publish)es a
+//! [`DomainEvent`] and returns. Each [`EventSubscriber`] consumes the bus on its own task, so
+//! notifications and webhook enqueueing no longer hold up the request or each other.
+//!
+//! Delivery is at most once, in this process only: an event is lost if the process stops before
+//! a subscriber handled it, and a subscriber that falls more than the bus capacity behind skips
+//! the events it missed, which are counted as dropped. Anything that has to survive that hands
+//! off to the job queue (webhook deliveries do). Audit entries that compliance relies on are
+//! still written inline by the services, never from the bus.
+
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+use crate::models::{EventBusMetricsSnapshot, SubscriberMetricsSnapshot};
+
+/// Something a service did that other parts of the system react to
+#[derive(Debug, Clone, PartialEq)]
+pub enum DomainEvent {
+    EncounterFinalized { encounter_id: String, patient_did: String, actor_did: String, tenant_id: Option<String> },
+    /// `tenant_id` is the tenant the credential was issued in, if the request had one
+    CredentialIssued { credential_id: String, patient_did: String, credential_type: String, tenant_id: Option<String> },
+    AccessGranted { grant_id: String, patient_did: String, grantee_did: String },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::EncounterFinalized { .. } => "encounter_finalized",
+            Self::CredentialIssued { .. } => "credential_issued",
+            Self::AccessGranted { .. } => "access_granted",
+        }
+    }
+}
+
+/// Reacts to the events on the bus. Errors and panics are logged and counted; they never
+/// reach the service that published the event, nor stop later events.
+#[async_trait]
+pub trait EventSubscriber: Send + Sync {
+    /// Names the subscriber in logs and metrics
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+struct SubscriberCounters {
+    handled: AtomicU64,
+    failed: AtomicU64,
+    panicked: AtomicU64,
+    dropped: AtomicU64,
+}
+
+// --- EventBus ---
+pub struct EventBus {
+    sender: broadcast::Sender<DomainEvent>,
+    published: AtomicU64,
+    subscribers: Mutex<BTreeMap<&'static str, Arc<SubscriberCounters>>>,
+}
+
+impl EventBus {
+    /// Each subscriber can fall `capacity` events behind before it starts missing them
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender, published: AtomicU64::new(0), subscribers: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Hand `event` to every subscriber without waiting for any of them
+    pub fn publish(&self, event: DomainEvent) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+        // Sending only fails when nobody subscribed, and then there is nobody to tell
+        let _ = self.sender.send(event);
+    }
+
+    /// Run `subscriber` on its own task for every event published from now on. The task ends
+    /// once the bus is dropped.
+    pub fn subscribe(&self, subscriber: Arc<dyn EventSubscriber>) -> JoinHandle<()> {
+        let mut receiver = self.sender.subscribe();
+        let counters = self.subscribers.lock().unwrap().entry(subscriber.name()).or_default().clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        counters.dropped.fetch_add(missed, Ordering::Relaxed);
+                        tracing::warn!("Event subscriber {} fell behind and dropped {} events", subscriber.name(), missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                match AssertUnwindSafe(subscriber.handle(&event)).catch_unwind().await {
+                    Ok(Ok(())) => counters.handled.fetch_add(1, Ordering::Relaxed),
+                    Ok(Err(e)) => {
+                        tracing::error!("Event subscriber {} failed on {}: {:#}", subscriber.name(), event.name(), e);
+                        counters.failed.fetch_add(1, Ordering::Relaxed)
+                    }
+                    Err(_) => {
+                        tracing::error!("Event subscriber {} panicked on {}", subscriber.name(), event.name());
+                        counters.panicked.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+            }
+        })
+    }
+
+    pub fn metrics(&self) -> EventBusMetricsSnapshot {
+        let subscribers = self.subscribers.lock().unwrap();
+        EventBusMetricsSnapshot {
+            published: self.published.load(Ordering::Relaxed),
+            subscribers: subscribers
+                .iter()
+                .map(|(name, counters)| SubscriberMetricsSnapshot {
+                    name: name.to_string(),
+                    handled: counters.handled.load(Ordering::Relaxed),
+                    failed: counters.failed.load(Ordering::Relaxed),
+                    panicked: counters.panicked.load(Ordering::Relaxed),
+                    dropped: counters.dropped.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Forwards what it handles, or misbehaves on events for one patient
+    struct Probe {
+        name: &'static str,
+        seen: mpsc::UnboundedSender<DomainEvent>,
+    }
+
+    #[async_trait]
+    impl EventSubscriber for Probe {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+            if let DomainEvent::AccessGranted { patient_did, .. } = event {
+                match patient_did.as_str() {
+                    "panics" => panic!("subscriber bug"),
+                    "fails" => return Err(anyhow!("SMTP timed out")),
+                    _ => {}
+                }
+            }
+            self.seen.send(event.clone()).unwrap();
+            Ok(())
+        }
+    }
+
+    fn grant(patient_did: &str) -> DomainEvent {
+        DomainEvent::AccessGranted { grant_id: "g".to_string(), patient_did: patient_did.to_string(), grantee_did: "did:hedera:testnet:0.0.2".to_string() }
+    }
+
+    async fn next(seen: &mut mpsc::UnboundedReceiver<DomainEvent>) -> DomainEvent {
+        tokio::time::timeout(Duration::from_secs(5), seen.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_subscriber_keeps_going_after_it_panics_or_fails() {
+        let bus = EventBus::new(16);
+        let (seen, mut received) = mpsc::unbounded_channel();
+        bus.subscribe(Arc::new(Probe { name: "probe", seen }));
+
+        bus.publish(grant("panics"));
+        bus.publish(grant("fails"));
+        bus.publish(grant("did:hedera:testnet:0.0.1"));
+
+        assert_eq!(next(&mut received).await, grant("did:hedera:testnet:0.0.1"));
+        let metrics = bus.metrics();
+        assert_eq!(metrics.published, 3);
+        let probe = &metrics.subscribers[0];
+        assert_eq!((probe.handled, probe.failed, probe.panicked, probe.dropped), (1, 1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_gets_every_event() {
+        let bus = EventBus::new(16);
+        let (first, mut first_received) = mpsc::unbounded_channel();
+        let (second, mut second_received) = mpsc::unbounded_channel();
+        bus.subscribe(Arc::new(Probe { name: "first", seen: first }));
+        bus.subscribe(Arc::new(Probe { name: "second", seen: second }));
+
+        bus.publish(grant("did:hedera:testnet:0.0.1"));
+
+        assert_eq!(next(&mut first_received).await, grant("did:hedera:testnet:0.0.1"));
+        assert_eq!(next(&mut second_received).await, grant("did:hedera:testnet:0.0.1"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn a_subscriber_that_falls_behind_drops_events_instead_of_blocking_publishers() {
+        let bus = EventBus::new(2);
+        let (seen, mut received) = mpsc::unbounded_channel();
+        bus.subscribe(Arc::new(Probe { name: "slow", seen }));
+
+        // On one thread the subscriber cannot run until the publisher yields
+        for n in 0..5 {
+            bus.publish(grant(&format!("did:hedera:testnet:0.0.{}", n)));
+        }
+
+        assert_eq!(next(&mut received).await, grant("did:hedera:testnet:0.0.3"));
+        assert_eq!(next(&mut received).await, grant("did:hedera:testnet:0.0.4"));
+        assert_eq!(bus.metrics().subscribers[0].dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn publishing_without_subscribers_is_counted_and_harmless() {
+        let bus = EventBus::new(16);
+        bus.publish(grant("did:hedera:testnet:0.0.1"));
+        assert_eq!(bus.metrics(), EventBusMetricsSnapshot { published: 1, subscribers: vec![] });
+    }
+}
@@ -0,0 +1,321 @@
+//! Plausible, deterministic records for the `seed` command and the integration tests.
+//!
+//! [`Fixtures`] draws names, demographics, visit reasons and vitals from an RNG seeded with a
+//! fixed number, so the same seed always produces the same people and records; only timestamps
+//! follow the clock. The free functions build the records and requests the API takes from values
+//! the caller picked. People get `example.com` emails and no phone numbers, so nothing seeded
+//! can reach a real inbox or phone.
+
+use chrono::{Duration, NaiveDate, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use uuid::Builder;
+
+use crate::api::handlers::CreateEncounterRequest;
+use crate::models::*;
+use crate::services::fhir::{FhirCodeSystems, FhirManager};
+
+/// The seed `seed` uses unless told otherwise
+pub const DEFAULT_SEED: u64 = 42;
+
+const ENCOUNTER_CLASS_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ActCode";
+const CONDITION_CATEGORY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/condition-category";
+
+const FEMALE_NAMES: [&str; 10] = ["Wanjiru", "Achieng", "Njeri", "Akinyi", "Mumbua", "Chebet", "Nafula", "Amina", "Faith", "Grace"];
+const MALE_NAMES: [&str; 10] = ["Kamau", "Otieno", "Mwangi", "Kiprono", "Mutua", "Omondi", "Hassan", "Brian", "Kevin", "Daniel"];
+const FAMILY_NAMES: [&str; 14] =
+    ["Kamau", "Otieno", "Mwangi", "Wanjiku", "Ochieng", "Kiptoo", "Mutiso", "Wafula", "Njoroge", "Odhiambo", "Chege", "Kariuki", "Barasa", "Mohamed"];
+const STREETS: [&str; 6] = ["Moi Avenue", "Kenyatta Avenue", "Oginga Odinga Street", "Ngong Road", "Jogoo Road", "Kimathi Street"];
+/// City, county and postal code
+const TOWNS: [(&str, &str, &str); 8] = [
+    ("Nairobi", "Nairobi", "00100"),
+    ("Mombasa", "Mombasa", "80100"),
+    ("Kisumu", "Kisumu", "40100"),
+    ("Nakuru", "Nakuru", "20100"),
+    ("Eldoret", "Uasin Gishu", "30100"),
+    ("Thika", "Kiambu", "01000"),
+    ("Machakos", "Machakos", "90100"),
+    ("Nyeri", "Nyeri", "10100"),
+];
+
+/// Why a patient comes in and what is usually done about it, coded from the embedded
+/// terminology subsets so every code resolves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presentation {
+    /// ICD-10 reason for the encounter
+    pub reason: &'static str,
+    /// SNOMED CT code and display of the diagnosis recorded as a Condition
+    pub diagnosis: (&'static str, &'static str),
+    /// RxNorm code of the medication prescribed for it
+    pub medication: &'static str,
+}
+
+pub const PRESENTATIONS: [Presentation; 8] = [
+    Presentation { reason: "I10", diagnosis: ("38341003", "Hypertensive disorder, systemic arterial (disorder)"), medication: "197361" },
+    Presentation { reason: "E11.9", diagnosis: ("44054006", "Diabetes mellitus type 2 (disorder)"), medication: "861007" },
+    Presentation { reason: "J06.9", diagnosis: ("54150009", "Upper respiratory infection (disorder)"), medication: "198440" },
+    Presentation { reason: "J02.9", diagnosis: ("43878008", "Streptococcal sore throat (disorder)"), medication: "308191" },
+    Presentation { reason: "N39.0", diagnosis: ("68566005", "Urinary tract infectious disease (disorder)"), medication: "309309" },
+    Presentation { reason: "M17.9", diagnosis: ("396275006", "Osteoarthritis (disorder)"), medication: "197805" },
+    Presentation { reason: "G43.909", diagnosis: ("37796009", "Migraine (disorder)"), medication: "310965" },
+    Presentation { reason: "B54", diagnosis: ("61462000", "Malaria (disorder)"), medication: "198440" },
+];
+
+/// A seeded source of fixture data
+pub struct Fixtures {
+    rng: StdRng,
+    /// People generated so far, numbering their emails so none repeat
+    people: u32,
+}
+
+impl Fixtures {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed), people: 0 }
+    }
+
+    /// A patient's demographics: name, gender, birth date, a home address and an email
+    pub fn fhir_patient(&mut self) -> FhirPatient {
+        let female = self.rng.gen_bool(0.5);
+        let name = self.person_name(female);
+        let (city, county, postal_code) = *TOWNS.choose(&mut self.rng).unwrap();
+        let street = format!("{} {}", self.rng.gen_range(1..300), STREETS.choose(&mut self.rng).unwrap());
+        let birth_date = NaiveDate::from_ymd_opt(1940, 1, 1).unwrap() + Duration::days(self.rng.gen_range(0..30_000));
+        FhirPatient {
+            resource_type: "Patient".to_string(),
+            id: self.uuid(),
+            identifier: vec![],
+            telecom: vec![self.email(&name)],
+            name: vec![name],
+            gender: if female { "female" } else { "male" }.to_string(),
+            birth_date: birth_date.format("%Y-%m-%d").to_string(),
+            address: vec![FhirAddress {
+                r#use: Some("home".to_string()),
+                line: vec![street],
+                city: Some(city.to_string()),
+                state: Some(county.to_string()),
+                postal_code: Some(postal_code.to_string()),
+                country: Some("KE".to_string()),
+            }],
+            photo: Vec::new(),
+        }
+    }
+
+    /// A practitioner's name, with the "Dr" prefix
+    pub fn practitioner_name(&mut self) -> FhirHumanName {
+        let female = self.rng.gen_bool(0.5);
+        FhirHumanName { prefix: vec!["Dr".to_string()], ..self.person_name(female) }
+    }
+
+    pub fn presentation(&mut self) -> Presentation {
+        *PRESENTATIONS.choose(&mut self.rng).unwrap()
+    }
+
+    /// Vitals around the normal ranges, now and then a little outside them
+    pub fn vitals(&mut self, taken_at: chrono::DateTime<Utc>) -> RecordVitalsRequest {
+        let systolic = self.rng.gen_range(100..=165) as f64;
+        RecordVitalsRequest {
+            systolic: Some(systolic),
+            diastolic: Some((systolic * self.rng.gen_range(0.58..0.68)).round()),
+            heart_rate: Some(self.rng.gen_range(55..=110) as f64),
+            temperature_c: Some((self.rng.gen_range(36.0..38.6_f64) * 10.0).round() / 10.0),
+            spo2: Some(self.rng.gen_range(93..=100) as f64),
+            respiratory_rate: Some(self.rng.gen_range(12..=22) as f64),
+            weight_kg: Some((self.rng.gen_range(45.0..110.0_f64) * 10.0).round() / 10.0),
+            effective_date_time: Some(taken_at),
+        }
+    }
+
+    /// Whether something that happens with probability `p` happens this time
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.rng.gen_bool(p)
+    }
+
+    /// A number in `range`
+    pub fn between(&mut self, range: std::ops::RangeInclusive<usize>) -> usize {
+        self.rng.gen_range(range)
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        items.choose(&mut self.rng).expect("picking from no items")
+    }
+
+    fn person_name(&mut self, female: bool) -> FhirHumanName {
+        let given = if female { FEMALE_NAMES.choose(&mut self.rng) } else { MALE_NAMES.choose(&mut self.rng) };
+        FhirHumanName {
+            r#use: Some("official".to_string()),
+            family: FAMILY_NAMES.choose(&mut self.rng).map(|family| family.to_string()),
+            given: given.map(|given| given.to_string()).into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn email(&mut self, name: &FhirHumanName) -> FhirContactPoint {
+        self.people += 1;
+        let given = name.given.first().map_or("patient", String::as_str);
+        let family = name.family.as_deref().unwrap_or("wecare");
+        FhirContactPoint {
+            system: "email".to_string(),
+            value: format!("{}.{}{}@example.com", given, family, self.people).to_lowercase(),
+            r#use: Some("home".to_string()),
+        }
+    }
+
+    /// A version 4 UUID from the seeded RNG rather than the system's
+    fn uuid(&mut self) -> String {
+        Builder::from_random_bytes(self.rng.gen()).into_uuid().to_string()
+    }
+}
+
+/// The DID of Hedera account `0.0.<account>` on `network`
+pub fn did(network: &str, account: u64) -> String {
+    format!("did:hedera:{}:0.0.{}", network, account)
+}
+
+/// A patient as registration stores one, with a verified email
+pub fn patient(did: &str, fhir_patient: FhirPatient) -> Patient {
+    Patient {
+        id: None,
+        did: did.to_string(),
+        fhir_patient,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        email_verified: true,
+        verification_token: None,
+        verification_token_expires: None,
+    }
+}
+
+/// An admin's account as the alerting services read it: only an `admin@example.com` email
+#[cfg(test)]
+pub fn admin(did: &str) -> Patient {
+    let email = FhirContactPoint { system: "email".to_string(), value: "admin@example.com".to_string(), r#use: None };
+    patient(did, FhirPatient { telecom: vec![email], ..Default::default() })
+}
+
+/// A practitioner's registration, with a KMPDC license valid until 2030 that is yet to be verified
+pub fn practitioner_registration(did: &str, name: Vec<FhirHumanName>, license_number: &str, organization_identifier: Option<&str>) -> CreatePractitionerRequest {
+    CreatePractitionerRequest {
+        fhir_practitioner: FhirPractitioner {
+            resource_type: "Practitioner".to_string(),
+            id: did.to_string(),
+            identifier: vec![],
+            name,
+            qualification: vec![],
+            telecom: vec![],
+        },
+        license_verification: LicenseVerification {
+            license_number: license_number.to_string(),
+            issuing_authority: "KMPDC".to_string(),
+            issue_date: "2020-01-01".to_string(),
+            expiry_date: "2030-01-01".to_string(),
+            hedera_transaction_id: String::new(),
+            ipfs_hash: String::new(),
+            verified: false,
+            expiry_warning_sent_at: None,
+        },
+        organization_identifier: organization_identifier.map(str::to_string),
+        practitioner_type: PractitionerType::default(),
+    }
+}
+
+/// An ambulatory visit for `presentation`'s reason
+pub fn encounter_request(patient_did: &str, practitioner_did: &str, presentation: Presentation, period: FhirPeriod) -> CreateEncounterRequest {
+    CreateEncounterRequest {
+        patient_did: patient_did.to_string(),
+        practitioner_did: practitioner_did.to_string(),
+        class: FhirCoding {
+            system: Some(ENCOUNTER_CLASS_SYSTEM.to_string()),
+            code: Some("AMB".to_string()),
+            display: Some("ambulatory".to_string()),
+        },
+        reason_code: vec![FhirCodeableConcept {
+            coding: vec![FhirCoding { system: Some(FhirCodeSystems::icd10().to_string()), code: Some(presentation.reason.to_string()), display: None }],
+            text: None,
+        }],
+        period,
+    }
+}
+
+/// An ambulatory encounter as the database stores one, in the default tenant and without a
+/// bundle; tests give a finished one its `final_bundle_ipfs_hash` and `bundle_key_version`
+#[cfg(test)]
+pub fn encounter(patient_did: &str, practitioner_did: &str, status: EncounterStatus) -> Encounter {
+    Encounter {
+        id: None,
+        patient_did: patient_did.to_string(),
+        practitioner_did: practitioner_did.to_string(),
+        fhir_encounter: FhirEncounter {
+            resource_type: "Encounter".to_string(),
+            id: uuid::Uuid::new_v4().to_string(),
+            status: status.fhir_code().to_string(),
+            class: FhirCoding { system: Some(ENCOUNTER_CLASS_SYSTEM.to_string()), code: Some("AMB".to_string()), display: Some("ambulatory".to_string()) },
+            subject: FhirReference { reference: format!("Patient/{}", patient_did), display: None },
+            participant: vec![],
+            period: FhirPeriod { start: None, end: None },
+            reason_code: vec![],
+        },
+        status,
+        status_reason: None,
+        final_bundle_ipfs_hash: None,
+        bundle_key_version: None,
+        bundle_history: vec![],
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        deleted_at: None,
+        tenant_id: None,
+    }
+}
+
+/// The diagnosis of `presentation`, made at the encounter on `onset`
+pub fn condition(patient_did: &str, encounter_id: &str, presentation: Presentation, onset: NaiveDate) -> FhirCondition {
+    let (code, display) = presentation.diagnosis;
+    let concept = FhirCodeableConcept {
+        coding: vec![FhirCoding { system: Some(FhirCodeSystems::snomed().to_string()), code: Some(code.to_string()), display: Some(display.to_string()) }],
+        text: Some(display.to_string()),
+    };
+    let category = FhirCodeableConcept {
+        coding: vec![FhirCoding {
+            system: Some(CONDITION_CATEGORY_SYSTEM.to_string()),
+            code: Some("encounter-diagnosis".to_string()),
+            display: Some("Encounter Diagnosis".to_string()),
+        }],
+        text: None,
+    };
+    FhirManager::create_condition(patient_did, Some(encounter_id), concept, vec![category], onset.into(), onset.into())
+}
+
+/// A prescription of `presentation`'s medication by its RxNorm code
+pub fn prescription_request(patient_did: &str, practitioner_did: &str, encounter_id: &str, presentation: Presentation) -> CreatePrescriptionRequest {
+    CreatePrescriptionRequest {
+        patient_did: patient_did.to_string(),
+        medication_request: FhirManager::create_medication_request(patient_did, practitioner_did, Some(encounter_id), FhirCodeableConcept::default(), vec![]),
+        rxnorm_code: Some(presentation.medication.to_string()),
+        issue_credential: false,
+        force: false,
+        override_reason: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::terminology;
+
+    #[test]
+    fn the_same_seed_generates_the_same_people() {
+        let people = |seed| {
+            let mut fixtures = Fixtures::new(seed);
+            (0..5).map(|_| serde_json::to_value(fixtures.fhir_patient()).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(people(7), people(7));
+        assert_ne!(people(7), people(8));
+    }
+
+    #[test]
+    fn every_presentation_prescribes_a_known_medication() {
+        for presentation in PRESENTATIONS {
+            assert!(terminology::medication(presentation.medication).is_some(), "{}", presentation.medication);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Translations of the emails, SMS and error messages users see.
+//!
+//! Each [`Locale`] has a bundle of keyed messages embedded from `data/i18n`. English is
+//! complete; a message missing from another bundle is sent in English and counted, so
+//! coverage can be followed at `GET /api/admin/i18n/missing`.
+//!
+//! Requests run inside [`scope`] with the caller's locale: the one saved with their
+//! notification preferences when their token carries it, otherwise the `Accept-Language`
+//! header. Work outside a request, such as notifications, passes the recipient's locale.
+
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::models::{Locale, MissingTranslation};
+
+const BUNDLES: [(Locale, &str); 2] = [
+    (Locale::En, include_str!("data/i18n/en.yaml")),
+    (Locale::Sw, include_str!("data/i18n/sw.yaml")),
+];
+
+lazy_static! {
+    static ref MESSAGES: HashMap<Locale, HashMap<String, String>> = load();
+    static ref MISSING: Mutex<BTreeMap<(Locale, String), u64>> = Mutex::new(BTreeMap::new());
+}
+
+tokio::task_local! {
+    static CURRENT_LOCALE: Locale;
+}
+
+/// The embedded bundles. They ship with the binary, so one that does not parse is a build defect.
+fn load() -> HashMap<Locale, HashMap<String, String>> {
+    BUNDLES
+        .iter()
+        .map(|(locale, source)| {
+            let messages = serde_yaml::from_str(source).unwrap_or_else(|e| panic!("the {} message bundle is invalid: {}", locale.as_str(), e));
+            (*locale, messages)
+        })
+        .collect()
+}
+
+/// Run `future` with messages in `locale`
+pub async fn scope<F: Future>(locale: Locale, future: F) -> F::Output {
+    CURRENT_LOCALE.scope(locale, future).await
+}
+
+/// The locale of the running request; English outside one
+pub fn current() -> Locale {
+    CURRENT_LOCALE.try_with(|locale| *locale).unwrap_or_default()
+}
+
+/// The supported locale an `Accept-Language` header prefers, if it names one
+pub fn from_accept_language(header: &str) -> Option<Locale> {
+    let mut ranked: Vec<(f32, Locale)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let locale = Locale::from_language_tag(parts.next()?)?;
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            (quality > 0.0).then_some((quality, locale))
+        })
+        .collect();
+    // Stable, so equally weighted languages keep the header's order
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked.first().map(|(_, locale)| *locale)
+}
+
+fn lookup(locale: Locale, key: &str) -> Option<&'static str> {
+    MESSAGES.get(&locale)?.get(key).map(String::as_str)
+}
+
+/// The `key` message in `locale`, with each `{name}` replaced by its value in `args`
+pub fn text(locale: Locale, key: &str, args: &[(&str, &str)]) -> String {
+    let message = match lookup(locale, key) {
+        Some(message) => message,
+        None => {
+            if locale != Locale::En {
+                record_missing(locale, key);
+            }
+            lookup(Locale::En, key).unwrap_or_else(|| {
+                tracing::error!("No English message for {}", key);
+                key
+            })
+        }
+    };
+    args.iter().fold(message.to_string(), |message, (name, value)| message.replace(&format!("{{{}}}", name), value))
+}
+
+/// Count a message that `locale` lacks and was sent in English instead
+pub fn record_missing(locale: Locale, key: &str) {
+    let mut missing = MISSING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let count = missing.entry((locale, key.to_string())).or_insert(0);
+    if *count == 0 {
+        tracing::warn!("No {} translation of {}; sending English", locale.as_str(), key);
+    }
+    *count += 1;
+}
+
+/// Every fallback to English so far, most frequent first
+pub fn missing_translations() -> Vec<MissingTranslation> {
+    let missing = MISSING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut translations: Vec<MissingTranslation> = missing
+        .iter()
+        .map(|((locale, key), count)| MissingTranslation { locale: *locale, key: key.clone(), count: *count })
+        .collect();
+    translations.sort_by(|a, b| b.count.cmp(&a.count));
+    translations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(message: &str) -> BTreeSet<&str> {
+        message.split('{').skip(1).filter_map(|rest| rest.split_once('}').map(|(name, _)| name)).collect()
+    }
+
+    #[test]
+    fn every_locale_translates_every_english_message_with_the_same_placeholders() {
+        let english = &MESSAGES[&Locale::En];
+        for locale in Locale::ALL {
+            let messages = &MESSAGES[&locale];
+            for (key, message) in english {
+                let translation = messages.get(key).unwrap_or_else(|| panic!("{} lacks {}", locale.as_str(), key));
+                assert_eq!(placeholders(translation), placeholders(message), "{} {}", locale.as_str(), key);
+            }
+            let extra: Vec<_> = messages.keys().filter(|key| !english.contains_key(*key)).collect();
+            assert!(extra.is_empty(), "{} has keys English lacks: {:?}", locale.as_str(), extra);
+        }
+    }
+
+    #[test]
+    fn messages_are_filled_in_per_locale() {
+        assert_eq!(text(Locale::En, "sms.otp", &[("code", "123456")]), "Your OTP is: 123456");
+        assert_eq!(text(Locale::Sw, "sms.otp", &[("code", "123456")]), "Nambari yako ya kuthibitisha ni: 123456");
+    }
+
+    #[test]
+    fn missing_translations_fall_back_to_english_and_are_counted() {
+        let key = "test.only_in_english";
+        // Not in any bundle, so English lacks it too and the key itself is sent
+        assert_eq!(text(Locale::Sw, key, &[]), key);
+        text(Locale::Sw, key, &[]);
+        assert_eq!(text(Locale::En, key, &[]), key);
+
+        let counted: Vec<_> = missing_translations().into_iter().filter(|missing| missing.key == key).collect();
+        assert_eq!(counted, vec![MissingTranslation { locale: Locale::Sw, key: key.to_string(), count: 2 }]);
+    }
+
+    #[test]
+    fn accept_language_picks_the_preferred_supported_locale() {
+        assert_eq!(from_accept_language("sw-KE,sw;q=0.9,en;q=0.8"), Some(Locale::Sw));
+        assert_eq!(from_accept_language("fr-FR, en;q=0.5, sw;q=0.7"), Some(Locale::Sw));
+        assert_eq!(from_accept_language("en-GB,sw"), Some(Locale::En));
+        assert_eq!(from_accept_language("sw;q=0, en;q=0.1"), Some(Locale::En));
+        assert_eq!(from_accept_language("fr, de"), None);
+        assert_eq!(from_accept_language(""), None);
+    }
+
+    #[tokio::test]
+    async fn the_locale_is_english_outside_a_scope() {
+        assert_eq!(current(), Locale::En);
+        assert_eq!(scope(Locale::Sw, async { current() }).await, Locale::Sw);
+    }
+}
@@ -0,0 +1,112 @@
+//! A background job queue backed by the `jobs` collection.
+//!
+//! Work is enqueued as a [`Job`] with a type and a JSON payload, and run by the [`JobHandler`]
+//! registered for that type on a [`JobWorkerPool`]. Workers claim jobs atomically with a lease,
+//! so several replicas can share the collection: a job runs on one worker at a time, and a job
+//! whose worker died is picked up again once its lease lapses.
+
+pub mod worker;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::Duration;
+use std::sync::Arc;
+
+use crate::models::*;
+use crate::store::JobStore;
+
+pub use worker::JobWorkerPool;
+
+const RETRY_BASE_DELAY_SECONDS: i64 = 30;
+const MAX_RETRY_DELAY_SECONDS: i64 = 3600;
+/// Largest page the admin job list returns
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+/// Delay before the next attempt after `attempts` failures: 30s, 1m, 2m, ... capped at an hour
+pub fn retry_delay(attempts: u32) -> Duration {
+    let seconds = RETRY_BASE_DELAY_SECONDS.saturating_mul(1 << attempts.saturating_sub(1).min(16));
+    Duration::seconds(seconds.min(MAX_RETRY_DELAY_SECONDS))
+}
+
+/// Why a job did not finish
+#[derive(Debug)]
+pub enum JobError {
+    /// Running the job again later could succeed; it is retried with backoff until it runs out of attempts
+    Transient(anyhow::Error),
+    /// Retrying cannot help; the job is dead-lettered straight away
+    Permanent(anyhow::Error),
+}
+
+impl From<anyhow::Error> for JobError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Transient(e)
+    }
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transient(e) | Self::Permanent(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+/// Runs the jobs of one type
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    /// The `job_type` of the jobs this handler runs
+    fn job_type(&self) -> &'static str;
+
+    /// Recurring handlers have a single job, created when the pool starts, that runs again
+    /// this long after each successful run
+    fn repeat_every(&self) -> Option<Duration> {
+        None
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError>;
+}
+
+// --- JobQueue ---
+/// The admin view of the queue
+pub struct JobQueue {
+    store: Arc<dyn JobStore>,
+}
+
+impl JobQueue {
+    pub fn new(store: Arc<dyn JobStore>) -> Self {
+        Self { store }
+    }
+
+    /// One page of jobs, latest `run_at` first
+    pub async fn list(&self, status: Option<JobStatus>, job_type: Option<&str>, page: u64, page_size: u64) -> anyhow::Result<Page<Job>> {
+        let page = page.max(1);
+        let page_size = page_size.clamp(1, MAX_PAGE_SIZE);
+        let (items, total) = self.store.list_jobs(status, job_type, (page - 1) * page_size, page_size as i64).await?;
+        Ok(Page { items, page, page_size, total })
+    }
+
+    /// Run a job again now with a fresh set of attempts, typically one that was dead-lettered
+    pub async fn retry(&self, id: &str) -> anyhow::Result<()> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid job id"))?;
+        if !self.store.requeue_job(id, None).await? {
+            return Err(anyhow!("Job not found or running"));
+        }
+        tracing::info!("Job {} requeued", id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_an_hour() {
+        assert_eq!(retry_delay(1), Duration::seconds(30));
+        assert_eq!(retry_delay(2), Duration::seconds(60));
+        assert_eq!(retry_delay(4), Duration::seconds(240));
+        assert_eq!(retry_delay(8), Duration::seconds(3600));
+        assert_eq!(retry_delay(u32::MAX), Duration::seconds(3600));
+    }
+}
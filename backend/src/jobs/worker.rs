@@ -0,0 +1,254 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use super::{retry_delay, JobError, JobHandler};
+use crate::config::JobConfig;
+use crate::models::Job;
+use crate::store::JobStore;
+
+/// Runs due jobs with `concurrency` workers, each claiming one job at a time
+pub struct JobWorkerPool {
+    store: Arc<dyn JobStore>,
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+    job_types: Vec<String>,
+    config: JobConfig,
+    /// Identifies this process in `locked_by`, so replicas never mistake each other's locks
+    instance: String,
+}
+
+impl JobWorkerPool {
+    pub fn new(store: Arc<dyn JobStore>, config: JobConfig) -> Self {
+        Self { store, handlers: HashMap::new(), job_types: Vec::new(), config, instance: Uuid::new_v4().to_string() }
+    }
+
+    /// Run jobs of `handler.job_type()` with `handler`
+    pub fn register(mut self, handler: Arc<dyn JobHandler>) -> Self {
+        let job_type = handler.job_type();
+        if self.handlers.insert(job_type, handler).is_none() {
+            self.job_types.push(job_type.to_string());
+        }
+        self
+    }
+
+    /// Work until `shutdown` is cancelled. Jobs already running are finished first.
+    pub async fn run(self, shutdown: CancellationToken) {
+        self.schedule_recurring(Utc::now()).await;
+
+        let pool = Arc::new(self);
+        let mut workers = JoinSet::new();
+        for n in 0..pool.config.concurrency {
+            let worker = format!("{}/{}", pool.instance, n);
+            workers.spawn(pool.clone().work(worker, shutdown.clone()));
+        }
+        while let Some(result) = workers.join_next().await {
+            if let Err(e) = result {
+                tracing::error!("Job worker panicked: {}", e);
+            }
+        }
+        tracing::info!("Job workers stopped");
+    }
+
+    /// Create the job of every recurring handler that does not have one yet
+    async fn schedule_recurring(&self, now: DateTime<Utc>) {
+        for handler in self.handlers.values().filter(|handler| handler.repeat_every().is_some()) {
+            let job = Job { unique_key: Some(handler.job_type().to_string()), ..Job::new(handler.job_type(), json!({}), now) };
+            match self.store.ensure_recurring_job(&job).await {
+                Ok(true) => tracing::info!("Scheduled recurring job {}", handler.job_type()),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to schedule recurring job {}: {}", handler.job_type(), e),
+            }
+        }
+    }
+
+    async fn work(self: Arc<Self>, worker: String, shutdown: CancellationToken) {
+        let poll_interval = time::Duration::from_secs(self.config.poll_interval_seconds);
+        while !shutdown.is_cancelled() {
+            match self.run_next(&worker, Utc::now()).await {
+                // Look for the next one straight away while jobs are due
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => tracing::error!("Job worker {} failed: {}", worker, e),
+            }
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = time::sleep(poll_interval) => {}
+            }
+        }
+    }
+
+    /// Claim and run one due job as `worker`; false when no job is due
+    pub async fn run_next(&self, worker: &str, now: DateTime<Utc>) -> Result<bool> {
+        let lease = Duration::seconds(self.config.lease_seconds);
+        let Some(job) = self.store.claim_job(&self.job_types, worker, now, lease).await? else {
+            return Ok(false);
+        };
+        let id = job.id.expect("jobs are read from the database");
+        let handler = &self.handlers[job.job_type.as_str()];
+
+        let released = match handler.run(&job).await {
+            Ok(()) => match handler.repeat_every() {
+                Some(every) => self.store.reschedule_job(id, worker, Utc::now() + every, None).await?,
+                None => self.store.complete_job(id, worker).await?,
+            },
+            Err(JobError::Transient(e)) if job.attempts < self.config.max_attempts => {
+                let run_at = Utc::now() + retry_delay(job.attempts);
+                tracing::warn!("Job {} ({}) failed (attempt {}), retrying at {}: {:#}", id, job.job_type, job.attempts, run_at, e);
+                self.store.reschedule_job(id, worker, run_at, Some(&format!("{:#}", e))).await?
+            }
+            Err(e) => {
+                tracing::error!("Giving up on job {} ({}) after {} attempt(s): {}", id, job.job_type, job.attempts, e);
+                self.store.bury_job(id, worker, &e.to_string()).await?
+            }
+        };
+        if !released {
+            tracing::warn!("Job {} ({}) outlived its {}s lease and was claimed by another worker", id, job.job_type, self.config.lease_seconds);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::models::JobStatus;
+    use crate::store::MockJobStore;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use bson::oid::ObjectId;
+    use mockall::Sequence;
+
+    const WORKER: &str = "instance/0";
+
+    struct Failing(fn() -> JobError);
+
+    #[async_trait]
+    impl JobHandler for Failing {
+        fn job_type(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn run(&self, _job: &Job) -> Result<(), JobError> {
+            Err((self.0)())
+        }
+    }
+
+    struct Recurring;
+
+    #[async_trait]
+    impl JobHandler for Recurring {
+        fn job_type(&self) -> &'static str {
+            "recurring"
+        }
+
+        fn repeat_every(&self) -> Option<Duration> {
+            Some(Duration::minutes(5))
+        }
+
+        async fn run(&self, _job: &Job) -> Result<(), JobError> {
+            Ok(())
+        }
+    }
+
+    fn claimed(job_type: &str, attempts: u32) -> Job {
+        Job { id: Some(ObjectId::new()), status: JobStatus::Running, attempts, ..Job::new(job_type, json!({}), Utc::now()) }
+    }
+
+    fn claims(store: &mut MockJobStore, job: Job) {
+        let mut seq = Sequence::new();
+        store
+            .expect_claim_job()
+            .withf(|types, worker, _, lease| types.len() == 1 && worker == WORKER && *lease == Duration::seconds(300))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(move |_, _, _, _| Ok(Some(job)));
+        store.expect_claim_job().times(1).in_sequence(&mut seq).returning(|_, _, _, _| Ok(None));
+    }
+
+    /// Run jobs until none is due, returning how many ran
+    async fn drain(pool: &JobWorkerPool) -> usize {
+        let mut ran = 0;
+        while pool.run_next(WORKER, Utc::now()).await.unwrap() {
+            ran += 1;
+        }
+        ran
+    }
+
+    fn pool(store: MockJobStore, handler: Arc<dyn JobHandler>) -> JobWorkerPool {
+        JobWorkerPool::new(Arc::new(store), JobConfig::default()).register(handler)
+    }
+
+    #[tokio::test]
+    async fn transient_failure_is_rescheduled_with_backoff() {
+        let mut store = MockJobStore::new();
+        claims(&mut store, claimed("failing", 1));
+        store
+            .expect_reschedule_job()
+            .withf(|_, worker, run_at, error| worker == WORKER && *run_at > Utc::now() + Duration::seconds(20) && *error == Some("SMTP timed out"))
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        let pool = pool(store, Arc::new(Failing(|| JobError::Transient(anyhow!("SMTP timed out")))));
+
+        assert_eq!(drain(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn last_attempt_is_dead_lettered() {
+        let mut store = MockJobStore::new();
+        claims(&mut store, claimed("failing", JobConfig::default().max_attempts));
+        store.expect_bury_job().withf(|_, _, error| error == "SMTP timed out").times(1).returning(|_, _, _| Ok(true));
+        let pool = pool(store, Arc::new(Failing(|| JobError::Transient(anyhow!("SMTP timed out")))));
+
+        assert_eq!(drain(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn permanent_failure_is_not_retried() {
+        let mut store = MockJobStore::new();
+        claims(&mut store, claimed("failing", 1));
+        store.expect_bury_job().times(1).returning(|_, _, _| Ok(true));
+        store.expect_reschedule_job().never();
+        let pool = pool(store, Arc::new(Failing(|| JobError::Permanent(anyhow!("bad address")))));
+
+        assert_eq!(drain(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn recurring_jobs_are_created_once_and_run_again_with_a_clean_count() {
+        let mut store = MockJobStore::new();
+        store
+            .expect_ensure_recurring_job()
+            .withf(|job| job.job_type == "recurring" && job.unique_key.as_deref() == Some("recurring"))
+            .times(1)
+            .returning(|_| Ok(false));
+        claims(&mut store, claimed("recurring", 3));
+        store
+            .expect_reschedule_job()
+            .withf(|_, _, run_at, error| *run_at > Utc::now() + Duration::minutes(4) && error.is_none())
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        let pool = pool(store, Arc::new(Recurring));
+
+        pool.schedule_recurring(Utc::now()).await;
+        assert_eq!(drain(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn stops_when_shutdown_is_requested() {
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        let mut store = MockJobStore::new();
+        store.expect_ensure_recurring_job().returning(|_| Ok(false));
+
+        time::timeout(time::Duration::from_secs(1), pool(store, Arc::new(Recurring)).run(shutdown))
+            .await
+            .expect("workers did not stop");
+    }
+}
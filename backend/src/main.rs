@@ -1,22 +1,8 @@
-use axum::{
-    http::{StatusCode, HeaderValue, Method},
-    http::header::{AUTHORIZATION, ACCEPT, CONTENT_TYPE},
-    response::Json,
-    routing::{get, post},
-    Router,
-    middleware,
-};
+use clap::Parser;
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::str::FromStr;
-use tokio::net::TcpListener;
-use tokio::time::{self, Duration};
-use tower_http::cors::CorsLayer;
-use tracing_subscriber;
+use tracing_subscriber::util::SubscriberInitExt;
 use dotenv;
-use crate::services::hedera::ContractId;
-
-#[cfg(feature = "tls")]
-use axum_server::{tls_rustls::RustlsConfig, bind_rustls};
 
 mod api;
 mod services;
@@ -24,206 +10,86 @@ mod models;
 // mod auth;
 mod utils;
 mod auditing;
+mod backup;
+mod cli;
 mod database;
 mod config;
+mod deadline;
+mod error_reporting;
+mod events;
+mod fixtures;
+mod i18n;
+mod jobs;
+mod migrations;
+mod seed;
+mod server;
 mod state;
+mod store;
+mod telemetry;
+mod tenancy;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(all(test, feature = "integration"))]
+mod tests;
 
-use crate::auditing::{AuditLogService, AuditingService};
-// use crate::auth::auth_middleware;
-// use crate::auth::high_assurance_auth_middleware;
+use crate::cli::{Cli, Command};
 use crate::config::Config;
-use crate::database::Database;
-use crate::api::handlers::*;
-use crate::services::ipfs::IpfsClient;
-use crate::services::hedera::{HederaClient, HealthcareHederaService};
-use crate::state::AppState;
-use crate::services::{AuthService, AuthServiceImpl, PatientService, EncounterService, VerifiableCredentialService, EmailService};
-// use crate::services::twilio::TwilioService;
-use crate::api::middleware::jwt_auth::{auth_middleware, high_assurance_auth_middleware};
 
+/// Lets benchmarks measure peak memory
+#[cfg(all(test, feature = "test"))]
+#[global_allocator]
+static ALLOCATOR: utils::peak_memory::PeakMemory = utils::peak_memory::PeakMemory::new();
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     dotenv::from_path("../.env").ok();
-
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter("healthcare_backend=debug,tower_http=debug")
-        .init();
+    let cli = Cli::parse();
+    let command = cli.command.clone().unwrap_or(Command::Serve);
 
     // Load configuration
-    let config = Arc::new(Config::load()?);
-    
-    // Initialize database with retry logic
-    let database = loop {
-        match Database::new(&config.database_url).await {
-            Ok(db) => {
-                tracing::info!("Successfully connected to the database.");
-                break Arc::new(db);
-            }
-            Err(e) => {
-                tracing::error!("Failed to connect to database: {}. Retrying in 5 seconds...", e);
-                time::sleep(Duration::from_secs(5)).await;
-            }
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            return cli::config_error(&command, &e.problems);
         }
     };
 
-    // Initialize IPFS client
-    let ipfs_client = Arc::new(IpfsClient::new(&config.ipfs_url));
-
-    // Initialize Hedera client
-    let hedera_client = Arc::new(HederaClient::new(&config.hedera_account_id, &config.hedera_private_key, &config.hedera_network)?);
-    let mut hedera_service = HealthcareHederaService::new((*hedera_client).clone());
-
-    // --- Configure Contracts ---
-    let access_control_contract_id = ContractId::from_str(
-        &config.healthcare_access_control_contract_id
-    )?;
-    let credentials_contract_id = ContractId::from_str(
-        &config.verifiable_credentials_contract_id
-    )?;
-    let audit_trail_contract_id = ContractId::from_str(
-        &config.audit_trail_contract_id
-    )?;
-
-    hedera_service.set_contract_ids(
-        access_control_contract_id,
-        credentials_contract_id,
-        audit_trail_contract_id,
-    );
-
-    let hedera_service = Arc::new(hedera_service);
-
-    // Initialize services
-    let audit_log_service = Arc::new(AuditLogService::new(database.clone()));
-    let auditing_service = Arc::new(AuditingService::new(database.clone(), hedera_service.clone()));
-    // let twilio_service = Arc::new(TwilioService::new(&config));
-    let email_service = Arc::new(EmailService::new(config.clone()));
-    let auth_service = Arc::new(AuthServiceImpl::new(
-        database.clone(), 
-        hedera_client.clone(), 
-        config.clone(), 
-        audit_log_service.clone(), 
-        // twilio_service.clone(),
-        email_service.clone(), // Pass email_service here
-    ));
-    let patient_service = Arc::new(PatientService::new(database.clone(), config.clone(), audit_log_service.clone()));
-    let encounter_service = Arc::new(EncounterService::new(database.clone(), ipfs_client.clone(), config.clone(), audit_log_service.clone()));
-    let vc_service = Arc::new(VerifiableCredentialService::new(database.clone(), ipfs_client.clone(), hedera_service.clone(), audit_log_service.clone()));
-    
-    let app_state = Arc::new(AppState {
-        database: database.clone(),
-        config: config.clone(),
-        ipfs_client,
-        hedera_client,
-        hedera_service,
-        audit_log_service,
-        auditing_service: auditing_service.clone(),
-        auth_service,
-        email_service, // Add email_service to AppState
-        // twilio_service,
-        patient_service,
-        encounter_service,
-        vc_service,
-    });
-
-    // --- Spawn Background Tasks ---
-    let audit_handle = tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(3600)); // Anchor logs every hour
-        loop {
-            interval.tick().await;
-            tracing::info!("Running periodic audit log anchoring...");
-            if let Err(e) = auditing_service.anchor_audit_logs().await {
-                tracing::error!("Failed to anchor audit logs: {}", e);
-            }
+    // Initialize tracing, as configured by LOG_FORMAT and LOG_LEVEL, exporting traces when OTEL_EXPORTER_OTLP_ENDPOINT is set
+    let exporter = match telemetry::exporter(&config.telemetry) {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            eprintln!("Failed to start trace export: {}", e);
+            return ExitCode::from(cli::EXIT_FAILED);
         }
-    });
-
-    // --- Protected Routes ---
-    let protected_routes = Router::new()
-        .route("/api/patients/:id", get(get_patient))
-        .route("/api/encounters", post(create_encounter))
-        .route("/api/encounters/:id/finalize", post(finalize_encounter))
-        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth_middleware));
-
-    // --- Protected High Assurance Routes ---
-    let protected_high_assurance_routes = Router::new()
-        .route("/api/credentials/issue", post(issue_credential))
-        .route_layer(middleware::from_fn_with_state(app_state.clone(), high_assurance_auth_middleware));
-
-    // --- Public Routes ---
-    let public_routes = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/auth/initiate", post(auth_initiate))
-        .route("/api/auth/register", post(register))
-        .route("/api/auth/verify", get(verify_email))
-        .route("/api/auth/step-up", post(step_up_auth))
-        .route("/api/auth/google", post(auth_google))
-        .route("/api/auth/google/verify", post(verify_google_token))
-        // .route("/api/auth/phone/initiate", post(auth_phone_initiate))
-        // .route("/api/auth/phone/verify", post(auth_phone_verify))
-        .route("/api/chat", post(chat));
-
-    // --- Build Application ---
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], app_state.config.server_port));
-
-    // Configure CORS to allow FlutterFlow app
-    // Only the FlutterFlow frontend URL is needed since that's where your app runs
-    let frontend_url = app_state.config.frontend_base_url.trim_end_matches('/');
-    let cors = CorsLayer::new()
-        .allow_origin(frontend_url.parse::<HeaderValue>().unwrap_or_else(|_| {
-            tracing::warn!("Invalid frontend URL in config, using permissive CORS");
-            "*".parse().unwrap()
-        }))
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
-        .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS]);
-
-    let app = Router::new()
-        .merge(public_routes)
-        .merge(protected_routes)
-        .merge(protected_high_assurance_routes)
-        .layer(cors)
-        .with_state(app_state.clone());
-
-    if app_state.config.use_tls {
-        #[cfg(feature = "tls")]
-        {
-            // Configure TLS
-            let tls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(
-                "cert.pem",
-                "key.pem",
-            )
-            .await?;
-
-            tracing::info!("Server running on https://{}", addr);
-            
-            axum_server::bind_rustls(addr, tls_config)
-                .serve(app.into_make_service())
-                .await?;
-        }
-        #[cfg(not(feature = "tls"))]
-        {
-            tracing::warn!("TLS is enabled in the configuration, but the `tls` feature is not compiled. Falling back to HTTP.");
-            let listener = TcpListener::bind(addr).await?;
-            axum::serve(listener, app.into_make_service()).await?;
-        }
-    } else {
-        tracing::info!("Server running on http://{}", addr);
-        let listener = TcpListener::bind(addr).await?;
-        axum::serve(listener, app.into_make_service()).await?;
+    };
+    // Report errors and panics when SENTRY_DSN is set; dropping the guard on exit sends the last reports
+    let error_reporting = error_reporting::init(&config.error_reporting);
+    let layers = exporter.into_iter().chain(error_reporting.as_ref().map(|_| error_reporting::layer())).collect();
+    let dynamic = config.dynamic.load();
+    let (subscriber, log_level) = match command {
+        Command::Serve => telemetry::subscriber(&config.logging, &dynamic.log_level, std::io::stdout, layers),
+        // One-off tasks print their result on stdout, so their logs go to stderr
+        _ => telemetry::subscriber(&config.logging, &dynamic.log_level, std::io::stderr, layers),
+    };
+    subscriber.init();
+    if cfg!(not(feature = "otel")) && config.telemetry.otlp_endpoint.is_some() {
+        tracing::warn!("OTEL_EXPORTER_OTLP_ENDPOINT is set, but the `otel` feature is not compiled. Traces will not be exported.");
+    }
+    if let Some(path) = &cli.config {
+        tracing::info!("Configuration read from {}", path.display());
     }
-    
-    // Cleanly shut down background tasks
-    audit_handle.abort();
-
-    Ok(())
-}
 
-async fn health_check() -> Result<Json<serde_json::Value>, StatusCode> {
-    Ok(Json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now()
-    })))
+    let exit_code = match command {
+        Command::Serve => match server::run(config, log_level).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                tracing::error!("Server failed: {:#}", e);
+                ExitCode::from(cli::EXIT_FAILED)
+            }
+        },
+        command => cli::run(command, &config).await,
+    };
+    telemetry::shutdown();
+    exit_code
 }
-
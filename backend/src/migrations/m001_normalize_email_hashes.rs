@@ -0,0 +1,65 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::doc;
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::{is_duplicate_key_error, Database};
+use crate::models::EncryptedPatient;
+use crate::utils::{hash_email, EncryptionKey};
+
+/// Recompute `email_hash` from the decrypted record using the normalized
+/// (trimmed, lowercased) email, and drop the hash of "" that phone-only
+/// patients were given before the sparse unique index existed.
+pub struct NormalizeEmailHashes {
+    encryption_key: EncryptionKey,
+}
+
+impl NormalizeEmailHashes {
+    pub fn new(encryption_key: &EncryptionKey) -> Self {
+        Self { encryption_key: encryption_key.clone() }
+    }
+}
+
+#[async_trait]
+impl Migration for NormalizeEmailHashes {
+    fn id(&self) -> &'static str {
+        "0001_normalize_email_hashes"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let collection: Collection<EncryptedPatient> = db.db.collection("patients");
+        let mut cursor = collection.find(None, None).await?;
+        let (mut updated, mut conflicts) = (0, 0);
+
+        while let Some(encrypted_patient) = cursor.try_next().await? {
+            let did = encrypted_patient.did.clone();
+            let current = encrypted_patient.email_hash.clone();
+            let patient = Database::decrypt_patient(encrypted_patient, &self.encryption_key)?;
+            let expected = patient.fhir_patient.telecom.iter()
+                .find(|c| c.system == "email")
+                .map(|c| hash_email(&c.value));
+
+            if expected == current {
+                continue;
+            }
+            let update = match &expected {
+                Some(hash) => doc! { "$set": { "email_hash": hash } },
+                None => doc! { "$unset": { "email_hash": "" } },
+            };
+            match collection.update_one(doc! { "did": &did }, update, None).await {
+                Ok(_) => updated += 1,
+                Err(e) if is_duplicate_key_error(&e) => {
+                    // Two legacy records normalize to the same email; needs a manual merge
+                    tracing::warn!(did = %did, "Normalized email collides with another patient; left unchanged");
+                    conflicts += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        tracing::info!("Normalized {} email hashes ({} conflicts left for review)", updated, conflicts);
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::doc;
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::Database;
+use crate::models::EncryptedPatient;
+use crate::utils::{hash_phone, EncryptionKey};
+
+/// Populate `phone_hash` on patients created before phone lookups were indexed,
+/// so `get_patient_by_phone` no longer has to decrypt every record.
+pub struct BackfillPhoneHashes {
+    encryption_key: EncryptionKey,
+}
+
+impl BackfillPhoneHashes {
+    pub fn new(encryption_key: &EncryptionKey) -> Self {
+        Self { encryption_key: encryption_key.clone() }
+    }
+}
+
+#[async_trait]
+impl Migration for BackfillPhoneHashes {
+    fn id(&self) -> &'static str {
+        "0002_backfill_phone_hashes"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let collection: Collection<EncryptedPatient> = db.db.collection("patients");
+        let mut cursor = collection.find(doc! { "phone_hash": null }, None).await?;
+        let mut updated = 0;
+
+        while let Some(encrypted_patient) = cursor.try_next().await? {
+            let did = encrypted_patient.did.clone();
+            let patient = Database::decrypt_patient(encrypted_patient, &self.encryption_key)?;
+            if let Some(phone) = patient.fhir_patient.telecom.iter().find(|c| c.system == "phone") {
+                collection
+                    .update_one(doc! { "did": &did }, doc! { "$set": { "phone_hash": hash_phone(&phone.value) } }, None)
+                    .await?;
+                updated += 1;
+            }
+        }
+
+        tracing::info!("Backfilled {} phone hashes", updated);
+        Ok(())
+    }
+}
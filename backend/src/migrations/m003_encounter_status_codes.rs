@@ -0,0 +1,34 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::Database;
+use crate::models::EncounterStatus;
+
+/// Rewrite encounter statuses stored under the old `Planned`/`Active`/`Finalized`
+/// names as FHIR codes, with the embedded `fhir_encounter.status` brought in step.
+pub struct EncounterStatusCodes;
+
+#[async_trait]
+impl Migration for EncounterStatusCodes {
+    fn id(&self) -> &'static str {
+        "0003_encounter_status_codes"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let collection: Collection<Document> = db.db.collection("encounters");
+        let renames = [
+            ("Planned", EncounterStatus::Planned),
+            ("Active", EncounterStatus::InProgress),
+            ("Finalized", EncounterStatus::Finished),
+        ];
+        for (legacy, status) in renames {
+            let update = doc! { "$set": { "status": bson::to_bson(&status)?, "fhir_encounter.status": status.fhir_code() } };
+            let result = collection.update_many(doc! { "status": legacy }, update, None).await?;
+            tracing::info!("Moved {} {} encounters to {}", result.modified_count, legacy, status.fhir_code());
+        }
+        Ok(())
+    }
+}
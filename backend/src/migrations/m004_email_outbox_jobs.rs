@@ -0,0 +1,68 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::{is_duplicate_key_error, Database};
+use crate::models::{JobStatus, SEND_EMAIL_JOB};
+
+/// Move emails from the old `email_outbox` collection onto the job queue as `send_email`
+/// jobs, keeping their ids so earlier admin retry links still work.
+pub struct EmailOutboxJobs;
+
+#[async_trait]
+impl Migration for EmailOutboxJobs {
+    fn id(&self) -> &'static str {
+        "0004_email_outbox_jobs"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let outbox: Collection<Document> = db.db.collection("email_outbox");
+        let jobs: Collection<Document> = db.db.collection("jobs");
+        let mut cursor = outbox.find(None, None).await?;
+        let mut moved = 0;
+
+        while let Some(email) = cursor.try_next().await? {
+            let Some(id) = email.get("_id").cloned() else {
+                continue;
+            };
+            let status = match email.get_str("status").unwrap_or("pending") {
+                "sent" => JobStatus::Succeeded,
+                "failed_permanent" => JobStatus::Dead,
+                _ => JobStatus::Pending,
+            };
+            let run_at = email.get("next_attempt_at").cloned().unwrap_or(Bson::DateTime(bson::DateTime::now()));
+            let job = doc! {
+                "_id": id.clone(),
+                "job_type": SEND_EMAIL_JOB,
+                "payload": {
+                    "recipient": email.get("recipient").cloned().unwrap_or(Bson::Null),
+                    "subject": email.get("subject").cloned().unwrap_or(Bson::Null),
+                    "template": email.get("template").cloned().unwrap_or(Bson::Null),
+                    "context": email.get("context").cloned().unwrap_or(Bson::Null),
+                },
+                "status": bson::to_bson(&status)?,
+                "attempts": email.get("attempts").cloned().unwrap_or(Bson::Int64(0)),
+                "run_at": run_at.clone(),
+                "locked_by": Bson::Null,
+                "locked_until": run_at,
+                "last_error": email.get("last_error").cloned().unwrap_or(Bson::Null),
+                "created_at": email.get("created_at").cloned().unwrap_or(Bson::Null),
+                "updated_at": email.get("updated_at").cloned().unwrap_or(Bson::Null),
+            };
+            // A re-run after a crash finds the job already there
+            match jobs.insert_one(job, None).await {
+                Ok(_) => {}
+                Err(e) if is_duplicate_key_error(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            outbox.delete_one(doc! { "_id": id }, None).await?;
+            moved += 1;
+        }
+
+        tracing::info!("Moved {} outbox emails onto the job queue", moved);
+        Ok(())
+    }
+}
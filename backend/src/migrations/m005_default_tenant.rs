@@ -0,0 +1,30 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::Database;
+use crate::tenancy::{DEFAULT_TENANT, TENANT_SCOPED_COLLECTIONS};
+
+/// Put everything written before tenancy in the default tenant. Queries already treat a
+/// missing `tenant_id` as the default tenant; this makes it explicit, so the data stays put
+/// if that fallback is ever removed.
+pub struct DefaultTenant;
+
+#[async_trait]
+impl Migration for DefaultTenant {
+    fn id(&self) -> &'static str {
+        "0005_default_tenant"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        for name in TENANT_SCOPED_COLLECTIONS {
+            let collection: Collection<Document> = db.db.collection(name);
+            let update = doc! { "$set": { "tenant_id": DEFAULT_TENANT } };
+            let result = collection.update_many(doc! { "tenant_id": Bson::Null }, update, None).await?;
+            tracing::info!("Assigned {} {} to the default tenant", result.modified_count, name);
+        }
+        Ok(())
+    }
+}
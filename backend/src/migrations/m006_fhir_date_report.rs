@@ -0,0 +1,74 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use chrono::Utc;
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+use serde::de::DeserializeOwned;
+
+use super::Migration;
+use crate::database::Database;
+use crate::models::*;
+use crate::services::fhir::FhirManager;
+
+/// List stored FHIR dates that are not in a FHIR format or are out of order, such as
+/// encounters that end before they start, in `fhir_date_issues`. Nothing is changed:
+/// only someone who knows the record can say what a date like "yesterday" meant.
+pub struct FhirDateReport;
+
+#[async_trait]
+impl Migration for FhirDateReport {
+    fn id(&self) -> &'static str {
+        "0006_fhir_date_report"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let mut issues = Vec::new();
+        issues.extend(scan(db, "encounters", Some("fhir_encounter"), |encounter: FhirEncounter| FhirManager::validate_period(&encounter.period)).await?);
+        issues.extend(scan(db, "observations", None, |observation: FhirObservation| FhirManager::validate_observation(&observation)).await?);
+        issues.extend(scan(db, "conditions", None, |condition: FhirCondition| FhirManager::validate_condition(&condition)).await?);
+        issues.extend(
+            scan(db, "prescriptions", Some("fhir_medication_request"), |request: FhirMedicationRequest| FhirManager::validate_medication_request(&request))
+                .await?,
+        );
+        if !issues.is_empty() {
+            tracing::warn!("Found {} stored FHIR dates that need correcting; see GET /api/admin/fhir-date-issues", issues.len());
+        }
+        db.replace_fhir_date_issues(&issues).await
+    }
+}
+
+/// Check the resource in `field`, or the whole document, of every record in `name`
+async fn scan<T: DeserializeOwned>(
+    db: &Database,
+    name: &str,
+    field: Option<&str>,
+    check: impl Fn(T) -> Result<()>,
+) -> Result<Vec<FhirDateIssue>> {
+    let collection: Collection<Document> = db.db.collection(name);
+    let mut cursor = collection.find(None, None).await?;
+    let mut issues = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        let record_id = match document.get("_id") {
+            Some(Bson::ObjectId(id)) => id.to_hex(),
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+        let resource = match field {
+            None => document,
+            Some(field) => match document.get_document(field) {
+                Ok(resource) => resource.clone(),
+                Err(_) => continue,
+            },
+        };
+        let problem = match bson::from_document::<T>(resource) {
+            Ok(resource) => match check(resource) {
+                Ok(()) => continue,
+                Err(e) => e.to_string(),
+            },
+            Err(e) => format!("Could not be read to check its dates: {}", e),
+        };
+        issues.push(FhirDateIssue { id: None, collection: name.to_string(), record_id, problem, found_at: Utc::now() });
+    }
+    Ok(issues)
+}
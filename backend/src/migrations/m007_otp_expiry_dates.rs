@@ -0,0 +1,43 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use chrono::{DateTime, Utc};
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::Database;
+
+/// Convert `otps.expires_at` stored as an RFC 3339 string, as codes were before it became a
+/// BSON date, so they can still be verified and the TTL index expires them. A code whose
+/// expiry cannot be read is removed; it would only fail at verification.
+pub struct OtpExpiryDates;
+
+#[async_trait]
+impl Migration for OtpExpiryDates {
+    fn id(&self) -> &'static str {
+        "0007_otp_expiry_dates"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let collection: Collection<Document> = db.db.collection("otps");
+        let mut cursor = collection.find(doc! { "expires_at": { "$type": "string" } }, None).await?;
+        let (mut converted, mut removed) = (0, 0);
+        while let Some(otp) = cursor.try_next().await? {
+            let id = otp.get_object_id("_id")?;
+            match otp.get_str("expires_at")?.parse::<DateTime<Utc>>() {
+                Ok(expires_at) => {
+                    let update = doc! { "$set": { "expires_at": bson::DateTime::from_chrono(expires_at) } };
+                    collection.update_one(doc! { "_id": id }, update, None).await?;
+                    converted += 1;
+                }
+                Err(_) => {
+                    collection.delete_one(doc! { "_id": id }, None).await?;
+                    removed += 1;
+                }
+            }
+        }
+        tracing::info!("Converted the expiry of {} OTPs to dates and removed {} unreadable ones", converted, removed);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use futures_util::stream::TryStreamExt;
+use mongodb::Collection;
+
+use super::Migration;
+use crate::database::Database;
+use crate::models::EncryptedPatient;
+use crate::utils::{decrypt, encrypt, EncryptionKey};
+
+/// Give every patient written before data keys one of its own: the record, and the survivor's
+/// record kept by each merge into it, are encrypted again with a new key wrapped by the master
+/// key. Until then erasing such a patient can only drop the ciphertext, not copies of it.
+pub struct PatientDataKeys {
+    encryption_key: EncryptionKey,
+}
+
+impl PatientDataKeys {
+    pub fn new(encryption_key: &EncryptionKey) -> Self {
+        Self { encryption_key: encryption_key.clone() }
+    }
+}
+
+#[async_trait]
+impl Migration for PatientDataKeys {
+    fn id(&self) -> &'static str {
+        "0008_patient_data_keys"
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let patients: Collection<EncryptedPatient> = db.db.collection("patients");
+        let merges: Collection<Document> = db.db.collection("patient_merges");
+        let mut cursor = patients.find(doc! { "wrapped_data_key": null, "erased_at": null }, None).await?;
+        let mut updated = 0;
+
+        while let Some(encrypted_patient) = cursor.try_next().await? {
+            let data_key = EncryptionKey::generate();
+            let fhir_patient_json = decrypt(&encrypted_patient.encrypted_fhir_patient, &self.encryption_key)?;
+            let update = doc! {
+                "$set": {
+                    "encrypted_fhir_patient": encrypt(&fhir_patient_json, &data_key)?,
+                    "wrapped_data_key": data_key.wrap(&self.encryption_key)?,
+                }
+            };
+            // A record given a key by an earlier, interrupted run keeps it
+            let result = patients.update_one(doc! { "did": &encrypted_patient.did, "wrapped_data_key": null }, update, None).await?;
+            if result.modified_count == 0 {
+                continue;
+            }
+
+            let mut merged = merges.find(doc! { "survivor_did": &encrypted_patient.did, "survivor_record_before": { "$ne": "" } }, None).await?;
+            while let Some(merge) = merged.try_next().await? {
+                let record_before = decrypt(merge.get_str("survivor_record_before")?, &self.encryption_key)?;
+                merges
+                    .update_one(doc! { "_id": merge.get_object_id("_id")? }, doc! { "$set": { "survivor_record_before": encrypt(&record_before, &data_key)? } }, None)
+                    .await?;
+            }
+            updated += 1;
+        }
+
+        tracing::info!("Gave {} patient records their own data key", updated);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+mod m001_normalize_email_hashes;
+mod m002_backfill_phone_hashes;
+mod m003_encounter_status_codes;
+mod m004_email_outbox_jobs;
+mod m005_default_tenant;
+mod m006_fhir_date_report;
+mod m007_otp_expiry_dates;
+mod m008_patient_data_keys;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, DateTime, Document};
+use mongodb::{options::UpdateOptions, Collection};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::database::{is_duplicate_key_error, Database};
+
+pub use m001_normalize_email_hashes::NormalizeEmailHashes;
+pub use m002_backfill_phone_hashes::BackfillPhoneHashes;
+pub use m003_encounter_status_codes::EncounterStatusCodes;
+pub use m004_email_outbox_jobs::EmailOutboxJobs;
+pub use m005_default_tenant::DefaultTenant;
+pub use m006_fhir_date_report::FhirDateReport;
+pub use m007_otp_expiry_dates::OtpExpiryDates;
+pub use m008_patient_data_keys::PatientDataKeys;
+
+/// How long a runner may hold the migration lock before another replica can take over
+const LOCK_TTL_MS: i64 = 10 * 60 * 1000;
+const LOCK_ID: &str = "schema_migrations";
+
+/// A one-off data change applied at most once per database.
+///
+/// Migrations run in the order returned by [`all`]. `up` should still be safe to
+/// re-run, since a crash between `up` and recording the id will repeat it.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// Unique, never-reused identifier recorded in `schema_migrations`
+    fn id(&self) -> &'static str;
+    async fn up(&self, db: &Database) -> Result<()>;
+}
+
+/// Every migration, in application order. New migrations go at the end.
+pub fn all(config: &Config) -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(NormalizeEmailHashes::new(&config.ipfs_encryption_key)),
+        Box::new(BackfillPhoneHashes::new(&config.ipfs_encryption_key)),
+        Box::new(EncounterStatusCodes),
+        Box::new(EmailOutboxJobs),
+        Box::new(DefaultTenant),
+        Box::new(FhirDateReport),
+        Box::new(OtpExpiryDates),
+        Box::new(PatientDataKeys::new(&config.ipfs_encryption_key)),
+    ]
+}
+
+/// Apply all pending migrations, returning the ids that were applied.
+pub async fn run(db: &Database, config: &Config) -> Result<Vec<String>> {
+    run_migrations(db, &all(config)).await
+}
+
+pub async fn run_migrations(db: &Database, migrations: &[Box<dyn Migration>]) -> Result<Vec<String>> {
+    let owner = Uuid::new_v4().to_string();
+    if !acquire_lock(db, &owner).await? {
+        tracing::info!("Another instance is running migrations; skipping.");
+        return Ok(Vec::new());
+    }
+
+    let result = apply_pending(db, migrations).await;
+    if let Err(e) = release_lock(db, &owner).await {
+        tracing::warn!("Failed to release migration lock: {}", e);
+    }
+    result
+}
+
+async fn apply_pending(db: &Database, migrations: &[Box<dyn Migration>]) -> Result<Vec<String>> {
+    let applied_collection: Collection<Document> = db.db.collection("schema_migrations");
+    let mut applied = Vec::new();
+
+    for migration in migrations {
+        let id = migration.id();
+        if applied_collection.find_one(doc! { "_id": id }, None).await?.is_some() {
+            tracing::debug!("Migration {} already applied", id);
+            continue;
+        }
+
+        tracing::info!("Applying migration {}...", id);
+        let started = std::time::Instant::now();
+        migration.up(db).await.map_err(|e| e.context(format!("Migration {} failed", id)))?;
+
+        applied_collection
+            .insert_one(doc! { "_id": id, "applied_at": DateTime::now() }, None)
+            .await?;
+        tracing::info!("Applied migration {} in {:?}", id, started.elapsed());
+        applied.push(id.to_string());
+    }
+
+    Ok(applied)
+}
+
+/// Take the lock document, or return `false` if another runner holds an unexpired lock.
+async fn acquire_lock(db: &Database, owner: &str) -> Result<bool> {
+    let locks: Collection<Document> = db.db.collection("schema_migrations_lock");
+    let now = DateTime::now();
+    let locked_until = DateTime::from_millis(now.timestamp_millis() + LOCK_TTL_MS);
+
+    // Matches only a free or expired lock; otherwise the upsert collides on `_id`
+    let filter = doc! { "_id": LOCK_ID, "locked_until": { "$lt": now } };
+    let update = doc! { "$set": { "locked_by": owner, "locked_until": locked_until } };
+    let options = UpdateOptions::builder().upsert(true).build();
+
+    match locks.update_one(filter, update, options).await {
+        Ok(_) => Ok(true),
+        Err(e) if is_duplicate_key_error(&e) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn release_lock(db: &Database, owner: &str) -> Result<()> {
+    let locks: Collection<Document> = db.db.collection("schema_migrations_lock");
+    locks.delete_one(doc! { "_id": LOCK_ID, "locked_by": owner }, None).await?;
+    Ok(())
+}
@@ -0,0 +1,283 @@
+//! FHIR `date` and `dateTime` values, as used by periods, observations, conditions and prescriptions.
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Offsets furthest ahead of and behind UTC, for reading a date whose zone is unknown
+const EARLIEST_ZONE: Tz = chrono_tz::Etc::GMTMinus14;
+const LATEST_ZONE: Tz = chrono_tz::Etc::GMTPlus12;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("'{0}' is not a FHIR date or dateTime: use YYYY, YYYY-MM, YYYY-MM-DD or YYYY-MM-DDThh:mm:ss with Z or an offset such as +03:00")]
+pub struct InvalidFhirDateTime(pub String);
+
+/// A FHIR `date` or `dateTime`: a year, a month, a day, or a time of day with its UTC offset.
+///
+/// The text is kept as written, so values round-trip unchanged. Parsing it is strict, but
+/// deserializing accepts any string: records stored before dates were checked still load,
+/// and [`FhirDateTime::is_valid`] tells them apart. New input is checked by the
+/// `FhirManager::validate_*` functions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FhirDateTime(String);
+
+/// What a valid [`FhirDateTime`] names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parsed {
+    Year(i32),
+    Month(i32, u32),
+    Day(NaiveDate),
+    Instant(DateTime<FixedOffset>),
+}
+
+impl FhirDateTime {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the value is in one of the FHIR formats; only data stored before they were enforced is not
+    pub fn is_valid(&self) -> bool {
+        parse(&self.0).is_some()
+    }
+
+    /// Whether it names a day or longer rather than a moment
+    pub fn is_date_only(&self) -> bool {
+        matches!(parse(&self.0), Some(Parsed::Year(_) | Parsed::Month(..) | Parsed::Day(_)))
+    }
+
+    /// The first moment it covers. Dates without a time start at midnight in `timezone`.
+    pub fn earliest(&self, timezone: Tz) -> Option<DateTime<Utc>> {
+        let first_day = match parse(&self.0)? {
+            Parsed::Instant(at) => return Some(at.with_timezone(&Utc)),
+            Parsed::Year(year) => NaiveDate::from_ymd_opt(year, 1, 1)?,
+            Parsed::Month(year, month) => NaiveDate::from_ymd_opt(year, month, 1)?,
+            Parsed::Day(day) => day,
+        };
+        midnight(first_day, timezone)
+    }
+
+    /// The last moment it covers. Dates without a time end just before the next midnight in `timezone`.
+    pub fn latest(&self, timezone: Tz) -> Option<DateTime<Utc>> {
+        let next_day = match parse(&self.0)? {
+            Parsed::Instant(at) => return Some(at.with_timezone(&Utc)),
+            Parsed::Year(year) => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+            Parsed::Month(year, 12) => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+            Parsed::Month(year, month) => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+            Parsed::Day(day) => day.succ_opt()?,
+        };
+        Some(midnight(next_day, timezone)? - Duration::nanoseconds(1))
+    }
+
+    /// Whether it certainly comes after `other`. Two dates are compared as written; when only
+    /// one has a time, the date is read in whichever zone is most favourable to the order.
+    pub fn is_after(&self, other: &FhirDateTime) -> bool {
+        let (start_zone, end_zone) = if self.is_date_only() && other.is_date_only() {
+            (Tz::UTC, Tz::UTC)
+        } else {
+            (EARLIEST_ZONE, LATEST_ZONE)
+        };
+        match (self.earliest(start_zone), other.latest(end_zone)) {
+            (Some(earliest), Some(latest)) => earliest > latest,
+            _ => false,
+        }
+    }
+
+    /// The value as a patient in `timezone` would read it: the local date of a time, and dates as written
+    pub fn date_in(&self, timezone: Tz) -> String {
+        match parse(&self.0) {
+            Some(Parsed::Instant(at)) => at.with_timezone(&timezone).date_naive().to_string(),
+            _ => self.0.clone(),
+        }
+    }
+}
+
+fn midnight(day: NaiveDate, timezone: Tz) -> Option<DateTime<Utc>> {
+    // A midnight skipped by a DST change starts the day at its first hour instead
+    let start = (0..3).find_map(|hour| timezone.from_local_datetime(&day.and_hms_opt(hour, 0, 0)?).earliest())?;
+    Some(start.with_timezone(&Utc))
+}
+
+/// `count` ASCII digits as a number
+fn digits(text: &str, count: usize) -> Option<u32> {
+    (text.len() == count && text.bytes().all(|b| b.is_ascii_digit())).then(|| text.parse().ok())?
+}
+
+fn parse(value: &str) -> Option<Parsed> {
+    let year = digits(value.get(..4)?, 4)? as i32;
+    if year == 0 {
+        return None;
+    }
+    let rest = &value[4..];
+    if rest.is_empty() {
+        return Some(Parsed::Year(year));
+    }
+    let month = digits(rest.strip_prefix('-')?.get(..2)?, 2)?;
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+    let rest = &rest[3..];
+    if rest.is_empty() {
+        return Some(Parsed::Month(year, month));
+    }
+    let day = NaiveDate::from_ymd_opt(year, month, digits(rest.strip_prefix('-')?.get(..2)?, 2)?)?;
+    let rest = &rest[3..];
+    if rest.is_empty() {
+        return Some(Parsed::Day(day));
+    }
+    // FHIR wants seconds and an offset with every time, which RFC 3339 requires too
+    if !rest.starts_with('T') {
+        return None;
+    }
+    let at = DateTime::parse_from_rfc3339(value).ok()?;
+    (at.date_naive() == day).then_some(Parsed::Instant(at))
+}
+
+impl FromStr for FhirDateTime {
+    type Err = InvalidFhirDateTime;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match parse(value) {
+            Some(_) => Ok(Self(value.to_string())),
+            None => Err(InvalidFhirDateTime(value.to_string())),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for FhirDateTime {
+    fn from(at: DateTime<Utc>) -> Self {
+        Self(at.to_rfc3339())
+    }
+}
+
+impl From<NaiveDate> for FhirDateTime {
+    fn from(day: NaiveDate) -> Self {
+        Self(day.format("%Y-%m-%d").to_string())
+    }
+}
+
+impl fmt::Display for FhirDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> FhirDateTime {
+        value.parse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn accepts_every_fhir_precision() {
+        let cases = [
+            ("2024", Parsed::Year(2024)),
+            ("2024-02", Parsed::Month(2024, 2)),
+            ("2024-02-29", Parsed::Day(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap())),
+            ("2024-02-29T09:30:00Z", Parsed::Instant(DateTime::parse_from_rfc3339("2024-02-29T09:30:00Z").unwrap())),
+            ("2024-02-29T09:30:00+03:00", Parsed::Instant(DateTime::parse_from_rfc3339("2024-02-29T06:30:00Z").unwrap())),
+            ("2024-02-29T09:30:00.125-05:00", Parsed::Instant(DateTime::parse_from_rfc3339("2024-02-29T14:30:00.125Z").unwrap())),
+            ("0001-01-01", Parsed::Day(NaiveDate::from_ymd_opt(1, 1, 1).unwrap())),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse(value), Some(expected), "{}", value);
+            assert_eq!(at(value).as_str(), value, "kept as written");
+        }
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        let cases = [
+            "",
+            "yesterday",
+            "24",
+            "0000",
+            "2024-2",
+            "2024-13",
+            "2024-00",
+            "2023-02-29",
+            "2024-04-31",
+            "2024-02-29T09:30",
+            "2024-02-29T09:30Z",
+            "2024-02-29T09:30:00",
+            "2024-02-29 09:30:00Z",
+            "2024-02-29t09:30:00Z",
+            "2024-02-29T25:00:00Z",
+            "2024-02-29T09:30:00+3:00",
+            " 2024-02-29",
+            "2024-02-29 ",
+            "29/02/2024",
+            "+2024-02-29",
+        ];
+        for value in cases {
+            assert_eq!(value.parse::<FhirDateTime>(), Err(InvalidFhirDateTime(value.to_string())), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn stored_values_load_whatever_they_hold() {
+        let loaded: FhirDateTime = serde_json::from_str("\"yesterday\"").unwrap();
+        assert!(!loaded.is_valid());
+        assert_eq!(loaded.earliest(Tz::UTC), None);
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), "\"yesterday\"");
+    }
+
+    #[test]
+    fn dates_cover_their_whole_span_in_the_given_zone() {
+        let nairobi: Tz = "Africa/Nairobi".parse().unwrap();
+        let utc = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc);
+        let cases = [
+            ("2024", "2023-12-31T21:00:00Z", "2024-12-31T20:59:59.999999999Z"),
+            ("2024-12", "2024-11-30T21:00:00Z", "2024-12-31T20:59:59.999999999Z"),
+            ("2024-03-05", "2024-03-04T21:00:00Z", "2024-03-05T20:59:59.999999999Z"),
+            ("2024-03-05T10:00:00+01:00", "2024-03-05T09:00:00Z", "2024-03-05T09:00:00Z"),
+        ];
+        for (value, earliest, latest) in cases {
+            assert_eq!(at(value).earliest(nairobi), Some(utc(earliest)), "{}", value);
+            assert_eq!(at(value).latest(nairobi), Some(utc(latest)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn a_skipped_midnight_starts_the_day_at_the_next_hour() {
+        // Santiago moved its clocks from midnight to 01:00 on 2024-09-08
+        let santiago: Tz = "America/Santiago".parse().unwrap();
+        let expected = DateTime::parse_from_rfc3339("2024-09-08T04:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(at("2024-09-08").earliest(santiago), Some(expected));
+    }
+
+    #[test]
+    fn ordering_reads_dates_without_a_zone_generously() {
+        let cases = [
+            ("2024-03-06", "2024-03-05", true),
+            ("2024-03-05", "2024-03-05", false),
+            ("2024-03", "2024-03-31", false),
+            ("2024-04", "2024-03-31", true),
+            ("2024-03-05T10:00:00Z", "2024-03-05T09:59:59Z", true),
+            ("2024-03-05T09:00:00+03:00", "2024-03-05T06:00:00Z", false),
+            // Late on the 5th in some zone is still the 5th somewhere else
+            ("2024-03-06T08:00:00Z", "2024-03-05", false),
+            ("2024-03-07T00:00:00Z", "2024-03-05", true),
+            ("2024-03-05", "2024-03-04T12:00:00Z", false),
+            ("2024-03-06", "2024-03-04T12:00:00Z", true),
+            ("yesterday", "2024-03-05", false),
+        ];
+        for (start, end, after) in cases {
+            let start = FhirDateTime(start.to_string());
+            assert_eq!(start.is_after(&FhirDateTime(end.to_string())), after, "{} after {}", start, end);
+        }
+    }
+
+    #[test]
+    fn summaries_show_the_patients_local_date() {
+        let nairobi: Tz = "Africa/Nairobi".parse().unwrap();
+        assert_eq!(at("2024-03-05T22:30:00Z").date_in(nairobi), "2024-03-06");
+        assert_eq!(at("2024-03-05T22:30:00Z").date_in(Tz::UTC), "2024-03-05");
+        assert_eq!(at("2024-03").date_in(nairobi), "2024-03");
+    }
+}
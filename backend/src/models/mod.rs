@@ -3,6 +3,10 @@ use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 use mongodb::bson::doc;
 
+mod fhir_datetime;
+
+pub use fhir_datetime::{FhirDateTime, InvalidFhirDateTime};
+
 // Core entity models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Patient {
@@ -23,12 +27,212 @@ pub struct EncryptedPatient {
     pub id: Option<ObjectId>,
     pub did: String,
     pub encrypted_fhir_patient: String,
-    pub email_hash: String,
+    /// Absent for patients registered without an email (sparse unique index)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone_hash: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub email_verified: bool,
     pub verification_token: Option<String>,
     pub verification_token_expires: Option<DateTime<Utc>>,
+    /// Tombstone set by a soft delete; live records have no value here
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// The patient's own data key, encrypted with the master key; `encrypted_fhir_patient` is
+    /// encrypted with it. Absent on records from before data keys, which use the master key,
+    /// and on erased ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_data_key: Option<String>,
+    /// Set once a deleted patient is erased past the restore window: the data key is destroyed,
+    /// so the record can no longer be decrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub erased_at: Option<DateTime<Utc>>,
+    /// Unset until the patient saves preferences; readers fall back to the defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification_preferences: Option<NotificationPreferences>,
+    /// Whether the patient lets the chat assistant read a summary of their record
+    #[serde(default)]
+    pub chat_record_consent: bool,
+    /// IANA time zone (e.g. `Africa/Nairobi`) used for times in reminders; UTC when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Authenticator app enrolled as a second factor, pending until its first code is confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<TotpEnrollment>,
+    /// One-time recovery codes, kept only as salted hashes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_codes: Vec<BackupCode>,
+    /// Set while an admin has suspended the account; suspended patients cannot sign in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// DIDs of duplicate records merged into this one; looking them up finds this patient
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_dids: Vec<String>,
+    /// Keyed blind-index tokens of the patient's name, for practitioner search; see `utils::name_search_tokens`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub search_tokens: Vec<String>,
+    /// Which search key made `search_tokens`; records made with another key, or none, get indexed again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_key_id: Option<String>,
+    /// Shown at the front desk to confirm who the patient is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photo: Option<PatientPhoto>,
+}
+
+/// Whether a patient's account may still be used, as the auth middleware checks on each request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Active,
+    /// Suspended by an admin
+    Disabled,
+    /// Soft-deleted; the record is kept until it is purged
+    Deleted,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Disabled => "disabled",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// The shared secret, encrypted like the rest of the patient record
+    pub encrypted_secret: String,
+    /// Unset until a code from the app has been confirmed; only then is it a usable factor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmed_at: Option<DateTime<Utc>>,
+    /// Time step of the last accepted code, so no code is accepted twice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_counter: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCode {
+    /// Hex, unique to this code
+    pub salt: String,
+    /// Hex SHA-256 of the salt followed by the code
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+/// A new set of backup codes; this is the only time they are shown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCodes {
+    pub codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCodeStatus {
+    pub remaining: usize,
+    /// Set once every code has been used, so the app can prompt for a new set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// What an authenticator app needs to be set up; the secret is only ever shown here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpEnrollmentStarted {
+    /// Base32, for typing into an app by hand
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// `otpauth_uri` as an SVG QR code
+    pub qr_svg: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// Second factors a session can be stepped up with, named in the high-assurance token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactor {
+    Sms,
+    Totp,
+    BackupCode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepUpRequest {
+    pub method: SecondFactor,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepUpResponse {
+    /// A high-assurance token to use in place of the caller's own
+    pub token: String,
+    pub second_factor: SecondFactor,
+    pub expires_at: DateTime<Utc>,
+    /// After stepping up with a backup code, how many unused ones are left
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_codes_remaining: Option<usize>,
+}
+
+/// An address refused by the sign-in endpoints after too many failed sign-ins
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpBlock {
+    pub ip: String,
+    pub blocked_at: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    /// Failed sign-ins, and distinct accounts they were for, within the window that led to the block
+    pub failures: usize,
+    pub accounts: usize,
+}
+
+/// A failed sign-in, remembered for the length of the detection window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedLogin {
+    pub at: DateTime<Utc>,
+    /// The account tried: a DID, or a hashed phone number before there is one
+    pub subject: String,
+}
+
+/// Recent failed sign-ins and any block of one address, as saved between restarts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginActivity {
+    pub ip: String,
+    pub failures: Vec<FailedLogin>,
+    pub block: Option<IpBlock>,
+}
+
+/// A sign-in, recorded when its token is issued. Unrevoked sessions are the account's known devices.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub did: String,
+    /// Sent by the app in `X-Installation-Id`; browsers have none
+    pub installation_id: Option<String>,
+    /// Browser or app and operating system, e.g. "Chrome on Android"
+    pub user_agent_family: String,
+    /// The client address with its host part dropped; the full address is not kept
+    pub subnet: Option<String>,
+    /// Approximate, and only looked up for sign-ins from a new device
+    pub location: Option<String>,
+    /// SHA-256 of the token in the new-device alert's revoke link
+    pub revoke_token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PractitionerType {
+    #[default]
+    Clinician,
+    /// Dispenses prescriptions once their license is verified
+    Pharmacist,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +242,11 @@ pub struct Practitioner {
     pub did: String,
     pub fhir_practitioner: FhirPractitioner,
     pub license_verification: LicenseVerification,
+    #[serde(default)]
+    pub practitioner_type: PractitionerType,
+    /// Tenant (clinic) the document belongs to; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -50,15 +259,362 @@ pub struct Encounter {
     pub practitioner_did: String,
     pub fhir_encounter: FhirEncounter,
     pub status: EncounterStatus,
+    /// Why it was cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
     pub final_bundle_ipfs_hash: Option<String>,
+    /// Version of the key the bundle is encrypted with; unset on bundles archived before
+    /// versions were recorded, which use [`LEGACY_BUNDLE_KEY_VERSION`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_key_version: Option<u32>,
+    /// Bundles this encounter pointed at before being re-encrypted, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bundle_history: Vec<BundleVersion>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Tenant (clinic) the document belongs to; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+/// Key version of bundles archived before versions were recorded
+pub const LEGACY_BUNDLE_KEY_VERSION: u32 = 1;
+
+/// A replaced copy of an encounter's bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleVersion {
+    pub ipfs_hash: String,
+    pub key_version: u32,
+    pub replaced_at: DateTime<Utc>,
 }
 
+/// A file attached to an encounter, such as a scan or a lab report, stored encrypted on IPFS
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub encounter_id: String,
+    pub patient_did: String,
+    pub uploaded_by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// The type the content was recognised as, whatever the upload declared
+    pub content_type: String,
+    pub size: u64,
+    /// Served by `GET /api/files/:cid`
+    pub ipfs_hash: String,
+    pub key_version: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A patient's photo for confirming who they are at the front desk. Both images are stored
+/// encrypted on IPFS and served by `GET /api/patients/:did/photo`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatientPhoto {
+    pub full: StoredPhoto,
+    pub thumbnail: StoredPhoto,
+    /// Of the full image, in pixels
+    pub width: u32,
+    pub height: u32,
+    pub key_version: u32,
+    pub uploaded_by: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+impl PatientPhoto {
+    /// How the FHIR Patient resource refers to the photo
+    pub fn fhir_attachment(&self, patient_did: &str) -> FhirAttachment {
+        FhirAttachment {
+            content_type: self.full.content_type.clone(),
+            url: format!("/api/patients/{}/photo", patient_did),
+            size: self.full.size,
+            title: Some("Patient photo".to_string()),
+            creation: Some(self.uploaded_at.to_rfc3339()),
+        }
+    }
+}
+
+/// One image of a [`PatientPhoto`] as stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredPhoto {
+    pub ipfs_hash: String,
+    pub content_type: String,
+    /// Of the image itself, before encryption
+    pub size: u64,
+    /// Hex SHA-256 of the image itself, checked when it is served
+    pub sha256: String,
+}
+
+/// Which image of a patient photo to serve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PhotoSize {
+    #[default]
+    Full,
+    Thumb,
+}
+
+/// Where an encounter is in its lifecycle, stored as its FHIR `Encounter.status` code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum EncounterStatus {
+    /// Booked through a confirmed appointment but not started yet
+    Planned,
+    InProgress,
+    /// Finalized, with its bundle on IPFS
+    Finished,
+    Cancelled,
+}
+
+impl EncounterStatus {
+    /// The matching FHIR `Encounter.status` code
+    pub fn fhir_code(self) -> &'static str {
+        match self {
+            Self::Planned => "planned",
+            Self::InProgress => "in-progress",
+            Self::Finished => "finished",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// The status for a FHIR `Encounter.status` code, if it is one encounters here go through
+    pub fn from_fhir_code(code: &str) -> Option<Self> {
+        [Self::Planned, Self::InProgress, Self::Finished, Self::Cancelled].into_iter().find(|status| status.fhir_code() == code)
+    }
+
+    /// Planned encounters start, started ones finish, and either may be cancelled.
+    /// Finished and cancelled encounters are final.
+    pub fn can_become(self, to: Self) -> bool {
+        matches!(
+            (self, to),
+            (Self::Planned, Self::InProgress) | (Self::InProgress, Self::Finished) | (Self::Planned | Self::InProgress, Self::Cancelled)
+        )
+    }
+}
+
+/// A new encounter with what checking its codes found
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncounterCreated {
+    #[serde(flatten)]
+    pub encounter: Encounter,
+    pub code_warnings: Vec<CodeWarning>,
+}
+
+// Terminology
+/// Code systems the terminology service knows, as named in API parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeSystem {
+    Icd10,
+    Loinc,
+    Snomed,
+    Rxnorm,
+}
+
+/// A code and its display text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerminologyConcept {
+    /// The system's canonical URI, as used in FHIR codings
+    pub system: String,
+    pub code: String,
+    pub display: String,
+}
+
+/// A medication from the RxNorm subset, for the prescribing UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MedicationConcept {
+    /// RxNorm concept id (RXCUI)
+    pub code: String,
+    pub display: String,
+    /// Not known for ingredients
+    pub dose_form: Option<String>,
+    pub strength: Option<String>,
+}
+
+/// A submitted coding that could not be confirmed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CodeWarning {
+    pub system: String,
+    pub code: String,
+    pub message: String,
+}
+
+// Appointments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppointmentStatus {
+    Requested,
+    Confirmed,
+    Cancelled,
+}
+
+/// Which side of an appointment a listing is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AppointmentParty {
+    #[default]
+    Patient,
+    Practitioner,
+}
+
+/// A visit a patient asked for; confirming it can plan the matching encounter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Appointment {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub patient_did: String,
+    pub practitioner_did: String,
+    pub status: AppointmentStatus,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub reason: FhirCodeableConcept,
+    pub location: String,
+    /// The planned encounter created when the appointment was confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encounter_id: Option<ObjectId>,
+    #[serde(default)]
+    pub reminder_sent_at: ReminderMarkers,
+    /// Start of every availability slot the appointment covers. No two confirmed
+    /// appointments of a practitioner may share one (enforced by a unique index).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slots: Vec<DateTime<Utc>>,
+    /// Tenant (clinic) the document belongs to; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// When each reminder for an appointment was claimed for sending
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReminderMarkers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub day_before: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_hours_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderStage {
+    DayBefore,
+    TwoHoursBefore,
+}
+
+impl ReminderStage {
+    /// Field under `reminder_sent_at` that marks this reminder as sent
+    pub fn marker(self) -> &'static str {
+        match self {
+            Self::DayBefore => "day_before",
+            Self::TwoHoursBefore => "two_hours_before",
+        }
+    }
+
+    /// How far ahead of the start the reminder goes out
+    pub fn lead_time(self) -> chrono::Duration {
+        match self {
+            Self::DayBefore => chrono::Duration::hours(24),
+            Self::TwoHoursBefore => chrono::Duration::hours(2),
+        }
+    }
+
+    /// Appointments starting in `(now + lower, now + upper]` are due this reminder.
+    /// The day-before window stops where the two-hour one begins, so an appointment
+    /// confirmed at short notice gets one reminder rather than two at once.
+    pub fn window(self) -> (chrono::Duration, chrono::Duration) {
+        match self {
+            Self::DayBefore => (Self::TwoHoursBefore.lead_time(), self.lead_time()),
+            Self::TwoHoursBefore => (chrono::Duration::zero(), self.lead_time()),
+        }
+    }
+}
+
+// Availability
+/// The hours a practitioner takes appointments, in their own time zone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub practitioner_did: String,
+    /// IANA name the weekly windows and exception dates are in
+    pub timezone: String,
+    pub weekly: Vec<WeeklyWindow>,
+    #[serde(default)]
+    pub exceptions: Vec<AvailabilityException>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Recurring hours on one day of every week
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeeklyWindow {
+    pub weekday: chrono::Weekday,
+    pub start: chrono::NaiveTime,
+    pub end: chrono::NaiveTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: chrono::NaiveTime,
+    pub end: chrono::NaiveTime,
+}
+
+/// Replaces the weekly hours on one date; no windows means the day is off
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailabilityException {
+    pub date: chrono::NaiveDate,
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+/// One bookable interval, `[start, end)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// The part of the FHIR `MedicationRequest.status` lifecycle prescribers drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PrescriptionStatus {
+    #[default]
     Active,
-    Finalized,
+    Completed,
+    Stopped,
+    Cancelled,
+}
+
+impl PrescriptionStatus {
+    /// The matching FHIR `MedicationRequest.status` code
+    pub fn fhir_code(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Completed => "completed",
+            Self::Stopped => "stopped",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Only active prescriptions move on, and every other status is final
+    pub fn can_become(self, to: Self) -> bool {
+        self == Self::Active && to != Self::Active
+    }
+
+    pub fn can_dispense(self) -> bool {
+        self == Self::Active
+    }
+}
+
+/// A pharmacy handing out medication against a prescription, after FHIR `MedicationDispense`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dispense {
+    pub quantity: FhirQuantity,
+    pub days_supply: u32,
+    /// The pharmacist's DID
+    pub performer_did: String,
+    pub dispensed_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,10 +624,104 @@ pub struct Prescription {
     pub patient_did: String,
     pub practitioner_did: String,
     pub fhir_medication_request: FhirMedicationRequest,
+    /// Prescriptions written before statuses were tracked are active
+    #[serde(default)]
+    pub status: PrescriptionStatus,
+    #[serde(default)]
+    pub dispenses: Vec<Dispense>,
+    /// Set once everything prescribed was handed out; no further dispenses are accepted
+    #[serde(default)]
+    pub fully_dispensed: bool,
+    /// The verifiable credential pharmacies check, if one was issued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<PrescriptionCredential>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Prescription {
+    /// The quantity prescribed, if the request set `dispenseRequest.quantity`
+    pub fn prescribed_quantity(&self) -> Option<f64> {
+        self.fhir_medication_request.dispense_request.as_ref()?.quantity.as_ref()?.value
+    }
+
+    pub fn dispensed_quantity(&self) -> f64 {
+        self.dispenses.iter().filter_map(|dispense| dispense.quantity.value).sum()
+    }
+}
+
+pub const PRESCRIPTION_CREDENTIAL_TYPE: &str = "PrescriptionCredential";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrescriptionCredential {
+    /// IPFS hash of the credential document, as anchored on Hedera
+    pub hash: String,
+    /// What the patient's QR code encodes: `rx:<hash>:<prescription hash>`
+    pub qr_payload: String,
+}
+
+/// The public part of a prescription credential, stored with it on the ledger.
+/// It names no patient and only a hash of the prescription id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrescriptionCredentialMetadata {
+    pub medication_code: Option<String>,
+    pub medication: Option<String>,
+    pub quantity: Option<FhirQuantity>,
+    pub prescriber_did: String,
+    /// Hex SHA-256 of the prescription id
+    pub prescription_hash: String,
+}
+
+// Consent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentStatus {
+    Active,
+    Inactive,
+}
+
+impl ConsentStatus {
+    /// The matching FHIR `Consent.status` code
+    pub fn fhir_code(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Inactive => "inactive",
+        }
+    }
+}
+
+/// A patient's explicit consent to share part of their record with one actor.
+/// The FHIR document is also stored encrypted on IPFS under `ipfs_hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Consent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub patient_did: String,
+    pub grantee_did: String,
+    pub status: ConsentStatus,
+    /// FHIR resource types the grantee may see, e.g. `Encounter`
+    pub data_classes: Vec<String>,
+    pub period_start: DateTime<Utc>,
+    pub period_end: Option<DateTime<Utc>>,
+    /// The access grant this consent was recorded for, when it came from one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_control_id: Option<ObjectId>,
+    pub fhir_consent: FhirConsent,
+    pub ipfs_hash: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Consent {
+    /// Whether this consent lets its grantee see `data_class` at `now`
+    pub fn covers(&self, data_class: &str, now: DateTime<Utc>) -> bool {
+        self.status == ConsentStatus::Active
+            && self.data_classes.iter().any(|class| class == data_class)
+            && self.period_start <= now
+            && self.period_end.map_or(true, |end| now < end)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessControl {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -82,83 +732,328 @@ pub struct AccessControl {
     pub active: bool,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    /// Taken through break-glass rather than granted by the patient
+    #[serde(default)]
+    pub emergency: bool,
+    /// The ledger transaction recording the grant, once the outbox dispatched it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hedera_transaction_id: Option<String>,
+}
+
+impl AccessControl {
+    /// Whether one of the grant's permissions reaches `data_class`
+    pub fn covers(&self, data_class: &str) -> bool {
+        self.permissions.iter().any(|permission| permission.data_classes().contains(&data_class))
+    }
+}
+
+// Guardianship
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationshipType {
+    Parent,
+    LegalGuardian,
+    /// Someone an adult dependent chose to manage their care, e.g. for an elderly parent
+    Delegate,
+}
+
+impl RelationshipType {
+    /// Parents and legal guardians act for minors, so by default their link ends at the dependent's 18th birthday
+    pub fn ends_at_majority(&self) -> bool {
+        matches!(self, Self::Parent | Self::LegalGuardian)
+    }
 }
 
+/// Lets `guardian_did` act for `dependent_did` on reads and appointment booking
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirBundle {
+pub struct Relationship {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub patient_did: String,
-    pub bundle: serde_json::Value,
-    pub version: u32,
+    pub guardian_did: String,
+    pub dependent_did: String,
+    pub relationship: RelationshipType,
+    /// The admin or verified practitioner who established the link
+    pub established_by: String,
     pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub active: bool,
+}
+
+impl Relationship {
+    pub fn is_current(&self, now: DateTime<Utc>) -> bool {
+        self.active && self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
 }
 
+// Organizations
+/// A clinic or other facility practitioners are affiliated with
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Otp {
+pub struct Organization {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub phone_number: String,
-    pub otp: String,
+    pub fhir_organization: FhirOrganization,
+    /// Tenant (clinic) the document belongs to; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
     pub created_at: DateTime<Utc>,
-    pub expires_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-// FHIR R4 Models
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct FhirPatient {
-    #[serde(rename = "resourceType")]
-    pub resource_type: String,
-    pub id: String,
-    pub identifier: Vec<FhirIdentifier>,
-    pub name: Vec<FhirHumanName>,
-    pub gender: String,
-    pub birth_date: String,
-    pub address: Vec<FhirAddress>,
-    pub telecom: Vec<FhirContactPoint>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AffiliationRole {
+    #[default]
+    Practitioner,
+    /// Manages the organization: approves affiliations and sees its appointments and encounters
+    Admin,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirPractitioner {
-    #[serde(rename = "resourceType")]
-    pub resource_type: String,
-    pub id: String,
-    pub identifier: Vec<FhirIdentifier>,
-    pub name: Vec<FhirHumanName>,
-    pub qualification: Vec<FhirPractitionerQualification>,
-    pub telecom: Vec<FhirContactPoint>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AffiliationStatus {
+    /// Requested by the practitioner, waiting for an organization admin
+    Pending,
+    Active,
+    Rejected,
 }
 
+/// A practitioner working at an organization in a role over a period
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirMedicationRequest {
-    #[serde(rename = "resourceType")]
-    pub resource_type: String,
-    pub id: String,
-    pub status: String,
-    pub intent: String,
-    pub medication_codeable_concept: FhirCodeableConcept,
-    pub subject: FhirReference,
-    pub encounter: Option<FhirReference>,
-    pub authored_on: String,
-    pub requester: FhirReference,
-    pub dosage_instruction: Vec<FhirDosageInstruction>,
+pub struct Affiliation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub practitioner_did: String,
+    pub organization_id: ObjectId,
+    pub role: AffiliationRole,
+    pub status: AffiliationStatus,
+    /// Set when the affiliation is approved unless given up front
+    pub period_start: Option<DateTime<Utc>>,
+    pub period_end: Option<DateTime<Utc>>,
+    /// The admin who approved or rejected it
+    pub decided_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirEncounter {
-    #[serde(rename = "resourceType")]
-    pub resource_type: String,
-    pub id: String,
-    pub status: String,
-    pub class: FhirCoding,
-    pub subject: FhirReference,
-    pub participant: Vec<FhirEncounterParticipant>,
-    pub period: FhirPeriod,
-    pub reason_code: Vec<FhirCodeableConcept>,
+impl Affiliation {
+    pub fn is_current(&self, now: DateTime<Utc>) -> bool {
+        self.status == AffiliationStatus::Active
+            && self.period_start.map_or(true, |start| start <= now)
+            && self.period_end.map_or(true, |end| now < end)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Referrals
+/// FHIR `ServiceRequest.priority` codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferralPriority {
+    #[default]
+    Routine,
+    Urgent,
+    Asap,
+    Stat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferralStatus {
+    /// Waiting for the receiving practitioner
+    Requested,
+    Accepted,
+    Rejected,
+    Completed,
+}
+
+impl ReferralStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Requested => "requested",
+            Self::Accepted => "accepted",
+            Self::Rejected => "rejected",
+            Self::Completed => "completed",
+        }
+    }
+}
+
+/// A patient referred from one practitioner to another along with selected records
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Referral {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub patient_did: String,
+    pub referring_did: String,
+    pub receiving_did: String,
+    pub reason: String,
+    pub priority: ReferralPriority,
+    pub status: ReferralStatus,
+    /// FHIR references such as `Encounter/<id>`; their types bound the grant made on acceptance
+    pub resources: Vec<String>,
+    /// The grant to the receiving practitioner, once accepted
+    pub access_control_id: Option<ObjectId>,
+    /// Why it was rejected, or the outcome it was completed with
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Referral {
+    /// The distinct resource types the attached references point at
+    pub fn resource_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = Vec::new();
+        for resource_type in self.resources.iter().filter_map(|reference| reference.split_once('/').map(|(kind, _)| kind)) {
+            if !types.contains(&resource_type) {
+                types.push(resource_type);
+            }
+        }
+        types
+    }
+}
+
+/// Which side of a practitioner's referrals to list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferralFolder {
+    /// Referrals sent to them
+    #[default]
+    Inbox,
+    /// Referrals they made
+    Outbox,
+}
+
+/// A practitioner's break-glass access to a record, queued until an admin reviews it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakGlassEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub practitioner_did: String,
+    pub patient_did: String,
+    pub justification: String,
+    pub access_control_id: ObjectId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub reviewed_by: Option<String>,
+    pub review_notes: Option<String>,
+    /// When admins were emailed about it still being unreviewed
+    pub alerted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirBundle {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub patient_did: String,
+    pub bundle: serde_json::Value,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A bundle's version metadata, without the (potentially large) payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirBundleVersion {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Otp {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub phone_number: String,
+    pub otp: String,
+    pub created_at: DateTime<Utc>,
+    // Stored as a BSON date so the TTL index on `expires_at` can expire it
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+// FHIR R4 Models
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FhirPatient {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub identifier: Vec<FhirIdentifier>,
+    pub name: Vec<FhirHumanName>,
+    pub gender: String,
+    pub birth_date: String,
+    pub address: Vec<FhirAddress>,
+    pub telecom: Vec<FhirContactPoint>,
+    /// Filled in from the stored [`PatientPhoto`] when the record is read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub photo: Vec<FhirAttachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirPractitioner {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub identifier: Vec<FhirIdentifier>,
+    pub name: Vec<FhirHumanName>,
+    pub qualification: Vec<FhirPractitionerQualification>,
+    pub telecom: Vec<FhirContactPoint>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirOrganization {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub active: bool,
+    pub identifier: Vec<FhirIdentifier>,
+    #[serde(rename = "type")]
+    pub organization_type: Vec<FhirCodeableConcept>,
+    pub name: String,
+    pub telecom: Vec<FhirContactPoint>,
+    pub address: Vec<FhirAddress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirMedicationRequest {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub status: String,
+    pub intent: String,
+    /// May be left out when prescribing by `rxnorm_code`
+    #[serde(default)]
+    pub medication_codeable_concept: FhirCodeableConcept,
+    pub subject: FhirReference,
+    pub encounter: Option<FhirReference>,
+    pub authored_on: FhirDateTime,
+    pub requester: FhirReference,
+    pub dosage_instruction: Vec<FhirDosageInstruction>,
+    /// Why the prescription was stopped or cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<FhirCodeableConcept>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispense_request: Option<FhirDispenseRequest>,
+}
+
+/// How much may be dispensed against a `MedicationRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirDispenseRequest {
+    pub quantity: Option<FhirQuantity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirEncounter {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub status: String,
+    pub class: FhirCoding,
+    pub subject: FhirReference,
+    pub participant: Vec<FhirEncounterParticipant>,
+    pub period: FhirPeriod,
+    pub reason_code: Vec<FhirCodeableConcept>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FhirObservation {
     #[serde(rename = "resourceType")]
     pub resource_type: String,
@@ -168,182 +1063,1748 @@ pub struct FhirObservation {
     pub code: FhirCodeableConcept,
     pub subject: FhirReference,
     pub encounter: Option<FhirReference>,
-    pub effective_date_time: String,
+    pub effective_date_time: FhirDateTime,
     pub value_quantity: Option<FhirQuantity>,
     pub value_string: Option<String>,
     pub interpretation: Vec<FhirCodeableConcept>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirCondition {
-    #[serde(rename = "resourceType")]
-    pub resource_type: String,
-    pub id: String,
-    pub clinical_status: FhirCodeableConcept,
-    pub verification_status: FhirCodeableConcept,
-    pub category: Vec<FhirCodeableConcept>,
-    pub code: FhirCodeableConcept,
-    pub subject: FhirReference,
-    pub encounter: Option<FhirReference>,
-    pub onset_date_time: String,
-    pub recorded_date: String,
+/// Vitals as a nurse enters them; each one given becomes a coded Observation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordVitalsRequest {
+    /// mmHg
+    pub systolic: Option<f64>,
+    /// mmHg
+    pub diastolic: Option<f64>,
+    /// Beats per minute
+    pub heart_rate: Option<f64>,
+    pub temperature_c: Option<f64>,
+    /// Percent
+    pub spo2: Option<f64>,
+    /// Breaths per minute
+    pub respiratory_rate: Option<f64>,
+    pub weight_kg: Option<f64>,
+    /// When they were taken; now if left out
+    #[serde(default)]
+    pub effective_date_time: Option<DateTime<Utc>>,
+}
+
+/// Bucket size of an observation trend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ObservationPeriod {
+    #[default]
+    Day,
+    /// Starting on Monday
+    Week,
+    Month,
+}
+
+impl ObservationPeriod {
+    /// The matching MongoDB `$dateTrunc` unit
+    pub fn unit(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+/// Statistics of the numeric readings in one period, which starts at `start` (UTC)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservationBucket {
+    pub start: DateTime<Utc>,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatestObservation {
+    pub value: Option<FhirQuantity>,
+    pub effective_date_time: FhirDateTime,
+    pub interpretation: Vec<FhirCodeableConcept>,
+}
+
+/// A patient's readings of one LOINC code, bucketed for charting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservationSummary {
+    pub code: String,
+    pub period: ObservationPeriod,
+    /// Oldest first
+    pub buckets: Vec<ObservationBucket>,
+    pub latest: Option<LatestObservation>,
+    /// Readings left out for having no numeric value or no readable date
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirCondition {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub clinical_status: FhirCodeableConcept,
+    pub verification_status: FhirCodeableConcept,
+    pub category: Vec<FhirCodeableConcept>,
+    pub code: FhirCodeableConcept,
+    pub subject: FhirReference,
+    pub encounter: Option<FhirReference>,
+    pub onset_date_time: FhirDateTime,
+    pub recorded_date: FhirDateTime,
+}
+
+/// One entry of a patient's problem list: every Condition recorded with the same code, merged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    pub system: Option<String>,
+    pub code: Option<String>,
+    pub display: Option<String>,
+    /// Clinical status of the most recently recorded Condition, e.g. `active` or `resolved`
+    pub clinical_status: String,
+    /// Earliest onset across the Conditions
+    pub onset_date_time: Option<FhirDateTime>,
+    /// Most recent recorded date across the Conditions
+    pub recorded_date: Option<FhirDateTime>,
+    /// Encounters the problem was recorded in; imported Conditions may have none
+    pub encounter_ids: Vec<String>,
+    pub condition_ids: Vec<String>,
+}
+
+// FHIR Common Types
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FhirAttachment {
+    pub content_type: String,
+    pub url: String,
+    pub size: u64,
+    pub title: Option<String>,
+    pub creation: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirIdentifier {
+    #[serde(rename = "use")]
+    pub use_field: Option<String>,
+    #[serde(rename = "type")]
+    pub identifier_type: Option<FhirCodeableConcept>,
+    pub system: Option<String>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FhirHumanName {
+    #[serde(rename = "use")]
+    pub r#use: Option<String>,
+    pub family: Option<String>,
+    pub given: Vec<String>,
+    pub prefix: Vec<String>,
+    pub suffix: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirAddress {
+    #[serde(rename = "use")]
+    pub r#use: Option<String>,
+    pub line: Vec<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirContactPoint {
+    pub system: String,
+    pub value: String,
+    #[serde(rename = "use")]
+    pub r#use: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FhirCodeableConcept {
+    pub coding: Vec<FhirCoding>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirCoding {
+    pub system: Option<String>,
+    pub code: Option<String>,
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirReference {
+    pub reference: String,
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirPractitionerQualification {
+    pub identifier: Vec<FhirIdentifier>,
+    pub code: FhirCodeableConcept,
+    pub period: Option<FhirPeriod>,
+    pub issuer: Option<FhirReference>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirDosageInstruction {
+    pub text: Option<String>,
+    pub timing: Option<FhirTiming>,
+    pub dose_and_rate: Vec<FhirDosageDoseAndRate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirDosageDoseAndRate {
+    #[serde(rename = "type")]
+    pub dose_type: Option<FhirCodeableConcept>,
+    pub dose_quantity: Option<FhirQuantity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirQuantity {
+    pub value: Option<f64>,
+    pub unit: Option<String>,
+    pub system: Option<String>,
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirTiming {
+    pub repeat: Option<FhirTimingRepeat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirTimingRepeat {
+    pub frequency: Option<u32>,
+    pub period: Option<f64>,
+    pub period_unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FhirEncounterParticipant {
+    #[serde(rename = "type")]
+    pub participant_type: Vec<FhirCodeableConcept>,
+    pub individual: Option<FhirReference>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirPeriod {
+    pub start: Option<FhirDateTime>,
+    pub end: Option<FhirDateTime>,
+}
+
+/// A stored FHIR date that is malformed or out of order. The `0006_fhir_date_report`
+/// migration lists them for manual cleanup and leaves the records themselves alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirDateIssue {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Where the record is, e.g. `encounters`
+    pub collection: String,
+    /// The record's `_id`
+    pub record_id: String,
+    pub problem: String,
+    pub found_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirConsent {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    pub id: String,
+    pub status: String,
+    pub scope: FhirCodeableConcept,
+    pub category: Vec<FhirCodeableConcept>,
+    pub patient: FhirReference,
+    #[serde(rename = "dateTime")]
+    pub date_time: FhirDateTime,
+    pub provision: FhirConsentProvision,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirConsentProvision {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub period: Option<FhirPeriod>,
+    pub actor: Vec<FhirConsentActor>,
+    pub class: Vec<FhirCoding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FhirConsentActor {
+    pub role: FhirCodeableConcept,
+    pub reference: FhirReference,
+}
+
+// License and Verification Models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LicenseVerification {
+    pub license_number: String,
+    pub issuing_authority: String,
+    pub issue_date: String,
+    /// `YYYY-MM-DD`; the license is valid through this day
+    pub expiry_date: String,
+    pub hedera_transaction_id: String,
+    pub ipfs_hash: String,
+    /// Cleared by the license expiry job once the expiry date has passed
+    pub verified: bool,
+    /// When the practitioner was warned the license is about to expire, so they are warned once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_warning_sent_at: Option<DateTime<Utc>>,
+}
+
+impl LicenseVerification {
+    /// The expiry date, if it is a full `YYYY-MM-DD` date
+    pub fn expiry(&self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(self.expiry_date.trim(), "%Y-%m-%d").ok()
+    }
+
+    /// Verified and not expired on `today`. A license without a readable expiry date is not
+    /// taken to be current.
+    pub fn is_current(&self, today: chrono::NaiveDate) -> bool {
+        self.verified && self.expiry().is_some_and(|expiry| expiry >= today)
+    }
+}
+
+/// A verified license that expires within the window the ops dashboard asked about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExpiringLicense {
+    pub practitioner_did: String,
+    pub license_number: String,
+    pub issuing_authority: String,
+    pub expiry_date: chrono::NaiveDate,
+    /// Negative once the date has passed and before the expiry job has run
+    pub days_remaining: i64,
+    pub warning_sent_at: Option<DateTime<Utc>>,
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiableCredential {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub subject_did: String,
+    pub credential_type: String,
+    pub issuer: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub ipfs_hash: String,
+    /// Unset until the outbox dispatcher stored the credential on the ledger
+    #[serde(default)]
+    pub hedera_transaction_id: Option<String>,
+    pub metadata: String,
+    /// Revoked credentials fail verification even though they remain on the ledger
+    #[serde(default)]
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Where the credential's revocation bit lives; none for credentials issued before status lists
+    #[serde(default)]
+    pub status_list: Option<StatusListEntry>,
+    /// How to reach the batch's anchored Merkle root from this credential; none for credentials
+    /// stored on the ledger one by one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<CredentialBatchProof>,
+}
+
+/// A batch-issued credential's place in its batch's Merkle tree, whose leaves are the SHA-256
+/// of each credential's IPFS hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CredentialBatchProof {
+    pub batch_id: ObjectId,
+    /// Hex-encoded root the batch anchored on the ledger
+    pub merkle_root: String,
+    pub leaf_index: u32,
+    pub leaf_count: u32,
+    /// Hex-encoded sibling hashes, from the leaf up
+    pub proof: Vec<String>,
+}
+
+/// Credentials issued together and anchored on the ledger as one Merkle root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialBatch {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Hex-encoded Merkle root of the batch's credentials, in issue order
+    pub merkle_root: String,
+    pub size: u32,
+    pub issuer: String,
+    pub created_at: DateTime<Utc>,
+    /// Unset until the outbox dispatcher anchored the root
+    #[serde(default)]
+    pub hedera_transaction_id: Option<String>,
+}
+
+/// What anchoring a batch costs next to storing its credentials one by one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchFeeComparison {
+    pub ledger_calls: u32,
+    pub ledger_calls_if_issued_singly: u32,
+    /// Average fee of the credentials stored one by one over the last 30 days; unknown without any
+    pub average_single_fee_hbar: Option<f64>,
+    pub estimated_saving_hbar: Option<f64>,
+    pub note: String,
+}
+
+/// A batch of issued credentials, in the order they were asked for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedCredentialBatch {
+    pub batch_id: ObjectId,
+    pub merkle_root: String,
+    pub credentials: Vec<VerifiableCredential>,
+    pub fees: BatchFeeComparison,
+}
+
+/// A credential's position in a published status list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusListEntry {
+    pub list_id: String,
+    pub index: u32,
+}
+
+/// A revocation status list. Each revocation bumps `revision`; the list is stale until a
+/// document at least that recent has been published.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialStatusList {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub list_id: String,
+    /// The next index to hand out
+    pub next_index: u32,
+    pub revision: u64,
+    pub published_revision: u64,
+    /// The signed status list credential as published, in canonical JSON
+    pub document: Option<String>,
+    pub ipfs_hash: Option<String>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresentationQuery {
+    /// DID of the verifier the presentation is for
+    pub audience: String,
+}
+
+/// A short-lived, signed presentation of a credential for one verifier, shown as a QR code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialPresentation {
+    /// What the QR code encodes
+    pub token: String,
+    pub audience: String,
+    pub expires_at: DateTime<Utc>,
+    /// The token as an SVG QR code
+    pub qr_svg: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyPresentationRequest {
+    pub token: String,
+}
+
+/// What a verifier learns from a scanned presentation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresentationVerdict {
+    pub valid: bool,
+    /// Why the presentation cannot be relied on, when it cannot
+    pub reason: Option<String>,
+    pub credential_id: Option<String>,
+    pub subject_did: Option<String>,
+    pub credential_type: Option<String>,
+    pub issuer: Option<String>,
+    /// Whether the issuer is in the trusted issuer registry
+    pub issuer_registered: Option<bool>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// Trusted issuer registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssuerStatus {
+    Active,
+    Suspended,
+}
+
+/// Someone allowed to issue credentials through the platform, and of which types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedIssuer {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub did: String,
+    pub display_name: String,
+    pub credential_types: Vec<String>,
+    pub status: IssuerStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body for registering an issuer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterIssuerRequest {
+    pub did: String,
+    pub display_name: String,
+    pub credential_types: Vec<String>,
+}
+
+/// Body for changing an issuer; fields left out stay as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateIssuerRequest {
+    pub display_name: Option<String>,
+    pub credential_types: Option<Vec<String>>,
+    pub status: Option<IssuerStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLog {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub did: String,
+    pub action: String,
+    pub timestamp: DateTime<Utc>,
+    pub details: Option<serde_json::Value>,
+    pub is_anchored: bool,
+    pub anchor_batch_id: Option<ObjectId>,
+    /// Tenant (clinic) the document belongs to; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+/// Where an audit anchoring run got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorBatchStatus {
+    /// Written before the root is sent to Hedera; the transaction may or may not have gone through
+    Pending,
+    /// Hedera accepted the root; the logs are not all marked anchored yet
+    Submitted,
+    Completed,
+}
+
+/// The intent to anchor a batch of audit logs, recorded before Hedera is called so an
+/// interrupted run can be finished without anchoring the same logs twice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnchorBatch {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Hex-encoded Merkle root of the batch; unique, so a root is only ever anchored once
+    pub merkle_root: String,
+    pub log_ids: Vec<ObjectId>,
+    pub status: AnchorBatchStatus,
+    pub transaction_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AnchorBatch {
+    pub fn new(merkle_root: [u8; 32], log_ids: Vec<ObjectId>, now: DateTime<Utc>) -> Self {
+        Self {
+            id: Some(ObjectId::new()),
+            merkle_root: hex::encode(merkle_root),
+            log_ids,
+            status: AnchorBatchStatus::Pending,
+            transaction_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn root(&self) -> anyhow::Result<[u8; 32]> {
+        let bytes = hex::decode(&self.merkle_root)?;
+        bytes.try_into().map_err(|_| anyhow::anyhow!("Merkle root {} is not 32 bytes", self.merkle_root))
+    }
+}
+
+/// An anchor batch checked against the audit logs it covers and the ledger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchVerification {
+    pub batch_id: String,
+    pub status: AnchorBatchStatus,
+    /// The root recorded when the batch was made
+    pub merkle_root: String,
+    /// The root of the batch's logs as they are stored now
+    pub recomputed_root: Option<String>,
+    pub log_count: usize,
+    /// Logs of the batch no longer in the database
+    pub missing_logs: usize,
+    pub transaction_id: Option<String>,
+    /// The transaction that anchored the root, as the mirror node reports it; None without a mirror node
+    pub ledger_transaction_id: Option<String>,
+    pub valid: bool,
+}
+
+/// Whether audit logs are still being anchored on Hedera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchoringStatus {
+    #[default]
+    Healthy,
+    /// The last run failed, but the backlog and its age are still under the alert thresholds
+    Failing,
+    /// The backlog or the time since the last successful run passed an alert threshold, and
+    /// admins were told; stays so until a run succeeds with the backlog back under its threshold
+    Lapsed,
+}
+
+/// How one audit anchoring run ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnchoringRun {
+    pub finished_at: DateTime<Utc>,
+    /// Logs the run anchored, including those of batches earlier runs left unfinished
+    pub anchored_logs: u64,
+    /// Logs still waiting to be anchored after the run
+    pub unanchored_logs: u64,
+    /// Why the run failed; None when it succeeded
+    pub error: Option<String>,
+}
+
+/// The `anchoring_health` document: the state of audit anchoring after the latest run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct AnchoringHealth {
+    pub status: AnchoringStatus,
+    pub unanchored_logs: u64,
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// The last run that did not fail, whether or not it had logs to anchor
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// When the current streak of failed runs began; None while the last run succeeded
+    pub failing_since: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    /// The latest runs, newest first
+    pub runs: Vec<AnchoringRun>,
+}
+
+// Patient notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCategory {
+    CredentialIssued,
+    AccessGranted,
+    RecordExported,
+    AppointmentReminder,
+    EmergencyAccess,
+    ReferralUpdated,
+    PrescriptionCancelled,
+    BackupCodeUsed,
+    NewSignIn,
+    EncounterFinalized,
+    ExportReady,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Email,
+    Sms,
+    Push,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+/// One delivery to one channel, kept so support can see what a patient was sent.
+/// Push notifications get one record per registered device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// The recipient, which for referral updates may be a practitioner
+    pub patient_did: String,
+    pub category: NotificationCategory,
+    pub channel: NotificationChannel,
+    pub status: NotificationStatus,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Preference toggle a notification falls under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreferenceCategory {
+    SecurityAlerts,
+    AppointmentReminders,
+    Marketing,
+}
+
+impl NotificationCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CredentialIssued => "credential_issued",
+            Self::AccessGranted => "access_granted",
+            Self::RecordExported => "record_exported",
+            Self::AppointmentReminder => "appointment_reminder",
+            Self::EmergencyAccess => "emergency_access",
+            Self::ReferralUpdated => "referral_updated",
+            Self::PrescriptionCancelled => "prescription_cancelled",
+            Self::BackupCodeUsed => "backup_code_used",
+            Self::NewSignIn => "new_sign_in",
+            Self::EncounterFinalized => "encounter_finalized",
+            Self::ExportReady => "export_ready",
+        }
+    }
+
+    pub fn preference_category(&self) -> PreferenceCategory {
+        match self {
+            // An accepted referral opens the record to another practitioner; a cancelled prescription must not be collected
+            Self::CredentialIssued
+            | Self::AccessGranted
+            | Self::RecordExported
+            | Self::EmergencyAccess
+            | Self::ReferralUpdated
+            | Self::PrescriptionCancelled
+            | Self::BackupCodeUsed
+            | Self::NewSignIn
+            | Self::EncounterFinalized
+            | Self::ExportReady => PreferenceCategory::SecurityAlerts,
+            Self::AppointmentReminder => PreferenceCategory::AppointmentReminders,
+        }
+    }
+}
+
+/// A language messages are translated into; see `crate::i18n`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Sw,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Self::En, Self::Sw];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Sw => "sw",
+        }
+    }
+
+    /// The locale of a language tag such as `sw` or `sw-KE`, when it is one we translate into
+    pub fn from_language_tag(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?;
+        Self::ALL.into_iter().find(|locale| locale.as_str().eq_ignore_ascii_case(language))
+    }
+}
+
+/// Messages sent in English because a locale lacks them, counted since the process started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MissingTranslation {
+    pub locale: Locale,
+    /// The message key, or `template:<name>` for an email template
+    pub key: String,
+    pub count: u64,
+}
+
+/// Stored on the patient record. Sign-in codes and email verification are not
+/// covered: they only go to the address the patient is using at that moment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub email: bool,
+    pub sms: bool,
+    /// Saved before push existed, preferences leave it on
+    #[serde(default = "enabled")]
+    pub push: bool,
+    pub security_alerts: bool,
+    pub appointment_reminders: bool,
+    pub marketing: bool,
+    /// Language of the patient's emails and SMS, and of error messages from their next sign-in
+    #[serde(default)]
+    pub locale: Locale,
+}
+
+impl Default for NotificationPreferences {
+    /// Transactional messages on, marketing off until the patient opts in
+    fn default() -> Self {
+        Self {
+            email: true,
+            sms: true,
+            push: true,
+            security_alerts: true,
+            appointment_reminders: true,
+            marketing: false,
+            locale: Locale::En,
+        }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl NotificationPreferences {
+    pub fn allows(&self, category: PreferenceCategory, channel: NotificationChannel) -> bool {
+        let channel_enabled = match channel {
+            NotificationChannel::Email => self.email,
+            NotificationChannel::Sms => self.sms,
+            NotificationChannel::Push => self.push,
+        };
+        let category_enabled = match category {
+            PreferenceCategory::SecurityAlerts => self.security_alerts,
+            PreferenceCategory::AppointmentReminders => self.appointment_reminders,
+            PreferenceCategory::Marketing => self.marketing,
+        };
+        channel_enabled && category_enabled
+    }
+}
+
+// Re-encryption jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReencryptionJobStatus {
+    Running,
+    Completed,
+}
+
+/// A pass over archived bundles moving them onto the current encryption key.
+/// Encounters are walked in `_id` order, so an interrupted job resumes after `resume_after`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReencryptionJob {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// The key version bundles are moved onto
+    pub key_version: u32,
+    pub status: ReencryptionJobStatus,
+    pub started_by: String,
+    /// The last encounter whose page was done
+    pub resume_after: Option<ObjectId>,
+    pub reencrypted: u64,
+    pub failed: u64,
+    /// The most recent failures; those bundles stay on their old key for the next job
+    pub failures: Vec<ReencryptionFailure>,
+    // A BSON date so a lapsed runner's job can be claimed by the query
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub lease_until: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReencryptionFailure {
+    pub encounter_id: ObjectId,
+    pub error: String,
+    pub at: DateTime<Utc>,
+}
+
+// Patient data exports
+/// Job type that builds the archive of one [`PatientExport`]
+pub const BUILD_PATIENT_EXPORT_JOB: &str = "build_patient_export";
+/// Recurring job type that deletes export archives past their retention
+pub const EXPIRE_PATIENT_EXPORTS_JOB: &str = "expire_patient_exports";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatientExportStatus {
+    /// Waiting for, or being built by, a job worker
+    Pending,
+    Ready,
+    Failed,
+    /// Past its retention; the archive was deleted
+    Expired,
+}
+
+/// A patient's request for a copy of everything held about them (GDPR data portability).
+/// The archive is a ZIP stored encrypted on IPFS until `expires_at`, when it is unpinned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatientExport {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub patient_did: String,
+    pub status: PatientExportStatus,
+    /// Set once the archive is built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ExportArchive>,
+    /// Why the build failed, for support; not shown to the patient
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    // A BSON date so the sweep can query for exports past their retention
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Where a built export archive is stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportArchive {
+    pub ipfs_hash: String,
+    /// Version of the key it is encrypted with
+    pub key_version: u32,
+    /// SHA-256 of the ZIP, checked before every download
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// One file of an export archive, as listed in its `manifest.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportManifestEntry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// An export as its patient sees it. The download URL is signed and short-lived, so a new
+/// one is made every time the export is read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatientExportView {
+    pub id: String,
+    pub status: PatientExportStatus,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// When the archive is deleted
+    pub expires_at: DateTime<Utc>,
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    pub download_url: Option<String>,
+    pub download_url_expires_at: Option<DateTime<Utc>>,
+}
+
+// Compliance reports
+/// Job type that generates one [`ComplianceReport`]
+pub const GENERATE_COMPLIANCE_REPORT_JOB: &str = "generate_compliance_report";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplianceReportStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// An auditor's report on one period of the audit log: the access, break-glass and credential
+/// events in it and the anchor batches proving them, signed and stored on IPFS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComplianceReport {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub requested_by: String,
+    /// The period covered, `[from, to)`
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub status: ComplianceReportStatus,
+    /// Percent done
+    pub progress: u8,
+    /// What the generating job is doing, while it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<ComplianceReportArtifacts>,
+    /// Whether every anchor batch in the report checked out when it was generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchors_valid: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Where a generated report's files are stored, encrypted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceReportArtifacts {
+    pub json_ipfs_hash: String,
+    pub html_ipfs_hash: String,
+    /// Version of the key both are encrypted with
+    pub key_version: u32,
+    /// SHA-256 of the signed JSON
+    pub sha256: String,
+}
+
+/// The signed JSON of a compliance report. It stands on its own: the signature covers the
+/// canonical JSON of everything but itself, and each batch carries what the ledger is asked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceReportDocument {
+    pub report_id: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub generated_by: String,
+    pub summary: ComplianceSummary,
+    pub categories: Vec<ComplianceCategory>,
+    pub anchor_batches: Vec<ReportedAnchorBatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ReportSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceSummary {
+    /// Every audit event in the period, reported or not
+    pub total_events: u64,
+    pub anchored_events: u64,
+    pub unanchored_events: u64,
+    pub anchor_batches: usize,
+    pub anchors_valid: bool,
+}
+
+/// The events of one kind in the period, oldest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceCategory {
+    /// `access`, `break_glass` or `credential_issuance`
+    pub category: String,
+    pub count: usize,
+    pub events: Vec<ComplianceEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceEvent {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    /// The DID the event is logged under, usually the patient
+    pub did: String,
+    pub action: String,
+    /// Who acted, when it was someone else
+    pub actor: Option<String>,
+    /// Made through a break-glass grant
+    pub emergency: bool,
+    pub anchor_batch_id: Option<String>,
+}
+
+/// An anchor batch as it was checked while the report was generated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportedAnchorBatch {
+    pub batch_id: String,
+    pub created_at: DateTime<Utc>,
+    pub merkle_root: String,
+    pub log_count: usize,
+    pub transaction_id: Option<String>,
+    /// The transaction the mirror node found carrying the root; None without a mirror node
+    pub ledger_transaction_id: Option<String>,
+    pub valid: bool,
+}
+
+/// A detached Ed25519 signature by the platform's credential signing key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportSignature {
+    /// Always `EdDSA`
+    pub alg: String,
+    pub verification_method: String,
+    pub public_key_multibase: String,
+    /// Base64url, without padding
+    pub value: String,
+}
+
+/// What re-checking a report's JSON found
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplianceReportVerification {
+    pub signature_valid: bool,
+    /// Signed with the key this server signs with now
+    pub signed_by_this_server: bool,
+    /// False without a mirror node, when the batches' transactions could not be looked up
+    pub anchors_checked: bool,
+    pub batches: Vec<AnchorCheck>,
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnchorCheck {
+    pub batch_id: String,
+    pub transaction_id: Option<String>,
+    pub ledger_transaction_id: Option<String>,
+    pub valid: bool,
+}
+
+// Background jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    /// Claimed by the worker in `locked_by` until `locked_until`
+    Running,
+    Succeeded,
+    /// Failed permanently or ran out of attempts; only an admin retry runs it again
+    Dead,
+}
+
+/// A unit of work in the `jobs` collection, run by the handler registered for its `job_type`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub job_type: String,
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    /// Attempts started so far, counted when a worker claims the job
+    pub attempts: u32,
+    // BSON dates so workers can query for jobs that are due or whose lock has lapsed
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub run_at: DateTime<Utc>,
+    pub locked_by: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub locked_until: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Set on recurring jobs, which exist once per key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub fn new(job_type: &str, payload: serde_json::Value, run_at: DateTime<Utc>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: None,
+            job_type: job_type.to_string(),
+            payload,
+            status: JobStatus::Pending,
+            attempts: 0,
+            run_at,
+            locked_by: None,
+            locked_until: run_at,
+            last_error: None,
+            unique_key: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobCounts {
+    pub pending: u64,
+    pub running: u64,
+    pub succeeded: u64,
+    pub dead: u64,
+}
+
+// Email outbox
+/// Job type that delivers one [`OutboxEmail`]
+pub const SEND_EMAIL_JOB: &str = "send_email";
+
+/// An email queued for delivery: the payload of a `send_email` job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub template: String,
+    pub context: serde_json::Value,
+}
+
+// Webhooks
+/// Job type that delivers one [`WebhookEvent`] to one webhook
+pub const DELIVER_WEBHOOK_JOB: &str = "deliver_webhook";
+
+/// Something that happened which a clinic's own systems can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEventType {
+    #[serde(rename = "encounter.finalized")]
+    EncounterFinalized,
+    #[serde(rename = "credential.issued")]
+    CredentialIssued,
+    #[serde(rename = "access.granted")]
+    AccessGranted,
+    /// Sent by `POST /api/webhooks/:id/test` only; it cannot be subscribed to
+    #[serde(rename = "ping")]
+    Ping,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EncounterFinalized => "encounter.finalized",
+            Self::CredentialIssued => "credential.issued",
+            Self::AccessGranted => "access.granted",
+            Self::Ping => "ping",
+        }
+    }
+}
+
+/// A subscription of a tenant's system to events, delivered as signed POSTs to `url`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Tenant (clinic) whose events are delivered; unset means the default tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// The admin who created it
+    pub owner_did: String,
+    pub url: String,
+    /// The signing secret, encrypted with the record encryption key
+    pub encrypted_secret: String,
+    pub events: Vec<WebhookEventType>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A webhook as the API shows it; the secret is only returned when the webhook is created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookView {
+    pub id: String,
+    pub tenant_id: Option<String>,
+    pub owner_did: String,
+    pub url: String,
+    pub events: Vec<WebhookEventType>,
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The JSON body of a webhook delivery. `data` holds resource ids only, never health data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// Stays the same across retries, so subscribers can drop repeats
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: WebhookEventType,
+    pub occurred_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+/// The payload of a `deliver_webhook` job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDeliveryJob {
+    pub webhook_id: String,
+    pub event: WebhookEvent,
+}
+
+// Notification feed
+/// An event for one user, pushed over `GET /api/notifications/stream` and kept for a short
+/// while so clients that reconnect or poll `GET /api/notifications` catch up on it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedEvent {
+    /// Increases with every event, so it doubles as the stream's `Last-Event-ID`
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub recipient_did: String,
+    /// The name of the event, e.g. `credential_issued`
+    pub event_type: String,
+    /// Resource ids only, as in webhook deliveries
+    pub data: serde_json::Value,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    /// Removed by a TTL index once this passes
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A feed event as the API shows it, both as the data of a stream event and in the pull fallback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedEventView {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub data: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+// Push devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DevicePlatform {
+    Android,
+    Ios,
+    Web,
+}
+
+/// A device that receives push notifications through Firebase Cloud Messaging
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub did: String,
+    /// Unique: a token registered again moves to whoever registered it last
+    pub fcm_token: String,
+    pub platform: DevicePlatform,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterDeviceRequest {
+    pub fcm_token: String,
+    pub platform: DevicePlatform,
+}
+
+// Outbox
+/// Job type that performs one [`OutboxAction`]
+pub const OUTBOX_JOB: &str = "outbox";
+
+/// An external call owed for a write, queued in the same transaction as the write: the payload
+/// of an `outbox` job. Each action can run more than once and still leave one result behind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum OutboxAction {
+    /// Pin an object already added to IPFS, so the node keeps it
+    Pin { ipfs_hash: String },
+    /// Store a credential on the ledger and write the transaction id back to it
+    StoreCredential {
+        credential_id: ObjectId,
+        subject_did: String,
+        credential_type: String,
+        ipfs_hash: String,
+        expires_at: Option<u64>,
+        metadata: String,
+    },
+    /// Record an access grant on the ledger and write the transaction id back to it
+    AnchorAccessGrant { grant_id: ObjectId, patient_did: String, grantee_did: String, expires_at: Option<u64> },
+    /// Anchor a batch's Merkle root on the ledger and write the transaction id back to its credentials
+    AnchorCredentialBatch { batch_id: ObjectId, merkle_root: String, size: u32 },
+}
+
+/// One attempt at delivering an event to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub webhook_id: ObjectId,
+    pub event_id: String,
+    pub event_type: WebhookEventType,
+    /// Counts from 1, across the job's retries
+    pub attempt: u32,
+    /// The subscriber's answer, unless it could not be reached
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Where a request sent with an `Idempotency-Key` got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdempotencyStatus {
+    InProgress,
+    Completed,
+}
+
+/// A request sent with an `Idempotency-Key` and, once it finished, the response replayed for
+/// later requests with the same key. Keys are scoped to the caller's DID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_did: String,
+    pub key: String,
+    /// SHA-256 of the method, path and body; a replay has to match it
+    pub request_hash: String,
+    pub status: IdempotencyStatus,
+    pub response_status: Option<u16>,
+    pub response_content_type: Option<String>,
+    pub response_body: Option<String>,
+    /// An in-progress claim past this point belongs to a request that never finished and can be taken over
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub locked_until: DateTime<Utc>,
+    /// Removed by a TTL index once this passes
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxStats {
+    pub pending: u64,
+    pub sent: u64,
+    pub failed_permanent: u64,
 }
 
-// FHIR Common Types
+// Admin views
+/// One page of a longer list; `page` counts from 1
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirIdentifier {
-    #[serde(rename = "use")]
-    pub use_field: Option<String>,
-    #[serde(rename = "type")]
-    pub identifier_type: Option<FhirCodeableConcept>,
-    pub system: Option<String>,
-    pub value: String,
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u64,
+    pub page_size: u64,
+    pub total: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct FhirHumanName {
-    #[serde(rename = "use")]
-    pub r#use: Option<String>,
-    pub family: Option<String>,
-    pub given: Vec<String>,
-    pub prefix: Vec<String>,
-    pub suffix: Vec<String>,
+/// Whether a suspected duplicate pair has been dealt with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeCandidateStatus {
+    Open,
+    Merged,
 }
 
+/// Two patient records the duplicate scan thinks belong to the same person
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirAddress {
-    #[serde(rename = "use")]
-    pub r#use: Option<String>,
-    pub line: Vec<String>,
-    pub city: Option<String>,
-    pub state: Option<String>,
-    pub postal_code: Option<String>,
-    pub country: Option<String>,
+pub struct MergeCandidate {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// The two DIDs in sorted order; a pair is recorded once
+    pub patient_dids: Vec<String>,
+    /// 0 to 1; higher is more likely the same person
+    pub score: f64,
+    /// What matched: `email`, `phone`, `name` or `birth_date`
+    pub reasons: Vec<String>,
+    pub status: MergeCandidateStatus,
+    pub detected_at: DateTime<Utc>,
 }
 
+/// A duplicate patient record folded into the survivor, with what is needed to undo it
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirContactPoint {
-    pub system: String,
-    pub value: String,
-    #[serde(rename = "use")]
-    pub r#use: Option<String>,
+pub struct PatientMerge {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub survivor_did: String,
+    pub duplicate_did: String,
+    pub merged_by: String,
+    pub merged_at: DateTime<Utc>,
+    /// Documents moved from the duplicate to the survivor
+    pub encounter_ids: Vec<ObjectId>,
+    pub prescription_ids: Vec<ObjectId>,
+    pub credential_ids: Vec<ObjectId>,
+    pub access_grant_ids: Vec<ObjectId>,
+    /// Contact points copied from the duplicate into the survivor's record
+    pub telecom_added: usize,
+    /// The survivor's encrypted record and identifier hashes as they were before the merge
+    pub survivor_record_before: String,
+    pub survivor_email_hash_before: Option<String>,
+    pub survivor_phone_hash_before: Option<String>,
+    /// The duplicate's identifier hashes, cleared from its tombstone; the survivor takes over those it lacked
+    pub duplicate_email_hash: Option<String>,
+    pub duplicate_phone_hash: Option<String>,
 }
 
+/// Admin request to fold `duplicate_did` into `survivor_did`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirCodeableConcept {
-    pub coding: Vec<FhirCoding>,
-    pub text: Option<String>,
+pub struct MergePatientsRequest {
+    pub survivor_did: String,
+    pub duplicate_did: String,
 }
 
+/// A patient found by name search; only these fields are decrypted for the practitioner
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirCoding {
-    pub system: Option<String>,
-    pub code: Option<String>,
-    pub display: Option<String>,
+pub struct PatientSearchResult {
+    pub did: String,
+    pub name: String,
+    pub birth_date: String,
+    pub gender: String,
 }
 
+/// What the admin patient list shows; the rest of the record stays encrypted
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirReference {
-    pub reference: String,
-    pub display: Option<String>,
+pub struct PatientSummary {
+    pub did: String,
+    pub name: String,
+    pub email_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub erased_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirPractitionerQualification {
-    pub identifier: Vec<FhirIdentifier>,
-    pub code: FhirCodeableConcept,
-    pub period: Option<FhirPeriod>,
-    pub issuer: Option<FhirReference>,
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SystemStats {
+    pub patients: u64,
+    pub practitioners: u64,
+    /// Keyed by status, e.g. `finished`; soft-deleted encounters are left out
+    pub encounters: std::collections::BTreeMap<String, u64>,
+    pub credentials_issued: u64,
+    pub unanchored_audit_logs: u64,
+    pub pending_emails: u64,
+    pub indexes: IndexBuildStatus,
+    pub sms: SmsStats,
+}
+
+/// Sign-in codes texted per UTC day, with what they are estimated to cost
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SmsStats {
+    pub daily_budget: Option<u32>,
+    pub usd_per_message: Option<f64>,
+    /// The last 30 days that had sends, newest first
+    pub days: Vec<SmsDailyUsage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirDosageInstruction {
-    pub text: Option<String>,
-    pub timing: Option<FhirTiming>,
-    pub dose_and_rate: Vec<FhirDosageDoseAndRate>,
+pub struct SmsDailyUsage {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub sent: u32,
+    pub estimated_usd: Option<f64>,
 }
 
+/// A count of codes texted in one window, to one number or to all of them; removed by a TTL
+/// index once the window is over
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirDosageDoseAndRate {
-    #[serde(rename = "type")]
-    pub dose_type: Option<FhirCodeableConcept>,
-    pub dose_quantity: Option<FhirQuantity>,
+pub struct SmsSendCounter {
+    /// The scope and window, e.g. `number:<hash>:day:2026-10-18` or `all:day:2026-10-18`
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub count: u32,
+    /// `YYYY-MM-DD`; only on the platform-wide daily counters the stats read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Where the index build that runs after startup has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexBuildState {
+    #[default]
+    NotStarted,
+    /// `SKIP_INDEX_CREATION` is set; indexes are built with `migrate --indexes-only`
+    Skipped,
+    Building,
+    Completed,
+    /// Every index was tried; the ones in `failed` are missing or not as specified
+    CompletedWithErrors,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexBuildStatus {
+    pub state: IndexBuildState,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Indexes created or found already in place
+    pub ensured: u32,
+    pub failed: Vec<IndexFailure>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirQuantity {
-    pub value: Option<f64>,
-    pub unit: Option<String>,
-    pub system: Option<String>,
-    pub code: Option<String>,
+pub struct IndexFailure {
+    pub collection: String,
+    pub keys: String,
+    /// An index on the same keys exists with other options, or under the same name with other keys
+    pub conflict: bool,
+    pub error: String,
 }
 
+/// How audit statistics are grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditGroupBy {
+    /// UTC calendar day, as `YYYY-MM-DD`
+    #[default]
+    Day,
+    Action,
+    /// The DID the entry is about, or `ip:<address>` for addresses
+    Did,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditCount {
+    pub key: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditSeriesPoint {
+    pub day: String,
+    pub action: String,
+    pub count: u64,
+}
+
+/// Aggregated audit log activity over `[from, to)`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirTiming {
-    pub repeat: Option<FhirTimingRepeat>,
+pub struct AuditStats {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub group_by: AuditGroupBy,
+    /// Days in order; actions and DIDs busiest first
+    pub groups: Vec<AuditCount>,
+    /// DIDs with the most entries, busiest first
+    pub top_actors: Vec<AuditCount>,
+    pub anchored: u64,
+    pub unanchored: u64,
+    /// Security-relevant actions (failed sign-ins, break-glass, exports) per day
+    pub security: Vec<AuditSeriesPoint>,
+}
+
+/// What a Hedera transaction the platform paid for was doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HederaOperation {
+    CredentialIssuance,
+    CredentialBatchAnchoring,
+    AuditAnchoring,
+    AccessGrant,
+}
+
+impl HederaOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HederaOperation::CredentialIssuance => "credential_issuance",
+            HederaOperation::CredentialBatchAnchoring => "credential_batch_anchoring",
+            HederaOperation::AuditAnchoring => "audit_anchoring",
+            HederaOperation::AccessGrant => "access_grant",
+        }
+    }
 }
 
+/// The fee of one submitted Hedera transaction, as charged on its record
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirTimingRepeat {
-    pub frequency: Option<u32>,
-    pub period: Option<f64>,
-    pub period_unit: Option<String>,
+pub struct HederaTransaction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub transaction_id: String,
+    pub operation: HederaOperation,
+    pub fee_tinybars: i64,
+    pub recorded_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct FhirEncounterParticipant {
-    #[serde(rename = "type")]
-    pub participant_type: Vec<FhirCodeableConcept>,
-    pub individual: Option<FhirReference>,
+/// How Hedera costs are grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HederaCostGroupBy {
+    #[default]
+    Operation,
+    /// UTC calendar day, as `YYYY-MM-DD`
+    Day,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HederaCostGroup {
+    pub key: String,
+    pub transactions: u64,
+    pub hbar: f64,
+    pub usd: Option<f64>,
 }
 
+/// Hedera fees paid over `[from, to)`, with a projection of the monthly spend
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FhirPeriod {
-    pub start: Option<String>,
-    pub end: Option<String>,
+pub struct HederaCostReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub group_by: HederaCostGroupBy,
+    /// Days in order; operations most expensive first
+    pub groups: Vec<HederaCostGroup>,
+    pub transactions: u64,
+    pub total_hbar: f64,
+    pub total_usd: Option<f64>,
+    /// The exchange rate the USD figures use; none when no rate is configured or reachable
+    pub usd_per_hbar: Option<f64>,
+    /// What a 30-day month costs at the rate of the trailing 7 days
+    pub projected_monthly_hbar: f64,
+    pub projected_monthly_usd: Option<f64>,
+    pub monthly_budget_hbar: Option<f64>,
 }
 
-// License and Verification Models
+/// Marks that admins have been warned about the budget for a month, so they are warned once
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LicenseVerification {
-    pub license_number: String,
-    pub issuing_authority: String,
-    pub issue_date: String,
-    pub expiry_date: String,
-    pub hedera_transaction_id: String,
-    pub ipfs_hash: String,
-    pub verified: bool,
+pub struct HederaBudgetAlert {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// `YYYY-MM`
+    pub month: String,
+    pub spent_hbar: f64,
+    pub budget_hbar: f64,
+    pub sent_at: DateTime<Utc>,
 }
 
+/// The operator account's balance as last checked, and whether ledger calls are held back for want of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperatorBalanceSnapshot {
+    /// None until the first check succeeds
+    pub balance_hbar: Option<f64>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub alert_hbar: f64,
+    /// Below the alert threshold, and admins were told
+    pub low: bool,
+    /// Hedera refused a transaction for insufficient balance; paid calls fail fast until a check finds it topped up
+    pub exhausted: bool,
+}
+
+/// What a configuration reload changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigReload {
+    /// Reloadable settings that changed, each as `{ "from": .., "to": .. }`
+    pub changed: serde_json::Map<String, serde_json::Value>,
+    /// Boot-time settings the new configuration changes, which were left as they are
+    pub ignored: Vec<String>,
+}
+
+// Chat history
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VerifiableCredential {
+pub struct ChatSession {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub subject_did: String,
-    pub credential_type: String,
-    pub issuer: String,
-    pub issued_at: DateTime<Utc>,
-    pub expires_at: Option<DateTime<Utc>>,
-    pub ipfs_hash: String,
-    pub hedera_transaction_id: String,
-    pub metadata: String,
+    pub owner_did: String,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Who wrote a chat message, named as Gemini expects in `contents[].role`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+    User,
+    Model,
 }
 
+// TODO: encrypt `content` with the patient's data key once envelope encryption is in place
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AuditLog {
+pub struct ChatMessage {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    pub session_id: ObjectId,
+    pub role: ChatRole,
+    pub content: String,
+    /// Identifiers replaced before the message went to the model, kept for review
+    #[serde(default)]
+    pub redactions: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatReply {
+    pub session_id: String,
+    pub reply: String,
+}
+
+/// One user's chat consumption on one UTC day, counted against the daily quota
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatUsage {
+    pub user_did: String,
+    /// `YYYY-MM-DD`
+    pub day: String,
+    #[serde(default)]
+    pub requests: u32,
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub reply_tokens: u64,
+}
+
+impl ChatUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.reply_tokens
+    }
+}
+
+/// A DID made an admin with `create-admin`, in addition to those in ADMIN_DIDS and PLATFORM_ADMIN_DIDS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminAccount {
+    #[serde(rename = "_id")]
     pub did: String,
-    pub action: String,
-    pub timestamp: DateTime<Utc>,
-    pub details: Option<serde_json::Value>,
-    pub is_anchored: bool,
-    pub anchor_batch_id: Option<ObjectId>,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
 }
 
+// Roles carried in the JWT claims
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub enum Role {
+    #[default]
+    Patient,
+    Practitioner,
+    /// Administers one tenant; sees only that tenant's practitioners, encounters and audit data
+    Admin,
+    /// Administers the whole platform: every tenant, and the patient accounts they share
+    PlatformAdmin,
+}
+
+impl Role {
+    /// Either kind of admin
+    pub fn is_admin(self) -> bool {
+        matches!(self, Role::Admin | Role::PlatformAdmin)
+    }
+}
 
 // Permission and Access Control
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Permission {
     Read,
     Write,
@@ -353,6 +2814,32 @@ pub enum Permission {
     ViewObservations,
 }
 
+/// FHIR resource types a consent can cover
+pub const CONSENT_DATA_CLASSES: [&str; 5] = ["Patient", "Encounter", "Observation", "Condition", "MedicationRequest"];
+
+impl Permission {
+    /// The data classes the consent recorded for a grant with this permission covers
+    pub fn data_classes(&self) -> &'static [&'static str] {
+        match self {
+            Self::Read => &["Patient"],
+            Self::Write | Self::ViewEncounters => &["Encounter", "Condition"],
+            Self::Prescribe | Self::ViewPrescriptions => &["MedicationRequest"],
+            Self::ViewObservations => &["Observation"],
+        }
+    }
+
+    /// The read-only permission that reaches `data_class`, if it is one grants can cover
+    pub fn viewing(data_class: &str) -> Option<Self> {
+        match data_class {
+            "Patient" => Some(Self::Read),
+            "Encounter" | "Condition" => Some(Self::ViewEncounters),
+            "MedicationRequest" => Some(Self::ViewPrescriptions),
+            "Observation" => Some(Self::ViewObservations),
+            _ => None,
+        }
+    }
+}
+
 // API Request/Response Models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreatePatientRequest {
@@ -363,12 +2850,118 @@ pub struct CreatePatientRequest {
 pub struct CreatePractitionerRequest {
     pub fhir_practitioner: FhirPractitioner,
     pub license_verification: LicenseVerification,
+    /// Identifier of an organization to ask to join; creates a pending affiliation
+    #[serde(default)]
+    pub organization_identifier: Option<String>,
+    #[serde(default)]
+    pub practitioner_type: PractitionerType,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreatePrescriptionRequest {
     pub patient_did: String,
     pub medication_request: FhirMedicationRequest,
+    /// Prescribe an RxNorm medication by code alone; it replaces the request's medication concept
+    #[serde(default)]
+    pub rxnorm_code: Option<String>,
+    /// Issue a verifiable credential pharmacies can check without access to the record
+    #[serde(default)]
+    pub issue_credential: bool,
+    /// Prescribe despite a major interaction warning; needs `override_reason`
+    #[serde(default)]
+    pub force: bool,
+    /// Why the prescriber is going ahead despite a major interaction, kept in the audit log
+    #[serde(default)]
+    pub override_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateEncounterStatusRequest {
+    pub status: EncounterStatus,
+    /// Required when cancelling
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Cancel even though observations were already recorded
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdatePrescriptionStatusRequest {
+    pub status: PrescriptionStatus,
+    /// Required when stopping or cancelling
+    #[serde(default)]
+    pub reason: Option<FhirCodeableConcept>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispenseRequest {
+    pub quantity: FhirQuantity,
+    pub days_supply: u32,
+}
+
+/// A prescription credential as a pharmacy is shown it: its hash, or the scanned QR code
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrescriptionReference {
+    pub credential_hash: Option<String>,
+    pub qr_payload: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiedDispenseRequest {
+    #[serde(flatten)]
+    pub reference: PrescriptionReference,
+    #[serde(flatten)]
+    pub dispense: DispenseRequest,
+}
+
+/// What a pharmacy learns from verifying a prescription, and nothing more
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrescriptionVerdict {
+    pub valid: bool,
+    /// Why the prescription cannot be filled, when it cannot
+    pub reason: Option<String>,
+    pub medication: Option<String>,
+    pub quantity: Option<FhirQuantity>,
+    pub prescriber_license_verified: Option<bool>,
+    /// Whether the credential's issuer is in the trusted issuer registry
+    pub issuer_registered: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionSeverity {
+    Minor,
+    Moderate,
+    Major,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionKind {
+    DrugInteraction,
+    /// The patient already has an active prescription for the same medication
+    DuplicateTherapy,
+}
+
+/// A problem between a new medication and one the patient is already taking
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InteractionWarning {
+    pub kind: InteractionKind,
+    pub severity: InteractionSeverity,
+    pub description: String,
+    /// The active medication the new one interacts with
+    pub interacting_drug: String,
+}
+
+/// A new prescription with what the interaction check found
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrescriptionCreated {
+    #[serde(flatten)]
+    pub prescription: Prescription,
+    pub warnings: Vec<InteractionWarning>,
+    /// False when the interaction source could not be reached, so only duplicates were checked
+    pub interactions_checked: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -379,11 +2972,163 @@ pub struct GrantAccessRequest {
     pub expires_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRelationshipRequest {
+    pub guardian_did: String,
+    pub dependent_did: String,
+    pub relationship: RelationshipType,
+    /// Defaults to the dependent's 18th birthday for parents and legal guardians
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Body for creating or replacing an organization
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationRequest {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub organization_type: Vec<FhirCodeableConcept>,
+    #[serde(default)]
+    pub identifier: Vec<FhirIdentifier>,
+    #[serde(default)]
+    pub telecom: Vec<FhirContactPoint>,
+    #[serde(default)]
+    pub address: Vec<FhirAddress>,
+    /// Tenant a platform admin creates the organization in; a tenant admin's organizations
+    /// always join its own tenant. Not changed by updates.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+/// Body for creating or replacing a webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub events: Vec<WebhookEventType>,
+    /// Inactive webhooks keep their settings but are not sent events
+    #[serde(default)]
+    pub active: Option<bool>,
+    /// Tenant a platform admin creates the webhook for; a tenant admin's webhooks always
+    /// belong to its own tenant. Not changed by updates.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+/// A practitioner asking to join an organization, named by one of its identifiers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestAffiliationRequest {
+    pub organization_identifier: String,
+    #[serde(default)]
+    pub role: AffiliationRole,
+}
+
+/// An affiliation an admin records directly, already active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateAffiliationRequest {
+    pub practitioner_did: String,
+    #[serde(default)]
+    pub role: AffiliationRole,
+    /// Defaults to now
+    #[serde(default)]
+    pub period_start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub period_end: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakGlassRequest {
+    pub patient_did: String,
+    pub justification: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReviewBreakGlassRequest {
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateReferralRequest {
+    pub patient_did: String,
+    pub receiving_did: String,
+    pub reason: String,
+    #[serde(default)]
+    pub priority: ReferralPriority,
+    /// FHIR references such as `Encounter/<id>` to share with the receiving practitioner
+    pub resources: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateReferralRequest {
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateConsentRequest {
+    pub grantee_did: String,
+    pub data_classes: Vec<String>,
+    /// Defaults to now
+    pub period_start: Option<DateTime<Utc>>,
+    pub period_end: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateAppointmentRequest {
+    /// A dependent to book for; the caller themselves when left out
+    #[serde(default)]
+    pub patient_did: Option<String>,
+    pub practitioner_did: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub reason: FhirCodeableConcept,
+    pub location: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetAvailabilityRequest {
+    pub timezone: String,
+    pub weekly: Vec<WeeklyWindow>,
+    #[serde(default)]
+    pub exceptions: Vec<AvailabilityException>,
+}
+
+/// Reminder deliveries since the process started, per channel
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReminderMetricsSnapshot {
+    pub email_sent: u64,
+    pub email_failed: u64,
+    pub sms_sent: u64,
+    pub sms_failed: u64,
+    pub push_sent: u64,
+    pub push_failed: u64,
+}
+
+/// Events published on the in-process bus since the process started, and what each subscriber made of them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventBusMetricsSnapshot {
+    pub published: u64,
+    pub subscribers: Vec<SubscriberMetricsSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriberMetricsSnapshot {
+    pub name: String,
+    pub handled: u64,
+    pub failed: u64,
+    pub panicked: u64,
+    /// Events it missed by falling more than the bus capacity behind
+    pub dropped: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// Set on errors the app reacts to specifically, such as `captcha_failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -393,6 +3138,7 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            code: None,
             timestamp: Utc::now(),
         }
     }
@@ -402,7 +3148,12 @@ impl<T> ApiResponse<T> {
             success: false,
             data: None,
             error: Some(error),
+            code: None,
             timestamp: Utc::now(),
         }
     }
+
+    pub fn error_with_code(error: String, code: &str) -> Self {
+        Self { code: Some(code.to_string()), ..Self::error(error) }
+    }
 }
@@ -0,0 +1,265 @@
+//! Development data for new environments and demos: verified practitioners, patients who granted
+//! them access, encounters in every status with vitals, diagnoses and prescriptions, vaccination
+//! credentials and audit logs, some of them anchored.
+//!
+//! Everything is written through the services the API uses, so records refer to each other as
+//! real traffic would leave them. Two things have no API of their own: practitioner licenses are
+//! marked verified in the database, and diagnoses are stored as Conditions directly. Names, DIDs
+//! and codes come from [`Fixtures`] and repeat for the same seed; the DIDs are fixed accounts on
+//! the configured network that are not backed by keys.
+
+use anyhow::{bail, Result};
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api::handlers::IssueCredentialRequest;
+use crate::config::Config;
+use crate::fixtures::{self, Fixtures, Presentation};
+use crate::jobs::JobWorkerPool;
+use crate::models::*;
+use crate::services::fhir::FhirManager;
+use crate::services::AuthServiceImpl;
+use crate::state::AppState;
+
+/// Who the audit log names for what `seed` did itself rather than as a seeded user
+pub const SEED_ACTOR: &str = "system:seed";
+/// Seeded DIDs are accounts from here on, 10 000 per seed; patients start `PATIENT_OFFSET` in
+const ACCOUNT_BASE: u64 = 8_000_000;
+const PATIENT_OFFSET: u64 = 1_000;
+pub const MAX_PRACTITIONERS: usize = PATIENT_OFFSET as usize;
+pub const MAX_PATIENTS: usize = 9_000;
+const VACCINATION_CREDENTIAL: &str = "VaccinationCredential";
+const VACCINES: [&str; 4] = ["Measles-Rubella", "Yellow fever", "Hepatitis B", "COVID-19 mRNA"];
+/// How often each status comes up; most visits are over
+const ENCOUNTER_STATUSES: [EncounterStatus; 6] = [
+    EncounterStatus::Finished,
+    EncounterStatus::Finished,
+    EncounterStatus::Finished,
+    EncounterStatus::InProgress,
+    EncounterStatus::Planned,
+    EncounterStatus::Cancelled,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct SeedOptions {
+    pub seed: u64,
+    pub patients: usize,
+    pub practitioners: usize,
+}
+
+/// One created record: a DID or database id, and a line describing it
+#[derive(Debug, Clone, Serialize)]
+pub struct Seeded {
+    pub id: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SeedSummary {
+    pub seed: u64,
+    pub practitioners: Vec<Seeded>,
+    pub patients: Vec<Seeded>,
+    pub grants: Vec<Seeded>,
+    pub encounters: Vec<Seeded>,
+    pub prescriptions: Vec<Seeded>,
+    pub credentials: Vec<Seeded>,
+    /// Audit logs the seed's anchoring run put on the ledger
+    pub anchored_logs: usize,
+    /// Audit logs written after it, left for the next run
+    pub unanchored_logs: usize,
+}
+
+impl SeedSummary {
+    /// Every created record as a row of kind, id and detail
+    pub fn table(&self) -> String {
+        let sections = [
+            ("practitioner", &self.practitioners),
+            ("patient", &self.patients),
+            ("grant", &self.grants),
+            ("encounter", &self.encounters),
+            ("prescription", &self.prescriptions),
+            ("credential", &self.credentials),
+        ];
+        let width = sections.iter().flat_map(|(_, rows)| rows.iter()).map(|row| row.id.len()).max().unwrap_or(0).max("ID".len());
+        let mut table = format!("{:<12}  {:<width$}  DETAIL\n", "KIND", "ID");
+        for (kind, rows) in sections {
+            for row in rows {
+                table.push_str(&format!("{:<12}  {:<width$}  {}\n", kind, row.id, row.detail));
+            }
+        }
+        table.push_str(&format!("Audit logs: {} anchored, {} unanchored (seed {})\n", self.anchored_logs, self.unanchored_logs, self.seed));
+        table
+    }
+}
+
+/// Seed data never goes into production; `APP_ENV` has to name another environment
+pub fn ensure_not_production(config: &Config) -> Result<()> {
+    if config.environment.eq_ignore_ascii_case("production") {
+        bail!("Refusing to seed a production environment; set APP_ENV to development, staging or the like");
+    }
+    Ok(())
+}
+
+/// Fill the database behind `state` with `options.patients` patients and their records, cared
+/// for by `options.practitioners` practitioners
+pub async fn seed(state: &AppState<AuthServiceImpl>, options: SeedOptions) -> Result<SeedSummary> {
+    ensure_not_production(&state.config)?;
+    if !(1..=MAX_PRACTITIONERS).contains(&options.practitioners) || !(1..=MAX_PATIENTS).contains(&options.patients) {
+        bail!("Seed between 1 and {} practitioners and between 1 and {} patients", MAX_PRACTITIONERS, MAX_PATIENTS);
+    }
+    let network = &state.config.hedera_network;
+    let base = ACCOUNT_BASE + (options.seed % 100) * 10_000;
+    let practitioner_dids: Vec<String> = (0..options.practitioners as u64).map(|i| fixtures::did(network, base + i)).collect();
+    if state.database.get_practitioner_by_did(&practitioner_dids[0]).await?.is_some() {
+        bail!("This database already holds seed {}'s data; drop it or pick another seed", options.seed);
+    }
+    let mut fixtures = Fixtures::new(options.seed);
+    let mut summary = SeedSummary { seed: options.seed, ..Default::default() };
+
+    let mut issuer = None;
+    for (i, did) in practitioner_dids.iter().enumerate() {
+        let name = fixtures.practitioner_name();
+        let license_number = format!("KMPDC-{}", base + i as u64);
+        state.practitioner_service.register(did, fixtures::practitioner_registration(did, vec![name.clone()], &license_number, None)).await?;
+        state.practitioner_service.verify_license(did, Utc::now().date_naive()).await?;
+        let mut detail = format!("{}, license {}", full_name(&name), license_number);
+        // The first practitioner vaccinates, when the platform can sign credentials
+        if i == 0 && state.config.credential_signing.is_some() {
+            let request = RegisterIssuerRequest { did: did.clone(), display_name: full_name(&name), credential_types: vec![VACCINATION_CREDENTIAL.to_string()] };
+            state.issuer_registry.register(SEED_ACTOR, request).await?;
+            issuer = Some(did.clone());
+            detail.push_str(", issues vaccination credentials");
+        }
+        summary.practitioners.push(Seeded { id: did.clone(), detail });
+    }
+
+    // Each patient grants one practitioner, who sees them at every visit
+    let mut patients = Vec::new();
+    for i in 0..options.patients as u64 {
+        let did = fixtures::did(network, base + PATIENT_OFFSET + i);
+        let fhir_patient = fixtures.fhir_patient();
+        let detail = format!(
+            "{}, {}, born {}",
+            fhir_patient.name.first().map(full_name).unwrap_or_default(),
+            fhir_patient.gender,
+            fhir_patient.birth_date
+        );
+        state.database.create_patient(&fixtures::patient(&did, fhir_patient), &state.config.ipfs_encryption_key).await?;
+        state.audit_log_service.log(&did, "register_patient", Some(json!({ "actor": SEED_ACTOR }))).await;
+        summary.patients.push(Seeded { id: did.clone(), detail });
+
+        let practitioner_did = fixtures.pick(&practitioner_dids).clone();
+        let request = GrantAccessRequest {
+            patient_did: did.clone(),
+            grantee_did: practitioner_did.clone(),
+            permissions: vec![Permission::Read, Permission::Write, Permission::Prescribe],
+            expires_at: None,
+        };
+        let grant = state.patient_service.grant_access(&did, request).await?;
+        summary.grants.push(Seeded { id: object_id(grant.id), detail: format!("{} to {}", did, practitioner_did) });
+        patients.push((did, practitioner_did));
+    }
+
+    // Registrations and grants end up anchored, the visits below are left for the next run
+    let before = state.database.get_unanchored_audit_logs().await?.len();
+    state.auditing_service.anchor_audit_logs().await?;
+    summary.anchored_logs = before.saturating_sub(state.database.get_unanchored_audit_logs().await?.len());
+
+    for (patient_did, practitioner_did) in &patients {
+        let mut prescribed = false;
+        for _ in 0..fixtures.between(1..=3) {
+            let status = *fixtures.pick(&ENCOUNTER_STATUSES);
+            let presentation = fixtures.presentation();
+            let encounter = Visit { patient_did, practitioner_did, presentation, status };
+            // One prescription each, so no patient's medications interact
+            let prescribe = status == EncounterStatus::Finished && !prescribed;
+            prescribed |= prescribe;
+            encounter.seed(state, &mut fixtures, prescribe, &mut summary).await?;
+        }
+        if let Some(issuer) = issuer.as_deref().filter(|_| fixtures.chance(0.5)) {
+            let vaccine = *fixtures.pick(&VACCINES);
+            let dose = fixtures.between(1..=3);
+            let request = IssueCredentialRequest {
+                subject_did: patient_did.clone(),
+                credential_type: VACCINATION_CREDENTIAL.to_string(),
+                expires_at: None,
+                metadata: json!({ "vaccine": vaccine, "dose": dose }).to_string(),
+            };
+            let credential = state.vc_service.issue_credential(issuer, Role::Practitioner, request).await?;
+            summary.credentials.push(Seeded { id: object_id(credential.id), detail: format!("{} dose {} for {}", vaccine, dose, patient_did) });
+        }
+    }
+
+    // Record grants and credentials on the ledger now rather than leaving the jobs to the server
+    let outbox = JobWorkerPool::new(state.database.clone(), state.config.jobs.clone()).register(state.outbox_dispatcher.clone());
+    while outbox.run_next("seed", Utc::now()).await? {}
+    summary.unanchored_logs = state.database.get_unanchored_audit_logs().await?.len();
+    Ok(summary)
+}
+
+/// One encounter of a seeded patient
+struct Visit<'a> {
+    patient_did: &'a str,
+    practitioner_did: &'a str,
+    presentation: Presentation,
+    status: EncounterStatus,
+}
+
+impl Visit<'_> {
+    async fn seed(&self, state: &AppState<AuthServiceImpl>, fixtures: &mut Fixtures, prescribe: bool, summary: &mut SeedSummary) -> Result<()> {
+        let now = Utc::now();
+        let start = match self.status {
+            EncounterStatus::Planned => now + Duration::days(fixtures.between(1..=30) as i64),
+            EncounterStatus::InProgress => now - Duration::minutes(20),
+            EncounterStatus::Finished | EncounterStatus::Cancelled => now - Duration::days(fixtures.between(1..=365) as i64),
+        };
+        let end = matches!(self.status, EncounterStatus::Finished).then(|| start + Duration::minutes(30));
+        let period = FhirPeriod { start: Some(start.into()), end: end.map(Into::into) };
+        let request = fixtures::encounter_request(self.patient_did, self.practitioner_did, self.presentation, period);
+
+        let encounter_id = if self.status == EncounterStatus::Planned {
+            object_id(state.encounter_service.plan_encounter(request).await?.id)
+        } else {
+            object_id(state.encounter_service.create_encounter(self.practitioner_did, Role::Practitioner, request).await?.encounter.id)
+        };
+        let mut detail = format!("{} {} for {}", self.status.fhir_code(), self.presentation.reason, self.patient_did);
+        match self.status {
+            EncounterStatus::Planned => {}
+            EncounterStatus::InProgress => {
+                state.encounter_service.record_vitals(self.practitioner_did, Role::Practitioner, &encounter_id, fixtures.vitals(start)).await?;
+            }
+            EncounterStatus::Cancelled => {
+                let request = UpdateEncounterStatusRequest { status: EncounterStatus::Cancelled, reason: Some("The patient did not attend".to_string()), force: false };
+                state.encounter_service.update_status(self.practitioner_did, Role::Practitioner, &encounter_id, request).await?;
+            }
+            EncounterStatus::Finished => {
+                state.encounter_service.record_vitals(self.practitioner_did, Role::Practitioner, &encounter_id, fixtures.vitals(start)).await?;
+                let condition = fixtures::condition(self.patient_did, &encounter_id, self.presentation, start.date_naive());
+                FhirManager::validate_condition(&condition)?;
+                state.database.create_condition(&condition).await?;
+                if prescribe {
+                    let request = fixtures::prescription_request(self.patient_did, self.practitioner_did, &encounter_id, self.presentation);
+                    let created = state.prescription_service.create(self.practitioner_did, Role::Practitioner, request).await?;
+                    let prescription_id = object_id(created.prescription.id);
+                    summary.prescriptions.push(Seeded { id: prescription_id, detail: format!("RxNorm {} for {}", self.presentation.medication, self.patient_did) });
+                }
+                let request = UpdateEncounterStatusRequest { status: EncounterStatus::Finished, reason: None, force: false };
+                let finished = state.encounter_service.update_status(self.practitioner_did, Role::Practitioner, &encounter_id, request).await?;
+                if let Some(ipfs_hash) = finished.final_bundle_ipfs_hash {
+                    detail.push_str(&format!(", bundle {}", ipfs_hash));
+                }
+            }
+        }
+        summary.encounters.push(Seeded { id: encounter_id, detail });
+        Ok(())
+    }
+}
+
+fn full_name(name: &FhirHumanName) -> String {
+    name.prefix.iter().chain(&name.given).chain(&name.family).cloned().collect::<Vec<_>>().join(" ")
+}
+
+fn object_id(id: Option<bson::oid::ObjectId>) -> String {
+    id.map(|id| id.to_hex()).unwrap_or_default()
+}
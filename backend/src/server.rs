@@ -0,0 +1,266 @@
+//! Starting the API server: the background workers, the listener and a graceful shutdown.
+//!
+//! `main` runs [`run`] for the `serve` command; the integration tests call [`serve`] directly,
+//! so they go through the same router and connection setup.
+
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::routes::build_router;
+use crate::config::Config;
+use crate::error_reporting;
+use crate::jobs::JobWorkerPool;
+use crate::services::break_glass::BreakGlassAlertWorker;
+use crate::services::compliance_report::ComplianceReportHandler;
+use crate::services::config_reload::{ConfigReloadService, SIGNAL_ACTOR};
+use crate::services::email_outbox::SendEmailHandler;
+use crate::services::hedera_balance::HederaBalanceWorker;
+use crate::services::hedera_costs::HederaBudgetWorker;
+use crate::services::ip_blocks::LoginActivityPersister;
+use crate::services::license_expiry::LicenseExpiryHandler;
+use crate::services::patient_export::{PatientExportExpiryHandler, PatientExportHandler};
+use crate::services::patient::PatientErasureHandler;
+use crate::services::patient_merge::DuplicateDetectionHandler;
+use crate::services::patient_search::PatientSearchIndexHandler;
+use crate::services::reminders::AppointmentReminderHandler;
+use crate::services::status_list::StatusListPublisher;
+use crate::services::vc_document::CredentialSigner;
+use crate::services::webhooks::WebhookDeliveryHandler;
+use crate::services::AuthServiceImpl;
+use crate::state::{AppState, AppStateBuilder};
+use crate::telemetry::LogLevelHandle;
+#[cfg(feature = "tls")]
+use crate::tls;
+
+/// How long in-flight requests get to complete once shutdown starts
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Build the app, start the background workers and serve until Ctrl+C or SIGTERM
+pub async fn run(config: Arc<Config>, log_level: LogLevelHandle) -> Result<()> {
+    let features = config.validate_features();
+    tracing::info!("Integrations: {}", features);
+    if !(features.sms && features.email && features.chat) {
+        tracing::warn!("Some integrations are disabled; requests that need them will be answered with 501 Not Implemented");
+    }
+    match &config.credential_signing {
+        Some(signing) => {
+            let signer = CredentialSigner::from_config(signing)?;
+            tracing::info!(
+                "Credentials are issued by {} and signed with {} (publicKeyMultibase {})",
+                signer.issuer_did(),
+                signer.verification_method(),
+                signer.public_key_multibase()
+            );
+        }
+        None => tracing::warn!("Credential signing is not configured; issuing credentials will be answered with 501 Not Implemented"),
+    }
+
+    let app_state = Arc::new(AppStateBuilder::new(config).with_log_level(log_level).build().await?);
+    let auditing_service = app_state.auditing_service.clone();
+
+    // Cancelled on Ctrl+C / SIGTERM; background workers watch it to finish their current work
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    // SIGHUP reloads the settings that can change without a restart
+    tokio::spawn(reload_on_hangup(app_state.config_reload_service.clone()));
+
+    // --- Spawn Background Tasks ---
+    let audit_handle = tokio::spawn(error_reporting::background("audit_anchoring", async move {
+        let mut interval = time::interval(Duration::from_secs(3600)); // Anchor logs every hour
+        loop {
+            interval.tick().await;
+            tracing::info!("Running periodic audit log anchoring...");
+            if let Err(e) = auditing_service.anchor_audit_logs().await {
+                tracing::error!("Failed to anchor audit logs: {}", e);
+            }
+        }
+    }));
+
+    // Email and webhook delivery, the Hedera and IPFS outbox, appointment reminders, duplicate patient scans, the name search index, patient exports, compliance reports, license expiry and patient erasure run on the job queue
+    let job_pool = JobWorkerPool::new(app_state.database.clone(), app_state.config.jobs.clone())
+        .register(Arc::new(SendEmailHandler::new(app_state.email_service.clone())))
+        .register(Arc::new(WebhookDeliveryHandler::new(app_state.webhook_service.clone())))
+        .register(app_state.outbox_dispatcher.clone())
+        .register(Arc::new(DuplicateDetectionHandler::new(app_state.patient_merge_service.clone())))
+        .register(Arc::new(PatientSearchIndexHandler::new(app_state.database.clone(), app_state.config.clone())))
+        .register(Arc::new(PatientExportHandler::new(app_state.patient_export_service.clone(), app_state.config.jobs.max_attempts)))
+        .register(Arc::new(PatientExportExpiryHandler::new(app_state.patient_export_service.clone())))
+        .register(Arc::new(ComplianceReportHandler::new(app_state.compliance_report_service.clone(), app_state.config.jobs.max_attempts)))
+        .register(Arc::new(LicenseExpiryHandler::new(app_state.license_expiry_service.clone())))
+        .register(Arc::new(PatientErasureHandler::new(app_state.patient_service.clone())))
+        .register(Arc::new(AppointmentReminderHandler::new(
+            app_state.database.clone(),
+            app_state.database.clone(),
+            app_state.database.clone(),
+            app_state.notification_service.clone(),
+            app_state.reminder_metrics.clone(),
+        )));
+    let job_handle = tokio::spawn(error_reporting::background("jobs", job_pool.run(shutdown.clone())));
+
+    let break_glass_worker = BreakGlassAlertWorker::new(
+        app_state.database.clone(),
+        app_state.database.clone(),
+        app_state.email_service.clone(),
+        app_state.config.clone(),
+    );
+    let break_glass_handle = tokio::spawn(error_reporting::background("break_glass_alerts", break_glass_worker.run(shutdown.clone())));
+
+    let status_list_publisher = StatusListPublisher::new(app_state.status_list_service.clone());
+    let status_list_handle = tokio::spawn(error_reporting::background("status_list", status_list_publisher.run(shutdown.clone())));
+
+    let hedera_budget_worker = HederaBudgetWorker::new(app_state.hedera_cost_service.clone());
+    let hedera_budget_handle = tokio::spawn(error_reporting::background("hedera_budget", hedera_budget_worker.run(shutdown.clone())));
+
+    let hedera_balance_worker = HederaBalanceWorker::new(app_state.hedera_balance_monitor.clone(), &app_state.config);
+    let hedera_balance_handle = tokio::spawn(error_reporting::background("hedera_balance", hedera_balance_worker.run(shutdown.clone())));
+
+    let login_activity_persister = LoginActivityPersister::new(app_state.ip_block_service.clone());
+    let login_activity_handle = tokio::spawn(error_reporting::background("login_activity", login_activity_persister.run(shutdown.clone())));
+
+    // --- Build Application ---
+    let addr = SocketAddr::from(([0, 0, 0, 0], app_state.config.server_port));
+
+    if app_state.config.use_tls {
+        #[cfg(feature = "tls")]
+        {
+            let app = build_router(app_state.clone());
+            // Configure TLS
+            let tls_config = tls::load_rustls_config(
+                std::path::Path::new(&app_state.config.tls_cert_path),
+                std::path::Path::new(&app_state.config.tls_key_path),
+            )
+            .await?;
+            let watcher = tls::CertWatcher::new(&app_state.config.tls_cert_path, &app_state.config.tls_key_path);
+            let _reload_handle = tls::spawn_reloader(tls_config.clone(), watcher);
+
+            tracing::info!("Server running on https://{}", addr);
+            spawn_index_build(&app_state);
+
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            let server_shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                server_shutdown.cancelled().await;
+                shutdown_handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+            });
+            axum_server::bind_rustls(addr, tls_config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        #[cfg(not(feature = "tls"))]
+        {
+            tracing::warn!("TLS is enabled in the configuration, but the `tls` feature is not compiled. Falling back to HTTP.");
+            let listener = TcpListener::bind(addr).await?;
+            spawn_index_build(&app_state);
+            serve(app_state.clone(), listener, shutdown.clone()).await?;
+        }
+    } else {
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("Server running on http://{}", addr);
+        spawn_index_build(&app_state);
+        serve(app_state.clone(), listener, shutdown.clone()).await?;
+    }
+
+    // Cleanly shut down background tasks; the workers finish what they are sending
+    shutdown.cancel();
+    audit_handle.abort();
+    if let Err(e) = job_handle.await {
+        tracing::error!("Job worker pool panicked: {}", e);
+    }
+    if let Err(e) = break_glass_handle.await {
+        tracing::error!("Break-glass alert worker panicked: {}", e);
+    }
+    if let Err(e) = hedera_budget_handle.await {
+        tracing::error!("Hedera budget worker panicked: {}", e);
+    }
+    if let Err(e) = hedera_balance_handle.await {
+        tracing::error!("Hedera balance worker panicked: {}", e);
+    }
+    if let Err(e) = status_list_handle.await {
+        tracing::error!("Status list publisher panicked: {}", e);
+    }
+    if let Err(e) = login_activity_handle.await {
+        tracing::error!("Sign-in failure persister panicked: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Build the indexes while requests are already being served, unless the operator builds them
+/// out of band; the admin stats and deep health check report how far it got
+fn spawn_index_build(app_state: &AppState<AuthServiceImpl>) {
+    let database = app_state.database.clone();
+    if app_state.config.skip_index_creation {
+        tracing::info!("SKIP_INDEX_CREATION is set; build indexes with `migrate --indexes-only`");
+        database.skip_index_creation();
+        return;
+    }
+    tokio::spawn(error_reporting::background("indexes", async move {
+        tracing::info!("Building indexes");
+        let status = database.create_indexes().await;
+        match status.failed.len() {
+            0 => tracing::info!("Indexes are built ({} ensured)", status.ensured),
+            failed => tracing::error!("{} of {} indexes could not be built; see GET /api/admin/stats", failed, status.ensured as usize + failed),
+        }
+    }));
+}
+
+/// Serve the API over plain HTTP on `listener` until `shutdown` is cancelled, letting
+/// in-flight requests finish
+pub async fn serve(app_state: Arc<AppState<AuthServiceImpl>>, listener: TcpListener, shutdown: CancellationToken) -> Result<()> {
+    let app = build_router(app_state);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown signal received; draining requests");
+    shutdown.cancel();
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(config_reload_service: Arc<ConfigReloadService>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        tracing::info!("SIGHUP received; reloading configuration");
+        if let Err(e) = config_reload_service.reload(SIGNAL_ACTOR).await {
+            tracing::error!("Failed to reload configuration: {}", e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_config_reload_service: Arc<ConfigReloadService>) {}
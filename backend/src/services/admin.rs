@@ -0,0 +1,231 @@
+//! Account management and platform statistics for admins.
+
+use anyhow::anyhow;
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::Database;
+use crate::models::*;
+use crate::services::{sms_throttle, DataAccessMonitor};
+use crate::store::{AdminStore, SessionStore};
+
+/// Largest page the patient list returns
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+// --- AdminService ---
+pub struct AdminService {
+    db: Arc<dyn AdminStore>,
+    sessions: Arc<dyn SessionStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+    access_monitor: Option<Arc<DataAccessMonitor>>,
+}
+
+impl AdminService {
+    pub fn new(db: Arc<dyn AdminStore>, sessions: Arc<dyn SessionStore>, config: Arc<Config>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, sessions, config, audit_log_service, access_monitor: None }
+    }
+
+    /// Alert on every patient list exported, which the hourly read limits do not cover
+    pub fn with_access_monitor(mut self, access_monitor: Arc<DataAccessMonitor>) -> Self {
+        self.access_monitor = Some(access_monitor);
+        self
+    }
+
+    /// One page of patients, newest first. Only the name is decrypted from each record.
+    pub async fn list_patients(&self, admin_did: &str, page: u64, page_size: u64) -> anyhow::Result<Page<PatientSummary>> {
+        let page = page.max(1);
+        let page_size = page_size.clamp(1, MAX_PAGE_SIZE);
+        let (patients, total) = self.db.list_patients((page - 1) * page_size, page_size as i64).await?;
+        let items: Vec<PatientSummary> = patients.into_iter().map(|patient| self.summarize(patient)).collect();
+        self.audit_log_service
+            .log(admin_did, "admin_list_patients", Some(json!({ "actor": admin_did, "page": page, "patients": items.iter().map(|p| &p.did).collect::<Vec<_>>() })))
+            .await;
+        if let Some(monitor) = &self.access_monitor {
+            monitor.record_admin_export(admin_did).await;
+        }
+        Ok(Page { items, page, page_size, total })
+    }
+
+    /// Practitioners, optionally only those whose license is (or is not) verified yet
+    pub async fn list_practitioners(&self, admin_did: &str, verified: Option<bool>) -> anyhow::Result<Vec<Practitioner>> {
+        let practitioners = self.db.list_practitioners(verified).await?;
+        self.audit_log_service
+            .log(admin_did, "admin_list_practitioners", Some(json!({ "actor": admin_did, "verified": verified })))
+            .await;
+        Ok(practitioners)
+    }
+
+    /// Suspend an account: it is signed out everywhere and refused at sign-in until re-enabled
+    pub async fn disable_patient(&self, admin_did: &str, did: &str) -> anyhow::Result<()> {
+        if !self.db.set_patient_disabled(did, true).await? {
+            return Err(anyhow!("Patient not found or already suspended"));
+        }
+        let sessions_revoked = self.sessions.revoke_sessions(did).await?;
+        self.audit_log_service
+            .log(did, "disable_patient", Some(json!({ "actor": admin_did, "sessions_revoked": sessions_revoked })))
+            .await;
+        Ok(())
+    }
+
+    pub async fn enable_patient(&self, admin_did: &str, did: &str) -> anyhow::Result<()> {
+        if !self.db.set_patient_disabled(did, false).await? {
+            return Err(anyhow!("Patient not found or not suspended"));
+        }
+        self.audit_log_service.log(did, "enable_patient", Some(json!({ "actor": admin_did }))).await;
+        Ok(())
+    }
+
+    pub async fn stats(&self, admin_did: &str) -> anyhow::Result<SystemStats> {
+        let mut stats = self.db.system_stats().await?;
+        let sends = self.db.daily_sms_sends(&sms_throttle::stats_since(Utc::now())).await?;
+        stats.sms = sms_throttle::stats(sends, &self.config.sms_throttle);
+        self.audit_log_service.log(admin_did, "admin_view_stats", Some(json!({ "actor": admin_did }))).await;
+        Ok(stats)
+    }
+
+    /// Stored FHIR dates found malformed or out of order when the `0006_fhir_date_report` migration ran
+    pub async fn fhir_date_issues(&self, admin_did: &str) -> anyhow::Result<Vec<FhirDateIssue>> {
+        let issues = self.db.list_fhir_date_issues().await?;
+        self.audit_log_service.log(admin_did, "admin_list_fhir_date_issues", Some(json!({ "actor": admin_did }))).await;
+        Ok(issues)
+    }
+
+    fn summarize(&self, patient: EncryptedPatient) -> PatientSummary {
+        let name = match Database::decrypt_fhir_patient(&patient, &self.config.ipfs_encryption_key) {
+            Ok(fhir_patient) => display_name(&fhir_patient),
+            // Nothing is left to read of an erased patient
+            Err(_) if patient.erased_at.is_some() => String::new(),
+            Err(e) => {
+                tracing::warn!(did = %patient.did, "Failed to decrypt patient for the admin list: {}", e);
+                String::new()
+            }
+        };
+        PatientSummary {
+            did: patient.did,
+            name,
+            email_verified: patient.email_verified,
+            created_at: patient.created_at,
+            disabled_at: patient.disabled_at,
+            deleted_at: patient.deleted_at,
+            erased_at: patient.erased_at,
+        }
+    }
+}
+
+/// "Jane Otieno", from the first name on the patient's FHIR resource
+pub(crate) fn display_name(patient: &FhirPatient) -> String {
+    let Some(name) = patient.name.first() else {
+        return String::new();
+    };
+    name.given.iter().chain(&name.family).map(String::as_str).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAdminStore, MockAuditStore, MockSessionStore};
+    use crate::utils::encrypt;
+    use std::sync::Mutex;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.100";
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config { ipfs_encryption_key: "11".repeat(32).into(), ..Default::default() })
+    }
+
+    fn service(db: MockAdminStore, sessions: MockSessionStore) -> (AdminService, Arc<Mutex<Vec<AuditLog>>>) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut audit_store = MockAuditStore::new();
+        let logged = logs.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.clone());
+            Ok(())
+        });
+        let audit = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        (AdminService::new(Arc::new(db), Arc::new(sessions), config(), audit), logs)
+    }
+
+    fn encrypted_patient(did: &str, encrypted_fhir_patient: String) -> EncryptedPatient {
+        EncryptedPatient {
+            id: None,
+            did: did.to_string(),
+            encrypted_fhir_patient,
+            email_hash: None,
+            phone_hash: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            email_verified: true,
+            verification_token: None,
+            verification_token_expires: None,
+            deleted_at: None,
+            wrapped_data_key: None,
+            erased_at: None,
+            notification_preferences: None,
+            chat_record_consent: false,
+            timezone: None,
+            totp: None,
+            backup_codes: Vec::new(),
+            disabled_at: None,
+            merged_dids: Vec::new(),
+            search_tokens: Vec::new(),
+            search_key_id: None,
+            photo: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn patient_list_shows_only_names_and_caps_the_page() {
+        let fhir_patient = json!({
+            "resourceType": "Patient", "id": "1", "identifier": [], "gender": "female", "birth_date": "1990-01-01",
+            "name": [{ "use": "official", "family": "Otieno", "given": ["Jane"], "prefix": [], "suffix": [] }],
+            "address": [], "telecom": [{ "system": "email", "value": "jane@example.com", "use": "home" }],
+        });
+        let readable = encrypt(fhir_patient.to_string().as_bytes(), &config().ipfs_encryption_key).unwrap();
+        let mut db = MockAdminStore::new();
+        db.expect_list_patients().withf(|skip, limit| *skip == 200 && *limit == 100).returning(move |_, _| {
+            Ok((vec![encrypted_patient(PATIENT, readable.clone()), encrypted_patient("did:hedera:testnet:0.0.2", "garbage".to_string())], 202))
+        });
+        let (service, logs) = service(db, MockSessionStore::new());
+
+        let page = service.list_patients(ADMIN, 3, 500).await.unwrap();
+
+        assert_eq!((page.page, page.page_size, page.total), (3, 100, 202));
+        assert_eq!(page.items[0].name, "Jane Otieno");
+        assert_eq!(page.items[1].name, "", "an unreadable record does not fail the page");
+        assert!(!serde_json::to_string(&page).unwrap().contains("jane@example.com"));
+        let logs = logs.lock().unwrap();
+        assert_eq!((logs[0].did.as_str(), logs[0].action.as_str()), (ADMIN, "admin_list_patients"));
+    }
+
+    #[tokio::test]
+    async fn disabling_signs_the_patient_out_and_is_audited() {
+        let mut db = MockAdminStore::new();
+        let disabled = Arc::new(Mutex::new(false));
+        let state = disabled.clone();
+        db.expect_set_patient_disabled().returning(move |_, disable| {
+            let mut disabled = state.lock().unwrap();
+            let changed = *disabled != disable;
+            *disabled = disable;
+            Ok(changed)
+        });
+        let mut sessions = MockSessionStore::new();
+        sessions.expect_revoke_sessions().withf(|did| did == PATIENT).times(1).returning(|_| Ok(2));
+        let (service, logs) = service(db, sessions);
+
+        service.disable_patient(ADMIN, PATIENT).await.unwrap();
+        assert!(service.disable_patient(ADMIN, PATIENT).await.is_err());
+        service.enable_patient(ADMIN, PATIENT).await.unwrap();
+        assert!(service.enable_patient(ADMIN, PATIENT).await.is_err());
+
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs.iter().map(|log| log.action.as_str()).collect::<Vec<_>>(), vec!["disable_patient", "enable_patient"]);
+        assert_eq!(logs[0].did, PATIENT);
+        assert_eq!(logs[0].details.as_ref().unwrap()["actor"], ADMIN);
+        assert_eq!(logs[0].details.as_ref().unwrap()["sessions_revoked"], 2);
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::anyhow;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::api::handlers::CreateEncounterRequest;
+use crate::auditing::AuditLogService;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::{AvailabilityService, EncounterService, OrganizationService, RelationshipService, ServiceError};
+use crate::store::AppointmentStore;
+
+/// HL7 v3 ActCode for an outpatient visit, the class given to encounters planned from appointments
+const AMBULATORY: &str = "AMB";
+const ACT_CODE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ActCode";
+
+// --- AppointmentService ---
+pub struct AppointmentService {
+    db: Arc<dyn AppointmentStore>,
+    availability_service: Arc<AvailabilityService>,
+    encounter_service: Arc<EncounterService>,
+    relationship_service: Arc<RelationshipService>,
+    organization_service: Arc<OrganizationService>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl AppointmentService {
+    pub fn new(
+        db: Arc<dyn AppointmentStore>,
+        availability_service: Arc<AvailabilityService>,
+        encounter_service: Arc<EncounterService>,
+        relationship_service: Arc<RelationshipService>,
+        organization_service: Arc<OrganizationService>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, availability_service, encounter_service, relationship_service, organization_service, audit_log_service }
+    }
+
+    /// Ask `request.practitioner_did` for a visit; it stays `requested` until they confirm it.
+    /// Guardians can book for a dependent by naming them in `request.patient_did`.
+    ///
+    /// The time has to line up with free slots of the practitioner's availability. Those
+    /// slots are stored on the appointment, and a unique index over them stops a second
+    /// overlapping appointment from being confirmed if another one got there first.
+    pub async fn request_appointment(&self, caller_did: &str, request: CreateAppointmentRequest) -> anyhow::Result<Appointment> {
+        let patient_did = request.patient_did.as_deref().unwrap_or(caller_did);
+        let booked_by_guardian = patient_did != caller_did;
+        if booked_by_guardian && self.relationship_service.guardianship(caller_did, patient_did).await?.is_none() {
+            return Err(ServiceError::Forbidden("Only a patient or their guardian can book for them".to_string()).into());
+        }
+        if request.end <= request.start {
+            return Err(anyhow!("Appointment must end after it starts"));
+        }
+        if request.start <= Utc::now() {
+            return Err(anyhow!("Appointments can only be requested for a future time"));
+        }
+        let slots = self.availability_service.slots_for(&request.practitioner_did, request.start, request.end).await?;
+        let mut appointment = Appointment {
+            id: None,
+            patient_did: patient_did.to_string(),
+            practitioner_did: request.practitioner_did,
+            status: AppointmentStatus::Requested,
+            start: request.start,
+            end: request.end,
+            reason: request.reason,
+            location: request.location,
+            encounter_id: None,
+            reminder_sent_at: ReminderMarkers::default(),
+            slots,
+            // The practitioner's tenant, filled in when it is stored
+            tenant_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let id = self.db.create_appointment(&appointment).await?;
+        let mut details = json!({ "appointment_id": id.to_hex() });
+        if booked_by_guardian {
+            details["guardian"] = json!(caller_did);
+            details["dependent"] = json!(patient_did);
+        }
+        self.audit_log_service.log(patient_did, "request_appointment", Some(details)).await;
+        appointment.id = Some(id);
+        Ok(appointment)
+    }
+
+    /// Confirm a requested appointment as its practitioner, optionally planning the encounter for it.
+    ///
+    /// The slot check is left to the database: a second confirmed appointment for the
+    /// same practitioner sharing a start time or slot violates a unique index and becomes a conflict.
+    pub async fn confirm_appointment(&self, practitioner_did: &str, appointment_id: &str, create_encounter: bool) -> anyhow::Result<Appointment> {
+        let (id, mut appointment) = self.appointment(appointment_id).await?;
+        if appointment.practitioner_did != practitioner_did {
+            return Err(ServiceError::Forbidden("Only the appointment's practitioner can confirm it".to_string()).into());
+        }
+        match self.db.update_appointment_status(id, AppointmentStatus::Requested, AppointmentStatus::Confirmed).await {
+            Ok(true) => {}
+            Ok(false) => return Err(anyhow!("Only requested appointments can be confirmed")),
+            Err(e) if matches!(e.downcast_ref::<DatabaseError>(), Some(DatabaseError::DuplicateKey(_))) => {
+                return Err(ServiceError::Conflict("The practitioner already has an appointment confirmed at that time".to_string()).into());
+            }
+            Err(e) => return Err(e),
+        }
+        appointment.status = AppointmentStatus::Confirmed;
+        self.audit_log_service.log(practitioner_did, "confirm_appointment", Some(json!({ "appointment_id": appointment_id }))).await;
+
+        if create_encounter {
+            let encounter = self.encounter_service.plan_encounter(encounter_request(&appointment)).await?;
+            if let Some(encounter_id) = encounter.id {
+                self.db.link_appointment_encounter(id, encounter_id).await?;
+                appointment.encounter_id = Some(encounter_id);
+            }
+        }
+        Ok(appointment)
+    }
+
+    /// Cancel one of the caller's own appointments, whether or not it was confirmed yet
+    pub async fn cancel_appointment(&self, patient_did: &str, appointment_id: &str) -> anyhow::Result<Appointment> {
+        let (id, mut appointment) = self.appointment(appointment_id).await?;
+        if appointment.patient_did != patient_did {
+            return Err(ServiceError::Forbidden("Only the patient who booked an appointment can cancel it".to_string()).into());
+        }
+        let mut cancelled = false;
+        for from in [AppointmentStatus::Requested, AppointmentStatus::Confirmed] {
+            if self.db.update_appointment_status(id, from, AppointmentStatus::Cancelled).await? {
+                cancelled = true;
+                break;
+            }
+        }
+        if !cancelled {
+            return Err(anyhow!("Appointment is already cancelled"));
+        }
+        appointment.status = AppointmentStatus::Cancelled;
+        self.audit_log_service.log(patient_did, "cancel_appointment", Some(json!({ "appointment_id": appointment_id }))).await;
+        Ok(appointment)
+    }
+
+    /// The caller's appointments on one side, starting within `[from, to)`.
+    /// With `organization_id` it is instead the organization's appointments, for its admins.
+    pub async fn list_appointments(
+        &self,
+        caller_did: &str,
+        caller_role: Role,
+        party: AppointmentParty,
+        organization_id: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Appointment>> {
+        match organization_id {
+            Some(organization_id) => {
+                let affiliations = self.organization_service.affiliations_in_scope(caller_did, caller_role, organization_id).await?;
+                self.db.list_affiliated_appointments(&affiliations, from, to).await
+            }
+            None => self.db.list_appointments(party, caller_did, from, to).await,
+        }
+    }
+
+    async fn appointment(&self, appointment_id: &str) -> anyhow::Result<(ObjectId, Appointment)> {
+        let id = ObjectId::parse_str(appointment_id).map_err(|_| anyhow!("Invalid appointment id"))?;
+        let appointment = self.db.get_appointment(id).await?.ok_or_else(|| anyhow!("Appointment not found"))?;
+        Ok((id, appointment))
+    }
+}
+
+fn encounter_request(appointment: &Appointment) -> CreateEncounterRequest {
+    CreateEncounterRequest {
+        patient_did: appointment.patient_did.clone(),
+        practitioner_did: appointment.practitioner_did.clone(),
+        class: FhirCoding { system: Some(ACT_CODE_SYSTEM.to_string()), code: Some(AMBULATORY.to_string()), display: Some("ambulatory".to_string()) },
+        reason_code: vec![appointment.reason.clone()],
+        period: FhirPeriod { start: Some(appointment.start.into()), end: Some(appointment.end.into()) },
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::events::EventBus;
+    use chrono::Duration;
+    use crate::config::Config;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::services::TerminologyService;
+    use crate::store::{
+        MockAppointmentStore, MockAuditStore, MockAvailabilityStore, MockEncounterStore, MockOrganizationStore, MockPatientStore, MockPractitionerStore,
+        MockRelationshipStore,
+    };
+
+    const APPOINTMENT_ID: &str = "65f1a2b3c4d5e6f708091a2b";
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+
+    fn service(appointments: MockAppointmentStore, encounters: MockEncounterStore) -> AppointmentService {
+        service_with(appointments, encounters, MockRelationshipStore::new())
+    }
+
+    fn service_with(appointments: MockAppointmentStore, encounters: MockEncounterStore, relationships: MockRelationshipStore) -> AppointmentService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        let organization_service = Arc::new(OrganizationService::new(
+            Arc::new(MockOrganizationStore::new()),
+            Arc::new(MockPractitionerStore::new()),
+            audit_log_service.clone(),
+        ));
+        let encounter_service = Arc::new(EncounterService::new(
+            Arc::new(encounters),
+            Arc::new(MockPatientStore::new()),
+            Arc::new(MockPractitionerStore::new()),
+            Arc::new(InMemoryObjectStorage::failing()),
+            organization_service.clone(),
+            Arc::new(Config::default()),
+            audit_log_service.clone(),
+            Arc::new(TerminologyService::new(&Config::default())),
+            Arc::new(EventBus::new(16)),
+        ));
+        let appointments = Arc::new(appointments);
+        let availability_service = Arc::new(AvailabilityService::new(
+            Arc::new(MockAvailabilityStore::new()),
+            appointments.clone(),
+            Arc::new(Config::default()),
+            audit_log_service.clone(),
+        ));
+        let relationship_service = Arc::new(RelationshipService::new(
+            Arc::new(relationships),
+            Arc::new(MockPatientStore::new()),
+            Arc::new(MockPractitionerStore::new()),
+            Arc::new(Config::default()),
+            audit_log_service.clone(),
+        ));
+        AppointmentService::new(appointments, availability_service, encounter_service, relationship_service, organization_service, audit_log_service)
+    }
+
+    fn appointment(status: AppointmentStatus) -> Appointment {
+        let start = Utc::now() + Duration::days(1);
+        Appointment {
+            id: Some(ObjectId::parse_str(APPOINTMENT_ID).unwrap()),
+            patient_did: PATIENT.to_string(),
+            practitioner_did: PRACTITIONER.to_string(),
+            status,
+            start,
+            end: start + Duration::minutes(30),
+            reason: FhirCodeableConcept { coding: vec![], text: Some("Follow-up".to_string()) },
+            location: "Room 4".to_string(),
+            encounter_id: None,
+            reminder_sent_at: ReminderMarkers::default(),
+            slots: vec![start],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    fn stored(status: AppointmentStatus) -> MockAppointmentStore {
+        let mut appointments = MockAppointmentStore::new();
+        appointments.expect_get_appointment().returning(move |_| Ok(Some(appointment(status))));
+        appointments
+    }
+
+    #[tokio::test]
+    async fn request_must_end_after_it_starts() {
+        let mut appointments = MockAppointmentStore::new();
+        appointments.expect_create_appointment().never();
+        let start = Utc::now() + Duration::days(1);
+        let request = CreateAppointmentRequest {
+            patient_did: None,
+            practitioner_did: PRACTITIONER.to_string(),
+            start,
+            end: start,
+            reason: FhirCodeableConcept { coding: vec![], text: None },
+            location: String::new(),
+        };
+
+        let err = service(appointments, MockEncounterStore::new()).request_appointment(PATIENT, request).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Appointment must end after it starts");
+    }
+
+    #[tokio::test]
+    async fn only_guardians_can_book_for_someone_else() {
+        let mut appointments = MockAppointmentStore::new();
+        appointments.expect_create_appointment().never();
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_get_relationship().returning(|_, _| Ok(None));
+        let start = Utc::now() + Duration::days(1);
+        let request = CreateAppointmentRequest {
+            patient_did: Some(PATIENT.to_string()),
+            practitioner_did: PRACTITIONER.to_string(),
+            start,
+            end: start + Duration::minutes(30),
+            reason: FhirCodeableConcept { coding: vec![], text: None },
+            location: String::new(),
+        };
+
+        let err = service_with(appointments, MockEncounterStore::new(), relationships)
+            .request_appointment("did:hedera:testnet:0.0.3", request)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn only_the_practitioner_can_confirm() {
+        let mut appointments = stored(AppointmentStatus::Requested);
+        appointments.expect_update_appointment_status().never();
+
+        let err = service(appointments, MockEncounterStore::new())
+            .confirm_appointment(PATIENT, APPOINTMENT_ID, false)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn confirming_a_booked_slot_is_a_conflict() {
+        let mut appointments = stored(AppointmentStatus::Requested);
+        appointments
+            .expect_update_appointment_status()
+            .returning(|_, _, _| Err(DatabaseError::DuplicateKey("E11000".to_string()).into()));
+        let mut encounters = MockEncounterStore::new();
+        encounters.expect_create_encounter().never();
+
+        let err = service(appointments, encounters)
+            .confirm_appointment(PRACTITIONER, APPOINTMENT_ID, true)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn confirming_can_plan_and_link_the_encounter() {
+        let encounter_id = ObjectId::new();
+        let mut appointments = stored(AppointmentStatus::Requested);
+        appointments
+            .expect_update_appointment_status()
+            .withf(|_, from, to| *from == AppointmentStatus::Requested && *to == AppointmentStatus::Confirmed)
+            .returning(|_, _, _| Ok(true));
+        appointments
+            .expect_link_appointment_encounter()
+            .withf(move |_, linked| *linked == encounter_id)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut encounters = MockEncounterStore::new();
+        encounters
+            .expect_create_encounter()
+            .withf(|e| matches!(e.status, EncounterStatus::Planned) && e.fhir_encounter.status == "planned" && e.patient_did == PATIENT)
+            .times(1)
+            .returning(move |_| Ok(encounter_id));
+
+        let confirmed = service(appointments, encounters)
+            .confirm_appointment(PRACTITIONER, APPOINTMENT_ID, true)
+            .await
+            .unwrap();
+
+        assert_eq!(confirmed.status, AppointmentStatus::Confirmed);
+        assert_eq!(confirmed.encounter_id, Some(encounter_id));
+    }
+
+    #[tokio::test]
+    async fn patient_can_cancel_a_confirmed_appointment() {
+        let mut appointments = stored(AppointmentStatus::Confirmed);
+        appointments
+            .expect_update_appointment_status()
+            .returning(|_, from, _| Ok(from == AppointmentStatus::Confirmed));
+
+        let cancelled = service(appointments, MockEncounterStore::new())
+            .cancel_appointment(PATIENT, APPOINTMENT_ID)
+            .await
+            .unwrap();
+
+        assert_eq!(cancelled.status, AppointmentStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn cancelling_twice_is_rejected() {
+        let mut appointments = stored(AppointmentStatus::Cancelled);
+        appointments.expect_update_appointment_status().returning(|_, _, _| Ok(false));
+
+        let err = service(appointments, MockEncounterStore::new())
+            .cancel_appointment(PATIENT, APPOINTMENT_ID)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "Appointment is already cancelled");
+    }
+}
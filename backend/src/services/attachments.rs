@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auditing::AuditLogService;
+use crate::config::{Config, UploadConfig};
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::scanner::{ScanVerdict, Scanner};
+use crate::services::ServiceError;
+use crate::store::{EncounterStore, PatientStore};
+use crate::utils;
+
+/// The kinds of file that can be attached, recognised by their content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentType {
+    Pdf,
+    Jpeg,
+    Png,
+    Dicom,
+}
+
+impl AttachmentType {
+    /// What `content` is by its magic bytes, if it is one of the accepted types.
+    /// The name and declared type of the upload play no part.
+    pub fn sniff(content: &[u8]) -> Option<Self> {
+        match infer::get(content)?.mime_type() {
+            "application/pdf" => Some(Self::Pdf),
+            "image/jpeg" => Some(Self::Jpeg),
+            "image/png" => Some(Self::Png),
+            "application/dicom" => Some(Self::Dicom),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Pdf => "application/pdf",
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Dicom => "application/dicom",
+        }
+    }
+
+    fn max_bytes(self, config: &UploadConfig) -> usize {
+        match self {
+            Self::Pdf => config.max_pdf_bytes,
+            Self::Jpeg | Self::Png => config.max_image_bytes,
+            Self::Dicom => config.max_dicom_bytes,
+        }
+    }
+}
+
+// --- AttachmentService ---
+/// Files attached to encounters. Uploads are checked by content type, size and a malware scan
+/// before being encrypted in frames and stored on IPFS, from where `GET /api/files/:cid` serves them.
+pub struct AttachmentService {
+    encounters: Arc<dyn EncounterStore>,
+    patients: Arc<dyn PatientStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    scanner: Arc<dyn Scanner>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl AttachmentService {
+    pub fn new(
+        encounters: Arc<dyn EncounterStore>,
+        patients: Arc<dyn PatientStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        scanner: Arc<dyn Scanner>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { encounters, patients, ipfs_client, scanner, config, audit_log_service }
+    }
+
+    /// Attach a file to an encounter that was not cancelled, as its practitioner or a practitioner
+    /// the patient granted `Write`
+    pub async fn upload(
+        &self,
+        caller_did: &str,
+        caller_role: Role,
+        encounter_id: &str,
+        filename: Option<String>,
+        content: &[u8],
+    ) -> Result<Attachment> {
+        let encounter_oid = bson::oid::ObjectId::parse_str(encounter_id).map_err(|_| anyhow!("Invalid encounter id"))?;
+        let encounter = self.encounters.get_encounter(encounter_oid).await?.ok_or_else(|| anyhow!("Encounter not found"))?;
+        let allowed = caller_role == Role::Practitioner
+            && (encounter.practitioner_did == caller_did
+                || self
+                    .patients
+                    .active_grants(&encounter.patient_did, caller_did)
+                    .await?
+                    .iter()
+                    .any(|grant| grant.permissions.contains(&Permission::Write)));
+        if !allowed {
+            return Err(ServiceError::Forbidden("You cannot attach files to this encounter".to_string()).into());
+        }
+        if encounter.status == EncounterStatus::Cancelled {
+            return Err(ServiceError::Conflict("Encounter was cancelled".to_string()).into());
+        }
+
+        let attachment_type = AttachmentType::sniff(content).ok_or(ServiceError::UnsupportedMediaType)?;
+        if content.len() > attachment_type.max_bytes(&self.config.uploads) {
+            return Err(ServiceError::PayloadTooLarge.into());
+        }
+        let filename = filename.map(|name| name.trim().to_string()).filter(|name| !name.is_empty());
+        self.scan(caller_did, &encounter.patient_did, encounter_id, filename.as_deref(), content).await?;
+
+        let key_version = self.config.ipfs_encryption_key_version;
+        let encrypted = utils::chunked::encrypt(content, &self.config.ipfs_encryption_key)?;
+        let ipfs_hash = self.ipfs_client.add_file(&encrypted, None).await?;
+        let mut attachment = Attachment {
+            id: None,
+            encounter_id: encounter_id.to_string(),
+            patient_did: encounter.patient_did.clone(),
+            uploaded_by: caller_did.to_string(),
+            filename,
+            content_type: attachment_type.mime_type().to_string(),
+            size: content.len() as u64,
+            ipfs_hash,
+            key_version,
+            created_at: Utc::now(),
+        };
+        attachment.id = Some(self.encounters.create_attachment(&attachment).await?);
+        self.audit_log_service
+            .log(
+                &encounter.patient_did,
+                "upload_attachment",
+                Some(json!({ "actor": caller_did, "encounter_id": encounter_id, "cid": attachment.ipfs_hash, "content_type": attachment.content_type })),
+            )
+            .await;
+        Ok(attachment)
+    }
+
+    /// Refuse infected uploads, and ones the scanner could not check in time unless uploads fail open
+    async fn scan(&self, caller_did: &str, patient_did: &str, encounter_id: &str, filename: Option<&str>, content: &[u8]) -> Result<()> {
+        let uploads = &self.config.uploads;
+        let outcome = tokio::time::timeout(Duration::from_secs(uploads.scan_timeout_seconds), self.scanner.scan(content))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("no verdict within {} seconds", uploads.scan_timeout_seconds)));
+        match outcome {
+            Ok(ScanVerdict::Clean) => Ok(()),
+            Ok(ScanVerdict::Infected(detection)) => {
+                tracing::warn!("Rejected an upload to encounter {} by {}: {}", encounter_id, caller_did, detection);
+                self.audit_log_service
+                    .log(
+                        patient_did,
+                        "attachment_rejected",
+                        Some(json!({ "actor": caller_did, "encounter_id": encounter_id, "filename": filename, "detection": detection })),
+                    )
+                    .await;
+                Err(ServiceError::MalwareDetected.into())
+            }
+            Err(e) if uploads.scan_fail_open => {
+                tracing::warn!("Malware scan unavailable, accepting the upload unscanned: {:#}", e);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Malware scan unavailable: {:#}", e);
+                Err(ServiceError::ScanUnavailable.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn dicom() -> Vec<u8> {
+        let mut content = vec![0u8; 128];
+        content.extend_from_slice(b"DICM\x02\x00\x00\x00");
+        content
+    }
+
+    #[test]
+    fn files_are_recognised_by_content_alone() {
+        assert_eq!(AttachmentType::sniff(b"%PDF-1.7\n%\xe2\xe3"), Some(AttachmentType::Pdf));
+        assert_eq!(AttachmentType::sniff(b"\xff\xd8\xff\xe0\0\x10JFIF\0"), Some(AttachmentType::Jpeg));
+        assert_eq!(AttachmentType::sniff(PNG), Some(AttachmentType::Png));
+        assert_eq!(AttachmentType::sniff(&dicom()), Some(AttachmentType::Dicom));
+    }
+
+    #[test]
+    fn other_content_is_refused_whatever_it_claims_to_be() {
+        // An executable, a web page and a zip, as they might arrive named scan.png or report.pdf
+        assert_eq!(AttachmentType::sniff(b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff"), None);
+        assert_eq!(AttachmentType::sniff(b"<!DOCTYPE html><html><script>"), None);
+        assert_eq!(AttachmentType::sniff(b"PK\x03\x04\x14\0\0\0\x08\0"), None);
+        assert_eq!(AttachmentType::sniff(b""), None);
+    }
+
+    #[test]
+    fn images_share_a_cap() {
+        let config = UploadConfig { max_pdf_bytes: 3, max_image_bytes: 2, max_dicom_bytes: 1, ..Default::default() };
+        assert_eq!(AttachmentType::Png.max_bytes(&config), AttachmentType::Jpeg.max_bytes(&config));
+        assert_eq!((AttachmentType::Pdf.max_bytes(&config), AttachmentType::Dicom.max_bytes(&config)), (3, 1));
+    }
+}
@@ -0,0 +1,140 @@
+//! Aggregated views of the audit log for the compliance dashboard.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration as StdDuration, Instant};
+
+use crate::models::*;
+use crate::store::AuditStore;
+
+/// Identical queries within this long are answered from memory
+const CACHE_TTL: StdDuration = StdDuration::from_secs(5 * 60);
+/// Range used when `from` is left out
+const DEFAULT_RANGE_DAYS: i64 = 30;
+/// Longest range one query may cover
+const MAX_RANGE_DAYS: i64 = 366;
+const DEFAULT_TOP: i64 = 10;
+const MAX_TOP: i64 = 100;
+
+/// Actions the dashboard highlights: failed or refused sign-ins, step-ups and tokens, blocked
+/// addresses, emergency access, record exports, attempts to finalize someone else's encounter,
+/// refused credential issuance and unusually many records read by one caller
+pub const SECURITY_ACTIONS: &[&str] = &[
+    "phone_auth_failed",
+    "phone_auth_locked",
+    "step_up_failed",
+    "step_up_locked",
+    "totp_confirmation_failed",
+    "totp_disable_failed",
+    "suspended_sign_in_refused",
+    "token_rejected",
+    "ip_blocked",
+    "break_glass",
+    "export_everything",
+    "finalize_encounter_denied",
+    "issue_credential_denied",
+    "data_access_alert",
+    "data_access_blocked",
+];
+
+/// The parameters of one query, exactly as given; they are also the cache key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuditStatsQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub group_by: AuditGroupBy,
+    pub top: Option<i64>,
+}
+
+// --- AuditAnalyticsService ---
+pub struct AuditAnalyticsService {
+    db: Arc<dyn AuditStore>,
+    cache: Mutex<HashMap<AuditStatsQuery, (Instant, AuditStats)>>,
+}
+
+impl AuditAnalyticsService {
+    pub fn new(db: Arc<dyn AuditStore>) -> Self {
+        Self { db, cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn stats(&self, query: AuditStatsQuery) -> Result<AuditStats> {
+        if let Some(stats) = self.cached(&query) {
+            return Ok(stats);
+        }
+
+        let to = query.to.unwrap_or_else(Utc::now);
+        let from = query.from.unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS));
+        if from >= to {
+            return Err(anyhow!("'from' must be before 'to'"));
+        }
+        if to - from > Duration::days(MAX_RANGE_DAYS) {
+            return Err(anyhow!("Audit statistics cover at most {} days at a time", MAX_RANGE_DAYS));
+        }
+        let top = query.top.unwrap_or(DEFAULT_TOP).clamp(1, MAX_TOP);
+
+        let stats = self.db.audit_stats(from, to, query.group_by, top, SECURITY_ACTIONS).await?;
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.retain(|_, (stored_at, _)| stored_at.elapsed() < CACHE_TTL);
+            cache.insert(query, (Instant::now(), stats.clone()));
+        }
+        Ok(stats)
+    }
+
+    fn cached(&self, query: &AuditStatsQuery) -> Option<AuditStats> {
+        let cache = self.cache.lock().ok()?;
+        cache
+            .get(query)
+            .filter(|(stored_at, _)| stored_at.elapsed() < CACHE_TTL)
+            .map(|(_, stats)| stats.clone())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::MockAuditStore;
+
+    fn stats(from: DateTime<Utc>, to: DateTime<Utc>, group_by: AuditGroupBy) -> AuditStats {
+        AuditStats { from, to, group_by, groups: vec![], top_actors: vec![], anchored: 0, unanchored: 0, security: vec![] }
+    }
+
+    fn query(group_by: AuditGroupBy) -> AuditStatsQuery {
+        AuditStatsQuery {
+            from: Some("2026-03-01T00:00:00Z".parse().unwrap()),
+            to: Some("2026-03-08T00:00:00Z".parse().unwrap()),
+            group_by,
+            top: Some(500),
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_queries_are_served_from_the_cache() {
+        let mut store = MockAuditStore::new();
+        store
+            .expect_audit_stats()
+            .withf(|_, _, _, top, actions| *top == MAX_TOP && actions.contains(&"break_glass"))
+            .times(2)
+            .returning(|from, to, group_by, _, _| Ok(stats(from, to, group_by)));
+        let service = AuditAnalyticsService::new(Arc::new(store));
+
+        service.stats(query(AuditGroupBy::Day)).await.unwrap();
+        service.stats(query(AuditGroupBy::Day)).await.unwrap();
+        let by_action = service.stats(query(AuditGroupBy::Action)).await.unwrap();
+
+        assert_eq!(by_action.group_by, AuditGroupBy::Action);
+    }
+
+    #[tokio::test]
+    async fn ranges_must_be_ordered_and_bounded() {
+        let mut store = MockAuditStore::new();
+        store.expect_audit_stats().never();
+        let service = AuditAnalyticsService::new(Arc::new(store));
+
+        let backwards = AuditStatsQuery { from: query(AuditGroupBy::Day).to, to: query(AuditGroupBy::Day).from, ..query(AuditGroupBy::Day) };
+        assert!(service.stats(backwards).await.is_err());
+        let too_long = AuditStatsQuery { from: Some("2024-01-01T00:00:00Z".parse().unwrap()), ..query(AuditGroupBy::Day) };
+        assert!(service.stats(too_long).await.is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Context, Result};
+use bson::oid::ObjectId;
 use chrono::{Duration, Utc};
 use jsonwebtoken::{encode, EncodingKey, Header};
-use rand::{Rng, RngCore};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
@@ -11,49 +12,66 @@ use hex;
 use crate::auditing::AuditLogService;
 use crate::api::middleware::jwt_auth::AuthClaims;
 use crate::config::Config;
-use crate::database::Database;
-use crate::services::did::DidManager;
+use crate::database::{Database, DatabaseError};
+use crate::i18n;
+use crate::services::did::DidRegistry;
 use crate::api::handlers::{RegisterRequest, GoogleAuthRequest, PhoneAuthInitiateRequest, PhoneAuthVerifyRequest};
-use crate::services::hedera::HederaClient;
 use crate::models::*;
 use crate::services::email::EmailService;
-use crate::services::twilio::TwilioService;
+use crate::services::ip_blocks::IpBlockService;
+use crate::services::phone_verification::{CodeCheck, PhoneLockout, PhoneVerifier};
+use crate::services::sessions::{ClientInfo, SessionService};
+use crate::services::sms_throttle::SmsThrottle;
+use crate::utils::hash_phone;
+use crate::services::ServiceError;
+use crate::tenancy::DEFAULT_TENANT;
 
 #[cfg(not(feature = "test"))]
 use google_jwt_signin::Client;
 #[cfg(feature = "test")]
 use mockall::automock;
 
+// Message keys, sent in the language of the request
+const ACCOUNT_EXISTS_MESSAGE: &str = "error.account_exists";
+const PHONE_LOCKED_MESSAGE: &str = "error.too_many_codes";
+const ACCOUNT_SUSPENDED_MESSAGE: &str = "error.account_suspended";
+
 // --- AuthService ---
 #[cfg_attr(feature = "test", automock)]
 pub trait AuthService: Send + Sync {
     fn new(
         db: Arc<Database>,
-        hedera_client: Arc<HederaClient>,
+        did_registry: Arc<dyn DidRegistry>,
         config: Arc<Config>,
         audit_log_service: Arc<AuditLogService>,
-        twilio_service: Arc<TwilioService>,
+        phone_verifier: Arc<dyn PhoneVerifier>,
         email_service: Arc<EmailService>,
+        session_service: Arc<SessionService>,
+        ip_blocks: Arc<IpBlockService>,
     ) -> Self
     where
         Self: Sized;
     async fn initiate_auth(&self, email: &str) -> anyhow::Result<InitiateAuthResponse>;
-    async fn register_new_user(&self, request: RegisterRequest) -> anyhow::Result<RegistrationResponse>;
-    async fn authenticate_with_google(&self, request: GoogleAuthRequest) -> Result<RegistrationResponse>;
+    async fn register_new_user(&self, request: RegisterRequest, client: ClientInfo) -> anyhow::Result<RegistrationResponse>;
+    async fn authenticate_with_google(&self, request: GoogleAuthRequest, client: ClientInfo) -> Result<RegistrationResponse>;
     async fn verify_google_token(&self, id_token: &str) -> Result<String>;
-    async fn get_patient_by_did(&self, did: &str) -> Result<Patient>;
+    async fn get_patient_by_did(&self, did: &str) -> Result<Option<(Patient, AccountStatus)>>;
     async fn initiate_phone_auth(&self, request: PhoneAuthInitiateRequest) -> anyhow::Result<()>;
-    async fn verify_phone_auth(&self, request: PhoneAuthVerifyRequest) -> anyhow::Result<RegistrationResponse>;
+    async fn verify_phone_auth(&self, request: PhoneAuthVerifyRequest, client: ClientInfo) -> anyhow::Result<RegistrationResponse>;
     async fn verify_email(&self, token: &str) -> anyhow::Result<EmailVerificationResponse>;
 }
 
 pub struct AuthServiceImpl {
     db: Arc<Database>,
-    hedera_client: Arc<HederaClient>,
+    did_registry: Arc<dyn DidRegistry>,
     config: Arc<Config>,
     audit_log_service: Arc<AuditLogService>,
-    twilio_service: Arc<TwilioService>,
+    phone_verifier: Arc<dyn PhoneVerifier>,
+    phone_lockout: PhoneLockout,
+    sms_throttle: SmsThrottle,
     email_service: Arc<EmailService>,
+    session_service: Arc<SessionService>,
+    ip_blocks: Arc<IpBlockService>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -86,19 +104,25 @@ struct GoogleUserInfo {
 impl AuthService for AuthServiceImpl {
     fn new(
         db: Arc<Database>,
-        hedera_client: Arc<HederaClient>,
+        did_registry: Arc<dyn DidRegistry>,
         config: Arc<Config>,
         audit_log_service: Arc<AuditLogService>,
-        twilio_service: Arc<TwilioService>,
+        phone_verifier: Arc<dyn PhoneVerifier>,
         email_service: Arc<EmailService>,
+        session_service: Arc<SessionService>,
+        ip_blocks: Arc<IpBlockService>,
     ) -> Self {
         Self {
+            sms_throttle: SmsThrottle::new(db.clone(), db.clone(), email_service.clone(), config.clone()),
             db,
-            hedera_client,
+            did_registry,
             config,
             audit_log_service,
-            twilio_service,
+            phone_verifier,
+            phone_lockout: PhoneLockout::default(),
             email_service,
+            session_service,
+            ip_blocks,
         }
     }
 
@@ -109,8 +133,15 @@ impl AuthService for AuthServiceImpl {
         })
     }
 
-    async fn register_new_user(&self, request: RegisterRequest) -> anyhow::Result<RegistrationResponse> {
-        let did = DidManager::create_did(&self.hedera_client, &request.public_key_hex, &self.config.hedera_network).await?;
+    async fn register_new_user(&self, request: RegisterRequest, client: ClientInfo) -> anyhow::Result<RegistrationResponse> {
+        // Cheap pre-check so the common duplicate case never creates a Hedera DID.
+        // Concurrent registrations can still race past it; the unique email_hash
+        // index is the real guard and is handled below.
+        if self.db.get_patient_by_email(&request.email, &self.config.ipfs_encryption_key).await?.is_some() {
+            return Err(ServiceError::Conflict(i18n::text(i18n::current(), ACCOUNT_EXISTS_MESSAGE, &[])).into());
+        }
+
+        let did = self.did_registry.create_did(&request.public_key_hex).await?;
         let fhir_patient = FhirPatient {
             resource_type: "Patient".to_string(),
             id: Uuid::new_v4().to_string(),
@@ -143,16 +174,27 @@ impl AuthService for AuthServiceImpl {
             verification_token_expires: Some(verification_token_expires),
         };
 
-        self.db.create_patient(&patient, &self.config.ipfs_encryption_key).await?;
+        if let Err(e) = self.db.create_patient(&patient, &self.config.ipfs_encryption_key).await {
+            if let Some(DatabaseError::DuplicateKey(_)) = e.downcast_ref::<DatabaseError>() {
+                tracing::warn!(did = %did, "Registration lost a race on email uniqueness; removing orphaned DID");
+                self.discard_did(&did).await;
+                return Err(ServiceError::Conflict(i18n::text(i18n::current(), ACCOUNT_EXISTS_MESSAGE, &[])).into());
+            }
+            return Err(e);
+        }
         self.audit_log_service.log(&did, "register_new_user", None).await;
+        let locale = request.locale.unwrap_or_else(i18n::current);
+        self.save_locale(&did, locale).await?;
 
-        // --- Send verification and welcome emails (fire and forget) ---
+        // --- Queue verification and welcome emails; the outbox worker delivers them ---
         self.email_service
-            .send_verification_email(&request.email, &request.name, &verification_token);
+            .send_verification_email(&request.email, &request.name, &verification_token, locale)
+            .await;
         self.email_service
-            .send_welcome_email(&request.email, &request.name);
+            .send_welcome_email(&request.email, &request.name, locale)
+            .await;
 
-        let token = self.generate_jwt_for_patient(&patient)?;
+        let token = self.generate_jwt_for_patient(&patient, &client).await?;
 
         Ok(RegistrationResponse { user: patient, token })
     }
@@ -163,12 +205,16 @@ impl AuthService for AuthServiceImpl {
     async fn authenticate_with_google(
         &self,
         request: GoogleAuthRequest,
+        client: ClientInfo,
     ) -> Result<RegistrationResponse> {
         // Step 1: Verify Google token and extract user info
-        let user_info = self
-            .verify_google_token_internal(&request.id_token)
-            .await
-            .context("Failed to verify Google token")?;
+        let user_info = match self.verify_google_token_internal(&request.id_token).await {
+            Ok(user_info) => user_info,
+            Err(e) => {
+                self.ip_blocks.record_failure(client.ip, "google").await;
+                return Err(e).context("Failed to verify Google token");
+            }
+        };
 
         // Step 2: Find existing patient or create new one
         let patient = self
@@ -178,7 +224,8 @@ impl AuthService for AuthServiceImpl {
 
         // Step 3: Generate JWT token with patient's DID
         let token = self
-            .generate_jwt_for_patient(&patient)
+            .generate_jwt_for_patient(&patient, &client)
+            .await
             .context("Failed to generate JWT")?;
 
         Ok(RegistrationResponse { user: patient, token })
@@ -192,100 +239,90 @@ impl AuthService for AuthServiceImpl {
         Ok(user_info.email)
     }
 
-    /// Get patient by their DID (used by middleware to load user from JWT)
-    async fn get_patient_by_did(&self, did: &str) -> Result<Patient> {
-        self.db
-            .get_patient_by_did(did, &self.config.ipfs_encryption_key)
-            .await?
-            .ok_or_else(|| anyhow!("Patient not found for DID: {}", did))
+    /// The patient a token was issued to and whether their account is still usable, for the
+    /// auth middleware; soft-deleted and suspended accounts are returned with their status
+    async fn get_patient_by_did(&self, did: &str) -> Result<Option<(Patient, AccountStatus)>> {
+        self.db.get_patient_account(did, &self.config.ipfs_encryption_key).await
     }
 
     async fn initiate_phone_auth(&self, request: PhoneAuthInitiateRequest) -> anyhow::Result<()> {
-        let otp = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
-        let otp_record = Otp {
-            id: None,
-            phone_number: request.phone_number.clone(),
-            otp: otp.clone(),
-            created_at: Utc::now(),
-            expires_at: Utc::now() + Duration::minutes(5),
-        };
-        self.db.create_otp(&otp_record).await?;
-        self.twilio_service.send_otp(&request.phone_number, &otp)?;
+        let subject = phone_subject(&request.phone_number);
+        if self.phone_lockout.is_locked(&subject) {
+            return Err(ServiceError::RateLimited(i18n::text(i18n::current(), PHONE_LOCKED_MESSAGE, &[])).into());
+        }
+        if let Err(e) = self.sms_throttle.claim(&request.phone_number, Utc::now()).await {
+            if matches!(e.downcast_ref::<ServiceError>(), Some(ServiceError::SmsLimited { .. })) {
+                self.audit_log_service.log(&subject, "phone_auth_throttled", None).await;
+            }
+            return Err(e);
+        }
+        self.phone_verifier.start(&request.phone_number).await?;
+        self.audit_log_service.log(&subject, "phone_auth_initiated", None).await;
         Ok(())
     }
 
-    async fn verify_phone_auth(&self, request: PhoneAuthVerifyRequest) -> anyhow::Result<RegistrationResponse> {
-        let otp_record = self.db.get_otp(&request.phone_number, &request.otp).await?;
+    async fn verify_phone_auth(&self, request: PhoneAuthVerifyRequest, client: ClientInfo) -> anyhow::Result<RegistrationResponse> {
+        let subject = phone_subject(&request.phone_number);
+        if self.phone_lockout.is_locked(&subject) {
+            return Err(ServiceError::RateLimited(i18n::text(i18n::current(), PHONE_LOCKED_MESSAGE, &[])).into());
+        }
 
-        if let Some(otp_record) = otp_record {
-            if otp_record.expires_at < Utc::now() {
+        match self.phone_verifier.check(&request.phone_number, &request.otp).await? {
+            CodeCheck::Approved => self.phone_lockout.clear(&subject),
+            // Guessing at numbers that were never sent a code is still a failed sign-in
+            CodeCheck::Expired => {
+                self.ip_blocks.record_failure(client.ip, &subject).await;
                 return Err(anyhow!("OTP has expired"));
             }
-
-            // For simplicity, we'll use the phone number to find the user.
-            // In a real application, you might want to have a separate way to link phone numbers to users.
-            let patient = self.db.get_patient_by_phone(&request.phone_number, &self.config.ipfs_encryption_key).await?;
-
-            if let Some(patient) = patient {
-                let expiration = Utc::now()
-                    .checked_add_signed(Duration::seconds(self.config.jwt_expiration_seconds))
-                    .expect("valid timestamp")
-                    .timestamp();
-                let claims = AuthClaims {
-                    sub: patient.did.clone(),
-                    exp: expiration as usize,
-                };
-                let token = encode(
-                    &Header::default(),
-                    &claims,
-                    &EncodingKey::from_secret(self.config.jwt_secret.as_ref()),
-                )?;
-                Ok(RegistrationResponse { user: patient, token })
-            } else {
-                // Create a new user
-                let mut public_key_bytes = [0u8; 32];
-                rand::thread_rng().fill_bytes(&mut public_key_bytes);
-                let public_key_hex = hex::encode(public_key_bytes);
-                let did = DidManager::create_did(&self.hedera_client, &public_key_hex, &self.config.hedera_network).await?;
-                let fhir_patient = FhirPatient {
-                    resource_type: "Patient".to_string(),
-                    id: Uuid::new_v4().to_string(),
-                    telecom: vec![FhirContactPoint {
-                        system: "phone".to_string(),
-                        value: request.phone_number.clone(),
-                        r#use: Some("home".to_string()),
-                    }],
-                    ..Default::default()
-                };
-                let patient = Patient {
-                    id: None,
-                    did: did.clone(),
-                    fhir_patient,
-                    created_at: Utc::now(),
-                    updated_at: Utc::now(),
-                    email_verified: true,
-                    verification_token: None,
-                    verification_token_expires: None,
-                };
-                self.db.create_patient(&patient, &self.config.ipfs_encryption_key).await?;
-                self.audit_log_service.log(&did, "register_new_user_phone", None).await;
-                let expiration = Utc::now()
-                    .checked_add_signed(Duration::seconds(self.config.jwt_expiration_seconds))
-                    .expect("valid timestamp")
-                    .timestamp();
-                let claims = AuthClaims {
-                    sub: did.clone(),
-                    exp: expiration as usize,
-                };
-                let token = encode(
-                    &Header::default(),
-                    &claims,
-                    &EncodingKey::from_secret(self.config.jwt_secret.as_ref()),
-                )?;
-                Ok(RegistrationResponse { user: patient, token })
+            CodeCheck::Rejected => {
+                self.ip_blocks.record_failure(client.ip, &subject).await;
+                self.audit_log_service.log(&subject, "phone_auth_failed", None).await;
+                if self.phone_lockout.record_failure(&subject) {
+                    tracing::warn!("Phone sign-in locked after repeated wrong codes");
+                    self.audit_log_service.log(&subject, "phone_auth_locked", None).await;
+                }
+                return Err(anyhow!("Invalid OTP"));
             }
+        }
+
+        // For simplicity, we'll use the phone number to find the user.
+        // In a real application, you might want to have a separate way to link phone numbers to users.
+        let patient = self.db.get_patient_by_phone(&request.phone_number, &self.config.ipfs_encryption_key).await?;
+
+        if let Some(patient) = patient {
+            let token = self.generate_jwt_for_patient(&patient, &client).await?;
+            Ok(RegistrationResponse { user: patient, token })
         } else {
-            Err(anyhow!("Invalid OTP"))
+            // Create a new user
+            let mut public_key_bytes = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut public_key_bytes);
+            let public_key_hex = hex::encode(public_key_bytes);
+            let did = self.did_registry.create_did(&public_key_hex).await?;
+            let fhir_patient = FhirPatient {
+                resource_type: "Patient".to_string(),
+                id: Uuid::new_v4().to_string(),
+                telecom: vec![FhirContactPoint {
+                    system: "phone".to_string(),
+                    value: request.phone_number.clone(),
+                    r#use: Some("home".to_string()),
+                }],
+                ..Default::default()
+            };
+            let patient = Patient {
+                id: None,
+                did: did.clone(),
+                fhir_patient,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                email_verified: true,
+                verification_token: None,
+                verification_token_expires: None,
+            };
+            self.db.create_patient(&patient, &self.config.ipfs_encryption_key).await?;
+            self.audit_log_service.log(&did, "register_new_user_phone", None).await;
+            self.save_locale(&did, i18n::current()).await?;
+            let token = self.generate_jwt_for_patient(&patient, &client).await?;
+            Ok(RegistrationResponse { user: patient, token })
         }
     }
 
@@ -330,14 +367,7 @@ impl AuthService for AuthServiceImpl {
                     .log(&patient.did, "email_verified", None)
                     .await;
 
-                tracing::info!(
-                    did = %patient.did,
-                    email = %patient.fhir_patient.telecom.iter()
-                        .find(|c| c.system == "email")
-                        .map(|c| c.value.as_str())
-                        .unwrap_or("unknown"),
-                    "Email verification successful"
-                );
+                tracing::info!(did = %patient.did, "Email verification successful");
 
                 Ok(EmailVerificationResponse {
                     success: true,
@@ -398,15 +428,11 @@ impl AuthServiceImpl {
             .await?
         {
             Some(patient) => {
-                tracing::info!(
-                    email = %user_info.email,
-                    did = %patient.did,
-                    "Existing user authenticated with Google"
-                );
+                tracing::info!(did = %patient.did, "Existing user authenticated with Google");
                 Ok(patient)
             }
             None => {
-                tracing::info!(email = %user_info.email, "Creating new user via Google auth");
+                tracing::info!("Creating new user via Google auth");
                 self.create_new_patient(user_info).await
             }
         }
@@ -418,13 +444,11 @@ impl AuthServiceImpl {
         let public_key_hex = generate_random_public_key();
 
         // Create DID on Hedera network
-        let did = DidManager::create_did(
-            &self.hedera_client,
-            &public_key_hex,
-            &self.config.hedera_network,
-        )
-        .await
-        .context("Failed to create Hedera DID")?;
+        let did = self
+            .did_registry
+            .create_did(&public_key_hex)
+            .await
+            .context("Failed to create Hedera DID")?;
 
         tracing::debug!(did = %did, "Created Hedera DID for new user");
 
@@ -443,36 +467,120 @@ impl AuthServiceImpl {
             verification_token_expires: None,
         };
 
-        // Persist to database
-        self.db
-            .create_patient(&patient, &self.config.ipfs_encryption_key)
-            .await
-            .context("Failed to save patient to database")?;
+        // Persist to database. If a concurrent sign-in for the same email won the
+        // race, drop our DID and log the user into the account that was created.
+        if let Err(e) = self.db.create_patient(&patient, &self.config.ipfs_encryption_key).await {
+            if let Some(DatabaseError::DuplicateKey(_)) = e.downcast_ref::<DatabaseError>() {
+                self.discard_did(&did).await;
+                return self
+                    .db
+                    .get_patient_by_email(&user_info.email, &self.config.ipfs_encryption_key)
+                    .await?
+                    .ok_or_else(|| anyhow!("Patient disappeared after duplicate key conflict"));
+            }
+            return Err(e).context("Failed to save patient to database");
+        }
 
         // Audit log
         self.audit_log_service
             .log(&did, "google_auth_new_user", None)
             .await;
+        self.save_locale(&did, i18n::current()).await?;
 
         Ok(patient)
     }
 
-    /// Generate JWT token with patient's DID as subject
-    fn generate_jwt_for_patient(&self, patient: &Patient) -> Result<String> {
+    /// Keep the language a patient signed up in, so later emails and SMS use it.
+    /// English is the default and needs no saved preferences.
+    async fn save_locale(&self, did: &str, locale: Locale) -> Result<()> {
+        if locale != Locale::En {
+            let preferences = NotificationPreferences { locale, ..Default::default() };
+            self.db.set_notification_preferences(did, &preferences).await?;
+        }
+        Ok(())
+    }
+
+    /// Best-effort removal of a DID created for a registration that did not persist
+    async fn discard_did(&self, did: &str) {
+        if let Err(e) = self.did_registry.delete_did(did).await {
+            tracing::error!(did = %did, "Failed to delete orphaned DID document: {}", e);
+        }
+    }
+
+    /// Resolve the role to embed in a token. Admins are bootstrapped from config, or added
+    /// with the `create-admin` command.
+    async fn role_for(&self, did: &str) -> Result<Role> {
+        if self.config.platform_admin_dids.iter().any(|admin| admin == did) {
+            Ok(Role::PlatformAdmin)
+        } else if self.config.admin_dids.iter().any(|admin| admin == did) {
+            Ok(Role::Admin)
+        } else {
+            Ok(self.db.admin_role(did).await?.unwrap_or(Role::Patient))
+        }
+    }
+
+    /// The tenant to embed in a token: a practitioner's own, or for a tenant admin that of the
+    /// organization it administers. Platform admins and patients have none.
+    async fn tenant_for(&self, did: &str, role: Role, org_id: Option<&str>) -> Result<Option<String>> {
+        if role == Role::PlatformAdmin {
+            return Ok(None);
+        }
+        // Sign-in runs outside any tenant, so this finds practitioners of every tenant
+        if let Some(practitioner) = self.db.get_practitioner_by_did(did).await? {
+            return Ok(Some(practitioner.tenant_id.unwrap_or_else(|| DEFAULT_TENANT.to_string())));
+        }
+        if role != Role::Admin {
+            return Ok(None);
+        }
+        let organization = match org_id.map(ObjectId::parse_str) {
+            Some(Ok(id)) => self.db.get_organization(id).await?,
+            _ => None,
+        };
+        Ok(Some(organization.and_then(|organization| organization.tenant_id).unwrap_or_else(|| DEFAULT_TENANT.to_string())))
+    }
+
+    /// The organization the DID currently administers, if any
+    async fn organization_for(&self, did: &str) -> Result<Option<String>> {
+        let now = Utc::now();
+        let affiliations = self.db.admin_affiliations(did).await?;
+        Ok(affiliations
+            .into_iter()
+            .find(|affiliation| affiliation.is_current(now))
+            .map(|affiliation| affiliation.organization_id.to_hex()))
+    }
+
+    /// Generate JWT token with patient's DID as subject. Every sign-in method ends here, so this
+    /// is where the sign-in is recorded as a session and checked for a new device.
+    async fn generate_jwt_for_patient(&self, patient: &Patient, client: &ClientInfo) -> Result<String> {
+        if self.db.is_patient_disabled(&patient.did).await? {
+            self.audit_log_service.log(&patient.did, "suspended_sign_in_refused", None).await;
+            return Err(ServiceError::Forbidden(i18n::text(i18n::current(), ACCOUNT_SUSPENDED_MESSAGE, &[])).into());
+        }
+
         let expiration = Utc::now()
             .checked_add_signed(Duration::seconds(self.config.jwt_expiration_seconds))
             .ok_or_else(|| anyhow!("Invalid expiration time"))?
             .timestamp();
 
+        let role = self.role_for(&patient.did).await?;
+        let org_id = self.organization_for(&patient.did).await?;
+        let tenant_id = self.tenant_for(&patient.did, role, org_id.as_deref()).await?;
+        let locale = self.db.get_notification_preferences(&patient.did).await?.map(|preferences| preferences.locale);
         let claims = AuthClaims {
             sub: patient.did.clone(), // DID goes in the JWT subject
             exp: expiration as usize,
+            role,
+            org_id,
+            second_factor: None,
+            sid: Some(self.session_service.start(&patient.did, client).await?),
+            tenant_id,
+            locale,
         };
 
         encode(
             &Header::default(),
             &claims,
-            &EncodingKey::from_secret(self.config.jwt_secret.as_ref()),
+            &EncodingKey::from_secret(self.config.jwt_secret.expose_secret().as_bytes()),
         )
         .map_err(Into::into)
     }
@@ -480,6 +588,11 @@ impl AuthServiceImpl {
 
 // --- Utility Functions ---
 
+/// Audit subject for phone sign-in events, which happen before there is a DID
+fn phone_subject(phone_number: &str) -> String {
+    format!("phone:{}", hash_phone(phone_number))
+}
+
 /// Generate a random 32-byte public key for DID creation
 fn generate_random_public_key() -> String {
     let mut bytes = [0u8; 32];
@@ -0,0 +1,220 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::schedule;
+use crate::services::ServiceError;
+use crate::store::{AppointmentStore, AvailabilityStore};
+
+/// Widest range `slots` expands in one call
+const MAX_SLOT_RANGE_DAYS: i64 = 31;
+/// How far before a range to look for confirmed appointments still running into it
+const LONGEST_APPOINTMENT: Duration = Duration::days(1);
+
+// --- AvailabilityService ---
+pub struct AvailabilityService {
+    db: Arc<dyn AvailabilityStore>,
+    appointments: Arc<dyn AppointmentStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl AvailabilityService {
+    pub fn new(
+        db: Arc<dyn AvailabilityStore>,
+        appointments: Arc<dyn AppointmentStore>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, appointments, config, audit_log_service }
+    }
+
+    pub async fn get_availability(&self, practitioner_did: &str) -> anyhow::Result<Option<Availability>> {
+        self.db.get_availability(practitioner_did).await
+    }
+
+    /// Replace the caller's own weekly hours and exceptions
+    pub async fn set_availability(&self, caller_did: &str, practitioner_did: &str, request: SetAvailabilityRequest) -> anyhow::Result<Availability> {
+        if caller_did != practitioner_did {
+            return Err(ServiceError::Forbidden("Practitioners can only manage their own availability".to_string()).into());
+        }
+        validate(&request)?;
+        let availability = Availability {
+            id: None,
+            practitioner_did: practitioner_did.to_string(),
+            timezone: request.timezone.trim().to_string(),
+            weekly: request.weekly,
+            exceptions: request.exceptions,
+            updated_at: Utc::now(),
+        };
+        self.db.save_availability(&availability).await?;
+        self.audit_log_service
+            .log(
+                practitioner_did,
+                "update_availability",
+                Some(json!({ "weekly_windows": availability.weekly.len(), "exceptions": availability.exceptions.len() })),
+            )
+            .await;
+        Ok(availability)
+    }
+
+    /// Bookable slots starting in `[from, to)`: the practitioner's hours minus their confirmed appointments
+    pub async fn slots(&self, practitioner_did: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Vec<Slot>> {
+        if to <= from {
+            return Err(anyhow!("`to` must be after `from`"));
+        }
+        if to - from > Duration::days(MAX_SLOT_RANGE_DAYS) {
+            return Err(anyhow!("Slots can be listed for at most {} days at a time", MAX_SLOT_RANGE_DAYS));
+        }
+        let Some(availability) = self.db.get_availability(practitioner_did).await? else {
+            return Ok(Vec::new());
+        };
+        let granularity = Duration::minutes(i64::from(self.config.appointment_slot_minutes));
+        let slots = schedule::expand(&availability, from, to, granularity);
+        let booked = self
+            .appointments
+            .list_appointments(AppointmentParty::Practitioner, practitioner_did, Some(from - LONGEST_APPOINTMENT), Some(to))
+            .await?;
+        Ok(schedule::subtract_booked(slots, &booked))
+    }
+
+    /// The free slots `[start, end)` would occupy, or an error if it is not bookable
+    pub async fn slots_for(&self, practitioner_did: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> anyhow::Result<Vec<DateTime<Utc>>> {
+        let free = self.slots(practitioner_did, start, end).await?;
+        schedule::covering(&free, start, end)
+            .ok_or_else(|| ServiceError::Conflict("The practitioner is not available at that time".to_string()).into())
+    }
+}
+
+fn validate(request: &SetAvailabilityRequest) -> anyhow::Result<()> {
+    if request.timezone.trim().parse::<chrono_tz::Tz>().is_err() {
+        return Err(anyhow!("Unknown time zone: {}", request.timezone.trim()));
+    }
+    let windows = request
+        .weekly
+        .iter()
+        .map(|window| (window.start, window.end))
+        .chain(request.exceptions.iter().flat_map(|exception| exception.windows.iter().map(|window| (window.start, window.end))));
+    for (start, end) in windows {
+        if end <= start {
+            return Err(anyhow!("Availability window {}-{} must end after it starts", start.format("%H:%M"), end.format("%H:%M")));
+        }
+    }
+    let mut dates = HashSet::new();
+    if let Some(duplicate) = request.exceptions.iter().find(|exception| !dates.insert(exception.date)) {
+        return Err(anyhow!("More than one exception for {}", duplicate.date));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAppointmentStore, MockAuditStore, MockAvailabilityStore};
+    use chrono::{NaiveDate, NaiveTime, TimeZone, Weekday};
+
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+
+    fn service(availability: MockAvailabilityStore, appointments: MockAppointmentStore) -> AvailabilityService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let config = Config { appointment_slot_minutes: 30, ..Default::default() };
+        AvailabilityService::new(
+            Arc::new(availability),
+            Arc::new(appointments),
+            Arc::new(config),
+            Arc::new(AuditLogService::new(Arc::new(audit_store))),
+        )
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn request(weekly: Vec<WeeklyWindow>, exceptions: Vec<AvailabilityException>) -> SetAvailabilityRequest {
+        SetAvailabilityRequest { timezone: "Africa/Nairobi".to_string(), weekly, exceptions }
+    }
+
+    #[tokio::test]
+    async fn only_the_practitioner_can_change_their_availability() {
+        let mut availability = MockAvailabilityStore::new();
+        availability.expect_save_availability().never();
+
+        let err = service(availability, MockAppointmentStore::new())
+            .set_availability("did:hedera:testnet:0.0.1", PRACTITIONER, request(vec![], vec![]))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn malformed_schedules_are_rejected() {
+        let backwards = request(vec![WeeklyWindow { weekday: Weekday::Mon, start: time(10, 0), end: time(9, 0) }], vec![]);
+        let day_off = |day| AvailabilityException { date: NaiveDate::from_ymd_opt(2026, 12, day).unwrap(), windows: vec![] };
+        let duplicated = request(vec![], vec![day_off(25), day_off(25)]);
+        let unknown_zone = SetAvailabilityRequest { timezone: "Mars/Olympus".to_string(), ..request(vec![], vec![]) };
+
+        for (request, message) in [
+            (backwards, "Availability window 10:00-09:00 must end after it starts"),
+            (duplicated, "More than one exception for 2026-12-25"),
+            (unknown_zone, "Unknown time zone: Mars/Olympus"),
+        ] {
+            let mut availability = MockAvailabilityStore::new();
+            availability.expect_save_availability().never();
+
+            let err = service(availability, MockAppointmentStore::new())
+                .set_availability(PRACTITIONER, PRACTITIONER, request)
+                .await
+                .unwrap_err();
+
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[tokio::test]
+    async fn requested_time_must_be_free_slots() {
+        // Mondays 09:00-10:00 Nairobi time; 06:00-06:30 UTC on 19 Oct is already confirmed
+        let monday = |hour, minute| Utc.with_ymd_and_hms(2026, 10, 19, hour, minute, 0).unwrap();
+        let mut availability = MockAvailabilityStore::new();
+        availability.expect_get_availability().returning(|did| {
+            Ok(Some(Availability {
+                id: None,
+                practitioner_did: did.to_string(),
+                timezone: "Africa/Nairobi".to_string(),
+                weekly: vec![WeeklyWindow { weekday: Weekday::Mon, start: time(9, 0), end: time(10, 0) }],
+                exceptions: vec![],
+                updated_at: Utc::now(),
+            }))
+        });
+        let mut appointments = MockAppointmentStore::new();
+        appointments.expect_list_appointments().returning(move |_, _, _, _| {
+            Ok(vec![Appointment {
+                id: None,
+                patient_did: "did:hedera:testnet:0.0.1".to_string(),
+                practitioner_did: PRACTITIONER.to_string(),
+                status: AppointmentStatus::Confirmed,
+                start: monday(6, 0),
+                end: monday(6, 30),
+                reason: FhirCodeableConcept { coding: vec![], text: None },
+                location: String::new(),
+                encounter_id: None,
+                reminder_sent_at: ReminderMarkers::default(),
+                slots: vec![monday(6, 0)],
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                tenant_id: None,
+            }])
+        });
+        let service = service(availability, appointments);
+
+        assert_eq!(service.slots_for(PRACTITIONER, monday(6, 30), monday(7, 0)).await.unwrap(), vec![monday(6, 30)]);
+        let err = service.slots_for(PRACTITIONER, monday(6, 0), monday(7, 0)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+}
@@ -0,0 +1,210 @@
+//! Single-use backup codes for getting into an account without its phone or authenticator app.
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::models::*;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::store::BackupCodeStore;
+
+const CODE_COUNT: usize = 10;
+/// Twelve characters of a 32-letter alphabet: 60 bits per code
+const CODE_LENGTH: usize = 12;
+/// No 0/O or 1/I, which are easy to misread on paper
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const EXHAUSTED_WARNING: &str = "All backup codes have been used; generate a new set";
+
+// --- BackupCodeService ---
+pub struct BackupCodeService {
+    db: Arc<dyn BackupCodeStore>,
+    notification_service: Arc<NotificationService>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl BackupCodeService {
+    pub fn new(db: Arc<dyn BackupCodeStore>, notification_service: Arc<NotificationService>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, notification_service, audit_log_service }
+    }
+
+    /// Issue a fresh set of codes, invalidating every earlier one
+    pub async fn generate(&self, did: &str) -> Result<BackupCodes> {
+        let (codes, stored): (Vec<String>, Vec<BackupCode>) = {
+            let mut rng = rand::thread_rng();
+            (0..CODE_COUNT)
+                .map(|_| {
+                    let code: String = (0..CODE_LENGTH).map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char).collect();
+                    let salt = hex::encode(rng.gen::<[u8; 16]>());
+                    let hash = code_hash(&salt, &code);
+                    (code, BackupCode { salt, hash, consumed_at: None })
+                })
+                .unzip()
+        };
+        if !self.db.replace_backup_codes(did, &stored).await? {
+            return Err(anyhow!("No account found for {}", did));
+        }
+        self.audit_log_service.log(did, "backup_codes_generated", Some(json!({ "count": CODE_COUNT }))).await;
+        Ok(BackupCodes { codes: codes.iter().map(|code| format_code(code)).collect() })
+    }
+
+    /// Use up `code` if it is one of the caller's unused codes, returning how many are left.
+    /// The patient is told on their other channels whenever a code is used.
+    pub async fn consume(&self, did: &str, code: &str) -> Result<Option<usize>> {
+        let code = normalize(code);
+        let codes = self.db.get_backup_codes(did).await?;
+        let Some(matched) = codes.iter().find(|stored| stored.consumed_at.is_none() && code_hash(&stored.salt, &code) == stored.hash) else {
+            return Ok(None);
+        };
+        // Lost to a concurrent request with the same code
+        if !self.db.consume_backup_code(did, &matched.hash).await? {
+            return Ok(None);
+        }
+        let remaining = self.db.get_backup_codes(did).await?.iter().filter(|stored| stored.consumed_at.is_none()).count();
+        self.audit_log_service.log(did, "backup_code_used", Some(json!({ "remaining": remaining }))).await;
+        self.notification_service.notify(NotificationEvent::BackupCodeUsed { patient_did: did.to_string(), remaining });
+        Ok(Some(remaining))
+    }
+
+    pub async fn status(&self, did: &str) -> Result<BackupCodeStatus> {
+        let codes = self.db.get_backup_codes(did).await?;
+        let remaining = codes.iter().filter(|stored| stored.consumed_at.is_none()).count();
+        let warning = (remaining == 0 && !codes.is_empty()).then(|| EXHAUSTED_WARNING.to_string());
+        Ok(BackupCodeStatus { remaining, warning })
+    }
+}
+
+fn code_hash(salt: &str, code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(code.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Shown in groups of four; typed however the user likes
+fn format_code(code: &str) -> String {
+    code.as_bytes().chunks(4).map(|group| std::str::from_utf8(group).unwrap()).collect::<Vec<_>>().join("-")
+}
+
+fn normalize(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::services::notification::MockNotificationSender;
+    use crate::store::{MockAuditStore, MockBackupCodeStore, MockNotificationStore, MockPatientStore, MockPractitionerStore};
+    use chrono::Utc;
+    use std::sync::Mutex;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+
+    /// A service over one patient's codes, kept in memory like the database would. With
+    /// `stale_reads` every read sees the codes unused, as when racing requests all read first.
+    fn service(stale_reads: bool) -> (Arc<BackupCodeService>, Arc<Mutex<Vec<BackupCode>>>) {
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let mut db = MockBackupCodeStore::new();
+        let codes = stored.clone();
+        db.expect_get_backup_codes().returning(move |_| {
+            let mut codes = codes.lock().unwrap().clone();
+            if stale_reads {
+                codes.iter_mut().for_each(|code| code.consumed_at = None);
+            }
+            Ok(codes)
+        });
+        let codes = stored.clone();
+        db.expect_replace_backup_codes().returning(move |_, new_codes| {
+            *codes.lock().unwrap() = new_codes.to_vec();
+            Ok(true)
+        });
+        let codes = stored.clone();
+        db.expect_consume_backup_code().returning(move |_, hash| {
+            let mut codes = codes.lock().unwrap();
+            match codes.iter_mut().find(|code| code.hash == hash && code.consumed_at.is_none()) {
+                Some(code) => {
+                    code.consumed_at = Some(Utc::now());
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        });
+
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(MockNotificationStore::new()),
+            Arc::new(patients),
+            Arc::new(practitioners),
+            Arc::new(MockNotificationSender::new()),
+            Arc::new(Config::default()),
+        ));
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let service = BackupCodeService::new(Arc::new(db), notification_service, Arc::new(AuditLogService::new(Arc::new(audit_store))));
+        (Arc::new(service), stored)
+    }
+
+    #[tokio::test]
+    async fn codes_are_stored_salted_and_work_once_however_they_are_typed() {
+        let (service, stored) = service(false);
+
+        let codes = service.generate(PATIENT).await.unwrap().codes;
+        assert_eq!(codes.len(), 10);
+        assert!(codes.iter().all(|code| code.len() == 14 && code.matches('-').count() == 2));
+        let stored_codes = stored.lock().unwrap().clone();
+        for (stored, code) in stored_codes.iter().zip(&codes) {
+            assert_ne!(stored.hash, code_hash("", &normalize(code)), "codes are salted");
+        }
+        assert_ne!(stored_codes[0].salt, stored_codes[1].salt);
+
+        let typed = codes[0].replace('-', " ").to_lowercase();
+        assert_eq!(service.consume(PATIENT, &typed).await.unwrap(), Some(9));
+        assert_eq!(service.consume(PATIENT, &codes[0]).await.unwrap(), None);
+        assert_eq!(service.consume(PATIENT, "AAAA-BBBB-CCCC").await.unwrap(), None);
+
+        let old = codes[1].clone();
+        service.generate(PATIENT).await.unwrap();
+        assert_eq!(service.consume(PATIENT, &old).await.unwrap(), None, "regenerating invalidates the old set");
+    }
+
+    #[tokio::test]
+    async fn a_code_raced_in_twice_is_only_accepted_once() {
+        let (service, stored) = service(true);
+        let code = service.generate(PATIENT).await.unwrap().codes.remove(0);
+
+        let attempts = (0..8).map(|_| {
+            let service = service.clone();
+            let code = code.clone();
+            tokio::spawn(async move { service.consume(PATIENT, &code).await.unwrap() })
+        });
+        let mut accepted = 0;
+        for attempt in attempts.collect::<Vec<_>>() {
+            accepted += attempt.await.unwrap().is_some() as usize;
+        }
+
+        assert_eq!(accepted, 1);
+        assert_eq!(stored.lock().unwrap().iter().filter(|code| code.consumed_at.is_some()).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn using_the_last_code_leaves_a_warning() {
+        let (service, _) = service(false);
+        assert!(service.status(PATIENT).await.unwrap().warning.is_none(), "no codes yet is not a warning");
+        let codes = service.generate(PATIENT).await.unwrap().codes;
+
+        for (used, code) in codes.iter().enumerate() {
+            assert!(service.status(PATIENT).await.unwrap().warning.is_none());
+            assert_eq!(service.consume(PATIENT, code).await.unwrap(), Some(codes.len() - used - 1));
+        }
+
+        let status = service.status(PATIENT).await.unwrap();
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.warning.as_deref(), Some(EXHAUSTED_WARNING));
+    }
+}
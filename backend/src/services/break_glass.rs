@@ -0,0 +1,352 @@
+use anyhow::{anyhow, Result};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::email::EmailService;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::services::ServiceError;
+use crate::store::{BreakGlassStore, PatientStore};
+
+/// Break-glass is for reading a record in an emergency, never for changing it
+const EMERGENCY_PERMISSIONS: [Permission; 4] =
+    [Permission::Read, Permission::ViewEncounters, Permission::ViewObservations, Permission::ViewPrescriptions];
+/// Anything shorter does not explain why the record was needed
+const MIN_JUSTIFICATION_CHARS: usize = 10;
+const ALERT_POLL_INTERVAL: time::Duration = time::Duration::from_secs(600);
+
+// --- BreakGlassService ---
+pub struct BreakGlassService {
+    db: Arc<dyn BreakGlassStore>,
+    patients: Arc<dyn PatientStore>,
+    notification_service: Arc<NotificationService>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl BreakGlassService {
+    pub fn new(
+        db: Arc<dyn BreakGlassStore>,
+        patients: Arc<dyn PatientStore>,
+        notification_service: Arc<NotificationService>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, patients, notification_service, config, audit_log_service }
+    }
+
+    /// Give a practitioner time-boxed, read-only access to a record without the patient's grant.
+    /// The patient is told straight away and the event waits in the admin review queue.
+    pub async fn break_glass(&self, caller_did: &str, caller_role: Role, request: BreakGlassRequest) -> Result<BreakGlassEvent> {
+        if caller_role != Role::Practitioner {
+            return Err(ServiceError::Forbidden("Only practitioners can use emergency access".to_string()).into());
+        }
+        let justification = request.justification.trim();
+        if justification.chars().count() < MIN_JUSTIFICATION_CHARS {
+            return Err(anyhow!("A justification of at least {} characters is required", MIN_JUSTIFICATION_CHARS));
+        }
+        if request.patient_did == caller_did {
+            return Err(anyhow!("Emergency access is for other patients' records"));
+        }
+
+        let now = Utc::now();
+        let expires_at = now + Duration::hours(self.config.break_glass_access_hours);
+        let access_control = AccessControl {
+            id: None,
+            patient_did: request.patient_did.clone(),
+            grantee_did: caller_did.to_string(),
+            permissions: EMERGENCY_PERMISSIONS.to_vec(),
+            active: true,
+            created_at: now,
+            expires_at: Some(expires_at),
+            emergency: true,
+            hedera_transaction_id: None,
+        };
+        let access_control_id = self.patients.grant_access(&access_control, &[]).await?;
+
+        let mut event = BreakGlassEvent {
+            id: None,
+            practitioner_did: caller_did.to_string(),
+            patient_did: request.patient_did,
+            justification: justification.to_string(),
+            access_control_id,
+            created_at: now,
+            expires_at,
+            reviewed_at: None,
+            reviewed_by: None,
+            review_notes: None,
+            alerted_at: None,
+        };
+        event.id = Some(self.db.create_break_glass_event(&event).await?);
+
+        tracing::warn!("Break-glass access to {} by {} until {}", event.patient_did, event.practitioner_did, expires_at);
+        self.audit_log_service
+            .log(
+                &event.patient_did,
+                "break_glass",
+                Some(json!({
+                    "actor": caller_did,
+                    "emergency": true,
+                    "justification": event.justification,
+                    "expires_at": expires_at,
+                    "event_id": event.id,
+                })),
+            )
+            .await;
+        self.notification_service.notify(NotificationEvent::EmergencyAccess {
+            patient_did: event.patient_did.clone(),
+            practitioner_did: event.practitioner_did.clone(),
+            justification: event.justification.clone(),
+        });
+        Ok(event)
+    }
+
+    /// The admin review queue, newest first; `reviewed` narrows it to one side
+    pub async fn list_events(&self, reviewed: Option<bool>) -> Result<Vec<BreakGlassEvent>> {
+        self.db.list_break_glass_events(reviewed).await
+    }
+
+    pub async fn review(&self, admin_did: &str, id: &str, request: ReviewBreakGlassRequest) -> Result<BreakGlassEvent> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid break-glass event id"))?;
+        let notes = request.notes.map(|notes| notes.trim().to_string()).filter(|notes| !notes.is_empty());
+        let event = self
+            .db
+            .review_break_glass_event(id, admin_did, notes)
+            .await?
+            .ok_or_else(|| ServiceError::Conflict("Break-glass event not found or already reviewed".to_string()))?;
+        self.audit_log_service
+            .log(&event.patient_did, "review_break_glass", Some(json!({ "actor": admin_did, "event_id": id })))
+            .await;
+        Ok(event)
+    }
+}
+
+/// Emails admins about break-glass events nobody reviewed within `break_glass_review_hours`
+pub struct BreakGlassAlertWorker {
+    events: Arc<dyn BreakGlassStore>,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    config: Arc<Config>,
+}
+
+impl BreakGlassAlertWorker {
+    pub fn new(events: Arc<dyn BreakGlassStore>, patients: Arc<dyn PatientStore>, email_service: Arc<EmailService>, config: Arc<Config>) -> Self {
+        Self { events, patients, email_service, config }
+    }
+
+    /// Poll until `shutdown` is cancelled. A sweep already running is finished first.
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = time::interval(ALERT_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.sweep(Utc::now()).await {
+                tracing::error!("Break-glass alert run failed: {}", e);
+            }
+        }
+        tracing::info!("Break-glass alert worker stopped");
+    }
+
+    /// Alert on every overdue event not alerted on before, returning how many were claimed.
+    /// Each event is alerted on once; it stays in the review queue until an admin reviews it.
+    pub async fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let created_before = now - Duration::hours(self.config.break_glass_review_hours);
+        let mut overdue = Vec::new();
+        while let Some(event) = self.events.claim_break_glass_alert(created_before, now).await? {
+            overdue.push(event);
+        }
+        if overdue.is_empty() {
+            return Ok(0);
+        }
+
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let Some(admin) = self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await? else {
+                continue;
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "review_hours": self.config.break_glass_review_hours,
+                "events": &overdue,
+            });
+            self.email_service
+                .enqueue(&email.value, "Break-glass access is waiting for review", "Break-glass-alert.html", &context)
+                .await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("{} break-glass events are overdue for review but no admin has an email address", overdue.len());
+        }
+        Ok(overdue.len())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::SmtpConfig;
+    use crate::fixtures;
+    use crate::services::notification::MockNotificationSender;
+    use crate::store::{MockAuditStore, MockBreakGlassStore, MockEmailOutboxStore, MockNotificationStore, MockPatientStore, MockPractitionerStore};
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+    const ADMIN: &str = "did:hedera:testnet:0.0.3";
+
+    fn service(events: MockBreakGlassStore, patients: MockPatientStore, audit_store: MockAuditStore) -> BreakGlassService {
+        let patients: Arc<dyn PatientStore> = Arc::new(patients);
+        let config = Arc::new(Config { break_glass_access_hours: 4, ..Default::default() });
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(MockNotificationStore::new()),
+            patients.clone(),
+            Arc::new(practitioners),
+            Arc::new(MockNotificationSender::new()),
+            config.clone(),
+        ));
+        BreakGlassService::new(Arc::new(events), patients, notification_service, config, Arc::new(AuditLogService::new(Arc::new(audit_store))))
+    }
+
+    fn request(justification: &str) -> BreakGlassRequest {
+        BreakGlassRequest { patient_did: PATIENT.to_string(), justification: justification.to_string() }
+    }
+
+    fn event(created_at: DateTime<Utc>) -> BreakGlassEvent {
+        BreakGlassEvent {
+            id: Some(ObjectId::new()),
+            practitioner_did: PRACTITIONER.to_string(),
+            patient_did: PATIENT.to_string(),
+            justification: "Unconscious on arrival".to_string(),
+            access_control_id: ObjectId::new(),
+            created_at,
+            expires_at: created_at + Duration::hours(4),
+            reviewed_at: None,
+            reviewed_by: None,
+            review_notes: None,
+            alerted_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn break_glass_creates_a_time_boxed_read_only_emergency_grant() {
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_grant_access()
+            .withf(|grant, outbox| {
+                outbox.is_empty()
+                    && grant.emergency
+                    && grant.grantee_did == PRACTITIONER
+                    && grant.expires_at.is_some_and(|expires| expires - grant.created_at == Duration::hours(4))
+                    && !grant.permissions.iter().any(|permission| matches!(permission, Permission::Write | Permission::Prescribe))
+            })
+            .times(1)
+            .returning(|_, _| Ok(ObjectId::new()));
+        patients.expect_get_notification_preferences().returning(|_| Ok(None));
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let mut events = MockBreakGlassStore::new();
+        events.expect_create_break_glass_event().times(1).returning(|_| Ok(ObjectId::new()));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| {
+                log.did == PATIENT
+                    && log.action == "break_glass"
+                    && log.details.as_ref().is_some_and(|details| details["emergency"] == true && details["actor"] == PRACTITIONER)
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let event = service(events, patients, audit_store)
+            .break_glass(PRACTITIONER, Role::Practitioner, request("  Unconscious on arrival, no next of kin  "))
+            .await
+            .unwrap();
+
+        assert_eq!(event.justification, "Unconscious on arrival, no next of kin");
+        assert!(event.id.is_some() && event.reviewed_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn break_glass_needs_a_practitioner_and_a_justification() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_grant_access().never();
+
+        let service = service(MockBreakGlassStore::new(), patients, MockAuditStore::new());
+
+        let err = service.break_glass(PATIENT, Role::Patient, request("Unconscious on arrival")).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+        let err = service.break_glass(PRACTITIONER, Role::Practitioner, request("   urgent ")).await.unwrap_err();
+        assert_eq!(err.to_string(), "A justification of at least 10 characters is required");
+    }
+
+    #[tokio::test]
+    async fn events_can_only_be_reviewed_once() {
+        let mut events = MockBreakGlassStore::new();
+        events
+            .expect_review_break_glass_event()
+            .withf(|_, reviewer, notes| reviewer == ADMIN && notes.as_deref() == Some("Confirmed with the ED"))
+            .times(1)
+            .returning(|_, reviewer, notes| {
+                Ok(Some(BreakGlassEvent { reviewed_at: Some(Utc::now()), reviewed_by: Some(reviewer.to_string()), review_notes: notes, ..event(Utc::now()) }))
+            });
+        events.expect_review_break_glass_event().withf(|_, _, notes| notes.is_none()).returning(|_, _, _| Ok(None));
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().withf(|log| log.action == "review_break_glass").times(1).returning(|_| Ok(()));
+        let service = service(events, MockPatientStore::new(), audit_store);
+        let id = ObjectId::new().to_hex();
+
+        let reviewed = service.review(ADMIN, &id, ReviewBreakGlassRequest { notes: Some(" Confirmed with the ED ".to_string()) }).await.unwrap();
+        assert_eq!(reviewed.reviewed_by.as_deref(), Some(ADMIN));
+
+        let err = service.review(ADMIN, &id, ReviewBreakGlassRequest::default()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn overdue_events_are_emailed_to_admins_once() {
+        let now = Utc::now();
+        let mut events = MockBreakGlassStore::new();
+        let mut claimed = vec![event(now - Duration::hours(30)), event(now - Duration::hours(25))];
+        events
+            .expect_claim_break_glass_alert()
+            .withf(move |created_before, at| *created_before == now - Duration::hours(24) && *at == now)
+            .returning(move |_, _| Ok(claimed.pop()));
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|did, _| {
+            Ok(Some(fixtures::admin(did)))
+        });
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox
+            .expect_enqueue_email()
+            .withf(|email| {
+                email.recipient == "admin@example.com"
+                    && email.template == "Break-glass-alert.html"
+                    && email.context["events"].as_array().is_some_and(|events| events.len() == 2)
+            })
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let config = Arc::new(Config {
+            admin_dids: vec![ADMIN.to_string()],
+            break_glass_review_hours: 24,
+            smtp: Some(SmtpConfig::default()),
+            ..Default::default()
+        });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(outbox)).unwrap());
+        let worker = BreakGlassAlertWorker::new(Arc::new(events), Arc::new(patients), email_service, config);
+
+        assert_eq!(worker.sweep(now).await.unwrap(), 2);
+        assert_eq!(worker.sweep(now).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,171 @@
+//! Checking the CAPTCHA tokens the app sends with registration and phone sign-in,
+//! so bots cannot create accounts or have codes texted at our expense.
+
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::config::{CaptchaConfig, CaptchaProvider};
+use crate::deadline;
+use crate::services::ServiceError;
+
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+const RECAPTCHA_VERIFY_URL: &str = "https://www.google.com/recaptcha/api/siteverify";
+const CAPTCHA_FAILED_MESSAGE: &str = "The CAPTCHA could not be verified. Please try again.";
+
+/// Both providers answer siteverify in the same shape
+#[derive(Deserialize)]
+struct SiteverifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+// --- CaptchaVerifier ---
+pub struct CaptchaVerifier {
+    http: Client,
+    config: Option<CaptchaConfig>,
+    url: String,
+}
+
+impl CaptchaVerifier {
+    pub fn new(config: Option<&CaptchaConfig>, http: Client) -> Self {
+        let url = match config.map(|config| config.provider) {
+            Some(CaptchaProvider::Recaptcha) => RECAPTCHA_VERIFY_URL,
+            _ => TURNSTILE_VERIFY_URL,
+        };
+        Self { http, config: config.cloned(), url: url.to_string() }
+    }
+
+    /// Point the client at a different siteverify endpoint (used by tests)
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.url = url.to_string();
+        self
+    }
+
+    /// Refuse the request unless `token` is a valid CAPTCHA solution; anything goes when no provider is configured
+    pub async fn verify(&self, token: Option<&str>, ip: Option<IpAddr>) -> Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let Some(token) = token.filter(|token| !token.is_empty()) else {
+            return Err(ServiceError::CaptchaFailed(CAPTCHA_FAILED_MESSAGE.to_string()).into());
+        };
+
+        match self.siteverify(config, token, ip).await {
+            Ok(response) if response.success => Ok(()),
+            Ok(response) => {
+                tracing::info!("CAPTCHA rejected: {}", response.error_codes.join(", "));
+                Err(ServiceError::CaptchaFailed(CAPTCHA_FAILED_MESSAGE.to_string()).into())
+            }
+            Err(e) if config.fail_open => {
+                tracing::warn!("CAPTCHA provider unavailable, letting the request through: {:#}", e);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("CAPTCHA provider unavailable: {:#}", e);
+                Err(ServiceError::CaptchaFailed(CAPTCHA_FAILED_MESSAGE.to_string()).into())
+            }
+        }
+    }
+
+    async fn siteverify(&self, config: &CaptchaConfig, token: &str, ip: Option<IpAddr>) -> Result<SiteverifyResponse> {
+        let mut form = vec![("secret", config.secret_key.expose_secret().to_string()), ("response", token.to_string())];
+        if let Some(ip) = ip {
+            form.push(("remoteip", ip.to_string()));
+        }
+        let response = self
+            .http
+            .post(&self.url)
+            .timeout(deadline::remaining_or(Duration::from_secs(config.timeout_seconds)))
+            .form(&form)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("CAPTCHA siteverify returned {}", response.status()));
+        }
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn verifier(fail_open: bool) -> (MockServer, CaptchaVerifier) {
+        let server = MockServer::start().await;
+        let config = CaptchaConfig {
+            provider: CaptchaProvider::Turnstile,
+            secret_key: "secret".into(),
+            timeout_seconds: 1,
+            fail_open,
+        };
+        let verifier = CaptchaVerifier::new(Some(&config), Client::new()).with_url(&format!("{}/siteverify", server.uri()));
+        (server, verifier)
+    }
+
+    fn is_captcha_failure(result: Result<()>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<ServiceError>(), Some(ServiceError::CaptchaFailed(_)))
+    }
+
+    #[tokio::test]
+    async fn valid_tokens_pass() {
+        let (server, verifier) = verifier(false).await;
+        Mock::given(method("POST"))
+            .and(path("/siteverify"))
+            .and(body_string_contains("secret=secret"))
+            .and(body_string_contains("response=solved"))
+            .and(body_string_contains("remoteip=203.0.113.7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        verifier.verify(Some("solved"), Some("203.0.113.7".parse().unwrap())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_and_missing_tokens_fail() {
+        let (server, verifier) = verifier(true).await;
+        Mock::given(method("POST"))
+            .and(path("/siteverify"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": false, "error-codes": ["invalid-input-response"] })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(is_captcha_failure(verifier.verify(Some("forged"), None).await), "fail-open is only for an unreachable provider");
+        assert!(is_captcha_failure(verifier.verify(None, None).await));
+        assert!(is_captcha_failure(verifier.verify(Some(""), None).await));
+    }
+
+    #[tokio::test]
+    async fn provider_timeout_follows_the_fail_open_flag() {
+        for fail_open in [false, true] {
+            let (server, verifier) = verifier(fail_open).await;
+            Mock::given(method("POST"))
+                .and(path("/siteverify"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true })).set_delay(Duration::from_secs(3)))
+                .mount(&server)
+                .await;
+
+            let result = verifier.verify(Some("solved"), None).await;
+            if fail_open {
+                assert!(result.is_ok());
+            } else {
+                assert!(is_captcha_failure(result));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn nothing_is_checked_without_a_provider() {
+        let verifier = CaptchaVerifier::new(None, Client::new()).with_url("http://127.0.0.1:9/siteverify");
+        verifier.verify(None, None).await.unwrap();
+    }
+}
@@ -0,0 +1,550 @@
+use anyhow::anyhow;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::gemini::{ChatModel, ModelReply};
+use crate::services::record_context::{prompt_with_record, RecordContext};
+use crate::services::redaction::Redactor;
+use crate::services::ServiceError;
+use crate::store::{ChatStore, PatientStore};
+
+/// Earlier turns sent along with each prompt
+const MAX_HISTORY_MESSAGES: i64 = 20;
+/// Rough token budget for that history, at about four characters per token
+const MAX_HISTORY_CHARS: usize = 16_000;
+const TITLE_CHARS: usize = 60;
+/// Sent instead of calling the model when a prompt touches a blocked topic
+pub const BLOCKED_TOPIC_REPLY: &str = "I can't help with that topic here. Please talk to your clinician, \
+or contact your local emergency services if you need help right now.";
+
+// --- ChatService ---
+pub struct ChatService {
+    store: Arc<dyn ChatStore>,
+    model: Arc<dyn ChatModel>,
+    patients: Arc<dyn PatientStore>,
+    record_context: Arc<RecordContext>,
+    audit_log_service: Arc<AuditLogService>,
+    config: Arc<Config>,
+}
+
+impl ChatService {
+    pub fn new(
+        store: Arc<dyn ChatStore>,
+        model: Arc<dyn ChatModel>,
+        patients: Arc<dyn PatientStore>,
+        record_context: Arc<RecordContext>,
+        audit_log_service: Arc<AuditLogService>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self { store, model, patients, record_context, audit_log_service, config }
+    }
+
+    /// Answer `prompt` in the caller's session, starting a new one when `session_id` is absent.
+    ///
+    /// With `use_my_data`, a summary of the caller's own record goes along with the
+    /// question; that needs the server switch and the patient's stored consent.
+    /// Both the prompt and the reply are stored only once the model has answered, so a
+    /// failed request leaves no dangling question in the history. The record summary
+    /// itself is never stored.
+    ///
+    /// Every model call counts against the caller's daily quota; once it is used up
+    /// the request fails with [`ServiceError::RateLimited`] naming the reset time.
+    pub async fn send(&self, owner_did: &str, session_id: Option<&str>, prompt: &str, use_my_data: bool) -> anyhow::Result<ChatReply> {
+        if use_my_data {
+            self.ensure_record_access(owner_did).await?;
+        }
+        let today = Utc::now().date_naive();
+        self.ensure_within_quota(owner_did, today).await?;
+
+        let (session_id, history) = match session_id {
+            Some(session_id) => {
+                let session = self.session(owner_did, session_id).await?;
+                let history = self.store.recent_chat_messages(session, MAX_HISTORY_MESSAGES).await?;
+                (session, bounded_history(history))
+            }
+            None => {
+                let session = ChatSession {
+                    id: None,
+                    owner_did: owner_did.to_string(),
+                    title: title_from(prompt),
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                (self.store.create_chat_session(&session).await?, Vec::new())
+            }
+        };
+
+        let (reply, redactions) = match blocked_topic(&self.config.chat_blocked_topics, prompt) {
+            Some(topic) => {
+                tracing::info!("Chat prompt in session {} matched blocked topic '{}'", session_id, topic);
+                let reply = ModelReply { text: BLOCKED_TOPIC_REPLY.to_string(), prompt_tokens: 0, reply_tokens: 0 };
+                (reply, 0)
+            }
+            None => {
+                let outbound = if use_my_data {
+                    self.prompt_with_record(owner_did, session_id, prompt).await?
+                } else {
+                    prompt.to_string()
+                };
+                // History is redacted too: it goes back to the model on every turn
+                let mut redactor = Redactor::new();
+                let history: Vec<ChatMessage> = history
+                    .into_iter()
+                    .map(|message| ChatMessage { content: redactor.redact(&message.content), ..message })
+                    .collect();
+                let redacted_history = redactor.applied();
+                let outbound = redactor.redact(&outbound);
+                let redactions = (redactor.applied() - redacted_history) as u32;
+                let reply = self.model.generate(&history, &outbound).await?;
+                self.store.record_chat_usage(owner_did, &today.to_string(), reply.prompt_tokens, reply.reply_tokens).await?;
+                (ModelReply { text: redactor.restore(&reply.text), ..reply }, redactions)
+            }
+        };
+
+        let messages = [(ChatRole::User, prompt, redactions), (ChatRole::Model, reply.text.as_str(), 0)];
+        for (role, content, redactions) in messages {
+            let message = ChatMessage { id: None, session_id, role, content: content.to_string(), redactions, created_at: Utc::now() };
+            self.store.add_chat_message(&message).await?;
+        }
+        // Counts only: the conversation itself stays out of the audit trail
+        self.audit_log_service
+            .log(owner_did, "chat", Some(json!({
+                "session_id": session_id.to_hex(),
+                "prompt_tokens": reply.prompt_tokens,
+                "reply_tokens": reply.reply_tokens,
+                "redactions": redactions,
+                "use_my_data": use_my_data,
+            })))
+            .await;
+        Ok(ChatReply { session_id: session_id.to_hex(), reply: reply.text })
+    }
+
+    /// Per-user consumption over the last `days` days, today included
+    pub async fn usage(&self, days: u32) -> anyhow::Result<Vec<ChatUsage>> {
+        let since = Utc::now().date_naive() - Duration::days(i64::from(days.max(1)) - 1);
+        self.store.list_chat_usage(&since.to_string()).await
+    }
+
+    pub async fn list_sessions(&self, owner_did: &str) -> anyhow::Result<Vec<ChatSession>> {
+        self.store.list_chat_sessions(owner_did).await
+    }
+
+    pub async fn list_messages(&self, owner_did: &str, session_id: &str) -> anyhow::Result<Vec<ChatMessage>> {
+        let session = self.session(owner_did, session_id).await?;
+        self.store.list_chat_messages(session).await
+    }
+
+    /// The question with the caller's record summary, audit-logged as a record access
+    async fn prompt_with_record(&self, owner_did: &str, session_id: ObjectId, prompt: &str) -> anyhow::Result<String> {
+        let summary = self.record_context.summarize(owner_did).await?;
+        let prompt_with_record = prompt_with_record(&summary, prompt);
+        self.audit_log_service
+            .log(owner_did, "chat_record_access", Some(json!({
+                "session_id": session_id.to_hex(),
+                "encounters": summary.encounters.len(),
+                "context_chars": prompt_with_record.chars().count(),
+            })))
+            .await;
+        Ok(prompt_with_record)
+    }
+
+    async fn ensure_record_access(&self, owner_did: &str) -> anyhow::Result<()> {
+        if !self.config.dynamic.load().chat_record_context {
+            return Err(ServiceError::NotConfigured("chat about your record is not enabled on this server".to_string()).into());
+        }
+        if !self.patients.get_chat_record_consent(owner_did).await? {
+            return Err(ServiceError::Forbidden("Allow the assistant to read your record before asking about it".to_string()).into());
+        }
+        Ok(())
+    }
+
+    async fn ensure_within_quota(&self, owner_did: &str, today: NaiveDate) -> anyhow::Result<()> {
+        let usage = self.store.get_chat_usage(owner_did, &today.to_string()).await?.unwrap_or_default();
+        if quota_exhausted(&usage, &self.config) {
+            let message = format!("Daily chat limit reached; it resets at {}", quota_reset(today).to_rfc3339());
+            return Err(ServiceError::RateLimited(message).into());
+        }
+        Ok(())
+    }
+
+    /// Resolve a session the caller owns; someone else's session looks exactly like a missing one
+    async fn session(&self, owner_did: &str, session_id: &str) -> anyhow::Result<ObjectId> {
+        let id = ObjectId::parse_str(session_id).map_err(|_| anyhow!("Invalid chat session id"))?;
+        match self.store.get_chat_session(id, owner_did).await? {
+            Some(_) => Ok(id),
+            None => Err(anyhow!("Chat session not found")),
+        }
+    }
+}
+
+/// Keep the newest messages that fit in [`MAX_HISTORY_CHARS`], oldest first.
+///
+/// Gemini expects a conversation to open with a user turn, so a model reply left
+/// at the front after trimming is dropped too.
+fn bounded_history(messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let mut budget = MAX_HISTORY_CHARS;
+    let mut kept: Vec<ChatMessage> = messages
+        .into_iter()
+        .rev()
+        .take_while(|message| {
+            let size = message.content.chars().count();
+            let fits = size <= budget;
+            budget = budget.saturating_sub(size);
+            fits
+        })
+        .collect();
+    kept.reverse();
+    let first_user_turn = kept.iter().position(|message| message.role == ChatRole::User).unwrap_or(kept.len());
+    kept.split_off(first_user_turn)
+}
+
+fn quota_exhausted(usage: &ChatUsage, config: &Config) -> bool {
+    let dynamic = config.dynamic.load();
+    let (requests, tokens) = (dynamic.chat_daily_request_limit, dynamic.chat_daily_token_limit);
+    (requests > 0 && usage.requests >= requests) || (tokens > 0 && usage.total_tokens() >= tokens)
+}
+
+/// Quotas run per UTC day, so they reset at the next midnight UTC
+fn quota_reset(today: NaiveDate) -> DateTime<Utc> {
+    (today + Duration::days(1)).and_time(NaiveTime::MIN).and_utc()
+}
+
+/// The first configured topic the prompt mentions, matched case-insensitively on word boundaries
+fn blocked_topic<'a>(topics: &'a [String], prompt: &str) -> Option<&'a str> {
+    let prompt = prompt.to_lowercase();
+    topics.iter().map(String::as_str).find(|topic| {
+        let topic = topic.to_lowercase();
+        prompt.match_indices(&topic).any(|(start, found)| {
+            let before = prompt[..start].chars().next_back();
+            let after = prompt[start + found.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+    })
+}
+
+fn title_from(prompt: &str) -> String {
+    let prompt = prompt.trim();
+    match prompt.char_indices().nth(TITLE_CHARS) {
+        Some((end, _)) => format!("{}…", prompt[..end].trim_end()),
+        None => prompt.to_string(),
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::DynamicConfig;
+    use crate::fixtures;
+    use crate::services::gemini::MockChatModel;
+    use crate::store::{MockAuditStore, MockChatStore, MockEncounterStore, MockPatientStore};
+
+    const DID: &str = "did:hedera:testnet:0.0.1";
+
+    fn service(store: MockChatStore, model: MockChatModel, patients: MockPatientStore, audit_store: MockAuditStore, config: Config) -> ChatService {
+        let patients: Arc<dyn PatientStore> = Arc::new(patients);
+        let config = Arc::new(config);
+        let mut encounters = MockEncounterStore::new();
+        encounters.expect_list_recent_encounters().returning(|_, _| Ok(Vec::new()));
+        let record_context = Arc::new(RecordContext::new(patients.clone(), Arc::new(encounters), config.clone()));
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        ChatService::new(Arc::new(store), Arc::new(model), patients, record_context, audit_log_service, config)
+    }
+
+    /// A store that lets the request through the daily quota and records its usage
+    fn store_within_quota() -> MockChatStore {
+        let mut store = MockChatStore::new();
+        store.expect_get_chat_usage().returning(|_, _| Ok(None));
+        store.expect_record_chat_usage().returning(|_, _, _, _| Ok(()));
+        store
+    }
+
+    fn audit_store_expecting_chat() -> MockAuditStore {
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.did == DID && log.action == "chat")
+            .times(1)
+            .returning(|_| Ok(()));
+        audit_store
+    }
+
+    fn model_reply(text: &str) -> ModelReply {
+        ModelReply { text: text.to_string(), prompt_tokens: 40, reply_tokens: 10 }
+    }
+
+    fn message(role: ChatRole, content: &str) -> ChatMessage {
+        ChatMessage { id: None, session_id: ObjectId::new(), role, content: content.to_string(), redactions: 0, created_at: Utc::now() }
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn short_history_is_kept_in_order() {
+        let history = vec![message(ChatRole::User, "first"), message(ChatRole::Model, "answer")];
+
+        assert_eq!(contents(&bounded_history(history)), vec!["first", "answer"]);
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped_once_over_budget() {
+        let long = "x".repeat(MAX_HISTORY_CHARS - 10);
+        let history = vec![
+            message(ChatRole::User, "old question"),
+            message(ChatRole::Model, &long),
+            message(ChatRole::User, "second question"),
+        ];
+
+        // The long reply fits but the question before it does not, leaving a model turn first
+        assert_eq!(contents(&bounded_history(history)), vec!["second question"]);
+    }
+
+    #[test]
+    fn blocked_topics_match_whole_words_in_any_case() {
+        let topics = vec!["overdose".to_string(), "self harm".to_string()];
+
+        assert_eq!(blocked_topic(&topics, "How much for an OVERDOSE?"), Some("overdose"));
+        assert_eq!(blocked_topic(&topics, "thoughts of self harm"), Some("self harm"));
+        assert_eq!(blocked_topic(&topics, "Is my dose an overdosed amount"), None);
+        assert_eq!(blocked_topic(&[], "overdose"), None);
+    }
+
+    #[test]
+    fn long_prompts_are_shortened_for_the_title() {
+        let title = title_from(&"é".repeat(100));
+
+        assert_eq!(title.chars().count(), TITLE_CHARS + 1);
+        assert!(title.ends_with('…'));
+        assert_eq!(title_from("  What is HbA1c?  "), "What is HbA1c?");
+    }
+
+    #[tokio::test]
+    async fn follow_up_includes_prior_turns_and_persists_both_messages() {
+        let session_id = ObjectId::new();
+        let mut store = store_within_quota();
+        store
+            .expect_get_chat_session()
+            .withf(move |id, owner| *id == session_id && owner == DID)
+            .returning(|_, owner| {
+                Ok(Some(ChatSession { id: None, owner_did: owner.to_string(), title: String::new(), created_at: Utc::now(), updated_at: Utc::now() }))
+            });
+        store.expect_recent_chat_messages().returning(move |_, _| {
+            Ok(vec![
+                ChatMessage { id: None, session_id, role: ChatRole::User, content: "What is HbA1c?".to_string(), redactions: 0, created_at: Utc::now() },
+                ChatMessage { id: None, session_id, role: ChatRole::Model, content: "A blood test.".to_string(), redactions: 0, created_at: Utc::now() },
+            ])
+        });
+        store
+            .expect_add_chat_message()
+            .withf(move |m| m.session_id == session_id)
+            .times(2)
+            .returning(|_| Ok(ObjectId::new()));
+        let mut model = MockChatModel::new();
+        model
+            .expect_generate()
+            .withf(|history, prompt| history.len() == 2 && prompt == "What is a normal value?")
+            .returning(|_, _| Ok(model_reply("Below 5.7%.")));
+
+        let reply = service(store, model, MockPatientStore::new(), audit_store_expecting_chat(), Config::default())
+            .send(DID, Some(&session_id.to_hex()), "What is a normal value?", false)
+            .await
+            .unwrap();
+
+        assert_eq!(reply.session_id, session_id.to_hex());
+        assert_eq!(reply.reply, "Below 5.7%.");
+    }
+
+    #[tokio::test]
+    async fn another_users_session_is_not_found() {
+        let mut store = MockChatStore::new();
+        store.expect_get_chat_session().returning(|_, _| Ok(None));
+        store.expect_list_chat_messages().never();
+
+        let err = service(store, MockChatModel::new(), MockPatientStore::new(), MockAuditStore::new(), Config::default())
+            .list_messages(DID, &ObjectId::new().to_hex())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "Chat session not found");
+    }
+
+    #[tokio::test]
+    async fn record_context_is_off_by_default() {
+        let mut model = MockChatModel::new();
+        model.expect_generate().never();
+
+        let err = service(MockChatStore::new(), model, MockPatientStore::new(), MockAuditStore::new(), Config::default())
+            .send(DID, None, "What did the doctor prescribe me?", true)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::NotConfigured(_))));
+    }
+
+    #[tokio::test]
+    async fn record_context_needs_the_patients_consent() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_chat_record_consent().returning(|_| Ok(false));
+        let config = Config { dynamic: DynamicConfig { chat_record_context: true, ..Default::default() }.into(), ..Default::default() };
+
+        let err = service(MockChatStore::new(), MockChatModel::new(), patients, MockAuditStore::new(), config)
+            .send(DID, None, "What did the doctor prescribe me?", true)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn record_context_is_sent_and_audited_but_not_stored() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_chat_record_consent().returning(|_| Ok(true));
+        patients.expect_get_patient_timezone().returning(|_| Ok(Some("Africa/Nairobi".to_string())));
+        patients.expect_get_patient_by_did().returning(|_, _| {
+            Ok(Some(fixtures::patient(DID, FhirPatient { gender: "female".to_string(), birth_date: "1990-04-02".to_string(), ..Default::default() })))
+        });
+        let mut audit_store = audit_store_expecting_chat();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.did == DID && log.action == "chat_record_access")
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut store = store_within_quota();
+        store.expect_create_chat_session().returning(|_| Ok(ObjectId::new()));
+        store
+            .expect_add_chat_message()
+            .withf(|m| !m.content.contains("<record>"))
+            .times(2)
+            .returning(|_| Ok(ObjectId::new()));
+        let mut model = MockChatModel::new();
+        model
+            .expect_generate()
+            .withf(|_, prompt| prompt.contains("born: 1990-04-02") && prompt.ends_with("Question: How old am I?"))
+            .returning(|_, _| Ok(model_reply("You are 36.")));
+        let config = Config { dynamic: DynamicConfig { chat_record_context: true, ..Default::default() }.into(), ..Default::default() };
+
+        service(store, model, patients, audit_store, config).send(DID, None, "How old am I?", true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocked_topic_gets_the_canned_reply_without_calling_the_model() {
+        let mut store = MockChatStore::new();
+        store.expect_get_chat_usage().returning(|_, _| Ok(None));
+        store.expect_record_chat_usage().never();
+        store.expect_create_chat_session().returning(|_| Ok(ObjectId::new()));
+        store
+            .expect_add_chat_message()
+            .withf(|m| m.role == ChatRole::User || m.content == BLOCKED_TOPIC_REPLY)
+            .times(2)
+            .returning(|_| Ok(ObjectId::new()));
+        let mut model = MockChatModel::new();
+        model.expect_generate().never();
+        let config = Config { chat_blocked_topics: vec!["overdose".to_string()], ..Default::default() };
+
+        let reply = service(store, model, MockPatientStore::new(), audit_store_expecting_chat(), config)
+            .send(DID, None, "What is a lethal overdose?", false)
+            .await
+            .unwrap();
+
+        assert_eq!(reply.reply, BLOCKED_TOPIC_REPLY);
+    }
+
+    #[tokio::test]
+    async fn identifiers_are_redacted_before_sending_and_restored_in_the_reply() {
+        let mut store = store_within_quota();
+        store.expect_create_chat_session().returning(|_| Ok(ObjectId::new()));
+        store
+            .expect_add_chat_message()
+            .withf(|m| m.role == ChatRole::User && m.content.contains("+254712345678") && m.redactions == 1)
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        store
+            .expect_add_chat_message()
+            .withf(|m| m.role == ChatRole::Model && m.redactions == 0)
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let mut model = MockChatModel::new();
+        model
+            .expect_generate()
+            .withf(|_, prompt| prompt == "Can the clinic text me on [PHONE_1]?")
+            .returning(|_, _| Ok(model_reply("Ask the clinic to save [PHONE_1] on your file.")));
+
+        let reply = service(store, model, MockPatientStore::new(), audit_store_expecting_chat(), Config::default())
+            .send(DID, None, "Can the clinic text me on +254712345678?", false)
+            .await
+            .unwrap();
+
+        assert_eq!(reply.reply, "Ask the clinic to save +254712345678 on your file.");
+    }
+
+    #[tokio::test]
+    async fn usage_is_recorded_and_audited_without_content() {
+        let mut store = MockChatStore::new();
+        store.expect_get_chat_usage().returning(|_, _| Ok(None));
+        store
+            .expect_record_chat_usage()
+            .withf(|did, day, prompt_tokens, reply_tokens| {
+                did == DID && day == Utc::now().date_naive().to_string() && (*prompt_tokens, *reply_tokens) == (40, 10)
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        store.expect_create_chat_session().returning(|_| Ok(ObjectId::new()));
+        store.expect_add_chat_message().times(2).returning(|_| Ok(ObjectId::new()));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| {
+                let details = log.details.clone().unwrap_or_default();
+                log.action == "chat"
+                    && details["prompt_tokens"] == 40
+                    && details["reply_tokens"] == 10
+                    && details["session_id"].is_string()
+                    && !details.to_string().contains("HbA1c")
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut model = MockChatModel::new();
+        model.expect_generate().returning(|_, _| Ok(model_reply("HbA1c is a blood test.")));
+
+        service(store, model, MockPatientStore::new(), audit_store, Config::default())
+            .send(DID, None, "What is HbA1c?", false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn exhausted_quota_is_rate_limited_until_midnight_utc() {
+        let mut store = MockChatStore::new();
+        store
+            .expect_get_chat_usage()
+            .returning(|did, day| Ok(Some(ChatUsage { user_did: did.to_string(), day: day.to_string(), requests: 3, ..Default::default() })));
+        store.expect_create_chat_session().never();
+        let mut model = MockChatModel::new();
+        model.expect_generate().never();
+        let config = Config { dynamic: DynamicConfig { chat_daily_request_limit: 3, ..Default::default() }.into(), ..Default::default() };
+
+        let err = service(store, model, MockPatientStore::new(), MockAuditStore::new(), config)
+            .send(DID, None, "What is HbA1c?", false)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::RateLimited(_))));
+        let tomorrow = Utc::now().date_naive() + Duration::days(1);
+        assert!(err.to_string().ends_with(&format!("resets at {}T00:00:00+00:00", tomorrow)));
+    }
+
+    #[test]
+    fn token_quota_counts_prompt_and_reply_and_zero_means_unlimited() {
+        let usage = ChatUsage { requests: 1, prompt_tokens: 900, reply_tokens: 100, ..Default::default() };
+
+        assert!(quota_exhausted(&usage, &Config { dynamic: DynamicConfig { chat_daily_token_limit: 1_000, ..Default::default() }.into(), ..Default::default() }));
+        assert!(!quota_exhausted(&usage, &Config { dynamic: DynamicConfig { chat_daily_token_limit: 1_001, ..Default::default() }.into(), ..Default::default() }));
+        assert!(!quota_exhausted(&usage, &Config::default()));
+    }
+}
@@ -0,0 +1,508 @@
+//! Signed reports for auditors on one period of the audit log: who accessed which records,
+//! every break-glass use and every credential issued, together with the anchor batches that
+//! put those events on Hedera. Generated in the background; the JSON is signed with the
+//! platform's credential key so it can be checked without trusting this server.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auditing::{AnchorLookup, AuditLogService, AuditingService};
+use crate::config::Config;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::vc_document::{canonical_json, verify_detached, CredentialSigner};
+use crate::services::ServiceError;
+use crate::store::{AuditStore, ComplianceReportStore, JobStore};
+use crate::utils;
+
+/// Longest period one report may cover
+const MAX_RANGE_DAYS: i64 = 366;
+/// Reports listed at a time
+const LIST_LIMIT: i64 = 50;
+const HTML_TEMPLATE: &str = include_str!("../templates/Compliance-report.html");
+
+/// Reads of a record and changes to who may read it
+const ACCESS_ACTIONS: &[&str] = &[
+    "get_patient",
+    "export_everything",
+    "download_file",
+    "list_prescriptions",
+    "list_problems",
+    "chat_record_access",
+    "summarize_observations",
+    "download_patient_export",
+    "grant_access",
+    "access_granted",
+    "revoke_consent",
+];
+const BREAK_GLASS_ACTIONS: &[&str] = &["break_glass", "review_break_glass"];
+/// Issuance is logged as `issue_credential: <type>`
+const CREDENTIAL_PREFIXES: &[&str] = &["issue_credential: "];
+
+/// The report's sections: name, actions and action prefixes
+const CATEGORIES: &[(&str, &[&str], &[&str])] = &[
+    ("access", ACCESS_ACTIONS, &[]),
+    ("break_glass", BREAK_GLASS_ACTIONS, &[]),
+    ("credential_issuance", &[], CREDENTIAL_PREFIXES),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Html,
+}
+
+/// One of a report's files, decrypted
+pub struct ReportArtifact {
+    pub filename: String,
+    pub content_type: &'static str,
+    pub content: Vec<u8>,
+}
+
+// --- ComplianceReportService ---
+pub struct ComplianceReportService {
+    reports: Arc<dyn ComplianceReportStore>,
+    jobs: Arc<dyn JobStore>,
+    audit_logs: Arc<dyn AuditStore>,
+    auditing_service: Arc<AuditingService>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl ComplianceReportService {
+    pub fn new(
+        reports: Arc<dyn ComplianceReportStore>,
+        jobs: Arc<dyn JobStore>,
+        audit_logs: Arc<dyn AuditStore>,
+        auditing_service: Arc<AuditingService>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { reports, jobs, audit_logs, auditing_service, ipfs_client, config, audit_log_service }
+    }
+
+    fn signer(&self) -> Result<CredentialSigner> {
+        match &self.config.credential_signing {
+            Some(signing) => CredentialSigner::from_config(signing),
+            None => Err(ServiceError::NotConfigured("Credential signing is not configured, so reports cannot be signed".to_string()).into()),
+        }
+    }
+
+    /// Queue a report on `[from, to)`
+    pub async fn request(&self, admin_did: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ComplianceReport> {
+        if from >= to {
+            return Err(anyhow!("'from' must be before 'to'"));
+        }
+        if to - from > Duration::days(MAX_RANGE_DAYS) {
+            return Err(anyhow!("A compliance report covers at most {} days", MAX_RANGE_DAYS));
+        }
+        // Refused now rather than failing in the background
+        self.signer()?;
+        let now = Utc::now();
+        let mut report = ComplianceReport {
+            id: None,
+            requested_by: admin_did.to_string(),
+            from,
+            to,
+            status: ComplianceReportStatus::Pending,
+            progress: 0,
+            stage: None,
+            artifacts: None,
+            anchors_valid: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+            completed_at: None,
+        };
+        let id = self.reports.create_compliance_report(&report).await?;
+        report.id = Some(id);
+        let job = Job::new(GENERATE_COMPLIANCE_REPORT_JOB, json!({ "report_id": id.to_hex() }), now);
+        if let Err(e) = self.jobs.enqueue_job(&job).await {
+            self.reports.fail_compliance_report(id, "Could not queue the report").await?;
+            return Err(e);
+        }
+        self.audit_log_service
+            .log(admin_did, "request_compliance_report", Some(json!({ "report_id": id.to_hex(), "from": from, "to": to })))
+            .await;
+        Ok(report)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<ComplianceReport> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Report not found"))?;
+        self.reports.get_compliance_report(id).await?.ok_or_else(|| anyhow!("Report not found"))
+    }
+
+    /// The most recent reports, newest first
+    pub async fn list(&self) -> Result<Vec<ComplianceReport>> {
+        self.reports.list_compliance_reports(LIST_LIMIT).await
+    }
+
+    /// The signed JSON or the HTML rendering of a completed report
+    pub async fn artifact(&self, admin_did: &str, id: &str, format: ReportFormat) -> Result<ReportArtifact> {
+        let report = self.get(id).await?;
+        let artifacts = match (&report.status, &report.artifacts) {
+            (ComplianceReportStatus::Completed, Some(artifacts)) => artifacts,
+            _ => return Err(ServiceError::Conflict("The report has not been generated yet".to_string()).into()),
+        };
+        let key = self
+            .config
+            .encryption_key(artifacts.key_version)
+            .ok_or_else(|| anyhow!("No key is configured for version {}", artifacts.key_version))?;
+        let (hash, extension, content_type) = match format {
+            ReportFormat::Json => (&artifacts.json_ipfs_hash, "json", "application/json"),
+            ReportFormat::Html => (&artifacts.html_ipfs_hash, "html", "text/html; charset=utf-8"),
+        };
+        let content = utils::chunked::decrypt(&self.ipfs_client.get_file(hash).await?, key)?;
+        if format == ReportFormat::Json && hex::encode(Sha256::digest(&content)) != artifacts.sha256 {
+            return Err(anyhow!("Report {} does not match its digest", id));
+        }
+        self.audit_log_service
+            .log(admin_did, "download_compliance_report", Some(json!({ "report_id": id, "format": extension })))
+            .await;
+        Ok(ReportArtifact { filename: format!("compliance-report-{}.{}", id, extension), content_type, content })
+    }
+
+    /// Check a report's JSON against its signature and each of its batches against the ledger
+    pub async fn verify(&self, document: &Value) -> Result<ComplianceReportVerification> {
+        let current_key = self.signer().ok().map(|signer| signer.public_key_multibase());
+        let lookup = self.auditing_service.anchor_lookup();
+        verify_report(document, lookup.as_deref(), current_key.as_deref()).await
+    }
+
+    /// Gather, sign and store report `id`. Reports already finished are left alone.
+    pub async fn generate(&self, id: ObjectId) -> Result<(), JobError> {
+        let Some(report) = self.reports.get_compliance_report(id).await? else {
+            return Err(JobError::Permanent(anyhow!("Report {} not found", id)));
+        };
+        if !matches!(report.status, ComplianceReportStatus::Pending | ComplianceReportStatus::Running) {
+            return Ok(());
+        }
+        let signer = self.signer().map_err(JobError::Permanent)?;
+
+        self.progress(id, 5, "collecting events").await?;
+        let mut categories = Vec::new();
+        for (category, actions, prefixes) in CATEGORIES {
+            let logs = self.audit_logs.audit_logs_in_period(report.from, report.to, actions, prefixes).await?;
+            categories.push(ComplianceCategory { category: category.to_string(), count: logs.len(), events: logs.iter().map(event).collect() });
+        }
+
+        // Batches made in the period, and the later ones that anchored its events
+        self.progress(id, 20, "checking anchor batches").await?;
+        let mut batches = self.audit_logs.anchor_batches_in_period(report.from, report.to).await?;
+        let mut seen: HashSet<ObjectId> = batches.iter().filter_map(|batch| batch.id).collect();
+        let referenced: Vec<ObjectId> = categories
+            .iter()
+            .flat_map(|category| &category.events)
+            .filter_map(|event| event.anchor_batch_id.as_deref().and_then(|id| ObjectId::parse_str(id).ok()))
+            .collect();
+        for batch_id in referenced {
+            if seen.insert(batch_id) {
+                batches.extend(self.audit_logs.get_anchor_batch(batch_id).await?);
+            }
+        }
+        batches.sort_by_key(|batch| batch.created_at);
+
+        let mut anchor_batches = Vec::with_capacity(batches.len());
+        for (checked, batch) in batches.iter().enumerate() {
+            let batch_id = batch.id.ok_or_else(|| JobError::Permanent(anyhow!("Anchor batch has no id")))?;
+            let Some(verification) = self.auditing_service.verify_batch(batch_id).await? else {
+                continue;
+            };
+            anchor_batches.push(ReportedAnchorBatch {
+                batch_id: verification.batch_id,
+                created_at: batch.created_at,
+                merkle_root: verification.merkle_root,
+                log_count: verification.log_count,
+                transaction_id: verification.transaction_id,
+                ledger_transaction_id: verification.ledger_transaction_id,
+                valid: verification.valid,
+            });
+            let progress = 20 + 60 * (checked + 1) / batches.len();
+            self.progress(id, progress as u8, &format!("checked {} of {} anchor batches", checked + 1, batches.len())).await?;
+        }
+
+        self.progress(id, 85, "signing").await?;
+        let stats = self.audit_logs.audit_stats(report.from, report.to, AuditGroupBy::Action, 1, &[]).await?;
+        let anchors_valid = anchor_batches.iter().all(|batch| batch.valid);
+        let mut document = ComplianceReportDocument {
+            report_id: id.to_hex(),
+            from: report.from,
+            to: report.to,
+            generated_at: Utc::now(),
+            generated_by: report.requested_by.clone(),
+            summary: ComplianceSummary {
+                total_events: stats.anchored + stats.unanchored,
+                anchored_events: stats.anchored,
+                unanchored_events: stats.unanchored,
+                anchor_batches: anchor_batches.len(),
+                anchors_valid,
+            },
+            categories,
+            anchor_batches,
+            signature: None,
+        };
+        document.signature = Some(sign_report(&document, &signer).map_err(JobError::Permanent)?);
+        let json = serde_json::to_vec_pretty(&document).map_err(|e| JobError::Permanent(e.into()))?;
+        let html = render_html(&document).map_err(JobError::Permanent)?;
+
+        self.progress(id, 90, "storing").await?;
+        let artifacts = ComplianceReportArtifacts {
+            json_ipfs_hash: self.store(&json).await?,
+            html_ipfs_hash: self.store(html.as_bytes()).await?,
+            key_version: self.config.ipfs_encryption_key_version,
+            sha256: hex::encode(Sha256::digest(&json)),
+        };
+        if !self.reports.complete_compliance_report(id, &artifacts, anchors_valid).await? {
+            return Ok(());
+        }
+        tracing::info!("Compliance report {} is ready ({} anchor batches, all valid: {})", id, document.summary.anchor_batches, anchors_valid);
+        self.audit_log_service
+            .log(&report.requested_by, "compliance_report_generated", Some(json!({ "report_id": id.to_hex(), "sha256": artifacts.sha256 })))
+            .await;
+        Ok(())
+    }
+
+    async fn progress(&self, id: ObjectId, progress: u8, stage: &str) -> Result<()> {
+        self.reports.record_compliance_report_progress(id, progress, stage).await?;
+        Ok(())
+    }
+
+    /// Encrypt, add and pin `content`; returns its IPFS hash
+    async fn store(&self, content: &[u8]) -> Result<String, JobError> {
+        let encrypted = utils::chunked::encrypt(content, &self.config.ipfs_encryption_key).map_err(JobError::Permanent)?;
+        let ipfs_hash = self.ipfs_client.add_file(&encrypted, None).await?;
+        self.ipfs_client.pin_add(&ipfs_hash).await?;
+        Ok(ipfs_hash)
+    }
+
+    /// Record why report `id` could not be generated
+    pub async fn fail(&self, id: ObjectId, error: &JobError) {
+        let message = match error {
+            JobError::Transient(e) | JobError::Permanent(e) => e.to_string(),
+        };
+        if let Err(e) = self.reports.fail_compliance_report(id, &message).await {
+            tracing::warn!("Could not mark compliance report {} as failed: {}", id, e);
+        }
+    }
+}
+
+fn event(log: &AuditLog) -> ComplianceEvent {
+    let details = log.details.as_ref();
+    ComplianceEvent {
+        id: log.id.map(|id| id.to_hex()).unwrap_or_default(),
+        timestamp: log.timestamp,
+        did: log.did.clone(),
+        action: log.action.clone(),
+        actor: details.and_then(|details| details.get("actor")).and_then(Value::as_str).map(str::to_string),
+        emergency: log.action == "break_glass" || details.and_then(|details| details.get("emergency")).and_then(Value::as_bool).unwrap_or(false),
+        anchor_batch_id: log.anchor_batch_id.map(|id| id.to_hex()),
+    }
+}
+
+/// What the signature covers: the canonical JSON of the report without its signature
+fn signing_input(document: &Value) -> Vec<u8> {
+    let mut unsigned = document.clone();
+    if let Some(object) = unsigned.as_object_mut() {
+        object.remove("signature");
+    }
+    canonical_json(&unsigned).into_bytes()
+}
+
+fn sign_report(document: &ComplianceReportDocument, signer: &CredentialSigner) -> Result<ReportSignature> {
+    let value = serde_json::to_value(document)?;
+    Ok(ReportSignature {
+        alg: "EdDSA".to_string(),
+        verification_method: signer.verification_method().to_string(),
+        public_key_multibase: signer.public_key_multibase(),
+        value: signer.sign_detached(&signing_input(&value)),
+    })
+}
+
+fn render_html(document: &ComplianceReportDocument) -> Result<String> {
+    let mut tera = Tera::default();
+    tera.add_raw_template("Compliance-report.html", HTML_TEMPLATE)?;
+    tera.autoescape_on(vec![".html"]);
+    Ok(tera.render("Compliance-report.html", &Context::from_serialize(document)?)?)
+}
+
+/// Re-check a report's JSON as it was handed out: the signature over everything else in it,
+/// and with `lookup`, that each batch's Merkle root is on the ledger. `current_key` is the
+/// multibase key this server signs with, if any.
+pub async fn verify_report(document: &Value, lookup: Option<&dyn AnchorLookup>, current_key: Option<&str>) -> Result<ComplianceReportVerification> {
+    let report: ComplianceReportDocument = serde_json::from_value(document.clone()).map_err(|e| anyhow!("Not a compliance report: {}", e))?;
+    let signature_valid = report.signature.as_ref().is_some_and(|signature| {
+        signature.alg == "EdDSA" && verify_detached(&signature.public_key_multibase, &signing_input(document), &signature.value)
+    });
+    let signed_by_this_server = signature_valid && report.signature.as_ref().map(|signature| signature.public_key_multibase.as_str()) == current_key;
+
+    let mut batches = Vec::with_capacity(report.anchor_batches.len());
+    for batch in &report.anchor_batches {
+        let root: Option<[u8; 32]> = hex::decode(&batch.merkle_root).ok().and_then(|root| root.try_into().ok());
+        let ledger_transaction_id = match (lookup, root) {
+            (Some(lookup), Some(root)) => lookup.find_anchor(root, batch.created_at).await?,
+            _ => None,
+        };
+        batches.push(AnchorCheck {
+            batch_id: batch.batch_id.clone(),
+            transaction_id: batch.transaction_id.clone(),
+            valid: ledger_transaction_id.is_some(),
+            ledger_transaction_id,
+        });
+    }
+    let anchors_checked = lookup.is_some();
+    let valid = signature_valid && (!anchors_checked || batches.iter().all(|batch| batch.valid));
+    Ok(ComplianceReportVerification { signature_valid, signed_by_this_server, anchors_checked, batches, valid })
+}
+
+/// Generates a requested report. Retries with the job's backoff; once it runs out of
+/// attempts the report is marked failed.
+pub struct ComplianceReportHandler {
+    service: Arc<ComplianceReportService>,
+    max_attempts: u32,
+}
+
+impl ComplianceReportHandler {
+    pub fn new(service: Arc<ComplianceReportService>, max_attempts: u32) -> Self {
+        Self { service, max_attempts }
+    }
+}
+
+#[async_trait]
+impl JobHandler for ComplianceReportHandler {
+    fn job_type(&self) -> &'static str {
+        GENERATE_COMPLIANCE_REPORT_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        let id = job
+            .payload
+            .get("report_id")
+            .and_then(Value::as_str)
+            .and_then(|id| ObjectId::parse_str(id).ok())
+            .ok_or_else(|| JobError::Permanent(anyhow!("Malformed compliance report job payload: {}", job.payload)))?;
+        let result = self.service.generate(id).await;
+        if let Err(e) = &result {
+            if matches!(e, JobError::Permanent(_)) || job.attempts >= self.max_attempts {
+                self.service.fail(id, e).await;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const ROOT: [u8; 32] = [3; 32];
+
+    struct OneAnchor;
+
+    #[async_trait]
+    impl AnchorLookup for OneAnchor {
+        async fn find_anchor(&self, root: [u8; 32], _since: DateTime<Utc>) -> Result<Option<String>> {
+            Ok((root == ROOT).then(|| "0.0.2@1700000000.000000001".to_string()))
+        }
+    }
+
+    fn signer() -> CredentialSigner {
+        CredentialSigner::new("did:hedera:testnet:0.0.5", "key-1", [7; 32])
+    }
+
+    fn signed_report() -> Value {
+        let at = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let mut document = ComplianceReportDocument {
+            report_id: "report".to_string(),
+            from: at,
+            to: at + Duration::days(30),
+            generated_at: at + Duration::days(31),
+            generated_by: "did:hedera:testnet:0.0.9".to_string(),
+            summary: ComplianceSummary { total_events: 1, anchored_events: 1, unanchored_events: 0, anchor_batches: 1, anchors_valid: true },
+            categories: vec![ComplianceCategory {
+                category: "break_glass".to_string(),
+                count: 1,
+                events: vec![ComplianceEvent {
+                    id: "log".to_string(),
+                    timestamp: at + Duration::hours(1),
+                    did: "did:hedera:testnet:0.0.1".to_string(),
+                    action: "break_glass".to_string(),
+                    actor: Some("did:hedera:testnet:0.0.2".to_string()),
+                    emergency: true,
+                    anchor_batch_id: Some("batch".to_string()),
+                }],
+            }],
+            anchor_batches: vec![ReportedAnchorBatch {
+                batch_id: "batch".to_string(),
+                created_at: at + Duration::hours(2),
+                merkle_root: hex::encode(ROOT),
+                log_count: 1,
+                transaction_id: Some("0.0.2@1700000000.000000001".to_string()),
+                ledger_transaction_id: None,
+                valid: true,
+            }],
+            signature: None,
+        };
+        document.signature = Some(sign_report(&document, &signer()).unwrap());
+        // As an auditor would read it back from the downloaded file
+        serde_json::from_slice(&serde_json::to_vec_pretty(&document).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_signed_report_verifies_on_its_own() {
+        let current = signer().public_key_multibase();
+        let verification = verify_report(&signed_report(), Some(&OneAnchor), Some(&current)).await.unwrap();
+        assert!(verification.signature_valid);
+        assert!(verification.signed_by_this_server);
+        assert!(verification.anchors_checked);
+        assert!(verification.batches.iter().all(|batch| batch.valid));
+        assert!(verification.valid);
+
+        // Without a mirror node only the signature is checked
+        let offline = verify_report(&signed_report(), None, None).await.unwrap();
+        assert!(offline.valid && !offline.anchors_checked && !offline.signed_by_this_server);
+    }
+
+    #[tokio::test]
+    async fn tampering_with_any_part_of_a_report_is_detected() {
+        let mut hidden = signed_report();
+        hidden["categories"][0]["events"] = json!([]);
+        assert!(!verify_report(&hidden, None, None).await.unwrap().signature_valid);
+
+        let mut added = signed_report();
+        added["note"] = json!("nothing to see");
+        assert!(!verify_report(&added, None, None).await.unwrap().signature_valid);
+
+        // Re-signing with another key is caught by the key it was signed with
+        let mut resigned: ComplianceReportDocument = serde_json::from_value(signed_report()).unwrap();
+        resigned.summary.total_events = 0;
+        resigned.signature = Some(sign_report(&resigned, &CredentialSigner::new("did:hedera:testnet:0.0.5", "key-1", [8; 32])).unwrap());
+        let resigned = verify_report(&serde_json::to_value(resigned).unwrap(), None, Some(&signer().public_key_multibase())).await.unwrap();
+        assert!(resigned.signature_valid && !resigned.signed_by_this_server);
+
+        // A root the ledger never saw
+        let mut forged = signed_report();
+        forged["anchor_batches"][0]["merkle_root"] = json!(hex::encode([4; 32]));
+        let verification = verify_report(&forged, Some(&OneAnchor), None).await.unwrap();
+        assert!(!verification.signature_valid && !verification.batches[0].valid && !verification.valid);
+    }
+
+    #[test]
+    fn the_html_rendering_escapes_what_it_shows() {
+        let mut document: ComplianceReportDocument = serde_json::from_value(signed_report()).unwrap();
+        document.categories[0].events[0].action = "<script>".to_string();
+        let html = render_html(&document).unwrap();
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("Break glass (1)"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Swapping in new [`DynamicConfig`] settings without a restart, on SIGHUP or an admin's request.
+//!
+//! The whole configuration is read again, from the config file and the environment as at
+//! startup, and validated before anything changes; a configuration with problems leaves the
+//! running one as it was. Only the dynamic settings are swapped in. Everything else, secrets and
+//! the database URL included, stays as it was at boot, and a reload that would change it only
+//! logs a warning naming those settings.
+
+use anyhow::Result;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::{Config, DynamicConfig};
+use crate::models::ConfigReload;
+use crate::services::ServiceError;
+use crate::telemetry::LogLevelHandle;
+
+/// Who the audit log names for reloads triggered by SIGHUP
+pub const SIGNAL_ACTOR: &str = "system:sighup";
+
+pub struct ConfigReloadService {
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+    /// Changes the level of the installed log subscriber; tests run without one
+    log_level: Option<LogLevelHandle>,
+}
+
+impl ConfigReloadService {
+    pub fn new(config: Arc<Config>, audit_log_service: Arc<AuditLogService>, log_level: Option<LogLevelHandle>) -> Self {
+        Self { config, audit_log_service, log_level }
+    }
+
+    /// Read the configuration again and apply its dynamic settings
+    pub async fn reload(&self, actor: &str) -> Result<ConfigReload> {
+        let reloaded = Config::load().map_err(|e| ServiceError::InvalidConfig(format!("The configuration was not reloaded: {}", e.problems.join("; "))))?;
+        self.apply(&reloaded, actor).await
+    }
+
+    /// Swap in the dynamic settings of `reloaded`, an already validated configuration, and
+    /// audit-log what changed
+    pub async fn apply(&self, reloaded: &Config, actor: &str) -> Result<ConfigReload> {
+        let ignored = self.config.boot_changes(reloaded);
+        if !ignored.is_empty() {
+            tracing::warn!("Configuration reload left {} as they were; they only change on a restart", ignored.join(", "));
+        }
+        let next = DynamicConfig::clone(&reloaded.dynamic.load());
+        let changed = self.config.dynamic.load().changes(&next);
+        if let Some(log_level) = self.log_level.as_ref().filter(|_| changed.contains_key("log_level")) {
+            log_level.set(&next.log_level)?;
+        }
+        self.config.dynamic.store(next);
+
+        let names: Vec<&str> = changed.keys().map(String::as_str).collect();
+        tracing::info!("Configuration reloaded by {}; changed: {}", actor, if names.is_empty() { "nothing".to_string() } else { names.join(", ") });
+        self.audit_log_service.log(actor, "reload_config", Some(json!({ "changed": changed, "ignored": ignored }))).await;
+        Ok(ConfigReload { changed, ignored })
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::MockAuditStore;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.1";
+
+    fn service(config: Arc<Config>, audit_store: MockAuditStore) -> ConfigReloadService {
+        ConfigReloadService::new(config, Arc::new(AuditLogService::new(Arc::new(audit_store))), None)
+    }
+
+    #[tokio::test]
+    async fn dynamic_settings_are_swapped_in_and_boot_settings_left_alone() {
+        let running = Arc::new(Config { database_url: "mongodb://first".into(), ..Default::default() });
+        let reloaded = Config {
+            database_url: "mongodb://second".into(),
+            dynamic: DynamicConfig { chat_daily_request_limit: 5, ..Default::default() }.into(),
+            ..Default::default()
+        };
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| {
+                let details = log.details.as_ref().unwrap();
+                log.did == ADMIN
+                    && log.action == "reload_config"
+                    && details["changed"] == json!({ "chat_daily_request_limit": { "from": 100, "to": 5 } })
+                    && details["ignored"] == json!(["database_url"])
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let reload = service(running.clone(), audit_store).apply(&reloaded, ADMIN).await.unwrap();
+
+        assert_eq!(reload.ignored, vec!["database_url"]);
+        assert_eq!(running.dynamic.load().chat_daily_request_limit, 5);
+        assert_eq!(running.database_url.expose_secret(), "mongodb://first");
+        // A clone made before the reload sees it too
+        assert_eq!(Config::clone(&running).dynamic.load().chat_daily_request_limit, 5);
+    }
+}
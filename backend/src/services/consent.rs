@@ -0,0 +1,366 @@
+use anyhow::anyhow;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::ServiceError;
+use crate::store::{ConsentStore, PatientStore};
+use crate::utils;
+
+const CONSENT_SCOPE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/consentscope";
+const LOINC: &str = "http://loinc.org";
+/// LOINC 59284-0, "Patient Consent"
+const PATIENT_CONSENT: &str = "59284-0";
+const ROLE_CODE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-RoleCode";
+const RESOURCE_TYPES: &str = "http://hl7.org/fhir/resource-types";
+
+// --- ConsentService ---
+pub struct ConsentService {
+    db: Arc<dyn ConsentStore>,
+    patients: Arc<dyn PatientStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl ConsentService {
+    pub fn new(
+        db: Arc<dyn ConsentStore>,
+        patients: Arc<dyn PatientStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, patients, ipfs_client, config, audit_log_service }
+    }
+
+    /// Record the consent behind a new access grant, covering what its permissions allow
+    pub async fn record_grant(&self, access_control: &AccessControl) -> anyhow::Result<Consent> {
+        let mut data_classes: Vec<String> = Vec::new();
+        for class in access_control.permissions.iter().flat_map(|permission| permission.data_classes()) {
+            if !data_classes.iter().any(|existing| existing == class) {
+                data_classes.push(class.to_string());
+            }
+        }
+        self.store(
+            &access_control.patient_did,
+            &access_control.grantee_did,
+            data_classes,
+            access_control.created_at,
+            access_control.expires_at,
+            access_control.id,
+        )
+        .await
+    }
+
+    /// Record a consent the patient gives directly, without an access grant
+    pub async fn create_consent(&self, caller_did: &str, patient_did: &str, request: CreateConsentRequest) -> anyhow::Result<Consent> {
+        ensure_owner(caller_did, patient_did)?;
+        if request.data_classes.is_empty() {
+            return Err(anyhow!("A consent must cover at least one data class"));
+        }
+        if let Some(unknown) = request.data_classes.iter().find(|class| !CONSENT_DATA_CLASSES.contains(&class.as_str())) {
+            return Err(anyhow!("Unknown data class: {} (expected one of {})", unknown, CONSENT_DATA_CLASSES.join(", ")));
+        }
+        let period_start = request.period_start.unwrap_or_else(Utc::now);
+        if request.period_end.is_some_and(|end| end <= period_start) {
+            return Err(anyhow!("Consent period must end after it starts"));
+        }
+        self.store(patient_did, &request.grantee_did, request.data_classes, period_start, request.period_end, None).await
+    }
+
+    pub async fn list_consents(&self, caller_did: &str, patient_did: &str) -> anyhow::Result<Vec<Consent>> {
+        ensure_owner(caller_did, patient_did)?;
+        self.db.list_consents(patient_did).await
+    }
+
+    /// Withdraw a consent. The grantee's access grant goes with it, since it was what the grant relied on.
+    pub async fn revoke_consent(&self, caller_did: &str, patient_did: &str, consent_id: &str) -> anyhow::Result<Consent> {
+        ensure_owner(caller_did, patient_did)?;
+        let id = ObjectId::parse_str(consent_id).map_err(|_| anyhow!("Invalid consent id"))?;
+        let mut consent = self
+            .db
+            .get_consent(id)
+            .await?
+            .filter(|consent| consent.patient_did == patient_did)
+            .ok_or_else(|| anyhow!("Consent not found"))?;
+        if consent.status == ConsentStatus::Inactive {
+            return Err(anyhow!("Consent is already inactive"));
+        }
+
+        consent.status = ConsentStatus::Inactive;
+        consent.fhir_consent.status = ConsentStatus::Inactive.fhir_code().to_string();
+        consent.ipfs_hash = self.upload(&consent.fhir_consent).await?;
+        if !self.db.deactivate_consent(id, &consent.fhir_consent, &consent.ipfs_hash).await? {
+            return Err(anyhow!("Consent is already inactive"));
+        }
+        // A consent recorded for a grant ends only that grant; standalone ones end the grantee's access
+        let grant_revoked = match consent.access_control_id {
+            Some(access_control_id) => self.patients.deactivate_grant(access_control_id).await?,
+            None => self.patients.deactivate_access(patient_did, &consent.grantee_did).await?,
+        };
+        self.audit_log_service
+            .log(
+                patient_did,
+                "revoke_consent",
+                Some(json!({ "consent_id": consent_id, "grantee": consent.grantee_did, "grant_revoked": grant_revoked })),
+            )
+            .await;
+        consent.updated_at = Utc::now();
+        Ok(consent)
+    }
+
+    /// Whether `grantee_did` has an active consent covering `data_class` of the patient's record right now
+    pub async fn permits(&self, patient_did: &str, grantee_did: &str, data_class: &str) -> anyhow::Result<bool> {
+        let now = Utc::now();
+        let consents = self.db.active_consents(patient_did, grantee_did).await?;
+        Ok(consents.iter().any(|consent| consent.covers(data_class, now)))
+    }
+
+    async fn store(
+        &self,
+        patient_did: &str,
+        grantee_did: &str,
+        data_classes: Vec<String>,
+        period_start: DateTime<Utc>,
+        period_end: Option<DateTime<Utc>>,
+        access_control_id: Option<ObjectId>,
+    ) -> anyhow::Result<Consent> {
+        let fhir_consent = fhir_consent(patient_did, grantee_did, &data_classes, period_start, period_end);
+        let ipfs_hash = self.upload(&fhir_consent).await?;
+        let mut consent = Consent {
+            id: None,
+            patient_did: patient_did.to_string(),
+            grantee_did: grantee_did.to_string(),
+            status: ConsentStatus::Active,
+            data_classes,
+            period_start,
+            period_end,
+            access_control_id,
+            fhir_consent,
+            ipfs_hash,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let id = self.db.create_consent(&consent).await?;
+        self.audit_log_service
+            .log(
+                patient_did,
+                "create_consent",
+                Some(json!({ "consent_id": id.to_hex(), "grantee": grantee_did, "data_classes": consent.data_classes })),
+            )
+            .await;
+        consent.id = Some(id);
+        Ok(consent)
+    }
+
+    /// Encrypt the FHIR document and add it to IPFS, returning its hash
+    async fn upload(&self, fhir_consent: &FhirConsent) -> anyhow::Result<String> {
+        let document = serde_json::to_vec(fhir_consent)?;
+        let encrypted = utils::encrypt(&document, &self.config.ipfs_encryption_key)?;
+        self.ipfs_client.add_file(encrypted.as_bytes(), None).await
+    }
+}
+
+fn ensure_owner(caller_did: &str, patient_did: &str) -> anyhow::Result<()> {
+    if caller_did != patient_did {
+        return Err(ServiceError::Forbidden("Patients can only manage their own consents".to_string()).into());
+    }
+    Ok(())
+}
+
+fn fhir_consent(
+    patient_did: &str,
+    grantee_did: &str,
+    data_classes: &[String],
+    period_start: DateTime<Utc>,
+    period_end: Option<DateTime<Utc>>,
+) -> FhirConsent {
+    let coding = |system: &str, code: &str, display: &str| FhirCoding {
+        system: Some(system.to_string()),
+        code: Some(code.to_string()),
+        display: Some(display.to_string()),
+    };
+    FhirConsent {
+        resource_type: "Consent".to_string(),
+        id: Uuid::new_v4().to_string(),
+        status: ConsentStatus::Active.fhir_code().to_string(),
+        scope: FhirCodeableConcept { coding: vec![coding(CONSENT_SCOPE_SYSTEM, "patient-privacy", "Privacy Consent")], text: None },
+        category: vec![FhirCodeableConcept { coding: vec![coding(LOINC, PATIENT_CONSENT, "Patient Consent")], text: None }],
+        patient: FhirReference { reference: format!("Patient/{}", patient_did), display: None },
+        date_time: Utc::now().into(),
+        provision: FhirConsentProvision {
+            r#type: "permit".to_string(),
+            period: Some(FhirPeriod { start: Some(period_start.into()), end: period_end.map(Into::into) }),
+            actor: vec![FhirConsentActor {
+                role: FhirCodeableConcept { coding: vec![coding(ROLE_CODE_SYSTEM, "PROV", "healthcare provider")], text: None },
+                reference: FhirReference { reference: format!("Practitioner/{}", grantee_did), display: None },
+            }],
+            class: data_classes.iter().map(|class| coding(RESOURCE_TYPES, class, class)).collect(),
+        },
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::store::{MockAuditStore, MockConsentStore, MockPatientStore};
+    use chrono::Duration;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+    const CONSENT_ID: &str = "65f1a2b3c4d5e6f708091a2b";
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config { ipfs_encryption_key: "00".repeat(32).into(), ..Default::default() })
+    }
+
+    fn service(consents: MockConsentStore, patients: MockPatientStore, ipfs: Arc<InMemoryObjectStorage>) -> ConsentService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        ConsentService::new(Arc::new(consents), Arc::new(patients), ipfs, config(), Arc::new(AuditLogService::new(Arc::new(audit_store))))
+    }
+
+    fn consent(status: ConsentStatus, data_classes: &[&str]) -> Consent {
+        let data_classes: Vec<String> = data_classes.iter().map(|class| class.to_string()).collect();
+        Consent {
+            id: Some(ObjectId::parse_str(CONSENT_ID).unwrap()),
+            patient_did: PATIENT.to_string(),
+            grantee_did: PRACTITIONER.to_string(),
+            status,
+            fhir_consent: fhir_consent(PATIENT, PRACTITIONER, &data_classes, Utc::now() - Duration::days(1), None),
+            data_classes,
+            period_start: Utc::now() - Duration::days(1),
+            period_end: None,
+            access_control_id: None,
+            ipfs_hash: "QmOld".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn grants_are_recorded_as_encrypted_fhir_consents() {
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let mut consents = MockConsentStore::new();
+        consents.expect_create_consent().times(1).returning(|_| Ok(ObjectId::new()));
+        let access_control = AccessControl {
+            id: Some(ObjectId::new()),
+            patient_did: PATIENT.to_string(),
+            grantee_did: PRACTITIONER.to_string(),
+            permissions: vec![Permission::ViewEncounters, Permission::Write, Permission::ViewObservations],
+            active: true,
+            created_at: Utc::now(),
+            expires_at: None,
+            emergency: false,
+            hedera_transaction_id: None,
+        };
+
+        let consent = service(consents, MockPatientStore::new(), ipfs.clone()).record_grant(&access_control).await.unwrap();
+
+        assert_eq!(consent.data_classes, vec!["Encounter", "Condition", "Observation"]);
+        assert_eq!(consent.access_control_id, access_control.id);
+        assert_eq!(consent.fhir_consent.provision.actor[0].reference.reference, format!("Practitioner/{}", PRACTITIONER));
+        assert_eq!(consent.fhir_consent.patient.reference, format!("Patient/{}", PATIENT));
+        assert!(ipfs.contains(&consent.ipfs_hash));
+        let stored = ipfs.get_file(&consent.ipfs_hash).await.unwrap();
+        let decrypted = utils::decrypt(std::str::from_utf8(&stored).unwrap(), &"00".repeat(32).into()).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&decrypted).unwrap();
+        assert_eq!(document["resourceType"], "Consent");
+        assert_eq!(document["provision"]["class"][0]["code"], "Encounter");
+    }
+
+    #[tokio::test]
+    async fn unknown_data_classes_are_rejected() {
+        let mut consents = MockConsentStore::new();
+        consents.expect_create_consent().never();
+        let request = CreateConsentRequest {
+            grantee_did: PRACTITIONER.to_string(),
+            data_classes: vec!["Genome".to_string()],
+            period_start: None,
+            period_end: None,
+        };
+
+        let err = service(consents, MockPatientStore::new(), Arc::new(InMemoryObjectStorage::new()))
+            .create_consent(PATIENT, PATIENT, request)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("Unknown data class: Genome"));
+    }
+
+    #[tokio::test]
+    async fn revoking_a_consent_deactivates_the_grant() {
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let mut consents = MockConsentStore::new();
+        consents.expect_get_consent().returning(|_| Ok(Some(consent(ConsentStatus::Active, &["Patient"]))));
+        consents
+            .expect_deactivate_consent()
+            .withf(|_, fhir, hash| fhir.status == "inactive" && hash != "QmOld")
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_deactivate_access()
+            .withf(|patient, grantee| patient == PATIENT && grantee == PRACTITIONER)
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let revoked = service(consents, patients, ipfs.clone()).revoke_consent(PATIENT, PATIENT, CONSENT_ID).await.unwrap();
+
+        assert_eq!(revoked.status, ConsentStatus::Inactive);
+        assert!(ipfs.contains(&revoked.ipfs_hash));
+    }
+
+    #[tokio::test]
+    async fn revoking_a_grants_consent_deactivates_only_that_grant() {
+        let access_control_id = ObjectId::new();
+        let mut consents = MockConsentStore::new();
+        consents.expect_get_consent().returning(move |_| {
+            let mut consent = consent(ConsentStatus::Active, &["Encounter"]);
+            consent.access_control_id = Some(access_control_id);
+            Ok(Some(consent))
+        });
+        consents.expect_deactivate_consent().returning(|_, _, _| Ok(true));
+        let mut patients = MockPatientStore::new();
+        patients.expect_deactivate_access().never();
+        patients.expect_deactivate_grant().withf(move |id| *id == access_control_id).times(1).returning(|_| Ok(true));
+
+        service(consents, patients, Arc::new(InMemoryObjectStorage::new())).revoke_consent(PATIENT, PATIENT, CONSENT_ID).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_patient_can_revoke() {
+        let mut consents = MockConsentStore::new();
+        consents.expect_deactivate_consent().never();
+
+        let err = service(consents, MockPatientStore::new(), Arc::new(InMemoryObjectStorage::new()))
+            .revoke_consent(PRACTITIONER, PATIENT, CONSENT_ID)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn consents_only_permit_their_data_classes_and_period() {
+        let mut consents = MockConsentStore::new();
+        consents.expect_active_consents().returning(|_, _| {
+            let mut expired = consent(ConsentStatus::Active, &["MedicationRequest"]);
+            expired.period_end = Some(Utc::now() - Duration::hours(1));
+            Ok(vec![consent(ConsentStatus::Active, &["Encounter"]), expired])
+        });
+        let service = service(consents, MockPatientStore::new(), Arc::new(InMemoryObjectStorage::new()));
+
+        assert!(service.permits(PATIENT, PRACTITIONER, "Encounter").await.unwrap());
+        assert!(!service.permits(PATIENT, PRACTITIONER, "Observation").await.unwrap());
+        assert!(!service.permits(PATIENT, PRACTITIONER, "MedicationRequest").await.unwrap());
+    }
+}
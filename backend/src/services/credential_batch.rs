@@ -0,0 +1,118 @@
+//! Merkle trees over credentials issued in a batch. The batch anchors one root on the ledger
+//! instead of storing each credential, and each credential keeps the proof leading to that root.
+
+use bson::oid::ObjectId;
+use rs_merkle::{algorithms::Sha256 as MerkleSha256, MerkleProof, MerkleTree};
+use sha2::{Digest, Sha256};
+
+use crate::models::{BatchFeeComparison, CredentialBatchProof};
+use crate::services::hedera_costs::to_hbar;
+
+/// The leaf a credential adds to its batch's tree: the SHA-256 of its IPFS hash, which itself
+/// commits to the encrypted document
+pub fn leaf(ipfs_hash: &str) -> [u8; 32] {
+    Sha256::digest(ipfs_hash.as_bytes()).into()
+}
+
+/// The root over credentials stored under `ipfs_hashes`, in order, and each one's proof; None
+/// without credentials
+pub fn prove(batch_id: ObjectId, ipfs_hashes: &[&str]) -> Option<([u8; 32], Vec<CredentialBatchProof>)> {
+    let leaves: Vec<[u8; 32]> = ipfs_hashes.iter().map(|ipfs_hash| leaf(ipfs_hash)).collect();
+    let tree = MerkleTree::<MerkleSha256>::from_leaves(&leaves);
+    let root = tree.root()?;
+    let proofs = (0..leaves.len())
+        .map(|index| CredentialBatchProof {
+            batch_id,
+            merkle_root: hex::encode(root),
+            leaf_index: index as u32,
+            leaf_count: leaves.len() as u32,
+            proof: tree.proof(&[index]).proof_hashes().iter().map(hex::encode).collect(),
+        })
+        .collect();
+    Some((root, proofs))
+}
+
+/// The root `proof` leads to from the credential stored under `ipfs_hash`, when that is the root
+/// the proof names. None when it leads elsewhere, as it does once the credential or the proof
+/// was changed.
+pub fn proven_root(ipfs_hash: &str, proof: &CredentialBatchProof) -> Option<[u8; 32]> {
+    if proof.leaf_index >= proof.leaf_count {
+        return None;
+    }
+    let hashes = proof
+        .proof
+        .iter()
+        .map(|hash| hex::decode(hash).ok().and_then(|hash| hash.try_into().ok()))
+        .collect::<Option<Vec<[u8; 32]>>>()?;
+    let root = MerkleProof::<MerkleSha256>::new(hashes)
+        .root(&[proof.leaf_index as usize], &[leaf(ipfs_hash)], proof.leaf_count as usize)
+        .ok()?;
+    (hex::encode(root) == proof.merkle_root).then_some(root)
+}
+
+/// One call for the batch against one per credential, priced at the average of `single_fees`,
+/// the tinybar fees of credentials recently stored one by one
+pub fn fee_comparison(size: u32, single_fees: &[i64]) -> BatchFeeComparison {
+    let average_single_fee_hbar = (!single_fees.is_empty()).then(|| to_hbar(single_fees.iter().sum()) / single_fees.len() as f64);
+    BatchFeeComparison {
+        ledger_calls: 1,
+        ledger_calls_if_issued_singly: size,
+        average_single_fee_hbar,
+        // The batch call costs about what a single one does: its arguments are a root and a count
+        estimated_saving_hbar: average_single_fee_hbar.map(|fee| fee * f64::from(size.saturating_sub(1))),
+        note: format!(
+            "One storeCredentialBatch call anchors all {} credentials instead of {} storeCredential calls. \
+             Each credential is still uploaded to IPFS on its own, and verifying one checks its Merkle proof against the anchored root.",
+            size, size
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("QmVaccination{}", index)).collect()
+    }
+
+    #[test]
+    fn every_credential_proves_the_batch_root() {
+        for count in [1, 2, 7] {
+            let hashes = hashes(count);
+            let (root, proofs) = prove(ObjectId::new(), &hashes.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
+            for (hash, proof) in hashes.iter().zip(&proofs) {
+                assert_eq!(proven_root(hash, proof), Some(root), "{} of {}", proof.leaf_index, count);
+            }
+        }
+        assert!(prove(ObjectId::new(), &[]).is_none());
+    }
+
+    #[test]
+    fn a_changed_credential_or_proof_proves_nothing() {
+        let hashes = hashes(5);
+        let (_, proofs) = prove(ObjectId::new(), &hashes.iter().map(String::as_str).collect::<Vec<_>>()).unwrap();
+        let middle = &proofs[2];
+
+        assert!(proven_root("QmSomethingElse", middle).is_none());
+        assert!(proven_root(&hashes[3], middle).is_none());
+        let mut forged = middle.clone();
+        forged.proof[0] = hex::encode([7; 32]);
+        assert!(proven_root(&hashes[2], &forged).is_none());
+        let moved = CredentialBatchProof { leaf_index: 3, ..middle.clone() };
+        assert!(proven_root(&hashes[2], &moved).is_none());
+        let outside = CredentialBatchProof { leaf_index: 5, ..middle.clone() };
+        assert!(proven_root(&hashes[2], &outside).is_none());
+    }
+
+    #[test]
+    fn savings_are_priced_at_the_recent_single_fee() {
+        let fees = fee_comparison(500, &[20_000_000, 30_000_000]);
+        assert_eq!((fees.ledger_calls, fees.ledger_calls_if_issued_singly), (1, 500));
+        assert_eq!(fees.average_single_fee_hbar, Some(0.25));
+        assert_eq!(fees.estimated_saving_hbar, Some(0.25 * 499.0));
+
+        let unknown = fee_comparison(10, &[]);
+        assert_eq!((unknown.average_single_fee_hbar, unknown.estimated_saving_hbar), (None, None));
+    }
+}
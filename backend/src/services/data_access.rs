@@ -0,0 +1,340 @@
+//! Per-actor limits on reading other people's records, to slow a stolen token being used to
+//! copy them in bulk.
+//!
+//! Each actor's reads over the last hour are counted in memory: the distinct patients whose
+//! records they opened and the distinct files (encounter bundles and attachments) they
+//! downloaded. Reaching the alert threshold flags the actor in the audit log and emails the
+//! admins, once an hour; going over the limit refuses anything new with 429 until older reads
+//! leave the window. Records and files already counted can still be read, so a clinician who
+//! trips the limit can carry on with the patients in front of them.
+//!
+//! Break-glass reads and admin exports are never refused, but always alerted on. The
+//! thresholds are read from [`DynamicConfig`](crate::config::DynamicConfig) on every call, and
+//! counts are kept per server instance.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::i18n;
+use crate::services::email::EmailService;
+use crate::services::login_anomaly::Clock;
+use crate::services::patient::Access;
+use crate::services::ServiceError;
+use crate::store::PatientStore;
+
+const WINDOW_MINUTES: i64 = 60;
+const TOO_MANY_RECORDS_MESSAGE: &str = "error.too_many_records";
+const TOO_MANY_DOWNLOADS_MESSAGE: &str = "error.too_many_downloads";
+
+/// Why an actor was alerted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessAlert {
+    /// Reached `data_access_patients_alert_per_hour` distinct patients
+    Patients,
+    /// Reached `data_access_downloads_alert_per_hour` distinct files
+    Downloads,
+    /// Read a record through break-glass
+    BreakGlass,
+    /// Exported patient data as an admin
+    AdminExport,
+}
+
+impl AccessAlert {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Patients => "patients_per_hour",
+            Self::Downloads => "downloads_per_hour",
+            Self::BreakGlass => "break_glass",
+            Self::AdminExport => "admin_export",
+        }
+    }
+
+    /// What the actor did, for the admins' email
+    fn describe(&self, count: usize) -> String {
+        match self {
+            Self::Patients => format!("opened the records of {} different patients within an hour", count),
+            Self::Downloads => format!("downloaded {} different files within an hour", count),
+            Self::BreakGlass => "read a record through break-glass access".to_string(),
+            Self::AdminExport => "exported patient data as an admin".to_string(),
+        }
+    }
+}
+
+/// What counting one more read came to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessVerdict {
+    Allow,
+    /// Allowed, and the count just reached the alert threshold
+    Alert(usize),
+    /// Over the limit; the read was not counted
+    Block(usize),
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    /// Patients read, with when each was last read
+    patients: HashMap<String, DateTime<Utc>>,
+    /// Files downloaded, with when each was last downloaded
+    files: HashMap<String, DateTime<Utc>>,
+    /// When each alert last went out
+    alerted: HashMap<AccessAlert, DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+/// Sliding-window counts per actor. Pure bookkeeping; auditing and email are left to
+/// [`DataAccessMonitor`].
+pub struct AccessWindow {
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    activity: Mutex<HashMap<String, Activity>>,
+}
+
+impl AccessWindow {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { window: Duration::minutes(WINDOW_MINUTES), clock, activity: Mutex::new(HashMap::new()) }
+    }
+
+    /// Count `actor` reading `key`, a patient for [`AccessAlert::Patients`] or a file for
+    /// [`AccessAlert::Downloads`]. Something already read within the window is not counted again.
+    pub fn count(&self, actor: &str, measure: AccessAlert, key: &str, alert_at: u32, limit: u32) -> AccessVerdict {
+        let now = self.clock.now();
+        let mut activity = self.activity.lock().unwrap();
+        // Forget actors who have been quiet for a whole window
+        activity.retain(|_, entry| entry.last_seen.is_some_and(|seen| seen + self.window > now));
+        let entry = activity.entry(actor.to_string()).or_default();
+        entry.last_seen = Some(now);
+        let seen = match measure {
+            AccessAlert::Downloads => &mut entry.files,
+            _ => &mut entry.patients,
+        };
+        seen.retain(|_, at| *at + self.window > now);
+        if !seen.contains_key(key) && seen.len() >= limit as usize {
+            return AccessVerdict::Block(seen.len());
+        }
+        seen.insert(key.to_string(), now);
+        let count = seen.len();
+        if count >= alert_at as usize && Self::first_alert(entry, measure, now, self.window) {
+            return AccessVerdict::Alert(count);
+        }
+        AccessVerdict::Allow
+    }
+
+    /// Whether an exempt read by `actor` should be alerted on: the first one in the window is
+    pub fn exempt(&self, actor: &str, reason: AccessAlert) -> bool {
+        let now = self.clock.now();
+        let mut activity = self.activity.lock().unwrap();
+        let entry = activity.entry(actor.to_string()).or_default();
+        entry.last_seen = Some(now);
+        Self::first_alert(entry, reason, now, self.window)
+    }
+
+    fn first_alert(entry: &mut Activity, reason: AccessAlert, now: DateTime<Utc>, window: Duration) -> bool {
+        if entry.alerted.get(&reason).is_some_and(|at| *at + window > now) {
+            return false;
+        }
+        entry.alerted.insert(reason, now);
+        true
+    }
+}
+
+// --- DataAccessMonitor ---
+pub struct DataAccessMonitor {
+    window: AccessWindow,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl DataAccessMonitor {
+    pub fn new(
+        patients: Arc<dyn PatientStore>,
+        email_service: Arc<EmailService>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self { window: AccessWindow::new(clock), patients, email_service, config, audit_log_service }
+    }
+
+    /// Count `actor_did` reading `patient_did`'s record, reached through `access`. Patients
+    /// reading their own record are not counted, and break-glass reads are only alerted on.
+    pub async fn record_read(&self, actor_did: &str, patient_did: &str, access: Access) -> Result<()> {
+        match access {
+            Access::Owner => Ok(()),
+            Access::Emergency => {
+                self.exempt(actor_did, AccessAlert::BreakGlass).await;
+                Ok(())
+            }
+            Access::Granted | Access::Guardian => {
+                let limits = self.config.dynamic.load();
+                let (alert_at, limit) = (limits.data_access_patients_alert_per_hour, limits.data_access_patients_per_hour);
+                let verdict = self.window.count(actor_did, AccessAlert::Patients, patient_did, alert_at, limit);
+                self.enforce(actor_did, AccessAlert::Patients, verdict, alert_at, limit).await
+            }
+        }
+    }
+
+    /// Count `actor_did` downloading the file `cid` of `patient_did`'s record, on top of the read
+    pub async fn record_download(&self, actor_did: &str, patient_did: &str, cid: &str, access: Access) -> Result<()> {
+        self.record_read(actor_did, patient_did, access).await?;
+        if !matches!(access, Access::Granted | Access::Guardian) {
+            return Ok(());
+        }
+        let limits = self.config.dynamic.load();
+        let (alert_at, limit) = (limits.data_access_downloads_alert_per_hour, limits.data_access_downloads_per_hour);
+        let verdict = self.window.count(actor_did, AccessAlert::Downloads, cid, alert_at, limit);
+        self.enforce(actor_did, AccessAlert::Downloads, verdict, alert_at, limit).await
+    }
+
+    /// An admin exported patient data; never refused
+    pub async fn record_admin_export(&self, admin_did: &str) {
+        self.exempt(admin_did, AccessAlert::AdminExport).await;
+    }
+
+    async fn enforce(&self, actor_did: &str, measure: AccessAlert, verdict: AccessVerdict, alert_at: u32, limit: u32) -> Result<()> {
+        match verdict {
+            AccessVerdict::Allow => Ok(()),
+            AccessVerdict::Alert(count) => {
+                self.alert(actor_did, measure, json!({ "count": count, "alert_threshold": alert_at, "limit": limit })).await;
+                Ok(())
+            }
+            AccessVerdict::Block(count) => {
+                tracing::warn!("Refused {} a read over the {} limit of {}", actor_did, measure.as_str(), limit);
+                let details = json!({ "actor": actor_did, "measure": measure.as_str(), "count": count, "limit": limit, "window_minutes": WINDOW_MINUTES, "suspicious": true });
+                self.audit_log_service.log(actor_did, "data_access_blocked", Some(details)).await;
+                let message = match measure {
+                    AccessAlert::Downloads => TOO_MANY_DOWNLOADS_MESSAGE,
+                    _ => TOO_MANY_RECORDS_MESSAGE,
+                };
+                Err(ServiceError::RateLimited(i18n::text(i18n::current(), message, &[])).into())
+            }
+        }
+    }
+
+    async fn exempt(&self, actor_did: &str, reason: AccessAlert) {
+        if self.window.exempt(actor_did, reason) {
+            self.alert(actor_did, reason, json!({ "exempt": true })).await;
+        }
+    }
+
+    /// Flag the actor in the audit log and email the admins
+    async fn alert(&self, actor_did: &str, reason: AccessAlert, mut details: serde_json::Value) {
+        tracing::warn!("Data-access alert for {}: {}", actor_did, reason.as_str());
+        details["actor"] = json!(actor_did);
+        details["reason"] = json!(reason.as_str());
+        details["window_minutes"] = json!(WINDOW_MINUTES);
+        details["suspicious"] = json!(true);
+        let count = details["count"].as_u64().unwrap_or(1) as usize;
+        self.audit_log_service.log(actor_did, "data_access_alert", Some(details)).await;
+
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let admin = match self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await {
+                Ok(Some(admin)) => admin,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::error!("Failed to look up admin {} for a data-access alert: {}", admin_did, e);
+                    continue;
+                }
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "actor_did": actor_did,
+                "activity": reason.describe(count),
+                "exempt": matches!(reason, AccessAlert::BreakGlass | AccessAlert::AdminExport),
+            });
+            self.email_service
+                .enqueue(&email.value, "Unusual access to patient records", "Data-access-alert.html", &context)
+                .await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("Data-access alert for {} but no admin has an email address", actor_did);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MovableClock(Mutex<DateTime<Utc>>);
+
+    impl Clock for MovableClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    impl MovableClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    fn window() -> (AccessWindow, Arc<MovableClock>) {
+        let clock = Arc::new(MovableClock(Mutex::new("2026-03-17T09:00:00Z".parse().unwrap())));
+        (AccessWindow::new(clock.clone()), clock)
+    }
+
+    #[test]
+    fn enumerating_patients_alerts_before_it_is_blocked() {
+        let (window, _) = window();
+        let verdicts: Vec<AccessVerdict> =
+            (0..7).map(|patient| window.count("practitioner", AccessAlert::Patients, &format!("patient-{}", patient), 3, 5)).collect();
+
+        assert_eq!(
+            verdicts,
+            [
+                AccessVerdict::Allow,
+                AccessVerdict::Allow,
+                AccessVerdict::Alert(3),
+                AccessVerdict::Allow,
+                AccessVerdict::Allow,
+                AccessVerdict::Block(5),
+                AccessVerdict::Block(5),
+            ]
+        );
+        // Patients already counted can still be read, and other actors have their own counts
+        assert_eq!(window.count("practitioner", AccessAlert::Patients, "patient-1", 3, 5), AccessVerdict::Allow);
+        assert_eq!(window.count("colleague", AccessAlert::Patients, "patient-6", 3, 5), AccessVerdict::Allow);
+        // Downloads are counted apart from patients
+        assert_eq!(window.count("practitioner", AccessAlert::Downloads, "Qm1", 3, 5), AccessVerdict::Allow);
+    }
+
+    #[test]
+    fn reads_leave_the_window_after_an_hour() {
+        let (window, clock) = window();
+        for patient in 0..5 {
+            window.count("practitioner", AccessAlert::Patients, &format!("patient-{}", patient), 3, 5);
+            clock.advance(Duration::minutes(10));
+        }
+        assert_eq!(window.count("practitioner", AccessAlert::Patients, "patient-5", 3, 5), AccessVerdict::Block(5));
+
+        // The first read was an hour ago, so it no longer counts; the alert went out 40 minutes ago
+        clock.advance(Duration::minutes(10));
+        assert_eq!(window.count("practitioner", AccessAlert::Patients, "patient-5", 3, 5), AccessVerdict::Allow);
+        // Once the alert is an hour old, the next read that keeps the count at the threshold alerts again
+        clock.advance(Duration::minutes(21));
+        assert_eq!(window.count("practitioner", AccessAlert::Patients, "patient-6", 3, 5), AccessVerdict::Alert(4));
+    }
+
+    #[test]
+    fn exempt_reads_alert_once_an_hour() {
+        let (window, clock) = window();
+        assert!(window.exempt("responder", AccessAlert::BreakGlass));
+        assert!(!window.exempt("responder", AccessAlert::BreakGlass));
+        assert!(window.exempt("responder", AccessAlert::AdminExport));
+        clock.advance(Duration::minutes(WINDOW_MINUTES));
+        assert!(window.exempt("responder", AccessAlert::BreakGlass));
+    }
+}
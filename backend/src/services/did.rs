@@ -1,5 +1,9 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hedera::FileId;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use crate::deadline;
 use crate::services::hedera::HederaClient;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +73,51 @@ impl DidManager {
 
         Ok(final_did)
     }
+
+    /// Delete the DID document file backing a `did:hedera` DID.
+    ///
+    /// Used to clean up a DID that was created for a registration that then lost a
+    /// race, so we don't leave orphaned files (and their rent) on Hedera.
+    pub async fn delete_did(hedera_client: &HederaClient, did: &str) -> Result<()> {
+        let file_id = did
+            .rsplit(':')
+            .next()
+            .ok_or_else(|| anyhow!("Malformed DID: {}", did))?
+            .parse::<FileId>()?;
+        hedera_client.delete_file(file_id).await
+    }
+}
+
+/// Creates and removes DIDs on behalf of the auth flows.
+///
+/// [`HederaDidRegistry`] publishes DID documents to the Hedera File Service;
+/// tests use the in-memory registry from `services::fakes`.
+#[async_trait]
+pub trait DidRegistry: Send + Sync {
+    async fn create_did(&self, public_key_hex: &str) -> Result<String>;
+    async fn delete_did(&self, did: &str) -> Result<()>;
+}
+
+pub struct HederaDidRegistry {
+    hedera_client: Arc<HederaClient>,
+    network: String,
+}
+
+impl HederaDidRegistry {
+    pub fn new(hedera_client: Arc<HederaClient>, network: &str) -> Self {
+        Self { hedera_client, network: network.to_string() }
+    }
+}
+
+#[async_trait]
+impl DidRegistry for HederaDidRegistry {
+    async fn create_did(&self, public_key_hex: &str) -> Result<String> {
+        deadline::bound(DidManager::create_did(&self.hedera_client, public_key_hex, &self.network)).await
+    }
+
+    async fn delete_did(&self, did: &str) -> Result<()> {
+        deadline::bound(DidManager::delete_did(&self.hedera_client, did)).await
+    }
 }
 
 #[cfg(test)]
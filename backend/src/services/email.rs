@@ -1,5 +1,7 @@
 use crate::config::Config;
-use lazy_static::lazy_static;
+use crate::i18n;
+use crate::models::{Locale, OutboxEmail, OutboxStats};
+use crate::store::EmailOutboxStore;
 use lettre::{
     message::{header, SinglePart},
     transport::smtp::authentication::Credentials,
@@ -10,18 +12,86 @@ use std::sync::Arc;
 use tera::{Context, Tera};
 use thiserror::Error;
 
-lazy_static! {
-    pub static ref TEMPLATES: Tera = {
-        let mut tera = match Tera::new("backend/src/templates/**/*.html") {
-            Ok(t) => t,
-            Err(e) => {
-                println!("Parsing error(s): {}", e);
-                ::std::process::exit(1);
-            }
-        };
-        tera.autoescape_on(vec![".html"]);
-        tera
+/// Templates compiled into the binary, so emails work wherever the executable is deployed
+const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    ("Verification-email.html", include_str!("../templates/Verification-email.html")),
+    ("Welcome-email.html", include_str!("../templates/Welcome-email.html")),
+    ("Credential-issued.html", include_str!("../templates/Credential-issued.html")),
+    ("Access-granted.html", include_str!("../templates/Access-granted.html")),
+    ("Record-exported.html", include_str!("../templates/Record-exported.html")),
+    ("Appointment-reminder.html", include_str!("../templates/Appointment-reminder.html")),
+    ("Emergency-access.html", include_str!("../templates/Emergency-access.html")),
+    ("Break-glass-alert.html", include_str!("../templates/Break-glass-alert.html")),
+    ("Referral-updated.html", include_str!("../templates/Referral-updated.html")),
+    ("Prescription-cancelled.html", include_str!("../templates/Prescription-cancelled.html")),
+    ("Backup-code-used.html", include_str!("../templates/Backup-code-used.html")),
+    ("New-sign-in.html", include_str!("../templates/New-sign-in.html")),
+    ("Encounter-finalized.html", include_str!("../templates/Encounter-finalized.html")),
+    ("Export-ready.html", include_str!("../templates/Export-ready.html")),
+    ("Hedera-budget-alert.html", include_str!("../templates/Hedera-budget-alert.html")),
+    ("Hedera-balance-alert.html", include_str!("../templates/Hedera-balance-alert.html")),
+    ("Sms-budget-alert.html", include_str!("../templates/Sms-budget-alert.html")),
+    ("License-expiring.html", include_str!("../templates/License-expiring.html")),
+    ("License-expired.html", include_str!("../templates/License-expired.html")),
+    ("Data-access-alert.html", include_str!("../templates/Data-access-alert.html")),
+    ("Audit-anchoring-alert.html", include_str!("../templates/Audit-anchoring-alert.html")),
+    // Translations live under their locale; admin and practitioner alerts are English only
+    ("sw/Verification-email.html", include_str!("../templates/sw/Verification-email.html")),
+    ("sw/Welcome-email.html", include_str!("../templates/sw/Welcome-email.html")),
+    ("sw/Credential-issued.html", include_str!("../templates/sw/Credential-issued.html")),
+    ("sw/Access-granted.html", include_str!("../templates/sw/Access-granted.html")),
+    ("sw/Record-exported.html", include_str!("../templates/sw/Record-exported.html")),
+    ("sw/Appointment-reminder.html", include_str!("../templates/sw/Appointment-reminder.html")),
+    ("sw/Emergency-access.html", include_str!("../templates/sw/Emergency-access.html")),
+    ("sw/Referral-updated.html", include_str!("../templates/sw/Referral-updated.html")),
+    ("sw/Prescription-cancelled.html", include_str!("../templates/sw/Prescription-cancelled.html")),
+    ("sw/Backup-code-used.html", include_str!("../templates/sw/Backup-code-used.html")),
+    ("sw/New-sign-in.html", include_str!("../templates/sw/New-sign-in.html")),
+    ("sw/Encounter-finalized.html", include_str!("../templates/sw/Encounter-finalized.html")),
+    ("sw/Export-ready.html", include_str!("../templates/sw/Export-ready.html")),
+];
+
+/// The name of `template_name` in `locale`: English templates sit at the top level,
+/// translations in a directory named after their locale
+pub fn localized_template(locale: Locale, template_name: &str) -> String {
+    match locale {
+        Locale::En => template_name.to_string(),
+        locale => format!("{}/{}", locale.as_str(), template_name),
+    }
+}
+
+/// `template_name`, or its English original when it is a translation that does not exist
+fn resolve_template<'a>(templates: &Tera, template_name: &'a str) -> &'a str {
+    if templates.get_template_names().any(|name| name == template_name) {
+        return template_name;
+    }
+    let Some((locale, english)) = template_name.split_once('/').and_then(|(prefix, english)| Some((Locale::from_language_tag(prefix)?, english))) else {
+        return template_name;
+    };
+    i18n::record_missing(locale, &format!("template:{}", english));
+    english
+}
+
+/// The embedded templates, with any same-named files in `override_dir` taking their place
+fn load_templates(override_dir: Option<&str>) -> Result<Tera, tera::Error> {
+    let mut tera = match override_dir {
+        Some(dir) if !std::path::Path::new(dir).is_dir() => {
+            return Err(tera::Error::msg(format!("email template directory '{}' does not exist", dir)));
+        }
+        Some(dir) => Tera::new(&format!("{}/**/*.html", dir.trim_end_matches('/')))?,
+        None => Tera::default(),
     };
+    let mut embedded = Tera::default();
+    embedded.add_raw_templates(EMBEDDED_TEMPLATES.iter().copied())?;
+    // `extend` keeps templates already present, so the override directory wins
+    tera.extend(&embedded)?;
+    tera.autoescape_on(vec![".html"]);
+    Ok(tera)
+}
+
+fn render<T: Serialize>(templates: &Tera, template_name: &str, context: &T) -> Result<String, EmailError> {
+    let context = Context::from_serialize(context)?;
+    Ok(templates.render(resolve_template(templates, template_name), &context)?)
 }
 
 #[derive(Error, Debug)]
@@ -34,6 +104,18 @@ pub enum EmailError {
     EmailBuild(#[from] lettre::error::Error),
     #[error("SMTP transport error: {0}")]
     SmtpTransport(#[from] lettre::transport::smtp::Error),
+    #[error("email is not configured on this server")]
+    NotConfigured,
+}
+
+impl EmailError {
+    /// Whether sending the same message again could succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::SmtpTransport(e) => !e.is_permanent(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -50,25 +132,38 @@ pub struct VerificationEmailContext {
 #[derive(Clone)]
 pub struct EmailService {
     config: Arc<Config>,
+    templates: Arc<Tera>,
+    outbox: Arc<dyn EmailOutboxStore>,
 }
 
 impl EmailService {
-    pub fn new(config: Arc<Config>) -> Self {
-        Self { config }
+    pub fn new(config: Arc<Config>, outbox: Arc<dyn EmailOutboxStore>) -> Result<Self, EmailError> {
+        let templates = Arc::new(load_templates(config.email_template_dir.as_deref())?);
+        Ok(Self { config, templates, outbox })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.smtp.is_some()
     }
 
-    async fn send_mail<T: Serialize>(
+    pub fn render<T: Serialize>(&self, template_name: &str, context: &T) -> Result<String, EmailError> {
+        render(&self.templates, template_name, context)
+    }
+
+    /// Render `template_name` with `context` and send it, waiting for the SMTP server to accept it
+    #[tracing::instrument(name = "smtp.send", skip_all, fields(otel.kind = "client", email.template = template_name))]
+    pub async fn send_mail<T: Serialize>(
         &self,
         to_email: &str,
         subject: &str,
         template_name: &str,
         context: &T,
     ) -> Result<(), EmailError> {
-        let context = Context::from_serialize(context)?;
-        let html_template = TEMPLATES.render(template_name, &context)?;
+        let smtp = self.config.smtp.as_ref().ok_or(EmailError::NotConfigured)?;
+        let html_template = self.render(template_name, context)?;
 
         let email = Message::builder()
-            .from(self.config.smtp.from_email.parse()?)
+            .from(smtp.from_email.parse()?)
             .to(to_email.parse()?)
             .subject(subject)
             .header(header::ContentType::TEXT_HTML)
@@ -79,13 +174,13 @@ impl EmailService {
             )?;
 
         let creds = Credentials::new(
-            self.config.smtp.username.clone(),
-            self.config.smtp.password.clone(),
+            smtp.username.clone(),
+            smtp.password.expose_secret().to_string(),
         );
 
-        let mailer = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.config.smtp.server)?
+        let mailer = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.server)?
             .credentials(creds)
-            .port(self.config.smtp.port)
+            .port(smtp.port)
             .timeout(Some(std::time::Duration::from_secs(30)))
             .build();
 
@@ -94,14 +189,52 @@ impl EmailService {
         Ok(())
     }
 
-    pub fn send_verification_email(
+    /// Queue an email as a background job, which is retried until SMTP accepts it
+    pub async fn enqueue<T: Serialize>(&self, to_email: &str, subject: &str, template_name: &str, context: &T) {
+        if !self.is_enabled() {
+            tracing::warn!("SMTP is not configured; skipping {}", template_name);
+            return;
+        }
+        let context = match serde_json::to_value(context) {
+            Ok(context) => context,
+            Err(e) => {
+                tracing::error!("Failed to serialize context for {}: {}", template_name, e);
+                return;
+            }
+        };
+        let email = OutboxEmail {
+            recipient: to_email.to_string(),
+            subject: subject.to_string(),
+            template: template_name.to_string(),
+            context,
+        };
+        match self.outbox.enqueue_email(&email).await {
+            Ok(id) => tracing::info!("Queued {} as {}", template_name, id),
+            Err(e) => tracing::error!("Failed to queue {}: {}", template_name, e),
+        }
+    }
+
+    /// Requeue an outbox email for immediate delivery, unless it is being sent right now
+    pub async fn retry_outbox_email(&self, id: &str) -> anyhow::Result<()> {
+        let id = bson::oid::ObjectId::parse_str(id).map_err(|_| anyhow::anyhow!("Invalid email id"))?;
+        if !self.outbox.retry_email(id).await? {
+            return Err(anyhow::anyhow!("Email not found or being sent"));
+        }
+        tracing::info!("Email {} requeued for delivery", id);
+        Ok(())
+    }
+
+    pub async fn outbox_stats(&self) -> anyhow::Result<OutboxStats> {
+        self.outbox.count_emails_by_status().await
+    }
+
+    pub async fn send_verification_email(
         &self,
         to_email: &str,
         username: &str,
         token: &str,
+        locale: Locale,
     ) {
-        let subject = "Email Verification";
-        let template_name = "Verification-email.html";
         // Point verification link to FlutterFlow app
         // FlutterFlow will handle the UI and call the backend API
         let verification_link = format!("{}/verify-email?token={}", self.config.frontend_base_url.trim_end_matches('/'), token);
@@ -111,37 +244,143 @@ impl EmailService {
             verification_link,
         };
 
-        let email_service = self.clone();
-        let to_email = to_email.to_string();
-        let subject = subject.to_string();
-        let template_name = template_name.to_string();
-        tokio::spawn(async move {
-            tracing::info!("Sending verification email to {}", to_email);
-            if let Err(e) = email_service.send_mail(&to_email, &subject, &template_name, &context).await {
-                tracing::error!("Failed to send verification email to {}: {}", to_email, e);
-            }
-        });
+        let subject = i18n::text(locale, "email.verification.subject", &[]);
+        self.enqueue(to_email, &subject, &localized_template(locale, "Verification-email.html"), &context).await;
     }
 
-    pub fn send_welcome_email(
+    pub async fn send_welcome_email(
         &self,
         to_email: &str,
         username: &str,
+        locale: Locale,
     ) {
-        let subject = "Welcome to Our Application";
-        let template_name = "Welcome-email.html";
-
         let context = WelcomeEmailContext {
             username: username.to_string(),
         };
 
-        let email_service = self.clone();
-        let to_email = to_email.to_string();
-        tokio::spawn(async move {
-            tracing::info!("Sending welcome email to {}", to_email);
-            if let Err(e) = email_service.send_mail(&to_email, subject, template_name, &context).await {
-                tracing::error!("Failed to send welcome email to {}: {}", to_email, e);
-            }
+        let subject = i18n::text(locale, "email.welcome.subject", &[]);
+        self.enqueue(to_email, &subject, &localized_template(locale, "Welcome-email.html"), &context).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> Tera {
+        load_templates(None).unwrap()
+    }
+
+    #[test]
+    fn renders_verification_email() {
+        let context = VerificationEmailContext {
+            username: "Amina".to_string(),
+            verification_link: "http://localhost:3000/verify-email?token=abc".to_string(),
+        };
+
+        let html = render(&templates(), "Verification-email.html", &context).unwrap();
+
+        assert!(html.contains("Amina"));
+        assert!(html.contains("verify-email?token=abc"));
+    }
+
+    #[test]
+    fn renders_welcome_email_with_escaping() {
+        let context = WelcomeEmailContext { username: "<b>Amina</b>".to_string() };
+
+        let html = render(&templates(), "Welcome-email.html", &context).unwrap();
+
+        assert!(html.contains("&lt;b&gt;Amina&lt;&#x2F;b&gt;"));
+    }
+
+    #[test]
+    fn renders_appointment_reminder_with_optional_location() {
+        let context = serde_json::json!({ "username": "Amina", "practitioner_name": "Dr. Otieno", "when": "Mon 19 Oct 2026, 09:00 EAT", "location": "" });
+
+        let html = render(&templates(), "Appointment-reminder.html", &context).unwrap();
+
+        assert!(html.contains("<strong>Dr. Otieno</strong> on <strong>Mon 19 Oct 2026, 09:00 EAT</strong>."));
+    }
+
+    #[test]
+    fn every_template_renders_in_every_locale() {
+        let templates = templates();
+        let context = serde_json::json!({
+            "username": "Amina",
+            "verification_link": "link",
+            "credential_type": "Vaccination",
+            "grantee_did": "did:hedera:testnet:0.0.2",
+            "practitioner_name": "Dr. Otieno",
+            "practitioner_did": "did:hedera:testnet:0.0.3",
+            "when": "Mon 19 Oct 2026, 09:00 EAT",
+            "location": "Nairobi",
+            "justification": "Unconscious on arrival",
+            "medication": "Amoxicillin",
+            "referral_id": "r1",
+            "status": "accepted",
+            "remaining": 3,
+            "device": "Chrome on Android",
+            "revoke_link": "revoke",
+            "review_hours": 24,
+            "events": [],
+            "month": "October 2026",
+            "spent_hbar": 80.0,
+            "budget_hbar": 100.0,
+            "percent": 80,
         });
+        for locale in Locale::ALL {
+            for (name, _) in EMBEDDED_TEMPLATES.iter().filter(|(name, _)| !name.contains('/')) {
+                let html = render(&templates, &localized_template(locale, name), &context)
+                    .unwrap_or_else(|e| panic!("{} in {}: {:?}", name, locale.as_str(), e));
+                assert!(html.contains("Amina"), "{} in {}", name, locale.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn translated_templates_are_used_and_missing_ones_fall_back_to_english() {
+        let context = VerificationEmailContext { username: "Amina".to_string(), verification_link: "link".to_string() };
+
+        let html = render(&templates(), &localized_template(Locale::Sw, "Verification-email.html"), &context).unwrap();
+        assert!(html.contains("lang=\"sw\""));
+        assert!(html.contains("Thibitisha Barua Pepe"));
+
+        // Admin alerts have no translation
+        let alert = serde_json::json!({ "username": "Amina", "review_hours": 24, "events": [] });
+        let html = render(&templates(), &localized_template(Locale::Sw, "Break-glass-alert.html"), &alert).unwrap();
+        assert!(html.contains("waiting for review"));
+        assert!(i18n::missing_translations().iter().any(|missing| missing.locale == Locale::Sw && missing.key == "template:Break-glass-alert.html"));
+    }
+
+    #[test]
+    fn unknown_template_is_an_error_not_an_exit() {
+        let err = render(&templates(), "Missing.html", &WelcomeEmailContext { username: "x".to_string() }).unwrap_err();
+
+        assert!(matches!(err, EmailError::TemplateError(_)));
+    }
+
+    #[test]
+    fn missing_override_directory_fails_at_construction() {
+        assert!(load_templates(Some("/nonexistent/templates")).is_err());
+    }
+
+    #[test]
+    fn override_directory_replaces_templates_by_name() {
+        let dir = std::env::temp_dir().join(format!("email-templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Welcome-email.html"), "Custom welcome, {{username}}").unwrap();
+
+        let templates = load_templates(Some(&dir.to_string_lossy())).unwrap();
+        let welcome = render(&templates, "Welcome-email.html", &WelcomeEmailContext { username: "Amina".to_string() }).unwrap();
+        let verification = render(
+            &templates,
+            "Verification-email.html",
+            &VerificationEmailContext { username: "Amina".to_string(), verification_link: "link".to_string() },
+        )
+        .unwrap();
+
+        assert_eq!(welcome, "Custom welcome, Amina");
+        assert!(verification.contains("link"));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,99 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::jobs::{JobError, JobHandler};
+use crate::models::{Job, OutboxEmail, SEND_EMAIL_JOB};
+use crate::services::email::EmailService;
+
+/// Delivers queued emails; transient SMTP failures are retried by the job queue with backoff
+pub struct SendEmailHandler {
+    email_service: Arc<EmailService>,
+}
+
+impl SendEmailHandler {
+    pub fn new(email_service: Arc<EmailService>) -> Self {
+        Self { email_service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for SendEmailHandler {
+    fn job_type(&self) -> &'static str {
+        SEND_EMAIL_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        let email: OutboxEmail = serde_json::from_value(job.payload.clone())
+            .map_err(|e| JobError::Permanent(anyhow!("Malformed email job: {}", e)))?;
+        match self
+            .email_service
+            .send_mail(&email.recipient, &email.subject, &email.template, &email.context)
+            .await
+        {
+            Ok(()) => {
+                tracing::info!("Sent {} ({:?})", email.template, job.id);
+                Ok(())
+            }
+            Err(e) if e.is_transient() => Err(JobError::Transient(e.into())),
+            Err(e) => Err(JobError::Permanent(e.into())),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::{Config, SmtpConfig};
+    use crate::store::MockEmailOutboxStore;
+    use chrono::Utc;
+
+    fn job(payload: serde_json::Value) -> Job {
+        Job::new(SEND_EMAIL_JOB, payload, Utc::now())
+    }
+
+    fn queued() -> Job {
+        job(serde_json::json!({
+            "recipient": "amina@example.com",
+            "subject": "Welcome",
+            "template": "Welcome-email.html",
+            "context": { "username": "Amina" },
+        }))
+    }
+
+    fn handler(smtp: Option<SmtpConfig>) -> SendEmailHandler {
+        let config = Arc::new(Config { smtp, ..Default::default() });
+        SendEmailHandler::new(Arc::new(EmailService::new(config, Arc::new(MockEmailOutboxStore::new())).unwrap()))
+    }
+
+    // Nothing listens on port 1, so the connection is refused: a transient failure
+    fn unreachable_smtp() -> Option<SmtpConfig> {
+        Some(SmtpConfig {
+            server: "127.0.0.1".to_string(),
+            port: 1,
+            username: "user".to_string(),
+            password: "password".into(),
+            from_email: "noreply@example.com".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_a_transient_failure() {
+        let result = handler(unreachable_smtp()).run(&queued()).await;
+        assert!(matches!(result, Err(JobError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn missing_configuration_is_not_retried() {
+        match handler(None).run(&queued()).await {
+            Err(JobError::Permanent(e)) => assert_eq!(e.to_string(), "email is not configured on this server"),
+            other => panic!("expected a permanent failure, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_payload_is_not_retried() {
+        let result = handler(unreachable_smtp()).run(&job(serde_json::json!({ "recipient": 42 }))).await;
+        assert!(matches!(result, Err(JobError::Permanent(_))));
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+/// Errors that services raise for expected failure cases.
+///
+/// Services keep returning `anyhow::Result`; a `ServiceError` inside the error
+/// chain tells the API layer which HTTP status to respond with.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("{0}")]
+    Conflict(String),
+    /// What the request names does not exist, where answering with an empty result would not do
+    #[error("{0}")]
+    NotFound(String),
+    /// The caller is authenticated but not allowed to act on this resource
+    #[error("{0}")]
+    Forbidden(String),
+    /// The action needs a high-assurance token from the step-up flow, which the caller's is not
+    #[error("this action needs a step-up token")]
+    StepUpRequired,
+    /// The feature depends on an integration this deployment has not configured
+    #[error("{0}")]
+    NotConfigured(String),
+    /// The client did not finish sending its request in time
+    #[error("request body was not received in time")]
+    RequestTimeout,
+    /// The request was accepted but handling it took too long
+    #[error("the server took too long to respond")]
+    Timeout,
+    #[error("request body is too large")]
+    PayloadTooLarge,
+    /// The uploaded file's content is not one of the accepted types, whatever it was declared as
+    #[error("only PDF, JPEG, PNG and DICOM files can be attached")]
+    UnsupportedMediaType,
+    /// An uploaded patient photo is not a JPEG or PNG of an accepted size
+    #[error("{0}")]
+    InvalidPhoto(String),
+    /// A practitioner's license details cannot be accepted, such as an unreadable expiry date
+    #[error("{0}")]
+    InvalidLicense(String),
+    /// The malware scanner found something in an upload
+    #[error("the file was rejected by the malware scan")]
+    MalwareDetected,
+    /// The malware scanner could not be reached in time and uploads are not let through unscanned
+    #[error("the file could not be scanned for malware")]
+    ScanUnavailable,
+    /// Hedera refused a transaction because the operator account cannot pay for it; ledger
+    /// calls fail fast with this until a balance check finds the account topped up
+    #[error("the Hedera operator account has run out of hbar")]
+    HederaBalanceExhausted,
+    /// The caller made too many attempts and has to wait before trying again
+    #[error("{0}")]
+    RateLimited(String),
+    /// A phone number was texted as many sign-in codes as its window allows; `retry_at` is when
+    /// the window ends
+    #[error("{message}")]
+    SmsLimited { message: String, retry_at: DateTime<Utc> },
+    /// The day's SMS budget is used up, so phone sign-in is off until midnight UTC
+    #[error("phone sign-in is unavailable until tomorrow")]
+    SmsBudgetExhausted,
+    /// The CAPTCHA sent with the request was missing, wrong or could not be checked
+    #[error("{0}")]
+    CaptchaFailed(String),
+    /// A DID in the request names no registered patient or practitioner, or one that may not take
+    /// part; `code` says which, such as `unknown_patient`
+    #[error("{message}")]
+    InvalidReference { code: &'static str, message: String },
+    /// The `Idempotency-Key` was already used for a request with a different method, path or body
+    #[error("this Idempotency-Key was already used for a different request")]
+    IdempotencyKeyReused,
+    /// A reloaded configuration did not pass validation, so the running one was kept
+    #[error("{0}")]
+    InvalidConfig(String),
+}
+
+impl ServiceError {
+    /// Machine-readable code sent alongside the message, for errors the app handles specially
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Self::CaptchaFailed(_) => Some("captcha_failed"),
+            Self::IdempotencyKeyReused => Some("idempotency_key_reused"),
+            Self::MalwareDetected => Some("malware_detected"),
+            Self::InvalidPhoto(_) => Some("invalid_photo"),
+            Self::InvalidLicense(_) => Some("invalid_license"),
+            Self::Timeout => Some("timeout"),
+            Self::HederaBalanceExhausted => Some("hedera_balance_exhausted"),
+            Self::SmsLimited { .. } => Some("sms_limited"),
+            Self::SmsBudgetExhausted => Some("sms_budget_exhausted"),
+            Self::StepUpRequired => Some("step_up_required"),
+            Self::InvalidReference { code, .. } => Some(code),
+            Self::InvalidConfig(_) => Some("invalid_config"),
+            _ => None,
+        }
+    }
+
+    /// Message key of errors whose text is fixed, so it can be sent in the caller's language
+    pub fn message_key(&self) -> Option<&'static str> {
+        match self {
+            Self::RequestTimeout => Some("error.request_timeout"),
+            Self::Timeout => Some("error.timeout"),
+            Self::StepUpRequired => Some("error.step_up_required"),
+            Self::PayloadTooLarge => Some("error.payload_too_large"),
+            Self::IdempotencyKeyReused => Some("error.idempotency_key_reused"),
+            Self::UnsupportedMediaType => Some("error.unsupported_media_type"),
+            Self::MalwareDetected => Some("error.malware_detected"),
+            Self::ScanUnavailable => Some("error.scan_unavailable"),
+            Self::HederaBalanceExhausted => Some("error.hedera_balance_exhausted"),
+            Self::SmsBudgetExhausted => Some("error.sms_budget_exhausted"),
+            _ => None,
+        }
+    }
+}
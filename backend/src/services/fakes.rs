@@ -0,0 +1,373 @@
+//! In-memory stand-ins for IPFS, Hedera and SMS verification, so services can be exercised offline.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::auditing::{AnchorLookup, ContractCallLookup};
+use crate::services::did::DidRegistry;
+use crate::services::hedera::LedgerAnchor;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::phone_verification::{CodeCheck, PhoneVerifier};
+use crate::utils::chunked::ByteStream;
+
+/// HashMap-backed object storage. Content ids are derived from the content hash,
+/// so identical uploads map to the same id like they do on IPFS.
+#[derive(Default)]
+pub struct InMemoryObjectStorage {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+    pins: Mutex<Vec<String>>,
+    unpins: Mutex<Vec<String>>,
+    uploads: AtomicUsize,
+    fail_uploads: AtomicBool,
+    delay: std::time::Duration,
+}
+
+impl InMemoryObjectStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Storage whose uploads always fail, for exercising error paths
+    pub fn failing() -> Self {
+        let storage = Self::default();
+        storage.fail_uploads.store(true, Ordering::SeqCst);
+        storage
+    }
+
+    /// Storage that takes `delay` to answer each upload, like an IPFS node under load
+    pub fn with_delay(self, delay: std::time::Duration) -> Self {
+        Self { delay, ..self }
+    }
+
+    /// Number of `add_file` and `add_stream` calls, including failed ones
+    pub fn upload_count(&self) -> usize {
+        self.uploads.load(Ordering::SeqCst)
+    }
+
+    pub fn pinned(&self) -> Vec<String> {
+        self.pins.lock().unwrap().clone()
+    }
+
+    pub fn unpinned(&self) -> Vec<String> {
+        self.unpins.lock().unwrap().clone()
+    }
+
+    /// Number of objects stored; uploads dropped while delayed store nothing
+    pub fn stored_count(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.objects.lock().unwrap().contains_key(hash)
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for InMemoryObjectStorage {
+    async fn add_file(&self, content: &[u8], _filename: Option<&str>) -> Result<String> {
+        self.uploads.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        if self.fail_uploads.load(Ordering::SeqCst) {
+            return Err(anyhow!("IPFS add failed: 503 Service Unavailable"));
+        }
+        let hash = format!("fake-{:x}", Sha256::digest(content));
+        self.objects.lock().unwrap().insert(hash.clone(), content.to_vec());
+        Ok(hash)
+    }
+
+    async fn add_stream(&self, mut content: ByteStream, length: u64, filename: Option<&str>) -> Result<String> {
+        let mut stored = Vec::with_capacity(length as usize);
+        while let Some(chunk) = content.next().await {
+            stored.extend_from_slice(&chunk?);
+        }
+        if stored.len() as u64 != length {
+            return Err(anyhow!("IPFS add failed: the stream held {} bytes, not {}", stored.len(), length));
+        }
+        self.add_file(&stored, filename).await
+    }
+
+    async fn get_file(&self, hash: &str) -> Result<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| anyhow!("IPFS get failed: 404 Not Found"))
+    }
+
+    /// Yields the range in small chunks, like a network response would
+    async fn get_range(&self, hash: &str, offset: u64, length: Option<u64>) -> Result<ByteStream> {
+        let object = self.get_file(hash).await?;
+        let start = (offset as usize).min(object.len());
+        let end = length.map_or(object.len(), |length| (start + length as usize).min(object.len()));
+        let chunks: Vec<Result<Bytes>> = object[start..end].chunks(4096).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        Ok(stream::iter(chunks).boxed())
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<Vec<String>> {
+        if !self.contains(hash) {
+            return Err(anyhow!("IPFS pin add failed: 404 Not Found"));
+        }
+        self.pins.lock().unwrap().push(hash.to_string());
+        Ok(vec![hash.to_string()])
+    }
+
+    /// Uploads count as pinned, as `ipfs add` pins by default
+    async fn pin_rm(&self, hash: &str) -> Result<Vec<String>> {
+        if !self.contains(hash) {
+            return Err(anyhow!("IPFS pin rm failed: 500 Internal Server Error"));
+        }
+        self.pins.lock().unwrap().retain(|pinned| pinned != hash);
+        self.unpins.lock().unwrap().push(hash.to_string());
+        Ok(vec![hash.to_string()])
+    }
+
+    async fn is_pinned(&self, hash: &str) -> Result<bool> {
+        let explicitly_pinned = self.pins.lock().unwrap().iter().any(|pinned| pinned == hash);
+        let unpinned = self.unpins.lock().unwrap().iter().any(|unpinned| unpinned == hash);
+        Ok(self.contains(hash) && (explicitly_pinned || !unpinned))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCredential {
+    pub subject_did: String,
+    pub credential_type: String,
+    pub ipfs_hash: String,
+    pub expires_at: Option<u64>,
+    pub metadata: String,
+    pub transaction_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedGrant {
+    pub grant_id: String,
+    pub patient_did: String,
+    pub grantee_did: String,
+    pub expires_at: Option<u64>,
+    pub transaction_id: String,
+}
+
+/// Ledger that records every call instead of submitting transactions. It doubles as the
+/// mirror node, answering lookups from the batches, credentials and grants it recorded.
+#[derive(Default)]
+pub struct RecordingLedgerAnchor {
+    anchored_batches: Mutex<Vec<([u8; 32], u64, String)>>,
+    credentials: Mutex<Vec<RecordedCredential>>,
+    credential_batches: Mutex<Vec<([u8; 32], u64, String)>>,
+    grants: Mutex<Vec<RecordedGrant>>,
+    next_transaction: AtomicUsize,
+    reject_next_anchor: AtomicBool,
+    lose_next_anchor_response: AtomicBool,
+    lose_next_record_response: AtomicBool,
+}
+
+impl RecordingLedgerAnchor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn anchored_batches(&self) -> Vec<([u8; 32], u64)> {
+        self.anchored_batches.lock().unwrap().iter().map(|(root, size, _)| (*root, *size)).collect()
+    }
+
+    /// Fail the next anchoring call without submitting anything
+    pub fn reject_next_anchor(&self) {
+        self.reject_next_anchor.store(true, Ordering::SeqCst);
+    }
+
+    /// Anchor the next batch but fail the call, like a timeout after the transaction reached consensus
+    pub fn lose_next_anchor_response(&self) {
+        self.lose_next_anchor_response.store(true, Ordering::SeqCst);
+    }
+
+    /// Store the next credential or record the next grant but fail the call, like a timeout
+    /// after the transaction reached consensus
+    pub fn lose_next_record_response(&self) {
+        self.lose_next_record_response.store(true, Ordering::SeqCst);
+    }
+
+    pub fn credentials(&self) -> Vec<RecordedCredential> {
+        self.credentials.lock().unwrap().clone()
+    }
+
+    pub fn credential_batches(&self) -> Vec<([u8; 32], u64)> {
+        self.credential_batches.lock().unwrap().iter().map(|(root, size, _)| (*root, *size)).collect()
+    }
+
+    pub fn grants(&self) -> Vec<RecordedGrant> {
+        self.grants.lock().unwrap().clone()
+    }
+
+    fn respond(&self, transaction_id: String) -> Result<String> {
+        if self.lose_next_record_response.swap(false, Ordering::SeqCst) {
+            return Err(anyhow!("Timed out waiting for the receipt of {}", transaction_id));
+        }
+        Ok(transaction_id)
+    }
+
+    fn transaction_id(&self) -> String {
+        let sequence = self.next_transaction.fetch_add(1, Ordering::SeqCst);
+        format!("0.0.2@{}.000000000", 1_700_000_000 + sequence)
+    }
+}
+
+#[async_trait]
+impl LedgerAnchor for RecordingLedgerAnchor {
+    async fn anchor_log_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String> {
+        if self.reject_next_anchor.swap(false, Ordering::SeqCst) {
+            return Err(anyhow!("INSUFFICIENT_PAYER_BALANCE"));
+        }
+        let transaction_id = self.transaction_id();
+        self.anchored_batches.lock().unwrap().push((root_hash, batch_size, transaction_id.clone()));
+        if self.lose_next_anchor_response.swap(false, Ordering::SeqCst) {
+            return Err(anyhow!("Timed out waiting for the receipt of {}", transaction_id));
+        }
+        Ok(transaction_id)
+    }
+
+    async fn store_credential(
+        &self,
+        subject_did: &str,
+        credential_type: &str,
+        ipfs_hash: &str,
+        expires_at: Option<u64>,
+        metadata: &str,
+    ) -> Result<String> {
+        let transaction_id = self.transaction_id();
+        self.credentials.lock().unwrap().push(RecordedCredential {
+            subject_did: subject_did.to_string(),
+            credential_type: credential_type.to_string(),
+            ipfs_hash: ipfs_hash.to_string(),
+            expires_at,
+            metadata: metadata.to_string(),
+            transaction_id: transaction_id.clone(),
+        });
+        self.respond(transaction_id)
+    }
+
+    async fn verify_credential(&self, credential_hash: &[u8]) -> Result<bool> {
+        let hash = String::from_utf8_lossy(credential_hash);
+        Ok(self.credentials.lock().unwrap().iter().any(|c| c.ipfs_hash == hash))
+    }
+
+    async fn store_credential_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String> {
+        let transaction_id = self.transaction_id();
+        self.credential_batches.lock().unwrap().push((root_hash, batch_size, transaction_id.clone()));
+        self.respond(transaction_id)
+    }
+
+    async fn verify_credential_batch(&self, root_hash: [u8; 32]) -> Result<bool> {
+        Ok(self.credential_batches.lock().unwrap().iter().any(|(root, _, _)| *root == root_hash))
+    }
+
+    async fn record_access_grant(&self, grant_id: &str, patient_did: &str, grantee_did: &str, expires_at: Option<u64>) -> Result<String> {
+        let transaction_id = self.transaction_id();
+        self.grants.lock().unwrap().push(RecordedGrant {
+            grant_id: grant_id.to_string(),
+            patient_did: patient_did.to_string(),
+            grantee_did: grantee_did.to_string(),
+            expires_at,
+            transaction_id: transaction_id.clone(),
+        });
+        self.respond(transaction_id)
+    }
+}
+
+#[async_trait]
+impl AnchorLookup for RecordingLedgerAnchor {
+    async fn find_anchor(&self, root: [u8; 32], _since: DateTime<Utc>) -> Result<Option<String>> {
+        let batches = self.anchored_batches.lock().unwrap();
+        Ok(batches.iter().find(|(anchored, _, _)| *anchored == root).map(|(_, _, transaction_id)| transaction_id.clone()))
+    }
+}
+
+#[async_trait]
+impl ContractCallLookup for RecordingLedgerAnchor {
+    /// Credentials are found by their IPFS hash, credential batches by their root, grants by their id
+    async fn find_call(&self, argument: &[u8], _since: DateTime<Utc>) -> Result<Option<String>> {
+        let batch = self.credential_batches.lock().unwrap().iter().find(|(root, _, _)| root[..] == *argument).map(|(_, _, transaction_id)| transaction_id.clone());
+        if batch.is_some() {
+            return Ok(batch);
+        }
+        let argument = String::from_utf8_lossy(argument);
+        let credential = self.credentials.lock().unwrap().iter().find(|c| c.ipfs_hash == argument).map(|c| c.transaction_id.clone());
+        let grant = || self.grants.lock().unwrap().iter().find(|g| g.grant_id == argument).map(|g| g.transaction_id.clone());
+        Ok(credential.or_else(grant))
+    }
+}
+
+/// DID registry that hands out sequential testnet DIDs without touching Hedera
+#[derive(Default)]
+pub struct InMemoryDidRegistry {
+    created: Mutex<Vec<String>>,
+    deleted: Mutex<Vec<String>>,
+}
+
+impl InMemoryDidRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn created(&self) -> Vec<String> {
+        self.created.lock().unwrap().clone()
+    }
+
+    pub fn deleted(&self) -> Vec<String> {
+        self.deleted.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DidRegistry for InMemoryDidRegistry {
+    async fn create_did(&self, _public_key_hex: &str) -> Result<String> {
+        let mut created = self.created.lock().unwrap();
+        let did = format!("did:hedera:testnet:0.0.{}", 1000 + created.len());
+        created.push(did.clone());
+        Ok(did)
+    }
+
+    async fn delete_did(&self, did: &str) -> Result<()> {
+        self.deleted.lock().unwrap().push(did.to_string());
+        Ok(())
+    }
+}
+
+/// Phone verifier that accepts a single fixed code and records who was sent one
+pub struct FakePhoneVerifier {
+    code: String,
+    started: Mutex<Vec<String>>,
+}
+
+impl FakePhoneVerifier {
+    pub fn new(code: &str) -> Self {
+        Self { code: code.to_string(), started: Mutex::new(Vec::new()) }
+    }
+
+    /// Phone numbers a code was sent to, in order
+    pub fn started(&self) -> Vec<String> {
+        self.started.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl PhoneVerifier for FakePhoneVerifier {
+    async fn start(&self, phone_number: &str) -> Result<()> {
+        self.started.lock().unwrap().push(phone_number.to_string());
+        Ok(())
+    }
+
+    async fn check(&self, phone_number: &str, code: &str) -> Result<CodeCheck> {
+        if !self.started.lock().unwrap().iter().any(|started| started == phone_number) {
+            return Ok(CodeCheck::Expired);
+        }
+        Ok(if code == self.code { CodeCheck::Approved } else { CodeCheck::Rejected })
+    }
+}
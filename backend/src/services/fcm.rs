@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::config::FcmConfig;
+
+const FCM_API_BASE_URL: &str = "https://fcm.googleapis.com";
+const MESSAGING_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+/// Lifetime of the signed assertion exchanged for an access token; Google allows at most an hour
+const ASSERTION_LIFETIME_SECONDS: i64 = 3600;
+/// Access tokens are replaced this long before Google says they expire
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum PushError {
+    /// The app was uninstalled or the token rotated; the device should be forgotten
+    #[error("device token is no longer valid: {0}")]
+    InvalidToken(String),
+    #[error("could not authenticate with FCM: {0}")]
+    Auth(String),
+    #[error("push provider error: {0}")]
+    Provider(String),
+}
+
+/// The fields of a Google service account key file that signing in needs
+#[derive(Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct AccessToken {
+    value: String,
+    refresh_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct SendResponse {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct FcmErrorResponse {
+    error: FcmErrorBody,
+}
+
+#[derive(Deserialize)]
+struct FcmErrorBody {
+    message: Option<String>,
+    status: Option<String>,
+    #[serde(default)]
+    details: Vec<FcmErrorDetail>,
+}
+
+#[derive(Deserialize)]
+struct FcmErrorDetail {
+    #[serde(rename = "errorCode")]
+    error_code: Option<String>,
+}
+
+/// Sends data-only messages through the FCM HTTP v1 API, signing in as a service account
+pub struct FcmClient {
+    http: Client,
+    base_url: String,
+    account: ServiceAccount,
+    key: EncodingKey,
+    access_token: Mutex<Option<AccessToken>>,
+}
+
+impl FcmClient {
+    pub fn from_config(config: &FcmConfig, http: Client) -> Result<Self> {
+        let credentials = std::fs::read_to_string(&config.credentials_path)
+            .with_context(|| format!("could not read FCM credentials from {}", config.credentials_path))?;
+        Self::from_service_account(&credentials, http)
+    }
+
+    /// `credentials` is the JSON of a service account key file
+    pub fn from_service_account(credentials: &str, http: Client) -> Result<Self> {
+        let account: ServiceAccount = serde_json::from_str(credentials).context("FCM credentials are not a service account key")?;
+        let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes()).context("FCM credentials have an invalid private key")?;
+        Ok(Self { http, base_url: FCM_API_BASE_URL.to_string(), account, key, access_token: Mutex::new(None) })
+    }
+
+    /// Point the client at a different API host (used by tests)
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Send `data` to one device and return the FCM message name.
+    ///
+    /// The message has no notification block, so the app decides what to show once it
+    /// wakes. Rate limiting (429) and server errors are retried with backoff, and a
+    /// rejected access token is replaced once; anything else is returned immediately.
+    #[tracing::instrument(name = "fcm.send", skip_all, fields(otel.kind = "client"))]
+    pub async fn send(&self, token: &str, data: &HashMap<String, String>) -> Result<String, PushError> {
+        let url = format!("{}/v1/projects/{}/messages:send", self.base_url, self.account.project_id);
+        let message = json!({
+            "message": {
+                "token": token,
+                "data": data,
+                "android": { "priority": "high" },
+                "apns": {
+                    "headers": { "apns-push-type": "background", "apns-priority": "5" },
+                    "payload": { "aps": { "content-available": 1 } },
+                },
+            }
+        });
+
+        let mut attempt = 1;
+        loop {
+            let access_token = self.access_token().await?;
+            let response = self.http.post(&url).bearer_auth(&access_token).json(&message).send().await;
+
+            let retry_after = match response {
+                Ok(response) if response.status().is_success() => {
+                    let sent: SendResponse = response
+                        .json()
+                        .await
+                        .map_err(|e| PushError::Provider(format!("unexpected response: {}", e)))?;
+                    return Ok(sent.name);
+                }
+                // Revoked before it expired: sign in again
+                Ok(response) if response.status() == StatusCode::UNAUTHORIZED && attempt < MAX_ATTEMPTS => {
+                    *self.access_token.lock().await = None;
+                    Some(Duration::ZERO)
+                }
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error() => {
+                    if attempt >= MAX_ATTEMPTS {
+                        return Err(Self::classify(response).await);
+                    }
+                    response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse().ok())
+                        .map(|seconds| Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+                }
+                Ok(response) => return Err(Self::classify(response).await),
+                Err(e) if attempt >= MAX_ATTEMPTS => return Err(PushError::Provider(e.to_string())),
+                Err(_) => None,
+            };
+
+            let delay = retry_after.unwrap_or(RETRY_BASE_DELAY * 2u32.pow(attempt - 1));
+            tracing::warn!(attempt, "FCM request failed; retrying in {:?}", delay);
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// The cached access token, or a new one from exchanging a signed assertion at the token URI
+    async fn access_token(&self) -> Result<String, PushError> {
+        let mut cached = self.access_token.lock().await;
+        if let Some(token) = cached.as_ref().filter(|token| Instant::now() < token.refresh_at) {
+            return Ok(token.value.clone());
+        }
+
+        let now = Utc::now().timestamp();
+        let claims = AssertionClaims {
+            iss: &self.account.client_email,
+            scope: MESSAGING_SCOPE,
+            aud: &self.account.token_uri,
+            iat: now,
+            exp: now + ASSERTION_LIFETIME_SECONDS,
+        };
+        let assertion =
+            jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key).map_err(|e| PushError::Auth(e.to_string()))?;
+        let response = self
+            .http
+            .post(&self.account.token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", assertion.as_str())])
+            .send()
+            .await
+            .map_err(|e| PushError::Auth(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(PushError::Auth(format!("token endpoint returned {}", response.status())));
+        }
+        let token: TokenResponse = response.json().await.map_err(|e| PushError::Auth(format!("unexpected token response: {}", e)))?;
+
+        let lifetime = Duration::from_secs(token.expires_in).saturating_sub(TOKEN_REFRESH_MARGIN);
+        *cached = Some(AccessToken { value: token.access_token.clone(), refresh_at: Instant::now() + lifetime });
+        Ok(token.access_token)
+    }
+
+    async fn classify(response: reqwest::Response) -> PushError {
+        let status = response.status();
+        let error = response.json::<FcmErrorResponse>().await.ok().map(|response| response.error);
+        let code = error
+            .as_ref()
+            .and_then(|e| e.details.iter().find_map(|detail| detail.error_code.clone()).or_else(|| e.status.clone()));
+        let message = error.and_then(|e| e.message).unwrap_or_else(|| status.to_string());
+
+        match (status, code.as_deref()) {
+            // The payload is always well-formed, so an invalid argument is the token
+            (_, Some("UNREGISTERED")) | (_, Some("INVALID_ARGUMENT")) | (_, Some("SENDER_ID_MISMATCH")) | (StatusCode::NOT_FOUND, _) => {
+                PushError::InvalidToken(message)
+            }
+            (StatusCode::UNAUTHORIZED, _) | (StatusCode::FORBIDDEN, _) => PushError::Auth(message),
+            _ => PushError::Provider(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SEND_PATH: &str = "/v1/projects/health-test/messages:send";
+
+    async fn client() -> (MockServer, FcmClient) {
+        let server = MockServer::start().await;
+        let credentials = json!({
+            "type": "service_account",
+            "project_id": "health-test",
+            "client_email": "push@health-test.iam.gserviceaccount.com",
+            "private_key": include_str!("../tests/data/fcm_test_key.pem"),
+            "token_uri": format!("{}/token", server.uri()),
+        });
+        let client = FcmClient::from_service_account(&credentials.to_string(), Client::new()).unwrap().with_base_url(&server.uri());
+        (server, client)
+    }
+
+    async fn mount_token(server: &MockServer, access_token: &str) {
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": access_token, "expires_in": 3599, "token_type": "Bearer" })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    fn data() -> HashMap<String, String> {
+        HashMap::from([("category".to_string(), "credential_issued".to_string()), ("hint".to_string(), "You have a new document".to_string())])
+    }
+
+    #[tokio::test]
+    async fn sends_a_data_only_message_and_reuses_the_access_token() {
+        let (server, client) = client().await;
+        mount_token(&server, "ya29.first").await;
+        Mock::given(method("POST"))
+            .and(path(SEND_PATH))
+            .and(header("authorization", "Bearer ya29.first"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "name": "projects/health-test/messages/1" })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        assert_eq!(client.send("device-token", &data()).await.unwrap(), "projects/health-test/messages/1");
+        client.send("device-token", &data()).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        // A JWT is URL-safe, so the assertion needs no decoding out of the form
+        let form = String::from_utf8(requests[0].body.clone()).unwrap();
+        let assertion = form.split('&').find_map(|pair| pair.strip_prefix("assertion=")).unwrap();
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.insecure_disable_signature_validation();
+        validation.set_audience(&[format!("{}/token", server.uri())]);
+        let claims = jsonwebtoken::decode::<serde_json::Value>(assertion, &DecodingKey::from_secret(&[]), &validation).unwrap().claims;
+        assert_eq!(claims["iss"], "push@health-test.iam.gserviceaccount.com");
+        assert_eq!(claims["scope"], MESSAGING_SCOPE);
+
+        let message: serde_json::Value = requests[1].body_json().unwrap();
+        assert_eq!(message["message"]["token"], "device-token");
+        assert_eq!(message["message"]["data"]["hint"], "You have a new document");
+        assert!(message["message"].get("notification").is_none());
+    }
+
+    #[tokio::test]
+    async fn unregistered_token_is_reported_invalid_without_a_retry() {
+        let (server, client) = client().await;
+        mount_token(&server, "ya29.first").await;
+        Mock::given(method("POST"))
+            .and(path(SEND_PATH))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": {
+                    "code": 404,
+                    "message": "Requested entity was not found.",
+                    "status": "NOT_FOUND",
+                    "details": [{ "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError", "errorCode": "UNREGISTERED" }]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client.send("stale-token", &data()).await.unwrap_err();
+
+        assert!(matches!(err, PushError::InvalidToken(_)));
+    }
+
+    #[tokio::test]
+    async fn rejected_access_token_is_replaced_once() {
+        let (server, client) = client().await;
+        mount_token(&server, "ya29.first").await;
+        mount_token(&server, "ya29.second").await;
+        Mock::given(method("POST"))
+            .and(path(SEND_PATH))
+            .and(header("authorization", "Bearer ya29.first"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({ "error": { "code": 401, "status": "UNAUTHENTICATED" } })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(SEND_PATH))
+            .and(header("authorization", "Bearer ya29.second"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "name": "projects/health-test/messages/2" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(client.send("device-token", &data()).await.unwrap(), "projects/health-test/messages/2");
+    }
+}
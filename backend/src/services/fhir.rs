@@ -1,43 +1,96 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde_json::{json, Value};
 use chrono::Utc;
+use serde::Serialize;
 use uuid::Uuid;
 
 use crate::models::*;
 
 pub struct FhirManager;
 
+/// A resource of a document Bundle, borrowed from wherever it was loaded
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(untagged)]
+pub enum BundleResource<'a> {
+    Patient(&'a FhirPatient),
+    Encounter(&'a FhirEncounter),
+    Observation(&'a FhirObservation),
+    Condition(&'a FhirCondition),
+    MedicationRequest(&'a FhirMedicationRequest),
+}
+
+#[derive(Serialize)]
+struct BundleEntry<'a> {
+    resource: BundleResource<'a>,
+}
+
+#[derive(Serialize)]
+struct DocumentBundle<'a> {
+    #[serde(rename = "resourceType")]
+    resource_type: &'static str,
+    id: String,
+    #[serde(rename = "type")]
+    bundle_type: &'static str,
+    timestamp: String,
+    entry: Vec<BundleEntry<'a>>,
+    signature: &'a Value,
+}
+
+/// A writer that only counts what is written to it
+struct ByteCount(usize);
+
+impl std::io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 impl FhirManager {
-    /// Create a FHIR Bundle containing all resources for a patient
-    pub fn create_patient_bundle(patient: &Patient, resources: Vec<Value>) -> Result<FhirBundle> {
-        let mut bundle_entries = vec![
-            json!({
-                "resource": patient.fhir_patient
-            })
-        ];
-
-        // Add all other resources
-        for resource in resources {
-            bundle_entries.push(json!({
-                "resource": resource
-            }));
-        }
+    /// Write a FHIR document Bundle of `patient` and `resources`, signed with `signature`, as JSON.
+    ///
+    /// The resources are serialized where they are rather than cloned into a `Value` first, and
+    /// the output is measured before it is written, so the bytes returned are the only copy.
+    pub fn write_document_bundle(patient: &Patient, resources: &[BundleResource<'_>], signature: &Value) -> Result<Vec<u8>> {
+        let bundle = DocumentBundle {
+            resource_type: "Bundle",
+            id: Uuid::new_v4().to_string(),
+            bundle_type: "document",
+            timestamp: Utc::now().to_rfc3339(),
+            entry: std::iter::once(BundleResource::Patient(&patient.fhir_patient))
+                .chain(resources.iter().copied())
+                .map(|resource| BundleEntry { resource })
+                .collect(),
+            signature,
+        };
+        let mut length = ByteCount(0);
+        serde_json::to_writer(&mut length, &bundle)?;
+        let mut bytes = Vec::with_capacity(length.0);
+        serde_json::to_writer(&mut bytes, &bundle)?;
+        Ok(bytes)
+    }
+
+    /// Create a FHIR searchset Bundle, the shape `Patient/$everything` returns
+    pub fn create_searchset_bundle(patient: &Patient, resources: Vec<Value>) -> Value {
+        let mut all = vec![json!(patient.fhir_patient)];
+        all.extend(resources);
+        Self::create_searchset(all)
+    }
 
-        let bundle = json!({
+    /// Create a FHIR searchset Bundle of `resources`, in order
+    pub fn create_searchset(resources: Vec<Value>) -> Value {
+        let entries: Vec<Value> = resources.into_iter().map(|resource| json!({ "resource": resource })).collect();
+        json!({
             "resourceType": "Bundle",
             "id": Uuid::new_v4().to_string(),
-            "type": "document",
+            "type": "searchset",
+            "total": entries.len(),
             "timestamp": Utc::now().to_rfc3339(),
-            "entry": bundle_entries
-        });
-
-        Ok(FhirBundle {
-            id: None,
-            patient_did: patient.did.clone(),
-            bundle,
-            version: 1,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
+            "entry": entries
         })
     }
 
@@ -60,6 +113,7 @@ impl FhirManager {
             birth_date: birth_date.to_string(),
             address,
             telecom,
+            photo: Vec::new(),
         }
     }
 
@@ -103,12 +157,14 @@ impl FhirManager {
                 reference: format!("Encounter/{}", id),
                 display: None,
             }),
-            authored_on: Utc::now().to_rfc3339(),
+            authored_on: Utc::now().into(),
             requester: FhirReference {
                 reference: format!("Practitioner/{}", practitioner_did),
                 display: None,
             },
             dosage_instruction: dosage_instructions,
+            status_reason: None,
+            dispense_request: None,
         }
     }
 
@@ -118,8 +174,8 @@ impl FhirManager {
         practitioner_did: &str,
         encounter_class: FhirCoding,
         reason_codes: Vec<FhirCodeableConcept>,
-        start_time: &str,
-        end_time: Option<&str>,
+        start_time: FhirDateTime,
+        end_time: Option<FhirDateTime>,
     ) -> FhirEncounter {
         FhirEncounter {
             resource_type: "Encounter".to_string(),
@@ -145,8 +201,8 @@ impl FhirManager {
                 }),
             }],
             period: FhirPeriod {
-                start: Some(start_time.to_string()),
-                end: end_time.map(|s| s.to_string()),
+                start: Some(start_time),
+                end: end_time,
             },
             reason_code: reason_codes,
         }
@@ -161,7 +217,7 @@ impl FhirManager {
         value_quantity: Option<FhirQuantity>,
         value_string: Option<String>,
         interpretation: Vec<FhirCodeableConcept>,
-        effective_time: &str,
+        effective_time: FhirDateTime,
     ) -> FhirObservation {
         FhirObservation {
             resource_type: "Observation".to_string(),
@@ -177,7 +233,7 @@ impl FhirManager {
                 reference: format!("Encounter/{}", id),
                 display: None,
             }),
-            effective_date_time: effective_time.to_string(),
+            effective_date_time: effective_time,
             value_quantity,
             value_string,
             interpretation,
@@ -190,8 +246,8 @@ impl FhirManager {
         encounter_id: Option<&str>,
         condition_code: FhirCodeableConcept,
         category: Vec<FhirCodeableConcept>,
-        onset_time: &str,
-        recorded_time: &str,
+        onset_time: FhirDateTime,
+        recorded_time: FhirDateTime,
     ) -> FhirCondition {
         FhirCondition {
             resource_type: "Condition".to_string(),
@@ -222,8 +278,8 @@ impl FhirManager {
                 reference: format!("Encounter/{}", id),
                 display: None,
             }),
-            onset_date_time: onset_time.to_string(),
-            recorded_date: recorded_time.to_string(),
+            onset_date_time: onset_time,
+            recorded_date: recorded_time,
         }
     }
 
@@ -249,6 +305,39 @@ impl FhirManager {
         Ok(())
     }
 
+    /// Check a Period: its dates are FHIR dates or dateTimes, and it does not end before it starts
+    pub fn validate_period(period: &FhirPeriod) -> Result<()> {
+        for date in period.start.iter().chain(&period.end) {
+            validate_date("period", date)?;
+        }
+        match (&period.start, &period.end) {
+            (Some(start), Some(end)) if start.is_after(end) => Err(anyhow!("The period ends ({}) before it starts ({})", end, start)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate_observation(observation: &FhirObservation) -> Result<()> {
+        validate_date("effective_date_time", &observation.effective_date_time)
+    }
+
+    /// Check a Condition's dates, and that it was not recorded before its onset
+    pub fn validate_condition(condition: &FhirCondition) -> Result<()> {
+        validate_date("onset_date_time", &condition.onset_date_time)?;
+        validate_date("recorded_date", &condition.recorded_date)?;
+        if is_given(&condition.onset_date_time) && is_given(&condition.recorded_date) && condition.onset_date_time.is_after(&condition.recorded_date) {
+            return Err(anyhow!(
+                "The condition was recorded ({}) before its onset ({})",
+                condition.recorded_date,
+                condition.onset_date_time
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_medication_request(request: &FhirMedicationRequest) -> Result<()> {
+        validate_date("authored_on", &request.authored_on)
+    }
+
     /// Convert FHIR resource to JSON string
     pub fn resource_to_json(resource: &Value) -> Result<String> {
         Ok(serde_json::to_string_pretty(resource)?)
@@ -260,6 +349,18 @@ impl FhirManager {
     }
 }
 
+/// Empty stands for a date the sender left out, as FHIR allows for all of these
+fn is_given(date: &FhirDateTime) -> bool {
+    !date.as_str().is_empty()
+}
+
+fn validate_date(field: &str, date: &FhirDateTime) -> Result<()> {
+    if is_given(date) && !date.is_valid() {
+        return Err(anyhow!("Invalid {}: {}", field, InvalidFhirDateTime(date.to_string())));
+    }
+    Ok(())
+}
+
 // Common FHIR code systems and values
 pub struct FhirCodeSystems;
 
@@ -277,7 +378,7 @@ impl FhirCodeSystems {
     }
 
     pub fn rxnorm() -> &'static str {
-        "http://www.nlm.nih.g/research/umls/rxnorm"
+        "http://www.nlm.nih.gov/research/umls/rxnorm"
     }
 
     pub fn npi() -> &'static str {
@@ -352,4 +453,59 @@ impl ObservationCodes {
             text: Some("Heart Rate".to_string()),
         }
     }
+
+    pub fn systolic_blood_pressure() -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(FhirCodeSystems::loinc().to_string()),
+                code: Some("8480-6".to_string()),
+                display: Some("Systolic blood pressure".to_string()),
+            }],
+            text: Some("Systolic Blood Pressure".to_string()),
+        }
+    }
+
+    pub fn diastolic_blood_pressure() -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(FhirCodeSystems::loinc().to_string()),
+                code: Some("8462-4".to_string()),
+                display: Some("Diastolic blood pressure".to_string()),
+            }],
+            text: Some("Diastolic Blood Pressure".to_string()),
+        }
+    }
+
+    pub fn oxygen_saturation() -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(FhirCodeSystems::loinc().to_string()),
+                code: Some("59408-5".to_string()),
+                display: Some("Oxygen saturation in Arterial blood by Pulse oximetry".to_string()),
+            }],
+            text: Some("Oxygen Saturation".to_string()),
+        }
+    }
+
+    pub fn respiratory_rate() -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(FhirCodeSystems::loinc().to_string()),
+                code: Some("9279-1".to_string()),
+                display: Some("Respiratory rate".to_string()),
+            }],
+            text: Some("Respiratory Rate".to_string()),
+        }
+    }
+
+    pub fn body_weight() -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(FhirCodeSystems::loinc().to_string()),
+                code: Some("29463-7".to_string()),
+                display: Some("Body weight".to_string()),
+            }],
+            text: Some("Body Weight".to_string()),
+        }
+    }
 }
@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::LEGACY_BUNDLE_KEY_VERSION;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::patient::read_details;
+use crate::services::{PatientService, ServiceError};
+use crate::store::EncounterStore;
+use crate::utils;
+use crate::utils::chunked::{self, ByteStream, Header, PREAMBLE_LEN};
+
+/// Which part of a file a download covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilePart {
+    Full,
+    /// `206 Partial Content` for these plaintext bytes
+    Range(Range<u64>),
+    /// `416 Range Not Satisfiable`; nothing is sent
+    Unsatisfiable,
+}
+
+/// A decrypted file on its way to the client
+pub struct FileDownload {
+    /// Plaintext length of the whole file
+    pub len: u64,
+    /// Quoted SHA-256 of the plaintext, which stays the same across re-encryption
+    pub etag: String,
+    pub content_type: String,
+    pub part: FilePart,
+    pub body: ByteStream,
+}
+
+enum Source {
+    Framed(Header),
+    Buffered(Bytes),
+}
+
+// --- FileService ---
+/// Serves files stored encrypted on IPFS to those allowed to see the resource they belong to.
+///
+/// Framed objects are decrypted as they stream from IPFS, so a download holds a couple of
+/// frames in memory however large the file is, and a range only fetches the frames it needs.
+pub struct FileService {
+    encounters: Arc<dyn EncounterStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    patient_service: Arc<PatientService>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl FileService {
+    pub fn new(
+        encounters: Arc<dyn EncounterStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        patient_service: Arc<PatientService>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { encounters, ipfs_client, patient_service, config, audit_log_service }
+    }
+
+    /// Open `cid`, an encounter's bundle or an attachment, for the caller, honouring the request's
+    /// `Range` and `If-Range` headers. Only the part the response covers is fetched from IPFS.
+    pub async fn download(&self, caller_did: &str, cid: &str, range: Option<&str>, if_range: Option<&str>) -> Result<FileDownload> {
+        let (patient_did, key_version, content_type) = match self.encounters.get_encounter_by_bundle(cid).await? {
+            Some(encounter) => (encounter.patient_did, encounter.bundle_key_version.unwrap_or(LEGACY_BUNDLE_KEY_VERSION), "application/fhir+json".to_string()),
+            None => {
+                let attachment = self.encounters.get_attachment_by_hash(cid).await?.ok_or_else(|| anyhow!("File not found"))?;
+                (attachment.patient_did, attachment.key_version, attachment.content_type)
+            }
+        };
+        let Some(access) = self.patient_service.check_access(caller_did, &patient_did, "Encounter").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this file".to_string()).into());
+        };
+        self.patient_service.count_download(caller_did, &patient_did, cid, access).await?;
+        let key = self.config.encryption_key(key_version).ok_or_else(|| anyhow!("No key is configured for version {}", key_version))?;
+
+        let preamble = self
+            .ipfs_client
+            .get_range(cid, 0, Some(PREAMBLE_LEN as u64))
+            .await?
+            .try_fold(Vec::new(), |mut preamble, chunk| async move {
+                preamble.extend_from_slice(&chunk);
+                Ok(preamble)
+            })
+            .await?;
+        let (len, digest, source) = if chunked::is_chunked(&preamble) {
+            let header = Header::parse(&preamble, key)?;
+            (header.plaintext_len(), header.digest_hex(), Source::Framed(header))
+        } else {
+            // Bundles from before framed encryption are small enough to decrypt in one go
+            let stored = self.ipfs_client.get_file(cid).await?;
+            let plaintext = utils::decrypt(std::str::from_utf8(&stored)?, key)?;
+            (plaintext.len() as u64, hex::encode(Sha256::digest(&plaintext)), Source::Buffered(Bytes::from(plaintext)))
+        };
+        let etag = format!("\"{}\"", digest);
+        let part = match range {
+            // A stale `If-Range` means the client's copy changed, so it gets the whole file again
+            Some(range) if if_range.map_or(true, |validator| validator.trim() == etag) => requested_part(range, len),
+            _ => FilePart::Full,
+        };
+
+        let mut details = read_details(caller_did, &patient_did, access);
+        details["cid"] = json!(cid);
+        self.audit_log_service.log(&patient_did, "download_file", Some(details)).await;
+
+        let bytes = match &part {
+            FilePart::Full if len > 0 => 0..len,
+            FilePart::Range(range) => range.clone(),
+            _ => 0..0,
+        };
+        let body = match source {
+            _ if bytes.is_empty() => stream::empty().boxed(),
+            Source::Buffered(plaintext) => stream::once(async move { Ok(plaintext.slice(bytes.start as usize..bytes.end as usize)) }).boxed(),
+            Source::Framed(header) => {
+                let span = header.span(&bytes);
+                let stored = self.ipfs_client.get_range(cid, span.stored.start, Some(span.stored.end - span.stored.start)).await?;
+                chunked::decrypt_stream(header, key, span, bytes.end - bytes.start, stored)?
+            }
+        };
+        Ok(FileDownload { len, etag, content_type, part, body })
+    }
+}
+
+/// The part of a `len`-byte file a `Range` header asks for.
+///
+/// Only single byte ranges are served; anything else, including several ranges at once,
+/// gets the whole file as RFC 9110 allows.
+pub fn requested_part(range: &str, len: u64) -> FilePart {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return FilePart::Full;
+    };
+    if spec.contains(',') {
+        return FilePart::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return FilePart::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+    let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(first), Ok(last)) if first <= last => first..last.saturating_add(1).min(len),
+        (Ok(first), Err(_)) if last.is_empty() => first..len,
+        (Err(_), Ok(suffix)) if first.is_empty() => len.saturating_sub(suffix)..len,
+        _ => return FilePart::Full,
+    };
+    if range.start >= len || range.is_empty() {
+        return FilePart::Unsatisfiable;
+    }
+    FilePart::Range(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_byte_ranges_are_resolved_against_the_length() {
+        assert_eq!(requested_part("bytes=0-99", 1000), FilePart::Range(0..100));
+        assert_eq!(requested_part("bytes=900-", 1000), FilePart::Range(900..1000));
+        assert_eq!(requested_part("bytes=-100", 1000), FilePart::Range(900..1000));
+        assert_eq!(requested_part("bytes=-5000", 1000), FilePart::Range(0..1000));
+        assert_eq!(requested_part("bytes=990-5000", 1000), FilePart::Range(990..1000));
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable_and_others_are_ignored() {
+        assert_eq!(requested_part("bytes=1000-", 1000), FilePart::Unsatisfiable);
+        assert_eq!(requested_part("bytes=-0", 1000), FilePart::Unsatisfiable);
+        assert_eq!(requested_part("bytes=0-", 0), FilePart::Unsatisfiable);
+        assert_eq!(requested_part("bytes=0-1,5-9", 1000), FilePart::Full);
+        assert_eq!(requested_part("bytes=9-1", 1000), FilePart::Full);
+        assert_eq!(requested_part("items=0-1", 1000), FilePart::Full);
+        assert_eq!(requested_part("bytes=abc", 1000), FilePart::Full);
+    }
+}
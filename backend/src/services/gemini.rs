@@ -1,19 +1,61 @@
-
-use anyhow::anyhow;
+use async_trait::async_trait;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[cfg(feature = "test")]
+use mockall::automock;
+
+use crate::config::{Config, GeminiConfig};
+use crate::models::{ChatMessage, ChatRole};
+use crate::services::ServiceError;
 
-use crate::config::Config;
+const GEMINI_API_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+/// Used to estimate token counts when a response carries no usage metadata
+const CHARS_PER_TOKEN: usize = 4;
+const HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+
+/// Why a Gemini request failed, worded so it can be shown to the user as is.
+/// The raw API response is only logged.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum GeminiError {
+    #[error("chat model {0} is not available")]
+    ModelNotFound(String),
+    #[error("the assistant is busy, try again later")]
+    QuotaExceeded,
+    #[error("the assistant can't help with that request")]
+    Blocked,
+    #[error("chat is not configured correctly on this server")]
+    InvalidKey,
+    #[error("the assistant is unavailable, try again later")]
+    Unavailable,
+}
+
+impl GeminiError {
+    /// Errors for which the next model in the fallback list is worth a try
+    fn try_next_model(&self) -> bool {
+        matches!(self, Self::ModelNotFound(_) | Self::QuotaExceeded)
+    }
+}
 
 // --- Gemini API Structs ---
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiRequest {
     contents: Vec<Content>,
-    #[serde(rename = "safetySettings")]
     safety_settings: Vec<SafetySetting>,
+    generation_config: GenerationConfig,
 }
 
 #[derive(Serialize)]
 struct Content {
+    role: ChatRole,
     parts: Vec<Part>,
 }
 
@@ -24,22 +66,51 @@ struct Part {
 
 #[derive(Serialize)]
 struct SafetySetting {
-    category: String,
+    category: &'static str,
     threshold: String,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    temperature: f32,
+    max_output_tokens: u32,
+}
+
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct Candidate {
-    content: ContentResponse,
+    content: Option<ContentResponse>,
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct ContentResponse {
+    #[serde(default)]
     parts: Vec<PartResponse>,
 }
 
@@ -48,36 +119,319 @@ struct PartResponse {
     text: String,
 }
 
-pub async fn ask_gemini(prompt: &str, config: &Config) -> anyhow::Result<String> {
-    let client = reqwest::Client::new();
-    let api_key = &config.gemini_api_key;
-    let url = format!("https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:generateContent?key={}", api_key);
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+}
 
+/// A model's answer and the tokens it cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelReply {
+    pub text: String,
+    pub prompt_tokens: u32,
+    pub reply_tokens: u32,
+}
+
+/// Anything that can continue a conversation, so the chat service can be tested without Gemini
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ChatModel: Send + Sync {
+    /// Reply to `prompt`, given the earlier turns of the conversation (oldest first)
+    async fn generate(&self, history: &[ChatMessage], prompt: &str) -> anyhow::Result<ModelReply>;
+}
+
+pub struct GeminiChatModel {
+    config: Arc<Config>,
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl GeminiChatModel {
+    pub fn new(config: Arc<Config>, http: reqwest::Client) -> Self {
+        Self { config, http, base_url: GEMINI_API_BASE_URL.to_string() }
+    }
+
+    /// Point the client at a different API host (used by tests)
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+}
+
+#[async_trait]
+impl ChatModel for GeminiChatModel {
+    async fn generate(&self, history: &[ChatMessage], prompt: &str) -> anyhow::Result<ModelReply> {
+        let Some(gemini) = &self.config.gemini else {
+            return Err(ServiceError::NotConfigured("chat is not configured on this server".to_string()).into());
+        };
+        match ask_gemini(&self.http, &self.base_url, gemini, history, prompt).await {
+            Ok(reply) => Ok(reply),
+            Err(e @ GeminiError::QuotaExceeded) => Err(ServiceError::RateLimited(e.to_string()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Ask the configured model, moving on to each fallback model in turn while the
+/// previous one is missing (404) or out of quota (429)
+pub async fn ask_gemini(
+    client: &reqwest::Client,
+    base_url: &str,
+    config: &GeminiConfig,
+    history: &[ChatMessage],
+    prompt: &str,
+) -> Result<ModelReply, GeminiError> {
     let request_body = GeminiRequest {
-        contents: vec![Content { parts: vec![Part { text: prompt.to_string() }] }],
-        safety_settings: vec![
-            SafetySetting {
-                category: "HARM_CATEGORY_DANGEROUS_CONTENT".to_string(),
-                threshold: "BLOCK_ONLY_HIGH".to_string(),
-            }
-        ]
+        contents: history
+            .iter()
+            .map(|message| Content { role: message.role, parts: vec![Part { text: message.content.clone() }] })
+            .chain(std::iter::once(Content { role: ChatRole::User, parts: vec![Part { text: prompt.to_string() }] }))
+            .collect(),
+        safety_settings: HARM_CATEGORIES
+            .into_iter()
+            .map(|category| SafetySetting { category, threshold: config.safety_threshold.clone() })
+            .collect(),
+        generation_config: GenerationConfig {
+            temperature: config.temperature,
+            max_output_tokens: config.max_output_tokens,
+        },
     };
 
-    let res = client.post(&url)
-        .json(&request_body)
+    let mut models = std::iter::once(&config.model).chain(&config.fallback_models).peekable();
+    loop {
+        let model = models.next().expect("the primary model is always tried");
+        match generate_content(client, base_url, config.api_key.expose_secret(), model, &request_body).await {
+            Err(e) if e.try_next_model() && models.peek().is_some() => {
+                tracing::warn!("Gemini model {} failed ({}); falling back", model, e);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[tracing::instrument(name = "gemini.generate_content", skip_all, fields(otel.kind = "client", gen_ai.system = "gemini", gen_ai.request.model = model))]
+async fn generate_content(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    request_body: &GeminiRequest,
+) -> Result<ModelReply, GeminiError> {
+    let url = format!("{}/v1beta/models/{}:generateContent", base_url, model);
+    let res = client
+        .post(&url)
+        .header("x-goog-api-key", api_key)
+        .json(request_body)
         .send()
-        .await?;
+        .await
+        .map_err(|e| {
+            tracing::error!("Gemini request failed: {}", e);
+            GeminiError::Unavailable
+        })?;
 
-    if res.status().is_success() {
-        let gemini_response = res.json::<GeminiResponse>().await?;
-        if let Some(candidate) = gemini_response.candidates.first() {
-            if let Some(part) = candidate.content.parts.first() {
-                return Ok(part.text.clone());
-            }
+    let status = res.status();
+    if !status.is_success() {
+        let message = res.json::<ErrorResponse>().await.map(|body| body.error.message).unwrap_or_default();
+        tracing::warn!("Gemini API returned {} for model {}: {}", status, model, message);
+        return Err(match status {
+            StatusCode::NOT_FOUND => GeminiError::ModelNotFound(model.to_string()),
+            StatusCode::TOO_MANY_REQUESTS => GeminiError::QuotaExceeded,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => GeminiError::InvalidKey,
+            // An unknown key is reported as a 400 rather than a 401
+            StatusCode::BAD_REQUEST if message.contains("API key") => GeminiError::InvalidKey,
+            _ => GeminiError::Unavailable,
+        });
+    }
+
+    let response = res.json::<GeminiResponse>().await.map_err(|e| {
+        tracing::error!("Unexpected Gemini response: {}", e);
+        GeminiError::Unavailable
+    })?;
+    if let Some(reason) = response.prompt_feedback.and_then(|feedback| feedback.block_reason) {
+        tracing::info!("Gemini blocked a prompt: {}", reason);
+        return Err(GeminiError::Blocked);
+    }
+    let Some(candidate) = response.candidates.into_iter().next() else {
+        return Err(GeminiError::Unavailable);
+    };
+    match candidate.content.and_then(|content| content.parts.into_iter().next()) {
+        Some(part) => {
+            let (prompt_tokens, reply_tokens) = match response.usage_metadata {
+                Some(usage) => (usage.prompt_token_count, usage.candidates_token_count),
+                None => {
+                    let prompt_chars = request_body.contents.iter().flat_map(|c| &c.parts).map(|p| p.text.chars().count()).sum();
+                    (estimate_tokens(prompt_chars), estimate_tokens(part.text.chars().count()))
+                }
+            };
+            Ok(ModelReply { text: part.text, prompt_tokens, reply_tokens })
+        }
+        None if candidate.finish_reason.as_deref() == Some("SAFETY") => Err(GeminiError::Blocked),
+        None => Err(GeminiError::Unavailable),
+    }
+}
+
+fn estimate_tokens(chars: usize) -> u32 {
+    chars.div_ceil(CHARS_PER_TOKEN) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config() -> GeminiConfig {
+        GeminiConfig {
+            api_key: "key".into(),
+            model: "primary".to_string(),
+            fallback_models: vec!["secondary".to_string()],
+            ..Default::default()
         }
-        Err(anyhow!("No content found in Gemini response"))
-    } else {
-        let error_body = res.text().await?;
-        Err(anyhow!("Gemini API request failed: {}", error_body))
+    }
+
+    fn reply(text: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": text }] }, "finishReason": "STOP" }]
+        }))
+    }
+
+    async fn ask(server: &MockServer, config: &GeminiConfig) -> Result<String, GeminiError> {
+        ask_gemini(&reqwest::Client::new(), &server.uri(), config, &[], "hello").await.map(|reply| reply.text)
+    }
+
+    #[tokio::test]
+    async fn chat_without_gemini_config_is_not_configured() {
+        let model = GeminiChatModel::new(Arc::new(Config::default()), reqwest::Client::new());
+
+        let err = model.generate(&[], "hello").await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::NotConfigured(_))));
+    }
+
+    #[tokio::test]
+    async fn request_carries_generation_settings() {
+        let server = MockServer::start().await;
+        let config = GeminiConfig { temperature: 0.5, max_output_tokens: 256, ..config() };
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/primary:generateContent"))
+            .and(header("x-goog-api-key", "key"))
+            .and(body_partial_json(json!({
+                "contents": [{ "role": "user", "parts": [{ "text": "hello" }] }],
+                "generationConfig": { "temperature": 0.5, "maxOutputTokens": 256 },
+            })))
+            .respond_with(reply("hi"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(ask(&server, &config).await.unwrap(), "hi");
+    }
+
+    #[tokio::test]
+    async fn missing_model_falls_back_to_the_next_one() {
+        let server = MockServer::start().await;
+        Mock::given(path("/v1beta/models/primary:generateContent"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": { "code": 404, "message": "models/primary is not found", "status": "NOT_FOUND" }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1beta/models/secondary:generateContent"))
+            .respond_with(reply("from secondary"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(ask(&server, &config()).await.unwrap(), "from secondary");
+    }
+
+    #[tokio::test]
+    async fn quota_exhausted_on_every_model_is_reported_as_such() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "error": { "code": 429, "message": "Resource has been exhausted", "status": "RESOURCE_EXHAUSTED" }
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        assert_eq!(ask(&server, &config()).await.unwrap_err(), GeminiError::QuotaExceeded);
+    }
+
+    #[tokio::test]
+    async fn invalid_key_is_not_retried_on_fallback() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": { "code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT" }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_eq!(ask(&server, &config()).await.unwrap_err(), GeminiError::InvalidKey);
+    }
+
+    #[tokio::test]
+    async fn blocked_prompt_is_a_safety_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "promptFeedback": { "blockReason": "SAFETY" }
+            })))
+            .mount(&server)
+            .await;
+
+        let err = ask(&server, &config()).await.unwrap_err();
+
+        assert_eq!(err, GeminiError::Blocked);
+        assert_eq!(err.to_string(), "the assistant can't help with that request");
+    }
+
+    #[tokio::test]
+    async fn reply_stopped_for_safety_is_a_safety_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "finishReason": "SAFETY" }]
+            })))
+            .mount(&server)
+            .await;
+
+        assert_eq!(ask(&server, &config()).await.unwrap_err(), GeminiError::Blocked);
+    }
+
+    #[tokio::test]
+    async fn token_counts_come_from_usage_metadata_or_are_estimated() {
+        let server = MockServer::start().await;
+        Mock::given(path("/v1beta/models/primary:generateContent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "hi" }] }, "finishReason": "STOP" }],
+                "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 3, "totalTokenCount": 15 }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1beta/models/secondary:generateContent"))
+            .respond_with(reply("a reply of 24 characters"))
+            .mount(&server)
+            .await;
+        let client = reqwest::Client::new();
+
+        let reported = ask_gemini(&client, &server.uri(), &config(), &[], "hello").await.unwrap();
+        let estimated = ask_gemini(&client, &server.uri(), &GeminiConfig { model: "secondary".to_string(), ..config() }, &[], "hello")
+            .await
+            .unwrap();
+
+        assert_eq!((reported.prompt_tokens, reported.reply_tokens), (12, 3));
+        assert_eq!((estimated.prompt_tokens, estimated.reply_tokens), (2, 6));
     }
 }
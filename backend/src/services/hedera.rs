@@ -1,8 +1,15 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
 use hedera::{
+    AccountBalanceQuery,
     Client,
     FileCreateTransaction,
     FileUpdateTransaction,
+    FileDeleteTransaction,
     ContractCreateTransaction,
     ContractFunctionParameters,
     PrivateKey,
@@ -13,15 +20,34 @@ use hedera::{
     ContractCallQuery,
     TransactionRecordQuery,
     TransactionRecord,
+    Status,
 };
 
+use crate::deadline;
+use crate::models::{HederaOperation, HederaTransaction};
+use crate::services::hedera_balance::OperatorFunds;
+use crate::services::ServiceError;
+use crate::store::HederaCostStore;
+
 // Re-export types needed by crate root to avoid name collisions with our module name
 pub use hedera::ContractId;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct HederaClient {
     client: Client,
+    operator_account_id: AccountId,
     operator_private_key: PrivateKey,
+    funds: Arc<OperatorFunds>,
+}
+
+/// Names the operator account only: the SDK's own `Debug` would print the private key
+impl fmt::Debug for HederaClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HederaClient")
+            .field("operator_account_id", &self.operator_account_id)
+            .field("operator_private_key", &"[REDACTED]")
+            .finish_non_exhaustive()
+    }
 }
 
 impl HederaClient {
@@ -36,10 +62,49 @@ impl HederaClient {
         };
         client.set_operator(account_id, private_key.clone());
 
-        Ok(Self { client, operator_private_key: private_key })
+        Ok(Self { client, operator_account_id: account_id, operator_private_key: private_key, funds: Arc::default() })
+    }
+
+    /// Share the breaker that stops paid calls once the operator account cannot pay for them
+    pub fn with_funds(mut self, funds: Arc<OperatorFunds>) -> Self {
+        self.funds = funds;
+        self
+    }
+
+    /// The operator account's hbar balance. The query is free, so it still answers once the
+    /// account can no longer pay for anything else.
+    #[tracing::instrument(name = "hedera.account_balance", skip_all, fields(otel.kind = "client"))]
+    pub async fn get_account_balance(&self) -> Result<Hbar> {
+        let mut query = AccountBalanceQuery::new();
+        query.account_id(self.operator_account_id);
+
+        let balance = query.execute(&self.client).await?;
+        Ok(balance.hbars)
+    }
+
+    /// Run a call the operator pays for, unless the network already refused one for want of
+    /// funds. Such a refusal opens the breaker, and the call fails with
+    /// [`ServiceError::HederaBalanceExhausted`] instead of the SDK's error.
+    async fn paid<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.funds.is_exhausted() {
+            return Err(ServiceError::HederaBalanceExhausted.into());
+        }
+        match call.await {
+            Err(e) if is_insufficient_payer_balance(&e) => {
+                self.funds.trip();
+                tracing::error!(operator = %self.operator_account_id, "Hedera refused a transaction for insufficient payer balance: {}", e);
+                Err(ServiceError::HederaBalanceExhausted.into())
+            }
+            result => result,
+        }
     }
 
+    #[tracing::instrument(name = "hedera.contract_create", skip_all, fields(otel.kind = "client"))]
     pub async fn create_contract(&self, bytecode: &[u8]) -> Result<ContractId> {
+        self.paid(self.submit_contract(bytecode)).await
+    }
+
+    async fn submit_contract(&self, bytecode: &[u8]) -> Result<ContractId> {
         // 1. Create a file on Hedera for the contract bytecode
         let mut file_tx = FileCreateTransaction::new();
         file_tx.keys([self.operator_private_key.public_key()])
@@ -66,77 +131,147 @@ impl HederaClient {
         Ok(contract_id)
     }
 
+    #[tracing::instrument(name = "hedera.contract_call", skip_all, fields(otel.kind = "client", hedera.contract_id = %contract_id, hedera.function = function_name))]
     pub async fn call_contract(
         &self,
         contract_id: &ContractId,
         function_name: &str,
         parameters: ContractFunctionParameters,
     ) -> Result<TransactionRecord> {
-        let mut tx = ContractExecuteTransaction::new();
-        tx.contract_id(*contract_id)
-            .gas(100_000)
-            .function(function_name)
-            .function_parameters(parameters.to_bytes(None))
-            .max_transaction_fee(Hbar::new(2));
-
-        let tx_response = tx.execute(&self.client).await?;
-        let record = TransactionRecordQuery::new()
-            .transaction_id(tx_response.transaction_id)
-            .execute(&self.client)
-            .await?;
-
-        Ok(record)
+        self.paid(async {
+            let mut tx = ContractExecuteTransaction::new();
+            tx.contract_id(*contract_id)
+                .gas(100_000)
+                .function(function_name)
+                .function_parameters(parameters.to_bytes(None))
+                .max_transaction_fee(Hbar::new(2));
+
+            let tx_response = tx.execute(&self.client).await?;
+            let record = TransactionRecordQuery::new()
+                .transaction_id(tx_response.transaction_id)
+                .execute(&self.client)
+                .await?;
+
+            Ok(record)
+        })
+        .await
     }
 
+    #[tracing::instrument(name = "hedera.contract_query", skip_all, fields(otel.kind = "client", hedera.contract_id = %contract_id, hedera.function = function_name))]
     pub async fn query_contract(
         &self,
         contract_id: &ContractId,
         function_name: &str,
         parameters: ContractFunctionParameters,
     ) -> Result<Vec<u8>> {
-        let mut query = ContractCallQuery::new();
-        query.contract_id(*contract_id)
-            .gas(100_000)
-            .function(function_name)
-            .function_parameters(parameters.to_bytes(None));
-
-        let result = query.execute(&self.client).await?;
-        Ok(result.as_bytes().to_vec())
+        self.paid(async {
+            let mut query = ContractCallQuery::new();
+            query.contract_id(*contract_id)
+                .gas(100_000)
+                .function(function_name)
+                .function_parameters(parameters.to_bytes(None));
+
+            let result = query.execute(&self.client).await?;
+            Ok(result.as_bytes().to_vec())
+        })
+        .await
     }
 
+    #[tracing::instrument(name = "hedera.file_create", skip_all, fields(otel.kind = "client"))]
     pub async fn create_file(&self, contents: &[u8]) -> Result<FileId> {
-        let mut file_tx = FileCreateTransaction::new();
-        file_tx.keys([self.operator_private_key.public_key()])
-            .contents(contents.to_vec())
-            .max_transaction_fee(Hbar::new(2));
-
-        let signed_tx = file_tx.freeze_with(&self.client)?.sign(self.operator_private_key.clone());
-        let tx_response = signed_tx.execute(&self.client).await?;
-        let receipt = tx_response.get_receipt(&self.client).await?;
-        let file_id = receipt.file_id.ok_or_else(|| anyhow::anyhow!("File ID not found in receipt "))?;
-
-        Ok(file_id)
+        self.paid(async {
+            let mut file_tx = FileCreateTransaction::new();
+            file_tx.keys([self.operator_private_key.public_key()])
+                .contents(contents.to_vec())
+                .max_transaction_fee(Hbar::new(2));
+
+            let signed_tx = file_tx.freeze_with(&self.client)?.sign(self.operator_private_key.clone());
+            let tx_response = signed_tx.execute(&self.client).await?;
+            let receipt = tx_response.get_receipt(&self.client).await?;
+            let file_id = receipt.file_id.ok_or_else(|| anyhow::anyhow!("File ID not found in receipt "))?;
+
+            Ok(file_id)
+        })
+        .await
     }
 
+    #[tracing::instrument(name = "hedera.file_update", skip_all, fields(otel.kind = "client", hedera.file_id = %file_id))]
     pub async fn update_file(&self, file_id: FileId, contents: &[u8]) -> Result<()> {
-        let mut file_tx = FileUpdateTransaction::new();
-        file_tx.file_id(file_id)
-            .contents(contents.to_vec())
-            .max_transaction_fee(Hbar::new(2));
+        self.paid(async {
+            let mut file_tx = FileUpdateTransaction::new();
+            file_tx.file_id(file_id)
+                .contents(contents.to_vec())
+                .max_transaction_fee(Hbar::new(2));
+
+            let signed_tx = file_tx.freeze_with(&self.client)?.sign(self.operator_private_key.clone());
+            let tx_response = signed_tx.execute(&self.client).await?;
+            tx_response.get_receipt(&self.client).await?;
+
+            Ok(())
+        })
+        .await
+    }
 
-        let signed_tx = file_tx.freeze_with(&self.client)?.sign(self.operator_private_key.clone());
-        let tx_response = signed_tx.execute(&self.client).await?;
-        tx_response.get_receipt(&self.client).await?;
+    #[tracing::instrument(name = "hedera.file_delete", skip_all, fields(otel.kind = "client", hedera.file_id = %file_id))]
+    pub async fn delete_file(&self, file_id: FileId) -> Result<()> {
+        self.paid(async {
+            let mut file_tx = FileDeleteTransaction::new();
+            file_tx.file_id(file_id)
+                .max_transaction_fee(Hbar::new(2));
+
+            let signed_tx = file_tx.freeze_with(&self.client)?.sign(self.operator_private_key.clone());
+            let tx_response = signed_tx.execute(&self.client).await?;
+            tx_response.get_receipt(&self.client).await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 }
 
+/// Whether the network refused a call because the operator account could not pay its fee,
+/// at pre-check or in the receipt
+fn is_insufficient_payer_balance(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<hedera::Error>(),
+        Some(
+            hedera::Error::TransactionPreCheckStatus { status: Status::InsufficientPayerBalance, .. }
+                | hedera::Error::QueryPreCheckStatus { status: Status::InsufficientPayerBalance, .. }
+                | hedera::Error::QueryPaymentPreCheckStatus { status: Status::InsufficientPayerBalance, .. }
+                | hedera::Error::ReceiptStatus { status: Status::InsufficientPayerBalance, .. }
+        )
+    )
+}
+
+/// Ledger operations the services rely on, returning transaction ids.
+///
+/// Implemented by [`HealthcareHederaService`]; tests use the recording fake from `services::fakes`.
+/// During a request, a call still waiting when the request's time is up is dropped with a 504;
+/// a transaction it submitted may still reach consensus, as after any lost response.
+#[async_trait]
+pub trait LedgerAnchor: Send + Sync {
+    async fn anchor_log_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String>;
+    async fn store_credential(
+        &self,
+        subject_did: &str,
+        credential_type: &str,
+        ipfs_hash: &str,
+        expires_at: Option<u64>,
+        metadata: &str,
+    ) -> Result<String>;
+    async fn verify_credential(&self, credential_hash: &[u8]) -> Result<bool>;
+    /// Anchor the Merkle root of a batch of `batch_size` credentials in one call
+    async fn store_credential_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String>;
+    async fn verify_credential_batch(&self, root_hash: [u8; 32]) -> Result<bool>;
+    async fn record_access_grant(&self, grant_id: &str, patient_did: &str, grantee_did: &str, expires_at: Option<u64>) -> Result<String>;
+}
+
 pub struct HealthcareHederaService {
     client: HederaClient,
     access_control_contract: Option<ContractId>,
     credentials_contract: Option<ContractId>,
     audit_trail_contract: Option<ContractId>,
+    transactions: Option<Arc<dyn HederaCostStore>>,
 }
 
 impl HealthcareHederaService {
@@ -146,6 +281,30 @@ impl HealthcareHederaService {
             access_control_contract: None,
             credentials_contract: None,
             audit_trail_contract: None,
+            transactions: None,
+        }
+    }
+
+    /// Record the fee of every anchoring and credential transaction, for the cost report
+    pub fn with_transaction_log(mut self, transactions: Arc<dyn HederaCostStore>) -> Self {
+        self.transactions = Some(transactions);
+        self
+    }
+
+    /// A fee that cannot be saved is logged; the transaction itself already went through
+    async fn record_fee(&self, operation: HederaOperation, record: &TransactionRecord) {
+        let Some(transactions) = &self.transactions else {
+            return;
+        };
+        let transaction = HederaTransaction {
+            id: None,
+            transaction_id: record.transaction_id.to_string(),
+            operation,
+            fee_tinybars: record.transaction_fee.to_tinybars(),
+            recorded_at: Utc::now(),
+        };
+        if let Err(e) = transactions.record_hedera_transaction(&transaction).await {
+            tracing::warn!(transaction_id = %transaction.transaction_id, "Failed to record the Hedera fee: {}", e);
         }
     }
 
@@ -225,4 +384,92 @@ impl HealthcareHederaService {
             Err(anyhow::anyhow!("Credentials contract not deployed "))
         }
     }
+
+    pub async fn store_credential_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<TransactionRecord> {
+        if let Some(contract_id) = &self.credentials_contract {
+            let mut params = ContractFunctionParameters::new();
+            params.add_bytes(&root_hash);
+            params.add_uint64(batch_size);
+
+            self.client.call_contract(contract_id, "storeCredentialBatch", params).await
+        } else {
+            Err(anyhow::anyhow!("Credentials contract not deployed "))
+        }
+    }
+
+    pub async fn verify_credential_batch(&self, root_hash: [u8; 32]) -> Result<bool> {
+        if let Some(contract_id) = &self.credentials_contract {
+            let mut params = ContractFunctionParameters::new();
+            params.add_bytes(&root_hash);
+
+            let result = self.client.query_contract(contract_id, "verifyCredentialBatch", params).await?;
+
+            // The result is a boolean encoded as a 32-byte array.
+            Ok(result[31] == 1)
+        } else {
+            Err(anyhow::anyhow!("Credentials contract not deployed "))
+        }
+    }
+
+    pub async fn record_access_grant(
+        &self,
+        grant_id: &str,
+        patient_did: &str,
+        grantee_did: &str,
+        expires_at: Option<u64>,
+    ) -> Result<TransactionRecord> {
+        if let Some(contract_id) = &self.access_control_contract {
+            let mut params = ContractFunctionParameters::new();
+            params.add_string(grant_id);
+            params.add_string(patient_did);
+            params.add_string(grantee_did);
+            params.add_uint64(expires_at.unwrap_or(0));
+
+            self.client.call_contract(contract_id, "grantAccess", params).await
+        } else {
+            Err(anyhow::anyhow!("AccessControl contract not deployed"))
+        }
+    }
+}
+
+#[async_trait]
+impl LedgerAnchor for HealthcareHederaService {
+    async fn anchor_log_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String> {
+        let record = deadline::bound(HealthcareHederaService::anchor_log_batch(self, root_hash, batch_size)).await?;
+        self.record_fee(HederaOperation::AuditAnchoring, &record).await;
+        Ok(record.transaction_id.to_string())
+    }
+
+    async fn store_credential(
+        &self,
+        subject_did: &str,
+        credential_type: &str,
+        ipfs_hash: &str,
+        expires_at: Option<u64>,
+        metadata: &str,
+    ) -> Result<String> {
+        let record = deadline::bound(HealthcareHederaService::store_credential(self, subject_did, credential_type, ipfs_hash, expires_at, metadata)).await?;
+        self.record_fee(HederaOperation::CredentialIssuance, &record).await;
+        Ok(record.transaction_id.to_string())
+    }
+
+    async fn verify_credential(&self, credential_hash: &[u8]) -> Result<bool> {
+        deadline::bound(HealthcareHederaService::verify_credential(self, credential_hash)).await
+    }
+
+    async fn store_credential_batch(&self, root_hash: [u8; 32], batch_size: u64) -> Result<String> {
+        let record = deadline::bound(HealthcareHederaService::store_credential_batch(self, root_hash, batch_size)).await?;
+        self.record_fee(HederaOperation::CredentialBatchAnchoring, &record).await;
+        Ok(record.transaction_id.to_string())
+    }
+
+    async fn verify_credential_batch(&self, root_hash: [u8; 32]) -> Result<bool> {
+        deadline::bound(HealthcareHederaService::verify_credential_batch(self, root_hash)).await
+    }
+
+    async fn record_access_grant(&self, grant_id: &str, patient_did: &str, grantee_did: &str, expires_at: Option<u64>) -> Result<String> {
+        let record = deadline::bound(HealthcareHederaService::record_access_grant(self, grant_id, patient_did, grantee_did, expires_at)).await?;
+        self.record_fee(HederaOperation::AccessGrant, &record).await;
+        Ok(record.transaction_id.to_string())
+    }
 }
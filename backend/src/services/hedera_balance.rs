@@ -0,0 +1,301 @@
+//! The Hedera operator account's hbar balance: checked every few minutes so admins hear
+//! about it before it runs dry, and a breaker that stops paid calls once Hedera has refused
+//! one for insufficient balance, rather than let every call fail with the SDK's error.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::models::*;
+use crate::services::email::EmailService;
+use crate::services::hedera::HederaClient;
+use crate::store::PatientStore;
+
+const TINYBARS_PER_HBAR: f64 = 100_000_000.0;
+/// Admins are warned again only after the balance has come back this far above the threshold,
+/// so a balance hovering around it does not send an email every check
+const REARM_FACTOR: f64 = 1.2;
+/// Enough for the costliest transaction the platform submits (a 16 hbar contract create); a
+/// check finding at least this much closes the breaker
+const OPERATING_HBAR: f64 = 20.0;
+
+/// Where the operator account's balance is read from; implemented by [`HederaClient`]
+#[async_trait]
+pub trait OperatorBalance: Send + Sync {
+    /// The operator account's balance in tinybars
+    async fn operator_balance(&self) -> Result<i64>;
+}
+
+#[async_trait]
+impl OperatorBalance for HederaClient {
+    async fn operator_balance(&self) -> Result<i64> {
+        Ok(self.get_account_balance().await?.to_tinybars())
+    }
+}
+
+/// Whether the operator account can still pay for transactions, shared by every Hedera call
+/// of this instance
+#[derive(Debug, Default)]
+pub struct OperatorFunds {
+    exhausted: AtomicBool,
+}
+
+impl OperatorFunds {
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Hedera refused a transaction for insufficient payer balance
+    pub fn trip(&self) {
+        self.exhausted.store(true, Ordering::Relaxed);
+    }
+
+    /// Let paid calls through again; returns whether they were held back
+    fn reset(&self) -> bool {
+        self.exhausted.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct BalanceState {
+    balance_tinybars: Option<i64>,
+    checked_at: Option<DateTime<Utc>>,
+    /// Admins were warned and the balance has not recovered since
+    low: bool,
+}
+
+// --- HederaBalanceMonitor ---
+pub struct HederaBalanceMonitor {
+    /// None when the ledger is not the real network, as in tests
+    source: Option<Arc<dyn OperatorBalance>>,
+    funds: Arc<OperatorFunds>,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    config: Arc<Config>,
+    state: Mutex<BalanceState>,
+}
+
+impl HederaBalanceMonitor {
+    pub fn new(
+        source: Option<Arc<dyn OperatorBalance>>,
+        funds: Arc<OperatorFunds>,
+        patients: Arc<dyn PatientStore>,
+        email_service: Arc<EmailService>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self { source, funds, patients, email_service, config, state: Mutex::default() }
+    }
+
+    /// The balance as last checked; no Hedera call is made
+    pub fn snapshot(&self) -> OperatorBalanceSnapshot {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        OperatorBalanceSnapshot {
+            balance_hbar: state.balance_tinybars.map(to_hbar),
+            checked_at: state.checked_at,
+            alert_hbar: self.config.hedera_costs.balance_alert_hbar,
+            low: state.low,
+            exhausted: self.funds.is_exhausted(),
+        }
+    }
+
+    /// The snapshot as Prometheus gauges, in the text exposition format
+    pub fn prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut gauges = String::new();
+        let mut gauge = |name: &str, help: &str, value: f64| {
+            let _ = write!(gauges, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+        };
+        if let Some(balance_hbar) = snapshot.balance_hbar {
+            gauge("hedera_operator_balance_hbar", "Hbar held by the Hedera operator account at the last check", balance_hbar);
+        }
+        gauge("hedera_operator_balance_alert_hbar", "Balance below which admins are emailed", snapshot.alert_hbar);
+        gauge("hedera_operator_balance_low", "1 while the balance is below the alert threshold", f64::from(u8::from(snapshot.low)));
+        gauge("hedera_operator_funds_exhausted", "1 while Hedera calls are held back for insufficient payer balance", f64::from(u8::from(snapshot.exhausted)));
+        gauges
+    }
+
+    /// Read the balance, let ledger calls through again once it pays for them, and email admins
+    /// when it first drops below the alert threshold. Returns whether an alert was sent.
+    pub async fn check(&self, now: DateTime<Utc>) -> Result<bool> {
+        let Some(source) = &self.source else {
+            return Ok(false);
+        };
+        let tinybars = source.operator_balance().await?;
+        let balance_hbar = to_hbar(tinybars);
+        if balance_hbar >= OPERATING_HBAR && self.funds.reset() {
+            tracing::info!("The Hedera operator account holds {:.2} hbar again; ledger calls resume", balance_hbar);
+        }
+
+        let alert_hbar = self.config.hedera_costs.balance_alert_hbar;
+        let alert = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.balance_tinybars = Some(tinybars);
+            state.checked_at = Some(now);
+            if balance_hbar >= alert_hbar * REARM_FACTOR {
+                state.low = false;
+            }
+            let alert = balance_hbar < alert_hbar && !state.low;
+            state.low |= alert;
+            alert
+        };
+        if !alert {
+            return Ok(false);
+        }
+
+        tracing::warn!("The Hedera operator account is down to {:.2} hbar, below the {:.2} hbar alert threshold", balance_hbar, alert_hbar);
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let Some(admin) = self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await? else {
+                continue;
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "account_id": &self.config.hedera_account_id,
+                "balance_hbar": format!("{:.2}", balance_hbar),
+                "alert_hbar": format!("{:.2}", alert_hbar),
+            });
+            self.email_service
+                .enqueue(&email.value, "The Hedera operator account is running low", "Hedera-balance-alert.html", &context)
+                .await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("The Hedera operator account is down to {:.2} hbar but no admin has an email address", balance_hbar);
+        }
+        Ok(true)
+    }
+}
+
+fn to_hbar(tinybars: i64) -> f64 {
+    tinybars as f64 / TINYBARS_PER_HBAR
+}
+
+/// Checks the operator account's balance every `HEDERA_BALANCE_CHECK_MINUTES`
+pub struct HederaBalanceWorker {
+    monitor: Arc<HederaBalanceMonitor>,
+    interval: time::Duration,
+}
+
+impl HederaBalanceWorker {
+    pub fn new(monitor: Arc<HederaBalanceMonitor>, config: &Config) -> Self {
+        Self { monitor, interval: time::Duration::from_secs(config.hedera_costs.balance_check_minutes * 60) }
+    }
+
+    /// Check right away, then on every interval until `shutdown` is cancelled
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = time::interval(self.interval);
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.monitor.check(Utc::now()).await {
+                tracing::error!("Hedera balance check failed: {}", e);
+            }
+        }
+        tracing::info!("Hedera balance worker stopped");
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::{HederaCostConfig, SmtpConfig};
+    use crate::fixtures;
+    use crate::store::{MockEmailOutboxStore, MockPatientStore};
+    use bson::oid::ObjectId;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.3";
+
+    /// Answers each balance query with the next of `hbar`
+    struct StubBalance {
+        hbar: Mutex<Vec<f64>>,
+    }
+
+    #[async_trait]
+    impl OperatorBalance for StubBalance {
+        async fn operator_balance(&self) -> Result<i64> {
+            let hbar = self.hbar.lock().unwrap().remove(0);
+            Ok((hbar * TINYBARS_PER_HBAR) as i64)
+        }
+    }
+
+    fn monitor(hbar: &[f64], funds: Arc<OperatorFunds>, outbox: MockEmailOutboxStore) -> HederaBalanceMonitor {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(Some(fixtures::admin(ADMIN))));
+        let config = Arc::new(Config {
+            admin_dids: vec![ADMIN.to_string()],
+            smtp: Some(SmtpConfig::default()),
+            hedera_costs: HederaCostConfig { balance_alert_hbar: 100.0, ..Default::default() },
+            ..Default::default()
+        });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(outbox)).unwrap());
+        let source = StubBalance { hbar: Mutex::new(hbar.to_vec()) };
+        HederaBalanceMonitor::new(Some(Arc::new(source)), funds, Arc::new(patients), email_service, config)
+    }
+
+    #[tokio::test]
+    async fn admins_are_warned_once_per_drop_below_the_threshold() {
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox
+            .expect_enqueue_email()
+            .withf(|email| email.recipient == "admin@example.com" && email.template == "Hedera-balance-alert.html")
+            .times(2)
+            .returning(|_| Ok(ObjectId::new()));
+        // Against a threshold of 100 hbar, re-armed at 120
+        let balances = [150.0, 99.99, 50.0, 110.0, 99.0, 130.0, 90.0];
+        let monitor = monitor(&balances, Arc::default(), outbox);
+
+        let mut alerts = Vec::new();
+        for _ in balances {
+            alerts.push(monitor.check(Utc::now()).await.unwrap());
+        }
+
+        assert_eq!(alerts, [false, true, false, false, false, false, true]);
+        let snapshot = monitor.snapshot();
+        assert_eq!((snapshot.balance_hbar, snapshot.low, snapshot.exhausted), (Some(90.0), true, false));
+        assert!(monitor.prometheus().contains("hedera_operator_balance_hbar 90\n"));
+    }
+
+    #[tokio::test]
+    async fn the_breaker_stays_open_until_the_balance_pays_for_transactions_again() {
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox.expect_enqueue_email().times(1).returning(|_| Ok(ObjectId::new()));
+        let funds = Arc::new(OperatorFunds::default());
+        let monitor = monitor(&[0.5, 19.0, 25.0], funds.clone(), outbox);
+        funds.trip();
+
+        assert!(monitor.check(Utc::now()).await.unwrap());
+        assert!(funds.is_exhausted());
+        monitor.check(Utc::now()).await.unwrap();
+        assert!(funds.is_exhausted(), "19 hbar does not pay for a contract create");
+        assert!(monitor.prometheus().contains("hedera_operator_funds_exhausted 1\n"));
+
+        monitor.check(Utc::now()).await.unwrap();
+        assert!(!funds.is_exhausted());
+        // Topped up, but still under the alert threshold: no second email
+        assert!(monitor.snapshot().low);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_checked_without_the_real_network() {
+        let config = Arc::new(Config::default());
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(MockEmailOutboxStore::new())).unwrap());
+        let monitor = HederaBalanceMonitor::new(None, Arc::default(), Arc::new(MockPatientStore::new()), email_service, config);
+
+        assert!(!monitor.check(Utc::now()).await.unwrap());
+        assert_eq!(monitor.snapshot().balance_hbar, None);
+        assert!(!monitor.prometheus().contains("hedera_operator_balance_hbar "));
+    }
+}
@@ -0,0 +1,343 @@
+//! What the platform pays Hedera: fees per operation and per day, a projection of the
+//! monthly spend, and a warning to admins before the monthly budget runs out.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::deadline;
+use crate::models::*;
+use crate::services::email::EmailService;
+use crate::store::{HederaCostStore, PatientStore};
+
+const TINYBARS_PER_HBAR: f64 = 100_000_000.0;
+/// The projection extrapolates this many trailing days to a 30-day month
+const PROJECTION_DAYS: i64 = 7;
+const PROJECTED_MONTH_DAYS: f64 = 30.0;
+/// Longest range one report may cover
+const MAX_RANGE_DAYS: i64 = 366;
+const MIRROR_NODE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+const BUDGET_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(24 * 60 * 60);
+
+#[derive(Deserialize)]
+struct ExchangeRateResponse {
+    current_rate: ExchangeRate,
+}
+
+#[derive(Deserialize)]
+struct ExchangeRate {
+    cent_equivalent: f64,
+    hbar_equivalent: f64,
+}
+
+// --- HederaCostService ---
+pub struct HederaCostService {
+    db: Arc<dyn HederaCostStore>,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    http: Client,
+    config: Arc<Config>,
+}
+
+impl HederaCostService {
+    pub fn new(db: Arc<dyn HederaCostStore>, patients: Arc<dyn PatientStore>, email_service: Arc<EmailService>, http: Client, config: Arc<Config>) -> Self {
+        Self { db, patients, email_service, http, config }
+    }
+
+    /// Fees recorded in `[from, to)`, by default the current month so far
+    pub async fn report(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, group_by: HederaCostGroupBy) -> Result<HederaCostReport> {
+        let now = Utc::now();
+        let to = to.unwrap_or(now);
+        let from = from.unwrap_or_else(|| month_start(to));
+        if from >= to {
+            return Err(anyhow!("'from' must be before 'to'"));
+        }
+        if to - from > Duration::days(MAX_RANGE_DAYS) {
+            return Err(anyhow!("Hedera cost reports cover at most {} days at a time", MAX_RANGE_DAYS));
+        }
+
+        let transactions = self.db.hedera_transactions(from, to).await?;
+        let trailing = self.db.hedera_transactions(now - Duration::days(PROJECTION_DAYS), now).await?;
+        let usd_per_hbar = self.usd_per_hbar().await;
+
+        let total_tinybars: i64 = transactions.iter().map(|transaction| transaction.fee_tinybars).sum();
+        let projected_monthly_hbar = project_monthly(trailing.iter().map(|transaction| transaction.fee_tinybars).sum());
+        Ok(HederaCostReport {
+            from,
+            to,
+            group_by,
+            groups: aggregate(&transactions, group_by, usd_per_hbar),
+            transactions: transactions.len() as u64,
+            total_hbar: to_hbar(total_tinybars),
+            total_usd: usd_per_hbar.map(|rate| to_hbar(total_tinybars) * rate),
+            usd_per_hbar,
+            projected_monthly_hbar,
+            projected_monthly_usd: usd_per_hbar.map(|rate| projected_monthly_hbar * rate),
+            monthly_budget_hbar: self.config.hedera_costs.monthly_budget_hbar,
+        })
+    }
+
+    /// Email admins once a month when the month's spend reaches the alert share of the budget.
+    /// Returns whether an alert was sent by this call.
+    pub async fn check_budget(&self, now: DateTime<Utc>) -> Result<bool> {
+        let costs = &self.config.hedera_costs;
+        let Some(budget_hbar) = costs.monthly_budget_hbar else {
+            return Ok(false);
+        };
+        let spent_tinybars: i64 = self.db.hedera_transactions(month_start(now), now).await?.iter().map(|transaction| transaction.fee_tinybars).sum();
+        let spent_hbar = to_hbar(spent_tinybars);
+        if spent_hbar < budget_hbar * f64::from(costs.budget_alert_percent) / 100.0 {
+            return Ok(false);
+        }
+
+        let alert = HederaBudgetAlert { id: None, month: now.format("%Y-%m").to_string(), spent_hbar, budget_hbar, sent_at: now };
+        if !self.db.claim_hedera_budget_alert(&alert).await? {
+            return Ok(false);
+        }
+
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let Some(admin) = self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await? else {
+                continue;
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "month": &alert.month,
+                "spent_hbar": format!("{:.2}", spent_hbar),
+                "budget_hbar": format!("{:.2}", budget_hbar),
+                "percent": (spent_hbar / budget_hbar * 100.0).round(),
+            });
+            self.email_service
+                .enqueue(&email.value, "Hedera spending is nearing the monthly budget", "Hedera-budget-alert.html", &context)
+                .await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("Hedera spending for {} reached {:.2} of {:.2} hbar but no admin has an email address", alert.month, spent_hbar, budget_hbar);
+        }
+        Ok(true)
+    }
+
+    /// The configured rate, or the mirror node's current one; `None` when neither is available
+    async fn usd_per_hbar(&self) -> Option<f64> {
+        let costs = &self.config.hedera_costs;
+        if let Some(rate) = costs.usd_per_hbar {
+            return Some(rate);
+        }
+        let mirror_node_url = costs.mirror_node_url.as_deref()?;
+        match self.fetch_exchange_rate(mirror_node_url).await {
+            Ok(rate) => Some(rate),
+            Err(e) => {
+                tracing::warn!("Failed to fetch the hbar exchange rate: {:#}", e);
+                None
+            }
+        }
+    }
+
+    async fn fetch_exchange_rate(&self, mirror_node_url: &str) -> Result<f64> {
+        let url = format!("{}/api/v1/network/exchangerate", mirror_node_url.trim_end_matches('/'));
+        let response = self.http.get(&url).timeout(deadline::remaining_or(MIRROR_NODE_TIMEOUT)).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Mirror node returned {}", response.status()));
+        }
+        let rate = response.json::<ExchangeRateResponse>().await?.current_rate;
+        if rate.hbar_equivalent <= 0.0 {
+            return Err(anyhow!("Mirror node returned an exchange rate for {} hbar", rate.hbar_equivalent));
+        }
+        Ok(rate.cent_equivalent / rate.hbar_equivalent / 100.0)
+    }
+}
+
+/// Days in order; operations most expensive first
+fn aggregate(transactions: &[HederaTransaction], group_by: HederaCostGroupBy, usd_per_hbar: Option<f64>) -> Vec<HederaCostGroup> {
+    let mut totals: BTreeMap<String, (u64, i64)> = BTreeMap::new();
+    for transaction in transactions {
+        let key = match group_by {
+            HederaCostGroupBy::Operation => transaction.operation.as_str().to_string(),
+            HederaCostGroupBy::Day => transaction.recorded_at.format("%Y-%m-%d").to_string(),
+        };
+        let (count, tinybars) = totals.entry(key).or_default();
+        *count += 1;
+        *tinybars += transaction.fee_tinybars;
+    }
+    let mut groups: Vec<(String, u64, i64)> = totals.into_iter().map(|(key, (count, tinybars))| (key, count, tinybars)).collect();
+    if group_by == HederaCostGroupBy::Operation {
+        groups.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    }
+    groups
+        .into_iter()
+        .map(|(key, transactions, tinybars)| HederaCostGroup {
+            key,
+            transactions,
+            hbar: to_hbar(tinybars),
+            usd: usd_per_hbar.map(|rate| to_hbar(tinybars) * rate),
+        })
+        .collect()
+}
+
+/// Hbar a 30-day month costs when it goes on as the trailing week did
+fn project_monthly(trailing_tinybars: i64) -> f64 {
+    to_hbar(trailing_tinybars) / PROJECTION_DAYS as f64 * PROJECTED_MONTH_DAYS
+}
+
+pub(crate) fn to_hbar(tinybars: i64) -> f64 {
+    tinybars as f64 / TINYBARS_PER_HBAR
+}
+
+/// Midnight UTC on the first day of `at`'s month
+fn month_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    let date = at.date_naive();
+    (date - Duration::days(i64::from(date.day0()))).and_time(NaiveTime::MIN).and_utc()
+}
+
+/// Checks the month's Hedera spend against the budget once a day
+pub struct HederaBudgetWorker {
+    service: Arc<HederaCostService>,
+}
+
+impl HederaBudgetWorker {
+    pub fn new(service: Arc<HederaCostService>) -> Self {
+        Self { service }
+    }
+
+    /// Check daily until `shutdown` is cancelled
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = time::interval(BUDGET_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.service.check_budget(Utc::now()).await {
+                tracing::error!("Hedera budget check failed: {}", e);
+            }
+        }
+        tracing::info!("Hedera budget worker stopped");
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::{HederaCostConfig, SmtpConfig};
+    use crate::fixtures;
+    use crate::store::{MockEmailOutboxStore, MockHederaCostStore, MockPatientStore};
+    use bson::oid::ObjectId;
+    use std::sync::Mutex;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.3";
+
+    fn transaction(operation: HederaOperation, fee_tinybars: i64, recorded_at: &str) -> HederaTransaction {
+        HederaTransaction {
+            id: None,
+            transaction_id: format!("0.0.2@{}", fee_tinybars),
+            operation,
+            fee_tinybars,
+            recorded_at: recorded_at.parse().unwrap(),
+        }
+    }
+
+    fn transactions() -> Vec<HederaTransaction> {
+        vec![
+            transaction(HederaOperation::AuditAnchoring, 5_000_000, "2026-03-01T08:00:00Z"),
+            transaction(HederaOperation::CredentialIssuance, 20_000_000, "2026-03-01T09:00:00Z"),
+            transaction(HederaOperation::AuditAnchoring, 5_000_000, "2026-03-02T08:00:00Z"),
+            transaction(HederaOperation::CredentialIssuance, 30_000_000, "2026-03-03T10:00:00Z"),
+        ]
+    }
+
+    #[test]
+    fn fees_are_summed_per_operation_and_per_day() {
+        let by_operation = aggregate(&transactions(), HederaCostGroupBy::Operation, Some(0.5));
+        assert_eq!(
+            by_operation,
+            vec![
+                HederaCostGroup { key: "credential_issuance".to_string(), transactions: 2, hbar: 0.5, usd: Some(0.25) },
+                HederaCostGroup { key: "audit_anchoring".to_string(), transactions: 2, hbar: 0.1, usd: Some(0.05) },
+            ]
+        );
+
+        let by_day = aggregate(&transactions(), HederaCostGroupBy::Day, None);
+        let days: Vec<(&str, u64, f64)> = by_day.iter().map(|group| (group.key.as_str(), group.transactions, group.hbar)).collect();
+        assert_eq!(days, vec![("2026-03-01", 2, 0.25), ("2026-03-02", 1, 0.05), ("2026-03-03", 1, 0.3)]);
+        assert!(by_day.iter().all(|group| group.usd.is_none()));
+    }
+
+    #[test]
+    fn a_week_of_fees_projects_to_a_thirty_day_month() {
+        assert_eq!(project_monthly(0), 0.0);
+        // 7 hbar over the trailing week is 1 hbar a day
+        assert!((project_monthly(700_000_000) - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn months_start_at_midnight_on_the_first() {
+        let at: DateTime<Utc> = "2026-03-17T15:42:00Z".parse().unwrap();
+        assert_eq!(month_start(at), "2026-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn admins_are_warned_once_a_month_at_the_alert_share_of_the_budget() {
+        let now: DateTime<Utc> = "2026-03-17T12:00:00Z".parse().unwrap();
+        let mut db = MockHederaCostStore::new();
+        // 0.9 hbar spent this month against a budget of 1 hbar
+        db.expect_hedera_transactions()
+            .withf(move |from, to| *from == "2026-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap() && *to == now)
+            .returning(|_, _| Ok(transactions().into_iter().chain([transaction(HederaOperation::CredentialIssuance, 30_000_000, "2026-03-16T10:00:00Z")]).collect()));
+        let claimed = Arc::new(Mutex::new(Vec::new()));
+        let months = claimed.clone();
+        db.expect_claim_hedera_budget_alert().returning(move |alert| {
+            let mut months = months.lock().unwrap();
+            let first = !months.contains(&alert.month);
+            months.push(alert.month.clone());
+            Ok(first)
+        });
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(Some(fixtures::admin(ADMIN))));
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox
+            .expect_enqueue_email()
+            .withf(|email| email.recipient == "admin@example.com" && email.template == "Hedera-budget-alert.html" && email.context["percent"] == 90.0)
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let config = Arc::new(Config {
+            admin_dids: vec![ADMIN.to_string()],
+            smtp: Some(SmtpConfig::default()),
+            hedera_costs: HederaCostConfig { monthly_budget_hbar: Some(1.0), budget_alert_percent: 80, ..Default::default() },
+            ..Default::default()
+        });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(outbox)).unwrap());
+        let service = HederaCostService::new(Arc::new(db), Arc::new(patients), email_service, Client::new(), config);
+
+        assert!(service.check_budget(now).await.unwrap());
+        assert!(!service.check_budget(now).await.unwrap(), "the month was already alerted on");
+        assert_eq!(*claimed.lock().unwrap(), vec!["2026-03", "2026-03"]);
+    }
+
+    #[tokio::test]
+    async fn spend_under_the_alert_share_sends_nothing() {
+        let mut db = MockHederaCostStore::new();
+        db.expect_hedera_transactions().returning(|_, _| Ok(transactions()));
+        db.expect_claim_hedera_budget_alert().never();
+        let config = Arc::new(Config {
+            smtp: Some(SmtpConfig::default()),
+            hedera_costs: HederaCostConfig { monthly_budget_hbar: Some(1.0), budget_alert_percent: 80, ..Default::default() },
+            ..Default::default()
+        });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(MockEmailOutboxStore::new())).unwrap());
+        let service = HederaCostService::new(Arc::new(db), Arc::new(MockPatientStore::new()), email_service, Client::new(), config);
+
+        assert!(!service.check_budget("2026-03-17T12:00:00Z".parse().unwrap()).await.unwrap());
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::models::{IdempotencyRecord, IdempotencyStatus};
+use crate::services::ServiceError;
+use crate::store::IdempotencyStore;
+
+/// Longest `Idempotency-Key` accepted
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// What to do with a request that carries an `Idempotency-Key`
+#[derive(Debug)]
+pub enum IdempotencyClaim {
+    /// This request holds the key: run it, then complete or release the claim
+    Claimed(ObjectId),
+    /// The same request already finished; send back its response
+    Replay(IdempotencyRecord),
+}
+
+// --- IdempotencyService ---
+pub struct IdempotencyService {
+    store: Arc<dyn IdempotencyStore>,
+    ttl: Duration,
+    /// How long a claim is held for a request in progress, beyond which it counts as abandoned
+    lock: Duration,
+}
+
+impl IdempotencyService {
+    pub fn new(store: Arc<dyn IdempotencyStore>, config: &Config) -> Self {
+        // The claim outlives every request the timeouts allow, so it is never taken over while its request runs
+        let lock = config.http.body_timeout_seconds + config.http.request_timeout_seconds.max(config.http.upstream_timeout_seconds);
+        Self {
+            store,
+            ttl: Duration::hours(config.idempotency.ttl_hours),
+            lock: Duration::seconds(lock as i64),
+        }
+    }
+
+    /// SHA-256 over what makes two requests the same one
+    pub fn fingerprint(method: &str, path: &str, body: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(method.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(path.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(body);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Keys are 1 to 255 visible ASCII characters, like the UUIDs clients usually send
+    pub fn is_valid_key(key: &str) -> bool {
+        (1..=MAX_KEY_LENGTH).contains(&key.len()) && key.bytes().all(|byte| byte.is_ascii_graphic())
+    }
+
+    /// Claim `key` for this request, or find the response to replay. A key reused for a
+    /// different request is a 422; a key whose first request is still running is a 409.
+    pub async fn claim(&self, user_did: &str, key: &str, request_hash: &str, now: DateTime<Utc>) -> Result<IdempotencyClaim> {
+        let record = IdempotencyRecord {
+            id: Some(ObjectId::new()),
+            user_did: user_did.to_string(),
+            key: key.to_string(),
+            request_hash: request_hash.to_string(),
+            status: IdempotencyStatus::InProgress,
+            response_status: None,
+            response_content_type: None,
+            response_body: None,
+            locked_until: now + self.lock,
+            expires_at: now + self.ttl,
+            created_at: now,
+        };
+        let Some(existing) = self.store.claim_idempotency_key(&record, now).await? else {
+            return Ok(IdempotencyClaim::Claimed(record.id.expect("the id is set above")));
+        };
+        if existing.request_hash != request_hash {
+            return Err(ServiceError::IdempotencyKeyReused.into());
+        }
+        match existing.status {
+            IdempotencyStatus::InProgress => {
+                Err(ServiceError::Conflict("A request with this Idempotency-Key is still in progress".to_string()).into())
+            }
+            IdempotencyStatus::Completed => Ok(IdempotencyClaim::Replay(existing)),
+        }
+    }
+
+    pub async fn complete(&self, id: ObjectId, status: u16, content_type: Option<&str>, body: &str) -> Result<()> {
+        self.store.complete_idempotency_key(id, status, content_type, body).await
+    }
+
+    pub async fn release(&self, id: ObjectId) -> Result<()> {
+        self.store.release_idempotency_key(id).await
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::MockIdempotencyStore;
+
+    const HASH: &str = "abc123";
+
+    fn existing(request_hash: &str, status: IdempotencyStatus) -> IdempotencyRecord {
+        let now = Utc::now();
+        IdempotencyRecord {
+            id: Some(ObjectId::new()),
+            user_did: "did:hedera:testnet:0.0.9001".to_string(),
+            key: "key-1".to_string(),
+            request_hash: request_hash.to_string(),
+            status,
+            response_status: (status == IdempotencyStatus::Completed).then_some(200),
+            response_content_type: Some("application/json".to_string()),
+            response_body: Some("{\"success\":true}".to_string()),
+            locked_until: now,
+            expires_at: now + Duration::hours(24),
+            created_at: now,
+        }
+    }
+
+    fn service(found: Option<IdempotencyRecord>) -> IdempotencyService {
+        let mut store = MockIdempotencyStore::new();
+        store
+            .expect_claim_idempotency_key()
+            .withf(|record, now| record.status == IdempotencyStatus::InProgress && record.expires_at == *now + Duration::hours(24))
+            .return_once(move |_, _| Ok(found));
+        IdempotencyService::new(Arc::new(store), &Config::default())
+    }
+
+    async fn claim(service: &IdempotencyService) -> Result<IdempotencyClaim> {
+        service.claim("did:hedera:testnet:0.0.9001", "key-1", HASH, Utc::now()).await
+    }
+
+    #[tokio::test]
+    async fn a_new_key_is_claimed() {
+        assert!(matches!(claim(&service(None)).await.unwrap(), IdempotencyClaim::Claimed(_)));
+    }
+
+    #[tokio::test]
+    async fn a_finished_request_is_replayed() {
+        match claim(&service(Some(existing(HASH, IdempotencyStatus::Completed)))).await.unwrap() {
+            IdempotencyClaim::Replay(record) => assert_eq!(record.response_status, Some(200)),
+            other => panic!("expected a replay, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_key_reused_for_another_request_is_refused() {
+        let error = claim(&service(Some(existing("other", IdempotencyStatus::Completed)))).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServiceError>(), Some(ServiceError::IdempotencyKeyReused)));
+    }
+
+    #[tokio::test]
+    async fn a_request_still_running_is_a_conflict() {
+        let error = claim(&service(Some(existing(HASH, IdempotencyStatus::InProgress)))).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[test]
+    fn keys_are_bounded_visible_ascii() {
+        assert!(IdempotencyService::is_valid_key("3f2b8c1e-6d1a-4c7e-9a51-0d6f0b6f1e2a"));
+        assert!(!IdempotencyService::is_valid_key(""));
+        assert!(!IdempotencyService::is_valid_key("has space"));
+        assert!(!IdempotencyService::is_valid_key(&"k".repeat(MAX_KEY_LENGTH + 1)));
+    }
+
+    #[test]
+    fn the_fingerprint_covers_method_path_and_body() {
+        let fingerprint = IdempotencyService::fingerprint("POST", "/api/encounters", b"{}");
+        assert_eq!(fingerprint, IdempotencyService::fingerprint("POST", "/api/encounters", b"{}"));
+        assert_ne!(fingerprint, IdempotencyService::fingerprint("POST", "/api/prescriptions", b"{}"));
+        assert_ne!(fingerprint, IdempotencyService::fingerprint("POST", "/api/encounters", b"{ }"));
+    }
+}
@@ -0,0 +1,399 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{Config, InteractionApiConfig};
+use crate::deadline;
+use crate::models::*;
+use crate::utils::SecretString;
+
+/// RxNorm ingredient pairs known to interact, embedded so the check works without network access
+const BUNDLED_DATASET: &str = include_str!("../data/drug_interactions.json");
+
+/// A medication as interaction sources see it: what it is called and its RxNorm codes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Drug {
+    pub name: String,
+    pub rxcuis: Vec<String>,
+}
+
+impl Drug {
+    pub fn from_request(request: &FhirMedicationRequest) -> Self {
+        let concept = &request.medication_codeable_concept;
+        let name = concept
+            .text
+            .clone()
+            .or_else(|| concept.coding.iter().find_map(|coding| coding.display.clone()))
+            .or_else(|| concept.coding.iter().find_map(|coding| coding.code.clone()))
+            .unwrap_or_default();
+        let rxcuis = concept
+            .coding
+            .iter()
+            .filter(|coding| coding.system.as_deref().is_some_and(|system| system.to_ascii_lowercase().contains("rxnorm")))
+            .filter_map(|coding| coding.code.clone())
+            .collect();
+        Self { name, rxcuis }
+    }
+}
+
+/// Where interaction data comes from: the bundled dataset or a commercial API
+#[async_trait]
+pub trait InteractionSource: Send + Sync {
+    /// Interactions between `drug` and each of `others`, each naming the other drug involved
+    async fn interactions(&self, drug: &Drug, others: &[Drug]) -> Result<Vec<InteractionWarning>>;
+}
+
+#[derive(Deserialize)]
+struct Dataset {
+    /// RxNorm ingredient code to ingredient name
+    ingredients: HashMap<String, String>,
+    pairs: Vec<DatasetPair>,
+}
+
+#[derive(Deserialize)]
+struct DatasetPair {
+    a: String,
+    b: String,
+    severity: InteractionSeverity,
+    description: String,
+}
+
+/// The interaction dataset shipped with the backend, keyed on RxNorm ingredients
+pub struct BundledInteractions {
+    ingredients: HashMap<String, String>,
+    pairs: HashMap<(String, String), (InteractionSeverity, String)>,
+}
+
+impl BundledInteractions {
+    pub fn load() -> Self {
+        Self::parse(BUNDLED_DATASET).expect("the bundled interaction dataset is valid")
+    }
+
+    fn parse(json: &str) -> Result<Self> {
+        let dataset: Dataset = serde_json::from_str(json)?;
+        let mut pairs = HashMap::new();
+        for pair in dataset.pairs {
+            for code in [&pair.a, &pair.b] {
+                if !dataset.ingredients.contains_key(code) {
+                    return Err(anyhow!("Interaction pair names unknown ingredient {}", code));
+                }
+            }
+            pairs.insert(pair_key(&pair.a, &pair.b), (pair.severity, pair.description));
+        }
+        Ok(Self { ingredients: dataset.ingredients, pairs })
+    }
+
+    /// The known ingredients of a drug. Prescriptions are often coded at product level, which
+    /// is not an ingredient code, so ingredients named in the medication's name count too.
+    fn ingredients<'a>(&'a self, drug: &Drug) -> HashSet<&'a str> {
+        let name = drug.name.to_lowercase();
+        let words: HashSet<&str> = name.split(|c: char| !c.is_alphanumeric()).collect();
+        self.ingredients
+            .iter()
+            .filter(|(rxcui, ingredient)| drug.rxcuis.contains(rxcui) || words.contains(ingredient.as_str()))
+            .map(|(rxcui, _)| rxcui.as_str())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl InteractionSource for BundledInteractions {
+    async fn interactions(&self, drug: &Drug, others: &[Drug]) -> Result<Vec<InteractionWarning>> {
+        let ingredients = self.ingredients(drug);
+        let mut warnings = Vec::new();
+        for other in others {
+            for theirs in self.ingredients(other) {
+                for ours in &ingredients {
+                    if let Some((severity, description)) = self.pairs.get(&pair_key(ours, theirs)) {
+                        warnings.push(InteractionWarning {
+                            kind: InteractionKind::DrugInteraction,
+                            severity: *severity,
+                            description: description.clone(),
+                            interacting_drug: other.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+fn pair_key(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct CheckRequest<'a> {
+    drug: &'a Drug,
+    others: &'a [Drug],
+}
+
+#[derive(Deserialize)]
+struct CheckResponse {
+    interactions: Vec<ApiInteraction>,
+}
+
+#[derive(Deserialize)]
+struct ApiInteraction {
+    /// Name of the drug from `others` involved
+    drug: String,
+    severity: InteractionSeverity,
+    description: String,
+}
+
+/// A commercial interaction API taking the new drug and the active ones in a single POST
+pub struct HttpInteractions {
+    http: Client,
+    url: String,
+    api_key: Option<SecretString>,
+    timeout: Duration,
+}
+
+impl HttpInteractions {
+    pub fn new(config: &InteractionApiConfig, http: Client) -> Self {
+        Self {
+            http,
+            url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            timeout: Duration::from_secs(config.timeout_seconds),
+        }
+    }
+}
+
+#[async_trait]
+impl InteractionSource for HttpInteractions {
+    async fn interactions(&self, drug: &Drug, others: &[Drug]) -> Result<Vec<InteractionWarning>> {
+        let mut request = self.http.post(&self.url).timeout(deadline::remaining_or(self.timeout)).json(&CheckRequest { drug, others });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key.expose_secret());
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Interaction API returned {}", response.status()));
+        }
+        let checked: CheckResponse = response.json().await?;
+        Ok(checked
+            .interactions
+            .into_iter()
+            .map(|interaction| InteractionWarning {
+                kind: InteractionKind::DrugInteraction,
+                severity: interaction.severity,
+                description: interaction.description,
+                interacting_drug: interaction.drug,
+            })
+            .collect())
+    }
+}
+
+/// What checking a new medication against the patient's active ones found
+#[derive(Debug, Clone, Default)]
+pub struct InteractionReport {
+    /// Most severe first
+    pub warnings: Vec<InteractionWarning>,
+    /// False when the interaction source failed and only duplicates were checked
+    pub complete: bool,
+}
+
+impl InteractionReport {
+    pub fn major(&self) -> impl Iterator<Item = &InteractionWarning> {
+        self.warnings.iter().filter(|warning| warning.severity == InteractionSeverity::Major)
+    }
+}
+
+// --- InteractionChecker ---
+pub struct InteractionChecker {
+    /// Always the bundled dataset, used to spot duplicates by ingredient
+    dataset: Arc<BundledInteractions>,
+    source: Arc<dyn InteractionSource>,
+}
+
+impl InteractionChecker {
+    pub fn bundled() -> Self {
+        let dataset = Arc::new(BundledInteractions::load());
+        Self { source: dataset.clone(), dataset }
+    }
+
+    pub fn with_source(source: Arc<dyn InteractionSource>) -> Self {
+        Self { dataset: Arc::new(BundledInteractions::load()), source }
+    }
+
+    /// The commercial API when one is configured, the bundled dataset otherwise
+    pub fn from_config(config: &Config) -> Self {
+        match &config.interaction_api {
+            Some(api) => Self::with_source(Arc::new(HttpInteractions::new(api, Client::new()))),
+            None => Self::bundled(),
+        }
+    }
+
+    /// Check `medication` against the patient's `active` prescriptions. This never fails: a
+    /// source that is down is logged and the report marked incomplete, so prescribing goes on.
+    pub async fn check(&self, medication: &FhirMedicationRequest, active: &[Prescription]) -> InteractionReport {
+        let drug = Drug::from_request(medication);
+        let others: Vec<Drug> = active.iter().map(|prescription| Drug::from_request(&prescription.fhir_medication_request)).collect();
+
+        let mut warnings: Vec<InteractionWarning> = others
+            .iter()
+            .filter(|other| self.is_duplicate(&drug, other))
+            .map(|other| InteractionWarning {
+                kind: InteractionKind::DuplicateTherapy,
+                severity: InteractionSeverity::Moderate,
+                description: format!("The patient already has an active prescription for {}", other.name),
+                interacting_drug: other.name.clone(),
+            })
+            .collect();
+        let complete = match self.source.interactions(&drug, &others).await {
+            Ok(found) => {
+                warnings.extend(found);
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Drug interaction check unavailable, prescribing without it: {}", e);
+                false
+            }
+        };
+        warnings.sort_by(|a, b| b.severity.cmp(&a.severity));
+        InteractionReport { warnings, complete }
+    }
+
+    fn is_duplicate(&self, drug: &Drug, other: &Drug) -> bool {
+        drug.rxcuis.iter().any(|rxcui| other.rxcuis.contains(rxcui))
+            || (!drug.name.is_empty() && drug.name.eq_ignore_ascii_case(&other.name))
+            || !self.dataset.ingredients(drug).is_disjoint(&self.dataset.ingredients(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fhir::FhirManager;
+    use chrono::Utc;
+    use serde_json::json;
+    use wiremock::matchers::{bearer_token, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const RXNORM: &str = "http://www.nlm.nih.gov/research/umls/rxnorm";
+
+    fn medication(text: &str, rxcui: Option<&str>) -> FhirMedicationRequest {
+        let coding = rxcui
+            .map(|code| FhirCoding { system: Some(RXNORM.to_string()), code: Some(code.to_string()), display: None })
+            .into_iter()
+            .collect();
+        FhirManager::create_medication_request("did:hedera:testnet:0.0.1", "did:hedera:testnet:0.0.2", None, FhirCodeableConcept { coding, text: Some(text.to_string()) }, vec![])
+    }
+
+    fn active(text: &str, rxcui: Option<&str>) -> Prescription {
+        Prescription {
+            id: None,
+            patient_did: "did:hedera:testnet:0.0.1".to_string(),
+            practitioner_did: "did:hedera:testnet:0.0.2".to_string(),
+            fhir_medication_request: medication(text, rxcui),
+            status: PrescriptionStatus::Active,
+            dispenses: vec![],
+            fully_dispensed: false,
+            credential: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn api(server: &MockServer) -> InteractionChecker {
+        let config = InteractionApiConfig { url: format!("{}/check", server.uri()), api_key: Some("key".into()), timeout_seconds: 1 };
+        InteractionChecker::with_source(Arc::new(HttpInteractions::new(&config, Client::new())))
+    }
+
+    #[test]
+    fn bundled_dataset_loads() {
+        let dataset = BundledInteractions::load();
+        assert!(!dataset.pairs.is_empty());
+
+        let unknown = r#"{ "ingredients": { "1191": "aspirin" }, "pairs": [{ "a": "1191", "b": "11289", "severity": "major", "description": "" }] }"#;
+        assert!(BundledInteractions::parse(unknown).is_err());
+    }
+
+    #[tokio::test]
+    async fn finds_interactions_by_code_or_name() {
+        let checker = InteractionChecker::bundled();
+        let warfarin = [active("Warfarin 5mg tablets", Some("11289"))];
+
+        let report = checker.check(&medication("Low-dose aspirin", Some("1191")), &warfarin).await;
+        assert!(report.complete);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].kind, InteractionKind::DrugInteraction);
+        assert_eq!(report.warnings[0].severity, InteractionSeverity::Major);
+        assert_eq!(report.warnings[0].interacting_drug, "Warfarin 5mg tablets");
+
+        // Product-level codes are not ingredients, so the name has to carry it
+        let report = checker.check(&medication("Ibuprofen 400mg tablets", Some("310965")), &warfarin).await;
+        assert_eq!(report.major().count(), 1);
+
+        let report = checker.check(&medication("Trimethoprim 200mg", None), &warfarin).await;
+        assert_eq!(report.warnings[0].severity, InteractionSeverity::Moderate);
+        assert_eq!(report.major().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn unrelated_medications_pass() {
+        let checker = InteractionChecker::bundled();
+        let active = [active("Omeprazole 20mg", None), active("Lisinopril 10mg", None)];
+
+        let report = checker.check(&medication("Paracetamol 500mg", None), &active).await;
+
+        assert!(report.complete);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn same_ingredient_is_duplicate_therapy() {
+        let checker = InteractionChecker::bundled();
+        let active = [active("Amoxicillin 250mg capsules", None), active("Simvastatin 40mg", None)];
+
+        let report = checker.check(&medication("amoxicillin 500 mg", None), &active).await;
+
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].kind, InteractionKind::DuplicateTherapy);
+        assert_eq!(report.warnings[0].interacting_drug, "Amoxicillin 250mg capsules");
+    }
+
+    #[tokio::test]
+    async fn warnings_come_from_the_api_when_configured() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/check"))
+            .and(bearer_token("key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "interactions": [{ "drug": "Sertraline 50mg", "severity": "major", "description": "Serotonin syndrome" }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let report = api(&server).check(&medication("Linezolid 600mg", None), &[active("Sertraline 50mg", None)]).await;
+
+        assert!(report.complete);
+        assert_eq!(report.major().map(|warning| warning.description.as_str()).collect::<Vec<_>>(), ["Serotonin syndrome"]);
+    }
+
+    #[tokio::test]
+    async fn an_unavailable_api_only_warns() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(503)).mount(&server).await;
+
+        let report = api(&server)
+            .check(&medication("Warfarin 3mg", None), &[active("Aspirin 75mg", None), active("Warfarin 5mg", None)])
+            .await;
+
+        // The bundled pair is not consulted, but duplicates are still caught locally
+        assert!(!report.complete);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].kind, InteractionKind::DuplicateTherapy);
+    }
+}
@@ -0,0 +1,197 @@
+//! Turning away addresses the [`FailedLoginDetector`] has blocked, with auditing, an admin view
+//! and persistence across restarts.
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::auditing::AuditLogService;
+use crate::config::LoginProtectionConfig;
+use crate::models::*;
+use crate::services::login_anomaly::{Clock, FailedLoginDetector};
+use crate::services::ServiceError;
+use crate::store::LoginActivityStore;
+
+/// How often the detector's state is saved and expired blocks are lifted
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+// --- IpBlockService ---
+pub struct IpBlockService {
+    detector: FailedLoginDetector,
+    store: Arc<dyn LoginActivityStore>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl IpBlockService {
+    pub fn new(
+        store: Arc<dyn LoginActivityStore>,
+        config: &LoginProtectionConfig,
+        clock: Arc<dyn Clock>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { detector: FailedLoginDetector::new(config, clock), store, audit_log_service }
+    }
+
+    /// Pick up the state saved before a restart. Without it detection simply starts over.
+    pub async fn load(&self) {
+        match self.store.load_login_activity().await {
+            Ok(saved) => self.detector.restore(saved),
+            Err(e) => tracing::error!("Failed to load saved sign-in failures; starting without them: {}", e),
+        }
+    }
+
+    /// Refuse sign-in attempts from a blocked address
+    pub fn check(&self, ip: IpAddr) -> Result<()> {
+        match self.detector.blocked(ip) {
+            Some(block) => Err(ServiceError::RateLimited(format!(
+                "Too many failed sign-ins from your network. Please try again after {}.",
+                block.until.format("%H:%M UTC")
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Count a failed sign-in to `subject`; requests of unknown origin are not counted
+    pub async fn record_failure(&self, ip: Option<IpAddr>, subject: &str) {
+        let Some(block) = ip.and_then(|ip| self.detector.record_failure(ip, subject)) else {
+            return;
+        };
+        tracing::warn!("Blocked {} after {} failed sign-ins to {} accounts", block.ip, block.failures, block.accounts);
+        let details = json!({ "failures": block.failures, "accounts": block.accounts, "until": block.until });
+        self.audit_log_service.log(&ip_subject(&block.ip), "ip_blocked", Some(details)).await;
+    }
+
+    pub fn blocks(&self) -> Vec<IpBlock> {
+        self.detector.blocks()
+    }
+
+    pub async fn unblock(&self, admin_did: &str, ip: &str) -> Result<IpBlock> {
+        let address: IpAddr = ip.parse().map_err(|_| anyhow!("'{}' is not an IP address", ip))?;
+        let block = self.detector.unblock(address).ok_or_else(|| anyhow!("{} is not blocked", ip))?;
+        self.audit_log_service
+            .log(&ip_subject(&block.ip), "ip_unblocked", Some(json!({ "actor": admin_did, "reason": "manual" })))
+            .await;
+        // Saved straight away so a restart does not bring the block back
+        if let Err(e) = self.persist().await {
+            tracing::error!("Failed to save sign-in failures after unblocking {}: {}", ip, e);
+        }
+        Ok(block)
+    }
+
+    /// Lift blocks that have run out and save what is left
+    pub async fn persist(&self) -> Result<()> {
+        for block in self.detector.sweep() {
+            self.audit_log_service.log(&ip_subject(&block.ip), "ip_unblocked", Some(json!({ "reason": "expired" }))).await;
+        }
+        self.store.replace_login_activity(&self.detector.snapshot()).await
+    }
+}
+
+/// Audit subject for events about an address rather than an account
+fn ip_subject(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Periodically saves the detector's state and lifts expired blocks
+pub struct LoginActivityPersister {
+    service: Arc<IpBlockService>,
+}
+
+impl LoginActivityPersister {
+    pub fn new(service: Arc<IpBlockService>) -> Self {
+        Self { service }
+    }
+
+    /// Run until `shutdown` is cancelled, saving once more on the way out
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = time::interval(PERSIST_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.service.persist().await {
+                tracing::error!("Failed to save sign-in failures: {}", e);
+            }
+        }
+        if let Err(e) = self.service.persist().await {
+            tracing::error!("Failed to save sign-in failures on shutdown: {}", e);
+        }
+        tracing::info!("Sign-in failure persister stopped");
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAuditStore, MockLoginActivityStore};
+    use chrono::{DateTime, Utc};
+    use std::sync::Mutex;
+
+    struct FixedClock(DateTime<Utc>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    fn service(store: MockLoginActivityStore) -> (IpBlockService, Arc<Mutex<Vec<String>>>) {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let mut audit_store = MockAuditStore::new();
+        let logged = actions.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(format!("{} {}", log.action, log.did));
+            Ok(())
+        });
+        let config = LoginProtectionConfig { max_accounts: 2, ..Default::default() };
+        let clock = Arc::new(FixedClock("2026-03-02T09:00:00Z".parse().unwrap()));
+        let service = IpBlockService::new(Arc::new(store), &config, clock, Arc::new(AuditLogService::new(Arc::new(audit_store))));
+        (service, actions)
+    }
+
+    #[tokio::test]
+    async fn blocks_are_audited_and_lifted_by_an_admin() {
+        let mut store = MockLoginActivityStore::new();
+        store.expect_replace_login_activity().returning(|_| Ok(()));
+        let (service, actions) = service(store);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        service.record_failure(Some(ip), "phone:a").await;
+        service.record_failure(None, "phone:b").await;
+        assert!(service.check(ip).is_ok());
+        service.record_failure(Some(ip), "phone:b").await;
+        let err = service.check(ip).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::RateLimited(_))));
+        assert_eq!(service.blocks().len(), 1);
+
+        assert!(service.unblock("did:hedera:testnet:0.0.100", "203.0.113.8").await.is_err());
+        service.unblock("did:hedera:testnet:0.0.100", "203.0.113.7").await.unwrap();
+        assert!(service.check(ip).is_ok());
+        assert_eq!(*actions.lock().unwrap(), vec!["ip_blocked ip:203.0.113.7", "ip_unblocked ip:203.0.113.7"]);
+    }
+
+    #[tokio::test]
+    async fn detection_fails_open_when_storage_is_down() {
+        let mut store = MockLoginActivityStore::new();
+        store.expect_load_login_activity().returning(|| Err(anyhow!("connection refused")));
+        store.expect_replace_login_activity().returning(|_| Err(anyhow!("connection refused")));
+        let (service, _) = service(store);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        service.load().await;
+        assert!(service.check(ip).is_ok());
+        assert!(service.persist().await.is_err());
+        assert!(service.check(ip).is_ok(), "a failed save blocks nobody");
+
+        // Detection itself carries on in memory
+        service.record_failure(Some(ip), "phone:a").await;
+        service.record_failure(Some(ip), "phone:b").await;
+        assert!(service.check(ip).is_err());
+    }
+}
@@ -1,8 +1,13 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::stream::{self, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::deadline;
+use crate::utils::chunked::ByteStream;
+
 #[derive(Debug, Clone)]
 pub struct IpfsClient {
     client: Client,
@@ -22,6 +27,64 @@ pub struct IpfsPinResponse {
     pub progress: Option<u32>,
 }
 
+/// `pin/ls` for one path: the pinned CIDs and their pin type
+#[derive(Debug, Deserialize)]
+pub struct IpfsPinListing {
+    #[serde(rename = "Keys")]
+    pub keys: HashMap<String, serde_json::Value>,
+}
+
+/// Content-addressed blob storage as used by the services.
+///
+/// Implemented by [`IpfsClient`]; tests use the in-memory fake from `services::fakes`.
+/// During a request, IPFS calls give up with a 504 when the request's time is up; a ranged
+/// read is only bounded until the node starts answering, as the download outlives the handler.
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    /// Store `content` and return its content identifier
+    async fn add_file(&self, content: &[u8], filename: Option<&str>) -> Result<String>;
+    /// Store the `length` bytes `content` yields, sending them as they are produced
+    async fn add_stream(&self, content: ByteStream, length: u64, filename: Option<&str>) -> Result<String>;
+    async fn get_file(&self, hash: &str) -> Result<Vec<u8>>;
+    /// Stream `length` bytes of an object starting at `offset`, or the rest of it when `length` is `None`
+    async fn get_range(&self, hash: &str, offset: u64, length: Option<u64>) -> Result<ByteStream>;
+    async fn pin_add(&self, hash: &str) -> Result<Vec<String>>;
+    async fn pin_rm(&self, hash: &str) -> Result<Vec<String>>;
+    /// Whether the node holds a recursive pin on `hash`
+    async fn is_pinned(&self, hash: &str) -> Result<bool>;
+}
+
+#[async_trait]
+impl ObjectStorage for IpfsClient {
+    async fn add_file(&self, content: &[u8], filename: Option<&str>) -> Result<String> {
+        deadline::bound(IpfsClient::add_file(self, content, filename)).await
+    }
+
+    async fn add_stream(&self, content: ByteStream, length: u64, filename: Option<&str>) -> Result<String> {
+        deadline::bound(IpfsClient::add_stream(self, content, length, filename)).await
+    }
+
+    async fn get_file(&self, hash: &str) -> Result<Vec<u8>> {
+        deadline::bound(IpfsClient::get_file(self, hash)).await
+    }
+
+    async fn get_range(&self, hash: &str, offset: u64, length: Option<u64>) -> Result<ByteStream> {
+        deadline::bound(IpfsClient::get_range(self, hash, offset, length)).await
+    }
+
+    async fn pin_add(&self, hash: &str) -> Result<Vec<String>> {
+        deadline::bound(IpfsClient::pin_add(self, hash)).await
+    }
+
+    async fn pin_rm(&self, hash: &str) -> Result<Vec<String>> {
+        deadline::bound(IpfsClient::pin_rm(self, hash)).await
+    }
+
+    async fn is_pinned(&self, hash: &str) -> Result<bool> {
+        deadline::bound(IpfsClient::is_pinned(self, hash)).await
+    }
+}
+
 impl IpfsClient {
     pub fn new(base_url: &str) -> Self {
         Self {
@@ -31,11 +94,23 @@ impl IpfsClient {
     }
 
     /// Add a file to IPFS
+    #[tracing::instrument(name = "ipfs.add", skip_all, fields(otel.kind = "client", ipfs.size = content.len(), ipfs.cid = tracing::field::Empty))]
     pub async fn add_file(&self, content: &[u8], filename: Option<&str>) -> Result<String> {
+        self.add_part(reqwest::multipart::Part::bytes(content.to_vec()), filename).await
+    }
+
+    /// Add a file to IPFS from a stream of `length` bytes, which are sent as they arrive
+    #[tracing::instrument(name = "ipfs.add", skip_all, fields(otel.kind = "client", ipfs.size = length, ipfs.cid = tracing::field::Empty))]
+    pub async fn add_stream(&self, content: ByteStream, length: u64, filename: Option<&str>) -> Result<String> {
+        let part = reqwest::multipart::Part::stream_with_length(reqwest::Body::wrap_stream(content), length);
+        self.add_part(part, filename).await
+    }
+
+    async fn add_part(&self, file: reqwest::multipart::Part, filename: Option<&str>) -> Result<String> {
         let url = format!("{}/api/v0/add", self.base_url);
         
         let mut form = reqwest::multipart::Form::new()
-            .part("file", reqwest::multipart::Part::bytes(content.to_vec()));
+            .part("file", file);
         
         if let Some(name) = filename {
             form = form.part("filename", reqwest::multipart::Part::text(name.to_string()));
@@ -52,6 +127,7 @@ impl IpfsClient {
         }
 
         let ipfs_response: IpfsResponse = response.json().await?;
+        tracing::Span::current().record("ipfs.cid", ipfs_response.hash.as_str());
         Ok(ipfs_response.hash)
     }
 
@@ -62,6 +138,7 @@ impl IpfsClient {
     }
 
     /// Retrieve a file from IPFS
+    #[tracing::instrument(name = "ipfs.cat", skip_all, fields(otel.kind = "client", ipfs.cid = hash))]
     pub async fn get_file(&self, hash: &str) -> Result<Vec<u8>> {
         let url = format!("{}/api/v0/cat/{}", self.base_url, hash);
         
@@ -78,6 +155,36 @@ impl IpfsClient {
         Ok(content.to_vec())
     }
 
+    /// Stream part of a file from IPFS as it arrives, without buffering it
+    #[tracing::instrument(name = "ipfs.cat", skip_all, fields(otel.kind = "client", ipfs.cid = hash, ipfs.offset = offset, ipfs.length = length))]
+    pub async fn get_range(&self, hash: &str, offset: u64, length: Option<u64>) -> Result<ByteStream> {
+        let url = format!("{}/api/v0/cat/{}", self.base_url, hash);
+        let mut query = vec![("offset", offset.to_string())];
+        if let Some(length) = length {
+            query.push(("length", length.to_string()));
+        }
+
+        let response = self.client
+            .post(&url)
+            .query(&query)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("IPFS get failed: {}", response.status()));
+        }
+
+        let chunks = stream::unfold(Some(response), |response| async move {
+            let mut response = response?;
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        });
+        Ok(chunks.boxed())
+    }
+
     /// Retrieve and parse a JSON object from IPFS
     pub async fn get_json<T: for<'de> Deserialize<'de>>(&self, hash: &str) -> Result<T> {
         let content = self.get_file(hash).await?;
@@ -86,6 +193,7 @@ impl IpfsClient {
     }
 
     /// Pin a file to IPFS (ensure it stays available)
+    #[tracing::instrument(name = "ipfs.pin_add", skip_all, fields(otel.kind = "client", ipfs.cid = hash))]
     pub async fn pin_add(&self, hash: &str) -> Result<Vec<String>> {
         let url = format!("{}/api/v0/pin/add/{}", self.base_url, hash);
         
@@ -103,6 +211,7 @@ impl IpfsClient {
     }
 
     /// Unpin a file from IPFS
+    #[tracing::instrument(name = "ipfs.pin_rm", skip_all, fields(otel.kind = "client", ipfs.cid = hash))]
     pub async fn pin_rm(&self, hash: &str) -> Result<Vec<String>> {
         let url = format!("{}/api/v0/pin/rm/{}", self.base_url, hash);
         
@@ -140,6 +249,28 @@ impl IpfsClient {
         Ok(pin_list)
     }
 
+    /// Whether `hash` is pinned recursively. The node answers an error naming the path as
+    /// "not pinned" when it is not, which is no failure here.
+    pub async fn is_pinned(&self, hash: &str) -> Result<bool> {
+        let url = format!("{}/api/v0/pin/ls/{}?type=recursive", self.base_url, hash);
+
+        let response = self.client
+            .post(&url)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            let listing: IpfsPinListing = response.json().await?;
+            return Ok(listing.keys.contains_key(hash));
+        }
+        let body = response.text().await.unwrap_or_default();
+        if body.contains("not pinned") {
+            return Ok(false);
+        }
+        Err(anyhow::anyhow!("IPFS pin ls failed: {}", status))
+    }
+
     /// Get file information
     pub async fn stat(&self, hash: &str) -> Result<IpfsResponse> {
         let url = format!("{}/api/v0/object/stat/{}", self.base_url, hash);
@@ -0,0 +1,118 @@
+//! The registry of who may issue which credentials through the platform.
+
+use anyhow::anyhow;
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::ServiceError;
+use crate::store::IssuerStore;
+
+// --- IssuerRegistryService ---
+pub struct IssuerRegistryService {
+    db: Arc<dyn IssuerStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl IssuerRegistryService {
+    pub fn new(db: Arc<dyn IssuerStore>, config: Arc<Config>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, config, audit_log_service }
+    }
+
+    pub async fn register(&self, admin_did: &str, request: RegisterIssuerRequest) -> anyhow::Result<TrustedIssuer> {
+        let did = request.did.trim();
+        if !did.starts_with("did:") {
+            return Err(anyhow!("An issuer is registered by its DID"));
+        }
+        let mut issuer = TrustedIssuer {
+            id: None,
+            did: did.to_string(),
+            display_name: display_name(&request.display_name)?,
+            credential_types: credential_types(request.credential_types)?,
+            status: IssuerStatus::Active,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let id = self.db.create_issuer(&issuer).await.map_err(|e| match e.downcast_ref::<DatabaseError>() {
+            Some(DatabaseError::DuplicateKey(_)) => ServiceError::Conflict("That DID is already a registered issuer".to_string()).into(),
+            _ => e,
+        })?;
+        issuer.id = Some(id);
+        self.audit_log_service
+            .log(admin_did, "register_issuer", Some(json!({ "issuer_did": issuer.did, "credential_types": issuer.credential_types })))
+            .await;
+        Ok(issuer)
+    }
+
+    pub async fn get(&self, did: &str) -> anyhow::Result<TrustedIssuer> {
+        self.find(did).await?.ok_or_else(|| anyhow!("Issuer not found"))
+    }
+
+    /// The registered issuer with this DID, whatever its status
+    pub async fn find(&self, did: &str) -> anyhow::Result<Option<TrustedIssuer>> {
+        self.db.get_issuer(did).await
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<TrustedIssuer>> {
+        self.db.list_issuers().await
+    }
+
+    /// Rename an issuer, change what it may issue, or suspend or reinstate it
+    pub async fn update(&self, admin_did: &str, did: &str, request: UpdateIssuerRequest) -> anyhow::Result<TrustedIssuer> {
+        let mut issuer = self.get(did).await?;
+        if let Some(name) = request.display_name {
+            issuer.display_name = display_name(&name)?;
+        }
+        if let Some(types) = request.credential_types {
+            issuer.credential_types = credential_types(types)?;
+        }
+        if let Some(status) = request.status {
+            issuer.status = status;
+        }
+        let updated = self.db.update_issuer(&issuer).await?.ok_or_else(|| anyhow!("Issuer not found"))?;
+        self.audit_log_service
+            .log(
+                admin_did,
+                "update_issuer",
+                Some(json!({ "issuer_did": updated.did, "status": updated.status, "credential_types": updated.credential_types })),
+            )
+            .await;
+        Ok(updated)
+    }
+
+    /// Whether `did` is a trusted issuer: an active registered one, or the platform itself
+    pub async fn is_registered(&self, did: &str) -> anyhow::Result<bool> {
+        if self.config.credential_signing.as_ref().is_some_and(|signing| signing.issuer_did == did) {
+            return Ok(true);
+        }
+        Ok(self.find(did).await?.is_some_and(|issuer| issuer.status == IssuerStatus::Active))
+    }
+}
+
+fn display_name(name: &str) -> anyhow::Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(anyhow!("An issuer needs a display name"));
+    }
+    Ok(name.to_string())
+}
+
+/// The types trimmed and without repeats; at least one is needed
+fn credential_types(types: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut allowed: Vec<String> = Vec::new();
+    for credential_type in types {
+        let credential_type = credential_type.trim();
+        if !credential_type.is_empty() && !allowed.iter().any(|known| known == credential_type) {
+            allowed.push(credential_type.to_string());
+        }
+    }
+    if allowed.is_empty() {
+        return Err(anyhow!("Name at least one credential type the issuer may issue"));
+    }
+    Ok(allowed)
+}
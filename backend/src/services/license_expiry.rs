@@ -0,0 +1,401 @@
+//! Practitioner licenses that are about to expire or have. A daily job warns practitioners
+//! [`WARNING_DAYS`] before their license expires, and once the date has passed clears the
+//! license's verification, which the prescribing and credential guards check. Both the
+//! practitioner and the admins of the organizations they work with are emailed.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate, Utc};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::services::email::EmailService;
+use crate::store::{OrganizationStore, PractitionerStore};
+
+/// Recurring job that warns about and revokes expiring licenses
+pub const LICENSE_EXPIRY_JOB: &str = "license_expiry";
+/// How long before its expiry date a license holder is warned
+pub const WARNING_DAYS: i64 = 30;
+/// Longest window the ops dashboard may ask about
+pub const MAX_WINDOW_DAYS: i64 = 365;
+
+/// What a sweep did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LicenseSweep {
+    pub warned: usize,
+    pub revoked: usize,
+}
+
+// --- LicenseExpiryService ---
+pub struct LicenseExpiryService {
+    practitioners: Arc<dyn PractitionerStore>,
+    organizations: Arc<dyn OrganizationStore>,
+    email_service: Arc<EmailService>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl LicenseExpiryService {
+    pub fn new(
+        practitioners: Arc<dyn PractitionerStore>,
+        organizations: Arc<dyn OrganizationStore>,
+        email_service: Arc<EmailService>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { practitioners, organizations, email_service, audit_log_service }
+    }
+
+    /// Verified licenses expiring within `days` of `today`, soonest first, for the ops dashboard
+    pub async fn expiring(&self, admin_did: &str, days: i64, today: NaiveDate) -> Result<Vec<ExpiringLicense>> {
+        let days = days.clamp(0, MAX_WINDOW_DAYS);
+        let licenses = expiring_licenses(&self.practitioners.licensed_practitioners().await?, today, days);
+        self.audit_log_service.log(admin_did, "admin_list_expiring_licenses", Some(json!({ "actor": admin_did, "days": days }))).await;
+        Ok(licenses)
+    }
+
+    /// Warn the holders of licenses expiring within [`WARNING_DAYS`] of `today`, once per
+    /// expiry date, and revoke the verification of licenses that expired before `today` or
+    /// whose expiry date cannot be read
+    pub async fn sweep(&self, today: NaiveDate) -> Result<LicenseSweep> {
+        let mut sweep = LicenseSweep::default();
+        for practitioner in self.practitioners.licensed_practitioners().await? {
+            let license = &practitioner.license_verification;
+            match license.expiry() {
+                Some(expiry) if expiry >= today => {
+                    let due = expiry - today <= Duration::days(WARNING_DAYS) && license.expiry_warning_sent_at.is_none();
+                    if due && self.practitioners.claim_license_expiry_warning(&practitioner.did, &license.expiry_date, Utc::now()).await? {
+                        self.warn(&practitioner, expiry, today).await?;
+                        sweep.warned += 1;
+                    }
+                }
+                expiry => {
+                    if self.practitioners.revoke_license_verification(&practitioner.did).await? {
+                        self.revoke(&practitioner, expiry).await?;
+                        sweep.revoked += 1;
+                    }
+                }
+            }
+        }
+        if sweep != LicenseSweep::default() {
+            tracing::info!("Warned {} practitioner(s) of expiring licenses and revoked {} expired license(s)", sweep.warned, sweep.revoked);
+        }
+        Ok(sweep)
+    }
+
+    async fn warn(&self, practitioner: &Practitioner, expiry: NaiveDate, today: NaiveDate) -> Result<()> {
+        let license = &practitioner.license_verification;
+        let context = json!({
+            "practitioner_name": display_name(practitioner),
+            "license_number": license.license_number,
+            "issuing_authority": license.issuing_authority,
+            "expiry_date": expiry.to_string(),
+            "days_remaining": (expiry - today).num_days(),
+        });
+        let notified = self.email(practitioner, "Your practice license expires soon", "License-expiring.html", context).await?;
+        self.audit_log_service
+            .log(&practitioner.did, "license_expiry_warning", Some(json!({ "expiry_date": expiry.to_string(), "notified": notified })))
+            .await;
+        Ok(())
+    }
+
+    async fn revoke(&self, practitioner: &Practitioner, expiry: Option<NaiveDate>) -> Result<()> {
+        let license = &practitioner.license_verification;
+        let context = json!({
+            "practitioner_name": display_name(practitioner),
+            "license_number": license.license_number,
+            "issuing_authority": license.issuing_authority,
+            "expiry_date": expiry.map(|expiry| expiry.to_string()),
+        });
+        let notified = self.email(practitioner, "A practice license is no longer verified", "License-expired.html", context).await?;
+        self.audit_log_service
+            .log(
+                &practitioner.did,
+                "license_expired",
+                Some(json!({
+                    "license_number": license.license_number,
+                    "expiry_date": license.expiry_date,
+                    "reason": if expiry.is_some() { "expired" } else { "unreadable_expiry_date" },
+                    "notified": notified,
+                })),
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Email the practitioner and the admins of the organizations they currently work with,
+    /// returning the DIDs of those who have an email address
+    async fn email(&self, practitioner: &Practitioner, subject: &str, template: &str, context: serde_json::Value) -> Result<Vec<String>> {
+        let mut recipients = vec![practitioner.clone()];
+        for admin_did in self.organization_admins(&practitioner.did).await? {
+            if let Some(admin) = self.practitioners.get_practitioner_by_did(&admin_did).await? {
+                recipients.push(admin);
+            }
+        }
+
+        let mut notified = Vec::new();
+        for recipient in recipients {
+            let Some(email) = recipient.fhir_practitioner.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let mut context = context.clone();
+            context["username"] = json!(display_name(&recipient));
+            context["own"] = json!(recipient.did == practitioner.did);
+            self.email_service.enqueue(&email.value, subject, template, &context).await;
+            notified.push(recipient.did);
+        }
+        Ok(notified)
+    }
+
+    async fn organization_admins(&self, practitioner_did: &str) -> Result<BTreeSet<String>> {
+        let now = Utc::now();
+        let mut admins = BTreeSet::new();
+        for affiliation in self.organizations.active_affiliations(practitioner_did).await? {
+            if !affiliation.is_current(now) {
+                continue;
+            }
+            for member in self.organizations.list_affiliations(affiliation.organization_id).await? {
+                if member.role == AffiliationRole::Admin && member.is_current(now) && member.practitioner_did != practitioner_did {
+                    admins.insert(member.practitioner_did);
+                }
+            }
+        }
+        Ok(admins)
+    }
+}
+
+/// The licenses of `practitioners` expiring within `days` of `today`, soonest first. Licenses
+/// already past their date are included until the expiry job revokes them.
+pub fn expiring_licenses(practitioners: &[Practitioner], today: NaiveDate, days: i64) -> Vec<ExpiringLicense> {
+    let mut licenses: Vec<ExpiringLicense> = practitioners
+        .iter()
+        .filter(|practitioner| practitioner.license_verification.verified)
+        .filter_map(|practitioner| {
+            let license = &practitioner.license_verification;
+            let expiry = license.expiry()?;
+            let days_remaining = (expiry - today).num_days();
+            (days_remaining <= days).then(|| ExpiringLicense {
+                practitioner_did: practitioner.did.clone(),
+                license_number: license.license_number.clone(),
+                issuing_authority: license.issuing_authority.clone(),
+                expiry_date: expiry,
+                days_remaining,
+                warning_sent_at: license.expiry_warning_sent_at,
+                tenant_id: practitioner.tenant_id.clone(),
+            })
+        })
+        .collect();
+    licenses.sort_by(|a, b| a.expiry_date.cmp(&b.expiry_date).then_with(|| a.practitioner_did.cmp(&b.practitioner_did)));
+    licenses
+}
+
+/// "Jane", from the first name on the practitioner's FHIR resource
+fn display_name(practitioner: &Practitioner) -> String {
+    practitioner
+        .fhir_practitioner
+        .name
+        .first()
+        .and_then(|name| name.given.first().cloned().or_else(|| name.family.clone()))
+        .unwrap_or_else(|| practitioner.did.clone())
+}
+
+/// Runs [`LicenseExpiryService::sweep`] once a day
+pub struct LicenseExpiryHandler {
+    service: Arc<LicenseExpiryService>,
+}
+
+impl LicenseExpiryHandler {
+    pub fn new(service: Arc<LicenseExpiryService>) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for LicenseExpiryHandler {
+    fn job_type(&self) -> &'static str {
+        LICENSE_EXPIRY_JOB
+    }
+
+    fn repeat_every(&self) -> Option<Duration> {
+        Some(Duration::days(1))
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        self.service.sweep(Utc::now().date_naive()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::{Config, SmtpConfig};
+    use crate::store::{MockAuditStore, MockEmailOutboxStore, MockOrganizationStore, MockPractitionerStore};
+    use bson::oid::ObjectId;
+    use std::sync::Mutex;
+
+    const EXPIRED: &str = "did:hedera:testnet:0.0.1";
+    const EXPIRING: &str = "did:hedera:testnet:0.0.2";
+    const UNREADABLE: &str = "did:hedera:testnet:0.0.3";
+    const WARNED: &str = "did:hedera:testnet:0.0.4";
+    const CURRENT: &str = "did:hedera:testnet:0.0.5";
+    const CLINIC_ADMIN: &str = "did:hedera:testnet:0.0.6";
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, 18).unwrap()
+    }
+
+    fn practitioner(did: &str, expiry_date: &str) -> Practitioner {
+        Practitioner {
+            id: None,
+            did: did.to_string(),
+            fhir_practitioner: FhirPractitioner {
+                resource_type: "Practitioner".to_string(),
+                id: did.to_string(),
+                identifier: vec![],
+                name: vec![FhirHumanName { given: vec!["Jane".to_string()], ..Default::default() }],
+                qualification: vec![],
+                telecom: vec![FhirContactPoint { system: "email".to_string(), value: format!("{}@example.com", &did[did.len() - 1..]), r#use: None }],
+            },
+            license_verification: LicenseVerification {
+                license_number: format!("KMPDC-{}", &did[did.len() - 1..]),
+                issuing_authority: "KMPDC".to_string(),
+                issue_date: "2021-01-01".to_string(),
+                expiry_date: expiry_date.to_string(),
+                hedera_transaction_id: String::new(),
+                ipfs_hash: String::new(),
+                verified: true,
+                expiry_warning_sent_at: None,
+            },
+            practitioner_type: PractitionerType::Clinician,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    fn licensed() -> Vec<Practitioner> {
+        let mut warned = practitioner(WARNED, "2026-10-25");
+        warned.license_verification.expiry_warning_sent_at = Some(Utc::now());
+        vec![
+            practitioner(EXPIRED, "2026-10-17"),
+            practitioner(EXPIRING, "2026-11-17"),
+            practitioner(UNREADABLE, "next year"),
+            warned,
+            practitioner(CURRENT, "2026-11-18"),
+        ]
+    }
+
+    fn affiliation(practitioner_did: &str, organization_id: ObjectId, role: AffiliationRole) -> Affiliation {
+        Affiliation {
+            id: Some(ObjectId::new()),
+            practitioner_did: practitioner_did.to_string(),
+            organization_id,
+            role,
+            status: AffiliationStatus::Active,
+            period_start: None,
+            period_end: None,
+            decided_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn unreadable_and_partial_dates_are_not_expiry_dates() {
+        let expiry = |value: &str| practitioner(EXPIRED, value).license_verification.expiry();
+
+        assert_eq!(expiry(" 2027-01-31 "), NaiveDate::from_ymd_opt(2027, 1, 31));
+        for value in ["2027-02-30", "31/01/2027", "2027-01", "2027", "", "next year"] {
+            assert_eq!(expiry(value), None, "{}", value);
+        }
+        // Valid through the expiry day itself
+        assert!(practitioner(EXPIRED, "2026-10-18").license_verification.is_current(today()));
+        assert!(!practitioner(EXPIRED, "2026-10-17").license_verification.is_current(today()));
+        assert!(!practitioner(EXPIRED, "next year").license_verification.is_current(today()));
+    }
+
+    #[test]
+    fn the_expiring_window_includes_licenses_not_yet_revoked() {
+        let mut unverified = practitioner(CLINIC_ADMIN, "2026-10-20");
+        unverified.license_verification.verified = false;
+        let mut practitioners = licensed();
+        practitioners.push(unverified);
+
+        let licenses = expiring_licenses(&practitioners, today(), 30);
+        let listed: Vec<(&str, i64)> = licenses.iter().map(|license| (license.practitioner_did.as_str(), license.days_remaining)).collect();
+
+        assert_eq!(listed, vec![(EXPIRED, -1), (WARNED, 7), (EXPIRING, 30)]);
+        assert_eq!(expiring_licenses(&practitioners, today(), 7).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_sweep_warns_once_and_revokes_expired_licenses() {
+        let clinic = ObjectId::new();
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_licensed_practitioners().returning(|| Ok(licensed()));
+        practitioners
+            .expect_claim_license_expiry_warning()
+            .withf(|did, expiry_date, _| did == EXPIRING && expiry_date == "2026-11-17")
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+        let revoked = Arc::new(Mutex::new(Vec::new()));
+        let revoking = revoked.clone();
+        practitioners.expect_revoke_license_verification().returning(move |did| {
+            revoking.lock().unwrap().push(did.to_string());
+            Ok(true)
+        });
+        practitioners.expect_get_practitioner_by_did().returning(|did| Ok(Some(practitioner(did, "2027-06-30"))));
+        let mut organizations = MockOrganizationStore::new();
+        organizations.expect_active_affiliations().returning(move |did| {
+            Ok(if did == EXPIRING { vec![affiliation(did, clinic, AffiliationRole::Practitioner)] } else { vec![] })
+        });
+        organizations.expect_list_affiliations().returning(move |organization_id| {
+            Ok(vec![
+                affiliation(EXPIRING, organization_id, AffiliationRole::Practitioner),
+                affiliation(CLINIC_ADMIN, organization_id, AffiliationRole::Admin),
+            ])
+        });
+        let emails = Arc::new(Mutex::new(Vec::new()));
+        let sent = emails.clone();
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox.expect_enqueue_email().returning(move |email| {
+            sent.lock().unwrap().push((email.recipient.clone(), email.template.clone(), email.context["own"] == true));
+            Ok(ObjectId::new())
+        });
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let logged = logs.clone();
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.clone());
+            Ok(())
+        });
+        let config = Arc::new(Config { smtp: Some(SmtpConfig::default()), ..Default::default() });
+        let service = LicenseExpiryService::new(
+            Arc::new(practitioners),
+            Arc::new(organizations),
+            Arc::new(EmailService::new(config, Arc::new(outbox)).unwrap()),
+            Arc::new(AuditLogService::new(Arc::new(audit_store))),
+        );
+
+        assert_eq!(service.sweep(today()).await.unwrap(), LicenseSweep { warned: 1, revoked: 2 });
+
+        assert_eq!(*revoked.lock().unwrap(), vec![EXPIRED, UNREADABLE]);
+        assert_eq!(
+            *emails.lock().unwrap(),
+            vec![
+                ("1@example.com".to_string(), "License-expired.html".to_string(), true),
+                ("2@example.com".to_string(), "License-expiring.html".to_string(), true),
+                ("6@example.com".to_string(), "License-expiring.html".to_string(), false),
+                ("3@example.com".to_string(), "License-expired.html".to_string(), true),
+            ]
+        );
+        let logs = logs.lock().unwrap();
+        let actions: Vec<(&str, &str)> = logs.iter().map(|log| (log.did.as_str(), log.action.as_str())).collect();
+        assert_eq!(actions, vec![(EXPIRED, "license_expired"), (EXPIRING, "license_expiry_warning"), (UNREADABLE, "license_expired")]);
+        assert_eq!(logs[1].details.as_ref().unwrap()["notified"], json!([EXPIRING, CLINIC_ADMIN]));
+        assert_eq!(logs[2].details.as_ref().unwrap()["reason"], "unreadable_expiry_date");
+    }
+}
@@ -0,0 +1,361 @@
+//! Spotting addresses that fail to sign in too often, or to too many accounts, as
+//! credential-stuffing runs do.
+//!
+//! Pure bookkeeping over an injected [`Clock`]; auditing, persistence and HTTP are left to
+//! [`IpBlockService`](crate::services::ip_blocks::IpBlockService). Whenever its state is
+//! unusable the detector fails open: it never blocks, so a bug here cannot lock everyone out.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::config::LoginProtectionConfig;
+use crate::models::*;
+
+/// The time, behind a trait so tests can move it
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A single address or a CIDR range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// `203.0.113.7`, `198.51.100.0/24` or the IPv6 equivalents
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().ok()?;
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= width)?,
+            None => width,
+        };
+        Some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, width) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => (u32::from(network) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        let shift = width - u32::from(self.prefix);
+        self.prefix == 0 || network >> shift == ip >> shift
+    }
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    failures: Vec<FailedLogin>,
+    block: Option<IpBlock>,
+}
+
+// --- FailedLoginDetector ---
+pub struct FailedLoginDetector {
+    max_failures: usize,
+    max_accounts: usize,
+    window: Duration,
+    block_for: Duration,
+    allowlist: Vec<IpNetwork>,
+    clock: Arc<dyn Clock>,
+    activity: Mutex<HashMap<IpAddr, Activity>>,
+}
+
+impl FailedLoginDetector {
+    /// Allowlist entries that do not parse are ignored; configuration validation reports them
+    pub fn new(config: &LoginProtectionConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            max_failures: config.max_failures,
+            max_accounts: config.max_accounts,
+            window: Duration::minutes(config.window_minutes),
+            block_for: Duration::minutes(config.block_minutes),
+            allowlist: config.allowlist.iter().filter_map(|entry| IpNetwork::parse(entry)).collect(),
+            clock,
+            activity: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_allowlisted(&self, ip: IpAddr) -> bool {
+        self.allowlist.iter().any(|network| network.contains(ip))
+    }
+
+    /// The block `ip` is under right now, if any
+    pub fn blocked(&self, ip: IpAddr) -> Option<IpBlock> {
+        let now = self.clock.now();
+        let activity = self.activity()?;
+        activity.get(&ip.to_canonical())?.block.clone().filter(|block| block.until > now)
+    }
+
+    /// Count a failed sign-in to `subject` from `ip`, returning the block it triggers. An address
+    /// is blocked once its failures within the window reach `max_failures`, or the accounts they
+    /// were for reach `max_accounts`.
+    pub fn record_failure(&self, ip: IpAddr, subject: &str) -> Option<IpBlock> {
+        let ip = ip.to_canonical();
+        if self.is_allowlisted(ip) {
+            return None;
+        }
+        let now = self.clock.now();
+        let mut activity = self.activity()?;
+        let entry = activity.entry(ip).or_default();
+        if entry.block.as_ref().is_some_and(|block| block.until > now) {
+            return None;
+        }
+        entry.failures.retain(|failure| failure.at + self.window > now);
+        entry.failures.push(FailedLogin { at: now, subject: subject.to_string() });
+
+        let accounts = entry.failures.iter().map(|failure| failure.subject.as_str()).collect::<HashSet<_>>().len();
+        if entry.failures.len() < self.max_failures && accounts < self.max_accounts {
+            return None;
+        }
+        let block = IpBlock { ip: ip.to_string(), blocked_at: now, until: now + self.block_for, failures: entry.failures.len(), accounts };
+        entry.failures.clear();
+        entry.block = Some(block.clone());
+        Some(block)
+    }
+
+    /// Lift the block on `ip` early, returning it if there was one
+    pub fn unblock(&self, ip: IpAddr) -> Option<IpBlock> {
+        let now = self.clock.now();
+        let mut activity = self.activity()?;
+        let entry = activity.get_mut(&ip.to_canonical())?;
+        entry.failures.clear();
+        entry.block.take().filter(|block| block.until > now)
+    }
+
+    /// Every block in force, oldest first
+    pub fn blocks(&self) -> Vec<IpBlock> {
+        let now = self.clock.now();
+        let Some(activity) = self.activity() else {
+            return Vec::new();
+        };
+        let mut blocks: Vec<IpBlock> = activity.values().filter_map(|entry| entry.block.clone()).filter(|block| block.until > now).collect();
+        blocks.sort_by_key(|block| block.blocked_at);
+        blocks
+    }
+
+    /// Forget failures that have left the window and blocks that have run out, returning those blocks
+    pub fn sweep(&self) -> Vec<IpBlock> {
+        let now = self.clock.now();
+        let Some(mut activity) = self.activity() else {
+            return Vec::new();
+        };
+        let mut expired = Vec::new();
+        for entry in activity.values_mut() {
+            entry.failures.retain(|failure| failure.at + self.window > now);
+            if entry.block.as_ref().is_some_and(|block| block.until <= now) {
+                expired.extend(entry.block.take());
+            }
+        }
+        activity.retain(|_, entry| !entry.failures.is_empty() || entry.block.is_some());
+        expired
+    }
+
+    /// Everything worth keeping across a restart
+    pub fn snapshot(&self) -> Vec<LoginActivity> {
+        let Some(activity) = self.activity() else {
+            return Vec::new();
+        };
+        activity
+            .iter()
+            .map(|(ip, entry)| LoginActivity { ip: ip.to_string(), failures: entry.failures.clone(), block: entry.block.clone() })
+            .collect()
+    }
+
+    /// Take back a [`snapshot`](Self::snapshot); anything already tracked is kept
+    pub fn restore(&self, saved: Vec<LoginActivity>) {
+        let Some(mut activity) = self.activity() else {
+            return;
+        };
+        for saved in saved {
+            let Ok(ip) = saved.ip.parse::<IpAddr>() else {
+                continue;
+            };
+            activity.entry(ip.to_canonical()).or_insert(Activity { failures: saved.failures, block: saved.block });
+        }
+    }
+
+    fn activity(&self) -> Option<MutexGuard<'_, HashMap<IpAddr, Activity>>> {
+        match self.activity.lock() {
+            Ok(activity) => Some(activity),
+            Err(_) => {
+                tracing::error!("Failed-login detector state is unusable; not blocking anyone");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that only moves when told to
+    struct ManualClock(Mutex<DateTime<Utc>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    const ATTACKER: &str = "203.0.113.7";
+
+    fn detector(allowlist: &[&str]) -> (FailedLoginDetector, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock(Mutex::new("2026-03-02T09:00:00Z".parse().unwrap())));
+        let config = LoginProtectionConfig {
+            max_failures: 6,
+            max_accounts: 3,
+            window_minutes: 10,
+            block_minutes: 30,
+            allowlist: allowlist.iter().map(|entry| entry.to_string()).collect(),
+        };
+        (FailedLoginDetector::new(&config, clock.clone()), clock)
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn spraying_accounts_from_one_address_blocks_it_for_the_cooldown() {
+        let (detector, clock) = detector(&[]);
+
+        assert_eq!(detector.record_failure(ip(ATTACKER), "phone:a"), None);
+        assert_eq!(detector.record_failure(ip(ATTACKER), "phone:b"), None);
+        let block = detector.record_failure(ip(ATTACKER), "phone:c").expect("a third account blocks the address");
+        assert_eq!((block.failures, block.accounts), (3, 3));
+        assert_eq!(block.until, clock.now() + Duration::minutes(30));
+        assert_eq!(detector.blocked(ip(ATTACKER)), Some(block.clone()));
+        assert_eq!(detector.blocked(ip("203.0.113.8")), None, "only the address itself");
+        assert_eq!(detector.blocks(), vec![block.clone()]);
+
+        clock.advance(Duration::minutes(29));
+        assert!(detector.blocked(ip(ATTACKER)).is_some());
+        clock.advance(Duration::minutes(1));
+        assert_eq!(detector.blocked(ip(ATTACKER)), None);
+        assert_eq!(detector.sweep(), vec![block]);
+        assert!(detector.snapshot().is_empty());
+    }
+
+    #[test]
+    fn one_account_failing_repeatedly_takes_the_failure_threshold() {
+        let (detector, _) = detector(&[]);
+
+        for _ in 0..5 {
+            assert_eq!(detector.record_failure(ip(ATTACKER), "phone:a"), None);
+        }
+        let block = detector.record_failure(ip(ATTACKER), "phone:a").unwrap();
+        assert_eq!((block.failures, block.accounts), (6, 1));
+    }
+
+    #[test]
+    fn failures_leave_the_sliding_window() {
+        let (detector, clock) = detector(&[]);
+
+        detector.record_failure(ip(ATTACKER), "phone:a");
+        clock.advance(Duration::minutes(6));
+        detector.record_failure(ip(ATTACKER), "phone:b");
+        clock.advance(Duration::minutes(5));
+        // The first failure is now 11 minutes old
+        assert_eq!(detector.record_failure(ip(ATTACKER), "phone:c"), None);
+        assert!(detector.record_failure(ip(ATTACKER), "phone:d").is_some());
+    }
+
+    #[test]
+    fn allowlisted_addresses_and_ranges_are_never_blocked() {
+        let (detector, _) = detector(&["198.51.100.0/24", "2001:db8::1"]);
+
+        for account in ["a", "b", "c", "d"] {
+            assert_eq!(detector.record_failure(ip("198.51.100.20"), account), None);
+            assert_eq!(detector.record_failure(ip("::ffff:198.51.100.21"), account), None);
+            assert_eq!(detector.record_failure(ip("2001:db8::1"), account), None);
+        }
+        assert!(detector.blocks().is_empty());
+        assert!(detector.is_allowlisted(ip("198.51.100.255")));
+        assert!(!detector.is_allowlisted(ip("198.51.101.1")));
+        assert!(!detector.is_allowlisted(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn unblocking_lifts_the_block_and_starts_counting_afresh() {
+        let (detector, _) = detector(&[]);
+        for account in ["a", "b", "c"] {
+            detector.record_failure(ip(ATTACKER), account);
+        }
+
+        assert!(detector.unblock(ip(ATTACKER)).is_some());
+        assert_eq!(detector.unblock(ip(ATTACKER)), None);
+        assert_eq!(detector.blocked(ip(ATTACKER)), None);
+        assert_eq!(detector.record_failure(ip(ATTACKER), "a"), None);
+    }
+
+    #[test]
+    fn a_snapshot_restores_blocks_and_failures() {
+        let (detector, _) = detector(&[]);
+        for account in ["a", "b", "c"] {
+            detector.record_failure(ip(ATTACKER), account);
+        }
+        detector.record_failure(ip("198.51.100.9"), "a");
+        detector.record_failure(ip("198.51.100.9"), "b");
+
+        let (restarted, _) = detector(&[]);
+        restarted.restore(detector.snapshot());
+
+        assert!(restarted.blocked(ip(ATTACKER)).is_some());
+        assert!(restarted.record_failure(ip("198.51.100.9"), "c").is_some(), "earlier failures still count");
+    }
+
+    #[test]
+    fn unusable_state_fails_open() {
+        let (detector, _) = detector(&[]);
+        for account in ["a", "b", "c"] {
+            detector.record_failure(ip(ATTACKER), account);
+        }
+        // A panic while holding the lock poisons it
+        std::thread::scope(|scope| {
+            let crashed = scope.spawn(|| {
+                let _activity = detector.activity.lock().unwrap();
+                panic!("bug while tracking failures");
+            });
+            assert!(crashed.join().is_err());
+        });
+
+        assert_eq!(detector.blocked(ip(ATTACKER)), None);
+        assert_eq!(detector.record_failure(ip(ATTACKER), "d"), None);
+        assert!(detector.blocks().is_empty());
+    }
+
+    #[test]
+    fn networks_parse_addresses_and_ranges() {
+        assert!(IpNetwork::parse("203.0.113.7").unwrap().contains(ip("203.0.113.7")));
+        assert!(!IpNetwork::parse("203.0.113.7").unwrap().contains(ip("203.0.113.8")));
+        assert!(IpNetwork::parse("10.0.0.0/8").unwrap().contains(ip("10.200.3.4")));
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains(ip("192.0.2.1")));
+        assert!(IpNetwork::parse("2001:db8::/32").unwrap().contains(ip("2001:db8:1::5")));
+        assert!(!IpNetwork::parse("2001:db8::/32").unwrap().contains(ip("203.0.113.7")));
+        assert_eq!(IpNetwork::parse("10.0.0.0/33"), None);
+        assert_eq!(IpNetwork::parse("clinic"), None);
+    }
+}
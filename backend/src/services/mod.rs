@@ -1,18 +1,114 @@
+pub mod admin;
+pub mod appointment;
+pub mod attachments;
+pub mod audit_analytics;
 pub mod auth;
+pub mod availability;
+pub mod backup_codes;
+pub mod break_glass;
+pub mod captcha;
+pub mod chat;
+pub mod compliance_report;
+pub mod config_reload;
+pub mod consent;
+pub mod credential_batch;
+pub mod data_access;
 pub mod did;
 pub mod email;
+pub mod email_outbox;
+pub mod error;
+#[cfg(feature = "test")]
+pub mod fakes;
+pub mod fcm;
 pub mod fhir;
+pub mod files;
 pub mod hedera;
+pub mod hedera_balance;
+pub mod hedera_costs;
+pub mod idempotency;
+pub mod interactions;
+pub mod ip_blocks;
 pub mod ipfs;
+pub mod issuer_registry;
+pub mod license_expiry;
+pub mod login_anomaly;
+pub mod notification;
+pub mod notification_feed;
+pub mod organization;
+pub mod outbox;
+pub mod patient_export;
+pub mod patient_merge;
+pub mod patient_photo;
+pub mod patient_search;
+pub mod phone_verification;
+pub mod practitioner;
+pub mod prescription;
+pub mod problems;
+pub mod record_context;
+pub mod referral;
+pub mod relationship;
+pub mod redaction;
+pub mod reencryption;
+pub mod reminders;
+pub mod scanner;
+pub mod schedule;
+pub mod sessions;
+pub mod sms_throttle;
+pub mod status_list;
+pub mod step_up;
+pub mod terminology;
+pub mod totp;
 pub mod twilio;
 pub mod gemini;
 pub mod patient;
 pub mod encounter;
 pub mod vc;
+pub mod vc_document;
+pub mod vitals;
+pub mod webhooks;
 
+pub use admin::AdminService;
+pub use appointment::AppointmentService;
+pub use attachments::AttachmentService;
+pub use audit_analytics::AuditAnalyticsService;
 pub use auth::{AuthService, AuthServiceImpl, RegistrationResponse, InitiateAuthResponse};
+pub use availability::AvailabilityService;
+pub use backup_codes::BackupCodeService;
+pub use break_glass::BreakGlassService;
+pub use captcha::CaptchaVerifier;
+pub use chat::ChatService;
+pub use compliance_report::ComplianceReportService;
+pub use config_reload::ConfigReloadService;
+pub use consent::ConsentService;
+pub use data_access::DataAccessMonitor;
 pub use email::EmailService;
+pub use error::ServiceError;
+pub use files::FileService;
+pub use hedera_balance::HederaBalanceMonitor;
+pub use hedera_costs::HederaCostService;
+pub use idempotency::IdempotencyService;
+pub use ip_blocks::IpBlockService;
+pub use issuer_registry::IssuerRegistryService;
+pub use license_expiry::LicenseExpiryService;
+pub use notification::NotificationService;
+pub use notification_feed::NotificationFeedService;
+pub use organization::OrganizationService;
 pub use patient::PatientService;
+pub use patient_export::PatientExportService;
+pub use patient_merge::PatientMergeService;
+pub use patient_photo::PatientPhotoService;
+pub use patient_search::PatientSearchService;
+pub use practitioner::PractitionerService;
+pub use prescription::PrescriptionService;
+pub use referral::ReferralService;
+pub use reencryption::ReencryptionService;
+pub use relationship::RelationshipService;
+pub use sessions::SessionService;
+pub use status_list::StatusListService;
+pub use step_up::StepUpService;
+pub use terminology::TerminologyService;
+pub use totp::TotpService;
 pub use encounter::EncounterService;
 pub use vc::VerifiableCredentialService;
-pub use gemini::ask_gemini;
\ No newline at end of file
+pub use webhooks::WebhookService;
+pub use gemini::{ask_gemini, GeminiChatModel};
\ No newline at end of file
@@ -0,0 +1,707 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "test")]
+use mockall::automock;
+
+use crate::config::Config;
+use crate::events::{DomainEvent, EventSubscriber};
+use crate::i18n;
+use crate::models::*;
+use crate::services::email::{localized_template, EmailService};
+use crate::services::fcm::{FcmClient, PushError};
+use crate::services::twilio::SmsSender;
+use crate::services::ServiceError;
+use crate::store::{NotificationStore, PatientStore, PractitionerStore};
+
+/// Something happened to a patient's account, or to a practitioner's referral, that they should hear about
+#[derive(Debug, Clone)]
+pub enum NotificationEvent {
+    CredentialIssued { patient_did: String, credential_type: String },
+    AccessGranted { patient_did: String, grantee_did: String },
+    RecordExported { patient_did: String },
+    /// `when` is already formatted in the patient's own time zone
+    AppointmentReminder { patient_did: String, practitioner_name: String, when: String, location: String },
+    /// A practitioner opened the record through break-glass
+    EmergencyAccess { patient_did: String, practitioner_did: String, justification: String },
+    /// `medication` goes in the email only
+    PrescriptionCancelled { patient_did: String, medication: String },
+    /// Sent to the patient and to whichever practitioner on the referral did not make the change
+    ReferralUpdated { recipient_did: String, referral_id: String, status: ReferralStatus },
+    /// A backup code was used to step up; `remaining` unused codes are left
+    BackupCodeUsed { patient_did: String, remaining: usize },
+    /// Someone signed in from a device the account has not used before
+    NewSignIn { patient_did: String, device: String, location: Option<String>, revoke_link: String },
+    /// A practitioner finalized an encounter, so its notes and results are in the record
+    EncounterFinalized { patient_did: String },
+    /// An export the patient asked for can be downloaded until `available_until`
+    ExportReady { patient_did: String, available_until: String },
+}
+
+impl NotificationEvent {
+    /// The patient, or for referral updates possibly a practitioner, the event is delivered to
+    pub fn recipient_did(&self) -> &str {
+        match self {
+            Self::CredentialIssued { patient_did, .. }
+            | Self::AccessGranted { patient_did, .. }
+            | Self::RecordExported { patient_did }
+            | Self::AppointmentReminder { patient_did, .. }
+            | Self::EmergencyAccess { patient_did, .. }
+            | Self::PrescriptionCancelled { patient_did, .. }
+            | Self::BackupCodeUsed { patient_did, .. }
+            | Self::NewSignIn { patient_did, .. }
+            | Self::EncounterFinalized { patient_did }
+            | Self::ExportReady { patient_did, .. } => patient_did,
+            Self::ReferralUpdated { recipient_did, .. } => recipient_did,
+        }
+    }
+
+    pub fn category(&self) -> NotificationCategory {
+        match self {
+            Self::CredentialIssued { .. } => NotificationCategory::CredentialIssued,
+            Self::AccessGranted { .. } => NotificationCategory::AccessGranted,
+            Self::RecordExported { .. } => NotificationCategory::RecordExported,
+            Self::AppointmentReminder { .. } => NotificationCategory::AppointmentReminder,
+            Self::EmergencyAccess { .. } => NotificationCategory::EmergencyAccess,
+            Self::PrescriptionCancelled { .. } => NotificationCategory::PrescriptionCancelled,
+            Self::ReferralUpdated { .. } => NotificationCategory::ReferralUpdated,
+            Self::BackupCodeUsed { .. } => NotificationCategory::BackupCodeUsed,
+            Self::NewSignIn { .. } => NotificationCategory::NewSignIn,
+            Self::EncounterFinalized { .. } => NotificationCategory::EncounterFinalized,
+            Self::ExportReady { .. } => NotificationCategory::ExportReady,
+        }
+    }
+
+    /// Subject, template and context of the email in `locale`
+    fn email(&self, username: &str, locale: Locale) -> (String, String, Value) {
+        let (key, template, context) = match self {
+            Self::CredentialIssued { credential_type, .. } => (
+                "email.credential_issued.subject",
+                "Credential-issued.html",
+                json!({ "username": username, "credential_type": credential_type }),
+            ),
+            Self::AccessGranted { grantee_did, .. } => (
+                "email.access_granted.subject",
+                "Access-granted.html",
+                json!({ "username": username, "grantee_did": grantee_did }),
+            ),
+            Self::RecordExported { .. } => (
+                "email.record_exported.subject",
+                "Record-exported.html",
+                json!({ "username": username }),
+            ),
+            Self::AppointmentReminder { practitioner_name, when, location, .. } => (
+                "email.appointment_reminder.subject",
+                "Appointment-reminder.html",
+                json!({ "username": username, "practitioner_name": practitioner_name, "when": when, "location": location }),
+            ),
+            Self::EmergencyAccess { practitioner_did, justification, .. } => (
+                "email.emergency_access.subject",
+                "Emergency-access.html",
+                json!({ "username": username, "practitioner_did": practitioner_did, "justification": justification }),
+            ),
+            Self::PrescriptionCancelled { medication, .. } => (
+                "email.prescription_cancelled.subject",
+                "Prescription-cancelled.html",
+                json!({ "username": username, "medication": medication }),
+            ),
+            Self::ReferralUpdated { referral_id, status, .. } => (
+                "email.referral_updated.subject",
+                "Referral-updated.html",
+                json!({ "username": username, "referral_id": referral_id, "status": referral_status(*status, locale) }),
+            ),
+            Self::BackupCodeUsed { remaining, .. } => (
+                "email.backup_code_used.subject",
+                "Backup-code-used.html",
+                json!({ "username": username, "remaining": remaining }),
+            ),
+            Self::NewSignIn { device, location, revoke_link, .. } => (
+                "email.new_sign_in.subject",
+                "New-sign-in.html",
+                json!({ "username": username, "device": device, "location": location, "revoke_link": revoke_link }),
+            ),
+            Self::EncounterFinalized { .. } => (
+                "email.encounter_finalized.subject",
+                "Encounter-finalized.html",
+                json!({ "username": username }),
+            ),
+            Self::ExportReady { available_until, .. } => (
+                "email.export_ready.subject",
+                "Export-ready.html",
+                json!({ "username": username, "available_until": available_until }),
+            ),
+        };
+        (i18n::text(locale, key, &[]), localized_template(locale, template), context)
+    }
+
+    // Kept free of clinical detail: SMS is neither private nor encrypted
+    fn sms_body(&self, locale: Locale) -> String {
+        match self {
+            Self::CredentialIssued { .. } => i18n::text(locale, "sms.credential_issued", &[]),
+            Self::AccessGranted { .. } => i18n::text(locale, "sms.access_granted", &[]),
+            Self::RecordExported { .. } => i18n::text(locale, "sms.record_exported", &[]),
+            Self::AppointmentReminder { practitioner_name, when, .. } => {
+                i18n::text(locale, "sms.appointment_reminder", &[("practitioner", practitioner_name), ("when", when)])
+            }
+            Self::EmergencyAccess { .. } => i18n::text(locale, "sms.emergency_access", &[]),
+            Self::PrescriptionCancelled { .. } => i18n::text(locale, "sms.prescription_cancelled", &[]),
+            Self::ReferralUpdated { status, .. } => i18n::text(locale, "sms.referral_updated", &[("status", &referral_status(*status, locale))]),
+            Self::BackupCodeUsed { remaining: 0, .. } => i18n::text(locale, "sms.backup_code_used_last", &[]),
+            Self::BackupCodeUsed { remaining, .. } => i18n::text(locale, "sms.backup_code_used", &[("remaining", &remaining.to_string())]),
+            // The revoke link is left to the email, where it cannot be read off a lock screen
+            Self::NewSignIn { device, location: Some(location), .. } => {
+                i18n::text(locale, "sms.new_sign_in_near", &[("device", device), ("location", location)])
+            }
+            Self::NewSignIn { device, .. } => i18n::text(locale, "sms.new_sign_in", &[("device", device)]),
+            Self::EncounterFinalized { .. } => i18n::text(locale, "sms.encounter_finalized", &[]),
+            Self::ExportReady { .. } => i18n::text(locale, "sms.export_ready", &[]),
+        }
+    }
+
+    // Shown on the lock screen, so a hint only: the app fetches the details once it is unlocked
+    fn push_data(&self, locale: Locale) -> HashMap<String, String> {
+        let hint = match self {
+            Self::CredentialIssued { .. } | Self::ExportReady { .. } => "push.new_document",
+            Self::EncounterFinalized { .. } => "push.record_update",
+            Self::AppointmentReminder { .. } => "push.appointment_reminder",
+            Self::AccessGranted { .. }
+            | Self::RecordExported { .. }
+            | Self::EmergencyAccess { .. }
+            | Self::PrescriptionCancelled { .. }
+            | Self::ReferralUpdated { .. }
+            | Self::BackupCodeUsed { .. }
+            | Self::NewSignIn { .. } => "push.account_activity",
+        };
+        HashMap::from([
+            ("category".to_string(), self.category().as_str().to_string()),
+            ("hint".to_string(), i18n::text(locale, hint, &[])),
+        ])
+    }
+}
+
+fn referral_status(status: ReferralStatus, locale: Locale) -> String {
+    i18n::text(locale, &format!("referral.status.{}", status.as_str()), &[])
+}
+
+/// Delivery channels, behind a trait so the service can be tested without SMTP, Twilio or FCM
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait NotificationSender: Send + Sync {
+    async fn send_email(&self, to: &str, subject: &str, template: &str, context: Value) -> Result<()>;
+    async fn send_sms(&self, to: &str, body: &str) -> Result<()>;
+    /// Fails with a [`PushError::InvalidToken`] when the device should be forgotten
+    async fn send_push(&self, fcm_token: &str, data: &HashMap<String, String>) -> Result<()>;
+}
+
+pub struct LiveNotificationSender {
+    email_service: Arc<EmailService>,
+    sms_sender: Arc<SmsSender>,
+    fcm: Option<Arc<FcmClient>>,
+}
+
+impl LiveNotificationSender {
+    pub fn new(email_service: Arc<EmailService>, sms_sender: Arc<SmsSender>, fcm: Option<Arc<FcmClient>>) -> Self {
+        Self { email_service, sms_sender, fcm }
+    }
+}
+
+#[async_trait]
+impl NotificationSender for LiveNotificationSender {
+    async fn send_email(&self, to: &str, subject: &str, template: &str, context: Value) -> Result<()> {
+        Ok(self.email_service.send_mail(to, subject, template, &context).await?)
+    }
+
+    async fn send_sms(&self, to: &str, body: &str) -> Result<()> {
+        self.sms_sender.send_sms(to, body).await?;
+        Ok(())
+    }
+
+    async fn send_push(&self, fcm_token: &str, data: &HashMap<String, String>) -> Result<()> {
+        let Some(fcm) = &self.fcm else {
+            return Err(ServiceError::NotConfigured("push notifications are not configured on this server".to_string()).into());
+        };
+        fcm.send(fcm_token, data).await?;
+        Ok(())
+    }
+}
+
+// --- NotificationService ---
+#[derive(Clone)]
+pub struct NotificationService {
+    store: Arc<dyn NotificationStore>,
+    patients: Arc<dyn PatientStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    sender: Arc<dyn NotificationSender>,
+    config: Arc<Config>,
+}
+
+/// Who a notification goes to: a patient with their saved preferences, or a practitioner with the defaults
+struct Recipient {
+    name: String,
+    telecom: Vec<FhirContactPoint>,
+    preferences: NotificationPreferences,
+}
+
+impl NotificationService {
+    pub fn new(
+        store: Arc<dyn NotificationStore>,
+        patients: Arc<dyn PatientStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        sender: Arc<dyn NotificationSender>,
+        config: Arc<Config>,
+    ) -> Self {
+        Self { store, patients, practitioners, sender, config }
+    }
+
+    /// Deliver in the background; the outcome is recorded in `notifications`, never returned to the caller
+    pub fn notify(&self, event: NotificationEvent) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = service.deliver(&event).await {
+                tracing::error!("Failed to notify {} of {:?}: {}", event.recipient_did(), event.category(), e);
+            }
+        });
+    }
+
+    /// Register a device for push notifications to `did`. Tokens are kept while push is not
+    /// configured, so devices start receiving as soon as it is.
+    pub async fn register_device(&self, did: &str, request: RegisterDeviceRequest) -> Result<()> {
+        if request.fcm_token.trim().is_empty() {
+            return Err(anyhow::anyhow!("An FCM token is required"));
+        }
+        let now = Utc::now();
+        let device = Device {
+            id: None,
+            did: did.to_string(),
+            fcm_token: request.fcm_token.trim().to_string(),
+            platform: request.platform,
+            created_at: now,
+            updated_at: now,
+        };
+        self.store.register_device(&device).await
+    }
+
+    /// Send `event` on every channel the recipient allows and has contact details for, and to
+    /// each of their devices, returning the notification records that were written
+    pub async fn deliver(&self, event: &NotificationEvent) -> Result<Vec<Notification>> {
+        let recipient_did = event.recipient_did();
+        let category = event.category().preference_category();
+        let Some(recipient) = self.recipient(recipient_did).await? else {
+            return Ok(Vec::new());
+        };
+
+        let mut addresses = Vec::new();
+        for (channel, system) in [(NotificationChannel::Email, "email"), (NotificationChannel::Sms, "phone")] {
+            if !recipient.preferences.allows(category, channel) {
+                continue;
+            }
+            if let Some(contact) = recipient.telecom.iter().find(|c| c.system == system) {
+                addresses.push((channel, contact.value.clone()));
+            }
+        }
+        if recipient.preferences.allows(category, NotificationChannel::Push) {
+            let devices = self.store.list_devices(recipient_did).await?;
+            addresses.extend(devices.into_iter().map(|device| (NotificationChannel::Push, device.fcm_token)));
+        }
+
+        let mut notifications = Vec::new();
+        for (channel, address) in addresses {
+            let mut notification = Notification {
+                id: None,
+                patient_did: recipient_did.to_string(),
+                category: event.category(),
+                channel,
+                status: NotificationStatus::Pending,
+                error: None,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            };
+            let id = self.store.create_notification(&notification).await?;
+            notification.id = Some(id);
+
+            let result = match channel {
+                NotificationChannel::Email => {
+                    let (subject, template, context) = event.email(&recipient.name, recipient.preferences.locale);
+                    self.sender.send_email(&address, &subject, &template, context).await
+                }
+                NotificationChannel::Sms => self.sender.send_sms(&address, &event.sms_body(recipient.preferences.locale)).await,
+                NotificationChannel::Push => self.sender.send_push(&address, &event.push_data(recipient.preferences.locale)).await,
+            };
+            match result {
+                Ok(()) => notification.status = NotificationStatus::Sent,
+                Err(e) => {
+                    tracing::warn!("{:?} notification to {} failed: {}", channel, recipient_did, e);
+                    if matches!(e.downcast_ref::<PushError>(), Some(PushError::InvalidToken(_))) {
+                        self.store.remove_device(&address).await?;
+                    }
+                    notification.status = NotificationStatus::Failed;
+                    notification.error = Some(e.to_string());
+                }
+            }
+            notification.updated_at = Utc::now();
+            self.store.update_notification_status(id, notification.status, notification.error.clone()).await?;
+            notifications.push(notification);
+        }
+        Ok(notifications)
+    }
+
+    /// Patients are looked up first; practitioners have no saved preferences, so they get the defaults
+    async fn recipient(&self, did: &str) -> Result<Option<Recipient>> {
+        if let Some(patient) = self.patients.get_patient_by_did(did, &self.config.ipfs_encryption_key).await? {
+            let preferences = self.patients.get_notification_preferences(did).await?.unwrap_or_default();
+            return Ok(Some(Recipient {
+                name: display_name(&patient.fhir_patient.name, preferences.locale),
+                telecom: patient.fhir_patient.telecom,
+                preferences,
+            }));
+        }
+        Ok(self.practitioners.get_practitioner_by_did(did).await?.map(|practitioner| Recipient {
+            name: display_name(&practitioner.fhir_practitioner.name, Locale::En),
+            telecom: practitioner.fhir_practitioner.telecom,
+            preferences: NotificationPreferences::default(),
+        }))
+    }
+}
+
+/// Notifies patients of the events on the bus that concern them
+pub struct NotificationSubscriber {
+    notification_service: Arc<NotificationService>,
+}
+
+impl NotificationSubscriber {
+    pub fn new(notification_service: Arc<NotificationService>) -> Self {
+        Self { notification_service }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for NotificationSubscriber {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        let notification = match event {
+            DomainEvent::CredentialIssued { patient_did, credential_type, .. } => {
+                NotificationEvent::CredentialIssued { patient_did: patient_did.clone(), credential_type: credential_type.clone() }
+            }
+            DomainEvent::AccessGranted { patient_did, grantee_did, .. } => {
+                NotificationEvent::AccessGranted { patient_did: patient_did.clone(), grantee_did: grantee_did.clone() }
+            }
+            DomainEvent::EncounterFinalized { patient_did, .. } => NotificationEvent::EncounterFinalized { patient_did: patient_did.clone() },
+        };
+        self.notification_service.deliver(&notification).await?;
+        Ok(())
+    }
+}
+
+fn display_name(names: &[FhirHumanName], locale: Locale) -> String {
+    names
+        .first()
+        .and_then(|name| name.given.first().cloned().or_else(|| name.family.clone()))
+        .unwrap_or_else(|| i18n::text(locale, "email.unnamed_recipient", &[]))
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::store::{MockNotificationStore, MockPatientStore, MockPractitionerStore};
+    use bson::oid::ObjectId;
+
+    const DID: &str = "did:hedera:testnet:0.0.1";
+
+    fn patient() -> Patient {
+        let fhir_patient = FhirPatient {
+            name: vec![FhirHumanName { given: vec!["Amina".to_string()], ..Default::default() }],
+            telecom: vec![
+                FhirContactPoint { system: "email".to_string(), value: "amina@example.com".to_string(), r#use: None },
+                FhirContactPoint { system: "phone".to_string(), value: "+254700000001".to_string(), r#use: None },
+            ],
+            ..Default::default()
+        };
+        fixtures::patient(DID, fhir_patient)
+    }
+
+    fn patients(preferences: Option<NotificationPreferences>) -> MockPatientStore {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(Some(patient())));
+        patients.expect_get_notification_preferences().returning(move |_| Ok(preferences.clone()));
+        patients
+    }
+
+    fn store() -> MockNotificationStore {
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store.expect_update_notification_status().returning(|_, _, _| Ok(()));
+        store.expect_list_devices().returning(|_| Ok(vec![]));
+        store
+    }
+
+    fn device(fcm_token: &str) -> Device {
+        Device {
+            id: None,
+            did: DID.to_string(),
+            fcm_token: fcm_token.to_string(),
+            platform: DevicePlatform::Android,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn service(
+        store: MockNotificationStore,
+        preferences: Option<NotificationPreferences>,
+        sender: MockNotificationSender,
+    ) -> NotificationService {
+        NotificationService::new(
+            Arc::new(store),
+            Arc::new(patients(preferences)),
+            Arc::new(MockPractitionerStore::new()),
+            Arc::new(sender),
+            Arc::new(Config::default()),
+        )
+    }
+
+    fn credential_issued() -> NotificationEvent {
+        NotificationEvent::CredentialIssued { patient_did: DID.to_string(), credential_type: "Vaccination".to_string() }
+    }
+
+    #[tokio::test]
+    async fn credential_issued_is_sent_on_both_channels_by_default() {
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_email()
+            .withf(|to, _, template, context| {
+                to == "amina@example.com" && template == "Credential-issued.html" && context["credential_type"] == "Vaccination"
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        sender
+            .expect_send_sms()
+            .withf(|to, _| to == "+254700000001")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let notifications = service(store(), None, sender).deliver(&credential_issued()).await.unwrap();
+
+        let channels: Vec<_> = notifications.iter().map(|n| (n.channel, n.status, n.category)).collect();
+        assert_eq!(
+            channels,
+            vec![
+                (NotificationChannel::Email, NotificationStatus::Sent, NotificationCategory::CredentialIssued),
+                (NotificationChannel::Sms, NotificationStatus::Sent, NotificationCategory::CredentialIssued),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn notifications_are_sent_in_the_patients_language() {
+        let preferences = NotificationPreferences { locale: Locale::Sw, ..Default::default() };
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_email()
+            .withf(|_, subject, template, _| subject == "Umepewa cheti kipya" && template == "sw/Credential-issued.html")
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        sender
+            .expect_send_sms()
+            .withf(|_, body| body.starts_with("Cheti kipya kimeongezwa"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let notifications = service(store(), Some(preferences), sender).deliver(&credential_issued()).await.unwrap();
+
+        assert_eq!(notifications.len(), 2);
+    }
+
+    #[test]
+    fn referral_statuses_are_translated() {
+        let event = NotificationEvent::ReferralUpdated { recipient_did: DID.to_string(), referral_id: "r1".to_string(), status: ReferralStatus::Accepted };
+
+        assert_eq!(event.sms_body(Locale::En), "A referral was accepted. Open the app for details.");
+        assert_eq!(event.sms_body(Locale::Sw), "Rufaa imekubaliwa. Fungua programu kwa maelezo zaidi.");
+        assert_eq!(event.email("Amina", Locale::Sw).2["status"], "imekubaliwa");
+    }
+
+    #[tokio::test]
+    async fn opted_out_category_sends_nothing() {
+        let preferences = NotificationPreferences { security_alerts: false, ..Default::default() };
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().never();
+
+        let event = NotificationEvent::AccessGranted { patient_did: DID.to_string(), grantee_did: "did:hedera:testnet:0.0.2".to_string() };
+        let notifications = service(store, Some(preferences), MockNotificationSender::new()).deliver(&event).await.unwrap();
+
+        assert!(notifications.is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_channel_is_skipped() {
+        let preferences = NotificationPreferences { sms: false, ..Default::default() };
+        let mut sender = MockNotificationSender::new();
+        sender.expect_send_email().times(1).returning(|_, _, _, _| Ok(()));
+        sender.expect_send_sms().never();
+
+        let event = NotificationEvent::RecordExported { patient_did: DID.to_string() };
+        let notifications = service(store(), Some(preferences), sender).deliver(&event).await.unwrap();
+
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].channel, NotificationChannel::Email);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_recorded_with_its_error() {
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store
+            .expect_update_notification_status()
+            .withf(|_, status, error| *status == NotificationStatus::Failed && error.as_deref() == Some("SMS is not configured on this server"))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        store
+            .expect_update_notification_status()
+            .withf(|_, status, _| *status == NotificationStatus::Sent)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        store.expect_list_devices().returning(|_| Ok(vec![]));
+        let mut sender = MockNotificationSender::new();
+        sender.expect_send_email().returning(|_, _, _, _| Ok(()));
+        sender
+            .expect_send_sms()
+            .returning(|_, _| Err(anyhow::anyhow!("SMS is not configured on this server")));
+
+        let notifications = service(store, None, sender).deliver(&credential_issued()).await.unwrap();
+
+        assert_eq!(notifications[1].status, NotificationStatus::Failed);
+        assert_eq!(notifications[1].error.as_deref(), Some("SMS is not configured on this server"));
+    }
+
+    #[tokio::test]
+    async fn push_goes_to_every_device_with_a_hint_instead_of_the_details() {
+        let preferences = NotificationPreferences { email: false, sms: false, locale: Locale::Sw, ..Default::default() };
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store.expect_update_notification_status().returning(|_, _, _| Ok(()));
+        store.expect_list_devices().returning(|_| Ok(vec![device("phone"), device("tablet")]));
+        store.expect_remove_device().never();
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_push()
+            .withf(|_, data| data["category"] == "credential_issued" && data["hint"] == "Una hati mpya" && data.len() == 2)
+            .times(2)
+            .returning(|_, _| Ok(()));
+
+        let notifications = service(store, Some(preferences), sender).deliver(&credential_issued()).await.unwrap();
+
+        assert_eq!(notifications.len(), 2);
+        assert!(notifications.iter().all(|n| n.channel == NotificationChannel::Push && n.status == NotificationStatus::Sent));
+    }
+
+    #[tokio::test]
+    async fn a_token_fcm_rejects_is_removed_and_the_failure_recorded() {
+        let preferences = NotificationPreferences { email: false, sms: false, ..Default::default() };
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store.expect_update_notification_status().returning(|_, _, _| Ok(()));
+        store.expect_list_devices().returning(|_| Ok(vec![device("stale"), device("phone")]));
+        store.expect_remove_device().withf(|token| token == "stale").times(1).returning(|_| Ok(true));
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_push()
+            .returning(|token, _| match token {
+                "stale" => Err(PushError::InvalidToken("Requested entity was not found.".to_string()).into()),
+                _ => Ok(()),
+            });
+
+        let notifications = service(store, Some(preferences), sender).deliver(&credential_issued()).await.unwrap();
+
+        let statuses: Vec<_> = notifications.iter().map(|n| n.status).collect();
+        assert_eq!(statuses, vec![NotificationStatus::Failed, NotificationStatus::Sent]);
+        assert_eq!(notifications[0].error.as_deref(), Some("device token is no longer valid: Requested entity was not found."));
+    }
+
+    #[tokio::test]
+    async fn devices_are_not_looked_up_when_push_is_off() {
+        let preferences = NotificationPreferences { push: false, ..Default::default() };
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store.expect_update_notification_status().returning(|_, _, _| Ok(()));
+        store.expect_list_devices().never();
+        let mut sender = MockNotificationSender::new();
+        sender.expect_send_email().returning(|_, _, _, _| Ok(()));
+        sender.expect_send_sms().returning(|_, _| Ok(()));
+
+        let event = NotificationEvent::EncounterFinalized { patient_did: DID.to_string() };
+        let notifications = service(store, Some(preferences), sender).deliver(&event).await.unwrap();
+
+        assert_eq!(notifications.len(), 2);
+    }
+
+    #[test]
+    fn preferences_saved_before_push_existed_leave_it_on() {
+        let saved = json!({ "email": true, "sms": false, "security_alerts": true, "appointment_reminders": true, "marketing": false });
+
+        let preferences: NotificationPreferences = serde_json::from_value(saved).unwrap();
+
+        assert!(preferences.allows(PreferenceCategory::SecurityAlerts, NotificationChannel::Push));
+    }
+
+    #[tokio::test]
+    async fn practitioners_are_reached_through_their_own_contact_details() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        patients.expect_get_notification_preferences().never();
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|did| {
+            Ok(Some(Practitioner {
+                id: None,
+                did: did.to_string(),
+                fhir_practitioner: FhirPractitioner {
+                    resource_type: "Practitioner".to_string(),
+                    id: did.to_string(),
+                    identifier: vec![],
+                    name: vec![FhirHumanName { family: Some("Otieno".to_string()), ..Default::default() }],
+                    qualification: vec![],
+                    telecom: vec![FhirContactPoint { system: "email".to_string(), value: "otieno@example.com".to_string(), r#use: None }],
+                },
+                license_verification: LicenseVerification {
+                    license_number: "KMPDC-1".to_string(),
+                    issuing_authority: "KMPDC".to_string(),
+                    issue_date: "2020-01-01".to_string(),
+                    expiry_date: "2030-01-01".to_string(),
+                    hedera_transaction_id: String::new(),
+                    ipfs_hash: String::new(),
+                    verified: true,
+                    expiry_warning_sent_at: None,
+                },
+                practitioner_type: PractitionerType::Clinician,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                tenant_id: None,
+            }))
+        });
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_email()
+            .withf(|to, _, template, context| to == "otieno@example.com" && template == "Referral-updated.html" && context["username"] == "Otieno")
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        sender.expect_send_sms().never();
+        let service = NotificationService::new(Arc::new(store()), Arc::new(patients), Arc::new(practitioners), Arc::new(sender), Arc::new(Config::default()));
+
+        let event = NotificationEvent::ReferralUpdated {
+            recipient_did: "did:hedera:testnet:0.0.2".to_string(),
+            referral_id: ObjectId::new().to_hex(),
+            status: ReferralStatus::Accepted,
+        };
+        let notifications = service.deliver(&event).await.unwrap();
+
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].patient_did, "did:hedera:testnet:0.0.2");
+    }
+}
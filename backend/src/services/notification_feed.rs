@@ -0,0 +1,356 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::config::Config;
+use crate::events::{DomainEvent, EventSubscriber};
+use crate::i18n;
+use crate::models::{FeedEvent, FeedEventView};
+use crate::services::ServiceError;
+use crate::store::FeedEventStore;
+
+/// Events returned by `GET /api/notifications`, and read per round when a stream catches up
+const PAGE_SIZE: i64 = 100;
+
+const TOO_MANY_STREAMS_MESSAGE: &str = "error.too_many_streams";
+
+/// The users whose feeds `event` goes on
+fn recipients(event: &DomainEvent) -> Vec<&str> {
+    match event {
+        DomainEvent::EncounterFinalized { patient_did, .. } | DomainEvent::CredentialIssued { patient_did, .. } => vec![patient_did.as_str()],
+        // The grantee learns the record is open to them
+        DomainEvent::AccessGranted { patient_did, grantee_did, .. } => vec![patient_did.as_str(), grantee_did.as_str()],
+    }
+}
+
+/// Resource ids only; the app reads anything more through the API
+fn event_data(event: &DomainEvent) -> Value {
+    match event {
+        DomainEvent::EncounterFinalized { encounter_id, .. } => json!({ "encounter_id": encounter_id }),
+        DomainEvent::CredentialIssued { credential_id, credential_type, .. } => {
+            json!({ "credential_id": credential_id, "credential_type": credential_type })
+        }
+        DomainEvent::AccessGranted { grant_id, patient_did, grantee_did } => {
+            json!({ "grant_id": grant_id, "patient_did": patient_did, "grantee_did": grantee_did })
+        }
+    }
+}
+
+pub fn view(event: &FeedEvent) -> FeedEventView {
+    FeedEventView {
+        id: event.id.map(|id| id.to_hex()).unwrap_or_default(),
+        event_type: event.event_type.clone(),
+        data: event.data.clone(),
+        created_at: event.created_at,
+    }
+}
+
+fn parse_event_id(id: &str) -> Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid event id"))
+}
+
+// --- NotificationFeedService ---
+/// Pushes the events on the bus that concern a user to the streams they hold open, and keeps
+/// them for `NOTIFICATION_RETENTION_HOURS` so a client that was away briefly replays what it missed.
+///
+/// Live events only reach streams held open on this server. With several instances, a client
+/// connected to another one gets them when it reconnects or polls.
+pub struct NotificationFeedService {
+    db: Arc<dyn FeedEventStore>,
+    config: Arc<Config>,
+    /// Every stream receives the same `Arc`, so fanning an event out copies a pointer per stream
+    sender: broadcast::Sender<Arc<FeedEvent>>,
+    open_streams: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl NotificationFeedService {
+    pub fn new(db: Arc<dyn FeedEventStore>, config: Arc<Config>) -> Self {
+        let (sender, _) = broadcast::channel(config.events.capacity.max(1));
+        Self { db, config, sender, open_streams: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Save `event` on the feed of each user it concerns, then push it to their open streams
+    pub async fn publish(&self, event: &DomainEvent) -> Result<()> {
+        let now = Utc::now();
+        for recipient_did in recipients(event) {
+            let feed_event = FeedEvent {
+                id: Some(ObjectId::new()),
+                recipient_did: recipient_did.to_string(),
+                event_type: event.name().to_string(),
+                data: event_data(event),
+                created_at: now,
+                expires_at: now + Duration::hours(self.config.notification_stream.retention_hours),
+            };
+            self.db.record_feed_event(&feed_event).await?;
+            // Sending only fails when no stream is open
+            let _ = self.sender.send(Arc::new(feed_event));
+        }
+        Ok(())
+    }
+
+    /// The caller's events after `since`, the id of the last one they saw, oldest first.
+    /// Without `since`, the oldest events still kept.
+    pub async fn list(&self, caller_did: &str, since: Option<&str>) -> Result<Vec<FeedEventView>> {
+        let after = since.map(parse_event_id).transpose()?;
+        let events = self.db.feed_events_after(caller_did, after, PAGE_SIZE).await?;
+        Ok(events.iter().map(view).collect())
+    }
+
+    /// Count a stream opened by `did`, or refuse it when they already hold
+    /// `NOTIFICATION_STREAMS_PER_USER`. It stays counted until the permit is dropped.
+    pub fn open_stream(&self, did: &str) -> Result<StreamPermit> {
+        let limit = self.config.notification_stream.max_streams_per_user;
+        let mut open_streams = self.open_streams.lock().unwrap();
+        let open = open_streams.entry(did.to_string()).or_insert(0);
+        if *open >= limit {
+            let limit = limit.to_string();
+            return Err(ServiceError::RateLimited(i18n::text(i18n::current(), TOO_MANY_STREAMS_MESSAGE, &[("limit", limit.as_str())])).into());
+        }
+        *open += 1;
+        Ok(StreamPermit { open_streams: self.open_streams.clone(), did: did.to_string() })
+    }
+
+    /// The events for `did` as they happen. With `last_event_id`, the events after it that are
+    /// still kept come first.
+    pub async fn subscribe(&self, did: &str, last_event_id: Option<&str>) -> Result<BoxStream<'static, Arc<FeedEvent>>> {
+        let last_id = last_event_id.map(parse_event_id).transpose()?;
+        // Subscribed before the backlog is read, so nothing published in between is missed;
+        // an event that turns up in both is only sent once
+        let mut cursor = FeedCursor {
+            db: self.db.clone(),
+            did: did.to_string(),
+            last_id,
+            backlog: VecDeque::new(),
+            receiver: self.sender.subscribe(),
+        };
+        if last_id.is_some() {
+            cursor.catch_up().await?;
+        }
+        Ok(stream::unfold(cursor, |mut cursor| async move { cursor.next().await.map(|event| (event, cursor)) }).boxed())
+    }
+}
+
+/// Holds a place among a user's open streams until dropped
+pub struct StreamPermit {
+    open_streams: Arc<Mutex<HashMap<String, usize>>>,
+    did: String,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut open_streams = self.open_streams.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(open) = open_streams.get_mut(&self.did) {
+            *open -= 1;
+            if *open == 0 {
+                open_streams.remove(&self.did);
+            }
+        }
+    }
+}
+
+/// How far one stream got, and what it still has to send from the database
+struct FeedCursor {
+    db: Arc<dyn FeedEventStore>,
+    did: String,
+    last_id: Option<ObjectId>,
+    backlog: VecDeque<Arc<FeedEvent>>,
+    receiver: broadcast::Receiver<Arc<FeedEvent>>,
+}
+
+impl FeedCursor {
+    /// Queue the stored events past the last one sent or queued
+    async fn catch_up(&mut self) -> Result<()> {
+        let mut after = self.backlog.back().and_then(|event| event.id).or(self.last_id);
+        loop {
+            let page = self.db.feed_events_after(&self.did, after, PAGE_SIZE).await?;
+            let more = page.len() as i64 == PAGE_SIZE;
+            after = page.last().and_then(|event| event.id).or(after);
+            self.backlog.extend(page.into_iter().map(Arc::new));
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn next(&mut self) -> Option<Arc<FeedEvent>> {
+        loop {
+            let event = match self.backlog.pop_front() {
+                Some(event) => event,
+                None => match self.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Notification stream of {} fell {} events behind; catching up from the database", self.did, missed);
+                        if let Err(e) = self.catch_up().await {
+                            // Ending the stream sends the client back with its Last-Event-ID
+                            tracing::error!("Notification stream of {} could not catch up: {:#}", self.did, e);
+                            return None;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            if event.recipient_did == self.did && event.id > self.last_id {
+                self.last_id = event.id;
+                return Some(event);
+            }
+        }
+    }
+}
+
+/// Puts the events on the bus on the feeds of the users they concern
+pub struct NotificationFeedSubscriber {
+    notification_feed_service: Arc<NotificationFeedService>,
+}
+
+impl NotificationFeedSubscriber {
+    pub fn new(notification_feed_service: Arc<NotificationFeedService>) -> Self {
+        Self { notification_feed_service }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for NotificationFeedSubscriber {
+    fn name(&self) -> &'static str {
+        "notification_feed"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> Result<()> {
+        self.notification_feed_service.publish(event).await
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::NotificationStreamConfig;
+    use crate::store::MockFeedEventStore;
+    use std::time::Duration as StdDuration;
+    use tokio::time::timeout;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+
+    fn service(db: MockFeedEventStore) -> NotificationFeedService {
+        let config = Config {
+            notification_stream: NotificationStreamConfig { max_streams_per_user: 2, ..Default::default() },
+            ..Default::default()
+        };
+        NotificationFeedService::new(Arc::new(db), Arc::new(config))
+    }
+
+    fn recording() -> MockFeedEventStore {
+        let mut db = MockFeedEventStore::new();
+        db.expect_record_feed_event().returning(|event| Ok(event.id.unwrap()));
+        db
+    }
+
+    fn credential_issued(patient_did: &str) -> DomainEvent {
+        DomainEvent::CredentialIssued {
+            credential_id: ObjectId::new().to_hex(),
+            patient_did: patient_did.to_string(),
+            credential_type: "VaccinationCredential".to_string(),
+            tenant_id: None,
+        }
+    }
+
+    fn stored(id: ObjectId, recipient_did: &str) -> FeedEvent {
+        FeedEvent {
+            id: Some(id),
+            recipient_did: recipient_did.to_string(),
+            event_type: "encounter_finalized".to_string(),
+            data: json!({ "encounter_id": "e" }),
+            created_at: Utc::now(),
+            expires_at: Utc::now() + Duration::hours(1),
+        }
+    }
+
+    async fn next(events: &mut BoxStream<'static, Arc<FeedEvent>>) -> Option<Arc<FeedEvent>> {
+        timeout(StdDuration::from_millis(500), events.next()).await.ok().flatten()
+    }
+
+    #[tokio::test]
+    async fn many_streams_share_one_copy_of_each_event() {
+        let feed = Arc::new(service(recording()));
+        let mut listeners = Vec::new();
+        for n in 0..1000 {
+            let did = if n % 10 == 0 { PRACTITIONER } else { PATIENT };
+            let mut events = feed.subscribe(did, None).await.unwrap();
+            listeners.push(tokio::spawn(async move { (did, next(&mut events).await) }));
+        }
+
+        feed.publish(&credential_issued(PATIENT)).await.unwrap();
+
+        let mut received = Vec::new();
+        for listener in listeners {
+            match listener.await.unwrap() {
+                (PATIENT, event) => received.push(event.expect("every patient stream gets the event")),
+                (_, event) => assert!(event.is_none(), "the event is not for the practitioner"),
+            }
+        }
+        assert_eq!(received.len(), 900);
+        assert!(received.iter().all(|event| Arc::ptr_eq(event, &received[0])));
+        assert_eq!(received[0].event_type, "credential_issued");
+    }
+
+    #[tokio::test]
+    async fn a_grant_goes_on_the_feeds_of_the_patient_and_the_grantee() {
+        let mut db = MockFeedEventStore::new();
+        db.expect_record_feed_event().withf(|event| event.recipient_did == PATIENT).times(1).returning(|event| Ok(event.id.unwrap()));
+        db.expect_record_feed_event().withf(|event| event.recipient_did == PRACTITIONER).times(1).returning(|event| Ok(event.id.unwrap()));
+        let feed = service(db);
+        let mut patient = feed.subscribe(PATIENT, None).await.unwrap();
+        let mut practitioner = feed.subscribe(PRACTITIONER, None).await.unwrap();
+
+        let grant = DomainEvent::AccessGranted { grant_id: "g".to_string(), patient_did: PATIENT.to_string(), grantee_did: PRACTITIONER.to_string() };
+        feed.publish(&grant).await.unwrap();
+
+        assert_eq!(next(&mut patient).await.unwrap().data["grant_id"], "g");
+        assert_eq!(next(&mut practitioner).await.unwrap().data["grantee_did"], PRACTITIONER);
+    }
+
+    #[tokio::test]
+    async fn a_stream_resumes_after_its_last_event_id_and_then_goes_live() {
+        let (seen, missed_first, missed_second) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let mut db = recording();
+        db.expect_feed_events_after()
+            .withf(move |did, after, _| did == PATIENT && *after == Some(seen))
+            .times(1)
+            .returning(move |_, _, _| Ok(vec![stored(missed_first, PATIENT), stored(missed_second, PATIENT)]));
+        let feed = service(db);
+
+        let mut events = feed.subscribe(PATIENT, Some(&seen.to_hex())).await.unwrap();
+        feed.publish(&credential_issued(PATIENT)).await.unwrap();
+
+        assert_eq!(next(&mut events).await.unwrap().id, Some(missed_first));
+        assert_eq!(next(&mut events).await.unwrap().id, Some(missed_second));
+        assert_eq!(next(&mut events).await.unwrap().event_type, "credential_issued");
+        assert!(next(&mut events).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn open_streams_are_capped_per_user_until_one_closes() {
+        let feed = service(MockFeedEventStore::new());
+        let first = feed.open_stream(PATIENT).unwrap();
+        let _second = feed.open_stream(PATIENT).unwrap();
+
+        let refused = feed.open_stream(PATIENT).err().unwrap();
+        assert!(matches!(refused.downcast_ref::<ServiceError>(), Some(ServiceError::RateLimited(_))));
+        assert!(feed.open_stream(PRACTITIONER).is_ok(), "other users have their own allowance");
+
+        drop(first);
+        assert!(feed.open_stream(PATIENT).is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_malformed_last_event_id_is_rejected() {
+        let feed = service(MockFeedEventStore::new());
+        assert!(feed.subscribe(PATIENT, Some("not-an-id")).await.is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::anyhow;
+use bson::oid::ObjectId;
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auditing::AuditLogService;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::ServiceError;
+use crate::store::{OrganizationStore, PractitionerStore};
+
+const IDENTIFIER_TAKEN: &str = "Another organization already uses that identifier";
+
+// --- OrganizationService ---
+pub struct OrganizationService {
+    db: Arc<dyn OrganizationStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl OrganizationService {
+    pub fn new(db: Arc<dyn OrganizationStore>, practitioners: Arc<dyn PractitionerStore>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, practitioners, audit_log_service }
+    }
+
+    pub async fn create_organization(&self, admin_did: &str, request: OrganizationRequest) -> anyhow::Result<Organization> {
+        let tenant_id = request.tenant_id.clone();
+        let fhir_organization = fhir_organization(Uuid::new_v4().to_string(), true, request)?;
+        let mut organization = Organization { id: None, fhir_organization, tenant_id, created_at: Utc::now(), updated_at: Utc::now() };
+        let id = self.db.create_organization(&organization).await.map_err(identifier_conflict)?;
+        organization.id = Some(id);
+        self.audit_log_service.log(admin_did, "create_organization", Some(json!({ "organization_id": id.to_hex() }))).await;
+        Ok(organization)
+    }
+
+    pub async fn get_organization(&self, organization_id: &str) -> anyhow::Result<Organization> {
+        let id = parse_id(organization_id, "organization")?;
+        self.db.get_organization(id).await?.ok_or_else(|| anyhow!("Organization not found"))
+    }
+
+    pub async fn list_organizations(&self) -> anyhow::Result<Vec<Organization>> {
+        self.db.list_organizations().await
+    }
+
+    /// Replace the organization's details, keeping its FHIR id and whether it is active
+    pub async fn update_organization(&self, admin_did: &str, organization_id: &str, request: OrganizationRequest) -> anyhow::Result<Organization> {
+        let mut organization = self.get_organization(organization_id).await?;
+        let current = &organization.fhir_organization;
+        organization.fhir_organization = fhir_organization(current.id.clone(), current.active, request)?;
+        self.save(admin_did, "update_organization", organization).await
+    }
+
+    /// Organizations are never removed: appointments and encounters keep pointing at them.
+    /// Deactivating one stops new practitioners from asking to join.
+    pub async fn deactivate_organization(&self, admin_did: &str, organization_id: &str) -> anyhow::Result<Organization> {
+        let mut organization = self.get_organization(organization_id).await?;
+        organization.fhir_organization.active = false;
+        self.save(admin_did, "deactivate_organization", organization).await
+    }
+
+    /// A registered practitioner asks to join an organization; it stays pending until an organization admin approves it
+    pub async fn request_affiliation(&self, practitioner_did: &str, request: RequestAffiliationRequest) -> anyhow::Result<Affiliation> {
+        self.ensure_practitioner(practitioner_did).await?;
+        let organization = self.find_active_organization(&request.organization_identifier).await?;
+        let organization_id = organization.id.ok_or_else(|| anyhow!("Organization has no id"))?;
+        let open = self.db.list_affiliations(organization_id).await?.into_iter().any(|affiliation| {
+            affiliation.practitioner_did == practitioner_did
+                && affiliation.role == request.role
+                && affiliation.status != AffiliationStatus::Rejected
+                && affiliation.period_end.map_or(true, |end| Utc::now() < end)
+        });
+        if open {
+            return Err(ServiceError::Conflict("You already have an affiliation with this organization in that role".to_string()).into());
+        }
+        let affiliation = Affiliation {
+            id: None,
+            practitioner_did: practitioner_did.to_string(),
+            organization_id,
+            role: request.role,
+            status: AffiliationStatus::Pending,
+            period_start: None,
+            period_end: None,
+            decided_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        self.insert_affiliation(practitioner_did, "request_affiliation", affiliation).await
+    }
+
+    /// Record an affiliation directly as an admin, e.g. to appoint an organization's first admin
+    pub async fn add_affiliation(&self, admin_did: &str, organization_id: &str, request: CreateAffiliationRequest) -> anyhow::Result<Affiliation> {
+        let organization_id = self.get_organization(organization_id).await?.id.ok_or_else(|| anyhow!("Organization has no id"))?;
+        self.ensure_practitioner(&request.practitioner_did).await?;
+        let period_start = request.period_start.unwrap_or_else(Utc::now);
+        if request.period_end.is_some_and(|end| end <= period_start) {
+            return Err(anyhow!("The affiliation must end after it starts"));
+        }
+        let affiliation = Affiliation {
+            id: None,
+            practitioner_did: request.practitioner_did,
+            organization_id,
+            role: request.role,
+            status: AffiliationStatus::Active,
+            period_start: Some(period_start),
+            period_end: request.period_end,
+            decided_by: Some(admin_did.to_string()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        self.insert_affiliation(admin_did, "add_affiliation", affiliation).await
+    }
+
+    pub async fn list_affiliations(&self, caller_did: &str, caller_role: Role, organization_id: &str) -> anyhow::Result<Vec<Affiliation>> {
+        let organization_id = self.ensure_organization_admin(caller_did, caller_role, organization_id).await?;
+        self.db.list_affiliations(organization_id).await
+    }
+
+    /// Approve or reject a pending affiliation as an admin of its organization
+    pub async fn decide_affiliation(
+        &self,
+        caller_did: &str,
+        caller_role: Role,
+        organization_id: &str,
+        affiliation_id: &str,
+        approve: bool,
+    ) -> anyhow::Result<Affiliation> {
+        let organization_id = self.ensure_organization_admin(caller_did, caller_role, organization_id).await?;
+        let id = parse_id(affiliation_id, "affiliation")?;
+        match self.db.get_affiliation(id).await? {
+            Some(affiliation) if affiliation.organization_id == organization_id => {}
+            _ => return Err(anyhow!("Affiliation not found")),
+        }
+        let status = if approve { AffiliationStatus::Active } else { AffiliationStatus::Rejected };
+        let affiliation = self
+            .db
+            .decide_affiliation(id, status, caller_did, Utc::now())
+            .await?
+            .ok_or_else(|| ServiceError::Conflict("The affiliation was already approved or rejected".to_string()))?;
+        let action = if approve { "approve_affiliation" } else { "reject_affiliation" };
+        self.audit_log_service
+            .log(caller_did, action, Some(json!({ "affiliation_id": affiliation_id, "practitioner": affiliation.practitioner_did })))
+            .await;
+        Ok(affiliation)
+    }
+
+    /// The organization's approved affiliations, for listing its activity; only its admins may ask
+    pub async fn affiliations_in_scope(&self, caller_did: &str, caller_role: Role, organization_id: &str) -> anyhow::Result<Vec<Affiliation>> {
+        let affiliations = self.list_affiliations(caller_did, caller_role, organization_id).await?;
+        Ok(affiliations.into_iter().filter(|affiliation| affiliation.status == AffiliationStatus::Active).collect())
+    }
+
+    pub async fn find_active_organization(&self, identifier: &str) -> anyhow::Result<Organization> {
+        self.db
+            .find_organization_by_identifier(identifier.trim())
+            .await?
+            .filter(|organization| organization.fhir_organization.active)
+            .ok_or_else(|| anyhow!("Organization not found"))
+    }
+
+    /// Admins manage every organization of their tenant; practitioners only those they currently hold an admin affiliation with
+    async fn ensure_organization_admin(&self, caller_did: &str, caller_role: Role, organization_id: &str) -> anyhow::Result<ObjectId> {
+        let id = parse_id(organization_id, "organization")?;
+        let allowed = caller_role.is_admin()
+            || self
+                .db
+                .admin_affiliations(caller_did)
+                .await?
+                .iter()
+                .any(|affiliation| affiliation.organization_id == id && affiliation.is_current(Utc::now()));
+        if !allowed {
+            return Err(ServiceError::Forbidden("Only the organization's admins can do that".to_string()).into());
+        }
+        Ok(id)
+    }
+
+    async fn ensure_practitioner(&self, did: &str) -> anyhow::Result<()> {
+        if self.practitioners.get_practitioner_by_did(did).await?.is_none() {
+            return Err(ServiceError::Forbidden("Only registered practitioners can be affiliated with an organization".to_string()).into());
+        }
+        Ok(())
+    }
+
+    async fn save(&self, admin_did: &str, action: &str, mut organization: Organization) -> anyhow::Result<Organization> {
+        let id = organization.id.ok_or_else(|| anyhow!("Organization has no id"))?;
+        if !self.db.update_organization(id, &organization.fhir_organization).await.map_err(identifier_conflict)? {
+            return Err(anyhow!("Organization not found"));
+        }
+        organization.updated_at = Utc::now();
+        self.audit_log_service.log(admin_did, action, Some(json!({ "organization_id": id.to_hex() }))).await;
+        Ok(organization)
+    }
+
+    async fn insert_affiliation(&self, actor_did: &str, action: &str, mut affiliation: Affiliation) -> anyhow::Result<Affiliation> {
+        let id = self.db.create_affiliation(&affiliation).await?;
+        affiliation.id = Some(id);
+        self.audit_log_service
+            .log(
+                actor_did,
+                action,
+                Some(json!({
+                    "affiliation_id": id.to_hex(),
+                    "organization_id": affiliation.organization_id.to_hex(),
+                    "practitioner": affiliation.practitioner_did,
+                    "role": affiliation.role,
+                })),
+            )
+            .await;
+        Ok(affiliation)
+    }
+}
+
+fn fhir_organization(id: String, active: bool, request: OrganizationRequest) -> anyhow::Result<FhirOrganization> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(anyhow!("Organization name is required"));
+    }
+    if request.identifier.iter().any(|identifier| identifier.value.trim().is_empty()) {
+        return Err(anyhow!("Organization identifiers need a value"));
+    }
+    Ok(FhirOrganization {
+        resource_type: "Organization".to_string(),
+        id,
+        active,
+        identifier: request.identifier,
+        organization_type: request.organization_type,
+        name: name.to_string(),
+        telecom: request.telecom,
+        address: request.address,
+    })
+}
+
+fn identifier_conflict(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<DatabaseError>() {
+        Some(DatabaseError::DuplicateKey(_)) => ServiceError::Conflict(IDENTIFIER_TAKEN.to_string()).into(),
+        _ => e,
+    }
+}
+
+fn parse_id(id: &str, what: &str) -> anyhow::Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid {} id", what))
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAuditStore, MockOrganizationStore, MockPractitionerStore};
+    use chrono::Duration;
+
+    const ORG_ADMIN: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+
+    fn service(organizations: MockOrganizationStore, practitioners: MockPractitionerStore) -> OrganizationService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        OrganizationService::new(Arc::new(organizations), Arc::new(practitioners), Arc::new(AuditLogService::new(Arc::new(audit_store))))
+    }
+
+    fn affiliation(organization_id: ObjectId, role: AffiliationRole, status: AffiliationStatus, period_end: Option<chrono::DateTime<Utc>>) -> Affiliation {
+        Affiliation {
+            id: Some(ObjectId::new()),
+            practitioner_did: ORG_ADMIN.to_string(),
+            organization_id,
+            role,
+            status,
+            period_start: Some(Utc::now() - Duration::days(30)),
+            period_end,
+            decided_by: None,
+            created_at: Utc::now() - Duration::days(30),
+            updated_at: Utc::now() - Duration::days(30),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_current_admins_of_that_organization_see_its_affiliations() {
+        let clinic = ObjectId::new();
+        let other_clinic = ObjectId::new();
+        let mut organizations = MockOrganizationStore::new();
+        organizations.expect_admin_affiliations().returning(move |_| {
+            Ok(vec![
+                affiliation(other_clinic, AffiliationRole::Admin, AffiliationStatus::Active, None),
+                affiliation(clinic, AffiliationRole::Admin, AffiliationStatus::Active, Some(Utc::now() - Duration::days(1))),
+            ])
+        });
+        organizations.expect_list_affiliations().never();
+        let service = service(organizations, MockPractitionerStore::new());
+
+        let err = service.list_affiliations(ORG_ADMIN, Role::Practitioner, &clinic.to_hex()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn only_active_affiliations_are_in_scope() {
+        let clinic = ObjectId::new();
+        let mut organizations = MockOrganizationStore::new();
+        organizations
+            .expect_admin_affiliations()
+            .returning(move |_| Ok(vec![affiliation(clinic, AffiliationRole::Admin, AffiliationStatus::Active, None)]));
+        organizations.expect_list_affiliations().returning(move |_| {
+            Ok(vec![
+                affiliation(clinic, AffiliationRole::Practitioner, AffiliationStatus::Pending, None),
+                affiliation(clinic, AffiliationRole::Practitioner, AffiliationStatus::Active, None),
+                affiliation(clinic, AffiliationRole::Practitioner, AffiliationStatus::Rejected, None),
+            ])
+        });
+        let service = service(organizations, MockPractitionerStore::new());
+
+        let scope = service.affiliations_in_scope(ORG_ADMIN, Role::Practitioner, &clinic.to_hex()).await.unwrap();
+        assert_eq!(scope.len(), 1);
+        assert_eq!(scope[0].status, AffiliationStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn unregistered_practitioners_cannot_request_affiliations() {
+        let mut organizations = MockOrganizationStore::new();
+        organizations.expect_create_affiliation().never();
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+
+        let request = RequestAffiliationRequest { organization_identifier: "MFL-10001".to_string(), role: AffiliationRole::Practitioner };
+        let err = service(organizations, practitioners).request_affiliation(PRACTITIONER, request).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn organizations_need_a_name() {
+        let mut organizations = MockOrganizationStore::new();
+        organizations.expect_create_organization().never();
+        let request = OrganizationRequest { name: "  ".to_string(), organization_type: vec![], identifier: vec![], telecom: vec![], address: vec![], tenant_id: None };
+
+        let err = service(organizations, MockPractitionerStore::new()).create_organization(ORG_ADMIN, request).await.unwrap_err();
+        assert_eq!(err.to_string(), "Organization name is required");
+    }
+}
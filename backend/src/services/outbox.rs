@@ -0,0 +1,305 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::auditing::ContractCallLookup;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::{Job, OutboxAction, OUTBOX_JOB};
+use crate::services::hedera::LedgerAnchor;
+use crate::services::ipfs::ObjectStorage;
+use crate::store::{CredentialStore, PatientStore};
+
+/// Performs the external calls queued in the outbox and writes the resulting transaction ids
+/// back to the credentials and grants they belong to.
+///
+/// A job can run again after its call went through: its worker died before completing it, or
+/// the response was lost on the way back. So an action does nothing once its record has a
+/// transaction id, and a retry looks for the earlier call on the mirror node before calling
+/// again. Retries wait at least 30 seconds, which gives the mirror node time to catch up.
+pub struct OutboxDispatcher {
+    credentials: Arc<dyn CredentialStore>,
+    grants: Arc<dyn PatientStore>,
+    ipfs: Arc<dyn ObjectStorage>,
+    ledger: Arc<dyn LedgerAnchor>,
+    credential_calls: Option<Arc<dyn ContractCallLookup>>,
+    grant_calls: Option<Arc<dyn ContractCallLookup>>,
+}
+
+impl OutboxDispatcher {
+    pub fn new(credentials: Arc<dyn CredentialStore>, grants: Arc<dyn PatientStore>, ipfs: Arc<dyn ObjectStorage>, ledger: Arc<dyn LedgerAnchor>) -> Self {
+        Self { credentials, grants, ipfs, ledger, credential_calls: None, grant_calls: None }
+    }
+
+    /// Look up the calls of earlier attempts on the Credentials and AccessControl contracts.
+    /// Without lookups, an action whose response was lost is sent again and may be recorded twice.
+    pub fn with_call_lookups(mut self, credentials: Arc<dyn ContractCallLookup>, grants: Arc<dyn ContractCallLookup>) -> Self {
+        self.credential_calls = Some(credentials);
+        self.grant_calls = Some(grants);
+        self
+    }
+
+    /// The transaction of an earlier attempt at this job that carried `argument`
+    async fn earlier_call(&self, lookup: Option<&Arc<dyn ContractCallLookup>>, job: &Job, argument: &[u8]) -> anyhow::Result<Option<String>> {
+        // Attempts are counted when a job is claimed, so only a retry can follow a call
+        if job.attempts <= 1 {
+            return Ok(None);
+        }
+        match lookup {
+            Some(lookup) => lookup.find_call(argument, job.created_at).await,
+            None => {
+                tracing::warn!("No mirror node to check outbox job {:?} against; it may reach the ledger twice", job.id);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl JobHandler for OutboxDispatcher {
+    fn job_type(&self) -> &'static str {
+        OUTBOX_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        let action: OutboxAction = serde_json::from_value(job.payload.clone())
+            .map_err(|e| JobError::Permanent(anyhow!("Malformed outbox job: {}", e)))?;
+        match action {
+            // Pinning an object that is already pinned changes nothing
+            OutboxAction::Pin { ipfs_hash } => {
+                self.ipfs.pin_add(&ipfs_hash).await?;
+            }
+            OutboxAction::StoreCredential { credential_id, subject_did, credential_type, ipfs_hash, expires_at, metadata } => {
+                let credential = self
+                    .credentials
+                    .get_credential(credential_id)
+                    .await?
+                    .ok_or_else(|| JobError::Permanent(anyhow!("Credential {} not found", credential_id)))?;
+                if credential.hedera_transaction_id.is_some() {
+                    return Ok(());
+                }
+                let transaction_id = match self.earlier_call(self.credential_calls.as_ref(), job, ipfs_hash.as_bytes()).await? {
+                    Some(transaction_id) => transaction_id,
+                    None => self.ledger.store_credential(&subject_did, &credential_type, &ipfs_hash, expires_at, &metadata).await?,
+                };
+                self.credentials.set_credential_transaction(credential_id, &transaction_id).await?;
+                tracing::info!("Stored credential {} on the ledger in {}", credential_id, transaction_id);
+            }
+            OutboxAction::AnchorCredentialBatch { batch_id, merkle_root, size } => {
+                let batch = self
+                    .credentials
+                    .get_credential_batch(batch_id)
+                    .await?
+                    .ok_or_else(|| JobError::Permanent(anyhow!("Credential batch {} not found", batch_id)))?;
+                let root: [u8; 32] = hex::decode(&merkle_root)
+                    .ok()
+                    .and_then(|root| root.try_into().ok())
+                    .ok_or_else(|| JobError::Permanent(anyhow!("Credential batch {} has a malformed root", batch_id)))?;
+                // A batch anchored by an earlier run may not have reached its credentials yet
+                let transaction_id = match batch.hedera_transaction_id {
+                    Some(transaction_id) => transaction_id,
+                    None => match self.earlier_call(self.credential_calls.as_ref(), job, &root).await? {
+                        Some(transaction_id) => transaction_id,
+                        None => self.ledger.store_credential_batch(root, u64::from(size)).await?,
+                    },
+                };
+                self.credentials.set_credential_batch_transaction(batch_id, &transaction_id).await?;
+                tracing::info!("Anchored credential batch {} of {} on the ledger in {}", batch_id, size, transaction_id);
+            }
+            OutboxAction::AnchorAccessGrant { grant_id, patient_did, grantee_did, expires_at } => {
+                let grant = self
+                    .grants
+                    .get_grant(grant_id)
+                    .await?
+                    .ok_or_else(|| JobError::Permanent(anyhow!("Access grant {} not found", grant_id)))?;
+                if grant.hedera_transaction_id.is_some() {
+                    return Ok(());
+                }
+                let transaction_id = match self.earlier_call(self.grant_calls.as_ref(), job, grant_id.to_hex().as_bytes()).await? {
+                    Some(transaction_id) => transaction_id,
+                    None => self.ledger.record_access_grant(&grant_id.to_hex(), &patient_did, &grantee_did, expires_at).await?,
+                };
+                self.grants.set_grant_transaction(grant_id, &transaction_id).await?;
+                tracing::info!("Recorded access grant {} on the ledger in {}", grant_id, transaction_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::models::{AccessControl, CredentialBatch, VerifiableCredential};
+    use crate::services::fakes::{InMemoryObjectStorage, RecordingLedgerAnchor};
+    use crate::store::{MockCredentialStore, MockPatientStore};
+    use bson::oid::ObjectId;
+    use chrono::Utc;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const GRANTEE: &str = "did:hedera:testnet:0.0.2";
+
+    fn credential(id: ObjectId, hedera_transaction_id: Option<&str>) -> VerifiableCredential {
+        VerifiableCredential {
+            id: Some(id),
+            subject_did: PATIENT.to_string(),
+            credential_type: "VaccinationCredential".to_string(),
+            issuer: GRANTEE.to_string(),
+            issued_at: Utc::now(),
+            expires_at: None,
+            ipfs_hash: "QmVaccination".to_string(),
+            hedera_transaction_id: hedera_transaction_id.map(str::to_string),
+            metadata: "{}".to_string(),
+            revoked_at: None,
+            status_list: None,
+            batch: None,
+        }
+    }
+
+    fn store_credential(id: ObjectId, attempts: u32) -> Job {
+        let action = OutboxAction::StoreCredential {
+            credential_id: id,
+            subject_did: PATIENT.to_string(),
+            credential_type: "VaccinationCredential".to_string(),
+            ipfs_hash: "QmVaccination".to_string(),
+            expires_at: None,
+            metadata: "{}".to_string(),
+        };
+        Job { id: Some(ObjectId::new()), attempts, ..Job::new(OUTBOX_JOB, serde_json::to_value(action).unwrap(), Utc::now()) }
+    }
+
+    fn dispatcher(credentials: MockCredentialStore, grants: MockPatientStore, ledger: &Arc<RecordingLedgerAnchor>) -> OutboxDispatcher {
+        OutboxDispatcher::new(Arc::new(credentials), Arc::new(grants), Arc::new(InMemoryObjectStorage::new()), ledger.clone())
+            .with_call_lookups(ledger.clone(), ledger.clone())
+    }
+
+    /// A store whose credential has no transaction id until one is written back
+    fn pending_credential(id: ObjectId) -> MockCredentialStore {
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_get_credential().returning(move |_| Ok(Some(credential(id, None))));
+        credentials
+    }
+
+    #[tokio::test]
+    async fn a_credential_is_stored_on_the_ledger_and_the_transaction_written_back() {
+        let id = ObjectId::new();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut credentials = pending_credential(id);
+        credentials
+            .expect_set_credential_transaction()
+            .withf(move |credential_id, transaction_id| *credential_id == id && transaction_id == "0.0.2@1700000000.000000000")
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        dispatcher(credentials, MockPatientStore::new(), &ledger).run(&store_credential(id, 1)).await.unwrap();
+
+        assert_eq!(ledger.credentials().len(), 1);
+        assert_eq!(ledger.credentials()[0].ipfs_hash, "QmVaccination");
+    }
+
+    #[tokio::test]
+    async fn a_retry_after_a_lost_response_finds_the_call_instead_of_storing_twice() {
+        let id = ObjectId::new();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        ledger.lose_next_record_response();
+        let mut credentials = pending_credential(id);
+        credentials
+            .expect_set_credential_transaction()
+            .withf(|_, transaction_id| transaction_id == "0.0.2@1700000000.000000000")
+            .times(1)
+            .returning(|_, _| Ok(true));
+        let dispatcher = dispatcher(credentials, MockPatientStore::new(), &ledger);
+
+        let lost = dispatcher.run(&store_credential(id, 1)).await;
+        assert!(matches!(lost, Err(JobError::Transient(_))));
+        dispatcher.run(&store_credential(id, 2)).await.unwrap();
+
+        assert_eq!(ledger.credentials().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_job_whose_worker_died_after_the_write_back_does_nothing_more() {
+        let id = ObjectId::new();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_get_credential().returning(move |_| Ok(Some(credential(id, Some("0.0.2@1")))));
+        credentials.expect_set_credential_transaction().never();
+
+        dispatcher(credentials, MockPatientStore::new(), &ledger).run(&store_credential(id, 2)).await.unwrap();
+
+        assert!(ledger.credentials().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_batch_root_is_anchored_once_and_written_to_its_credentials() {
+        let id = ObjectId::new();
+        let root = [3; 32];
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        ledger.lose_next_record_response();
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_get_credential_batch().returning(move |_| {
+            Ok(Some(CredentialBatch {
+                id: Some(id),
+                merkle_root: hex::encode(root),
+                size: 500,
+                issuer: GRANTEE.to_string(),
+                created_at: Utc::now(),
+                hedera_transaction_id: None,
+            }))
+        });
+        credentials
+            .expect_set_credential_batch_transaction()
+            .withf(move |batch_id, transaction_id| *batch_id == id && transaction_id == "0.0.2@1700000000.000000000")
+            .times(1)
+            .returning(|_, _| Ok(true));
+        let dispatcher = dispatcher(credentials, MockPatientStore::new(), &ledger);
+        let action = OutboxAction::AnchorCredentialBatch { batch_id: id, merkle_root: hex::encode(root), size: 500 };
+        let job = |attempts| Job { attempts, ..Job::new(OUTBOX_JOB, serde_json::to_value(&action).unwrap(), Utc::now()) };
+
+        assert!(matches!(dispatcher.run(&job(1)).await, Err(JobError::Transient(_))));
+        dispatcher.run(&job(2)).await.unwrap();
+
+        assert_eq!(ledger.credential_batches(), vec![(root, 500)]);
+        assert!(ledger.credentials().is_empty());
+    }
+
+    #[tokio::test]
+    async fn grants_are_recorded_under_their_id() {
+        let id = ObjectId::new();
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut grants = MockPatientStore::new();
+        grants.expect_get_grant().returning(move |_| {
+            Ok(Some(AccessControl {
+                id: Some(id),
+                patient_did: PATIENT.to_string(),
+                grantee_did: GRANTEE.to_string(),
+                permissions: vec![],
+                active: true,
+                created_at: Utc::now(),
+                expires_at: None,
+                emergency: false,
+                hedera_transaction_id: None,
+            }))
+        });
+        grants.expect_set_grant_transaction().withf(move |grant_id, _| *grant_id == id).times(1).returning(|_, _| Ok(true));
+        let action = OutboxAction::AnchorAccessGrant { grant_id: id, patient_did: PATIENT.to_string(), grantee_did: GRANTEE.to_string(), expires_at: Some(1_900_000_000) };
+        let job = Job { attempts: 1, ..Job::new(OUTBOX_JOB, serde_json::to_value(action).unwrap(), Utc::now()) };
+
+        dispatcher(MockCredentialStore::new(), grants, &ledger).run(&job).await.unwrap();
+
+        let recorded = ledger.grants();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!((recorded[0].grant_id.as_str(), recorded[0].grantee_did.as_str()), (id.to_hex().as_str(), GRANTEE));
+    }
+
+    #[tokio::test]
+    async fn an_action_for_a_missing_record_is_not_retried() {
+        let ledger = Arc::new(RecordingLedgerAnchor::new());
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_get_credential().returning(|_| Ok(None));
+
+        let result = dispatcher(credentials, MockPatientStore::new(), &ledger).run(&store_credential(ObjectId::new(), 1)).await;
+
+        assert!(matches!(result, Err(JobError::Permanent(_))));
+        assert!(ledger.credentials().is_empty());
+    }
+}
@@ -1,23 +1,698 @@
 
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
 use std::sync::Arc;
 use crate::config::Config;
-use crate::database::Database;
+use crate::events::{DomainEvent, EventBus};
+use crate::jobs::{JobError, JobHandler};
+use crate::store::{EncounterStore, PatientStore};
 use crate::models::*;
 use crate::auditing::AuditLogService;
+use crate::services::fhir::FhirManager;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::services::problems::problem_list;
+use crate::services::{ConsentService, DataAccessMonitor, RelationshipService, ServiceError};
+use crate::utils;
+
+/// Recurring job that erases patients once their restore window is over
+pub const ERASE_DELETED_PATIENTS_JOB: &str = "erase_deleted_patients";
+
+/// How a caller reached a patient's record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Owner,
+    Granted,
+    /// As the patient's guardian or delegate
+    Guardian,
+    /// Through break-glass; every read is tagged as such in the audit log
+    Emergency,
+}
 
 // --- PatientService ---
 pub struct PatientService {
-    db: Arc<Database>,
+    db: Arc<dyn PatientStore>,
+    encounters: Arc<dyn EncounterStore>,
     config: Arc<Config>,
     audit_log_service: Arc<AuditLogService>,
+    notification_service: Arc<NotificationService>,
+    consent_service: Arc<ConsentService>,
+    relationship_service: Arc<RelationshipService>,
+    events: Arc<EventBus>,
+    access_monitor: Option<Arc<DataAccessMonitor>>,
 }
 
 impl PatientService {
-    pub fn new(db: Arc<Database>, config: Arc<Config>, audit_log_service: Arc<AuditLogService>) -> Self {
-        Self { db, config, audit_log_service }
+    pub fn new(
+        db: Arc<dyn PatientStore>,
+        encounters: Arc<dyn EncounterStore>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+        notification_service: Arc<NotificationService>,
+        consent_service: Arc<ConsentService>,
+        relationship_service: Arc<RelationshipService>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Self { db, encounters, config, audit_log_service, notification_service, consent_service, relationship_service, events, access_monitor: None }
+    }
+
+    /// Hold callers to the hourly limits on reading other patients' records
+    pub fn with_access_monitor(mut self, access_monitor: Arc<DataAccessMonitor>) -> Self {
+        self.access_monitor = Some(access_monitor);
+        self
+    }
+
+    /// Count the caller reading `did`'s record, reached through `access`, refusing it with
+    /// [`ServiceError::RateLimited`] once they have read too many patients this hour
+    pub async fn count_read(&self, caller_did: &str, did: &str, access: Access) -> anyhow::Result<()> {
+        match &self.access_monitor {
+            Some(monitor) => monitor.record_read(caller_did, did, access).await,
+            None => Ok(()),
+        }
     }
-    pub async fn get_patient(&self, did: &str) -> anyhow::Result<Option<Patient>> {
-        self.audit_log_service.log(did, "get_patient", None).await;
+
+    /// As [`count_read`](Self::count_read), also counting the download of the file `cid`
+    pub async fn count_download(&self, caller_did: &str, did: &str, cid: &str, access: Access) -> anyhow::Result<()> {
+        match &self.access_monitor {
+            Some(monitor) => monitor.record_download(caller_did, did, cid, access).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The patient's record, for the patient themselves, their guardians or someone they shared it with
+    pub async fn get_patient(&self, caller_did: &str, did: &str) -> anyhow::Result<Option<Patient>> {
+        let Some(access) = self.check_access(caller_did, did, "Patient").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's record".to_string()).into());
+        };
+        self.count_read(caller_did, did, access).await?;
+        self.audit_log_service.log(did, "get_patient", Some(read_details(caller_did, did, access))).await;
         self.db.get_patient_by_did(did, &self.config.ipfs_encryption_key).await
     }
+
+    /// As [`get_patient`](Self::get_patient) for the patient reading their own record, which the
+    /// auth middleware already loaded
+    pub async fn own_record(&self, patient: Patient) -> Patient {
+        self.audit_log_service.log(&patient.did, "get_patient", Some(read_details(&patient.did, &patient.did, Access::Owner))).await;
+        patient
+    }
+
+    /// How `caller_did` may see `data_class` of the patient's record, if at all: always for the
+    /// patient and their current guardians, otherwise with an active grant whose permissions cover it
+    /// and, when `require_consent` is set, a consent covering it. Break-glass grants need no consent;
+    /// that is what they are for.
+    pub async fn check_access(&self, caller_did: &str, patient_did: &str, data_class: &str) -> anyhow::Result<Option<Access>> {
+        if caller_did == patient_did {
+            return Ok(Some(Access::Owner));
+        }
+        if self.relationship_service.guardianship(caller_did, patient_did).await?.is_some() {
+            return Ok(Some(Access::Guardian));
+        }
+        let (emergency, granted): (Vec<AccessControl>, Vec<AccessControl>) = self
+            .db
+            .active_grants(patient_did, caller_did)
+            .await?
+            .into_iter()
+            .filter(|grant| grant.covers(data_class))
+            .partition(|grant| grant.emergency);
+        if !granted.is_empty()
+            && (!self.config.dynamic.load().require_consent || self.consent_service.permits(patient_did, caller_did, data_class).await?)
+        {
+            return Ok(Some(Access::Granted));
+        }
+        Ok((!emergency.is_empty()).then_some(Access::Emergency))
+    }
+
+    /// Whether one of the caller's grants on the patient's record includes `permission`,
+    /// for actions such as prescribing that reading access does not cover
+    pub async fn has_permission(&self, caller_did: &str, patient_did: &str, permission: Permission) -> anyhow::Result<bool> {
+        let grants = self.db.active_grants(patient_did, caller_did).await?;
+        Ok(grants.iter().any(|grant| grant.permissions.contains(&permission)))
+    }
+
+    /// FHIR `Patient/$everything`: a searchset Bundle of the patient, their encounters and,
+    /// for the patient themselves, their consents. Grantees only get the classes they may see.
+    pub async fn everything(&self, caller_did: &str, did: &str) -> anyhow::Result<serde_json::Value> {
+        let Some(access) = self.check_access(caller_did, did, "Patient").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's record".to_string()).into());
+        };
+        self.count_read(caller_did, did, access).await?;
+        let patient = self
+            .db
+            .get_patient_by_did(did, &self.config.ipfs_encryption_key)
+            .await?
+            .ok_or_else(|| anyhow!("Patient not found"))?;
+
+        let mut resources = Vec::new();
+        if self.check_access(caller_did, did, "Encounter").await?.is_some() {
+            // A limit of 0 returns every encounter
+            for encounter in self.encounters.list_recent_encounters(did, 0).await? {
+                resources.push(json!(encounter.fhir_encounter));
+            }
+        }
+        if caller_did == did {
+            for consent in self.consent_service.list_consents(caller_did, did).await? {
+                resources.push(json!(consent.fhir_consent));
+            }
+        }
+
+        let mut details = read_details(caller_did, did, access);
+        details["resources"] = json!(resources.len() + 1);
+        self.audit_log_service.log(did, "export_everything", Some(details)).await;
+        self.notification_service.notify(NotificationEvent::RecordExported { patient_did: did.to_string() });
+        Ok(FhirManager::create_searchset_bundle(&patient, resources))
+    }
+
+    /// Trend of one LOINC code in the patient's observations, for anyone who may read them
+    pub async fn observation_summary(
+        &self,
+        caller_did: &str,
+        did: &str,
+        code: &str,
+        period: ObservationPeriod,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<ObservationSummary> {
+        if code.trim().is_empty() {
+            return Err(anyhow!("A LOINC code is required"));
+        }
+        if from.zip(to).is_some_and(|(from, to)| from >= to) {
+            return Err(anyhow!("'from' must be before 'to'"));
+        }
+        let Some(access) = self.check_access(caller_did, did, "Observation").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's observations".to_string()).into());
+        };
+        self.count_read(caller_did, did, access).await?;
+        let mut details = read_details(caller_did, did, access);
+        details["code"] = json!(code);
+        self.audit_log_service.log(did, "summarize_observations", Some(details)).await;
+        self.encounters.summarize_observations(did, code.trim(), period, from, to).await
+    }
+
+    /// The patient's problem list, merged from the conditions of all their encounters and
+    /// imported data, for anyone who may read their conditions
+    pub async fn problems(&self, caller_did: &str, did: &str, include_resolved: bool) -> anyhow::Result<Vec<Problem>> {
+        let Some(access) = self.check_access(caller_did, did, "Condition").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's conditions".to_string()).into());
+        };
+        self.count_read(caller_did, did, access).await?;
+        self.audit_log_service.log(did, "list_problems", Some(read_details(caller_did, did, access))).await;
+        let timezone = utils::patient_timezone(self.db.get_patient_timezone(did).await?.as_deref());
+        Ok(problem_list(self.encounters.list_patient_conditions(did).await?, include_resolved, timezone))
+    }
+
+    pub async fn soft_delete_patient(&self, admin_did: &str, did: &str) -> anyhow::Result<()> {
+        if !self.db.soft_delete_patient(did).await? {
+            return Err(anyhow!("Patient not found"));
+        }
+        self.audit_log_service.log(did, "soft_delete_patient", Some(json!({ "actor": admin_did }))).await;
+        Ok(())
+    }
+
+    pub async fn restore_patient(&self, admin_did: &str, did: &str) -> anyhow::Result<()> {
+        let grace_period = chrono::Duration::days(self.config.soft_delete_grace_days);
+        if !self.db.restore_patient(did, grace_period).await? {
+            return Err(anyhow!("Patient is not deleted or the restore window has passed"));
+        }
+        self.audit_log_service.log(did, "restore_patient", Some(json!({ "actor": admin_did }))).await;
+        Ok(())
+    }
+
+    /// Erase the patients deleted longer ago than the restore window, destroying their data keys.
+    /// Returns how many were erased.
+    pub async fn erase_expired(&self) -> anyhow::Result<usize> {
+        let deleted_before = Utc::now() - chrono::Duration::days(self.config.soft_delete_grace_days);
+        let erased = self.db.erase_deleted_patients(deleted_before).await?;
+        for did in &erased {
+            self.audit_log_service.log(did, "erase_patient", None).await;
+        }
+        if !erased.is_empty() {
+            tracing::info!("Erased {} deleted patient(s)", erased.len());
+        }
+        Ok(erased.len())
+    }
+
+    /// Let `grantee_did` see the caller's own record; patients cannot grant access to anyone else's
+    pub async fn grant_access(&self, caller_did: &str, request: GrantAccessRequest) -> anyhow::Result<AccessControl> {
+        if request.patient_did != caller_did {
+            return Err(anyhow!("Patients can only grant access to their own record"));
+        }
+        let access_control = self
+            .create_grant(AccessControl {
+                id: None,
+                patient_did: request.patient_did,
+                grantee_did: request.grantee_did,
+                permissions: request.permissions,
+                active: true,
+                created_at: chrono::Utc::now(),
+                expires_at: request.expires_at,
+                emergency: false,
+                hedera_transaction_id: None,
+            })
+            .await?;
+        self.audit_log_service
+            .log(&access_control.patient_did, "grant_access", Some(json!({ "grantee": access_control.grantee_did })))
+            .await;
+        if let Some(id) = access_control.id {
+            self.events.publish(DomainEvent::AccessGranted {
+                grant_id: id.to_hex(),
+                patient_did: access_control.patient_did.clone(),
+                grantee_did: access_control.grantee_did.clone(),
+            });
+        }
+        Ok(access_control)
+    }
+
+    /// Store a grant along with the consent behind it; callers check it is the patient's to give.
+    /// The outbox records the grant on the ledger afterwards.
+    pub async fn create_grant(&self, mut access_control: AccessControl) -> anyhow::Result<AccessControl> {
+        let grant_id = *access_control.id.get_or_insert_with(bson::oid::ObjectId::new);
+        let outbox = [OutboxAction::AnchorAccessGrant {
+            grant_id,
+            patient_did: access_control.patient_did.clone(),
+            grantee_did: access_control.grantee_did.clone(),
+            expires_at: access_control.expires_at.map(|expires_at| expires_at.timestamp() as u64),
+        }];
+        access_control.id = Some(self.db.grant_access(&access_control, &outbox).await?);
+        // Regulators expect an explicit consent artifact behind every grant
+        self.consent_service.record_grant(&access_control).await?;
+        Ok(access_control)
+    }
+
+    /// False when the grant does not exist or already ended
+    pub async fn deactivate_grant(&self, id: bson::oid::ObjectId) -> anyhow::Result<bool> {
+        self.db.deactivate_grant(id).await
+    }
+
+    /// The caller's own preferences, or the defaults if they never saved any
+    pub async fn get_notification_preferences(&self, caller_did: &str, did: &str) -> anyhow::Result<NotificationPreferences> {
+        ensure_owner(caller_did, did)?;
+        Ok(self.db.get_notification_preferences(did).await?.unwrap_or_default())
+    }
+
+    pub async fn update_notification_preferences(
+        &self,
+        caller_did: &str,
+        did: &str,
+        preferences: NotificationPreferences,
+    ) -> anyhow::Result<NotificationPreferences> {
+        ensure_owner(caller_did, did)?;
+        if !self.db.set_notification_preferences(did, &preferences).await? {
+            return Err(anyhow!("Patient not found"));
+        }
+        self.audit_log_service
+            .log(did, "update_notification_preferences", Some(json!({ "preferences": preferences })))
+            .await;
+        Ok(preferences)
+    }
+
+    pub async fn get_chat_record_consent(&self, caller_did: &str, did: &str) -> anyhow::Result<bool> {
+        ensure_owner(caller_did, did)?;
+        self.db.get_chat_record_consent(did).await
+    }
+
+    /// Allow or stop the chat assistant reading a summary of the patient's record
+    pub async fn set_chat_record_consent(&self, caller_did: &str, did: &str, enabled: bool) -> anyhow::Result<bool> {
+        ensure_owner(caller_did, did)?;
+        if !self.db.set_chat_record_consent(did, enabled).await? {
+            return Err(anyhow!("Patient not found"));
+        }
+        self.audit_log_service.log(did, "update_chat_record_consent", Some(json!({ "enabled": enabled }))).await;
+        Ok(enabled)
+    }
+
+    /// The IANA time zone reminders are written in; `None` means UTC
+    pub async fn get_timezone(&self, caller_did: &str, did: &str) -> anyhow::Result<Option<String>> {
+        ensure_owner(caller_did, did)?;
+        self.db.get_patient_timezone(did).await
+    }
+
+    pub async fn set_timezone(&self, caller_did: &str, did: &str, timezone: &str) -> anyhow::Result<String> {
+        ensure_owner(caller_did, did)?;
+        let timezone = timezone.trim();
+        if timezone.parse::<chrono_tz::Tz>().is_err() {
+            return Err(anyhow!("Unknown time zone: {}", timezone));
+        }
+        if !self.db.set_patient_timezone(did, timezone).await? {
+            return Err(anyhow!("Patient not found"));
+        }
+        self.audit_log_service.log(did, "update_timezone", Some(json!({ "timezone": timezone }))).await;
+        Ok(timezone.to_string())
+    }
+}
+
+/// Audit details for a read of `did`'s record, tagged when it went through guardianship or break-glass
+pub(crate) fn read_details(caller_did: &str, did: &str, access: Access) -> serde_json::Value {
+    let mut details = json!({ "actor": caller_did });
+    match access {
+        Access::Guardian => {
+            details["guardian"] = json!(caller_did);
+            details["dependent"] = json!(did);
+        }
+        Access::Emergency => details["emergency"] = json!(true),
+        Access::Owner | Access::Granted => {}
+    }
+    details
+}
+
+fn ensure_owner(caller_did: &str, did: &str) -> anyhow::Result<()> {
+    if caller_did != did {
+        return Err(ServiceError::Forbidden("Patients can only manage their own settings".to_string()).into());
+    }
+    Ok(())
+}
+
+/// Erases deleted patients past the restore window, hourly
+pub struct PatientErasureHandler {
+    service: Arc<PatientService>,
+}
+
+impl PatientErasureHandler {
+    pub fn new(service: Arc<PatientService>) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for PatientErasureHandler {
+    fn job_type(&self) -> &'static str {
+        ERASE_DELETED_PATIENTS_JOB
+    }
+
+    fn repeat_every(&self) -> Option<chrono::Duration> {
+        Some(chrono::Duration::hours(1))
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        self.service.erase_expired().await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::DynamicConfig;
+    use crate::services::notification::MockNotificationSender;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::store::{
+        MockAuditStore, MockConsentStore, MockEncounterStore, MockNotificationStore, MockPatientStore, MockPractitionerStore, MockRelationshipStore,
+    };
+
+    const DID: &str = "did:hedera:testnet:0.0.1";
+    const GRANTEE: &str = "did:hedera:testnet:0.0.2";
+
+    fn service(patients: MockPatientStore, audit_store: MockAuditStore) -> PatientService {
+        service_with(patients, audit_store, MockConsentStore::new(), no_relationships(), Config::default())
+    }
+
+    fn no_relationships() -> MockRelationshipStore {
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_get_relationship().returning(|_, _| Ok(None));
+        relationships
+    }
+
+    fn service_with(
+        patients: MockPatientStore,
+        audit_store: MockAuditStore,
+        consents: MockConsentStore,
+        relationships: MockRelationshipStore,
+        config: Config,
+    ) -> PatientService {
+        let patients: Arc<dyn PatientStore> = Arc::new(patients);
+        let config = Arc::new(config);
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        // Notified patients are not found in these tests, so delivery falls through to the practitioner lookup
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(MockNotificationStore::new()),
+            patients.clone(),
+            Arc::new(practitioners),
+            Arc::new(MockNotificationSender::new()),
+            config.clone(),
+        ));
+        let consent_service = Arc::new(ConsentService::new(
+            Arc::new(consents),
+            patients.clone(),
+            Arc::new(InMemoryObjectStorage::new()),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let relationship_service = Arc::new(RelationshipService::new(
+            Arc::new(relationships),
+            patients.clone(),
+            Arc::new(MockPractitionerStore::new()),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        PatientService::new(
+            patients,
+            Arc::new(MockEncounterStore::new()),
+            config,
+            audit_log_service,
+            notification_service,
+            consent_service,
+            relationship_service,
+            Arc::new(EventBus::new(16)),
+        )
+    }
+
+    #[tokio::test]
+    async fn unsaved_preferences_default_to_transactional_only() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_notification_preferences().returning(|_| Ok(None));
+
+        let preferences = service(patients, MockAuditStore::new()).get_notification_preferences(DID, DID).await.unwrap();
+
+        assert!(preferences.allows(PreferenceCategory::SecurityAlerts, NotificationChannel::Sms));
+        assert!(!preferences.allows(PreferenceCategory::Marketing, NotificationChannel::Email));
+    }
+
+    #[tokio::test]
+    async fn preference_changes_are_saved_and_audited() {
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_set_notification_preferences()
+            .withf(|did, preferences| did == DID && !preferences.sms)
+            .times(1)
+            .returning(|_, _| Ok(true));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.did == DID && log.action == "update_notification_preferences")
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let preferences = NotificationPreferences { sms: false, ..Default::default() };
+        service(patients, audit_store).update_notification_preferences(DID, DID, preferences).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_patients_preferences_are_forbidden() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_set_notification_preferences().never();
+
+        let err = service(patients, MockAuditStore::new())
+            .update_notification_preferences("did:hedera:testnet:0.0.2", DID, NotificationPreferences::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn unknown_time_zones_are_rejected() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_set_patient_timezone().never();
+
+        let err = service(patients, MockAuditStore::new()).set_timezone(DID, DID, "Africa/Atlantis").await.unwrap_err();
+
+        assert_eq!(err.to_string(), "Unknown time zone: Africa/Atlantis");
+    }
+
+    #[tokio::test]
+    async fn time_zone_changes_are_saved_and_audited() {
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_set_patient_timezone()
+            .withf(|did, timezone| did == DID && timezone == "Africa/Nairobi")
+            .times(1)
+            .returning(|_, _| Ok(true));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.did == DID && log.action == "update_timezone")
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let timezone = service(patients, audit_store).set_timezone(DID, DID, " Africa/Nairobi ").await.unwrap();
+
+        assert_eq!(timezone, "Africa/Nairobi");
+    }
+
+    fn consent(data_classes: &[&str]) -> Consent {
+        Consent {
+            id: None,
+            patient_did: DID.to_string(),
+            grantee_did: GRANTEE.to_string(),
+            status: ConsentStatus::Active,
+            data_classes: data_classes.iter().map(|class| class.to_string()).collect(),
+            period_start: chrono::Utc::now() - chrono::Duration::days(1),
+            period_end: None,
+            access_control_id: None,
+            fhir_consent: serde_json::from_value(json!({
+                "resourceType": "Consent",
+                "id": "c1",
+                "status": "active",
+                "scope": { "coding": [], "text": null },
+                "category": [],
+                "patient": { "reference": format!("Patient/{}", DID), "display": null },
+                "dateTime": "2026-10-01T00:00:00Z",
+                "provision": { "type": "permit", "period": null, "actor": [], "class": [] },
+            }))
+            .unwrap(),
+            ipfs_hash: "QmConsent".to_string(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    fn grant(emergency: bool) -> AccessControl {
+        AccessControl {
+            id: Some(bson::oid::ObjectId::new()),
+            patient_did: DID.to_string(),
+            grantee_did: GRANTEE.to_string(),
+            permissions: vec![Permission::Read, Permission::ViewEncounters],
+            active: true,
+            created_at: chrono::Utc::now(),
+            expires_at: None,
+            emergency,
+            hedera_transaction_id: None,
+        }
+    }
+
+    fn granted(emergency: bool) -> MockPatientStore {
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_active_grants()
+            .returning(move |patient, grantee| Ok((patient == DID && grantee == GRANTEE).then(|| grant(emergency)).into_iter().collect()));
+        patients
+    }
+
+    #[tokio::test]
+    async fn grantees_need_a_grant_and_optionally_a_covering_consent() {
+        let mut consents = MockConsentStore::new();
+        consents.expect_active_consents().returning(|_, _| Ok(vec![consent(&["Patient"])]));
+        let without_consent = service(granted(false), MockAuditStore::new());
+        let with_consent = service_with(granted(false), MockAuditStore::new(), consents, no_relationships(), Config { dynamic: DynamicConfig { require_consent: true, ..Default::default() }.into(), ..Default::default() });
+
+        assert_eq!(without_consent.check_access(DID, DID, "Encounter").await.unwrap(), Some(Access::Owner));
+        assert_eq!(without_consent.check_access(GRANTEE, DID, "Encounter").await.unwrap(), Some(Access::Granted));
+        assert_eq!(without_consent.check_access("did:hedera:testnet:0.0.3", DID, "Patient").await.unwrap(), None);
+        assert_eq!(with_consent.check_access(GRANTEE, DID, "Patient").await.unwrap(), Some(Access::Granted));
+        assert_eq!(with_consent.check_access(GRANTEE, DID, "Encounter").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn grants_only_reach_the_data_classes_their_permissions_cover() {
+        let service = service(granted(false), MockAuditStore::new());
+
+        assert_eq!(service.check_access(GRANTEE, DID, "Condition").await.unwrap(), Some(Access::Granted));
+        assert_eq!(service.check_access(GRANTEE, DID, "Observation").await.unwrap(), None);
+        assert_eq!(service.check_access(GRANTEE, DID, "MedicationRequest").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn emergency_reads_skip_consent_and_are_tagged_in_the_audit_log() {
+        let mut patients = granted(true);
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.action == "get_patient" && log.details.as_ref().is_some_and(|details| details["emergency"] == true))
+            .times(1)
+            .returning(|_| Ok(()));
+        let mut consents = MockConsentStore::new();
+        consents.expect_active_consents().never();
+        let service = service_with(patients, audit_store, consents, no_relationships(), Config { dynamic: DynamicConfig { require_consent: true, ..Default::default() }.into(), ..Default::default() });
+
+        assert_eq!(service.check_access(GRANTEE, DID, "Encounter").await.unwrap(), Some(Access::Emergency));
+        service.get_patient(GRANTEE, DID).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn granting_access_records_a_consent() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_grant_access().times(1).returning(|_, _| Ok(bson::oid::ObjectId::new()));
+        patients.expect_get_notification_preferences().returning(|_| Ok(None));
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let mut consents = MockConsentStore::new();
+        consents
+            .expect_create_consent()
+            .withf(|consent| consent.grantee_did == GRANTEE && consent.data_classes == ["Observation"] && consent.access_control_id.is_some())
+            .times(1)
+            .returning(|_| Ok(bson::oid::ObjectId::new()));
+        let config = Config { ipfs_encryption_key: "00".repeat(32).into(), ..Default::default() };
+        let request = GrantAccessRequest {
+            patient_did: DID.to_string(),
+            grantee_did: GRANTEE.to_string(),
+            permissions: vec![Permission::ViewObservations],
+            expires_at: None,
+        };
+
+        service_with(patients, audit_store, consents, no_relationships(), config).grant_access(DID, request).await.unwrap();
+    }
+
+    fn guardian_of_dependent(expires_at: chrono::DateTime<chrono::Utc>) -> MockRelationshipStore {
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_get_relationship().returning(move |guardian, dependent| {
+            Ok(Some(Relationship {
+                id: None,
+                guardian_did: guardian.to_string(),
+                dependent_did: dependent.to_string(),
+                relationship: RelationshipType::Parent,
+                established_by: "did:hedera:testnet:0.0.9".to_string(),
+                created_at: expires_at - chrono::Duration::days(365),
+                expires_at: Some(expires_at),
+                active: true,
+            }))
+        });
+        relationships
+    }
+
+    #[tokio::test]
+    async fn guardians_read_their_dependents_records_with_both_dids_audited() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_active_grants().never();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| {
+                log.did == DID
+                    && log.details.as_ref().is_some_and(|details| details["guardian"] == GRANTEE && details["dependent"] == DID)
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+        let relationships = guardian_of_dependent(chrono::Utc::now() + chrono::Duration::days(30));
+        let service = service_with(patients, audit_store, MockConsentStore::new(), relationships, Config::default());
+
+        assert_eq!(service.check_access(GRANTEE, DID, "Encounter").await.unwrap(), Some(Access::Guardian));
+        service.get_patient(GRANTEE, DID).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_guardianship_falls_back_to_grants() {
+        let mut patients = MockPatientStore::new();
+        patients.expect_active_grants().returning(|_, _| Ok(vec![]));
+        let relationships = guardian_of_dependent(chrono::Utc::now() - chrono::Duration::seconds(1));
+        let service = service_with(patients, MockAuditStore::new(), MockConsentStore::new(), relationships, Config::default());
+
+        assert_eq!(service.check_access(GRANTEE, DID, "Patient").await.unwrap(), None);
+        let err = service.get_patient(GRANTEE, DID).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
 }
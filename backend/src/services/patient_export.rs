@@ -0,0 +1,497 @@
+//! Patients' copies of everything held about them (GDPR data portability): a ZIP of their FHIR
+//! resources, credentials, consents and audit trail, built in the background and handed out
+//! through short-lived signed links.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::DatabaseError;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::services::ServiceError;
+use crate::store::{AuditStore, ConsentStore, CredentialStore, EncounterStore, JobStore, PatientExportStore, PatientStore, PrescriptionStore};
+use crate::utils;
+
+/// Expired exports unpinned per sweep; the rest wait for the next run
+const EXPIRY_BATCH_SIZE: i64 = 100;
+
+/// One file of an archive
+struct ExportFile {
+    path: String,
+    content: Vec<u8>,
+}
+
+impl ExportFile {
+    fn json(path: impl Into<String>, value: &impl Serialize) -> Result<Self> {
+        Ok(Self { path: path.into(), content: serde_json::to_vec_pretty(value)? })
+    }
+
+    fn manifest_entry(&self) -> ExportManifestEntry {
+        ExportManifestEntry { path: self.path.clone(), sha256: hex::encode(Sha256::digest(&self.content)), size: self.content.len() as u64 }
+    }
+}
+
+/// A decrypted archive on its way to the patient
+pub struct ExportDownload {
+    pub filename: String,
+    pub archive: Vec<u8>,
+}
+
+// --- PatientExportService ---
+pub struct PatientExportService {
+    exports: Arc<dyn PatientExportStore>,
+    jobs: Arc<dyn JobStore>,
+    patients: Arc<dyn PatientStore>,
+    encounters: Arc<dyn EncounterStore>,
+    prescriptions: Arc<dyn PrescriptionStore>,
+    credentials: Arc<dyn CredentialStore>,
+    consents: Arc<dyn ConsentStore>,
+    audit_logs: Arc<dyn AuditStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+    notification_service: Arc<NotificationService>,
+}
+
+impl PatientExportService {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        exports: Arc<dyn PatientExportStore>,
+        jobs: Arc<dyn JobStore>,
+        patients: Arc<dyn PatientStore>,
+        encounters: Arc<dyn EncounterStore>,
+        prescriptions: Arc<dyn PrescriptionStore>,
+        credentials: Arc<dyn CredentialStore>,
+        consents: Arc<dyn ConsentStore>,
+        audit_logs: Arc<dyn AuditStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+        notification_service: Arc<NotificationService>,
+    ) -> Self {
+        Self {
+            exports,
+            jobs,
+            patients,
+            encounters,
+            prescriptions,
+            credentials,
+            consents,
+            audit_logs,
+            ipfs_client,
+            config,
+            audit_log_service,
+            notification_service,
+        }
+    }
+
+    fn retention(&self) -> Duration {
+        Duration::hours(self.config.patient_exports.retention_hours)
+    }
+
+    /// Queue an export of the caller's own record. A patient has at most one export being
+    /// built; asking again while it is returns that one.
+    pub async fn request(&self, caller_did: &str, patient_did: &str) -> Result<PatientExportView> {
+        if caller_did != patient_did {
+            return Err(ServiceError::Forbidden("Patients can only export their own record".to_string()).into());
+        }
+        let now = Utc::now();
+        let mut export = PatientExport {
+            id: Some(ObjectId::new()),
+            patient_did: patient_did.to_string(),
+            status: PatientExportStatus::Pending,
+            archive: None,
+            error: None,
+            created_at: now,
+            completed_at: None,
+            // Pushed back once the archive is built; a build that never finishes is swept then
+            expires_at: now + self.retention(),
+        };
+        let id = match self.exports.create_patient_export(&export).await {
+            Ok(id) => id,
+            Err(e) if matches!(e.downcast_ref::<DatabaseError>(), Some(DatabaseError::DuplicateKey(_))) => {
+                match self.exports.pending_patient_export(patient_did).await? {
+                    Some(pending) => return Ok(self.view(&pending)),
+                    // Finished between the insert and the lookup
+                    None => return Err(ServiceError::Conflict("An export was just completed; try again".to_string()).into()),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        export.id = Some(id);
+        let job = Job::new(BUILD_PATIENT_EXPORT_JOB, json!({ "export_id": id.to_hex() }), now);
+        if let Err(e) = self.jobs.enqueue_job(&job).await {
+            // Otherwise the pending export would block new requests until it expired
+            self.exports.fail_patient_export(id, "Could not queue the export").await?;
+            return Err(e);
+        }
+        self.audit_log_service.log(patient_did, "request_patient_export", Some(json!({ "export_id": id.to_hex() }))).await;
+        Ok(self.view(&export))
+    }
+
+    /// The caller's export `export_id`, with a fresh download link once it is ready
+    pub async fn get(&self, caller_did: &str, patient_did: &str, export_id: &str) -> Result<PatientExportView> {
+        if caller_did != patient_did {
+            return Err(ServiceError::Forbidden("Patients can only see their own exports".to_string()).into());
+        }
+        let export = match ObjectId::parse_str(export_id) {
+            Ok(id) => self.exports.get_patient_export(id).await?,
+            Err(_) => None,
+        };
+        match export {
+            Some(export) if export.patient_did == patient_did => Ok(self.view(&export)),
+            _ => Err(anyhow!("Export not found")),
+        }
+    }
+
+    fn view(&self, export: &PatientExport) -> PatientExportView {
+        let id = export.id.map(|id| id.to_hex()).unwrap_or_default();
+        let now = Utc::now();
+        let link = match export.status {
+            PatientExportStatus::Ready if export.expires_at > now => {
+                let expires_at = (now + Duration::minutes(self.config.patient_exports.download_url_minutes)).min(export.expires_at);
+                let signature = download_signature(self.config.jwt_secret.expose_secret(), &id, expires_at.timestamp());
+                let url = format!(
+                    "{}/api/exports/{}/download?expires={}&signature={}",
+                    self.config.backend_base_url.trim_end_matches('/'),
+                    id,
+                    expires_at.timestamp(),
+                    signature
+                );
+                Some((url, expires_at))
+            }
+            _ => None,
+        };
+        let (download_url, download_url_expires_at) = link.unzip();
+        PatientExportView {
+            id,
+            status: export.status,
+            created_at: export.created_at,
+            completed_at: export.completed_at,
+            expires_at: export.expires_at,
+            size: export.archive.as_ref().map(|archive| archive.size),
+            sha256: export.archive.as_ref().map(|archive| archive.sha256.clone()),
+            download_url,
+            download_url_expires_at,
+        }
+    }
+
+    /// The archive behind a signed download link. The signature stands in for a token, so a
+    /// link works on its own but only until it expires; every download is audit-logged.
+    pub async fn download(&self, export_id: &str, expires: i64, signature: &str) -> Result<ExportDownload> {
+        if !download_signature_matches(self.config.jwt_secret.expose_secret(), export_id, expires, signature) {
+            return Err(ServiceError::Forbidden("This download link is not valid".to_string()).into());
+        }
+        if Utc::now().timestamp() > expires {
+            return Err(ServiceError::Forbidden("This download link has expired".to_string()).into());
+        }
+        let id = ObjectId::parse_str(export_id).map_err(|_| anyhow!("Export not found"))?;
+        let export = self.exports.get_patient_export(id).await?.ok_or_else(|| anyhow!("Export not found"))?;
+        let archive = match (&export.status, &export.archive) {
+            (PatientExportStatus::Ready, Some(archive)) if export.expires_at > Utc::now() => archive,
+            _ => return Err(ServiceError::Forbidden("This export is no longer available".to_string()).into()),
+        };
+        let key = self.config.encryption_key(archive.key_version).ok_or_else(|| anyhow!("No key is configured for version {}", archive.key_version))?;
+        let zip = utils::chunked::decrypt(&self.ipfs_client.get_file(&archive.ipfs_hash).await?, key)?;
+        if hex::encode(Sha256::digest(&zip)) != archive.sha256 {
+            return Err(anyhow!("Archive of export {} does not match its digest", export_id));
+        }
+        self.audit_log_service
+            .log(&export.patient_did, "download_patient_export", Some(json!({ "export_id": export_id, "size": zip.len() })))
+            .await;
+        Ok(ExportDownload { filename: format!("wecare-export-{}.zip", export_id), archive: zip })
+    }
+
+    /// Gather the patient's record into the archive of export `id`, store it encrypted and tell
+    /// the patient it is ready. Exports no longer pending are left alone.
+    pub async fn build(&self, id: ObjectId) -> Result<(), JobError> {
+        let Some(export) = self.exports.get_patient_export(id).await? else {
+            return Err(JobError::Permanent(anyhow!("Export {} not found", id)));
+        };
+        if export.status != PatientExportStatus::Pending {
+            return Ok(());
+        }
+        let Some(patient) = self.patients.get_patient_by_did(&export.patient_did, &self.config.ipfs_encryption_key).await? else {
+            return Err(JobError::Permanent(anyhow!("Patient {} not found", export.patient_did)));
+        };
+        let files = self.gather(&patient).await?;
+        let zip = zip_archive(&export, &files).map_err(JobError::Permanent)?;
+
+        let encrypted = utils::chunked::encrypt(&zip, &self.config.ipfs_encryption_key).map_err(JobError::Permanent)?;
+        let ipfs_hash = self.ipfs_client.add_file(&encrypted, None).await?;
+        self.ipfs_client.pin_add(&ipfs_hash).await?;
+        let archive = ExportArchive {
+            ipfs_hash,
+            key_version: self.config.ipfs_encryption_key_version,
+            sha256: hex::encode(Sha256::digest(&zip)),
+            size: zip.len() as u64,
+        };
+        let expires_at = Utc::now() + self.retention();
+        if !self.exports.complete_patient_export(id, &archive, expires_at).await? {
+            // Expired or failed while it was being built, so nothing will ever point at the archive
+            if let Err(e) = self.ipfs_client.pin_rm(&archive.ipfs_hash).await {
+                tracing::warn!("Could not unpin the orphaned archive of export {}: {}", id, e);
+            }
+            return Ok(());
+        }
+        tracing::info!("Export {} of {} is ready ({} bytes)", id, export.patient_did, archive.size);
+        self.notification_service.notify(NotificationEvent::ExportReady {
+            patient_did: export.patient_did,
+            available_until: expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        });
+        Ok(())
+    }
+
+    /// Every file of the archive but the manifest
+    async fn gather(&self, patient: &Patient) -> Result<Vec<ExportFile>> {
+        let did = &patient.did;
+        let mut files = vec![ExportFile::json("Patient.json", &patient.fhir_patient)?];
+
+        // A limit of 0 lists every encounter
+        for encounter in self.encounters.list_recent_encounters(did, 0).await? {
+            let id = encounter.id.map(|id| id.to_hex()).unwrap_or_default();
+            let path = format!("encounters/{}.json", id);
+            files.push(match &encounter.final_bundle_ipfs_hash {
+                Some(hash) => {
+                    let version = encounter.bundle_key_version.unwrap_or(LEGACY_BUNDLE_KEY_VERSION);
+                    let key = self.config.encryption_key(version).ok_or_else(|| anyhow!("No key is configured for version {}", version))?;
+                    ExportFile { path, content: utils::chunked::decrypt(&self.ipfs_client.get_file(hash).await?, key)? }
+                }
+                // Not finalized, so there is no bundle yet; the encounter is all there is
+                None => ExportFile::json(path, &encounter.fhir_encounter)?,
+            });
+        }
+
+        let prescriptions: Vec<Value> = self
+            .prescriptions
+            .list_prescriptions(did, None)
+            .await?
+            .into_iter()
+            .map(|prescription| {
+                json!({
+                    "id": prescription.id.map(|id| id.to_hex()),
+                    "status": prescription.status,
+                    "medication_request": prescription.fhir_medication_request,
+                    "dispenses": prescription.dispenses,
+                    "created_at": prescription.created_at,
+                })
+            })
+            .collect();
+        files.push(ExportFile::json("prescriptions.json", &prescriptions)?);
+
+        for credential in self.credentials.list_credentials_by_subject(did).await? {
+            let stored = self.ipfs_client.get_file(&credential.ipfs_hash).await?;
+            let document = utils::decrypt(std::str::from_utf8(&stored)?, &self.config.ipfs_encryption_key)?;
+            let id = credential.id.map(|id| id.to_hex()).unwrap_or_default();
+            files.push(ExportFile { path: format!("credentials/{}.json", id), content: document });
+        }
+
+        let consents: Vec<FhirConsent> = self.consents.list_consents(did).await?.into_iter().map(|consent| consent.fhir_consent).collect();
+        files.push(ExportFile::json("consents.json", &consents)?);
+
+        let trail: Vec<Value> = self
+            .audit_logs
+            .list_audit_logs_by_did(did)
+            .await?
+            .into_iter()
+            .map(|log| json!({ "timestamp": log.timestamp, "action": log.action, "details": log.details, "anchored": log.is_anchored }))
+            .collect();
+        files.push(ExportFile::json("audit-trail.json", &trail)?);
+        Ok(files)
+    }
+
+    /// Record why export `id` could not be built
+    pub async fn fail(&self, id: ObjectId, error: &JobError) {
+        let message = match error {
+            JobError::Transient(e) | JobError::Permanent(e) => e.to_string(),
+        };
+        if let Err(e) = self.exports.fail_patient_export(id, &message).await {
+            tracing::warn!("Could not mark export {} as failed: {}", id, e);
+        }
+    }
+
+    /// Unpin the archives of exports past their retention and mark them expired; returns how many
+    pub async fn expire_due(&self) -> Result<usize> {
+        let mut expired = 0;
+        for export in self.exports.expired_patient_exports(Utc::now(), EXPIRY_BATCH_SIZE).await? {
+            let id = export.id.ok_or_else(|| anyhow!("Export has no id"))?;
+            if let Some(archive) = &export.archive {
+                // Left for the next sweep, so the archive is not forgotten while still pinned
+                if let Err(e) = self.ipfs_client.pin_rm(&archive.ipfs_hash).await {
+                    tracing::warn!("Could not unpin the archive of export {}: {}", id, e);
+                    continue;
+                }
+            }
+            if self.exports.expire_patient_export(id).await? {
+                expired += 1;
+            }
+        }
+        if expired > 0 {
+            tracing::info!("Expired {} patient export(s)", expired);
+        }
+        Ok(expired)
+    }
+}
+
+/// The `signature` of a download link for export `id` valid until the Unix time `expires`
+pub fn download_signature(key: &str, id: &str, expires: i64) -> String {
+    hex::encode(download_mac(key, id, expires).finalize().into_bytes())
+}
+
+fn download_signature_matches(key: &str, id: &str, expires: i64, signature: &str) -> bool {
+    // Compared in constant time
+    hex::decode(signature).map_or(false, |signature| download_mac(key, id, expires).verify_slice(&signature).is_ok())
+}
+
+fn download_mac(key: &str, id: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"patient-export:");
+    mac.update(id.as_bytes());
+    mac.update(b".");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// The ZIP of `files` plus a `manifest.json` listing each with its SHA-256
+fn zip_archive(export: &PatientExport, files: &[ExportFile]) -> Result<Vec<u8>> {
+    let manifest = json!({
+        "export_id": export.id.map(|id| id.to_hex()),
+        "patient_did": export.patient_did,
+        "generated_at": Utc::now(),
+        "files": files.iter().map(ExportFile::manifest_entry).collect::<Vec<_>>(),
+    });
+    let manifest = ExportFile::json("manifest.json", &manifest)?;
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for file in files.iter().chain([&manifest]) {
+        zip.start_file(file.path.as_str(), options)?;
+        zip.write_all(&file.content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Builds the archive of a requested export. Retries with the job's backoff; once it runs out
+/// of attempts the export is marked failed so the patient can ask again.
+pub struct PatientExportHandler {
+    service: Arc<PatientExportService>,
+    max_attempts: u32,
+}
+
+impl PatientExportHandler {
+    pub fn new(service: Arc<PatientExportService>, max_attempts: u32) -> Self {
+        Self { service, max_attempts }
+    }
+}
+
+#[async_trait]
+impl JobHandler for PatientExportHandler {
+    fn job_type(&self) -> &'static str {
+        BUILD_PATIENT_EXPORT_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        let id = job
+            .payload
+            .get("export_id")
+            .and_then(Value::as_str)
+            .and_then(|id| ObjectId::parse_str(id).ok())
+            .ok_or_else(|| JobError::Permanent(anyhow!("Malformed export job payload: {}", job.payload)))?;
+        let result = self.service.build(id).await;
+        if let Err(e) = &result {
+            if matches!(e, JobError::Permanent(_)) || job.attempts >= self.max_attempts {
+                self.service.fail(id, e).await;
+            }
+        }
+        result
+    }
+}
+
+/// Deletes export archives once their retention is over, hourly
+pub struct PatientExportExpiryHandler {
+    service: Arc<PatientExportService>,
+}
+
+impl PatientExportExpiryHandler {
+    pub fn new(service: Arc<PatientExportService>) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for PatientExportExpiryHandler {
+    fn job_type(&self) -> &'static str {
+        EXPIRE_PATIENT_EXPORTS_JOB
+    }
+
+    fn repeat_every(&self) -> Option<Duration> {
+        Some(Duration::hours(1))
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        self.service.expire_due().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn export() -> PatientExport {
+        PatientExport {
+            id: Some(ObjectId::new()),
+            patient_did: "did:hedera:testnet:0.0.1".to_string(),
+            status: PatientExportStatus::Pending,
+            archive: None,
+            error: None,
+            created_at: Utc::now(),
+            completed_at: None,
+            expires_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn manifest_lists_every_file_with_its_digest() {
+        let files = vec![
+            ExportFile::json("Patient.json", &json!({ "resourceType": "Patient" })).unwrap(),
+            ExportFile { path: "encounters/1.json".to_string(), content: b"{}".to_vec() },
+        ];
+        let mut zip = zip::ZipArchive::new(Cursor::new(zip_archive(&export(), &files).unwrap())).unwrap();
+        let mut manifest = String::new();
+        zip.by_name("manifest.json").unwrap().read_to_string(&mut manifest).unwrap();
+        let manifest: Value = serde_json::from_str(&manifest).unwrap();
+        let entries: Vec<ExportManifestEntry> = serde_json::from_value(manifest["files"].clone()).unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in entries {
+            let mut content = Vec::new();
+            zip.by_name(&entry.path).unwrap().read_to_end(&mut content).unwrap();
+            assert_eq!(entry.sha256, hex::encode(Sha256::digest(&content)));
+            assert_eq!(entry.size, content.len() as u64);
+        }
+    }
+
+    #[test]
+    fn download_signature_binds_export_and_expiry() {
+        let signature = download_signature("secret", "abc", 100);
+        assert!(download_signature_matches("secret", "abc", 100, &signature));
+        assert!(!download_signature_matches("secret", "abc", 101, &signature));
+        assert!(!download_signature_matches("secret", "abd", 100, &signature));
+        assert!(!download_signature_matches("other", "abc", 100, &signature));
+        assert!(!download_signature_matches("secret", "abc", 100, "not hex"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Finding patients registered twice and folding the duplicate record into the survivor.
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::Database;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::store::{JobStore, PatientMergeStore, SessionStore};
+use crate::utils::{hash_email, hash_phone, normalize_email, normalize_phone};
+
+/// Job type that scans every patient for likely duplicates
+pub const DETECT_DUPLICATE_PATIENTS_JOB: &str = "detect_duplicate_patients";
+/// Pairs scoring below this are not recorded
+pub const MIN_SCORE: f64 = 0.5;
+/// Largest page the candidate list returns
+pub const MAX_PAGE_SIZE: u64 = 100;
+/// Patients sharing one key beyond this are too common a match (a clinic's front-desk phone,
+/// "john smith") to compare pairwise; the block is skipped
+const MAX_BLOCK_SIZE: usize = 50;
+
+const EMAIL_WEIGHT: f64 = 0.6;
+const PHONE_WEIGHT: f64 = 0.6;
+const NAME_WEIGHT: f64 = 0.3;
+const BIRTH_DATE_WEIGHT: f64 = 0.2;
+
+/// What the scan compares, taken from one decrypted record
+#[derive(Debug)]
+struct Identity {
+    did: String,
+    email_hashes: BTreeSet<String>,
+    phone_hashes: BTreeSet<String>,
+    name: Option<String>,
+    birth_date: Option<String>,
+}
+
+impl Identity {
+    fn new(did: &str, patient: &FhirPatient) -> Self {
+        let hashes = |system: &str, hash: fn(&str) -> String| {
+            patient.telecom.iter().filter(|c| c.system == system && !c.value.trim().is_empty()).map(|c| hash(&c.value)).collect()
+        };
+        Self {
+            did: did.to_string(),
+            email_hashes: hashes("email", hash_email),
+            phone_hashes: hashes("phone", hash_phone),
+            name: patient.name.first().and_then(normalized_name),
+            birth_date: Some(patient.birth_date.trim().to_string()).filter(|date| !date.is_empty()),
+        }
+    }
+
+    /// The blocks this patient falls in; only patients sharing a block are compared
+    fn blocking_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.email_hashes.iter().map(|hash| format!("email:{}", hash)).collect();
+        keys.extend(self.phone_hashes.iter().map(|hash| format!("phone:{}", hash)));
+        if let Some(name) = &self.name {
+            keys.push(format!("name:{}", name));
+        }
+        keys
+    }
+}
+
+/// "Jane Wanjiru Otieno" and "otieno, jane wanjiru" both become "jane otieno wanjiru"
+fn normalized_name(name: &FhirHumanName) -> Option<String> {
+    let mut tokens: Vec<String> = name
+        .given
+        .iter()
+        .chain(&name.family)
+        .flat_map(|part| part.split_whitespace())
+        .map(|token| token.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect::<String>())
+        .filter(|token| !token.is_empty())
+        .collect();
+    tokens.sort();
+    (!tokens.is_empty()).then(|| tokens.join(" "))
+}
+
+/// How alike two patients are, 0 to 1, and what matched
+fn score(a: &Identity, b: &Identity) -> (f64, Vec<String>) {
+    let mut score = 0.0;
+    let mut reasons = Vec::new();
+    if !a.email_hashes.is_disjoint(&b.email_hashes) {
+        score += EMAIL_WEIGHT;
+        reasons.push("email".to_string());
+    }
+    if !a.phone_hashes.is_disjoint(&b.phone_hashes) {
+        score += PHONE_WEIGHT;
+        reasons.push("phone".to_string());
+    }
+    if a.name.is_some() && a.name == b.name {
+        score += NAME_WEIGHT;
+        reasons.push("name".to_string());
+    }
+    if a.birth_date.is_some() && a.birth_date == b.birth_date {
+        score += BIRTH_DATE_WEIGHT;
+        reasons.push("birth_date".to_string());
+    }
+    (f64::min(score, 1.0), reasons)
+}
+
+/// Candidate pairs among `identities`, keyed by the sorted DID pair
+fn find_candidates(identities: &[Identity]) -> BTreeMap<(usize, usize), (f64, Vec<String>)> {
+    let mut blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, identity) in identities.iter().enumerate() {
+        for key in identity.blocking_keys() {
+            blocks.entry(key).or_default().push(index);
+        }
+    }
+    let mut candidates = BTreeMap::new();
+    for (key, members) in blocks {
+        if members.len() > MAX_BLOCK_SIZE {
+            tracing::warn!("Skipping duplicate block {} with {} patients", key.split(':').next().unwrap_or_default(), members.len());
+            continue;
+        }
+        for (i, &a) in members.iter().enumerate() {
+            for &b in &members[i + 1..] {
+                let pair = if identities[a].did < identities[b].did { (a, b) } else { (b, a) };
+                if candidates.contains_key(&pair) {
+                    continue;
+                }
+                let (score, reasons) = score(&identities[pair.0], &identities[pair.1]);
+                if score >= MIN_SCORE {
+                    candidates.insert(pair, (score, reasons));
+                }
+            }
+        }
+    }
+    candidates
+}
+
+/// Contact points are the same when their system and normalized value match
+fn contact_key(contact: &FhirContactPoint) -> (String, String) {
+    let value = match contact.system.as_str() {
+        "email" => normalize_email(&contact.value),
+        "phone" | "sms" => normalize_phone(&contact.value),
+        _ => contact.value.trim().to_lowercase(),
+    };
+    (contact.system.clone(), value)
+}
+
+/// Append the duplicate's contact points the survivor lacks; returns how many were added
+fn merge_telecom(survivor: &mut Vec<FhirContactPoint>, duplicate: &[FhirContactPoint]) -> usize {
+    let mut known: BTreeSet<(String, String)> = survivor.iter().map(contact_key).collect();
+    let before = survivor.len();
+    for contact in duplicate {
+        if known.insert(contact_key(contact)) {
+            survivor.push(contact.clone());
+        }
+    }
+    survivor.len() - before
+}
+
+// --- PatientMergeService ---
+pub struct PatientMergeService {
+    db: Arc<dyn PatientMergeStore>,
+    jobs: Arc<dyn JobStore>,
+    sessions: Arc<dyn SessionStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl PatientMergeService {
+    pub fn new(
+        db: Arc<dyn PatientMergeStore>,
+        jobs: Arc<dyn JobStore>,
+        sessions: Arc<dyn SessionStore>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, jobs, sessions, config, audit_log_service }
+    }
+
+    /// Queue a duplicate scan; returns the job id
+    pub async fn request_scan(&self, admin_did: &str) -> anyhow::Result<String> {
+        let job = Job::new(DETECT_DUPLICATE_PATIENTS_JOB, json!({ "requested_by": admin_did }), Utc::now());
+        let id = self.jobs.enqueue_job(&job).await?;
+        self.audit_log_service
+            .log(admin_did, "admin_scan_duplicate_patients", Some(json!({ "actor": admin_did, "job_id": id.to_hex() })))
+            .await;
+        Ok(id.to_hex())
+    }
+
+    /// Compare every live patient and record the pairs likely to be the same person.
+    /// Returns how many pairs were recorded.
+    pub async fn detect_duplicates(&self) -> anyhow::Result<usize> {
+        let identities: Vec<Identity> = self
+            .db
+            .live_patients()
+            .await?
+            .into_iter()
+            .filter_map(|patient| {
+                match Database::decrypt_fhir_patient(&patient, &self.config.ipfs_encryption_key) {
+                    Ok(fhir_patient) => Some(Identity::new(&patient.did, &fhir_patient)),
+                    Err(e) => {
+                        tracing::warn!(did = %patient.did, "Failed to decrypt patient for the duplicate scan: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let candidates = find_candidates(&identities);
+        let now = Utc::now();
+        for (&(a, b), (score, reasons)) in &candidates {
+            self.db
+                .upsert_merge_candidate(&MergeCandidate {
+                    id: None,
+                    patient_dids: vec![identities[a].did.clone(), identities[b].did.clone()],
+                    score: *score,
+                    reasons: reasons.clone(),
+                    status: MergeCandidateStatus::Open,
+                    detected_at: now,
+                })
+                .await?;
+        }
+        tracing::info!("Duplicate scan compared {} patients and found {} candidate pairs", identities.len(), candidates.len());
+        Ok(candidates.len())
+    }
+
+    /// One page of candidate pairs, most likely duplicates first
+    pub async fn list_candidates(&self, admin_did: &str, status: Option<MergeCandidateStatus>, page: u64, page_size: u64) -> anyhow::Result<Page<MergeCandidate>> {
+        let page = page.max(1);
+        let page_size = page_size.clamp(1, MAX_PAGE_SIZE);
+        let (items, total) = self.db.list_merge_candidates(status, (page - 1) * page_size, page_size as i64).await?;
+        self.audit_log_service
+            .log(admin_did, "admin_list_merge_candidates", Some(json!({ "actor": admin_did, "page": page })))
+            .await;
+        Ok(Page { items, page, page_size, total })
+    }
+
+    /// Fold the duplicate record into the survivor. The duplicate is signed out everywhere and
+    /// its DID resolves to the survivor from then on.
+    pub async fn merge(&self, admin_did: &str, survivor_did: &str, duplicate_did: &str) -> anyhow::Result<PatientMerge> {
+        if survivor_did == duplicate_did {
+            return Err(anyhow!("A patient cannot be merged into itself"));
+        }
+        let key = &self.config.ipfs_encryption_key;
+        // Both must be live records under their own DID, not aliases of another patient
+        let mut survivor = self
+            .db
+            .find_patient_by_did(survivor_did, key, false)
+            .await?
+            .filter(|patient| patient.did == survivor_did)
+            .ok_or_else(|| anyhow!("Surviving patient not found"))?;
+        let duplicate = self
+            .db
+            .find_patient_by_did(duplicate_did, key, false)
+            .await?
+            .filter(|patient| patient.did == duplicate_did)
+            .ok_or_else(|| anyhow!("Duplicate patient not found"))?;
+
+        let telecom_added = merge_telecom(&mut survivor.fhir_patient.telecom, &duplicate.fhir_patient.telecom);
+        let merge = self.db.merge_patients(&survivor, duplicate_did, admin_did, telecom_added, key).await?;
+        let sessions_revoked = self.sessions.revoke_sessions(duplicate_did).await?;
+
+        let details = json!({
+            "actor": admin_did,
+            "merge_id": merge.id.map(|id| id.to_hex()),
+            "survivor_did": survivor_did,
+            "duplicate_did": duplicate_did,
+            "encounters": merge.encounter_ids.len(),
+            "prescriptions": merge.prescription_ids.len(),
+            "credentials": merge.credential_ids.len(),
+            "access_grants": merge.access_grant_ids.len(),
+            "telecom_added": telecom_added,
+            "sessions_revoked": sessions_revoked,
+        });
+        self.audit_log_service.log(survivor_did, "merge_patients", Some(details.clone())).await;
+        self.audit_log_service.log(duplicate_did, "merge_patients", Some(details)).await;
+        Ok(merge)
+    }
+}
+
+/// Runs a duplicate scan queued by an admin
+pub struct DuplicateDetectionHandler {
+    service: Arc<PatientMergeService>,
+}
+
+impl DuplicateDetectionHandler {
+    pub fn new(service: Arc<PatientMergeService>) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for DuplicateDetectionHandler {
+    fn job_type(&self) -> &'static str {
+        DETECT_DUPLICATE_PATIENTS_JOB
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        self.service.detect_duplicates().await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::patient;
+    use crate::store::{MockAuditStore, MockJobStore, MockPatientMergeStore, MockSessionStore};
+    use crate::utils::encrypt;
+    use std::sync::Mutex;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.100";
+    const SURVIVOR: &str = "did:hedera:testnet:0.0.1";
+    const DUPLICATE: &str = "did:hedera:testnet:0.0.2";
+
+    fn contact(system: &str, value: &str) -> FhirContactPoint {
+        FhirContactPoint { system: system.to_string(), value: value.to_string(), r#use: None }
+    }
+
+    fn fhir_patient(given: &str, family: &str, birth_date: &str, telecom: Vec<FhirContactPoint>) -> FhirPatient {
+        FhirPatient {
+            name: vec![FhirHumanName {
+                r#use: None,
+                family: Some(family.to_string()),
+                given: given.split_whitespace().map(str::to_string).collect(),
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+            }],
+            birth_date: birth_date.to_string(),
+            telecom,
+            ..Default::default()
+        }
+    }
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config { ipfs_encryption_key: "11".repeat(32).into(), ..Default::default() })
+    }
+
+    fn encrypted(did: &str, fhir_patient: &FhirPatient) -> EncryptedPatient {
+        let encrypted_fhir_patient = encrypt(serde_json::to_string(fhir_patient).unwrap().as_bytes(), &config().ipfs_encryption_key).unwrap();
+        EncryptedPatient {
+            id: None,
+            did: did.to_string(),
+            encrypted_fhir_patient,
+            email_hash: None,
+            phone_hash: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            email_verified: true,
+            verification_token: None,
+            verification_token_expires: None,
+            deleted_at: None,
+            wrapped_data_key: None,
+            erased_at: None,
+            notification_preferences: None,
+            chat_record_consent: false,
+            timezone: None,
+            totp: None,
+            backup_codes: Vec::new(),
+            disabled_at: None,
+            merged_dids: Vec::new(),
+            search_tokens: Vec::new(),
+            search_key_id: None,
+            photo: None,
+        }
+    }
+
+    fn service(db: MockPatientMergeStore, sessions: MockSessionStore) -> (PatientMergeService, Arc<Mutex<Vec<AuditLog>>>) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut audit_store = MockAuditStore::new();
+        let logged = logs.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.clone());
+            Ok(())
+        });
+        let audit = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        (PatientMergeService::new(Arc::new(db), Arc::new(MockJobStore::new()), Arc::new(sessions), config(), audit), logs)
+    }
+
+    fn identity(did: &str, patient: FhirPatient) -> Identity {
+        Identity::new(did, &patient)
+    }
+
+    #[test]
+    fn names_are_compared_without_case_punctuation_or_order() {
+        let a = fhir_patient("Jane Wanjiru", "Otieno", "", Vec::new());
+        let b = fhir_patient("otieno", "JANE wanjiru.", "", Vec::new());
+        assert_eq!(normalized_name(&a.name[0]).as_deref(), Some("jane otieno wanjiru"));
+        assert_eq!(normalized_name(&a.name[0]), normalized_name(&b.name[0]));
+    }
+
+    #[test]
+    fn a_shared_phone_or_email_is_enough_for_a_candidate() {
+        let clinic = identity(SURVIVOR, fhir_patient("Jane", "Otieno", "", vec![contact("phone", "+254 700 000 001")]));
+        let home = identity(DUPLICATE, fhir_patient("J.", "Otieno", "", vec![contact("phone", "+254700000001"), contact("email", "jane@example.com")]));
+
+        let (score, reasons) = score(&clinic, &home);
+
+        assert!(score >= MIN_SCORE);
+        assert_eq!(reasons, vec!["phone"]);
+    }
+
+    #[test]
+    fn a_name_alone_is_not_enough_but_with_the_birth_date_it_is() {
+        let a = identity(SURVIVOR, fhir_patient("Jane", "Otieno", "1990-01-01", Vec::new()));
+        let b = identity(DUPLICATE, fhir_patient("Jane", "Otieno", "1991-02-02", Vec::new()));
+        let c = identity("did:hedera:testnet:0.0.3", fhir_patient("Jane", "Otieno", "1990-01-01", Vec::new()));
+
+        assert!(score(&a, &b).0 < MIN_SCORE);
+        let (score, reasons) = score(&a, &c);
+        assert!(score >= MIN_SCORE);
+        assert_eq!(reasons, vec!["name", "birth_date"]);
+    }
+
+    #[test]
+    fn scores_are_capped_at_one() {
+        let telecom = vec![contact("email", "jane@example.com"), contact("phone", "+254700000001")];
+        let a = identity(SURVIVOR, fhir_patient("Jane", "Otieno", "1990-01-01", telecom.clone()));
+        let b = identity(DUPLICATE, fhir_patient("Jane", "Otieno", "1990-01-01", telecom));
+        assert_eq!(score(&a, &b).0, 1.0);
+    }
+
+    #[test]
+    fn only_patients_sharing_a_key_are_paired_once() {
+        let identities = vec![
+            identity(DUPLICATE, fhir_patient("Jane", "Otieno", "1990-01-01", vec![contact("email", "jane@example.com")])),
+            identity(SURVIVOR, fhir_patient("Jane", "Otieno", "1990-01-01", vec![contact("email", "JANE@example.com ")])),
+            identity("did:hedera:testnet:0.0.3", fhir_patient("Peter", "Kamau", "1990-01-01", Vec::new())),
+        ];
+
+        let candidates = find_candidates(&identities);
+
+        assert_eq!(candidates.len(), 1);
+        let (&(a, b), (_, reasons)) = candidates.iter().next().unwrap();
+        assert_eq!((identities[a].did.as_str(), identities[b].did.as_str()), (SURVIVOR, DUPLICATE));
+        assert_eq!(reasons, &vec!["email", "name", "birth_date"]);
+    }
+
+    #[test]
+    fn telecom_merge_skips_contact_points_the_survivor_has() {
+        let mut survivor = vec![contact("phone", "+254 700 000 001")];
+        let duplicate = vec![contact("phone", "+254700000001"), contact("email", "Jane@Example.com"), contact("email", "jane@example.com")];
+
+        assert_eq!(merge_telecom(&mut survivor, &duplicate), 1);
+        assert_eq!(survivor.len(), 2);
+        assert_eq!(survivor[1].value, "Jane@Example.com");
+    }
+
+    #[tokio::test]
+    async fn detection_records_each_candidate_pair() {
+        let mut db = MockPatientMergeStore::new();
+        let jane = fhir_patient("Jane", "Otieno", "1990-01-01", vec![contact("phone", "+254700000001")]);
+        let patients = vec![encrypted(DUPLICATE, &jane), encrypted(SURVIVOR, &jane), encrypted("did:hedera:testnet:0.0.3", &fhir_patient("Peter", "Kamau", "", Vec::new()))];
+        db.expect_live_patients().return_once(move || Ok(patients));
+        db.expect_upsert_merge_candidate()
+            .withf(|candidate| candidate.patient_dids == [SURVIVOR, DUPLICATE] && candidate.status == MergeCandidateStatus::Open && candidate.score == 1.0)
+            .times(1)
+            .returning(|_| Ok(()));
+        let (service, _) = service(db, MockSessionStore::new());
+
+        assert_eq!(service.detect_duplicates().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn merge_folds_the_duplicate_telecom_into_the_survivor_and_signs_it_out() {
+        let mut db = MockPatientMergeStore::new();
+        db.expect_find_patient_by_did().returning(|did, _, include_deleted| {
+            assert!(!include_deleted);
+            let telecom = if did == SURVIVOR { vec![contact("phone", "+254700000001")] } else { vec![contact("email", "jane@example.com")] };
+            Ok(Some(patient(did, fhir_patient("Jane", "Otieno", "", telecom))))
+        });
+        db.expect_merge_patients()
+            .withf(|survivor, duplicate, merged_by, added, _| {
+                survivor.did == SURVIVOR && survivor.fhir_patient.telecom.len() == 2 && duplicate == DUPLICATE && merged_by == ADMIN && *added == 1
+            })
+            .returning(|survivor, duplicate, merged_by, telecom_added, _| {
+                Ok(PatientMerge {
+                    id: Some(bson::oid::ObjectId::new()),
+                    survivor_did: survivor.did.clone(),
+                    duplicate_did: duplicate.to_string(),
+                    merged_by: merged_by.to_string(),
+                    merged_at: Utc::now(),
+                    encounter_ids: vec![bson::oid::ObjectId::new()],
+                    prescription_ids: Vec::new(),
+                    credential_ids: Vec::new(),
+                    access_grant_ids: Vec::new(),
+                    telecom_added,
+                    survivor_record_before: String::new(),
+                    survivor_email_hash_before: None,
+                    survivor_phone_hash_before: None,
+                    duplicate_email_hash: None,
+                    duplicate_phone_hash: None,
+                })
+            });
+        let mut sessions = MockSessionStore::new();
+        sessions.expect_revoke_sessions().withf(|did| did == DUPLICATE).times(1).returning(|_| Ok(2));
+        let (service, logs) = service(db, sessions);
+
+        let merge = service.merge(ADMIN, SURVIVOR, DUPLICATE).await.unwrap();
+
+        assert_eq!(merge.telecom_added, 1);
+        let logs = logs.lock().unwrap();
+        let dids: Vec<&str> = logs.iter().map(|log| log.did.as_str()).collect();
+        assert_eq!(dids, vec![SURVIVOR, DUPLICATE]);
+        assert!(logs.iter().all(|log| log.action == "merge_patients"));
+    }
+
+    #[tokio::test]
+    async fn an_alias_is_not_merged_again() {
+        let mut db = MockPatientMergeStore::new();
+        // DUPLICATE was already merged into the survivor, so it resolves to the survivor's record
+        db.expect_find_patient_by_did().returning(|_, _, _| Ok(Some(patient(SURVIVOR, fhir_patient("Jane", "Otieno", "", Vec::new())))));
+        db.expect_merge_patients().never();
+        let (service, _) = service(db, MockSessionStore::new());
+
+        let error = service.merge(ADMIN, SURVIVOR, DUPLICATE).await.unwrap_err();
+        assert_eq!(error.to_string(), "Duplicate patient not found");
+    }
+
+    #[tokio::test]
+    async fn a_patient_cannot_be_merged_into_itself() {
+        let (service, _) = service(MockPatientMergeStore::new(), MockSessionStore::new());
+        assert!(service.merge(ADMIN, SURVIVOR, SURVIVOR).await.is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use image::{ImageFormat, ImageOutputFormat};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::{Config, UploadConfig};
+use crate::models::*;
+use crate::services::attachments::AttachmentType;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::patient::read_details;
+use crate::services::{PatientService, ServiceError};
+use crate::store::PatientStore;
+use crate::utils;
+
+/// JPEG quality of thumbnails, which are only ever shown small
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// A photo checked and ready to store, with its thumbnail
+#[derive(Debug)]
+struct PreparedPhoto {
+    content_type: &'static str,
+    width: u32,
+    height: u32,
+    /// Always a JPEG
+    thumbnail: Vec<u8>,
+}
+
+/// An image of a patient photo, decrypted
+pub struct PhotoDownload {
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+// --- PatientPhotoService ---
+/// The photo clinics show at the front desk to confirm they are treating the right person.
+/// The photo and a thumbnail made of it are encrypted, pinned on IPFS and recorded on the patient.
+pub struct PatientPhotoService {
+    patients: Arc<dyn PatientStore>,
+    patient_service: Arc<PatientService>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl PatientPhotoService {
+    pub fn new(
+        patients: Arc<dyn PatientStore>,
+        patient_service: Arc<PatientService>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { patients, patient_service, ipfs_client, config, audit_log_service }
+    }
+
+    /// Set or replace the patient's photo, as the patient or a practitioner they granted `Write`.
+    /// A replaced photo is unpinned.
+    pub async fn upload(&self, caller_did: &str, caller_role: Role, did: &str, content: &[u8]) -> Result<PatientPhoto> {
+        let allowed = caller_did == did
+            || (caller_role == Role::Practitioner
+                && self
+                    .patients
+                    .active_grants(did, caller_did)
+                    .await?
+                    .iter()
+                    .any(|grant| grant.permissions.contains(&Permission::Write)));
+        if !allowed {
+            return Err(ServiceError::Forbidden("You cannot change this patient's photo".to_string()).into());
+        }
+
+        // Decoding a large image takes a while, so it stays off the request threads
+        let uploads = self.config.uploads.clone();
+        let owned = content.to_vec();
+        let prepared = tokio::task::spawn_blocking(move || prepare(&owned, &uploads)).await??;
+
+        let photo = PatientPhoto {
+            full: self.store(content, prepared.content_type).await?,
+            thumbnail: self.store(&prepared.thumbnail, "image/jpeg").await?,
+            width: prepared.width,
+            height: prepared.height,
+            key_version: self.config.ipfs_encryption_key_version,
+            uploaded_by: caller_did.to_string(),
+            uploaded_at: Utc::now(),
+        };
+        let previous = self.patients.get_patient_photo(did).await?;
+        if !self.patients.set_patient_photo(did, &photo).await? {
+            self.unpin(&photo).await;
+            return Err(ServiceError::NotFound("Patient not found".to_string()).into());
+        }
+        if let Some(previous) = &previous {
+            self.unpin(previous).await;
+        }
+
+        self.audit_log_service
+            .log(
+                did,
+                "upload_patient_photo",
+                Some(json!({
+                    "actor": caller_did,
+                    "cid": photo.full.ipfs_hash,
+                    "thumbnail_cid": photo.thumbnail.ipfs_hash,
+                    "replaced_cid": previous.as_ref().map(|previous| &previous.full.ipfs_hash),
+                })),
+            )
+            .await;
+        Ok(photo)
+    }
+
+    /// The photo or its thumbnail, for anyone who may read the patient's record
+    pub async fn download(&self, caller_did: &str, did: &str, size: PhotoSize) -> Result<PhotoDownload> {
+        let Some(access) = self.patient_service.check_access(caller_did, did, "Patient").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's photo".to_string()).into());
+        };
+        self.patient_service.count_read(caller_did, did, access).await?;
+        let photo = self
+            .patients
+            .get_patient_photo(did)
+            .await?
+            .ok_or_else(|| ServiceError::NotFound("This patient has no photo".to_string()))?;
+        let stored = match size {
+            PhotoSize::Full => &photo.full,
+            PhotoSize::Thumb => &photo.thumbnail,
+        };
+        let key = self
+            .config
+            .encryption_key(photo.key_version)
+            .ok_or_else(|| anyhow!("No key is configured for version {}", photo.key_version))?;
+        let content = utils::chunked::decrypt(&self.ipfs_client.get_file(&stored.ipfs_hash).await?, key)?;
+        if hex::encode(Sha256::digest(&content)) != stored.sha256 {
+            return Err(anyhow!("Photo {} does not match its recorded digest", stored.ipfs_hash));
+        }
+
+        let mut details = read_details(caller_did, did, access);
+        details["size"] = json!(size);
+        self.audit_log_service.log(did, "view_patient_photo", Some(details)).await;
+        Ok(PhotoDownload { content_type: stored.content_type.clone(), content })
+    }
+
+    async fn store(&self, content: &[u8], content_type: &str) -> Result<StoredPhoto> {
+        let encrypted = utils::chunked::encrypt(content, &self.config.ipfs_encryption_key)?;
+        let ipfs_hash = self.ipfs_client.add_file(&encrypted, None).await?;
+        self.ipfs_client.pin_add(&ipfs_hash).await?;
+        Ok(StoredPhoto {
+            ipfs_hash,
+            content_type: content_type.to_string(),
+            size: content.len() as u64,
+            sha256: hex::encode(Sha256::digest(content)),
+        })
+    }
+
+    async fn unpin(&self, photo: &PatientPhoto) {
+        for stored in [&photo.full, &photo.thumbnail] {
+            if let Err(e) = self.ipfs_client.pin_rm(&stored.ipfs_hash).await {
+                tracing::warn!("Could not unpin patient photo {}: {:#}", stored.ipfs_hash, e);
+            }
+        }
+    }
+}
+
+/// Check that `content` is a JPEG or PNG within the configured size and dimensions, and make
+/// its thumbnail. The dimensions are read from the header before the image is decoded.
+fn prepare(content: &[u8], uploads: &UploadConfig) -> Result<PreparedPhoto> {
+    if content.len() > uploads.max_photo_bytes {
+        return Err(ServiceError::PayloadTooLarge.into());
+    }
+    let (format, content_type) = match AttachmentType::sniff(content) {
+        Some(AttachmentType::Jpeg) => (ImageFormat::Jpeg, "image/jpeg"),
+        Some(AttachmentType::Png) => (ImageFormat::Png, "image/png"),
+        _ => return Err(ServiceError::InvalidPhoto("Photos must be JPEG or PNG images".to_string()).into()),
+    };
+    let unreadable = || ServiceError::InvalidPhoto("The photo could not be read".to_string());
+    let (width, height) = image::io::Reader::with_format(Cursor::new(content), format).into_dimensions().map_err(|_| unreadable())?;
+    let (min, max) = (uploads.photo_min_dimension, uploads.photo_max_dimension);
+    if width.min(height) < min || width.max(height) > max {
+        return Err(ServiceError::InvalidPhoto(format!(
+            "Photos must be between {} and {} pixels wide and high, this one is {}x{}",
+            min, max, width, height
+        ))
+        .into());
+    }
+
+    let image = image::load_from_memory_with_format(content, format).map_err(|_| unreadable())?;
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .thumbnail(uploads.photo_thumbnail_size, uploads.photo_thumbnail_size)
+        .to_rgb8()
+        .write_to(&mut thumbnail, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))?;
+    Ok(PreparedPhoto { content_type, width, height, thumbnail: thumbnail.into_inner() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn encoded(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let mut content = Cursor::new(Vec::new());
+        RgbImage::from_pixel(width, height, Rgb([200, 120, 80])).write_to(&mut content, format).unwrap();
+        content.into_inner()
+    }
+
+    fn invalid_photo(result: Result<PreparedPhoto>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<ServiceError>(), Some(ServiceError::InvalidPhoto(_)))
+    }
+
+    #[test]
+    fn thumbnails_keep_the_aspect_ratio() {
+        let uploads = UploadConfig::default();
+        let prepared = prepare(&encoded(400, 300, ImageOutputFormat::Png), &uploads).unwrap();
+
+        assert_eq!((prepared.content_type, prepared.width, prepared.height), ("image/png", 400, 300));
+        let thumbnail = image::load_from_memory_with_format(&prepared.thumbnail, ImageFormat::Jpeg).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (160, 120));
+    }
+
+    #[test]
+    fn photos_are_checked_by_content_and_dimensions() {
+        let uploads = UploadConfig { photo_min_dimension: 200, photo_max_dimension: 500, ..Default::default() };
+
+        assert!(prepare(&encoded(300, 300, ImageOutputFormat::Jpeg(90)), &uploads).is_ok());
+        assert!(invalid_photo(prepare(&encoded(300, 150, ImageOutputFormat::Jpeg(90)), &uploads)));
+        assert!(invalid_photo(prepare(&encoded(600, 300, ImageOutputFormat::Png), &uploads)));
+        assert!(invalid_photo(prepare(b"%PDF-1.7\n%\xe2\xe3", &uploads)));
+        // The right magic bytes on something that is not an image
+        assert!(invalid_photo(prepare(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", &uploads)));
+    }
+
+    #[test]
+    fn oversized_uploads_are_refused_before_decoding() {
+        let uploads = UploadConfig { max_photo_bytes: 10, ..Default::default() };
+        let result = prepare(&encoded(300, 300, ImageOutputFormat::Png), &uploads);
+
+        assert!(matches!(result.unwrap_err().downcast_ref::<ServiceError>(), Some(ServiceError::PayloadTooLarge)));
+    }
+}
@@ -0,0 +1,317 @@
+//! Practitioners finding their own patients by name without names being stored in the clear.
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::Duration;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::Database;
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::services::admin::display_name;
+use crate::services::ServiceError;
+use crate::store::PatientSearchStore;
+use crate::utils::{name_search_token, name_words, NAME_SEARCH_MAX_CHARS, NAME_SEARCH_MIN_CHARS};
+
+/// Recurring job that indexes patients not yet indexed under the current search key
+pub const INDEX_PATIENT_SEARCH_JOB: &str = "index_patient_search";
+const INDEX_INTERVAL_MINUTES: i64 = 60;
+const INDEX_BATCH_SIZE: i64 = 100;
+/// Most words a query may have
+pub const MAX_TERMS: usize = 5;
+/// Most name matches looked at per search, before the ones the practitioner cannot see are
+/// dropped. A very common name can hide a related patient beyond this; a longer query finds them.
+const MAX_CANDIDATES: i64 = 500;
+/// Hard cap on `PATIENT_SEARCH_MAX_RESULTS`
+const MAX_RESULTS: usize = 100;
+
+// --- PatientSearchService ---
+pub struct PatientSearchService {
+    db: Arc<dyn PatientSearchStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl PatientSearchService {
+    pub fn new(db: Arc<dyn PatientSearchStore>, config: Arc<Config>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, config, audit_log_service }
+    }
+
+    /// Patients whose name has a word starting with each word of `query`, among those the
+    /// practitioner has an active grant from, an appointment with or an encounter with.
+    /// Nothing about other matches leaves this function, not even how many there were.
+    pub async fn search(&self, caller_did: &str, caller_role: Role, query: &str) -> anyhow::Result<Vec<PatientSearchResult>> {
+        if caller_role != Role::Practitioner {
+            return Err(ServiceError::Forbidden("Only practitioners can search for patients".to_string()).into());
+        }
+        let Some(key) = &self.config.patient_search.key else {
+            return Err(ServiceError::NotConfigured("Patient search is not configured on this server".to_string()).into());
+        };
+        let tokens = query_tokens(query, key.expose_secret())?;
+
+        let candidates = self.db.search_patients(&tokens, MAX_CANDIDATES).await?;
+        let candidate_dids: Vec<String> = candidates.iter().map(|patient| patient.did.clone()).collect();
+        let related = if candidate_dids.is_empty() {
+            Default::default()
+        } else {
+            self.db.related_patient_dids(caller_did, &candidate_dids).await?
+        };
+        let limit = self.config.patient_search.max_results.min(MAX_RESULTS);
+        // Only records the practitioner may see are decrypted
+        let results: Vec<PatientSearchResult> = candidates
+            .into_iter()
+            .filter(|patient| related.contains(&patient.did))
+            .filter_map(|patient| self.summarize(patient))
+            .take(limit)
+            .collect();
+
+        // The query itself is not logged: it is a name
+        self.audit_log_service
+            .log(
+                caller_did,
+                "search_patients",
+                Some(json!({ "actor": caller_did, "terms": tokens.len(), "patients": results.iter().map(|p| &p.did).collect::<Vec<_>>() })),
+            )
+            .await;
+        Ok(results)
+    }
+
+    fn summarize(&self, patient: EncryptedPatient) -> Option<PatientSearchResult> {
+        match Database::decrypt_fhir_patient(&patient, &self.config.ipfs_encryption_key) {
+            Ok(fhir_patient) => Some(PatientSearchResult {
+                did: patient.did,
+                name: display_name(&fhir_patient),
+                birth_date: fhir_patient.birth_date,
+                gender: fhir_patient.gender,
+            }),
+            Err(e) => {
+                tracing::warn!(did = %patient.did, "Failed to decrypt patient for search: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// One token per query word, made the way the index tokens for a name prefix are
+fn query_tokens(query: &str, key: &str) -> anyhow::Result<Vec<String>> {
+    let words = name_words(query);
+    if words.is_empty() || words.len() > MAX_TERMS {
+        return Err(anyhow!("Search for 1 to {} words of a name", MAX_TERMS));
+    }
+    if words.iter().any(|word| word.chars().count() < NAME_SEARCH_MIN_CHARS) {
+        return Err(anyhow!("Each search word needs at least {} characters", NAME_SEARCH_MIN_CHARS));
+    }
+    let mut tokens: Vec<String> = words
+        .iter()
+        .map(|word| name_search_token(&word.chars().take(NAME_SEARCH_MAX_CHARS).collect::<String>(), key))
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    Ok(tokens)
+}
+
+/// Indexes records written before search was turned on or under an earlier key; records
+/// written since are indexed as they are saved
+pub struct PatientSearchIndexHandler {
+    db: Arc<dyn PatientSearchStore>,
+    config: Arc<Config>,
+}
+
+impl PatientSearchIndexHandler {
+    pub fn new(db: Arc<dyn PatientSearchStore>, config: Arc<Config>) -> Self {
+        Self { db, config }
+    }
+}
+
+#[async_trait]
+impl JobHandler for PatientSearchIndexHandler {
+    fn job_type(&self) -> &'static str {
+        INDEX_PATIENT_SEARCH_JOB
+    }
+
+    fn repeat_every(&self) -> Option<Duration> {
+        Some(Duration::minutes(INDEX_INTERVAL_MINUTES))
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        let mut indexed = 0;
+        loop {
+            let batch = self.db.reindex_patient_search(&self.config.ipfs_encryption_key, INDEX_BATCH_SIZE).await?;
+            indexed += batch;
+            if batch < INDEX_BATCH_SIZE as usize {
+                break;
+            }
+        }
+        if indexed > 0 {
+            tracing::info!("Indexed {} patients for name search", indexed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::PatientSearchConfig;
+    use crate::store::{MockAuditStore, MockPatientSearchStore};
+    use crate::utils::{encrypt, name_search_tokens};
+    use chrono::Utc;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    const KEY: &str = "patient-search-unit-test-key-0123456789";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+    const RELATED: &str = "did:hedera:testnet:0.0.10";
+    const UNRELATED: &str = "did:hedera:testnet:0.0.11";
+
+    fn config() -> Config {
+        Config {
+            ipfs_encryption_key: "11".repeat(32).into(),
+            patient_search: PatientSearchConfig { key: Some(KEY.into()), max_results: 20 },
+            ..Default::default()
+        }
+    }
+
+    fn encrypted(did: &str, given: &str, family: &str) -> EncryptedPatient {
+        let fhir_patient = FhirPatient {
+            name: vec![FhirHumanName {
+                r#use: None,
+                family: Some(family.to_string()),
+                given: vec![given.to_string()],
+                prefix: Vec::new(),
+                suffix: Vec::new(),
+            }],
+            birth_date: "1990-01-01".to_string(),
+            ..Default::default()
+        };
+        EncryptedPatient {
+            id: None,
+            did: did.to_string(),
+            encrypted_fhir_patient: encrypt(serde_json::to_string(&fhir_patient).unwrap().as_bytes(), &config().ipfs_encryption_key).unwrap(),
+            email_hash: None,
+            phone_hash: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            email_verified: true,
+            verification_token: None,
+            verification_token_expires: None,
+            deleted_at: None,
+            wrapped_data_key: None,
+            erased_at: None,
+            notification_preferences: None,
+            chat_record_consent: false,
+            timezone: None,
+            totp: None,
+            backup_codes: Vec::new(),
+            disabled_at: None,
+            merged_dids: Vec::new(),
+            search_tokens: name_search_tokens([given, family], KEY),
+            search_key_id: None,
+            photo: None,
+        }
+    }
+
+    fn service(db: MockPatientSearchStore, config: Config) -> (PatientSearchService, Arc<Mutex<Vec<AuditLog>>>) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut audit_store = MockAuditStore::new();
+        let logged = logs.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.clone());
+            Ok(())
+        });
+        let audit = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        (PatientSearchService::new(Arc::new(db), Arc::new(config), audit), logs)
+    }
+
+    /// A store that matches tokens like MongoDB's `$all` and relates the practitioner to `RELATED` only
+    fn store(patients: Vec<EncryptedPatient>) -> MockPatientSearchStore {
+        let mut db = MockPatientSearchStore::new();
+        db.expect_search_patients().returning(move |tokens, _| {
+            Ok(patients.iter().filter(|patient| tokens.iter().all(|token| patient.search_tokens.contains(token))).cloned().collect())
+        });
+        db.expect_related_patient_dids().returning(|practitioner, dids| {
+            assert_eq!(practitioner, PRACTITIONER);
+            Ok(dids.iter().filter(|did| *did == RELATED).cloned().collect::<HashSet<_>>())
+        });
+        db
+    }
+
+    #[test]
+    fn query_words_match_name_prefixes_in_any_case() {
+        let tokens = name_search_tokens(["Jane Wanjiru", "Otieno"], KEY);
+        for query in ["wanj", "JANE", "otieno jane", "Otie"] {
+            let query = query_tokens(query, KEY).unwrap();
+            assert!(query.iter().all(|token| tokens.contains(token)), "{:?}", query);
+        }
+        assert!(!tokens.contains(&query_tokens("kamau", KEY).unwrap()[0]));
+    }
+
+    #[test]
+    fn tokens_depend_on_the_key() {
+        assert_ne!(query_tokens("jane", KEY).unwrap(), query_tokens("jane", &"x".repeat(32)).unwrap());
+    }
+
+    #[test]
+    fn short_or_empty_queries_are_refused() {
+        assert!(query_tokens("ja", KEY).is_err());
+        assert!(query_tokens("  ", KEY).is_err());
+        assert!(query_tokens("jane ab", KEY).is_err());
+        assert!(query_tokens("one two three four five six", KEY).is_err());
+    }
+
+    #[tokio::test]
+    async fn only_related_patients_are_returned() {
+        let patients = vec![encrypted(UNRELATED, "Jane", "Otieno"), encrypted(RELATED, "Jane", "Otieno"), encrypted("did:hedera:testnet:0.0.12", "Peter", "Kamau")];
+        let (service, logs) = service(store(patients), config());
+
+        let results = service.search(PRACTITIONER, Role::Practitioner, "jane oti").await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].did.as_str(), results[0].name.as_str()), (RELATED, "Jane Otieno"));
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].action, "search_patients");
+        let details = logs[0].details.as_ref().unwrap();
+        assert_eq!(details["patients"], json!([RELATED]));
+        assert!(!details.to_string().contains("jane"), "the query is not logged");
+    }
+
+    #[tokio::test]
+    async fn results_are_capped() {
+        let patients = (0..5).map(|_| encrypted(RELATED, "Jane", "Otieno")).collect();
+        let mut config = config();
+        config.patient_search.max_results = 2;
+        let (service, _) = service(store(patients), config);
+
+        assert_eq!(service.search(PRACTITIONER, Role::Practitioner, "jane").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_matches_skip_the_relationship_lookup() {
+        let mut db = MockPatientSearchStore::new();
+        db.expect_search_patients().returning(|_, _| Ok(Vec::new()));
+        db.expect_related_patient_dids().never();
+        let (service, _) = service(db, config());
+
+        assert!(service.search(PRACTITIONER, Role::Practitioner, "jane").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn patients_cannot_search() {
+        let (service, _) = service(MockPatientSearchStore::new(), config());
+        let error = service.search(RELATED, Role::Patient, "jane").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn search_is_off_without_a_key() {
+        let mut config = config();
+        config.patient_search.key = None;
+        let (service, _) = service(MockPatientSearchStore::new(), config);
+        let error = service.search(PRACTITIONER, Role::Practitioner, "jane").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ServiceError>(), Some(ServiceError::NotConfigured(_))));
+    }
+}
@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::config::TwilioConfig;
+use crate::database::Database;
+use crate::models::Otp;
+use crate::services::twilio::SmsSender;
+use crate::utils::SecretString;
+
+const TWILIO_VERIFY_BASE_URL: &str = "https://verify.twilio.com";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeCheck {
+    Approved,
+    Rejected,
+    Expired,
+}
+
+/// Sends one-time codes to a phone number and checks them.
+///
+/// [`TwilioVerify`] delegates both steps to Twilio; [`LocalOtp`] stores codes in
+/// the `otps` collection and delivers them over plain SMS.
+#[async_trait]
+pub trait PhoneVerifier: Send + Sync {
+    async fn start(&self, phone_number: &str) -> Result<()>;
+    async fn check(&self, phone_number: &str, code: &str) -> Result<CodeCheck>;
+}
+
+#[derive(Deserialize)]
+struct VerificationResponse {
+    status: String,
+}
+
+pub struct TwilioVerify {
+    http: Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: SecretString,
+    service_sid: String,
+}
+
+impl TwilioVerify {
+    pub fn new(config: &TwilioConfig, service_sid: &str, http: Client) -> Self {
+        Self {
+            http,
+            base_url: TWILIO_VERIFY_BASE_URL.to_string(),
+            account_sid: config.account_sid.clone(),
+            auth_token: config.auth_token.clone(),
+            service_sid: service_sid.to_string(),
+        }
+    }
+
+    /// Point the client at a different API host (used by tests)
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    async fn post(&self, resource: &str, form: &[(&str, &str)]) -> Result<reqwest::Response> {
+        let url = format!("{}/v2/Services/{}/{}", self.base_url, self.service_sid, resource);
+        Ok(self
+            .http
+            .post(url)
+            .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+            .form(form)
+            .send()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl PhoneVerifier for TwilioVerify {
+    async fn start(&self, phone_number: &str) -> Result<()> {
+        let response = self.post("Verifications", &[("To", phone_number), ("Channel", "sms")]).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Twilio Verify request failed: {}", response.status()));
+        }
+        Ok(())
+    }
+
+    async fn check(&self, phone_number: &str, code: &str) -> Result<CodeCheck> {
+        let response = self.post("VerificationCheck", &[("To", phone_number), ("Code", code)]).await?;
+        // Twilio answers 404 once a verification has expired, been approved, or run out of attempts
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(CodeCheck::Expired);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!("Twilio Verify check failed: {}", response.status()));
+        }
+        let verification: VerificationResponse = response.json().await?;
+        Ok(match verification.status.as_str() {
+            "approved" => CodeCheck::Approved,
+            "expired" | "canceled" => CodeCheck::Expired,
+            _ => CodeCheck::Rejected,
+        })
+    }
+}
+
+/// Codes generated here, kept in MongoDB until used or expired, and sent as SMS
+pub struct LocalOtp {
+    db: Arc<Database>,
+    sms_sender: Arc<SmsSender>,
+}
+
+impl LocalOtp {
+    pub fn new(db: Arc<Database>, sms_sender: Arc<SmsSender>) -> Self {
+        Self { db, sms_sender }
+    }
+}
+
+#[async_trait]
+impl PhoneVerifier for LocalOtp {
+    async fn start(&self, phone_number: &str) -> Result<()> {
+        let otp = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        let otp_record = Otp {
+            id: None,
+            phone_number: phone_number.to_string(),
+            otp: otp.clone(),
+            created_at: Utc::now(),
+            expires_at: Utc::now() + Duration::minutes(5),
+        };
+        self.db.create_otp(&otp_record).await?;
+        self.sms_sender.send_otp(phone_number, &otp).await?;
+        Ok(())
+    }
+
+    async fn check(&self, phone_number: &str, code: &str) -> Result<CodeCheck> {
+        let Some(otp_record) = self.db.get_otp(phone_number, code).await? else {
+            return Ok(CodeCheck::Rejected);
+        };
+        if otp_record.expires_at < Utc::now() {
+            return Ok(CodeCheck::Expired);
+        }
+        self.db.delete_otps(phone_number).await?;
+        Ok(CodeCheck::Approved)
+    }
+}
+
+/// Locks a phone number out of verification after repeated wrong codes.
+/// Applied by the auth service in front of whichever [`PhoneVerifier`] is active.
+pub struct PhoneLockout {
+    max_failures: u32,
+    window: Duration,
+    failures: Mutex<HashMap<String, (u32, DateTime<Utc>)>>,
+}
+
+impl Default for PhoneLockout {
+    fn default() -> Self {
+        Self::new(5, Duration::minutes(15))
+    }
+}
+
+impl PhoneLockout {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        Self { max_failures, window, failures: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn is_locked(&self, phone_number: &str) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get(phone_number) {
+            Some((_, first_failure)) if *first_failure + self.window < Utc::now() => {
+                failures.remove(phone_number);
+                false
+            }
+            Some((count, _)) => *count >= self.max_failures,
+            None => false,
+        }
+    }
+
+    /// Record a wrong code; returns true when this failure triggers the lockout
+    pub fn record_failure(&self, phone_number: &str) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Utc::now();
+        let entry = failures.entry(phone_number.to_string()).or_insert((0, now));
+        if entry.1 + self.window < now {
+            *entry = (0, now);
+        }
+        entry.0 += 1;
+        entry.0 == self.max_failures
+    }
+
+    pub fn clear(&self, phone_number: &str) {
+        self.failures.lock().unwrap().remove(phone_number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn verify() -> (MockServer, TwilioVerify) {
+        let server = MockServer::start().await;
+        let config = TwilioConfig {
+            account_sid: "AC123".to_string(),
+            auth_token: "token".into(),
+            ..Default::default()
+        };
+        let verify = TwilioVerify::new(&config, "VA123", Client::new()).with_base_url(&server.uri());
+        (server, verify)
+    }
+
+    #[tokio::test]
+    async fn start_requests_an_sms_verification() {
+        let (server, verify) = verify().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/Services/VA123/Verifications"))
+            .and(body_string_contains("Channel=sms"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "status": "pending" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        verify.start("+15555550100").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_maps_verification_status() {
+        let (server, verify) = verify().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/Services/VA123/VerificationCheck"))
+            .and(body_string_contains("Code=123456"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "approved", "valid": true })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/Services/VA123/VerificationCheck"))
+            .and(body_string_contains("Code=000000"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "pending", "valid": false })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/Services/VA123/VerificationCheck"))
+            .and(body_string_contains("Code=999999"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        assert_eq!(verify.check("+15555550100", "123456").await.unwrap(), CodeCheck::Approved);
+        assert_eq!(verify.check("+15555550100", "000000").await.unwrap(), CodeCheck::Rejected);
+        assert_eq!(verify.check("+15555550100", "999999").await.unwrap(), CodeCheck::Expired);
+    }
+
+    #[test]
+    fn lockout_engages_after_max_failures_and_clears() {
+        let lockout = PhoneLockout::new(3, Duration::minutes(15));
+
+        assert!(!lockout.record_failure("+15555550100"));
+        assert!(!lockout.record_failure("+15555550100"));
+        assert!(!lockout.is_locked("+15555550100"));
+        assert!(lockout.record_failure("+15555550100"));
+        assert!(lockout.is_locked("+15555550100"));
+        assert!(!lockout.is_locked("+15555550199"));
+
+        lockout.clear("+15555550100");
+        assert!(!lockout.is_locked("+15555550100"));
+    }
+
+    #[test]
+    fn lockout_expires_with_the_window() {
+        let lockout = PhoneLockout::new(1, Duration::zero());
+
+        lockout.record_failure("+15555550100");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        assert!(!lockout.is_locked("+15555550100"));
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::{OrganizationService, ServiceError};
+use crate::store::PractitionerStore;
+
+// --- PractitionerService ---
+pub struct PractitionerService {
+    db: Arc<dyn PractitionerStore>,
+    organization_service: Arc<OrganizationService>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PractitionerRegistration {
+    pub practitioner: Practitioner,
+    /// The pending affiliation with the organization named at registration
+    pub affiliation: Option<Affiliation>,
+}
+
+impl PractitionerService {
+    pub fn new(db: Arc<dyn PractitionerStore>, organization_service: Arc<OrganizationService>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, organization_service, audit_log_service }
+    }
+
+    /// Register the caller as a practitioner. The license starts out unverified whatever the request says,
+    /// and its expiry date has to be a `YYYY-MM-DD` date. Naming an organization creates a pending
+    /// affiliation for its admins to approve, and puts the practitioner in the organization's tenant.
+    pub async fn register(&self, caller_did: &str, request: CreatePractitionerRequest) -> anyhow::Result<PractitionerRegistration> {
+        let expiry = license_expiry(&request.license_verification)?;
+        // Fail before anything is written if the organization does not exist
+        let tenant_id = match &request.organization_identifier {
+            Some(identifier) => self.organization_service.find_active_organization(identifier).await?.tenant_id,
+            None => None,
+        };
+
+        let mut license_verification = request.license_verification;
+        license_verification.expiry_date = expiry.to_string();
+        license_verification.verified = false;
+        license_verification.expiry_warning_sent_at = None;
+        let practitioner = Practitioner {
+            id: None,
+            did: caller_did.to_string(),
+            fhir_practitioner: FhirPractitioner { resource_type: "Practitioner".to_string(), ..request.fhir_practitioner },
+            license_verification,
+            practitioner_type: request.practitioner_type,
+            tenant_id,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        if let Err(e) = self.db.create_practitioner(&practitioner).await {
+            if let Some(DatabaseError::DuplicateKey(_)) = e.downcast_ref::<DatabaseError>() {
+                return Err(ServiceError::Conflict("You are already registered as a practitioner".to_string()).into());
+            }
+            return Err(e);
+        }
+        self.audit_log_service.log(caller_did, "register_practitioner", None).await;
+
+        let affiliation = match request.organization_identifier {
+            Some(organization_identifier) => Some(
+                self.organization_service
+                    .request_affiliation(caller_did, RequestAffiliationRequest { organization_identifier, role: AffiliationRole::Practitioner })
+                    .await?,
+            ),
+            None => None,
+        };
+        Ok(PractitionerRegistration { practitioner, affiliation })
+    }
+
+    /// Record that the practitioner's license was checked against its issuing authority. A license
+    /// whose expiry date is unreadable or already past on `today` is refused.
+    pub async fn verify_license(&self, did: &str, today: NaiveDate) -> anyhow::Result<()> {
+        let practitioner = self
+            .db
+            .get_practitioner_by_did(did)
+            .await?
+            .ok_or_else(|| ServiceError::NotFound(format!("{} is not a registered practitioner", did)))?;
+        if license_expiry(&practitioner.license_verification)? < today {
+            return Err(ServiceError::InvalidLicense("The license has already expired".to_string()).into());
+        }
+        self.db.verify_practitioner_license(did).await?;
+        self.audit_log_service.log(did, "verify_practitioner_license", None).await;
+        Ok(())
+    }
+}
+
+fn license_expiry(license: &LicenseVerification) -> anyhow::Result<NaiveDate> {
+    license.expiry().ok_or_else(|| {
+        ServiceError::InvalidLicense(format!("The license expiry date '{}' is not a YYYY-MM-DD date", license.expiry_date.trim())).into()
+    })
+}
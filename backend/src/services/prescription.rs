@@ -0,0 +1,808 @@
+use anyhow::{anyhow, Result};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::interactions::InteractionChecker;
+use crate::services::terminology;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::services::patient::read_details;
+use crate::services::vc::{prescription_hash, CredentialCheck};
+use crate::services::{PatientService, ServiceError, VerifiableCredentialService};
+use crate::store::{PractitionerStore, PrescriptionStore};
+
+const VERIFY_LIMITED_MESSAGE: &str = "Too many prescription verifications. Please wait a minute and try again.";
+
+// --- PrescriptionService ---
+pub struct PrescriptionService {
+    db: Arc<dyn PrescriptionStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    patient_service: Arc<PatientService>,
+    vc_service: Arc<VerifiableCredentialService>,
+    notification_service: Arc<NotificationService>,
+    audit_log_service: Arc<AuditLogService>,
+    interaction_checker: InteractionChecker,
+    verify_limiter: RequestLimiter,
+    config: Arc<Config>,
+}
+
+impl PrescriptionService {
+    pub fn new(
+        db: Arc<dyn PrescriptionStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        patient_service: Arc<PatientService>,
+        vc_service: Arc<VerifiableCredentialService>,
+        notification_service: Arc<NotificationService>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        let verify_limiter = RequestLimiter::new(Duration::minutes(1));
+        let interaction_checker = InteractionChecker::from_config(&config);
+        Self { db, practitioners, patient_service, vc_service, notification_service, audit_log_service, interaction_checker, verify_limiter, config }
+    }
+
+    /// Write a prescription for a patient who granted the caller `Prescribe`, as a practitioner whose
+    /// license has not expired; it always starts out active. Refused attempts are audit-logged.
+    /// With `issue_credential` it comes with a credential pharmacies can verify.
+    ///
+    /// The medication is either the request's concept or, with `rxnorm_code`, the embedded RxNorm
+    /// entry for that code. It is checked against the patient's active prescriptions first; a major
+    /// interaction is only overridden with `force` and a reason, which is audited.
+    pub async fn create(&self, caller_did: &str, caller_role: Role, mut request: CreatePrescriptionRequest) -> Result<PrescriptionCreated> {
+        // The prescriber is always the caller; a body naming someone else is refused rather than rewritten
+        let requester = request.medication_request.requester.reference.as_str();
+        let denial = if !requester.is_empty() && requester != format!("Practitioner/{}", caller_did) {
+            Some("You can only prescribe as yourself")
+        } else if caller_role != Role::Practitioner || !self.patient_service.has_permission(caller_did, &request.patient_did, Permission::Prescribe).await? {
+            Some("You are not allowed to prescribe for this patient")
+        } else {
+            self.license_problem(caller_did).await?
+        };
+        if let Some(reason) = denial {
+            self.audit_log_service.log(&request.patient_did, "create_prescription_denied", Some(json!({ "actor": caller_did, "reason": reason }))).await;
+            return Err(ServiceError::Forbidden(reason.to_string()).into());
+        }
+        if let Some(code) = &request.rxnorm_code {
+            let medication = terminology::medication(code).ok_or_else(|| anyhow!("Unknown RxNorm code '{}'", code.trim()))?;
+            request.medication_request.medication_codeable_concept = medication.codeable_concept();
+        }
+        let concept = &request.medication_request.medication_codeable_concept;
+        if concept.coding.is_empty() && concept.text.as_deref().map_or(true, |text| text.trim().is_empty()) {
+            return Err(anyhow!("Choose a medication, or send its rxnorm_code"));
+        }
+        let active = self.db.list_prescriptions(&request.patient_did, Some(PrescriptionStatus::Active)).await?;
+        let report = self.interaction_checker.check(&request.medication_request, &active).await;
+        let majors: Vec<String> = report.major().map(|warning| format!("{} ({})", warning.interacting_drug, warning.description)).collect();
+        let override_reason = if majors.is_empty() {
+            None
+        } else if !request.force {
+            return Err(ServiceError::Conflict(format!(
+                "Major interaction with {}. Send force with an override_reason to prescribe anyway",
+                majors.join("; ")
+            ))
+            .into());
+        } else {
+            let reason = request.override_reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+            Some(reason.ok_or_else(|| anyhow!("A reason is required to override a major interaction"))?.to_string())
+        };
+        let now = Utc::now();
+        let mut medication_request = request.medication_request;
+        medication_request.resource_type = "MedicationRequest".to_string();
+        medication_request.id = Uuid::new_v4().to_string();
+        medication_request.status = PrescriptionStatus::Active.fhir_code().to_string();
+        medication_request.status_reason = None;
+        medication_request.subject = FhirReference { reference: format!("Patient/{}", request.patient_did), display: None };
+        medication_request.requester = FhirReference { reference: format!("Practitioner/{}", caller_did), display: None };
+        medication_request.authored_on = now.into();
+        let id = ObjectId::new();
+        let mut prescription = Prescription {
+            id: Some(id),
+            patient_did: request.patient_did,
+            practitioner_did: caller_did.to_string(),
+            fhir_medication_request: medication_request,
+            status: PrescriptionStatus::Active,
+            dispenses: Vec::new(),
+            fully_dispensed: false,
+            credential: None,
+            created_at: now,
+            updated_at: now,
+        };
+        // Issued first so a failure leaves no prescription behind that was promised a credential
+        if request.issue_credential {
+            prescription.credential = Some(self.vc_service.issue_prescription_credential(&prescription).await?);
+        }
+        self.db.create_prescription(&prescription).await?;
+        self.audit_log_service.log(&prescription.patient_did, "create_prescription", Some(json!({ "actor": caller_did, "prescription_id": id }))).await;
+        if let Some(reason) = override_reason {
+            self.audit_log_service
+                .log(
+                    &prescription.patient_did,
+                    "override_interaction_warning",
+                    Some(json!({ "actor": caller_did, "prescription_id": id, "reason": reason, "interactions": majors })),
+                )
+                .await;
+        }
+        Ok(PrescriptionCreated { prescription, warnings: report.warnings, interactions_checked: report.complete })
+    }
+
+    /// Why the caller's license does not let them prescribe, if it does not. An expired license
+    /// never does; with `require_verified_practitioners`, neither does an unverified one, which
+    /// includes one the license expiry job revoked.
+    async fn license_problem(&self, caller_did: &str) -> Result<Option<&'static str>> {
+        let require_verified = self.config.dynamic.load().require_verified_practitioners;
+        let Some(practitioner) = self.practitioners.get_practitioner_by_did(caller_did).await? else {
+            return Ok(require_verified.then_some("You are not a registered practitioner"));
+        };
+        let license = &practitioner.license_verification;
+        if license.expiry().is_some_and(|expiry| expiry < Utc::now().date_naive()) {
+            return Ok(Some("Your license has expired"));
+        }
+        Ok((require_verified && !license.verified).then_some("Your license is not verified"))
+    }
+
+    /// The patient's prescriptions, newest first, for anyone who may see their medication
+    pub async fn list(&self, caller_did: &str, patient_did: &str, status: Option<PrescriptionStatus>) -> Result<Vec<Prescription>> {
+        let Some(access) = self.patient_service.check_access(caller_did, patient_did, "MedicationRequest").await? else {
+            return Err(ServiceError::Forbidden("You do not have access to this patient's prescriptions".to_string()).into());
+        };
+        self.patient_service.count_read(caller_did, patient_did, access).await?;
+        self.audit_log_service.log(patient_did, "list_prescriptions", Some(read_details(caller_did, patient_did, access))).await;
+        self.db.list_prescriptions(patient_did, status).await
+    }
+
+    /// Move a prescription on from active; only its prescriber may, and stopping or cancelling needs a reason.
+    /// Its credential is revoked, as it can no longer be filled.
+    pub async fn update_status(&self, caller_did: &str, id: &str, request: UpdatePrescriptionStatusRequest) -> Result<Prescription> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid prescription id"))?;
+        let prescription = self.db.get_prescription(id).await?.ok_or_else(|| anyhow!("Prescription not found"))?;
+        if prescription.practitioner_did != caller_did {
+            return Err(ServiceError::Forbidden("Only the prescriber can change this prescription's status".to_string()).into());
+        }
+        let (from, to) = (prescription.status, request.status);
+        if !from.can_become(to) {
+            return Err(ServiceError::Conflict(format!("A {} prescription cannot become {}", from.fhir_code(), to.fhir_code())).into());
+        }
+        if matches!(to, PrescriptionStatus::Stopped | PrescriptionStatus::Cancelled) && request.reason.is_none() {
+            return Err(anyhow!("A reason is required to mark a prescription {}", to.fhir_code()));
+        }
+        let updated = self
+            .db
+            .update_prescription_status(id, from, to, request.reason)
+            .await?
+            .ok_or_else(|| ServiceError::Conflict(format!("Prescription is no longer {}", from.fhir_code())))?;
+
+        self.audit_log_service
+            .log(
+                &updated.patient_did,
+                "update_prescription_status",
+                Some(json!({ "actor": caller_did, "prescription_id": id, "from": from, "to": to })),
+            )
+            .await;
+        if let Some(credential) = &updated.credential {
+            // Verification also checks the status, so a failure here does not leave it fillable
+            if let Err(e) = self.vc_service.revoke_credential(caller_did, &credential.hash).await {
+                tracing::error!("Failed to revoke the credential of prescription {}: {}", id, e);
+            }
+        }
+        if to == PrescriptionStatus::Cancelled {
+            self.notification_service.notify(NotificationEvent::PrescriptionCancelled {
+                patient_did: updated.patient_did.clone(),
+                medication: medication_name(&updated),
+            });
+        }
+        Ok(updated)
+    }
+
+    /// Record a pharmacist handing out medication against an active prescription
+    pub async fn dispense(&self, caller_did: &str, caller_role: Role, id: &str, request: DispenseRequest) -> Result<Prescription> {
+        self.ensure_pharmacist(caller_did, caller_role).await?;
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid prescription id"))?;
+        let prescription = self.db.get_prescription(id).await?.ok_or_else(|| anyhow!("Prescription not found"))?;
+        if !prescription.status.can_dispense() {
+            return Err(ServiceError::Conflict("Only active prescriptions can be dispensed".to_string()).into());
+        }
+        self.record_dispense(caller_did, prescription, request).await
+    }
+
+    /// Check a prescription credential shown to a pharmacy. Anyone may ask, at most
+    /// `prescription_verify_per_minute` times a minute from one `client` address.
+    pub async fn verify(&self, client: &str, reference: PrescriptionReference) -> Result<PrescriptionVerdict> {
+        if !self.verify_limiter.allow(client, self.config.dynamic.load().prescription_verify_per_minute) {
+            return Err(ServiceError::RateLimited(VERIFY_LIMITED_MESSAGE.to_string()).into());
+        }
+        let (verdict, _) = self.assess(&reference).await?;
+        Ok(verdict)
+    }
+
+    /// Verify a credential and dispense against the prescription behind it in one step, for
+    /// pharmacists who were never given the prescription itself
+    pub async fn dispense_verified(&self, caller_did: &str, caller_role: Role, request: VerifiedDispenseRequest) -> Result<Prescription> {
+        self.ensure_pharmacist(caller_did, caller_role).await?;
+        match self.assess(&request.reference).await? {
+            (PrescriptionVerdict { valid: true, .. }, Some(prescription)) => self.record_dispense(caller_did, prescription, request.dispense).await,
+            (verdict, _) => Err(ServiceError::Conflict(verdict.reason.unwrap_or_else(|| "The prescription cannot be dispensed".to_string())).into()),
+        }
+    }
+
+    async fn ensure_pharmacist(&self, caller_did: &str, caller_role: Role) -> Result<()> {
+        let is_pharmacist = caller_role == Role::Practitioner
+            && self.practitioners.get_practitioner_by_did(caller_did).await?.is_some_and(|practitioner| {
+                practitioner.practitioner_type == PractitionerType::Pharmacist && practitioner.license_verification.verified
+            });
+        if !is_pharmacist {
+            return Err(ServiceError::Forbidden("Only verified pharmacists can dispense prescriptions".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Add a dispense, marking the prescription fully dispensed once all of the prescribed
+    /// quantity (or, without one, anything) was handed out
+    async fn record_dispense(&self, caller_did: &str, prescription: Prescription, request: DispenseRequest) -> Result<Prescription> {
+        let Some(quantity) = request.quantity.value.filter(|value| *value > 0.0) else {
+            return Err(anyhow!("A dispense needs a positive quantity and days supply"));
+        };
+        if request.days_supply == 0 {
+            return Err(anyhow!("A dispense needs a positive quantity and days supply"));
+        }
+        if prescription.fully_dispensed {
+            return Err(ServiceError::Conflict("The prescription was already dispensed in full".to_string()).into());
+        }
+        let fills = match prescription.prescribed_quantity() {
+            Some(prescribed) => {
+                let remaining = prescribed - prescription.dispensed_quantity();
+                if quantity > remaining {
+                    return Err(anyhow!("Only {} of the prescribed quantity is left to dispense", remaining));
+                }
+                quantity >= remaining
+            }
+            None => true,
+        };
+        let id = prescription.id.expect("stored prescriptions have an id");
+        let dispense = Dispense { quantity: request.quantity, days_supply: request.days_supply, performer_did: caller_did.to_string(), dispensed_at: Utc::now() };
+        let updated = self
+            .db
+            .add_dispense(id, &dispense, prescription.dispenses.len(), fills)
+            .await?
+            .ok_or_else(|| ServiceError::Conflict("The prescription changed while it was being dispensed; verify it again".to_string()))?;
+
+        self.audit_log_service
+            .log(
+                &updated.patient_did,
+                "dispense_prescription",
+                Some(json!({ "actor": caller_did, "prescription_id": id, "days_supply": dispense.days_supply, "fully_dispensed": fills })),
+            )
+            .await;
+        Ok(updated)
+    }
+
+    /// The verdict on a presented credential, with the prescription behind it when there is one
+    async fn assess(&self, reference: &PrescriptionReference) -> Result<(PrescriptionVerdict, Option<Prescription>)> {
+        let (credential_hash, presented_hash) = match (&reference.credential_hash, &reference.qr_payload) {
+            (Some(credential_hash), _) => (credential_hash.as_str(), None),
+            (None, Some(qr_payload)) => {
+                let parts = qr_payload.strip_prefix("rx:").and_then(|rest| rest.split_once(':'));
+                let (credential_hash, prescription_hash) = parts.ok_or_else(|| anyhow!("Unrecognised prescription QR code"))?;
+                (credential_hash, Some(prescription_hash))
+            }
+            (None, None) => return Err(anyhow!("Provide a credential_hash or a qr_payload")),
+        };
+        let credential = match self.vc_service.check_credential(credential_hash, PRESCRIPTION_CREDENTIAL_TYPE).await? {
+            CredentialCheck::Valid(credential) => credential,
+            check => return Ok((rejected(check.problem().unwrap_or("Invalid credential")), None)),
+        };
+        let metadata: PrescriptionCredentialMetadata = serde_json::from_str(&credential.metadata)?;
+        if presented_hash.is_some_and(|presented| presented != metadata.prescription_hash) {
+            return Ok((rejected("The QR code does not match the credential"), None));
+        }
+        let prescription = match self.db.get_prescription_by_credential(credential_hash).await? {
+            Some(prescription) if prescription.id.is_some_and(|id| prescription_hash(&id.to_hex()) == metadata.prescription_hash) => prescription,
+            _ => return Ok((rejected("The credential does not match a prescription"), None)),
+        };
+
+        let problem = match prescription.status {
+            PrescriptionStatus::Cancelled => Some("The prescription was cancelled".to_string()),
+            status if !status.can_dispense() => Some(format!("The prescription is {}", status.fhir_code())),
+            _ if prescription.fully_dispensed => Some("The prescription was already dispensed".to_string()),
+            _ => None,
+        };
+        let prescriber = self.practitioners.get_practitioner_by_did(&metadata.prescriber_did).await?;
+        let verdict = PrescriptionVerdict {
+            valid: problem.is_none(),
+            reason: problem,
+            medication: metadata.medication,
+            quantity: metadata.quantity,
+            prescriber_license_verified: Some(prescriber.is_some_and(|prescriber| prescriber.license_verification.verified)),
+            issuer_registered: Some(self.vc_service.issuer_registered(&credential.issuer).await?),
+        };
+        self.audit_log_service
+            .log(&prescription.patient_did, "verify_prescription", Some(json!({ "prescription_id": prescription.id, "valid": verdict.valid })))
+            .await;
+        Ok((verdict, Some(prescription)))
+    }
+}
+
+fn rejected(reason: &str) -> PrescriptionVerdict {
+    PrescriptionVerdict {
+        valid: false,
+        reason: Some(reason.to_string()),
+        medication: None,
+        quantity: None,
+        prescriber_license_verified: None,
+        issuer_registered: None,
+    }
+}
+
+/// Allows each key `limit` requests per `window`, counted from its first request in the window
+/// Counts requests per key over a fixed window. The limit is passed on every call, so a
+/// reloaded setting applies straight away.
+struct RequestLimiter {
+    window: Duration,
+    requests: Mutex<HashMap<String, (u32, DateTime<Utc>)>>,
+}
+
+impl RequestLimiter {
+    fn new(window: Duration) -> Self {
+        Self { window, requests: Mutex::new(HashMap::new()) }
+    }
+
+    /// Count a request from `key`; false once it is over `limit`
+    fn allow(&self, key: &str, limit: u32) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let now = Utc::now();
+        // Forget finished windows so one-off clients do not pile up
+        requests.retain(|_, (_, started)| *started + self.window > now);
+        let entry = requests.entry(key.to_string()).or_insert((0, now));
+        entry.0 += 1;
+        entry.0 <= limit
+    }
+}
+
+fn medication_name(prescription: &Prescription) -> String {
+    let medication = &prescription.fhir_medication_request.medication_codeable_concept;
+    medication
+        .text
+        .clone()
+        .or_else(|| medication.coding.iter().find_map(|coding| coding.display.clone()))
+        .unwrap_or_else(|| "your medication".to_string())
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::events::EventBus;
+    use crate::config::{Config, CredentialSigningConfig, DynamicConfig};
+    use crate::services::fakes::{InMemoryObjectStorage, RecordingLedgerAnchor};
+    use crate::services::fhir::FhirManager;
+    use crate::services::notification::MockNotificationSender;
+    use crate::services::{ConsentService, IssuerRegistryService, RelationshipService, StatusListService};
+    use crate::store::{
+        MockAuditStore, MockConsentStore, MockCredentialStore, MockEncounterStore, MockNotificationStore, MockPatientStore, MockPractitionerStore,
+        MockPrescriptionStore, MockIssuerStore, MockRelationshipStore, MockStatusListStore, PatientStore,
+    };
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRESCRIBER: &str = "did:hedera:testnet:0.0.2";
+    const PHARMACIST: &str = "did:hedera:testnet:0.0.3";
+
+    fn service(
+        prescriptions: MockPrescriptionStore,
+        mut patients: MockPatientStore,
+        practitioners: MockPractitionerStore,
+        credentials: MockCredentialStore,
+        audit_store: MockAuditStore,
+    ) -> PrescriptionService {
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let patients: Arc<dyn PatientStore> = Arc::new(patients);
+        let practitioners: Arc<dyn PractitionerStore> = Arc::new(practitioners);
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_get_relationship().returning(|_, _| Ok(None));
+        let config = Arc::new(Config {
+            dynamic: DynamicConfig { prescription_verify_per_minute: 10, ..Default::default() }.into(),
+            ipfs_encryption_key: "00".repeat(32).into(),
+            credential_signing: Some(CredentialSigningConfig { issuer_did: "did:hedera:testnet:0.0.5".to_string(), key: "07".repeat(32).into(), key_id: "key-1".to_string() }),
+            ..Default::default()
+        });
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(MockNotificationStore::new()),
+            patients.clone(),
+            practitioners.clone(),
+            Arc::new(MockNotificationSender::new()),
+            config.clone(),
+        ));
+        let consent_service = Arc::new(ConsentService::new(
+            Arc::new(MockConsentStore::new()),
+            patients.clone(),
+            Arc::new(InMemoryObjectStorage::new()),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let relationship_service = Arc::new(RelationshipService::new(
+            Arc::new(relationships),
+            patients.clone(),
+            practitioners.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let events = Arc::new(EventBus::new(16));
+        let patient_service = Arc::new(PatientService::new(
+            patients,
+            Arc::new(MockEncounterStore::new()),
+            config.clone(),
+            audit_log_service.clone(),
+            notification_service.clone(),
+            consent_service,
+            relationship_service,
+            events.clone(),
+        ));
+        // Issued credentials get a bit in a list that is never published here
+        let mut lists = MockStatusListStore::new();
+        lists.expect_allocate_status_index().returning(|_| Ok(StatusListEntry { list_id: "1".to_string(), index: 0 }));
+        lists.expect_get_status_list().returning(|_| Ok(None));
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let status_lists = Arc::new(StatusListService::new(Arc::new(lists), Arc::new(MockCredentialStore::new()), ipfs.clone(), config.clone()));
+        let vc_service = Arc::new(VerifiableCredentialService::new(
+            Arc::new(credentials),
+            ipfs,
+            Arc::new(RecordingLedgerAnchor::new()),
+            config.clone(),
+            audit_log_service.clone(),
+            events,
+            status_lists,
+            // Prescription credentials are issued by the platform itself, which needs no registration
+            Arc::new(IssuerRegistryService::new(Arc::new(MockIssuerStore::new()), config.clone(), audit_log_service.clone())),
+            Arc::new(MockPractitionerStore::new()),
+        ));
+        PrescriptionService::new(Arc::new(prescriptions), practitioners, patient_service, vc_service, notification_service, config, audit_log_service)
+    }
+
+    fn prescription(id: ObjectId, status: PrescriptionStatus) -> Prescription {
+        Prescription {
+            id: Some(id),
+            patient_did: PATIENT.to_string(),
+            practitioner_did: PRESCRIBER.to_string(),
+            fhir_medication_request: FhirManager::create_medication_request(
+                PATIENT,
+                PRESCRIBER,
+                None,
+                FhirCodeableConcept { coding: vec![], text: Some("Amoxicillin 500mg".to_string()) },
+                vec![],
+            ),
+            status,
+            dispenses: vec![],
+            fully_dispensed: false,
+            credential: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// A store holding one prescription that moves to whatever status it is asked to
+    fn stored(id: ObjectId, status: PrescriptionStatus) -> MockPrescriptionStore {
+        let mut prescriptions = MockPrescriptionStore::new();
+        prescriptions.expect_get_prescription().returning(move |_| Ok(Some(prescription(id, status))));
+        prescriptions
+            .expect_update_prescription_status()
+            .returning(move |_, from, to, _| Ok((from == status).then(|| prescription(id, to))));
+        prescriptions
+    }
+
+    fn no_practitioners() -> MockPractitionerStore {
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        practitioners
+    }
+
+    fn practitioner(did: &str, verified: bool) -> Practitioner {
+        Practitioner {
+            id: None,
+            did: did.to_string(),
+            fhir_practitioner: FhirPractitioner {
+                resource_type: "Practitioner".to_string(),
+                id: did.to_string(),
+                identifier: vec![],
+                name: vec![],
+                qualification: vec![],
+                telecom: vec![],
+            },
+            license_verification: LicenseVerification {
+                license_number: "PPB-1".to_string(),
+                issuing_authority: "PPB".to_string(),
+                issue_date: "2020-01-01".to_string(),
+                expiry_date: "2030-01-01".to_string(),
+                hedera_transaction_id: String::new(),
+                ipfs_hash: String::new(),
+                verified,
+                expiry_warning_sent_at: None,
+            },
+            practitioner_type: PractitionerType::Clinician,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    fn cancel() -> UpdatePrescriptionStatusRequest {
+        UpdatePrescriptionStatusRequest {
+            status: PrescriptionStatus::Cancelled,
+            reason: Some(FhirCodeableConcept { coding: vec![], text: Some("Prescribed in error".to_string()) }),
+        }
+    }
+
+    fn dispense_request() -> DispenseRequest {
+        DispenseRequest { quantity: FhirQuantity { value: Some(21.0), unit: Some("capsule".to_string()), system: None, code: None }, days_supply: 7 }
+    }
+
+    #[test]
+    fn only_active_prescriptions_move_on_or_are_dispensed() {
+        use PrescriptionStatus::*;
+        for to in [Completed, Stopped, Cancelled] {
+            assert!(Active.can_become(to));
+            assert!(!to.can_become(Active));
+            assert!(!to.can_dispense());
+        }
+        assert!(!Active.can_become(Active));
+        assert!(!Stopped.can_become(Cancelled));
+        assert!(Active.can_dispense());
+    }
+
+    #[tokio::test]
+    async fn cancelling_is_audited_with_both_statuses() {
+        let id = ObjectId::new();
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.action == "update_prescription_status" && log.details.as_ref().is_some_and(|details| details["from"] == "active" && details["to"] == "cancelled"))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let cancelled = service(stored(id, PrescriptionStatus::Active), MockPatientStore::new(), no_practitioners(), MockCredentialStore::new(), audit_store)
+            .update_status(PRESCRIBER, &id.to_hex(), cancel())
+            .await
+            .unwrap();
+
+        assert_eq!(cancelled.status, PrescriptionStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn finished_prescriptions_cannot_change_status() {
+        let id = ObjectId::new();
+        let service = service(stored(id, PrescriptionStatus::Completed), MockPatientStore::new(), no_practitioners(), MockCredentialStore::new(), MockAuditStore::new());
+
+        let err = service.update_status(PRESCRIBER, &id.to_hex(), cancel()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+
+        let err = service.update_status(PHARMACIST, &id.to_hex(), cancel()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn stopping_needs_a_reason() {
+        let id = ObjectId::new();
+        let mut prescriptions = MockPrescriptionStore::new();
+        prescriptions.expect_get_prescription().returning(move |_| Ok(Some(prescription(id, PrescriptionStatus::Active))));
+        prescriptions.expect_update_prescription_status().never();
+
+        let err = service(prescriptions, MockPatientStore::new(), no_practitioners(), MockCredentialStore::new(), MockAuditStore::new())
+            .update_status(PRESCRIBER, &id.to_hex(), UpdatePrescriptionStatusRequest { status: PrescriptionStatus::Stopped, reason: None })
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "A reason is required to mark a prescription stopped");
+    }
+
+    #[tokio::test]
+    async fn cancelled_prescriptions_cannot_be_dispensed() {
+        let id = ObjectId::new();
+        let mut prescriptions = stored(id, PrescriptionStatus::Cancelled);
+        prescriptions.expect_add_dispense().never();
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|did| {
+            Ok(Some(Practitioner {
+                practitioner_type: PractitionerType::Pharmacist,
+                ..practitioner(did, true)
+            }))
+        });
+
+        let err = service(prescriptions, MockPatientStore::new(), practitioners, MockCredentialStore::new(), MockAuditStore::new())
+            .dispense(PHARMACIST, Role::Practitioner, &id.to_hex(), dispense_request())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn only_verified_pharmacists_dispense() {
+        let id = ObjectId::new();
+        let clinician = practitioner(PRESCRIBER, true);
+        let unverified = Practitioner { practitioner_type: PractitionerType::Pharmacist, ..practitioner(PHARMACIST, false) };
+        for caller in [clinician, unverified] {
+            let mut prescriptions = stored(id, PrescriptionStatus::Active);
+            prescriptions.expect_add_dispense().never();
+            let mut practitioners = MockPractitionerStore::new();
+            let did = caller.did.clone();
+            practitioners.expect_get_practitioner_by_did().returning(move |_| Ok(Some(caller.clone())));
+
+            let err = service(prescriptions, MockPatientStore::new(), practitioners, MockCredentialStore::new(), MockAuditStore::new())
+                .dispense(&did, Role::Practitioner, &id.to_hex(), dispense_request())
+                .await
+                .unwrap_err();
+
+            assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn credentials_verify_until_the_prescription_is_filled() {
+        // Stores that keep what was written, so the credential and prescription can be read back
+        let stored_prescription: Arc<Mutex<Option<Prescription>>> = Arc::default();
+        let stored_credential: Arc<Mutex<Option<VerifiableCredential>>> = Arc::default();
+        let mut prescriptions = MockPrescriptionStore::new();
+        let written = stored_prescription.clone();
+        prescriptions.expect_create_prescription().times(1).returning(move |prescription| {
+            *written.lock().unwrap() = Some(prescription.clone());
+            Ok(prescription.id.unwrap())
+        });
+        prescriptions.expect_list_prescriptions().returning(|_, _| Ok(vec![]));
+        let read = stored_prescription.clone();
+        prescriptions.expect_get_prescription_by_credential().returning(move |_| Ok(read.lock().unwrap().clone()));
+        let dispensed = stored_prescription.clone();
+        prescriptions.expect_add_dispense().times(1).returning(move |_, dispense, previous_dispenses, fills| {
+            let mut stored = dispensed.lock().unwrap();
+            let prescription = stored.as_mut().unwrap();
+            assert_eq!(prescription.dispenses.len(), previous_dispenses);
+            prescription.dispenses.push(dispense.clone());
+            prescription.fully_dispensed = fills;
+            Ok(Some(prescription.clone()))
+        });
+        let mut credentials = MockCredentialStore::new();
+        let issued = stored_credential.clone();
+        credentials.expect_create_verifiable_credential().times(1).returning(move |credential, _| {
+            *issued.lock().unwrap() = Some(credential.clone());
+            Ok(())
+        });
+        let known = stored_credential.clone();
+        credentials.expect_get_credential_by_hash().returning(move |_| Ok(known.lock().unwrap().clone()));
+        let mut patients = MockPatientStore::new();
+        patients.expect_active_grants().returning(|patient_did, grantee_did| {
+            Ok(vec![AccessControl {
+                id: Some(ObjectId::new()),
+                patient_did: patient_did.to_string(),
+                grantee_did: grantee_did.to_string(),
+                permissions: vec![Permission::Prescribe],
+                active: true,
+                created_at: Utc::now(),
+                expires_at: None,
+                emergency: false,
+                hedera_transaction_id: None,
+            }])
+        });
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|did| {
+            Ok(Some(match did {
+                PHARMACIST => Practitioner { practitioner_type: PractitionerType::Pharmacist, ..practitioner(did, true) },
+                _ => practitioner(did, true),
+            }))
+        });
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let service = service(prescriptions, patients, practitioners, credentials, audit_store);
+
+        let request = CreatePrescriptionRequest {
+            patient_did: PATIENT.to_string(),
+            medication_request: prescription(ObjectId::new(), PrescriptionStatus::Active).fhir_medication_request,
+            rxnorm_code: None,
+            issue_credential: true,
+            force: false,
+            override_reason: None,
+        };
+        let created = service.create(PRESCRIBER, Role::Practitioner, request).await.unwrap().prescription;
+        let credential = created.credential.unwrap();
+        let metadata: PrescriptionCredentialMetadata = serde_json::from_str(&stored_credential.lock().unwrap().as_ref().unwrap().metadata).unwrap();
+        assert_eq!(metadata.prescription_hash, prescription_hash(&created.id.unwrap().to_hex()));
+        assert_eq!(metadata.prescriber_did, PRESCRIBER);
+
+        let scanned = PrescriptionReference { credential_hash: None, qr_payload: Some(credential.qr_payload.clone()) };
+        let verdict = service.verify("10.0.0.1", scanned.clone()).await.unwrap();
+        assert!(verdict.valid);
+        assert_eq!(verdict.medication.as_deref(), Some("Amoxicillin 500mg"));
+        assert_eq!(verdict.prescriber_license_verified, Some(true));
+
+        let forged = PrescriptionReference { credential_hash: None, qr_payload: Some(format!("rx:{}:{}", credential.hash, prescription_hash("other"))) };
+        assert!(!service.verify("10.0.0.1", forged).await.unwrap().valid);
+
+        let dispense = VerifiedDispenseRequest { reference: scanned.clone(), dispense: dispense_request() };
+        let filled = service.dispense_verified(PHARMACIST, Role::Practitioner, dispense.clone()).await.unwrap();
+        assert!(filled.fully_dispensed);
+
+        let verdict = service.verify("10.0.0.1", scanned).await.unwrap();
+        assert_eq!(verdict.reason.as_deref(), Some("The prescription was already dispensed"));
+        let err = service.dispense_verified(PHARMACIST, Role::Practitioner, dispense).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn major_interactions_need_an_audited_override() {
+        let mut prescriptions = MockPrescriptionStore::new();
+        prescriptions.expect_list_prescriptions().returning(|_, status| {
+            assert_eq!(status, Some(PrescriptionStatus::Active));
+            let mut warfarin = prescription(ObjectId::new(), PrescriptionStatus::Active);
+            warfarin.fhir_medication_request.medication_codeable_concept.text = Some("Warfarin 5mg".to_string());
+            Ok(vec![warfarin])
+        });
+        prescriptions.expect_create_prescription().times(1).returning(|prescription| Ok(prescription.id.unwrap()));
+        let mut patients = MockPatientStore::new();
+        patients.expect_active_grants().returning(|patient_did, grantee_did| {
+            Ok(vec![AccessControl {
+                id: Some(ObjectId::new()),
+                patient_did: patient_did.to_string(),
+                grantee_did: grantee_did.to_string(),
+                permissions: vec![Permission::Prescribe],
+                active: true,
+                created_at: Utc::now(),
+                expires_at: None,
+                emergency: false,
+                hedera_transaction_id: None,
+            }])
+        });
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(|log| log.action == "override_interaction_warning")
+            .times(1)
+            .returning(|log| {
+                let details = log.details.as_ref().unwrap();
+                assert_eq!(details["reason"], "INR monitored weekly");
+                assert_eq!(details["interactions"].as_array().unwrap().len(), 1);
+                Ok(())
+            });
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let service = service(prescriptions, patients, no_practitioners(), MockCredentialStore::new(), audit_store);
+
+        let mut request = CreatePrescriptionRequest {
+            patient_did: PATIENT.to_string(),
+            medication_request: prescription(ObjectId::new(), PrescriptionStatus::Active).fhir_medication_request,
+            rxnorm_code: None,
+            issue_credential: false,
+            force: false,
+            override_reason: None,
+        };
+        request.medication_request.medication_codeable_concept.text = Some("Aspirin 75mg".to_string());
+
+        let err = service.create(PRESCRIBER, Role::Practitioner, request.clone()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+
+        request.force = true;
+        let err = service.create(PRESCRIBER, Role::Practitioner, request.clone()).await.unwrap_err();
+        assert_eq!(err.to_string(), "A reason is required to override a major interaction");
+
+        request.override_reason = Some("INR monitored weekly".to_string());
+        let created = service.create(PRESCRIBER, Role::Practitioner, request).await.unwrap();
+        assert!(created.interactions_checked);
+        assert_eq!(created.warnings[0].severity, InteractionSeverity::Major);
+        assert_eq!(created.warnings[0].interacting_drug, "Warfarin 5mg");
+    }
+
+    #[test]
+    fn verifications_are_limited_per_client() {
+        let limiter = RequestLimiter::new(Duration::minutes(1));
+
+        assert!(limiter.allow("10.0.0.1", 2));
+        assert!(limiter.allow("10.0.0.1", 2));
+        assert!(!limiter.allow("10.0.0.1", 2));
+        assert!(limiter.allow("10.0.0.2", 2));
+        // A raised limit counts the requests already made
+        assert!(limiter.allow("10.0.0.1", 4));
+
+        let expired = RequestLimiter::new(Duration::zero());
+        assert!(expired.allow("10.0.0.1", 1));
+        assert!(expired.allow("10.0.0.1", 1));
+    }
+}
@@ -0,0 +1,190 @@
+//! Merges a patient's Conditions into a problem list, one entry per coded problem.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+use crate::models::*;
+
+/// Clinical statuses that put a problem on the active list
+const ACTIVE_STATUSES: [&str; 3] = ["active", "recurrence", "relapse"];
+/// Verification statuses of Conditions that never count
+const DISCARDED_VERIFICATIONS: [&str; 2] = ["entered-in-error", "refuted"];
+
+fn status_code(concept: &FhirCodeableConcept) -> Option<&str> {
+    concept.coding.iter().find_map(|coding| coding.code.as_deref())
+}
+
+/// What identifies a problem: its first coded system and code, else its text
+fn problem_key(code: &FhirCodeableConcept) -> Option<(String, String)> {
+    code.coding
+        .iter()
+        .find_map(|coding| Some((coding.system.clone().unwrap_or_default(), coding.code.clone()?)))
+        .or_else(|| code.text.as_deref().map(str::trim).filter(|text| !text.is_empty()).map(|text| (String::new(), text.to_lowercase())))
+}
+
+/// The patient's problems, active ones first and each group most recently recorded first.
+/// A problem takes the status of its latest Condition, so one resolved since is left off
+/// unless `include_resolved` is set. Dates without a time start at midnight in the patient's `timezone`.
+pub fn problem_list(conditions: Vec<FhirCondition>, include_resolved: bool, timezone: Tz) -> Vec<Problem> {
+    let mut groups: HashMap<(String, String), Vec<FhirCondition>> = HashMap::new();
+    for condition in conditions {
+        if status_code(&condition.verification_status).is_some_and(|status| DISCARDED_VERIFICATIONS.contains(&status)) {
+            continue;
+        }
+        if let Some(key) = problem_key(&condition.code) {
+            groups.entry(key).or_default().push(condition);
+        }
+    }
+
+    let mut problems: Vec<(Option<DateTime<Utc>>, Problem)> = groups
+        .into_values()
+        .map(|mut group| {
+            group.sort_by_key(|condition| condition.recorded_date.earliest(timezone));
+            let latest = group.last().expect("groups are never empty");
+            let coding = latest.code.coding.iter().find(|coding| coding.code.is_some());
+            let recorded = &latest.recorded_date;
+            let onset = group
+                .iter()
+                .map(|condition| &condition.onset_date_time)
+                .filter(|onset| !onset.as_str().trim().is_empty())
+                .min_by_key(|onset| onset.earliest(timezone).unwrap_or(DateTime::<Utc>::MAX_UTC));
+            let mut encounter_ids: Vec<String> = Vec::new();
+            for condition in &group {
+                let id = condition.encounter.as_ref().and_then(|encounter| encounter.reference.strip_prefix("Encounter/"));
+                if let Some(id) = id.filter(|id| !encounter_ids.iter().any(|known| known == id)) {
+                    encounter_ids.push(id.to_string());
+                }
+            }
+            let problem = Problem {
+                system: coding.and_then(|coding| coding.system.clone()),
+                code: coding.and_then(|coding| coding.code.clone()),
+                display: latest
+                    .code
+                    .text
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(str::to_string)
+                    .or_else(|| coding.and_then(|coding| coding.display.clone())),
+                clinical_status: status_code(&latest.clinical_status).unwrap_or("unknown").to_string(),
+                onset_date_time: onset.cloned(),
+                recorded_date: (!recorded.as_str().trim().is_empty()).then(|| recorded.clone()),
+                encounter_ids,
+                condition_ids: group.iter().map(|condition| condition.id.clone()).collect(),
+            };
+            (recorded.earliest(timezone), problem)
+        })
+        .filter(|(_, problem)| include_resolved || ACTIVE_STATUSES.contains(&problem.clinical_status.as_str()))
+        .collect();
+    problems.sort_by(|(a_recorded, a), (b_recorded, b)| {
+        let a_active = ACTIVE_STATUSES.contains(&a.clinical_status.as_str());
+        let b_active = ACTIVE_STATUSES.contains(&b.clinical_status.as_str());
+        b_active.cmp(&a_active).then(b_recorded.cmp(a_recorded))
+    });
+    problems.into_iter().map(|(_, problem)| problem).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fhir::{FhirCodeSystems, FhirManager};
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+
+    fn icd10(code: &str, display: &str) -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding { system: Some(FhirCodeSystems::icd10().to_string()), code: Some(code.to_string()), display: Some(display.to_string()) }],
+            text: None,
+        }
+    }
+
+    /// An empty date stands for one the source system left out
+    fn date(value: &str) -> FhirDateTime {
+        if value.is_empty() { FhirDateTime::default() } else { value.parse().unwrap() }
+    }
+
+    fn condition(code: FhirCodeableConcept, encounter_id: Option<&str>, onset: &str, recorded: &str, status: &str) -> FhirCondition {
+        let mut condition = FhirManager::create_condition(PATIENT, encounter_id, code, vec![], date(onset), date(recorded));
+        condition.clinical_status.coding[0].code = Some(status.to_string());
+        condition
+    }
+
+    #[test]
+    fn conditions_with_the_same_code_are_merged() {
+        let problems = problem_list(
+            vec![
+                condition(icd10("E11.9", "Type 2 diabetes"), Some("e1"), "2021-06-01", "2021-06-03T10:00:00Z", "active"),
+                condition(icd10("E11.9", "Type 2 diabetes mellitus"), Some("e2"), "2020-11-15T00:00:00+03:00", "2024-02-01T09:00:00Z", "active"),
+                // Imported without an encounter
+                condition(icd10("E11.9", "T2DM"), None, "", "2022-01-01", "active"),
+                condition(icd10("I10", "Hypertension"), Some("e2"), "2023-05-05", "2023-05-05", "active"),
+            ],
+            false,
+            Tz::UTC,
+        );
+
+        assert_eq!(problems.len(), 2);
+        let diabetes = &problems[0];
+        assert_eq!(diabetes.code.as_deref(), Some("E11.9"));
+        assert_eq!(diabetes.display.as_deref(), Some("Type 2 diabetes mellitus"));
+        assert_eq!(diabetes.onset_date_time.as_ref().map(FhirDateTime::as_str), Some("2020-11-15T00:00:00+03:00"));
+        assert_eq!(diabetes.recorded_date.as_ref().map(FhirDateTime::as_str), Some("2024-02-01T09:00:00Z"));
+        assert_eq!(diabetes.encounter_ids, ["e1", "e2"]);
+        assert_eq!(diabetes.condition_ids.len(), 3);
+        assert_eq!(problems[1].code.as_deref(), Some("I10"));
+    }
+
+    #[test]
+    fn resolved_problems_are_only_listed_on_request() {
+        let conditions = vec![
+            condition(icd10("J18.9", "Pneumonia"), Some("e1"), "2023-01-10", "2023-01-10", "active"),
+            condition(icd10("J18.9", "Pneumonia"), Some("e2"), "2023-01-10", "2023-02-01", "resolved"),
+            condition(icd10("F41.1", "Anxiety"), Some("e2"), "2022-03-01", "2022-03-01", "recurrence"),
+        ];
+
+        let active = problem_list(conditions.clone(), false, Tz::UTC);
+        assert_eq!(active.iter().map(|problem| problem.code.as_deref().unwrap()).collect::<Vec<_>>(), ["F41.1"]);
+
+        let all = problem_list(conditions, true, Tz::UTC);
+        assert_eq!(all.len(), 2);
+        assert_eq!((all[1].code.as_deref(), all[1].clinical_status.as_str()), (Some("J18.9"), "resolved"));
+    }
+
+    #[test]
+    fn uncoded_conditions_group_by_text_and_errors_are_dropped() {
+        let text = |text: &str| FhirCodeableConcept { coding: vec![], text: Some(text.to_string()) };
+        let mut mistaken = condition(icd10("C34.90", "Lung cancer"), Some("e1"), "", "2024-01-01", "active");
+        mistaken.verification_status.coding[0].code = Some("entered-in-error".to_string());
+
+        let problems = problem_list(
+            vec![
+                condition(text("Chronic back pain"), Some("e1"), "", "2023-01-01", "active"),
+                condition(text("chronic back pain "), Some("e2"), "", "2024-01-01", "active"),
+                condition(text(""), Some("e3"), "", "2024-01-01", "active"),
+                mistaken,
+            ],
+            true,
+            Tz::UTC,
+        );
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].code, None);
+        assert_eq!(problems[0].display.as_deref(), Some("chronic back pain"));
+        assert_eq!(problems[0].encounter_ids, ["e1", "e2"]);
+    }
+
+    #[test]
+    fn dates_without_a_time_are_read_in_the_patients_zone() {
+        let conditions = vec![
+            condition(icd10("E11.9", "Diabetes, recorded at the clinic"), Some("e1"), "", "2024-03-04T22:00:00Z", "active"),
+            condition(icd10("E11.9", "Diabetes, imported"), None, "", "2024-03-05", "active"),
+        ];
+
+        // 22:00 UTC is already 01:00 on the 5th in Nairobi, after the imported date began
+        let nairobi = problem_list(conditions.clone(), false, "Africa/Nairobi".parse().unwrap());
+        assert_eq!(nairobi[0].recorded_date.as_ref().map(FhirDateTime::as_str), Some("2024-03-04T22:00:00Z"));
+        let utc = problem_list(conditions, false, Tz::UTC);
+        assert_eq!(utc[0].recorded_date.as_ref().map(FhirDateTime::as_str), Some("2024-03-05"));
+    }
+}
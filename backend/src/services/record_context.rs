@@ -0,0 +1,258 @@
+//! The patient-record summary sent with a chat question when the patient opts in.
+//!
+//! Loading is separate from rendering so the prompt budget and truncation rules
+//! can be tested without a database.
+
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::models::*;
+use crate::store::{EncounterStore, PatientStore};
+use crate::utils;
+
+/// Encounters looked at, most recent first
+const RECENT_ENCOUNTERS: i64 = 5;
+/// Upper bound on the rendered record section, instruction excluded
+pub const MAX_CONTEXT_CHARS: usize = 4_000;
+/// Longer entries are cut so one free-text field cannot crowd out the rest
+const MAX_ITEM_CHARS: usize = 200;
+const TRUNCATED_MARKER: &str = "[Further record entries omitted]";
+
+pub const RECORD_INSTRUCTION: &str = "You are answering a patient's question about their own health record. \
+Use only the record summary below for facts about the patient and say so when it does not contain the answer. \
+Do not diagnose or change treatment; remind the patient to confirm any medical decision with their clinician.";
+
+/// What the assistant may see of a patient's record
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordSummary {
+    pub demographics: Option<String>,
+    /// Empty until the record holds AllergyIntolerance resources
+    pub allergies: Vec<String>,
+    pub medications: Vec<String>,
+    pub conditions: Vec<String>,
+    /// Most recent first
+    pub encounters: Vec<String>,
+}
+
+impl RecordSummary {
+    /// Render within [`MAX_CONTEXT_CHARS`].
+    ///
+    /// Sections go in a fixed order (demographics, allergies, medications,
+    /// conditions, encounters) and entries keep their order within a section.
+    /// Each entry is capped at [`MAX_ITEM_CHARS`]; the first entry that no longer
+    /// fits ends the summary, followed by a marker saying entries were left out.
+    pub fn render(&self) -> String {
+        let sections: [(&str, &[String]); 4] = [
+            ("Allergies", &self.allergies),
+            ("Current medications", &self.medications),
+            ("Active conditions", &self.conditions),
+            ("Recent encounters", &self.encounters),
+        ];
+        // (line, is a section title)
+        let mut lines: Vec<(String, bool)> = self.demographics.iter().map(|d| (clip(d), false)).collect();
+        for (title, items) in sections {
+            if items.is_empty() {
+                continue;
+            }
+            lines.push((format!("{}:", title), true));
+            lines.extend(items.iter().map(|item| (format!("- {}", clip(item)), false)));
+        }
+
+        // Room is kept for the marker so the result never exceeds the budget
+        let budget = MAX_CONTEXT_CHARS - TRUNCATED_MARKER.len() - 1;
+        let mut kept: Vec<&(String, bool)> = Vec::new();
+        let mut used = 0;
+        for line in &lines {
+            let needed = line.0.chars().count() + usize::from(!kept.is_empty());
+            if used + needed > budget {
+                // A section title with none of its entries is of no use to the model
+                if kept.last().is_some_and(|(_, is_title)| *is_title) {
+                    kept.pop();
+                }
+                let mut rendered: Vec<&str> = kept.iter().map(|(line, _)| line.as_str()).collect();
+                rendered.push(TRUNCATED_MARKER);
+                return rendered.join("\n");
+            }
+            used += needed;
+            kept.push(line);
+        }
+        if kept.is_empty() {
+            return "No entries are on record yet.".to_string();
+        }
+        kept.iter().map(|(line, _)| line.as_str()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// The question wrapped with the scope instruction and the rendered record
+pub fn prompt_with_record(summary: &RecordSummary, question: &str) -> String {
+    format!("{}\n\n<record>\n{}\n</record>\n\nQuestion: {}", RECORD_INSTRUCTION, summary.render(), question)
+}
+
+fn clip(text: &str) -> String {
+    match text.char_indices().nth(MAX_ITEM_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+fn concept_text(concept: &FhirCodeableConcept) -> Option<String> {
+    concept
+        .text
+        .clone()
+        .or_else(|| concept.coding.iter().find_map(|coding| coding.display.clone().or_else(|| coding.code.clone())))
+}
+
+fn is_active(concept: &FhirCodeableConcept) -> bool {
+    concept.coding.iter().any(|coding| coding.code.as_deref() == Some("active"))
+}
+
+/// Gathers a [`RecordSummary`] from the patient's stored resources
+pub struct RecordContext {
+    patients: Arc<dyn PatientStore>,
+    encounters: Arc<dyn EncounterStore>,
+    config: Arc<Config>,
+}
+
+impl RecordContext {
+    pub fn new(patients: Arc<dyn PatientStore>, encounters: Arc<dyn EncounterStore>, config: Arc<Config>) -> Self {
+        Self { patients, encounters, config }
+    }
+
+    pub async fn summarize(&self, patient_did: &str) -> anyhow::Result<RecordSummary> {
+        let mut summary = RecordSummary::default();
+        if let Some(patient) = self.patients.get_patient_by_did(patient_did, &self.config.ipfs_encryption_key).await? {
+            let fhir = patient.fhir_patient;
+            if !fhir.gender.is_empty() || !fhir.birth_date.is_empty() {
+                summary.demographics = Some(format!("Gender: {}; born: {}", or_unknown(&fhir.gender), or_unknown(&fhir.birth_date)));
+            }
+        }
+
+        // Dates are given as the patient would read them on their own calendar
+        let timezone = utils::patient_timezone(self.patients.get_patient_timezone(patient_did).await?.as_deref());
+        for encounter in self.encounters.list_recent_encounters(patient_did, RECENT_ENCOUNTERS).await? {
+            let Some(encounter_id) = encounter.id.map(|id| id.to_hex()) else { continue };
+            let fhir = &encounter.fhir_encounter;
+            let reasons: Vec<String> = fhir.reason_code.iter().filter_map(concept_text).collect();
+            let date = match &fhir.period.start {
+                Some(start) => start.date_in(timezone),
+                None => encounter.created_at.with_timezone(&timezone).date_naive().to_string(),
+            };
+            summary.encounters.push(if reasons.is_empty() {
+                format!("{}: visit", date)
+            } else {
+                format!("{}: {}", date, reasons.join(", "))
+            });
+
+            for condition in self.encounters.get_conditions_for_encounter(&encounter_id).await? {
+                if !is_active(&condition.clinical_status) {
+                    continue;
+                }
+                if let Some(text) = concept_text(&condition.code).filter(|text| !summary.conditions.contains(text)) {
+                    summary.conditions.push(text);
+                }
+            }
+            for request in self.encounters.get_medication_requests_for_encounter(&encounter_id).await? {
+                if request.status != "active" {
+                    continue;
+                }
+                let Some(medication) = concept_text(&request.medication_codeable_concept) else { continue };
+                let dosage = request.dosage_instruction.iter().find_map(|d| d.text.clone());
+                let prescribed = request.authored_on.date_in(timezone);
+                let entry = match dosage {
+                    Some(dosage) => format!("{} ({}), prescribed {}", medication, dosage, prescribed),
+                    None => format!("{}, prescribed {}", medication, prescribed),
+                };
+                summary.medications.push(entry);
+            }
+        }
+        Ok(summary)
+    }
+}
+
+fn or_unknown(value: &str) -> &str {
+    if value.is_empty() { "unknown" } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> RecordSummary {
+        RecordSummary {
+            demographics: Some("Gender: female; born: 1990-04-02".to_string()),
+            allergies: vec![],
+            medications: vec!["Metformin (500 mg twice daily), prescribed 2026-09-12".to_string()],
+            conditions: vec!["Type 2 diabetes".to_string()],
+            encounters: vec!["2026-09-12: Follow-up".to_string(), "2026-06-01: Annual check-up".to_string()],
+        }
+    }
+
+    #[test]
+    fn renders_sections_in_a_fixed_order() {
+        assert_eq!(
+            summary().render(),
+            "Gender: female; born: 1990-04-02\n\
+             Current medications:\n- Metformin (500 mg twice daily), prescribed 2026-09-12\n\
+             Active conditions:\n- Type 2 diabetes\n\
+             Recent encounters:\n- 2026-09-12: Follow-up\n- 2026-06-01: Annual check-up"
+        );
+    }
+
+    #[test]
+    fn empty_record_says_so() {
+        assert_eq!(RecordSummary::default().render(), "No entries are on record yet.");
+    }
+
+    #[test]
+    fn long_entries_are_clipped() {
+        let summary = RecordSummary { conditions: vec!["x".repeat(1_000)], ..Default::default() };
+
+        let rendered = summary.render();
+
+        assert_eq!(rendered, format!("Active conditions:\n- {}…", "x".repeat(MAX_ITEM_CHARS)));
+    }
+
+    #[test]
+    fn over_budget_record_keeps_higher_priority_sections() {
+        let entry = "y".repeat(MAX_ITEM_CHARS);
+        let summary = RecordSummary {
+            medications: vec![entry.clone(); 10],
+            encounters: vec![entry.clone(); 30],
+            ..Default::default()
+        };
+
+        let rendered = summary.render();
+
+        assert!(rendered.chars().count() <= MAX_CONTEXT_CHARS);
+        assert!(rendered.ends_with(TRUNCATED_MARKER));
+        assert_eq!(rendered.matches("Current medications:").count(), 1);
+        assert_eq!(rendered.matches(&format!("- {}", entry)).count(), 19);
+        // Rendering is deterministic
+        assert_eq!(rendered, summary.render());
+    }
+
+    #[test]
+    fn section_title_is_dropped_when_none_of_its_entries_fit() {
+        let entry = "z".repeat(MAX_ITEM_CHARS);
+        // The next section's title still fits after 19 entries, but none of its entries do
+        let summary = RecordSummary {
+            medications: vec![entry.clone(); 19],
+            conditions: vec![entry],
+            ..Default::default()
+        };
+
+        let rendered = summary.render();
+
+        assert!(!rendered.contains("Active conditions:"));
+        assert!(rendered.ends_with(&format!("\n{}", TRUNCATED_MARKER)));
+    }
+
+    #[test]
+    fn prompt_puts_the_record_between_instruction_and_question() {
+        let prompt = prompt_with_record(&summary(), "What was I prescribed last month?");
+
+        assert!(prompt.starts_with(RECORD_INSTRUCTION));
+        assert!(prompt.contains("<record>\nGender: female"));
+        assert!(prompt.ends_with("</record>\n\nQuestion: What was I prescribed last month?"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Replaces personal identifiers in outbound text with placeholder tokens: chat
+//! messages before they reach the model, and error reports before they leave the
+//! process.
+//!
+//! Pure and deterministic: the same value always gets the same placeholder within
+//! one [`Redactor`], so the model can still refer to "[PHONE_1]" and the reply can
+//! be mapped back for the patient who wrote it.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref EMAIL: Regex = Regex::new(r"[\p{L}\p{N}._%+-]+@[\p{L}\p{N}-]+(?:\.[\p{L}\p{N}-]+)*\.\p{L}{2,}").unwrap();
+    // W3C DIDs, such as did:hedera:testnet:0.0.1234 or did:key:z6Mk…
+    static ref DID: Regex = Regex::new(r"\bdid:[a-z0-9]+:[A-Za-z0-9._%:-]*[A-Za-z0-9]").unwrap();
+    // US social security numbers
+    static ref SSN: Regex = Regex::new(r"\b\d{3}-\d{2}-\d{4}\b").unwrap();
+    // Digits with the usual phone separators; the digit count is checked separately
+    static ref PHONE: Regex = Regex::new(r"\+?\d[\d \t().-]{6,}\d").unwrap();
+    // Passport and similar document numbers: one or two capitals followed by 6–8 digits
+    static ref DOCUMENT_NUMBER: Regex = Regex::new(r"\b[A-Z]{1,2}\d{6,8}\b").unwrap();
+    // A bare run of 7–8 digits, the length of most national ID numbers
+    static ref ID_NUMBER: Regex = Regex::new(r"\b\d{7,8}\b").unwrap();
+}
+
+/// Fewest and most digits a phone number has, country code included
+const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 9..=15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiKind {
+    Email,
+    Did,
+    Phone,
+    IdNumber,
+}
+
+impl PiiKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Email => "EMAIL",
+            Self::Did => "DID",
+            Self::Phone => "PHONE",
+            Self::IdNumber => "ID",
+        }
+    }
+}
+
+/// Redacts one conversation, remembering which placeholder stands for which value
+#[derive(Debug, Default)]
+pub struct Redactor {
+    // (placeholder, original), in order of first appearance
+    replacements: Vec<(String, String)>,
+    applied: usize,
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `text` with every detected identifier replaced by its placeholder
+    pub fn redact(&mut self, text: &str) -> String {
+        // Emails first: they may contain digit runs the other detectors would split
+        let text = self.replace(text, &EMAIL, PiiKind::Email, |_| true);
+        // DIDs next, before their account numbers can pass for phone or ID numbers
+        let text = self.replace(&text, &DID, PiiKind::Did, |_| true);
+        let text = self.replace(&text, &SSN, PiiKind::IdNumber, |_| true);
+        let text = self.replace(&text, &PHONE, PiiKind::Phone, |candidate| {
+            PHONE_DIGITS.contains(&candidate.chars().filter(|c| c.is_numeric()).count())
+        });
+        let text = self.replace(&text, &DOCUMENT_NUMBER, PiiKind::IdNumber, |_| true);
+        self.replace(&text, &ID_NUMBER, PiiKind::IdNumber, |_| true)
+    }
+
+    /// Put the original values back wherever the reply repeats a placeholder
+    pub fn restore(&self, text: &str) -> String {
+        self.replacements
+            .iter()
+            .fold(text.to_string(), |text, (placeholder, original)| text.replace(placeholder, original))
+    }
+
+    /// How many identifiers have been replaced so far, repeats included
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    fn replace(&mut self, text: &str, pattern: &Regex, kind: PiiKind, accept: impl Fn(&str) -> bool) -> String {
+        let mut redacted = String::with_capacity(text.len());
+        let mut last = 0;
+        for found in pattern.find_iter(text) {
+            // Separators the phone pattern picked up at the edges stay in the text
+            let value = found.as_str().trim();
+            if !accept(value) {
+                continue;
+            }
+            let start = found.start() + found.as_str().find(value).unwrap_or(0);
+            redacted.push_str(&text[last..start]);
+            redacted.push_str(&self.placeholder(kind, value));
+            last = start + value.len();
+            self.applied += 1;
+        }
+        redacted.push_str(&text[last..]);
+        redacted
+    }
+
+    fn placeholder(&mut self, kind: PiiKind, value: &str) -> String {
+        if let Some((placeholder, _)) = self.replacements.iter().find(|(_, original)| original == value) {
+            return placeholder.clone();
+        }
+        let prefix = format!("[{}_", kind.label());
+        let n = self.replacements.iter().filter(|(placeholder, _)| placeholder.starts_with(&prefix)).count() + 1;
+        let placeholder = format!("{}{}]", prefix, n);
+        self.replacements.push((placeholder.clone(), value.to_string()));
+        placeholder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(text: &str) -> (String, usize) {
+        let mut redactor = Redactor::new();
+        let redacted = redactor.redact(text);
+        (redacted, redactor.applied())
+    }
+
+    #[test]
+    fn emails_are_replaced() {
+        assert_eq!(redact("Email me at amina.o@example.co.ke please"), ("Email me at [EMAIL_1] please".to_string(), 1));
+        assert_eq!(redact("Écrivez à josé.müller@correo.es."), ("Écrivez à [EMAIL_1].".to_string(), 1));
+    }
+
+    #[test]
+    fn phone_numbers_in_common_formats_are_replaced() {
+        assert_eq!(redact("Nipigie kwa 0712 345 678 kesho").0, "Nipigie kwa [PHONE_1] kesho");
+        assert_eq!(redact("Call +254 (712) 345-678.").0, "Call [PHONE_1].");
+        assert_eq!(redact("Appelez le 06.12.34.56.78 ce soir").0, "Appelez le [PHONE_1] ce soir");
+        // Arabic-Indic digits
+        assert_eq!(redact("رقمي ٠٧١٢٣٤٥٦٧٨ شكرا").0, "رقمي [PHONE_1] شكرا");
+    }
+
+    #[test]
+    fn id_numbers_are_replaced() {
+        assert_eq!(redact("Mi DNI es 12345678").0, "Mi DNI es [ID_1]");
+        assert_eq!(redact("Namba yangu ya kitambulisho ni 2345678.").0, "Namba yangu ya kitambulisho ni [ID_1].");
+        assert_eq!(redact("SSN 123-45-6789").0, "SSN [ID_1]");
+        assert_eq!(redact("Passport A1234567 expires soon").0, "Passport [ID_1] expires soon");
+    }
+
+    #[test]
+    fn dids_are_replaced_whole() {
+        assert_eq!(redact("Share it with did:hedera:testnet:0.0.123456789.").0, "Share it with [DID_1].");
+        assert_eq!(redact("Signed by did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK#z6Mk").0, "Signed by [DID_1]#z6Mk");
+    }
+
+    #[test]
+    fn clinical_numbers_are_left_alone() {
+        let text = "On 2026-09-12 my BP was 120/80, HbA1c 6.5%, taking 500 mg twice daily since 12.09.2026";
+
+        assert_eq!(redact(text), (text.to_string(), 0));
+    }
+
+    #[test]
+    fn repeated_values_share_a_placeholder_and_count_each_time() {
+        let (redacted, applied) = redact("0712345678, again 0712345678, or 0722000111");
+
+        assert_eq!(redacted, "[PHONE_1], again [PHONE_1], or [PHONE_2]");
+        assert_eq!(applied, 3);
+    }
+
+    #[test]
+    fn placeholders_stay_consistent_across_messages() {
+        let mut redactor = Redactor::new();
+
+        assert_eq!(redactor.redact("I am amina@example.com"), "I am [EMAIL_1]");
+        assert_eq!(redactor.redact("Is amina@example.com right?"), "Is [EMAIL_1] right?");
+        assert_eq!(redactor.applied(), 2);
+    }
+
+    #[test]
+    fn restore_maps_placeholders_back() {
+        let mut redactor = Redactor::new();
+        redactor.redact("My number is +254712345678 and ID 12345678");
+
+        assert_eq!(
+            redactor.restore("We will text [PHONE_1] about ID [ID_1]."),
+            "We will text +254712345678 about ID 12345678."
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Moves archived encounter bundles onto the current encryption key after a key change.
+
+use anyhow::{anyhow, Result};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream::{self, StreamExt};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::ServiceError;
+use crate::store::{EncounterStore, ReencryptionJobStore};
+use crate::utils;
+
+/// How long a runner may go without saving a page before another start takes the job over
+const JOB_LEASE_SECONDS: i64 = 600;
+
+// --- ReencryptionService ---
+#[derive(Clone)]
+pub struct ReencryptionService {
+    jobs: Arc<dyn ReencryptionJobStore>,
+    encounters: Arc<dyn EncounterStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl ReencryptionService {
+    pub fn new(
+        jobs: Arc<dyn ReencryptionJobStore>,
+        encounters: Arc<dyn EncounterStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { jobs, encounters, ipfs_client, config, audit_log_service }
+    }
+
+    fn lease_until() -> DateTime<Utc> {
+        Utc::now() + Duration::seconds(JOB_LEASE_SECONDS)
+    }
+
+    /// Start re-encrypting, in the background, every bundle not yet on the current key.
+    /// A job whose runner stopped (a crash or restart) is resumed where it left off instead;
+    /// while one is still being worked on, starting another is a conflict.
+    pub async fn start(&self, admin_did: &str) -> Result<ReencryptionJob> {
+        let lease_until = Self::lease_until();
+        let (job, resumed) = match self.jobs.claim_reencryption_job(lease_until).await? {
+            Some(job) => (job, true),
+            None => {
+                if self.jobs.running_reencryption_job().await?.is_some() {
+                    return Err(ServiceError::Conflict("A re-encryption job is already running".to_string()).into());
+                }
+                let now = Utc::now();
+                let mut job = ReencryptionJob {
+                    id: None,
+                    key_version: self.config.ipfs_encryption_key_version,
+                    status: ReencryptionJobStatus::Running,
+                    started_by: admin_did.to_string(),
+                    resume_after: None,
+                    reencrypted: 0,
+                    failed: 0,
+                    failures: Vec::new(),
+                    lease_until,
+                    created_at: now,
+                    updated_at: now,
+                    completed_at: None,
+                };
+                match self.jobs.create_reencryption_job(&job).await {
+                    Ok(id) => job.id = Some(id),
+                    // Another admin started one at the same moment
+                    Err(e) if matches!(e.downcast_ref::<DatabaseError>(), Some(DatabaseError::DuplicateKey(_))) => {
+                        return Err(ServiceError::Conflict("A re-encryption job is already running".to_string()).into());
+                    }
+                    Err(e) => return Err(e),
+                }
+                (job, false)
+            }
+        };
+        let id = job.id.ok_or_else(|| anyhow!("Re-encryption job has no id"))?;
+        self.audit_log_service
+            .log(admin_did, "start_reencryption_job", Some(json!({ "job_id": id, "key_version": job.key_version, "resumed": resumed })))
+            .await;
+
+        let service = self.clone();
+        let runner_job = job.clone();
+        tokio::spawn(async move {
+            if let Err(e) = service.run(runner_job, lease_until).await {
+                tracing::error!("Re-encryption job {} stopped: {}", id, e);
+            }
+        });
+        Ok(job)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<ReencryptionJob> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid job id"))?;
+        self.jobs.get_reencryption_job(id).await?.ok_or_else(|| anyhow!("Re-encryption job not found"))
+    }
+
+    /// All jobs, newest first
+    pub async fn list(&self) -> Result<Vec<ReencryptionJob>> {
+        self.jobs.list_reencryption_jobs().await
+    }
+
+    /// Work through the job a page at a time, at most `REENCRYPTION_CONCURRENCY` bundles at once,
+    /// saving progress after each page. Returns the completed job, or `None` if the lease held
+    /// until `lease_until` was lost to another runner.
+    pub async fn run(&self, job: ReencryptionJob, mut lease_until: DateTime<Utc>) -> Result<Option<ReencryptionJob>> {
+        let id = job.id.ok_or_else(|| anyhow!("Re-encryption job has no id"))?;
+        let key_version = job.key_version;
+        let mut resume_after = job.resume_after;
+        loop {
+            let page = self
+                .encounters
+                .list_bundles_to_reencrypt(key_version, resume_after, self.config.reencryption.batch_size as i64)
+                .await?;
+            let Some(last) = page.last().and_then(|encounter| encounter.id) else {
+                return self.jobs.complete_reencryption_job(id, lease_until).await;
+            };
+
+            let outcomes: Vec<(ObjectId, Result<()>)> = stream::iter(page)
+                .map(|encounter| async move { (encounter.id.unwrap_or(last), self.reencrypt(encounter, key_version).await) })
+                .buffer_unordered(self.config.reencryption.concurrency)
+                .collect()
+                .await;
+            let mut reencrypted = 0;
+            let mut failures = Vec::new();
+            for (encounter_id, outcome) in outcomes {
+                match outcome {
+                    Ok(()) => reencrypted += 1,
+                    Err(e) => {
+                        tracing::warn!("Could not re-encrypt the bundle of encounter {}: {}", encounter_id, e);
+                        failures.push(ReencryptionFailure { encounter_id, error: e.to_string(), at: Utc::now() });
+                    }
+                }
+            }
+
+            let renewed_until = Self::lease_until();
+            if !self.jobs.record_reencryption_progress(id, lease_until, renewed_until, last, reencrypted, &failures).await? {
+                tracing::warn!("Re-encryption job {} was taken over by another runner", id);
+                return Ok(None);
+            }
+            lease_until = renewed_until;
+            resume_after = Some(last);
+        }
+    }
+
+    /// Re-encrypt one encounter's bundle with key `key_version`, pin the new copy, point the
+    /// encounter at it and unpin the old one
+    async fn reencrypt(&self, encounter: Encounter, key_version: u32) -> Result<()> {
+        let encounter_id = encounter.id.ok_or_else(|| anyhow!("Encounter has no id"))?;
+        let old_hash = encounter.final_bundle_ipfs_hash.ok_or_else(|| anyhow!("Encounter has no bundle"))?;
+        let old_version = encounter.bundle_key_version.unwrap_or(LEGACY_BUNDLE_KEY_VERSION);
+        let old_key = self.config.encryption_key(old_version).ok_or_else(|| anyhow!("No key is configured for version {}", old_version))?;
+        let new_key = self.config.encryption_key(key_version).ok_or_else(|| anyhow!("No key is configured for version {}", key_version))?;
+
+        let stored = self.ipfs_client.get_file(&old_hash).await?;
+        // Bundles from before framed encryption come out framed too
+        let bundle = utils::chunked::decrypt(&stored, old_key)?;
+        let encrypted = utils::chunked::encrypt(&bundle, new_key)?;
+        let new_hash = self.ipfs_client.add_file(&encrypted, None).await?;
+        self.ipfs_client.pin_add(&new_hash).await?;
+
+        let replaced = BundleVersion { ipfs_hash: old_hash.clone(), key_version: old_version, replaced_at: Utc::now() };
+        if !self.encounters.replace_encounter_bundle(encounter_id, &replaced, &new_hash, key_version).await? {
+            // Nothing points at the new copy, so it need not stay pinned
+            if let Err(e) = self.ipfs_client.pin_rm(&new_hash).await {
+                tracing::warn!("Could not unpin unused bundle {}: {}", new_hash, e);
+            }
+            return Err(anyhow!("The encounter's bundle changed while it was being re-encrypted"));
+        }
+        if let Err(e) = self.ipfs_client.pin_rm(&old_hash).await {
+            tracing::warn!("Re-encrypted encounter {} but could not unpin its old bundle {}: {}", encounter_id, old_hash, e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::ReencryptionConfig;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::store::{MockAuditStore, MockEncounterStore, MockReencryptionJobStore};
+    use crate::utils::EncryptionKey;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const OLD_KEY: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW_KEY: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config {
+            ipfs_encryption_key: NEW_KEY.into(),
+            ipfs_encryption_key_version: 2,
+            ipfs_retired_encryption_keys: HashMap::from([(1, OLD_KEY.into())]),
+            reencryption: ReencryptionConfig { concurrency: 2, batch_size: 10 },
+            ..Default::default()
+        })
+    }
+
+    fn audit_log_service() -> Arc<AuditLogService> {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        Arc::new(AuditLogService::new(Arc::new(audit_store)))
+    }
+
+    fn job() -> ReencryptionJob {
+        ReencryptionJob {
+            id: Some(ObjectId::new()),
+            key_version: 2,
+            status: ReencryptionJobStatus::Running,
+            started_by: "did:hedera:testnet:0.0.100".to_string(),
+            resume_after: None,
+            reencrypted: 0,
+            failed: 0,
+            failures: Vec::new(),
+            lease_until: Utc::now(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+        }
+    }
+
+    fn archived(id: ObjectId, ipfs_hash: &str, key_version: Option<u32>) -> Encounter {
+        Encounter {
+            id: Some(id),
+            patient_did: "did:hedera:testnet:0.0.1".to_string(),
+            practitioner_did: "did:hedera:testnet:0.0.2".to_string(),
+            fhir_encounter: FhirEncounter {
+                resource_type: "Encounter".to_string(),
+                id: "e1".to_string(),
+                status: "finished".to_string(),
+                class: FhirCoding { system: None, code: Some("AMB".to_string()), display: None },
+                subject: FhirReference { reference: "Patient/did:hedera:testnet:0.0.1".to_string(), display: None },
+                participant: vec![],
+                period: FhirPeriod { start: None, end: None },
+                reason_code: vec![],
+            },
+            status: EncounterStatus::Finished,
+            status_reason: None,
+            final_bundle_ipfs_hash: Some(ipfs_hash.to_string()),
+            bundle_key_version: key_version,
+            bundle_history: vec![],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn bundles_move_to_the_current_key_and_failures_are_recorded() {
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let old_hash = ipfs.add_file(utils::encrypt(b"{\"resourceType\":\"Bundle\"}", &EncryptionKey::from_hex(OLD_KEY)).unwrap().as_bytes(), None).await.unwrap();
+        let (migrated, unknown_key) = (ObjectId::new(), ObjectId::new());
+        let pages = Mutex::new(vec![vec![archived(migrated, &old_hash, None), archived(unknown_key, &old_hash, Some(7))], vec![]]);
+        let replaced_with = Arc::new(Mutex::new(None));
+
+        let mut encounters = MockEncounterStore::new();
+        encounters
+            .expect_list_bundles_to_reencrypt()
+            .withf(|key_version, _, limit| *key_version == 2 && *limit == 10)
+            .times(2)
+            .returning(move |_, _, _| Ok(pages.lock().unwrap().remove(0)));
+        let recorded = replaced_with.clone();
+        encounters
+            .expect_replace_encounter_bundle()
+            .withf(move |id, replaced, _, key_version| *id == migrated && replaced.key_version == 1 && *key_version == 2)
+            .times(1)
+            .returning(move |_, _, new_hash, _| {
+                *recorded.lock().unwrap() = Some(new_hash.to_string());
+                Ok(true)
+            });
+        let mut jobs = MockReencryptionJobStore::new();
+        jobs.expect_record_reencryption_progress()
+            .withf(move |_, _, _, resume_after, reencrypted, failures| {
+                *reencrypted == 1 && failures.len() == 1 && failures[0].encounter_id == unknown_key && failures[0].error == "No key is configured for version 7" && *resume_after == unknown_key
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| Ok(true));
+        jobs.expect_complete_reencryption_job().times(1).returning(|_, _| Ok(Some(ReencryptionJob { status: ReencryptionJobStatus::Completed, ..job() })));
+        let service = ReencryptionService::new(Arc::new(jobs), Arc::new(encounters), ipfs.clone(), config(), audit_log_service());
+
+        let done = service.run(job(), Utc::now()).await.unwrap().unwrap();
+
+        assert_eq!(done.status, ReencryptionJobStatus::Completed);
+        let new_hash = replaced_with.lock().unwrap().clone().unwrap();
+        let stored = ipfs.get_file(&new_hash).await.unwrap();
+        assert!(utils::chunked::is_chunked(&stored));
+        assert_eq!(utils::chunked::decrypt(&stored, &EncryptionKey::from_hex(NEW_KEY)).unwrap(), b"{\"resourceType\":\"Bundle\"}");
+        assert_eq!(ipfs.pinned(), [new_hash]);
+        assert_eq!(ipfs.unpinned(), [old_hash]);
+    }
+
+    #[tokio::test]
+    async fn a_job_still_being_worked_on_is_not_started_twice() {
+        let mut jobs = MockReencryptionJobStore::new();
+        jobs.expect_claim_reencryption_job().returning(|_| Ok(None));
+        jobs.expect_running_reencryption_job().returning(|| Ok(Some(job())));
+        jobs.expect_create_reencryption_job().never();
+        let service = ReencryptionService::new(Arc::new(jobs), Arc::new(MockEncounterStore::new()), Arc::new(InMemoryObjectStorage::new()), config(), audit_log_service());
+
+        let err = service.start("did:hedera:testnet:0.0.100").await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+}
@@ -0,0 +1,397 @@
+use anyhow::{anyhow, Result};
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::services::{PatientService, ServiceError};
+use crate::store::{PractitionerStore, ReferralStore};
+
+// --- ReferralService ---
+pub struct ReferralService {
+    db: Arc<dyn ReferralStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    patient_service: Arc<PatientService>,
+    notification_service: Arc<NotificationService>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl ReferralService {
+    pub fn new(
+        db: Arc<dyn ReferralStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        patient_service: Arc<PatientService>,
+        notification_service: Arc<NotificationService>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, practitioners, patient_service, notification_service, config, audit_log_service }
+    }
+
+    /// Refer a patient to another registered practitioner, attaching records the caller can see themselves
+    pub async fn create(&self, caller_did: &str, caller_role: Role, request: CreateReferralRequest) -> Result<Referral> {
+        ensure_practitioner(caller_role)?;
+        if request.receiving_did == caller_did {
+            return Err(anyhow!("You cannot refer a patient to yourself"));
+        }
+        if request.patient_did == caller_did || request.patient_did == request.receiving_did {
+            return Err(anyhow!("The patient cannot be a practitioner on their own referral"));
+        }
+        let reason = request.reason.trim();
+        if reason.is_empty() {
+            return Err(anyhow!("A reason for the referral is required"));
+        }
+        if request.resources.is_empty() {
+            return Err(anyhow!("Attach at least one record to the referral"));
+        }
+        for reference in &request.resources {
+            let supported = reference
+                .split_once('/')
+                .is_some_and(|(resource_type, id)| !id.is_empty() && Permission::viewing(resource_type).is_some());
+            if !supported {
+                return Err(anyhow!("Unsupported resource reference: {} (expected one of {} followed by /<id>)", reference, CONSENT_DATA_CLASSES.join(", ")));
+            }
+        }
+        let now = Utc::now();
+        let mut referral = Referral {
+            id: None,
+            patient_did: request.patient_did,
+            referring_did: caller_did.to_string(),
+            receiving_did: request.receiving_did,
+            reason: reason.to_string(),
+            priority: request.priority,
+            status: ReferralStatus::Requested,
+            resources: request.resources,
+            access_control_id: None,
+            note: None,
+            created_at: now,
+            updated_at: now,
+        };
+        // Nobody can pass on more of the record than they can see
+        for resource_type in referral.resource_types() {
+            if self.patient_service.check_access(caller_did, &referral.patient_did, resource_type).await?.is_none() {
+                return Err(ServiceError::Forbidden(format!("You do not have access to this patient's {} records", resource_type)).into());
+            }
+        }
+        if self.practitioners.get_practitioner_by_did(&referral.receiving_did).await?.is_none() {
+            return Err(anyhow!("Receiving practitioner not found"));
+        }
+        referral.id = Some(self.db.create_referral(&referral).await?);
+
+        self.record_transition(&referral, caller_did, "create_referral", json!({ "receiving": referral.receiving_did })).await;
+        Ok(referral)
+    }
+
+    /// The caller's received (inbox) or sent (outbox) referrals, newest first
+    pub async fn list(&self, caller_did: &str, caller_role: Role, folder: ReferralFolder, status: Option<ReferralStatus>) -> Result<Vec<Referral>> {
+        ensure_practitioner(caller_role)?;
+        self.db.list_referrals(folder, caller_did, status).await
+    }
+
+    /// Accept a requested referral, giving the caller read access to the attached records' types
+    /// for `referral_access_days`
+    pub async fn accept(&self, caller_did: &str, id: &str) -> Result<Referral> {
+        let mut referral = self.transition(caller_did, id, ReferralStatus::Requested, ReferralStatus::Accepted, None).await?;
+
+        let mut permissions: Vec<Permission> = Vec::new();
+        for permission in referral.resource_types().into_iter().filter_map(Permission::viewing) {
+            if !permissions.contains(&permission) {
+                permissions.push(permission);
+            }
+        }
+        let now = Utc::now();
+        let access_control = self
+            .patient_service
+            .create_grant(AccessControl {
+                id: None,
+                patient_did: referral.patient_did.clone(),
+                grantee_did: referral.receiving_did.clone(),
+                permissions,
+                active: true,
+                created_at: now,
+                expires_at: Some(now + Duration::days(self.config.referral_access_days)),
+                emergency: false,
+                hedera_transaction_id: None,
+            })
+            .await?;
+        let access_control_id = access_control.id.expect("created grants have an id");
+        self.db.link_referral_grant(referral_id(&referral), access_control_id).await?;
+        referral.access_control_id = Some(access_control_id);
+
+        self.record_transition(
+            &referral,
+            caller_did,
+            "accept_referral",
+            json!({ "access_control_id": access_control_id, "expires_at": access_control.expires_at }),
+        )
+        .await;
+        Ok(referral)
+    }
+
+    pub async fn reject(&self, caller_did: &str, id: &str, request: UpdateReferralRequest) -> Result<Referral> {
+        let referral = self.transition(caller_did, id, ReferralStatus::Requested, ReferralStatus::Rejected, note(request)).await?;
+        self.record_transition(&referral, caller_did, "reject_referral", json!({})).await;
+        Ok(referral)
+    }
+
+    /// Close an accepted referral; the access it granted ends with it
+    pub async fn complete(&self, caller_did: &str, id: &str, request: UpdateReferralRequest) -> Result<Referral> {
+        let referral = self.transition(caller_did, id, ReferralStatus::Accepted, ReferralStatus::Completed, note(request)).await?;
+        let grant_revoked = match referral.access_control_id {
+            Some(access_control_id) => self.patient_service.deactivate_grant(access_control_id).await?,
+            None => false,
+        };
+        self.record_transition(&referral, caller_did, "complete_referral", json!({ "grant_revoked": grant_revoked })).await;
+        Ok(referral)
+    }
+
+    /// Only the receiving practitioner moves a referral on, and only from `from`
+    async fn transition(&self, caller_did: &str, id: &str, from: ReferralStatus, to: ReferralStatus, note: Option<String>) -> Result<Referral> {
+        let id = ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid referral id"))?;
+        let referral = self.db.get_referral(id).await?.ok_or_else(|| anyhow!("Referral not found"))?;
+        if referral.receiving_did != caller_did {
+            return Err(ServiceError::Forbidden("Only the receiving practitioner can update this referral".to_string()).into());
+        }
+        self.db
+            .transition_referral(id, from, to, note)
+            .await?
+            .ok_or_else(|| ServiceError::Conflict(format!("Referral is no longer {}", from.as_str())).into())
+    }
+
+    /// Audit the change against the patient's record and tell everyone on the referral except whoever made it
+    async fn record_transition(&self, referral: &Referral, actor_did: &str, action: &str, mut details: Value) {
+        details["actor"] = json!(actor_did);
+        details["referral_id"] = json!(referral.id);
+        details["status"] = json!(referral.status);
+        self.audit_log_service.log(&referral.patient_did, action, Some(details)).await;
+
+        for recipient_did in [&referral.patient_did, &referral.referring_did, &referral.receiving_did] {
+            if recipient_did != actor_did {
+                self.notification_service.notify(NotificationEvent::ReferralUpdated {
+                    recipient_did: recipient_did.clone(),
+                    referral_id: referral_id(referral).to_hex(),
+                    status: referral.status,
+                });
+            }
+        }
+    }
+}
+
+fn ensure_practitioner(caller_role: Role) -> Result<()> {
+    if caller_role != Role::Practitioner {
+        return Err(ServiceError::Forbidden("Only practitioners can make and receive referrals".to_string()).into());
+    }
+    Ok(())
+}
+
+fn note(request: UpdateReferralRequest) -> Option<String> {
+    request.note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty())
+}
+
+// Referrals are only handed around after they were stored or read back
+fn referral_id(referral: &Referral) -> ObjectId {
+    referral.id.expect("stored referrals have an id")
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::events::EventBus;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::services::notification::MockNotificationSender;
+    use crate::services::{ConsentService, RelationshipService};
+    use crate::store::{
+        MockAuditStore, MockConsentStore, MockEncounterStore, MockNotificationStore, MockPatientStore, MockPractitionerStore, MockReferralStore,
+        MockRelationshipStore, PatientStore,
+    };
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const GP: &str = "did:hedera:testnet:0.0.2";
+    const SPECIALIST: &str = "did:hedera:testnet:0.0.3";
+
+    fn service(referrals: MockReferralStore, mut patients: MockPatientStore, consents: MockConsentStore, audit_store: MockAuditStore) -> ReferralService {
+        // Nobody notified is registered, so deliveries stop at the lookups
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(None));
+        let patients: Arc<dyn PatientStore> = Arc::new(patients);
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let practitioners: Arc<dyn PractitionerStore> = Arc::new(practitioners);
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_get_relationship().returning(|_, _| Ok(None));
+        let config = Arc::new(Config { referral_access_days: 14, ipfs_encryption_key: "00".repeat(32).into(), ..Default::default() });
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(MockNotificationStore::new()),
+            patients.clone(),
+            practitioners.clone(),
+            Arc::new(MockNotificationSender::new()),
+            config.clone(),
+        ));
+        let consent_service = Arc::new(ConsentService::new(
+            Arc::new(consents),
+            patients.clone(),
+            Arc::new(InMemoryObjectStorage::new()),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let relationship_service = Arc::new(RelationshipService::new(
+            Arc::new(relationships),
+            patients.clone(),
+            practitioners.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let patient_service = Arc::new(PatientService::new(
+            patients,
+            Arc::new(MockEncounterStore::new()),
+            config.clone(),
+            audit_log_service.clone(),
+            notification_service.clone(),
+            consent_service,
+            relationship_service,
+            Arc::new(EventBus::new(16)),
+        ));
+        ReferralService::new(Arc::new(referrals), practitioners, patient_service, notification_service, config, audit_log_service)
+    }
+
+    fn referral(id: ObjectId, status: ReferralStatus) -> Referral {
+        Referral {
+            id: Some(id),
+            patient_did: PATIENT.to_string(),
+            referring_did: GP.to_string(),
+            receiving_did: SPECIALIST.to_string(),
+            reason: "Suspected arrhythmia".to_string(),
+            priority: ReferralPriority::Urgent,
+            status,
+            resources: vec!["Encounter/e1".to_string(), "Condition/c1".to_string(), "Observation/o1".to_string()],
+            access_control_id: (status != ReferralStatus::Requested).then(ObjectId::new),
+            note: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// A store holding one referral that moves to whatever status it is asked to
+    fn stored(id: ObjectId, status: ReferralStatus) -> MockReferralStore {
+        let mut referrals = MockReferralStore::new();
+        referrals.expect_get_referral().returning(move |_| Ok(Some(referral(id, status))));
+        referrals.expect_transition_referral().returning(move |_, from, to, note| {
+            Ok((from == status).then(|| Referral { note, ..referral(id, to) }))
+        });
+        referrals
+    }
+
+    fn audited(action: &'static str) -> MockAuditStore {
+        let mut audit_store = MockAuditStore::new();
+        audit_store
+            .expect_create_audit_log()
+            .withf(move |log| log.did == PATIENT && log.action == action && log.details.as_ref().is_some_and(|details| details["actor"] == SPECIALIST))
+            .times(1)
+            .returning(|_| Ok(()));
+        audit_store
+    }
+
+    #[tokio::test]
+    async fn accepting_grants_read_access_to_only_the_attached_types_for_a_limited_time() {
+        let id = ObjectId::new();
+        let mut referrals = stored(id, ReferralStatus::Requested);
+        referrals.expect_link_referral_grant().withf(move |referral_id, _| *referral_id == id).times(1).returning(|_, _| Ok(()));
+        let mut patients = MockPatientStore::new();
+        patients
+            .expect_grant_access()
+            .withf(|grant, outbox| {
+                grant.grantee_did == SPECIALIST
+                    && grant.permissions == [Permission::ViewEncounters, Permission::ViewObservations]
+                    && grant.expires_at.is_some_and(|expires| expires - grant.created_at == Duration::days(14))
+                    && !grant.emergency
+                    && matches!(outbox, [OutboxAction::AnchorAccessGrant { grant_id, grantee_did, .. }] if Some(*grant_id) == grant.id && grantee_did == SPECIALIST)
+            })
+            .times(1)
+            .returning(|grant, _| Ok(grant.id.unwrap()));
+        let mut consents = MockConsentStore::new();
+        consents
+            .expect_create_consent()
+            .withf(|consent| consent.data_classes == ["Encounter", "Condition", "Observation"])
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+
+        let mut audit_store = audited("accept_referral");
+        audit_store.expect_create_audit_log().withf(|log| log.action == "create_consent").returning(|_| Ok(()));
+
+        let accepted = service(referrals, patients, consents, audit_store).accept(SPECIALIST, &id.to_hex()).await.unwrap();
+
+        assert_eq!(accepted.status, ReferralStatus::Accepted);
+        assert!(accepted.access_control_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn only_the_receiving_practitioner_moves_a_referral_on() {
+        let id = ObjectId::new();
+        let mut referrals = MockReferralStore::new();
+        referrals.expect_get_referral().returning(move |_| Ok(Some(referral(id, ReferralStatus::Requested))));
+        referrals.expect_transition_referral().never();
+        let mut patients = MockPatientStore::new();
+        patients.expect_grant_access().never();
+
+        let err = service(referrals, patients, MockConsentStore::new(), MockAuditStore::new()).accept(GP, &id.to_hex()).await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn a_rejected_referral_cannot_be_accepted() {
+        let id = ObjectId::new();
+        let mut patients = MockPatientStore::new();
+        patients.expect_grant_access().never();
+
+        let err = service(stored(id, ReferralStatus::Rejected), patients, MockConsentStore::new(), MockAuditStore::new())
+            .accept(SPECIALIST, &id.to_hex())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn completing_ends_the_referral_grant() {
+        let id = ObjectId::new();
+        let mut patients = MockPatientStore::new();
+        patients.expect_deactivate_grant().times(1).returning(|_| Ok(true));
+
+        let completed = service(stored(id, ReferralStatus::Accepted), patients, MockConsentStore::new(), audited("complete_referral"))
+            .complete(SPECIALIST, &id.to_hex(), UpdateReferralRequest { note: Some("  Started on beta blockers ".to_string()) })
+            .await
+            .unwrap();
+
+        assert_eq!(completed.status, ReferralStatus::Completed);
+        assert_eq!(completed.note.as_deref(), Some("Started on beta blockers"));
+    }
+
+    #[tokio::test]
+    async fn referrers_cannot_share_records_they_cannot_see() {
+        let mut referrals = MockReferralStore::new();
+        referrals.expect_create_referral().never();
+        let mut patients = MockPatientStore::new();
+        patients.expect_active_grants().returning(|_, _| Ok(vec![]));
+        let request = CreateReferralRequest {
+            patient_did: PATIENT.to_string(),
+            receiving_did: SPECIALIST.to_string(),
+            reason: "Suspected arrhythmia".to_string(),
+            priority: ReferralPriority::Urgent,
+            resources: vec!["Encounter/e1".to_string()],
+        };
+        let service = service(referrals, patients, MockConsentStore::new(), MockAuditStore::new());
+
+        let err = service.create(GP, Role::Practitioner, request.clone()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+
+        let unsupported = CreateReferralRequest { resources: vec!["Genome/g1".to_string()], ..request };
+        let err = service.create(GP, Role::Practitioner, unsupported).await.unwrap_err();
+        assert!(err.to_string().starts_with("Unsupported resource reference: Genome/g1"));
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Months, NaiveDate, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::database::DatabaseError;
+use crate::models::*;
+use crate::services::ServiceError;
+use crate::store::{PatientStore, PractitionerStore, RelationshipStore};
+
+const AGE_OF_MAJORITY: Months = Months::new(18 * 12);
+
+// --- RelationshipService ---
+pub struct RelationshipService {
+    db: Arc<dyn RelationshipStore>,
+    patients: Arc<dyn PatientStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl RelationshipService {
+    pub fn new(
+        db: Arc<dyn RelationshipStore>,
+        patients: Arc<dyn PatientStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, patients, practitioners, config, audit_log_service }
+    }
+
+    /// Link a guardian to a dependent; only admins and verified practitioners may.
+    /// Parents and legal guardians lose access at the dependent's 18th birthday unless an earlier expiry is given.
+    pub async fn establish(&self, caller_did: &str, caller_role: Role, request: CreateRelationshipRequest) -> anyhow::Result<Relationship> {
+        self.ensure_can_establish(caller_did, caller_role).await?;
+        if request.guardian_did == request.dependent_did {
+            return Err(anyhow!("A patient cannot be their own guardian"));
+        }
+        let dependent = self
+            .patients
+            .get_patient_by_did(&request.dependent_did, &self.config.ipfs_encryption_key)
+            .await?
+            .ok_or_else(|| anyhow!("Dependent not found"))?;
+
+        let now = Utc::now();
+        let expires_at = if request.relationship.ends_at_majority() {
+            let majority = majority(&dependent.fhir_patient.birth_date)
+                .ok_or_else(|| anyhow!("The dependent's birth date is needed to work out when guardianship ends"))?;
+            if majority <= now {
+                return Err(anyhow!("The dependent is already an adult; link them with a delegate instead"));
+            }
+            Some(request.expires_at.map_or(majority, |requested| requested.min(majority)))
+        } else {
+            request.expires_at
+        };
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(anyhow!("The relationship must expire in the future"));
+        }
+
+        let mut relationship = Relationship {
+            id: None,
+            guardian_did: request.guardian_did,
+            dependent_did: request.dependent_did,
+            relationship: request.relationship,
+            established_by: caller_did.to_string(),
+            created_at: now,
+            expires_at,
+            active: true,
+        };
+        match self.db.create_relationship(&relationship).await {
+            Ok(id) => relationship.id = Some(id),
+            Err(e) if matches!(e.downcast_ref::<DatabaseError>(), Some(DatabaseError::DuplicateKey(_))) => {
+                return Err(ServiceError::Conflict("The guardian is already linked to this dependent".to_string()).into());
+            }
+            Err(e) => return Err(e),
+        }
+        self.audit_log_service
+            .log(
+                &relationship.dependent_did,
+                "establish_relationship",
+                Some(json!({
+                    "actor": caller_did,
+                    "guardian": relationship.guardian_did,
+                    "relationship": relationship.relationship,
+                    "expires_at": relationship.expires_at,
+                })),
+            )
+            .await;
+        Ok(relationship)
+    }
+
+    /// The link that lets `guardian_did` act for `dependent_did` right now, if any
+    pub async fn guardianship(&self, guardian_did: &str, dependent_did: &str) -> anyhow::Result<Option<Relationship>> {
+        let relationship = self.db.get_relationship(guardian_did, dependent_did).await?;
+        Ok(relationship.filter(|relationship| relationship.is_current(Utc::now())))
+    }
+
+    /// The caller's own links, as guardian or as dependent
+    pub async fn list_relationships(&self, caller_did: &str, did: &str) -> anyhow::Result<Vec<Relationship>> {
+        if caller_did != did {
+            return Err(ServiceError::Forbidden("Patients can only list their own relationships".to_string()).into());
+        }
+        self.db.list_relationships(did).await
+    }
+
+    async fn ensure_can_establish(&self, caller_did: &str, caller_role: Role) -> anyhow::Result<()> {
+        let allowed = match caller_role {
+            Role::Admin | Role::PlatformAdmin => true,
+            Role::Practitioner => self
+                .practitioners
+                .get_practitioner_by_did(caller_did)
+                .await?
+                .is_some_and(|practitioner| practitioner.license_verification.verified),
+            Role::Patient => false,
+        };
+        if !allowed {
+            return Err(ServiceError::Forbidden("Only admins and verified practitioners can link guardians".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Midnight UTC on the 18th birthday of someone born on `birth_date` (`YYYY-MM-DD`).
+/// A 29 February birthday comes of age on 28 February.
+fn majority(birth_date: &str) -> Option<DateTime<Utc>> {
+    let birth_date = NaiveDate::parse_from_str(birth_date.trim(), "%Y-%m-%d").ok()?;
+    Some(birth_date.checked_add_months(AGE_OF_MAJORITY)?.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::store::{MockAuditStore, MockPatientStore, MockPractitionerStore, MockRelationshipStore};
+    use bson::oid::ObjectId;
+    use chrono::{Datelike, Duration, TimeZone};
+
+    const GUARDIAN: &str = "did:hedera:testnet:0.0.1";
+    const DEPENDENT: &str = "did:hedera:testnet:0.0.2";
+    const ADMIN: &str = "did:hedera:testnet:0.0.3";
+
+    fn service(relationships: MockRelationshipStore, patients: MockPatientStore, practitioners: MockPractitionerStore) -> RelationshipService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        RelationshipService::new(
+            Arc::new(relationships),
+            Arc::new(patients),
+            Arc::new(practitioners),
+            Arc::new(Config::default()),
+            Arc::new(AuditLogService::new(Arc::new(audit_store))),
+        )
+    }
+
+    fn dependent_born(birth_date: String) -> MockPatientStore {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(move |did, _| {
+            Ok(Some(fixtures::patient(did, FhirPatient { birth_date: birth_date.clone(), ..Default::default() })))
+        });
+        patients
+    }
+
+    fn request(relationship: RelationshipType) -> CreateRelationshipRequest {
+        CreateRelationshipRequest {
+            guardian_did: GUARDIAN.to_string(),
+            dependent_did: DEPENDENT.to_string(),
+            relationship,
+            expires_at: None,
+        }
+    }
+
+    fn link(expires_at: Option<DateTime<Utc>>) -> Relationship {
+        Relationship {
+            id: Some(ObjectId::new()),
+            guardian_did: GUARDIAN.to_string(),
+            dependent_did: DEPENDENT.to_string(),
+            relationship: RelationshipType::Parent,
+            established_by: ADMIN.to_string(),
+            created_at: Utc::now() - Duration::days(365),
+            expires_at,
+            active: true,
+        }
+    }
+
+    #[test]
+    fn majority_is_the_eighteenth_birthday() {
+        assert_eq!(majority("2010-06-15"), Some(Utc.with_ymd_and_hms(2028, 6, 15, 0, 0, 0).unwrap()));
+        assert_eq!(majority("2008-02-29"), Some(Utc.with_ymd_and_hms(2026, 2, 28, 0, 0, 0).unwrap()));
+        assert_eq!(majority("2010"), None);
+        assert_eq!(majority(""), None);
+    }
+
+    #[tokio::test]
+    async fn parent_links_expire_at_the_dependents_eighteenth_birthday() {
+        let born = Utc::now().date_naive() - Months::new(10 * 12);
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_create_relationship().times(1).returning(|_| Ok(ObjectId::new()));
+
+        let relationship = service(relationships, dependent_born(born.to_string()), MockPractitionerStore::new())
+            .establish(ADMIN, Role::Admin, request(RelationshipType::Parent))
+            .await
+            .unwrap();
+
+        let expires_at = relationship.expires_at.unwrap();
+        assert_eq!(expires_at.date_naive().year(), born.year() + 18);
+        assert_eq!((expires_at.month(), expires_at.day()), (born.month(), born.day()));
+    }
+
+    #[tokio::test]
+    async fn adults_can_only_be_linked_to_a_delegate() {
+        let born = (Utc::now().date_naive() - Months::new(30 * 12)).to_string();
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_create_relationship().times(1).returning(|_| Ok(ObjectId::new()));
+        let service = service(relationships, dependent_born(born), MockPractitionerStore::new());
+
+        let err = service.establish(ADMIN, Role::Admin, request(RelationshipType::LegalGuardian)).await.unwrap_err();
+        assert_eq!(err.to_string(), "The dependent is already an adult; link them with a delegate instead");
+
+        let delegate = service.establish(ADMIN, Role::Admin, request(RelationshipType::Delegate)).await.unwrap();
+        assert_eq!(delegate.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn only_admins_and_verified_practitioners_can_link_guardians() {
+        let mut relationships = MockRelationshipStore::new();
+        relationships.expect_create_relationship().never();
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let service = service(relationships, MockPatientStore::new(), practitioners);
+
+        for role in [Role::Patient, Role::Practitioner] {
+            let err = service.establish(GUARDIAN, role, request(RelationshipType::Delegate)).await.unwrap_err();
+            assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_links_no_longer_grant_guardianship() {
+        let mut expired = MockRelationshipStore::new();
+        expired.expect_get_relationship().returning(|_, _| Ok(Some(link(Some(Utc::now() - Duration::minutes(1))))));
+        let mut current = MockRelationshipStore::new();
+        current.expect_get_relationship().returning(|_, _| Ok(Some(link(Some(Utc::now() + Duration::days(1))))));
+
+        let expired = service(expired, MockPatientStore::new(), MockPractitionerStore::new());
+        let current = service(current, MockPatientStore::new(), MockPractitionerStore::new());
+
+        assert!(expired.guardianship(GUARDIAN, DEPENDENT).await.unwrap().is_none());
+        assert!(current.guardianship(GUARDIAN, DEPENDENT).await.unwrap().is_some());
+    }
+}
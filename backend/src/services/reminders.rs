@@ -0,0 +1,296 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::store::{AppointmentStore, PatientStore, PractitionerStore};
+use crate::utils;
+
+/// Recurring job that sends the reminders currently due
+pub const APPOINTMENT_REMINDERS_JOB: &str = "appointment_reminders";
+const SWEEP_INTERVAL_MINUTES: i64 = 5;
+const STAGES: [ReminderStage; 2] = [ReminderStage::DayBefore, ReminderStage::TwoHoursBefore];
+
+/// Reminder deliveries per channel, counted since the process started
+#[derive(Debug, Default)]
+pub struct ReminderMetrics {
+    email_sent: AtomicU64,
+    email_failed: AtomicU64,
+    sms_sent: AtomicU64,
+    sms_failed: AtomicU64,
+    push_sent: AtomicU64,
+    push_failed: AtomicU64,
+}
+
+impl ReminderMetrics {
+    fn record(&self, notification: &Notification) {
+        let counter = match (notification.channel, notification.status) {
+            (NotificationChannel::Email, NotificationStatus::Sent) => &self.email_sent,
+            (NotificationChannel::Email, _) => &self.email_failed,
+            (NotificationChannel::Sms, NotificationStatus::Sent) => &self.sms_sent,
+            (NotificationChannel::Sms, _) => &self.sms_failed,
+            (NotificationChannel::Push, NotificationStatus::Sent) => &self.push_sent,
+            (NotificationChannel::Push, _) => &self.push_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ReminderMetricsSnapshot {
+        ReminderMetricsSnapshot {
+            email_sent: self.email_sent.load(Ordering::Relaxed),
+            email_failed: self.email_failed.load(Ordering::Relaxed),
+            sms_sent: self.sms_sent.load(Ordering::Relaxed),
+            sms_failed: self.sms_failed.load(Ordering::Relaxed),
+            push_sent: self.push_sent.load(Ordering::Relaxed),
+            push_failed: self.push_failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Sends reminders for confirmed appointments a day and two hours before they start
+pub struct AppointmentReminderHandler {
+    appointments: Arc<dyn AppointmentStore>,
+    patients: Arc<dyn PatientStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    notification_service: Arc<NotificationService>,
+    metrics: Arc<ReminderMetrics>,
+}
+
+impl AppointmentReminderHandler {
+    pub fn new(
+        appointments: Arc<dyn AppointmentStore>,
+        patients: Arc<dyn PatientStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        notification_service: Arc<NotificationService>,
+        metrics: Arc<ReminderMetrics>,
+    ) -> Self {
+        Self { appointments, patients, practitioners, notification_service, metrics }
+    }
+
+    /// Remind every appointment currently due, returning how many were claimed.
+    ///
+    /// Each appointment is claimed before anything is sent, so a failed delivery is
+    /// recorded (in `notifications` and the metrics) rather than retried.
+    pub async fn sweep(&self, now: DateTime<Utc>) -> Result<usize> {
+        let mut claimed = 0;
+        for stage in STAGES {
+            while let Some(appointment) = self.appointments.claim_appointment_reminder(stage, now).await? {
+                claimed += 1;
+                if let Err(e) = self.remind(&appointment).await {
+                    tracing::error!("Failed to send {:?} reminder to {}: {}", stage, appointment.patient_did, e);
+                }
+            }
+        }
+        Ok(claimed)
+    }
+
+    async fn remind(&self, appointment: &Appointment) -> Result<()> {
+        let timezone = self.patients.get_patient_timezone(&appointment.patient_did).await?;
+        let practitioner = self.practitioners.get_practitioner_by_did(&appointment.practitioner_did).await?;
+        let event = NotificationEvent::AppointmentReminder {
+            patient_did: appointment.patient_did.clone(),
+            practitioner_name: practitioner.as_ref().map(practitioner_name).unwrap_or_else(|| "your practitioner".to_string()),
+            when: local_time(appointment.start, timezone.as_deref()),
+            location: appointment.location.clone(),
+        };
+        for notification in self.notification_service.deliver(&event).await? {
+            self.metrics.record(&notification);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobHandler for AppointmentReminderHandler {
+    fn job_type(&self) -> &'static str {
+        APPOINTMENT_REMINDERS_JOB
+    }
+
+    fn repeat_every(&self) -> Option<Duration> {
+        Some(Duration::minutes(SWEEP_INTERVAL_MINUTES))
+    }
+
+    async fn run(&self, _job: &Job) -> Result<(), JobError> {
+        self.sweep(Utc::now()).await?;
+        Ok(())
+    }
+}
+
+/// `start` in the patient's time zone, e.g. "Mon 19 Oct 2026, 09:00 EAT". An unset or
+/// unknown zone falls back to UTC, which the abbreviation makes explicit.
+pub fn local_time(start: DateTime<Utc>, timezone: Option<&str>) -> String {
+    start.with_timezone(&utils::patient_timezone(timezone)).format("%a %-d %b %Y, %H:%M %Z").to_string()
+}
+
+/// "Dr. Jane Otieno", from the first name on the practitioner's FHIR resource
+fn practitioner_name(practitioner: &Practitioner) -> String {
+    let Some(name) = practitioner.fhir_practitioner.name.first() else {
+        return "your practitioner".to_string();
+    };
+    name.prefix
+        .iter()
+        .chain(&name.given)
+        .chain(&name.family)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::fixtures;
+    use crate::services::notification::MockNotificationSender;
+    use crate::store::{MockAppointmentStore, MockNotificationStore, MockPatientStore, MockPractitionerStore};
+    use bson::oid::ObjectId;
+    use chrono::TimeZone;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const PRACTITIONER: &str = "did:hedera:testnet:0.0.2";
+
+    fn appointment(start: DateTime<Utc>) -> Appointment {
+        Appointment {
+            id: Some(ObjectId::new()),
+            patient_did: PATIENT.to_string(),
+            practitioner_did: PRACTITIONER.to_string(),
+            status: AppointmentStatus::Confirmed,
+            start,
+            end: start + Duration::minutes(30),
+            reason: FhirCodeableConcept { coding: vec![], text: Some("Follow-up".to_string()) },
+            location: "Room 4".to_string(),
+            encounter_id: None,
+            reminder_sent_at: ReminderMarkers::default(),
+            slots: vec![start],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    fn practitioner() -> Practitioner {
+        Practitioner {
+            id: None,
+            did: PRACTITIONER.to_string(),
+            fhir_practitioner: FhirPractitioner {
+                resource_type: "Practitioner".to_string(),
+                id: "p1".to_string(),
+                identifier: vec![],
+                name: vec![FhirHumanName {
+                    family: Some("Otieno".to_string()),
+                    given: vec!["Jane".to_string()],
+                    prefix: vec!["Dr.".to_string()],
+                    ..Default::default()
+                }],
+                qualification: vec![],
+                telecom: vec![],
+            },
+            license_verification: LicenseVerification {
+                license_number: String::new(),
+                issuing_authority: String::new(),
+                issue_date: String::new(),
+                expiry_date: String::new(),
+                hedera_transaction_id: String::new(),
+                ipfs_hash: String::new(),
+                verified: true,
+                expiry_warning_sent_at: None,
+            },
+            practitioner_type: PractitionerType::Clinician,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    fn patients() -> MockPatientStore {
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_timezone().returning(|_| Ok(Some("Africa/Nairobi".to_string())));
+        patients.expect_get_notification_preferences().returning(|_| Ok(None));
+        patients.expect_get_patient_by_did().returning(|did, _| {
+            let telecom = vec![
+                FhirContactPoint { system: "email".to_string(), value: "amina@example.com".to_string(), r#use: None },
+                FhirContactPoint { system: "phone".to_string(), value: "+254700000001".to_string(), r#use: None },
+            ];
+            Ok(Some(fixtures::patient(did, FhirPatient { telecom, ..Default::default() })))
+        });
+        patients
+    }
+
+    fn handler(appointments: MockAppointmentStore, sender: MockNotificationSender, metrics: Arc<ReminderMetrics>) -> AppointmentReminderHandler {
+        let patients: Arc<dyn PatientStore> = Arc::new(patients());
+        let mut store = MockNotificationStore::new();
+        store.expect_create_notification().returning(|_| Ok(ObjectId::new()));
+        store.expect_update_notification_status().returning(|_, _, _| Ok(()));
+        store.expect_list_devices().returning(|_| Ok(vec![]));
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(Some(practitioner())));
+        let practitioners: Arc<dyn PractitionerStore> = Arc::new(practitioners);
+        let notification_service = Arc::new(NotificationService::new(
+            Arc::new(store),
+            patients.clone(),
+            practitioners.clone(),
+            Arc::new(sender),
+            Arc::new(Config::default()),
+        ));
+        AppointmentReminderHandler::new(Arc::new(appointments), patients, practitioners, notification_service, metrics)
+    }
+
+    #[test]
+    fn local_time_uses_the_patients_zone_and_falls_back_to_utc() {
+        let start = Utc.with_ymd_and_hms(2026, 10, 19, 6, 0, 0).unwrap();
+
+        assert_eq!(local_time(start, Some("Africa/Nairobi")), "Mon 19 Oct 2026, 09:00 EAT");
+        assert_eq!(local_time(start, Some("America/New_York")), "Mon 19 Oct 2026, 02:00 EDT");
+        assert_eq!(local_time(start, Some("Mars/Olympus")), "Mon 19 Oct 2026, 06:00 UTC");
+        assert_eq!(local_time(start, None), "Mon 19 Oct 2026, 06:00 UTC");
+    }
+
+    #[test]
+    fn reminder_windows_do_not_overlap() {
+        assert_eq!(ReminderStage::DayBefore.window(), (Duration::hours(2), Duration::hours(24)));
+        assert_eq!(ReminderStage::TwoHoursBefore.window(), (Duration::zero(), Duration::hours(2)));
+    }
+
+    #[tokio::test]
+    async fn due_reminders_are_sent_on_every_allowed_channel_and_counted() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 6, 0, 0).unwrap();
+        let mut appointments = MockAppointmentStore::new();
+        let mut day_before = Some(appointment(now + Duration::hours(24)));
+        appointments
+            .expect_claim_appointment_reminder()
+            .withf(|stage, _| *stage == ReminderStage::DayBefore)
+            .returning(move |_, _| Ok(day_before.take()));
+        appointments
+            .expect_claim_appointment_reminder()
+            .withf(|stage, _| *stage == ReminderStage::TwoHoursBefore)
+            .returning(|_, _| Ok(None));
+        let mut sender = MockNotificationSender::new();
+        sender
+            .expect_send_email()
+            .withf(|_, _, template, context| {
+                template == "Appointment-reminder.html"
+                    && context["practitioner_name"] == "Dr. Jane Otieno"
+                    && context["when"] == "Mon 19 Oct 2026, 09:00 EAT"
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        sender
+            .expect_send_sms()
+            .withf(|_, body| body.contains("Dr. Jane Otieno on Mon 19 Oct 2026, 09:00 EAT") && !body.contains("Follow-up"))
+            .times(1)
+            .returning(|_, _| Err(anyhow::anyhow!("SMS is not configured on this server")));
+        let metrics = Arc::new(ReminderMetrics::default());
+
+        let claimed = handler(appointments, sender, metrics.clone()).sweep(now).await.unwrap();
+
+        assert_eq!(claimed, 1);
+        assert_eq!(
+            metrics.snapshot(),
+            ReminderMetricsSnapshot { email_sent: 1, email_failed: 0, sms_sent: 0, sms_failed: 1, ..Default::default() }
+        );
+    }
+}
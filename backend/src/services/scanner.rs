@@ -0,0 +1,147 @@
+//! Malware scanning of uploads before they are encrypted and stored.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Bytes sent per INSTREAM chunk; clamd's default StreamMaxLength still caps the whole file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// With the scanner's name for what it found
+    Infected(String),
+}
+
+/// Checks file content for malware.
+///
+/// An error means the scan could not be done; whether the upload goes ahead then is up to the caller.
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(&self, content: &[u8]) -> Result<ScanVerdict>;
+}
+
+/// Passes everything; used when no scanner is configured
+pub struct NoopScanner;
+
+#[async_trait]
+impl Scanner for NoopScanner {
+    async fn scan(&self, _content: &[u8]) -> Result<ScanVerdict> {
+        Ok(ScanVerdict::Clean)
+    }
+}
+
+/// ClamAV's clamd over TCP, using the INSTREAM command
+pub struct ClamdScanner {
+    address: String,
+}
+
+impl ClamdScanner {
+    /// `address` is `host:port`, e.g. `localhost:3310`
+    pub fn new(address: &str) -> Self {
+        Self { address: address.to_string() }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    async fn scan(&self, content: &[u8]) -> Result<ScanVerdict> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        // The `z` prefix asks for a NUL-terminated reply
+        stream.write_all(b"zINSTREAM\0").await?;
+        for chunk in content.chunks(CHUNK_SIZE) {
+            stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+            stream.write_all(chunk).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+        stream.flush().await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        parse_reply(&reply)
+    }
+}
+
+/// `stream: OK`, `stream: <signature> FOUND` or `<message> ERROR`
+fn parse_reply(reply: &[u8]) -> Result<ScanVerdict> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+    let result = reply.strip_prefix("stream:").map(str::trim).ok_or_else(|| anyhow!("clamd replied '{}'", reply))?;
+    if result == "OK" {
+        return Ok(ScanVerdict::Clean);
+    }
+    match result.strip_suffix(" FOUND") {
+        Some(signature) => Ok(ScanVerdict::Infected(signature.trim().to_string())),
+        None => Err(anyhow!("clamd could not scan the file: {}", result)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    /// Answers one INSTREAM command per connection like clamd does, recording the chunk sizes it
+    /// was sent. Content containing `EICAR` is reported infected, and `reply` overrides the answer.
+    async fn stub_clamd(reply: Option<&'static str>) -> (String, Arc<Mutex<Vec<Vec<u32>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut command = [0u8; 10];
+                socket.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+                let (mut sizes, mut content) = (Vec::new(), Vec::new());
+                loop {
+                    let size = socket.read_u32().await.unwrap();
+                    sizes.push(size);
+                    if size == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0u8; size as usize];
+                    socket.read_exact(&mut chunk).await.unwrap();
+                    content.extend_from_slice(&chunk);
+                }
+                recorded.lock().unwrap().push(sizes);
+                let infected = content.windows(5).any(|window| window == b"EICAR");
+                let answer = reply.unwrap_or(if infected { "stream: Eicar-Test-Signature FOUND" } else { "stream: OK" });
+                socket.write_all(format!("{}\0", answer).as_bytes()).await.unwrap();
+            }
+        });
+        (address, received)
+    }
+
+    #[tokio::test]
+    async fn content_is_streamed_in_length_prefixed_chunks_ending_with_an_empty_one() {
+        let (address, received) = stub_clamd(None).await;
+        let scanner = ClamdScanner::new(&address);
+
+        let verdict = scanner.scan(&vec![b'a'; CHUNK_SIZE + 10]).await.unwrap();
+
+        assert_eq!(verdict, ScanVerdict::Clean);
+        assert_eq!(*received.lock().unwrap(), vec![vec![CHUNK_SIZE as u32, 10, 0]]);
+    }
+
+    #[tokio::test]
+    async fn detections_come_back_with_their_signature_name() {
+        let (address, _) = stub_clamd(None).await;
+
+        let verdict = ClamdScanner::new(&address).scan(b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!").await.unwrap();
+
+        assert_eq!(verdict, ScanVerdict::Infected("Eicar-Test-Signature".to_string()));
+    }
+
+    #[tokio::test]
+    async fn scanner_errors_and_unreachable_scanners_are_errors() {
+        let (address, _) = stub_clamd(Some("INSTREAM size limit exceeded. ERROR")).await;
+        assert!(ClamdScanner::new(&address).scan(b"%PDF-1.7").await.is_err());
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        assert!(ClamdScanner::new(&closed).scan(b"%PDF-1.7").await.is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::models::{Appointment, AppointmentStatus, Availability, Slot, TimeWindow};
+
+/// Every `granularity`-long slot that fits inside the practitioner's windows and starts in `[from, to)`.
+///
+/// Windows are read in the practitioner's time zone, so a 09:00 start stays 09:00 local
+/// across daylight-saving changes. Local times skipped by a clock change yield no slot.
+pub fn expand(availability: &Availability, from: DateTime<Utc>, to: DateTime<Utc>, granularity: Duration) -> Vec<Slot> {
+    if granularity <= Duration::zero() || to <= from {
+        return Vec::new();
+    }
+    let tz = availability.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    let first_day = from.with_timezone(&tz).date_naive();
+    let last_day = to.with_timezone(&tz).date_naive();
+
+    let mut slots = Vec::new();
+    for day in first_day.iter_days().take_while(|day| *day <= last_day) {
+        for window in windows_on(availability, day) {
+            let end = day.and_time(window.end);
+            let mut start = day.and_time(window.start);
+            while start + granularity <= end {
+                if let Some(local) = tz.from_local_datetime(&start).earliest() {
+                    let start = local.with_timezone(&Utc);
+                    if start >= from && start < to {
+                        slots.push(Slot { start, end: start + granularity });
+                    }
+                }
+                start += granularity;
+            }
+        }
+    }
+    // Overlapping windows on the same day would otherwise list a slot twice
+    slots.sort_by_key(|slot| slot.start);
+    slots.dedup_by_key(|slot| slot.start);
+    slots
+}
+
+/// The windows that apply on `day`: its exception if there is one, otherwise the weekly hours
+fn windows_on(availability: &Availability, day: NaiveDate) -> Vec<TimeWindow> {
+    match availability.exceptions.iter().find(|exception| exception.date == day) {
+        Some(exception) => exception.windows.clone(),
+        None => availability
+            .weekly
+            .iter()
+            .filter(|window| window.weekday == day.weekday())
+            .map(|window| TimeWindow { start: window.start, end: window.end })
+            .collect(),
+    }
+}
+
+/// `slots` without those overlapping a confirmed appointment
+pub fn subtract_booked(slots: Vec<Slot>, appointments: &[Appointment]) -> Vec<Slot> {
+    let confirmed: Vec<_> = appointments.iter().filter(|a| a.status == AppointmentStatus::Confirmed).collect();
+    slots
+        .into_iter()
+        .filter(|slot| !confirmed.iter().any(|a| a.start < slot.end && slot.start < a.end))
+        .collect()
+}
+
+/// Starts of the consecutive slots that exactly cover `[start, end)`, or `None`
+/// if the interval does not line up with free slots
+pub fn covering(slots: &[Slot], start: DateTime<Utc>, end: DateTime<Utc>) -> Option<Vec<DateTime<Utc>>> {
+    let first = slots.iter().position(|slot| slot.start == start)?;
+    let mut covered = Vec::new();
+    let mut reached = start;
+    for slot in &slots[first..] {
+        if slot.start != reached || reached >= end {
+            break;
+        }
+        covered.push(slot.start);
+        reached = slot.end;
+    }
+    (reached == end).then_some(covered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AvailabilityException, FhirCodeableConcept, ReminderMarkers, WeeklyWindow};
+    use chrono::{NaiveTime, Weekday};
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, hour, minute, 0).unwrap()
+    }
+
+    fn availability(timezone: &str, weekly: Vec<WeeklyWindow>, exceptions: Vec<AvailabilityException>) -> Availability {
+        Availability {
+            id: None,
+            practitioner_did: "did:hedera:testnet:0.0.2".to_string(),
+            timezone: timezone.to_string(),
+            weekly,
+            exceptions,
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn weekly(weekday: Weekday, start: NaiveTime, end: NaiveTime) -> WeeklyWindow {
+        WeeklyWindow { weekday, start, end }
+    }
+
+    fn starts(slots: &[Slot]) -> Vec<DateTime<Utc>> {
+        slots.iter().map(|slot| slot.start).collect()
+    }
+
+    fn appointment(start: DateTime<Utc>, end: DateTime<Utc>, status: AppointmentStatus) -> Appointment {
+        Appointment {
+            id: None,
+            patient_did: "did:hedera:testnet:0.0.1".to_string(),
+            practitioner_did: "did:hedera:testnet:0.0.2".to_string(),
+            status,
+            start,
+            end,
+            reason: FhirCodeableConcept { coding: vec![], text: None },
+            location: String::new(),
+            encounter_id: None,
+            reminder_sent_at: ReminderMarkers::default(),
+            slots: vec![],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            tenant_id: None,
+        }
+    }
+
+    #[test]
+    fn weekly_windows_repeat_every_week_in_the_practitioners_zone() {
+        // Mondays 09:00-10:00 in Nairobi (UTC+3); 19 and 26 Oct 2026 are Mondays
+        let schedule = availability("Africa/Nairobi", vec![weekly(Weekday::Mon, time(9, 0), time(10, 0))], vec![]);
+
+        let slots = expand(&schedule, utc(18, 0, 0), utc(27, 0, 0), Duration::minutes(30));
+
+        assert_eq!(starts(&slots), vec![utc(19, 6, 0), utc(19, 6, 30), utc(26, 6, 0), utc(26, 6, 30)]);
+        assert_eq!(slots[0].end, utc(19, 6, 30));
+    }
+
+    #[test]
+    fn slots_that_do_not_fit_the_window_or_range_are_dropped() {
+        let schedule = availability("UTC", vec![weekly(Weekday::Mon, time(9, 0), time(10, 15))], vec![]);
+
+        let slots = expand(&schedule, utc(19, 9, 30), utc(20, 0, 0), Duration::minutes(30));
+
+        // 09:00 starts before the range; 10:00-10:30 runs past the window
+        assert_eq!(starts(&slots), vec![utc(19, 9, 30)]);
+    }
+
+    #[test]
+    fn exceptions_replace_the_weekly_hours_for_their_date() {
+        let schedule = availability(
+            "UTC",
+            vec![weekly(Weekday::Mon, time(9, 0), time(10, 0)), weekly(Weekday::Tue, time(9, 0), time(10, 0))],
+            vec![
+                // Day off on Monday 19th, afternoon only on Tuesday 20th, extra hours on Wednesday 21st
+                AvailabilityException { date: NaiveDate::from_ymd_opt(2026, 10, 19).unwrap(), windows: vec![] },
+                AvailabilityException {
+                    date: NaiveDate::from_ymd_opt(2026, 10, 20).unwrap(),
+                    windows: vec![TimeWindow { start: time(14, 0), end: time(14, 30) }],
+                },
+                AvailabilityException {
+                    date: NaiveDate::from_ymd_opt(2026, 10, 21).unwrap(),
+                    windows: vec![TimeWindow { start: time(8, 0), end: time(8, 30) }],
+                },
+            ],
+        );
+
+        let slots = expand(&schedule, utc(19, 0, 0), utc(22, 0, 0), Duration::minutes(30));
+
+        assert_eq!(starts(&slots), vec![utc(20, 14, 0), utc(21, 8, 0)]);
+    }
+
+    #[test]
+    fn local_hours_are_kept_across_a_daylight_saving_change() {
+        // Europe/Berlin leaves summer time on Sunday 25 Oct 2026
+        let schedule = availability(
+            "Europe/Berlin",
+            vec![weekly(Weekday::Sat, time(9, 0), time(9, 30)), weekly(Weekday::Mon, time(9, 0), time(9, 30))],
+            vec![],
+        );
+
+        let slots = expand(&schedule, utc(24, 0, 0), utc(27, 0, 0), Duration::minutes(30));
+
+        assert_eq!(starts(&slots), vec![utc(24, 7, 0), utc(26, 8, 0)]);
+    }
+
+    #[test]
+    fn overlapping_windows_do_not_duplicate_slots() {
+        let schedule = availability(
+            "UTC",
+            vec![weekly(Weekday::Mon, time(9, 0), time(10, 0)), weekly(Weekday::Mon, time(9, 30), time(10, 30))],
+            vec![],
+        );
+
+        let slots = expand(&schedule, utc(19, 0, 0), utc(20, 0, 0), Duration::minutes(30));
+
+        assert_eq!(starts(&slots), vec![utc(19, 9, 0), utc(19, 9, 30), utc(19, 10, 0)]);
+    }
+
+    #[test]
+    fn only_confirmed_appointments_take_slots() {
+        let schedule = availability("UTC", vec![weekly(Weekday::Mon, time(9, 0), time(11, 0))], vec![]);
+        let slots = expand(&schedule, utc(19, 0, 0), utc(20, 0, 0), Duration::minutes(30));
+        let appointments = [
+            appointment(utc(19, 9, 15), utc(19, 9, 45), AppointmentStatus::Confirmed),
+            appointment(utc(19, 10, 0), utc(19, 10, 30), AppointmentStatus::Requested),
+            appointment(utc(19, 10, 30), utc(19, 11, 0), AppointmentStatus::Cancelled),
+        ];
+
+        let free = subtract_booked(slots, &appointments);
+
+        assert_eq!(starts(&free), vec![utc(19, 10, 0), utc(19, 10, 30)]);
+    }
+
+    #[test]
+    fn requests_must_line_up_with_consecutive_free_slots() {
+        let slots = [
+            Slot { start: utc(19, 9, 0), end: utc(19, 9, 30) },
+            Slot { start: utc(19, 9, 30), end: utc(19, 10, 0) },
+            Slot { start: utc(19, 10, 30), end: utc(19, 11, 0) },
+        ];
+
+        assert_eq!(covering(&slots, utc(19, 9, 0), utc(19, 10, 0)), Some(vec![utc(19, 9, 0), utc(19, 9, 30)]));
+        assert_eq!(covering(&slots, utc(19, 9, 30), utc(19, 10, 0)), Some(vec![utc(19, 9, 30)]));
+        // Misaligned start or end, or a gap in the middle
+        assert_eq!(covering(&slots, utc(19, 9, 15), utc(19, 9, 45)), None);
+        assert_eq!(covering(&slots, utc(19, 9, 0), utc(19, 9, 45)), None);
+        assert_eq!(covering(&slots, utc(19, 9, 30), utc(19, 11, 0)), None);
+    }
+}
@@ -0,0 +1,321 @@
+//! Recording the devices an account signs in from, and alerting the owner to new ones.
+
+use anyhow::Result;
+use bson::oid::ObjectId;
+use chrono::Utc;
+use rand::Rng;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::deadline;
+use crate::models::*;
+use crate::services::notification::{NotificationEvent, NotificationService};
+use crate::store::SessionStore;
+
+/// How many recent sessions a sign-in is compared against
+const KNOWN_SESSION_LIMIT: i64 = 200;
+const GEOLOCATION_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What a sign-in request says about where it came from
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub installation_id: Option<String>,
+}
+
+/// The parts of a client that identify a device across sign-ins
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceFingerprint {
+    pub installation_id: Option<String>,
+    pub user_agent_family: String,
+    pub subnet: Option<String>,
+}
+
+impl DeviceFingerprint {
+    pub fn of(client: &ClientInfo) -> Self {
+        Self {
+            installation_id: client.installation_id.as_deref().map(str::trim).filter(|id| !id.is_empty()).map(str::to_string),
+            user_agent_family: user_agent_family(client.user_agent.as_deref().unwrap_or_default()),
+            subnet: client.ip.map(subnet),
+        }
+    }
+
+    /// The same app installation, or the same browser from the same subnet. Addresses alone
+    /// change too often (mobile networks, DHCP) to require an exact match.
+    pub fn matches(&self, known: &Session) -> bool {
+        let same_installation = self.installation_id.is_some() && self.installation_id == known.installation_id;
+        let same_browser_and_network = self.user_agent_family == known.user_agent_family && self.subnet.is_some() && self.subnet == known.subnet;
+        same_installation || same_browser_and_network
+    }
+}
+
+#[derive(Deserialize)]
+struct GeolocationResponse {
+    city: Option<String>,
+    #[serde(alias = "country")]
+    country_name: Option<String>,
+}
+
+// --- SessionService ---
+pub struct SessionService {
+    db: Arc<dyn SessionStore>,
+    notification_service: Arc<NotificationService>,
+    http_client: Client,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl SessionService {
+    pub fn new(
+        db: Arc<dyn SessionStore>,
+        notification_service: Arc<NotificationService>,
+        http_client: Client,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, notification_service, http_client, config, audit_log_service }
+    }
+
+    /// Record a sign-in about to be given a token, returning the session id to put in it.
+    /// A device the account has not signed in from before is audit-logged, and unless it is
+    /// the account's first the owner is sent an alert with a link that revokes the session.
+    pub async fn start(&self, did: &str, client: &ClientInfo) -> Result<String> {
+        let fingerprint = DeviceFingerprint::of(client);
+        let known = self.db.known_sessions(did, KNOWN_SESSION_LIMIT).await?;
+        let new_device = !known.iter().any(|session| fingerprint.matches(session));
+        let location = match (new_device, client.ip) {
+            (true, Some(ip)) => self.locate(ip).await,
+            _ => None,
+        };
+
+        let revoke_token = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+        let session = Session {
+            id: None,
+            did: did.to_string(),
+            installation_id: fingerprint.installation_id,
+            user_agent_family: fingerprint.user_agent_family,
+            subnet: fingerprint.subnet,
+            location,
+            revoke_token_hash: token_hash(&revoke_token),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        let session_id = self.db.create_session(&session).await?.to_hex();
+
+        if new_device {
+            let details = json!({
+                "session_id": session_id,
+                "device": session.user_agent_family,
+                "location": session.location,
+                "first_device": known.is_empty(),
+            });
+            self.audit_log_service.log(did, "new_device_sign_in", Some(details)).await;
+            if !known.is_empty() {
+                self.notification_service.notify(NotificationEvent::NewSignIn {
+                    patient_did: did.to_string(),
+                    device: session.user_agent_family,
+                    location: session.location,
+                    revoke_link: format!("{}/api/auth/sessions/revoke?token={}", self.config.backend_base_url.trim_end_matches('/'), revoke_token),
+                });
+            }
+        }
+        Ok(session_id)
+    }
+
+    /// Revoke the session a new-device alert was about. False if the link is unknown or was used.
+    pub async fn revoke_with_link(&self, token: &str) -> Result<bool> {
+        let Some(session) = self.db.revoke_session_by_token(&token_hash(token)).await? else {
+            return Ok(false);
+        };
+        let session_id = session.id.map(|id| id.to_hex());
+        self.audit_log_service.log(&session.did, "session_revoked", Some(json!({ "session_id": session_id, "via": "sign_in_alert" }))).await;
+        Ok(true)
+    }
+
+    /// Whether tokens issued for the session are still good
+    pub async fn is_active(&self, session_id: &str) -> Result<bool> {
+        let Ok(id) = ObjectId::parse_str(session_id) else {
+            return Ok(false);
+        };
+        Ok(self.db.get_session(id).await?.is_some_and(|session| session.revoked_at.is_none()))
+    }
+
+    /// "City, Country" for a public address, when a geolocation service is configured.
+    /// Best effort: the alert goes out without a location rather than not at all.
+    async fn locate(&self, ip: IpAddr) -> Option<String> {
+        let url = self.config.geoip_url.as_deref()?;
+        if !is_public(ip) {
+            return None;
+        }
+        let response = self.http_client.get(url.replace("{ip}", &ip.to_string())).timeout(deadline::remaining_or(GEOLOCATION_TIMEOUT)).send().await;
+        let located = match response.and_then(|response| response.error_for_status()) {
+            Ok(response) => response.json::<GeolocationResponse>().await,
+            Err(e) => Err(e),
+        };
+        match located {
+            Ok(GeolocationResponse { city, country_name }) => {
+                let parts: Vec<String> = city.into_iter().chain(country_name).filter(|part| !part.is_empty()).collect();
+                (!parts.is_empty()).then(|| parts.join(", "))
+            }
+            Err(e) => {
+                tracing::warn!("IP geolocation failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Browser or app and operating system, coarse enough to survive version updates
+pub fn user_agent_family(user_agent: &str) -> String {
+    // Order matters: Edge and Opera also claim Chrome, Chrome also claims Safari
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Chrome/", "Chrome"),
+        ("CriOS/", "Chrome"),
+        ("Firefox/", "Firefox"),
+        ("FxiOS/", "Firefox"),
+        ("Safari/", "Safari"),
+        ("Dart/", "Mobile app"),
+        ("okhttp/", "Mobile app"),
+    ];
+    // Android also claims Linux, iOS also claims Mac OS X
+    const SYSTEMS: &[(&str, &str)] = &[
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ];
+    let find = |table: &[(&str, &'static str)]| table.iter().find(|(marker, _)| user_agent.contains(marker)).map(|(_, name)| *name);
+    match (find(BROWSERS), find(SYSTEMS)) {
+        (Some(browser), Some(system)) => format!("{} on {}", browser, system),
+        (Some(browser), None) => browser.to_string(),
+        (None, Some(system)) => format!("Unknown browser on {}", system),
+        (None, None) => "Unknown device".to_string(),
+    }
+}
+
+/// The /24 an IPv4 address is in, or the /64 of an IPv6 one (a single customer's network)
+fn subnet(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}:{:x}::/64", segments[0], segments[1], segments[2], segments[3])
+        }
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()),
+        IpAddr::V6(ip) => !(ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xfe00) == 0xfc00),
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+
+    const CHROME_ON_ANDROID: &str =
+        "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36";
+    const SAFARI_ON_IPHONE: &str =
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1";
+    const EDGE_ON_WINDOWS: &str =
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0";
+
+    fn client(user_agent: &str, ip: &str, installation_id: Option<&str>) -> ClientInfo {
+        ClientInfo {
+            user_agent: Some(user_agent.to_string()),
+            ip: Some(ip.parse().unwrap()),
+            installation_id: installation_id.map(str::to_string),
+        }
+    }
+
+    fn known(client: &ClientInfo) -> Session {
+        let fingerprint = DeviceFingerprint::of(client);
+        Session {
+            id: None,
+            did: "did:hedera:testnet:0.0.1".to_string(),
+            installation_id: fingerprint.installation_id,
+            user_agent_family: fingerprint.user_agent_family,
+            subnet: fingerprint.subnet,
+            location: None,
+            revoke_token_hash: String::new(),
+            created_at: Utc::now(),
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn user_agents_are_reduced_to_browser_and_system() {
+        assert_eq!(user_agent_family(CHROME_ON_ANDROID), "Chrome on Android");
+        assert_eq!(user_agent_family(SAFARI_ON_IPHONE), "Safari on iOS");
+        assert_eq!(user_agent_family(EDGE_ON_WINDOWS), "Edge on Windows");
+        assert_eq!(user_agent_family("Dart/3.4 (dart:io)"), "Mobile app");
+        assert_eq!(user_agent_family(""), "Unknown device");
+        assert_eq!(
+            user_agent_family(&CHROME_ON_ANDROID.replace("126.0.0.0", "127.0.6533.64")),
+            user_agent_family(CHROME_ON_ANDROID),
+            "browser updates keep the family"
+        );
+    }
+
+    #[test]
+    fn addresses_are_kept_only_to_their_subnet() {
+        assert_eq!(subnet("203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(subnet("::ffff:203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(subnet("2001:db8:85a3:8d3:1319:8a2e:370:7348".parse().unwrap()), "2001:db8:85a3:8d3::/64");
+    }
+
+    #[test]
+    fn the_same_installation_is_known_from_any_network_or_browser() {
+        let before = known(&client(CHROME_ON_ANDROID, "203.0.113.7", Some("install-1")));
+        assert!(DeviceFingerprint::of(&client(SAFARI_ON_IPHONE, "198.51.100.9", Some("install-1"))).matches(&before));
+        assert!(!DeviceFingerprint::of(&client(SAFARI_ON_IPHONE, "198.51.100.9", Some("install-2"))).matches(&before));
+    }
+
+    #[test]
+    fn the_same_browser_is_known_from_the_same_subnet_only() {
+        let before = known(&client(CHROME_ON_ANDROID, "203.0.113.7", None));
+        assert!(DeviceFingerprint::of(&client(CHROME_ON_ANDROID, "203.0.113.200", None)).matches(&before));
+        assert!(!DeviceFingerprint::of(&client(CHROME_ON_ANDROID, "203.0.114.7", None)).matches(&before), "another /24");
+        assert!(!DeviceFingerprint::of(&client(EDGE_ON_WINDOWS, "203.0.113.7", None)).matches(&before), "another browser");
+        // A fresh install on the same phone and network is still the same device
+        assert!(DeviceFingerprint::of(&client(CHROME_ON_ANDROID, "203.0.113.7", Some("install-2"))).matches(&before));
+    }
+
+    #[test]
+    fn unknown_addresses_and_blank_installation_ids_never_match() {
+        let before = known(&ClientInfo { user_agent: Some(CHROME_ON_ANDROID.to_string()), ip: None, installation_id: Some(" ".to_string()) });
+        assert_eq!(before.installation_id, None);
+        let again = DeviceFingerprint::of(&ClientInfo { user_agent: Some(CHROME_ON_ANDROID.to_string()), ip: None, installation_id: Some(String::new()) });
+        assert!(!again.matches(&before));
+    }
+
+    #[test]
+    fn only_public_addresses_are_geolocated() {
+        assert!(is_public("203.0.113.7".parse().unwrap()));
+        assert!(!is_public("10.1.2.3".parse().unwrap()));
+        assert!(!is_public("127.0.0.1".parse().unwrap()));
+        assert!(!is_public("::1".parse().unwrap()));
+        assert!(!is_public("fd00::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,250 @@
+//! Caps on the sign-in codes texted to phone numbers.
+//!
+//! Every code sent through Twilio costs money, and the request that triggers one needs no
+//! account, so a loop can text hundreds of codes to a single victim. Each number gets a
+//! 15-minute and a daily allowance, and the platform a daily budget across all numbers. The
+//! counts live in `sms_sends`, one counter per scope and window, and are taken with a single
+//! findAndModify each so concurrent requests cannot both take the last send.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::config::{Config, SmsThrottleConfig};
+use crate::i18n;
+use crate::models::*;
+use crate::services::email::EmailService;
+use crate::services::ServiceError;
+use crate::store::{PatientStore, SmsSendStore};
+use crate::utils::hash_phone;
+
+const WINDOW_SECONDS: i64 = 15 * 60;
+/// How long the platform-wide daily counters are kept for the admin stats
+const STATS_DAYS: i64 = 30;
+
+pub struct SmsThrottle {
+    db: Arc<dyn SmsSendStore>,
+    patients: Arc<dyn PatientStore>,
+    email_service: Arc<EmailService>,
+    config: Arc<Config>,
+}
+
+impl SmsThrottle {
+    pub fn new(db: Arc<dyn SmsSendStore>, patients: Arc<dyn PatientStore>, email_service: Arc<EmailService>, config: Arc<Config>) -> Self {
+        Self { db, patients, email_service, config }
+    }
+
+    /// Take one send for `phone_number` at `now`, or fail with `SmsLimited` when the number has
+    /// had its allowance for the window and `SmsBudgetExhausted` when the platform has had its
+    /// budget for the day. A refused send gives back what it had already counted.
+    pub async fn claim(&self, phone_number: &str, now: DateTime<Utc>) -> Result<()> {
+        let limits = &self.config.sms_throttle;
+        let number = hash_phone(phone_number);
+        let day = now.date_naive();
+        let window_start = now.timestamp() - now.timestamp().rem_euclid(WINDOW_SECONDS);
+        let window_end = DateTime::from_timestamp(window_start + WINDOW_SECONDS, 0).unwrap_or(now);
+
+        let per_number = [
+            (format!("number:{}:15m:{}", number, window_start), window_end, limits.per_number_per_15_minutes),
+            (format!("number:{}:day:{}", number, day), day_end(day), limits.per_number_per_day),
+        ];
+        let mut counted: Vec<&str> = Vec::new();
+        for (key, expires_at, limit) in &per_number {
+            let counter = SmsSendCounter { key: key.clone(), count: 0, day: None, expires_at: *expires_at };
+            if self.db.count_sms_send(&counter, *limit).await?.is_none() {
+                self.release(&counted).await;
+                let message = i18n::text(i18n::current(), "error.too_many_sms", &[("time", &expires_at.format("%H:%M UTC").to_string())]);
+                return Err(ServiceError::SmsLimited { message, retry_at: *expires_at }.into());
+            }
+            counted.push(key);
+        }
+
+        let total = SmsSendCounter {
+            key: format!("all:day:{}", day),
+            count: 0,
+            day: Some(day.to_string()),
+            expires_at: day_end(day) + Duration::days(STATS_DAYS),
+        };
+        if self.db.count_sms_send(&total, limits.daily_budget.unwrap_or(u32::MAX)).await?.is_none() {
+            self.release(&counted).await;
+            if let Err(e) = self.alert_budget_exhausted(day, now).await {
+                tracing::error!("Failed to alert admins that the SMS budget for {} is used up: {:#}", day, e);
+            }
+            return Err(ServiceError::SmsBudgetExhausted.into());
+        }
+        Ok(())
+    }
+
+    async fn release(&self, keys: &[&str]) {
+        for key in keys {
+            if let Err(e) = self.db.uncount_sms_send(key).await {
+                tracing::warn!("Failed to give back an SMS send that was refused: {:#}", e);
+            }
+        }
+    }
+
+    /// Email the platform admins, once a day, that phone sign-in has been turned off
+    async fn alert_budget_exhausted(&self, day: NaiveDate, now: DateTime<Utc>) -> Result<()> {
+        if !self.db.claim_sms_budget_alert(&day.to_string(), day_end(day).max(now)).await? {
+            return Ok(());
+        }
+        let limits = &self.config.sms_throttle;
+        let budget = limits.daily_budget.unwrap_or_default();
+        tracing::error!("The SMS budget of {} messages for {} is used up; phone sign-in is off until midnight UTC", budget, day);
+
+        let mut recipients = 0;
+        for admin_did in &self.config.admin_dids {
+            let Some(admin) = self.patients.get_patient_by_did(admin_did, &self.config.ipfs_encryption_key).await? else {
+                continue;
+            };
+            let Some(email) = admin.fhir_patient.telecom.iter().find(|contact| contact.system == "email") else {
+                continue;
+            };
+            let context = json!({
+                "username": admin.fhir_patient.name.first().and_then(|name| name.given.first()).map_or("admin", String::as_str),
+                "day": day.to_string(),
+                "budget": budget,
+                "estimated_usd": limits.usd_per_message.map(|price| format!("{:.2}", price * f64::from(budget))),
+            });
+            self.email_service.enqueue(&email.value, "The daily SMS budget is used up", "Sms-budget-alert.html", &context).await;
+            recipients += 1;
+        }
+        if recipients == 0 {
+            tracing::error!("The SMS budget for {} is used up but no admin has an email address", day);
+        }
+        Ok(())
+    }
+}
+
+/// The first day the admin stats cover when they are read at `now`
+pub fn stats_since(now: DateTime<Utc>) -> String {
+    (now.date_naive() - Duration::days(STATS_DAYS - 1)).to_string()
+}
+
+/// The admin stats for the platform-wide daily counters, newest day first
+pub fn stats(counters: Vec<SmsSendCounter>, config: &SmsThrottleConfig) -> SmsStats {
+    let days = counters
+        .into_iter()
+        .filter_map(|counter| {
+            let day = counter.day?;
+            Some(SmsDailyUsage { day, sent: counter.count, estimated_usd: config.usd_per_message.map(|price| price * f64::from(counter.count)) })
+        })
+        .collect();
+    SmsStats { daily_budget: config.daily_budget, usd_per_message: config.usd_per_message, days }
+}
+
+/// Midnight UTC at the end of `day`
+fn day_end(day: NaiveDate) -> DateTime<Utc> {
+    (day + Duration::days(1)).and_time(NaiveTime::MIN).and_utc()
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::SmtpConfig;
+    use crate::fixtures;
+    use crate::store::{MockEmailOutboxStore, MockPatientStore, MockSmsSendStore};
+    use bson::oid::ObjectId;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const ADMIN: &str = "did:hedera:testnet:0.0.3";
+    const NUMBER: &str = "+254700000001";
+
+    /// A store that keeps its counters in memory, as `Database::count_sms_send` does in Mongo
+    fn store(counts: Arc<Mutex<HashMap<String, u32>>>) -> MockSmsSendStore {
+        let mut db = MockSmsSendStore::new();
+        let counting = counts.clone();
+        db.expect_count_sms_send().returning(move |counter, limit| {
+            let mut counts = counting.lock().unwrap();
+            let count = counts.entry(counter.key.clone()).or_default();
+            if *count >= limit {
+                return Ok(None);
+            }
+            *count += 1;
+            Ok(Some(*count))
+        });
+        db.expect_uncount_sms_send().returning(move |key| {
+            *counts.lock().unwrap().get_mut(key).unwrap() -= 1;
+            Ok(())
+        });
+        db
+    }
+
+    fn throttle(db: MockSmsSendStore, patients: MockPatientStore, outbox: MockEmailOutboxStore, limits: SmsThrottleConfig) -> SmsThrottle {
+        let config = Arc::new(Config { admin_dids: vec![ADMIN.to_string()], smtp: Some(SmtpConfig::default()), sms_throttle: limits, ..Default::default() });
+        let email_service = Arc::new(EmailService::new(config.clone(), Arc::new(outbox)).unwrap());
+        SmsThrottle::new(Arc::new(db), Arc::new(patients), email_service, config)
+    }
+
+    #[tokio::test]
+    async fn a_number_is_refused_until_its_window_ends_and_keeps_its_daily_allowance() {
+        let counts = Arc::new(Mutex::new(HashMap::new()));
+        let limits = SmsThrottleConfig { per_number_per_15_minutes: 2, per_number_per_day: 3, ..Default::default() };
+        let throttle = throttle(store(counts.clone()), MockPatientStore::new(), MockEmailOutboxStore::new(), limits);
+        let now: DateTime<Utc> = "2026-03-17T12:05:00Z".parse().unwrap();
+
+        throttle.claim(NUMBER, now).await.unwrap();
+        throttle.claim(NUMBER, now).await.unwrap();
+        let refused = throttle.claim(NUMBER, now).await.unwrap_err();
+        match refused.downcast_ref::<ServiceError>() {
+            Some(ServiceError::SmsLimited { retry_at, .. }) => assert_eq!(*retry_at, "2026-03-17T12:15:00Z".parse::<DateTime<Utc>>().unwrap()),
+            other => panic!("expected SmsLimited, got {:?}", other),
+        }
+        // The refused send did not use up one of the day's
+        throttle.claim(NUMBER, "2026-03-17T12:15:00Z".parse().unwrap()).await.unwrap();
+        let day_refused = throttle.claim(NUMBER, "2026-03-17T12:30:00Z".parse().unwrap()).await.unwrap_err();
+        match day_refused.downcast_ref::<ServiceError>() {
+            Some(ServiceError::SmsLimited { retry_at, .. }) => assert_eq!(*retry_at, "2026-03-18T00:00:00Z".parse::<DateTime<Utc>>().unwrap()),
+            other => panic!("expected SmsLimited, got {:?}", other),
+        }
+        assert_eq!(counts.lock().unwrap()["all:day:2026-03-17"], 3);
+    }
+
+    #[tokio::test]
+    async fn an_exhausted_budget_turns_phone_sign_in_off_and_alerts_the_admins_once() {
+        let counts = Arc::new(Mutex::new(HashMap::new()));
+        let mut db = store(counts.clone());
+        let claimed = Arc::new(Mutex::new(Vec::new()));
+        let days = claimed.clone();
+        db.expect_claim_sms_budget_alert().returning(move |day, _| {
+            let mut days = days.lock().unwrap();
+            let first = !days.iter().any(|claimed: &String| claimed == day);
+            days.push(day.to_string());
+            Ok(first)
+        });
+        let mut patients = MockPatientStore::new();
+        patients.expect_get_patient_by_did().returning(|_, _| Ok(Some(fixtures::admin(ADMIN))));
+        let mut outbox = MockEmailOutboxStore::new();
+        outbox
+            .expect_enqueue_email()
+            .withf(|email| email.template == "Sms-budget-alert.html" && email.context["budget"] == 1 && email.context["estimated_usd"] == "0.05")
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let limits = SmsThrottleConfig { daily_budget: Some(1), usd_per_message: Some(0.05), ..Default::default() };
+        let throttle = throttle(db, patients, outbox, limits);
+        let now: DateTime<Utc> = "2026-03-17T12:05:00Z".parse().unwrap();
+
+        throttle.claim(NUMBER, now).await.unwrap();
+        for number in ["+254700000002", "+254700000003"] {
+            let refused = throttle.claim(number, now).await.unwrap_err();
+            assert!(matches!(refused.downcast_ref::<ServiceError>(), Some(ServiceError::SmsBudgetExhausted)));
+        }
+        assert_eq!(*claimed.lock().unwrap(), vec!["2026-03-17", "2026-03-17"]);
+        // The refused numbers keep their allowances
+        let hash = hash_phone("+254700000002");
+        assert_eq!(counts.lock().unwrap()[&format!("number:{}:day:2026-03-17", hash)], 0);
+    }
+
+    #[test]
+    fn the_stats_estimate_each_days_cost() {
+        let counter = |day: &str, count| SmsSendCounter { key: format!("all:day:{}", day), count, day: Some(day.to_string()), expires_at: Utc::now() };
+        let config = SmsThrottleConfig { daily_budget: Some(500), usd_per_message: Some(0.25), ..Default::default() };
+        let stats = stats(vec![counter("2026-03-17", 40), counter("2026-03-16", 3)], &config);
+        let days: Vec<(&str, u32, Option<f64>)> = stats.days.iter().map(|day| (day.day.as_str(), day.sent, day.estimated_usd)).collect();
+        assert_eq!(days, vec![("2026-03-17", 40, Some(10.0)), ("2026-03-16", 3, Some(0.75))]);
+        assert_eq!(stats.daily_budget, Some(500));
+        assert_eq!(stats_since("2026-03-17T12:00:00Z".parse().unwrap()), "2026-02-16");
+    }
+}
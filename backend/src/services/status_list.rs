@@ -0,0 +1,355 @@
+//! Revocation status lists in the StatusList2021 format. Every issued credential gets a bit
+//! in a list; revoking it sets the bit, and the signed list is published to IPFS so verifiers
+//! can check a credential offline instead of asking us or the ledger.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::models::*;
+use crate::services::ipfs::ObjectStorage;
+use crate::services::vc_document::{canonical_json, CredentialSigner, W3cCredentialBuilder};
+use crate::services::ServiceError;
+use crate::store::{CredentialStore, StatusListStore};
+
+pub const STATUS_LIST_CONTEXT: &str = "https://w3id.org/vc/status-list/2021/v1";
+/// Bits per list. The spec's minimum, so a list does not give away how few credentials it covers.
+pub const STATUS_LIST_SIZE: u32 = 131_072;
+const STATUS_PURPOSE: &str = "revocation";
+const PUBLISH_POLL_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+/// The bits of one status list; index 0 is the most significant bit of the first byte
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusBitstring {
+    bytes: Vec<u8>,
+}
+
+impl StatusBitstring {
+    pub fn new(size: u32) -> Self {
+        Self { bytes: vec![0; size.div_ceil(8) as usize] }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.bytes.len() as u32 * 8
+    }
+
+    pub fn set(&mut self, index: u32) -> Result<()> {
+        let byte = self
+            .bytes
+            .get_mut(index as usize / 8)
+            .ok_or_else(|| anyhow!("Status index {} is outside a list of {} bits", index, self.bytes.len() * 8))?;
+        *byte |= 0x80 >> (index % 8);
+        Ok(())
+    }
+
+    /// Whether the bit is set; indexes past the end never are
+    pub fn is_set(&self, index: u32) -> bool {
+        self.bytes.get(index as usize / 8).is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    /// The `encodedList` form: GZIP-compressed, then base64url without padding
+    pub fn encode(&self) -> Result<String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.bytes)?;
+        Ok(URL_SAFE_NO_PAD.encode(encoder.finish()?))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let compressed = URL_SAFE_NO_PAD.decode(encoded.trim_end_matches('='))?;
+        let mut bytes = Vec::new();
+        // No list is larger, whatever the compressed data claims
+        GzDecoder::new(compressed.as_slice()).take(STATUS_LIST_SIZE as u64 / 8).read_to_end(&mut bytes)?;
+        Ok(Self { bytes })
+    }
+}
+
+#[derive(Clone)]
+pub struct StatusListService {
+    lists: Arc<dyn StatusListStore>,
+    credentials: Arc<dyn CredentialStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    config: Arc<Config>,
+}
+
+impl StatusListService {
+    pub fn new(lists: Arc<dyn StatusListStore>, credentials: Arc<dyn CredentialStore>, ipfs_client: Arc<dyn ObjectStorage>, config: Arc<Config>) -> Self {
+        Self { lists, credentials, ipfs_client, config }
+    }
+
+    /// A bit for a credential about to be issued
+    pub async fn allocate(&self) -> Result<StatusListEntry> {
+        self.lists.allocate_status_index(STATUS_LIST_SIZE).await
+    }
+
+    /// The `credentialStatus` of the credential at `entry`
+    pub fn credential_status(&self, entry: &StatusListEntry) -> Value {
+        let list_url = self.list_url(&entry.list_id);
+        json!({
+            "id": format!("{}#{}", list_url, entry.index),
+            "type": "StatusList2021Entry",
+            "statusPurpose": STATUS_PURPOSE,
+            "statusListIndex": entry.index.to_string(),
+            "statusListCredential": list_url,
+        })
+    }
+
+    /// Republish the list once a credential on it was revoked. A failed publish is only logged:
+    /// the list stays stale, so the publisher worker tries again.
+    pub async fn revoked(&self, entry: &StatusListEntry) -> Result<()> {
+        self.lists.mark_status_list_stale(&entry.list_id).await?;
+        if let Err(e) = self.publish(&entry.list_id).await {
+            tracing::error!("Failed to publish status list {} after a revocation: {}", entry.list_id, e);
+        }
+        Ok(())
+    }
+
+    /// Whether the entry's bit is set in the latest published list. A list not published yet
+    /// revokes nothing.
+    pub async fn is_revoked(&self, entry: &StatusListEntry) -> Result<bool> {
+        let Some(document) = self.lists.get_status_list(&entry.list_id).await?.and_then(|list| list.document) else {
+            return Ok(false);
+        };
+        let document: Value = serde_json::from_str(&document)?;
+        let encoded = document["credentialSubject"]["encodedList"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Status list {} has no encodedList", entry.list_id))?;
+        Ok(StatusBitstring::decode(encoded)?.is_set(entry.index))
+    }
+
+    /// The latest signed status list credential, published first if it never was
+    pub async fn document(&self, list_id: &str) -> Result<String> {
+        let list = self.lists.get_status_list(list_id).await?.ok_or_else(|| anyhow!("Status list not found"))?;
+        if let Some(document) = list.document {
+            return Ok(document);
+        }
+        self.publish(list_id).await?;
+        self.lists
+            .get_status_list(list_id)
+            .await?
+            .and_then(|list| list.document)
+            .ok_or_else(|| anyhow!("Status list {} could not be published", list_id))
+    }
+
+    /// Sign the list's current bits as a `StatusList2021Credential`, pin it on IPFS and record it
+    /// as the latest, unless a more recent one was published meanwhile
+    pub async fn publish(&self, list_id: &str) -> Result<()> {
+        let signer = self.signer()?;
+        // The revision is read before the bits, so a revocation landing in between leaves the list stale
+        let list = self.lists.get_status_list(list_id).await?.ok_or_else(|| anyhow!("Status list not found"))?;
+        let mut bits = StatusBitstring::new(STATUS_LIST_SIZE);
+        for index in self.credentials.revoked_status_indexes(list_id).await? {
+            bits.set(index)?;
+        }
+
+        let list_url = self.list_url(list_id);
+        let document = W3cCredentialBuilder::new("StatusList2021Credential", &format!("{}#list", list_url), Utc::now())
+            .with_id(&list_url)
+            .with_context(STATUS_LIST_CONTEXT)
+            .with_claim("type", json!("StatusList2021"))
+            .with_claim("statusPurpose", json!(STATUS_PURPOSE))
+            .with_claim("encodedList", json!(bits.encode()?))
+            .sign(&signer);
+        let document = canonical_json(&document);
+        let ipfs_hash = self.ipfs_client.add_file(document.as_bytes(), None).await?;
+        self.ipfs_client.pin_add(&ipfs_hash).await?;
+
+        let superseded = match self.lists.publish_status_list(list_id, list.revision, &document, &ipfs_hash).await? {
+            Some(previous) => previous.ipfs_hash.filter(|previous| *previous != ipfs_hash),
+            // A newer document won; ours is only unpinned if it is not byte for byte the winner
+            None => {
+                let latest = self.lists.get_status_list(list_id).await?.and_then(|list| list.ipfs_hash);
+                (latest.as_deref() != Some(ipfs_hash.as_str())).then(|| ipfs_hash.clone())
+            }
+        };
+        if let Some(superseded) = superseded {
+            if let Err(e) = self.ipfs_client.pin_rm(&superseded).await {
+                tracing::warn!("Failed to unpin superseded status list {}: {}", superseded, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish every list changed since its last document, returning how many were published
+    pub async fn publish_stale(&self) -> Result<usize> {
+        let mut published = 0;
+        for list in self.lists.stale_status_lists().await? {
+            match self.publish(&list.list_id).await {
+                Ok(()) => published += 1,
+                Err(e) => tracing::error!("Failed to publish status list {}: {}", list.list_id, e),
+            }
+        }
+        Ok(published)
+    }
+
+    /// Where the list is served, which is also its credential `id`
+    fn list_url(&self, list_id: &str) -> String {
+        format!("{}/api/credentials/status-list/{}", self.config.backend_base_url.trim_end_matches('/'), list_id)
+    }
+
+    fn signer(&self) -> Result<CredentialSigner> {
+        match &self.config.credential_signing {
+            Some(signing) => CredentialSigner::from_config(signing),
+            None => Err(ServiceError::NotConfigured("Credential signing is not configured".to_string()).into()),
+        }
+    }
+}
+
+/// Publishes lists that are stale: new ones, and any whose publishing after a revocation failed
+pub struct StatusListPublisher {
+    service: Arc<StatusListService>,
+}
+
+impl StatusListPublisher {
+    pub fn new(service: Arc<StatusListService>) -> Self {
+        Self { service }
+    }
+
+    /// Poll until `shutdown` is cancelled. A run already publishing is finished first.
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = time::interval(PUBLISH_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Err(e) = self.service.publish_stale().await {
+                tracing::error!("Status list publishing run failed: {}", e);
+            }
+        }
+        tracing::info!("Status list publisher stopped");
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::CredentialSigningConfig;
+    use crate::services::fakes::InMemoryObjectStorage;
+    use crate::store::{MockCredentialStore, MockStatusListStore};
+    use std::sync::Mutex;
+
+    const ISSUER: &str = "did:hedera:testnet:0.0.5";
+    /// An empty list of the minimum size, as the spec's own example encodes it
+    const SPEC_EMPTY_LIST: &str = "H4sIAAAAAAAAA-3BMQEAAADCoPVPbQwfoAAAAAAAAAAAAAAAAAAAAIC3AYbSVKsAQAAA";
+
+    #[test]
+    fn bits_are_packed_most_significant_first() {
+        let mut bits = StatusBitstring::new(16);
+        bits.set(0).unwrap();
+        bits.set(9).unwrap();
+        bits.set(15).unwrap();
+
+        assert_eq!(bits.bytes, [0b1000_0000, 0b0100_0001]);
+        assert!(bits.is_set(9) && !bits.is_set(8));
+        assert!(!bits.is_set(16));
+        assert!(bits.set(16).is_err());
+    }
+
+    #[test]
+    fn encoded_lists_decode_to_the_same_bits() {
+        let mut bits = StatusBitstring::new(STATUS_LIST_SIZE);
+        for index in [0, 7, 8, 94_567, STATUS_LIST_SIZE - 1] {
+            bits.set(index).unwrap();
+        }
+
+        let encoded = bits.encode().unwrap();
+        assert!(!encoded.contains(|c| matches!(c, '+' | '/' | '=')));
+        // Long runs of zeros compress to almost nothing
+        assert!(encoded.len() < 200, "{} characters", encoded.len());
+        assert_eq!(StatusBitstring::decode(&encoded).unwrap(), bits);
+
+        let empty = StatusBitstring::decode(SPEC_EMPTY_LIST).unwrap();
+        assert_eq!(empty, StatusBitstring::new(STATUS_LIST_SIZE));
+        assert_eq!(empty.size(), STATUS_LIST_SIZE);
+    }
+
+    /// A service over one list, "1", whose revoked indexes are `revoked`
+    fn service(revoked: Arc<Mutex<Vec<u32>>>, ipfs: Arc<InMemoryObjectStorage>) -> StatusListService {
+        let list: Arc<Mutex<CredentialStatusList>> = Arc::new(Mutex::new(CredentialStatusList {
+            id: None,
+            list_id: "1".to_string(),
+            next_index: 10,
+            revision: 1,
+            published_revision: 0,
+            document: None,
+            ipfs_hash: None,
+            published_at: None,
+            created_at: Utc::now(),
+        }));
+        let mut lists = MockStatusListStore::new();
+        let read = list.clone();
+        lists.expect_get_status_list().returning(move |id| Ok(Some(read.lock().unwrap().clone()).filter(|list| list.list_id == id)));
+        let stale = list.clone();
+        lists.expect_mark_status_list_stale().returning(move |_| {
+            stale.lock().unwrap().revision += 1;
+            Ok(())
+        });
+        let published = list.clone();
+        lists.expect_publish_status_list().returning(move |_, revision, document, ipfs_hash| {
+            let mut list = published.lock().unwrap();
+            if list.published_revision >= revision {
+                return Ok(None);
+            }
+            let previous = list.clone();
+            list.published_revision = revision;
+            list.document = Some(document.to_string());
+            list.ipfs_hash = Some(ipfs_hash.to_string());
+            Ok(Some(previous))
+        });
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_revoked_status_indexes().returning(move |_| Ok(revoked.lock().unwrap().clone()));
+        let config = Arc::new(Config {
+            backend_base_url: "https://api.example.com/".to_string(),
+            credential_signing: Some(CredentialSigningConfig { issuer_did: ISSUER.to_string(), key: "07".repeat(32).into(), key_id: "key-1".to_string() }),
+            ..Default::default()
+        });
+        StatusListService::new(Arc::new(lists), Arc::new(credentials), ipfs, config)
+    }
+
+    #[tokio::test]
+    async fn revoking_a_credential_sets_its_bit_in_the_published_list() {
+        let revoked = Arc::new(Mutex::new(vec![]));
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let service = service(revoked.clone(), ipfs.clone());
+        let entry = StatusListEntry { list_id: "1".to_string(), index: 5 };
+        let neighbour = StatusListEntry { list_id: "1".to_string(), index: 6 };
+
+        let status = service.credential_status(&entry);
+        assert_eq!(status["statusListCredential"], "https://api.example.com/api/credentials/status-list/1");
+        assert_eq!((status["statusListIndex"].as_str(), status["type"].as_str()), (Some("5"), Some("StatusList2021Entry")));
+
+        // Never published, so the first read publishes the empty list
+        let first = service.document("1").await.unwrap();
+        assert!(!service.is_revoked(&entry).await.unwrap());
+
+        revoked.lock().unwrap().push(5);
+        service.revoked(&entry).await.unwrap();
+
+        assert!(service.is_revoked(&entry).await.unwrap());
+        assert!(!service.is_revoked(&neighbour).await.unwrap());
+        let document: Value = serde_json::from_str(&service.document("1").await.unwrap()).unwrap();
+        assert_eq!(document["type"], json!(["VerifiableCredential", "StatusList2021Credential"]));
+        assert_eq!(document["@context"][1], STATUS_LIST_CONTEXT);
+        assert_eq!(document["issuer"], ISSUER);
+        assert_eq!(document["credentialSubject"]["type"], "StatusList2021");
+        assert_eq!(document["credentialSubject"]["statusPurpose"], "revocation");
+        assert!(StatusBitstring::decode(document["credentialSubject"]["encodedList"].as_str().unwrap()).unwrap().is_set(5));
+
+        // The new list is what is pinned on IPFS; the one it replaced is not pinned any more
+        let latest = ipfs.pinned().last().unwrap().clone();
+        assert_eq!(ipfs.get_file(&latest).await.unwrap(), canonical_json(&document).into_bytes());
+        assert_eq!(ipfs.unpinned(), [ipfs.pinned()[0].clone()]);
+        assert_ne!(first, canonical_json(&document));
+    }
+}
@@ -0,0 +1,123 @@
+//! Stepping a session up to high assurance with a second factor.
+
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::api::middleware::jwt_auth::{AuthClaims, AuthContext};
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::i18n;
+use crate::models::*;
+use crate::services::backup_codes::BackupCodeService;
+use crate::services::phone_verification::{CodeCheck, PhoneLockout, PhoneVerifier};
+use crate::services::totp::TotpService;
+use crate::services::ServiceError;
+use crate::store::PatientStore;
+
+/// Message key, sent in the language of the request
+const STEP_UP_LOCKED_MESSAGE: &str = "error.too_many_codes";
+
+// --- StepUpService ---
+pub struct StepUpService {
+    patients: Arc<dyn PatientStore>,
+    totp_service: Arc<TotpService>,
+    backup_codes: Arc<BackupCodeService>,
+    phone_verifier: Arc<dyn PhoneVerifier>,
+    /// Wrong codes of either kind count against the account, not the phone number
+    lockout: PhoneLockout,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl StepUpService {
+    pub fn new(
+        patients: Arc<dyn PatientStore>,
+        totp_service: Arc<TotpService>,
+        backup_codes: Arc<BackupCodeService>,
+        phone_verifier: Arc<dyn PhoneVerifier>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { patients, totp_service, backup_codes, phone_verifier, lockout: PhoneLockout::default(), config, audit_log_service }
+    }
+
+    /// Text a one-time code to the phone number on the caller's record, which is fetched when
+    /// the auth middleware did not already load it as `patient`
+    pub async fn send_sms_code(&self, did: &str, patient: Option<&Patient>) -> Result<()> {
+        if self.lockout.is_locked(did) {
+            return Err(ServiceError::RateLimited(i18n::text(i18n::current(), STEP_UP_LOCKED_MESSAGE, &[])).into());
+        }
+        let phone_number = self.phone_number(did, patient).await?;
+        self.phone_verifier.start(&phone_number).await?;
+        self.audit_log_service.log(did, "step_up_sms_sent", None).await;
+        Ok(())
+    }
+
+    /// Exchange a code from a second factor for a short-lived high-assurance token
+    /// carrying the caller's role and naming the factor used
+    pub async fn step_up(&self, caller: &AuthContext, patient: Option<&Patient>, request: StepUpRequest) -> Result<StepUpResponse> {
+        let did = caller.user_did.as_str();
+        if self.lockout.is_locked(did) {
+            return Err(ServiceError::RateLimited(i18n::text(i18n::current(), STEP_UP_LOCKED_MESSAGE, &[])).into());
+        }
+        let mut backup_codes_remaining = None;
+        let accepted = match request.method {
+            SecondFactor::Sms => match self.phone_verifier.check(&self.phone_number(did, patient).await?, request.code.trim()).await? {
+                CodeCheck::Approved => true,
+                CodeCheck::Expired => return Err(anyhow!("The code has expired; request a new one")),
+                CodeCheck::Rejected => false,
+            },
+            SecondFactor::Totp => self.totp_service.verify(did, &request.code).await?,
+            SecondFactor::BackupCode => {
+                backup_codes_remaining = self.backup_codes.consume(did, &request.code).await?;
+                backup_codes_remaining.is_some()
+            }
+        };
+        if !accepted {
+            self.audit_log_service.log(did, "step_up_failed", Some(json!({ "second_factor": request.method }))).await;
+            if self.lockout.record_failure(did) {
+                tracing::warn!("Step-up locked after repeated wrong codes");
+                self.audit_log_service.log(did, "step_up_locked", None).await;
+            }
+            return Err(anyhow!("Invalid code"));
+        }
+        self.lockout.clear(did);
+
+        let expires_at = Utc::now() + Duration::minutes(self.config.step_up_minutes);
+        let claims = AuthClaims {
+            sub: did.to_string(),
+            exp: expires_at.timestamp() as usize,
+            role: caller.role,
+            org_id: caller.org_id.clone(),
+            second_factor: Some(request.method),
+            // Revoking the sign-in also revokes what it was stepped up to
+            sid: caller.session_id.clone(),
+            tenant_id: caller.tenant_id.clone(),
+            locale: caller.locale,
+        };
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(self.config.jwt_secret.expose_secret().as_bytes()))?;
+        self.audit_log_service.log(did, "step_up", Some(json!({ "second_factor": request.method }))).await;
+        Ok(StepUpResponse { token, second_factor: request.method, expires_at, backup_codes_remaining })
+    }
+
+    async fn phone_number(&self, did: &str, patient: Option<&Patient>) -> Result<String> {
+        let patient = match patient {
+            Some(patient) => patient.clone(),
+            None => self
+                .patients
+                .get_patient_by_did(did, &self.config.ipfs_encryption_key)
+                .await?
+                .ok_or_else(|| anyhow!("No account found for {}", did))?,
+        };
+        patient
+            .fhir_patient
+            .telecom
+            .into_iter()
+            .find(|contact| contact.system == "phone")
+            .map(|contact| contact.value)
+            .ok_or_else(|| anyhow!("There is no phone number on your record to text a code to"))
+    }
+}
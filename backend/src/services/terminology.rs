@@ -0,0 +1,602 @@
+//! ICD-10, LOINC, SNOMED CT and RxNorm code lookups for autocomplete and for checking submitted codings.
+//!
+//! Common codes are embedded so lookups work offline; a FHIR terminology server, when
+//! configured, answers for everything else. Lookups are cached in memory.
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{Config, TerminologyConfig};
+use crate::deadline;
+use crate::models::*;
+use crate::services::fhir::FhirCodeSystems;
+
+/// Subsets of each code system covering common primary-care use, one `code<TAB>display` per line.
+/// RxNorm lines add the dose form and strength, left empty for ingredients.
+const ICD10_SUBSET: &str = include_str!("../data/terminology/icd10.tsv");
+const LOINC_SUBSET: &str = include_str!("../data/terminology/loinc.tsv");
+const SNOMED_SUBSET: &str = include_str!("../data/terminology/snomed.tsv");
+const RXNORM_SUBSET: &str = include_str!("../data/terminology/rxnorm.tsv");
+
+pub const MAX_SEARCH_RESULTS: usize = 50;
+
+lazy_static! {
+    static ref EMBEDDED: EmbeddedCodes = EmbeddedCodes::load();
+}
+
+impl CodeSystem {
+    pub fn uri(self) -> &'static str {
+        match self {
+            Self::Icd10 => FhirCodeSystems::icd10(),
+            Self::Loinc => FhirCodeSystems::loinc(),
+            Self::Snomed => FhirCodeSystems::snomed(),
+            Self::Rxnorm => FhirCodeSystems::rxnorm(),
+        }
+    }
+
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        [Self::Icd10, Self::Loinc, Self::Snomed, Self::Rxnorm].into_iter().find(|system| system.uri() == uri)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Icd10 => "ICD-10",
+            Self::Loinc => "LOINC",
+            Self::Snomed => "SNOMED CT",
+            Self::Rxnorm => "RxNorm",
+        }
+    }
+}
+
+struct EmbeddedCode {
+    code: String,
+    display: String,
+    dose_form: Option<String>,
+    strength: Option<String>,
+}
+
+impl EmbeddedCode {
+    fn medication(&self) -> MedicationConcept {
+        MedicationConcept {
+            code: self.code.clone(),
+            display: self.display.clone(),
+            dose_form: self.dose_form.clone(),
+            strength: self.strength.clone(),
+        }
+    }
+}
+
+/// The embedded code subsets, parsed on first use
+struct EmbeddedCodes {
+    codes: HashMap<CodeSystem, Vec<EmbeddedCode>>,
+}
+
+impl EmbeddedCodes {
+    fn load() -> Self {
+        let subsets = [
+            (CodeSystem::Icd10, ICD10_SUBSET),
+            (CodeSystem::Loinc, LOINC_SUBSET),
+            (CodeSystem::Snomed, SNOMED_SUBSET),
+            (CodeSystem::Rxnorm, RXNORM_SUBSET),
+        ];
+        Self { codes: subsets.into_iter().map(|(system, tsv)| (system, Self::parse(tsv))).collect() }
+    }
+
+    fn parse(tsv: &str) -> Vec<EmbeddedCode> {
+        let column = |columns: &[&str], index: usize| columns.get(index).map(|value| value.trim()).filter(|value| !value.is_empty()).map(str::to_string);
+        tsv.lines()
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .filter_map(|columns| {
+                Some(EmbeddedCode {
+                    code: column(&columns, 0)?,
+                    display: column(&columns, 1)?,
+                    dose_form: column(&columns, 2),
+                    strength: column(&columns, 3),
+                })
+            })
+            .collect()
+    }
+
+    fn find(&self, system: CodeSystem, code: &str) -> Option<&EmbeddedCode> {
+        self.codes[&system].iter().find(|known| known.code.eq_ignore_ascii_case(code))
+    }
+
+    fn lookup(&self, system: CodeSystem, code: &str) -> Option<TerminologyConcept> {
+        self.find(system, code).map(|found| concept(system, &found.code, &found.display))
+    }
+
+    /// Codes starting with `text`, then displays with a word starting with each word of `text`
+    fn search(&self, system: CodeSystem, text: &str, limit: usize) -> Vec<TerminologyConcept> {
+        let text = text.to_lowercase();
+        let terms: Vec<&str> = text.split_whitespace().collect();
+        let codes = &self.codes[&system];
+        let by_code = codes.iter().filter(|known| known.code.to_lowercase().starts_with(&text));
+        let by_display = codes.iter().filter(|known| {
+            let display = known.display.to_lowercase();
+            !known.code.to_lowercase().starts_with(&text) && terms.iter().all(|term| words(&display).any(|word| word.starts_with(term)))
+        });
+        by_code.chain(by_display).take(limit).map(|found| concept(system, &found.code, &found.display)).collect()
+    }
+
+    /// Medications by how well their name matches `text`: names starting with it first, then
+    /// names with a word starting with each word of it, then names merely containing them.
+    /// Within a rank ingredients come first, then single-ingredient products, then combinations.
+    fn search_medications(&self, text: &str, limit: usize) -> Vec<MedicationConcept> {
+        let text = text.to_lowercase();
+        let terms: Vec<&str> = text.split_whitespace().collect();
+        let mut ranked: Vec<(u8, &EmbeddedCode)> = self.codes[&CodeSystem::Rxnorm]
+            .iter()
+            .filter_map(|known| {
+                let name = known.display.to_lowercase();
+                let rank = if name.starts_with(&text) || known.code == text {
+                    0
+                } else if terms.iter().all(|term| words(&name).any(|word| word.starts_with(term))) {
+                    1
+                } else if terms.iter().all(|term| name.contains(term)) {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, known))
+            })
+            .collect();
+        ranked.sort_by_key(|(rank, known)| (*rank, known.dose_form.is_some(), known.display.contains(" / "), known.display.clone()));
+        ranked.into_iter().take(limit).map(|(_, known)| known.medication()).collect()
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+/// The embedded RxNorm entry for `rxcui`. Only the embedded subset is consulted, so what can be
+/// prescribed by code alone does not depend on a terminology server being reachable.
+pub fn medication(rxcui: &str) -> Option<MedicationConcept> {
+    EMBEDDED.find(CodeSystem::Rxnorm, rxcui.trim()).map(EmbeddedCode::medication)
+}
+
+impl MedicationConcept {
+    /// The concept to prescribe it by, coded in RxNorm
+    pub fn codeable_concept(&self) -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(CodeSystem::Rxnorm.uri().to_string()),
+                code: Some(self.code.clone()),
+                display: Some(self.display.clone()),
+            }],
+            text: Some(self.display.clone()),
+        }
+    }
+}
+
+fn concept(system: CodeSystem, code: &str, display: &str) -> TerminologyConcept {
+    TerminologyConcept { system: system.uri().to_string(), code: code.to_string(), display: display.to_string() }
+}
+
+#[derive(Deserialize)]
+struct Parameters {
+    #[serde(default)]
+    parameter: Vec<Parameter>,
+}
+
+#[derive(Deserialize)]
+struct Parameter {
+    name: String,
+    #[serde(rename = "valueString")]
+    value_string: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ValueSet {
+    expansion: Option<Expansion>,
+}
+
+#[derive(Deserialize)]
+struct Expansion {
+    #[serde(default)]
+    contains: Vec<ExpansionConcept>,
+}
+
+#[derive(Deserialize)]
+struct ExpansionConcept {
+    code: String,
+    display: Option<String>,
+}
+
+/// A FHIR terminology server, asked through `CodeSystem/$lookup` and `ValueSet/$expand`
+struct TerminologyServer {
+    http: Client,
+    base_url: String,
+    timeout: Duration,
+}
+
+impl TerminologyServer {
+    async fn lookup(&self, system: CodeSystem, code: &str) -> Result<Option<TerminologyConcept>> {
+        let response = self
+            .http
+            .get(format!("{}/CodeSystem/$lookup", self.base_url))
+            .query(&[("system", system.uri()), ("code", code)])
+            .header("Accept", "application/fhir+json")
+            .timeout(deadline::remaining_or(self.timeout))
+            .send()
+            .await?;
+        // Servers answer an unknown code with an OperationOutcome and one of these
+        if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST) {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!("Terminology server returned {}", response.status()));
+        }
+        let parameters: Parameters = response.json().await?;
+        let display = parameters.parameter.into_iter().find(|parameter| parameter.name == "display").and_then(|parameter| parameter.value_string);
+        Ok(Some(concept(system, code, display.as_deref().unwrap_or_default())))
+    }
+
+    async fn search(&self, system: CodeSystem, text: &str, limit: usize) -> Result<Vec<TerminologyConcept>> {
+        let response = self
+            .http
+            .get(format!("{}/ValueSet/$expand", self.base_url))
+            .query(&[("url", format!("{}?fhir_vs", system.uri())), ("filter", text.to_string()), ("count", limit.to_string())])
+            .header("Accept", "application/fhir+json")
+            .timeout(deadline::remaining_or(self.timeout))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Terminology server returned {}", response.status()));
+        }
+        let value_set: ValueSet = response.json().await?;
+        Ok(value_set
+            .expansion
+            .map(|expansion| expansion.contains)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|found| concept(system, &found.code, found.display.as_deref().unwrap_or_default()))
+            .collect())
+    }
+}
+
+/// Map with a fixed capacity that evicts the least recently used entry. Eviction scans
+/// every entry, which is cheap at the few thousand lookups it is sized for.
+struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    clock: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), entries: HashMap::new(), clock: 0 }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(value, used)| {
+            *used = clock;
+            value.clone()
+        })
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| key.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(key, (value, self.clock));
+    }
+}
+
+// --- TerminologyService ---
+pub struct TerminologyService {
+    embedded: &'static EmbeddedCodes,
+    server: Option<TerminologyServer>,
+    /// Lookup results, including codes found not to exist
+    cache: Mutex<LruCache<(CodeSystem, String), Option<TerminologyConcept>>>,
+    /// Medication searches by lowercased text and limit; prescribers type the same prefixes all day
+    medication_searches: Mutex<LruCache<(String, usize), Vec<MedicationConcept>>>,
+    reject_unknown: bool,
+}
+
+impl TerminologyService {
+    pub fn new(config: &Config) -> Self {
+        Self::with_config(&config.terminology, Client::new())
+    }
+
+    pub fn with_config(config: &TerminologyConfig, http: Client) -> Self {
+        Self {
+            embedded: &EMBEDDED,
+            server: config.server_url.as_ref().map(|url| TerminologyServer {
+                http,
+                base_url: url.trim_end_matches('/').to_string(),
+                timeout: Duration::from_secs(config.timeout_seconds),
+            }),
+            cache: Mutex::new(LruCache::new(config.cache_size)),
+            medication_searches: Mutex::new(LruCache::new(config.cache_size)),
+            reject_unknown: config.reject_unknown,
+        }
+    }
+
+    /// The concept for `code`, or `None` when the code does not exist. Fails only when the
+    /// code is not embedded and the terminology server could not be asked.
+    pub async fn lookup(&self, system: CodeSystem, code: &str) -> Result<Option<TerminologyConcept>> {
+        let key = (system, code.trim().to_uppercase());
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            return Ok(cached);
+        }
+        let found = match (self.embedded.lookup(system, code.trim()), &self.server) {
+            (Some(concept), _) => Some(concept),
+            (None, Some(server)) => server.lookup(system, code.trim()).await?,
+            (None, None) => None,
+        };
+        self.cache.lock().unwrap().insert(key, found.clone());
+        Ok(found)
+    }
+
+    /// Up to `limit` concepts whose code or display matches `text`, embedded ones first.
+    /// The terminology server fills in the rest; if it is down the embedded matches are returned alone.
+    pub async fn search(&self, system: CodeSystem, text: &str, limit: usize) -> Result<Vec<TerminologyConcept>> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(anyhow!("Enter some text to search for"));
+        }
+        let limit = limit.clamp(1, MAX_SEARCH_RESULTS);
+        let mut found = self.embedded.search(system, text, limit);
+        if let Some(server) = self.server.as_ref().filter(|_| found.len() < limit) {
+            match server.search(system, text, limit).await {
+                Ok(remote) => {
+                    for concept in remote {
+                        if found.len() < limit && !found.iter().any(|known| known.code == concept.code) {
+                            found.push(concept);
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!("Terminology server search failed, returning embedded matches only: {}", e),
+            }
+        }
+        Ok(found)
+    }
+
+    /// Up to `limit` medications from the embedded RxNorm subset, best matches first
+    pub fn search_medications(&self, text: &str, limit: usize) -> Result<Vec<MedicationConcept>> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(anyhow!("Enter some text to search for"));
+        }
+        let key = (text.to_lowercase(), limit.clamp(1, MAX_SEARCH_RESULTS));
+        if let Some(cached) = self.medication_searches.lock().unwrap().get(&key) {
+            return Ok(cached);
+        }
+        let found = self.embedded.search_medications(&key.0, key.1);
+        self.medication_searches.lock().unwrap().insert(key, found.clone());
+        Ok(found)
+    }
+
+    /// Check every ICD-10, LOINC, SNOMED CT and RxNorm coding in `concepts`, filling in missing displays.
+    /// Unknown codes are returned as warnings, or refused when the configuration says so;
+    /// codes that could not be checked are always only warnings.
+    pub async fn check_codings(&self, concepts: &mut [FhirCodeableConcept]) -> Result<Vec<CodeWarning>> {
+        let mut warnings = Vec::new();
+        for coding in concepts.iter_mut().flat_map(|concept| concept.coding.iter_mut()) {
+            let (Some(system), Some(code)) = (coding.system.as_deref().and_then(CodeSystem::from_uri), coding.code.clone()) else {
+                continue;
+            };
+            let warning = |message: String| CodeWarning { system: system.uri().to_string(), code: code.clone(), message };
+            match self.lookup(system, &code).await {
+                Ok(Some(concept)) => {
+                    if coding.display.as_deref().map_or(true, |display| display.trim().is_empty()) {
+                        coding.display = Some(concept.display);
+                    }
+                }
+                Ok(None) if self.reject_unknown => return Err(anyhow!("Unknown {} code '{}'", system.label(), code)),
+                Ok(None) => warnings.push(warning(format!("Unknown {} code '{}'", system.label(), code))),
+                Err(e) => {
+                    tracing::warn!("Could not check {} code {}: {}", system.label(), code, e);
+                    warnings.push(warning(format!("{} code '{}' could not be checked", system.label(), code)));
+                }
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn embedded() -> TerminologyService {
+        TerminologyService::with_config(&TerminologyConfig::default(), Client::new())
+    }
+
+    fn with_server(server: &MockServer, reject_unknown: bool) -> TerminologyService {
+        let config = TerminologyConfig { server_url: Some(format!("{}/", server.uri())), timeout_seconds: 1, cache_size: 10, reject_unknown };
+        TerminologyService::with_config(&config, Client::new())
+    }
+
+    fn coded(system: CodeSystem, code: &str, display: Option<&str>) -> FhirCodeableConcept {
+        FhirCodeableConcept {
+            coding: vec![FhirCoding { system: Some(system.uri().to_string()), code: Some(code.to_string()), display: display.map(str::to_string) }],
+            text: None,
+        }
+    }
+
+    #[test]
+    fn every_embedded_line_parses() {
+        let codes = EmbeddedCodes::load();
+        for (system, tsv) in [(CodeSystem::Icd10, ICD10_SUBSET), (CodeSystem::Loinc, LOINC_SUBSET), (CodeSystem::Snomed, SNOMED_SUBSET), (CodeSystem::Rxnorm, RXNORM_SUBSET)] {
+            assert_eq!(codes.codes[&system].len(), tsv.lines().count());
+        }
+        // Every product has both a dose form and a strength, ingredients neither
+        assert!(codes.codes[&CodeSystem::Rxnorm].iter().all(|medication| medication.dose_form.is_some() == medication.strength.is_some()));
+    }
+
+    #[tokio::test]
+    async fn looks_up_embedded_codes() {
+        let terminology = embedded();
+
+        let hypertension = terminology.lookup(CodeSystem::Icd10, "i10").await.unwrap().unwrap();
+        assert_eq!((hypertension.code.as_str(), hypertension.display.as_str()), ("I10", "Essential (primary) hypertension"));
+        assert_eq!(hypertension.system, "http://hl7.org/fhir/sid/icd-10-cm");
+        assert_eq!(terminology.lookup(CodeSystem::Loinc, "8867-4").await.unwrap().unwrap().display, "Heart rate");
+        assert!(terminology.lookup(CodeSystem::Snomed, "8867-4").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn searches_codes_then_display_words() {
+        let terminology = embedded();
+        let codes = |found: Vec<TerminologyConcept>| found.into_iter().map(|concept| concept.code).collect::<Vec<_>>();
+
+        assert_eq!(codes(terminology.search(CodeSystem::Icd10, "diab", 10).await.unwrap()), ["E10.9", "E11.9", "E11.65"]);
+        assert_eq!(codes(terminology.search(CodeSystem::Icd10, "type 2 diab", 1).await.unwrap()), ["E11.9"]);
+        assert_eq!(codes(terminology.search(CodeSystem::Icd10, "E11", 10).await.unwrap()), ["E11.9", "E11.65"]);
+        assert_eq!(codes(terminology.search(CodeSystem::Loinc, "blood pressure", 10).await.unwrap()), ["8462-4", "8480-6", "85354-9"]);
+        assert!(terminology.search(CodeSystem::Snomed, "  ", 10).await.is_err());
+    }
+
+    #[test]
+    fn amox_surfaces_amoxicillin_first() {
+        let terminology = embedded();
+        let names = |text: &str, limit: usize| {
+            terminology.search_medications(text, limit).unwrap().into_iter().map(|medication| medication.display).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("amox", 20),
+            [
+                "amoxicillin",
+                "amoxicillin 250 MG Oral Capsule",
+                "amoxicillin 500 MG Oral Capsule",
+                "amoxicillin 875 MG Oral Tablet",
+                "amoxicillin 875 MG / clavulanate 125 MG Oral Tablet",
+            ]
+        );
+        // A later word matching beats a match inside a word
+        assert_eq!(names("metformin", 3), ["metformin", "metformin hydrochloride 500 MG Oral Tablet", "24 HR metformin hydrochloride 500 MG Extended Release Oral Tablet"]);
+        assert_eq!(names("ibuprofen 400", 5), ["ibuprofen 400 MG Oral Tablet"]);
+        assert_eq!(names("profen", 5)[0], "ibuprofen");
+        assert_eq!(names("AMOX", 1), ["amoxicillin"]);
+        assert!(terminology.search_medications(" ", 5).is_err());
+    }
+
+    #[test]
+    fn medications_expand_from_their_rxnorm_code() {
+        let capsule = medication(" 308191 ").unwrap();
+        assert_eq!((capsule.dose_form.as_deref(), capsule.strength.as_deref()), (Some("Oral Capsule"), Some("500 MG")));
+
+        let concept = capsule.codeable_concept();
+        assert_eq!(concept.coding[0].system.as_deref(), Some("http://www.nlm.nih.gov/research/umls/rxnorm"));
+        assert_eq!(concept.text.as_deref(), Some("amoxicillin 500 MG Oral Capsule"));
+        assert!(medication("11289").unwrap().dose_form.is_none());
+        assert!(medication("99999999").is_none());
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        cache.insert("c", 3);
+
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!((cache.get(&"a"), cache.get(&"c")), (Some(1), Some(3)));
+    }
+
+    #[tokio::test]
+    async fn codes_outside_the_subsets_come_from_the_server_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/CodeSystem/$lookup"))
+            .and(query_param("system", "http://snomed.info/sct"))
+            .and(query_param("code", "73211009"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "resourceType": "Parameters",
+                "parameter": [{ "name": "name", "valueString": "SNOMED CT" }, { "name": "display", "valueString": "Diabetes mellitus" }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/CodeSystem/$lookup"))
+            .and(query_param("code", "999"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "resourceType": "OperationOutcome" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let terminology = with_server(&server, false);
+
+        for _ in 0..2 {
+            assert_eq!(terminology.lookup(CodeSystem::Snomed, "73211009").await.unwrap().unwrap().display, "Diabetes mellitus");
+            assert!(terminology.lookup(CodeSystem::Snomed, "999").await.unwrap().is_none());
+        }
+        // Embedded codes never reach the server
+        assert!(terminology.lookup(CodeSystem::Snomed, "44054006").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn server_search_tops_up_embedded_matches() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ValueSet/$expand"))
+            .and(query_param("filter", "gout"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "resourceType": "ValueSet",
+                "expansion": { "contains": [
+                    { "system": "http://hl7.org/fhir/sid/icd-10-cm", "code": "M10.9", "display": "Gout, unspecified" },
+                    { "system": "http://hl7.org/fhir/sid/icd-10-cm", "code": "M10.071", "display": "Idiopathic gout, right ankle and foot" }
+                ] }
+            })))
+            .mount(&server)
+            .await;
+
+        let found = with_server(&server, false).search(CodeSystem::Icd10, "gout", 5).await.unwrap();
+        assert_eq!(found.iter().map(|concept| concept.code.as_str()).collect::<Vec<_>>(), ["M10.9", "M10.071"]);
+
+        let unavailable = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(503)).mount(&unavailable).await;
+        assert_eq!(with_server(&unavailable, false).search(CodeSystem::Icd10, "gout", 5).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn checking_codings_fills_displays_and_warns_about_unknown_codes() {
+        let terminology = embedded();
+        let mut concepts = vec![
+            coded(CodeSystem::Icd10, "E11.9", None),
+            coded(CodeSystem::Icd10, "I10", Some("High blood pressure")),
+            coded(CodeSystem::Loinc, "0000-0", None),
+            FhirCodeableConcept { coding: vec![], text: Some("Follow-up".to_string()) },
+        ];
+
+        let warnings = terminology.check_codings(&mut concepts).await.unwrap();
+
+        assert_eq!(concepts[0].coding[0].display.as_deref(), Some("Type 2 diabetes mellitus without complications"));
+        // A display the practitioner wrote is kept
+        assert_eq!(concepts[1].coding[0].display.as_deref(), Some("High blood pressure"));
+        assert_eq!(warnings, [CodeWarning { system: "http://loinc.org".to_string(), code: "0000-0".to_string(), message: "Unknown LOINC code '0000-0'".to_string() }]);
+    }
+
+    #[tokio::test]
+    async fn strict_checking_refuses_unknown_codes_but_not_unreachable_servers() {
+        let unavailable = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(503)).mount(&unavailable).await;
+        let terminology = with_server(&unavailable, true);
+
+        let warnings = terminology.check_codings(&mut [coded(CodeSystem::Snomed, "73211009", None)]).await.unwrap();
+        assert_eq!(warnings[0].message, "SNOMED CT code '73211009' could not be checked");
+
+        let strict = TerminologyService::with_config(&TerminologyConfig { reject_unknown: true, ..Default::default() }, Client::new());
+        let err = strict.check_codings(&mut [coded(CodeSystem::Icd10, "X99.9", None)]).await.unwrap_err();
+        assert_eq!(err.to_string(), "Unknown ICD-10 code 'X99.9'");
+    }
+}
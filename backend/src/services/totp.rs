@@ -0,0 +1,299 @@
+//! Authenticator-app codes (RFC 6238 TOTP) as a second factor.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::RngCore;
+use serde_json::json;
+use sha1::Sha1;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::models::*;
+use crate::services::ServiceError;
+use crate::store::TotpStore;
+use crate::utils;
+
+/// Shown as the account's issuer in authenticator apps
+const TOTP_ISSUER: &str = "WeCare";
+const STEP_SECONDS: i64 = 30;
+const DIGITS: usize = 6;
+/// 160 bits, the HMAC-SHA1 block RFC 4226 recommends
+const SECRET_BYTES: usize = 20;
+/// Codes from one step either side of now still count, for clocks that drift
+const WINDOW: i64 = 1;
+const ALREADY_ENROLLED_MESSAGE: &str = "An authenticator app is already set up; disable it before enrolling another";
+
+// --- TotpService ---
+pub struct TotpService {
+    db: Arc<dyn TotpStore>,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl TotpService {
+    pub fn new(db: Arc<dyn TotpStore>, config: Arc<Config>, audit_log_service: Arc<AuditLogService>) -> Self {
+        Self { db, config, audit_log_service }
+    }
+
+    /// Generate a new secret for the caller's app. It only counts as a factor once
+    /// [`confirm`](Self::confirm) has seen a code from it; enrolling again before then starts over.
+    pub async fn enroll(&self, did: &str) -> Result<TotpEnrollmentStarted> {
+        if self.db.get_totp(did).await?.is_some_and(|enrollment| enrollment.confirmed_at.is_some()) {
+            return Err(ServiceError::Conflict(ALREADY_ENROLLED_MESSAGE.to_string()).into());
+        }
+        let mut secret = Zeroizing::new([0u8; SECRET_BYTES]);
+        rand::thread_rng().fill_bytes(secret.as_mut_slice());
+        let enrollment = TotpEnrollment {
+            encrypted_secret: utils::encrypt(secret.as_slice(), &self.config.ipfs_encryption_key)?,
+            confirmed_at: None,
+            last_counter: None,
+            created_at: Utc::now(),
+        };
+        if !self.db.start_totp_enrollment(did, &enrollment).await? {
+            return Err(anyhow!("No account found for {}", did));
+        }
+        self.audit_log_service.log(did, "totp_enrollment_started", None).await;
+
+        let secret = base32(secret.as_slice());
+        let otpauth_uri = otpauth_uri(did, &secret);
+        let qr_svg = QrCode::new(otpauth_uri.as_bytes())?.render::<svg::Color>().min_dimensions(256, 256).build();
+        Ok(TotpEnrollmentStarted { secret, otpauth_uri, qr_svg })
+    }
+
+    /// Activate a pending enrollment with the first code the app shows
+    pub async fn confirm(&self, did: &str, code: &str) -> Result<()> {
+        let enrollment = self.db.get_totp(did).await?.ok_or_else(|| anyhow!("Start enrolling an authenticator app first"))?;
+        if enrollment.confirmed_at.is_some() {
+            return Err(ServiceError::Conflict(ALREADY_ENROLLED_MESSAGE.to_string()).into());
+        }
+        let Some(counter) = matching_counter(&self.secret(&enrollment)?, code, Utc::now()) else {
+            self.audit_log_service.log(did, "totp_confirmation_failed", None).await;
+            return Err(anyhow!("Invalid code"));
+        };
+        if !self.db.confirm_totp(did, counter).await? {
+            return Err(ServiceError::Conflict(ALREADY_ENROLLED_MESSAGE.to_string()).into());
+        }
+        self.audit_log_service.log(did, "totp_enabled", None).await;
+        Ok(())
+    }
+
+    /// Whether `code` comes from the caller's confirmed app. A code is accepted at most once,
+    /// and never after a code from a later time step.
+    pub async fn verify(&self, did: &str, code: &str) -> Result<bool> {
+        let enrollment = match self.db.get_totp(did).await? {
+            Some(enrollment) if enrollment.confirmed_at.is_some() => enrollment,
+            _ => return Err(anyhow!("No authenticator app is set up")),
+        };
+        let Some(counter) = matching_counter(&self.secret(&enrollment)?, code, Utc::now()) else {
+            return Ok(false);
+        };
+        if enrollment.last_counter.is_some_and(|last| counter <= last) {
+            tracing::warn!("Rejected a replayed authenticator code");
+            return Ok(false);
+        }
+        // Loses to a concurrent request that used the same code first
+        self.db.accept_totp_counter(did, counter).await
+    }
+
+    /// Turn the factor off; needs a currently valid code from the app
+    pub async fn disable(&self, did: &str, code: &str) -> Result<()> {
+        if !self.verify(did, code).await? {
+            self.audit_log_service.log(did, "totp_disable_failed", None).await;
+            return Err(anyhow!("Invalid code"));
+        }
+        self.db.remove_totp(did).await?;
+        self.audit_log_service.log(did, "totp_disabled", None).await;
+        Ok(())
+    }
+
+    /// Remove a patient's enrollment for them, e.g. after they lost the device
+    pub async fn admin_disable(&self, admin_did: &str, did: &str) -> Result<()> {
+        if !self.db.remove_totp(did).await? {
+            return Err(anyhow!("No authenticator app is set up for {}", did));
+        }
+        self.audit_log_service.log(did, "totp_disabled", Some(json!({ "actor": admin_did, "admin_override": true }))).await;
+        Ok(())
+    }
+
+    fn secret(&self, enrollment: &TotpEnrollment) -> Result<Zeroizing<Vec<u8>>> {
+        utils::decrypt(&enrollment.encrypted_secret, &self.config.ipfs_encryption_key).map(Zeroizing::new)
+    }
+}
+
+/// The code an app shows for time step `counter` (RFC 6238 with HMAC-SHA1, six digits)
+pub fn code_at(secret: &[u8], counter: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // Dynamic truncation from RFC 4226 section 5.3
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+/// The time step within the window around `now` whose code is `code`
+pub fn matching_counter(secret: &[u8], code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let current = now.timestamp().div_euclid(STEP_SECONDS);
+    (current - WINDOW..=current + WINDOW).find(|counter| constant_time_eq(code_at(secret, *counter).as_bytes(), code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// RFC 4648 base32 without padding, the form authenticator apps expect
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Key URI in the format authenticator apps scan, labelled with the account's DID
+fn otpauth_uri(did: &str, secret: &str) -> String {
+    let label = did.replace(':', "%3A");
+    format!(
+        "otpauth://totp/{issuer}:{label}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = TOTP_ISSUER,
+    )
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAuditStore, MockTotpStore};
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    /// The SHA-1 key of the RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_vectors() {
+        // The RFC lists eight digits; apps show the last six
+        for (time, code) in [(59, "287082"), (1_111_111_109, "081804"), (1_234_567_890, "005924"), (2_000_000_000, "279037")] {
+            assert_eq!(code_at(RFC_SECRET, time / STEP_SECONDS), code, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        let now = at(1_111_111_109);
+        let step = now.timestamp() / STEP_SECONDS;
+
+        assert_eq!(matching_counter(RFC_SECRET, &code_at(RFC_SECRET, step), now), Some(step));
+        assert_eq!(matching_counter(RFC_SECRET, &code_at(RFC_SECRET, step - 1), now), Some(step - 1));
+        assert_eq!(matching_counter(RFC_SECRET, &code_at(RFC_SECRET, step + 1), now), Some(step + 1));
+        assert_eq!(matching_counter(RFC_SECRET, &code_at(RFC_SECRET, step - 2), now), None);
+        assert_eq!(matching_counter(RFC_SECRET, "08180", now), None);
+        assert_eq!(matching_counter(RFC_SECRET, "o81804", now), None);
+    }
+
+    #[test]
+    fn secrets_are_base32_in_the_key_uri() {
+        assert_eq!(base32(RFC_SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(
+            otpauth_uri(PATIENT, "GEZDGNBV"),
+            "otpauth://totp/WeCare:did%3Ahedera%3Atestnet%3A0.0.1?secret=GEZDGNBV&issuer=WeCare&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    /// A service over one patient's enrollment, kept in memory
+    fn service(enrollment: Arc<Mutex<Option<TotpEnrollment>>>) -> (TotpService, Arc<Mutex<Vec<String>>>) {
+        let mut db = MockTotpStore::new();
+        let stored = enrollment.clone();
+        db.expect_get_totp().returning(move |_| Ok(stored.lock().unwrap().clone()));
+        let stored = enrollment.clone();
+        db.expect_start_totp_enrollment().returning(move |_, enrollment| {
+            *stored.lock().unwrap() = Some(enrollment.clone());
+            Ok(true)
+        });
+        let stored = enrollment.clone();
+        db.expect_confirm_totp().returning(move |_, counter| {
+            let mut stored = stored.lock().unwrap();
+            let enrollment = stored.as_mut().unwrap();
+            enrollment.confirmed_at = Some(Utc::now());
+            enrollment.last_counter = Some(counter);
+            Ok(true)
+        });
+        let stored = enrollment.clone();
+        db.expect_accept_totp_counter().returning(move |_, counter| {
+            let mut stored = stored.lock().unwrap();
+            let enrollment = stored.as_mut().unwrap();
+            if enrollment.last_counter.is_some_and(|last| counter <= last) {
+                return Ok(false);
+            }
+            enrollment.last_counter = Some(counter);
+            Ok(true)
+        });
+        let stored = enrollment;
+        db.expect_remove_totp().returning(move |_| Ok(stored.lock().unwrap().take().is_some()));
+
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let mut audit_store = MockAuditStore::new();
+        let logged = actions.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.action.clone());
+            Ok(())
+        });
+        let config = Arc::new(Config { ipfs_encryption_key: "00".repeat(32).into(), ..Default::default() });
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        (TotpService::new(Arc::new(db), config, audit_log_service), actions)
+    }
+
+    #[tokio::test]
+    async fn an_enrolled_code_is_accepted_once_and_disabling_needs_a_fresh_one() {
+        let enrollment = Arc::new(Mutex::new(None));
+        let (service, actions) = service(enrollment.clone());
+
+        let started = service.enroll(PATIENT).await.unwrap();
+        assert!(started.otpauth_uri.contains(&started.secret));
+        assert!(started.qr_svg.starts_with("<?xml"));
+        let secret = utils::decrypt(&enrollment.lock().unwrap().as_ref().unwrap().encrypted_secret, &"00".repeat(32).into()).unwrap();
+        assert_eq!(base32(&secret), started.secret);
+        let step = Utc::now().timestamp().div_euclid(STEP_SECONDS);
+
+        assert!(service.verify(PATIENT, &code_at(&secret, step)).await.is_err(), "unconfirmed apps are no factor yet");
+        service.confirm(PATIENT, &code_at(&secret, step - 1)).await.unwrap();
+        let enrolled_again = service.enroll(PATIENT).await.unwrap_err();
+        assert!(matches!(enrolled_again.downcast_ref::<ServiceError>(), Some(ServiceError::Conflict(_))));
+
+        assert!(service.verify(PATIENT, &code_at(&secret, step)).await.unwrap());
+        assert!(!service.verify(PATIENT, &code_at(&secret, step)).await.unwrap(), "a code is only good once");
+        assert!(!service.verify(PATIENT, &code_at(&secret, step - 1)).await.unwrap(), "nor is an older one");
+
+        assert!(service.disable(PATIENT, &code_at(&secret, step)).await.is_err());
+        service.disable(PATIENT, &code_at(&secret, step + 1)).await.unwrap();
+        assert!(enrollment.lock().unwrap().is_none());
+        assert_eq!(
+            *actions.lock().unwrap(),
+            ["totp_enrollment_started", "totp_enabled", "totp_disable_failed", "totp_disabled"]
+        );
+    }
+}
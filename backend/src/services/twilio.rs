@@ -1,23 +1,281 @@
-use twilio::{Client, OutboundMessage};
-use crate::config::Config;
-use anyhow::anyhow;
+use anyhow::Result;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::time::{sleep, Duration};
 
+use crate::config::{Config, TwilioConfig};
+use crate::i18n;
+use crate::services::ServiceError;
+use crate::utils::SecretString;
+
+const TWILIO_API_BASE_URL: &str = "https://api.twilio.com";
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum SmsError {
+    #[error("invalid phone number: {0}")]
+    InvalidNumber(String),
+    #[error("phone number is unreachable: {0}")]
+    Unreachable(String),
+    #[error("SMS quota exceeded, try again later")]
+    QuotaExceeded,
+    #[error("SMS provider error: {0}")]
+    Provider(String),
+}
+
+#[derive(Deserialize)]
+struct MessageResponse {
+    sid: String,
+}
+
+#[derive(Deserialize)]
+struct TwilioErrorResponse {
+    code: Option<u32>,
+    message: Option<String>,
+}
+
+/// Sends SMS through the Twilio Messages REST API without blocking the runtime
 pub struct TwilioService {
-    client: Client,
+    http: Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: SecretString,
     from_phone_number: String,
 }
 
 impl TwilioService {
-    pub fn new(config: &Config) -> Self {
-        let client = Client::new(&config.twilio_account_sid, &config.twilio_auth_token);
-        let from_phone_number = config.twilio_phone_number.clone();
-        Self { client, from_phone_number }
+    pub fn new(config: &TwilioConfig, http: Client) -> Self {
+        Self {
+            http,
+            base_url: TWILIO_API_BASE_URL.to_string(),
+            account_sid: config.account_sid.clone(),
+            auth_token: config.auth_token.clone(),
+            from_phone_number: config.phone_number.clone(),
+        }
+    }
+
+    /// Point the client at a different API host (used by tests)
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
     }
 
-    pub fn send_otp(&self, to: &str, otp: &str) -> anyhow::Result<()> {
-        let body = format!("Your OTP is: {}", otp);
-        let message = OutboundMessage::new(&self.from_phone_number, to, &body);
-        self.client.send_message(message).map_err(|e| anyhow!("{:?}", e))?;
-        Ok(())
+    /// Text `otp` in the language of the request being served
+    pub async fn send_otp(&self, to: &str, otp: &str) -> Result<String, SmsError> {
+        self.send_sms(to, &i18n::text(i18n::current(), "sms.otp", &[("code", otp)])).await
+    }
+
+    /// Send a message and return its Twilio message SID.
+    ///
+    /// Rate limiting (429) and server errors are retried with backoff; anything
+    /// else is returned immediately.
+    #[tracing::instrument(name = "twilio.send_sms", skip_all, fields(otel.kind = "client"))]
+    pub async fn send_sms(&self, to: &str, body: &str) -> Result<String, SmsError> {
+        let url = format!("{}/2010-04-01/Accounts/{}/Messages.json", self.base_url, self.account_sid);
+        let mut attempt = 1;
+        loop {
+            let response = self
+                .http
+                .post(&url)
+                .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+                .form(&[("To", to), ("From", self.from_phone_number.as_str()), ("Body", body)])
+                .send()
+                .await;
+
+            let retry_after = match response {
+                Ok(response) if response.status().is_success() => {
+                    let message: MessageResponse = response
+                        .json()
+                        .await
+                        .map_err(|e| SmsError::Provider(format!("unexpected response: {}", e)))?;
+                    return Ok(message.sid);
+                }
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error() => {
+                    if attempt >= MAX_ATTEMPTS {
+                        return Err(Self::classify(response).await);
+                    }
+                    response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse().ok())
+                        .map(|seconds| Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+                }
+                Ok(response) => return Err(Self::classify(response).await),
+                Err(e) if attempt >= MAX_ATTEMPTS => return Err(SmsError::Provider(e.to_string())),
+                Err(_) => None,
+            };
+
+            let delay = retry_after.unwrap_or(RETRY_BASE_DELAY * 2u32.pow(attempt - 1));
+            tracing::warn!(attempt, "Twilio request failed; retrying in {:?}", delay);
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn classify(response: reqwest::Response) -> SmsError {
+        let status = response.status();
+        let error: Option<TwilioErrorResponse> = response.json().await.ok();
+        let code = error.as_ref().and_then(|e| e.code);
+        let message = error
+            .and_then(|e| e.message)
+            .unwrap_or_else(|| status.to_string());
+
+        match (status, code) {
+            (StatusCode::TOO_MANY_REQUESTS, _) | (_, Some(20429)) => SmsError::QuotaExceeded,
+            // 21211: invalid 'To' number, 21614: not a mobile number
+            (_, Some(21211)) | (_, Some(21614)) => SmsError::InvalidNumber(message),
+            // 21610: recipient unsubscribed, 21612: route not available, 30003/30005: handset unreachable or unknown
+            (_, Some(21610)) | (_, Some(21612)) | (_, Some(30003)) | (_, Some(30005)) => SmsError::Unreachable(message),
+            _ => SmsError::Provider(message),
+        }
+    }
+}
+
+/// SMS delivery, or an explicit error when no provider is configured
+pub enum SmsSender {
+    Twilio(TwilioService),
+    Disabled,
+}
+
+impl SmsSender {
+    pub fn from_config(config: &Config, http: Client) -> Self {
+        match &config.twilio {
+            Some(twilio) => Self::Twilio(TwilioService::new(twilio, http)),
+            None => Self::Disabled,
+        }
+    }
+
+    pub async fn send_otp(&self, to: &str, otp: &str) -> anyhow::Result<String> {
+        match self {
+            Self::Twilio(twilio) => Ok(twilio.send_otp(to, otp).await?),
+            Self::Disabled => Err(ServiceError::NotConfigured("phone auth is not configured on this server".to_string()).into()),
+        }
+    }
+
+    pub async fn send_sms(&self, to: &str, body: &str) -> anyhow::Result<String> {
+        match self {
+            Self::Twilio(twilio) => Ok(twilio.send_sms(to, body).await?),
+            Self::Disabled => Err(ServiceError::NotConfigured("SMS is not configured on this server".to_string()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{basic_auth, body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const MESSAGES_PATH: &str = "/2010-04-01/Accounts/AC123/Messages.json";
+
+    async fn service() -> (MockServer, TwilioService) {
+        let server = MockServer::start().await;
+        let config = TwilioConfig {
+            account_sid: "AC123".to_string(),
+            auth_token: "token".into(),
+            phone_number: "+15555550000".to_string(),
+            ..Default::default()
+        };
+        let service = TwilioService::new(&config, Client::new()).with_base_url(&server.uri());
+        (server, service)
+    }
+
+    #[tokio::test]
+    async fn sends_message_and_returns_sid() {
+        let (server, service) = service().await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .and(basic_auth("AC123", "token"))
+            .and(body_string_contains("Body=Your+OTP+is%3A+123456"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "sid": "SM1", "status": "queued" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let sid = service.send_otp("+15555550100", "123456").await.unwrap();
+
+        assert_eq!(sid, "SM1");
+    }
+
+    #[tokio::test]
+    async fn codes_are_sent_in_the_requests_language() {
+        let (server, service) = service().await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .and(body_string_contains("Body=Nambari+yako+ya+kuthibitisha+ni%3A+123456"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "sid": "SM1", "status": "queued" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        i18n::scope(crate::models::Locale::Sw, service.send_otp("+15555550100", "123456")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_number_is_not_retried() {
+        let (server, service) = service().await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "code": 21211,
+                "message": "The 'To' number 12 is not a valid phone number.",
+                "status": 400
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = service.send_sms("12", "hi").await.unwrap_err();
+
+        assert!(matches!(err, SmsError::InvalidNumber(_)));
+    }
+
+    #[tokio::test]
+    async fn rate_limited_request_is_retried() {
+        let (server, service) = service().await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({ "code": 20429, "message": "Too Many Requests" })))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({ "sid": "SM2" })))
+            .mount(&server)
+            .await;
+
+        let sid = service.send_sms("+15555550100", "hi").await.unwrap();
+
+        assert_eq!(sid, "SM2");
+    }
+
+    #[tokio::test]
+    async fn persistent_rate_limit_reports_quota_exceeded() {
+        let (server, service) = service().await;
+        Mock::given(method("POST"))
+            .and(path(MESSAGES_PATH))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({ "code": 20429, "message": "Too Many Requests" })))
+            .expect(u64::from(MAX_ATTEMPTS))
+            .mount(&server)
+            .await;
+
+        let err = service.send_sms("+15555550100", "hi").await.unwrap_err();
+
+        assert!(matches!(err, SmsError::QuotaExceeded));
+    }
+
+    #[tokio::test]
+    async fn disabled_sender_reports_not_configured() {
+        let err = SmsSender::Disabled.send_otp("+15555550100", "123456").await.unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::NotConfigured(_))));
+        assert_eq!(err.to_string(), "phone auth is not configured on this server");
     }
 }
@@ -1,28 +1,979 @@
-
+use anyhow::anyhow;
+use bson::oid::ObjectId;
+use chrono::{Duration, TimeZone, Utc};
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use uuid::Uuid;
 
-use crate::database::Database;
-use crate::services::ipfs::IpfsClient;
-use crate::services::hedera::HealthcareHederaService;
+use crate::config::Config;
+use crate::events::{DomainEvent, EventBus};
+use crate::models::*;
+use crate::store::{CredentialStore, HederaCostStore, PractitionerStore};
+use crate::services::ipfs::ObjectStorage;
+use crate::services::credential_batch;
+use crate::services::hedera::LedgerAnchor;
+use crate::services::issuer_registry::IssuerRegistryService;
 use crate::auditing::AuditLogService;
+use crate::services::status_list::{StatusListService, STATUS_LIST_CONTEXT};
+use crate::services::vc_document::{canonical_json, CredentialSigner, W3cCredentialBuilder};
+use crate::services::ServiceError;
 use crate::api::handlers::IssueCredentialRequest;
+use crate::tenancy;
+use crate::utils;
+use crate::utils::SecretString;
+
+/// What checking a presented credential found, locally and on the ledger
+#[derive(Debug, Clone)]
+pub enum CredentialCheck {
+    Valid(VerifiableCredential),
+    Unknown,
+    Revoked,
+    Expired,
+    /// Known here, but the ledger does not confirm it
+    NotAnchored,
+}
+
+impl CredentialCheck {
+    /// Why the credential cannot be relied on, unless it can
+    pub fn problem(&self) -> Option<&'static str> {
+        match self {
+            Self::Valid(_) => None,
+            Self::Unknown => Some("Unknown credential"),
+            Self::Revoked => Some("The credential was revoked"),
+            Self::Expired => Some("The credential has expired"),
+            Self::NotAnchored => Some("The credential could not be confirmed on the ledger"),
+        }
+    }
+}
+
+/// How many documents of a batch are signed and uploaded at once
+const BATCH_UPLOAD_CONCURRENCY: usize = 8;
+
+/// A credential the caller may issue, checked and ready to sign
+struct CredentialDraft {
+    issuer: TrustedIssuer,
+    credential_type: String,
+    claims: serde_json::Map<String, Value>,
+    expires_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// What a presentation token signs
+#[derive(Debug, Serialize, Deserialize)]
+struct PresentationClaims {
+    /// The credential's id
+    cid: String,
+    /// The credential's subject
+    sub: String,
+    /// The verifier the presentation is for
+    aud: String,
+    iat: i64,
+    exp: i64,
+    jti: String,
+}
 
 // --- VerifiableCredentialService ---
 pub struct VerifiableCredentialService {
-    db: Arc<Database>,
-    ipfs_client: Arc<IpfsClient>,
-    hedera_service: Arc<HealthcareHederaService>,
+    db: Arc<dyn CredentialStore>,
+    ipfs_client: Arc<dyn ObjectStorage>,
+    hedera_service: Arc<dyn LedgerAnchor>,
+    config: Arc<Config>,
     audit_log_service: Arc<AuditLogService>,
+    events: Arc<EventBus>,
+    status_lists: Arc<StatusListService>,
+    issuers: Arc<IssuerRegistryService>,
+    practitioners: Arc<dyn PractitionerStore>,
+    fee_history: Option<Arc<dyn HederaCostStore>>,
 }
 
 impl VerifiableCredentialService {
-    pub fn new(db: Arc<Database>, ipfs_client: Arc<IpfsClient>, hedera_service: Arc<HealthcareHederaService>, audit_log_service: Arc<AuditLogService>) -> Self {
-        Self { db, ipfs_client, hedera_service, audit_log_service }
+    pub fn new(
+        db: Arc<dyn CredentialStore>,
+        ipfs_client: Arc<dyn ObjectStorage>,
+        hedera_service: Arc<dyn LedgerAnchor>,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+        events: Arc<EventBus>,
+        status_lists: Arc<StatusListService>,
+        issuers: Arc<IssuerRegistryService>,
+        practitioners: Arc<dyn PractitionerStore>,
+    ) -> Self {
+        Self { db, ipfs_client, hedera_service, config, audit_log_service, events, status_lists, issuers, practitioners, fee_history: None }
+    }
+
+    /// Price what batch issuance saves at the fees recently paid for single credentials
+    pub fn with_fee_history(mut self, fee_history: Arc<dyn HederaCostStore>) -> Self {
+        self.fee_history = Some(fee_history);
+        self
+    }
+
+    /// Issue a credential as the caller, who has to be an active registered issuer allowed to
+    /// issue its type and, when signed in as a practitioner, hold a verified and unexpired
+    /// license; patients never issue. Anyone refused is audit-logged as a security event. The
+    /// document is signed by the platform and names the caller, as the registry knows them,
+    /// in `issuedBy`.
+    pub async fn issue_credential(&self, caller_did: &str, caller_role: Role, request: IssueCredentialRequest) -> anyhow::Result<VerifiableCredential> {
+        let draft = self.authorize_issue(caller_did, caller_role, &request).await?;
+        let signer = self.signer()?;
+        let expires_at = request.expires_at;
+        let credential = self.sign_and_upload(&signer, draft, request).await?;
+        self.db.create_verifiable_credential(&credential, &credential_outbox(&credential, expires_at)).await?;
+        self.issued(caller_did, &credential).await;
+        Ok(credential)
+    }
+
+    /// Issue up to `CREDENTIAL_BATCH_MAX_SIZE` credentials as the caller in one go, as after a
+    /// vaccination clinic day. Each is checked as [`Self::issue_credential`] checks one, and one
+    /// refusal refuses the whole batch before anything is uploaded. Every document still goes to
+    /// IPFS on its own, but the ledger gets a single Merkle root over all of them, and each
+    /// credential keeps the proof leading from it to that root.
+    pub async fn issue_credential_batch(&self, caller_did: &str, caller_role: Role, requests: Vec<IssueCredentialRequest>) -> anyhow::Result<IssuedCredentialBatch> {
+        let max_size = self.config.credential_batch_max_size;
+        if requests.is_empty() || requests.len() > max_size {
+            return Err(anyhow!("A batch holds between 1 and {} credentials, not {}", max_size, requests.len()));
+        }
+        let mut drafts = Vec::with_capacity(requests.len());
+        for (index, request) in requests.iter().enumerate() {
+            let draft = self.authorize_issue(caller_did, caller_role, request).await.map_err(|e| match e.downcast::<ServiceError>() {
+                Ok(refused) => refused.into(),
+                Err(e) => anyhow!("Credential {} of the batch: {}", index + 1, e),
+            })?;
+            drafts.push(draft);
+        }
+        let signer = self.signer()?;
+        let mut credentials: Vec<VerifiableCredential> = stream::iter(drafts.into_iter().zip(requests))
+            .map(|(draft, request)| self.sign_and_upload(&signer, draft, request))
+            .buffered(BATCH_UPLOAD_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let batch_id = ObjectId::new();
+        let ipfs_hashes: Vec<&str> = credentials.iter().map(|credential| credential.ipfs_hash.as_str()).collect();
+        let (root, proofs) = credential_batch::prove(batch_id, &ipfs_hashes).ok_or_else(|| anyhow!("A batch needs credentials"))?;
+        for (credential, proof) in credentials.iter_mut().zip(proofs) {
+            credential.batch = Some(proof);
+        }
+        let batch = CredentialBatch {
+            id: Some(batch_id),
+            merkle_root: hex::encode(root),
+            size: credentials.len() as u32,
+            issuer: caller_did.to_string(),
+            created_at: Utc::now(),
+            hedera_transaction_id: None,
+        };
+        let mut outbox: Vec<OutboxAction> = credentials.iter().map(|credential| OutboxAction::Pin { ipfs_hash: credential.ipfs_hash.clone() }).collect();
+        outbox.push(OutboxAction::AnchorCredentialBatch { batch_id, merkle_root: batch.merkle_root.clone(), size: batch.size });
+        self.db.create_credential_batch(&batch, &credentials, &outbox).await?;
+        for credential in &credentials {
+            self.issued(caller_did, credential).await;
+        }
+
+        let fees = credential_batch::fee_comparison(batch.size, &self.recent_single_fees().await);
+        Ok(IssuedCredentialBatch { batch_id, merkle_root: batch.merkle_root, credentials, fees })
+    }
+
+    /// Check the caller may issue `request`, and that it is well formed
+    async fn authorize_issue(&self, caller_did: &str, caller_role: Role, request: &IssueCredentialRequest) -> anyhow::Result<CredentialDraft> {
+        let credential_type = request.credential_type.trim();
+        if credential_type.is_empty() || request.subject_did.trim().is_empty() {
+            return Err(anyhow!("A credential needs a subject_did and a credential_type"));
+        }
+        let license_problem = match caller_role {
+            Role::Patient => Some("Patients cannot issue credentials"),
+            Role::Practitioner => self.license_problem(caller_did).await?,
+            Role::Admin | Role::PlatformAdmin => None,
+        };
+        let issuer = match license_problem {
+            Some(problem) => Err(problem.to_string()),
+            None => match self.issuers.find(caller_did).await? {
+                Some(issuer) if issuer.status != IssuerStatus::Active => Err("Your issuer registration is suspended".to_string()),
+                Some(issuer) if !issuer.credential_types.iter().any(|allowed| allowed == credential_type) => {
+                    Err(format!("You are not registered to issue {}", credential_type))
+                }
+                Some(issuer) => Ok(issuer),
+                None => Err("You are not a registered credential issuer".to_string()),
+            },
+        };
+        let issuer = match issuer {
+            Ok(issuer) => issuer,
+            Err(reason) => {
+                self.audit_log_service
+                    .log(
+                        &request.subject_did,
+                        "issue_credential_denied",
+                        Some(json!({ "actor": caller_did, "credential_type": credential_type, "reason": reason, "suspicious": true })),
+                    )
+                    .await;
+                return Err(ServiceError::Forbidden(reason).into());
+            }
+        };
+        let Ok(Value::Object(claims)) = serde_json::from_str::<Value>(&request.metadata) else {
+            return Err(anyhow!("metadata must be a JSON object"));
+        };
+        let issued_at = Utc::now();
+        let expires_at = request
+            .expires_at
+            .map(|expires_at| Utc.timestamp_opt(expires_at as i64, 0).single().ok_or_else(|| anyhow!("Invalid expires_at")))
+            .transpose()?;
+        if expires_at.is_some_and(|expires_at| expires_at <= issued_at) {
+            return Err(anyhow!("expires_at has to be in the future"));
+        }
+        Ok(CredentialDraft { issuer, credential_type: credential_type.to_string(), claims, expires_at })
+    }
+
+    /// Sign the credential's W3C document and add it to IPFS, encrypted
+    async fn sign_and_upload(&self, signer: &CredentialSigner, draft: CredentialDraft, request: IssueCredentialRequest) -> anyhow::Result<VerifiableCredential> {
+        let CredentialDraft { issuer, credential_type, claims, expires_at } = draft;
+        let issued_at = Utc::now();
+        let credential_id = ObjectId::new();
+        let status_entry = self.status_lists.allocate().await?;
+        let mut builder = W3cCredentialBuilder::new(&credential_type, &request.subject_did, issued_at)
+            .with_id(&self.credential_url(credential_id))
+            .with_context(STATUS_LIST_CONTEXT)
+            .with_expiration(expires_at)
+            .with_status(self.status_lists.credential_status(&status_entry));
+        for (name, value) in claims {
+            builder = builder.with_claim(&name, value);
+        }
+        let document = builder.with_claim("issuedBy", json!({ "id": issuer.did, "name": issuer.display_name })).sign(signer);
+        let encrypted = utils::encrypt(canonical_json(&document).as_bytes(), &self.config.ipfs_encryption_key)?;
+        // An upload whose credential is never saved only leaves an unreferenced object behind
+        let ipfs_hash = self.ipfs_client.add_file(encrypted.as_bytes(), None).await?;
+
+        Ok(VerifiableCredential {
+            id: Some(credential_id),
+            subject_did: request.subject_did,
+            credential_type,
+            issuer: issuer.did,
+            issued_at,
+            expires_at,
+            ipfs_hash,
+            hedera_transaction_id: None,
+            metadata: request.metadata,
+            revoked_at: None,
+            status_list: Some(status_entry),
+            batch: None,
+        })
+    }
+
+    /// Audit-log a saved credential and tell subscribers about it
+    async fn issued(&self, caller_did: &str, credential: &VerifiableCredential) {
+        let details = match &credential.batch {
+            Some(batch) => json!({ "actor": caller_did, "batch_id": batch.batch_id.to_hex() }),
+            None => json!({ "actor": caller_did }),
+        };
+        self.audit_log_service
+            .log(&credential.subject_did, &format!("issue_credential: {}", credential.credential_type), Some(details))
+            .await;
+        self.events.publish(DomainEvent::CredentialIssued {
+            credential_id: credential.id.expect("credentials get their id before they are stored").to_hex(),
+            patient_did: credential.subject_did.clone(),
+            credential_type: credential.credential_type.clone(),
+            tenant_id: tenancy::current(),
+        });
+    }
+
+    /// Fees of the credentials stored on the ledger one by one over the last 30 days, in tinybars
+    async fn recent_single_fees(&self) -> Vec<i64> {
+        let Some(fee_history) = &self.fee_history else {
+            return Vec::new();
+        };
+        let now = Utc::now();
+        match fee_history.hedera_transactions(now - Duration::days(30), now).await {
+            Ok(transactions) => transactions
+                .iter()
+                .filter(|transaction| transaction.operation == HederaOperation::CredentialIssuance)
+                .map(|transaction| transaction.fee_tinybars)
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Failed to read recent credential fees: {:#}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Why a practitioner may not issue credentials, if they may not: only a practitioner on
+    /// record whose license is verified and has not expired issues anything
+    async fn license_problem(&self, practitioner_did: &str) -> anyhow::Result<Option<&'static str>> {
+        let Some(practitioner) = self.practitioners.get_practitioner_by_did(practitioner_did).await? else {
+            return Ok(Some("You are not a registered practitioner"));
+        };
+        let license = &practitioner.license_verification;
+        if !license.verified {
+            return Ok(Some("Your license is not verified"));
+        }
+        // Checked here too, for the day between the date passing and the expiry job running
+        Ok((!license.is_current(Utc::now().date_naive())).then_some("Your license has expired"))
+    }
+
+    /// Whether `did` is a trusted issuer in the registry
+    pub async fn issuer_registered(&self, did: &str) -> anyhow::Result<bool> {
+        self.issuers.is_registered(did).await
+    }
+
+    /// Issue the credential a pharmacy checks instead of reading the record. The signed W3C
+    /// document is encrypted on IPFS; the ledger only gets its hash and metadata naming no patient.
+    pub async fn issue_prescription_credential(&self, prescription: &Prescription) -> anyhow::Result<PrescriptionCredential> {
+        let id = prescription.id.ok_or_else(|| anyhow!("A prescription needs an id before a credential can be issued for it"))?;
+        let signer = self.signer()?;
+        let medication = &prescription.fhir_medication_request.medication_codeable_concept;
+        let metadata = PrescriptionCredentialMetadata {
+            medication_code: medication.coding.iter().find_map(|coding| coding.code.clone()),
+            medication: medication.text.clone().or_else(|| medication.coding.iter().find_map(|coding| coding.display.clone())),
+            quantity: prescription.fhir_medication_request.dispense_request.as_ref().and_then(|request| request.quantity.clone()),
+            prescriber_did: prescription.practitioner_did.clone(),
+            prescription_hash: prescription_hash(&id.to_hex()),
+        };
+        let issued_at = Utc::now();
+        let credential_id = ObjectId::new();
+        let status_entry = self.status_lists.allocate().await?;
+        let document = W3cCredentialBuilder::new(PRESCRIPTION_CREDENTIAL_TYPE, &prescription.patient_did, issued_at)
+            .with_id(&self.credential_url(credential_id))
+            .with_context(STATUS_LIST_CONTEXT)
+            .with_claim("prescription", serde_json::to_value(&metadata)?)
+            .with_status(self.status_lists.credential_status(&status_entry))
+            .sign(&signer);
+        let encrypted = utils::encrypt(canonical_json(&document).as_bytes(), &self.config.ipfs_encryption_key)?;
+        let ipfs_hash = self.ipfs_client.add_file(encrypted.as_bytes(), None).await?;
+
+        let metadata = serde_json::to_string(&metadata)?;
+        let credential = VerifiableCredential {
+            id: Some(credential_id),
+            subject_did: prescription.patient_did.clone(),
+            credential_type: PRESCRIPTION_CREDENTIAL_TYPE.to_string(),
+            issuer: signer.issuer_did().to_string(),
+            issued_at,
+            expires_at: None,
+            ipfs_hash: ipfs_hash.clone(),
+            hedera_transaction_id: None,
+            metadata,
+            revoked_at: None,
+            status_list: Some(status_entry),
+            batch: None,
+        };
+        self.db.create_verifiable_credential(&credential, &credential_outbox(&credential, None)).await?;
+        self.audit_log_service.log(&prescription.patient_did, &format!("issue_credential: {}", PRESCRIPTION_CREDENTIAL_TYPE), None).await;
+        self.events.publish(DomainEvent::CredentialIssued {
+            credential_id: credential_id.to_hex(),
+            patient_did: prescription.patient_did.clone(),
+            credential_type: PRESCRIPTION_CREDENTIAL_TYPE.to_string(),
+            tenant_id: tenancy::current(),
+        });
+
+        let qr_payload = format!("rx:{}:{}", ipfs_hash, prescription_hash(&id.to_hex()));
+        Ok(PrescriptionCredential { hash: ipfs_hash, qr_payload })
+    }
+
+    /// Check a credential of `credential_type` by its IPFS hash: known here, not revoked or
+    /// expired, not revoked in its published status list, and confirmed by the ledger, directly
+    /// or through its batch's Merkle proof
+    pub async fn check_credential(&self, ipfs_hash: &str, credential_type: &str) -> anyhow::Result<CredentialCheck> {
+        let Some(credential) = self.db.get_credential_by_hash(ipfs_hash).await?.filter(|c| c.credential_type == credential_type) else {
+            return Ok(CredentialCheck::Unknown);
+        };
+        if credential.revoked_at.is_some() {
+            return Ok(CredentialCheck::Revoked);
+        }
+        if credential.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Ok(CredentialCheck::Expired);
+        }
+        // What offline verifiers see, and cheaper than the ledger
+        if let Some(entry) = &credential.status_list {
+            if self.status_lists.is_revoked(entry).await? {
+                return Ok(CredentialCheck::Revoked);
+            }
+        }
+        // A batch-issued credential is on the ledger through its batch's root
+        let anchored = match &credential.batch {
+            Some(batch) => match credential_batch::proven_root(&credential.ipfs_hash, batch) {
+                Some(root) => self.hedera_service.verify_credential_batch(root).await?,
+                None => false,
+            },
+            None => self.hedera_service.verify_credential(ipfs_hash.as_bytes()).await?,
+        };
+        if !anchored {
+            return Ok(CredentialCheck::NotAnchored);
+        }
+        Ok(CredentialCheck::Valid(credential))
+    }
+
+    /// The credential's W3C document, as stored on IPFS and anchored on the ledger; for its subject only
+    pub async fn credential_document(&self, caller_did: &str, credential_id: &str) -> anyhow::Result<serde_json::Value> {
+        let id = ObjectId::parse_str(credential_id).map_err(|_| anyhow!("Invalid credential id"))?;
+        let credential = self.db.get_credential(id).await?.ok_or_else(|| anyhow!("Credential not found"))?;
+        if credential.subject_did != caller_did {
+            return Err(ServiceError::Forbidden("Only the credential's subject can read it".to_string()).into());
+        }
+        let stored = self.ipfs_client.get_file(&credential.ipfs_hash).await?;
+        let document = utils::decrypt(std::str::from_utf8(&stored)?, &self.config.ipfs_encryption_key)?;
+        Ok(serde_json::from_slice(&document)?)
+    }
+
+    /// Sign a presentation of the caller's own credential for `audience`, valid for
+    /// `CREDENTIAL_PRESENTATION_MINUTES`, and render it as a QR code
+    pub async fn present_credential(&self, owner_did: &str, credential_id: &str, audience: &str) -> anyhow::Result<CredentialPresentation> {
+        let secret = self.presentation_secret()?;
+        let audience = audience.trim();
+        if audience.is_empty() {
+            return Err(anyhow!("Name the verifier the presentation is for"));
+        }
+        let id = ObjectId::parse_str(credential_id).map_err(|_| anyhow!("Invalid credential id"))?;
+        let credential = self.db.get_credential(id).await?.ok_or_else(|| anyhow!("Credential not found"))?;
+        if credential.subject_did != owner_did {
+            return Err(ServiceError::Forbidden("Only the credential's subject can present it".to_string()).into());
+        }
+        if credential.revoked_at.is_some() {
+            return Err(ServiceError::Conflict("The credential was revoked".to_string()).into());
+        }
+        if credential.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(ServiceError::Conflict("The credential has expired".to_string()).into());
+        }
+
+        let issued_at = Utc::now();
+        let expires_at = issued_at + Duration::minutes(self.config.credential_presentation_minutes);
+        let claims = PresentationClaims {
+            cid: id.to_hex(),
+            sub: credential.subject_did.clone(),
+            aud: audience.to_string(),
+            iat: issued_at.timestamp(),
+            exp: expires_at.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+        };
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+        let qr_svg = QrCode::new(token.as_bytes())?.render::<svg::Color>().min_dimensions(256, 256).build();
+        self.audit_log_service
+            .log(
+                owner_did,
+                "present_credential",
+                Some(json!({ "credential_id": claims.cid, "credential_type": credential.credential_type, "audience": audience, "expires_at": expires_at })),
+            )
+            .await;
+        Ok(CredentialPresentation { token, audience: audience.to_string(), expires_at, qr_svg })
+    }
+
+    /// Check a scanned presentation: signed by us, for `verifier_did`, not expired, and for a
+    /// credential that still verifies locally and on the ledger
+    pub async fn verify_presentation(&self, verifier_did: &str, token: &str) -> anyhow::Result<PresentationVerdict> {
+        let secret = self.presentation_secret()?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        validation.set_audience(&[verifier_did]);
+        validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+        let claims = match decode::<PresentationClaims>(token.trim(), &DecodingKey::from_secret(secret.as_bytes()), &validation) {
+            Ok(data) => data.claims,
+            Err(e) => {
+                let reason = match e.kind() {
+                    ErrorKind::ExpiredSignature => "The presentation has expired",
+                    ErrorKind::InvalidAudience => "The presentation was made for another verifier",
+                    _ => "The presentation is not valid",
+                };
+                self.audit_log_service
+                    .log(verifier_did, "verify_credential_presentation", Some(json!({ "valid": false, "reason": reason })))
+                    .await;
+                return Ok(PresentationVerdict { valid: false, reason: Some(reason.to_string()), ..Default::default() });
+            }
+        };
+
+        let credential = match ObjectId::parse_str(&claims.cid) {
+            Ok(id) => self.db.get_credential(id).await?.filter(|credential| credential.subject_did == claims.sub),
+            Err(_) => None,
+        };
+        let problem = match &credential {
+            Some(credential) => self.check_credential(&credential.ipfs_hash, &credential.credential_type).await?.problem(),
+            None => CredentialCheck::Unknown.problem(),
+        };
+        let issuer_registered = match &credential {
+            Some(credential) => Some(self.issuer_registered(&credential.issuer).await?),
+            None => None,
+        };
+        self.audit_log_service
+            .log(
+                &claims.sub,
+                "verify_credential_presentation",
+                Some(json!({ "actor": verifier_did, "credential_id": claims.cid, "valid": problem.is_none(), "reason": problem })),
+            )
+            .await;
+        Ok(PresentationVerdict {
+            valid: problem.is_none(),
+            reason: problem.map(str::to_string),
+            credential_id: Some(claims.cid),
+            subject_did: Some(claims.sub),
+            credential_type: credential.as_ref().map(|credential| credential.credential_type.clone()),
+            issuer: credential.as_ref().map(|credential| credential.issuer.clone()),
+            issuer_registered,
+            expires_at: Utc.timestamp_opt(claims.exp, 0).single(),
+        })
+    }
+
+    fn signer(&self) -> anyhow::Result<CredentialSigner> {
+        match &self.config.credential_signing {
+            Some(signing) => CredentialSigner::from_config(signing),
+            None => Err(ServiceError::NotConfigured("Credential signing is not configured".to_string()).into()),
+        }
+    }
+
+    /// Where the credential's document can be fetched, which is also its W3C `id`
+    fn credential_url(&self, id: ObjectId) -> String {
+        format!("{}/api/credentials/{}", self.config.backend_base_url.trim_end_matches('/'), id.to_hex())
     }
 
-    pub async fn issue_credential(&self, request: IssueCredentialRequest) -> anyhow::Result<String> {
-        self.audit_log_service.log(&request.subject_did, &format!("issue_credential: {}", request.credential_type), None).await;
-        // ... implementation
-        Ok("".to_string())
+    fn presentation_secret(&self) -> anyhow::Result<&str> {
+        self.config
+            .credential_presentation_secret
+            .as_ref()
+            .map(SecretString::expose_secret)
+            .ok_or_else(|| ServiceError::NotConfigured("Credential presentations are not configured".to_string()).into())
+    }
+
+    /// Stop a credential from verifying, and set its bit in its status list for verifiers
+    /// checking offline; the ledger entry stays
+    pub async fn revoke_credential(&self, actor_did: &str, ipfs_hash: &str) -> anyhow::Result<bool> {
+        let Some(credential) = self.db.get_credential_by_hash(ipfs_hash).await? else {
+            return Ok(false);
+        };
+        let revoked = self.db.revoke_credential(ipfs_hash).await?;
+        if revoked {
+            self.audit_log_service
+                .log(&credential.subject_did, &format!("revoke_credential: {}", credential.credential_type), Some(json!({ "actor": actor_did })))
+                .await;
+            if let Some(entry) = &credential.status_list {
+                self.status_lists.revoked(entry).await?;
+            }
+        }
+        Ok(revoked)
+    }
+}
+
+/// What the outbox owes a new credential: pinning its document and storing it on the ledger
+fn credential_outbox(credential: &VerifiableCredential, expires_at: Option<u64>) -> [OutboxAction; 2] {
+    [
+        OutboxAction::Pin { ipfs_hash: credential.ipfs_hash.clone() },
+        OutboxAction::StoreCredential {
+            credential_id: credential.id.expect("credentials get their id before they are stored"),
+            subject_did: credential.subject_did.clone(),
+            credential_type: credential.credential_type.clone(),
+            ipfs_hash: credential.ipfs_hash.clone(),
+            expires_at,
+            metadata: credential.metadata.clone(),
+        },
+    ]
+}
+
+/// Hex SHA-256 of a prescription id, which is all a prescription credential reveals of it
+pub fn prescription_hash(prescription_id: &str) -> String {
+    format!("{:x}", Sha256::digest(prescription_id.as_bytes()))
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::config::CredentialSigningConfig;
+    use crate::services::fakes::{InMemoryObjectStorage, RecordingLedgerAnchor};
+    use crate::services::fhir::FhirManager;
+    use std::sync::Mutex;
+    use crate::store::{MockAuditStore, MockCredentialStore, MockIssuerStore, MockPractitionerStore, MockStatusListStore};
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+    const FRONT_DESK: &str = "did:hedera:testnet:0.0.7";
+    const SECRET: &str = "presentation-secret-for-unit-tests-only";
+    const ISSUER: &str = "did:hedera:testnet:0.0.5";
+    /// A registered issuer of vaccination credentials
+    const REGISTRAR: &str = "did:hedera:testnet:0.0.2";
+    const SUSPENDED: &str = "did:hedera:testnet:0.0.3";
+    /// Registered issuers whose practitioner license is unverified, or has lapsed
+    const UNVERIFIED: &str = "did:hedera:testnet:0.0.4";
+    const LAPSED: &str = "did:hedera:testnet:0.0.6";
+
+    fn trusted(did: &str, status: IssuerStatus) -> TrustedIssuer {
+        TrustedIssuer {
+            id: Some(ObjectId::new()),
+            did: did.to_string(),
+            display_name: "County Immunization Programme".to_string(),
+            credential_types: vec!["VaccinationCredential".to_string()],
+            status,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn practitioner(did: &str, verified: bool, expiry_date: &str) -> Practitioner {
+        Practitioner {
+            id: None,
+            did: did.to_string(),
+            fhir_practitioner: FhirPractitioner {
+                resource_type: "Practitioner".to_string(),
+                id: did.to_string(),
+                identifier: vec![],
+                name: vec![],
+                qualification: vec![],
+                telecom: vec![],
+            },
+            license_verification: LicenseVerification {
+                license_number: "KMPDC-1".to_string(),
+                issuing_authority: "KMPDC".to_string(),
+                issue_date: "2020-01-01".to_string(),
+                expiry_date: expiry_date.to_string(),
+                hedera_transaction_id: String::new(),
+                ipfs_hash: String::new(),
+                verified,
+                expiry_warning_sent_at: None,
+            },
+            practitioner_type: PractitionerType::default(),
+            tenant_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn credential(id: ObjectId) -> VerifiableCredential {
+        VerifiableCredential {
+            id: Some(id),
+            subject_did: PATIENT.to_string(),
+            credential_type: "VaccinationCredential".to_string(),
+            issuer: REGISTRAR.to_string(),
+            issued_at: Utc::now(),
+            expires_at: None,
+            ipfs_hash: "QmVaccination".to_string(),
+            hedera_transaction_id: Some("0.0.2@1".to_string()),
+            metadata: "{}".to_string(),
+            revoked_at: None,
+            status_list: None,
+            batch: None,
+        }
+    }
+
+    async fn service(id: ObjectId) -> VerifiableCredentialService {
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_get_credential().returning(move |requested| Ok((requested == id).then(|| credential(id))));
+        credentials.expect_get_credential_by_hash().returning(move |_| Ok(Some(credential(id))));
+        let ledger = RecordingLedgerAnchor::new();
+        ledger.store_credential(PATIENT, "VaccinationCredential", "QmVaccination", None, "{}").await.unwrap();
+        service_with(credentials, ledger, Arc::new(InMemoryObjectStorage::new()))
+    }
+
+    fn service_with(credentials: MockCredentialStore, ledger: RecordingLedgerAnchor, ipfs: Arc<InMemoryObjectStorage>) -> VerifiableCredentialService {
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        service_with_audit(credentials, ledger, ipfs, audit_store)
+    }
+
+    fn service_with_audit(
+        credentials: MockCredentialStore,
+        ledger: RecordingLedgerAnchor,
+        ipfs: Arc<InMemoryObjectStorage>,
+        audit_store: MockAuditStore,
+    ) -> VerifiableCredentialService {
+        let audit_log_service = Arc::new(AuditLogService::new(Arc::new(audit_store)));
+        let config = Arc::new(Config {
+            ipfs_encryption_key: "00".repeat(32).into(),
+            backend_base_url: "https://api.example.com".to_string(),
+            credential_signing: Some(CredentialSigningConfig { issuer_did: ISSUER.to_string(), key: "07".repeat(32).into(), key_id: "key-1".to_string() }),
+            credential_presentation_secret: Some(SECRET.into()),
+            credential_presentation_minutes: 5,
+            credential_batch_max_size: 5,
+            ..Default::default()
+        });
+        let mut lists = MockStatusListStore::new();
+        lists.expect_allocate_status_index().returning(|_| Ok(StatusListEntry { list_id: "1".to_string(), index: 0 }));
+        lists.expect_get_status_list().returning(|_| Ok(None));
+        let status_lists = Arc::new(StatusListService::new(Arc::new(lists), Arc::new(MockCredentialStore::new()), ipfs.clone(), config.clone()));
+        let mut issuers = MockIssuerStore::new();
+        issuers.expect_get_issuer().returning(|did| {
+            Ok(match did {
+                REGISTRAR | UNVERIFIED | LAPSED => Some(trusted(did, IssuerStatus::Active)),
+                SUSPENDED => Some(trusted(SUSPENDED, IssuerStatus::Suspended)),
+                _ => None,
+            })
+        });
+        let issuers = Arc::new(IssuerRegistryService::new(Arc::new(issuers), config.clone(), audit_log_service.clone()));
+        let mut practitioners = MockPractitionerStore::new();
+        practitioners.expect_get_practitioner_by_did().returning(|did| {
+            Ok(match did {
+                REGISTRAR | SUSPENDED => Some(practitioner(did, true, "2099-01-01")),
+                UNVERIFIED => Some(practitioner(did, false, "2099-01-01")),
+                LAPSED => Some(practitioner(did, true, "2020-01-01")),
+                _ => None,
+            })
+        });
+        VerifiableCredentialService::new(
+            Arc::new(credentials),
+            ipfs,
+            Arc::new(ledger),
+            config,
+            audit_log_service,
+            Arc::new(EventBus::new(16)),
+            status_lists,
+            issuers,
+            Arc::new(practitioners),
+        )
+    }
+
+    #[tokio::test]
+    async fn issued_credentials_are_stored_as_signed_w3c_documents() {
+        let stored: Arc<Mutex<Option<VerifiableCredential>>> = Arc::default();
+        let mut credentials = MockCredentialStore::new();
+        let created = stored.clone();
+        credentials.expect_create_verifiable_credential().times(1).returning(move |credential, outbox| {
+            // Pinned and put on the ledger by the outbox, once the credential is saved
+            assert!(
+                matches!(outbox, [OutboxAction::Pin { ipfs_hash }, OutboxAction::StoreCredential { credential_id, .. }]
+                    if *ipfs_hash == credential.ipfs_hash && credential.id == Some(*credential_id)),
+                "{:?}",
+                outbox
+            );
+            assert_eq!(credential.hedera_transaction_id, None);
+            *created.lock().unwrap() = Some(credential.clone());
+            Ok(())
+        });
+        let known = stored.clone();
+        credentials.expect_get_credential().returning(move |_| Ok(known.lock().unwrap().clone()));
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let service = service_with(credentials, RecordingLedgerAnchor::new(), ipfs.clone());
+        let prescription = Prescription {
+            id: Some(ObjectId::new()),
+            patient_did: PATIENT.to_string(),
+            practitioner_did: "did:hedera:testnet:0.0.2".to_string(),
+            fhir_medication_request: FhirManager::create_medication_request(
+                PATIENT,
+                "did:hedera:testnet:0.0.2",
+                None,
+                FhirCodeableConcept { coding: vec![], text: Some("Amoxicillin 500mg".to_string()) },
+                vec![],
+            ),
+            status: PrescriptionStatus::Active,
+            dispenses: vec![],
+            fully_dispensed: false,
+            credential: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let issued = service.issue_prescription_credential(&prescription).await.unwrap();
+
+        let record = stored.lock().unwrap().clone().unwrap();
+        assert_eq!(record.issuer, ISSUER);
+        let id = record.id.unwrap().to_hex();
+        let document = service.credential_document(PATIENT, &id).await.unwrap();
+        assert_eq!(document["id"], format!("https://api.example.com/api/credentials/{}", id));
+        assert_eq!(document["type"], json!(["VerifiableCredential", PRESCRIPTION_CREDENTIAL_TYPE]));
+        assert_eq!(document["issuer"], ISSUER);
+        assert_eq!(document["credentialSubject"]["id"], PATIENT);
+        assert_eq!(document["credentialSubject"]["prescription"]["prescriber_did"], "did:hedera:testnet:0.0.2");
+        assert_eq!(document["proof"]["verificationMethod"], "did:hedera:testnet:0.0.5#key-1");
+        assert_eq!(record.status_list, Some(StatusListEntry { list_id: "1".to_string(), index: 0 }));
+        assert_eq!(document["credentialStatus"]["statusListCredential"], "https://api.example.com/api/credentials/status-list/1");
+        assert_eq!(document["credentialStatus"]["statusListIndex"], "0");
+        // What went on the ledger is the hash of exactly this document
+        let blob = ipfs.get_file(&issued.hash).await.unwrap();
+        assert_eq!(utils::decrypt(std::str::from_utf8(&blob).unwrap(), &"00".repeat(32).into()).unwrap(), canonical_json(&document).into_bytes());
+
+        let err = service.credential_document("did:hedera:testnet:0.0.9", &id).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn only_registered_issuers_issue_their_credential_types() {
+        let stored: Arc<Mutex<Option<VerifiableCredential>>> = Arc::default();
+        let mut credentials = MockCredentialStore::new();
+        let created = stored.clone();
+        credentials.expect_create_verifiable_credential().times(1).returning(move |credential, _| {
+            *created.lock().unwrap() = Some(credential.clone());
+            Ok(())
+        });
+        let known = stored.clone();
+        credentials.expect_get_credential().returning(move |_| Ok(known.lock().unwrap().clone()));
+        let logs: Arc<Mutex<Vec<AuditLog>>> = Arc::default();
+        let mut audit_store = MockAuditStore::new();
+        let logged = logs.clone();
+        audit_store.expect_create_audit_log().returning(move |log| {
+            logged.lock().unwrap().push(log.clone());
+            Ok(())
+        });
+        let service = service_with_audit(credentials, RecordingLedgerAnchor::new(), Arc::new(InMemoryObjectStorage::new()), audit_store);
+        let request = |credential_type: &str| IssueCredentialRequest {
+            subject_did: PATIENT.to_string(),
+            credential_type: credential_type.to_string(),
+            expires_at: None,
+            metadata: r#"{"vaccine":{"code":"208"}}"#.to_string(),
+        };
+
+        for (caller, role, credential_type) in [
+            ("did:hedera:testnet:0.0.9", Role::Practitioner, "VaccinationCredential"),
+            (SUSPENDED, Role::Practitioner, "VaccinationCredential"),
+            (REGISTRAR, Role::Practitioner, "MedicalLicenseCredential"),
+            // Registered as issuers, but not practitioners anyone can rely on yet
+            (UNVERIFIED, Role::Practitioner, "VaccinationCredential"),
+            (LAPSED, Role::Practitioner, "VaccinationCredential"),
+            // Nor does a patient's session issue anything, whatever the registry says of their DID
+            (REGISTRAR, Role::Patient, "VaccinationCredential"),
+        ] {
+            let err = service.issue_credential(caller, role, request(credential_type)).await.unwrap_err();
+            assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))), "{} issued {}", caller, credential_type);
+        }
+        let denied: Vec<AuditLog> = logs.lock().unwrap().iter().filter(|log| log.action == "issue_credential_denied").cloned().collect();
+        let reasons: Vec<&Value> = denied.iter().map(|log| &log.details.as_ref().unwrap()["reason"]).collect();
+        assert_eq!(
+            reasons,
+            [
+                "You are not a registered practitioner",
+                "Your issuer registration is suspended",
+                "You are not registered to issue MedicalLicenseCredential",
+                "Your license is not verified",
+                "Your license has expired",
+                "Patients cannot issue credentials",
+            ]
+        );
+        assert_eq!(denied[1].details.as_ref().unwrap()["actor"], SUSPENDED);
+        assert!(denied.iter().all(|log| log.details.as_ref().unwrap()["suspicious"] == true));
+
+        let issued = service.issue_credential(REGISTRAR, Role::Practitioner, request("VaccinationCredential")).await.unwrap();
+        assert_eq!(issued.issuer, REGISTRAR);
+        let document = service.credential_document(PATIENT, &issued.id.unwrap().to_hex()).await.unwrap();
+        // Signed by the platform, naming the registered issuer
+        assert_eq!(document["issuer"], ISSUER);
+        assert_eq!(document["credentialSubject"]["issuedBy"], json!({ "id": REGISTRAR, "name": "County Immunization Programme" }));
+        assert_eq!(document["credentialSubject"]["vaccine"]["code"], "208");
+    }
+
+    #[tokio::test]
+    async fn presentations_verify_for_their_audience_only() {
+        let id = ObjectId::new();
+        let service = service(id).await;
+
+        let presentation = service.present_credential(PATIENT, &id.to_hex(), FRONT_DESK).await.unwrap();
+        assert!(presentation.qr_svg.starts_with("<?xml"));
+        assert!(presentation.expires_at <= Utc::now() + Duration::minutes(5));
+
+        let verdict = service.verify_presentation(FRONT_DESK, &presentation.token).await.unwrap();
+        assert!(verdict.valid, "{:?}", verdict.reason);
+        assert_eq!(verdict.subject_did.as_deref(), Some(PATIENT));
+        assert_eq!(verdict.credential_type.as_deref(), Some("VaccinationCredential"));
+        assert_eq!(verdict.issuer_registered, Some(true));
+
+        let elsewhere = service.verify_presentation("did:hedera:testnet:0.0.8", &presentation.token).await.unwrap();
+        assert_eq!(elsewhere.reason.as_deref(), Some("The presentation was made for another verifier"));
+
+        let err = service.present_credential("did:hedera:testnet:0.0.9", &id.to_hex(), FRONT_DESK).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn expired_and_tampered_presentations_are_rejected() {
+        let id = ObjectId::new();
+        let service = service(id).await;
+
+        let now = Utc::now().timestamp();
+        let expired = PresentationClaims {
+            cid: id.to_hex(),
+            sub: PATIENT.to_string(),
+            aud: FRONT_DESK.to_string(),
+            iat: now - 600,
+            exp: now - 300,
+            jti: Uuid::new_v4().to_string(),
+        };
+        let expired = encode(&Header::new(Algorithm::HS256), &expired, &EncodingKey::from_secret(SECRET.as_bytes())).unwrap();
+        let verdict = service.verify_presentation(FRONT_DESK, &expired).await.unwrap();
+        assert!(!verdict.valid);
+        assert_eq!(verdict.reason.as_deref(), Some("The presentation has expired"));
+
+        // Swap in another subject while keeping the original signature
+        let token = service.present_credential(PATIENT, &id.to_hex(), FRONT_DESK).await.unwrap().token;
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let forged_claims = json!({ "cid": id.to_hex(), "sub": "did:hedera:testnet:0.0.66", "aud": FRONT_DESK, "iat": now, "exp": now + 300, "jti": "x" });
+        let forged_payload = base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, forged_claims.to_string());
+        parts[1] = &forged_payload;
+        let verdict = service.verify_presentation(FRONT_DESK, &parts.join(".")).await.unwrap();
+        assert!(!verdict.valid);
+        assert_eq!(verdict.reason.as_deref(), Some("The presentation is not valid"));
+
+        let signed_elsewhere = encode(
+            &Header::new(Algorithm::HS256),
+            &PresentationClaims { cid: id.to_hex(), sub: PATIENT.to_string(), aud: FRONT_DESK.to_string(), iat: now, exp: now + 300, jti: "y".to_string() },
+            &EncodingKey::from_secret(b"some-other-secret-that-is-long-enough"),
+        )
+        .unwrap();
+        assert!(!service.verify_presentation(FRONT_DESK, &signed_elsewhere).await.unwrap().valid);
+    }
+
+    #[tokio::test]
+    async fn batch_credentials_verify_through_their_merkle_proof() {
+        let stored: Arc<Mutex<Vec<VerifiableCredential>>> = Arc::default();
+        let mut credentials = MockCredentialStore::new();
+        let created = stored.clone();
+        credentials.expect_create_credential_batch().times(1).returning(move |batch, issued, outbox| {
+            // Every document is pinned, but only the root goes on the ledger
+            assert_eq!(outbox.len(), issued.len() + 1);
+            assert!(
+                matches!(outbox.last(), Some(OutboxAction::AnchorCredentialBatch { batch_id, merkle_root, size: 5 })
+                    if batch.id == Some(*batch_id) && *merkle_root == batch.merkle_root),
+                "{:?}",
+                outbox
+            );
+            *created.lock().unwrap() = issued.to_vec();
+            Ok(())
+        });
+        let known = stored.clone();
+        credentials
+            .expect_get_credential_by_hash()
+            .returning(move |ipfs_hash| Ok(known.lock().unwrap().iter().find(|credential| credential.ipfs_hash == ipfs_hash).cloned()));
+        let service = service_with(credentials, RecordingLedgerAnchor::new(), Arc::new(InMemoryObjectStorage::new()));
+        let request = |patient: u32| IssueCredentialRequest {
+            subject_did: format!("did:hedera:testnet:0.0.{}", 1000 + patient),
+            credential_type: "VaccinationCredential".to_string(),
+            expires_at: None,
+            metadata: r#"{"vaccine":{"code":"208"}}"#.to_string(),
+        };
+
+        let batch = service.issue_credential_batch(REGISTRAR, Role::Practitioner, (0..5).map(request).collect()).await.unwrap();
+        assert_eq!(batch.credentials.len(), 5);
+        assert_eq!((batch.fees.ledger_calls, batch.fees.ledger_calls_if_issued_singly), (1, 5));
+        let middle = batch.credentials[2].clone();
+        assert_eq!(middle.subject_did, "did:hedera:testnet:0.0.1002");
+        assert_eq!(middle.batch.as_ref().map(|proof| (proof.leaf_index, proof.leaf_count)), Some((2, 5)));
+        // Until the outbox anchors the root, nothing confirms it
+        let pending = service.check_credential(&middle.ipfs_hash, "VaccinationCredential").await.unwrap();
+        assert!(matches!(pending, CredentialCheck::NotAnchored));
+
+        let root: [u8; 32] = hex::decode(&batch.merkle_root).unwrap().try_into().unwrap();
+        service.hedera_service.store_credential_batch(root, 5).await.unwrap();
+        let check = service.check_credential(&middle.ipfs_hash, "VaccinationCredential").await.unwrap();
+        assert!(matches!(check, CredentialCheck::Valid(_)), "{:?}", check);
+
+        // A proof edited in the database no longer leads to the anchored root
+        stored.lock().unwrap()[2].batch.as_mut().unwrap().proof[0] = hex::encode([9; 32]);
+        let tampered = service.check_credential(&middle.ipfs_hash, "VaccinationCredential").await.unwrap();
+        assert!(matches!(tampered, CredentialCheck::NotAnchored));
+    }
+
+    #[tokio::test]
+    async fn one_refused_credential_refuses_the_whole_batch() {
+        let mut credentials = MockCredentialStore::new();
+        credentials.expect_create_credential_batch().never();
+        let ipfs = Arc::new(InMemoryObjectStorage::new());
+        let service = service_with(credentials, RecordingLedgerAnchor::new(), ipfs.clone());
+        let request = |credential_type: &str| IssueCredentialRequest {
+            subject_did: PATIENT.to_string(),
+            credential_type: credential_type.to_string(),
+            expires_at: None,
+            metadata: "{}".to_string(),
+        };
+
+        let mixed = vec![request("VaccinationCredential"), request("MedicalLicenseCredential")];
+        let err = service.issue_credential_batch(REGISTRAR, Role::Practitioner, mixed).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ServiceError>(), Some(ServiceError::Forbidden(_))));
+        let malformed = vec![request("VaccinationCredential"), IssueCredentialRequest { metadata: "[]".to_string(), ..request("VaccinationCredential") }];
+        let err = service.issue_credential_batch(REGISTRAR, Role::Practitioner, malformed).await.unwrap_err();
+        assert_eq!(err.to_string(), "Credential 2 of the batch: metadata must be a JSON object");
+        for size in [0, 6] {
+            let requests = (0..size).map(|_| request("VaccinationCredential")).collect();
+            assert!(service.issue_credential_batch(REGISTRAR, Role::Practitioner, requests).await.is_err(), "{}", size);
+        }
+        assert_eq!(ipfs.upload_count(), 0);
     }
 }
@@ -0,0 +1,296 @@
+//! Renders issued credentials as W3C Verifiable Credentials (data model 1.1), proved with
+//! an EdDSA-signed JWT-VC that external wallets can import and verify.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::config::CredentialSigningConfig;
+
+pub const VC_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+/// Content type of a credential document
+pub const VC_MEDIA_TYPE: &str = "application/vc+json";
+/// Multicodec prefix of an Ed25519 public key in `publicKeyMultibase`
+const ED25519_PUB_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// The platform's issuing identity and the Ed25519 key it signs credentials with
+pub struct CredentialSigner {
+    issuer_did: String,
+    verification_method: String,
+    signing_key: SigningKey,
+}
+
+impl CredentialSigner {
+    pub fn new(issuer_did: &str, key_id: &str, seed: [u8; 32]) -> Self {
+        Self {
+            issuer_did: issuer_did.to_string(),
+            verification_method: format!("{}#{}", issuer_did, key_id),
+            signing_key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    pub fn from_config(config: &CredentialSigningConfig) -> Result<Self> {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        hex::decode_to_slice(config.key.expose_secret(), seed.as_mut_slice())
+            .map_err(|_| anyhow!("CREDENTIAL_SIGNING_KEY must be 32 bytes"))?;
+        Ok(Self::new(&config.issuer_did, &config.key_id, *seed))
+    }
+
+    pub fn issuer_did(&self) -> &str {
+        &self.issuer_did
+    }
+
+    /// DID URL of the key, as verifiers look it up in the issuer's DID document
+    pub fn verification_method(&self) -> &str {
+        &self.verification_method
+    }
+
+    /// The public key as it is published in the DID document
+    pub fn public_key_multibase(&self) -> String {
+        let mut bytes = ED25519_PUB_MULTICODEC.to_vec();
+        bytes.extend_from_slice(self.signing_key.verifying_key().as_bytes());
+        format!("z{}", bs58::encode(bytes).into_string())
+    }
+
+    /// A detached Ed25519 signature over `message`, base64url without padding
+    pub fn sign_detached(&self, message: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.sign(message).to_bytes())
+    }
+
+    /// A compact JWS over `claims`, signed with EdDSA
+    fn sign_jwt(&self, claims: &Value) -> String {
+        let header = json!({ "alg": "EdDSA", "kid": self.verification_method, "typ": "JWT" });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(canonical_json(&header)),
+            URL_SAFE_NO_PAD.encode(canonical_json(claims))
+        );
+        let signature = self.signing_key.sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+}
+
+/// Builds the W3C document for one credential
+pub struct W3cCredentialBuilder {
+    id: String,
+    credential_type: String,
+    subject_did: String,
+    claims: Map<String, Value>,
+    contexts: Vec<String>,
+    status: Option<Value>,
+    issuance_date: DateTime<Utc>,
+    expiration_date: Option<DateTime<Utc>>,
+}
+
+impl W3cCredentialBuilder {
+    pub fn new(credential_type: &str, subject_did: &str, issuance_date: DateTime<Utc>) -> Self {
+        Self {
+            id: format!("urn:uuid:{}", Uuid::new_v4()),
+            credential_type: credential_type.to_string(),
+            subject_did: subject_did.to_string(),
+            claims: Map::new(),
+            contexts: vec![VC_CONTEXT.to_string()],
+            status: None,
+            issuance_date,
+            expiration_date: None,
+        }
+    }
+
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = id.to_string();
+        self
+    }
+
+    /// Add a property of `credentialSubject` next to its `id`
+    pub fn with_claim(mut self, name: &str, value: Value) -> Self {
+        self.claims.insert(name.to_string(), value);
+        self
+    }
+
+    /// Add a JSON-LD context after the base one, for the terms of an extension
+    pub fn with_context(mut self, context: &str) -> Self {
+        self.contexts.push(context.to_string());
+        self
+    }
+
+    /// Set `credentialStatus`, where verifiers check whether the credential was revoked
+    pub fn with_status(mut self, status: Value) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_expiration(mut self, expiration_date: Option<DateTime<Utc>>) -> Self {
+        self.expiration_date = expiration_date;
+        self
+    }
+
+    /// The credential with a `JwtProof2020` proof. The JWT carries the credential in its
+    /// `vc` claim, as the data model's JWT encoding has it, so the proof covers every field.
+    pub fn sign(self, signer: &CredentialSigner) -> Value {
+        let mut subject = self.claims;
+        subject.insert("id".to_string(), json!(self.subject_did));
+        let mut credential = json!({
+            "@context": self.contexts,
+            "id": self.id,
+            "type": ["VerifiableCredential", self.credential_type],
+            "issuer": signer.issuer_did,
+            "issuanceDate": xsd_date_time(self.issuance_date),
+            "credentialSubject": subject,
+        });
+        if let Some(status) = self.status {
+            credential["credentialStatus"] = status;
+        }
+        let mut claims = json!({
+            "iss": signer.issuer_did,
+            "sub": self.subject_did,
+            "jti": self.id,
+            "nbf": self.issuance_date.timestamp(),
+            "vc": credential.clone(),
+        });
+        if let Some(expiration_date) = self.expiration_date {
+            credential["expirationDate"] = json!(xsd_date_time(expiration_date));
+            claims["exp"] = json!(expiration_date.timestamp());
+            claims["vc"]["expirationDate"] = credential["expirationDate"].clone();
+        }
+        credential["proof"] = json!({
+            "type": "JwtProof2020",
+            "created": xsd_date_time(self.issuance_date),
+            "verificationMethod": signer.verification_method,
+            "proofPurpose": "assertionMethod",
+            "jwt": signer.sign_jwt(&claims),
+        });
+        credential
+    }
+}
+
+/// Check a signature from [`CredentialSigner::sign_detached`] with the key published as `public_key_multibase`
+pub fn verify_detached(public_key_multibase: &str, message: &[u8], signature: &str) -> bool {
+    let Some(key) = public_key_multibase.strip_prefix('z').and_then(|encoded| bs58::decode(encoded).into_vec().ok()) else {
+        return false;
+    };
+    let Some(key) = key.strip_prefix(&ED25519_PUB_MULTICODEC[..]).and_then(|key| <[u8; 32]>::try_from(key).ok()) else {
+        return false;
+    };
+    let (Ok(key), Ok(signature)) = (VerifyingKey::from_bytes(&key), URL_SAFE_NO_PAD.decode(signature)) else {
+        return false;
+    };
+    Signature::from_slice(&signature).is_ok_and(|signature| key.verify(message, &signature).is_ok())
+}
+
+/// JSON with object members sorted by name and no insignificant whitespace, so the same
+/// credential always serializes, and therefore signs and hashes, to the same bytes
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut members: Vec<_> = map.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let members: Vec<String> = members
+                .into_iter()
+                .map(|(name, value)| format!("{}:{}", Value::String(name.clone()), canonical_json(value)))
+                .collect();
+            format!("{{{}}}", members.join(","))
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical_json).collect::<Vec<_>>().join(",")),
+        scalar => scalar.to_string(),
+    }
+}
+
+/// An `xsd:dateTime` to the second, in UTC
+fn xsd_date_time(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const ISSUER: &str = "did:hedera:testnet:0.0.5";
+    const SUBJECT: &str = "did:hedera:testnet:0.0.1";
+
+    fn signer() -> CredentialSigner {
+        CredentialSigner::new(ISSUER, "key-1", [7; 32])
+    }
+
+    fn credential() -> Value {
+        W3cCredentialBuilder::new("VaccinationCredential", SUBJECT, Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap())
+            .with_id("urn:uuid:00000000-0000-4000-8000-000000000001")
+            .with_claim("vaccine", json!({ "code": "208", "lot": "EK9231" }))
+            .with_expiration(Some(Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap()))
+            .sign(&signer())
+    }
+
+    #[test]
+    fn canonical_json_sorts_members_at_every_level() {
+        let value = json!({ "b": [{ "z": 1, "a": "x\"y" }], "a": null, "@context": true });
+        assert_eq!(canonical_json(&value), r#"{"@context":true,"a":null,"b":[{"a":"x\"y","z":1}]}"#);
+    }
+
+    #[test]
+    fn credentials_follow_the_data_model() {
+        let credential = credential();
+
+        assert_eq!(credential["@context"], json!([VC_CONTEXT]));
+        assert_eq!(credential["type"], json!(["VerifiableCredential", "VaccinationCredential"]));
+        assert_eq!(credential["issuer"], ISSUER);
+        assert_eq!(credential["issuanceDate"], "2024-03-01T09:30:00Z");
+        assert_eq!(credential["expirationDate"], "2025-03-01T00:00:00Z");
+        assert_eq!(credential["credentialSubject"], json!({ "id": SUBJECT, "vaccine": { "code": "208", "lot": "EK9231" } }));
+        assert_eq!(credential["proof"]["type"], "JwtProof2020");
+        assert_eq!(credential["proof"]["verificationMethod"], "did:hedera:testnet:0.0.5#key-1");
+        assert_eq!(credential["proof"]["proofPurpose"], "assertionMethod");
+    }
+
+    #[test]
+    fn signing_with_a_fixed_key_gives_a_fixed_proof() {
+        let credential = credential();
+        let jwt = credential["proof"]["jwt"].as_str().unwrap();
+
+        assert_eq!(jwt, EXPECTED_JWT);
+        assert_eq!(signer().public_key_multibase(), EXPECTED_PUBLIC_KEY_MULTIBASE);
+
+        // The signature checks out with the public key, and the payload holds the credential
+        let (signing_input, signature) = jwt.rsplit_once('.').unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        signer().signing_key.verifying_key().verify(signing_input.as_bytes(), &signature).unwrap();
+        let payload: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(signing_input.split('.').nth(1).unwrap()).unwrap()).unwrap();
+        let mut unsigned = credential.clone();
+        unsigned.as_object_mut().unwrap().remove("proof");
+        assert_eq!(payload["vc"], unsigned);
+        assert_eq!((payload["iss"].as_str(), payload["sub"].as_str()), (Some(ISSUER), Some(SUBJECT)));
+        assert_eq!((payload["nbf"].as_i64(), payload["exp"].as_i64()), (Some(1709285400), Some(1740787200)));
+    }
+
+    #[test]
+    fn a_changed_credential_no_longer_matches_its_signature() {
+        let jwt = credential()["proof"]["jwt"].as_str().unwrap().to_string();
+        let (signing_input, signature) = jwt.rsplit_once('.').unwrap();
+        let (header, payload) = signing_input.split_once('.').unwrap();
+        let mut claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        claims["vc"]["credentialSubject"]["vaccine"]["lot"] = json!("FORGED");
+        let tampered = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(canonical_json(&claims)));
+
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        assert!(signer().signing_key.verifying_key().verify(tampered.as_bytes(), &signature).is_err());
+    }
+
+    const EXPECTED_PUBLIC_KEY_MULTIBASE: &str = "z6MkvDqGT54cXesYGvABpF1UapVNwjCqRcafi4Px6Thv5T3Z";
+    // Signed with the same canonical JSON and seed by an independent Ed25519 implementation
+    const EXPECTED_JWT: &str = concat!(
+        "eyJhbGciOiJFZERTQSIsImtpZCI6ImRpZDpoZWRlcmE6dGVzdG5ldDowLjAuNSNrZXktMSIsInR5cCI6IkpXVCJ9.",
+        "eyJleHAiOjE3NDA3ODcyMDAsImlzcyI6ImRpZDpoZWRlcmE6dGVzdG5ldDowLjAuNSIsImp0aSI6InVybjp1dWlkOjAwMDAwMDAw",
+        "LTAwMDAtNDAwMC04MDAwLTAwMDAwMDAwMDAwMSIsIm5iZiI6MTcwOTI4NTQwMCwic3ViIjoiZGlkOmhlZGVyYTp0ZXN0bmV0OjAu",
+        "MC4xIiwidmMiOnsiQGNvbnRleHQiOlsiaHR0cHM6Ly93d3cudzMub3JnLzIwMTgvY3JlZGVudGlhbHMvdjEiXSwiY3JlZGVudGlh",
+        "bFN1YmplY3QiOnsiaWQiOiJkaWQ6aGVkZXJhOnRlc3RuZXQ6MC4wLjEiLCJ2YWNjaW5lIjp7ImNvZGUiOiIyMDgiLCJsb3QiOiJF",
+        "SzkyMzEifX0sImV4cGlyYXRpb25EYXRlIjoiMjAyNS0wMy0wMVQwMDowMDowMFoiLCJpZCI6InVybjp1dWlkOjAwMDAwMDAwLTAw",
+        "MDAtNDAwMC04MDAwLTAwMDAwMDAwMDAwMSIsImlzc3VhbmNlRGF0ZSI6IjIwMjQtMDMtMDFUMDk6MzA6MDBaIiwiaXNzdWVyIjoi",
+        "ZGlkOmhlZGVyYTp0ZXN0bmV0OjAuMC41IiwidHlwZSI6WyJWZXJpZmlhYmxlQ3JlZGVudGlhbCIsIlZhY2NpbmF0aW9uQ3JlZGVu",
+        "dGlhbCJdfX0.",
+        "xkvpkfwhaJQje37x4lNYa1CG6ED1ASd4WkGGdlB7mlP-dnRxDuN241wrG7wQ_QewxpMjmQFkCrMciSf5Z2FCCg",
+    );
+}
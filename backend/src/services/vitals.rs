@@ -0,0 +1,204 @@
+//! Expands quick-entry vitals into coded FHIR Observations.
+//!
+//! Each value gets its LOINC code, a UCUM unit, the `vital-signs` category and, where the
+//! configuration has a reference range for it, a low/normal/high interpretation.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+
+use crate::config::{ReferenceRange, VitalsConfig};
+use crate::models::*;
+use crate::services::fhir::{FhirManager, ObservationCodes};
+
+const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+const CATEGORY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+const INTERPRETATION_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ObservationInterpretation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpretation {
+    Low,
+    Normal,
+    High,
+}
+
+impl Interpretation {
+    /// Where `value` falls against `range`; both ends count as normal
+    pub fn of(value: f64, range: ReferenceRange) -> Self {
+        if value < range.low {
+            Self::Low
+        } else if value > range.high {
+            Self::High
+        } else {
+            Self::Normal
+        }
+    }
+
+    /// The HL7 v3 ObservationInterpretation coding
+    pub fn coding(self) -> FhirCodeableConcept {
+        let (code, display) = match self {
+            Self::Low => ("L", "Low"),
+            Self::Normal => ("N", "Normal"),
+            Self::High => ("H", "High"),
+        };
+        FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some(INTERPRETATION_SYSTEM.to_string()),
+                code: Some(code.to_string()),
+                display: Some(display.to_string()),
+            }],
+            text: None,
+        }
+    }
+}
+
+/// One entered value and how it is coded
+struct Vital {
+    field: &'static str,
+    value: f64,
+    code: FhirCodeableConcept,
+    unit: &'static str,
+    ucum: &'static str,
+    /// Highest plausible value, to catch slips such as a weight typed into SpO2
+    max: f64,
+    range: Option<ReferenceRange>,
+}
+
+/// The Observations for every vital in `request`, linked to the patient and encounter
+pub fn vital_sign_observations(
+    patient_did: &str,
+    encounter_id: &str,
+    request: &RecordVitalsRequest,
+    ranges: &VitalsConfig,
+    effective: DateTime<Utc>,
+) -> Result<Vec<FhirObservation>> {
+    let entered = [
+        (request.systolic, "systolic", ObservationCodes::systolic_blood_pressure(), "mmHg", "mm[Hg]", 300.0, Some(ranges.systolic)),
+        (request.diastolic, "diastolic", ObservationCodes::diastolic_blood_pressure(), "mmHg", "mm[Hg]", 200.0, Some(ranges.diastolic)),
+        (request.heart_rate, "heart_rate", ObservationCodes::heart_rate(), "beats/minute", "/min", 300.0, Some(ranges.heart_rate)),
+        (request.temperature_c, "temperature_c", ObservationCodes::body_temperature(), "°C", "Cel", 45.0, Some(ranges.temperature_c)),
+        (request.spo2, "spo2", ObservationCodes::oxygen_saturation(), "%", "%", 100.0, Some(ranges.spo2)),
+        (request.respiratory_rate, "respiratory_rate", ObservationCodes::respiratory_rate(), "breaths/minute", "/min", 100.0, Some(ranges.respiratory_rate)),
+        (request.weight_kg, "weight_kg", ObservationCodes::body_weight(), "kg", "kg", 700.0, None),
+    ];
+    let vitals: Vec<Vital> = entered
+        .into_iter()
+        .filter_map(|(value, field, code, unit, ucum, max, range)| value.map(|value| Vital { field, value, code, unit, ucum, max, range }))
+        .collect();
+    if vitals.is_empty() {
+        return Err(anyhow!("Enter at least one vital sign"));
+    }
+    if let Some(vital) = vitals.iter().find(|vital| vital.value.is_nan() || vital.value <= 0.0 || vital.value > vital.max) {
+        return Err(anyhow!("{} must be above 0 and at most {}, got {}", vital.field, vital.max, vital.value));
+    }
+
+    let category = FhirCodeableConcept {
+        coding: vec![FhirCoding {
+            system: Some(CATEGORY_SYSTEM.to_string()),
+            code: Some("vital-signs".to_string()),
+            display: Some("Vital Signs".to_string()),
+        }],
+        text: None,
+    };
+    let effective = FhirDateTime::from(effective);
+    Ok(vitals
+        .into_iter()
+        .map(|vital| {
+            let quantity = FhirQuantity {
+                value: Some(vital.value),
+                unit: Some(vital.unit.to_string()),
+                system: Some(UCUM_SYSTEM.to_string()),
+                code: Some(vital.ucum.to_string()),
+            };
+            let interpretation = vital.range.map(|range| Interpretation::of(vital.value, range).coding()).into_iter().collect();
+            FhirManager::create_observation(
+                patient_did,
+                Some(encounter_id),
+                vital.code,
+                vec![category.clone()],
+                Some(quantity),
+                None,
+                interpretation,
+                effective.clone(),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATIENT: &str = "did:hedera:testnet:0.0.1";
+
+    fn observations(request: RecordVitalsRequest) -> Vec<FhirObservation> {
+        vital_sign_observations(PATIENT, "e1", &request, &VitalsConfig::default(), Utc::now()).unwrap()
+    }
+
+    fn code(concept: &FhirCodeableConcept) -> &str {
+        concept.coding[0].code.as_deref().unwrap()
+    }
+
+    #[test]
+    fn every_vital_gets_its_loinc_code_and_ucum_unit() {
+        let all = observations(RecordVitalsRequest {
+            systolic: Some(120.0),
+            diastolic: Some(80.0),
+            heart_rate: Some(72.0),
+            temperature_c: Some(36.8),
+            spo2: Some(98.0),
+            respiratory_rate: Some(16.0),
+            weight_kg: Some(70.5),
+            effective_date_time: None,
+        });
+
+        let coded: Vec<(&str, &str)> =
+            all.iter().map(|observation| (code(&observation.code), observation.value_quantity.as_ref().unwrap().code.as_deref().unwrap())).collect();
+        assert_eq!(
+            coded,
+            [("8480-6", "mm[Hg]"), ("8462-4", "mm[Hg]"), ("8867-4", "/min"), ("8310-5", "Cel"), ("59408-5", "%"), ("9279-1", "/min"), ("29463-7", "kg")]
+        );
+        for observation in &all {
+            assert_eq!(observation.encounter.as_ref().unwrap().reference, "Encounter/e1");
+            assert_eq!(observation.subject.reference, format!("Patient/{}", PATIENT));
+            assert_eq!(code(&observation.category[0]), "vital-signs");
+            assert_eq!(observation.value_quantity.as_ref().unwrap().system.as_deref(), Some("http://unitsofmeasure.org"));
+        }
+        // Weight has no reference range, so no interpretation
+        assert!(all[6].interpretation.is_empty());
+        assert!(all[..6].iter().all(|observation| code(&observation.interpretation[0]) == "N"));
+    }
+
+    #[test]
+    fn interpretation_boundaries_are_inclusive() {
+        let range = ReferenceRange::new(60.0, 100.0);
+
+        assert_eq!(Interpretation::of(59.9, range), Interpretation::Low);
+        assert_eq!(Interpretation::of(60.0, range), Interpretation::Normal);
+        assert_eq!(Interpretation::of(100.0, range), Interpretation::Normal);
+        assert_eq!(Interpretation::of(100.1, range), Interpretation::High);
+    }
+
+    #[test]
+    fn readings_outside_the_configured_ranges_are_flagged() {
+        let interpreted = |request| observations(request).iter().map(|observation| code(&observation.interpretation[0]).to_string()).collect::<Vec<_>>();
+
+        assert_eq!(interpreted(RecordVitalsRequest { systolic: Some(141.0), diastolic: Some(59.0), ..Default::default() }), ["H", "L"]);
+        assert_eq!(interpreted(RecordVitalsRequest { temperature_c: Some(37.9), spo2: Some(94.0), ..Default::default() }), ["H", "L"]);
+        assert_eq!(interpreted(RecordVitalsRequest { temperature_c: Some(36.1), spo2: Some(100.0), ..Default::default() }), ["N", "N"]);
+
+        let strict = VitalsConfig { heart_rate: ReferenceRange::new(50.0, 90.0), ..Default::default() };
+        let observations = vital_sign_observations(PATIENT, "e1", &RecordVitalsRequest { heart_rate: Some(95.0), ..Default::default() }, &strict, Utc::now()).unwrap();
+        assert_eq!(observations[0].interpretation[0].coding[0].display.as_deref(), Some("High"));
+    }
+
+    #[test]
+    fn empty_and_implausible_entries_are_rejected() {
+        let ranges = VitalsConfig::default();
+        let record = |request: RecordVitalsRequest| vital_sign_observations(PATIENT, "e1", &request, &ranges, Utc::now()).map_err(|e| e.to_string());
+
+        assert_eq!(record(RecordVitalsRequest::default()).unwrap_err(), "Enter at least one vital sign");
+        assert_eq!(record(RecordVitalsRequest { spo2: Some(101.0), ..Default::default() }).unwrap_err(), "spo2 must be above 0 and at most 100, got 101");
+        assert!(record(RecordVitalsRequest { weight_kg: Some(0.0), ..Default::default() }).is_err());
+        assert!(record(RecordVitalsRequest { heart_rate: Some(f64::NAN), ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,518 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, StatusCode, Url};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::auditing::AuditLogService;
+use crate::config::Config;
+use crate::deadline;
+use crate::events::{DomainEvent, EventSubscriber};
+use crate::jobs::{JobError, JobHandler};
+use crate::models::*;
+use crate::store::{PractitionerStore, WebhookStore};
+use crate::tenancy::{self, DEFAULT_TENANT};
+use crate::utils;
+
+/// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}` under the webhook's secret
+pub const SIGNATURE_HEADER: &str = "X-WeCare-Signature";
+/// Unix seconds when the delivery was signed; subscribers should refuse old ones
+pub const TIMESTAMP_HEADER: &str = "X-WeCare-Timestamp";
+pub const EVENT_HEADER: &str = "X-WeCare-Event";
+pub const EVENT_ID_HEADER: &str = "X-WeCare-Event-Id";
+
+/// Delivery attempts shown per webhook
+const DELIVERIES_LISTED: i64 = 50;
+
+/// The webhook event type a domain event is delivered as
+fn event_type(event: &DomainEvent) -> WebhookEventType {
+    match event {
+        DomainEvent::EncounterFinalized { .. } => WebhookEventType::EncounterFinalized,
+        DomainEvent::CredentialIssued { .. } => WebhookEventType::CredentialIssued,
+        DomainEvent::AccessGranted { .. } => WebhookEventType::AccessGranted,
+    }
+}
+
+/// Resource ids only; subscribers read anything more through the API, with their own access
+fn event_data(event: &DomainEvent) -> Value {
+    match event {
+        DomainEvent::EncounterFinalized { encounter_id, .. } => json!({ "encounter_id": encounter_id }),
+        DomainEvent::CredentialIssued { credential_id, .. } => json!({ "credential_id": credential_id }),
+        DomainEvent::AccessGranted { grant_id, .. } => json!({ "grant_id": grant_id }),
+    }
+}
+
+/// The `X-WeCare-Signature` of a delivery
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// --- WebhookService ---
+pub struct WebhookService {
+    db: Arc<dyn WebhookStore>,
+    practitioners: Arc<dyn PractitionerStore>,
+    http_client: Client,
+    config: Arc<Config>,
+    audit_log_service: Arc<AuditLogService>,
+}
+
+impl WebhookService {
+    pub fn new(
+        db: Arc<dyn WebhookStore>,
+        practitioners: Arc<dyn PractitionerStore>,
+        http_client: Client,
+        config: Arc<Config>,
+        audit_log_service: Arc<AuditLogService>,
+    ) -> Self {
+        Self { db, practitioners, http_client, config, audit_log_service }
+    }
+
+    /// Subscribe a URL to events. The signing secret is generated here and only ever returned now.
+    pub async fn create_webhook(&self, admin_did: &str, request: WebhookRequest) -> anyhow::Result<WebhookView> {
+        let (url, events) = self.validate(&request)?;
+        let secret = format!("whsec_{}", hex::encode(rand::random::<[u8; 32]>()));
+        let mut webhook = Webhook {
+            id: None,
+            // A tenant admin's webhooks are stamped with its tenant whatever the request says
+            tenant_id: tenancy::current().or(request.tenant_id),
+            owner_did: admin_did.to_string(),
+            url,
+            encrypted_secret: utils::encrypt(secret.as_bytes(), &self.config.ipfs_encryption_key)?,
+            events,
+            active: request.active.unwrap_or(true),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let id = self.db.create_webhook(&webhook).await?;
+        webhook.id = Some(id);
+        self.audit_log_service.log(admin_did, "create_webhook", Some(json!({ "webhook_id": id.to_hex(), "url": webhook.url }))).await;
+        Ok(view(webhook, Some(secret)))
+    }
+
+    pub async fn list_webhooks(&self) -> anyhow::Result<Vec<WebhookView>> {
+        Ok(self.db.list_webhooks().await?.into_iter().map(|webhook| view(webhook, None)).collect())
+    }
+
+    pub async fn get_webhook(&self, webhook_id: &str) -> anyhow::Result<WebhookView> {
+        Ok(view(self.find(webhook_id).await?, None))
+    }
+
+    /// Replace the URL, events and whether it is active; the secret and tenant stay
+    pub async fn update_webhook(&self, admin_did: &str, webhook_id: &str, request: WebhookRequest) -> anyhow::Result<WebhookView> {
+        let mut webhook = self.find(webhook_id).await?;
+        let (url, events) = self.validate(&request)?;
+        let id = webhook.id.ok_or_else(|| anyhow!("Webhook has no id"))?;
+        webhook.active = request.active.unwrap_or(webhook.active);
+        if !self.db.update_webhook(id, &url, &events, webhook.active).await? {
+            return Err(anyhow!("Webhook not found"));
+        }
+        webhook.url = url;
+        webhook.events = events;
+        webhook.updated_at = Utc::now();
+        self.audit_log_service.log(admin_did, "update_webhook", Some(json!({ "webhook_id": webhook_id, "url": webhook.url }))).await;
+        Ok(view(webhook, None))
+    }
+
+    /// Stop delivering to the webhook for good; deliveries already queued are dropped
+    pub async fn delete_webhook(&self, admin_did: &str, webhook_id: &str) -> anyhow::Result<()> {
+        let id = parse_id(webhook_id)?;
+        if !self.db.delete_webhook(id).await? {
+            return Err(anyhow!("Webhook not found"));
+        }
+        self.audit_log_service.log(admin_did, "delete_webhook", Some(json!({ "webhook_id": webhook_id }))).await;
+        Ok(())
+    }
+
+    /// The latest delivery attempts to the webhook, newest first
+    pub async fn deliveries(&self, webhook_id: &str) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let webhook = self.find(webhook_id).await?;
+        self.db.list_webhook_deliveries(webhook.id.ok_or_else(|| anyhow!("Webhook has no id"))?, DELIVERIES_LISTED).await
+    }
+
+    /// Queue a `ping` event for the webhook, whether or not it is active, to check the receiving end
+    pub async fn send_test(&self, admin_did: &str, webhook_id: &str) -> anyhow::Result<WebhookEvent> {
+        let webhook = self.find(webhook_id).await?;
+        let event = new_event(WebhookEventType::Ping, json!({ "webhook_id": webhook_id }));
+        self.db.enqueue_webhook_delivery(&WebhookDeliveryJob { webhook_id: webhook_id.to_string(), event: event.clone() }).await?;
+        self.audit_log_service.log(admin_did, "test_webhook", Some(json!({ "webhook_id": webhook_id, "url": webhook.url }))).await;
+        Ok(event)
+    }
+
+    /// Queue a delivery of `event` to every active webhook of its tenant subscribed to it
+    pub async fn emit(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let Some(tenant) = self.tenant_of(event).await? else {
+            return Ok(());
+        };
+        let webhooks = self.db.subscribed_webhooks(&tenant, event_type(event)).await?;
+        if webhooks.is_empty() {
+            return Ok(());
+        }
+        // One event id for every subscriber, so systems that receive it twice can tell
+        let webhook_event = new_event(event_type(event), event_data(event));
+        for webhook in webhooks {
+            let Some(id) = webhook.id else { continue };
+            self.db.enqueue_webhook_delivery(&WebhookDeliveryJob { webhook_id: id.to_hex(), event: webhook_event.clone() }).await?;
+        }
+        Ok(())
+    }
+
+    /// The tenant whose webhooks hear about `event`, if any
+    async fn tenant_of(&self, event: &DomainEvent) -> anyhow::Result<Option<String>> {
+        Ok(match event {
+            DomainEvent::EncounterFinalized { tenant_id, .. } | DomainEvent::CredentialIssued { tenant_id, .. } => {
+                Some(tenant_id.clone().unwrap_or_else(|| DEFAULT_TENANT.to_string()))
+            }
+            DomainEvent::AccessGranted { grantee_did, .. } => self
+                .practitioners
+                .get_practitioner_by_did(grantee_did)
+                .await?
+                .map(|practitioner| practitioner.tenant_id.unwrap_or_else(|| DEFAULT_TENANT.to_string())),
+        })
+    }
+
+    /// POST the event to the webhook, signed, and record the attempt. Unreachable subscribers,
+    /// server errors, 408 and 429 are retried by the job queue with backoff; other answers are final.
+    pub async fn deliver(&self, delivery: &WebhookDeliveryJob, attempt: u32) -> Result<(), JobError> {
+        let event = &delivery.event;
+        let id = ObjectId::parse_str(&delivery.webhook_id).map_err(|_| JobError::Permanent(anyhow!("Malformed webhook id {}", delivery.webhook_id)))?;
+        let webhook = match self.db.get_webhook(id).await? {
+            Some(webhook) if webhook.active || event.event_type == WebhookEventType::Ping => webhook,
+            _ => {
+                tracing::info!("Dropped {} {} for webhook {}, which was deleted or deactivated", event.event_type.as_str(), event.id, id);
+                return Ok(());
+            }
+        };
+        let secret = utils::decrypt(&webhook.encrypted_secret, &self.config.ipfs_encryption_key)
+            .and_then(|secret| Ok(Zeroizing::new(String::from_utf8(secret)?)))
+            .map_err(|e| JobError::Permanent(anyhow!("Cannot read the secret of webhook {}: {}", id, e)))?;
+        let body = serde_json::to_vec(event).map_err(|e| JobError::Permanent(e.into()))?;
+        let timestamp = Utc::now().timestamp();
+
+        let started = Instant::now();
+        let response = self
+            .http_client
+            .post(&webhook.url)
+            .timeout(deadline::remaining_or(Duration::from_secs(self.config.webhooks.timeout_seconds)))
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.event_type.as_str())
+            .header(EVENT_ID_HEADER, &event.id)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, signature(&secret, timestamp, &body))
+            .body(body)
+            .send()
+            .await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+
+        let (status_code, outcome) = match response {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), Ok(())),
+            Ok(response) => {
+                let status = response.status();
+                let error = anyhow!("The webhook answered {}", status);
+                let retry = status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS;
+                (Some(status.as_u16()), Err(if retry { JobError::Transient(error) } else { JobError::Permanent(error) }))
+            }
+            Err(e) => (None, Err(JobError::Transient(anyhow!("The webhook could not be reached: {}", e)))),
+        };
+        let record = WebhookDelivery {
+            id: None,
+            webhook_id: id,
+            event_id: event.id.clone(),
+            event_type: event.event_type,
+            attempt,
+            status_code,
+            error: outcome.as_ref().err().map(ToString::to_string),
+            duration_ms,
+            attempted_at: Utc::now(),
+        };
+        if let Err(e) = self.db.record_webhook_delivery(&record).await {
+            tracing::warn!("Failed to record delivery {} of {} to webhook {}: {}", attempt, event.id, id, e);
+        }
+        outcome
+    }
+
+    async fn find(&self, webhook_id: &str) -> anyhow::Result<Webhook> {
+        self.db.get_webhook(parse_id(webhook_id)?).await?.ok_or_else(|| anyhow!("Webhook not found"))
+    }
+
+    /// The request's URL and its events, without repeats
+    fn validate(&self, request: &WebhookRequest) -> anyhow::Result<(String, Vec<WebhookEventType>)> {
+        let url = Url::parse(request.url.trim()).map_err(|_| anyhow!("Invalid webhook URL"))?;
+        match url.scheme() {
+            "https" => {}
+            "http" if self.config.webhooks.allow_http => {}
+            _ => return Err(anyhow!("Webhook URLs have to use https")),
+        }
+        if url.host_str().is_none() {
+            return Err(anyhow!("Invalid webhook URL"));
+        }
+        if request.events.contains(&WebhookEventType::Ping) {
+            return Err(anyhow!("ping events are only sent by the test endpoint and cannot be subscribed to"));
+        }
+        let mut events = Vec::new();
+        for event in &request.events {
+            if !events.contains(event) {
+                events.push(*event);
+            }
+        }
+        if events.is_empty() {
+            return Err(anyhow!("A webhook needs at least one event"));
+        }
+        Ok((url.to_string(), events))
+    }
+}
+
+fn new_event(event_type: WebhookEventType, data: Value) -> WebhookEvent {
+    WebhookEvent { id: format!("evt_{}", Uuid::new_v4().simple()), event_type, occurred_at: Utc::now(), data }
+}
+
+fn view(webhook: Webhook, secret: Option<String>) -> WebhookView {
+    WebhookView {
+        id: webhook.id.map(|id| id.to_hex()).unwrap_or_default(),
+        tenant_id: webhook.tenant_id,
+        owner_did: webhook.owner_did,
+        url: webhook.url,
+        events: webhook.events,
+        active: webhook.active,
+        secret,
+        created_at: webhook.created_at,
+        updated_at: webhook.updated_at,
+    }
+}
+
+fn parse_id(id: &str) -> anyhow::Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| anyhow!("Invalid webhook id"))
+}
+
+/// Queues webhook deliveries for the events on the bus. The job queue takes it from there,
+/// so a delivery survives restarts once it is queued.
+pub struct WebhookSubscriber {
+    webhook_service: Arc<WebhookService>,
+}
+
+impl WebhookSubscriber {
+    pub fn new(webhook_service: Arc<WebhookService>) -> Self {
+        Self { webhook_service }
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for WebhookSubscriber {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        self.webhook_service.emit(event).await
+    }
+}
+
+/// Runs `deliver_webhook` jobs
+pub struct WebhookDeliveryHandler {
+    webhook_service: Arc<WebhookService>,
+}
+
+impl WebhookDeliveryHandler {
+    pub fn new(webhook_service: Arc<WebhookService>) -> Self {
+        Self { webhook_service }
+    }
+}
+
+#[async_trait]
+impl JobHandler for WebhookDeliveryHandler {
+    fn job_type(&self) -> &'static str {
+        DELIVER_WEBHOOK_JOB
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        let delivery: WebhookDeliveryJob = serde_json::from_value(job.payload.clone())
+            .map_err(|e| JobError::Permanent(anyhow!("Malformed webhook delivery job: {}", e)))?;
+        self.webhook_service.deliver(&delivery, job.attempts).await
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use crate::store::{MockAuditStore, MockPractitionerStore, MockWebhookStore};
+    use std::sync::Mutex;
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SECRET: &str = "whsec_test";
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config {
+            ipfs_encryption_key: "00".repeat(32).into(),
+            webhooks: crate::config::WebhookConfig { allow_http: true, timeout_seconds: 1 },
+            ..Default::default()
+        })
+    }
+
+    fn webhook(url: String, active: bool) -> Webhook {
+        Webhook {
+            id: Some(ObjectId::new()),
+            tenant_id: None,
+            owner_did: "did:hedera:testnet:0.0.1".to_string(),
+            url,
+            encrypted_secret: utils::encrypt(SECRET.as_bytes(), &config().ipfs_encryption_key).unwrap(),
+            events: vec![WebhookEventType::EncounterFinalized],
+            active,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// A service whose one webhook posts to `url`, and the attempts it records
+    fn service(webhook: Webhook) -> (WebhookService, Arc<Mutex<Vec<WebhookDelivery>>>) {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let mut store = MockWebhookStore::new();
+        store.expect_get_webhook().returning(move |_| Ok(Some(webhook.clone())));
+        let sink = recorded.clone();
+        store.expect_record_webhook_delivery().returning(move |delivery| {
+            sink.lock().unwrap().push(delivery.clone());
+            Ok(())
+        });
+        let mut audit_store = MockAuditStore::new();
+        audit_store.expect_create_audit_log().returning(|_| Ok(()));
+        let service = WebhookService::new(
+            Arc::new(store),
+            Arc::new(MockPractitionerStore::new()),
+            Client::new(),
+            config(),
+            Arc::new(AuditLogService::new(Arc::new(audit_store))),
+        );
+        (service, recorded)
+    }
+
+    fn delivery(webhook: &Webhook) -> WebhookDeliveryJob {
+        WebhookDeliveryJob {
+            webhook_id: webhook.id.unwrap().to_hex(),
+            event: new_event(WebhookEventType::EncounterFinalized, json!({ "encounter_id": "65f000000000000000000001" })),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed_with_the_webhooks_secret() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hooks"))
+            .and(header("X-WeCare-Event", "encounter.finalized"))
+            .and(header_exists("X-WeCare-Signature"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let webhook = webhook(format!("{}/hooks", server.uri()), true);
+        let (service, recorded) = service(webhook.clone());
+        let delivery = delivery(&webhook);
+
+        assert!(service.deliver(&delivery, 1).await.is_ok());
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let timestamp: i64 = request.headers.get("X-WeCare-Timestamp").unwrap().to_str().unwrap().parse().unwrap();
+        let signed = request.headers.get("X-WeCare-Signature").unwrap().to_str().unwrap();
+        assert_eq!(signed, signature(SECRET, timestamp, &request.body));
+        assert_ne!(signed, signature("whsec_other", timestamp, &request.body));
+        let body: WebhookEvent = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body, delivery.event);
+        assert_eq!(request.headers.get("X-WeCare-Event-Id").unwrap(), delivery.event.id.as_str());
+
+        let recorded = recorded.lock().unwrap();
+        assert_eq!((recorded[0].attempt, recorded[0].status_code, recorded[0].error.as_deref()), (1, Some(204), None));
+    }
+
+    #[tokio::test]
+    async fn unreachable_and_overloaded_subscribers_are_retried_but_rejections_are_final() {
+        let server = MockServer::start().await;
+        for (route, status) in [("/down", 503), ("/busy", 429), ("/slow", 408), ("/rejects", 400), ("/gone", 410)] {
+            Mock::given(path(route)).respond_with(ResponseTemplate::new(status)).mount(&server).await;
+        }
+        Mock::given(path("/hangs")).respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(3))).mount(&server).await;
+
+        for (route, retried) in [("/down", true), ("/busy", true), ("/slow", true), ("/hangs", true), ("/rejects", false), ("/gone", false)] {
+            let webhook = webhook(format!("{}{}", server.uri(), route), true);
+            let (service, recorded) = service(webhook.clone());
+            let outcome = service.deliver(&delivery(&webhook), 3).await;
+            assert_eq!(matches!(outcome, Err(JobError::Transient(_))), retried, "{}: {:?}", route, outcome);
+            assert_eq!(matches!(outcome, Err(JobError::Permanent(_))), !retried, "{}: {:?}", route, outcome);
+            let recorded = recorded.lock().unwrap();
+            assert_eq!(recorded.len(), 1);
+            assert_eq!(recorded[0].attempt, 3);
+            assert!(recorded[0].error.is_some());
+        }
+
+        // Nothing listens on port 1
+        let webhook = webhook("http://127.0.0.1:1/hooks".to_string(), true);
+        let (service, recorded) = service(webhook.clone());
+        assert!(matches!(service.deliver(&delivery(&webhook), 1).await, Err(JobError::Transient(_))));
+        assert_eq!(recorded.lock().unwrap()[0].status_code, None);
+    }
+
+    #[tokio::test]
+    async fn deactivated_webhooks_only_get_pings() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).expect(1).mount(&server).await;
+        let webhook = webhook(server.uri(), false);
+        let (service, recorded) = service(webhook.clone());
+
+        assert!(service.deliver(&delivery(&webhook), 1).await.is_ok());
+        assert!(recorded.lock().unwrap().is_empty());
+
+        let ping = WebhookDeliveryJob { event: new_event(WebhookEventType::Ping, json!({})), ..delivery(&webhook) };
+        assert!(service.deliver(&ping, 1).await.is_ok());
+        assert_eq!(recorded.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn events_go_to_the_subscribers_in_their_tenant() {
+        let mut store = MockWebhookStore::new();
+        let subscriber = webhook("https://clinic.example/hooks".to_string(), true);
+        let subscriber_id = subscriber.id.unwrap().to_hex();
+        store
+            .expect_subscribed_webhooks()
+            .withf(|tenant, event_type| tenant == "kisumu" && *event_type == WebhookEventType::EncounterFinalized)
+            .times(1)
+            .returning(move |_, _| Ok(vec![subscriber.clone()]));
+        store
+            .expect_enqueue_webhook_delivery()
+            .withf(move |job| job.webhook_id == subscriber_id && job.event.data == json!({ "encounter_id": "enc-1" }))
+            .times(1)
+            .returning(|_| Ok(ObjectId::new()));
+        let mut practitioners = MockPractitionerStore::new();
+        // A grant to someone who is not a practitioner belongs to no clinic
+        practitioners.expect_get_practitioner_by_did().returning(|_| Ok(None));
+        let service = WebhookService::new(
+            Arc::new(store),
+            Arc::new(practitioners),
+            Client::new(),
+            config(),
+            Arc::new(AuditLogService::new(Arc::new(MockAuditStore::new()))),
+        );
+
+        let finalized = DomainEvent::EncounterFinalized {
+            encounter_id: "enc-1".to_string(),
+            patient_did: "did:hedera:testnet:0.0.6".to_string(),
+            actor_did: "did:hedera:testnet:0.0.1".to_string(),
+            tenant_id: Some("kisumu".to_string()),
+        };
+        service.emit(&finalized).await.unwrap();
+        let granted = DomainEvent::AccessGranted {
+            grant_id: "grant-1".to_string(),
+            patient_did: "did:hedera:testnet:0.0.6".to_string(),
+            grantee_did: "did:hedera:testnet:0.0.7".to_string(),
+        };
+        service.emit(&granted).await.unwrap();
+    }
+}
@@ -1,25 +1,589 @@
+use anyhow::Result;
+use std::str::FromStr;
 use std::sync::Arc;
+use tokio::time::{self, Duration};
 
-use crate::auditing::{AuditLogService, AuditingService};
-use crate::config::Config;
+use crate::api::middleware::jwt_auth::CurrentUserCache;
+use crate::auditing::audit_log::AuditSubscriber;
+use crate::auditing::{AnchoringMonitor, AuditLogService, AuditingService, MirrorNodeAnchorLookup};
+use crate::config::{Config, TwilioConfig};
 use crate::database::Database;
-use crate::services::ipfs::IpfsClient;
-use crate::services::hedera::{HederaClient, HealthcareHederaService};
-use crate::services::{AuthService, EmailService, PatientService, EncounterService, VerifiableCredentialService};
-use crate::services::twilio::TwilioService;
+use crate::events::EventBus;
+use crate::jobs::JobQueue;
+use crate::migrations;
+use crate::services::did::{DidRegistry, HederaDidRegistry};
+use crate::services::fcm::FcmClient;
+use crate::services::ipfs::{IpfsClient, ObjectStorage};
+use crate::services::login_anomaly::SystemClock;
+use crate::services::outbox::OutboxDispatcher;
+use crate::services::hedera::{ContractId, HederaClient, HealthcareHederaService, LedgerAnchor};
+use crate::services::hedera_balance::{OperatorBalance, OperatorFunds};
+use crate::services::{AdminService, AppointmentService, AttachmentService, AuditAnalyticsService, AuthService, AvailabilityService, AuthServiceImpl, BackupCodeService, BreakGlassService, CaptchaVerifier, ChatService, ComplianceReportService, ConfigReloadService, ConsentService, DataAccessMonitor, EmailService, FileService, GeminiChatModel, HederaBalanceMonitor, HederaCostService, IdempotencyService, NotificationFeedService, NotificationService, OrganizationService, PatientExportService, PatientMergeService, PatientPhotoService, PatientSearchService, PatientService, PractitionerService, PrescriptionService, ReencryptionService, ReferralService, RelationshipService, SessionService, EncounterService, IpBlockService, IssuerRegistryService, LicenseExpiryService, StatusListService, StepUpService, TerminologyService, TotpService, VerifiableCredentialService, WebhookService};
+use crate::services::notification::{LiveNotificationSender, NotificationSubscriber};
+use crate::services::phone_verification::{LocalOtp, PhoneVerifier, TwilioVerify};
+use crate::services::record_context::RecordContext;
+use crate::services::reminders::ReminderMetrics;
+use crate::services::scanner::{ClamdScanner, NoopScanner, Scanner};
+use crate::services::twilio::SmsSender;
+use crate::services::notification_feed::NotificationFeedSubscriber;
+use crate::services::webhooks::WebhookSubscriber;
+use crate::telemetry::LogLevelHandle;
 
 pub struct AppState<T: AuthService> {
     pub database: Arc<Database>,
     pub config: Arc<Config>,
-    pub ipfs_client: Arc<IpfsClient>,
-    pub hedera_client: Arc<HederaClient>,
-    pub hedera_service: Arc<HealthcareHederaService>,
+    pub ipfs_client: Arc<dyn ObjectStorage>,
+    pub hedera_service: Arc<dyn LedgerAnchor>,
     pub audit_log_service: Arc<AuditLogService>,
     pub auditing_service: Arc<AuditingService>,
+    /// How audit anchoring runs have gone, for the health checks
+    pub anchoring_monitor: Arc<AnchoringMonitor>,
     pub auth_service: Arc<T>,
+    pub session_service: Arc<SessionService>,
+    pub ip_block_service: Arc<IpBlockService>,
+    pub captcha_verifier: Arc<CaptchaVerifier>,
+    pub totp_service: Arc<TotpService>,
+    pub step_up_service: Arc<StepUpService>,
+    pub backup_code_service: Arc<BackupCodeService>,
     pub email_service: Arc<EmailService>,
-    pub twilio_service: Arc<TwilioService>,
+    pub sms_sender: Arc<SmsSender>,
+    pub notification_service: Arc<NotificationService>,
+    pub notification_feed_service: Arc<NotificationFeedService>,
+    pub consent_service: Arc<ConsentService>,
+    pub relationship_service: Arc<RelationshipService>,
     pub patient_service: Arc<PatientService>,
+    pub break_glass_service: Arc<BreakGlassService>,
+    pub referral_service: Arc<ReferralService>,
+    pub prescription_service: Arc<PrescriptionService>,
+    pub organization_service: Arc<OrganizationService>,
+    pub practitioner_service: Arc<PractitionerService>,
     pub encounter_service: Arc<EncounterService>,
+    pub file_service: Arc<FileService>,
+    pub attachment_service: Arc<AttachmentService>,
+    pub patient_photo_service: Arc<PatientPhotoService>,
+    pub reencryption_service: Arc<ReencryptionService>,
+    pub terminology_service: Arc<TerminologyService>,
+    pub availability_service: Arc<AvailabilityService>,
+    pub appointment_service: Arc<AppointmentService>,
     pub vc_service: Arc<VerifiableCredentialService>,
+    pub status_list_service: Arc<StatusListService>,
+    pub issuer_registry: Arc<IssuerRegistryService>,
+    pub admin_service: Arc<AdminService>,
+    pub patient_export_service: Arc<PatientExportService>,
+    pub compliance_report_service: Arc<ComplianceReportService>,
+    pub patient_merge_service: Arc<PatientMergeService>,
+    pub license_expiry_service: Arc<LicenseExpiryService>,
+    pub patient_search_service: Arc<PatientSearchService>,
+    pub audit_analytics_service: Arc<AuditAnalyticsService>,
+    pub hedera_cost_service: Arc<HederaCostService>,
+    pub hedera_balance_monitor: Arc<HederaBalanceMonitor>,
+    pub job_queue: Arc<JobQueue>,
+    pub idempotency_service: Arc<IdempotencyService>,
+    pub chat_service: Arc<ChatService>,
+    pub config_reload_service: Arc<ConfigReloadService>,
+    pub webhook_service: Arc<WebhookService>,
+    pub event_bus: Arc<EventBus>,
+    pub outbox_dispatcher: Arc<OutboxDispatcher>,
+    pub reminder_metrics: Arc<ReminderMetrics>,
+    /// Callers' accounts as `auth_middleware` recently loaded them
+    pub current_users: Arc<CurrentUserCache>,
+}
+
+/// Wires the database, external clients and services into an [`AppState`].
+///
+/// Anything not overridden is built from the [`Config`]; tests swap in fakes
+/// for the parts that would otherwise talk to MongoDB, IPFS or Hedera.
+pub struct AppStateBuilder {
+    config: Arc<Config>,
+    database: Option<Arc<Database>>,
+    auth_service: Option<Arc<AuthServiceImpl>>,
+    ipfs: Option<Arc<dyn ObjectStorage>>,
+    ledger: Option<Arc<dyn LedgerAnchor>>,
+    did_registry: Option<Arc<dyn DidRegistry>>,
+    phone_verifier: Option<Arc<dyn PhoneVerifier>>,
+    fcm: Option<Arc<FcmClient>>,
+    scanner: Option<Arc<dyn Scanner>>,
+    log_level: Option<LogLevelHandle>,
+}
+
+impl AppStateBuilder {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            database: None,
+            auth_service: None,
+            ipfs: None,
+            ledger: None,
+            did_registry: None,
+            phone_verifier: None,
+            fcm: None,
+            scanner: None,
+            log_level: None,
+        }
+    }
+
+    pub fn with_database(mut self, database: Arc<Database>) -> Self {
+        self.database = Some(database);
+        self
+    }
+
+    pub fn with_auth_service(mut self, auth_service: Arc<AuthServiceImpl>) -> Self {
+        self.auth_service = Some(auth_service);
+        self
+    }
+
+    pub fn with_ipfs(mut self, ipfs: Arc<dyn ObjectStorage>) -> Self {
+        self.ipfs = Some(ipfs);
+        self
+    }
+
+    pub fn with_ledger(mut self, ledger: Arc<dyn LedgerAnchor>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_did_registry(mut self, did_registry: Arc<dyn DidRegistry>) -> Self {
+        self.did_registry = Some(did_registry);
+        self
+    }
+
+    pub fn with_phone_verifier(mut self, phone_verifier: Arc<dyn PhoneVerifier>) -> Self {
+        self.phone_verifier = Some(phone_verifier);
+        self
+    }
+
+    pub fn with_fcm(mut self, fcm: Arc<FcmClient>) -> Self {
+        self.fcm = Some(fcm);
+        self
+    }
+
+    pub fn with_scanner(mut self, scanner: Arc<dyn Scanner>) -> Self {
+        self.scanner = Some(scanner);
+        self
+    }
+
+    /// Let configuration reloads change the level of the installed log subscriber
+    pub fn with_log_level(mut self, log_level: LogLevelHandle) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    pub async fn build(self) -> Result<AppState<AuthServiceImpl>> {
+        let config = self.config;
+
+        let database = match self.database {
+            Some(database) => database,
+            None => Arc::new(connect_database(&config).await),
+        };
+
+        if config.run_migrations {
+            let applied = migrations::run(&database, &config).await?;
+            tracing::info!("Migrations complete ({} applied)", applied.len());
+        }
+
+        let ipfs_client = self
+            .ipfs
+            .unwrap_or_else(|| Arc::new(IpfsClient::new(&config.ipfs_url)));
+
+        // Only connect to Hedera when something still needs the real network
+        let mut hedera_client = None;
+        let operator_funds = Arc::new(OperatorFunds::default());
+        let hedera_service: Arc<dyn LedgerAnchor> = match self.ledger {
+            Some(ledger) => ledger,
+            None => Arc::new(
+                healthcare_hedera_service(&hedera(&mut hedera_client, &operator_funds, &config)?, &config)?.with_transaction_log(database.clone()),
+            ),
+        };
+        let did_registry: Arc<dyn DidRegistry> = match self.did_registry {
+            Some(registry) => registry,
+            None => Arc::new(HederaDidRegistry::new(hedera(&mut hedera_client, &operator_funds, &config)?, &config.hedera_network)),
+        };
+
+        let audit_log_service = Arc::new(AuditLogService::new(database.clone()));
+        // One connection pool for outbound HTTP integrations
+        let http_client = reqwest::Client::new();
+        let auditing_service = auditing_service(database.clone(), hedera_service.clone(), &config, &http_client);
+        let mut outbox_dispatcher = OutboxDispatcher::new(database.clone(), database.clone(), ipfs_client.clone(), hedera_service.clone());
+        if let Some(mirror_node_url) = &config.hedera_costs.mirror_node_url {
+            let calls_to = |contract_id: &str| Arc::new(MirrorNodeAnchorLookup::new(http_client.clone(), mirror_node_url, contract_id));
+            outbox_dispatcher = outbox_dispatcher.with_call_lookups(
+                calls_to(&config.verifiable_credentials_contract_id),
+                calls_to(&config.healthcare_access_control_contract_id),
+            );
+        }
+        let sms_sender = Arc::new(SmsSender::from_config(&config, http_client.clone()));
+        // Twilio Verify owns code generation and expiry when a service is configured
+        let phone_verifier: Arc<dyn PhoneVerifier> = match (self.phone_verifier, &config.twilio) {
+            (Some(verifier), _) => verifier,
+            (None, Some(twilio @ TwilioConfig { verify_service_sid: Some(service_sid), .. })) => {
+                Arc::new(TwilioVerify::new(twilio, service_sid, http_client.clone()))
+            }
+            (None, _) => Arc::new(LocalOtp::new(database.clone(), sms_sender.clone())),
+        };
+        let email_service = Arc::new(EmailService::new(config.clone(), database.clone())?);
+        let anchoring_monitor =
+            Arc::new(AnchoringMonitor::new(database.clone(), database.clone(), email_service.clone(), config.clone(), Arc::new(SystemClock)));
+        let auditing_service = Arc::new(auditing_service.with_monitor(anchoring_monitor.clone()));
+        let fcm = match (self.fcm, &config.fcm) {
+            (Some(fcm), _) => Some(fcm),
+            (None, Some(fcm_config)) => Some(Arc::new(FcmClient::from_config(fcm_config, http_client.clone())?)),
+            (None, None) => None,
+        };
+        let notification_service = Arc::new(NotificationService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            Arc::new(LiveNotificationSender::new(email_service.clone(), sms_sender.clone(), fcm)),
+            config.clone(),
+        ));
+        let session_service = Arc::new(SessionService::new(
+            database.clone(),
+            notification_service.clone(),
+            http_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let ip_block_service = Arc::new(IpBlockService::new(
+            database.clone(),
+            &config.login_protection,
+            Arc::new(SystemClock),
+            audit_log_service.clone(),
+        ));
+        ip_block_service.load().await;
+        let captcha_verifier = Arc::new(CaptchaVerifier::new(config.captcha.as_ref(), http_client.clone()));
+        let auth_service = match self.auth_service {
+            Some(auth_service) => auth_service,
+            None => Arc::new(AuthServiceImpl::new(
+                database.clone(),
+                did_registry,
+                config.clone(),
+                audit_log_service.clone(),
+                phone_verifier.clone(),
+                email_service.clone(),
+                session_service.clone(),
+                ip_block_service.clone(),
+            )),
+        };
+        let totp_service = Arc::new(TotpService::new(database.clone(), config.clone(), audit_log_service.clone()));
+        let backup_code_service =
+            Arc::new(BackupCodeService::new(database.clone(), notification_service.clone(), audit_log_service.clone()));
+        let step_up_service = Arc::new(StepUpService::new(
+            database.clone(),
+            totp_service.clone(),
+            backup_code_service.clone(),
+            phone_verifier,
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let consent_service = Arc::new(ConsentService::new(
+            database.clone(),
+            database.clone(),
+            ipfs_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let relationship_service = Arc::new(RelationshipService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let webhook_service = Arc::new(WebhookService::new(
+            database.clone(),
+            database.clone(),
+            http_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let notification_feed_service = Arc::new(NotificationFeedService::new(database.clone(), config.clone()));
+        // Side effects of finalizing encounters, issuing credentials and granting access run behind the bus
+        let event_bus = Arc::new(EventBus::new(config.events.capacity));
+        event_bus.subscribe(Arc::new(NotificationSubscriber::new(notification_service.clone())));
+        event_bus.subscribe(Arc::new(NotificationFeedSubscriber::new(notification_feed_service.clone())));
+        event_bus.subscribe(Arc::new(WebhookSubscriber::new(webhook_service.clone())));
+        event_bus.subscribe(Arc::new(AuditSubscriber::new(database.clone(), database.clone())));
+        let access_monitor = Arc::new(DataAccessMonitor::new(
+            database.clone(),
+            email_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+            Arc::new(SystemClock),
+        ));
+        let patient_service = Arc::new(
+            PatientService::new(
+                database.clone(),
+                database.clone(),
+                config.clone(),
+                audit_log_service.clone(),
+                notification_service.clone(),
+                consent_service.clone(),
+                relationship_service.clone(),
+                event_bus.clone(),
+            )
+            .with_access_monitor(access_monitor.clone()),
+        );
+        let break_glass_service = Arc::new(BreakGlassService::new(
+            database.clone(),
+            database.clone(),
+            notification_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let referral_service = Arc::new(ReferralService::new(
+            database.clone(),
+            database.clone(),
+            patient_service.clone(),
+            notification_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let organization_service = Arc::new(OrganizationService::new(database.clone(), database.clone(), audit_log_service.clone()));
+        let practitioner_service = Arc::new(PractitionerService::new(database.clone(), organization_service.clone(), audit_log_service.clone()));
+        let terminology_service = Arc::new(TerminologyService::with_config(&config.terminology, http_client.clone()));
+        let encounter_service = Arc::new(EncounterService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            ipfs_client.clone(),
+            organization_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+            terminology_service.clone(),
+            event_bus.clone(),
+        ));
+        let reencryption_service = Arc::new(ReencryptionService::new(
+            database.clone(),
+            database.clone(),
+            ipfs_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let availability_service = Arc::new(AvailabilityService::new(database.clone(), database.clone(), config.clone(), audit_log_service.clone()));
+        let appointment_service = Arc::new(AppointmentService::new(
+            database.clone(),
+            availability_service.clone(),
+            encounter_service.clone(),
+            relationship_service.clone(),
+            organization_service.clone(),
+            audit_log_service.clone(),
+        ));
+        let issuer_registry = Arc::new(IssuerRegistryService::new(database.clone(), config.clone(), audit_log_service.clone()));
+        let admin_service =
+            Arc::new(AdminService::new(database.clone(), database.clone(), config.clone(), audit_log_service.clone()).with_access_monitor(access_monitor));
+        let license_expiry_service =
+            Arc::new(LicenseExpiryService::new(database.clone(), database.clone(), email_service.clone(), audit_log_service.clone()));
+        let patient_merge_service = Arc::new(PatientMergeService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let patient_export_service = Arc::new(PatientExportService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            ipfs_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+            notification_service.clone(),
+        ));
+        let compliance_report_service = Arc::new(ComplianceReportService::new(
+            database.clone(),
+            database.clone(),
+            database.clone(),
+            auditing_service.clone(),
+            ipfs_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let patient_search_service = Arc::new(PatientSearchService::new(database.clone(), config.clone(), audit_log_service.clone()));
+        let audit_analytics_service = Arc::new(AuditAnalyticsService::new(database.clone()));
+        let job_queue = Arc::new(JobQueue::new(database.clone()));
+        let idempotency_service = Arc::new(IdempotencyService::new(database.clone(), &config));
+        let hedera_cost_service = Arc::new(HederaCostService::new(
+            database.clone(),
+            database.clone(),
+            email_service.clone(),
+            http_client.clone(),
+            config.clone(),
+        ));
+        let hedera_balance_monitor = Arc::new(HederaBalanceMonitor::new(
+            hedera_client.clone().map(|client| client as Arc<dyn OperatorBalance>),
+            operator_funds,
+            database.clone(),
+            email_service.clone(),
+            config.clone(),
+        ));
+        let status_list_service = Arc::new(StatusListService::new(database.clone(), database.clone(), ipfs_client.clone(), config.clone()));
+        let vc_service = Arc::new(VerifiableCredentialService::new(
+            database.clone(),
+            ipfs_client.clone(),
+            hedera_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+            event_bus.clone(),
+            status_list_service.clone(),
+            issuer_registry.clone(),
+            database.clone(),
+        )
+        .with_fee_history(database.clone()));
+        let prescription_service = Arc::new(PrescriptionService::new(
+            database.clone(),
+            database.clone(),
+            patient_service.clone(),
+            vc_service.clone(),
+            notification_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let scanner: Arc<dyn Scanner> = match (self.scanner, &config.uploads.clamd_address) {
+            (Some(scanner), _) => scanner,
+            (None, Some(address)) => Arc::new(ClamdScanner::new(address)),
+            (None, None) => Arc::new(NoopScanner),
+        };
+        let attachment_service = Arc::new(AttachmentService::new(
+            database.clone(),
+            database.clone(),
+            ipfs_client.clone(),
+            scanner,
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let patient_photo_service = Arc::new(PatientPhotoService::new(
+            database.clone(),
+            patient_service.clone(),
+            ipfs_client.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let file_service = Arc::new(FileService::new(
+            database.clone(),
+            ipfs_client.clone(),
+            patient_service.clone(),
+            config.clone(),
+            audit_log_service.clone(),
+        ));
+        let record_context = Arc::new(RecordContext::new(database.clone(), database.clone(), config.clone()));
+        let chat_service = Arc::new(ChatService::new(
+            database.clone(),
+            Arc::new(GeminiChatModel::new(config.clone(), http_client.clone())),
+            database.clone(),
+            record_context,
+            audit_log_service.clone(),
+            config.clone(),
+        ));
+        let config_reload_service = Arc::new(ConfigReloadService::new(config.clone(), audit_log_service.clone(), self.log_level));
+
+        Ok(AppState {
+            database,
+            config,
+            ipfs_client,
+            hedera_service,
+            audit_log_service,
+            auditing_service,
+            anchoring_monitor,
+            auth_service,
+            session_service,
+            ip_block_service,
+            captcha_verifier,
+            totp_service,
+            step_up_service,
+            backup_code_service,
+            email_service,
+            sms_sender,
+            notification_service,
+            notification_feed_service,
+            consent_service,
+            relationship_service,
+            patient_service,
+            break_glass_service,
+            referral_service,
+            prescription_service,
+            organization_service,
+            practitioner_service,
+            encounter_service,
+            file_service,
+            attachment_service,
+            patient_photo_service,
+            reencryption_service,
+            terminology_service,
+            availability_service,
+            appointment_service,
+            vc_service,
+            status_list_service,
+            issuer_registry,
+            admin_service,
+            patient_export_service,
+            compliance_report_service,
+            patient_merge_service,
+            license_expiry_service,
+            patient_search_service,
+            audit_analytics_service,
+            hedera_cost_service,
+            hedera_balance_monitor,
+            job_queue,
+            idempotency_service,
+            chat_service,
+            config_reload_service,
+            webhook_service,
+            event_bus,
+            outbox_dispatcher: Arc::new(outbox_dispatcher),
+            reminder_metrics: Arc::new(ReminderMetrics::default()),
+            current_users: Arc::new(CurrentUserCache::default()),
+        })
+    }
+}
+
+async fn connect_database(config: &Config) -> Database {
+    loop {
+        match Database::new(config.database_url.expose_secret()).await {
+            Ok(db) => {
+                tracing::info!("Successfully connected to the database.");
+                return db.with_patient_search_key(config.patient_search.key.clone());
+            }
+            Err(e) => {
+                tracing::error!("Failed to connect to database: {}. Retrying in 5 seconds...", e);
+                time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+fn hedera(client: &mut Option<Arc<HederaClient>>, funds: &Arc<OperatorFunds>, config: &Config) -> Result<Arc<HederaClient>> {
+    if client.is_none() {
+        let hedera_client = HederaClient::new(&config.hedera_account_id, config.hedera_private_key.expose_secret(), &config.hedera_network)?;
+        *client = Some(Arc::new(hedera_client.with_funds(funds.clone())));
+    }
+    Ok(client.clone().unwrap())
+}
+
+/// Audit log anchoring on `ledger`, checking the mirror node for interrupted runs when there is one
+pub fn auditing_service(database: Arc<Database>, ledger: Arc<dyn LedgerAnchor>, config: &Config, http_client: &reqwest::Client) -> AuditingService {
+    let auditing_service = AuditingService::new(database, ledger);
+    match &config.hedera_costs.mirror_node_url {
+        Some(mirror_node_url) => auditing_service.with_anchor_lookup(Arc::new(MirrorNodeAnchorLookup::new(
+            http_client.clone(),
+            mirror_node_url,
+            &config.audit_trail_contract_id,
+        ))),
+        None => auditing_service,
+    }
+}
+
+/// The platform's contracts, called as the configured operator account
+pub fn healthcare_hedera_service(client: &HederaClient, config: &Config) -> Result<HealthcareHederaService> {
+    let mut hedera_service = HealthcareHederaService::new(client.clone());
+    hedera_service.set_contract_ids(
+        ContractId::from_str(&config.healthcare_access_control_contract_id)?,
+        ContractId::from_str(&config.verifiable_credentials_contract_id)?,
+        ContractId::from_str(&config.audit_trail_contract_id)?,
+    );
+    Ok(hedera_service)
 }
@@ -0,0 +1,1448 @@
+//! Storage traits covering the `Database` operations services depend on.
+//!
+//! Services hold these as trait objects so they can be unit tested against
+//! mocks (generated under the `test` feature) instead of a live MongoDB.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashSet;
+
+#[cfg(feature = "test")]
+use mockall::automock;
+
+use crate::database::Database;
+use crate::models::*;
+use crate::utils::EncryptionKey;
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PatientStore: Send + Sync {
+    async fn get_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>>;
+    async fn soft_delete_patient(&self, did: &str) -> Result<bool>;
+    async fn restore_patient(&self, did: &str, grace_period: Duration) -> Result<bool>;
+    async fn erase_deleted_patients(&self, deleted_before: DateTime<Utc>) -> Result<Vec<String>>;
+    async fn grant_access(&self, access_control: &AccessControl, outbox: &[OutboxAction]) -> Result<ObjectId>;
+    async fn get_grant(&self, id: ObjectId) -> Result<Option<AccessControl>>;
+    async fn set_grant_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool>;
+    async fn active_grants(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<AccessControl>>;
+    async fn deactivate_access(&self, patient_did: &str, grantee_did: &str) -> Result<bool>;
+    async fn deactivate_grant(&self, id: ObjectId) -> Result<bool>;
+    async fn get_notification_preferences(&self, patient_did: &str) -> Result<Option<NotificationPreferences>>;
+    async fn set_notification_preferences(&self, patient_did: &str, preferences: &NotificationPreferences) -> Result<bool>;
+    async fn get_chat_record_consent(&self, patient_did: &str) -> Result<bool>;
+    async fn set_chat_record_consent(&self, patient_did: &str, enabled: bool) -> Result<bool>;
+    async fn get_patient_timezone(&self, patient_did: &str) -> Result<Option<String>>;
+    async fn set_patient_timezone(&self, patient_did: &str, timezone: &str) -> Result<bool>;
+    async fn get_patient_photo(&self, patient_did: &str) -> Result<Option<PatientPhoto>>;
+    async fn set_patient_photo(&self, patient_did: &str, photo: &PatientPhoto) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait TotpStore: Send + Sync {
+    async fn get_totp(&self, did: &str) -> Result<Option<TotpEnrollment>>;
+    async fn start_totp_enrollment(&self, did: &str, enrollment: &TotpEnrollment) -> Result<bool>;
+    async fn confirm_totp(&self, did: &str, counter: i64) -> Result<bool>;
+    async fn accept_totp_counter(&self, did: &str, counter: i64) -> Result<bool>;
+    async fn remove_totp(&self, did: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait BackupCodeStore: Send + Sync {
+    async fn get_backup_codes(&self, did: &str) -> Result<Vec<BackupCode>>;
+    async fn replace_backup_codes(&self, did: &str, codes: &[BackupCode]) -> Result<bool>;
+    async fn consume_backup_code(&self, did: &str, hash: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn create_session(&self, session: &Session) -> Result<ObjectId>;
+    async fn get_session(&self, id: ObjectId) -> Result<Option<Session>>;
+    async fn known_sessions(&self, did: &str, limit: i64) -> Result<Vec<Session>>;
+    async fn revoke_session_by_token(&self, token_hash: &str) -> Result<Option<Session>>;
+    async fn revoke_sessions(&self, did: &str) -> Result<u64>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait LoginActivityStore: Send + Sync {
+    async fn load_login_activity(&self) -> Result<Vec<LoginActivity>>;
+    async fn replace_login_activity(&self, activity: &[LoginActivity]) -> Result<()>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait AdminStore: Send + Sync {
+    async fn list_patients(&self, skip: u64, limit: i64) -> Result<(Vec<EncryptedPatient>, u64)>;
+    async fn list_practitioners(&self, verified: Option<bool>) -> Result<Vec<Practitioner>>;
+    async fn set_patient_disabled(&self, did: &str, disabled: bool) -> Result<bool>;
+    async fn system_stats(&self) -> Result<SystemStats>;
+    async fn list_fhir_date_issues(&self) -> Result<Vec<FhirDateIssue>>;
+    /// The platform-wide daily SMS counters from `since_day` on, newest first
+    async fn daily_sms_sends(&self, since_day: &str) -> Result<Vec<SmsSendCounter>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PatientSearchStore: Send + Sync {
+    async fn search_patients(&self, tokens: &[String], limit: i64) -> Result<Vec<EncryptedPatient>>;
+    async fn related_patient_dids(&self, practitioner_did: &str, patient_dids: &[String]) -> Result<HashSet<String>>;
+    async fn reindex_patient_search(&self, encryption_key: &EncryptionKey, limit: i64) -> Result<usize>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PatientMergeStore: Send + Sync {
+    async fn find_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey, include_deleted: bool) -> Result<Option<Patient>>;
+    async fn live_patients(&self) -> Result<Vec<EncryptedPatient>>;
+    async fn upsert_merge_candidate(&self, candidate: &MergeCandidate) -> Result<()>;
+    async fn list_merge_candidates(&self, status: Option<MergeCandidateStatus>, skip: u64, limit: i64) -> Result<(Vec<MergeCandidate>, u64)>;
+    async fn merge_patients(&self, survivor: &Patient, duplicate_did: &str, merged_by: &str, telecom_added: usize, encryption_key: &EncryptionKey) -> Result<PatientMerge>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait HederaCostStore: Send + Sync {
+    async fn record_hedera_transaction(&self, transaction: &HederaTransaction) -> Result<()>;
+    async fn hedera_transactions(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<HederaTransaction>>;
+    async fn claim_hedera_budget_alert(&self, alert: &HederaBudgetAlert) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait SmsSendStore: Send + Sync {
+    /// The counter's count after adding one, or `None` when it already stood at `limit`
+    async fn count_sms_send(&self, counter: &SmsSendCounter, limit: u32) -> Result<Option<u32>>;
+    async fn uncount_sms_send(&self, key: &str) -> Result<()>;
+    async fn claim_sms_budget_alert(&self, day: &str, expires_at: DateTime<Utc>) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait EncounterStore: Send + Sync {
+    async fn create_encounter(&self, encounter: &Encounter) -> Result<ObjectId>;
+    async fn get_encounter(&self, encounter_id: ObjectId) -> Result<Option<Encounter>>;
+    async fn get_encounter_by_bundle(&self, ipfs_hash: &str) -> Result<Option<Encounter>>;
+    async fn create_attachment(&self, attachment: &Attachment) -> Result<ObjectId>;
+    async fn get_attachment_by_hash(&self, ipfs_hash: &str) -> Result<Option<Attachment>>;
+    async fn list_recent_encounters(&self, patient_did: &str, limit: i64) -> Result<Vec<Encounter>>;
+    async fn list_encounters(&self, party: AppointmentParty, did: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Encounter>>;
+    async fn list_affiliated_encounters(&self, affiliations: &[Affiliation], from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Encounter>>;
+    async fn create_observations(&self, observations: &[FhirObservation]) -> Result<()>;
+    async fn get_observations_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirObservation>>;
+    async fn summarize_observations(
+        &self,
+        patient_did: &str,
+        code: &str,
+        period: ObservationPeriod,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<ObservationSummary>;
+    async fn list_patient_conditions(&self, patient_did: &str) -> Result<Vec<FhirCondition>>;
+    async fn get_conditions_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirCondition>>;
+    async fn get_medication_requests_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirMedicationRequest>>;
+    async fn claim_encounter_finalization(&self, encounter_id: ObjectId, lease_until: DateTime<Utc>) -> Result<Option<Encounter>>;
+    async fn release_encounter_finalization(&self, encounter_id: ObjectId, lease_until: DateTime<Utc>) -> Result<()>;
+    async fn finalize_encounter(&self, encounter_id: ObjectId, ipfs_hash: &str, key_version: u32, lease_until: DateTime<Utc>) -> Result<bool>;
+    async fn update_encounter_status(&self, encounter_id: ObjectId, from: EncounterStatus, to: EncounterStatus, reason: Option<String>) -> Result<Option<Encounter>>;
+    async fn soft_delete_encounter(&self, encounter_id: ObjectId) -> Result<bool>;
+    async fn restore_encounter(&self, encounter_id: ObjectId, grace_period: Duration) -> Result<bool>;
+    async fn list_bundles_to_reencrypt(&self, key_version: u32, after: Option<ObjectId>, limit: i64) -> Result<Vec<Encounter>>;
+    async fn replace_encounter_bundle(&self, encounter_id: ObjectId, replaced: &BundleVersion, ipfs_hash: &str, key_version: u32) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    async fn create_audit_log(&self, log: &AuditLog) -> Result<()>;
+    async fn get_unanchored_audit_logs(&self) -> Result<Vec<AuditLog>>;
+    async fn count_unanchored_audit_logs(&self) -> Result<u64>;
+    async fn mark_logs_as_anchored(&self, log_ids: &[ObjectId], anchor_batch_id: ObjectId) -> Result<()>;
+    async fn create_anchor_batch(&self, batch: &AnchorBatch) -> Result<bool>;
+    async fn unfinished_anchor_batches(&self) -> Result<Vec<AnchorBatch>>;
+    async fn update_anchor_batch(&self, id: ObjectId, status: AnchorBatchStatus, transaction_id: Option<&str>) -> Result<()>;
+    async fn get_anchor_batch(&self, id: ObjectId) -> Result<Option<AnchorBatch>>;
+    async fn get_anchoring_health(&self) -> Result<Option<AnchoringHealth>>;
+    async fn save_anchoring_health(&self, health: &AnchoringHealth) -> Result<()>;
+    async fn get_audit_logs_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<AuditLog>>;
+    async fn list_audit_logs_by_did(&self, did: &str) -> Result<Vec<AuditLog>>;
+    async fn audit_logs_in_period(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        actions: &[&'static str],
+        action_prefixes: &[&'static str],
+    ) -> Result<Vec<AuditLog>>;
+    async fn anchor_batches_in_period(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<AnchorBatch>>;
+    async fn audit_stats(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        group_by: AuditGroupBy,
+        top: i64,
+        security_actions: &[&'static str],
+    ) -> Result<AuditStats>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait NotificationStore: Send + Sync {
+    async fn create_notification(&self, notification: &Notification) -> Result<ObjectId>;
+    async fn update_notification_status(&self, id: ObjectId, status: NotificationStatus, error: Option<String>) -> Result<()>;
+    async fn register_device(&self, device: &Device) -> Result<()>;
+    async fn list_devices(&self, did: &str) -> Result<Vec<Device>>;
+    async fn remove_device(&self, fcm_token: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    async fn enqueue_job(&self, job: &Job) -> Result<ObjectId>;
+    async fn ensure_recurring_job(&self, job: &Job) -> Result<bool>;
+    async fn claim_job(&self, job_types: &[String], worker: &str, now: DateTime<Utc>, lease: Duration) -> Result<Option<Job>>;
+    async fn complete_job(&self, id: ObjectId, worker: &str) -> Result<bool>;
+    async fn reschedule_job(&self, id: ObjectId, worker: &str, run_at: DateTime<Utc>, error: Option<&str>) -> Result<bool>;
+    async fn bury_job(&self, id: ObjectId, worker: &str, error: &str) -> Result<bool>;
+    async fn requeue_job(&self, id: ObjectId, job_type: Option<&str>) -> Result<bool>;
+    async fn list_jobs(&self, status: Option<JobStatus>, job_type: Option<&str>, skip: u64, limit: i64) -> Result<(Vec<Job>, u64)>;
+    async fn count_jobs(&self, job_type: Option<&str>) -> Result<JobCounts>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    async fn claim_idempotency_key(&self, record: &IdempotencyRecord, now: DateTime<Utc>) -> Result<Option<IdempotencyRecord>>;
+    async fn complete_idempotency_key(&self, id: ObjectId, status: u16, content_type: Option<&str>, body: &str) -> Result<()>;
+    async fn release_idempotency_key(&self, id: ObjectId) -> Result<()>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait EmailOutboxStore: Send + Sync {
+    async fn enqueue_email(&self, email: &OutboxEmail) -> Result<ObjectId>;
+    async fn retry_email(&self, id: ObjectId) -> Result<bool>;
+    async fn count_emails_by_status(&self) -> Result<OutboxStats>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait WebhookStore: Send + Sync {
+    async fn create_webhook(&self, webhook: &Webhook) -> Result<ObjectId>;
+    async fn get_webhook(&self, id: ObjectId) -> Result<Option<Webhook>>;
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>>;
+    async fn update_webhook(&self, id: ObjectId, url: &str, events: &[WebhookEventType], active: bool) -> Result<bool>;
+    async fn delete_webhook(&self, id: ObjectId) -> Result<bool>;
+    async fn subscribed_webhooks(&self, tenant: &str, event_type: WebhookEventType) -> Result<Vec<Webhook>>;
+    async fn enqueue_webhook_delivery(&self, delivery: &WebhookDeliveryJob) -> Result<ObjectId>;
+    async fn record_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<()>;
+    async fn list_webhook_deliveries(&self, webhook_id: ObjectId, limit: i64) -> Result<Vec<WebhookDelivery>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait FeedEventStore: Send + Sync {
+    async fn record_feed_event(&self, event: &FeedEvent) -> Result<ObjectId>;
+    async fn feed_events_after(&self, recipient_did: &str, after: Option<ObjectId>, limit: i64) -> Result<Vec<FeedEvent>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PractitionerStore: Send + Sync {
+    async fn create_practitioner(&self, practitioner: &Practitioner) -> Result<()>;
+    async fn get_practitioner_by_did(&self, did: &str) -> Result<Option<Practitioner>>;
+    async fn verify_practitioner_license(&self, did: &str) -> Result<bool>;
+    /// Every practitioner whose license is verified, in every tenant
+    async fn licensed_practitioners(&self) -> Result<Vec<Practitioner>>;
+    /// Mark the practitioner warned that the license expiring on `expiry_date` is about to;
+    /// false when they already were, or the license changed
+    async fn claim_license_expiry_warning(&self, did: &str, expiry_date: &str, now: DateTime<Utc>) -> Result<bool>;
+    /// Clear the practitioner's license verification; false when it was not verified
+    async fn revoke_license_verification(&self, did: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PrescriptionStore: Send + Sync {
+    async fn create_prescription(&self, prescription: &Prescription) -> Result<ObjectId>;
+    async fn get_prescription(&self, id: ObjectId) -> Result<Option<Prescription>>;
+    async fn list_prescriptions(&self, patient_did: &str, status: Option<PrescriptionStatus>) -> Result<Vec<Prescription>>;
+    async fn update_prescription_status(
+        &self,
+        id: ObjectId,
+        from: PrescriptionStatus,
+        to: PrescriptionStatus,
+        reason: Option<FhirCodeableConcept>,
+    ) -> Result<Option<Prescription>>;
+    async fn get_prescription_by_credential(&self, credential_hash: &str) -> Result<Option<Prescription>>;
+    async fn add_dispense(&self, id: ObjectId, dispense: &Dispense, previous_dispenses: usize, fills: bool) -> Result<Option<Prescription>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    async fn create_verifiable_credential(&self, credential: &VerifiableCredential, outbox: &[OutboxAction]) -> Result<()>;
+    async fn set_credential_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool>;
+    async fn create_credential_batch(&self, batch: &CredentialBatch, credentials: &[VerifiableCredential], outbox: &[OutboxAction]) -> Result<()>;
+    async fn get_credential_batch(&self, id: ObjectId) -> Result<Option<CredentialBatch>>;
+    async fn set_credential_batch_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool>;
+    async fn get_credential(&self, id: ObjectId) -> Result<Option<VerifiableCredential>>;
+    async fn get_credential_by_hash(&self, ipfs_hash: &str) -> Result<Option<VerifiableCredential>>;
+    async fn list_credentials_by_subject(&self, subject_did: &str) -> Result<Vec<VerifiableCredential>>;
+    async fn revoke_credential(&self, ipfs_hash: &str) -> Result<bool>;
+    async fn revoked_status_indexes(&self, list_id: &str) -> Result<Vec<u32>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait IssuerStore: Send + Sync {
+    async fn create_issuer(&self, issuer: &TrustedIssuer) -> Result<ObjectId>;
+    async fn get_issuer(&self, did: &str) -> Result<Option<TrustedIssuer>>;
+    async fn list_issuers(&self) -> Result<Vec<TrustedIssuer>>;
+    async fn update_issuer(&self, issuer: &TrustedIssuer) -> Result<Option<TrustedIssuer>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait StatusListStore: Send + Sync {
+    async fn allocate_status_index(&self, list_size: u32) -> Result<StatusListEntry>;
+    async fn get_status_list(&self, list_id: &str) -> Result<Option<CredentialStatusList>>;
+    async fn mark_status_list_stale(&self, list_id: &str) -> Result<()>;
+    async fn stale_status_lists(&self) -> Result<Vec<CredentialStatusList>>;
+    async fn publish_status_list(&self, list_id: &str, revision: u64, document: &str, ipfs_hash: &str) -> Result<Option<CredentialStatusList>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait AppointmentStore: Send + Sync {
+    async fn create_appointment(&self, appointment: &Appointment) -> Result<ObjectId>;
+    async fn get_appointment(&self, id: ObjectId) -> Result<Option<Appointment>>;
+    async fn update_appointment_status(&self, id: ObjectId, from: AppointmentStatus, to: AppointmentStatus) -> Result<bool>;
+    async fn link_appointment_encounter(&self, id: ObjectId, encounter_id: ObjectId) -> Result<()>;
+    async fn claim_appointment_reminder(&self, stage: ReminderStage, now: DateTime<Utc>) -> Result<Option<Appointment>>;
+    async fn list_appointments(&self, party: AppointmentParty, did: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Appointment>>;
+    async fn list_affiliated_appointments(&self, affiliations: &[Affiliation], from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Appointment>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ConsentStore: Send + Sync {
+    async fn create_consent(&self, consent: &Consent) -> Result<ObjectId>;
+    async fn get_consent(&self, id: ObjectId) -> Result<Option<Consent>>;
+    async fn list_consents(&self, patient_did: &str) -> Result<Vec<Consent>>;
+    async fn active_consents(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<Consent>>;
+    async fn deactivate_consent(&self, id: ObjectId, fhir_consent: &FhirConsent, ipfs_hash: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait RelationshipStore: Send + Sync {
+    async fn create_relationship(&self, relationship: &Relationship) -> Result<ObjectId>;
+    async fn get_relationship(&self, guardian_did: &str, dependent_did: &str) -> Result<Option<Relationship>>;
+    async fn list_relationships(&self, did: &str) -> Result<Vec<Relationship>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait OrganizationStore: Send + Sync {
+    async fn create_organization(&self, organization: &Organization) -> Result<ObjectId>;
+    async fn get_organization(&self, id: ObjectId) -> Result<Option<Organization>>;
+    async fn find_organization_by_identifier(&self, identifier: &str) -> Result<Option<Organization>>;
+    async fn list_organizations(&self) -> Result<Vec<Organization>>;
+    async fn update_organization(&self, id: ObjectId, fhir_organization: &FhirOrganization) -> Result<bool>;
+    async fn create_affiliation(&self, affiliation: &Affiliation) -> Result<ObjectId>;
+    async fn get_affiliation(&self, id: ObjectId) -> Result<Option<Affiliation>>;
+    async fn list_affiliations(&self, organization_id: ObjectId) -> Result<Vec<Affiliation>>;
+    async fn decide_affiliation(&self, id: ObjectId, status: AffiliationStatus, decided_by: &str, now: DateTime<Utc>) -> Result<Option<Affiliation>>;
+    async fn admin_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>>;
+    async fn active_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait BreakGlassStore: Send + Sync {
+    async fn create_break_glass_event(&self, event: &BreakGlassEvent) -> Result<ObjectId>;
+    async fn list_break_glass_events(&self, reviewed: Option<bool>) -> Result<Vec<BreakGlassEvent>>;
+    async fn review_break_glass_event(&self, id: ObjectId, reviewer_did: &str, notes: Option<String>) -> Result<Option<BreakGlassEvent>>;
+    async fn claim_break_glass_alert(&self, created_before: DateTime<Utc>, now: DateTime<Utc>) -> Result<Option<BreakGlassEvent>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ReencryptionJobStore: Send + Sync {
+    async fn create_reencryption_job(&self, job: &ReencryptionJob) -> Result<ObjectId>;
+    async fn get_reencryption_job(&self, id: ObjectId) -> Result<Option<ReencryptionJob>>;
+    async fn list_reencryption_jobs(&self) -> Result<Vec<ReencryptionJob>>;
+    async fn claim_reencryption_job(&self, lease_until: DateTime<Utc>) -> Result<Option<ReencryptionJob>>;
+    async fn running_reencryption_job(&self) -> Result<Option<ReencryptionJob>>;
+    async fn record_reencryption_progress(
+        &self,
+        id: ObjectId,
+        lease_until: DateTime<Utc>,
+        renewed_until: DateTime<Utc>,
+        resume_after: ObjectId,
+        reencrypted: u64,
+        failures: &[ReencryptionFailure],
+    ) -> Result<bool>;
+    async fn complete_reencryption_job(&self, id: ObjectId, lease_until: DateTime<Utc>) -> Result<Option<ReencryptionJob>>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ComplianceReportStore: Send + Sync {
+    async fn create_compliance_report(&self, report: &ComplianceReport) -> Result<ObjectId>;
+    async fn get_compliance_report(&self, id: ObjectId) -> Result<Option<ComplianceReport>>;
+    async fn list_compliance_reports(&self, limit: i64) -> Result<Vec<ComplianceReport>>;
+    async fn record_compliance_report_progress(&self, id: ObjectId, progress: u8, stage: &str) -> Result<bool>;
+    async fn complete_compliance_report(&self, id: ObjectId, artifacts: &ComplianceReportArtifacts, anchors_valid: bool) -> Result<bool>;
+    async fn fail_compliance_report(&self, id: ObjectId, error: &str) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait PatientExportStore: Send + Sync {
+    async fn create_patient_export(&self, export: &PatientExport) -> Result<ObjectId>;
+    async fn get_patient_export(&self, id: ObjectId) -> Result<Option<PatientExport>>;
+    async fn pending_patient_export(&self, patient_did: &str) -> Result<Option<PatientExport>>;
+    async fn complete_patient_export(&self, id: ObjectId, archive: &ExportArchive, expires_at: DateTime<Utc>) -> Result<bool>;
+    async fn fail_patient_export(&self, id: ObjectId, error: &str) -> Result<bool>;
+    async fn expired_patient_exports(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<PatientExport>>;
+    async fn expire_patient_export(&self, id: ObjectId) -> Result<bool>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ReferralStore: Send + Sync {
+    async fn create_referral(&self, referral: &Referral) -> Result<ObjectId>;
+    async fn get_referral(&self, id: ObjectId) -> Result<Option<Referral>>;
+    async fn list_referrals(&self, folder: ReferralFolder, did: &str, status: Option<ReferralStatus>) -> Result<Vec<Referral>>;
+    async fn transition_referral(&self, id: ObjectId, from: ReferralStatus, to: ReferralStatus, note: Option<String>) -> Result<Option<Referral>>;
+    async fn link_referral_grant(&self, id: ObjectId, access_control_id: ObjectId) -> Result<()>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait AvailabilityStore: Send + Sync {
+    async fn get_availability(&self, practitioner_did: &str) -> Result<Option<Availability>>;
+    async fn save_availability(&self, availability: &Availability) -> Result<()>;
+}
+
+#[cfg_attr(feature = "test", automock)]
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+    async fn create_chat_session(&self, session: &ChatSession) -> Result<ObjectId>;
+    async fn get_chat_session(&self, id: ObjectId, owner_did: &str) -> Result<Option<ChatSession>>;
+    async fn list_chat_sessions(&self, owner_did: &str) -> Result<Vec<ChatSession>>;
+    async fn add_chat_message(&self, message: &ChatMessage) -> Result<ObjectId>;
+    async fn recent_chat_messages(&self, session_id: ObjectId, limit: i64) -> Result<Vec<ChatMessage>>;
+    async fn list_chat_messages(&self, session_id: ObjectId) -> Result<Vec<ChatMessage>>;
+    async fn get_chat_usage(&self, user_did: &str, day: &str) -> Result<Option<ChatUsage>>;
+    async fn record_chat_usage(&self, user_did: &str, day: &str, prompt_tokens: u32, reply_tokens: u32) -> Result<()>;
+    async fn list_chat_usage(&self, since_day: &str) -> Result<Vec<ChatUsage>>;
+}
+
+#[async_trait]
+impl PatientStore for Database {
+    async fn get_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey) -> Result<Option<Patient>> {
+        Database::get_patient_by_did(self, did, encryption_key).await
+    }
+
+    async fn soft_delete_patient(&self, did: &str) -> Result<bool> {
+        Database::soft_delete_patient(self, did).await
+    }
+
+    async fn restore_patient(&self, did: &str, grace_period: Duration) -> Result<bool> {
+        Database::restore_patient(self, did, grace_period).await
+    }
+
+    async fn erase_deleted_patients(&self, deleted_before: DateTime<Utc>) -> Result<Vec<String>> {
+        Database::erase_deleted_patients(self, deleted_before).await
+    }
+
+    async fn grant_access(&self, access_control: &AccessControl, outbox: &[OutboxAction]) -> Result<ObjectId> {
+        Database::grant_access(self, access_control, outbox).await
+    }
+
+    async fn get_grant(&self, id: ObjectId) -> Result<Option<AccessControl>> {
+        Database::get_grant(self, id).await
+    }
+
+    async fn set_grant_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        Database::set_grant_transaction(self, id, transaction_id).await
+    }
+
+    async fn active_grants(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<AccessControl>> {
+        Database::active_grants(self, patient_did, grantee_did).await
+    }
+
+    async fn deactivate_access(&self, patient_did: &str, grantee_did: &str) -> Result<bool> {
+        Database::deactivate_access(self, patient_did, grantee_did).await
+    }
+
+    async fn deactivate_grant(&self, id: ObjectId) -> Result<bool> {
+        Database::deactivate_grant(self, id).await
+    }
+
+    async fn get_notification_preferences(&self, patient_did: &str) -> Result<Option<NotificationPreferences>> {
+        Database::get_notification_preferences(self, patient_did).await
+    }
+
+    async fn set_notification_preferences(&self, patient_did: &str, preferences: &NotificationPreferences) -> Result<bool> {
+        Database::set_notification_preferences(self, patient_did, preferences).await
+    }
+
+    async fn get_chat_record_consent(&self, patient_did: &str) -> Result<bool> {
+        Database::get_chat_record_consent(self, patient_did).await
+    }
+
+    async fn set_chat_record_consent(&self, patient_did: &str, enabled: bool) -> Result<bool> {
+        Database::set_chat_record_consent(self, patient_did, enabled).await
+    }
+
+    async fn get_patient_timezone(&self, patient_did: &str) -> Result<Option<String>> {
+        Database::get_patient_timezone(self, patient_did).await
+    }
+
+    async fn set_patient_timezone(&self, patient_did: &str, timezone: &str) -> Result<bool> {
+        Database::set_patient_timezone(self, patient_did, timezone).await
+    }
+
+    async fn get_patient_photo(&self, patient_did: &str) -> Result<Option<PatientPhoto>> {
+        Database::get_patient_photo(self, patient_did).await
+    }
+
+    async fn set_patient_photo(&self, patient_did: &str, photo: &PatientPhoto) -> Result<bool> {
+        Database::set_patient_photo(self, patient_did, photo).await
+    }
+}
+
+#[async_trait]
+impl TotpStore for Database {
+    async fn get_totp(&self, did: &str) -> Result<Option<TotpEnrollment>> {
+        Database::get_totp(self, did).await
+    }
+
+    async fn start_totp_enrollment(&self, did: &str, enrollment: &TotpEnrollment) -> Result<bool> {
+        Database::start_totp_enrollment(self, did, enrollment).await
+    }
+
+    async fn confirm_totp(&self, did: &str, counter: i64) -> Result<bool> {
+        Database::confirm_totp(self, did, counter).await
+    }
+
+    async fn accept_totp_counter(&self, did: &str, counter: i64) -> Result<bool> {
+        Database::accept_totp_counter(self, did, counter).await
+    }
+
+    async fn remove_totp(&self, did: &str) -> Result<bool> {
+        Database::remove_totp(self, did).await
+    }
+}
+
+#[async_trait]
+impl BackupCodeStore for Database {
+    async fn get_backup_codes(&self, did: &str) -> Result<Vec<BackupCode>> {
+        Database::get_backup_codes(self, did).await
+    }
+
+    async fn replace_backup_codes(&self, did: &str, codes: &[BackupCode]) -> Result<bool> {
+        Database::replace_backup_codes(self, did, codes).await
+    }
+
+    async fn consume_backup_code(&self, did: &str, hash: &str) -> Result<bool> {
+        Database::consume_backup_code(self, did, hash).await
+    }
+}
+
+#[async_trait]
+impl SessionStore for Database {
+    async fn create_session(&self, session: &Session) -> Result<ObjectId> {
+        Database::create_session(self, session).await
+    }
+
+    async fn get_session(&self, id: ObjectId) -> Result<Option<Session>> {
+        Database::get_session(self, id).await
+    }
+
+    async fn known_sessions(&self, did: &str, limit: i64) -> Result<Vec<Session>> {
+        Database::known_sessions(self, did, limit).await
+    }
+
+    async fn revoke_session_by_token(&self, token_hash: &str) -> Result<Option<Session>> {
+        Database::revoke_session_by_token(self, token_hash).await
+    }
+
+    async fn revoke_sessions(&self, did: &str) -> Result<u64> {
+        Database::revoke_sessions(self, did).await
+    }
+}
+
+#[async_trait]
+impl LoginActivityStore for Database {
+    async fn load_login_activity(&self) -> Result<Vec<LoginActivity>> {
+        Database::load_login_activity(self).await
+    }
+
+    async fn replace_login_activity(&self, activity: &[LoginActivity]) -> Result<()> {
+        Database::replace_login_activity(self, activity).await
+    }
+}
+
+#[async_trait]
+impl AdminStore for Database {
+    async fn list_patients(&self, skip: u64, limit: i64) -> Result<(Vec<EncryptedPatient>, u64)> {
+        Database::list_patients(self, skip, limit).await
+    }
+
+    async fn list_practitioners(&self, verified: Option<bool>) -> Result<Vec<Practitioner>> {
+        Database::list_practitioners(self, verified).await
+    }
+
+    async fn set_patient_disabled(&self, did: &str, disabled: bool) -> Result<bool> {
+        Database::set_patient_disabled(self, did, disabled).await
+    }
+
+    async fn system_stats(&self) -> Result<SystemStats> {
+        Database::system_stats(self).await
+    }
+
+    async fn list_fhir_date_issues(&self) -> Result<Vec<FhirDateIssue>> {
+        Database::list_fhir_date_issues(self).await
+    }
+
+    async fn daily_sms_sends(&self, since_day: &str) -> Result<Vec<SmsSendCounter>> {
+        Database::daily_sms_sends(self, since_day).await
+    }
+}
+
+#[async_trait]
+impl PatientSearchStore for Database {
+    async fn search_patients(&self, tokens: &[String], limit: i64) -> Result<Vec<EncryptedPatient>> {
+        Database::search_patients(self, tokens, limit).await
+    }
+
+    async fn related_patient_dids(&self, practitioner_did: &str, patient_dids: &[String]) -> Result<HashSet<String>> {
+        Database::related_patient_dids(self, practitioner_did, patient_dids).await
+    }
+
+    async fn reindex_patient_search(&self, encryption_key: &EncryptionKey, limit: i64) -> Result<usize> {
+        Database::reindex_patient_search(self, encryption_key, limit).await
+    }
+}
+
+#[async_trait]
+impl PatientMergeStore for Database {
+    async fn find_patient_by_did(&self, did: &str, encryption_key: &EncryptionKey, include_deleted: bool) -> Result<Option<Patient>> {
+        Database::find_patient_by_did(self, did, encryption_key, include_deleted).await
+    }
+
+    async fn live_patients(&self) -> Result<Vec<EncryptedPatient>> {
+        Database::live_patients(self).await
+    }
+
+    async fn upsert_merge_candidate(&self, candidate: &MergeCandidate) -> Result<()> {
+        Database::upsert_merge_candidate(self, candidate).await
+    }
+
+    async fn list_merge_candidates(&self, status: Option<MergeCandidateStatus>, skip: u64, limit: i64) -> Result<(Vec<MergeCandidate>, u64)> {
+        Database::list_merge_candidates(self, status, skip, limit).await
+    }
+
+    async fn merge_patients(&self, survivor: &Patient, duplicate_did: &str, merged_by: &str, telecom_added: usize, encryption_key: &EncryptionKey) -> Result<PatientMerge> {
+        Database::merge_patients(self, survivor, duplicate_did, merged_by, telecom_added, encryption_key).await
+    }
+}
+
+#[async_trait]
+impl HederaCostStore for Database {
+    async fn record_hedera_transaction(&self, transaction: &HederaTransaction) -> Result<()> {
+        Database::record_hedera_transaction(self, transaction).await
+    }
+
+    async fn hedera_transactions(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<HederaTransaction>> {
+        Database::hedera_transactions(self, from, to).await
+    }
+
+    async fn claim_hedera_budget_alert(&self, alert: &HederaBudgetAlert) -> Result<bool> {
+        Database::claim_hedera_budget_alert(self, alert).await
+    }
+}
+
+#[async_trait]
+impl SmsSendStore for Database {
+    async fn count_sms_send(&self, counter: &SmsSendCounter, limit: u32) -> Result<Option<u32>> {
+        Database::count_sms_send(self, counter, limit).await
+    }
+
+    async fn uncount_sms_send(&self, key: &str) -> Result<()> {
+        Database::uncount_sms_send(self, key).await
+    }
+
+    async fn claim_sms_budget_alert(&self, day: &str, expires_at: DateTime<Utc>) -> Result<bool> {
+        Database::claim_sms_budget_alert(self, day, expires_at).await
+    }
+}
+
+#[async_trait]
+impl EncounterStore for Database {
+    async fn create_encounter(&self, encounter: &Encounter) -> Result<ObjectId> {
+        Database::create_encounter(self, encounter).await
+    }
+
+    async fn get_encounter(&self, encounter_id: ObjectId) -> Result<Option<Encounter>> {
+        Database::get_encounter(self, encounter_id).await
+    }
+
+    async fn get_encounter_by_bundle(&self, ipfs_hash: &str) -> Result<Option<Encounter>> {
+        Database::get_encounter_by_bundle(self, ipfs_hash).await
+    }
+
+    async fn create_attachment(&self, attachment: &Attachment) -> Result<ObjectId> {
+        Database::create_attachment(self, attachment).await
+    }
+
+    async fn get_attachment_by_hash(&self, ipfs_hash: &str) -> Result<Option<Attachment>> {
+        Database::get_attachment_by_hash(self, ipfs_hash).await
+    }
+
+    async fn list_recent_encounters(&self, patient_did: &str, limit: i64) -> Result<Vec<Encounter>> {
+        Database::list_recent_encounters(self, patient_did, limit).await
+    }
+
+    async fn list_encounters(&self, party: AppointmentParty, did: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Encounter>> {
+        Database::list_encounters(self, party, did, from, to).await
+    }
+
+    async fn list_affiliated_encounters(&self, affiliations: &[Affiliation], from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Encounter>> {
+        Database::list_affiliated_encounters(self, affiliations, from, to).await
+    }
+
+    async fn create_observations(&self, observations: &[FhirObservation]) -> Result<()> {
+        Database::create_observations(self, observations).await
+    }
+
+    async fn get_observations_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirObservation>> {
+        Database::get_observations_for_encounter(self, encounter_id).await
+    }
+
+    async fn summarize_observations(
+        &self,
+        patient_did: &str,
+        code: &str,
+        period: ObservationPeriod,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<ObservationSummary> {
+        Database::summarize_observations(self, patient_did, code, period, from, to).await
+    }
+
+    async fn list_patient_conditions(&self, patient_did: &str) -> Result<Vec<FhirCondition>> {
+        Database::list_patient_conditions(self, patient_did).await
+    }
+
+    async fn get_conditions_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirCondition>> {
+        Database::get_conditions_for_encounter(self, encounter_id).await
+    }
+
+    async fn get_medication_requests_for_encounter(&self, encounter_id: &str) -> Result<Vec<FhirMedicationRequest>> {
+        Database::get_medication_requests_for_encounter(self, encounter_id).await
+    }
+
+    async fn claim_encounter_finalization(&self, encounter_id: ObjectId, lease_until: DateTime<Utc>) -> Result<Option<Encounter>> {
+        Database::claim_encounter_finalization(self, encounter_id, lease_until).await
+    }
+
+    async fn release_encounter_finalization(&self, encounter_id: ObjectId, lease_until: DateTime<Utc>) -> Result<()> {
+        Database::release_encounter_finalization(self, encounter_id, lease_until).await
+    }
+
+    async fn finalize_encounter(&self, encounter_id: ObjectId, ipfs_hash: &str, key_version: u32, lease_until: DateTime<Utc>) -> Result<bool> {
+        Database::finalize_encounter(self, encounter_id, ipfs_hash, key_version, lease_until).await
+    }
+
+    async fn update_encounter_status(&self, encounter_id: ObjectId, from: EncounterStatus, to: EncounterStatus, reason: Option<String>) -> Result<Option<Encounter>> {
+        Database::update_encounter_status(self, encounter_id, from, to, reason).await
+    }
+
+    async fn soft_delete_encounter(&self, encounter_id: ObjectId) -> Result<bool> {
+        Database::soft_delete_encounter(self, encounter_id).await
+    }
+
+    async fn restore_encounter(&self, encounter_id: ObjectId, grace_period: Duration) -> Result<bool> {
+        Database::restore_encounter(self, encounter_id, grace_period).await
+    }
+
+    async fn list_bundles_to_reencrypt(&self, key_version: u32, after: Option<ObjectId>, limit: i64) -> Result<Vec<Encounter>> {
+        Database::list_bundles_to_reencrypt(self, key_version, after, limit).await
+    }
+
+    async fn replace_encounter_bundle(&self, encounter_id: ObjectId, replaced: &BundleVersion, ipfs_hash: &str, key_version: u32) -> Result<bool> {
+        Database::replace_encounter_bundle(self, encounter_id, replaced, ipfs_hash, key_version).await
+    }
+}
+
+#[async_trait]
+impl AuditStore for Database {
+    async fn create_audit_log(&self, log: &AuditLog) -> Result<()> {
+        Database::create_audit_log(self, log).await
+    }
+
+    async fn get_unanchored_audit_logs(&self) -> Result<Vec<AuditLog>> {
+        Database::get_unanchored_audit_logs(self).await
+    }
+
+    async fn count_unanchored_audit_logs(&self) -> Result<u64> {
+        Database::count_unanchored_audit_logs(self).await
+    }
+
+    async fn mark_logs_as_anchored(&self, log_ids: &[ObjectId], anchor_batch_id: ObjectId) -> Result<()> {
+        Database::mark_logs_as_anchored(self, log_ids, anchor_batch_id).await
+    }
+
+    async fn create_anchor_batch(&self, batch: &AnchorBatch) -> Result<bool> {
+        Database::create_anchor_batch(self, batch).await
+    }
+
+    async fn unfinished_anchor_batches(&self) -> Result<Vec<AnchorBatch>> {
+        Database::unfinished_anchor_batches(self).await
+    }
+
+    async fn update_anchor_batch(&self, id: ObjectId, status: AnchorBatchStatus, transaction_id: Option<&str>) -> Result<()> {
+        Database::update_anchor_batch(self, id, status, transaction_id).await
+    }
+
+    async fn get_anchor_batch(&self, id: ObjectId) -> Result<Option<AnchorBatch>> {
+        Database::get_anchor_batch(self, id).await
+    }
+
+    async fn get_anchoring_health(&self) -> Result<Option<AnchoringHealth>> {
+        Database::get_anchoring_health(self).await
+    }
+
+    async fn save_anchoring_health(&self, health: &AnchoringHealth) -> Result<()> {
+        Database::save_anchoring_health(self, health).await
+    }
+
+    async fn get_audit_logs_by_ids(&self, ids: &[ObjectId]) -> Result<Vec<AuditLog>> {
+        Database::get_audit_logs_by_ids(self, ids).await
+    }
+
+    async fn list_audit_logs_by_did(&self, did: &str) -> Result<Vec<AuditLog>> {
+        Database::list_audit_logs_by_did(self, did).await
+    }
+
+    async fn audit_logs_in_period(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        actions: &[&'static str],
+        action_prefixes: &[&'static str],
+    ) -> Result<Vec<AuditLog>> {
+        Database::audit_logs_in_period(self, from, to, actions, action_prefixes).await
+    }
+
+    async fn anchor_batches_in_period(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<AnchorBatch>> {
+        Database::anchor_batches_in_period(self, from, to).await
+    }
+
+    async fn audit_stats(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        group_by: AuditGroupBy,
+        top: i64,
+        security_actions: &[&'static str],
+    ) -> Result<AuditStats> {
+        Database::audit_stats(self, from, to, group_by, top, security_actions).await
+    }
+}
+
+#[async_trait]
+impl NotificationStore for Database {
+    async fn create_notification(&self, notification: &Notification) -> Result<ObjectId> {
+        Database::create_notification(self, notification).await
+    }
+
+    async fn update_notification_status(&self, id: ObjectId, status: NotificationStatus, error: Option<String>) -> Result<()> {
+        Database::update_notification_status(self, id, status, error).await
+    }
+
+    async fn register_device(&self, device: &Device) -> Result<()> {
+        Database::register_device(self, device).await
+    }
+
+    async fn list_devices(&self, did: &str) -> Result<Vec<Device>> {
+        Database::list_devices(self, did).await
+    }
+
+    async fn remove_device(&self, fcm_token: &str) -> Result<bool> {
+        Database::remove_device(self, fcm_token).await
+    }
+}
+
+#[async_trait]
+impl JobStore for Database {
+    async fn enqueue_job(&self, job: &Job) -> Result<ObjectId> {
+        Database::enqueue_job(self, job).await
+    }
+
+    async fn ensure_recurring_job(&self, job: &Job) -> Result<bool> {
+        Database::ensure_recurring_job(self, job).await
+    }
+
+    async fn claim_job(&self, job_types: &[String], worker: &str, now: DateTime<Utc>, lease: Duration) -> Result<Option<Job>> {
+        Database::claim_job(self, job_types, worker, now, lease).await
+    }
+
+    async fn complete_job(&self, id: ObjectId, worker: &str) -> Result<bool> {
+        Database::complete_job(self, id, worker).await
+    }
+
+    async fn reschedule_job(&self, id: ObjectId, worker: &str, run_at: DateTime<Utc>, error: Option<&str>) -> Result<bool> {
+        Database::reschedule_job(self, id, worker, run_at, error).await
+    }
+
+    async fn bury_job(&self, id: ObjectId, worker: &str, error: &str) -> Result<bool> {
+        Database::bury_job(self, id, worker, error).await
+    }
+
+    async fn requeue_job(&self, id: ObjectId, job_type: Option<&str>) -> Result<bool> {
+        Database::requeue_job(self, id, job_type).await
+    }
+
+    async fn list_jobs(&self, status: Option<JobStatus>, job_type: Option<&str>, skip: u64, limit: i64) -> Result<(Vec<Job>, u64)> {
+        Database::list_jobs(self, status, job_type, skip, limit).await
+    }
+
+    async fn count_jobs(&self, job_type: Option<&str>) -> Result<JobCounts> {
+        Database::count_jobs(self, job_type).await
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for Database {
+    async fn claim_idempotency_key(&self, record: &IdempotencyRecord, now: DateTime<Utc>) -> Result<Option<IdempotencyRecord>> {
+        Database::claim_idempotency_key(self, record, now).await
+    }
+
+    async fn complete_idempotency_key(&self, id: ObjectId, status: u16, content_type: Option<&str>, body: &str) -> Result<()> {
+        Database::complete_idempotency_key(self, id, status, content_type, body).await
+    }
+
+    async fn release_idempotency_key(&self, id: ObjectId) -> Result<()> {
+        Database::release_idempotency_key(self, id).await
+    }
+}
+
+#[async_trait]
+impl EmailOutboxStore for Database {
+    async fn enqueue_email(&self, email: &OutboxEmail) -> Result<ObjectId> {
+        Database::enqueue_email(self, email).await
+    }
+
+    async fn retry_email(&self, id: ObjectId) -> Result<bool> {
+        Database::retry_email(self, id).await
+    }
+
+    async fn count_emails_by_status(&self) -> Result<OutboxStats> {
+        Database::count_emails_by_status(self).await
+    }
+}
+
+#[async_trait]
+impl WebhookStore for Database {
+    async fn create_webhook(&self, webhook: &Webhook) -> Result<ObjectId> {
+        Database::create_webhook(self, webhook).await
+    }
+
+    async fn get_webhook(&self, id: ObjectId) -> Result<Option<Webhook>> {
+        Database::get_webhook(self, id).await
+    }
+
+    async fn list_webhooks(&self) -> Result<Vec<Webhook>> {
+        Database::list_webhooks(self).await
+    }
+
+    async fn update_webhook(&self, id: ObjectId, url: &str, events: &[WebhookEventType], active: bool) -> Result<bool> {
+        Database::update_webhook(self, id, url, events, active).await
+    }
+
+    async fn delete_webhook(&self, id: ObjectId) -> Result<bool> {
+        Database::delete_webhook(self, id).await
+    }
+
+    async fn subscribed_webhooks(&self, tenant: &str, event_type: WebhookEventType) -> Result<Vec<Webhook>> {
+        Database::subscribed_webhooks(self, tenant, event_type).await
+    }
+
+    async fn enqueue_webhook_delivery(&self, delivery: &WebhookDeliveryJob) -> Result<ObjectId> {
+        Database::enqueue_webhook_delivery(self, delivery).await
+    }
+
+    async fn record_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<()> {
+        Database::record_webhook_delivery(self, delivery).await
+    }
+
+    async fn list_webhook_deliveries(&self, webhook_id: ObjectId, limit: i64) -> Result<Vec<WebhookDelivery>> {
+        Database::list_webhook_deliveries(self, webhook_id, limit).await
+    }
+}
+
+#[async_trait]
+impl FeedEventStore for Database {
+    async fn record_feed_event(&self, event: &FeedEvent) -> Result<ObjectId> {
+        Database::record_feed_event(self, event).await
+    }
+
+    async fn feed_events_after(&self, recipient_did: &str, after: Option<ObjectId>, limit: i64) -> Result<Vec<FeedEvent>> {
+        Database::feed_events_after(self, recipient_did, after, limit).await
+    }
+}
+
+#[async_trait]
+impl PractitionerStore for Database {
+    async fn create_practitioner(&self, practitioner: &Practitioner) -> Result<()> {
+        Database::create_practitioner(self, practitioner).await
+    }
+
+    async fn get_practitioner_by_did(&self, did: &str) -> Result<Option<Practitioner>> {
+        Database::get_practitioner_by_did(self, did).await
+    }
+    async fn verify_practitioner_license(&self, did: &str) -> Result<bool> {
+        Database::verify_practitioner_license(self, did).await
+    }
+    async fn licensed_practitioners(&self) -> Result<Vec<Practitioner>> {
+        Database::list_practitioners(self, Some(true)).await
+    }
+    async fn claim_license_expiry_warning(&self, did: &str, expiry_date: &str, now: DateTime<Utc>) -> Result<bool> {
+        Database::claim_license_expiry_warning(self, did, expiry_date, now).await
+    }
+    async fn revoke_license_verification(&self, did: &str) -> Result<bool> {
+        Database::revoke_license_verification(self, did).await
+    }
+}
+
+#[async_trait]
+impl PrescriptionStore for Database {
+    async fn create_prescription(&self, prescription: &Prescription) -> Result<ObjectId> {
+        Database::create_prescription(self, prescription).await
+    }
+
+    async fn get_prescription(&self, id: ObjectId) -> Result<Option<Prescription>> {
+        Database::get_prescription(self, id).await
+    }
+
+    async fn list_prescriptions(&self, patient_did: &str, status: Option<PrescriptionStatus>) -> Result<Vec<Prescription>> {
+        Database::list_prescriptions(self, patient_did, status).await
+    }
+
+    async fn update_prescription_status(
+        &self,
+        id: ObjectId,
+        from: PrescriptionStatus,
+        to: PrescriptionStatus,
+        reason: Option<FhirCodeableConcept>,
+    ) -> Result<Option<Prescription>> {
+        Database::update_prescription_status(self, id, from, to, reason).await
+    }
+
+    async fn get_prescription_by_credential(&self, credential_hash: &str) -> Result<Option<Prescription>> {
+        Database::get_prescription_by_credential(self, credential_hash).await
+    }
+
+    async fn add_dispense(&self, id: ObjectId, dispense: &Dispense, previous_dispenses: usize, fills: bool) -> Result<Option<Prescription>> {
+        Database::add_dispense(self, id, dispense, previous_dispenses, fills).await
+    }
+}
+
+#[async_trait]
+impl CredentialStore for Database {
+    async fn create_verifiable_credential(&self, credential: &VerifiableCredential, outbox: &[OutboxAction]) -> Result<()> {
+        Database::create_verifiable_credential(self, credential, outbox).await
+    }
+
+    async fn set_credential_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        Database::set_credential_transaction(self, id, transaction_id).await
+    }
+
+    async fn create_credential_batch(&self, batch: &CredentialBatch, credentials: &[VerifiableCredential], outbox: &[OutboxAction]) -> Result<()> {
+        Database::create_credential_batch(self, batch, credentials, outbox).await
+    }
+
+    async fn get_credential_batch(&self, id: ObjectId) -> Result<Option<CredentialBatch>> {
+        Database::get_credential_batch(self, id).await
+    }
+
+    async fn set_credential_batch_transaction(&self, id: ObjectId, transaction_id: &str) -> Result<bool> {
+        Database::set_credential_batch_transaction(self, id, transaction_id).await
+    }
+
+    async fn get_credential(&self, id: ObjectId) -> Result<Option<VerifiableCredential>> {
+        Database::get_credential(self, id).await
+    }
+
+    async fn get_credential_by_hash(&self, ipfs_hash: &str) -> Result<Option<VerifiableCredential>> {
+        Database::get_credential_by_hash(self, ipfs_hash).await
+    }
+
+    async fn list_credentials_by_subject(&self, subject_did: &str) -> Result<Vec<VerifiableCredential>> {
+        Database::list_credentials_by_subject(self, subject_did).await
+    }
+
+    async fn revoke_credential(&self, ipfs_hash: &str) -> Result<bool> {
+        Database::revoke_credential(self, ipfs_hash).await
+    }
+
+    async fn revoked_status_indexes(&self, list_id: &str) -> Result<Vec<u32>> {
+        Database::revoked_status_indexes(self, list_id).await
+    }
+}
+
+#[async_trait]
+impl IssuerStore for Database {
+    async fn create_issuer(&self, issuer: &TrustedIssuer) -> Result<ObjectId> {
+        Database::create_issuer(self, issuer).await
+    }
+
+    async fn get_issuer(&self, did: &str) -> Result<Option<TrustedIssuer>> {
+        Database::get_issuer(self, did).await
+    }
+
+    async fn list_issuers(&self) -> Result<Vec<TrustedIssuer>> {
+        Database::list_issuers(self).await
+    }
+
+    async fn update_issuer(&self, issuer: &TrustedIssuer) -> Result<Option<TrustedIssuer>> {
+        Database::update_issuer(self, issuer).await
+    }
+}
+
+#[async_trait]
+impl StatusListStore for Database {
+    async fn allocate_status_index(&self, list_size: u32) -> Result<StatusListEntry> {
+        Database::allocate_status_index(self, list_size).await
+    }
+
+    async fn get_status_list(&self, list_id: &str) -> Result<Option<CredentialStatusList>> {
+        Database::get_status_list(self, list_id).await
+    }
+
+    async fn mark_status_list_stale(&self, list_id: &str) -> Result<()> {
+        Database::mark_status_list_stale(self, list_id).await
+    }
+
+    async fn stale_status_lists(&self) -> Result<Vec<CredentialStatusList>> {
+        Database::stale_status_lists(self).await
+    }
+
+    async fn publish_status_list(&self, list_id: &str, revision: u64, document: &str, ipfs_hash: &str) -> Result<Option<CredentialStatusList>> {
+        Database::publish_status_list(self, list_id, revision, document, ipfs_hash).await
+    }
+}
+
+#[async_trait]
+impl AppointmentStore for Database {
+    async fn create_appointment(&self, appointment: &Appointment) -> Result<ObjectId> {
+        Database::create_appointment(self, appointment).await
+    }
+
+    async fn get_appointment(&self, id: ObjectId) -> Result<Option<Appointment>> {
+        Database::get_appointment(self, id).await
+    }
+
+    async fn update_appointment_status(&self, id: ObjectId, from: AppointmentStatus, to: AppointmentStatus) -> Result<bool> {
+        Database::update_appointment_status(self, id, from, to).await
+    }
+
+    async fn link_appointment_encounter(&self, id: ObjectId, encounter_id: ObjectId) -> Result<()> {
+        Database::link_appointment_encounter(self, id, encounter_id).await
+    }
+
+    async fn claim_appointment_reminder(&self, stage: ReminderStage, now: DateTime<Utc>) -> Result<Option<Appointment>> {
+        Database::claim_appointment_reminder(self, stage, now).await
+    }
+
+    async fn list_appointments(&self, party: AppointmentParty, did: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Appointment>> {
+        Database::list_appointments(self, party, did, from, to).await
+    }
+
+    async fn list_affiliated_appointments(&self, affiliations: &[Affiliation], from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<Appointment>> {
+        Database::list_affiliated_appointments(self, affiliations, from, to).await
+    }
+}
+
+#[async_trait]
+impl ConsentStore for Database {
+    async fn create_consent(&self, consent: &Consent) -> Result<ObjectId> {
+        Database::create_consent(self, consent).await
+    }
+
+    async fn get_consent(&self, id: ObjectId) -> Result<Option<Consent>> {
+        Database::get_consent(self, id).await
+    }
+
+    async fn list_consents(&self, patient_did: &str) -> Result<Vec<Consent>> {
+        Database::list_consents(self, patient_did).await
+    }
+
+    async fn active_consents(&self, patient_did: &str, grantee_did: &str) -> Result<Vec<Consent>> {
+        Database::active_consents(self, patient_did, grantee_did).await
+    }
+
+    async fn deactivate_consent(&self, id: ObjectId, fhir_consent: &FhirConsent, ipfs_hash: &str) -> Result<bool> {
+        Database::deactivate_consent(self, id, fhir_consent, ipfs_hash).await
+    }
+}
+
+#[async_trait]
+impl RelationshipStore for Database {
+    async fn create_relationship(&self, relationship: &Relationship) -> Result<ObjectId> {
+        Database::create_relationship(self, relationship).await
+    }
+
+    async fn get_relationship(&self, guardian_did: &str, dependent_did: &str) -> Result<Option<Relationship>> {
+        Database::get_relationship(self, guardian_did, dependent_did).await
+    }
+
+    async fn list_relationships(&self, did: &str) -> Result<Vec<Relationship>> {
+        Database::list_relationships(self, did).await
+    }
+}
+
+#[async_trait]
+impl OrganizationStore for Database {
+    async fn create_organization(&self, organization: &Organization) -> Result<ObjectId> {
+        Database::create_organization(self, organization).await
+    }
+
+    async fn get_organization(&self, id: ObjectId) -> Result<Option<Organization>> {
+        Database::get_organization(self, id).await
+    }
+
+    async fn find_organization_by_identifier(&self, identifier: &str) -> Result<Option<Organization>> {
+        Database::find_organization_by_identifier(self, identifier).await
+    }
+
+    async fn list_organizations(&self) -> Result<Vec<Organization>> {
+        Database::list_organizations(self).await
+    }
+
+    async fn update_organization(&self, id: ObjectId, fhir_organization: &FhirOrganization) -> Result<bool> {
+        Database::update_organization(self, id, fhir_organization).await
+    }
+
+    async fn create_affiliation(&self, affiliation: &Affiliation) -> Result<ObjectId> {
+        Database::create_affiliation(self, affiliation).await
+    }
+
+    async fn get_affiliation(&self, id: ObjectId) -> Result<Option<Affiliation>> {
+        Database::get_affiliation(self, id).await
+    }
+
+    async fn list_affiliations(&self, organization_id: ObjectId) -> Result<Vec<Affiliation>> {
+        Database::list_affiliations(self, organization_id).await
+    }
+
+    async fn decide_affiliation(&self, id: ObjectId, status: AffiliationStatus, decided_by: &str, now: DateTime<Utc>) -> Result<Option<Affiliation>> {
+        Database::decide_affiliation(self, id, status, decided_by, now).await
+    }
+
+    async fn admin_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>> {
+        Database::admin_affiliations(self, practitioner_did).await
+    }
+    async fn active_affiliations(&self, practitioner_did: &str) -> Result<Vec<Affiliation>> {
+        Database::active_affiliations(self, practitioner_did).await
+    }
+}
+
+#[async_trait]
+impl BreakGlassStore for Database {
+    async fn create_break_glass_event(&self, event: &BreakGlassEvent) -> Result<ObjectId> {
+        Database::create_break_glass_event(self, event).await
+    }
+
+    async fn list_break_glass_events(&self, reviewed: Option<bool>) -> Result<Vec<BreakGlassEvent>> {
+        Database::list_break_glass_events(self, reviewed).await
+    }
+
+    async fn review_break_glass_event(&self, id: ObjectId, reviewer_did: &str, notes: Option<String>) -> Result<Option<BreakGlassEvent>> {
+        Database::review_break_glass_event(self, id, reviewer_did, notes).await
+    }
+
+    async fn claim_break_glass_alert(&self, created_before: DateTime<Utc>, now: DateTime<Utc>) -> Result<Option<BreakGlassEvent>> {
+        Database::claim_break_glass_alert(self, created_before, now).await
+    }
+}
+
+#[async_trait]
+impl ReencryptionJobStore for Database {
+    async fn create_reencryption_job(&self, job: &ReencryptionJob) -> Result<ObjectId> {
+        Database::create_reencryption_job(self, job).await
+    }
+
+    async fn get_reencryption_job(&self, id: ObjectId) -> Result<Option<ReencryptionJob>> {
+        Database::get_reencryption_job(self, id).await
+    }
+
+    async fn list_reencryption_jobs(&self) -> Result<Vec<ReencryptionJob>> {
+        Database::list_reencryption_jobs(self).await
+    }
+
+    async fn claim_reencryption_job(&self, lease_until: DateTime<Utc>) -> Result<Option<ReencryptionJob>> {
+        Database::claim_reencryption_job(self, lease_until).await
+    }
+
+    async fn running_reencryption_job(&self) -> Result<Option<ReencryptionJob>> {
+        Database::running_reencryption_job(self).await
+    }
+
+    async fn record_reencryption_progress(
+        &self,
+        id: ObjectId,
+        lease_until: DateTime<Utc>,
+        renewed_until: DateTime<Utc>,
+        resume_after: ObjectId,
+        reencrypted: u64,
+        failures: &[ReencryptionFailure],
+    ) -> Result<bool> {
+        Database::record_reencryption_progress(self, id, lease_until, renewed_until, resume_after, reencrypted, failures).await
+    }
+
+    async fn complete_reencryption_job(&self, id: ObjectId, lease_until: DateTime<Utc>) -> Result<Option<ReencryptionJob>> {
+        Database::complete_reencryption_job(self, id, lease_until).await
+    }
+}
+
+#[async_trait]
+impl ComplianceReportStore for Database {
+    async fn create_compliance_report(&self, report: &ComplianceReport) -> Result<ObjectId> {
+        Database::create_compliance_report(self, report).await
+    }
+
+    async fn get_compliance_report(&self, id: ObjectId) -> Result<Option<ComplianceReport>> {
+        Database::get_compliance_report(self, id).await
+    }
+
+    async fn list_compliance_reports(&self, limit: i64) -> Result<Vec<ComplianceReport>> {
+        Database::list_compliance_reports(self, limit).await
+    }
+
+    async fn record_compliance_report_progress(&self, id: ObjectId, progress: u8, stage: &str) -> Result<bool> {
+        Database::record_compliance_report_progress(self, id, progress, stage).await
+    }
+
+    async fn complete_compliance_report(&self, id: ObjectId, artifacts: &ComplianceReportArtifacts, anchors_valid: bool) -> Result<bool> {
+        Database::complete_compliance_report(self, id, artifacts, anchors_valid).await
+    }
+
+    async fn fail_compliance_report(&self, id: ObjectId, error: &str) -> Result<bool> {
+        Database::fail_compliance_report(self, id, error).await
+    }
+}
+
+#[async_trait]
+impl PatientExportStore for Database {
+    async fn create_patient_export(&self, export: &PatientExport) -> Result<ObjectId> {
+        Database::create_patient_export(self, export).await
+    }
+
+    async fn get_patient_export(&self, id: ObjectId) -> Result<Option<PatientExport>> {
+        Database::get_patient_export(self, id).await
+    }
+
+    async fn pending_patient_export(&self, patient_did: &str) -> Result<Option<PatientExport>> {
+        Database::pending_patient_export(self, patient_did).await
+    }
+
+    async fn complete_patient_export(&self, id: ObjectId, archive: &ExportArchive, expires_at: DateTime<Utc>) -> Result<bool> {
+        Database::complete_patient_export(self, id, archive, expires_at).await
+    }
+
+    async fn fail_patient_export(&self, id: ObjectId, error: &str) -> Result<bool> {
+        Database::fail_patient_export(self, id, error).await
+    }
+
+    async fn expired_patient_exports(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<PatientExport>> {
+        Database::expired_patient_exports(self, now, limit).await
+    }
+
+    async fn expire_patient_export(&self, id: ObjectId) -> Result<bool> {
+        Database::expire_patient_export(self, id).await
+    }
+}
+
+#[async_trait]
+impl ReferralStore for Database {
+    async fn create_referral(&self, referral: &Referral) -> Result<ObjectId> {
+        Database::create_referral(self, referral).await
+    }
+
+    async fn get_referral(&self, id: ObjectId) -> Result<Option<Referral>> {
+        Database::get_referral(self, id).await
+    }
+
+    async fn list_referrals(&self, folder: ReferralFolder, did: &str, status: Option<ReferralStatus>) -> Result<Vec<Referral>> {
+        Database::list_referrals(self, folder, did, status).await
+    }
+
+    async fn transition_referral(&self, id: ObjectId, from: ReferralStatus, to: ReferralStatus, note: Option<String>) -> Result<Option<Referral>> {
+        Database::transition_referral(self, id, from, to, note).await
+    }
+
+    async fn link_referral_grant(&self, id: ObjectId, access_control_id: ObjectId) -> Result<()> {
+        Database::link_referral_grant(self, id, access_control_id).await
+    }
+}
+
+#[async_trait]
+impl AvailabilityStore for Database {
+    async fn get_availability(&self, practitioner_did: &str) -> Result<Option<Availability>> {
+        Database::get_availability(self, practitioner_did).await
+    }
+
+    async fn save_availability(&self, availability: &Availability) -> Result<()> {
+        Database::save_availability(self, availability).await
+    }
+}
+
+#[async_trait]
+impl ChatStore for Database {
+    async fn create_chat_session(&self, session: &ChatSession) -> Result<ObjectId> {
+        Database::create_chat_session(self, session).await
+    }
+
+    async fn get_chat_session(&self, id: ObjectId, owner_did: &str) -> Result<Option<ChatSession>> {
+        Database::get_chat_session(self, id, owner_did).await
+    }
+
+    async fn list_chat_sessions(&self, owner_did: &str) -> Result<Vec<ChatSession>> {
+        Database::list_chat_sessions(self, owner_did).await
+    }
+
+    async fn add_chat_message(&self, message: &ChatMessage) -> Result<ObjectId> {
+        Database::add_chat_message(self, message).await
+    }
+
+    async fn recent_chat_messages(&self, session_id: ObjectId, limit: i64) -> Result<Vec<ChatMessage>> {
+        Database::recent_chat_messages(self, session_id, limit).await
+    }
+
+    async fn list_chat_messages(&self, session_id: ObjectId) -> Result<Vec<ChatMessage>> {
+        Database::list_chat_messages(self, session_id).await
+    }
+
+    async fn get_chat_usage(&self, user_did: &str, day: &str) -> Result<Option<ChatUsage>> {
+        Database::get_chat_usage(self, user_did, day).await
+    }
+
+    async fn record_chat_usage(&self, user_did: &str, day: &str, prompt_tokens: u32, reply_tokens: u32) -> Result<()> {
+        Database::record_chat_usage(self, user_did, day, prompt_tokens, reply_tokens).await
+    }
+
+    async fn list_chat_usage(&self, since_day: &str) -> Result<Vec<ChatUsage>> {
+        Database::list_chat_usage(self, since_day).await
+    }
+}
@@ -0,0 +1,175 @@
+//! Log output and, with the `otel` feature, trace export over OTLP.
+//!
+//! Spans are ordinary `tracing` spans: the request log opens one per request, continuing the
+//! caller's `traceparent`, and MongoDB commands, IPFS, Hedera and the outside APIs open theirs
+//! beneath it. Without the feature they still give log lines their context; they are just not
+//! exported. Span attributes name collections, operations, CIDs and contract functions, never
+//! the documents, filters or message contents involved.
+
+use axum::http::HeaderMap;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::config::{DynamicConfig, LogFormat, LoggingConfig, TelemetryConfig};
+
+/// Something the subscriber passes spans and events on to besides the log output, like the trace exporter
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The subscriber `main` installs: `level` filter directives, written pretty or as JSON lines
+/// to `writer`, with `layers` seeing the same spans and events. The returned handle changes the
+/// level while the subscriber runs.
+pub fn subscriber<W>(logging: &LoggingConfig, level: &str, writer: W, layers: Vec<BoxedLayer>) -> (Box<dyn tracing::Subscriber + Send + Sync>, LogLevelHandle)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    // Config validation already rejected directives that do not parse
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|_| EnvFilter::new(DynamicConfig::default().log_level));
+    let (filter, handle) = reload::Layer::new(filter);
+    let output = tracing_subscriber::fmt::layer().with_writer(writer);
+    let output = match logging.format {
+        LogFormat::Pretty => output.pretty().boxed(),
+        LogFormat::Json => output.json().flatten_event(true).boxed(),
+    };
+    let layers: Vec<BoxedLayer> = std::iter::once(output).chain(layers).collect();
+    (Box::new(tracing_subscriber::registry().with(layers.with_filter(filter))), LogLevelHandle(handle))
+}
+
+/// Swaps the filter directives of a running [`subscriber`]
+#[derive(Clone)]
+pub struct LogLevelHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogLevelHandle {
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        self.0.reload(EnvFilter::try_new(directives)?)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for LogLevelHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LogLevelHandle")
+    }
+}
+
+/// The OTLP exporter, when `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Spans are sent in batches;
+/// call [`shutdown`] before exiting so the last ones are not lost.
+#[cfg(feature = "otel")]
+pub fn exporter(telemetry: &TelemetryConfig) -> anyhow::Result<Option<BoxedLayer>> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::Resource;
+
+    let Some(endpoint) = &telemetry.otlp_endpoint else {
+        return Ok(None);
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(
+            opentelemetry_sdk::trace::config()
+                .with_sampler(sampler(telemetry.sample_ratio))
+                .with_resource(Resource::new([KeyValue::new("service.name", telemetry.service_name.clone())])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    Ok(Some(layer(tracer)))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn exporter(_telemetry: &TelemetryConfig) -> anyhow::Result<Option<BoxedLayer>> {
+    Ok(None)
+}
+
+/// Samples `ratio` of new traces, and follows the caller's decision for traces it started
+#[cfg(feature = "otel")]
+pub fn sampler(ratio: f64) -> opentelemetry_sdk::trace::Sampler {
+    use opentelemetry_sdk::trace::Sampler;
+    Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+}
+
+/// Hands spans to `tracer`; [`exporter`] builds its own, tests pass one with an in-memory exporter
+#[cfg(feature = "otel")]
+pub fn layer<T>(tracer: T) -> BoxedLayer
+where
+    T: opentelemetry::trace::Tracer + tracing_opentelemetry::PreSampledTracer + Send + Sync + 'static,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer).boxed()
+}
+
+/// Send the spans still waiting in the exporter's batch
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Make `span` a child of the trace named by the request's W3C `traceparent` header, so a trace
+/// started by the front proxy continues here. Does nothing without the header or the `otel` feature.
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        if headers.contains_key("traceparent") {
+            span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(headers)));
+        }
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
+
+#[cfg(feature = "otel")]
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Opens a span for each command the MongoDB driver sends, named after the command and its
+/// collection, e.g. `findAndModify encounters`. The driver reports commands from the task that
+/// made the call, so each span sits under the `Database` method and request it belongs to.
+/// Failures only mark the span; their messages can quote the document that clashed.
+#[derive(Default)]
+pub struct MongoCommandSpans {
+    open: Mutex<HashMap<i32, tracing::Span>>,
+}
+
+impl CommandEventHandler for MongoCommandSpans {
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        // The command's first field names the collection, except for commands like commitTransaction
+        let collection = event.command.get_str(&event.command_name).unwrap_or_default();
+        let name = if collection.is_empty() { event.command_name.clone() } else { format!("{} {}", event.command_name, collection) };
+        let span = tracing::info_span!(
+            "mongodb.command",
+            otel.name = %name,
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            db.system = "mongodb",
+            db.name = %event.db,
+            db.operation = %event.command_name,
+            db.mongodb.collection = collection,
+        );
+        self.open.lock().unwrap().insert(event.request_id, span);
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.open.lock().unwrap().remove(&event.request_id);
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        if let Some(span) = self.open.lock().unwrap().remove(&event.request_id) {
+            span.record("otel.status_code", "ERROR");
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Which clinic (tenant) a request acts for, and the collection wrapper that keeps its queries inside it.
+//!
+//! The auth middleware runs every request inside [`scope`]. [`Database`](crate::database::Database)
+//! opens tenant-scoped collections through [`ScopedCollection`], which reads that scope, so no
+//! handler or service passes the tenant along or has to remember to filter by it.
+
+use bson::{doc, Bson, Document};
+use mongodb::options::{AggregateOptions, CountOptions, DeleteOptions, DistinctOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, InsertOneOptions, UpdateModifications, UpdateOptions};
+use mongodb::results::{DeleteResult, InsertOneResult, UpdateResult};
+use mongodb::{Collection, Cursor};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+
+/// Tenant of everything written before tenancy existed, and of admins and practitioners
+/// whose tokens do not name one
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Collections whose documents belong to one tenant. Patients are global, as is everything
+/// keyed only by the patient (consents, grants, prescriptions, credentials).
+pub const TENANT_SCOPED_COLLECTIONS: [&str; 6] = ["practitioners", "organizations", "encounters", "appointments", "audit_logs", "webhooks"];
+
+tokio::task_local! {
+    static CURRENT_TENANT: Option<String>;
+}
+
+/// Run `future` confined to `tenant`, or across every tenant when `None`
+pub async fn scope<F: Future>(tenant: Option<String>, future: F) -> F::Output {
+    CURRENT_TENANT.scope(tenant, future).await
+}
+
+/// The tenant the running request is confined to. `None` for patients, platform admins and
+/// background work, which see every tenant; also `None` in tasks spawned off a request.
+pub fn current() -> Option<String> {
+    CURRENT_TENANT.try_with(Clone::clone).ok().flatten()
+}
+
+/// Matches the tenant's documents. Documents without a tenant were written before tenancy or
+/// outside any tenant, and belong to the default one.
+pub fn tenant_filter(tenant: &str) -> Document {
+    if tenant == DEFAULT_TENANT {
+        doc! { "tenant_id": { "$in": [DEFAULT_TENANT, Bson::Null] } }
+    } else {
+        doc! { "tenant_id": tenant }
+    }
+}
+
+/// A collection confined to the tenant in scope when it was opened. Reads and updates only
+/// match that tenant's documents and inserts are stamped with it; unconfined, it passes
+/// everything through.
+pub struct ScopedCollection<T: Send + Sync> {
+    collection: Collection<T>,
+    tenant: Option<String>,
+}
+
+impl<T: Send + Sync> ScopedCollection<T> {
+    pub fn new(collection: Collection<T>) -> Self {
+        Self { collection, tenant: current() }
+    }
+
+    fn filter(&self, filter: impl Into<Option<Document>>) -> Document {
+        let filter = filter.into().unwrap_or_default();
+        match &self.tenant {
+            None => filter,
+            Some(tenant) if filter.is_empty() => tenant_filter(tenant),
+            // `$and` so the tenant clause cannot clash with a key or `$or` already in the filter
+            Some(tenant) => doc! { "$and": [filter, tenant_filter(tenant)] },
+        }
+    }
+
+    pub async fn insert_one(&self, item: &T, options: impl Into<Option<InsertOneOptions>>) -> mongodb::error::Result<InsertOneResult>
+    where
+        T: Serialize,
+    {
+        match &self.tenant {
+            None => self.collection.insert_one(item, options).await,
+            Some(tenant) => {
+                let mut document = bson::to_document(item)?;
+                document.insert("tenant_id", tenant);
+                self.collection.clone_with_type::<Document>().insert_one(document, options).await
+            }
+        }
+    }
+
+    pub async fn find(&self, filter: impl Into<Option<Document>>, options: impl Into<Option<FindOptions>>) -> mongodb::error::Result<Cursor<T>>
+    where
+        T: DeserializeOwned + Unpin,
+    {
+        self.collection.find(self.filter(filter), options).await
+    }
+
+    pub async fn find_one(&self, filter: impl Into<Option<Document>>, options: impl Into<Option<FindOneOptions>>) -> mongodb::error::Result<Option<T>>
+    where
+        T: DeserializeOwned + Unpin,
+    {
+        self.collection.find_one(self.filter(filter), options).await
+    }
+
+    pub async fn find_one_and_update(
+        &self,
+        filter: Document,
+        update: impl Into<UpdateModifications>,
+        options: impl Into<Option<FindOneAndUpdateOptions>>,
+    ) -> mongodb::error::Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.collection.find_one_and_update(self.filter(filter), update, options).await
+    }
+
+    pub async fn update_one(&self, filter: Document, update: impl Into<UpdateModifications>, options: impl Into<Option<UpdateOptions>>) -> mongodb::error::Result<UpdateResult> {
+        self.collection.update_one(self.filter(filter), update, options).await
+    }
+
+    pub async fn update_many(&self, filter: Document, update: impl Into<UpdateModifications>, options: impl Into<Option<UpdateOptions>>) -> mongodb::error::Result<UpdateResult> {
+        self.collection.update_many(self.filter(filter), update, options).await
+    }
+
+    pub async fn delete_one(&self, filter: Document, options: impl Into<Option<DeleteOptions>>) -> mongodb::error::Result<DeleteResult> {
+        self.collection.delete_one(self.filter(filter), options).await
+    }
+
+    pub async fn count_documents(&self, filter: impl Into<Option<Document>>, options: impl Into<Option<CountOptions>>) -> mongodb::error::Result<u64> {
+        self.collection.count_documents(self.filter(filter), options).await
+    }
+
+    pub async fn distinct(&self, field: &str, filter: impl Into<Option<Document>>, options: impl Into<Option<DistinctOptions>>) -> mongodb::error::Result<Vec<Bson>> {
+        self.collection.distinct(field, self.filter(filter), options).await
+    }
+
+    /// Runs `pipeline` over the tenant's documents only
+    pub async fn aggregate(&self, pipeline: impl IntoIterator<Item = Document>, options: impl Into<Option<AggregateOptions>>) -> mongodb::error::Result<Cursor<Document>> {
+        let stages: Vec<Document> = match &self.tenant {
+            None => pipeline.into_iter().collect(),
+            Some(tenant) => std::iter::once(doc! { "$match": tenant_filter(tenant) }).chain(pipeline).collect(),
+        };
+        self.collection.aggregate(stages, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_scope_is_visible_only_inside_it() {
+        assert_eq!(current(), None);
+        let inside = scope(Some("clinic-b".to_string()), async { current() }).await;
+        assert_eq!(inside.as_deref(), Some("clinic-b"));
+        assert_eq!(scope(None, async { current() }).await, None);
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn documents_without_a_tenant_belong_to_the_default_one() {
+        assert_eq!(tenant_filter(DEFAULT_TENANT), doc! { "tenant_id": { "$in": [DEFAULT_TENANT, Bson::Null] } });
+        assert_eq!(tenant_filter("clinic-b"), doc! { "tenant_id": "clinic-b" });
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{Duration, DurationRound, Utc};
+use serde_json::{json, Value};
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.8001";
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Publish the practitioner's hours as the same window on every day of the week, in UTC
+async fn publish_availability(app: &TestApp, start: &str, end: &str, exceptions: Value) {
+    let weekly: Vec<Value> = WEEKDAYS.iter().map(|day| json!({ "weekday": day, "start": start, "end": end })).collect();
+    let response = app
+        .client
+        .put(app.url(&format!("/api/practitioners/{}/availability", PRACTITIONER)))
+        .bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner))
+        .json(&json!({ "timezone": "UTC", "weekly": weekly, "exceptions": exceptions }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+async fn request(app: &TestApp, patient_did: &str, start: chrono::DateTime<Utc>, minutes: i64) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/appointments"))
+        .bearer_auth(app.mint_jwt(patient_did, Role::Patient))
+        .json(&json!({
+            "practitioner_did": PRACTITIONER,
+            "start": start,
+            "end": start + Duration::minutes(minutes),
+            "reason": { "coding": [], "text": "Follow-up" },
+            "location": "Room 4",
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn request_slot(app: &TestApp, patient_did: &str, start: chrono::DateTime<Utc>) -> String {
+    let body: Value = request(app, patient_did, start, 30).await.json().await.unwrap();
+    assert_eq!(body["data"]["status"], "requested");
+    body["data"]["_id"]["$oid"].as_str().unwrap().to_string()
+}
+
+async fn post(app: &TestApp, path: &str, did: &str, role: Role, body: Value) -> reqwest::Response {
+    app.client.post(app.url(path)).bearer_auth(app.mint_jwt(did, role)).json(&body).send().await.unwrap()
+}
+
+async fn slot_starts(app: &TestApp, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>) -> Vec<chrono::DateTime<Utc>> {
+    let body: Value = app
+        .client
+        .get(app.url(&format!("/api/practitioners/{}/slots", PRACTITIONER)))
+        .query(&[("from", from.to_rfc3339()), ("to", to.to_rfc3339())])
+        .bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.8101", Role::Patient))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["data"].as_array().unwrap().iter().map(|slot| slot["start"].as_str().unwrap().parse().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn confirmed_slot_cannot_be_double_booked_until_cancelled() {
+    let app = spawn_test_app().await;
+    publish_availability(&app, "00:00", "23:59", json!([])).await;
+    let start = (Utc::now() + Duration::days(2)).duration_trunc(Duration::hours(1)).unwrap();
+    let first = request_slot(&app, "did:hedera:testnet:0.0.8101", start).await;
+    let second = request_slot(&app, "did:hedera:testnet:0.0.8102", start).await;
+
+    let confirmed = post(&app, &format!("/api/appointments/{}/confirm", first), PRACTITIONER, Role::Practitioner, json!({ "create_encounter": true })).await;
+    let body: Value = confirmed.json().await.unwrap();
+    assert_eq!(body["data"]["status"], "confirmed");
+    assert!(body["data"]["encounter_id"].is_object());
+
+    let double_booked = post(&app, &format!("/api/appointments/{}/confirm", second), PRACTITIONER, Role::Practitioner, json!({})).await;
+    assert_eq!(double_booked.status(), reqwest::StatusCode::CONFLICT);
+
+    let cancelled = post(&app, &format!("/api/appointments/{}/cancel", first), "did:hedera:testnet:0.0.8101", Role::Patient, json!({})).await;
+    assert_eq!(cancelled.status(), reqwest::StatusCode::OK);
+    let rebooked = post(&app, &format!("/api/appointments/{}/confirm", second), PRACTITIONER, Role::Practitioner, json!({})).await;
+    let body: Value = rebooked.json().await.unwrap();
+    assert_eq!(body["data"]["status"], "confirmed");
+
+    let listed: Value = app
+        .client
+        .get(app.url("/api/appointments?role=practitioner"))
+        .bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed["data"].as_array().unwrap().len(), 2);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn requests_must_fit_free_slots_of_the_availability() {
+    let app = spawn_test_app().await;
+    let day = (Utc::now() + Duration::days(2)).duration_trunc(Duration::days(1)).unwrap();
+    let day_off = (day + Duration::days(1)).date_naive();
+    publish_availability(&app, "09:00", "11:00", json!([{ "date": day_off, "windows": [] }])).await;
+    let at = |hour: i64, minute: i64| day + Duration::hours(hour) + Duration::minutes(minute);
+
+    assert_eq!(slot_starts(&app, day, day + Duration::days(2)).await, vec![at(9, 0), at(9, 30), at(10, 0), at(10, 30)]);
+
+    // Outside the hours, misaligned, or on the day off
+    for (start, minutes) in [(at(8, 30), 30), (at(9, 15), 30), (at(24 + 9, 0), 30)] {
+        let response = request(&app, "did:hedera:testnet:0.0.8101", start, minutes).await;
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    }
+
+    // Two overlapping requests with different starts: only the first confirmation wins
+    let hour_long: Value = request(&app, "did:hedera:testnet:0.0.8101", at(9, 0), 60).await.json().await.unwrap();
+    let hour_long = hour_long["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    let overlapping = request_slot(&app, "did:hedera:testnet:0.0.8102", at(9, 30)).await;
+    let confirmed = post(&app, &format!("/api/appointments/{}/confirm", hour_long), PRACTITIONER, Role::Practitioner, json!({})).await;
+    assert_eq!(confirmed.status(), reqwest::StatusCode::OK);
+    let clash = post(&app, &format!("/api/appointments/{}/confirm", overlapping), PRACTITIONER, Role::Practitioner, json!({})).await;
+    assert_eq!(clash.status(), reqwest::StatusCode::CONFLICT);
+
+    assert_eq!(slot_starts(&app, day, day + Duration::days(1)).await, vec![at(10, 0), at(10, 30)]);
+    let taken = request(&app, "did:hedera:testnet:0.0.8103", at(9, 30), 30).await;
+    assert_eq!(taken.status(), reqwest::StatusCode::CONFLICT);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,147 @@
+use bson::doc;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::fixtures;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.1";
+const PRACTITIONER_DID: &str = "did:hedera:testnet:0.0.2";
+const PDF: &[u8] = b"%PDF-1.7\n1 0 obj << /Type /Catalog >> endobj\n%%EOF\n";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0";
+
+async fn in_progress_encounter(app: &TestApp) -> String {
+    let encounter = fixtures::encounter(PATIENT_DID, PRACTITIONER_DID, EncounterStatus::InProgress);
+    app.database.create_encounter(&encounter).await.unwrap().to_hex()
+}
+
+/// Upload `content` as the encounter's practitioner, declaring it a PNG called `scan.png`
+async fn upload(app: &TestApp, encounter_id: &str, content: &[u8]) -> reqwest::Response {
+    app.client
+        .post(app.url(&format!("/api/encounters/{}/attachments?filename=scan.png", encounter_id)))
+        .bearer_auth(app.mint_jwt(PRACTITIONER_DID, Role::Practitioner))
+        .header(CONTENT_TYPE, "image/png")
+        .body(content.to_vec())
+        .send()
+        .await
+        .unwrap()
+}
+
+/// A clamd that reports every stream it is sent as infected
+async fn infected_clamd() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut command = [0u8; 10];
+            socket.read_exact(&mut command).await.unwrap();
+            loop {
+                let size = socket.read_u32().await.unwrap();
+                if size == 0 {
+                    break;
+                }
+                socket.read_exact(&mut vec![0u8; size as usize]).await.unwrap();
+            }
+            socket.write_all(b"stream: Win.Test.EICAR_HDB-1 FOUND\0").await.unwrap();
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn uploads_are_typed_by_their_content_not_their_name() {
+    let app = spawn_test_app().await;
+    let encounter_id = in_progress_encounter(&app).await;
+
+    // A PDF sent as scan.png is stored, and later served, as the PDF it is
+    let uploaded: Value = upload(&app, &encounter_id, PDF).await.json().await.unwrap();
+    assert_eq!(uploaded["success"], true, "{}", uploaded);
+    assert_eq!((uploaded["data"]["content_type"].as_str(), uploaded["data"]["filename"].as_str()), (Some("application/pdf"), Some("scan.png")));
+    let cid = uploaded["data"]["ipfs_hash"].as_str().unwrap();
+    let file = app
+        .client
+        .get(app.url(&format!("/api/files/{}", cid)))
+        .bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(file.headers()[CONTENT_TYPE], "application/pdf");
+    assert_eq!(file.bytes().await.unwrap(), PDF);
+
+    // An executable or a web page is refused whatever it is called
+    for spoofed in [&b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff\0\0"[..], b"<!DOCTYPE html><html><script>alert(1)</script>"] {
+        assert_eq!(upload(&app, &encounter_id, spoofed).await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let patient_upload = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/attachments", encounter_id)))
+        .bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient))
+        .body(PNG.to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(patient_upload.status(), StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn each_type_has_its_own_size_cap() {
+    let app = spawn_test_app_with(|config| config.uploads.max_image_bytes = PNG.len() - 1).await;
+    let encounter_id = in_progress_encounter(&app).await;
+
+    assert_eq!(upload(&app, &encounter_id, PNG).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let pdf: Value = upload(&app, &encounter_id, PDF).await.json().await.unwrap();
+    assert_eq!(pdf["success"], true, "{}", pdf);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn infected_uploads_are_rejected_and_audited() {
+    let clamd = infected_clamd().await;
+    let app = spawn_test_app_with(|config| config.uploads.clamd_address = Some(clamd)).await;
+    let encounter_id = in_progress_encounter(&app).await;
+
+    let rejected = upload(&app, &encounter_id, PNG).await;
+    assert_eq!(rejected.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = rejected.json().await.unwrap();
+    assert_eq!(body["code"], "malware_detected");
+
+    let audit = app
+        .database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .find_one(doc! { "did": PATIENT_DID, "action": "attachment_rejected" }, None)
+        .await
+        .unwrap()
+        .expect("the rejection was not audit-logged");
+    let details = audit.details.unwrap();
+    assert_eq!((details["detection"].as_str(), details["actor"].as_str()), (Some("Win.Test.EICAR_HDB-1"), Some(PRACTITIONER_DID)));
+    assert_eq!(app.database.db.collection::<Attachment>("attachments").count_documents(doc! {}, None).await.unwrap(), 0);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn an_unreachable_scanner_fails_closed_unless_configured_to_fail_open() {
+    let unreachable = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+    let closed = spawn_test_app_with(|config| config.uploads.clamd_address = Some(unreachable.clone())).await;
+    let encounter_id = in_progress_encounter(&closed).await;
+    assert_eq!(upload(&closed, &encounter_id, PNG).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+    closed.cleanup().await;
+
+    let open = spawn_test_app_with(|config| {
+        config.uploads.clamd_address = Some(unreachable);
+        config.uploads.scan_fail_open = true;
+    })
+    .await;
+    let encounter_id = in_progress_encounter(&open).await;
+    let accepted: Value = upload(&open, &encounter_id, PNG).await.json().await.unwrap();
+    assert_eq!(accepted["success"], true, "{}", accepted);
+    open.cleanup().await;
+}
@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::auditing::{AnchoringMonitor, AuditingService};
+use crate::models::{AnchorBatch, AuditLog, Role};
+use crate::services::email::EmailService;
+use crate::services::login_anomaly::SystemClock;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const ADMIN: &str = "did:hedera:testnet:0.0.8501";
+const ALICE: &str = "did:hedera:testnet:0.0.8502";
+const BOB: &str = "did:hedera:testnet:0.0.8503";
+
+fn entry(did: &str, action: &str, timestamp: &str, is_anchored: bool) -> AuditLog {
+    AuditLog {
+        id: None,
+        did: did.to_string(),
+        action: action.to_string(),
+        timestamp: timestamp.parse::<DateTime<Utc>>().unwrap(),
+        details: None,
+        is_anchored,
+        anchor_batch_id: None,
+        tenant_id: None,
+    }
+}
+
+async fn audit_stats(app: &TestApp, query: &str) -> Value {
+    let body: Value = app
+        .client
+        .get(app.url(&format!("/api/admin/audit/stats?from=2026-03-01T00:00:00Z&to=2026-03-03T00:00:00Z&{}", query)))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::Admin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["success"], true, "{}", body);
+    body["data"].clone()
+}
+
+#[tokio::test]
+async fn audit_stats_group_seeded_logs_within_the_range() {
+    let app = spawn_test_app().await;
+    app.database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .insert_many(
+            [
+                entry(ALICE, "get_patient", "2026-03-01T08:00:00Z", true),
+                entry(ALICE, "get_patient", "2026-03-01T09:30:00.250Z", true),
+                entry(ALICE, "export_everything", "2026-03-01T10:00:00Z", false),
+                entry(BOB, "phone_auth_failed", "2026-03-02T23:59:59Z", false),
+                entry(BOB, "phone_auth_failed", "2026-03-02T07:00:00Z", false),
+                entry(BOB, "break_glass", "2026-03-02T12:00:00Z", false),
+                // Outside the range
+                entry(BOB, "break_glass", "2026-02-28T23:59:59Z", false),
+                entry(ALICE, "get_patient", "2026-03-03T00:00:00Z", false),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let by_day = audit_stats(&app, "group_by=day").await;
+    assert_eq!(by_day["groups"], json!([{ "key": "2026-03-01", "count": 3 }, { "key": "2026-03-02", "count": 3 }]));
+    assert_eq!((by_day["anchored"].as_u64(), by_day["unanchored"].as_u64()), (Some(2), Some(4)));
+    assert_eq!(
+        by_day["security"],
+        json!([
+            { "day": "2026-03-01", "action": "export_everything", "count": 1 },
+            { "day": "2026-03-02", "action": "break_glass", "count": 1 },
+            { "day": "2026-03-02", "action": "phone_auth_failed", "count": 2 },
+        ])
+    );
+
+    let by_action = audit_stats(&app, "group_by=action").await;
+    assert_eq!(by_action["groups"][0], json!({ "key": "get_patient", "count": 2 }));
+    assert_eq!(by_action["groups"].as_array().unwrap().len(), 4);
+
+    let by_did = audit_stats(&app, "group_by=did&top=1").await;
+    assert_eq!(by_did["groups"], json!([{ "key": ALICE, "count": 3 }, { "key": BOB, "count": 3 }]));
+    assert_eq!(by_did["top_actors"], json!([{ "key": ALICE, "count": 3 }]));
+
+    let forbidden = app
+        .client
+        .get(app.url("/api/admin/audit/stats"))
+        .bearer_auth(app.mint_jwt(ALICE, Role::Patient))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forbidden.status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn an_interrupted_anchor_run_is_completed_once_and_its_root_cannot_be_reused() {
+    let app = spawn_test_app().await;
+    for action in ["get_patient", "create_encounter"] {
+        app.database.create_audit_log(&entry(ALICE, action, "2026-03-01T10:00:00Z", false)).await.unwrap();
+    }
+    let auditing = AuditingService::new(app.database.clone(), app.ledger.clone()).with_anchor_lookup(app.ledger.clone());
+
+    app.ledger.lose_next_anchor_response();
+    assert!(auditing.anchor_audit_logs().await.is_err());
+    assert_eq!(app.database.unfinished_anchor_batches().await.unwrap().len(), 1);
+    auditing.anchor_audit_logs().await.unwrap();
+
+    assert_eq!(app.ledger.anchored_batches().len(), 1);
+    assert!(app.database.unfinished_anchor_batches().await.unwrap().is_empty());
+    assert!(app.database.get_unanchored_audit_logs().await.unwrap().is_empty());
+    let root = app.ledger.anchored_batches()[0].0;
+    assert!(!app.database.create_anchor_batch(&AnchorBatch::new(root, vec![], Utc::now())).await.unwrap());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn admins_see_failed_anchor_runs_in_the_audit_health() {
+    let app = spawn_test_app().await;
+    app.database.create_audit_log(&entry(ALICE, "get_patient", "2026-03-01T10:00:00Z", false)).await.unwrap();
+    let email_service = Arc::new(EmailService::new(app.config.clone(), app.database.clone()).unwrap());
+    let monitor = AnchoringMonitor::new(app.database.clone(), app.database.clone(), email_service, app.config.clone(), Arc::new(SystemClock));
+    let auditing = AuditingService::new(app.database.clone(), app.ledger.clone())
+        .with_anchor_lookup(app.ledger.clone())
+        .with_monitor(Arc::new(monitor));
+    let health = |query: &'static str| {
+        app.client.get(app.url(&format!("/api/admin/audit/health{}", query))).bearer_auth(app.mint_jwt(ADMIN, Role::Admin)).send()
+    };
+
+    for _ in 0..2 {
+        app.ledger.reject_next_anchor();
+        assert!(auditing.anchor_audit_logs().await.is_err());
+    }
+    let failing: Value = health("?runs=1").await.unwrap().json().await.unwrap();
+    let failing = &failing["data"];
+    assert_eq!((failing["status"].as_str(), failing["consecutive_failures"].as_u64()), (Some("failing"), Some(2)));
+    assert_eq!(failing["runs"].as_array().unwrap().len(), 1);
+    assert_eq!(failing["runs"][0]["error"], "INSUFFICIENT_PAYER_BALANCE");
+    assert!(failing["last_success_at"].is_null());
+    let deep: Value = app.client.get(app.url("/health/deep")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(deep["audit_anchoring"]["status"], "failing");
+    assert_eq!(deep["audit_anchoring"]["consecutive_failures"], 2);
+    // The unauthenticated check leaves out the runs and their errors
+    assert!(deep["audit_anchoring"].get("runs").is_none());
+    assert!(!deep.to_string().contains("INSUFFICIENT_PAYER_BALANCE"));
+    let metrics = app.client.get(app.url("/metrics")).send().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("audit_anchoring_consecutive_failures 2\n"), "{}", metrics);
+
+    auditing.anchor_audit_logs().await.unwrap();
+    let recovered: Value = health("").await.unwrap().json().await.unwrap();
+    let recovered = &recovered["data"];
+    assert_eq!((recovered["status"].as_str(), recovered["consecutive_failures"].as_u64()), (Some("healthy"), Some(0)));
+    assert_eq!(recovered["runs"].as_array().unwrap().len(), 3);
+    assert!(recovered["runs"][0]["error"].is_null());
+
+    let forbidden = app.client.get(app.url("/api/admin/audit/health")).bearer_auth(app.mint_jwt(ALICE, Role::Patient)).send().await.unwrap();
+    assert_eq!(forbidden.status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
@@ -1,44 +1,422 @@
-use super::*;
-use axum::{
-    body::Body,
-    http::{Request, StatusCode},
-};
-use tower::ServiceExt; // for `oneshot`
-use std::sync::Arc;
-use health_remastered::services::MockAuthService;
-use health_remastered::models::{Patient, FhirPatient};
-use health_remastered::services::RegistrationResponse;
+use bson::doc;
+use chrono::Utc;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::models::{AuditLog, Role, Session};
+use crate::services::totp::code_at;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp, TEST_PHONE_CODE};
+use crate::utils;
 
 #[tokio::test]
 async fn test_auth_google() {
-    let mut mock_auth_service = MockAuthService::new();
-
-    mock_auth_service.expect_authenticate_with_google()
-        .returning(|_| Ok(RegistrationResponse {
-            user: Patient {
-                id: None,
-                did: "did:hedera:testnet:123".to_string(),
-                fhir_patient: FhirPatient::default(),
-                created_at: chrono::Utc::now(),
-                updated_at: chrono::Utc::now(),
-            },
-            token: "dummy_token".to_string(),
-        }));
-
-    let app = create_app(Arc::new(mock_auth_service)).await;
-
-    let request = Request::builder()
-        .method("POST")
-        .uri("/api/auth/google")
-        .header("Content-Type", "application/json")
-        .body(Body::from(
-            r#"{
-                "id_token": "dummy_token"
-            }"#,
-        ))
+    let app = spawn_test_app().await;
+
+    let response = app
+        .client
+        .post(app.url("/api/auth/google"))
+        .json(&json!({ "id_token": "dummy_token" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true);
+    assert!(body["data"]["token"].as_str().is_some_and(|token| !token.is_empty()));
+    assert_eq!(body["data"]["user"]["did"], app.did_registry.created()[0].as_str());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn google_sign_in_reuses_existing_account() {
+    let app = spawn_test_app().await;
+
+    for _ in 0..2 {
+        let response = app
+            .client
+            .post(app.url("/api/auth/google"))
+            .json(&json!({ "id_token": "dummy_token" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    assert_eq!(app.did_registry.created().len(), 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn duplicate_registration_returns_conflict() {
+    let app = spawn_test_app().await;
+    let request = json!({ "name": "Jane Doe", "email": "jane@example.com", "public_key_hex": "00".repeat(32) });
+
+    let first = app.client.post(app.url("/api/auth/register")).json(&request).send().await.unwrap();
+    let second = app.client.post(app.url("/api/auth/register")).json(&request).send().await.unwrap();
+
+    assert_eq!(first.status(), reqwest::StatusCode::OK);
+    assert_eq!(second.status(), reqwest::StatusCode::CONFLICT);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn concurrent_registrations_for_one_email_create_one_patient() {
+    let app = spawn_test_app().await;
+    let request = json!({ "name": "Jane Doe", "email": "race@example.com", "public_key_hex": "00".repeat(32) });
+
+    let attempts = (0..6).map(|_| app.client.post(app.url("/api/auth/register")).json(&request).send());
+    let mut statuses: Vec<_> = futures_util::future::join_all(attempts).await.into_iter().map(|response| response.unwrap().status()).collect();
+    statuses.sort();
+
+    assert_eq!(statuses, [vec![reqwest::StatusCode::OK], vec![reqwest::StatusCode::CONFLICT; 5]].concat());
+    let patients = app.database.db.collection::<bson::Document>("patients");
+    assert_eq!(patients.count_documents(None, None).await.unwrap(), 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn phone_sign_in_creates_account_once_code_is_verified() {
+    let app = spawn_test_app().await;
+    let phone = "+15555550100";
+
+    let response = app
+        .client
+        .post(app.url("/api/auth/phone/initiate"))
+        .json(&json!({ "phone_number": phone }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(app.phone_verifier.started(), vec![phone.to_string()]);
+
+    let response = app
+        .client
+        .post(app.url("/api/auth/phone/verify"))
+        .json(&json!({ "phone_number": phone, "otp": TEST_PHONE_CODE }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true);
+    assert_eq!(body["data"]["user"]["did"], app.did_registry.created()[0].as_str());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn repeated_wrong_codes_lock_the_phone_number() {
+    let app = spawn_test_app().await;
+    let phone = "+15555550101";
+    app.client
+        .post(app.url("/api/auth/phone/initiate"))
+        .json(&json!({ "phone_number": phone }))
+        .send()
+        .await
+        .unwrap();
+
+    let mut statuses = Vec::new();
+    for _ in 0..6 {
+        let response = app
+            .client
+            .post(app.url("/api/auth/phone/verify"))
+            .json(&json!({ "phone_number": phone, "otp": "000000" }))
+            .send()
+            .await
+            .unwrap();
+        statuses.push(response.status());
+    }
+
+    assert!(statuses[..5].iter().all(|status| *status == reqwest::StatusCode::OK));
+    assert_eq!(statuses[5], reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert!(app.did_registry.created().is_empty());
+
+    app.cleanup().await;
+}
+
+/// Sign in by phone, returning the new account's DID and token
+async fn phone_account(app: &TestApp, phone: &str) -> (String, String) {
+    app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": phone })).send().await.unwrap();
+    let body: Value = app
+        .client
+        .post(app.url("/api/auth/phone/verify"))
+        .json(&json!({ "phone_number": phone, "otp": TEST_PHONE_CODE }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
         .unwrap();
+    (body["data"]["user"]["did"].as_str().unwrap().to_string(), body["data"]["token"].as_str().unwrap().to_string())
+}
+
+async fn post(app: &TestApp, path: &str, token: &str, body: Value) -> Value {
+    app.client.post(app.url(path)).bearer_auth(token).json(&body).send().await.unwrap().json().await.unwrap()
+}
+
+#[tokio::test]
+async fn high_assurance_routes_need_a_token_stepped_up_by_sms_or_authenticator() {
+    let app = spawn_test_app().await;
+    let (did, token) = phone_account(&app, "+15555550102").await;
+    let issue = |token: String| {
+        app.client
+            .post(app.url("/api/credentials/issue"))
+            .bearer_auth(token)
+            .json(&json!({ "subject_did": did, "credential_type": "LabResultCredential", "metadata": "{}" }))
+            .send()
+    };
+    assert_eq!(issue(token.clone()).await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    app.client.post(app.url("/api/auth/step-up/sms")).bearer_auth(&token).send().await.unwrap();
+    let by_sms = post(&app, "/api/auth/step-up", &token, json!({ "method": "sms", "code": TEST_PHONE_CODE })).await;
+    assert_eq!(by_sms["data"]["second_factor"], "sms", "step-up failed: {}", by_sms);
+    // Past the high-assurance check; refused only for not being a registered issuer
+    let stepped_up = by_sms["data"]["token"].as_str().unwrap().to_string();
+    assert_eq!(issue(stepped_up).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+
+    let enrolled = post(&app, "/api/auth/totp/enroll", &token, json!({})).await;
+    assert!(enrolled["data"]["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/WeCare:"));
+    let stored = app.database.get_totp(&did).await.unwrap().unwrap();
+    let secret = utils::decrypt(&stored.encrypted_secret, &app.config.ipfs_encryption_key).unwrap();
+    let step = Utc::now().timestamp() / 30;
+    assert_eq!(post(&app, "/api/auth/totp/confirm", &token, json!({ "code": code_at(&secret, step) })).await["success"], true);
+
+    let by_app = post(&app, "/api/auth/step-up", &token, json!({ "method": "totp", "code": code_at(&secret, step + 1) })).await;
+    assert_eq!(by_app["data"]["second_factor"], "totp", "step-up failed: {}", by_app);
+    let replayed = post(&app, "/api/auth/step-up", &token, json!({ "method": "totp", "code": code_at(&secret, step + 1) })).await;
+    assert_eq!(replayed["success"], false);
+
+    // Disabling takes a valid code, or an admin
+    let disabled = post(&app, "/api/auth/totp/disable", &token, json!({ "code": code_at(&secret, step) })).await;
+    assert_eq!(disabled["success"], false);
+    let admin = app.mint_jwt("did:hedera:testnet:0.0.100", Role::PlatformAdmin);
+    let removed = app.client.delete(app.url(&format!("/api/admin/patients/{}/totp", did))).bearer_auth(admin).send().await.unwrap();
+    assert_eq!(removed.status(), reqwest::StatusCode::OK);
+    assert!(app.database.get_totp(&did).await.unwrap().is_none());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn backup_codes_from_enrollment_step_up_once_each_and_regenerating_needs_high_assurance() {
+    let app = spawn_test_app().await;
+    let (did, token) = phone_account(&app, "+15555550103").await;
+    post(&app, "/api/auth/totp/enroll", &token, json!({})).await;
+    let stored = app.database.get_totp(&did).await.unwrap().unwrap();
+    let secret = utils::decrypt(&stored.encrypted_secret, &app.config.ipfs_encryption_key).unwrap();
+    let confirmed = post(&app, "/api/auth/totp/confirm", &token, json!({ "code": code_at(&secret, Utc::now().timestamp() / 30) })).await;
+    let codes: Vec<String> = serde_json::from_value(confirmed["data"]["codes"].clone()).unwrap();
+    assert_eq!(codes.len(), 10);
+
+    let by_code = post(&app, "/api/auth/step-up", &token, json!({ "method": "backup_code", "code": codes[0] })).await;
+    assert_eq!(by_code["data"]["second_factor"], "backup_code", "step-up failed: {}", by_code);
+    assert_eq!(by_code["data"]["backup_codes_remaining"], 9);
+    assert_eq!(post(&app, "/api/auth/step-up", &token, json!({ "method": "backup_code", "code": codes[0] })).await["success"], false);
+
+    let step_up = json!({ "method": "backup_code", "code": codes[1] });
+    let (first, second) = tokio::join!(post(&app, "/api/auth/step-up", &token, step_up.clone()), post(&app, "/api/auth/step-up", &token, step_up.clone()));
+    assert_eq!([&first, &second].iter().filter(|response| response["success"] == true).count(), 1);
+    let status: Value = app.client.get(app.url("/api/auth/backup-codes")).bearer_auth(&token).send().await.unwrap().json().await.unwrap();
+    assert_eq!(status["data"]["remaining"], 8);
+
+    let regenerate = |token: &str| app.client.post(app.url("/api/auth/backup-codes/regenerate")).bearer_auth(token).send();
+    assert_eq!(regenerate(&token).await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+    let stepped_up = by_code["data"]["token"].as_str().unwrap().to_string();
+    let fresh: Value = regenerate(&stepped_up).await.unwrap().json().await.unwrap();
+    assert_eq!(fresh["data"]["codes"].as_array().unwrap().len(), 10);
+    assert_eq!(post(&app, "/api/auth/step-up", &token, json!({ "method": "backup_code", "code": codes[2] })).await["success"], false);
+
+    app.cleanup().await;
+}
+
+async fn phone_sign_in(app: &TestApp, phone: &str, user_agent: &str, installation_id: Option<&str>) -> (String, String) {
+    app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": phone })).send().await.unwrap();
+    let mut request = app
+        .client
+        .post(app.url("/api/auth/phone/verify"))
+        .header(reqwest::header::USER_AGENT, user_agent)
+        .json(&json!({ "phone_number": phone, "otp": TEST_PHONE_CODE }));
+    if let Some(installation_id) = installation_id {
+        request = request.header("X-Installation-Id", installation_id);
+    }
+    let body: Value = request.send().await.unwrap().json().await.unwrap();
+    (body["data"]["user"]["did"].as_str().unwrap().to_string(), body["data"]["token"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn sign_ins_from_new_devices_are_logged_and_can_be_revoked_from_the_alert() {
+    const APP: &str = "Dart/3.4 (dart:io)";
+    const BROWSER: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0";
+    let app = spawn_test_app().await;
+    let new_devices = |did: String| {
+        let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+        async move { audit_logs.count_documents(doc! { "did": did, "action": "new_device_sign_in" }, None).await.unwrap() }
+    };
+
+    let (did, app_token) = phone_sign_in(&app, "+15555550104", APP, Some("install-1")).await;
+    phone_sign_in(&app, "+15555550104", APP, Some("install-1")).await;
+    assert_eq!(new_devices(did.clone()).await, 1, "only the first sign-in was from a new device");
+
+    let (_, browser_token) = phone_sign_in(&app, "+15555550104", BROWSER, None).await;
+    assert_eq!(new_devices(did.clone()).await, 2);
+    let sessions = app.database.known_sessions(&did, 10).await.unwrap();
+    assert_eq!(sessions.len(), 3);
+    assert_eq!(sessions[0].user_agent_family, "Firefox on Windows");
+    assert_eq!(sessions[0].subnet.as_deref(), Some("127.0.0.0/24"));
+
+    // The alert's link carries a token only its hash is stored for; give the session a known one
+    let token_hash = format!("{:x}", Sha256::digest(b"0badc0de"));
+    app.database
+        .db
+        .collection::<Session>("sessions")
+        .update_one(doc! { "_id": sessions[0].id }, doc! { "$set": { "revoke_token_hash": token_hash } }, None)
+        .await
+        .unwrap();
+    let backup_codes = |token: &String| app.client.get(app.url("/api/auth/backup-codes")).bearer_auth(token).send();
+
+    // A mail scanner or link preview fetching the link only gets the confirm page
+    let page = app.client.get(app.url("/api/auth/sessions/revoke?token=0badc0de")).send().await.unwrap().text().await.unwrap();
+    assert!(page.contains(r#"name="token" value="0badc0de""#), "{}", page);
+    assert_eq!(backup_codes(&browser_token).await.unwrap().status(), reqwest::StatusCode::OK);
+
+    let revoke = || async { app.client.post(app.url("/api/auth/sessions/revoke")).form(&[("token", "0badc0de")]).send().await.unwrap().json::<Value>().await.unwrap() };
+    assert_eq!(revoke().await["success"], true);
+    assert_eq!(revoke().await["success"], false, "a link works once");
+
+    assert_eq!(backup_codes(&browser_token).await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(backup_codes(&app_token).await.unwrap().status(), reqwest::StatusCode::OK);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn an_address_guessing_codes_for_many_numbers_is_blocked_until_an_admin_lifts_it() {
+    let app = spawn_test_app_with(|config| config.login_protection.max_accounts = 3).await;
+    let initiate = || app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": "+15555550120" })).send();
+
+    for phone in ["+15555550110", "+15555550111", "+15555550112"] {
+        app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": phone })).send().await.unwrap();
+        let body: Value = app
+            .client
+            .post(app.url("/api/auth/phone/verify"))
+            .json(&json!({ "phone_number": phone, "otp": "000000" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["success"], false);
+    }
+    assert_eq!(initiate().await.unwrap().status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let health = app.client.get(app.url("/health")).send().await.unwrap();
+    assert_eq!(health.status(), reqwest::StatusCode::OK, "only sign-in is blocked");
+
+    let admin = app.mint_jwt("did:hedera:testnet:0.0.100", Role::PlatformAdmin);
+    let blocks: Value = app.client.get(app.url("/api/admin/security/blocks")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(blocks["data"][0]["ip"], "127.0.0.1");
+    assert_eq!(blocks["data"][0]["accounts"], 3);
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    assert_eq!(audit_logs.count_documents(doc! { "did": "ip:127.0.0.1", "action": "ip_blocked" }, None).await.unwrap(), 1);
+
+    let unblocked = app.client.delete(app.url("/api/admin/security/blocks/127.0.0.1")).bearer_auth(&admin).send().await.unwrap();
+    assert_eq!(unblocked.status(), reqwest::StatusCode::OK);
+    assert_eq!(initiate().await.unwrap().status(), reqwest::StatusCode::OK);
+    assert_eq!(audit_logs.count_documents(doc! { "did": "ip:127.0.0.1", "action": "ip_unblocked" }, None).await.unwrap(), 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn suspended_patients_are_signed_out_and_refused_until_re_enabled() {
+    const ADMIN: &str = "did:hedera:testnet:0.0.100";
+    let app = spawn_test_app().await;
+    let (did, token) = phone_sign_in(&app, "+15555550130", "Dart/3.4 (dart:io)", Some("install-1")).await;
+    let admin = app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin);
+    let get = |path: &str, token: &str| app.client.get(app.url(path)).bearer_auth(token).send();
+    let post = |path: String| app.client.post(app.url(&path)).bearer_auth(&admin).send();
+
+    let without_step_up = get("/api/admin/stats", &app.mint_jwt(ADMIN, Role::PlatformAdmin)).await.unwrap();
+    assert_eq!(without_step_up.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let stats: Value = get("/api/admin/stats", &admin).await.unwrap().json().await.unwrap();
+    assert_eq!(stats["data"]["patients"], 1);
+    let patients: Value = get("/api/admin/patients?page_size=10", &admin).await.unwrap().json().await.unwrap();
+    assert_eq!((patients["data"]["total"].as_u64(), patients["data"]["items"][0]["did"].as_str()), (Some(1), Some(did.as_str())));
+
+    assert_eq!(post(format!("/api/admin/patients/{}/disable", did)).await.unwrap().json::<Value>().await.unwrap()["success"], true);
+    assert_eq!(get("/api/auth/backup-codes", &token).await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+    app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": "+15555550130" })).send().await.unwrap();
+    let refused = app
+        .client
+        .post(app.url("/api/auth/phone/verify"))
+        .json(&json!({ "phone_number": "+15555550130", "otp": TEST_PHONE_CODE }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(refused.status(), reqwest::StatusCode::FORBIDDEN);
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    let disabled = audit_logs.find_one(doc! { "did": &did, "action": "disable_patient" }, None).await.unwrap().unwrap();
+    assert_eq!(disabled.details.unwrap()["actor"], ADMIN);
+
+    assert_eq!(post(format!("/api/admin/patients/{}/enable", did)).await.unwrap().json::<Value>().await.unwrap()["success"], true);
+    phone_sign_in(&app, "+15555550130", "Dart/3.4 (dart:io)", Some("install-1")).await;
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_number_is_sent_three_codes_per_quarter_hour() {
+    let app = spawn_test_app().await;
+    let phone = "+15555550140";
+    let initiate = || app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": phone })).send();
+
+    for _ in 0..3 {
+        assert_eq!(initiate().await.unwrap().status(), reqwest::StatusCode::OK);
+    }
+    let refused = initiate().await.unwrap();
+    assert_eq!(refused.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: i64 = refused.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=15 * 60).contains(&retry_after), "{}", retry_after);
+    let body: Value = refused.json().await.unwrap();
+    assert_eq!(body["code"], "sms_limited");
+    assert_eq!(app.phone_verifier.started().len(), 3);
+    // Another number is unaffected
+    let other = app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": "+15555550141" })).send().await.unwrap();
+    assert_eq!(other.status(), reqwest::StatusCode::OK);
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    assert_eq!(audit_logs.count_documents(doc! { "action": "phone_auth_throttled" }, None).await.unwrap(), 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn phone_sign_in_stops_for_the_day_once_the_sms_budget_is_used_up() {
+    let app = spawn_test_app_with(|config| {
+        config.sms_throttle.daily_budget = Some(2);
+        config.sms_throttle.usd_per_message = Some(0.25);
+    })
+    .await;
+    let initiate = |phone: &'static str| app.client.post(app.url("/api/auth/phone/initiate")).json(&json!({ "phone_number": phone })).send();
+
+    assert_eq!(initiate("+15555550150").await.unwrap().status(), reqwest::StatusCode::OK);
+    assert_eq!(initiate("+15555550151").await.unwrap().status(), reqwest::StatusCode::OK);
+    let refused = initiate("+15555550152").await.unwrap();
+    assert_eq!(refused.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(refused.json::<Value>().await.unwrap()["code"], "sms_budget_exhausted");
+    assert_eq!(app.phone_verifier.started().len(), 2);
 
-    let response = app.oneshot(request).await.unwrap();
+    let admin = app.mint_high_assurance_jwt("did:hedera:testnet:0.0.100", Role::PlatformAdmin);
+    let stats: Value = app.client.get(app.url("/api/admin/stats")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    let today = &stats["data"]["sms"]["days"][0];
+    assert_eq!(today["day"], Utc::now().date_naive().to_string());
+    assert_eq!((today["sent"].as_u64(), today["estimated_usd"].as_f64()), (Some(2), Some(0.5)));
+    assert_eq!(stats["data"]["sms"]["daily_budget"], 2);
 
-    assert_eq!(response.status(), StatusCode::OK);
+    app.cleanup().await;
 }
@@ -0,0 +1,133 @@
+use bson::oid::ObjectId;
+use bson::{doc, Document};
+use std::path::PathBuf;
+
+use crate::backup::ContentMode;
+use crate::cli::{self, EXIT_CHECK_FAILED};
+use crate::database::Database;
+use crate::services::ipfs::{IpfsClient, ObjectStorage};
+use crate::tests::helpers::{spawn_test_app, TestApp};
+use crate::tests::ipfs_stub;
+
+const LOST_ATTACHMENT: &str = "stub-lost";
+
+/// An encounter and a credential whose content is on the app's IPFS node, and an attachment
+/// whose content is not; returns the encounter and the two CIDs
+async fn seed_records(app: &TestApp) -> (Document, String, String) {
+    let ipfs = IpfsClient::new(&app.ipfs.uri());
+    let bundle = ipfs.add_file(b"encrypted bundle", None).await.unwrap();
+    let credential = ipfs.add_file(b"encrypted credential", None).await.unwrap();
+    let encounter = doc! {
+        "_id": ObjectId::new(),
+        "patient_did": "did:hedera:testnet:0.0.9901",
+        "status": "finalized",
+        "final_bundle_ipfs_hash": &bundle,
+        "bundle_key_version": 1,
+    };
+    app.database.db.collection::<Document>("encounters").insert_one(&encounter, None).await.unwrap();
+    app.database
+        .db
+        .collection::<Document>("verifiable_credentials")
+        .insert_one(doc! { "_id": ObjectId::new(), "ipfs_hash": &credential }, None)
+        .await
+        .unwrap();
+    app.database
+        .db
+        .collection::<Document>("attachments")
+        .insert_one(doc! { "_id": ObjectId::new(), "ipfs_hash": LOST_ATTACHMENT, "key_version": 1 }, None)
+        .await
+        .unwrap();
+    (encounter, bundle, credential)
+}
+
+async fn empty_database(app: &TestApp) -> Database {
+    Database::new_with_name(app.config.database_url.expose_secret(), &format!("healthcare_test_{}", ObjectId::new())).await.unwrap()
+}
+
+fn backup_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("backup-{}", ObjectId::new()))
+}
+
+#[tokio::test]
+async fn a_backup_restores_the_collections_and_content_into_an_empty_deployment() {
+    let app = spawn_test_app().await;
+    let (encounter, bundle, credential) = seed_records(&app).await;
+    let dir = backup_dir();
+
+    let backed_up = cli::backup(&app.database, &IpfsClient::new(&app.ipfs.uri()), &app.config, &dir, ContentMode::Include).await.unwrap();
+    assert_eq!(backed_up.code, EXIT_CHECK_FAILED, "{}", backed_up.result);
+    assert_eq!(backed_up.result["content_referenced"], 3);
+    assert_eq!(backed_up.result["content_included"], 2);
+    assert_eq!(backed_up.result["missing"][0]["cid"], LOST_ATTACHMENT);
+    assert_eq!(backed_up.result["missing"][0]["references"][0]["collection"], "attachments");
+    // Nothing on disk is in the clear
+    let dump = std::fs::read(dir.join("collections/encounters.bson.enc")).unwrap();
+    assert!(!dump.windows(bundle.len()).any(|window| window == bundle.as_bytes()));
+    let rerun = cli::backup(&app.database, &IpfsClient::new(&app.ipfs.uri()), &app.config, &dir, ContentMode::Include).await;
+    assert!(rerun.unwrap_err().to_string().contains("finished backup"));
+
+    let target = empty_database(&app).await;
+    let node = ipfs_stub::start().await;
+    let storage = IpfsClient::new(&node.uri());
+    let restored = cli::restore(&target, &storage, &app.config, &dir, false).await.unwrap();
+    assert_eq!(restored.code, EXIT_CHECK_FAILED, "{}", restored.result);
+    assert_eq!(restored.result["content_restored"], 2);
+    assert_eq!(restored.result["missing"][0]["cid"], LOST_ATTACHMENT);
+
+    let copy = target.db.collection::<Document>("encounters").find_one(doc! { "_id": encounter.get_object_id("_id").unwrap() }, None).await.unwrap();
+    assert_eq!(copy, Some(encounter));
+    for collection in ["verifiable_credentials", "attachments"] {
+        assert_eq!(target.db.collection::<Document>(collection).count_documents(None, None).await.unwrap(), 1);
+    }
+    assert_eq!(storage.get_file(&bundle).await.unwrap(), b"encrypted bundle");
+    assert_eq!(storage.get_file(&credential).await.unwrap(), b"encrypted credential");
+    assert!(storage.is_pinned(&bundle).await.unwrap());
+
+    // Running it again picks up the restore's progress and only retries what is missing
+    let resumed = cli::restore(&target, &storage, &app.config, &dir, false).await.unwrap();
+    assert_eq!(resumed.result["content_restored"], 2);
+    assert_eq!(resumed.result["missing"].as_array().unwrap().len(), 1);
+    assert_eq!(target.db.collection::<Document>("encounters").count_documents(None, None).await.unwrap(), 1);
+
+    // A fresh restore will not write over documents unless told to
+    std::fs::remove_file(dir.join("restore-progress.json")).unwrap();
+    let refused = cli::restore(&target, &storage, &app.config, &dir, false).await;
+    assert!(refused.unwrap_err().to_string().contains("--overwrite"));
+    cli::restore(&target, &storage, &app.config, &dir, true).await.unwrap();
+    assert_eq!(target.db.collection::<Document>("encounters").count_documents(None, None).await.unwrap(), 1);
+
+    target.db.drop(None).await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_pins_only_backup_checks_the_node_still_holds_the_content() {
+    let app = spawn_test_app().await;
+    let (_, bundle, _) = seed_records(&app).await;
+    let dir = backup_dir();
+    let storage = IpfsClient::new(&app.ipfs.uri());
+
+    let backed_up = cli::backup(&app.database, &storage, &app.config, &dir, ContentMode::VerifyPins).await.unwrap();
+    assert_eq!(backed_up.result["content_pinned"], 2, "{}", backed_up.result);
+    assert_eq!(backed_up.result["content_included"], 0);
+    assert_eq!(backed_up.result["missing"][0]["cid"], LOST_ATTACHMENT);
+    assert!(!dir.join("content").join(format!("{}.enc", bundle)).exists());
+
+    // The database is lost but the IPFS node is not
+    let target = empty_database(&app).await;
+    let restored = cli::restore(&target, &storage, &app.config, &dir, false).await.unwrap();
+    assert_eq!(restored.result["content_already_pinned"], 2, "{}", restored.result);
+    assert_eq!(restored.result["missing"].as_array().unwrap().len(), 1);
+
+    // Onto a node that never had the content there is nothing to restore it from
+    let elsewhere = empty_database(&app).await;
+    let node = ipfs_stub::start().await;
+    let stranded = cli::restore(&elsewhere, &IpfsClient::new(&node.uri()), &app.config, &dir, false).await.unwrap();
+    assert_eq!(stranded.result["missing"].as_array().unwrap().len(), 3);
+
+    target.db.drop(None).await.unwrap();
+    elsewhere.db.drop(None).await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    app.cleanup().await;
+}
@@ -0,0 +1,64 @@
+use serde_json::{json, Value};
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8301";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.8302";
+const ADMIN: &str = "did:hedera:testnet:0.0.8303";
+
+async fn break_glass(app: &TestApp, did: &str, role: Role) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/access/break-glass"))
+        .bearer_auth(app.mint_high_assurance_jwt(did, role))
+        .json(&json!({ "patient_did": PATIENT, "justification": "Unconscious on arrival in the ED" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn review_queue(app: &TestApp) -> Value {
+    let body: Value = app
+        .client
+        .get(app.url("/api/admin/break-glass?reviewed=false"))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::PlatformAdmin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["data"].clone()
+}
+
+#[tokio::test]
+async fn break_glass_opens_the_record_until_reviewed_by_an_admin() {
+    let app = spawn_test_app().await;
+    let patient_path = format!("/api/patients/{}", PATIENT);
+    let read = |app: &TestApp| app.client.get(app.url(&patient_path)).bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner)).send();
+
+    assert_eq!(read(&app).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(break_glass(&app, PATIENT, Role::Patient).await.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let body: Value = break_glass(&app, PRACTITIONER, Role::Practitioner).await.json().await.unwrap();
+    assert_eq!(body["data"]["practitioner_did"], PRACTITIONER);
+    assert_eq!(read(&app).await.unwrap().status(), reqwest::StatusCode::OK);
+
+    let queue = review_queue(&app).await;
+    assert_eq!(queue.as_array().unwrap().len(), 1);
+    let event_id = queue[0]["_id"]["$oid"].as_str().unwrap().to_string();
+    let review = |notes: &'static str| {
+        app.client
+            .post(app.url(&format!("/api/admin/break-glass/{}/review", event_id)))
+            .bearer_auth(app.mint_jwt(ADMIN, Role::PlatformAdmin))
+            .json(&json!({ "notes": notes }))
+            .send()
+    };
+
+    let reviewed: Value = review("Confirmed with the ED").await.unwrap().json().await.unwrap();
+    assert_eq!(reviewed["data"]["reviewed_by"], ADMIN);
+    assert_eq!(review("again").await.unwrap().status(), reqwest::StatusCode::CONFLICT);
+    assert!(review_queue(&app).await.as_array().unwrap().is_empty());
+
+    app.cleanup().await;
+}
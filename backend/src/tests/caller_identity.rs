@@ -0,0 +1,146 @@
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9401";
+const OTHER_PATIENT: &str = "did:hedera:testnet:0.0.9402";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9403";
+const OTHER_PRACTITIONER: &str = "did:hedera:testnet:0.0.9404";
+
+fn encounter(patient_did: &str, practitioner_did: &str) -> Value {
+    json!({
+        "patient_did": patient_did,
+        "practitioner_did": practitioner_did,
+        "class": { "system": null, "code": "AMB", "display": null },
+        "reason_code": [],
+        "period": { "start": null, "end": null }
+    })
+}
+
+async fn post(app: &TestApp, path: &str, token: &str, body: Value) -> reqwest::Response {
+    app.client.post(app.url(path)).bearer_auth(token).json(&body).send().await.unwrap()
+}
+
+/// Who was refused what, as the audit log recorded it
+async fn denials(app: &TestApp, action: &str) -> Vec<(String, Value)> {
+    let logs = app.database.get_unanchored_audit_logs().await.unwrap();
+    logs.into_iter().filter(|log| log.action == action).map(|log| (log.did, log.details.unwrap_or_default()["actor"].clone())).collect()
+}
+
+#[tokio::test]
+async fn encounters_are_only_started_by_their_own_practitioner() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+
+    let response = post(&app, "/api/encounters", &practitioner, encounter(PATIENT, OTHER_PRACTITIONER)).await;
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    // A patient cannot start one either, even naming themselves
+    let response = post(&app, "/api/encounters", &app.mint_jwt(PATIENT, Role::Patient), encounter(PATIENT, OTHER_PRACTITIONER)).await;
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    assert_eq!(denials(&app, "create_encounter_denied").await, vec![(PATIENT.to_string(), json!(PRACTITIONER)), (PATIENT.to_string(), json!(PATIENT))]);
+    let created: Value = post(&app, "/api/encounters", &practitioner, encounter(PATIENT, PRACTITIONER)).await.json().await.unwrap();
+    assert_eq!(created["data"]["practitioner_did"], PRACTITIONER);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn encounters_for_unregistered_or_unverified_parties_are_refused_with_422() {
+    let app = spawn_test_app_with(|config| config.dynamic.update(|dynamic| dynamic.require_verified_practitioners = true)).await;
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+    let refused = |response: Value| (response["code"].clone(), response["success"].clone());
+
+    let unknown_patient = post(&app, "/api/encounters", &practitioner, encounter(PATIENT, PRACTITIONER)).await;
+    assert_eq!(unknown_patient.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(refused(unknown_patient.json().await.unwrap()), (json!("unknown_patient"), json!(false)));
+    app.create_patient(PATIENT).await;
+    let unknown_practitioner: Value = post(&app, "/api/encounters", &practitioner, encounter(PATIENT, PRACTITIONER)).await.json().await.unwrap();
+    assert_eq!(unknown_practitioner["code"], "unknown_practitioner");
+    // Registration leaves the license unverified
+    app.register_practitioner(PRACTITIONER, None).await;
+    let unverified: Value = post(&app, "/api/encounters", &practitioner, encounter(PATIENT, PRACTITIONER)).await.json().await.unwrap();
+    assert_eq!(unverified["code"], "unverified_practitioner");
+    assert!(unverified["error"].as_str().unwrap().contains(PRACTITIONER));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn only_the_encounters_practitioner_finalizes_it() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let created: Value = post(&app, "/api/encounters", &app.mint_jwt(PRACTITIONER, Role::Practitioner), encounter(PATIENT, PRACTITIONER)).await.json().await.unwrap();
+    let encounter_id = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    let finalize_path = format!("/api/encounters/{}/finalize", encounter_id);
+
+    // Even the encounter's own practitioner needs a step-up token
+    let response = post(&app, &finalize_path, &app.mint_jwt(PRACTITIONER, Role::Practitioner), json!({})).await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    for token in [app.mint_high_assurance_jwt(OTHER_PRACTITIONER, Role::Practitioner), app.mint_high_assurance_jwt(PATIENT, Role::Patient)] {
+        let response = post(&app, &finalize_path, &token, json!({})).await;
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+
+    let stored = app.database.get_encounter(bson::oid::ObjectId::parse_str(&encounter_id).unwrap()).await.unwrap().unwrap();
+    assert_eq!((stored.status, stored.final_bundle_ipfs_hash), (EncounterStatus::InProgress, None));
+    assert_eq!(denials(&app, "finalize_encounter_denied").await, vec![(PATIENT.to_string(), json!(OTHER_PRACTITIONER)), (PATIENT.to_string(), json!(PATIENT))]);
+    let logs = app.database.get_unanchored_audit_logs().await.unwrap();
+    assert!(logs.iter().filter(|log| log.action == "finalize_encounter_denied").all(|log| log.details.as_ref().unwrap()["suspicious"] == true));
+    // Nor can finishing the encounter through its status skip the step-up
+    let finished = post(&app, &format!("/api/encounters/{}/status", encounter_id), &app.mint_jwt(PRACTITIONER, Role::Practitioner), json!({ "status": "finished" })).await;
+    assert_eq!(finished.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn prescriptions_are_written_as_the_caller_for_patients_who_granted_them() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let granted = post(
+        &app,
+        "/api/access/grants",
+        &app.mint_jwt(PATIENT, Role::Patient),
+        json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["Prescribe"], "expires_at": null }),
+    )
+    .await;
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+    let prescribe = |patient_did: &str, requester: &str| {
+        json!({
+            "patient_did": patient_did,
+            "medication_request": {
+                "resourceType": "MedicationRequest",
+                "id": "",
+                "status": "draft",
+                "intent": "order",
+                "medication_codeable_concept": { "coding": [], "text": "Amoxicillin 500mg" },
+                "subject": { "reference": "", "display": null },
+                "encounter": null,
+                "authored_on": "",
+                "requester": { "reference": requester, "display": null },
+                "dosage_instruction": [],
+                "dispense_request": null,
+            }
+        })
+    };
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+
+    let as_someone_else = post(&app, "/api/prescriptions", &practitioner, prescribe(PATIENT, &format!("Practitioner/{}", OTHER_PRACTITIONER))).await;
+    assert_eq!(as_someone_else.status(), reqwest::StatusCode::FORBIDDEN);
+    let for_another_patient = post(&app, "/api/prescriptions", &practitioner, prescribe(OTHER_PATIENT, "")).await;
+    assert_eq!(for_another_patient.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(
+        denials(&app, "create_prescription_denied").await,
+        vec![(PATIENT.to_string(), json!(PRACTITIONER)), (OTHER_PATIENT.to_string(), json!(PRACTITIONER))]
+    );
+
+    let created: Value = post(&app, "/api/prescriptions", &practitioner, prescribe(PATIENT, &format!("Practitioner/{}", PRACTITIONER))).await.json().await.unwrap();
+    assert_eq!(created["data"]["practitioner_did"], PRACTITIONER, "{}", created);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,83 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with};
+
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.7001";
+
+#[tokio::test]
+async fn chat_without_a_token_is_rejected() {
+    let app = spawn_test_app().await;
+
+    let response = app
+        .client
+        .post(app.url("/api/chat"))
+        .json(&json!({ "prompt": "What is HbA1c?" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn exhausted_daily_quota_returns_429_with_reset_time() {
+    let app = spawn_test_app_with(|config| config.dynamic.update(|dynamic| dynamic.chat_daily_request_limit = 2)).await;
+    let today = Utc::now().date_naive().to_string();
+    for _ in 0..2 {
+        app.database.record_chat_usage(PATIENT_DID, &today, 50, 20).await.unwrap();
+    }
+    let token = app.mint_jwt(PATIENT_DID, Role::Patient);
+
+    let response = app
+        .client
+        .post(app.url("/api/chat"))
+        .bearer_auth(token)
+        .json(&json!({ "prompt": "What is HbA1c?" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().is_some_and(|error| error.contains("resets at")));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn admins_see_usage_per_user_per_day() {
+    let app = spawn_test_app().await;
+    let today = Utc::now().date_naive().to_string();
+    app.database.record_chat_usage(PATIENT_DID, &today, 50, 20).await.unwrap();
+    app.database.record_chat_usage(PATIENT_DID, &today, 30, 10).await.unwrap();
+
+    let forbidden = app
+        .client
+        .get(app.url("/api/admin/chat/usage"))
+        .bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = app
+        .client
+        .get(app.url("/api/admin/chat/usage?days=1"))
+        .bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.1", Role::PlatformAdmin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(forbidden.status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(
+        body["data"],
+        json!([{ "user_did": PATIENT_DID, "day": today, "requests": 2, "prompt_tokens": 80, "reply_tokens": 30 }])
+    );
+
+    app.cleanup().await;
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::{doc, Document};
+use chrono::Utc;
+use mongodb::options::IndexOptions;
+use mongodb::IndexModel;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::auditing::AuditingService;
+use crate::cli::{self, ContractDeployer, EXIT_CHECK_FAILED, EXIT_FAILED, EXIT_NOT_FOUND};
+use crate::fixtures::Fixtures;
+use crate::models::{AuditLog, Role};
+use crate::seed::SeedOptions;
+use crate::services::AuthServiceImpl;
+use crate::state::{AppState, AppStateBuilder};
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const ALICE: &str = "did:hedera:testnet:0.0.9801";
+const NEW_ADMIN: &str = "did:hedera:testnet:0.0.9802";
+
+fn entry(action: &str) -> AuditLog {
+    AuditLog {
+        id: None,
+        did: ALICE.to_string(),
+        action: action.to_string(),
+        timestamp: Utc::now(),
+        details: None,
+        is_anchored: false,
+        anchor_batch_id: None,
+        tenant_id: None,
+    }
+}
+
+/// Hands out sequential contract ids and remembers the bytecode it was given
+#[derive(Default)]
+struct FakeDeployer {
+    deployed: Mutex<Vec<Vec<u8>>>,
+}
+
+#[async_trait]
+impl ContractDeployer for FakeDeployer {
+    async fn deploy_contract(&self, bytecode: &[u8]) -> Result<String> {
+        let mut deployed = self.deployed.lock().unwrap();
+        deployed.push(bytecode.to_vec());
+        Ok(format!("0.0.{}", 7000 + deployed.len()))
+    }
+}
+
+#[tokio::test]
+async fn migrate_applies_pending_migrations_once() {
+    let app = spawn_test_app().await;
+
+    cli::migrate(&app.database, &app.config).await.unwrap();
+    let rerun = cli::migrate(&app.database, &app.config).await.unwrap();
+
+    assert_eq!(rerun.code, 0);
+    assert_eq!(rerun.result, json!({ "applied": [] }));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn anchor_now_anchors_the_pending_logs_and_verify_batch_checks_them() {
+    let app = spawn_test_app().await;
+    for action in ["get_patient", "create_encounter"] {
+        app.database.create_audit_log(&entry(action)).await.unwrap();
+    }
+    let auditing = AuditingService::new(app.database.clone(), app.ledger.clone()).with_anchor_lookup(app.ledger.clone());
+
+    let anchored = cli::anchor_now(&app.database, &auditing).await.unwrap();
+    assert_eq!(anchored.code, 0);
+    assert!(anchored.result["anchored"].as_u64().unwrap() >= 2, "{}", anchored.result);
+    assert_eq!(anchored.result["unanchored"], 0);
+
+    let log = app
+        .database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .find_one(doc! { "did": ALICE, "action": "get_patient" }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let batch_id = log.anchor_batch_id.unwrap().to_hex();
+    let verified = cli::verify_batch(&auditing, &batch_id).await.unwrap();
+    assert_eq!(verified.code, 0, "{}", verified.result);
+    assert_eq!(verified.result["valid"], true);
+
+    // Editing an anchored log breaks the batch
+    app.database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .update_one(doc! { "_id": log.id.unwrap() }, doc! { "$set": { "did": "did:hedera:testnet:0.0.1" } }, None)
+        .await
+        .unwrap();
+    let tampered = cli::verify_batch(&auditing, &batch_id).await.unwrap();
+    assert_eq!(tampered.code, EXIT_CHECK_FAILED);
+    assert_eq!(tampered.result["valid"], false);
+
+    let unknown = cli::verify_batch(&auditing, &bson::oid::ObjectId::new().to_hex()).await.unwrap();
+    assert_eq!(unknown.code, EXIT_NOT_FOUND);
+    assert!(cli::verify_batch(&auditing, "not-an-id").await.is_err());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn building_indexes_reports_one_that_exists_with_other_options() {
+    let app = spawn_test_app().await;
+    let otps = app.database.db.collection::<Document>("otps");
+    otps.drop_index("expires_at_1", None).await.unwrap();
+    let other_ttl = IndexOptions::builder().expire_after(Duration::from_secs(60)).build();
+    otps.create_index(IndexModel::builder().keys(doc! { "expires_at": 1 }).options(other_ttl).build(), None).await.unwrap();
+
+    let built = cli::create_indexes(&app.database).await.unwrap();
+    assert_eq!(built.code, EXIT_FAILED);
+    assert_eq!(built.result["state"], "completed_with_errors");
+    let failed = built.result["failed"].as_array().unwrap();
+    assert_eq!(failed.len(), 1, "{}", built.result);
+    assert_eq!((failed[0]["collection"].as_str(), failed[0]["conflict"].as_bool()), (Some("otps"), Some(true)));
+
+    let admin = app.mint_high_assurance_jwt(NEW_ADMIN, Role::PlatformAdmin);
+    let stats: Value = app.client.get(app.url("/api/admin/stats")).bearer_auth(admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(stats["data"]["indexes"]["failed"][0]["keys"], failed[0]["keys"]);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn with_index_creation_skipped_migrate_indexes_only_builds_them() {
+    let app = spawn_test_app_with(|config| config.skip_index_creation = true).await;
+    let health: Value = app.client.get(app.url("/health/deep")).send().await.unwrap().json().await.unwrap();
+    assert_eq!((health["indexes"]["state"].as_str(), health["indexes_ready"].as_bool()), (Some("skipped"), Some(true)));
+    let referrals = app.database.db.collection::<Document>("referrals");
+    assert!(referrals.list_index_names().await.unwrap_or_default().len() <= 1, "only _id before the build");
+
+    let built = cli::create_indexes(&app.database).await.unwrap();
+    assert_eq!((built.code, built.result["state"].as_str()), (0, Some("completed")));
+    assert!(referrals.list_index_names().await.unwrap().contains(&"receiving_did_1_status_1_created_at_-1".to_string()));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn create_admin_grants_the_role_once() {
+    let app = spawn_test_app().await;
+
+    let created = cli::create_admin(&app.database, NEW_ADMIN, false).await.unwrap();
+    assert_eq!(created.result, json!({ "did": NEW_ADMIN, "role": Role::Admin, "created": true }));
+    assert_eq!(app.database.admin_role(NEW_ADMIN).await.unwrap(), Some(Role::Admin));
+
+    let promoted = cli::create_admin(&app.database, NEW_ADMIN, true).await.unwrap();
+    assert_eq!(promoted.result["created"], false);
+    assert_eq!(app.database.admin_role(NEW_ADMIN).await.unwrap(), Some(Role::PlatformAdmin));
+    assert!(cli::create_admin(&app.database, "0.0.9802", false).await.is_err());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn deploy_contracts_reads_every_bytecode_file_before_deploying() {
+    let dir = std::env::temp_dir().join(format!("contracts-{}", bson::oid::ObjectId::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("HealthcareAccessControl.bin"), "0x6080\n").unwrap();
+    std::fs::write(dir.join("VerifiableCredentials.bin"), "6081").unwrap();
+    let deployer = FakeDeployer::default();
+
+    assert!(cli::deploy_contracts(&deployer, &dir).await.is_err());
+    assert!(deployer.deployed.lock().unwrap().is_empty());
+
+    std::fs::write(dir.join("AuditTrail.bin"), "6082").unwrap();
+    let deployed = cli::deploy_contracts(&deployer, &dir).await.unwrap();
+    assert_eq!(
+        deployed.result,
+        json!({
+            "HEALTHCARE_ACCESS_CONTROL_CONTRACT_ID": "0.0.7001",
+            "VERIFIABLE_CREDENTIALS_CONTRACT_ID": "0.0.7002",
+            "AUDIT_TRAIL_CONTRACT_ID": "0.0.7003",
+        })
+    );
+    assert_eq!(deployer.deployed.lock().unwrap()[0], b"6080".to_vec());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The services `seed` writes through, on the test app's database and fake ledger
+async fn seed_state(app: &TestApp) -> AppState<AuthServiceImpl> {
+    AppStateBuilder::new(app.config.clone())
+        .with_database(app.database.clone())
+        .with_ledger(app.ledger.clone())
+        .with_did_registry(app.did_registry.clone())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn seed_writes_records_the_api_serves_once_per_seed() {
+    let app = spawn_test_app().await;
+    let state = seed_state(&app).await;
+    let options = SeedOptions { seed: 7, patients: 4, practitioners: 2 };
+
+    let seeded = cli::seed(&state, options).await.unwrap();
+    let summary = &seeded.result;
+    assert_eq!(seeded.code, 0);
+    assert_eq!((summary["practitioners"].as_array().unwrap().len(), summary["patients"].as_array().unwrap().len()), (2, 4));
+    assert_eq!(summary["grants"].as_array().unwrap().len(), 4);
+    assert!(summary["encounters"].as_array().unwrap().len() >= 4);
+    assert!(summary["anchored_logs"].as_u64().unwrap() > 0 && summary["unanchored_logs"].as_u64().unwrap() > 0, "{}", summary);
+    // The outbox ran, so the grants are on the ledger
+    assert_eq!(app.ledger.grants().len(), 4);
+
+    // The same seed generates the same people
+    let first = Fixtures::new(7).fhir_patient();
+    let name = &first.name[0];
+    let expected = format!("{} {}, {}, born {}", name.given[0], name.family.as_deref().unwrap(), first.gender, first.birth_date);
+    assert_eq!(summary["patients"][0]["detail"], expected);
+
+    // Each patient's practitioner can read their record through the API
+    let grant = summary["grants"][0]["detail"].as_str().unwrap();
+    let (patient_did, practitioner_did) = grant.split_once(" to ").unwrap();
+    let practitioner = app.database.get_practitioner_by_did(practitioner_did).await.unwrap().unwrap();
+    assert!(practitioner.license_verification.verified);
+    let record = app
+        .client
+        .get(app.url(&format!("/api/patients/{}", patient_did)))
+        .bearer_auth(app.mint_jwt(practitioner_did, Role::Practitioner))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(record.status(), reqwest::StatusCode::OK);
+
+    assert!(cli::seed(&state, options).await.is_err());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn seed_refuses_to_run_in_production() {
+    let app = spawn_test_app_with(|config| config.environment = "production".to_string()).await;
+    let state = seed_state(&app).await;
+
+    let refused = cli::seed(&state, SeedOptions { seed: 1, patients: 1, practitioners: 1 }).await;
+
+    assert!(refused.unwrap_err().to_string().contains("production"));
+    assert!(app.database.list_practitioners(None).await.unwrap().is_empty());
+
+    app.cleanup().await;
+}
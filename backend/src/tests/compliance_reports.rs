@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::auditing::AuditingService;
+use crate::config::JobConfig;
+use crate::jobs::JobWorkerPool;
+use crate::models::*;
+use crate::services::compliance_report::{verify_report, ComplianceReportHandler};
+use crate::state::AppStateBuilder;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const ADMIN_DID: &str = "did:hedera:testnet:0.0.9401";
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.9402";
+const DOCTOR_DID: &str = "did:hedera:testnet:0.0.9403";
+const PERIOD: &str = r#"{ "from": "2026-03-01T00:00:00Z", "to": "2026-04-01T00:00:00Z" }"#;
+
+fn entry(action: &str, timestamp: &str, details: Option<Value>) -> AuditLog {
+    AuditLog {
+        id: None,
+        did: PATIENT_DID.to_string(),
+        action: action.to_string(),
+        timestamp: timestamp.parse::<DateTime<Utc>>().unwrap(),
+        details,
+        is_anchored: false,
+        anchor_batch_id: None,
+        tenant_id: None,
+    }
+}
+
+async fn request_report(app: &TestApp, token: &str) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/admin/reports/compliance"))
+        .bearer_auth(token)
+        .header(CONTENT_TYPE, "application/json")
+        .body(PERIOD)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn admin_get(app: &TestApp, path: &str) -> reqwest::Response {
+    app.client.get(app.url(path)).bearer_auth(app.mint_jwt(ADMIN_DID, Role::PlatformAdmin)).send().await.unwrap()
+}
+
+async fn verify(app: &TestApp, document: &Value) -> Value {
+    let response = app
+        .client
+        .post(app.url("/api/admin/reports/compliance/verify"))
+        .bearer_auth(app.mint_jwt(ADMIN_DID, Role::PlatformAdmin))
+        .json(document)
+        .send()
+        .await
+        .unwrap();
+    response.json::<Value>().await.unwrap()["data"].clone()
+}
+
+/// Run the report jobs that are due, as `main`'s worker pool would
+async fn generate_reports(app: &TestApp) {
+    let state = AppStateBuilder::new(app.config.clone())
+        .with_database(app.database.clone())
+        .with_ledger(app.ledger.clone())
+        .with_did_registry(app.did_registry.clone())
+        .build()
+        .await
+        .unwrap();
+    let pool = JobWorkerPool::new(app.database.clone(), JobConfig::default())
+        .register(Arc::new(ComplianceReportHandler::new(state.compliance_report_service.clone(), JobConfig::default().max_attempts)));
+    while pool.run_next("reports/0", Utc::now()).await.unwrap() {}
+}
+
+#[tokio::test]
+async fn a_report_lists_the_periods_events_and_verifies_against_the_ledger() {
+    let app = spawn_test_app().await;
+    let logs = [
+        entry("get_patient", "2026-03-02T09:00:00Z", Some(json!({ "actor": DOCTOR_DID }))),
+        entry("break_glass", "2026-03-03T02:00:00Z", Some(json!({ "actor": DOCTOR_DID, "reason": "unconscious" }))),
+        entry("get_patient", "2026-03-03T02:05:00Z", Some(json!({ "actor": DOCTOR_DID, "emergency": true }))),
+        entry("issue_credential: VaccinationCredential", "2026-03-10T12:00:00Z", Some(json!({ "actor": DOCTOR_DID }))),
+        entry("update_timezone", "2026-03-11T12:00:00Z", None),
+        // Outside the period
+        entry("get_patient", "2026-04-01T00:00:00Z", Some(json!({ "actor": DOCTOR_DID }))),
+    ];
+    for log in &logs {
+        app.database.create_audit_log(log).await.unwrap();
+    }
+    AuditingService::new(app.database.clone(), app.ledger.clone()).anchor_audit_logs().await.unwrap();
+
+    let requested: Value = request_report(&app, &app.mint_jwt(ADMIN_DID, Role::PlatformAdmin)).await.json().await.unwrap();
+    assert_eq!(requested["data"]["status"], "pending", "{}", requested);
+    let id = requested["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+
+    generate_reports(&app).await;
+    let report: Value = admin_get(&app, &format!("/api/admin/reports/compliance/{}", id)).await.json().await.unwrap();
+    assert_eq!(report["data"]["status"], "completed", "{}", report);
+    assert_eq!(report["data"]["progress"], 100);
+    assert_eq!(report["data"]["anchors_valid"], true);
+
+    let document: Value = admin_get(&app, &format!("/api/admin/reports/compliance/{}/json", id)).await.json().await.unwrap();
+    let counts: Vec<(&str, u64)> =
+        document["categories"].as_array().unwrap().iter().map(|category| (category["category"].as_str().unwrap(), category["count"].as_u64().unwrap())).collect();
+    assert_eq!(counts, [("access", 2), ("break_glass", 1), ("credential_issuance", 1)]);
+    assert_eq!(document["categories"][0]["events"][1]["emergency"], true);
+    assert_eq!(document["categories"][0]["events"][1]["actor"], DOCTOR_DID);
+    assert_eq!(document["summary"]["total_events"], 5);
+    assert_eq!(document["summary"]["anchored_events"], 5);
+    // The batch was made after the period, but anchors its events
+    let batches = document["anchor_batches"].as_array().unwrap();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0]["merkle_root"], hex::encode(app.ledger.anchored_batches()[0].0));
+    assert_eq!(batches[0]["valid"], true);
+
+    let html = admin_get(&app, &format!("/api/admin/reports/compliance/{}/html", id)).await;
+    assert!(html.headers()[CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    assert!(html.text().await.unwrap().contains(batches[0]["merkle_root"].as_str().unwrap()));
+
+    // The server has no mirror node, so only the signature is checked there
+    let verification = verify(&app, &document).await;
+    assert_eq!((verification["valid"].as_bool(), verification["signed_by_this_server"].as_bool()), (Some(true), Some(true)));
+    assert_eq!(verification["anchors_checked"], false);
+    let on_ledger = verify_report(&document, Some(app.ledger.as_ref()), None).await.unwrap();
+    assert!(on_ledger.anchors_checked && on_ledger.valid);
+
+    let mut tampered = document.clone();
+    tampered["categories"][1]["events"] = json!([]);
+    assert_eq!(verify(&app, &tampered).await["signature_valid"], false);
+
+    let listed: Value = admin_get(&app, "/api/admin/reports/compliance").await.json().await.unwrap();
+    assert_eq!(listed["data"].as_array().unwrap().len(), 1);
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn reports_are_for_platform_admins_and_need_a_signing_key() {
+    let app = spawn_test_app().await;
+    let forbidden = request_report(&app, &app.mint_jwt(PATIENT_DID, Role::Patient)).await;
+    assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+    app.cleanup().await;
+
+    let unsigned = spawn_test_app_with(|config| config.credential_signing = None).await;
+    let refused = request_report(&unsigned, &unsigned.mint_jwt(ADMIN_DID, Role::PlatformAdmin)).await;
+    assert_eq!(refused.status(), StatusCode::NOT_IMPLEMENTED);
+    unsigned.cleanup().await;
+}
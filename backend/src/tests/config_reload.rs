@@ -0,0 +1,64 @@
+use reqwest::header::{ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::config::{Config, DynamicConfig};
+use crate::models::*;
+use crate::services::ConfigReloadService;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const ADMIN: &str = "did:hedera:testnet:0.0.9701";
+const ADMIN_ORIGIN: &str = "https://admin.example.com";
+
+async fn allowed_origin(app: &TestApp, origin: &str) -> Option<String> {
+    let preflight = app
+        .client
+        .request(reqwest::Method::OPTIONS, app.url("/api/encounters"))
+        .header(ORIGIN, origin)
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .send()
+        .await
+        .unwrap();
+    preflight.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn reloaded_cors_origins_apply_to_the_next_request() {
+    let app = spawn_test_app().await;
+    assert_eq!(allowed_origin(&app, "http://localhost:3000").await.as_deref(), Some("http://localhost:3000"));
+    assert_eq!(allowed_origin(&app, ADMIN_ORIGIN).await, None);
+
+    let reloaded = Config {
+        jwt_secret: "a-new-secret".into(),
+        dynamic: DynamicConfig { cors_allowed_origins: vec![ADMIN_ORIGIN.to_string()], ..DynamicConfig::clone(&app.config.dynamic.load()) }.into(),
+        ..Default::default()
+    };
+    let reload_service = ConfigReloadService::new(app.config.clone(), Arc::new(AuditLogService::new(app.database.clone())), None);
+    let reload = reload_service.apply(&reloaded, ADMIN).await.unwrap();
+
+    assert_eq!(reload.changed.keys().collect::<Vec<_>>(), vec!["cors_allowed_origins"]);
+    assert!(reload.ignored.contains(&"jwt_secret".to_string()));
+    assert_eq!(allowed_origin(&app, ADMIN_ORIGIN).await.as_deref(), Some(ADMIN_ORIGIN));
+    assert_eq!(allowed_origin(&app, "http://localhost:3000").await, None);
+    // Boot-time settings such as the JWT secret are kept
+    assert_eq!(app.config.jwt_secret.expose_secret(), "integration-test-secret");
+
+    let logs = app.database.get_unanchored_audit_logs().await.unwrap();
+    let entry = logs.iter().find(|log| log.action == "reload_config").unwrap();
+    assert_eq!(entry.did, ADMIN);
+    assert_eq!(entry.details.as_ref().unwrap()["changed"]["cors_allowed_origins"]["to"], serde_json::json!([ADMIN_ORIGIN]));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn only_stepped_up_admins_reload_the_configuration() {
+    let app = spawn_test_app().await;
+    let reload = |token: String| app.client.post(app.url("/api/admin/config/reload")).bearer_auth(token).send();
+
+    assert_eq!(reload(app.mint_jwt(ADMIN, Role::Admin)).await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+    let practitioner = app.mint_high_assurance_jwt("did:hedera:testnet:0.0.9702", Role::Practitioner);
+    assert_eq!(reload(practitioner).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,55 @@
+use serde_json::{json, Value};
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8201";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.8202";
+
+async fn get(app: &TestApp, path: &str, did: &str, role: Role) -> reqwest::Response {
+    app.client.get(app.url(path)).bearer_auth(app.mint_jwt(did, role)).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn grants_are_backed_by_consents_and_revoking_one_ends_access() {
+    let app = spawn_test_app().await;
+    let patient_path = format!("/api/patients/{}", PATIENT);
+
+    let before = get(&app, &patient_path, PRACTITIONER, Role::Practitioner).await;
+    assert_eq!(before.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["Read", "ViewEncounters"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+    assert_eq!(get(&app, &patient_path, PRACTITIONER, Role::Practitioner).await.status(), reqwest::StatusCode::OK);
+
+    let consents: Value = get(&app, &format!("{}/consents", patient_path), PATIENT, Role::Patient).await.json().await.unwrap();
+    let consent = &consents["data"][0];
+    assert_eq!(consent["status"], "active");
+    assert_eq!(consent["data_classes"], json!(["Patient", "Encounter", "Condition"]));
+    assert_eq!(consent["fhir_consent"]["resourceType"], "Consent");
+    let encrypted = app.fetch_from_ipfs(consent["ipfs_hash"].as_str().unwrap()).await;
+    assert!(!encrypted.is_empty());
+
+    let consent_id = consent["_id"]["$oid"].as_str().unwrap();
+    let revoked = app
+        .client
+        .post(app.url(&format!("{}/consents/{}/revoke", patient_path, consent_id)))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = revoked.json().await.unwrap();
+    assert_eq!(body["data"]["status"], "inactive");
+
+    let after = get(&app, &patient_path, PRACTITIONER, Role::Practitioner).await;
+    assert_eq!(after.status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,317 @@
+use bson::oid::ObjectId;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::services::hedera::LedgerAnchor;
+use crate::services::ipfs::IpfsClient;
+use crate::services::vc_document::{canonical_json, CredentialSigner, W3cCredentialBuilder};
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+use crate::utils;
+
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.1";
+const FRONT_DESK_DID: &str = "did:hedera:testnet:0.0.7";
+
+/// An anchored vaccination credential of the patient's
+async fn vaccination_credential(app: &TestApp) -> String {
+    let id = ObjectId::new();
+    let credential = VerifiableCredential {
+        id: Some(id),
+        subject_did: PATIENT_DID.to_string(),
+        credential_type: "VaccinationCredential".to_string(),
+        issuer: "did:hedera:testnet:0.0.2".to_string(),
+        issued_at: Utc::now(),
+        expires_at: None,
+        ipfs_hash: format!("QmVaccination{}", id.to_hex()),
+        hedera_transaction_id: None,
+        metadata: "{}".to_string(),
+        revoked_at: None,
+        status_list: None,
+        batch: None,
+    };
+    app.ledger.store_credential(PATIENT_DID, &credential.credential_type, &credential.ipfs_hash, None, "{}").await.unwrap();
+    app.database.create_verifiable_credential(&credential, &[]).await.unwrap();
+    id.to_hex()
+}
+
+/// Registration never verifies a license, so issuers get theirs verified here
+async fn licensed_practitioner(app: &TestApp, did: &str) {
+    let practitioner = Practitioner {
+        id: None,
+        did: did.to_string(),
+        fhir_practitioner: FhirPractitioner {
+            resource_type: "Practitioner".to_string(),
+            id: did.to_string(),
+            identifier: vec![],
+            name: vec![],
+            qualification: vec![],
+            telecom: vec![],
+        },
+        license_verification: LicenseVerification {
+            license_number: "KMLTTB-1".to_string(),
+            issuing_authority: "KMLTTB".to_string(),
+            issue_date: "2020-01-01".to_string(),
+            expiry_date: "2099-01-01".to_string(),
+            hedera_transaction_id: String::new(),
+            ipfs_hash: String::new(),
+            verified: true,
+            expiry_warning_sent_at: None,
+        },
+        practitioner_type: PractitionerType::default(),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        tenant_id: None,
+    };
+    app.database.create_practitioner(&practitioner).await.unwrap();
+}
+
+async fn register_issuer(app: &TestApp, did: &str) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/admin/issuers"))
+        .bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.100", Role::PlatformAdmin))
+        .json(&json!({ "did": did, "display_name": "Nairobi Reference Lab", "credential_types": ["LabResultCredential"] }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn issue(app: &TestApp, token: &str, credential_type: &str) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/credentials/issue"))
+        .bearer_auth(token)
+        .json(&json!({ "subject_did": PATIENT_DID, "credential_type": credential_type, "metadata": "{\"result\":\"negative\"}" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn verify(app: &TestApp, verifier_did: &str, token: &str) -> Value {
+    app.client
+        .post(app.url("/api/credentials/presentations/verify"))
+        .bearer_auth(app.mint_jwt(verifier_did, Role::Practitioner))
+        .json(&json!({ "token": token }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// A presentation of the subject's credential for the front desk
+async fn present(app: &TestApp, credential_id: &str, subject_did: &str) -> String {
+    let presented: Value = app
+        .client
+        .get(app.url(&format!("/api/credentials/{}/qr?audience={}", credential_id, FRONT_DESK_DID)))
+        .bearer_auth(app.mint_jwt(subject_did, Role::Patient))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    presented["data"]["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn a_presented_credential_verifies_at_the_front_desk_it_was_made_for() {
+    let app = spawn_test_app_with(|config| {
+        config.credential_presentation_secret = Some("integration-presentation-secret-0123456789".into());
+        config.credential_presentation_minutes = 5;
+    })
+    .await;
+    let credential_id = vaccination_credential(&app).await;
+    let qr_url = app.url(&format!("/api/credentials/{}/qr?audience={}", credential_id, FRONT_DESK_DID));
+
+    let someone_else = app.client.get(&qr_url).bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.9", Role::Patient)).send().await.unwrap();
+    assert_eq!(someone_else.status(), 403);
+
+    let presented: Value = app.client.get(&qr_url).bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(presented["success"], true, "presentation failed: {}", presented);
+    assert!(presented["data"]["qr_svg"].as_str().unwrap().contains("<svg"));
+    let token = presented["data"]["token"].as_str().unwrap();
+
+    let verdict = verify(&app, FRONT_DESK_DID, token).await;
+    assert_eq!(verdict["data"]["valid"], true, "verification failed: {}", verdict);
+    assert_eq!(verdict["data"]["subject_did"], PATIENT_DID);
+    assert_eq!(verdict["data"]["credential_type"], "VaccinationCredential");
+
+    let elsewhere = verify(&app, "did:hedera:testnet:0.0.8", token).await;
+    assert_eq!(elsewhere["data"]["valid"], false);
+
+    let (unsigned, signature) = token.rsplit_once('.').unwrap();
+    let tampered = verify(&app, FRONT_DESK_DID, &format!("{}.{}", unsigned, signature.chars().rev().collect::<String>())).await;
+    assert_eq!(tampered["data"]["valid"], false);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn presentations_need_a_signing_secret() {
+    let app = spawn_test_app().await;
+    let credential_id = vaccination_credential(&app).await;
+
+    let response = app
+        .client
+        .get(app.url(&format!("/api/credentials/{}/qr?audience={}", credential_id, FRONT_DESK_DID)))
+        .bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 501);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn credential_documents_are_served_as_vc_json_to_their_subject() {
+    let app = spawn_test_app().await;
+    let signer = CredentialSigner::from_config(app.config.credential_signing.as_ref().unwrap()).unwrap();
+    let document = W3cCredentialBuilder::new("VaccinationCredential", PATIENT_DID, Utc::now())
+        .with_claim("vaccine", json!({ "code": "208" }))
+        .sign(&signer);
+    let encrypted = utils::encrypt(canonical_json(&document).as_bytes(), &app.config.ipfs_encryption_key).unwrap();
+    let ipfs_hash = IpfsClient::new(&app.ipfs.uri()).add_file(encrypted.as_bytes(), None).await.unwrap();
+    let id = ObjectId::new();
+    let credential = VerifiableCredential {
+        id: Some(id),
+        subject_did: PATIENT_DID.to_string(),
+        credential_type: "VaccinationCredential".to_string(),
+        issuer: signer.issuer_did().to_string(),
+        issued_at: Utc::now(),
+        expires_at: None,
+        ipfs_hash,
+        hedera_transaction_id: None,
+        metadata: "{}".to_string(),
+        revoked_at: None,
+        status_list: None,
+        batch: None,
+    };
+    app.database.create_verifiable_credential(&credential, &[]).await.unwrap();
+    let url = app.url(&format!("/api/credentials/{}", id.to_hex()));
+
+    let response = app.client.get(&url).bearer_auth(app.mint_jwt(PATIENT_DID, Role::Patient)).send().await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/vc+json");
+    assert_eq!(response.json::<Value>().await.unwrap(), document);
+
+    let someone_else = app.client.get(&url).bearer_auth(app.mint_jwt(FRONT_DESK_DID, Role::Practitioner)).send().await.unwrap();
+    assert_eq!(someone_else.status(), 403);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn only_active_registered_issuers_issue_their_credential_types() {
+    let app = spawn_test_app().await;
+    let admin = app.mint_jwt("did:hedera:testnet:0.0.100", Role::PlatformAdmin);
+    let lab = "did:hedera:testnet:0.0.20";
+    licensed_practitioner(&app, lab).await;
+    let token = app.mint_high_assurance_jwt(lab, Role::Practitioner);
+    assert_eq!(issue(&app, &token, "LabResultCredential").await.status(), 403);
+
+    let registered: Value = register_issuer(&app, lab).await.json().await.unwrap();
+    assert_eq!(registered["data"]["status"], "active", "registration failed: {}", registered);
+    assert_eq!(register_issuer(&app, lab).await.status(), 409);
+
+    assert_eq!(issue(&app, &token, "VaccinationCredential").await.status(), 403);
+    let issued: Value = issue(&app, &token, "LabResultCredential").await.json().await.unwrap();
+    assert_eq!(issued["success"], true, "issuance failed: {}", issued);
+    assert_eq!(issued["data"]["issuer"], lab);
+
+    let suspended = app
+        .client
+        .put(app.url(&format!("/api/admin/issuers/{}", lab)))
+        .bearer_auth(&admin)
+        .json(&json!({ "status": "suspended" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(suspended.status(), 200);
+    assert_eq!(issue(&app, &token, "LabResultCredential").await.status(), 403);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn patients_and_unverified_practitioners_issue_nothing_even_when_registered() {
+    let app = spawn_test_app().await;
+    let unverified = "did:hedera:testnet:0.0.21";
+    // Registration leaves the license unverified
+    app.register_practitioner(unverified, None).await;
+    for did in [PATIENT_DID, unverified] {
+        assert_eq!(register_issuer(&app, did).await.status(), 200);
+    }
+
+    let as_patient = issue(&app, &app.mint_high_assurance_jwt(PATIENT_DID, Role::Patient), "LabResultCredential").await;
+    assert_eq!(as_patient.status(), 403);
+    let as_unverified = issue(&app, &app.mint_high_assurance_jwt(unverified, Role::Practitioner), "LabResultCredential").await;
+    assert_eq!(as_unverified.status(), 403);
+
+    let logs = app.database.get_unanchored_audit_logs().await.unwrap();
+    let denied: Vec<Value> = logs.into_iter().filter(|log| log.action == "issue_credential_denied").map(|log| log.details.unwrap()).collect();
+    assert_eq!(denied.iter().map(|details| details["actor"].clone()).collect::<Vec<_>>(), vec![json!(PATIENT_DID), json!(unverified)]);
+    assert_eq!(denied[1]["reason"], "Your license is not verified");
+    assert!(denied.iter().all(|details| details["suspicious"] == true));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn credentials_issued_in_a_batch_verify_through_their_merkle_proof() {
+    let app = spawn_test_app_with(|config| {
+        config.credential_presentation_secret = Some("integration-presentation-secret-0123456789".into());
+        config.credential_presentation_minutes = 5;
+    })
+    .await;
+    let lab = "did:hedera:testnet:0.0.22";
+    licensed_practitioner(&app, lab).await;
+    assert_eq!(register_issuer(&app, lab).await.status(), 200);
+    let subject = |index: usize| format!("did:hedera:testnet:0.0.{}", 300 + index);
+    let requests: Vec<Value> = (0..5)
+        .map(|index| json!({ "subject_did": subject(index), "credential_type": "LabResultCredential", "metadata": "{\"result\":\"negative\"}" }))
+        .collect();
+
+    let issued: Value = app
+        .client
+        .post(app.url("/api/credentials/issue-batch"))
+        .bearer_auth(app.mint_high_assurance_jwt(lab, Role::Practitioner))
+        .json(&json!({ "credentials": requests }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issued["success"], true, "batch issuance failed: {}", issued);
+    assert_eq!(issued["data"]["credentials"].as_array().unwrap().len(), 5);
+    assert_eq!((issued["data"]["fees"]["ledger_calls"].as_u64(), issued["data"]["fees"]["ledger_calls_if_issued_singly"].as_u64()), (Some(1), Some(5)));
+
+    // Five pins, and a single call anchoring the root instead of five
+    assert_eq!(app.dispatch_outbox().await, 6);
+    assert_eq!(app.ledger.credential_batches().len(), 1);
+    assert!(app.ledger.credentials().is_empty());
+    assert_eq!(hex::encode(app.ledger.credential_batches()[0].0), issued["data"]["merkle_root"].as_str().unwrap());
+
+    let credential_id = |index: usize| issued["data"]["credentials"][index]["_id"]["$oid"].as_str().unwrap().to_string();
+    let verdict = verify(&app, FRONT_DESK_DID, &present(&app, &credential_id(2), &subject(2)).await).await;
+    assert_eq!(verdict["data"]["valid"], true, "verification failed: {}", verdict);
+    let middle = app.database.get_credential(ObjectId::parse_str(credential_id(2)).unwrap()).await.unwrap().unwrap();
+    assert!(middle.hedera_transaction_id.is_some());
+
+    // A proof changed in the database leads to a root the ledger never saw
+    app.database
+        .db
+        .collection::<bson::Document>("verifiable_credentials")
+        .update_one(
+            bson::doc! { "_id": ObjectId::parse_str(credential_id(3)).unwrap() },
+            bson::doc! { "$set": { "batch.proof.0": hex::encode([9u8; 32]) } },
+            None,
+        )
+        .await
+        .unwrap();
+    let tampered = verify(&app, FRONT_DESK_DID, &present(&app, &credential_id(3), &subject(3)).await).await;
+    assert_eq!(tampered["data"]["valid"], false);
+    assert_eq!(tampered["data"]["reason"], "The credential could not be confirmed on the ledger");
+
+    app.cleanup().await;
+}
@@ -0,0 +1,54 @@
+use bson::doc;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::models::*;
+use crate::tests::helpers::spawn_test_app;
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9971";
+const ADMIN: &str = "did:hedera:testnet:0.0.9972";
+
+#[tokio::test]
+async fn the_callers_record_is_loaded_once_for_a_burst_of_requests() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let token = app.mint_jwt(PATIENT, Role::Patient);
+
+    let before = app.commands.count("find", "patients");
+    for _ in 0..5 {
+        let response = app.client.get(app.url(&format!("/api/patients/{}", PATIENT))).bearer_auth(&token).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<Value>().await.unwrap()["data"]["did"], PATIENT);
+    }
+    // The middleware's lookup serves the handler too, and the next four requests
+    assert_eq!(app.commands.count("find", "patients") - before, 1);
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    assert_eq!(audit_logs.count_documents(doc! { "did": PATIENT, "action": "get_patient" }, None).await.unwrap(), 5);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_token_of_a_suspended_account_is_rejected_and_audited() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let token = app.mint_jwt(PATIENT, Role::Patient);
+    let get = || app.client.get(app.url(&format!("/api/patients/{}", PATIENT))).bearer_auth(&token).send();
+    assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+
+    let disabled = app
+        .client
+        .post(app.url(&format!("/api/admin/patients/{}/disable", PATIENT)))
+        .bearer_auth(app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(disabled.status(), StatusCode::OK);
+    // Not served from the cache filled by the first request
+    assert_eq!(get().await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    let rejected = audit_logs.find_one(doc! { "did": PATIENT, "action": "token_rejected" }, None).await.unwrap().unwrap();
+    assert_eq!(rejected.details.unwrap()["reason"], "disabled");
+
+    app.cleanup().await;
+}
@@ -0,0 +1,69 @@
+use bson::doc;
+use reqwest::StatusCode;
+use serde_json::json;
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app_with, TestApp};
+
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9960";
+
+fn patient(index: usize) -> String {
+    format!("did:hedera:testnet:0.0.996{}", index + 1)
+}
+
+async fn read(app: &TestApp, did: &str) -> StatusCode {
+    app.client
+        .get(app.url(&format!("/api/patients/{}", did)))
+        .bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn enumerating_patients_is_alerted_on_before_it_is_refused() {
+    let app = spawn_test_app_with(|config| {
+        config.dynamic =
+            DynamicConfig { data_access_patients_alert_per_hour: 2, data_access_patients_per_hour: 4, ..DynamicConfig::clone(&config.dynamic.load()) }.into();
+    })
+    .await;
+    for index in 0..6 {
+        let did = patient(index);
+        app.create_patient(&did).await;
+        let granted = app
+            .client
+            .post(app.url("/api/access/grants"))
+            .bearer_auth(app.mint_jwt(&did, Role::Patient))
+            .json(&json!({ "patient_did": did, "grantee_did": PRACTITIONER, "permissions": ["Read"], "expires_at": null }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(granted.status(), StatusCode::OK);
+    }
+
+    let mut statuses = Vec::new();
+    for index in 0..6 {
+        statuses.push(read(&app, &patient(index)).await);
+    }
+    assert_eq!(
+        statuses,
+        [StatusCode::OK, StatusCode::OK, StatusCode::OK, StatusCode::OK, StatusCode::TOO_MANY_REQUESTS, StatusCode::TOO_MANY_REQUESTS]
+    );
+    // The records already opened stay readable
+    assert_eq!(read(&app, &patient(0)).await, StatusCode::OK);
+    // Patients reading their own record are not counted
+    let own = app.client.get(app.url(&format!("/api/patients/{}", patient(5)))).bearer_auth(app.mint_jwt(&patient(5), Role::Patient)).send().await.unwrap();
+    assert_eq!(own.status(), StatusCode::OK);
+
+    let audit_logs = app.database.db.collection::<AuditLog>("audit_logs");
+    let alert = audit_logs.find_one(doc! { "did": PRACTITIONER, "action": "data_access_alert" }, None).await.unwrap().unwrap();
+    let details = alert.details.unwrap();
+    assert_eq!((details["reason"].as_str(), details["count"].as_u64(), details["suspicious"].as_bool()), (Some("patients_per_hour"), Some(2), Some(true)));
+    let blocked = audit_logs.find_one(doc! { "did": PRACTITIONER, "action": "data_access_blocked" }, None).await.unwrap().unwrap();
+    assert!(alert.timestamp < blocked.timestamp);
+    assert_eq!(audit_logs.count_documents(doc! { "did": PRACTITIONER, "action": "data_access_alert" }, None).await.unwrap(), 1);
+    assert_eq!(audit_logs.count_documents(doc! { "did": PRACTITIONER, "action": "data_access_blocked" }, None).await.unwrap(), 2);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,321 @@
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+use crate::utils;
+
+async fn register(app: &TestApp, email: &str) -> (String, String) {
+    let response = app
+        .client
+        .post(app.url("/api/auth/register"))
+        .json(&json!({ "name": "Jane Doe", "email": email, "public_key_hex": "00".repeat(32) }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "registration failed: {}", body);
+    let did = body["data"]["user"]["did"].as_str().unwrap().to_string();
+    let token = body["data"]["token"].as_str().unwrap().to_string();
+    (did, token)
+}
+
+fn blood_pressure(encounter_id: &str, patient_did: &str) -> FhirObservation {
+    FhirObservation {
+        resource_type: "Observation".to_string(),
+        id: Uuid::new_v4().to_string(),
+        status: "final".to_string(),
+        category: vec![],
+        code: FhirCodeableConcept {
+            coding: vec![FhirCoding {
+                system: Some("http://loinc.org".to_string()),
+                code: Some("8480-6".to_string()),
+                display: Some("Systolic blood pressure".to_string()),
+            }],
+            text: None,
+        },
+        subject: FhirReference { reference: format!("Patient/{}", patient_did), display: None },
+        encounter: Some(FhirReference { reference: format!("Encounter/{}", encounter_id), display: None }),
+        effective_date_time: chrono::Utc::now().into(),
+        value_quantity: Some(FhirQuantity {
+            value: Some(120.0),
+            unit: Some("mmHg".to_string()),
+            system: Some("http://unitsofmeasure.org".to_string()),
+            code: Some("mm[Hg]".to_string()),
+        }),
+        value_string: None,
+        interpretation: vec![],
+    }
+}
+
+/// Uploads the stub IPFS node has received so far
+async fn ipfs_uploads(app: &TestApp) -> usize {
+    let requests = app.ipfs.received_requests().await.unwrap_or_default();
+    requests.iter().filter(|request| request.url.path() == "/api/v0/add").count()
+}
+
+#[tokio::test]
+async fn register_encounter_observation_finalize_and_fetch_bundle() {
+    let app = spawn_test_app().await;
+    let (patient_did, _patient_token) = register(&app, "flow@example.com").await;
+    let practitioner_did = "did:hedera:testnet:0.0.9001";
+    let practitioner_token = app.mint_jwt(practitioner_did, Role::Practitioner);
+    // Finalizing needs a step-up token
+    let finalize_token = app.mint_high_assurance_jwt(practitioner_did, Role::Practitioner);
+    app.register_practitioner(practitioner_did, None).await;
+
+    // Create encounter
+    let response = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&practitioner_token)
+        .json(&json!({
+            "patient_did": patient_did,
+            "practitioner_did": practitioner_did,
+            "class": { "system": null, "code": "AMB", "display": "ambulatory" },
+            "reason_code": [
+                { "coding": [{ "system": "http://hl7.org/fhir/sid/icd-10-cm", "code": "E11.9", "display": null }], "text": null },
+                { "coding": [{ "system": "http://hl7.org/fhir/sid/icd-10-cm", "code": "E99.99", "display": "Typo" }], "text": null }
+            ],
+            "period": { "start": null, "end": null }
+        }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "create encounter failed: {}", body);
+    let encounter_id = body["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    // Known codes get their display filled in; unknown ones are kept, with a warning
+    let reasons = &body["data"]["fhir_encounter"]["reason_code"];
+    assert_eq!(reasons[0]["coding"][0]["display"], "Type 2 diabetes mellitus without complications");
+    assert_eq!(body["data"]["code_warnings"][0]["message"], "Unknown ICD-10 code 'E99.99'");
+
+    // Add observation
+    app.database
+        .create_observation(&blood_pressure(&encounter_id, &patient_did))
+        .await
+        .unwrap();
+
+    // Quick-entry vitals become coded observations on the same encounter
+    let vitals_path = format!("/api/encounters/{}/vitals", encounter_id);
+    let response = app
+        .client
+        .post(app.url(&vitals_path))
+        .bearer_auth(&practitioner_token)
+        .json(&json!({ "heart_rate": 118, "spo2": 97 }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "record vitals failed: {}", body);
+    assert_eq!(body["data"][0]["code"]["coding"][0]["code"], "8867-4");
+    assert_eq!(body["data"][0]["interpretation"][0]["coding"][0]["code"], "H");
+    assert_eq!(body["data"][1]["value_quantity"]["code"], "%");
+
+    // Finalize
+    let response = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(&finalize_token)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "finalize failed: {}", body);
+    let ipfs_hash = body["data"].as_str().unwrap().to_string();
+    let stored = app.database.get_encounter(bson::oid::ObjectId::parse_str(&encounter_id).unwrap()).await.unwrap().unwrap();
+    assert_eq!((stored.status, stored.fhir_encounter.status.as_str()), (EncounterStatus::Finished, "finished"));
+
+    // Fetch bundle
+    let encrypted = app.fetch_from_ipfs(&ipfs_hash).await;
+    let decrypted = utils::chunked::decrypt(&encrypted, &app.config.ipfs_encryption_key).unwrap();
+    let bundle: Value = serde_json::from_slice(&decrypted).unwrap();
+    let resource_types: Vec<&str> = bundle["entry"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|entry| entry["resource"]["resourceType"].as_str())
+        .collect();
+    assert!(resource_types.contains(&"Patient"));
+    assert!(resource_types.contains(&"Encounter"));
+    assert_eq!(resource_types.iter().filter(|resource_type| **resource_type == "Observation").count(), 3);
+
+    let response = app.client.post(app.url(&vitals_path)).bearer_auth(&practitioner_token).json(&json!({ "heart_rate": 70 })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    // Finalizing again returns the same bundle
+    let response = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(&finalize_token)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "second finalize failed: {}", body);
+    assert_eq!(body["data"], ipfs_hash.as_str());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn concurrent_finalizations_upload_one_bundle() {
+    let app = spawn_test_app().await;
+    let (patient_did, _patient_token) = register(&app, "concurrent@example.com").await;
+    let practitioner_did = "did:hedera:testnet:0.0.9004";
+    let practitioner_token = app.mint_jwt(practitioner_did, Role::Practitioner);
+    // Finalizing needs a step-up token
+    let finalize_token = app.mint_high_assurance_jwt(practitioner_did, Role::Practitioner);
+    app.register_practitioner(practitioner_did, None).await;
+    let response = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&practitioner_token)
+        .json(&json!({
+            "patient_did": patient_did,
+            "practitioner_did": practitioner_did,
+            "class": { "system": null, "code": "AMB", "display": "ambulatory" },
+            "reason_code": [],
+            "period": { "start": null, "end": null }
+        }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    let encounter_id = body["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    let finalize_url = app.url(&format!("/api/encounters/{}/finalize", encounter_id));
+    let uploads_before = ipfs_uploads(&app).await;
+
+    let (client, finalize_url, finalize_token) = (&app.client, &finalize_url, &finalize_token);
+    let responses = futures_util::future::join_all((0..8).map(|_| async move {
+        let response = client.post(finalize_url).bearer_auth(finalize_token).send().await.unwrap();
+        response.json::<Value>().await.unwrap()
+    }))
+    .await;
+
+    assert_eq!(ipfs_uploads(&app).await - uploads_before, 1);
+    let stored = app.database.get_encounter(bson::oid::ObjectId::parse_str(&encounter_id).unwrap()).await.unwrap().unwrap();
+    let ipfs_hash = stored.final_bundle_ipfs_hash.expect("encounter was not finalized");
+    // Calls that overlapped the winning one are told it is in progress; the rest get its bundle
+    for body in &responses {
+        match body["success"].as_bool() {
+            Some(true) => assert_eq!(body["data"], ipfs_hash.as_str()),
+            _ => assert_eq!(body["error"], "Encounter is already being finalized"),
+        }
+    }
+    assert!(responses.iter().any(|body| body["success"] == true));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn cancelling_an_encounter_with_observations_needs_force() {
+    let app = spawn_test_app().await;
+    let (patient_did, _patient_token) = register(&app, "cancel@example.com").await;
+    let practitioner_did = "did:hedera:testnet:0.0.9002";
+    let practitioner_token = app.mint_jwt(practitioner_did, Role::Practitioner);
+    // Finalizing needs a step-up token
+    let finalize_token = app.mint_high_assurance_jwt(practitioner_did, Role::Practitioner);
+    app.register_practitioner(practitioner_did, None).await;
+    let response = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&practitioner_token)
+        .json(&json!({
+            "patient_did": patient_did,
+            "practitioner_did": practitioner_did,
+            "class": { "system": null, "code": "AMB", "display": "ambulatory" },
+            "reason_code": [],
+            "period": { "start": null, "end": null }
+        }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["data"]["status"], "in-progress", "create encounter failed: {}", body);
+    let encounter_id = body["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    app.database.create_observation(&blood_pressure(&encounter_id, &patient_did)).await.unwrap();
+    let status_path = format!("/api/encounters/{}/status", encounter_id);
+
+    // Someone else's encounter
+    let other_token = app.mint_jwt("did:hedera:testnet:0.0.9003", Role::Practitioner);
+    let response = app.client.post(app.url(&status_path)).bearer_auth(&other_token).json(&json!({ "status": "cancelled", "reason": "Duplicate" })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = app.client.post(app.url(&status_path)).bearer_auth(&practitioner_token).json(&json!({ "status": "cancelled", "reason": "Duplicate" })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    let response = app
+        .client
+        .post(app.url(&status_path))
+        .bearer_auth(&practitioner_token)
+        .json(&json!({ "status": "cancelled", "reason": "Duplicate", "force": true }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "cancel failed: {}", body);
+    assert_eq!(body["data"]["status"], "cancelled");
+    assert_eq!(body["data"]["fhir_encounter"]["status"], "cancelled");
+    assert_eq!(body["data"]["status_reason"], "Duplicate");
+
+    // Cancelled encounters are final
+    let response = app.client.post(app.url(&status_path)).bearer_auth(&practitioner_token).json(&json!({ "status": "in-progress" })).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let response = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(&finalize_token)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "A cancelled encounter cannot be finalized");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn protected_routes_reject_missing_and_forged_tokens() {
+    let app = spawn_test_app().await;
+
+    let missing = app.client.post(app.url("/api/encounters")).json(&json!({})).send().await.unwrap();
+    assert_eq!(missing.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let forged = app
+        .client
+        .get(app.url("/api/patients/did:hedera:testnet:0.0.1"))
+        .bearer_auth("not-a-jwt")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forged.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn concurrent_bundle_writes_get_distinct_versions() {
+    let app = spawn_test_app().await;
+    let bundle = FhirBundle {
+        id: None,
+        patient_did: "did:hedera:testnet:0.0.1".to_string(),
+        bundle: json!({ "resourceType": "Bundle" }),
+        version: 1,
+        created_at: chrono::Utc::now(),
+        updated_at: chrono::Utc::now(),
+    };
+
+    let writes = (0..4).map(|_| {
+        let database = app.database.clone();
+        let bundle = bundle.clone();
+        tokio::spawn(async move { database.create_fhir_bundle(&bundle).await })
+    });
+    let mut versions: Vec<u32> = futures_util::future::join_all(writes).await.into_iter().map(|write| write.unwrap().unwrap()).collect();
+    versions.sort();
+    assert_eq!(versions, vec![1, 2, 3, 4]);
+    let latest = app.database.get_fhir_bundle(&bundle.patient_did).await.unwrap().unwrap();
+    assert_eq!(latest.version, 4);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,74 @@
+//! Wiremock stand-in for the Google token endpoint and the FCM send endpoint `FcmClient` calls.
+//! Tokens starting with `stale-` are rejected as unregistered, as FCM does once an app is removed.
+
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+use crate::services::fcm::FcmClient;
+
+pub const PROJECT_ID: &str = "health-test";
+
+pub async fn start() -> MockServer {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": "ya29.stub", "expires_in": 3599, "token_type": "Bearer" })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/v1/projects/{}/messages:send", PROJECT_ID)))
+        .respond_with(SendMessage)
+        .mount(&server)
+        .await;
+
+    server
+}
+
+/// A client signed in as a test service account, sending to `server`
+pub fn client(server: &MockServer) -> FcmClient {
+    let credentials = json!({
+        "type": "service_account",
+        "project_id": PROJECT_ID,
+        "client_email": "push@health-test.iam.gserviceaccount.com",
+        "private_key": include_str!("data/fcm_test_key.pem"),
+        "token_uri": format!("{}/token", server.uri()),
+    });
+    FcmClient::from_service_account(&credentials.to_string(), reqwest::Client::new())
+        .expect("invalid test FCM credentials")
+        .with_base_url(&server.uri())
+}
+
+/// The messages `server` was asked to send, oldest first
+pub async fn sent_messages(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(|request| request.url.path().ends_with("messages:send"))
+        .filter_map(|request| request.body_json::<Value>().ok())
+        .map(|body| body["message"].clone())
+        .collect()
+}
+
+struct SendMessage;
+
+impl Respond for SendMessage {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body: Value = request.body_json().unwrap_or_default();
+        let token = body["message"]["token"].as_str().unwrap_or_default();
+        if token.starts_with("stale-") {
+            return ResponseTemplate::new(404).set_body_json(json!({
+                "error": {
+                    "code": 404,
+                    "message": "Requested entity was not found.",
+                    "status": "NOT_FOUND",
+                    "details": [{ "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError", "errorCode": "UNREGISTERED" }]
+                }
+            }));
+        }
+        ResponseTemplate::new(200).set_body_json(json!({ "name": format!("projects/{}/messages/{}", PROJECT_ID, token) }))
+    }
+}
@@ -0,0 +1,80 @@
+use serde_json::{json, Value};
+
+use crate::migrations::{FhirDateReport, Migration};
+use crate::models::*;
+use crate::services::fhir::{FhirCodeSystems, FhirManager};
+use crate::tests::helpers::spawn_test_app;
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9601";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9602";
+
+#[tokio::test]
+async fn encounters_that_end_before_they_start_are_refused() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let create = |period: Value| {
+        app.client
+            .post(app.url("/api/encounters"))
+            .bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner))
+            .json(&json!({
+                "patient_did": PATIENT,
+                "practitioner_did": PRACTITIONER,
+                "class": { "system": null, "code": "AMB", "display": "ambulatory" },
+                "reason_code": [],
+                "period": period
+            }))
+            .send()
+    };
+
+    let backwards: Value = create(json!({ "start": "2025-03-01T10:00:00+03:00", "end": "2025-03-01T09:30:00+03:00" })).await.unwrap().json().await.unwrap();
+    assert_eq!(backwards["success"], false);
+    assert_eq!(backwards["error"], "The period ends (2025-03-01T09:30:00+03:00) before it starts (2025-03-01T10:00:00+03:00)");
+    let malformed: Value = create(json!({ "start": "01/03/2025", "end": null })).await.unwrap().json().await.unwrap();
+    assert_eq!(malformed["success"], false);
+    assert!(malformed["error"].as_str().unwrap().starts_with("Invalid period: '01/03/2025' is not a FHIR date or dateTime"));
+
+    // A visit on the day it started, recorded without a time, is fine in any zone
+    let same_day: Value = create(json!({ "start": "2025-03-01T23:30:00+03:00", "end": "2025-03-01" })).await.unwrap().json().await.unwrap();
+    assert_eq!(same_day["success"], true, "{}", same_day);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn stored_dates_that_need_correcting_are_reported_but_left_alone() {
+    let app = spawn_test_app().await;
+    let yesterday: FhirDateTime = serde_json::from_value(json!("yesterday")).unwrap();
+    let observation = FhirManager::create_observation(PATIENT, Some("enc-1"), FhirCodeSystems::heart_rate(), vec![], None, None, vec![], yesterday.clone());
+    app.database.create_observation(&observation).await.unwrap();
+    let backwards = FhirManager::create_condition(
+        PATIENT,
+        None,
+        FhirCodeSystems::blood_pressure(),
+        vec![],
+        "2025-03-01".parse().unwrap(),
+        "2025-02-01".parse().unwrap(),
+    );
+    app.database.create_condition(&backwards).await.unwrap();
+    let fine = FhirManager::create_condition(PATIENT, None, FhirCodeSystems::blood_pressure(), vec![], "2025-02".parse().unwrap(), "2025-02-14".parse().unwrap());
+    app.database.create_condition(&fine).await.unwrap();
+
+    FhirDateReport.up(&app.database).await.unwrap();
+
+    let issues = app.database.list_fhir_date_issues().await.unwrap();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].collection, "conditions");
+    assert_eq!(issues[0].problem, "The condition was recorded (2025-02-01) before its onset (2025-03-01)");
+    assert_eq!(issues[1].collection, "observations");
+    assert!(issues[1].problem.starts_with("Invalid effective_date_time: 'yesterday' is not a FHIR date or dateTime"));
+    let stored = app.database.get_observations_for_encounter("enc-1").await.unwrap();
+    assert_eq!(stored[0].effective_date_time, yesterday);
+
+    let list = |token: String| app.client.get(app.url("/api/admin/fhir-date-issues")).bearer_auth(token).send();
+    assert_eq!(list(app.mint_jwt("did:hedera:testnet:0.0.9603", Role::Admin)).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+    let listed: Value = list(app.mint_jwt("did:hedera:testnet:0.0.9604", Role::PlatformAdmin)).await.unwrap().json().await.unwrap();
+    assert_eq!(listed["data"].as_array().unwrap().len(), 2);
+    assert_eq!(listed["data"][1]["collection"], "observations");
+
+    app.cleanup().await;
+}
@@ -0,0 +1,90 @@
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9801";
+const UNREGISTERED: &str = "did:hedera:testnet:0.0.9802";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9803";
+
+const FHIR: &str = "application/fhir+json";
+
+async fn get(app: &TestApp, path: &str, token: &str, accept: &str) -> Response {
+    app.client.get(app.url(path)).bearer_auth(token).header(ACCEPT, accept).send().await.unwrap()
+}
+
+async fn outcome(response: Response) -> (StatusCode, String, Value) {
+    let status = response.status();
+    let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+    (status, content_type, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn clinical_reads_answer_in_the_format_asked_for() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let token = app.mint_jwt(PATIENT, Role::Patient);
+
+    let (status, content_type, patient) = outcome(get(&app, &format!("/api/patients/{}", PATIENT), &token, FHIR).await).await;
+    assert_eq!((status, content_type.as_str()), (StatusCode::OK, FHIR));
+    assert_eq!(patient["resourceType"], "Patient");
+    assert_eq!(patient["id"], PATIENT);
+    let (status, content_type, envelope) =
+        outcome(get(&app, &format!("/api/patients/{}", PATIENT), &token, "application/json").await).await;
+    assert_eq!((status, content_type.as_str()), (StatusCode::OK, "application/json"));
+    assert_eq!(envelope["success"], true);
+    assert_eq!(envelope["data"]["did"], PATIENT);
+
+    let (_, _, bundle) = outcome(get(&app, &format!("/api/patients/{}/problems", PATIENT), &token, FHIR).await).await;
+    assert_eq!((bundle["resourceType"].as_str(), bundle["type"].as_str(), bundle["total"].as_u64()), (Some("Bundle"), Some("searchset"), Some(0)));
+    // No preference keeps the envelope
+    let response = app.client.get(app.url(&format!("/api/patients/{}/problems", PATIENT))).bearer_auth(&token).send().await.unwrap();
+    let (_, _, envelope) = outcome(response).await;
+    assert_eq!(envelope["data"], serde_json::json!([]));
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn errors_become_operation_outcomes_for_fhir_callers() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+
+    // Refused by the policy table
+    let (status, content_type, refused) = outcome(get(&app, &format!("/api/patients/{}", PATIENT), &practitioner, FHIR).await).await;
+    assert_eq!((status, content_type.as_str()), (StatusCode::FORBIDDEN, FHIR));
+    assert_eq!(refused["resourceType"], "OperationOutcome");
+    assert_eq!(refused["issue"][0]["severity"], "error");
+    assert_eq!(refused["issue"][0]["code"], "forbidden");
+    let response = get(&app, &format!("/api/patients/{}", PATIENT), &practitioner, "application/json").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.headers().get(CONTENT_TYPE).map_or(true, |content_type| content_type != FHIR));
+
+    // A read of a record that does not exist
+    let unregistered = app.mint_jwt(UNREGISTERED, Role::Patient);
+    let (status, _, missing) = outcome(get(&app, &format!("/api/patients/{}", UNREGISTERED), &unregistered, FHIR).await).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(missing["issue"][0]["code"], "not-found");
+    let (status, _, envelope) = outcome(get(&app, &format!("/api/patients/{}", UNREGISTERED), &unregistered, "application/json").await).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((envelope["success"].as_bool(), envelope["data"].is_null()), (Some(true), true));
+
+    // A FHIR body that does not fit, rejected before the handler runs
+    let response = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&practitioner)
+        .header(ACCEPT, FHIR)
+        .header(CONTENT_TYPE, FHIR)
+        .body(r#"{"resourceType": "Encounter"}"#)
+        .send()
+        .await
+        .unwrap();
+    let (status, content_type, invalid) = outcome(response).await;
+    assert_eq!((status, content_type.as_str()), (StatusCode::UNPROCESSABLE_ENTITY, FHIR));
+    assert_eq!(invalid["issue"][0]["code"], "invalid");
+    assert!(invalid["issue"][0]["diagnostics"].as_str().unwrap().contains("patient_did"));
+    app.cleanup().await;
+}
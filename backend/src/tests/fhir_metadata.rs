@@ -0,0 +1,48 @@
+use reqwest::{header, StatusCode};
+use serde_json::Value;
+
+use crate::api::capability::FHIR_ROUTES;
+use crate::api::routes::build_router_with_paths;
+use crate::state::AppStateBuilder;
+use crate::tests::helpers::spawn_test_app;
+
+const SNAPSHOT: &str = include_str!("data/capability_statement.json");
+
+#[tokio::test]
+async fn metadata_serves_the_capability_statement_unauthenticated() {
+    let app = spawn_test_app().await;
+
+    let response = app.client.get(app.url("/metadata")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/fhir+json");
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=3600");
+    let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+    let mut statement: Value = response.json().await.unwrap();
+
+    // Dated when first served, so the date is all that differs from the snapshot
+    let date = statement.as_object_mut().unwrap().remove("date").unwrap();
+    assert!(chrono::DateTime::parse_from_rfc3339(date.as_str().unwrap()).is_ok());
+    let snapshot: Value = serde_json::from_str(SNAPSHOT).unwrap();
+    assert_eq!(statement, snapshot, "regenerate src/tests/data/capability_statement.json if the change is intended");
+
+    let revalidated = app.client.get(app.url("/metadata")).header(header::IF_NONE_MATCH, &etag).send().await.unwrap();
+    assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn every_fhir_route_is_served() {
+    let app = spawn_test_app().await;
+    let state = AppStateBuilder::new(app.config.clone())
+        .with_database(app.database.clone())
+        .with_ledger(app.ledger.clone())
+        .with_did_registry(app.did_registry.clone())
+        .build()
+        .await
+        .unwrap();
+    let (_, paths) = build_router_with_paths(state);
+
+    let missing: Vec<&str> = FHIR_ROUTES.iter().map(|route| route.path).filter(|path| !paths.contains(path)).collect();
+    assert!(missing.is_empty(), "FHIR routes that are not served: {:?}", missing);
+    app.cleanup().await;
+}
@@ -0,0 +1,115 @@
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::fixtures;
+use crate::models::*;
+use crate::services::ipfs::IpfsClient;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+use crate::utils;
+use crate::utils::chunked::{FRAME_SIZE, PREAMBLE_LEN};
+
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.1";
+const STRANGER_DID: &str = "did:hedera:testnet:0.0.3";
+
+/// A finished encounter of the patient's whose bundle is `stored` on IPFS
+async fn encounter_with_bundle(app: &TestApp, stored: &[u8]) -> String {
+    let ipfs_hash = IpfsClient::new(&app.ipfs.uri()).add_file(stored, None).await.unwrap();
+    let encounter = Encounter {
+        final_bundle_ipfs_hash: Some(ipfs_hash.clone()),
+        bundle_key_version: Some(app.config.ipfs_encryption_key_version),
+        ..fixtures::encounter(PATIENT_DID, "did:hedera:testnet:0.0.2", EncounterStatus::Finished)
+    };
+    app.database.create_encounter(&encounter).await.unwrap();
+    ipfs_hash
+}
+
+/// Several frames' worth of plaintext, ending part-way through a frame
+fn large_file() -> Vec<u8> {
+    (0..3 * FRAME_SIZE + 1234).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+async fn download(app: &TestApp, did: &str, cid: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = app.client.get(app.url(&format!("/api/files/{}", cid))).bearer_auth(app.mint_jwt(did, Role::Patient));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap()
+}
+
+fn header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> &str {
+    response.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn a_file_streams_with_its_length_and_digest_and_resumes_after_a_disconnect() {
+    let app = spawn_test_app().await;
+    let plaintext = large_file();
+    let cid = encounter_with_bundle(&app, &utils::chunked::encrypt(&plaintext, &app.config.ipfs_encryption_key).unwrap()).await;
+    let etag = format!("\"{:x}\"", Sha256::digest(&plaintext));
+
+    let full = download(&app, PATIENT_DID, &cid, &[]).await;
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!(header(&full, CONTENT_LENGTH), plaintext.len().to_string());
+    assert_eq!((header(&full, ETAG), header(&full, ACCEPT_RANGES)), (etag.as_str(), "bytes"));
+    assert_eq!(full.bytes().await.unwrap(), plaintext);
+
+    // Drop the connection once the first chunk is in, then pick up from there
+    let mut interrupted = download(&app, PATIENT_DID, &cid, &[]).await;
+    let received = interrupted.chunk().await.unwrap().unwrap();
+    assert!(!received.is_empty() && received.len() < plaintext.len());
+    drop(interrupted);
+    let resume_from = format!("bytes={}-", received.len());
+    let resumed = download(&app, PATIENT_DID, &cid, &[(RANGE.as_str(), &resume_from), (IF_RANGE.as_str(), &etag)]).await;
+    assert_eq!(resumed.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(header(&resumed, CONTENT_RANGE), format!("bytes {}-{}/{}", received.len(), plaintext.len() - 1, plaintext.len()));
+    assert_eq!(header(&resumed, CONTENT_LENGTH), (plaintext.len() - received.len()).to_string());
+    let mut reassembled = received.to_vec();
+    reassembled.extend_from_slice(&resumed.bytes().await.unwrap());
+    assert_eq!(reassembled, plaintext);
+
+    // A range within two frames only fetches those frames from IPFS
+    let straddling = format!("bytes={}-{}", FRAME_SIZE - 10, FRAME_SIZE + 9);
+    let part = download(&app, PATIENT_DID, &cid, &[(RANGE.as_str(), &straddling)]).await;
+    assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(part.bytes().await.unwrap(), plaintext[FRAME_SIZE - 10..FRAME_SIZE + 10]);
+    let requests = app.ipfs.received_requests().await.unwrap_or_default();
+    let first_frame_offset = format!("offset={}", PREAMBLE_LEN);
+    let length = format!("length={}", 2 * (FRAME_SIZE + 16));
+    assert!(requests.iter().any(|request| {
+        let query = request.url.query().unwrap_or_default();
+        query.contains(&first_frame_offset) && query.contains(&length)
+    }));
+
+    // A copy that changed since the client started gets sent whole again
+    let stale = download(&app, PATIENT_DID, &cid, &[(RANGE.as_str(), "bytes=100-"), (IF_RANGE.as_str(), "\"outdated\"")]).await;
+    assert_eq!((stale.status(), header(&stale, CONTENT_LENGTH)), (StatusCode::OK, plaintext.len().to_string().as_str()));
+
+    let past_the_end = format!("bytes={}-", plaintext.len());
+    let unsatisfiable = download(&app, PATIENT_DID, &cid, &[(RANGE.as_str(), &past_the_end)]).await;
+    assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(header(&unsatisfiable, CONTENT_RANGE), format!("bytes */{}", plaintext.len()));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn files_are_only_served_to_those_who_may_see_their_encounter() {
+    let app = spawn_test_app().await;
+    // Bundles archived before framed encryption are still served, ranges included
+    let legacy = utils::encrypt(b"{\"resourceType\":\"Bundle\"}", &app.config.ipfs_encryption_key).unwrap();
+    let cid = encounter_with_bundle(&app, legacy.as_bytes()).await;
+
+    let denied = download(&app, STRANGER_DID, &cid, &[]).await;
+    assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+
+    let part = download(&app, PATIENT_DID, &cid, &[(RANGE.as_str(), "bytes=2-13")]).await;
+    assert_eq!((part.status(), header(&part, CONTENT_RANGE)), (StatusCode::PARTIAL_CONTENT, "bytes 2-13/26"));
+    assert_eq!(part.bytes().await.unwrap(), &b"resourceType"[..]);
+
+    let unknown: Value = download(&app, PATIENT_DID, "stub-unknown", &[]).await.json().await.unwrap();
+    assert_eq!((unknown["success"].as_bool(), unknown["error"].as_str()), (Some(false), Some("File not found")));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,266 @@
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use testcontainers_modules::mongo::Mongo;
+use testcontainers_modules::testcontainers::{runners::AsyncRunner, ContainerAsync};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use wiremock::MockServer;
+
+use crate::api::middleware::jwt_auth::AuthClaims;
+use crate::config::{Config, CredentialSigningConfig, DynamicConfig, JobConfig, PatientSearchConfig};
+use crate::database::Database;
+use crate::jobs::JobWorkerPool;
+use crate::fixtures;
+use crate::models::{FhirPatient, Role, SecondFactor};
+use crate::services::fakes::{FakePhoneVerifier, InMemoryDidRegistry, RecordingLedgerAnchor};
+use crate::services::ipfs::IpfsClient;
+use crate::services::outbox::OutboxDispatcher;
+use crate::state::AppStateBuilder;
+use crate::telemetry::MongoCommandSpans;
+use crate::tests::{fcm_stub, ipfs_stub};
+
+/// A running backend bound to a random local port.
+///
+/// MongoDB comes from a throwaway single-node replica set, as the outbox needs
+/// transactions, unless `TEST_DATABASE_URL` points at an existing one. IPFS and FCM are wiremock stubs, and Hedera and SMS verification
+/// are replaced by fakes. The fake verifier accepts [`TEST_PHONE_CODE`].
+pub const TEST_PHONE_CODE: &str = "123456";
+
+pub struct TestApp {
+    pub address: SocketAddr,
+    pub client: reqwest::Client,
+    pub config: Arc<Config>,
+    pub database: Arc<Database>,
+    pub did_registry: Arc<InMemoryDidRegistry>,
+    pub ledger: Arc<RecordingLedgerAnchor>,
+    pub phone_verifier: Arc<FakePhoneVerifier>,
+    pub ipfs: MockServer,
+    pub fcm: MockServer,
+    pub commands: Arc<CommandCounter>,
+    _mongo: Option<ContainerAsync<Mongo>>,
+}
+
+impl TestApp {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// Sign a token the auth middleware will accept for `did` acting as `role`
+    pub fn mint_jwt(&self, did: &str, role: Role) -> String {
+        self.sign(did, role, None, None)
+    }
+
+    /// Like [`mint_jwt`](Self::mint_jwt), for a practitioner or admin of `tenant_id`
+    pub fn mint_tenant_jwt(&self, did: &str, role: Role, tenant_id: &str) -> String {
+        self.sign(did, role, None, Some(tenant_id))
+    }
+
+    /// Like [`mint_jwt`](Self::mint_jwt), for a session stepped up with an authenticator app,
+    /// which the high-assurance routes require
+    pub fn mint_high_assurance_jwt(&self, did: &str, role: Role) -> String {
+        self.sign(did, role, Some(SecondFactor::Totp), None)
+    }
+
+    fn sign(&self, did: &str, role: Role, second_factor: Option<SecondFactor>, tenant_id: Option<&str>) -> String {
+        let claims = AuthClaims {
+            sub: did.to_string(),
+            exp: (Utc::now() + Duration::seconds(self.config.jwt_expiration_seconds)).timestamp() as usize,
+            role,
+            org_id: None,
+            second_factor,
+            sid: None,
+            tenant_id: tenant_id.map(str::to_string),
+            locale: None,
+        };
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(self.config.jwt_secret.expose_secret().as_bytes()))
+            .expect("failed to sign test JWT")
+    }
+
+    /// Register `did` as a practitioner through the API, optionally asking to join an organization
+    pub async fn register_practitioner(&self, did: &str, organization_identifier: Option<&str>) -> Value {
+        self.client
+            .post(self.url("/api/practitioners"))
+            .bearer_auth(self.mint_jwt(did, Role::Practitioner))
+            .json(&fixtures::practitioner_registration(did, vec![], "KMPDC-1", organization_identifier))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    /// Store a patient under a DID the test picked, as registration would with a generated one
+    pub async fn create_patient(&self, did: &str) {
+        let fhir_patient = FhirPatient { resource_type: "Patient".to_string(), id: did.to_string(), ..Default::default() };
+        self.database.create_patient(&fixtures::patient(did, fhir_patient), &self.config.ipfs_encryption_key).await.unwrap();
+    }
+
+    /// Read back an object the app stored on the stub IPFS node
+    pub async fn fetch_from_ipfs(&self, hash: &str) -> Vec<u8> {
+        IpfsClient::new(&self.ipfs.uri())
+            .get_file(hash)
+            .await
+            .expect("object missing from stub IPFS")
+    }
+
+    /// A worker for outbox jobs, as `main` registers it, with the fake ledger as the mirror node
+    pub fn outbox_worker(&self) -> JobWorkerPool {
+        let ipfs = Arc::new(IpfsClient::new(&self.ipfs.uri()));
+        let dispatcher = OutboxDispatcher::new(self.database.clone(), self.database.clone(), ipfs, self.ledger.clone())
+            .with_call_lookups(self.ledger.clone(), self.ledger.clone());
+        JobWorkerPool::new(self.database.clone(), JobConfig::default()).register(Arc::new(dispatcher))
+    }
+
+    /// Run the outbox jobs that are due now, returning how many ran
+    pub async fn dispatch_outbox(&self) -> usize {
+        let worker = self.outbox_worker();
+        let mut ran = 0;
+        while worker.run_next("outbox/0", Utc::now()).await.unwrap() {
+            ran += 1;
+        }
+        ran
+    }
+
+    /// Drop the test database; only matters when reusing a shared server via `TEST_DATABASE_URL`
+    pub async fn cleanup(self) {
+        self.database.db.drop(None).await.expect("failed to drop test database");
+    }
+}
+
+pub async fn spawn_test_app() -> TestApp {
+    spawn_test_app_with(|_| {}).await
+}
+
+/// Like [`spawn_test_app`], letting the test adjust the configuration first
+pub async fn spawn_test_app_with(configure: impl FnOnce(&mut Config)) -> TestApp {
+    let (database_url, mongo) = start_mongo().await;
+    let ipfs = ipfs_stub::start().await;
+    let fcm = fcm_stub::start().await;
+
+    let mut config = Config {
+        database_url: database_url.clone().into(),
+        hedera_network: "testnet".to_string(),
+        ipfs_url: ipfs.uri(),
+        jwt_secret: "integration-test-secret".into(),
+        jwt_expiration_seconds: 3600,
+        ipfs_encryption_key: "00".repeat(32).into(),
+        frontend_base_url: "http://localhost:3000".to_string(),
+        backend_base_url: "http://localhost:8000".to_string(),
+        soft_delete_grace_days: 30,
+        appointment_slot_minutes: 30,
+        referral_access_days: 30,
+        step_up_minutes: 15,
+        credential_batch_max_size: 500,
+        patient_search: PatientSearchConfig { key: Some("patient-search-test-key-0123456789".into()), max_results: 20 },
+        credential_signing: Some(CredentialSigningConfig {
+            issuer_did: "did:hedera:testnet:0.0.5".to_string(),
+            key: "07".repeat(32).into(),
+            key_id: "key-1".to_string(),
+        }),
+        dynamic: DynamicConfig { prescription_verify_per_minute: 1000, ..Default::default() }.into(),
+        ..Default::default()
+    };
+    configure(&mut config);
+    let config = Arc::new(config);
+
+    let db_name = format!("healthcare_test_{}", bson::oid::ObjectId::new());
+    let commands = Arc::new(CommandCounter::default());
+    let database = Arc::new(
+        Database::new_observed(&database_url, &db_name, commands.clone())
+            .await
+            .expect("failed to connect to test MongoDB")
+            .with_patient_search_key(config.patient_search.key.clone()),
+    );
+    // Built before the tests run, which rely on the unique indexes, rather than in the background
+    if config.skip_index_creation {
+        database.skip_index_creation();
+    } else {
+        database.create_indexes().await;
+    }
+
+    let did_registry = Arc::new(InMemoryDidRegistry::new());
+    let ledger = Arc::new(RecordingLedgerAnchor::new());
+    let phone_verifier = Arc::new(FakePhoneVerifier::new(TEST_PHONE_CODE));
+
+    // IPFS is left to the builder default: config.ipfs_url already points at the stub
+    let app_state = AppStateBuilder::new(config.clone())
+        .with_database(database.clone())
+        .with_ledger(ledger.clone())
+        .with_did_registry(did_registry.clone())
+        .with_phone_verifier(phone_verifier.clone())
+        .with_fcm(Arc::new(fcm_stub::client(&fcm)))
+        .build()
+        .await
+        .expect("failed to build test app state");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        crate::server::serve(Arc::new(app_state), listener, CancellationToken::new()).await.unwrap();
+    });
+
+    TestApp {
+        address,
+        client: reqwest::Client::new(),
+        config,
+        database,
+        did_registry,
+        ledger,
+        phone_verifier,
+        ipfs,
+        fcm,
+        commands,
+        _mongo: mongo,
+    }
+}
+
+/// Counts the commands the app sends MongoDB by name and collection, so tests can assert how
+/// many queries a request costs. They are still traced as they are outside tests.
+#[derive(Default)]
+pub struct CommandCounter {
+    spans: MongoCommandSpans,
+    started: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl CommandCounter {
+    /// How many `command_name` commands on `collection` were sent so far, e.g. `("find", "patients")`
+    pub fn count(&self, command_name: &str, collection: &str) -> usize {
+        let started = self.started.lock().unwrap();
+        started.get(&(command_name.to_string(), collection.to_string())).copied().unwrap_or(0)
+    }
+}
+
+impl CommandEventHandler for CommandCounter {
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        let collection = event.command.get_str(&event.command_name).unwrap_or_default().to_string();
+        *self.started.lock().unwrap().entry((event.command_name.clone(), collection)).or_default() += 1;
+        self.spans.handle_command_started_event(event);
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.spans.handle_command_succeeded_event(event);
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        self.spans.handle_command_failed_event(event);
+    }
+}
+
+async fn start_mongo() -> (String, Option<ContainerAsync<Mongo>>) {
+    if let Ok(uri) = std::env::var("TEST_DATABASE_URL") {
+        return (uri, None);
+    }
+    let container = Mongo::repl_set()
+        .start()
+        .await
+        .expect("failed to start MongoDB container (is Docker running?)");
+    let port = container.get_host_port_ipv4(27017).await.unwrap();
+    // The member advertises its container hostname; connect to it directly instead
+    (format!("mongodb://127.0.0.1:{}/?directConnection=true", port), Some(container))
+}
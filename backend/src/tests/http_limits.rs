@@ -0,0 +1,162 @@
+use bson::oid::ObjectId;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::models::{EncounterStatus, Role};
+use crate::tests::helpers::{spawn_test_app_with, TestApp};
+
+#[tokio::test]
+async fn oversized_body_is_rejected_with_413() {
+    let app = spawn_test_app_with(|config| config.http.max_body_bytes = 1024).await;
+    let token = app.mint_jwt("did:hedera:testnet:0.0.9001", Role::Practitioner);
+    app.register_practitioner("did:hedera:testnet:0.0.9001", None).await;
+
+    let response = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(token)
+        .json(&json!({ "padding": "x".repeat(4096) }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn slow_request_body_times_out_with_408() {
+    let app = spawn_test_app_with(|config| config.http.body_timeout_seconds = 1).await;
+
+    // Promise 100 bytes, send 2, then stall
+    let mut stream = TcpStream::connect(app.address).await.unwrap();
+    stream
+        .write_all(b"POST /api/auth/google HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 100\r\n\r\n{\"")
+        .await
+        .unwrap();
+    let mut response = vec![0u8; 1024];
+    let read = tokio::time::timeout(Duration::from_secs(10), stream.read(&mut response))
+        .await
+        .expect("server never answered the stalled request")
+        .unwrap();
+
+    assert!(String::from_utf8_lossy(&response[..read]).starts_with("HTTP/1.1 408"));
+
+    app.cleanup().await;
+}
+
+/// An encounter ready to finalize, with the stub IPFS node taking `delay` to store its bundle
+async fn encounter_with_slow_ipfs(app: &TestApp, delay: Duration) -> (String, String) {
+    Mock::given(method("POST"))
+        .and(path("/api/v0/add"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "name": "bundle", "hash": "QmSlowBundle", "size": "1" })).set_delay(delay))
+        .with_priority(1)
+        .mount(&app.ipfs)
+        .await;
+    // A step-up token, as finalizing needs one
+    let token = app.mint_high_assurance_jwt("did:hedera:testnet:0.0.9001", Role::Practitioner);
+    app.register_practitioner("did:hedera:testnet:0.0.9001", None).await;
+    let registered: Value = app
+        .client
+        .post(app.url("/api/auth/register"))
+        .json(&json!({ "name": "Slow Path", "email": "slow@example.com", "public_key_hex": "00".repeat(32) }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let encounter: Value = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&token)
+        .json(&json!({
+            "patient_did": registered["data"]["user"]["did"],
+            "practitioner_did": "did:hedera:testnet:0.0.9001",
+            "class": { "system": null, "code": "AMB", "display": null },
+            "reason_code": [],
+            "period": { "start": null, "end": null }
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    (token, encounter["data"]["_id"]["$oid"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn slow_upstream_times_out_with_504_and_stops_working() {
+    let app = spawn_test_app_with(|config| {
+        config.http.request_timeout_seconds = 1;
+        config.http.upstream_timeout_seconds = 1;
+    })
+    .await;
+    let (token, encounter_id) = encounter_with_slow_ipfs(&app, Duration::from_secs(3)).await;
+
+    let response = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "timeout");
+    // Had finalizing carried on, it would have recorded the bundle once IPFS answered
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let encounter = app.database.get_encounter(ObjectId::parse_str(&encounter_id).unwrap()).await.unwrap().unwrap();
+    assert_eq!((encounter.status, encounter.final_bundle_ipfs_hash), (EncounterStatus::InProgress, None));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn upstream_routes_get_longer_than_the_default_budget() {
+    let app = spawn_test_app_with(|config| {
+        config.http.request_timeout_seconds = 1;
+        config.http.upstream_timeout_seconds = 10;
+    })
+    .await;
+    let (token, encounter_id) = encounter_with_slow_ipfs(&app, Duration::from_secs(2)).await;
+
+    let response = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["data"], "QmSlowBundle", "{}", body);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn large_responses_are_compressed() {
+    let app = spawn_test_app_with(|config| config.http.compression_min_bytes = 16).await;
+
+    let response = app
+        .client
+        .post(app.url("/api/auth/google"))
+        .header("Accept-Encoding", "gzip")
+        .json(&json!({ "id_token": "dummy_token" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers().get("content-encoding").unwrap(), "gzip");
+
+    app.cleanup().await;
+}
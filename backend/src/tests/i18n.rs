@@ -0,0 +1,118 @@
+use serde_json::{json, Value};
+
+use crate::config::SmtpConfig;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+async fn register(app: &TestApp, body: Value, accept_language: Option<&str>) -> reqwest::Response {
+    let mut request = app.client.post(app.url("/api/auth/register")).json(&body);
+    if let Some(accept_language) = accept_language {
+        request = request.header("Accept-Language", accept_language);
+    }
+    request.send().await.unwrap()
+}
+
+async fn queued_emails(app: &TestApp) -> Vec<OutboxEmail> {
+    let (jobs, _) = app.database.list_jobs(None, Some(SEND_EMAIL_JOB), 0, 100).await.unwrap();
+    jobs.into_iter().map(|job| serde_json::from_value(job.payload).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn registering_in_swahili_saves_the_language_and_queues_swahili_emails() {
+    // Emails are only queued with SMTP configured; no worker runs to deliver them
+    let app = spawn_test_app_with(|config| {
+        config.smtp = Some(SmtpConfig {
+            server: "smtp.invalid".to_string(),
+            port: 587,
+            username: "wecare".to_string(),
+            password: "secret".into(),
+            from_email: "noreply@example.com".to_string(),
+        })
+    })
+    .await;
+    let body = json!({ "name": "Amina Njeri", "email": "amina@example.com", "public_key_hex": "00".repeat(32) });
+
+    let registered: Value = register(&app, body.clone(), Some("sw-KE,sw;q=0.9,en;q=0.8")).await.json().await.unwrap();
+    let did = registered["data"]["user"]["did"].as_str().unwrap();
+
+    let preferences = app.database.get_notification_preferences(did).await.unwrap().unwrap();
+    assert_eq!(preferences.locale, Locale::Sw);
+    let emails = queued_emails(&app).await;
+    let verification = emails.iter().find(|email| email.template == "sw/Verification-email.html").unwrap();
+    assert_eq!(verification.subject, "Uthibitisho wa barua pepe");
+    assert!(emails.iter().all(|email| email.template.starts_with("sw/")));
+
+    let duplicate = register(&app, body, Some("sw")).await;
+    assert_eq!(duplicate.status(), reqwest::StatusCode::CONFLICT);
+    let duplicate: Value = duplicate.json().await.unwrap();
+    assert_eq!(duplicate["error"], "Akaunti yenye barua pepe hii tayari ipo. Tafadhali ingia.");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn the_registration_body_overrides_the_header_and_preferences_change_the_language() {
+    let app = spawn_test_app().await;
+    let body = json!({ "name": "John Doe", "email": "john@example.com", "public_key_hex": "00".repeat(32), "locale": "sw" });
+
+    let registered: Value = register(&app, body, Some("en")).await.json().await.unwrap();
+    let did = registered["data"]["user"]["did"].as_str().unwrap();
+    let token = registered["data"]["token"].as_str().unwrap();
+
+    let url = app.url(&format!("/api/patients/{}/preferences", did));
+    let mut preferences: Value = app.client.get(&url).bearer_auth(token).send().await.unwrap().json().await.unwrap();
+    assert_eq!(preferences["data"]["locale"], "sw");
+
+    preferences["data"]["locale"] = json!("en");
+    let updated = app.client.put(&url).bearer_auth(token).json(&preferences["data"]).send().await.unwrap();
+    assert_eq!(updated.status(), reqwest::StatusCode::OK);
+    assert_eq!(app.database.get_notification_preferences(did).await.unwrap().unwrap().locale, Locale::En);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn invalid_bodies_are_reported_in_the_callers_language() {
+    let app = spawn_test_app().await;
+    let body = json!({ "name": "Amina Njeri", "email": "amina@example.com", "public_key_hex": "00".repeat(32), "locale": "sw" });
+    let registered: Value = register(&app, body, None).await.json().await.unwrap();
+    let did = registered["data"]["user"]["did"].as_str().unwrap();
+    let token = registered["data"]["token"].as_str().unwrap();
+
+    // No Accept-Language: the token carries the language saved at registration
+    let rejected = app
+        .client
+        .put(app.url(&format!("/api/patients/{}/preferences", did)))
+        .bearer_auth(token)
+        .json(&json!({ "locale": "fr" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let rejected: Value = rejected.json().await.unwrap();
+    assert_eq!(rejected["code"], "invalid_body");
+    assert!(rejected["error"].as_str().unwrap().starts_with("Maudhui ya ombi si sahihi: "));
+
+    // Unauthenticated requests follow the header, and English is the default
+    let english: Value = register(&app, json!({ "name": "No Email" }), None).await.json().await.unwrap();
+    assert!(english["error"].as_str().unwrap().starts_with("The request body is not valid: "));
+    let swahili: Value = register(&app, json!({ "name": "No Email" }), Some("sw")).await.json().await.unwrap();
+    assert!(swahili["error"].as_str().unwrap().starts_with("Maudhui ya ombi si sahihi: "));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn missing_translations_are_reported_to_platform_admins() {
+    let app = spawn_test_app().await;
+
+    let missing = |token: String| app.client.get(app.url("/api/admin/i18n/missing")).bearer_auth(token).send();
+    let admin = missing(app.mint_jwt("did:hedera:testnet:0.0.9501", Role::Admin)).await.unwrap();
+    assert_eq!(admin.status(), reqwest::StatusCode::FORBIDDEN);
+    let platform_admin = missing(app.mint_jwt("did:hedera:testnet:0.0.9502", Role::PlatformAdmin)).await.unwrap();
+    assert_eq!(platform_admin.status(), reqwest::StatusCode::OK);
+    let body: Value = platform_admin.json().await.unwrap();
+    assert!(body["data"].is_array());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,153 @@
+use bson::{doc, Document};
+use chrono::{Duration, Utc};
+use futures_util::future::join_all;
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9101";
+
+async fn register_patient(app: &TestApp, email: &str) -> String {
+    let body: Value = app
+        .client
+        .post(app.url("/api/auth/register"))
+        .json(&json!({ "name": "Jane Doe", "email": email, "public_key_hex": "00".repeat(32) }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["success"], true, "registration failed: {}", body);
+    body["data"]["user"]["did"].as_str().unwrap().to_string()
+}
+
+fn encounter(patient_did: &str, class_code: &str) -> Value {
+    json!({
+        "patient_did": patient_did,
+        "practitioner_did": PRACTITIONER,
+        "class": { "system": null, "code": class_code, "display": null },
+        "reason_code": [],
+        "period": { "start": null, "end": null }
+    })
+}
+
+async fn create_encounter(app: &TestApp, key: &str, body: &Value) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(app.mint_jwt(PRACTITIONER, Role::Practitioner))
+        .header("Idempotency-Key", key)
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn encounter_count(app: &TestApp) -> u64 {
+    app.database.db.collection::<Document>("encounters").count_documents(doc! {}, None).await.unwrap()
+}
+
+#[tokio::test]
+async fn a_retried_request_is_answered_from_the_first_one() {
+    let app = spawn_test_app().await;
+    let patient_did = register_patient(&app, "idempotent@example.com").await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let body = encounter(&patient_did, "AMB");
+
+    let first = create_encounter(&app, "retry-1", &body).await;
+    assert!(first.headers().get("idempotent-replayed").is_none());
+    let first: Value = first.json().await.unwrap();
+    assert_eq!(first["success"], true, "{}", first);
+    let retried = create_encounter(&app, "retry-1", &body).await;
+    assert_eq!(retried.headers()["idempotent-replayed"], "true");
+    let retried: Value = retried.json().await.unwrap();
+
+    assert_eq!(retried, first);
+    assert_eq!(encounter_count(&app).await, 1);
+    // A new key is a new request
+    let other: Value = create_encounter(&app, "retry-2", &body).await.json().await.unwrap();
+    assert_ne!(other["data"]["_id"], first["data"]["_id"]);
+    assert_eq!(encounter_count(&app).await, 2);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_key_reused_with_a_different_body_is_refused() {
+    let app = spawn_test_app().await;
+    let patient_did = register_patient(&app, "reused@example.com").await;
+    app.register_practitioner(PRACTITIONER, None).await;
+
+    create_encounter(&app, "reused", &encounter(&patient_did, "AMB")).await;
+    let response = create_encounter(&app, "reused", &encounter(&patient_did, "EMER")).await;
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "idempotency_key_reused", "{}", body);
+    assert_eq!(encounter_count(&app).await, 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn concurrent_requests_with_the_same_key_run_once() {
+    let app = spawn_test_app().await;
+    let patient_did = register_patient(&app, "concurrent@example.com").await;
+    app.register_practitioner(PRACTITIONER, None).await;
+    let body = encounter(&patient_did, "AMB");
+
+    let responses = join_all((0..5).map(|_| create_encounter(&app, "concurrent", &body))).await;
+
+    let mut created = Vec::new();
+    for response in responses {
+        match response.status() {
+            reqwest::StatusCode::CONFLICT => {}
+            reqwest::StatusCode::OK => {
+                let body: Value = response.json().await.unwrap();
+                assert_eq!(body["success"], true, "{}", body);
+                created.push(body["data"]["_id"].clone());
+            }
+            other => panic!("unexpected status {}", other),
+        }
+    }
+    assert!(!created.is_empty());
+    assert!(created.iter().all(|id| *id == created[0]), "{:?}", created);
+    assert_eq!(encounter_count(&app).await, 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn one_of_two_racing_claims_wins_and_an_abandoned_claim_is_taken_over() {
+    let app = spawn_test_app().await;
+    let now = Utc::now();
+    let record = |hash: &str| IdempotencyRecord {
+        id: Some(bson::oid::ObjectId::new()),
+        user_did: PRACTITIONER.to_string(),
+        key: "race".to_string(),
+        request_hash: hash.to_string(),
+        status: IdempotencyStatus::InProgress,
+        response_status: None,
+        response_content_type: None,
+        response_body: None,
+        locked_until: now + Duration::seconds(90),
+        expires_at: now + Duration::hours(24),
+        created_at: now,
+    };
+    let (first, second) = (record("first"), record("second"));
+
+    let (a, b) = tokio::join!(
+        app.database.claim_idempotency_key(&first, now),
+        app.database.claim_idempotency_key(&second, now)
+    );
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert_eq!(a.is_none() as u8 + b.is_none() as u8, 1, "exactly one claim wins");
+
+    // Once the lock lapses without the request finishing, a retry takes the key over
+    let later = now + Duration::seconds(91);
+    let retry = IdempotencyRecord { locked_until: later + Duration::seconds(90), ..record("retry") };
+    assert!(app.database.claim_idempotency_key(&retry, later).await.unwrap().is_none());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,128 @@
+//! Wiremock stand-in for the IPFS HTTP API endpoints `IpfsClient` calls.
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+pub async fn start() -> MockServer {
+    let server = MockServer::start().await;
+    let objects = Objects::default();
+
+    Mock::given(method("POST"))
+        .and(path("/api/v0/add"))
+        .respond_with(Add(objects.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/api/v0/cat/[^/]+$"))
+        .respond_with(Cat(objects.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/api/v0/pin/add/[^/]+$"))
+        .respond_with(PinAdd(objects.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/api/v0/pin/rm/[^/]+$"))
+        .respond_with(PinAdd(objects.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/api/v0/pin/ls/[^/]+$"))
+        .respond_with(PinLs(objects))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v0/version"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "Version": "stub" })))
+        .mount(&server)
+        .await;
+
+    server
+}
+
+struct Add(Objects);
+
+impl Respond for Add {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let Some(content) = first_multipart_part(request) else {
+            return ResponseTemplate::new(400);
+        };
+        let hash = format!("stub-{:x}", Sha256::digest(&content));
+        let size = content.len().to_string();
+        self.0.lock().unwrap().insert(hash.clone(), content);
+        ResponseTemplate::new(200).set_body_json(json!({ "name": hash, "hash": hash, "size": size }))
+    }
+}
+
+/// Honours the `offset` and `length` parameters `IpfsClient::get_range` sends
+struct Cat(Objects);
+
+impl Respond for Cat {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let Some(content) = self.0.lock().unwrap().get(last_segment(request)).cloned() else {
+            return ResponseTemplate::new(404);
+        };
+        let param = |name: &str| request.url.query_pairs().find(|(key, _)| key == name).and_then(|(_, value)| value.parse::<usize>().ok());
+        let start = param("offset").unwrap_or(0).min(content.len());
+        let end = param("length").map_or(content.len(), |length| (start + length).min(content.len()));
+        ResponseTemplate::new(200).set_body_bytes(content[start..end].to_vec())
+    }
+}
+
+/// Answers `pin/add` and `pin/rm` alike; the stub does not track pins
+struct PinAdd(Objects);
+
+impl Respond for PinAdd {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let hash = last_segment(request);
+        if !self.0.lock().unwrap().contains_key(hash) {
+            return ResponseTemplate::new(404);
+        }
+        ResponseTemplate::new(200).set_body_json(json!({ "pins": [hash], "progress": null }))
+    }
+}
+
+/// Everything stored counts as pinned, as `ipfs add` pins by default
+struct PinLs(Objects);
+
+impl Respond for PinLs {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let hash = last_segment(request);
+        if !self.0.lock().unwrap().contains_key(hash) {
+            return ResponseTemplate::new(500).set_body_json(json!({ "Message": format!("path '{}' is not pinned", hash), "Code": 0 }));
+        }
+        ResponseTemplate::new(200).set_body_json(json!({ "Keys": { hash: { "Type": "recursive" } } }))
+    }
+}
+
+fn last_segment(request: &Request) -> &str {
+    request.url.path().rsplit('/').next().unwrap_or_default()
+}
+
+/// Body of the first part of a `multipart/form-data` request, which is where
+/// `IpfsClient::add_file` puts the file
+fn first_multipart_part(request: &Request) -> Option<Vec<u8>> {
+    let content_type = request.headers.get("content-type")?.to_str().ok()?;
+    let boundary = content_type.split("boundary=").nth(1)?.trim_matches('"');
+    let delimiter = format!("\r\n--{}", boundary);
+
+    let body = &request.body;
+    let headers_end = find(body, b"\r\n\r\n", 0)? + 4;
+    let content_end = find(body, delimiter.as_bytes(), headers_end)?;
+    Some(body[headers_end..content_end].to_vec())
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::config::JobConfig;
+use crate::jobs::{JobError, JobHandler, JobWorkerPool};
+use crate::models::{Job, JobStatus, Role};
+use crate::tests::helpers::spawn_test_app;
+
+const ADMIN: &str = "did:hedera:testnet:0.0.8601";
+
+/// Records every job it runs, yielding in between so the two workers interleave
+#[derive(Default)]
+struct Recorder {
+    ran: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl JobHandler for Recorder {
+    fn job_type(&self) -> &'static str {
+        "record"
+    }
+
+    async fn run(&self, job: &Job) -> Result<(), JobError> {
+        tokio::task::yield_now().await;
+        self.ran.lock().unwrap().push(job.payload["n"].to_string());
+        Ok(())
+    }
+}
+
+async fn drain(pool: &JobWorkerPool, worker: &str) {
+    while pool.run_next(worker, Utc::now()).await.unwrap() {}
+}
+
+#[tokio::test]
+async fn two_workers_racing_for_the_same_jobs_run_each_once() {
+    let app = spawn_test_app().await;
+    for n in 0..20 {
+        app.database.enqueue_job(&Job::new("record", json!({ "n": n }), Utc::now())).await.unwrap();
+    }
+    let recorder = Arc::new(Recorder::default());
+    let first = JobWorkerPool::new(app.database.clone(), JobConfig::default()).register(recorder.clone());
+    let second = JobWorkerPool::new(app.database.clone(), JobConfig::default()).register(recorder.clone());
+
+    tokio::join!(drain(&first, "first/0"), drain(&second, "second/0"));
+
+    let ran = recorder.ran.lock().unwrap().clone();
+    assert_eq!(ran.len(), 20, "every job ran exactly once: {:?}", ran);
+    assert_eq!(ran.iter().collect::<HashSet<_>>().len(), 20);
+    let counts = app.database.count_jobs(Some("record")).await.unwrap();
+    assert_eq!((counts.succeeded, counts.pending, counts.running), (20, 0, 0));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_lapsed_lock_is_taken_over_and_the_stale_worker_cannot_finish_the_job() {
+    let app = spawn_test_app().await;
+    let id = app.database.enqueue_job(&Job::new("record", json!({}), Utc::now())).await.unwrap();
+    let types = vec!["record".to_string()];
+    let now = Utc::now();
+
+    let claimed = app.database.claim_job(&types, "first/0", now, Duration::seconds(30)).await.unwrap().unwrap();
+    assert_eq!((claimed.status, claimed.attempts), (JobStatus::Running, 1));
+    assert!(app.database.claim_job(&types, "second/0", now, Duration::seconds(30)).await.unwrap().is_none(), "the lock is still held");
+
+    let later = now + Duration::seconds(31);
+    let taken_over = app.database.claim_job(&types, "second/0", later, Duration::seconds(30)).await.unwrap().unwrap();
+    assert_eq!((taken_over.locked_by.as_deref(), taken_over.attempts), (Some("second/0"), 2));
+    assert!(!app.database.complete_job(id, "first/0").await.unwrap());
+    assert!(app.database.complete_job(id, "second/0").await.unwrap());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn admins_see_dead_jobs_and_can_retry_them() {
+    let app = spawn_test_app().await;
+    let id = app.database.enqueue_job(&Job::new("record", json!({}), Utc::now())).await.unwrap();
+    app.database.claim_job(&["record".to_string()], "first/0", Utc::now(), Duration::seconds(30)).await.unwrap().unwrap();
+    app.database.bury_job(id, "first/0", "gave up").await.unwrap();
+    let token = app.mint_jwt(ADMIN, Role::PlatformAdmin);
+
+    let dead: Value = app.client.get(app.url("/api/admin/jobs?status=dead")).bearer_auth(&token).send().await.unwrap().json().await.unwrap();
+    assert_eq!(dead["data"]["total"], 1, "{}", dead);
+    assert_eq!(dead["data"]["items"][0]["last_error"], "gave up");
+
+    let retried: Value = app
+        .client
+        .post(app.url(&format!("/api/admin/jobs/{}/retry", id.to_hex())))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(retried["success"], true, "{}", retried);
+    let pending: Value = app.client.get(app.url("/api/admin/jobs?status=pending")).bearer_auth(&token).send().await.unwrap().json().await.unwrap();
+    assert_eq!(pending["data"]["items"][0]["attempts"], 0);
+
+    app.cleanup().await;
+}
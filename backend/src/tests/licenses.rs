@@ -0,0 +1,84 @@
+use chrono::{Duration, Utc};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::fixtures;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const SOON: &str = "did:hedera:testnet:0.0.9951";
+const LATER: &str = "did:hedera:testnet:0.0.9952";
+const ADMIN: &str = "did:hedera:testnet:0.0.9953";
+
+/// Store a verified practitioner whose license expires `days` from today
+async fn licensed(app: &TestApp, did: &str, days: i64) {
+    let registration = fixtures::practitioner_registration(did, vec![], "KMPDC-1", None);
+    let practitioner = Practitioner {
+        id: None,
+        did: did.to_string(),
+        fhir_practitioner: registration.fhir_practitioner,
+        license_verification: LicenseVerification {
+            expiry_date: (Utc::now() + Duration::days(days)).date_naive().to_string(),
+            verified: true,
+            ..registration.license_verification
+        },
+        practitioner_type: PractitionerType::Clinician,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        tenant_id: None,
+    };
+    app.database.create_practitioner(&practitioner).await.unwrap();
+}
+
+#[tokio::test]
+async fn registration_refuses_an_unreadable_or_past_expiry_date() {
+    let app = spawn_test_app().await;
+
+    for expiry_date in ["01/01/2030", "2001-01-01"] {
+        let mut registration = fixtures::practitioner_registration(SOON, vec![], "KMPDC-1", None);
+        registration.license_verification.expiry_date = expiry_date.to_string();
+        let response = app
+            .client
+            .post(app.url("/api/practitioners"))
+            .bearer_auth(app.mint_jwt(SOON, Role::Practitioner))
+            .json(&registration)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", expiry_date);
+        assert_eq!(response.json::<Value>().await.unwrap()["code"], "invalid_license");
+    }
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn admins_see_the_licenses_expiring_within_the_window() {
+    let app = spawn_test_app().await;
+    licensed(&app, SOON, 10).await;
+    licensed(&app, LATER, 200).await;
+
+    let listed = |days: i64| {
+        app.client
+            .get(app.url(&format!("/api/admin/practitioners/expiring?days={}", days)))
+            .bearer_auth(app.mint_jwt(ADMIN, Role::Admin))
+            .send()
+    };
+    let soon: Value = listed(30).await.unwrap().json().await.unwrap();
+    let soon = soon["data"].as_array().unwrap();
+    assert_eq!(soon.len(), 1, "{:?}", soon);
+    assert_eq!((soon[0]["practitioner_did"].as_str(), soon[0]["days_remaining"].as_i64()), (Some(SOON), Some(10)));
+    let year: Value = listed(365).await.unwrap().json().await.unwrap();
+    assert_eq!(year["data"].as_array().unwrap().len(), 2);
+
+    let practitioner = app
+        .client
+        .get(app.url("/api/admin/practitioners/expiring"))
+        .bearer_auth(app.mint_jwt(SOON, Role::Practitioner))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(practitioner.status(), StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,77 @@
+use bson::{doc, Bson, Document};
+use chrono::{Duration, SubsecRound, Utc};
+
+use crate::fixtures;
+use crate::migrations::{self, run_migrations, Migration, OtpExpiryDates, PatientDataKeys};
+use crate::models::*;
+use crate::tests::helpers::spawn_test_app;
+use crate::utils;
+
+#[tokio::test]
+async fn migration_chain_is_idempotent() {
+    let app = spawn_test_app().await;
+    let chain = migrations::all(&app.config);
+
+    let first = run_migrations(&app.database, &chain).await.unwrap();
+    let second = run_migrations(&app.database, &chain).await.unwrap();
+
+    assert_eq!(
+        first,
+        vec![
+            "0001_normalize_email_hashes",
+            "0002_backfill_phone_hashes",
+            "0003_encounter_status_codes",
+            "0004_email_outbox_jobs",
+            "0005_default_tenant",
+            "0006_fhir_date_report",
+            "0007_otp_expiry_dates",
+            "0008_patient_data_keys",
+        ]
+    );
+    assert!(second.is_empty());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn otps_stored_with_a_string_expiry_are_converted_to_dates() {
+    let app = spawn_test_app().await;
+    // As the code was stored before its expiry became a BSON date
+    let expires_at = Utc::now().trunc_subsecs(3) + Duration::minutes(5);
+    let otps = app.database.db.collection::<Document>("otps");
+    let legacy = doc! { "phone_number": "+254700000009", "otp": "123456", "created_at": bson::to_bson(&Utc::now()).unwrap(), "expires_at": bson::to_bson(&expires_at).unwrap() };
+    otps.insert_one(legacy, None).await.unwrap();
+
+    OtpExpiryDates.up(&app.database).await.unwrap();
+
+    let stored = otps.find_one(doc! { "phone_number": "+254700000009" }, None).await.unwrap().unwrap();
+    assert_eq!(stored.get("expires_at"), Some(&Bson::DateTime(expires_at.into())));
+    let otp = app.database.get_otp("+254700000009", "123456").await.unwrap().unwrap();
+    assert_eq!(otp.expires_at, expires_at);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn patients_from_before_data_keys_get_their_own() {
+    let app = spawn_test_app().await;
+    let did = "did:hedera:testnet:0.0.9971";
+    let key = &app.config.ipfs_encryption_key;
+    let fhir_patient = FhirPatient { birth_date: "1990-01-01".to_string(), ..Default::default() };
+    app.database.create_patient(&fixtures::patient(did, fhir_patient.clone()), key).await.unwrap();
+    // As the record was stored before data keys: encrypted with the master key
+    let patients = app.database.db.collection::<Document>("patients");
+    let legacy = utils::encrypt(serde_json::to_string(&fhir_patient).unwrap().as_bytes(), key).unwrap();
+    let update = doc! { "$set": { "encrypted_fhir_patient": &legacy }, "$unset": { "wrapped_data_key": "" } };
+    patients.update_one(doc! { "did": did }, update, None).await.unwrap();
+
+    PatientDataKeys::new(key).up(&app.database).await.unwrap();
+
+    let stored = patients.find_one(doc! { "did": did }, None).await.unwrap().unwrap();
+    assert!(stored.get_str("wrapped_data_key").is_ok());
+    assert!(utils::decrypt(stored.get_str("encrypted_fhir_patient").unwrap(), key).is_err());
+    let patient = app.database.get_patient_by_did(did, key).await.unwrap().unwrap();
+    assert_eq!(patient.fhir_patient.birth_date, "1990-01-01");
+
+    app.cleanup().await;
+}
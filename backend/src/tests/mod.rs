@@ -1,2 +1,53 @@
-pub mod auth_handlers;
+//! End-to-end tests that drive the HTTP API. Run with `cargo test --features integration`.
+
+mod appointments;
+mod attachments;
+mod audit;
+mod auth_handlers;
+mod backup;
+mod break_glass;
+mod caller_identity;
+mod chat;
+mod compliance_reports;
+mod cli;
+mod config_reload;
+mod consents;
+mod credentials;
+mod current_user;
+mod data_access;
+mod encounter_flow;
+mod fcm_stub;
+mod fhir_dates;
+mod fhir_format;
+mod fhir_metadata;
+mod files;
 pub mod helpers;
+mod http_limits;
+mod i18n;
+mod idempotency;
+mod jobs;
+mod licenses;
+mod ipfs_stub;
+mod migrations;
+mod notifications;
+mod observations;
+mod organizations;
+mod outbox;
+mod patient_exports;
+mod patient_merge;
+mod patient_photo;
+mod patient_search;
+mod policy;
+mod prescriptions;
+mod problems;
+mod push;
+mod reencryption;
+mod referrals;
+mod relationships;
+mod request_log;
+mod soft_delete;
+mod tenancy;
+mod terminology;
+#[cfg(feature = "otel")]
+mod tracing_export;
+mod webhooks;
@@ -0,0 +1,169 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::timeout;
+
+use crate::models::Role;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9901";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9902";
+const SPECIALIST: &str = "did:hedera:testnet:0.0.9903";
+
+async fn grant(app: &TestApp, grantee_did: &str) -> String {
+    let granted: Value = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": grantee_did, "permissions": ["Read"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(granted["success"], true, "{}", granted);
+    granted["data"]["_id"]["$oid"].as_str().unwrap().to_string()
+}
+
+async fn open_stream(app: &TestApp, did: &str, last_event_id: Option<&str>) -> reqwest::Response {
+    let mut request = app.client.get(app.url("/api/notifications/stream")).bearer_auth(app.mint_jwt(did, Role::Patient));
+    if let Some(id) = last_event_id {
+        request = request.header("Last-Event-ID", id);
+    }
+    request.send().await.unwrap()
+}
+
+/// One server-sent event: its id, its type and its JSON data
+struct StreamEvent {
+    id: String,
+    event_type: String,
+    data: Value,
+}
+
+/// Reads events off an open stream, skipping heartbeats
+struct StreamReader {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl StreamReader {
+    fn new(response: reqwest::Response) -> Self {
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        Self { response, buffer: String::new() }
+    }
+
+    async fn next(&mut self) -> StreamEvent {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let frame: String = self.buffer.drain(..end + 2).collect();
+                let field = |name: &str| frame.lines().find_map(|line| line.strip_prefix(name)).map(|value| value.trim_start().to_string());
+                // Heartbeats are comments, without data
+                if let Some(data) = field("data:") {
+                    return StreamEvent {
+                        id: field("id:").unwrap_or_default(),
+                        event_type: field("event:").unwrap_or_default(),
+                        data: serde_json::from_str(&data).unwrap(),
+                    };
+                }
+                continue;
+            }
+            let chunk = timeout(Duration::from_secs(10), self.response.chunk()).await.expect("no event within 10s").unwrap();
+            self.buffer.push_str(std::str::from_utf8(&chunk.expect("stream ended")).unwrap());
+        }
+    }
+}
+
+async fn list(app: &TestApp, did: &str, since: Option<&str>) -> Vec<Value> {
+    let url = match since {
+        Some(since) => app.url(&format!("/api/notifications?since={}", since)),
+        None => app.url("/api/notifications"),
+    };
+    let listed: Value = app.client.get(url).bearer_auth(app.mint_jwt(did, Role::Patient)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(listed["success"], true, "{}", listed);
+    listed["data"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn a_stream_pushes_the_callers_events_and_replays_missed_ones_after_a_reconnect() {
+    let app = spawn_test_app().await;
+    let mut stream = StreamReader::new(open_stream(&app, PATIENT, None).await);
+
+    let first_grant = grant(&app, PRACTITIONER).await;
+    let first = stream.next().await;
+    assert_eq!(first.event_type, "access_granted");
+    assert_eq!((first.data["id"].as_str(), first.data["type"].as_str()), (Some(first.id.as_str()), Some("access_granted")));
+    assert_eq!(first.data["data"]["grant_id"], first_grant);
+
+    // The connection drops, and the patient grants access again while it is down
+    drop(stream);
+    let second_grant = grant(&app, SPECIALIST).await;
+    let mut stored = Vec::new();
+    for _ in 0..50 {
+        stored = list(&app, PATIENT, Some(&first.id)).await;
+        if !stored.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(stored.len(), 1, "the second grant was never saved on the feed");
+
+    let mut stream = StreamReader::new(open_stream(&app, PATIENT, Some(&first.id)).await);
+    let replayed = stream.next().await;
+    assert_eq!(replayed.data["data"]["grant_id"], second_grant);
+    assert_eq!(replayed.id, stored[0]["id"].as_str().unwrap());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn the_pull_fallback_lists_each_users_own_events_after_since() {
+    let app = spawn_test_app().await;
+    // Streams are opened first so the test knows when both events were saved
+    let mut patient_stream = StreamReader::new(open_stream(&app, PATIENT, None).await);
+    grant(&app, PRACTITIONER).await;
+    grant(&app, SPECIALIST).await;
+    patient_stream.next().await;
+    let last = patient_stream.next().await;
+
+    let events = list(&app, PATIENT, None).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1]["id"], last.id.as_str());
+    assert_eq!(list(&app, PATIENT, events[0]["id"].as_str()).await, vec![events[1].clone()]);
+    assert!(list(&app, PATIENT, Some(&last.id)).await.is_empty());
+
+    // The grantee hears about their own grant only
+    let practitioner_events = list(&app, PRACTITIONER, None).await;
+    assert_eq!(practitioner_events.len(), 1);
+    assert_eq!(practitioner_events[0]["data"]["grantee_did"], PRACTITIONER);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn streams_beyond_the_per_user_cap_are_refused_until_one_closes() {
+    let app = spawn_test_app_with(|config| {
+        config.notification_stream.max_streams_per_user = 1;
+        config.notification_stream.heartbeat_seconds = 1;
+    })
+    .await;
+
+    let first = open_stream(&app, PATIENT, None).await;
+    assert_eq!(first.status(), 200);
+    assert_eq!(open_stream(&app, PATIENT, None).await.status(), 429);
+    assert_eq!(open_stream(&app, PRACTITIONER, None).await.status(), 200, "the cap is per user");
+
+    // The server lets go of the stream once a heartbeat finds the connection closed
+    drop(first);
+    let mut status = 429;
+    for _ in 0..50 {
+        status = open_stream(&app, PATIENT, None).await.status().as_u16();
+        if status == 200 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, 200);
+
+    app.cleanup().await;
+}
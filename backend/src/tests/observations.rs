@@ -0,0 +1,89 @@
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8801";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.8802";
+const HEART_RATE: &str = "8867-4";
+
+fn observation(code: &str, effective_date_time: &str, value: Option<f64>, interpretation: Option<&str>) -> FhirObservation {
+    FhirObservation {
+        resource_type: "Observation".to_string(),
+        id: Uuid::new_v4().to_string(),
+        status: "final".to_string(),
+        category: vec![],
+        code: FhirCodeableConcept {
+            coding: vec![FhirCoding { system: Some("http://loinc.org".to_string()), code: Some(code.to_string()), display: None }],
+            text: None,
+        },
+        subject: FhirReference { reference: format!("Patient/{}", PATIENT), display: None },
+        encounter: None,
+        effective_date_time: effective_date_time.parse().unwrap(),
+        value_quantity: value.map(|value| FhirQuantity { value: Some(value), unit: Some("/min".to_string()), system: None, code: None }),
+        value_string: value.is_none().then(|| "Irregular".to_string()),
+        interpretation: interpretation
+            .map(|code| FhirCodeableConcept { coding: vec![], text: Some(code.to_string()) })
+            .into_iter()
+            .collect(),
+    }
+}
+
+async fn summary(app: &TestApp, did: &str, role: Role, query: &str) -> reqwest::Response {
+    app.client
+        .get(app.url(&format!("/api/patients/{}/observations/summary?{}", PATIENT, query)))
+        .bearer_auth(app.mint_jwt(did, role))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn observations_are_summarized_per_period_for_readers_of_the_record() {
+    let app = spawn_test_app().await;
+    for observation in [
+        observation(HEART_RATE, "2026-03-02T08:00:00+00:00", Some(60.0), None),
+        observation(HEART_RATE, "2026-03-02T20:00:00+00:00", Some(80.0), None),
+        // Still 3 March in UTC
+        observation(HEART_RATE, "2026-03-04T01:30:00+03:00", Some(100.0), Some("High")),
+        observation(HEART_RATE, "2026-03-05T09:00:00Z", None, None),
+        observation(HEART_RATE, "2026-04-01T09:00:00Z", Some(70.0), None),
+        observation("29463-7", "2026-03-02T08:00:00Z", Some(72.5), None),
+    ] {
+        app.database.create_observation(&observation).await.unwrap();
+    }
+
+    let query = format!("code={}&from=2026-03-01T00:00:00Z&to=2026-03-31T00:00:00Z", HEART_RATE);
+    let daily: Value = summary(&app, PATIENT, Role::Patient, &query).await.json().await.unwrap();
+    let buckets = daily["data"]["buckets"].as_array().unwrap();
+    assert_eq!(buckets.len(), 2);
+    assert_eq!(buckets[0]["count"], 2);
+    assert_eq!((buckets[0]["min"].as_f64(), buckets[0]["max"].as_f64(), buckets[0]["mean"].as_f64()), (Some(60.0), Some(80.0), Some(70.0)));
+    assert!(buckets[1]["start"].as_str().unwrap().starts_with("2026-03-03"));
+    assert_eq!(daily["data"]["latest"]["value"]["value"], 100.0);
+    assert_eq!(daily["data"]["latest"]["interpretation"][0]["text"], "High");
+    assert_eq!(daily["data"]["skipped"], 1);
+
+    let monthly: Value = summary(&app, PATIENT, Role::Patient, &format!("code={}&period=month", HEART_RATE)).await.json().await.unwrap();
+    let counts: Vec<u64> = monthly["data"]["buckets"].as_array().unwrap().iter().map(|bucket| bucket["count"].as_u64().unwrap()).collect();
+    assert_eq!(counts, [3, 1]);
+    assert_eq!(monthly["data"]["latest"]["effective_date_time"], "2026-04-01T09:00:00Z");
+
+    assert_eq!(summary(&app, PRACTITIONER, Role::Practitioner, &format!("code={}", HEART_RATE)).await.status(), reqwest::StatusCode::FORBIDDEN);
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["ViewObservations"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+    let weekly: Value = summary(&app, PRACTITIONER, Role::Practitioner, &format!("code={}&period=week", HEART_RATE)).await.json().await.unwrap();
+    // 2 March 2026 is a Monday, so March's readings share a week
+    assert!(weekly["data"]["buckets"][0]["start"].as_str().unwrap().starts_with("2026-03-02"));
+    assert_eq!(weekly["data"]["buckets"][0]["count"], 3);
+
+    app.cleanup().await;
+}
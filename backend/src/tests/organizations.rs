@@ -0,0 +1,95 @@
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const ADMIN: &str = "did:hedera:testnet:0.0.8501";
+const CLINIC_ADMIN: &str = "did:hedera:testnet:0.0.8502";
+const CLINICIAN: &str = "did:hedera:testnet:0.0.8503";
+const ELSEWHERE: &str = "did:hedera:testnet:0.0.8504";
+const PATIENT: &str = "did:hedera:testnet:0.0.8505";
+
+async fn book(app: &TestApp, practitioner_did: &str) {
+    let start = Utc::now() + Duration::days(1);
+    let appointment = Appointment {
+        id: None,
+        patient_did: PATIENT.to_string(),
+        practitioner_did: practitioner_did.to_string(),
+        status: AppointmentStatus::Requested,
+        start,
+        end: start + Duration::minutes(30),
+        reason: FhirCodeableConcept { coding: vec![], text: Some("Check-up".to_string()) },
+        location: "Room 1".to_string(),
+        encounter_id: None,
+        reminder_sent_at: ReminderMarkers::default(),
+        slots: vec![],
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        tenant_id: None,
+    };
+    app.database.create_appointment(&appointment).await.unwrap();
+}
+
+#[tokio::test]
+async fn clinic_admins_approve_affiliations_and_see_only_their_clinics_appointments() {
+    let app = spawn_test_app().await;
+    let admin = app.mint_jwt(ADMIN, Role::Admin);
+    let create = |identifier: &'static str| {
+        app.client
+            .post(app.url("/api/admin/organizations"))
+            .bearer_auth(&admin)
+            .json(&json!({ "name": "Riverside Clinic", "identifier": [{ "system": "https://mfl.health.go.ke", "value": identifier }] }))
+            .send()
+    };
+    let created: Value = create("MFL-10001").await.unwrap().json().await.unwrap();
+    assert_eq!(created["data"]["fhir_organization"]["resourceType"], "Organization");
+    assert_eq!(create("MFL-10001").await.unwrap().status(), reqwest::StatusCode::CONFLICT);
+    let organization_id = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+
+    // The clinician asks to join at registration; their license is left for verification
+    let registered = app.register_practitioner(CLINICIAN, Some("MFL-10001")).await;
+    assert_eq!(registered["data"]["practitioner"]["license_verification"]["verified"], false);
+    assert_eq!(registered["data"]["affiliation"]["status"], "pending");
+    let affiliation_id = registered["data"]["affiliation"]["_id"]["$oid"].as_str().unwrap().to_string();
+    app.register_practitioner(CLINIC_ADMIN, None).await;
+    app.register_practitioner(ELSEWHERE, None).await;
+
+    let appointed = app
+        .client
+        .post(app.url(&format!("/api/admin/organizations/{}/affiliations", organization_id)))
+        .bearer_auth(&admin)
+        .json(&json!({ "practitioner_did": CLINIC_ADMIN, "role": "admin" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(appointed.status(), reqwest::StatusCode::OK);
+
+    let approve = |did: &str| {
+        app.client
+            .post(app.url(&format!("/api/organizations/{}/affiliations/{}/approve", organization_id, affiliation_id)))
+            .bearer_auth(app.mint_jwt(did, Role::Practitioner))
+            .send()
+    };
+    assert_eq!(approve(ELSEWHERE).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+    let approved: Value = approve(CLINIC_ADMIN).await.unwrap().json().await.unwrap();
+    assert_eq!(approved["data"]["status"], "active");
+    assert!(approved["data"]["period_start"].is_string());
+    assert_eq!(approve(CLINIC_ADMIN).await.unwrap().status(), reqwest::StatusCode::CONFLICT);
+
+    book(&app, CLINICIAN).await;
+    book(&app, ELSEWHERE).await;
+    let clinic_appointments = |did: &str| {
+        app.client
+            .get(app.url(&format!("/api/appointments?organization_id={}", organization_id)))
+            .bearer_auth(app.mint_jwt(did, Role::Practitioner))
+            .send()
+    };
+    let listed: Value = clinic_appointments(CLINIC_ADMIN).await.unwrap().json().await.unwrap();
+    let listed = listed["data"].as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["practitioner_did"], CLINICIAN);
+    assert_eq!(clinic_appointments(ELSEWHERE).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
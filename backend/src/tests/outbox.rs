@@ -0,0 +1,117 @@
+use bson::oid::ObjectId;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::services::ipfs::IpfsClient;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9801";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9802";
+
+async fn grant(app: &TestApp) -> ObjectId {
+    let granted: Value = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["Read"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(granted["success"], true, "{}", granted);
+    ObjectId::parse_str(granted["data"]["_id"]["$oid"].as_str().unwrap()).unwrap()
+}
+
+fn credential(ipfs_hash: &str) -> VerifiableCredential {
+    VerifiableCredential {
+        id: Some(ObjectId::new()),
+        subject_did: PATIENT.to_string(),
+        credential_type: "VaccinationCredential".to_string(),
+        issuer: PRACTITIONER.to_string(),
+        issued_at: Utc::now(),
+        expires_at: None,
+        ipfs_hash: ipfs_hash.to_string(),
+        hedera_transaction_id: None,
+        metadata: "{}".to_string(),
+        revoked_at: None,
+        status_list: None,
+        batch: None,
+    }
+}
+
+fn store(credential: &VerifiableCredential) -> OutboxAction {
+    OutboxAction::StoreCredential {
+        credential_id: credential.id.unwrap(),
+        subject_did: credential.subject_did.clone(),
+        credential_type: credential.credential_type.clone(),
+        ipfs_hash: credential.ipfs_hash.clone(),
+        expires_at: None,
+        metadata: credential.metadata.clone(),
+    }
+}
+
+#[tokio::test]
+async fn a_grant_committed_before_a_crash_is_recorded_on_the_ledger_by_the_dispatcher() {
+    let app = spawn_test_app().await;
+
+    // The process stops right after the request: the grant and its outbox job are saved, nothing was sent
+    let grant_id = grant(&app).await;
+    assert!(app.ledger.grants().is_empty());
+    assert_eq!(app.database.count_jobs(Some(OUTBOX_JOB)).await.unwrap().pending, 1);
+    assert_eq!(app.database.get_grant(grant_id).await.unwrap().unwrap().hedera_transaction_id, None);
+
+    assert_eq!(app.dispatch_outbox().await, 1);
+
+    let recorded = app.ledger.grants();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!((recorded[0].grant_id.clone(), recorded[0].patient_did.as_str()), (grant_id.to_hex(), PATIENT));
+    let stored = app.database.get_grant(grant_id).await.unwrap().unwrap();
+    assert_eq!(stored.hedera_transaction_id, Some(recorded[0].transaction_id.clone()));
+    assert_eq!(app.database.count_jobs(Some(OUTBOX_JOB)).await.unwrap().succeeded, 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_call_whose_response_was_lost_is_not_sent_again() {
+    let app = spawn_test_app().await;
+    let grant_id = grant(&app).await;
+
+    // Consensus is reached but the dispatcher never hears back, so the job is retried
+    app.ledger.lose_next_record_response();
+    let worker = app.outbox_worker();
+    assert!(worker.run_next("outbox/0", Utc::now()).await.unwrap());
+    assert_eq!(app.database.get_grant(grant_id).await.unwrap().unwrap().hedera_transaction_id, None);
+    assert!(worker.run_next("outbox/0", Utc::now() + Duration::seconds(31)).await.unwrap());
+
+    let recorded = app.ledger.grants();
+    assert_eq!(recorded.len(), 1, "the retry found the first call on the mirror node");
+    let stored = app.database.get_grant(grant_id).await.unwrap().unwrap();
+    assert_eq!(stored.hedera_transaction_id, Some(recorded[0].transaction_id.clone()));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_credential_and_its_outbox_are_written_together_or_not_at_all() {
+    let app = spawn_test_app().await;
+    let ipfs_hash = IpfsClient::new(&app.ipfs.uri()).add_file(b"signed document", None).await.unwrap();
+    let issued = credential(&ipfs_hash);
+    let outbox = [OutboxAction::Pin { ipfs_hash: issued.ipfs_hash.clone() }, store(&issued)];
+
+    app.database.create_verifiable_credential(&issued, &outbox).await.unwrap();
+    // Saving it again fails on the duplicate id, and takes its outbox jobs down with it
+    assert!(app.database.create_verifiable_credential(&issued, &outbox).await.is_err());
+    assert_eq!(app.database.count_jobs(Some(OUTBOX_JOB)).await.unwrap().pending, 2);
+
+    assert_eq!(app.dispatch_outbox().await, 2);
+    let anchored = app.database.get_credential(issued.id.unwrap()).await.unwrap().unwrap();
+    assert_eq!(anchored.hedera_transaction_id, Some(app.ledger.credentials()[0].transaction_id.clone()));
+    assert_eq!(app.ledger.credentials().len(), 1);
+    assert_eq!(app.database.count_jobs(Some(OUTBOX_JOB)).await.unwrap().succeeded, 2);
+
+    app.cleanup().await;
+}
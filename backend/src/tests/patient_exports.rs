@@ -0,0 +1,156 @@
+use bson::doc;
+use chrono::{Duration, Utc};
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use crate::config::JobConfig;
+use crate::fixtures;
+use crate::jobs::JobWorkerPool;
+use crate::models::*;
+use crate::services::ipfs::IpfsClient;
+use crate::services::patient_export::PatientExportHandler;
+use crate::state::AppStateBuilder;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+use crate::utils;
+
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.9301";
+const STRANGER_DID: &str = "did:hedera:testnet:0.0.9302";
+
+/// A finished encounter of the patient's with `bundle` stored encrypted on IPFS
+async fn seed_finished_encounter(app: &TestApp, bundle: &Value) -> String {
+    let stored = utils::chunked::encrypt(&serde_json::to_vec(bundle).unwrap(), &app.config.ipfs_encryption_key).unwrap();
+    let ipfs_hash = IpfsClient::new(&app.ipfs.uri()).add_file(&stored, None).await.unwrap();
+    let encounter = Encounter {
+        final_bundle_ipfs_hash: Some(ipfs_hash),
+        bundle_key_version: Some(app.config.ipfs_encryption_key_version),
+        ..fixtures::encounter(PATIENT_DID, "did:hedera:testnet:0.0.9303", EncounterStatus::Finished)
+    };
+    app.database.create_encounter(&encounter).await.unwrap().to_hex()
+}
+
+async fn request_export(app: &TestApp, token: &str, did: &str) -> reqwest::Response {
+    app.client.post(app.url(&format!("/api/patients/{}/export", did))).bearer_auth(token).send().await.unwrap()
+}
+
+async fn get_export(app: &TestApp, id: &str) -> Value {
+    let token = app.mint_high_assurance_jwt(PATIENT_DID, Role::Patient);
+    let response = app.client.get(app.url(&format!("/api/patients/{}/export/{}", PATIENT_DID, id))).bearer_auth(token).send().await.unwrap();
+    response.json::<Value>().await.unwrap()["data"].clone()
+}
+
+/// The download link points at the configured base URL; the test app listens elsewhere
+fn local(app: &TestApp, download_url: &str) -> String {
+    app.url(download_url.strip_prefix(&app.config.backend_base_url).unwrap())
+}
+
+/// Run the export jobs that are due, as `main`'s worker pool would
+async fn build_exports(app: &TestApp) {
+    let state = AppStateBuilder::new(app.config.clone())
+        .with_database(app.database.clone())
+        .with_ledger(app.ledger.clone())
+        .with_did_registry(app.did_registry.clone())
+        .build()
+        .await
+        .unwrap();
+    let pool = JobWorkerPool::new(app.database.clone(), JobConfig::default())
+        .register(Arc::new(PatientExportHandler::new(state.patient_export_service.clone(), JobConfig::default().max_attempts)));
+    while pool.run_next("exports/0", Utc::now()).await.unwrap() {}
+}
+
+async fn audit_actions(app: &TestApp) -> Vec<String> {
+    let collection = app.database.db.collection::<AuditLog>("audit_logs");
+    let mut cursor = collection.find(doc! { "did": PATIENT_DID }, None).await.unwrap();
+    let mut actions = Vec::new();
+    while cursor.advance().await.unwrap() {
+        actions.push(cursor.deserialize_current().unwrap().action);
+    }
+    actions
+}
+
+#[tokio::test]
+async fn a_patient_downloads_an_archive_of_their_record_through_a_signed_link() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT_DID).await;
+    let bundle = json!({ "resourceType": "Bundle", "type": "document", "entry": [] });
+    let encounter_id = seed_finished_encounter(&app, &bundle).await;
+    let token = app.mint_high_assurance_jwt(PATIENT_DID, Role::Patient);
+
+    let requested: Value = request_export(&app, &token, PATIENT_DID).await.json().await.unwrap();
+    assert_eq!(requested["data"]["status"], "pending");
+    let id = requested["data"]["id"].as_str().unwrap().to_string();
+    // Asking again while it is being built returns the same export
+    let again: Value = request_export(&app, &token, PATIENT_DID).await.json().await.unwrap();
+    assert_eq!(again["data"]["id"], id.as_str());
+
+    build_exports(&app).await;
+    let export = get_export(&app, &id).await;
+    assert_eq!(export["status"], "ready");
+    let download_url = local(&app, export["download_url"].as_str().unwrap());
+
+    let response = app.client.get(&download_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/zip");
+    assert!(response.headers()[CONTENT_DISPOSITION].to_str().unwrap().starts_with("attachment;"));
+    let archive = response.bytes().await.unwrap();
+    assert_eq!(export["sha256"], hex::encode(Sha256::digest(&archive)));
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive.to_vec())).unwrap();
+    let mut read = |path: &str| {
+        let mut content = Vec::new();
+        zip.by_name(path).unwrap().read_to_end(&mut content).unwrap();
+        content
+    };
+    let manifest: Value = serde_json::from_slice(&read("manifest.json")).unwrap();
+    let entries: Vec<ExportManifestEntry> = serde_json::from_value(manifest["files"].clone()).unwrap();
+    let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+    let encounter_path = format!("encounters/{}.json", encounter_id);
+    for expected in ["Patient.json", encounter_path.as_str(), "prescriptions.json", "consents.json", "audit-trail.json"] {
+        assert!(paths.contains(&expected), "{} missing from {:?}", expected, paths);
+    }
+    for entry in &entries {
+        assert_eq!(entry.sha256, hex::encode(Sha256::digest(read(&entry.path))));
+    }
+    assert_eq!(serde_json::from_slice::<Value>(&read(&encounter_path)).unwrap(), bundle);
+    let patient: Value = serde_json::from_slice(&read("Patient.json")).unwrap();
+    assert_eq!(patient["id"], PATIENT_DID);
+
+    let actions = audit_actions(&app).await;
+    assert!(actions.iter().any(|action| action == "request_patient_export"));
+    assert!(actions.iter().any(|action| action == "download_patient_export"));
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn exports_are_the_owners_alone_and_links_cannot_be_altered() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT_DID).await;
+
+    // Exporting needs a second factor, and only the patient can export their record
+    let ordinary = request_export(&app, &app.mint_jwt(PATIENT_DID, Role::Patient), PATIENT_DID).await;
+    assert_eq!(ordinary.status(), StatusCode::UNAUTHORIZED);
+    let stranger = request_export(&app, &app.mint_high_assurance_jwt(STRANGER_DID, Role::Patient), PATIENT_DID).await;
+    assert_eq!(stranger.status(), StatusCode::FORBIDDEN);
+
+    let token = app.mint_high_assurance_jwt(PATIENT_DID, Role::Patient);
+    let requested: Value = request_export(&app, &token, PATIENT_DID).await.json().await.unwrap();
+    let id = requested["data"]["id"].as_str().unwrap().to_string();
+    build_exports(&app).await;
+    let download_url = local(&app, get_export(&app, &id).await["download_url"].as_str().unwrap());
+
+    let (base, signature) = download_url.rsplit_once("signature=").unwrap();
+    let forged = format!("{}signature={}", base, "0".repeat(signature.len()));
+    assert_eq!(app.client.get(&forged).send().await.unwrap().status(), StatusCode::FORBIDDEN);
+    let extended = download_url.replace("expires=", "expires=9");
+    assert_eq!(app.client.get(&extended).send().await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    // Once the retention is over the link stops working even before it expires
+    let collection = app.database.db.collection::<PatientExport>("patient_exports");
+    let past = bson::DateTime::from_chrono(Utc::now() - Duration::minutes(1));
+    collection.update_one(doc! { "_id": bson::oid::ObjectId::parse_str(&id).unwrap() }, doc! { "$set": { "expires_at": past } }, None).await.unwrap();
+    assert_eq!(app.client.get(&download_url).send().await.unwrap().status(), StatusCode::FORBIDDEN);
+    app.cleanup().await;
+}
@@ -0,0 +1,294 @@
+use bson::{doc, oid::ObjectId, Document};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::auditing::AuditLogService;
+use crate::fixtures;
+use crate::models::*;
+use crate::services::PatientMergeService;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const ADMIN: &str = "did:hedera:testnet:0.0.9200";
+const SURVIVOR: &str = "did:hedera:testnet:0.0.9201";
+const DUPLICATE: &str = "did:hedera:testnet:0.0.9202";
+const BYSTANDER: &str = "did:hedera:testnet:0.0.9203";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9204";
+
+fn contact(system: &str, value: &str) -> FhirContactPoint {
+    FhirContactPoint { system: system.to_string(), value: value.to_string(), r#use: None }
+}
+
+async fn seed_patient(app: &TestApp, did: &str, given: &str, telecom: Vec<FhirContactPoint>) {
+    let patient = fixtures::patient(did, FhirPatient {
+        resource_type: "Patient".to_string(),
+        id: did.to_string(),
+        name: vec![FhirHumanName {
+            r#use: None,
+            family: Some("Otieno".to_string()),
+            given: vec![given.to_string()],
+            prefix: vec![],
+            suffix: vec![],
+        }],
+        birth_date: "1990-01-01".to_string(),
+        telecom,
+        ..Default::default()
+    });
+    app.database.create_patient(&patient, &app.config.ipfs_encryption_key).await.unwrap();
+}
+
+/// Jane registered at the clinic by phone, then again at home by email; Peter is unrelated
+async fn seed_patients(app: &TestApp) {
+    seed_patient(app, SURVIVOR, "Jane", vec![contact("phone", "+254700000001")]).await;
+    seed_patient(app, DUPLICATE, "Jane", vec![contact("email", "jane@example.com"), contact("phone", "+254 700 000 001")]).await;
+    seed_patient(app, BYSTANDER, "Peter", vec![contact("email", "peter@example.com")]).await;
+}
+
+async fn seed_encounter(app: &TestApp, patient_did: &str) -> ObjectId {
+    let encounter = fixtures::encounter(patient_did, PRACTITIONER, EncounterStatus::InProgress);
+    app.database.create_encounter(&encounter).await.unwrap()
+}
+
+/// Only the fields the merge rewrites; the rest of a prescription does not matter here
+async fn seed_prescription(app: &TestApp, patient_did: &str) -> ObjectId {
+    let prescription = doc! {
+        "patient_did": patient_did,
+        "practitioner_did": PRACTITIONER,
+        "fhir_medication_request": { "subject": { "reference": format!("Patient/{}", patient_did) } },
+    };
+    let collection = app.database.db.collection::<Document>("prescriptions");
+    collection.insert_one(prescription, None).await.unwrap().inserted_id.as_object_id().unwrap()
+}
+
+async fn seed_credential(app: &TestApp, subject_did: &str) -> ObjectId {
+    let id = ObjectId::new();
+    let credential = VerifiableCredential {
+        id: Some(id),
+        subject_did: subject_did.to_string(),
+        credential_type: "VaccinationCredential".to_string(),
+        issuer: PRACTITIONER.to_string(),
+        issued_at: Utc::now(),
+        expires_at: None,
+        ipfs_hash: format!("QmVaccination{}", id.to_hex()),
+        hedera_transaction_id: None,
+        metadata: "{}".to_string(),
+        revoked_at: None,
+        status_list: None,
+        batch: None,
+    };
+    app.database.create_verifiable_credential(&credential, &[]).await.unwrap();
+    id
+}
+
+async fn seed_grant(app: &TestApp, patient_did: &str) -> ObjectId {
+    let grant = AccessControl {
+        id: None,
+        patient_did: patient_did.to_string(),
+        grantee_did: PRACTITIONER.to_string(),
+        permissions: vec![Permission::Read],
+        active: true,
+        created_at: Utc::now(),
+        expires_at: None,
+        emergency: false,
+        hedera_transaction_id: None,
+    };
+    app.database.grant_access(&grant, &[]).await.unwrap()
+}
+
+async fn document(app: &TestApp, collection: &str, id: ObjectId) -> Document {
+    app.database.db.collection::<Document>(collection).find_one(doc! { "_id": id }, None).await.unwrap().unwrap()
+}
+
+async fn merge(app: &TestApp, survivor_did: &str, duplicate_did: &str) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/admin/patients/merge"))
+        .bearer_auth(app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin))
+        .json(&json!({ "survivor_did": survivor_did, "duplicate_did": duplicate_did }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn merge_service(app: &TestApp) -> PatientMergeService {
+    let audit = Arc::new(AuditLogService::new(app.database.clone()));
+    PatientMergeService::new(app.database.clone(), app.database.clone(), app.database.clone(), app.config.clone(), audit)
+}
+
+#[tokio::test]
+async fn the_scan_records_the_duplicate_pair_for_review() {
+    let app = spawn_test_app().await;
+    seed_patients(&app).await;
+
+    let queued: Value = app
+        .client
+        .post(app.url("/api/admin/patients/duplicates/scan"))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::PlatformAdmin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(queued["success"], true, "{}", queued);
+    let job_id = ObjectId::parse_str(queued["data"].as_str().unwrap()).unwrap();
+    assert_eq!(document(&app, "jobs", job_id).await.get_str("job_type").unwrap(), "detect_duplicate_patients");
+
+    // The worker pool does not run in tests; run the job's work directly, twice to show it is idempotent
+    let service = merge_service(&app);
+    assert_eq!(service.detect_duplicates().await.unwrap(), 1);
+    assert_eq!(service.detect_duplicates().await.unwrap(), 1);
+
+    let listed: Value = app
+        .client
+        .get(app.url("/api/admin/patients/duplicates?status=open"))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::PlatformAdmin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(listed["data"]["total"], 1, "{}", listed);
+    let candidate = &listed["data"]["items"][0];
+    assert_eq!(candidate["patient_dids"], json!([SURVIVOR, DUPLICATE]));
+    assert_eq!(candidate["reasons"], json!(["phone", "name", "birth_date"]));
+    assert_eq!(candidate["score"], 1.0);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn merging_repoints_every_reference_to_the_survivor() {
+    let app = spawn_test_app().await;
+    seed_patients(&app).await;
+    let encounters = [seed_encounter(&app, DUPLICATE).await, seed_encounter(&app, DUPLICATE).await];
+    let prescription = seed_prescription(&app, DUPLICATE).await;
+    let credential = seed_credential(&app, DUPLICATE).await;
+    let grant = seed_grant(&app, DUPLICATE).await;
+    // The survivor's own records and the bystander's stay where they are
+    let own_encounter = seed_encounter(&app, SURVIVOR).await;
+    let bystander_encounter = seed_encounter(&app, BYSTANDER).await;
+    let bystander_prescription = seed_prescription(&app, BYSTANDER).await;
+    let bystander_credential = seed_credential(&app, BYSTANDER).await;
+    let bystander_grant = seed_grant(&app, BYSTANDER).await;
+
+    let response: Value = merge(&app, SURVIVOR, DUPLICATE).await.json().await.unwrap();
+    assert_eq!(response["success"], true, "{}", response);
+    let merged = &response["data"];
+
+    let survivor_reference = format!("Patient/{}", SURVIVOR);
+    for id in encounters {
+        let encounter = document(&app, "encounters", id).await;
+        assert_eq!(encounter.get_str("patient_did").unwrap(), SURVIVOR);
+        assert_eq!(encounter.get_document("fhir_encounter").unwrap().get_document("subject").unwrap().get_str("reference").unwrap(), survivor_reference);
+    }
+    let moved = document(&app, "prescriptions", prescription).await;
+    assert_eq!(moved.get_str("patient_did").unwrap(), SURVIVOR);
+    assert_eq!(moved.get_document("fhir_medication_request").unwrap().get_document("subject").unwrap().get_str("reference").unwrap(), survivor_reference);
+    assert_eq!(document(&app, "verifiable_credentials", credential).await.get_str("subject_did").unwrap(), SURVIVOR);
+    assert_eq!(document(&app, "access_controls", grant).await.get_str("patient_did").unwrap(), SURVIVOR);
+    assert_eq!(app.database.active_grants(SURVIVOR, PRACTITIONER).await.unwrap().len(), 1);
+    assert!(app.database.active_grants(DUPLICATE, PRACTITIONER).await.unwrap().is_empty());
+
+    assert_eq!(document(&app, "encounters", own_encounter).await.get_str("patient_did").unwrap(), SURVIVOR);
+    assert_eq!(document(&app, "encounters", bystander_encounter).await.get_str("patient_did").unwrap(), BYSTANDER);
+    assert_eq!(document(&app, "prescriptions", bystander_prescription).await.get_str("patient_did").unwrap(), BYSTANDER);
+    assert_eq!(document(&app, "verifiable_credentials", bystander_credential).await.get_str("subject_did").unwrap(), BYSTANDER);
+    assert_eq!(document(&app, "access_controls", bystander_grant).await.get_str("patient_did").unwrap(), BYSTANDER);
+
+    // The merge document lists exactly what moved, for undoing it
+    let ids = |field: &str| {
+        let mut ids: Vec<String> = merged[field].as_array().unwrap().iter().map(|id| id["$oid"].as_str().unwrap().to_string()).collect();
+        ids.sort();
+        ids
+    };
+    let mut expected: Vec<String> = encounters.iter().map(ObjectId::to_hex).collect();
+    expected.sort();
+    assert_eq!(ids("encounter_ids"), expected);
+    assert_eq!(ids("prescription_ids"), vec![prescription.to_hex()]);
+    assert_eq!(ids("credential_ids"), vec![credential.to_hex()]);
+    assert_eq!(ids("access_grant_ids"), vec![grant.to_hex()]);
+    let merge_id = ObjectId::parse_str(merged["_id"]["$oid"].as_str().unwrap()).unwrap();
+    let stored = document(&app, "patient_merges", merge_id).await;
+    assert_eq!(stored.get_str("duplicate_did").unwrap(), DUPLICATE);
+    assert_eq!(stored.get_str("merged_by").unwrap(), ADMIN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn the_duplicate_becomes_an_alias_of_the_survivor() {
+    let app = spawn_test_app().await;
+    seed_patients(&app).await;
+    let key = &app.config.ipfs_encryption_key;
+
+    let response: Value = merge(&app, SURVIVOR, DUPLICATE).await.json().await.unwrap();
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(response["data"]["telecom_added"], 1, "the phone number is the same one, formatted differently");
+
+    // Old references to the duplicate resolve to the survivor, whose record gained the email
+    let resolved = app.database.get_patient_by_did(DUPLICATE, key).await.unwrap().unwrap();
+    assert_eq!(resolved.did, SURVIVOR);
+    let telecom: Vec<&str> = resolved.fhir_patient.telecom.iter().map(|c| c.value.as_str()).collect();
+    assert_eq!(telecom, vec!["+254700000001", "jane@example.com"]);
+    assert_eq!(app.database.get_patient_by_email("jane@example.com", key).await.unwrap().unwrap().did, SURVIVOR);
+
+    // The duplicate itself is soft-deleted and still there for admins
+    let tombstone = app.database.find_patient_by_did(DUPLICATE, key, true).await.unwrap().unwrap();
+    assert_eq!(tombstone.did, DUPLICATE);
+    let raw = app.database.db.collection::<Document>("patients").find_one(doc! { "did": DUPLICATE }, None).await.unwrap().unwrap();
+    assert!(raw.get("deleted_at").is_some_and(|deleted_at| *deleted_at != bson::Bson::Null));
+    assert!(raw.get("email_hash").is_none());
+
+    // Merging the survivor into a third record carries the alias along
+    seed_patient(&app, "did:hedera:testnet:0.0.9205", "Jane", vec![]).await;
+    let response: Value = merge(&app, "did:hedera:testnet:0.0.9205", SURVIVOR).await.json().await.unwrap();
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(app.database.get_patient_by_did(DUPLICATE, key).await.unwrap().unwrap().did, "did:hedera:testnet:0.0.9205");
+    // And the bystander is untouched throughout
+    assert_eq!(app.database.get_patient_by_did(BYSTANDER, key).await.unwrap().unwrap().did, BYSTANDER);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_record_is_merged_only_once_and_candidates_are_closed() {
+    let app = spawn_test_app().await;
+    seed_patients(&app).await;
+    merge_service(&app).detect_duplicates().await.unwrap();
+
+    assert_eq!(merge(&app, SURVIVOR, DUPLICATE).await.status(), reqwest::StatusCode::OK);
+    let again: Value = merge(&app, SURVIVOR, DUPLICATE).await.json().await.unwrap();
+    assert_eq!(again["success"], false, "{}", again);
+    let reversed: Value = merge(&app, DUPLICATE, SURVIVOR).await.json().await.unwrap();
+    assert_eq!(reversed["success"], false, "{}", reversed);
+    let itself: Value = merge(&app, SURVIVOR, SURVIVOR).await.json().await.unwrap();
+    assert_eq!(itself["success"], false, "{}", itself);
+    assert_eq!(app.database.db.collection::<Document>("patient_merges").count_documents(doc! {}, None).await.unwrap(), 1);
+
+    let (candidates, _) = app.database.list_merge_candidates(None, 0, 10).await.unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].status, MergeCandidateStatus::Merged);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn merging_needs_a_stepped_up_admin() {
+    let app = spawn_test_app().await;
+    seed_patients(&app).await;
+
+    let response = app
+        .client
+        .post(app.url("/api/admin/patients/merge"))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::PlatformAdmin))
+        .json(&json!({ "survivor_did": SURVIVOR, "duplicate_did": DUPLICATE }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(app.database.get_patient_by_did(DUPLICATE, &app.config.ipfs_encryption_key).await.unwrap().unwrap().did, DUPLICATE);
+
+    app.cleanup().await;
+}
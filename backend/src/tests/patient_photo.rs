@@ -0,0 +1,130 @@
+use bson::doc;
+use image::{ImageOutputFormat, Rgb, RgbImage};
+use reqwest::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9901";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9902";
+
+fn photo(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+    let mut content = Cursor::new(Vec::new());
+    RgbImage::from_pixel(width, height, Rgb([90, 140, 200])).write_to(&mut content, format).unwrap();
+    content.into_inner()
+}
+
+async fn upload(app: &TestApp, token: &str, content: Vec<u8>) -> reqwest::Response {
+    app.client.put(app.url(&format!("/api/patients/{}/photo", PATIENT))).bearer_auth(token).body(content).send().await.unwrap()
+}
+
+async fn download(app: &TestApp, token: &str, query: &str) -> reqwest::Response {
+    app.client.get(app.url(&format!("/api/patients/{}/photo{}", PATIENT, query))).bearer_auth(token).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn the_patient_sets_and_replaces_their_photo() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let token = app.mint_jwt(PATIENT, Role::Patient);
+    let original = photo(400, 300, ImageOutputFormat::Png);
+
+    let uploaded: Value = upload(&app, &token, original.clone()).await.json().await.unwrap();
+    assert_eq!(uploaded["success"], true, "{}", uploaded);
+    assert_eq!((uploaded["data"]["width"].as_u64(), uploaded["data"]["height"].as_u64()), (Some(400), Some(300)));
+
+    let full = download(&app, &token, "").await;
+    assert_eq!(full.status(), StatusCode::OK);
+    assert_eq!((full.headers()[CONTENT_TYPE].to_str().unwrap(), full.headers()[CACHE_CONTROL].to_str().unwrap()), ("image/png", "private, no-store"));
+    assert_eq!(full.bytes().await.unwrap(), original);
+    let thumb = download(&app, &token, "?size=thumb").await;
+    assert_eq!(thumb.headers()[CONTENT_TYPE], "image/jpeg");
+    let thumbnail = image::load_from_memory(&thumb.bytes().await.unwrap()).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (160, 120));
+
+    // What is stored on IPFS is encrypted
+    let cid = uploaded["data"]["full"]["ipfs_hash"].as_str().unwrap();
+    assert_ne!(app.fetch_from_ipfs(cid).await, original);
+
+    let replaced: Value = upload(&app, &token, photo(300, 300, ImageOutputFormat::Jpeg(90))).await.json().await.unwrap();
+    assert_eq!(replaced["success"], true, "{}", replaced);
+    assert_eq!(download(&app, &token, "").await.headers()[CONTENT_TYPE], "image/jpeg");
+    let audit = app
+        .database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .find_one(doc! { "did": PATIENT, "action": "upload_patient_photo", "details.replaced_cid": cid }, None)
+        .await
+        .unwrap();
+    assert!(audit.is_some(), "the replacement was not audit-logged");
+    let views = app.database.db.collection::<AuditLog>("audit_logs").count_documents(doc! { "did": PATIENT, "action": "view_patient_photo" }, None);
+    assert_eq!(views.await.unwrap(), 3);
+
+    // The FHIR Patient links to the photo rather than embedding it
+    let patient: Value = app
+        .client
+        .get(app.url(&format!("/api/patients/{}", PATIENT)))
+        .bearer_auth(&token)
+        .header(ACCEPT, "application/fhir+json")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(patient["photo"][0]["url"], format!("/api/patients/{}/photo", PATIENT));
+    assert_eq!(patient["photo"][0]["content_type"], "image/jpeg");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn photos_are_checked_before_they_are_stored() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let token = app.mint_jwt(PATIENT, Role::Patient);
+
+    for (content, status) in [
+        (photo(100, 100, ImageOutputFormat::Png), StatusCode::UNPROCESSABLE_ENTITY),
+        (b"%PDF-1.7\n1 0 obj << /Type /Catalog >> endobj\n%%EOF\n".to_vec(), StatusCode::UNPROCESSABLE_ENTITY),
+    ] {
+        let response = upload(&app, &token, content).await;
+        assert_eq!(response.status(), status);
+        assert_eq!(response.json::<Value>().await.unwrap()["code"], "invalid_photo");
+    }
+    assert_eq!(download(&app, &token, "").await.status(), StatusCode::NOT_FOUND);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn practitioners_need_a_write_grant_to_change_the_photo() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let patient = app.mint_jwt(PATIENT, Role::Patient);
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+
+    assert_eq!(upload(&app, &practitioner, photo(300, 300, ImageOutputFormat::Png)).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(download(&app, &practitioner, "").await.status(), StatusCode::FORBIDDEN);
+
+    // Read lets the front desk see the photo, but not replace it
+    let grant = |permissions: Value| {
+        app.client
+            .post(app.url("/api/access/grants"))
+            .bearer_auth(&patient)
+            .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": permissions, "expires_at": null }))
+            .send()
+    };
+    assert_eq!(grant(json!(["Read"])).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(upload(&app, &patient, photo(300, 300, ImageOutputFormat::Png)).await.status(), StatusCode::OK);
+    assert_eq!(download(&app, &practitioner, "?size=thumb").await.status(), StatusCode::OK);
+    assert_eq!(upload(&app, &practitioner, photo(300, 300, ImageOutputFormat::Png)).await.status(), StatusCode::FORBIDDEN);
+
+    assert_eq!(grant(json!(["Write"])).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(upload(&app, &practitioner, photo(300, 300, ImageOutputFormat::Png)).await.status(), StatusCode::OK);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,183 @@
+use bson::{doc, Document};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::fixtures;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, spawn_test_app_with, TestApp};
+
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9300";
+const OTHER_PRACTITIONER: &str = "did:hedera:testnet:0.0.9301";
+const GRANTED: &str = "did:hedera:testnet:0.0.9310";
+const BOOKED: &str = "did:hedera:testnet:0.0.9311";
+const SEEN: &str = "did:hedera:testnet:0.0.9312";
+const STRANGER: &str = "did:hedera:testnet:0.0.9313";
+const EXPIRED: &str = "did:hedera:testnet:0.0.9314";
+const CANCELLED: &str = "did:hedera:testnet:0.0.9315";
+
+async fn seed_patient(app: &TestApp, did: &str, given: &str, family: &str) {
+    let patient = fixtures::patient(did, FhirPatient {
+        resource_type: "Patient".to_string(),
+        id: did.to_string(),
+        name: vec![FhirHumanName {
+            r#use: None,
+            family: Some(family.to_string()),
+            given: vec![given.to_string()],
+            prefix: vec![],
+            suffix: vec![],
+        }],
+        gender: "female".to_string(),
+        birth_date: "1990-01-01".to_string(),
+        ..Default::default()
+    });
+    app.database.create_patient(&patient, &app.config.ipfs_encryption_key).await.unwrap();
+}
+
+async fn seed_grant(app: &TestApp, patient_did: &str, expires_at: Option<chrono::DateTime<Utc>>) {
+    let grant = AccessControl {
+        id: None,
+        patient_did: patient_did.to_string(),
+        grantee_did: PRACTITIONER.to_string(),
+        permissions: vec![Permission::Read],
+        active: true,
+        created_at: Utc::now(),
+        expires_at,
+        emergency: false,
+        hedera_transaction_id: None,
+    };
+    app.database.grant_access(&grant, &[]).await.unwrap();
+}
+
+/// Only the fields the relationship lookup reads
+async fn seed_appointment(app: &TestApp, patient_did: &str, status: AppointmentStatus) {
+    let appointment = doc! {
+        "patient_did": patient_did,
+        "practitioner_did": PRACTITIONER,
+        "status": bson::to_bson(&status).unwrap(),
+    };
+    app.database.db.collection::<Document>("appointments").insert_one(appointment, None).await.unwrap();
+}
+
+async fn seed_encounter(app: &TestApp, patient_did: &str) {
+    let encounter = doc! { "patient_did": patient_did, "practitioner_did": PRACTITIONER };
+    app.database.db.collection::<Document>("encounters").insert_one(encounter, None).await.unwrap();
+}
+
+/// Six Jane Otienos; the practitioner has a grant from one, an appointment with one and an
+/// encounter with one. The rest are strangers or relationships that have ended.
+async fn seed(app: &TestApp) {
+    for did in [GRANTED, BOOKED, SEEN, STRANGER, EXPIRED, CANCELLED] {
+        seed_patient(app, did, "Jane", "Otieno").await;
+    }
+    seed_grant(app, GRANTED, None).await;
+    seed_appointment(app, BOOKED, AppointmentStatus::Requested).await;
+    seed_encounter(app, SEEN).await;
+    seed_grant(app, EXPIRED, Some(Utc::now() - Duration::days(1))).await;
+    seed_appointment(app, CANCELLED, AppointmentStatus::Cancelled).await;
+}
+
+async fn search(app: &TestApp, did: &str, role: Role, query: &str) -> reqwest::Response {
+    app.client
+        .get(app.url("/api/patients/search"))
+        .query(&[("q", query)])
+        .bearer_auth(app.mint_jwt(did, role))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn dids(response: &Value) -> Vec<String> {
+    let mut dids: Vec<String> = response["data"].as_array().unwrap().iter().map(|p| p["did"].as_str().unwrap().to_string()).collect();
+    dids.sort();
+    dids
+}
+
+#[tokio::test]
+async fn practitioners_find_only_their_own_patients() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+
+    for query in ["jane otieno", "Otie", "JAN"] {
+        let response: Value = search(&app, PRACTITIONER, Role::Practitioner, query).await.json().await.unwrap();
+        assert_eq!(response["success"], true, "{}", response);
+        assert_eq!(dids(&response), vec![GRANTED, BOOKED, SEEN], "{}", query);
+    }
+    let result = &search(&app, PRACTITIONER, Role::Practitioner, "jane").await.json::<Value>().await.unwrap()["data"][0];
+    assert_eq!(result["name"], "Jane Otieno");
+    assert_eq!(result["birth_date"], "1990-01-01");
+    assert_eq!(result["gender"], "female");
+
+    // A name that is not theirs, and a practitioner with no relationships, find nothing
+    let response: Value = search(&app, PRACTITIONER, Role::Practitioner, "kamau").await.json().await.unwrap();
+    assert_eq!(response["data"], json!([]));
+    let response: Value = search(&app, OTHER_PRACTITIONER, Role::Practitioner, "jane").await.json().await.unwrap();
+    assert_eq!(response["data"], json!([]));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn searches_are_audited_without_the_query() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+
+    search(&app, PRACTITIONER, Role::Practitioner, "otieno").await;
+
+    let log = app
+        .database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .find_one(doc! { "did": PRACTITIONER, "action": "search_patients" }, None)
+        .await
+        .unwrap()
+        .expect("the search is audited");
+    let details = log.details.unwrap();
+    assert_eq!(details["patients"].as_array().unwrap().len(), 3);
+    let details = details.to_string();
+    assert!(details.contains(GRANTED));
+    assert!(!details.contains(STRANGER));
+    assert!(!details.to_lowercase().contains("otieno"));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn only_practitioners_search_and_queries_need_three_letters() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+
+    assert_eq!(search(&app, GRANTED, Role::Patient, "jane").await.status(), reqwest::StatusCode::FORBIDDEN);
+    let response: Value = search(&app, PRACTITIONER, Role::Practitioner, "ja").await.json().await.unwrap();
+    assert_eq!(response["success"], false, "{}", response);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn records_saved_before_search_was_enabled_are_indexed_by_the_sweep() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let patients = app.database.db.collection::<Document>("patients");
+    patients.update_many(doc! {}, doc! { "$unset": { "search_tokens": "", "search_key_id": "" } }, None).await.unwrap();
+
+    let response: Value = search(&app, PRACTITIONER, Role::Practitioner, "jane").await.json().await.unwrap();
+    assert_eq!(response["data"], json!([]));
+
+    let indexed = app.database.reindex_patient_search(&app.config.ipfs_encryption_key, 100).await.unwrap();
+    assert_eq!(indexed, 6);
+    assert_eq!(app.database.reindex_patient_search(&app.config.ipfs_encryption_key, 100).await.unwrap(), 0);
+    let response: Value = search(&app, PRACTITIONER, Role::Practitioner, "jane").await.json().await.unwrap();
+    assert_eq!(dids(&response), vec![GRANTED, BOOKED, SEEN]);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn search_is_unavailable_without_a_key() {
+    let app = spawn_test_app_with(|config| config.patient_search.key = None).await;
+
+    let response = search(&app, PRACTITIONER, Role::Practitioner, "jane").await;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_IMPLEMENTED);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,80 @@
+use reqwest::StatusCode;
+
+use crate::api::policy::{policy_for, POLICIES};
+use crate::api::routes::build_router_with_paths;
+use crate::models::{AuditLog, Role};
+use crate::state::AppStateBuilder;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9701";
+const OTHER_PATIENT: &str = "did:hedera:testnet:0.0.9702";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9703";
+
+async fn get(app: &TestApp, path: &str, token: &str) -> StatusCode {
+    app.client.get(app.url(path)).bearer_auth(token).send().await.unwrap().status()
+}
+
+async fn denials(app: &TestApp) -> Vec<AuditLog> {
+    let logs = app.database.get_unanchored_audit_logs().await.unwrap();
+    logs.into_iter().filter(|log| log.action == "access_denied").collect()
+}
+
+#[tokio::test]
+async fn every_authenticated_route_has_a_policy() {
+    let app = spawn_test_app().await;
+    let state = AppStateBuilder::new(app.config.clone())
+        .with_database(app.database.clone())
+        .with_ledger(app.ledger.clone())
+        .with_did_registry(app.did_registry.clone())
+        .build()
+        .await
+        .unwrap();
+    let (_, paths) = build_router_with_paths(state);
+
+    let unprotected: Vec<&str> = paths.iter().copied().filter(|path| policy_for(path).is_none()).collect();
+    assert!(unprotected.is_empty(), "routes without a policy: {:?}", unprotected);
+    let stale: Vec<&str> = POLICIES.iter().map(|(path, _)| *path).filter(|path| !paths.contains(path)).collect();
+    assert!(stale.is_empty(), "policies for routes that are not served: {:?}", stale);
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn refusals_are_401_or_403_and_audited_alike() {
+    let app = spawn_test_app().await;
+    app.create_patient(PATIENT).await;
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+
+    // No grant on the record
+    assert_eq!(get(&app, &format!("/api/patients/{}/problems", PATIENT), &practitioner).await, StatusCode::FORBIDDEN);
+    // Another patient's settings
+    assert_eq!(get(&app, &format!("/api/patients/{}/timezone", PATIENT), &app.mint_jwt(OTHER_PATIENT, Role::Patient)).await, StatusCode::FORBIDDEN);
+    // The wrong role, and a missing step-up, which signing in again with a second factor fixes
+    assert_eq!(get(&app, "/api/patients/search?q=jane", &app.mint_jwt(PATIENT, Role::Patient)).await, StatusCode::FORBIDDEN);
+    assert_eq!(get(&app, "/api/admin/stats", &app.mint_jwt(PRACTITIONER, Role::Admin)).await, StatusCode::UNAUTHORIZED);
+    // Without a caller there is nobody to audit
+    let anonymous = app.client.get(app.url(&format!("/api/patients/{}", PATIENT))).send().await.unwrap();
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+    let logged: Vec<(String, String, String)> = denials(&app)
+        .await
+        .into_iter()
+        .map(|log| {
+            let details = log.details.unwrap();
+            (log.did, details["actor"].as_str().unwrap().to_string(), details["route"].as_str().unwrap().to_string())
+        })
+        .collect();
+    assert_eq!(
+        logged,
+        vec![
+            (PATIENT.to_string(), PRACTITIONER.to_string(), "GET /api/patients/:id/problems".to_string()),
+            (PATIENT.to_string(), OTHER_PATIENT.to_string(), "GET /api/patients/:id/timezone".to_string()),
+            (PATIENT.to_string(), PATIENT.to_string(), "GET /api/patients/search".to_string()),
+            (PRACTITIONER.to_string(), PRACTITIONER.to_string(), "GET /api/admin/stats".to_string()),
+        ]
+    );
+
+    // The patient themselves, and a stepped-up admin, are let through
+    assert_eq!(get(&app, &format!("/api/patients/{}/problems", PATIENT), &app.mint_jwt(PATIENT, Role::Patient)).await, StatusCode::OK);
+    assert_eq!(get(&app, "/api/admin/stats", &app.mint_high_assurance_jwt(PRACTITIONER, Role::Admin)).await, StatusCode::OK);
+    app.cleanup().await;
+}
@@ -0,0 +1,363 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::fixtures;
+use crate::models::*;
+use crate::services::status_list::StatusBitstring;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8701";
+const PRESCRIBER: &str = "did:hedera:testnet:0.0.8702";
+const PHARMACIST: &str = "did:hedera:testnet:0.0.8703";
+
+async fn post(app: &TestApp, path: &str, did: &str, body: Value) -> reqwest::Response {
+    app.client.post(app.url(path)).bearer_auth(app.mint_jwt(did, Role::Practitioner)).json(&body).send().await.unwrap()
+}
+
+async fn list(app: &TestApp, query: &str) -> Vec<Value> {
+    let listed: Value = app
+        .client
+        .get(app.url(&format!("/api/patients/{}/prescriptions?{}", PATIENT, query)))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    listed["data"].as_array().unwrap().clone()
+}
+
+/// Pharmacists are only trusted once their license is verified, which registration never does
+async fn verified_pharmacist(app: &TestApp) {
+    let pharmacist = Practitioner {
+        id: None,
+        did: PHARMACIST.to_string(),
+        fhir_practitioner: FhirPractitioner {
+            resource_type: "Practitioner".to_string(),
+            id: PHARMACIST.to_string(),
+            identifier: vec![],
+            name: vec![],
+            qualification: vec![],
+            telecom: vec![],
+        },
+        license_verification: LicenseVerification {
+            license_number: "PPB-1".to_string(),
+            issuing_authority: "PPB".to_string(),
+            issue_date: "2020-01-01".to_string(),
+            expiry_date: "2030-01-01".to_string(),
+            hedera_transaction_id: String::new(),
+            ipfs_hash: String::new(),
+            verified: true,
+            expiry_warning_sent_at: None,
+        },
+        practitioner_type: PractitionerType::Pharmacist,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        tenant_id: None,
+    };
+    app.database.create_practitioner(&pharmacist).await.unwrap();
+}
+
+async fn grant_prescribing(app: &TestApp) {
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRESCRIBER, "permissions": ["Prescribe"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+}
+
+fn medication_request(quantity: Option<f64>) -> Value {
+    json!({
+        "resourceType": "MedicationRequest",
+        "id": "",
+        "status": "draft",
+        "intent": "order",
+        "medication_codeable_concept": { "coding": [{ "system": "http://www.nlm.nih.gov/research/umls/rxnorm", "code": "308191", "display": null }], "text": "Amoxicillin 500mg" },
+        "subject": { "reference": "", "display": null },
+        "encounter": null,
+        "authored_on": "",
+        "requester": { "reference": "", "display": null },
+        "dosage_instruction": [],
+        "dispense_request": quantity.map(|value| json!({ "quantity": { "value": value, "unit": "capsule", "system": null, "code": null } })),
+    })
+}
+
+async fn verify(app: &TestApp, reference: Value) -> Value {
+    let verdict: Value = app.client.post(app.url("/api/prescriptions/verify")).json(&reference).send().await.unwrap().json().await.unwrap();
+    verdict["data"].clone()
+}
+
+/// The bits of a published status list, fetched without an account as any verifier would
+async fn published_bits(app: &TestApp, list_id: &str) -> StatusBitstring {
+    let response = app.client.get(app.url(&format!("/api/credentials/status-list/{}", list_id))).send().await.unwrap();
+    assert_eq!(response.headers()["content-type"], "application/vc+json");
+    let list: Value = response.json().await.unwrap();
+    StatusBitstring::decode(list["credentialSubject"]["encodedList"].as_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn prescriptions_move_through_their_lifecycle_and_are_dispensed_while_active() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRESCRIBER, None).await;
+    verified_pharmacist(&app).await;
+
+    let prescribe = || {
+        post(
+            &app,
+            "/api/prescriptions",
+            PRESCRIBER,
+            json!({ "patient_did": PATIENT, "medication_request": medication_request(None) }),
+        )
+    };
+    assert_eq!(prescribe().await.status(), reqwest::StatusCode::FORBIDDEN);
+    grant_prescribing(&app).await;
+
+    let created: Value = prescribe().await.json().await.unwrap();
+    assert_eq!(created["data"]["status"], "active");
+    assert_eq!(created["data"]["fhir_medication_request"]["status"], "active");
+    let first = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    let created: Value = prescribe().await.json().await.unwrap();
+    assert_eq!(created["data"]["warnings"][0]["kind"], "duplicate_therapy");
+    let second = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+
+    let dispense = json!({ "quantity": { "value": 21.0, "unit": "capsule", "system": null, "code": null }, "days_supply": 7 });
+    assert_eq!(post(&app, &format!("/api/prescriptions/{}/dispense", first), PRESCRIBER, dispense.clone()).await.status(), reqwest::StatusCode::FORBIDDEN);
+    let dispensed: Value = post(&app, &format!("/api/prescriptions/{}/dispense", first), PHARMACIST, dispense.clone()).await.json().await.unwrap();
+    assert_eq!(dispensed["data"]["dispenses"][0]["performer_did"], PHARMACIST);
+    assert_eq!(dispensed["data"]["dispenses"][0]["days_supply"], 7);
+
+    let completed: Value = post(&app, &format!("/api/prescriptions/{}/status", first), PRESCRIBER, json!({ "status": "completed" })).await.json().await.unwrap();
+    assert_eq!(completed["data"]["fhir_medication_request"]["status"], "completed");
+
+    let cancel_path = format!("/api/prescriptions/{}/status", second);
+    assert_eq!(post(&app, &cancel_path, PHARMACIST, json!({ "status": "cancelled", "reason": { "coding": [], "text": "Allergy" } })).await.status(), reqwest::StatusCode::FORBIDDEN);
+    let cancelled: Value = post(&app, &cancel_path, PRESCRIBER, json!({ "status": "cancelled", "reason": { "coding": [], "text": "Allergy" } })).await.json().await.unwrap();
+    assert_eq!(cancelled["data"]["fhir_medication_request"]["status_reason"]["text"], "Allergy");
+    assert_eq!(post(&app, &cancel_path, PRESCRIBER, json!({ "status": "stopped", "reason": { "coding": [], "text": "Allergy" } })).await.status(), reqwest::StatusCode::CONFLICT);
+    assert_eq!(post(&app, &format!("/api/prescriptions/{}/dispense", second), PHARMACIST, dispense).await.status(), reqwest::StatusCode::CONFLICT);
+
+    assert_eq!(list(&app, "").await.len(), 2);
+    let cancelled = list(&app, "status=cancelled").await;
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0]["_id"]["$oid"], second);
+    assert!(list(&app, "status=active").await.is_empty());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn pharmacies_verify_credentials_and_cannot_fill_a_prescription_twice() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRESCRIBER, None).await;
+    verified_pharmacist(&app).await;
+    grant_prescribing(&app).await;
+
+    let prescribe = || {
+        post(&app, "/api/prescriptions", PRESCRIBER, json!({ "patient_did": PATIENT, "medication_request": medication_request(Some(20.0)), "issue_credential": true }))
+    };
+    let created: Value = prescribe().await.json().await.unwrap();
+    let credential_hash = created["data"]["credential"]["hash"].as_str().unwrap().to_string();
+    let qr_payload = created["data"]["credential"]["qr_payload"].as_str().unwrap().to_string();
+
+    // The outbox pins the credential and stores it on the ledger after the request
+    assert!(app.ledger.credentials().is_empty());
+    app.dispatch_outbox().await;
+
+    // The metadata anchored with it says what was prescribed and by whom, but not for whom
+    let anchored = app.ledger.credentials();
+    assert_eq!(anchored.len(), 1);
+    assert_eq!(anchored[0].credential_type, "PrescriptionCredential");
+    assert!(anchored[0].metadata.contains("308191"));
+    assert!(!anchored[0].metadata.contains(PATIENT));
+
+    let verdict = verify(&app, json!({ "credential_hash": credential_hash })).await;
+    assert_eq!(verdict["valid"], true);
+    assert_eq!(verdict["medication"], "Amoxicillin 500mg");
+    assert_eq!(verdict["prescriber_license_verified"], false);
+    assert_eq!(verify(&app, json!({ "credential_hash": "unknown" })).await["valid"], false);
+
+    let dispense = |quantity: f64| {
+        post(
+            &app,
+            "/api/prescriptions/dispense",
+            PHARMACIST,
+            json!({ "qr_payload": qr_payload, "quantity": { "value": quantity, "unit": "capsule", "system": null, "code": null }, "days_supply": 5 }),
+        )
+    };
+    let partial: Value = dispense(10.0).await.json().await.unwrap();
+    assert_eq!(partial["data"]["fully_dispensed"], false);
+    assert_eq!(verify(&app, json!({ "qr_payload": qr_payload })).await["valid"], true);
+    let filled: Value = dispense(10.0).await.json().await.unwrap();
+    assert_eq!(filled["data"]["fully_dispensed"], true);
+
+    let verdict = verify(&app, json!({ "qr_payload": qr_payload })).await;
+    assert_eq!(verdict["valid"], false);
+    assert_eq!(verdict["reason"], "The prescription was already dispensed");
+    assert_eq!(dispense(10.0).await.status(), reqwest::StatusCode::CONFLICT);
+
+    // Cancelling revokes the credential
+    let created: Value = prescribe().await.json().await.unwrap();
+    let cancel_path = format!("/api/prescriptions/{}/status", created["data"]["_id"]["$oid"].as_str().unwrap());
+    post(&app, &cancel_path, PRESCRIBER, json!({ "status": "cancelled", "reason": { "coding": [], "text": "Allergy" } })).await;
+    let verdict = verify(&app, json!({ "credential_hash": created["data"]["credential"]["hash"] })).await;
+    assert_eq!(verdict["reason"], "The credential was revoked");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn major_interactions_block_prescribing_unless_overridden_with_a_reason() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRESCRIBER, None).await;
+    grant_prescribing(&app).await;
+    let prescribe = |text: &str, code: &str, extra: Value| {
+        let mut request = json!({ "patient_did": PATIENT, "medication_request": medication_request(None) });
+        request["medication_request"]["medication_codeable_concept"] =
+            json!({ "coding": [{ "system": "http://www.nlm.nih.gov/research/umls/rxnorm", "code": code, "display": null }], "text": text });
+        request.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        post(&app, "/api/prescriptions", PRESCRIBER, request)
+    };
+
+    let warfarin: Value = prescribe("Warfarin 5mg", "11289", json!({})).await.json().await.unwrap();
+    assert_eq!(warfarin["data"]["warnings"], json!([]));
+    assert_eq!(warfarin["data"]["interactions_checked"], true);
+
+    assert_eq!(prescribe("Aspirin 75mg", "1191", json!({})).await.status(), reqwest::StatusCode::CONFLICT);
+    assert_eq!(list(&app, "status=active").await.len(), 1);
+    let unexplained: Value = prescribe("Aspirin 75mg", "1191", json!({ "force": true, "override_reason": " " })).await.json().await.unwrap();
+    assert_eq!(unexplained["success"], false);
+
+    let aspirin: Value = prescribe("Aspirin 75mg", "1191", json!({ "force": true, "override_reason": "Recent stent, INR checked weekly" }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(aspirin["data"]["status"], "active");
+    assert_eq!(aspirin["data"]["warnings"][0]["severity"], "major");
+    assert_eq!(aspirin["data"]["warnings"][0]["interacting_drug"], "Warfarin 5mg");
+
+    // Once warfarin is stopped, nothing is left to interact with
+    let warfarin_id = warfarin["data"]["_id"]["$oid"].as_str().unwrap();
+    post(&app, &format!("/api/prescriptions/{}/status", warfarin_id), PRESCRIBER, json!({ "status": "stopped", "reason": { "coding": [], "text": "Bleeding risk" } })).await;
+    let ibuprofen: Value = prescribe("Ibuprofen 400mg", "5640", json!({})).await.json().await.unwrap();
+    assert_eq!(ibuprofen["data"]["warnings"][0]["severity"], "minor");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn medications_can_be_found_by_name_and_prescribed_by_rxnorm_code() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRESCRIBER, None).await;
+    grant_prescribing(&app).await;
+
+    let found: Value = app
+        .client
+        .get(app.url("/api/terminology/medications?q=amox&limit=2"))
+        .bearer_auth(app.mint_jwt(PRESCRIBER, Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(found["data"][0]["display"], "amoxicillin");
+    assert_eq!(found["data"][1]["code"], "308182");
+    assert_eq!((found["data"][1]["dose_form"].as_str(), found["data"][1]["strength"].as_str()), (Some("Oral Capsule"), Some("250 MG")));
+
+    let mut request = json!({ "patient_did": PATIENT, "medication_request": medication_request(None), "rxnorm_code": "308182" });
+    request["medication_request"].as_object_mut().unwrap().remove("medication_codeable_concept");
+    let prescribed: Value = post(&app, "/api/prescriptions", PRESCRIBER, request.clone()).await.json().await.unwrap();
+    let medication = &prescribed["data"]["fhir_medication_request"]["medication_codeable_concept"];
+    assert_eq!(medication["coding"][0]["code"], "308182");
+    assert_eq!(medication["text"], "amoxicillin 250 MG Oral Capsule");
+
+    request["rxnorm_code"] = json!("0000000");
+    let unknown: Value = post(&app, "/api/prescriptions", PRESCRIBER, request).await.json().await.unwrap();
+    assert_eq!(unknown["error"], "Unknown RxNorm code '0000000'");
+    assert_eq!(list(&app, "status=active").await.len(), 1);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn cancelling_a_prescription_sets_its_bit_in_the_published_status_list() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(PRESCRIBER, None).await;
+    grant_prescribing(&app).await;
+    let created: Value =
+        post(&app, "/api/prescriptions", PRESCRIBER, json!({ "patient_did": PATIENT, "medication_request": medication_request(None), "issue_credential": true }))
+            .await
+            .json()
+            .await
+            .unwrap();
+    let hash = created["data"]["credential"]["hash"].as_str().unwrap();
+    let credential = app.database.get_credential_by_hash(hash).await.unwrap().unwrap();
+    let entry = credential.status_list.clone().unwrap();
+
+    // The credential names the list its bit is in
+    let document: Value = app
+        .client
+        .get(app.url(&format!("/api/credentials/{}", credential.id.unwrap().to_hex())))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let list_url = document["credentialStatus"]["statusListCredential"].as_str().unwrap();
+    assert!(list_url.ends_with(&format!("/api/credentials/status-list/{}", entry.list_id)));
+    assert_eq!(document["credentialStatus"]["statusListIndex"], entry.index.to_string());
+
+    assert!(!published_bits(&app, &entry.list_id).await.is_set(entry.index));
+
+    let cancel_path = format!("/api/prescriptions/{}/status", created["data"]["_id"]["$oid"].as_str().unwrap());
+    post(&app, &cancel_path, PRESCRIBER, json!({ "status": "cancelled", "reason": { "coding": [], "text": "Allergy" } })).await;
+
+    assert!(published_bits(&app, &entry.list_id).await.is_set(entry.index));
+    // The same document is what was published to IPFS
+    let published = app.database.get_status_list(&entry.list_id).await.unwrap().unwrap();
+    assert_eq!(app.fetch_from_ipfs(published.ipfs_hash.as_deref().unwrap()).await, published.document.unwrap().into_bytes());
+    assert_eq!(verify(&app, json!({ "credential_hash": hash })).await["reason"], "The credential was revoked");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn an_expired_license_cannot_prescribe() {
+    let app = spawn_test_app().await;
+    let mut registration = fixtures::practitioner_registration(PRESCRIBER, vec![], "KMPDC-1", None);
+    registration.license_verification.expiry_date = (Utc::now() - chrono::Duration::days(1)).date_naive().to_string();
+    let prescriber = Practitioner {
+        id: None,
+        did: PRESCRIBER.to_string(),
+        fhir_practitioner: registration.fhir_practitioner,
+        license_verification: registration.license_verification,
+        practitioner_type: PractitionerType::Clinician,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        tenant_id: None,
+    };
+    app.database.create_practitioner(&prescriber).await.unwrap();
+    grant_prescribing(&app).await;
+
+    let refused = post(&app, "/api/prescriptions", PRESCRIBER, json!({ "patient_did": PATIENT, "medication_request": medication_request(None) })).await;
+    assert_eq!(refused.status(), reqwest::StatusCode::FORBIDDEN);
+    let denial = app
+        .database
+        .db
+        .collection::<AuditLog>("audit_logs")
+        .find_one(bson::doc! { "did": PATIENT, "action": "create_prescription_denied" }, None)
+        .await
+        .unwrap()
+        .expect("the refusal was not audit-logged");
+    assert_eq!(denial.details.unwrap()["reason"], "Your license has expired");
+
+    app.cleanup().await;
+}
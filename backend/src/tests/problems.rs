@@ -0,0 +1,69 @@
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::services::fhir::{FhirCodeSystems, FhirManager};
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8901";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.8902";
+
+fn condition(code: &str, display: &str, encounter_id: Option<&str>, recorded: &str, status: &str) -> FhirCondition {
+    let concept = FhirCodeableConcept {
+        coding: vec![FhirCoding { system: Some(FhirCodeSystems::icd10().to_string()), code: Some(code.to_string()), display: Some(display.to_string()) }],
+        text: None,
+    };
+    let recorded: FhirDateTime = recorded.parse().unwrap();
+    let mut condition = FhirManager::create_condition(PATIENT, encounter_id, concept, vec![], recorded.clone(), recorded);
+    condition.clinical_status.coding[0].code = Some(status.to_string());
+    condition
+}
+
+async fn problems(app: &TestApp, did: &str, role: Role, query: &str) -> reqwest::Response {
+    app.client
+        .get(app.url(&format!("/api/patients/{}/problems{}", PATIENT, query)))
+        .bearer_auth(app.mint_jwt(did, role))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn problem_list_merges_conditions_across_encounters() {
+    let app = spawn_test_app().await;
+    for condition in [
+        condition("E11.9", "Type 2 diabetes mellitus without complications", Some("enc-1"), "2023-03-01", "active"),
+        condition("E11.9", "Type 2 diabetes mellitus without complications", Some("enc-2"), "2025-01-15", "active"),
+        condition("J18.9", "Pneumonia, unspecified organism", Some("enc-2"), "2024-11-02", "active"),
+        condition("J18.9", "Pneumonia, unspecified organism", Some("enc-3"), "2024-12-01", "resolved"),
+        // Imported from another system, outside any encounter here
+        condition("I10", "Essential (primary) hypertension", None, "2019-06-20", "active"),
+    ] {
+        app.database.create_condition(&condition).await.unwrap();
+    }
+
+    let active: Value = problems(&app, PATIENT, Role::Patient, "").await.json().await.unwrap();
+    let listed = active["data"].as_array().unwrap();
+    assert_eq!(listed.iter().map(|problem| problem["code"].as_str().unwrap()).collect::<Vec<_>>(), ["E11.9", "I10"]);
+    assert_eq!(listed[0]["encounter_ids"], json!(["enc-1", "enc-2"]));
+    assert_eq!((listed[0]["onset_date_time"].as_str(), listed[0]["recorded_date"].as_str()), (Some("2023-03-01"), Some("2025-01-15")));
+    assert_eq!(listed[1]["encounter_ids"], json!([]));
+
+    let all: Value = problems(&app, PATIENT, Role::Patient, "?include_resolved=true").await.json().await.unwrap();
+    assert_eq!(all["data"][2]["code"], "J18.9");
+    assert_eq!(all["data"][2]["clinical_status"], "resolved");
+
+    assert_eq!(problems(&app, PRACTITIONER, Role::Practitioner, "").await.status(), reqwest::StatusCode::FORBIDDEN);
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["ViewEncounters"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+    let shared: Value = problems(&app, PRACTITIONER, Role::Practitioner, "").await.json().await.unwrap();
+    assert_eq!(shared["data"].as_array().unwrap().len(), 2);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,120 @@
+use bson::doc;
+use futures_util::TryStreamExt;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::models::{DevicePlatform, Notification, NotificationChannel, NotificationStatus, Role};
+use crate::tests::fcm_stub;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9912";
+
+async fn register_patient(app: &TestApp) -> String {
+    let body: Value = app
+        .client
+        .post(app.url("/api/auth/register"))
+        .json(&json!({ "name": "Amina Wanjiru", "email": "amina.push@example.com", "public_key_hex": "00".repeat(32) }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["success"], true, "registration failed: {}", body);
+    body["data"]["user"]["did"].as_str().unwrap().to_string()
+}
+
+async fn register_device(app: &TestApp, did: &str, fcm_token: &str, platform: &str) -> Value {
+    app.client
+        .post(app.url("/api/devices"))
+        .bearer_auth(app.mint_jwt(did, Role::Patient))
+        .json(&json!({ "fcm_token": fcm_token, "platform": platform }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn push_notifications(app: &TestApp, did: &str) -> Vec<Notification> {
+    app.database
+        .db
+        .collection::<Notification>("notifications")
+        .find(doc! { "patient_did": did, "channel": "push" }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn events_are_pushed_to_each_device_as_hints_and_rejected_tokens_are_forgotten() {
+    let app = spawn_test_app().await;
+    let patient = register_patient(&app).await;
+    for (token, platform) in [("phone-token", "android"), ("stale-tablet-token", "ios")] {
+        let registered = register_device(&app, &patient, token, platform).await;
+        assert_eq!(registered["success"], true, "{}", registered);
+    }
+
+    let granted: Value = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(&patient, Role::Patient))
+        .json(&json!({ "patient_did": patient, "grantee_did": PRACTITIONER, "permissions": ["Read"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(granted["success"], true, "{}", granted);
+
+    let mut notifications = Vec::new();
+    for _ in 0..50 {
+        notifications = push_notifications(&app, &patient).await;
+        if notifications.len() == 2 && notifications.iter().all(|n| n.status != NotificationStatus::Pending) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let mut statuses: Vec<_> = notifications.iter().map(|n| (n.channel, n.status)).collect();
+    statuses.sort_by_key(|(_, status)| *status == NotificationStatus::Failed);
+    assert_eq!(
+        statuses,
+        vec![(NotificationChannel::Push, NotificationStatus::Sent), (NotificationChannel::Push, NotificationStatus::Failed)]
+    );
+
+    // Only the category and a lock-screen hint leave the server; the grantee is fetched in the app
+    let messages = fcm_stub::sent_messages(&app.fcm).await;
+    assert_eq!(messages.len(), 2);
+    for message in &messages {
+        assert_eq!(message["data"], json!({ "category": "access_granted", "hint": "There is new activity on your account" }));
+        assert!(message.get("notification").is_none());
+    }
+
+    let devices = app.database.list_devices(&patient).await.unwrap();
+    assert_eq!(devices.iter().map(|d| d.fcm_token.as_str()).collect::<Vec<_>>(), vec!["phone-token"]);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_token_registered_again_moves_to_the_new_account() {
+    let app = spawn_test_app().await;
+    let first = "did:hedera:testnet:0.0.9913";
+    let second = "did:hedera:testnet:0.0.9914";
+
+    register_device(&app, first, "shared-token", "android").await;
+    let moved = register_device(&app, second, "shared-token", "web").await;
+    assert_eq!(moved["success"], true, "{}", moved);
+    let empty = register_device(&app, second, " ", "web").await;
+    assert_eq!(empty["success"], false);
+
+    assert!(app.database.list_devices(first).await.unwrap().is_empty());
+    let devices = app.database.list_devices(second).await.unwrap();
+    assert_eq!((devices.len(), devices[0].platform), (1, DevicePlatform::Web));
+
+    app.cleanup().await;
+}
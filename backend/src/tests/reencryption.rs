@@ -0,0 +1,91 @@
+use bson::oid::ObjectId;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::fixtures;
+use crate::models::*;
+use crate::services::ipfs::IpfsClient;
+use crate::tests::helpers::{spawn_test_app_with, TestApp};
+use crate::utils::{self, EncryptionKey};
+
+const ADMIN_DID: &str = "did:hedera:testnet:0.0.100";
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.1";
+
+/// A finished encounter whose bundle was archived under key version 1, before versions were recorded
+async fn archived_encounter(app: &TestApp, old_key: &EncryptionKey) -> (ObjectId, String) {
+    let encrypted = utils::encrypt(b"{\"resourceType\":\"Bundle\"}", old_key).unwrap();
+    let ipfs_hash = IpfsClient::new(&app.ipfs.uri()).add_file(encrypted.as_bytes(), None).await.unwrap();
+    let encounter = Encounter {
+        final_bundle_ipfs_hash: Some(ipfs_hash.clone()),
+        ..fixtures::encounter(PATIENT_DID, "did:hedera:testnet:0.0.2", EncounterStatus::Finished)
+    };
+    (app.database.create_encounter(&encounter).await.unwrap(), ipfs_hash)
+}
+
+#[tokio::test]
+async fn rotating_the_key_reencrypts_archived_bundles() {
+    let old_key = EncryptionKey::from_hex(&"11".repeat(32));
+    let new_key = EncryptionKey::from_hex(&"22".repeat(32));
+    let retired = old_key.clone();
+    let app = spawn_test_app_with(|config| {
+        config.ipfs_encryption_key = new_key.clone();
+        config.ipfs_encryption_key_version = 2;
+        config.ipfs_retired_encryption_keys = HashMap::from([(1, retired)]);
+    })
+    .await;
+    let (encounter_id, old_hash) = archived_encounter(&app, &old_key).await;
+    let admin_token = app.mint_jwt(ADMIN_DID, Role::PlatformAdmin);
+
+    let started: Value = app
+        .client
+        .post(app.url("/api/admin/reencryption-jobs"))
+        .bearer_auth(&admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(started["success"], true, "start failed: {}", started);
+    assert_eq!(started["data"]["key_version"], 2);
+    let job_url = app.url(&format!("/api/admin/reencryption-jobs/{}", started["data"]["_id"]["$oid"].as_str().unwrap()));
+
+    let mut job = Value::Null;
+    for _ in 0..50 {
+        job = app.client.get(&job_url).bearer_auth(&admin_token).send().await.unwrap().json().await.unwrap();
+        if job["data"]["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(job["data"]["status"], "completed", "job did not finish: {}", job);
+    assert_eq!((job["data"]["reencrypted"].as_u64(), job["data"]["failed"].as_u64()), (Some(1), Some(0)));
+
+    let stored = app.database.get_encounter(encounter_id).await.unwrap().unwrap();
+    let new_hash = stored.final_bundle_ipfs_hash.unwrap();
+    assert_ne!(new_hash, old_hash);
+    assert_eq!(stored.bundle_key_version, Some(2));
+    assert_eq!(stored.bundle_history.len(), 1);
+    assert_eq!((stored.bundle_history[0].ipfs_hash.as_str(), stored.bundle_history[0].key_version), (old_hash.as_str(), 1));
+    let blob = app.fetch_from_ipfs(&new_hash).await;
+    assert_eq!(utils::chunked::decrypt(&blob, &new_key).unwrap(), b"{\"resourceType\":\"Bundle\"}");
+
+    let requests = app.ipfs.received_requests().await.unwrap_or_default();
+    assert!(requests.iter().any(|request| request.url.path() == format!("/api/v0/pin/rm/{}", old_hash)));
+
+    // Everything is on the current key now, so a second job has nothing to do
+    let rerun: Value = app
+        .client
+        .post(app.url("/api/admin/reencryption-jobs"))
+        .bearer_auth(&admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(rerun["success"], true, "second start failed: {}", rerun);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,94 @@
+use serde_json::{json, Value};
+
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PATIENT: &str = "did:hedera:testnet:0.0.8601";
+const GP: &str = "did:hedera:testnet:0.0.8602";
+const SPECIALIST: &str = "did:hedera:testnet:0.0.8603";
+
+async fn post(app: &TestApp, path: &str, did: &str, body: Value) -> reqwest::Response {
+    app.client.post(app.url(path)).bearer_auth(app.mint_jwt(did, Role::Practitioner)).json(&body).send().await.unwrap()
+}
+
+async fn list(app: &TestApp, did: &str, query: &str) -> Vec<Value> {
+    let listed: Value = app
+        .client
+        .get(app.url(&format!("/api/referrals?{}", query)))
+        .bearer_auth(app.mint_jwt(did, Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    listed["data"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn accepted_referrals_grant_scoped_access_until_completed() {
+    let app = spawn_test_app().await;
+    app.register_practitioner(GP, None).await;
+    app.register_practitioner(SPECIALIST, None).await;
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": GP, "permissions": ["Read", "ViewEncounters"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), reqwest::StatusCode::OK);
+
+    let refer = |resources: Value| {
+        post(
+            &app,
+            "/api/referrals",
+            GP,
+            json!({ "patient_did": PATIENT, "receiving_did": SPECIALIST, "reason": "Suspected arrhythmia", "priority": "urgent", "resources": resources }),
+        )
+    };
+    // The GP was never shown the patient's observations, so cannot pass them on
+    assert_eq!(refer(json!(["Encounter/e1", "Observation/o1"])).await.status(), reqwest::StatusCode::FORBIDDEN);
+    let created: Value = refer(json!(["Encounter/e1", "Condition/c1"])).await.json().await.unwrap();
+    assert_eq!(created["data"]["status"], "requested");
+    let referral_id = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+
+    assert_eq!(list(&app, SPECIALIST, "folder=inbox&status=requested").await.len(), 1);
+    assert_eq!(list(&app, GP, "folder=outbox").await.len(), 1);
+    assert!(list(&app, GP, "folder=inbox").await.is_empty());
+
+    let accept_path = format!("/api/referrals/{}/accept", referral_id);
+    assert_eq!(post(&app, &accept_path, GP, json!({})).await.status(), reqwest::StatusCode::FORBIDDEN);
+    let accepted: Value = post(&app, &accept_path, SPECIALIST, json!({})).await.json().await.unwrap();
+    assert_eq!(accepted["data"]["status"], "accepted");
+    assert_eq!(post(&app, &accept_path, SPECIALIST, json!({})).await.status(), reqwest::StatusCode::CONFLICT);
+
+    // Encounters were attached, demographics were not
+    let grants = app.database.active_grants(PATIENT, SPECIALIST).await.unwrap();
+    assert_eq!(grants.len(), 1);
+    assert_eq!(grants[0].permissions, [Permission::ViewEncounters]);
+    assert!(grants[0].expires_at.is_some());
+    let demographics = app
+        .client
+        .get(app.url(&format!("/api/patients/{}", PATIENT)))
+        .bearer_auth(app.mint_jwt(SPECIALIST, Role::Practitioner))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(demographics.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let completed: Value = post(&app, &format!("/api/referrals/{}/complete", referral_id), SPECIALIST, json!({ "note": "Holter monitor normal" }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(completed["data"]["status"], "completed");
+    assert_eq!(completed["data"]["note"], "Holter monitor normal");
+    assert!(app.database.active_grants(PATIENT, SPECIALIST).await.unwrap().is_empty());
+    // The GP's own grant is untouched
+    assert_eq!(app.database.active_grants(PATIENT, GP).await.unwrap().len(), 1);
+    assert_eq!(list(&app, SPECIALIST, "status=completed").await.len(), 1);
+
+    app.cleanup().await;
+}
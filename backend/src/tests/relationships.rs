@@ -0,0 +1,103 @@
+use chrono::{Duration, Months, Utc};
+use serde_json::{json, Value};
+
+use crate::fixtures;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const GUARDIAN: &str = "did:hedera:testnet:0.0.8401";
+const CHILD: &str = "did:hedera:testnet:0.0.8402";
+const PARENT_OF_ADULT: &str = "did:hedera:testnet:0.0.8403";
+const ADULT: &str = "did:hedera:testnet:0.0.8404";
+const ADMIN: &str = "did:hedera:testnet:0.0.8405";
+
+async fn seed_patient(app: &TestApp, did: &str, age_years: u32) {
+    let patient = fixtures::patient(did, FhirPatient {
+        resource_type: "Patient".to_string(),
+        id: did.to_string(),
+        birth_date: (Utc::now().date_naive() - Months::new(age_years * 12)).to_string(),
+        ..Default::default()
+    });
+    app.database.create_patient(&patient, &app.config.ipfs_encryption_key).await.unwrap();
+}
+
+async fn link(app: &TestApp, guardian: &str, dependent: &str, relationship: &str, expires_at: Option<chrono::DateTime<Utc>>) -> reqwest::Response {
+    app.client
+        .post(app.url("/api/relationships"))
+        .bearer_auth(app.mint_jwt(ADMIN, Role::Admin))
+        .json(&json!({ "guardian_did": guardian, "dependent_did": dependent, "relationship": relationship, "expires_at": expires_at }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn read(app: &TestApp, caller: &str, did: &str) -> reqwest::StatusCode {
+    app.client
+        .get(app.url(&format!("/api/patients/{}", did)))
+        .bearer_auth(app.mint_jwt(caller, Role::Patient))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn guardians_read_dependents_records_but_cannot_manage_their_consents() {
+    let app = spawn_test_app().await;
+    seed_patient(&app, CHILD, 10).await;
+    assert_eq!(read(&app, GUARDIAN, CHILD).await, reqwest::StatusCode::FORBIDDEN);
+
+    let linked: Value = link(&app, GUARDIAN, CHILD, "parent", None).await.json().await.unwrap();
+    assert_eq!(linked["data"]["relationship"], "parent");
+    assert!(linked["data"]["expires_at"].is_string());
+    assert_eq!(link(&app, GUARDIAN, CHILD, "parent", None).await.status(), reqwest::StatusCode::CONFLICT);
+
+    assert_eq!(read(&app, GUARDIAN, CHILD).await, reqwest::StatusCode::OK);
+    let consent = app
+        .client
+        .post(app.url(&format!("/api/patients/{}/consents", CHILD)))
+        .bearer_auth(app.mint_jwt(GUARDIAN, Role::Patient))
+        .json(&json!({ "grantee_did": ADMIN, "data_classes": ["Patient"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(consent.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Only a guardian can book for the child
+    let booking = app
+        .client
+        .post(app.url("/api/appointments"))
+        .bearer_auth(app.mint_jwt(ADULT, Role::Patient))
+        .json(&json!({
+            "patient_did": CHILD,
+            "practitioner_did": "did:hedera:testnet:0.0.8406",
+            "start": Utc::now() + Duration::days(1),
+            "end": Utc::now() + Duration::days(1) + Duration::minutes(30),
+            "reason": { "coding": [], "text": "Check-up" },
+            "location": "Room 1",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(booking.status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn guardianship_ends_at_majority_or_expiry() {
+    let app = spawn_test_app().await;
+    seed_patient(&app, ADULT, 30).await;
+
+    let parent: Value = link(&app, PARENT_OF_ADULT, ADULT, "parent", None).await.json().await.unwrap();
+    assert_eq!(parent["success"], false);
+
+    let delegate = link(&app, PARENT_OF_ADULT, ADULT, "delegate", Some(Utc::now() + Duration::seconds(2))).await;
+    assert_eq!(delegate.status(), reqwest::StatusCode::OK);
+    assert_eq!(read(&app, PARENT_OF_ADULT, ADULT).await, reqwest::StatusCode::OK);
+
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    assert_eq!(read(&app, PARENT_OF_ADULT, ADULT).await, reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,104 @@
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::api::middleware::request_log::REQUEST_ID;
+use crate::config::{LogFormat, LoggingConfig};
+use crate::models::Role;
+use crate::telemetry;
+use crate::tests::helpers::spawn_test_app_with;
+
+const NAME: &str = "Wanjiku Kamau";
+const EMAIL: &str = "wanjiku.kamau@example.com";
+const PATIENT_DID: &str = "did:hedera:testnet:0.0.1";
+
+/// Everything the subscriber writes; with the JSON format, one object per line
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    fn lines(&self) -> Vec<Value> {
+        self.text().lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+    }
+
+    /// The request log line for `request_id`, written once the server is done with the response body
+    async fn request_line(&self, request_id: &str) -> Value {
+        for _ in 0..200 {
+            if let Some(line) = self.lines().into_iter().find(|line| line["message"] == "Request completed" && line["request_id"] == request_id) {
+                return line;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no request log line for {}", request_id);
+    }
+}
+
+fn request_id(response: &reqwest::Response) -> String {
+    response.headers()[REQUEST_ID.as_str()].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn requests_are_logged_by_route_template_without_bodies_paths_or_query_strings() {
+    // The server runs on this test's thread, so a thread-local subscriber sees everything it logs
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let logging = LoggingConfig { format: LogFormat::Json, redacted_routes: vec!["/api/patients/:id/timezone".to_string()] };
+    let (subscriber, _) = telemetry::subscriber(&logging, "healthcare_backend=debug", move || writer.clone(), vec![]);
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+    let app = spawn_test_app_with(|config| config.logging = logging.clone()).await;
+
+    // The second registration fails, and its error chain is logged under its request id
+    let registration = json!({ "name": NAME, "email": EMAIL, "public_key_hex": "00".repeat(32) });
+    let first = app.client.post(app.url("/api/auth/register")).header(REQUEST_ID.as_str(), "register-1").json(&registration).send().await.unwrap();
+    assert_eq!(request_id(&first), "register-1");
+    drop(first);
+    let second = app.client.post(app.url("/api/auth/register")).json(&registration).send().await.unwrap();
+    assert_eq!(second.status(), StatusCode::CONFLICT);
+    let second_id = request_id(&second);
+
+    let line = logs.request_line("register-1").await;
+    assert_eq!((line["method"].as_str(), line["route"].as_str(), line["status"].as_u64()), (Some("POST"), Some("/api/auth/register"), Some(200)));
+    assert_eq!(line["request_bytes"].as_u64(), Some(registration.to_string().len() as u64));
+    assert!(line["response_bytes"].as_u64().is_some_and(|bytes| bytes > 0) && line["latency_ms"].is_u64());
+    assert_eq!(logs.request_line(&second_id).await["status"], 409);
+    assert!(logs.lines().iter().any(|line| {
+        line["level"] == "WARN" && line["request_id"] == second_id.as_str() && line["message"].as_str().is_some_and(|message| message.starts_with("Request failed"))
+    }));
+
+    // The DID in the path and the query string stay out; the caller is a keyed hash
+    let token = app.mint_jwt(PATIENT_DID, Role::Patient);
+    let patient = app.client.get(app.url(&format!("/api/patients/{}?email={}", PATIENT_DID, EMAIL))).bearer_auth(&token).send().await.unwrap();
+    let line = logs.request_line(&request_id(&patient)).await;
+    assert_eq!(line["route"], "/api/patients/:id");
+    assert!(line["caller"].as_str().is_some_and(|caller| caller.len() == 16 && !PATIENT_DID.contains(caller)));
+
+    // Listed routes lose their template as well
+    let timezone = app.client.get(app.url(&format!("/api/patients/{}/timezone", PATIENT_DID))).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(logs.request_line(&request_id(&timezone)).await["route"], "[redacted]");
+
+    let written = logs.text();
+    assert!(!written.contains(EMAIL) && !written.contains("wanjiku.kamau%40example.com"), "the email was logged:\n{}", written);
+    assert!(!written.contains(NAME), "the name was logged:\n{}", written);
+    for line in logs.lines().iter().filter(|line| line["message"] == "Request completed") {
+        assert!(!line.to_string().contains(PATIENT_DID), "a path was logged: {}", line);
+    }
+
+    app.cleanup().await;
+}
@@ -0,0 +1,218 @@
+use bson::{doc, oid::ObjectId, Document};
+use chrono::{Duration, Utc};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::database::Database;
+use crate::fixtures;
+use crate::models::*;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+use crate::utils;
+
+const PATIENT: &str = "did:hedera:testnet:0.0.9981";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9982";
+const ADMIN: &str = "did:hedera:testnet:0.0.9983";
+
+/// A named patient the practitioner has seen, with a grant to read their record and encounters.
+/// Returns the encounter's id and FHIR id.
+async fn seed(app: &TestApp) -> (ObjectId, String) {
+    let name = FhirHumanName { family: Some("Wafula".to_string()), given: vec!["Nafula".to_string()], ..Default::default() };
+    let fhir_patient = FhirPatient { resource_type: "Patient".to_string(), id: PATIENT.to_string(), name: vec![name], ..Default::default() };
+    app.database.create_patient(&fixtures::patient(PATIENT, fhir_patient), &app.config.ipfs_encryption_key).await.unwrap();
+    let granted = app
+        .client
+        .post(app.url("/api/access/grants"))
+        .bearer_auth(app.mint_jwt(PATIENT, Role::Patient))
+        .json(&json!({ "patient_did": PATIENT, "grantee_did": PRACTITIONER, "permissions": ["Read", "ViewEncounters"], "expires_at": null }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(granted.status(), StatusCode::OK);
+    let encounter = fixtures::encounter(PATIENT, PRACTITIONER, EncounterStatus::InProgress);
+    (app.database.create_encounter(&encounter).await.unwrap(), encounter.fhir_encounter.id)
+}
+
+async fn get(app: &TestApp, path: &str, token: &str) -> reqwest::Response {
+    app.client.get(app.url(path)).bearer_auth(token).send().await.unwrap()
+}
+
+async fn post(app: &TestApp, path: &str, token: &str) -> Value {
+    app.client.post(app.url(path)).bearer_auth(token).send().await.unwrap().json().await.unwrap()
+}
+
+async fn delete(app: &TestApp, path: &str, token: &str) -> Value {
+    app.client.delete(app.url(path)).bearer_auth(token).send().await.unwrap().json().await.unwrap()
+}
+
+/// The FHIR ids of the encounters in the patient's `$everything`, as the practitioner reads it
+async fn everything_encounters(app: &TestApp) -> Vec<String> {
+    let token = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+    let bundle: Value = get(app, &format!("/api/patients/{}/$everything", PATIENT), &token).await.json().await.unwrap();
+    let entries = bundle["entry"].as_array().unwrap_or_else(|| panic!("{}", bundle));
+    entries.iter().filter(|entry| entry["resource"]["resourceType"] == "Encounter").map(|entry| entry["resource"]["id"].as_str().unwrap().to_string()).collect()
+}
+
+/// The FHIR ids of the encounters listed to the patient and to the practitioner
+async fn listed_encounters(app: &TestApp) -> (Vec<String>, Vec<String>) {
+    let ids = |response: Value| -> Vec<String> {
+        response["data"].as_array().unwrap().iter().map(|encounter| encounter["fhir_encounter"]["id"].as_str().unwrap().to_string()).collect()
+    };
+    let patient = get(app, "/api/encounters", &app.mint_jwt(PATIENT, Role::Patient)).await.json().await.unwrap();
+    let practitioner = get(app, "/api/encounters?role=practitioner", &app.mint_jwt(PRACTITIONER, Role::Practitioner)).await.json().await.unwrap();
+    (ids(patient), ids(practitioner))
+}
+
+async fn stored_patient(app: &TestApp) -> EncryptedPatient {
+    app.database.db.collection::<EncryptedPatient>("patients").find_one(doc! { "did": PATIENT }, None).await.unwrap().unwrap()
+}
+
+/// Run the erasure sweep as the hourly job does
+async fn erase(app: &TestApp) -> Vec<String> {
+    app.database.erase_deleted_patients(Utc::now() - Duration::days(app.config.soft_delete_grace_days)).await.unwrap()
+}
+
+/// Move the tombstone on `filter`'s document back past the grace period
+async fn backdate_deletion(app: &TestApp, collection: &str, filter: Document) {
+    let deleted_at = bson::to_bson(&(Utc::now() - Duration::days(app.config.soft_delete_grace_days + 1))).unwrap();
+    let updated = app.database.db.collection::<Document>(collection).update_one(filter, doc! { "$set": { "deleted_at": deleted_at } }, None).await.unwrap();
+    assert_eq!(updated.modified_count, 1);
+}
+
+#[tokio::test]
+async fn a_deleted_patient_disappears_from_every_read_until_restored_within_the_grace_period() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let admin = app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin);
+    let practitioner = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+    let patient_path = format!("/api/patients/{}", PATIENT);
+    let read = || async { get(&app, &patient_path, &practitioner).await.json::<Value>().await.unwrap() };
+    let found = || async {
+        let response: Value = app
+            .client
+            .get(app.url("/api/patients/search"))
+            .query(&[("q", "nafula")])
+            .bearer_auth(&practitioner)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        response["data"].as_array().unwrap().iter().any(|patient| patient["did"] == PATIENT)
+    };
+    assert_eq!(read().await["data"]["did"], PATIENT);
+    assert!(found().await);
+    assert_eq!(everything_encounters(&app).await.len(), 1);
+
+    assert_eq!(delete(&app, &format!("/api/admin/patients/{}", PATIENT), &admin).await["success"], true);
+    assert!(read().await["data"].is_null());
+    assert!(!found().await);
+    let everything: Value = get(&app, &format!("{}/$everything", patient_path), &practitioner).await.json().await.unwrap();
+    assert_eq!(everything["success"], false, "{}", everything);
+    // Their own tokens stop working too
+    assert_eq!(get(&app, &patient_path, &app.mint_jwt(PATIENT, Role::Patient)).await.status(), StatusCode::UNAUTHORIZED);
+    // Admins still see the tombstone
+    let listed: Value = get(&app, "/api/admin/patients?page_size=100", &admin).await.json().await.unwrap();
+    let summary = listed["data"]["items"].as_array().unwrap().iter().find(|patient| patient["did"] == PATIENT).cloned().unwrap();
+    assert!(!summary["deleted_at"].is_null());
+
+    assert_eq!(post(&app, &format!("/api/admin/patients/{}/restore", PATIENT), &admin).await["success"], true);
+    assert_eq!(read().await["data"]["did"], PATIENT);
+    assert!(found().await);
+    assert_eq!(everything_encounters(&app).await.len(), 1);
+    assert_eq!(get(&app, &patient_path, &app.mint_jwt(PATIENT, Role::Patient)).await.status(), StatusCode::OK);
+
+    // Past the grace period a deletion stays
+    assert_eq!(delete(&app, &format!("/api/admin/patients/{}", PATIENT), &admin).await["success"], true);
+    backdate_deletion(&app, "patients", doc! { "did": PATIENT }).await;
+    assert_eq!(post(&app, &format!("/api/admin/patients/{}/restore", PATIENT), &admin).await["success"], false);
+    assert!(read().await["data"].is_null());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_deleted_encounter_disappears_from_every_list_until_restored_within_the_grace_period() {
+    let app = spawn_test_app().await;
+    let (encounter_id, fhir_id) = seed(&app).await;
+    let admin = app.mint_jwt(ADMIN, Role::Admin);
+    let encounter_path = format!("/api/admin/encounters/{}", encounter_id.to_hex());
+
+    assert_eq!(listed_encounters(&app).await, (vec![fhir_id.clone()], vec![fhir_id.clone()]));
+    assert_eq!(everything_encounters(&app).await, vec![fhir_id.clone()]);
+
+    assert_eq!(delete(&app, &encounter_path, &admin).await["success"], true);
+    assert_eq!(listed_encounters(&app).await, (vec![], vec![]));
+    assert_eq!(everything_encounters(&app).await, Vec::<String>::new());
+    // Deleting it again finds no live encounter
+    assert_eq!(delete(&app, &encounter_path, &admin).await["success"], false);
+
+    assert_eq!(post(&app, &format!("{}/restore", encounter_path), &admin).await["success"], true);
+    assert_eq!(listed_encounters(&app).await, (vec![fhir_id.clone()], vec![fhir_id.clone()]));
+    assert_eq!(everything_encounters(&app).await, vec![fhir_id]);
+
+    // Past the grace period a deletion stays
+    assert_eq!(delete(&app, &encounter_path, &admin).await["success"], true);
+    backdate_deletion(&app, "encounters", doc! { "_id": encounter_id }).await;
+    assert_eq!(post(&app, &format!("{}/restore", encounter_path), &admin).await["success"], false);
+    assert_eq!(listed_encounters(&app).await, (vec![], vec![]));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_patient_deleted_past_the_grace_period_is_erased_by_destroying_their_key() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let admin = app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin);
+    let key = &app.config.ipfs_encryption_key;
+    let before = stored_patient(&app).await;
+    // The record opens with the patient's own key, not the master key
+    let data_key = Database::patient_data_key(&before, key).unwrap();
+    assert!(utils::decrypt(&before.encrypted_fhir_patient, &data_key).is_ok());
+    assert!(utils::decrypt(&before.encrypted_fhir_patient, key).is_err());
+
+    assert_eq!(delete(&app, &format!("/api/admin/patients/{}", PATIENT), &admin).await["success"], true);
+    // Still restorable, so nothing is erased yet
+    assert!(erase(&app).await.is_empty());
+    backdate_deletion(&app, "patients", doc! { "did": PATIENT }).await;
+    assert_eq!(erase(&app).await, vec![PATIENT.to_string()]);
+    assert!(erase(&app).await.is_empty());
+
+    let after = stored_patient(&app).await;
+    assert!(after.erased_at.is_some());
+    assert!(after.wrapped_data_key.is_none() && after.search_tokens.is_empty() && after.search_key_id.is_none());
+    // The ciphertext stays, with nothing left in the database that opens it
+    assert_eq!(after.encrypted_fhir_patient, before.encrypted_fhir_patient);
+    assert!(Database::decrypt_fhir_patient(&after, key).is_err());
+    assert!(app.database.find_patient_by_did(PATIENT, key, true).await.is_err());
+    assert_eq!(post(&app, &format!("/api/admin/patients/{}/restore", PATIENT), &admin).await["success"], false);
+    // Admins still see the tombstone, without a name
+    let listed: Value = get(&app, "/api/admin/patients?page_size=100", &admin).await.json().await.unwrap();
+    let summary = listed["data"]["items"].as_array().unwrap().iter().find(|patient| patient["did"] == PATIENT).cloned().unwrap();
+    assert!(!summary["erased_at"].is_null());
+    assert_eq!(summary["name"], "");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_record_from_before_data_keys_loses_its_ciphertext_when_erased() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let admin = app.mint_high_assurance_jwt(ADMIN, Role::PlatformAdmin);
+    // As the record was stored before data keys: encrypted with the master key
+    let legacy = utils::encrypt(br#"{"resourceType":"Patient"}"#, &app.config.ipfs_encryption_key).unwrap();
+    let update = doc! { "$set": { "encrypted_fhir_patient": &legacy }, "$unset": { "wrapped_data_key": "" } };
+    app.database.db.collection::<Document>("patients").update_one(doc! { "did": PATIENT }, update, None).await.unwrap();
+
+    assert_eq!(delete(&app, &format!("/api/admin/patients/{}", PATIENT), &admin).await["success"], true);
+    backdate_deletion(&app, "patients", doc! { "did": PATIENT }).await;
+    assert_eq!(erase(&app).await, vec![PATIENT.to_string()]);
+
+    let after = stored_patient(&app).await;
+    assert!(after.erased_at.is_some());
+    assert_eq!(after.encrypted_fhir_patient, "");
+
+    app.cleanup().await;
+}
@@ -0,0 +1,253 @@
+use bson::{doc, oid::ObjectId, Document};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::future::Future;
+
+use crate::fixtures;
+use crate::migrations::{DefaultTenant, Migration};
+use crate::models::*;
+use crate::tenancy::{self, DEFAULT_TENANT};
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const CLINIC_B: &str = "clinic-b";
+const PATIENT: &str = "did:hedera:testnet:0.0.9400";
+const PRACTITIONER_A: &str = "did:hedera:testnet:0.0.9401";
+const PRACTITIONER_B: &str = "did:hedera:testnet:0.0.9402";
+const ADMIN_A: &str = "did:hedera:testnet:0.0.9403";
+const ADMIN_B: &str = "did:hedera:testnet:0.0.9404";
+const PLATFORM_ADMIN: &str = "did:hedera:testnet:0.0.9405";
+
+async fn seed_practitioner(app: &TestApp, did: &str, tenant_id: Option<&str>) {
+    let practitioner = Practitioner {
+        id: None,
+        did: did.to_string(),
+        fhir_practitioner: FhirPractitioner {
+            resource_type: "Practitioner".to_string(),
+            id: did.to_string(),
+            identifier: vec![],
+            name: vec![],
+            qualification: vec![],
+            telecom: vec![],
+        },
+        license_verification: LicenseVerification {
+            license_number: String::new(),
+            issuing_authority: String::new(),
+            issue_date: String::new(),
+            expiry_date: String::new(),
+            hedera_transaction_id: String::new(),
+            ipfs_hash: String::new(),
+            verified: true,
+            expiry_warning_sent_at: None,
+        },
+        practitioner_type: PractitionerType::Clinician,
+        tenant_id: tenant_id.map(str::to_string),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+    app.database.create_practitioner(&practitioner).await.unwrap();
+}
+
+/// Written outside any tenant, so the encounter takes its practitioner's tenant
+async fn seed_encounter(app: &TestApp, practitioner_did: &str) -> ObjectId {
+    let encounter = fixtures::encounter(PATIENT, practitioner_did, EncounterStatus::InProgress);
+    app.database.create_encounter(&encounter).await.unwrap()
+}
+
+/// Practitioner A works at the original clinic (the default tenant), practitioner B at clinic B;
+/// the patient has an encounter with each
+async fn seed(app: &TestApp) -> (ObjectId, ObjectId) {
+    seed_practitioner(app, PRACTITIONER_A, None).await;
+    seed_practitioner(app, PRACTITIONER_B, Some(CLINIC_B)).await;
+    (seed_encounter(app, PRACTITIONER_A).await, seed_encounter(app, PRACTITIONER_B).await)
+}
+
+async fn in_tenant<F: Future>(tenant: &str, future: F) -> F::Output {
+    tenancy::scope(Some(tenant.to_string()), future).await
+}
+
+async fn raw(app: &TestApp, collection: &str, filter: Document) -> Document {
+    app.database.db.collection::<Document>(collection).find_one(filter, None).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn cross_tenant_reads_return_nothing_even_with_guessed_ids() {
+    let app = spawn_test_app().await;
+    let (encounter_a, encounter_b) = seed(&app).await;
+    assert_eq!(raw(&app, "encounters", doc! { "_id": encounter_b }).await.get_str("tenant_id").unwrap(), CLINIC_B);
+
+    assert!(in_tenant(CLINIC_B, app.database.get_encounter(encounter_a)).await.unwrap().is_none());
+    assert!(in_tenant(CLINIC_B, app.database.get_encounter(encounter_b)).await.unwrap().is_some());
+    let listed = in_tenant(CLINIC_B, app.database.list_recent_encounters(PATIENT, 10)).await.unwrap();
+    assert_eq!(listed.iter().map(|e| e.id.unwrap()).collect::<Vec<_>>(), vec![encounter_b]);
+    assert!(in_tenant(CLINIC_B, app.database.get_practitioner_by_did(PRACTITIONER_A)).await.unwrap().is_none());
+    // Records from before tenancy belong to the default tenant, and only to it
+    assert!(in_tenant(DEFAULT_TENANT, app.database.get_encounter(encounter_a)).await.unwrap().is_some());
+    assert!(in_tenant(DEFAULT_TENANT, app.database.get_encounter(encounter_b)).await.unwrap().is_none());
+
+    // Clinic B's admin cannot delete clinic A's encounter by its id, nor change anything in it
+    let deleted: Value = app
+        .client
+        .delete(app.url(&format!("/api/admin/encounters/{}", encounter_a)))
+        .bearer_auth(app.mint_tenant_jwt(ADMIN_B, Role::Admin, CLINIC_B))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["success"], false, "{}", deleted);
+    assert!(raw(&app, "encounters", doc! { "_id": encounter_a }).await.get("deleted_at").is_none());
+    // Nor can clinic A's practitioner move clinic B's encounter along
+    let moved: Value = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/status", encounter_b)))
+        .bearer_auth(app.mint_high_assurance_jwt(PRACTITIONER_A, Role::Practitioner))
+        .json(&json!({ "status": "finished" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(moved["success"], false, "{}", moved);
+    assert_eq!(raw(&app, "encounters", doc! { "_id": encounter_b }).await.get_str("status").unwrap(), "in-progress");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn tenant_admins_see_only_their_tenant_and_platform_admins_see_all() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let practitioners = |token: String| {
+        let app = &app;
+        async move {
+            let body: Value = app.client.get(app.url("/api/admin/practitioners")).bearer_auth(token).send().await.unwrap().json().await.unwrap();
+            let mut dids: Vec<String> = body["data"].as_array().unwrap().iter().map(|p| p["did"].as_str().unwrap().to_string()).collect();
+            dids.sort();
+            dids
+        }
+    };
+
+    assert_eq!(practitioners(app.mint_high_assurance_jwt(ADMIN_A, Role::Admin)).await, vec![PRACTITIONER_A]);
+    let in_clinic_b: Vec<String> = in_tenant(CLINIC_B, app.database.list_practitioners(None)).await.unwrap().into_iter().map(|p| p.did).collect();
+    assert_eq!(in_clinic_b, vec![PRACTITIONER_B]);
+    assert_eq!(practitioners(app.mint_high_assurance_jwt(PLATFORM_ADMIN, Role::PlatformAdmin)).await, vec![PRACTITIONER_A, PRACTITIONER_B]);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn audit_logs_are_stamped_and_read_per_tenant() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+
+    // Any request of clinic B's practitioner is audited in clinic B
+    let searched = app
+        .client
+        .get(app.url("/api/patients/search"))
+        .query(&[("q", "jane")])
+        .bearer_auth(app.mint_tenant_jwt(PRACTITIONER_B, Role::Practitioner, CLINIC_B))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(searched.status(), reqwest::StatusCode::OK);
+    let log = raw(&app, "audit_logs", doc! { "did": PRACTITIONER_B, "action": "search_patients" }).await;
+    assert_eq!(log.get_str("tenant_id").unwrap(), CLINIC_B);
+
+    let from = (Utc::now() - Duration::hours(1)).to_rfc3339();
+    let to = (Utc::now() + Duration::hours(1)).to_rfc3339();
+    let actors = |token: String| {
+        let (app, from, to) = (&app, from.clone(), to.clone());
+        async move {
+            let body: Value = app
+                .client
+                .get(app.url("/api/admin/audit/stats"))
+                .query(&[("from", from.as_str()), ("to", to.as_str()), ("group_by", "did")])
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["success"], true, "{}", body);
+            body["data"]["groups"].as_array().unwrap().iter().map(|g| g["key"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        }
+    };
+    let clinic_b = actors(app.mint_tenant_jwt(ADMIN_B, Role::Admin, CLINIC_B)).await;
+    assert!(clinic_b.contains(&PRACTITIONER_B.to_string()));
+    assert!(!clinic_b.iter().any(|did| did == PRACTITIONER_A || did == PATIENT));
+    assert!(!actors(app.mint_jwt(ADMIN_A, Role::Admin)).await.contains(&PRACTITIONER_B.to_string()));
+    assert!(actors(app.mint_jwt(PLATFORM_ADMIN, Role::PlatformAdmin)).await.contains(&PRACTITIONER_B.to_string()));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn appointments_join_the_practitioners_tenant() {
+    let app = spawn_test_app().await;
+    seed(&app).await;
+    let appointment = Appointment {
+        id: None,
+        patient_did: PATIENT.to_string(),
+        practitioner_did: PRACTITIONER_B.to_string(),
+        status: AppointmentStatus::Requested,
+        start: Utc::now() + Duration::days(1),
+        end: Utc::now() + Duration::days(1) + Duration::minutes(30),
+        reason: FhirCodeableConcept { coding: vec![], text: Some("Check-up".to_string()) },
+        location: "Clinic B".to_string(),
+        encounter_id: None,
+        reminder_sent_at: ReminderMarkers::default(),
+        slots: vec![],
+        tenant_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    // Booked by the patient, outside any tenant
+    let id = app.database.create_appointment(&appointment).await.unwrap();
+
+    assert_eq!(raw(&app, "appointments", doc! { "_id": id }).await.get_str("tenant_id").unwrap(), CLINIC_B);
+    assert!(in_tenant(DEFAULT_TENANT, app.database.get_appointment(id)).await.unwrap().is_none());
+    assert!(in_tenant(CLINIC_B, app.database.get_appointment(id)).await.unwrap().is_some());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn platform_resources_need_a_platform_admin() {
+    let app = spawn_test_app().await;
+
+    let jobs = |token: String| app.client.get(app.url("/api/admin/jobs")).bearer_auth(token).send();
+    assert_eq!(jobs(app.mint_jwt(ADMIN_A, Role::Admin)).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+    assert_eq!(jobs(app.mint_jwt(PLATFORM_ADMIN, Role::PlatformAdmin)).await.unwrap().status(), reqwest::StatusCode::OK);
+
+    // Patients are shared by every tenant, so no tenant admin manages their accounts
+    let disabled = app
+        .client
+        .post(app.url(&format!("/api/admin/patients/{}/disable", PATIENT)))
+        .bearer_auth(app.mint_high_assurance_jwt(ADMIN_A, Role::Admin))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(disabled.status(), reqwest::StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn the_migration_puts_existing_data_in_the_default_tenant() {
+    let app = spawn_test_app().await;
+    let (encounter_a, encounter_b) = seed(&app).await;
+    assert!(raw(&app, "encounters", doc! { "_id": encounter_a }).await.get("tenant_id").is_none());
+
+    DefaultTenant.up(&app.database).await.unwrap();
+
+    assert_eq!(raw(&app, "encounters", doc! { "_id": encounter_a }).await.get_str("tenant_id").unwrap(), DEFAULT_TENANT);
+    assert_eq!(raw(&app, "practitioners", doc! { "did": PRACTITIONER_A }).await.get_str("tenant_id").unwrap(), DEFAULT_TENANT);
+    assert_eq!(raw(&app, "encounters", doc! { "_id": encounter_b }).await.get_str("tenant_id").unwrap(), CLINIC_B);
+    assert!(in_tenant(DEFAULT_TENANT, app.database.get_encounter(encounter_a)).await.unwrap().is_some());
+
+    app.cleanup().await;
+}
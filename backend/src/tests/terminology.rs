@@ -0,0 +1,28 @@
+use serde_json::Value;
+
+use crate::models::Role;
+use crate::tests::helpers::spawn_test_app;
+
+#[tokio::test]
+async fn terminology_search_autocompletes_codes_for_signed_in_users() {
+    let app = spawn_test_app().await;
+    let search = |query: &str| app.client.get(app.url(&format!("/api/terminology/search?{}", query)));
+
+    let found: Value = search("system=icd10&q=diab&limit=2")
+        .bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.8901", Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let codes: Vec<&str> = found["data"].as_array().unwrap().iter().map(|concept| concept["code"].as_str().unwrap()).collect();
+    assert_eq!(codes, ["E10.9", "E11.9"]);
+    assert_eq!(found["data"][0]["system"], "http://hl7.org/fhir/sid/icd-10-cm");
+
+    let unknown_system = search("system=cpt&q=99213").bearer_auth(app.mint_jwt("did:hedera:testnet:0.0.8901", Role::Practitioner)).send().await.unwrap();
+    assert_eq!(unknown_system.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(search("system=loinc&q=heart").send().await.unwrap().status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    app.cleanup().await;
+}
@@ -0,0 +1,98 @@
+use opentelemetry::trace::{SpanId, SpanKind, TraceId, TracerProvider as _};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+use opentelemetry_sdk::trace::TracerProvider;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::config::LoggingConfig;
+use crate::fixtures;
+use crate::models::*;
+use crate::telemetry;
+use crate::tests::helpers::{spawn_test_app, TestApp};
+
+const PRACTITIONER_DID: &str = "did:hedera:testnet:0.0.2";
+/// What the front proxy sends: trace 4bf9…4736, from its span 00f0…02b7, sampled
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+/// A registered patient's encounter, ready to be finalized by `PRACTITIONER_DID`
+async fn in_progress_encounter(app: &TestApp) -> String {
+    let registration: Value = app
+        .client
+        .post(app.url("/api/auth/register"))
+        .json(&json!({ "name": "Achieng Otieno", "email": "achieng@example.com", "public_key_hex": "00".repeat(32) }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let patient_did = registration["data"]["user"]["did"].as_str().expect("registration failed").to_string();
+    let encounter = fixtures::encounter(&patient_did, PRACTITIONER_DID, EncounterStatus::InProgress);
+    app.database.create_encounter(&encounter).await.unwrap().to_hex()
+}
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<String> {
+    span.attributes.iter().find(|attribute| attribute.key.as_str() == key).map(|attribute| attribute.value.as_str().into_owned())
+}
+
+#[tokio::test]
+async fn finalizing_an_encounter_continues_the_proxys_trace_down_to_mongodb_and_ipfs() {
+    // The server runs on this test's thread, so a thread-local subscriber sees all of its spans
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    let layers = vec![telemetry::layer(provider.tracer("healthcare-backend"))];
+    let (subscriber, _) = telemetry::subscriber(&LoggingConfig::default(), "healthcare_backend=info", std::io::sink, layers);
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+    let app = spawn_test_app().await;
+    let encounter_id = in_progress_encounter(&app).await;
+
+    let finalized: Value = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(app.mint_high_assurance_jwt(PRACTITIONER_DID, Role::Practitioner))
+        .header("traceparent", TRACEPARENT)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(finalized["success"], true, "{}", finalized);
+    let cid = finalized["data"].as_str().unwrap();
+
+    // Spans are exported as they end, which for the request is just after the response went out
+    let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+    let mut spans = Vec::new();
+    for _ in 0..200 {
+        spans = exporter.get_finished_spans().unwrap().into_iter().filter(|span| span.span_context.trace_id() == trace_id).collect();
+        if spans.iter().any(|span| span.span_kind == SpanKind::Server) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let named = |name: &str| spans.iter().find(|span| span.name == name).unwrap_or_else(|| panic!("no {} span in {:#?}", name, spans));
+    let children = |parent: &SpanData| spans.iter().filter(|span| span.parent_span_id == parent.span_context.span_id()).collect::<Vec<_>>();
+
+    let request = named("POST /api/encounters/:id/finalize");
+    assert_eq!((request.span_kind.clone(), request.parent_span_id), (SpanKind::Server, SpanId::from_hex("00f067aa0ba902b7").unwrap()));
+    assert_eq!(attribute(request, "http.response.status_code").as_deref(), Some("200"));
+
+    let finalize = named("encounter.finalize");
+    assert_eq!(finalize.parent_span_id, request.span_context.span_id());
+    let upload = named("encounter.upload_bundle");
+    assert_eq!(upload.parent_span_id, finalize.span_context.span_id());
+
+    // Claiming and recording the encounter are commands under finalize, reading the patient and
+    // storing the bundle sit under the upload
+    let collections = |parent: &SpanData| {
+        children(parent).into_iter().filter(|span| span.span_kind == SpanKind::Client).filter_map(|span| attribute(span, "db.mongodb.collection")).collect::<Vec<_>>()
+    };
+    assert!(collections(finalize).iter().filter(|collection| *collection == "encounters").count() >= 2, "{:#?}", spans);
+    assert!(collections(upload).contains(&"patients".to_string()), "{:#?}", spans);
+    let ipfs = named("ipfs.add");
+    assert_eq!((ipfs.parent_span_id, ipfs.span_kind.clone()), (upload.span_context.span_id(), SpanKind::Client));
+    assert_eq!(attribute(ipfs, "ipfs.cid").as_deref(), Some(cid));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,177 @@
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::auditing::AuditLogService;
+use crate::config::JobConfig;
+use crate::fixtures;
+use crate::jobs::JobWorkerPool;
+use crate::models::*;
+use crate::services::webhooks::{signature, WebhookDeliveryHandler, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::services::WebhookService;
+use crate::tests::helpers::{spawn_test_app_with, TestApp};
+
+const ADMIN: &str = "did:hedera:testnet:0.0.9701";
+const PRACTITIONER: &str = "did:hedera:testnet:0.0.9702";
+const PATIENT: &str = "did:hedera:testnet:0.0.9703";
+
+/// A worker for webhook deliveries, as `main` registers it
+fn worker(app: &TestApp) -> JobWorkerPool {
+    let webhooks = WebhookService::new(
+        app.database.clone(),
+        app.database.clone(),
+        reqwest::Client::new(),
+        app.config.clone(),
+        Arc::new(AuditLogService::new(app.database.clone())),
+    );
+    JobWorkerPool::new(app.database.clone(), JobConfig::default()).register(Arc::new(WebhookDeliveryHandler::new(Arc::new(webhooks))))
+}
+
+async fn finalized_encounter(app: &TestApp) -> String {
+    let patient = fixtures::patient(PATIENT, FhirPatient { resource_type: "Patient".to_string(), id: PATIENT.to_string(), ..Default::default() });
+    app.database.create_patient(&patient, &app.config.ipfs_encryption_key).await.unwrap();
+    app.register_practitioner(PRACTITIONER, None).await;
+    let token = app.mint_jwt(PRACTITIONER, Role::Practitioner);
+    let created: Value = app
+        .client
+        .post(app.url("/api/encounters"))
+        .bearer_auth(&token)
+        .json(&json!({
+            "patient_did": PATIENT,
+            "practitioner_did": PRACTITIONER,
+            "class": { "system": null, "code": "AMB", "display": "ambulatory" },
+            "reason_code": [],
+            "period": { "start": null, "end": null }
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let encounter_id = created["data"]["_id"]["$oid"].as_str().unwrap().to_string();
+    let finalized: Value = app
+        .client
+        .post(app.url(&format!("/api/encounters/{}/finalize", encounter_id)))
+        .bearer_auth(app.mint_high_assurance_jwt(PRACTITIONER, Role::Practitioner))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(finalized["success"], true, "{}", finalized);
+    encounter_id
+}
+
+/// The delivery is queued by a subscriber on the event bus, after the request returned
+async fn wait_for_queued_delivery(app: &TestApp) {
+    for _ in 0..50 {
+        if app.database.count_jobs(Some(DELIVER_WEBHOOK_JOB)).await.unwrap().pending > 0 {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("no webhook delivery was queued");
+}
+
+#[tokio::test]
+async fn finalized_encounters_are_delivered_signed_and_retried_after_a_failure() {
+    let app = spawn_test_app_with(|config| config.webhooks.allow_http = true).await;
+    let subscriber = MockServer::start().await;
+    Mock::given(method("POST")).and(path("/hooks")).respond_with(ResponseTemplate::new(503)).up_to_n_times(1).mount(&subscriber).await;
+    Mock::given(method("POST")).and(path("/hooks")).respond_with(ResponseTemplate::new(200)).mount(&subscriber).await;
+    let admin = app.mint_jwt(ADMIN, Role::Admin);
+
+    let created: Value = app
+        .client
+        .post(app.url("/api/webhooks"))
+        .bearer_auth(&admin)
+        .json(&json!({ "url": format!("{}/hooks", subscriber.uri()), "events": ["encounter.finalized"] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(created["success"], true, "{}", created);
+    let webhook_id = created["data"]["id"].as_str().unwrap().to_string();
+    let secret = created["data"]["secret"].as_str().unwrap().to_string();
+    let listed: Value = app.client.get(app.url("/api/webhooks")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert!(listed["data"][0].get("secret").is_none(), "the secret is only shown once");
+
+    let encounter_id = finalized_encounter(&app).await;
+    wait_for_queued_delivery(&app).await;
+    let worker = worker(&app);
+    let now = Utc::now();
+    assert!(worker.run_next("webhooks/0", now).await.unwrap());
+    assert!(!worker.run_next("webhooks/0", now).await.unwrap(), "the retry waits out its backoff");
+    assert!(worker.run_next("webhooks/0", now + Duration::seconds(31)).await.unwrap());
+
+    let requests = subscriber.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let (first, second): (Value, Value) = (serde_json::from_slice(&requests[0].body).unwrap(), serde_json::from_slice(&requests[1].body).unwrap());
+    assert_eq!(first["id"], second["id"], "a retry carries the same event id");
+    assert_eq!(second["type"], "encounter.finalized");
+    assert_eq!(second["data"], json!({ "encounter_id": encounter_id }));
+    let timestamp: i64 = requests[1].headers.get(TIMESTAMP_HEADER).unwrap().to_str().unwrap().parse().unwrap();
+    assert_eq!(requests[1].headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(), signature(&secret, timestamp, &requests[1].body));
+
+    let deliveries: Value = app
+        .client
+        .get(app.url(&format!("/api/webhooks/{}/deliveries", webhook_id)))
+        .bearer_auth(&admin)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let deliveries = deliveries["data"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!((deliveries[0]["attempt"].as_u64(), deliveries[0]["status_code"].as_u64()), (Some(2), Some(200)));
+    assert_eq!((deliveries[1]["attempt"].as_u64(), deliveries[1]["status_code"].as_u64()), (Some(1), Some(503)));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn webhooks_are_managed_by_admins_of_their_own_tenant_and_can_be_pinged() {
+    let app = spawn_test_app_with(|config| config.webhooks.allow_http = true).await;
+    let subscriber = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(204)).mount(&subscriber).await;
+    let admin = app.mint_tenant_jwt(ADMIN, Role::Admin, "kisumu");
+    let create = |token: String, body: Value| app.client.post(app.url("/api/webhooks")).bearer_auth(token).json(&body).send();
+
+    let insecure: Value = create(admin.clone(), json!({ "url": "ftp://clinic.example/hooks", "events": ["credential.issued"] })).await.unwrap().json().await.unwrap();
+    assert_eq!(insecure["error"], "Webhook URLs have to use https");
+    let ping: Value = create(admin.clone(), json!({ "url": subscriber.uri(), "events": ["ping"] })).await.unwrap().json().await.unwrap();
+    assert_eq!(ping["success"], false);
+    let practitioner = create(app.mint_jwt(PRACTITIONER, Role::Practitioner), json!({ "url": subscriber.uri(), "events": ["credential.issued"] })).await.unwrap();
+    assert_eq!(practitioner.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let created: Value = create(admin.clone(), json!({ "url": subscriber.uri(), "events": ["access.granted"], "active": false })).await.unwrap().json().await.unwrap();
+    assert_eq!(created["data"]["tenant_id"], "kisumu");
+    let webhook_id = created["data"]["id"].as_str().unwrap().to_string();
+    let other_tenant = app.mint_tenant_jwt("did:hedera:testnet:0.0.9704", Role::Admin, "nakuru");
+    let hidden = app.client.get(app.url(&format!("/api/webhooks/{}", webhook_id))).bearer_auth(other_tenant).send().await.unwrap();
+    assert_eq!(hidden.json::<Value>().await.unwrap()["error"], "Webhook not found");
+
+    // Inactive webhooks still get pings, to check the receiving end before switching them on
+    let test_url = app.url(&format!("/api/webhooks/{}/test", webhook_id));
+    let pinged: Value = app.client.post(test_url).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(pinged["data"]["type"], "ping");
+    assert!(worker(&app).run_next("webhooks/0", Utc::now()).await.unwrap());
+    let requests = subscriber.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].headers.get("X-WeCare-Event").unwrap(), "ping");
+
+    let deleted = app.client.delete(app.url(&format!("/api/webhooks/{}", webhook_id))).bearer_auth(&admin).send().await.unwrap();
+    assert_eq!(deleted.status(), reqwest::StatusCode::OK);
+    let listed: Value = app.client.get(app.url("/api/webhooks")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(listed["data"], json!([]));
+
+    app.cleanup().await;
+}
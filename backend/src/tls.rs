@@ -0,0 +1,183 @@
+//! Loading and hot-reloading the HTTPS certificate.
+
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+
+/// How often the certificate files are checked for renewal
+pub const CERT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Load the certificate and key, failing with an error that names the offending file
+pub async fn load_rustls_config(cert_path: &Path, key_path: &Path) -> Result<RustlsConfig> {
+    ensure_readable(cert_path, "TLS certificate")?;
+    ensure_readable(key_path, "TLS private key")?;
+    RustlsConfig::from_pem_file(cert_path, key_path)
+        .await
+        .with_context(|| format!(
+            "failed to parse TLS certificate {} / key {} (expected PEM-encoded files)",
+            cert_path.display(),
+            key_path.display()
+        ))
+}
+
+fn ensure_readable(path: &Path, what: &str) -> Result<()> {
+    std::fs::File::open(path)
+        .map(drop)
+        .map_err(|e| anyhow!("{} {} could not be read: {}", what, path.display(), e))
+}
+
+/// Tracks the certificate files and re-applies them to a live `RustlsConfig` when they change
+pub struct CertWatcher {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    fingerprint: Option<[u8; 32]>,
+}
+
+impl CertWatcher {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        let mut watcher = Self { cert_path: cert_path.into(), key_path: key_path.into(), fingerprint: None };
+        watcher.fingerprint = watcher.current_fingerprint().ok();
+        watcher
+    }
+
+    // Content hash rather than mtime: renewals can land within the filesystem's timestamp resolution
+    fn current_fingerprint(&self) -> std::io::Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        hasher.update(std::fs::read(&self.cert_path)?);
+        hasher.update(std::fs::read(&self.key_path)?);
+        Ok(hasher.finalize().into())
+    }
+
+    /// Reload when the files differ from what was last applied. Returns whether a reload happened.
+    pub async fn reload_if_changed(&mut self, config: &RustlsConfig) -> Result<bool> {
+        let fingerprint = self.current_fingerprint()?;
+        if self.fingerprint == Some(fingerprint) {
+            return Ok(false);
+        }
+        self.reload(config).await?;
+        self.fingerprint = Some(fingerprint);
+        Ok(true)
+    }
+
+    /// Unconditionally re-read the files into `config`; on error the previous certificate stays active
+    pub async fn reload(&self, config: &RustlsConfig) -> Result<()> {
+        config
+            .reload_from_pem_file(&self.cert_path, &self.key_path)
+            .await
+            .with_context(|| format!("failed to reload TLS certificate from {}", self.cert_path.display()))
+    }
+}
+
+/// Poll for renewed certificates, and reload immediately on SIGHUP
+pub fn spawn_reloader(config: RustlsConfig, mut watcher: CertWatcher) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("failed to install SIGHUP handler");
+        let mut interval = time::interval(CERT_POLL_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            #[cfg(unix)]
+            let forced = tokio::select! {
+                _ = interval.tick() => false,
+                _ = hangup.recv() => true,
+            };
+            #[cfg(not(unix))]
+            let forced = {
+                interval.tick().await;
+                false
+            };
+
+            let result = if forced {
+                watcher.reload(&config).await.map(|_| true)
+            } else {
+                watcher.reload_if_changed(&config).await
+            };
+            match result {
+                Ok(true) => tracing::info!("Reloaded TLS certificate from {}", watcher.cert_path.display()),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Keeping the current TLS certificate: {:#}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempCert {
+        dir: PathBuf,
+    }
+
+    impl TempCert {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("healthcare-tls-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let temp = Self { dir };
+            temp.renew();
+            temp
+        }
+
+        fn cert(&self) -> PathBuf {
+            self.dir.join("cert.pem")
+        }
+
+        fn key(&self) -> PathBuf {
+            self.dir.join("key.pem")
+        }
+
+        fn renew(&self) {
+            let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            std::fs::write(self.cert(), certified.cert.pem()).unwrap();
+            std::fs::write(self.key(), certified.key_pair.serialize_pem()).unwrap();
+        }
+    }
+
+    impl Drop for TempCert {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn reloads_only_after_files_change() {
+        let temp = TempCert::new();
+        let config = load_rustls_config(&temp.cert(), &temp.key()).await.unwrap();
+        let mut watcher = CertWatcher::new(temp.cert(), temp.key());
+
+        assert!(!watcher.reload_if_changed(&config).await.unwrap());
+
+        temp.renew();
+        assert!(watcher.reload_if_changed(&config).await.unwrap());
+        assert!(!watcher.reload_if_changed(&config).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn broken_renewal_keeps_watching() {
+        let temp = TempCert::new();
+        let config = load_rustls_config(&temp.cert(), &temp.key()).await.unwrap();
+        let mut watcher = CertWatcher::new(temp.cert(), temp.key());
+
+        std::fs::write(temp.cert(), "not a certificate").unwrap();
+        assert!(watcher.reload_if_changed(&config).await.is_err());
+
+        temp.renew();
+        assert!(watcher.reload_if_changed(&config).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_named_in_the_error() {
+        let temp = TempCert::new();
+        let missing = temp.dir.join("missing.pem");
+
+        let err = load_rustls_config(&missing, &temp.key()).await.unwrap_err();
+
+        assert!(err.to_string().starts_with("TLS certificate"));
+        assert!(err.to_string().contains("missing.pem"));
+    }
+}
@@ -0,0 +1,372 @@
+//! AES-256-GCM in fixed-size frames, for objects too large to decrypt in one piece.
+//!
+//! An object is a header, the plaintext's SHA-256 encrypted on its own, then the frames:
+//!
+//! ```text
+//! magic (4) | frame size (u32) | plaintext length (u64) | nonce prefix (8)
+//! encrypted digest (32 + tag)
+//! frame 0 (frame size + tag) | frame 1 | ... | last frame (the remainder + tag)
+//! ```
+//!
+//! Frame `i` uses the nonce `prefix || i` and the digest `prefix || u32::MAX`. Every frame
+//! authenticates the header, so frames cannot be moved between objects, reordered or
+//! dropped from the end without decryption failing. Any frame can be decrypted without
+//! the ones before it, which is what lets downloads seek.
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+use super::EncryptionKey;
+
+/// Plaintext bytes per frame; a download holds about two frames in memory
+pub const FRAME_SIZE: usize = 64 * 1024;
+const MAGIC: [u8; 4] = *b"\x89HRF";
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 4 + 4 + 8 + 8;
+const DIGEST_LEN: usize = 32;
+/// Everything before the first frame
+pub const PREAMBLE_LEN: usize = HEADER_LEN + DIGEST_LEN + TAG_LEN;
+const DIGEST_NONCE_INDEX: u32 = u32::MAX;
+
+/// Plaintext or stored bytes, as they arrive
+pub type ByteStream = BoxStream<'static, Result<Bytes>>;
+
+/// Whether `stored` is in the framed format rather than the base64 one of [`super::encrypt`]
+pub fn is_chunked(stored: &[u8]) -> bool {
+    stored.starts_with(&MAGIC)
+}
+
+pub fn encrypt(data: &[u8], key: &EncryptionKey) -> Result<Vec<u8>> {
+    encrypt_with_frame_size(data, key, FRAME_SIZE)
+}
+
+pub fn encrypt_with_frame_size(data: &[u8], key: &EncryptionKey, frame_size: usize) -> Result<Vec<u8>> {
+    let cipher = key.cipher()?;
+    let (raw, preamble) = preamble(&cipher, data, frame_size)?;
+    let mut stored = Vec::with_capacity(stored_len(data.len(), frame_size) as usize);
+    stored.extend_from_slice(&preamble);
+    for (index, frame) in data.chunks(frame_size).enumerate() {
+        stored.extend_from_slice(&seal(&cipher, &raw, index as u32, frame)?);
+    }
+    Ok(stored)
+}
+
+/// Encrypt `data` into a stream of the stored object, with its length.
+///
+/// Frames are sealed from `data` as the stream is read, so an upload holds the plaintext and
+/// the frame being sent rather than a second, encrypted copy of the whole object.
+pub fn encrypt_stream(data: Bytes, key: &EncryptionKey) -> Result<(u64, ByteStream)> {
+    let cipher = key.cipher()?;
+    let (raw, preamble) = preamble(&cipher, &data, FRAME_SIZE)?;
+    let len = stored_len(data.len(), FRAME_SIZE);
+    let frames = (0..data.len().div_ceil(FRAME_SIZE)).map(move |index| {
+        let frame = &data[index * FRAME_SIZE..data.len().min((index + 1) * FRAME_SIZE)];
+        seal(&cipher, &raw, index as u32, frame).map(Bytes::from)
+    });
+    Ok((len, stream::iter(std::iter::once(Ok(Bytes::from(preamble))).chain(frames)).boxed()))
+}
+
+/// The header of an object holding `data` and everything stored before its first frame
+fn preamble(cipher: &Aes256Gcm, data: &[u8], frame_size: usize) -> Result<([u8; HEADER_LEN], Vec<u8>)> {
+    if frame_size == 0 || data.len().div_ceil(frame_size) >= DIGEST_NONCE_INDEX as usize {
+        return Err(anyhow!("Frame size {} does not fit {} bytes", frame_size, data.len()));
+    }
+    let mut nonce_prefix = [0u8; 8];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    let mut raw = [0u8; HEADER_LEN];
+    raw[..4].copy_from_slice(&MAGIC);
+    raw[4..8].copy_from_slice(&(frame_size as u32).to_be_bytes());
+    raw[8..16].copy_from_slice(&(data.len() as u64).to_be_bytes());
+    raw[16..].copy_from_slice(&nonce_prefix);
+
+    let mut preamble = Vec::with_capacity(PREAMBLE_LEN);
+    preamble.extend_from_slice(&raw);
+    let digest = Sha256::digest(data);
+    preamble.extend_from_slice(&seal(cipher, &raw, DIGEST_NONCE_INDEX, &digest)?);
+    Ok((raw, preamble))
+}
+
+/// Size of the stored object for `plaintext_len` bytes in frames of `frame_size`
+fn stored_len(plaintext_len: usize, frame_size: usize) -> u64 {
+    (PREAMBLE_LEN + plaintext_len + plaintext_len.div_ceil(frame_size) * TAG_LEN) as u64
+}
+
+/// Decrypt a whole object in either format
+pub fn decrypt(stored: &[u8], key: &EncryptionKey) -> Result<Vec<u8>> {
+    if !is_chunked(stored) {
+        return super::decrypt(std::str::from_utf8(stored)?, key);
+    }
+    let header = Header::parse(stored.get(..PREAMBLE_LEN).ok_or_else(|| anyhow!("Invalid encrypted data length"))?, key)?;
+    let cipher = key.cipher()?;
+    let mut plaintext = Vec::with_capacity(header.plaintext_len as usize);
+    let mut offset = PREAMBLE_LEN;
+    for index in 0..header.frame_count() {
+        let frame_len = header.stored_frame_len(index);
+        let frame = stored.get(offset..offset + frame_len).ok_or_else(|| anyhow!("Encrypted object is truncated"))?;
+        plaintext.extend_from_slice(&header.open_frame(&cipher, index, frame)?);
+        offset += frame_len;
+    }
+    Ok(plaintext)
+}
+
+/// The header and digest of a framed object, which is all a reader needs to find any frame
+#[derive(Debug, Clone)]
+pub struct Header {
+    raw: [u8; HEADER_LEN],
+    frame_size: u64,
+    plaintext_len: u64,
+    digest: [u8; DIGEST_LEN],
+}
+
+/// Where the frames covering a plaintext range sit in the stored object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSpan {
+    pub first_frame: u64,
+    /// Bytes of the stored object to read
+    pub stored: Range<u64>,
+    /// Plaintext bytes to drop from the start of the first frame
+    pub skip: usize,
+}
+
+impl Header {
+    /// Parse the first [`PREAMBLE_LEN`] bytes of an object
+    pub fn parse(preamble: &[u8], key: &EncryptionKey) -> Result<Self> {
+        if preamble.len() < PREAMBLE_LEN || !is_chunked(preamble) {
+            return Err(anyhow!("Not a framed encrypted object"));
+        }
+        let raw: [u8; HEADER_LEN] = preamble[..HEADER_LEN].try_into()?;
+        let frame_size = u32::from_be_bytes(raw[4..8].try_into()?) as u64;
+        let plaintext_len = u64::from_be_bytes(raw[8..16].try_into()?);
+        if frame_size == 0 {
+            return Err(anyhow!("Framed object has a frame size of 0"));
+        }
+        let digest = open(&key.cipher()?, &raw, DIGEST_NONCE_INDEX, &preamble[HEADER_LEN..PREAMBLE_LEN])?;
+        Ok(Self { raw, frame_size, plaintext_len, digest: digest.try_into().map_err(|_| anyhow!("Invalid digest length"))? })
+    }
+
+    pub fn plaintext_len(&self) -> u64 {
+        self.plaintext_len
+    }
+
+    /// SHA-256 of the whole plaintext, as hex
+    pub fn digest_hex(&self) -> String {
+        hex::encode(self.digest)
+    }
+
+    fn frame_count(&self) -> u64 {
+        self.plaintext_len.div_ceil(self.frame_size)
+    }
+
+    fn stored_frame_len(&self, index: u64) -> usize {
+        (self.frame_size.min(self.plaintext_len - index * self.frame_size)) as usize + TAG_LEN
+    }
+
+    fn stored_offset(&self, index: u64) -> u64 {
+        PREAMBLE_LEN as u64 + index * (self.frame_size + TAG_LEN as u64)
+    }
+
+    /// The frames holding the non-empty plaintext `range`, which must lie within the object
+    pub fn span(&self, range: &Range<u64>) -> FrameSpan {
+        let first_frame = range.start / self.frame_size;
+        let last_frame = (range.end - 1) / self.frame_size;
+        FrameSpan {
+            first_frame,
+            stored: self.stored_offset(first_frame)..self.stored_offset(last_frame) + self.stored_frame_len(last_frame) as u64,
+            skip: (range.start - first_frame * self.frame_size) as usize,
+        }
+    }
+
+    fn open_frame(&self, cipher: &Aes256Gcm, index: u64, frame: &[u8]) -> Result<Vec<u8>> {
+        open(cipher, &self.raw, index as u32, frame)
+    }
+}
+
+/// Decrypt `stored`, the bytes of `span`, as they arrive, yielding `len` plaintext bytes.
+///
+/// Only the frame being filled is buffered, so memory stays at about two frames
+/// whatever the size of the object.
+pub fn decrypt_stream(header: Header, key: &EncryptionKey, span: FrameSpan, len: u64, stored: ByteStream) -> Result<ByteStream> {
+    struct State {
+        cipher: Aes256Gcm,
+        header: Header,
+        stored: ByteStream,
+        buffer: Vec<u8>,
+        index: u64,
+        skip: usize,
+        remaining: u64,
+    }
+
+    let state = State { cipher: key.cipher()?, header, stored, buffer: Vec::new(), index: span.first_frame, skip: span.skip, remaining: len };
+    let frames = stream::unfold(state, |mut state| async move {
+        if state.remaining == 0 {
+            return None;
+        }
+        loop {
+            let frame_len = state.header.stored_frame_len(state.index);
+            if state.buffer.len() >= frame_len {
+                let rest = state.buffer.split_off(frame_len);
+                let frame = std::mem::replace(&mut state.buffer, rest);
+                let item = state.header.open_frame(&state.cipher, state.index, &frame).map(|mut plaintext| {
+                    let start = std::mem::take(&mut state.skip);
+                    let end = (start as u64 + state.remaining).min(plaintext.len() as u64) as usize;
+                    state.remaining -= (end - start) as u64;
+                    plaintext.truncate(end);
+                    plaintext.drain(..start);
+                    Bytes::from(plaintext)
+                });
+                // A frame that fails to decrypt ends the stream
+                if item.is_err() {
+                    state.remaining = 0;
+                }
+                state.index += 1;
+                return Some((item, state));
+            }
+            match state.stored.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    state.remaining = 0;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.remaining = 0;
+                    return Some((Err(anyhow!("Encrypted object ended in the middle of frame {}", state.index)), state));
+                }
+            }
+        }
+    });
+    Ok(frames.boxed())
+}
+
+fn nonce(header: &[u8; HEADER_LEN], index: u32) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&header[16..]);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+fn seal(cipher: &Aes256Gcm, header: &[u8; HEADER_LEN], index: u32, plaintext: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .encrypt(Nonce::from_slice(&nonce(header, index)), Payload { msg: plaintext, aad: header })
+        .map_err(|e| anyhow!("Encryption failed: {}", e))
+}
+
+fn open(cipher: &Aes256Gcm, header: &[u8; HEADER_LEN], index: u32, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .decrypt(Nonce::from_slice(&nonce(header, index)), Payload { msg: ciphertext, aad: header })
+        .map_err(|e| anyhow!("Decryption failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> EncryptionKey {
+        EncryptionKey::from_hex(&"07".repeat(32))
+    }
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// `stored` in chunks of `chunk_size`, as a network response would arrive
+    fn chunks(stored: &[u8], chunk_size: usize) -> ByteStream {
+        let chunks: Vec<Result<Bytes>> = stored.chunks(chunk_size).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        stream::iter(chunks).boxed()
+    }
+
+    async fn read(header: &Header, stored: &[u8], range: Range<u64>) -> Result<Vec<u8>> {
+        let span = header.span(&range);
+        let bytes = &stored[span.stored.start as usize..span.stored.end as usize];
+        let mut plaintext = Vec::new();
+        let mut frames = decrypt_stream(header.clone(), &key(), span, range.end - range.start, chunks(bytes, 7))?;
+        while let Some(frame) = frames.next().await {
+            plaintext.extend_from_slice(&frame?);
+        }
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn whole_objects_round_trip_in_either_format() {
+        let data = plaintext(1000);
+
+        let framed = encrypt_with_frame_size(&data, &key(), 64).unwrap();
+        let legacy = super::super::encrypt(&data, &key()).unwrap();
+
+        assert!(is_chunked(&framed) && !is_chunked(legacy.as_bytes()));
+        assert_eq!(framed.len(), PREAMBLE_LEN + 1000 + 16 * TAG_LEN);
+        assert_eq!(decrypt(&framed, &key()).unwrap(), data);
+        assert_eq!(decrypt(legacy.as_bytes(), &key()).unwrap(), data);
+        assert_eq!(decrypt(&encrypt(&[], &key()).unwrap(), &key()).unwrap(), Vec::<u8>::new());
+    }
+
+    #[tokio::test]
+    async fn a_streamed_object_is_as_long_as_it_says_and_decrypts() {
+        for len in [0, 1000, 3 * FRAME_SIZE, 3 * FRAME_SIZE + 1] {
+            let data = plaintext(len);
+            let (stored_len, mut frames) = encrypt_stream(Bytes::from(data.clone()), &key()).unwrap();
+            let mut stored = Vec::new();
+            while let Some(frame) = frames.next().await {
+                stored.extend_from_slice(&frame.unwrap());
+            }
+
+            assert_eq!(stored.len() as u64, stored_len, "{}", len);
+            assert!(is_chunked(&stored));
+            assert_eq!(decrypt(&stored, &key()).unwrap(), data, "{}", len);
+        }
+    }
+
+    #[tokio::test]
+    async fn any_range_decrypts_from_just_its_frames() {
+        let data = plaintext(1000);
+        let stored = encrypt_with_frame_size(&data, &key(), 64).unwrap();
+        let header = Header::parse(&stored[..PREAMBLE_LEN], &key()).unwrap();
+
+        assert_eq!((header.plaintext_len(), header.digest_hex()), (1000, hex::encode(Sha256::digest(&data))));
+        for range in [0..1000, 0..1, 63..65, 64..128, 100..999, 960..1000, 999..1000] {
+            assert_eq!(read(&header, &stored, range.clone()).await.unwrap(), data[range.start as usize..range.end as usize], "{:?}", range);
+        }
+        let span = header.span(&(100..200));
+        assert_eq!((span.first_frame, span.skip), (1, 36));
+        assert_eq!(span.stored, PREAMBLE_LEN as u64 + 80..PREAMBLE_LEN as u64 + 4 * 80);
+    }
+
+    #[tokio::test]
+    async fn tampering_and_truncation_are_detected() {
+        let data = plaintext(300);
+        let stored = encrypt_with_frame_size(&data, &key(), 64).unwrap();
+        let header = Header::parse(&stored[..PREAMBLE_LEN], &key()).unwrap();
+
+        let mut flipped = stored.clone();
+        flipped[PREAMBLE_LEN + 70] ^= 1;
+        assert!(read(&header, &flipped, 0..300).await.is_err());
+        assert!(decrypt(&stored[..stored.len() - 1], &key()).is_err());
+
+        // A header claiming a shorter plaintext no longer matches what the frames authenticate
+        let mut shortened = stored.clone();
+        shortened[8..16].copy_from_slice(&128u64.to_be_bytes());
+        assert!(Header::parse(&shortened[..PREAMBLE_LEN], &key()).is_err());
+
+        let other_key = EncryptionKey::from_hex(&"08".repeat(32));
+        assert!(Header::parse(&stored[..PREAMBLE_LEN], &other_key).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_stream_cut_off_mid_frame_ends_in_an_error() {
+        let stored = encrypt_with_frame_size(&plaintext(300), &key(), 64).unwrap();
+        let header = Header::parse(&stored[..PREAMBLE_LEN], &key()).unwrap();
+        let span = header.span(&(0..300));
+
+        let mut frames = decrypt_stream(header, &key(), span, 300, chunks(&stored[PREAMBLE_LEN..PREAMBLE_LEN + 100], 50)).unwrap();
+
+        assert_eq!(frames.next().await.unwrap().unwrap().len(), 64);
+        assert!(frames.next().await.unwrap().is_err());
+        assert!(frames.next().await.is_none());
+    }
+}
@@ -1,17 +1,24 @@
 use anyhow::{anyhow, Result};
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm, Key, Nonce,
+    aead::{Aead, AeadCore, OsRng},
+    Aes256Gcm, Nonce,
 };
 use base64::{engine::general_purpose, Engine as _};
 use hex;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+pub mod chunked;
+#[cfg(all(test, feature = "test"))]
+pub mod peak_memory;
+pub mod secret;
+
+pub use secret::{EncryptionKey, SecretString};
 
 // Encrypts data using AES-256-GCM and returns a base64 encoded string
 // Format: base64(nonce:ciphertext)
-pub fn encrypt(data: &[u8], key: &str) -> Result<String> {
-    let key_bytes = hex::decode(key)?;
-    let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
+pub fn encrypt(data: &[u8], key: &EncryptionKey) -> Result<String> {
+    let cipher = key.cipher()?;
 
     // Generate a random nonce for each encryption for security
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
@@ -28,10 +35,8 @@ pub fn encrypt(data: &[u8], key: &str) -> Result<String> {
 }
 
 // Decrypts a base64 encoded string using AES-256-GCM
-pub fn decrypt(encrypted_data: &str, key: &str) -> Result<Vec<u8>> {
-    let key_bytes = hex::decode(key)?;
-    let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
+pub fn decrypt(encrypted_data: &str, key: &EncryptionKey) -> Result<Vec<u8>> {
+    let cipher = key.cipher()?;
 
     let data_bytes = general_purpose::STANDARD.decode(encrypted_data)?;
     if data_bytes.len() < 12 { // AES-GCM nonce is 12 bytes
@@ -47,3 +52,83 @@ pub fn decrypt(encrypted_data: &str, key: &str) -> Result<Vec<u8>> {
 
     Ok(plaintext)
 }
+
+// Normalizes an email for lookups: surrounding whitespace and case are not significant
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// Normalizes a phone number to digits with an optional leading '+' (E.164-style)
+pub fn normalize_phone(phone: &str) -> String {
+    let trimmed = phone.trim();
+    let digits: String = trimmed.chars().filter(|c| c.is_ascii_digit()).collect();
+    if trimmed.starts_with('+') {
+        format!("+{}", digits)
+    } else {
+        digits
+    }
+}
+
+// SHA-256 of the normalized email, used as the lookup key for encrypted patients
+pub fn hash_email(email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(normalize_email(email).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// SHA-256 of the normalized phone number, used as the lookup key for encrypted patients
+pub fn hash_phone(phone: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(normalize_phone(phone).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Shortest name prefix indexed for search, which is also the shortest search term
+pub const NAME_SEARCH_MIN_CHARS: usize = 3;
+/// Longer words are indexed, and searched, by their first this many characters
+pub const NAME_SEARCH_MAX_CHARS: usize = 20;
+
+// Lowercased alphanumeric words: "O'Brien-Otieno, Jane" gives "obrienotieno" and "jane"
+pub fn name_words(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// HMAC-SHA256 blind index of a name prefix, so patients can be found by name without it being stored in the clear
+pub fn name_search_token(prefix: &str, key: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"name:");
+    mac.update(prefix.as_bytes());
+    hex::encode(&mac.finalize().into_bytes()[..16])
+}
+
+// Search tokens for a name: one per prefix of 3 to 20 characters of each word, so "wanj" finds
+// "Wanjiru". Equal names always give equal tokens; anyone holding the database can tell which
+// patients share a name or name prefix without learning the name. That linkability is the
+// price of searching encrypted names, and why the tokens are keyed and search can be turned off.
+pub fn name_search_tokens<'a>(names: impl IntoIterator<Item = &'a str>, key: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = names
+        .into_iter()
+        .flat_map(name_words)
+        .flat_map(|word| {
+            let chars: Vec<char> = word.chars().take(NAME_SEARCH_MAX_CHARS).collect();
+            (NAME_SEARCH_MIN_CHARS..=chars.len()).map(move |len| chars[..len].iter().collect::<String>())
+        })
+        .map(|prefix| name_search_token(&prefix, key))
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+// Identifies the key search tokens were made with, so records indexed under another key can be found and redone
+pub fn name_search_key_id(key: &str) -> String {
+    name_search_token("\0key-id", key)
+}
+
+// The IANA time zone a patient saved, or UTC when they saved none or it is not recognised
+pub fn patient_timezone(timezone: Option<&str>) -> chrono_tz::Tz {
+    timezone.and_then(|name| name.parse().ok()).unwrap_or(chrono_tz::Tz::UTC)
+}
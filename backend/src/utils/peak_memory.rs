@@ -0,0 +1,73 @@
+//! The system allocator with a high-water mark, installed in test builds so benchmarks can
+//! report the peak memory of a piece of work. The counts cover the whole process, so a
+//! measurement is only meaningful from a test run on its own.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Default)]
+pub struct PeakMemory {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl PeakMemory {
+    pub const fn new() -> Self {
+        Self { current: AtomicUsize::new(0), peak: AtomicUsize::new(0) }
+    }
+
+    /// Run `work` and return its output with the most it held allocated at once, beyond what
+    /// was allocated when it started
+    pub async fn measure<T>(&self, work: impl Future<Output = T>) -> (T, usize) {
+        let before = self.current.load(Ordering::SeqCst);
+        self.peak.store(before, Ordering::SeqCst);
+        let output = work.await;
+        (output, self.peak.load(Ordering::SeqCst).saturating_sub(before))
+    }
+
+    fn grew(&self, size: usize) {
+        let now = self.current.fetch_add(size, Ordering::SeqCst) + size;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn shrank(&self, size: usize) {
+        self.current.fetch_sub(size, Ordering::SeqCst);
+    }
+}
+
+unsafe impl GlobalAlloc for PeakMemory {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.shrank(layout.size());
+    }
+
+    /// Counted as the change in size, although a reallocation that moves briefly holds both
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let moved = System.realloc(ptr, layout, new_size);
+        if !moved.is_null() {
+            if new_size > layout.size() {
+                self.grew(new_size - layout.size());
+            } else {
+                self.shrank(layout.size() - new_size);
+            }
+        }
+        moved
+    }
+}
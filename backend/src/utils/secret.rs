@@ -0,0 +1,196 @@
+//! Secrets held in memory: wiped when dropped and never printed.
+//!
+//! `Debug` and `Serialize` write `[REDACTED]`, so a secret that ends up in a log line, an error
+//! report or a dumped `Config` does not leak. The value is only reachable through
+//! `expose_secret`, which keeps every place that really needs it easy to find.
+
+use std::fmt;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// A password, token or API key
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// An AES-256-GCM key, decoded from hex once when configuration is read rather than on every
+/// encrypt and decrypt. A key that was given but is not 32 bytes of hex is kept as malformed,
+/// so configuration validation can tell it apart from a missing one.
+#[derive(Clone, Default)]
+pub struct EncryptionKey {
+    bytes: Option<Zeroizing<[u8; 32]>>,
+    malformed: bool,
+}
+
+impl EncryptionKey {
+    pub fn from_hex(hex_key: &str) -> Self {
+        if hex_key.is_empty() {
+            return Self::default();
+        }
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        match hex::decode_to_slice(hex_key, bytes.as_mut_slice()) {
+            Ok(()) => Self { bytes: Some(bytes), malformed: false },
+            Err(_) => Self { bytes: None, malformed: true },
+        }
+    }
+
+    /// No key was given
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_none() && !self.malformed
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.bytes.is_some()
+    }
+
+    pub fn cipher(&self) -> Result<Aes256Gcm> {
+        let bytes = self.bytes.as_ref().ok_or_else(|| anyhow!("Encryption key must be 32 bytes"))?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(bytes.as_slice())))
+    }
+
+    /// A new random key, such as a patient's own data key
+    pub fn generate() -> Self {
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(bytes.as_mut_slice());
+        Self { bytes: Some(bytes), malformed: false }
+    }
+
+    /// This key encrypted with `master`, to be stored beside the data it encrypts
+    pub fn wrap(&self, master: &EncryptionKey) -> Result<String> {
+        let bytes = self.bytes.as_ref().ok_or_else(|| anyhow!("Encryption key must be 32 bytes"))?;
+        super::encrypt(bytes.as_slice(), master)
+    }
+
+    /// A key stored by [`wrap`](Self::wrap)
+    pub fn from_wrapped(wrapped: &str, master: &EncryptionKey) -> Result<Self> {
+        let unwrapped = Zeroizing::new(super::decrypt(wrapped, master)?);
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        if unwrapped.len() != bytes.len() {
+            return Err(anyhow!("Wrapped key must be 32 bytes"));
+        }
+        bytes.copy_from_slice(&unwrapped);
+        Ok(Self { bytes: Some(bytes), malformed: false })
+    }
+}
+
+impl From<String> for EncryptionKey {
+    fn from(hex_key: String) -> Self {
+        let hex_key = Zeroizing::new(hex_key);
+        Self::from_hex(&hex_key)
+    }
+}
+
+impl From<&str> for EncryptionKey {
+    fn from(hex_key: &str) -> Self {
+        Self::from_hex(hex_key)
+    }
+}
+
+impl PartialEq for EncryptionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes.as_deref() == other.bytes.as_deref() && self.malformed == other.malformed
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for EncryptionKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_when_printed_or_serialized() {
+        let token = SecretString::from("hunter2");
+        let key = EncryptionKey::from_hex(&"ab".repeat(32));
+
+        assert_eq!(format!("{:?} {:?}", token, key), "[REDACTED] [REDACTED]");
+        assert_eq!(serde_json::to_string(&(&token, &key)).unwrap(), r#"["[REDACTED]","[REDACTED]"]"#);
+        assert_eq!(token.expose_secret(), "hunter2");
+    }
+
+    #[test]
+    fn encryption_keys_are_decoded_once_and_checked() {
+        assert!(EncryptionKey::from_hex(&"00".repeat(32)).is_valid());
+        assert!(EncryptionKey::from_hex("").is_empty());
+        for malformed in ["abcd", &"zz".repeat(32), &"00".repeat(33)] {
+            let key = EncryptionKey::from_hex(malformed);
+            assert!(!key.is_empty() && !key.is_valid() && key.cipher().is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn wrapped_keys_only_unwrap_with_their_master_key() {
+        let master = EncryptionKey::from_hex(&"ab".repeat(32));
+        let key = EncryptionKey::generate();
+        let wrapped = key.wrap(&master).unwrap();
+
+        assert!(key.is_valid() && key != EncryptionKey::generate());
+        assert!(EncryptionKey::from_wrapped(&wrapped, &master).unwrap() == key);
+        assert!(EncryptionKey::from_wrapped(&wrapped, &EncryptionKey::from_hex(&"cd".repeat(32))).is_err());
+    }
+}